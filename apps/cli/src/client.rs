@@ -0,0 +1,109 @@
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::{header, Client as HttpClient, RequestBuilder};
+use serde_json::{json, Value};
+
+/// Calls the procedures of a running node, through the rspc endpoint of its HTTP server
+pub struct Client {
+	http: HttpClient,
+	url: String,
+	token: Option<String>,
+	library_id: Option<String>,
+}
+
+impl Client {
+	pub fn new(url: &str, token: Option<String>, library_id: Option<String>) -> Self {
+		Self {
+			http: HttpClient::new(),
+			url: format!("{}/rspc", url.trim_end_matches('/')),
+			token,
+			library_id,
+		}
+	}
+
+	pub async fn query(&self, key: &str, input: Value) -> Result<Value> {
+		let mut req = self.http.get(format!("{}/{key}", self.url));
+		if !input.is_null() {
+			req = req.query(&[("input", input.to_string())]);
+		}
+
+		self.send(key, req).await
+	}
+
+	pub async fn mutation(&self, key: &str, input: Value) -> Result<Value> {
+		let req = self
+			.http
+			.post(format!("{}/{key}", self.url))
+			.header(header::CONTENT_TYPE, "application/json")
+			.body(input.to_string());
+
+		self.send(key, req).await
+	}
+
+	pub async fn library_query(&mut self, key: &str, arg: Value) -> Result<Value> {
+		let input = self.library_args(arg).await?;
+		self.query(key, input).await
+	}
+
+	pub async fn library_mutation(&mut self, key: &str, arg: Value) -> Result<Value> {
+		let input = self.library_args(arg).await?;
+		self.mutation(key, input).await
+	}
+
+	async fn send(&self, key: &str, mut req: RequestBuilder) -> Result<Value> {
+		if let Some(token) = &self.token {
+			req = req.bearer_auth(token);
+		}
+
+		let res = req
+			.send()
+			.await
+			.with_context(|| format!("unable to reach the node at {}", self.url))?;
+
+		let status = res.status();
+		let body = res.text().await.context("unable to read the response")?;
+		if status == reqwest::StatusCode::UNAUTHORIZED {
+			bail!("the node refused the token, set it with --token or $SD_AUTH_TOKEN");
+		}
+
+		let body = serde_json::from_str::<Value>(&body)
+			.with_context(|| format!("unexpected response to '{key}' ({status}): {body}"))?;
+
+		// Responses are wrapped in a JSON-RPC envelope
+		let result = body.get("result").unwrap_or(&body);
+		match result.get("type").and_then(Value::as_str) {
+			Some("response") => Ok(result.get("data").cloned().unwrap_or(Value::Null)),
+			Some("error") => Err(anyhow!(
+				"'{key}' failed: {}",
+				result["data"]["message"]
+					.as_str()
+					.unwrap_or("unknown error")
+			)),
+			_ => bail!("unexpected response to '{key}' ({status}): {body}"),
+		}
+	}
+
+	/// Wraps `arg` with the id of the library, the only library of the node when none was given
+	async fn library_args(&mut self, arg: Value) -> Result<Value> {
+		let library_id = match &self.library_id {
+			Some(library_id) => library_id.clone(),
+			None => {
+				let libraries = self.query("library.list", Value::Null).await?;
+				let library_id = match libraries.as_array().map(Vec::as_slice) {
+					Some([library]) => library["uuid"]
+						.as_str()
+						.context("the library has no id")?
+						.to_string(),
+					Some([]) | None => bail!("the node has no library"),
+					Some(_) => {
+						bail!("the node has several libraries, choose one with --library or $SD_LIBRARY, `sd libraries` lists them")
+					}
+				};
+
+				self.library_id = Some(library_id.clone());
+				library_id
+			}
+		};
+
+		Ok(json!({ "library_id": library_id, "arg": arg }))
+	}
+}
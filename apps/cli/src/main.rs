@@ -1,85 +1,351 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use indoc::printdoc;
-use sd_crypto::header::file::FileHeader;
-use std::path::PathBuf;
-use tokio::fs::File;
+use anyhow::Result;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use serde_json::{json, Value};
+use std::{path::PathBuf, time::Duration};
 
+mod client;
+mod header;
+
+use client::Client;
+
+/// Drives a Spacedrive node, like the server running on a NAS, from the command line
 #[derive(Parser)]
+#[command(name = "sd")]
 struct Args {
-	#[arg(help = "the file path to get details for")]
-	path: PathBuf,
+	#[command(flatten)]
+	node: NodeArgs,
+	/// Prints the responses of the node as JSON, for scripts
+	#[arg(long, global = true)]
+	json: bool,
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(ClapArgs)]
+struct NodeArgs {
+	/// The address of the node's HTTP server
+	#[arg(
+		long,
+		env = "SD_URL",
+		default_value = "http://localhost:8080",
+		global = true
+	)]
+	url: String,
+	/// The token the node requires, if it requires one
+	#[arg(long, env = "SD_AUTH_TOKEN", global = true, hide_env_values = true)]
+	token: Option<String>,
+	/// The id of the library, needed when the node has several
+	#[arg(long, env = "SD_LIBRARY", global = true)]
+	library: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Lists the libraries of the node
+	Libraries,
+	#[command(subcommand)]
+	Locations(LocationsCommand),
+	/// Identifies the files of a location, giving them objects
+	Identify {
+		location_id: i32,
+		/// Only the files under this directory of the location
+		#[arg(long, default_value = "")]
+		path: String,
+	},
+	/// Generates the thumbnails of the files of a location
+	Thumbnails {
+		location_id: i32,
+		/// Only the files under this directory of the location
+		#[arg(long, default_value = "")]
+		path: String,
+		/// Generates the thumbnails that already exist again
+		#[arg(long)]
+		regenerate: bool,
+	},
+	/// Searches the files of the library by name
+	Search {
+		query: String,
+		#[arg(long)]
+		location: Option<i32>,
+		#[arg(long, default_value_t = 100)]
+		take: i32,
+	},
+	#[command(subcommand)]
+	Tags(TagsCommand),
+	#[command(subcommand)]
+	Jobs(JobsCommand),
+	/// Prints the header of a file encrypted by Spacedrive, it doesn't need a node
+	Header { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum LocationsCommand {
+	List,
+	/// Adds a directory of the node as a location, and indexes it
+	Add {
+		path: String,
+	},
+	/// Indexes a location again from scratch
+	Rescan {
+		location_id: i32,
+	},
+}
+
+#[derive(Subcommand)]
+enum TagsCommand {
+	List,
+	Create {
+		name: String,
+		#[arg(long, default_value = "#2563EB")]
+		color: String,
+	},
+	/// Tags objects, or untags them with --unassign
+	Assign {
+		tag_id: i32,
+		#[arg(required = true)]
+		object_ids: Vec<i32>,
+		#[arg(long)]
+		unassign: bool,
+	},
+}
+
+#[derive(Subcommand)]
+enum JobsCommand {
+	List,
+	/// Prints the progress of the running jobs until they're all done
+	Watch {
+		/// Seconds between two updates
+		#[arg(long, default_value_t = 1)]
+		interval: u64,
+	},
+	Cancel {
+		job_id: String,
+	},
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-	let args = Args::parse();
+	let Args {
+		node,
+		json: print_json,
+		command,
+	} = Args::parse();
+
+	if let Command::Header { path } = &command {
+		return header::print_header(path).await;
+	}
+
+	let mut client = Client::new(&node.url, node.token, node.library);
+
+	let (result, print): (Value, fn(&Value)) = match command {
+		Command::Libraries => (
+			client.query("library.list", Value::Null).await?,
+			print_libraries,
+		),
+		Command::Locations(LocationsCommand::List) => (
+			client.library_query("locations.list", Value::Null).await?,
+			print_locations,
+		),
+		Command::Locations(LocationsCommand::Add { path }) => (
+			client
+				.library_mutation(
+					"locations.create",
+					json!({ "path": path, "dry_run": false, "indexer_rules_ids": [] }),
+				)
+				.await?,
+			print_nothing,
+		),
+		Command::Locations(LocationsCommand::Rescan { location_id }) => (
+			client
+				.library_mutation("locations.fullRescan", json!(location_id))
+				.await?,
+			print_nothing,
+		),
+		Command::Identify { location_id, path } => (
+			client
+				.library_mutation(
+					"jobs.identifyUniqueFiles",
+					json!({ "id": location_id, "path": path }),
+				)
+				.await?,
+			print_nothing,
+		),
+		Command::Thumbnails {
+			location_id,
+			path,
+			regenerate,
+		} => (
+			client
+				.library_mutation(
+					"jobs.generateThumbsForLocation",
+					json!({ "id": location_id, "path": path, "regenerate": regenerate }),
+				)
+				.await?,
+			print_nothing,
+		),
+		Command::Search {
+			query,
+			location,
+			take,
+		} => (
+			client
+				.library_query(
+					"search.paths",
+					json!({ "take": take, "filter": { "search": query, "locationId": location } }),
+				)
+				.await?,
+			print_search,
+		),
+		Command::Tags(TagsCommand::List) => (
+			client.library_query("tags.list", Value::Null).await?,
+			print_tags,
+		),
+		Command::Tags(TagsCommand::Create { name, color }) => (
+			client
+				.library_mutation("tags.create", json!({ "name": name, "color": color }))
+				.await?,
+			|tag| print_tags(&json!([tag])),
+		),
+		Command::Tags(TagsCommand::Assign {
+			tag_id,
+			object_ids,
+			unassign,
+		}) => (
+			client
+				.library_mutation(
+					"tags.assign",
+					json!({ "tag_id": tag_id, "object_ids": object_ids, "unassign": unassign }),
+				)
+				.await?,
+			print_nothing,
+		),
+		Command::Jobs(JobsCommand::List) => (
+			client.library_query("jobs.reports", Value::Null).await?,
+			print_jobs,
+		),
+		Command::Jobs(JobsCommand::Watch { interval }) => {
+			return watch_jobs(
+				&mut client,
+				Duration::from_secs(interval.max(1)),
+				print_json,
+			)
+			.await
+		}
+		Command::Jobs(JobsCommand::Cancel { job_id }) => (
+			client
+				.library_mutation("jobs.cancel", json!(job_id))
+				.await?,
+			print_nothing,
+		),
+		Command::Header { .. } => unreachable!("handled without a node"),
+	};
 
-	let mut reader = File::open(args.path).await.context("unable to open file")?;
-	let (header, aad) = FileHeader::from_reader(&mut reader).await?;
-	print_crypto_details(&header, &aad);
+	if print_json {
+		if !result.is_null() {
+			println!("{}", serde_json::to_string_pretty(&result)?);
+		}
+	} else {
+		print(&result);
+	}
 
 	Ok(())
 }
 
-fn print_crypto_details(header: &FileHeader, aad: &[u8]) {
-	printdoc! {"
-        Header version: {version}
-        Encryption algorithm: {algorithm}
-        AAD (hex): {hex}
-    ",
-		version = header.version,
-		algorithm = header.algorithm,
-		hex = hex::encode(aad)
-	};
+fn print_nothing(_: &Value) {}
 
-	header.keyslots.iter().enumerate().for_each(|(i, k)| {
-		printdoc! {"
-            Keyslot {index}:
-              Version: {version}
-              Algorithm: {algorithm}
-              Hashing algorithm: {hashing_algorithm}
-              Salt (hex): {salt}
-              Master Key (hex, encrypted): {master}
-              Master key nonce (hex): {nonce}
-        ",
-			index = i + i,
-			version = k.version,
-			algorithm = k.algorithm,
-			hashing_algorithm = k.hashing_algorithm,
-			salt = hex::encode(&*k.salt),
-			master = hex::encode(&*k.master_key),
-			nonce = hex::encode(k.nonce)
-		};
-	});
-
-	header.metadata.iter().for_each(|m| {
-		printdoc! {"
-            Metadata:
-              Version: {version}
-              Algorithm: {algorithm}
-              Encrypted size: {size}
-              Nonce (hex): {nonce}
-        ",
-			version = m.version,
-			algorithm = m.algorithm,
-			size = m.metadata.len(),
-			nonce = hex::encode(m.metadata_nonce)
+fn print_libraries(libraries: &Value) {
+	for library in libraries.as_array().into_iter().flatten() {
+		println!(
+			"{}  {}",
+			library["uuid"].as_str().unwrap_or_default(),
+			library["config"]["name"].as_str().unwrap_or_default()
+		);
+	}
+}
+
+fn print_locations(locations: &Value) {
+	for location in locations.as_array().into_iter().flatten() {
+		println!(
+			"{:>4}  {:<24}  {}",
+			location["id"],
+			location["name"].as_str().unwrap_or_default(),
+			location["path"].as_str().unwrap_or_default()
+		);
+	}
+}
+
+fn print_search(results: &Value) {
+	for item in results["items"].as_array().into_iter().flatten() {
+		let file_path = &item["item"];
+		let mut name = file_path["name"].as_str().unwrap_or_default().to_string();
+		if let Some(extension) = file_path["extension"].as_str().filter(|e| !e.is_empty()) {
+			name = format!("{name}.{extension}");
 		}
-	});
-
-	header.preview_media.iter().for_each(|p| {
-		printdoc! {"
-            Preview Media:
-              Version: {version}
-              Algorithm: {algorithm}
-              Encrypted size: {size}
-              Nonce (hex): {nonce}
-        ",
-			version = p.version,
-			algorithm = p.algorithm,
-			size = p.media.len(),
-			nonce = hex::encode(p.media_nonce)
-		};
-	});
+
+		println!(
+			"{:>4}  {}{}",
+			file_path["location_id"],
+			file_path["materialized_path"].as_str().unwrap_or_default(),
+			name
+		);
+	}
+}
+
+fn print_tags(tags: &Value) {
+	for tag in tags.as_array().into_iter().flatten() {
+		println!(
+			"{:>4}  {}",
+			tag["id"],
+			tag["name"].as_str().unwrap_or_default()
+		);
+	}
+}
+
+fn job_line(job: &Value) -> String {
+	format!(
+		"{}  {:<24}  {:<20}  {}/{}  {}",
+		job["id"].as_str().unwrap_or_default(),
+		job["name"].as_str().unwrap_or_default(),
+		job["status"].as_str().unwrap_or_default(),
+		job["completed_task_count"],
+		job["task_count"],
+		job["message"].as_str().unwrap_or_default()
+	)
+}
+
+fn jobs(reports: &Value) -> impl Iterator<Item = &Value> {
+	reports["groups"]
+		.as_array()
+		.into_iter()
+		.flatten()
+		.flat_map(|group| group["jobs"].as_array().into_iter().flatten())
+}
+
+fn print_jobs(reports: &Value) {
+	for job in jobs(reports) {
+		println!("{}", job_line(job));
+	}
+}
+
+async fn watch_jobs(client: &mut Client, interval: Duration, print_json: bool) -> Result<()> {
+	loop {
+		let reports = client.library_query("jobs.reports", Value::Null).await?;
+		let active = jobs(&reports)
+			.filter(|job| matches!(job["status"].as_str(), Some("Running" | "Queued")))
+			.collect::<Vec<_>>();
+
+		if active.is_empty() {
+			return Ok(());
+		}
+
+		for job in active {
+			if print_json {
+				println!("{job}");
+			} else {
+				println!("{}", job_line(job));
+			}
+		}
+
+		tokio::time::sleep(interval).await;
+	}
 }
@@ -5,14 +5,14 @@
 
 use std::{fs, path::PathBuf, sync::Arc, time::Duration};
 
-use sd_core::{custom_uri::create_custom_uri_endpoint, Node, NodeError};
+use sd_core::{custom_uri::create_custom_uri_endpoint, ipc, Node, NodeError};
 
 use tauri::{
 	api::path, async_runtime::block_on, ipc::RemoteDomainAccessScope, plugin::TauriPlugin, Manager,
 	RunEvent, Runtime,
 };
 use tokio::{task::block_in_place, time::sleep};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 #[cfg(target_os = "linux")]
 mod app_linux;
@@ -92,6 +92,17 @@ async fn main() -> tauri::Result<()> {
 	let app = tauri::Builder::default();
 	let (node, app) = match result {
 		Ok((node, router)) => {
+			// For the tools running on this machine, like shell extensions
+			tokio::spawn({
+				let (node, router) = (node.clone(), router.clone());
+				async move {
+					let path = ipc::default_path(&node.data_dir);
+					if let Err(e) = ipc::serve(node, router, path).await {
+						warn!("Failed to serve the API on a local socket: {e}");
+					}
+				}
+			});
+
 			// This is a super cringe workaround for: https://github.com/tauri-apps/tauri/issues/3725 & https://bugs.webkit.org/show_bug.cgi?id=146351#c5
 			#[cfg(target_os = "linux")]
 			let app = app_linux::setup(app, rx, create_custom_uri_endpoint(node.clone()).axum()).await;
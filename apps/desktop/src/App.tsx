@@ -64,6 +64,11 @@ const platform: Platform = {
 			`thumbnail/${keyParts.map((i) => encodeURIComponent(i)).join('/')}`,
 			'spacedrive'
 		),
+	getSpriteUrlByThumbKey: (keyParts) =>
+		convertFileSrc(
+			`sprite/${keyParts.map((i) => encodeURIComponent(i)).join('/')}`,
+			'spacedrive'
+		),
 	getFileUrl: (libraryId, locationLocalId, filePathId, _linux_workaround) => {
 		const path = `file/${libraryId}/${locationLocalId}/${filePathId}`;
 		if (_linux_workaround && customUriServerUrl) {
@@ -49,7 +49,11 @@ const CreatingLibraryScreen = ({ navigation }: OnboardingStackScreenProps<'Creat
 
 	const create = async () => {
 		telemetryStore.shareTelemetry = obStore.shareTelemetry;
-		createLibrary.mutate({ name: obStore.newLibraryName });
+		createLibrary.mutate({
+			name: obStore.newLibraryName,
+			password: null,
+			remember_password: false
+		});
 
 		return;
 	};
@@ -53,6 +53,7 @@ const EditLocationSettingsScreen = ({
 			name: data.displayName,
 			sync_preview_media: data.syncPreviewMedia,
 			generate_preview_media: data.generatePreviewMedia,
+			generate_video_thumbnails: null,
 			hidden: data.hidden,
 			indexer_rules_ids: []
 		})
@@ -33,7 +33,12 @@ const LibraryGeneralSettingsScreen = ({
 	const { mutate: editLibrary } = useBridgeMutation('library.edit');
 
 	useAutoForm(form, (value) => {
-		editLibrary({ description: value.description, name: value.name, id: library.uuid });
+		editLibrary({
+			description: value.description,
+			name: value.name,
+			id: library.uuid,
+			sync_conflict_policy: null
+		});
 		// console.log('Updated', value);
 		// TODO: Show toast
 	});
@@ -0,0 +1,108 @@
+//! Token authentication, for servers reachable from other machines like a NAS driven by remote
+//! clients. With `$SD_AUTH_TOKEN` set, every request but the health check needs the token, as a
+//! bearer token or as a `token` query parameter for the requests a browser can't add a header to,
+//! like the websocket of rspc and the `<img>` of a thumbnail.
+//!
+//! The API tokens of the node are accepted the same way, limited to their scopes. They're checked
+//! even on a server without `$SD_AUTH_TOKEN`, which lets anyone in without a token anyway.
+
+use std::sync::Arc;
+
+use axum::{
+	extract::State,
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use http::{header, Method, Request, StatusCode};
+use sd_core::{ApiToken, Node, Procedure};
+
+#[derive(Clone)]
+pub struct AuthState {
+	/// The token of `$SD_AUTH_TOKEN`, which can do everything
+	pub token: Option<Arc<str>>,
+	pub node: Arc<Node>,
+}
+
+/// Compares in a time that doesn't depend on where the tokens differ, so they can't be guessed from
+/// how long requests take to be refused
+fn tokens_match(expected: &str, given: &str) -> bool {
+	expected.len() == given.len()
+		&& expected
+			.bytes()
+			.zip(given.bytes())
+			.fold(0, |diff, (a, b)| diff | (a ^ b))
+			== 0
+}
+
+fn request_token<B>(req: &Request<B>) -> Option<String> {
+	req.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "))
+		.map(str::to_string)
+		.or_else(|| {
+			// Percent-decoded, as tokens can have characters that are escaped in URLs
+			form_urlencoded::parse(req.uri().query()?.as_bytes())
+				.find_map(|(key, value)| (key == "token").then(|| value.into_owned()))
+		})
+}
+
+/// Whether the scopes of the token cover the request
+fn token_allows<B>(token: &ApiToken, req: &Request<B>) -> bool {
+	let path = req.uri().path();
+
+	if let Some(key) = path.strip_prefix("/rspc/") {
+		// The websocket and the batches can call any procedure, whatever they're sent to
+		let batched = req.uri().query().map_or(false, |query| {
+			query.split('&').any(|pair| pair.starts_with("batch"))
+		});
+		if key == "ws" || key.starts_with('_') || batched {
+			return false;
+		}
+
+		return match *req.method() {
+			Method::GET => token.allows(Procedure::Query(key)),
+			Method::POST => token.allows(Procedure::Mutation(key)),
+			_ => false,
+		};
+	}
+
+	if let Some(operation) = path.strip_prefix("/spacedrive/") {
+		return token.allows_file_content(
+			operation.starts_with("thumbnail/") || operation.starts_with("sprite/"),
+		);
+	}
+
+	if path == "/graphql" || path.starts_with("/graphql/") {
+		return token.allows_graphql();
+	}
+
+	// The web app and the OpenAPI document
+	true
+}
+
+pub async fn require_token<B>(
+	State(state): State<AuthState>,
+	req: Request<B>,
+	next: Next<B>,
+) -> Response {
+	let Some(given) = request_token(&req) else {
+		return match state.token {
+			Some(_) => (StatusCode::UNAUTHORIZED, "401 Unauthorized").into_response(),
+			None => next.run(req).await,
+		};
+	};
+
+	if matches!(&state.token, Some(token) if tokens_match(token, &given)) {
+		return next.run(req).await;
+	}
+
+	match state.node.api_tokens().authenticate(&given).await {
+		Some(token) if token_allows(&token, &req) => next.run(req).await,
+		Some(_) => (StatusCode::FORBIDDEN, "403 Forbidden").into_response(),
+		None if state.token.is_some() => {
+			(StatusCode::UNAUTHORIZED, "401 Unauthorized").into_response()
+		}
+		None => next.run(req).await,
+	}
+}
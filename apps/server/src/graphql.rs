@@ -0,0 +1,26 @@
+//! The GraphQL API of the core at `/graphql`, with GraphiQL to explore it on `GET`. It's behind the
+//! same token as the rest of the server.
+
+use std::sync::Arc;
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{extract::State, response::Html, routing::get, Router};
+use sd_core::{
+	graphql::{schema, GraphQLSchema},
+	Node,
+};
+
+async fn graphiql() -> Html<String> {
+	Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+async fn execute(State(schema): State<GraphQLSchema>, req: GraphQLRequest) -> GraphQLResponse {
+	schema.execute(req.into_inner()).await.into()
+}
+
+pub fn router(node: Arc<Node>) -> Router {
+	Router::new()
+		.route("/", get(graphiql).post(execute))
+		.with_state(schema(node))
+}
@@ -1,9 +1,13 @@
-use std::{env, net::SocketAddr, path::Path};
+use std::{env, net::SocketAddr, path::Path, sync::Arc};
 
-use axum::routing::get;
-use sd_core::{custom_uri::create_custom_uri_endpoint, Node};
-use tracing::info;
+use axum::{http::header, middleware, routing::get};
+use sd_core::{custom_uri::create_custom_uri_endpoint, ipc, webdav::create_webdav_endpoint, Node};
+use tracing::{info, warn};
 
+mod auth;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod metrics;
 mod utils;
 
 #[cfg(feature = "assets")]
@@ -42,13 +46,48 @@ async fn main() {
 	};
 	let signal = utils::axum_shutdown_signal(node.clone());
 
+	#[cfg(feature = "graphql")]
+	let graphql = graphql::router(node.clone());
+
+	let webdav = create_webdav_endpoint(node.clone(), "/webdav").axum();
+
+	if let Some(metrics_port) = env::var("SD_METRICS_PORT")
+		.ok()
+		.and_then(|port| port.parse::<u16>().ok())
+	{
+		tokio::spawn(metrics::serve(node.clone(), metrics_port));
+	}
+
+	// For the tools running on this machine, like shell extensions
+	tokio::spawn({
+		let (node, router) = (node.clone(), router.clone());
+		async move {
+			let path = ipc::default_path(&node.data_dir);
+			if let Err(e) = ipc::serve(node, router, path).await {
+				warn!("Failed to serve the API on a local socket: {e}");
+			}
+		}
+	});
+
+	// Made from the procedures of the router, like the TypeScript bindings
+	let openapi = sd_core::api::openapi::generate(&router).to_string();
+
 	let app = axum::Router::new()
-		.route("/health", get(|| async { "OK" }))
+		.route(
+			"/openapi.json",
+			get(move || async move { ([(header::CONTENT_TYPE, "application/json")], openapi) }),
+		)
 		.nest(
 			"/spacedrive",
 			create_custom_uri_endpoint(node.clone()).axum(),
 		)
-		.nest("/rspc", router.endpoint(move || node.clone()).axum());
+		.nest("/rspc", {
+			let node = node.clone();
+			router.endpoint(move || node.clone()).axum()
+		});
+
+	#[cfg(feature = "graphql")]
+	let app = app.nest("/graphql", graphql);
 
 	#[cfg(feature = "assets")]
 	let app = app
@@ -124,6 +163,29 @@ async fn main() {
 		.route("/", get(|| async { "Spacedrive Server!" }))
 		.fallback(|| async { "404 Not Found: We're past the event horizon..." });
 
+	let token = match env::var("SD_AUTH_TOKEN") {
+		Ok(token) if !token.is_empty() => {
+			info!("Requests need the token set in $SD_AUTH_TOKEN or an API token");
+			Some(Arc::<str>::from(token))
+		}
+		_ => {
+			warn!("$SD_AUTH_TOKEN isn't set, anyone who can reach the server can use it");
+			None
+		}
+	};
+	let app = app.layer(middleware::from_fn_with_state(
+		auth::AuthState {
+			token,
+			node: node.clone(),
+		},
+		auth::require_token,
+	));
+
+	// After the authentication, for the health checks of containers and load balancers
+	let app = app.route("/health", get(|| async { "OK" }));
+	// WebDAV clients can't send the token, the shares have their own credentials
+	let app = app.nest("/webdav", webdav);
+
 	let mut addr = "[::]:8080".parse::<SocketAddr>().unwrap(); // This listens on IPv6 and IPv4
 	addr.set_port(port);
 	info!("Listening on http://localhost:{}", port);
@@ -0,0 +1,33 @@
+use std::{
+	net::{Ipv6Addr, SocketAddr},
+	sync::Arc,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use sd_core::Node;
+use tracing::{error, info};
+
+/// Serves the metrics of the node at `/metrics` of `port`, for Prometheus to scrape. They're on
+/// their own port, without the token, so they can be kept off the network the node is served on.
+pub async fn serve(node: Arc<Node>, port: u16) {
+	let app = Router::new()
+		.route("/metrics", get(metrics))
+		.with_state(node);
+
+	// IPv6 and IPv4, like the server of the app
+	let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+	info!("Serving metrics on http://localhost:{port}/metrics");
+	if let Err(e) = axum::Server::bind(&addr)
+		.serve(app.into_make_service())
+		.await
+	{
+		error!("Metrics server failed: {e}");
+	}
+}
+
+async fn metrics(State(node): State<Arc<Node>>) -> impl IntoResponse {
+	(
+		[(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+		node.metrics().await,
+	)
+}
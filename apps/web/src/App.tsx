@@ -36,6 +36,8 @@ const platform: Platform = {
 	platform: 'web',
 	getThumbnailUrlByThumbKey: (keyParts) =>
 		`${spacedriveURL}/thumbnail/${keyParts.map((i) => encodeURIComponent(i)).join('/')}.webp`,
+	getSpriteUrlByThumbKey: (keyParts) =>
+		`${spacedriveURL}/sprite/${keyParts.map((i) => encodeURIComponent(i)).join('/')}.webp`,
 	getFileUrl: (libraryId, locationLocalId, filePathId) =>
 		`${spacedriveURL}/file/${encodeURIComponent(libraryId)}/${encodeURIComponent(
 			locationLocalId
@@ -0,0 +1,17 @@
+-- CreateTable
+CREATE TABLE "sync_conflict" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "model" TEXT NOT NULL,
+    "record_id" BLOB NOT NULL,
+    "field" TEXT NOT NULL,
+    "value" BLOB NOT NULL,
+    "node_id" INTEGER NOT NULL,
+    "other_value" BLOB NOT NULL,
+    "other_node_id" INTEGER NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    CONSTRAINT "sync_conflict_node_id_fkey" FOREIGN KEY ("node_id") REFERENCES "node" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "sync_conflict_other_node_id_fkey" FOREIGN KEY ("other_node_id") REFERENCES "node" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "sync_conflict_model_record_id_field_key" ON "sync_conflict"("model", "record_id", "field");
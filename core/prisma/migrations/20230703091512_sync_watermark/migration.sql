@@ -0,0 +1,10 @@
+-- CreateTable
+CREATE TABLE "sync_watermark" (
+    "node_id" INTEGER NOT NULL,
+    "origin_id" INTEGER NOT NULL,
+    "timestamp" BIGINT NOT NULL,
+
+    PRIMARY KEY ("node_id", "origin_id"),
+    CONSTRAINT "sync_watermark_node_id_fkey" FOREIGN KEY ("node_id") REFERENCES "node" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "sync_watermark_origin_id_fkey" FOREIGN KEY ("origin_id") REFERENCES "node" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,9 @@
+-- DropIndex
+DROP INDEX "sync_conflict_model_record_id_field_key";
+
+-- AlterTable
+ALTER TABLE "sync_conflict" ADD COLUMN "date_resolved" DATETIME;
+ALTER TABLE "sync_conflict" ADD COLUMN "resolved_value" BLOB;
+
+-- CreateIndex
+CREATE INDEX "sync_conflict_model_record_id_field_idx" ON "sync_conflict"("model", "record_id", "field");
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "node" ADD COLUMN "can_delete" BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE "node" ADD COLUMN "can_read_files" BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE "node" ADD COLUMN "can_spacedrop" BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE "node" ADD COLUMN "can_sync" BOOLEAN NOT NULL DEFAULT true;
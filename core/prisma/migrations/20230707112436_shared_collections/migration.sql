@@ -0,0 +1,37 @@
+-- CreateTable
+CREATE TABLE "shared_collection" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "color" TEXT,
+    "tag_id" INTEGER,
+    "library_id" BLOB NOT NULL,
+    "library_name" TEXT NOT NULL,
+    "peer_id" TEXT NOT NULL,
+    "identity" BLOB NOT NULL,
+    "access" INTEGER NOT NULL,
+    "include_files" BOOLEAN NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    "date_synced" DATETIME,
+    CONSTRAINT "shared_collection_tag_id_fkey" FOREIGN KEY ("tag_id") REFERENCES "tag" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateTable
+CREATE TABLE "shared_collection_item" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "collection_id" INTEGER NOT NULL,
+    "object_pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "extension" TEXT,
+    "kind" INTEGER NOT NULL,
+    "size_in_bytes" TEXT NOT NULL,
+    "date_modified" DATETIME,
+    "local_path" TEXT,
+    CONSTRAINT "shared_collection_item_collection_id_fkey" FOREIGN KEY ("collection_id") REFERENCES "shared_collection" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "shared_collection_pub_id_key" ON "shared_collection"("pub_id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "shared_collection_item_collection_id_object_pub_id_key" ON "shared_collection_item"("collection_id", "object_pub_id");
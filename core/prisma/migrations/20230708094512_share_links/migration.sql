@@ -0,0 +1,15 @@
+-- CreateTable
+CREATE TABLE "share_link" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "object_id" INTEGER NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    "date_expires" DATETIME,
+    "date_revoked" DATETIME,
+    "redemptions" INTEGER NOT NULL DEFAULT 0,
+    "date_redeemed" DATETIME,
+    CONSTRAINT "share_link_object_id_fkey" FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "share_link_pub_id_key" ON "share_link"("pub_id");
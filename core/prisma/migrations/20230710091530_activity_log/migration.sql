@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE "activity_log" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "kind" INTEGER NOT NULL,
+    "details" BLOB,
+    "node_id" INTEGER,
+    "date_created" DATETIME NOT NULL,
+    CONSTRAINT "activity_log_node_id_fkey" FOREIGN KEY ("node_id") REFERENCES "node" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE INDEX "activity_log_date_created_idx" ON "activity_log"("date_created");
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "object" ADD COLUMN "date_deleted" DATETIME;
+
+-- AlterTable
+ALTER TABLE "tag" ADD COLUMN "date_deleted" DATETIME;
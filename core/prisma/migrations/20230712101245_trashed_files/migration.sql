@@ -0,0 +1,10 @@
+-- CreateTable
+CREATE TABLE "trashed_file" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "location_id" INTEGER NOT NULL,
+    "original_path" TEXT NOT NULL,
+    "trash_path" TEXT NOT NULL,
+    "is_dir" BOOLEAN NOT NULL,
+    "date_trashed" DATETIME NOT NULL,
+    CONSTRAINT "trashed_file_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,9 @@
+-- CreateTable
+CREATE TABLE "archive_source" (
+    "archive_id" INTEGER NOT NULL,
+    "source_id" INTEGER NOT NULL,
+
+    PRIMARY KEY ("archive_id", "source_id"),
+    CONSTRAINT "archive_source_archive_id_fkey" FOREIGN KEY ("archive_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "archive_source_source_id_fkey" FOREIGN KEY ("source_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
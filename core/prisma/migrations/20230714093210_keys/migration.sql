@@ -0,0 +1,45 @@
+-- CreateTable
+CREATE TABLE "key" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "uuid" TEXT NOT NULL,
+    "version" TEXT NOT NULL,
+    "key_type" TEXT NOT NULL,
+    "name" TEXT,
+    "default" BOOLEAN NOT NULL DEFAULT false,
+    "date_created" DATETIME DEFAULT CURRENT_TIMESTAMP,
+    "algorithm" TEXT NOT NULL,
+    "hashing_algorithm" TEXT NOT NULL,
+    "content_salt" BLOB NOT NULL,
+    "master_key" BLOB NOT NULL,
+    "master_key_nonce" BLOB NOT NULL,
+    "key_nonce" BLOB NOT NULL,
+    "key" BLOB NOT NULL,
+    "salt" BLOB NOT NULL,
+    "automount" BOOLEAN NOT NULL DEFAULT false
+);
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_object" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "kind" INTEGER,
+    "key_id" INTEGER,
+    "hidden" BOOLEAN,
+    "favorite" BOOLEAN,
+    "important" BOOLEAN,
+    "note" TEXT,
+    "date_created" DATETIME,
+    "date_accessed" DATETIME,
+    "date_deleted" DATETIME,
+    CONSTRAINT "object_key_id_fkey" FOREIGN KEY ("key_id") REFERENCES "key" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+INSERT INTO "new_object" ("date_accessed", "date_created", "date_deleted", "favorite", "hidden", "id", "important", "key_id", "kind", "note", "pub_id") SELECT "date_accessed", "date_created", "date_deleted", "favorite", "hidden", "id", "important", NULL, "kind", "note", "pub_id" FROM "object";
+DROP TABLE "object";
+ALTER TABLE "new_object" RENAME TO "object";
+CREATE UNIQUE INDEX "object_pub_id_key" ON "object"("pub_id");
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
+
+-- CreateIndex
+CREATE UNIQUE INDEX "key_uuid_key" ON "key"("uuid");
@@ -0,0 +1,9 @@
+-- CreateTable
+CREATE TABLE "derived_object" (
+    "derived_id" INTEGER NOT NULL,
+    "original_id" INTEGER NOT NULL,
+
+    PRIMARY KEY ("derived_id", "original_id"),
+    CONSTRAINT "derived_object_derived_id_fkey" FOREIGN KEY ("derived_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "derived_object_original_id_fkey" FOREIGN KEY ("original_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
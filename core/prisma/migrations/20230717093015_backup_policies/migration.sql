@@ -0,0 +1,32 @@
+-- CreateTable
+CREATE TABLE "backup_policy" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "tag_id" INTEGER,
+    "location_id" INTEGER,
+    "kind" INTEGER,
+    "target_location_id" INTEGER NOT NULL,
+    "target_path" TEXT NOT NULL,
+    "interval_hours" INTEGER NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    "date_last_run" DATETIME,
+    CONSTRAINT "backup_policy_tag_id_fkey" FOREIGN KEY ("tag_id") REFERENCES "tag" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "backup_policy_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "backup_policy_target_location_id_fkey" FOREIGN KEY ("target_location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateTable
+CREATE TABLE "object_backup" (
+    "policy_id" INTEGER NOT NULL,
+    "object_id" INTEGER NOT NULL,
+    "path" TEXT NOT NULL,
+    "date_backed_up" DATETIME NOT NULL,
+
+    PRIMARY KEY ("policy_id", "object_id"),
+    CONSTRAINT "object_backup_policy_id_fkey" FOREIGN KEY ("policy_id") REFERENCES "backup_policy" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "object_backup_object_id_fkey" FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "backup_policy_pub_id_key" ON "backup_policy"("pub_id");
@@ -0,0 +1,14 @@
+-- CreateTable
+CREATE TABLE "file_operation" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "batch_id" BLOB NOT NULL,
+    "kind" INTEGER NOT NULL,
+    "source" TEXT,
+    "destination" TEXT,
+    "state" BLOB,
+    "date_created" DATETIME NOT NULL,
+    "date_undone" DATETIME
+);
+
+-- CreateIndex
+CREATE INDEX "file_operation_batch_id_idx" ON "file_operation"("batch_id");
@@ -0,0 +1,10 @@
+-- CreateTable
+CREATE TABLE "plugin_metadata" (
+    "object_id" INTEGER NOT NULL,
+    "plugin_id" TEXT NOT NULL,
+    "data" BLOB NOT NULL,
+    "date_created" DATETIME NOT NULL,
+
+    PRIMARY KEY ("object_id", "plugin_id"),
+    CONSTRAINT "plugin_metadata_object_id_fkey" FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
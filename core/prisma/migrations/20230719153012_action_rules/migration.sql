@@ -0,0 +1,18 @@
+-- CreateTable
+CREATE TABLE "action_rule" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "enabled" BOOLEAN NOT NULL DEFAULT true,
+    "trigger" INTEGER NOT NULL,
+    "location_id" INTEGER,
+    "pattern" TEXT,
+    "job_name" TEXT,
+    "actions" BLOB NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    "date_last_run" DATETIME,
+    CONSTRAINT "action_rule_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "action_rule_pub_id_key" ON "action_rule"("pub_id");
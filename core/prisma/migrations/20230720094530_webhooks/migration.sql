@@ -0,0 +1,19 @@
+-- CreateTable
+CREATE TABLE "webhook" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "url" TEXT NOT NULL,
+    "enabled" BOOLEAN NOT NULL DEFAULT true,
+    "events" BLOB NOT NULL,
+    "location_id" INTEGER,
+    "tag_id" INTEGER,
+    "secret" TEXT,
+    "date_created" DATETIME NOT NULL,
+    "date_last_delivery" DATETIME,
+    "last_status" INTEGER,
+    CONSTRAINT "webhook_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "webhook_tag_id_fkey" FOREIGN KEY ("tag_id") REFERENCES "tag" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "webhook_pub_id_key" ON "webhook"("pub_id");
@@ -0,0 +1,11 @@
+-- CreateTable
+CREATE TABLE "catalog_metadata" (
+    "object_id" INTEGER NOT NULL,
+    "catalog" INTEGER NOT NULL,
+    "rating" INTEGER,
+    "edits" TEXT,
+    "date_imported" DATETIME NOT NULL,
+
+    PRIMARY KEY ("object_id", "catalog"),
+    CONSTRAINT "catalog_metadata_object_id_fkey" FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,19 @@
+-- CreateTable
+CREATE TABLE "webdav_share" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "location_id" INTEGER,
+    "tag_id" INTEGER,
+    "username" TEXT NOT NULL,
+    "password_hash" BLOB NOT NULL,
+    "date_created" DATETIME NOT NULL,
+    CONSTRAINT "webdav_share_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "webdav_share_tag_id_fkey" FOREIGN KEY ("tag_id") REFERENCES "tag" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "webdav_share_pub_id_key" ON "webdav_share"("pub_id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "webdav_share_name_key" ON "webdav_share"("name");
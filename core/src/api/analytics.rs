@@ -0,0 +1,44 @@
+use crate::node::AnalyticsEvent;
+
+use std::path::PathBuf;
+
+use rspc::{alpha::AlphaRouter, ErrorCode};
+use tracing::error;
+
+use super::{Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("get", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.analytics.report()) })
+		})
+		.procedure("setEnabled", {
+			R.mutation(|ctx, enabled: bool| async move {
+				ctx.config
+					.write(|mut config| config.analytics_enabled = enabled)
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.analytics.set_enabled(enabled);
+
+				Ok(())
+			})
+		})
+		.procedure("record", {
+			// Ignored while the analytics are turned off
+			R.mutation(|ctx, event: AnalyticsEvent| async move { Ok(ctx.analytics.record(event)?) })
+		})
+		.procedure("export", {
+			// Writes the analytics as JSON to a file of this node
+			R.mutation(|ctx, path: PathBuf| async move { Ok(ctx.analytics.export(&path).await?) })
+		})
+		.procedure("clear", {
+			R.mutation(|ctx, _: ()| async move { Ok(ctx.analytics.clear().await?) })
+		})
+}
@@ -0,0 +1,29 @@
+use crate::node::TokenScope;
+
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::{Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.api_tokens().list().await) })
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateApiTokenArgs {
+				pub name: String,
+				pub scopes: Vec<TokenScope>,
+			}
+
+			R.mutation(|ctx, args: CreateApiTokenArgs| async move {
+				Ok(ctx.api_tokens().create(args.name, args.scopes).await?)
+			})
+		})
+		.procedure("revoke", {
+			R.mutation(|ctx, id: Uuid| async move { Ok(ctx.api_tokens().revoke(id).await?) })
+		})
+}
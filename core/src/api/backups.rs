@@ -0,0 +1,311 @@
+use crate::{
+	invalidate_query,
+	library::backup::{
+		find_policy, list_snapshots, unprotected_object_filters, BackupError, BackupJobInit,
+		BackupKey, BackupRetention, BackupSnapshot, BackupTarget, BackupTargetKind,
+		PolicyBackupJobInit, SanitisedBackupTarget,
+	},
+	location::{file_path_helper::ensure_sub_path_is_directory, LocationError},
+	prisma::{backup_policy, location, object, object_backup, tag},
+};
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use rspc::alpha::AlphaRouter;
+use sd_crypto::Protected;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("targets", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.config
+					.backup_targets
+					.iter()
+					.map(SanitisedBackupTarget::from)
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("addTarget", {
+			#[derive(Type, Deserialize)]
+			pub struct AddBackupTargetArgs {
+				pub name: String,
+				pub kind: BackupTargetKind,
+				/// Which snapshots are kept on the target, defaults to 7 daily and 4 weekly ones
+				pub retention: Option<BackupRetention>,
+				/// Whether the library is backed up to the target once a day
+				pub automatic: bool,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: AddBackupTargetArgs| async move {
+					// Fail early on targets we can't reach, instead of on the first backup
+					list_snapshots(args.kind.store()?.as_ref(), Some(library.id)).await?;
+
+					let id = Uuid::new_v4();
+					ctx.library_manager
+						.add_backup_target(
+							library.id,
+							BackupTarget {
+								id,
+								name: args.name,
+								kind: args.kind,
+								retention: args.retention.unwrap_or_default(),
+								automatic: args.automatic,
+							},
+						)
+						.await?;
+
+					invalidate_query!(library, "backups.targets");
+
+					Ok(id)
+				})
+		})
+		.procedure("editTarget", {
+			#[derive(Type, Deserialize)]
+			pub struct EditBackupTargetArgs {
+				pub target_id: Uuid,
+				pub retention: Option<BackupRetention>,
+				pub automatic: Option<bool>,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: EditBackupTargetArgs| async move {
+					ctx.library_manager
+						.edit_backup_target(
+							library.id,
+							args.target_id,
+							args.retention,
+							args.automatic,
+						)
+						.await?;
+
+					invalidate_query!(library, "backups.targets");
+
+					Ok(())
+				})
+		})
+		.procedure("removeTarget", {
+			R.with2(library())
+				.mutation(|(ctx, library), target_id: Uuid| async move {
+					ctx.library_manager
+						.remove_backup_target(library.id, target_id)
+						.await?;
+
+					invalidate_query!(library, "backups.targets");
+
+					Ok(())
+				})
+		})
+		.procedure("setPassword", {
+			#[derive(Type, Deserialize)]
+			pub struct SetBackupPasswordArgs {
+				pub password: Protected<String>,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: SetBackupPasswordArgs| async move {
+					let key = BackupKey::new(args.password).await?;
+
+					Ok(ctx.library_manager.set_backup_key(library.id, key).await?)
+				})
+		})
+		.procedure("create", {
+			R.with2(library())
+				.mutation(|(_, library), args: BackupJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("snapshots", {
+			R.with2(library())
+				.query(|(_, library), target_id: Uuid| async move {
+					let target = library
+						.config
+						.backup_targets
+						.iter()
+						.find(|target| target.id == target_id)
+						.ok_or(BackupError::TargetNotFound(target_id))?;
+
+					Ok(list_snapshots(target.kind.store()?.as_ref(), Some(library.id)).await?)
+				})
+		})
+		.procedure("findSnapshots", {
+			// The snapshots of every library on the target, to restore them on a node that doesn't have
+			// the library yet
+			R.query(|_, target: BackupTargetKind| async move {
+				Ok(list_snapshots(target.store()?.as_ref(), None).await?)
+			})
+		})
+		.procedure("restore", {
+			#[derive(Type, Deserialize)]
+			pub struct RestoreBackupArgs {
+				pub target: BackupTargetKind,
+				pub snapshot: String,
+				pub password: Protected<String>,
+			}
+
+			R.mutation(|ctx, args: RestoreBackupArgs| async move {
+				let snapshot = BackupSnapshot::from_name(&args.snapshot)?;
+
+				Ok(ctx
+					.library_manager
+					.restore_backup(&args.target, &snapshot, args.password)
+					.await?)
+			})
+		})
+		.procedure("policies", {
+			/// A backup policy, with how many of its objects are backed up and how many aren't yet
+			#[derive(Serialize, Type)]
+			pub struct BackupPolicyStatus {
+				pub policy: backup_policy::Data,
+				pub backed_up: u32,
+				pub unprotected: u32,
+			}
+
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				let mut policies = vec![];
+
+				for policy in library.db.backup_policy().find_many(vec![]).exec().await? {
+					let backed_up = library
+						.db
+						.object_backup()
+						.count(vec![object_backup::policy_id::equals(policy.id)])
+						.exec()
+						.await? as u32;
+					let unprotected = library
+						.db
+						.object()
+						.count(unprotected_object_filters(&policy))
+						.exec()
+						.await? as u32;
+
+					policies.push(BackupPolicyStatus {
+						policy,
+						backed_up,
+						unprotected,
+					});
+				}
+
+				Ok(policies)
+			})
+		})
+		.procedure("createPolicy", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateBackupPolicyArgs {
+				pub name: String,
+				/// Backs up the objects with this tag
+				pub tag_id: Option<tag::id::Type>,
+				/// Backs up the objects with files in this location
+				pub location_id: Option<location::id::Type>,
+				/// Backs up the objects of this kind, from `ObjectKind`
+				pub kind: Option<i32>,
+				/// The location the objects are backed up to, and the existing directory in it
+				pub target_location_id: location::id::Type,
+				pub target_path: PathBuf,
+				/// How often new objects are backed up, in hours
+				pub interval_hours: u32,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: CreateBackupPolicyArgs| async move {
+					if args.tag_id.is_none() && args.location_id.is_none() && args.kind.is_none() {
+						return Err(BackupError::EmptyPolicy.into());
+					}
+
+					let target_location_path = library
+						.db
+						.location()
+						.find_unique(location::id::equals(args.target_location_id))
+						.select(location::select!({ path }))
+						.exec()
+						.await?
+						.ok_or(LocationError::IdNotFound(args.target_location_id))?
+						.path
+						.ok_or(LocationError::MissingPath(args.target_location_id))?;
+					ensure_sub_path_is_directory(&target_location_path, &args.target_path)
+						.await
+						.map_err(LocationError::from)?;
+
+					let policy = library
+						.db
+						.backup_policy()
+						.create(
+							Uuid::new_v4().as_bytes().to_vec(),
+							args.name,
+							location::id::equals(args.target_location_id),
+							args.target_path.to_string_lossy().into_owned(),
+							args.interval_hours.clamp(1, i32::MAX as u32) as i32,
+							Utc::now().into(),
+							vec![
+								backup_policy::tag_id::set(args.tag_id),
+								backup_policy::location_id::set(args.location_id),
+								backup_policy::kind::set(args.kind),
+							],
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "backups.policies");
+
+					Ok(policy.id)
+				})
+		})
+		.procedure("deletePolicy", {
+			// The copies made by the policy are kept
+			R.with2(library()).mutation(
+				|(_, library), policy_id: backup_policy::id::Type| async move {
+					library
+						.db
+						.backup_policy()
+						.delete(backup_policy::id::equals(policy_id))
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "backups.policies");
+
+					Ok(())
+				},
+			)
+		})
+		.procedure("runPolicy", {
+			R.with2(library())
+				.mutation(|(_, library), args: PolicyBackupJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("unprotectedObjects", {
+			// The objects of a policy that aren't backed up yet, or of every policy if `null`
+			R.with2(library()).query(
+				|(_, library), policy_id: Option<backup_policy::id::Type>| async move {
+					let policies = match policy_id {
+						Some(policy_id) => vec![find_policy(&library, policy_id).await?],
+						None => library.db.backup_policy().find_many(vec![]).exec().await?,
+					};
+
+					let mut objects = vec![];
+					for policy in &policies {
+						objects.extend(
+							library
+								.db
+								.object()
+								.find_many(unprotected_object_filters(policy))
+								.exec()
+								.await?,
+						);
+					}
+
+					// An object matching many policies is listed once
+					objects.sort_by_key(|object: &object::Data| object.id);
+					objects.dedup_by_key(|object| object.id);
+
+					Ok(objects)
+				},
+			)
+		})
+}
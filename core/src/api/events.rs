@@ -0,0 +1,108 @@
+//! One stream of the events of the core, for clients that aren't the app, like scripts and other
+//! programs driving a server. Like every subscription, it's served over the websocket of rspc, at
+//! `/rspc/ws`, and only the topics a client asks for are sent to it.
+
+use crate::{job::JobProgressEvent, sync::SyncMessage};
+
+use rspc::alpha::AlphaRouter;
+use sd_sync::CRDTOperation;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{utils::library, CoreEvent, Ctx, R};
+
+#[derive(Serialize, Deserialize, Type, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventTopic {
+	JobProgress,
+	ThumbnailReady,
+	LocationsOnline,
+	SyncActivity,
+}
+
+#[derive(Serialize, Type, Debug)]
+#[serde(tag = "topic", content = "data")]
+pub enum Event {
+	JobProgress(JobProgressEvent),
+	ThumbnailReady {
+		thumb_key: Vec<String>,
+	},
+	/// The pub ids of all the locations online, each time one goes online or offline
+	LocationsOnline {
+		location_pub_ids: Vec<Vec<u8>>,
+	},
+	/// An operation made by this node, or received from another one
+	SyncActivity {
+		ingested: bool,
+		operation: CRDTOperation,
+	},
+}
+
+impl Event {
+	fn topic(&self) -> EventTopic {
+		match self {
+			Self::JobProgress(_) => EventTopic::JobProgress,
+			Self::ThumbnailReady { .. } => EventTopic::ThumbnailReady,
+			Self::LocationsOnline { .. } => EventTopic::LocationsOnline,
+			Self::SyncActivity { .. } => EventTopic::SyncActivity,
+		}
+	}
+}
+
+#[derive(Deserialize, Type, Debug)]
+pub struct EventsArgs {
+	/// The topics to send, all of them when empty
+	#[serde(default)]
+	#[specta(optional)]
+	pub topics: Vec<EventTopic>,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router().procedure("listen", {
+		R.with2(library())
+			.subscription(|(ctx, library), args: EventsArgs| async move {
+				let mut event_bus_rx = ctx.event_bus.0.subscribe();
+				let mut online_rx = ctx.location_manager.online_rx();
+				let mut sync_rx = library.sync.tx.subscribe();
+
+				async_stream::stream! {
+					loop {
+						// Events missed by a client too slow to keep up are skipped
+						let event = tokio::select! {
+							event = event_bus_rx.recv() => match event {
+								Ok(CoreEvent::JobProgress(progress)) => Event::JobProgress(progress),
+								Ok(CoreEvent::NewThumbnail { thumb_key }) => {
+									Event::ThumbnailReady { thumb_key }
+								}
+								Ok(_) | Err(RecvError::Lagged(_)) => continue,
+								Err(RecvError::Closed) => break,
+							},
+							locations = online_rx.recv() => match locations {
+								Ok(locations) => Event::LocationsOnline {
+									location_pub_ids: locations.into_iter().collect(),
+								},
+								Err(RecvError::Lagged(_)) => continue,
+								Err(RecvError::Closed) => break,
+							},
+							message = sync_rx.recv() => match message {
+								Ok(SyncMessage::Ingested(operation)) => Event::SyncActivity {
+									ingested: true,
+									operation,
+								},
+								Ok(SyncMessage::Created(operation)) => Event::SyncActivity {
+									ingested: false,
+									operation,
+								},
+								Err(RecvError::Lagged(_)) => continue,
+								Err(RecvError::Closed) => break,
+							},
+						};
+
+						if args.topics.is_empty() || args.topics.contains(&event.topic()) {
+							yield event;
+						}
+					}
+				}
+			})
+	})
+}
@@ -4,15 +4,37 @@ use crate::{
 	library::Library,
 	location::{
 		file_path_helper::{
-			file_path_to_isolate, file_path_to_isolate_with_id, FilePathError, IsolatedFilePathData,
+			file_path_to_isolate, file_path_to_isolate_with_id, join_location_relative_path,
+			FilePathError, IsolatedFilePathData,
 		},
 		find_location, LocationError,
 	},
-	object::fs::{
-		copy::FileCopierJobInit, cut::FileCutterJobInit, delete::FileDeleterJobInit,
-		erase::FileEraserJobInit,
+	object::{
+		fs::{
+			archive::{extract::FileExtractorJobInit, FileArchiverJobInit},
+			batch_rename::{self, BatchRenameJobInit},
+			convert::ImageConverterJobInit,
+			copy::FileCopierJobInit,
+			cut::FileCutterJobInit,
+			decrypt::FileDecryptorJobInit,
+			dedup::FileDeduplicatorJobInit,
+			delete::FileDeleterJobInit,
+			encrypt::FileEncryptorJobInit,
+			erase::FileEraserJobInit,
+			inbox::{self, InboxArgs},
+			mirror::MirrorJobInit,
+			mover::FileMoverJobInit,
+			os_trash,
+			split::{join::FileJoinerJobInit, FileSplitterJobInit},
+			transcode::VideoTranscoderJobInit,
+		},
+		metadata_export::MetadataExportJobInit,
+		preview::{get_text_preview, get_video_sprite, get_waveform},
+	},
+	prisma::{
+		archive_source, derived_object, file_path, location, object, trashed_file, SortOrder,
 	},
-	prisma::{file_path, location, object},
+	sync,
 };
 
 use std::path::Path;
@@ -22,6 +44,7 @@ use futures::future::join_all;
 use regex::Regex;
 use rspc::{alpha::AlphaRouter, ErrorCode};
 use serde::Deserialize;
+use serde_json::json;
 use specta::Type;
 use tokio::fs;
 use tracing::error;
@@ -41,11 +64,59 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 						.db
 						.object()
 						.find_unique(object::id::equals(args.id))
-						.include(object::include!({ file_paths media_data }))
+						.include(object::include!({ file_paths media_data catalog_metadata }))
 						.exec()
 						.await?)
 				})
 		})
+		.procedure("getWaveform", {
+			R.with2(library())
+				.query(|(_, library), object_id: i32| async move {
+					let Some(cas_id) = get_object_cas_id(&library, object_id).await? else {
+						return Ok(None);
+					};
+
+					get_waveform(&library, &cas_id).await.map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::InternalServerError,
+							"Failed to read waveform".to_string(),
+							e,
+						)
+					})
+				})
+		})
+		.procedure("getTextPreview", {
+			R.with2(library())
+				.query(|(_, library), object_id: i32| async move {
+					let Some(cas_id) = get_object_cas_id(&library, object_id).await? else {
+						return Ok(None);
+					};
+
+					get_text_preview(&library, &cas_id).await.map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::InternalServerError,
+							"Failed to read text preview".to_string(),
+							e,
+						)
+					})
+				})
+		})
+		.procedure("getVideoSprite", {
+			R.with2(library())
+				.query(|(_, library), object_id: i32| async move {
+					let Some(cas_id) = get_object_cas_id(&library, object_id).await? else {
+						return Ok(None);
+					};
+
+					get_video_sprite(&library, &cas_id).await.map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::InternalServerError,
+							"Failed to read video sprite".to_string(),
+							e,
+						)
+					})
+				})
+		})
 		.procedure("setNote", {
 			#[derive(Type, Deserialize)]
 			pub struct SetNoteArgs {
@@ -55,15 +126,23 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 
 			R.with2(library())
 				.mutation(|(_, library), args: SetNoteArgs| async move {
-					library
-						.db
-						.object()
-						.update(
+					let Library { db, sync, .. } = &library;
+
+					let object = get_object_pub_id(&library, args.id).await?;
+
+					sync.write_op(
+						db,
+						sync.shared_update(
+							sync::object::SyncId { pub_id: object },
+							object::note::NAME,
+							json!(&args.note),
+						),
+						db.object().update(
 							object::id::equals(args.id),
 							vec![object::note::set(args.note)],
-						)
-						.exec()
-						.await?;
+						),
+					)
+					.await?;
 
 					invalidate_query!(library, "search.paths");
 					invalidate_query!(library, "search.objects");
@@ -80,15 +159,23 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 
 			R.with2(library())
 				.mutation(|(_, library), args: SetFavoriteArgs| async move {
-					library
-						.db
-						.object()
-						.update(
+					let Library { db, sync, .. } = &library;
+
+					let object = get_object_pub_id(&library, args.id).await?;
+
+					sync.write_op(
+						db,
+						sync.shared_update(
+							sync::object::SyncId { pub_id: object },
+							object::favorite::NAME,
+							json!(args.favorite),
+						),
+						db.object().update(
 							object::id::equals(args.id),
 							vec![object::favorite::set(Some(args.favorite))],
-						)
-						.exec()
-						.await?;
+						),
+					)
+					.await?;
 
 					invalidate_query!(library, "search.paths");
 					invalidate_query!(library, "search.objects");
@@ -130,24 +217,77 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					Ok(())
 				})
 		})
-		// .procedure("encryptFiles", {
-		// 	R.with2(library())
-		// 		.mutation(|(_, library), args: FileEncryptorJobInit| async move {
-		// 			library.spawn_job(args).await.map_err(Into::into)
-		// 		})
-		// })
-		// .procedure("decryptFiles", {
-		// 	R.with2(library())
-		// 		.mutation(|(_, library), args: FileDecryptorJobInit| async move {
-		// 			library.spawn_job(args).await.map_err(Into::into)
-		// 		})
-		// })
+		.procedure("encryptFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileEncryptorJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("decryptFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileDecryptorJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
 		.procedure("deleteFiles", {
 			R.with2(library())
 				.mutation(|(_, library), args: FileDeleterJobInit| async move {
 					library.spawn_job(args).await.map_err(Into::into)
 				})
 		})
+		.procedure("trashed", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.trashed_file()
+					.find_many(vec![])
+					.order_by(trashed_file::date_trashed::order(SortOrder::Desc))
+					.exec()
+					.await?)
+			})
+		})
+		.procedure("restoreTrashed", {
+			R.with2(library()).mutation(
+				|(_, library), ids: Vec<trashed_file::id::Type>| async move {
+					let trashed_files = library
+						.db
+						.trashed_file()
+						.find_many(vec![trashed_file::id::in_vec(ids)])
+						.include(trashed_file::include!({ location: select { path } }))
+						.exec()
+						.await?;
+
+					for trashed_file in trashed_files {
+						let location_path = trashed_file
+							.location
+							.path
+							.ok_or(LocationError::MissingPath(trashed_file.location_id))?;
+
+						// The watcher indexes the file again once it's back
+						os_trash::restore(
+							Path::new(&trashed_file.trash_path),
+							&join_location_relative_path(
+								location_path,
+								&trashed_file.original_path,
+							),
+						)
+						.await?;
+
+						library
+							.db
+							.trashed_file()
+							.delete(trashed_file::id::equals(trashed_file.id))
+							.exec()
+							.await?;
+					}
+
+					invalidate_query!(library, "files.trashed");
+					invalidate_query!(library, "search.paths");
+
+					Ok(())
+				},
+			)
+		})
 		.procedure("eraseFiles", {
 			R.with2(library())
 				.mutation(|(_, library), args: FileEraserJobInit| async move {
@@ -172,6 +312,110 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					library.spawn_job(args).await.map_err(Into::into)
 				})
 		})
+		.procedure("moveFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileMoverJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("previewBatchRename", {
+			R.with2(library())
+				.query(|(_, library), args: BatchRenameJobInit| async move {
+					Ok(batch_rename::preview(&library.db, &args).await?)
+				})
+		})
+		.procedure("batchRename", {
+			R.with2(library())
+				.mutation(|(_, library), args: BatchRenameJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("archiveFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileArchiverJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("extractFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileExtractorJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("deduplicateFiles", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileDeduplicatorJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("convertImages", {
+			R.with2(library())
+				.mutation(|(_, library), args: ImageConverterJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("transcodeVideos", {
+			R.with2(library())
+				.mutation(|(_, library), args: VideoTranscoderJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("mirror", {
+			R.with2(library())
+				.mutation(|(_, library), args: MirrorJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("splitFile", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileSplitterJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("joinFile", {
+			R.with2(library())
+				.mutation(|(_, library), args: FileJoinerJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("ingest", {
+			R.with2(library())
+				.mutation(|(_, library), args: InboxArgs| async move {
+					Ok(inbox::ingest(&library, args).await?)
+				})
+		})
+		.procedure("exportMetadata", {
+			R.with2(library())
+				.mutation(|(_, library), args: MetadataExportJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("getDerivedObjects", {
+			R.with2(library())
+				.query(|(_, library), original_id: object::id::Type| async move {
+					Ok(library
+						.db
+						.object()
+						.find_many(vec![object::derived_from::some(vec![
+							derived_object::original_id::equals(original_id),
+						])])
+						.exec()
+						.await?)
+				})
+		})
+		.procedure("getArchiveSources", {
+			R.with2(library())
+				.query(|(_, library), archive_id: object::id::Type| async move {
+					Ok(library
+						.db
+						.object()
+						.find_many(vec![object::archived_in::some(vec![
+							archive_source::archive_id::equals(archive_id),
+						])])
+						.exec()
+						.await?)
+				})
+		})
 		.procedure("renameFile", {
 			#[derive(Type, Deserialize)]
 			pub struct FromPattern {
@@ -384,3 +628,39 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 				})
 		})
 }
+
+/// Previews are stored by content, so any of the object's file paths will do
+async fn get_object_cas_id(
+	library: &Library,
+	object_id: object::id::Type,
+) -> Result<Option<String>, rspc::Error> {
+	Ok(library
+		.db
+		.file_path()
+		.find_first(vec![
+			file_path::object_id::equals(Some(object_id)),
+			file_path::cas_id::not(None),
+		])
+		.select(file_path::select!({ cas_id }))
+		.exec()
+		.await?
+		.and_then(|file_path| file_path.cas_id))
+}
+
+async fn get_object_pub_id(
+	library: &Library,
+	object_id: object::id::Type,
+) -> Result<Vec<u8>, rspc::Error> {
+	library
+		.db
+		.object()
+		.find_unique(object::id::equals(object_id))
+		.select(object::select!({ pub_id }))
+		.exec()
+		.await?
+		.map(|object| object.pub_id)
+		.ok_or(rspc::Error::new(
+			ErrorCode::NotFound,
+			"Error finding object in db".into(),
+		))
+}
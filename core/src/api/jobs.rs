@@ -1,17 +1,18 @@
 use crate::{
 	invalidate_query,
-	job::{job_without_data, JobManager, JobReport, JobStatus},
+	job::{job_without_data, JobManager, JobReport, JobStatus, MESSAGES},
 	location::{find_location, LocationError},
+	node::logs::Logs,
 	object::{
 		file_identifier::file_identifier_job::FileIdentifierJobInit,
 		preview::thumbnailer_job::ThumbnailerJobInit,
-		validation::validator_job::ObjectValidatorJobInit,
+		validation::{validator_job::ObjectValidatorJobInit, verifier_job::ObjectVerifierJobInit},
 	},
 	prisma::{job, location, SortOrder},
 };
 
 use std::{
-	collections::{hash_map::Entry, HashMap, VecDeque},
+	collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
 	path::PathBuf,
 };
 
@@ -20,7 +21,10 @@ use prisma_client_rust::or;
 use rspc::alpha::AlphaRouter;
 use serde::{Deserialize, Serialize};
 use specta::Type;
-use tokio::time::{interval, Duration};
+use tokio::{
+	sync::broadcast::error::RecvError,
+	time::{interval, Duration},
+};
 use tracing::{info, trace};
 use uuid::Uuid;
 
@@ -62,6 +66,39 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					}
 				})
 		})
+		.procedure("logs", {
+			// The last lines the job logged, then the ones it logs from now on
+			R.with2(library())
+				.subscription(|(_, library), job_id: Uuid| async move {
+					let logs = Logs::get()?;
+					// Before reading the tail, not to miss what's logged in between
+					let mut job_lines_rx = logs.job_lines();
+					let tail = logs.job_tail(job_id).await?;
+
+					let last_sent = tail.last().map(|line| line.timestamp);
+
+					Ok(async_stream::stream! {
+						for line in tail {
+							yield line;
+						}
+
+						loop {
+							match job_lines_rx.recv().await {
+								Ok(line)
+									if line.job_id == Some(job_id)
+										&& line.library_id == Some(library.id)
+										&& last_sent.map_or(true, |last_sent| line.timestamp > last_sent) =>
+								{
+									yield line;
+								}
+								// Lines missed by a client too slow to keep up are skipped
+								Ok(_) | Err(RecvError::Lagged(_)) => continue,
+								Err(RecvError::Closed) => break,
+							}
+						}
+					})
+				})
+		})
 		.procedure("reports", {
 			// Reports provides the client with a list of JobReports
 			// - we query with a custom select! to avoid returning paused job cache `job.data`
@@ -174,6 +211,15 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					})
 				})
 		})
+		.procedure("messages", {
+			// The English text of the codes of the job messages, to translate them from
+			R.query(|_, _: ()| async move {
+				Ok(MESSAGES
+					.iter()
+					.map(|(code, text)| (code.to_string(), text.to_string()))
+					.collect::<BTreeMap<_, _>>())
+			})
+		})
 		.procedure("isActive", {
 			R.with2(library()).query(|(ctx, _), _: ()| async move {
 				Ok(ctx.job_manager.has_active_workers().await)
@@ -249,6 +295,7 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			pub struct GenerateThumbsForLocationArgs {
 				pub id: location::id::Type,
 				pub path: PathBuf,
+				pub regenerate: bool,
 			}
 
 			R.with2(library()).mutation(
@@ -261,12 +308,42 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 						.spawn_job(ThumbnailerJobInit {
 							location,
 							sub_path: Some(args.path),
+							regenerate: args.regenerate,
 						})
 						.await
 						.map_err(Into::into)
 				},
 			)
 		})
+		.procedure("checkThumbnailIntegrity", {
+			R.with2(library())
+				.mutation(|(ctx, library), _: ()| async move {
+					// Thumbnails are shared by all libraries, so a thumbnail that isn't used by this
+					// library could still be used by another one
+					let prune_orphans =
+						ctx.library_manager.get_all_libraries_config().await.len() == 1;
+
+					library
+						.spawn_job(ThumbnailIntegrityJobInit { prune_orphans })
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("prioritizeThumbnails", {
+			// Thumbnails for the items visible on screen, generated ahead of the thumbnailer jobs.
+			// The ones of files on other nodes are fetched from them instead.
+			R.with2(library())
+				.mutation(|(ctx, library), cas_ids: Vec<String>| async move {
+					ctx.p2p.request_thumbnails(library.clone(), cas_ids.clone());
+
+					library
+						.thumbnail_priority()
+						.request(library.clone(), cas_ids)
+						.await;
+
+					Ok(())
+				})
+		})
 		.procedure("objectValidator", {
 			#[derive(Type, Deserialize)]
 			pub struct ObjectValidatorArgs {
@@ -276,10 +353,7 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 
 			R.with2(library())
 				.mutation(|(_, library), args: ObjectValidatorArgs| async move {
-					let Some(location) =  find_location(&library, args.id)
-						.exec()
-						.await?
-						else {
+					let Some(location) = find_location(&library, args.id).exec().await? else {
 						return Err(LocationError::IdNotFound(args.id).into());
 					};
 
@@ -292,6 +366,12 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 						.map_err(Into::into)
 				})
 		})
+		.procedure("objectVerifier", {
+			R.with2(library())
+				.mutation(|(_, library), args: ObjectVerifierJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
 		.procedure("identifyUniqueFiles", {
 			#[derive(Type, Deserialize)]
 			pub struct IdentifyUniqueFilesArgs {
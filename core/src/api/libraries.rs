@@ -1,15 +1,29 @@
 use crate::{
-	library::LibraryConfig,
+	library::{
+		activity::{self, ActivityPageArgs},
+		catalog_import::CatalogImportJobInit,
+		cleanup::OrphanCleanupJobInit,
+		export,
+		integrity::{IntegrityCheckJobInit, IntegrityRepairJobInit},
+		maintenance::MaintenanceJobInit,
+		merge::LibraryMergeJobInit,
+		LibraryConfig, LibraryOverview, LibrarySettingsPatch,
+	},
+	object::preview::{enforce_preview_media_budget, preview_media_usage},
 	prisma::statistics,
+	sync::ConflictPolicy,
 	util::MaybeUndefined,
 	volume::{get_volumes, save_volume},
 };
 
+use std::{collections::HashMap, path::PathBuf};
+
 use chrono::Utc;
 use rspc::alpha::AlphaRouter;
+use sd_crypto::Protected;
 use serde::Deserialize;
 use specta::Type;
-use tracing::debug;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
 use super::{
@@ -26,99 +40,300 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 		})
 		.procedure("statistics", {
 			R.with2(library()).query(|(_, library), _: ()| async move {
-				let _statistics = library
-					.db
-					.statistics()
-					.find_unique(statistics::id::equals(library.node_local_id))
-					.exec()
-					.await?;
-
-				// TODO: get from database, not sys
-				let volumes = get_volumes();
-				save_volume(&library).await?;
-
-				let mut available_capacity: u64 = 0;
-				let mut total_capacity: u64 = 0;
-
-				if let Ok(volumes) = volumes {
-					for volume in volumes {
-						total_capacity += volume.total_capacity;
-						available_capacity += volume.available_capacity;
-					}
-				}
+				// Measuring the volumes and the sizes of the library is slow, and they rarely change
+				library
+					.queries
+					.get_or_query("library.statistics", &["library.statistics"], || async {
+						let _statistics = library
+							.db
+							.statistics()
+							.find_unique(statistics::id::equals(library.node_local_id))
+							.exec()
+							.await?;
 
-				let library_db_size = get_size(
-					library
-						.config()
-						.data_directory()
-						.join("libraries")
-						.join(&format!("{}.db", library.id)),
-				)
-				.await
-				.unwrap_or(0);
-
-				let thumbnail_folder_size =
-					get_size(library.config().data_directory().join("thumbnails"))
+						// TODO: get from database, not sys
+						let volumes = get_volumes();
+						save_volume(&library).await?;
+
+						let mut available_capacity: u64 = 0;
+						let mut total_capacity: u64 = 0;
+
+						if let Ok(volumes) = volumes {
+							for volume in volumes {
+								total_capacity += volume.total_capacity;
+								available_capacity += volume.available_capacity;
+							}
+						}
+
+						let library_db_size = get_size(
+							library
+								.config()
+								.data_directory()
+								.join("libraries")
+								.join(&format!("{}.db", library.id)),
+						)
 						.await
 						.unwrap_or(0);
 
-				use statistics::*;
-				let params = vec![
-					id::set(1), // Each library is a database so only one of these ever exists
-					date_captured::set(Utc::now().into()),
-					total_object_count::set(0),
-					library_db_size::set(library_db_size.to_string()),
-					total_bytes_used::set(0.to_string()),
-					total_bytes_capacity::set(total_capacity.to_string()),
-					total_unique_bytes::set(0.to_string()),
-					total_bytes_free::set(available_capacity.to_string()),
-					preview_media_bytes::set(thumbnail_folder_size.to_string()),
-				];
-
-				Ok(library
-					.db
-					.statistics()
-					.upsert(
-						statistics::id::equals(1), // Each library is a database so only one of these ever exists
-						statistics::create(params.clone()),
-						params,
-					)
-					.exec()
-					.await?)
+						let preview_media_size = preview_media_usage(&library).await?.total();
+
+						use statistics::*;
+						let params = vec![
+							id::set(1), // Each library is a database so only one of these ever exists
+							date_captured::set(Utc::now().into()),
+							total_object_count::set(0),
+							library_db_size::set(library_db_size.to_string()),
+							total_bytes_used::set(0.to_string()),
+							total_bytes_capacity::set(total_capacity.to_string()),
+							total_unique_bytes::set(0.to_string()),
+							total_bytes_free::set(available_capacity.to_string()),
+							preview_media_bytes::set(preview_media_size.to_string()),
+						];
+
+						Ok::<_, rspc::Error>(
+							library
+								.db
+								.statistics()
+								.upsert(
+									statistics::id::equals(1), // Each library is a database so only one of these ever exists
+									statistics::create(params.clone()),
+									params,
+								)
+								.exec()
+								.await?,
+						)
+					})
+					.await
 			})
 		})
+		.procedure("overview", {
+			R.with2(library())
+				.query(|(ctx, library), _: ()| async move {
+					Ok(LibraryOverview::get(&library, &ctx.p2p).await?)
+				})
+		})
+		.procedure("activity", {
+			R.with2(library())
+				.query(|(_, library), args: ActivityPageArgs| async move {
+					Ok(activity::page(&library, args).await?)
+				})
+		})
 		.procedure("create", {
 			#[derive(Deserialize, Type)]
 			pub struct CreateLibraryArgs {
 				name: String,
+				/// Encrypts the database of the library on disk, with a key unlocked by this password
+				password: Option<Protected<String>>,
+				/// Keeps the key of the database in the OS keychain, to unlock the library on startup
+				remember_password: bool,
 			}
 
 			R.mutation(|ctx, args: CreateLibraryArgs| async move {
 				debug!("Creating library");
 
-				let new_library = ctx
-					.library_manager
-					.create(
-						LibraryConfig::new(args.name.to_string(), ctx.config.get().await.id),
-						ctx.config.get().await,
-					)
-					.await?;
+				let config = LibraryConfig::new(args.name.to_string(), ctx.config.get().await.id);
+
+				let new_library = match args.password {
+					Some(password) => {
+						ctx.library_manager
+							.create_encrypted(
+								config,
+								ctx.config.get().await,
+								password,
+								args.remember_password,
+							)
+							.await?
+					}
+					None => {
+						ctx.library_manager
+							.create(config, ctx.config.get().await)
+							.await?
+					}
+				};
 
 				Ok(new_library)
 			})
 		})
+		.procedure("settings", {
+			R.with2(library())
+				.query(|(_, library), _: ()| async move { Ok(library.config.settings.clone()) })
+		})
+		.procedure("patchSettings", {
+			R.with2(library())
+				.mutation(|(ctx, library), patch: LibrarySettingsPatch| async move {
+					let settings = ctx
+						.library_manager
+						.patch_settings(library.id, patch)
+						.await?;
+
+					// A lower limit evicts preview media right away, not after the next thumbnailer
+					if let Some(max_size_mb) = settings.preview_media_max_size_mb {
+						tokio::spawn(async move {
+							if let Err(e) =
+								enforce_preview_media_budget(&library, max_size_mb).await
+							{
+								warn!(
+									"Failed to keep the preview media under their size limit: {e}"
+								);
+							}
+						});
+					}
+
+					Ok(settings)
+				})
+		})
+		.procedure("clone", {
+			R.with2(library())
+				.mutation(|(ctx, library), name: Option<String>| async move {
+					Ok(ctx.library_manager.clone_library(library.id, name).await?)
+				})
+		})
 		.procedure("edit", {
 			#[derive(Type, Deserialize)]
 			pub struct EditLibraryArgs {
 				pub id: Uuid,
 				pub name: Option<String>,
 				pub description: MaybeUndefined<String>,
+				pub sync_conflict_policy: Option<ConflictPolicy>,
 			}
 
 			R.mutation(|ctx, args: EditLibraryArgs| async move {
 				Ok(ctx
 					.library_manager
-					.edit(args.id, args.name, args.description)
+					.edit(
+						args.id,
+						args.name,
+						args.description,
+						args.sync_conflict_policy,
+					)
+					.await?)
+			})
+		})
+		.procedure("locked", {
+			R.query(|ctx, _: ()| async move { ctx.library_manager.get_locked().await })
+		})
+		.procedure("unlock", {
+			#[derive(Type, Deserialize)]
+			pub struct UnlockLibraryArgs {
+				pub id: Uuid,
+				pub password: Protected<String>,
+				pub remember: bool,
+			}
+
+			R.mutation(|ctx, args: UnlockLibraryArgs| async move {
+				Ok(ctx
+					.library_manager
+					.unlock(args.id, args.password, args.remember)
+					.await?)
+			})
+		})
+		.procedure("maintain", {
+			// Runs the maintenance of the database now, instead of waiting for the device to be idle
+			R.with2(library())
+				.mutation(|(_, library), _: ()| async move {
+					library
+						.spawn_job(MaintenanceJobInit {})
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("merge", {
+			// Merges another library of this node into this one, the other library is left as is
+			R.with2(library())
+				.mutation(|(ctx, library), source_library_id: Uuid| async move {
+					if source_library_id == library.id {
+						return Err(rspc::Error::new(
+							rspc::ErrorCode::BadRequest,
+							"a library can't be merged into itself".into(),
+						));
+					}
+
+					if ctx
+						.library_manager
+						.get_library(source_library_id)
+						.await
+						.is_none()
+					{
+						return Err(rspc::Error::new(
+							rspc::ErrorCode::NotFound,
+							"library to merge not found".into(),
+						));
+					}
+
+					library
+						.spawn_job(LibraryMergeJobInit { source_library_id })
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("checkIntegrity", {
+			// Looks for inconsistencies in the database, the repair plan is in the job's metadata
+			R.with2(library())
+				.mutation(|(_, library), _: ()| async move {
+					library
+						.spawn_job(IntegrityCheckJobInit {})
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("repairIntegrity", {
+			// Applies the repair plan of a completed integrity check
+			R.with2(library())
+				.mutation(|(_, library), check_job_id: Uuid| async move {
+					library
+						.spawn_job(IntegrityRepairJobInit { check_job_id })
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("cleanupOrphans", {
+			// Removes the records left behind by deleted locations, objects and libraries. With
+			// `dry_run`, they are only counted in the job's metadata.
+			R.with2(library())
+				.mutation(|(_, library), dry_run: bool| async move {
+					library
+						.spawn_job(OrphanCleanupJobInit { dry_run })
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("importCatalog", {
+			// Imports the keywords, collections, ratings and edits of a Lightroom or digiKam catalog
+			// onto the objects of the library
+			R.with2(library())
+				.mutation(|(_, library), args: CatalogImportJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("export", {
+			#[derive(Type, Deserialize)]
+			pub struct ExportLibraryArgs {
+				/// The file to write the export to
+				pub path: PathBuf,
+				pub include_thumbnails: bool,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: ExportLibraryArgs| async move {
+					Ok(export::export(&library, &args.path, args.include_thumbnails).await?)
+				})
+		})
+		.procedure("inspectExport", {
+			// Reads what an export holds, to ask where its locations are before importing it
+			R.query(|_, path: PathBuf| async move { Ok(export::inspect(&path).await?) })
+		})
+		.procedure("import", {
+			#[derive(Type, Deserialize)]
+			pub struct ImportLibraryArgs {
+				pub path: PathBuf,
+				/// The path on this node of the locations of the export, by their `pub_id`
+				pub locations: HashMap<Uuid, PathBuf>,
+			}
+
+			R.mutation(|ctx, args: ImportLibraryArgs| async move {
+				Ok(ctx
+					.library_manager
+					.import(&args.path, args.locations)
 					.await?)
 			})
 		})
@@ -48,12 +48,16 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 		.procedure("list", {
 			R.with2(library()).query(|(_, library), _: ()| async move {
 				Ok(library
-					.db
-					.location()
-					.find_many(vec![])
-					.order_by(location::date_created::order(SortOrder::Desc))
-					.include(location::include!({ node }))
-					.exec()
+					.queries
+					.get_or_query("locations.list", &["locations.list"], || {
+						library
+							.db
+							.location()
+							.find_many(vec![])
+							.order_by(location::date_created::order(SortOrder::Desc))
+							.include(location::include!({ node }))
+							.exec()
+					})
 					.await?)
 			})
 		})
@@ -61,10 +65,18 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			R.with2(library())
 				.query(|(_, library), location_id: location::id::Type| async move {
 					Ok(library
-						.db
-						.location()
-						.find_unique(location::id::equals(location_id))
-						.exec()
+						.queries
+						.get_or_query(
+							format!("locations.get.{location_id}"),
+							&["locations.list", "locations.get"],
+							|| {
+								library
+									.db
+									.location()
+									.find_unique(location::id::equals(location_id))
+									.exec()
+							},
+						)
 						.await?)
 				})
 		})
@@ -164,6 +176,25 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					Ok(AbortOnDrop(handle))
 				})
 		})
+		.procedure("listRemote", {
+			#[derive(Deserialize, Type, Debug)]
+			pub struct ListRemoteArgs {
+				pub location_id: location::id::Type,
+				/// Relative to the location
+				pub path: String,
+			}
+
+			// The live contents of a directory in a location of another paired node
+			R.with2(library())
+				.query(|(ctx, library), args: ListRemoteArgs| async move {
+					Ok(ctx
+						.p2p
+						.remote_fs(&library, args.location_id)
+						.await?
+						.read_dir(&args.path)
+						.await?)
+				})
+		})
 		.procedure(
 			"online",
 			R.subscription(|ctx, _: ()| async move {
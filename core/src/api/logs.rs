@@ -0,0 +1,46 @@
+use crate::node::logs::{LogLevel, Logs};
+
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::{Ctx, R};
+
+/// How many lines are given when no limit is asked for
+const DEFAULT_RECENT_LIMIT: u32 = 200;
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("filter", {
+			R.query(|_, _: ()| async move { Ok(Logs::get()?.directives()) })
+		})
+		.procedure("setFilter", {
+			// Directives like `warn,sd_core=debug,sd_core::job=trace`, empty for the default ones
+			R.mutation(|_, directives: String| async move {
+				Logs::get()?.set_directives(&directives)?;
+
+				Ok(())
+			})
+		})
+		.procedure("recent", {
+			#[derive(Type, Deserialize)]
+			pub struct RecentLogsArgs {
+				/// Only the lines logged while working on this library
+				#[serde(default)]
+				pub library_id: Option<Uuid>,
+				#[serde(default)]
+				pub min_level: Option<LogLevel>,
+				#[serde(default)]
+				pub limit: Option<u32>,
+			}
+
+			R.query(|_, args: RecentLogsArgs| async move {
+				Ok(Logs::get()?.recent(
+					args.library_id,
+					args.min_level.unwrap_or(LogLevel::Trace),
+					args.limit.unwrap_or(DEFAULT_RECENT_LIMIT) as usize,
+				))
+			})
+		})
+}
@@ -1,4 +1,6 @@
-use crate::{job::JobProgressEvent, node::SanitisedNodeConfig, Node};
+use crate::{
+	job::JobProgressEvent, library::notifications::Notification, node::SanitisedNodeConfig, Node,
+};
 use rspc::{alpha::Rspc, Config};
 use serde::{Deserialize, Serialize};
 use specta::Type;
@@ -18,21 +20,38 @@ pub enum CoreEvent {
 	NewThumbnail { thumb_key: Vec<String> },
 	JobProgress(JobProgressEvent),
 	InvalidateOperation(InvalidateOperationEvent),
+	Notification(Notification),
 }
 
+mod analytics;
+mod api_tokens;
+mod backups;
 mod categories;
+mod events;
 mod files;
 mod jobs;
 mod keys;
 mod libraries;
 mod locations;
+mod logs;
+mod mounts;
 mod nodes;
+mod notifications;
+pub mod openapi;
+mod operations;
 mod p2p;
+mod plugins;
+mod rules;
 mod search;
+mod share_links;
+mod sharing;
 mod sync;
 mod tags;
+mod trash;
 pub mod utils;
 pub mod volumes;
+mod webdav;
+mod webhooks;
 
 #[derive(Serialize, Deserialize, Debug, Type)]
 struct NodeState {
@@ -75,13 +94,28 @@ pub(crate) fn mount() -> Arc<Router> {
 		.merge("volumes.", volumes::mount())
 		.merge("tags.", tags::mount())
 		.merge("categories.", categories::mount())
-		// .merge("keys.", keys::mount())
+		.merge("keys.", keys::mount())
 		.merge("locations.", locations::mount())
 		.merge("files.", files::mount())
 		.merge("jobs.", jobs::mount())
 		.merge("p2p.", p2p::mount())
 		.merge("nodes.", nodes::mount())
+		.merge("notifications.", notifications::mount())
 		.merge("sync.", sync::mount())
+		.merge("sharing.", sharing::mount())
+		.merge("shareLinks.", share_links::mount())
+		.merge("backups.", backups::mount())
+		.merge("trash.", trash::mount())
+		.merge("operations.", operations::mount())
+		.merge("events.", events::mount())
+		.merge("plugins.", plugins::mount())
+		.merge("rules.", rules::mount())
+		.merge("webhooks.", webhooks::mount())
+		.merge("mounts.", mounts::mount())
+		.merge("webdav.", webdav::mount())
+		.merge("logs.", logs::mount())
+		.merge("analytics.", analytics::mount())
+		.merge("apiTokens.", api_tokens::mount())
 		.merge("invalidation.", utils::mount_invalidate())
 		.build(
 			#[allow(clippy::let_and_return)]
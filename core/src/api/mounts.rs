@@ -0,0 +1,31 @@
+use crate::{invalidate_query, mount::MountArgs};
+
+use std::path::PathBuf;
+
+use rspc::alpha::AlphaRouter;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.mounts.list().await) })
+		})
+		.procedure("mount", {
+			R.with2(library())
+				.mutation(|(ctx, library), args: MountArgs| async move {
+					let info = ctx.mounts.mount(library.clone(), args).await?;
+
+					invalidate_query!(library, "mounts.list");
+
+					Ok(info)
+				})
+		})
+		.procedure("unmount", {
+			R.mutation(|ctx, path: PathBuf| async move {
+				ctx.mounts.unmount(&path).await?;
+
+				Ok(())
+			})
+		})
+}
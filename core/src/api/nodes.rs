@@ -1,4 +1,9 @@
-use crate::prisma::{location, node};
+use crate::{
+	object::preview::ThumbnailSize,
+	p2p::{BandwidthLimits, SyncSchedule},
+	prisma::{location, node},
+	util::db::DatabaseSettings,
+};
 use rspc::{alpha::AlphaRouter, ErrorCode};
 
 use serde::Deserialize;
@@ -13,21 +18,29 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			#[derive(Deserialize, Type)]
 			pub struct ChangeNodeNameArgs {
 				pub name: Option<String>,
+				pub thumbnail_size: Option<ThumbnailSize>,
 			}
 			// TODO: validate name isn't empty or too long
 
 			R.mutation(|ctx, args: ChangeNodeNameArgs| async move {
-				if let Some(name) = args.name {
+				if let Some(name) = &args.name {
 					if name.is_empty() || name.len() > 32 {
 						return Err(rspc::Error::new(
 							ErrorCode::BadRequest,
 							"invalid node name".into(),
 						));
 					}
+				}
 
+				if args.name.is_some() || args.thumbnail_size.is_some() {
 					ctx.config
 						.write(|mut config| {
-							config.name = name;
+							if let Some(name) = args.name {
+								config.name = name;
+							}
+							if let Some(thumbnail_size) = args.thumbnail_size {
+								config.thumbnail_size = thumbnail_size;
+							}
 						})
 						.await
 						.map_err(|err| {
@@ -42,6 +55,129 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 				Ok(())
 			})
 		})
+		.procedure("setP2PPort", {
+			// `None` uses a random free port, applied the next time the app starts
+			R.mutation(|ctx, port: Option<u16>| async move {
+				ctx.config
+					.write(|mut config| config.p2p_port = port.map(u32::from))
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				Ok(())
+			})
+		})
+		.procedure("setRelay", {
+			// The address of the relay, `None` stops using one
+			R.mutation(|ctx, relay: Option<String>| async move {
+				let relay = relay.filter(|relay| !relay.is_empty());
+
+				ctx.p2p
+					.set_relay(relay.as_deref())
+					.await
+					.map_err(|err| rspc::Error::new(ErrorCode::BadRequest, err.to_string()))?;
+
+				ctx.config
+					.write(|mut config| config.p2p_relay = relay)
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				Ok(())
+			})
+		})
+		.procedure("setThumbnailCacheMaxSize", {
+			// Size in MiB, `None` removes the limit
+			R.mutation(|ctx, max_size_mb: Option<u32>| async move {
+				ctx.config
+					.write(|mut config| config.thumbnail_cache_max_size_mb = max_size_mb)
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.thumbnail_cache.evict().await;
+
+				Ok(())
+			})
+		})
+		.procedure("setBandwidthLimits", {
+			R.mutation(|ctx, limits: BandwidthLimits| async move {
+				ctx.config
+					.write({
+						let limits = limits.clone();
+						|mut config| config.p2p_bandwidth_limits = limits
+					})
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.p2p.set_bandwidth_limits(limits);
+
+				Ok(())
+			})
+		})
+		.procedure("setSyncSchedule", {
+			R.mutation(|ctx, schedule: SyncSchedule| async move {
+				ctx.config
+					.write({
+						let schedule = schedule.clone();
+						|mut config| config.p2p_sync_schedule = schedule
+					})
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.p2p.set_sync_schedule(schedule);
+
+				Ok(())
+			})
+		})
+		.procedure("setDatabaseSettings", {
+			// Applied to the libraries when they're next loaded
+			R.mutation(|ctx, settings: DatabaseSettings| async move {
+				settings
+					.validate()
+					.map_err(|err| rspc::Error::new(ErrorCode::BadRequest, err.to_string()))?;
+
+				ctx.config
+					.write(|mut config| config.database = settings)
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				Ok(())
+			})
+		})
 		// TODO: add pagination!! and maybe ordering etc
 		.procedure("listLocations", {
 			R.with2(library())
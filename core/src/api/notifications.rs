@@ -0,0 +1,24 @@
+use rspc::alpha::AlphaRouter;
+
+use super::{utils::library, CoreEvent, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router().procedure("listen", {
+		R.with2(library())
+			.subscription(|(ctx, library), _: ()| async move {
+				let mut event_bus_rx = ctx.event_bus.0.subscribe();
+				async_stream::stream! {
+					while let Ok(event) = event_bus_rx.recv().await {
+						match event {
+							CoreEvent::Notification(notification)
+								if notification.library_id == library.id =>
+							{
+								yield notification
+							}
+							_ => {}
+						}
+					}
+				}
+			})
+	})
+}
@@ -0,0 +1,437 @@
+//! An OpenAPI document of the procedures of the router, as they're called over HTTP, for the
+//! clients of other languages. It's made from the same types as the TypeScript bindings.
+//!
+//! Queries are `GET /rspc/<key>?input=<JSON>` and mutations are `POST /rspc/<key>` with the input as
+//! the body, both answering with the result in a JSON-RPC envelope. Subscriptions need the
+//! WebSocket of rspc, they're left out.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+use specta::{
+	DataType, EnumRepr, EnumType, EnumVariant, LiteralType, NamedDataType, NamedDataTypeItem,
+	ObjectType, PrimitiveType, TupleType,
+};
+
+use super::Router;
+
+const SCHEMAS_PATH: &str = "#/components/schemas/";
+
+/// Turns the types of specta into the schemas of OpenAPI 3.0
+struct Schemas<'a> {
+	definitions: HashMap<&'static str, &'a NamedDataType>,
+	/// The types given to the generics of the type being converted, by name
+	generics: HashMap<String, Value>,
+}
+
+impl<'a> Schemas<'a> {
+	fn schema(&self, ty: &DataType) -> Value {
+		match ty {
+			DataType::Any => json!({}),
+			DataType::Primitive(primitive) => primitive_schema(primitive),
+			DataType::Literal(literal) => literal_schema(literal),
+			DataType::List(item) => json!({ "type": "array", "items": self.schema(item) }),
+			DataType::Nullable(inner) => nullable(self.schema(inner)),
+			DataType::Record(record) => {
+				json!({ "type": "object", "additionalProperties": self.schema(&record.1) })
+			}
+			DataType::Named(named) => self.item_schema(&named.item),
+			DataType::Object(object) => self.object_schema(object),
+			DataType::Enum(enum_type) => self.enum_schema(enum_type),
+			DataType::Tuple(tuple) => self.tuple_schema(tuple),
+			DataType::Result(result) => {
+				json!({ "oneOf": [self.schema(&result.0), self.schema(&result.1)] })
+			}
+			DataType::Reference(reference) if reference.generics.is_empty() => {
+				json!({ "$ref": format!("{SCHEMAS_PATH}{}", reference.name) })
+			}
+			// Types with generics, like `LibraryArgs<T>`, are inlined with what they're given
+			DataType::Reference(reference) => match self.definitions.get(reference.name) {
+				Some(named) => {
+					let params = match &named.item {
+						NamedDataTypeItem::Object(object) => &object.generics,
+						NamedDataTypeItem::Enum(enum_type) => enum_type.generics(),
+						NamedDataTypeItem::Tuple(_) => return json!({}),
+					};
+
+					Schemas {
+						definitions: self.definitions.clone(),
+						generics: params
+							.iter()
+							.zip(&reference.generics)
+							.map(|(param, ty)| (param.to_string(), self.schema(ty)))
+							.collect(),
+					}
+					.item_schema(&named.item)
+				}
+				None => json!({}),
+			},
+			DataType::Generic(generic) => self
+				.generics
+				.get(&generic.to_string())
+				.cloned()
+				.unwrap_or_else(|| json!({})),
+			_ => json!({}),
+		}
+	}
+
+	fn item_schema(&self, item: &NamedDataTypeItem) -> Value {
+		match item {
+			NamedDataTypeItem::Object(object) => self.object_schema(object),
+			NamedDataTypeItem::Enum(enum_type) => self.enum_schema(enum_type),
+			NamedDataTypeItem::Tuple(tuple) => self.tuple_schema(tuple),
+		}
+	}
+
+	fn object_schema(&self, object: &ObjectType) -> Value {
+		let mut properties = Map::new();
+		let mut required = vec![];
+		let mut flattened = vec![];
+
+		for field in &object.fields {
+			if field.flatten {
+				flattened.push(self.schema(&field.ty));
+				continue;
+			}
+
+			properties.insert(field.key.to_string(), self.schema(&field.ty));
+			if !field.optional {
+				required.push(field.key);
+			}
+		}
+
+		if let Some(tag) = object.tag {
+			properties.insert(tag.to_string(), json!({ "type": "string" }));
+			required.push(tag);
+		}
+
+		let mut schema = json!({ "type": "object", "properties": properties });
+		if !required.is_empty() {
+			schema["required"] = json!(required);
+		}
+
+		if flattened.is_empty() {
+			schema
+		} else {
+			flattened.push(schema);
+			json!({ "allOf": flattened })
+		}
+	}
+
+	fn tuple_schema(&self, tuple: &TupleType) -> Value {
+		match tuple {
+			TupleType::Named { fields, .. } if fields.len() == 1 => self.schema(&fields[0]),
+			// OpenAPI 3.0 has no tuples, only arrays of any of their types
+			TupleType::Named { fields, .. } => json!({
+				"type": "array",
+				"items": { "oneOf": fields.iter().map(|ty| self.schema(ty)).collect::<Vec<_>>() },
+				"minItems": fields.len(),
+				"maxItems": fields.len(),
+			}),
+			TupleType::Unnamed => json!({ "type": "array", "maxItems": 0 }),
+		}
+	}
+
+	fn enum_schema(&self, enum_type: &EnumType) -> Value {
+		let variants = match enum_type {
+			EnumType::Untagged { variants, .. } => {
+				return json!({
+					"oneOf": variants.iter().map(|variant| self.variant_schema(variant)).collect::<Vec<_>>()
+				})
+			}
+			EnumType::Tagged { variants, repr, .. } => variants
+				.iter()
+				.map(|(name, variant)| self.tagged_variant_schema(name, variant, repr))
+				.collect::<Vec<_>>(),
+		};
+
+		// Unit variants only are an enum of strings
+		match variants
+			.iter()
+			.map(|variant| {
+				variant
+					.get("enum")
+					.and_then(|values| values.get(0))
+					.cloned()
+			})
+			.collect::<Option<Vec<_>>>()
+		{
+			Some(values) if matches!(repr_of(enum_type), Some(EnumRepr::External)) => {
+				json!({ "type": "string", "enum": values })
+			}
+			_ => json!({ "oneOf": variants }),
+		}
+	}
+
+	fn variant_schema(&self, variant: &EnumVariant) -> Value {
+		match variant {
+			EnumVariant::Unit => json!({ "nullable": true }),
+			EnumVariant::Unnamed(tuple) => self.tuple_schema(tuple),
+			EnumVariant::Named(object) => self.object_schema(object),
+		}
+	}
+
+	fn tagged_variant_schema(&self, name: &str, variant: &EnumVariant, repr: &EnumRepr) -> Value {
+		match repr {
+			EnumRepr::External => match variant {
+				EnumVariant::Unit => json!({ "type": "string", "enum": [name] }),
+				_ => json!({
+					"type": "object",
+					"properties": { name: self.variant_schema(variant) },
+					"required": [name],
+				}),
+			},
+			EnumRepr::Internal { tag } => {
+				let tag_schema = json!({
+					"type": "object",
+					"properties": { *tag: { "type": "string", "enum": [name] } },
+					"required": [tag],
+				});
+
+				match variant {
+					EnumVariant::Unit => tag_schema,
+					_ => json!({ "allOf": [tag_schema, self.variant_schema(variant)] }),
+				}
+			}
+			EnumRepr::Adjacent { tag, content } => {
+				let mut properties = Map::new();
+				properties.insert(tag.to_string(), json!({ "type": "string", "enum": [name] }));
+				let mut required = vec![*tag];
+				if !matches!(variant, EnumVariant::Unit) {
+					properties.insert(content.to_string(), self.variant_schema(variant));
+					required.push(content);
+				}
+
+				json!({ "type": "object", "properties": properties, "required": required })
+			}
+			EnumRepr::Untagged => self.variant_schema(variant),
+		}
+	}
+}
+
+fn repr_of(enum_type: &EnumType) -> Option<&EnumRepr> {
+	match enum_type {
+		EnumType::Tagged { repr, .. } => Some(repr),
+		EnumType::Untagged { .. } => None,
+	}
+}
+
+fn nullable(mut schema: Value) -> Value {
+	// Siblings of `$ref` are ignored in OpenAPI 3.0
+	if schema.get("$ref").is_some() {
+		return json!({ "allOf": [schema], "nullable": true });
+	}
+
+	if let Some(object) = schema.as_object_mut() {
+		object.insert("nullable".to_string(), json!(true));
+	}
+
+	schema
+}
+
+fn primitive_schema(primitive: &PrimitiveType) -> Value {
+	use PrimitiveType::*;
+
+	match primitive {
+		i8 | i16 | i32 | u8 | u16 => json!({ "type": "integer", "format": "int32" }),
+		// Not used by the bindings, which can't hold them in a number
+		i64 | i128 | isize | u32 | u64 | u128 | usize => {
+			json!({ "type": "integer", "format": "int64" })
+		}
+		f32 => json!({ "type": "number", "format": "float" }),
+		f64 => json!({ "type": "number", "format": "double" }),
+		bool => json!({ "type": "boolean" }),
+		char | String => json!({ "type": "string" }),
+		#[allow(unreachable_patterns)]
+		_ => json!({}),
+	}
+}
+
+fn literal_schema(literal: &LiteralType) -> Value {
+	match literal {
+		LiteralType::String(value) => json!({ "type": "string", "enum": [value] }),
+		LiteralType::bool(value) => json!({ "type": "boolean", "enum": [value] }),
+		LiteralType::None => json!({ "nullable": true }),
+		_ => json!({}),
+	}
+}
+
+/// The response of rspc to a call of a procedure
+fn envelope(result: Value) -> Value {
+	json!({
+		"type": "object",
+		"properties": {
+			"jsonrpc": { "type": "string" },
+			"id": { "nullable": true },
+			"result": {
+				"oneOf": [
+					{
+						"type": "object",
+						"properties": {
+							"type": { "type": "string", "enum": ["response"] },
+							"data": result,
+						},
+						"required": ["type", "data"],
+					},
+					{
+						"type": "object",
+						"properties": {
+							"type": { "type": "string", "enum": ["error"] },
+							"data": {
+								"type": "object",
+								"properties": {
+									"code": { "type": "integer" },
+									"message": { "type": "string" },
+								},
+							},
+						},
+						"required": ["type", "data"],
+					},
+				],
+			},
+		},
+		"required": ["result"],
+	})
+}
+
+fn operation(key: &str, schemas: &Schemas, result: &DataType) -> Map<String, Value> {
+	let mut operation = Map::new();
+	operation.insert("operationId".to_string(), json!(key));
+	operation.insert(
+		"tags".to_string(),
+		json!([key.split_once('.').map_or(key, |(namespace, _)| namespace)]),
+	);
+	operation.insert(
+		"responses".to_string(),
+		json!({
+			"200": {
+				"description": "The result of the procedure, or why it failed",
+				"content": { "application/json": { "schema": envelope(schemas.schema(result)) } },
+			}
+		}),
+	);
+
+	operation
+}
+
+/// Procedures without an input take `()`, which the bindings show as `null` or `never`
+fn takes_input(input: &DataType) -> bool {
+	!matches!(input, DataType::Tuple(TupleType::Unnamed))
+}
+
+pub fn generate(router: &Router) -> Value {
+	let type_map = router.typ_store();
+	let definitions = type_map
+		.values()
+		.flatten()
+		.map(|named| (named.name, named))
+		.collect::<HashMap<_, _>>();
+
+	let schemas = Schemas {
+		definitions: definitions.clone(),
+		generics: HashMap::new(),
+	};
+
+	let mut paths = Map::new();
+
+	for (key, procedure) in router.queries().iter() {
+		let mut get = operation(key, &schemas, &procedure.ty.result);
+		if takes_input(&procedure.ty.input) {
+			get.insert(
+				"parameters".to_string(),
+				json!([{
+					"name": "input",
+					"in": "query",
+					"required": true,
+					"content": { "application/json": { "schema": schemas.schema(&procedure.ty.input) } },
+				}]),
+			);
+		}
+
+		paths.insert(format!("/rspc/{key}"), json!({ "get": get }));
+	}
+
+	for (key, procedure) in router.mutations().iter() {
+		let mut post = operation(key, &schemas, &procedure.ty.result);
+		if takes_input(&procedure.ty.input) {
+			post.insert(
+				"requestBody".to_string(),
+				json!({
+					"required": true,
+					"content": { "application/json": { "schema": schemas.schema(&procedure.ty.input) } },
+				}),
+			);
+		}
+
+		paths.insert(format!("/rspc/{key}"), json!({ "post": post }));
+	}
+
+	let mut components = Map::new();
+	let mut names = definitions.keys().copied().collect::<Vec<_>>();
+	names.sort_unstable();
+	for name in names {
+		let named = definitions[name];
+		let mut schema = schemas.item_schema(&named.item);
+		if !named.comments.is_empty() {
+			if let Some(object) = schema.as_object_mut() {
+				object.insert(
+					"description".to_string(),
+					json!(named
+						.comments
+						.iter()
+						.map(|line| line.trim())
+						.collect::<Vec<_>>()
+						.join("\n")),
+				);
+			}
+		}
+
+		components.insert(name.to_string(), schema);
+	}
+
+	json!({
+		"openapi": "3.0.3",
+		"info": {
+			"title": "Spacedrive",
+			"version": env!("CARGO_PKG_VERSION"),
+			"description": "The procedures of a Spacedrive node. The procedures of a library take \
+				its id with their input, as `{ \"library_id\": ..., \"arg\": ... }`.",
+		},
+		"paths": paths,
+		"components": { "schemas": components },
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn documents_the_procedures() {
+		let router = crate::api::mount();
+		let document = generate(&router);
+
+		let search = &document["paths"]["/rspc/search.paths"]["get"];
+		assert_eq!(search["operationId"], "search.paths");
+		assert_eq!(search["tags"][0], "search");
+		assert!(search["parameters"][0]["content"]["application/json"]["schema"].is_object());
+
+		assert!(document["paths"]["/rspc/tags.create"]["post"]["requestBody"].is_object());
+		assert!(document["paths"]["/rspc/buildInfo"]["get"]
+			.get("parameters")
+			.is_none());
+		assert!(document["components"]["schemas"]["NodeState"].is_object());
+	}
+
+	#[test]
+	fn makes_references_nullable() {
+		assert_eq!(
+			nullable(json!({ "$ref": "#/components/schemas/Tag" })),
+			json!({ "allOf": [{ "$ref": "#/components/schemas/Tag" }], "nullable": true })
+		);
+		assert_eq!(
+			nullable(json!({ "type": "string" })),
+			json!({ "type": "string", "nullable": true })
+		);
+	}
+}
@@ -0,0 +1,14 @@
+use crate::library::journal;
+
+use rspc::alpha::AlphaRouter;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router().procedure("undo", {
+		R.with2(library())
+			.mutation(
+				|(_, library), _: ()| async move { Ok(journal::undo_last_batch(&library).await?) },
+			)
+	})
+}
@@ -3,9 +3,12 @@ use sd_p2p::PeerId;
 use serde::Deserialize;
 use specta::Type;
 use std::path::PathBuf;
+use tracing::error;
 use uuid::Uuid;
 
-use crate::p2p::P2PEvent;
+use crate::p2p::{
+	validate_address, DeviceConditions, P2PEvent, RetryPolicy, TransferPriority, TransferWindow,
+};
 
 use super::{utils::library, Ctx, R};
 
@@ -35,6 +38,64 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 				}
 			})
 		})
+		.procedure("setDeviceConditions", {
+			// Reported by the app as the core can't tell them on every platform
+			R.mutation(|ctx, conditions: DeviceConditions| async move {
+				ctx.p2p.sync_scheduler.set_conditions(conditions);
+
+				Ok(())
+			})
+		})
+		.procedure("manualPeers", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.p2p.manual_peers.list()) })
+		})
+		.procedure("addManualPeer", {
+			// A hostname or IP address followed by a port
+			R.mutation(|ctx, address: String| async move {
+				let address = address.trim().to_string();
+				validate_address(&address)?;
+
+				ctx.config
+					.write({
+						let address = address.clone();
+						|mut config| {
+							if !config.p2p_manual_peers.contains(&address) {
+								config.p2p_manual_peers.push(address);
+							}
+						}
+					})
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.p2p.manual_peers.add(address);
+
+				Ok(())
+			})
+		})
+		.procedure("removeManualPeer", {
+			R.mutation(|ctx, address: String| async move {
+				ctx.config
+					.write(|mut config| config.p2p_manual_peers.retain(|a| *a != address))
+					.await
+					.map_err(|err| {
+						error!("Failed to write config: {}", err);
+						rspc::Error::new(
+							ErrorCode::InternalServerError,
+							"error updating config".into(),
+						)
+					})?;
+
+				ctx.p2p.manual_peers.remove(&address);
+
+				Ok(())
+			})
+		})
 		.procedure("spacedrop", {
 			#[derive(Type, Deserialize)]
 			pub struct SpacedropArgs {
@@ -43,22 +104,66 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			}
 
 			R.mutation(|ctx, args: SpacedropArgs| async move {
-				// TODO: Handle multiple files path and error if zero paths
-				ctx.p2p
+				Ok(ctx
+					.p2p
 					.big_bad_spacedrop(
 						args.peer_id,
-						PathBuf::from(
-							args.file_path
-								.first()
-								.expect("https://linear.app/spacedriveapp/issue/ENG-625/spacedrop-multiple-files"),
-						),
+						args.file_path.into_iter().map(PathBuf::from).collect(),
 					)
-					.await
-					.map_err(|_| {
-						rspc::Error::new(ErrorCode::InternalServerError, "todo".to_string())
-					})
+					.await?)
+			})
+		})
+		.procedure("transferQueue", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.p2p.transfer_queue.list().await) })
+		})
+		.procedure("queueSpacedrop", {
+			#[derive(Type, Deserialize)]
+			pub struct QueueSpacedropArgs {
+				peer_id: PeerId,
+				file_path: Vec<String>,
+				priority: TransferPriority,
+				/// `null` uses the default policy
+				retry: Option<RetryPolicy>,
+				/// `null` sends it as soon as possible
+				window: Option<TransferWindow>,
+			}
+
+			R.mutation(|ctx, args: QueueSpacedropArgs| async move {
+				Ok(ctx
+					.p2p
+					.transfer_queue
+					.enqueue(
+						args.peer_id,
+						args.file_path.into_iter().map(PathBuf::from).collect(),
+						args.priority,
+						args.retry.unwrap_or_default(),
+						args.window,
+					)
+					.await?)
 			})
 		})
+		.procedure("scheduleQueuedTransfer", {
+			#[derive(Type, Deserialize)]
+			pub struct ScheduleQueuedTransferArgs {
+				id: Uuid,
+				priority: TransferPriority,
+				window: Option<TransferWindow>,
+			}
+
+			R.mutation(|ctx, args: ScheduleQueuedTransferArgs| async move {
+				Ok(ctx
+					.p2p
+					.transfer_queue
+					.schedule(args.id, args.priority, args.window)
+					.await?)
+			})
+		})
+		.procedure("retryQueuedTransfer", {
+			R.mutation(|ctx, id: Uuid| async move { Ok(ctx.p2p.transfer_queue.retry(id).await?) })
+		})
+		.procedure("removeQueuedTransfer", {
+			R.mutation(|ctx, id: Uuid| async move { Ok(ctx.p2p.remove_queued_transfer(id).await?) })
+		})
 		.procedure("acceptSpacedrop", {
 			R.mutation(|ctx, (id, path): (Uuid, Option<String>)| async move {
 				match path {
@@ -78,4 +183,20 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			R.with2(library())
 				.mutation(|(ctx, lib), id: PeerId| async move { ctx.p2p.pair(id, lib) })
 		})
+		.procedure("pairWithPayload", {
+			R.with2(library())
+				.mutation(|(ctx, lib), payload: String| async move {
+					Ok(ctx.p2p.pair_with_payload(&payload, lib)?)
+				})
+		})
+		.procedure("pairingPayload", {
+			R.with2(library())
+				.query(|(ctx, lib), _: ()| async move { Ok(ctx.p2p.pairing_payload(&lib)) })
+		})
+		.procedure("pairingResponse", {
+			R.mutation(|ctx, (id, accepted): (u16, bool)| async move {
+				ctx.p2p.confirm_pairing(id, accepted);
+				Ok(())
+			})
+		})
 }
@@ -0,0 +1,70 @@
+use crate::{
+	plugins::{metadata_job::PluginMetadataJobInit, plugin_job::PluginJobInit},
+	prisma::{object, plugin_metadata},
+};
+
+use rspc::alpha::AlphaRouter;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.query(|ctx, _: ()| async move { Ok(ctx.plugins.list()) })
+		})
+		.procedure("search", {
+			#[derive(Type, Deserialize)]
+			pub struct PluginSearchArgs {
+				pub query: String,
+				/// The most results of each plugin, 20 when not set
+				pub take: Option<u32>,
+			}
+
+			R.query(|ctx, args: PluginSearchArgs| async move {
+				Ok(ctx
+					.plugins
+					.search(&args.query, args.take.unwrap_or(20))
+					.await)
+			})
+		})
+		.procedure("metadata", {
+			#[derive(Type, Serialize)]
+			pub struct ObjectPluginMetadata {
+				pub plugin_id: String,
+				pub data: Value,
+			}
+
+			R.with2(library())
+				.query(|(_, library), object_id: object::id::Type| async move {
+					Ok(library
+						.db
+						.plugin_metadata()
+						.find_many(vec![plugin_metadata::object_id::equals(object_id)])
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|metadata| {
+							Some(ObjectPluginMetadata {
+								data: serde_json::from_slice(&metadata.data).ok()?,
+								plugin_id: metadata.plugin_id,
+							})
+						})
+						.collect::<Vec<_>>())
+				})
+		})
+		.procedure("extractMetadata", {
+			R.with2(library())
+				.mutation(|(_, library), args: PluginMetadataJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+		.procedure("runJob", {
+			R.with2(library())
+				.mutation(|(_, library), args: PluginJobInit| async move {
+					library.spawn_job(args).await.map_err(Into::into)
+				})
+		})
+}
@@ -0,0 +1,147 @@
+use crate::{
+	invalidate_query,
+	library::rules::{self, RuleAction, RuleError, RuleTrigger},
+	prisma::{action_rule, location},
+};
+
+use chrono::{DateTime, Utc};
+use rspc::alpha::AlphaRouter;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+#[derive(Type, Serialize)]
+pub struct ActionRule {
+	pub id: action_rule::id::Type,
+	pub name: String,
+	pub enabled: bool,
+	pub trigger: RuleTrigger,
+	pub location_id: Option<location::id::Type>,
+	pub pattern: Option<String>,
+	pub job_name: Option<String>,
+	pub actions: Vec<RuleAction>,
+	pub date_created: DateTime<Utc>,
+	pub date_last_run: Option<DateTime<Utc>>,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.action_rule()
+					.find_many(vec![])
+					.exec()
+					.await?
+					.into_iter()
+					.filter_map(|rule| {
+						Some(ActionRule {
+							trigger: RuleTrigger::try_from(rule.trigger).ok()?,
+							actions: serde_json::from_slice(&rule.actions).ok()?,
+							id: rule.id,
+							name: rule.name,
+							enabled: rule.enabled,
+							location_id: rule.location_id,
+							pattern: rule.pattern,
+							job_name: rule.job_name,
+							date_created: rule.date_created.into(),
+							date_last_run: rule.date_last_run.map(Into::into),
+						})
+					})
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateRuleArgs {
+				pub name: String,
+				pub trigger: RuleTrigger,
+				/// Only the files of this location
+				pub location_id: Option<location::id::Type>,
+				/// A glob the names of the files match, like `*.pdf`
+				pub pattern: Option<String>,
+				/// Only the jobs of this name
+				pub job_name: Option<String>,
+				/// Run in order, the first one failing stops the others
+				pub actions: Vec<RuleAction>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: CreateRuleArgs| async move {
+					let actions = rules::validate(
+						args.trigger,
+						args.pattern.as_deref(),
+						args.job_name.as_deref(),
+						&args.actions,
+					)?;
+
+					let rule = library
+						.db
+						.action_rule()
+						.create(
+							Uuid::new_v4().as_bytes().to_vec(),
+							args.name,
+							args.trigger as i32,
+							actions,
+							Utc::now().into(),
+							vec![
+								action_rule::location_id::set(args.location_id),
+								action_rule::pattern::set(args.pattern),
+								action_rule::job_name::set(args.job_name),
+							],
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "rules.list");
+
+					Ok(rule.id)
+				})
+		})
+		.procedure("setEnabled", {
+			#[derive(Type, Deserialize)]
+			pub struct SetRuleEnabledArgs {
+				pub id: action_rule::id::Type,
+				pub enabled: bool,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SetRuleEnabledArgs| async move {
+					let updated = library
+						.db
+						.action_rule()
+						.update_many(
+							vec![action_rule::id::equals(args.id)],
+							vec![action_rule::enabled::set(args.enabled)],
+						)
+						.exec()
+						.await?;
+
+					if updated == 0 {
+						return Err(RuleError::NotFound(args.id).into());
+					}
+
+					invalidate_query!(library, "rules.list");
+
+					Ok(())
+				})
+		})
+		.procedure("delete", {
+			R.with2(library())
+				.mutation(|(_, library), id: action_rule::id::Type| async move {
+					library
+						.db
+						.action_rule()
+						.delete_many(vec![action_rule::id::equals(id)])
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "rules.list");
+
+					Ok(())
+				})
+		})
+}
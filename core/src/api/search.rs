@@ -200,7 +200,7 @@ impl ObjectFilterArgs {
 		use object::*;
 
 		chain_optional_iter(
-			[],
+			[date_deleted::equals(None)],
 			[
 				self.hidden.to_param(),
 				self.favorite.map(Some).map(favorite::equals),
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rspc::alpha::AlphaRouter;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{
+	invalidate_query,
+	p2p::{ShareLinkError, ShareToken},
+	prisma::{object, share_link},
+};
+
+use super::{utils::library, Ctx, R};
+
+/// A link to an object of the library that other nodes can redeem
+#[derive(Serialize, Type)]
+pub struct ShareLink {
+	pub id: share_link::id::Type,
+	pub object_id: object::id::Type,
+	pub token: String,
+	pub date_created: DateTime<FixedOffset>,
+	pub date_expires: Option<DateTime<FixedOffset>>,
+	pub date_revoked: Option<DateTime<FixedOffset>>,
+	pub redemptions: i32,
+	pub date_redeemed: Option<DateTime<FixedOffset>>,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			// The links of an object, or of every object if `null`
+			R.with2(library()).query(
+				|(ctx, library), object_id: Option<object::id::Type>| async move {
+					let peer_id = ctx.p2p.manager.peer_id();
+
+					Ok(library
+						.db
+						.share_link()
+						.find_many(
+							object_id
+								.map(|id| vec![share_link::object_id::equals(id)])
+								.unwrap_or_default(),
+						)
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|link| {
+							let token = ShareToken::new(
+								&library,
+								peer_id,
+								Uuid::from_slice(&link.pub_id).ok()?,
+								link.date_expires.map(Into::into),
+							);
+
+							Some(ShareLink {
+								id: link.id,
+								object_id: link.object_id,
+								token: token.to_string(),
+								date_created: link.date_created,
+								date_expires: link.date_expires,
+								date_revoked: link.date_revoked,
+								redemptions: link.redemptions,
+								date_redeemed: link.date_redeemed,
+							})
+						})
+						.collect::<Vec<_>>())
+				},
+			)
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateShareLinkArgs {
+				pub object_id: object::id::Type,
+				/// `null` for the link to be valid until it's revoked
+				pub date_expires: Option<DateTime<Utc>>,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: CreateShareLinkArgs| async move {
+					if matches!(args.date_expires, Some(expires) if expires <= Utc::now()) {
+						return Err(ShareLinkError::ExpiryInPast.into());
+					}
+
+					library
+						.db
+						.object()
+						.find_unique(object::id::equals(args.object_id))
+						.select(object::select!({ id }))
+						.exec()
+						.await?
+						.ok_or(ShareLinkError::ObjectNotFound(args.object_id))?;
+
+					let pub_id = Uuid::new_v4();
+					library
+						.db
+						.share_link()
+						.create(
+							pub_id.as_bytes().to_vec(),
+							object::id::equals(args.object_id),
+							Utc::now().into(),
+							vec![share_link::date_expires::set(
+								args.date_expires.map(Into::into),
+							)],
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "shareLinks.list");
+
+					Ok(ShareToken::new(
+						&library,
+						ctx.p2p.manager.peer_id(),
+						pub_id,
+						args.date_expires,
+					)
+					.to_string())
+				})
+		})
+		.procedure("revoke", {
+			R.with2(library())
+				.mutation(|(_, library), id: share_link::id::Type| async move {
+					library
+						.db
+						.share_link()
+						.update(
+							share_link::id::equals(id),
+							vec![share_link::date_revoked::set(Some(Utc::now().into()))],
+						)
+						.exec()
+						.await
+						.map_err(|_| ShareLinkError::LinkNotFound(id))?;
+
+					invalidate_query!(library, "shareLinks.list");
+
+					Ok(())
+				})
+		})
+		.procedure("redeem", {
+			#[derive(Type, Deserialize)]
+			pub struct RedeemShareLinkArgs {
+				pub token: String,
+				/// The directory to save the file of the object to, the shared directory if `null`
+				pub path: Option<PathBuf>,
+			}
+
+			R.mutation(|ctx, args: RedeemShareLinkArgs| async move {
+				Ok(ctx.p2p.redeem_share_link(&args.token, args.path).await?)
+			})
+		})
+}
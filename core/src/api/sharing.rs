@@ -0,0 +1,135 @@
+use chrono::{DateTime, FixedOffset};
+use rspc::alpha::AlphaRouter;
+use sd_p2p::PeerId;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{
+	p2p::{CollectionEdit, SharingAccess},
+	prisma::{shared_collection, shared_collection_item, tag},
+};
+
+use super::{utils::library, Ctx, R};
+
+/// A tag of this library shared with another user's library, or one of theirs shared with it
+#[derive(Serialize, Type)]
+pub struct SharedCollection {
+	pub id: shared_collection::id::Type,
+	pub name: String,
+	pub color: Option<String>,
+	/// The tag that is shared, `null` for the collections shared with this library
+	pub tag_id: Option<tag::id::Type>,
+	/// The library on the other side
+	pub library_name: String,
+	pub peer_id: String,
+	pub access: SharingAccess,
+	pub include_files: bool,
+	pub item_count: u32,
+	pub date_created: DateTime<FixedOffset>,
+	/// When the items were last received from the owner, `null` for the collections we own
+	pub date_synced: Option<DateTime<FixedOffset>>,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.shared_collection()
+					.find_many(vec![])
+					.include(shared_collection::include!({ items: select { id } }))
+					.exec()
+					.await?
+					.into_iter()
+					.map(|collection| SharedCollection {
+						id: collection.id,
+						name: collection.name,
+						color: collection.color,
+						tag_id: collection.tag_id,
+						library_name: collection.library_name,
+						peer_id: collection.peer_id,
+						access: collection.access.into(),
+						include_files: collection.include_files,
+						item_count: collection.items.len() as u32,
+						date_created: collection.date_created,
+						date_synced: collection.date_synced,
+					})
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("items", {
+			R.with2(library())
+				.query(|(_, library), collection_id: i32| async move {
+					Ok(library
+						.db
+						.shared_collection_item()
+						.find_many(vec![shared_collection_item::collection_id::equals(
+							collection_id,
+						)])
+						.exec()
+						.await?)
+				})
+		})
+		.procedure("share", {
+			#[derive(Type, Deserialize)]
+			pub struct ShareArgs {
+				pub tag_id: i32,
+				pub peer_id: PeerId,
+				pub access: SharingAccess,
+				pub include_files: bool,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: ShareArgs| async move {
+					ctx.p2p
+						.share_collection(
+							&library,
+							args.tag_id,
+							args.peer_id,
+							args.access,
+							args.include_files,
+						)
+						.await?;
+
+					Ok(())
+				})
+		})
+		.procedure("respond", {
+			// The library to add the collection to, `None` rejects it
+			R.mutation(|ctx, (id, library_id): (Uuid, Option<Uuid>)| async move {
+				match library_id {
+					Some(library_id) => ctx.p2p.accept_share(id, library_id),
+					None => ctx.p2p.reject_share(id),
+				}
+			})
+		})
+		.procedure("refresh", {
+			R.with2(library())
+				.mutation(|(ctx, library), collection_id: i32| async move {
+					Ok(ctx.p2p.refresh_collection(&library, collection_id).await?)
+				})
+		})
+		.procedure("edit", {
+			#[derive(Type, Deserialize)]
+			pub struct EditArgs {
+				pub collection_id: i32,
+				pub edit: CollectionEdit,
+			}
+
+			R.with2(library())
+				.mutation(|(ctx, library), args: EditArgs| async move {
+					Ok(ctx
+						.p2p
+						.edit_collection(&library, args.collection_id, args.edit)
+						.await?)
+				})
+		})
+		.procedure("unshare", {
+			R.with2(library())
+				.mutation(|(ctx, library), collection_id: i32| async move {
+					Ok(ctx.p2p.unshare_collection(&library, collection_id).await?)
+				})
+		})
+}
@@ -1,9 +1,81 @@
-use rspc::alpha::AlphaRouter;
+use std::{collections::HashMap, str::FromStr};
 
-use crate::sync::SyncMessage;
+use chrono::{DateTime, FixedOffset, Utc};
+use rspc::{alpha::AlphaRouter, ErrorCode};
+use sd_p2p::PeerId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{
+	invalidate_query,
+	library::Library,
+	p2p::{NodePermissions, TrustLevel},
+	prisma::{location, node, sync_conflict, sync_scope, tag, SortOrder},
+	sync::{
+		compact_ops, record_sync_status, ConflictResolution, SyncMessage, SyncScope,
+		SyncStatusModel,
+	},
+};
 
 use super::{utils::library, Ctx, R};
 
+/// A paired node and the locations and tags that are synced with it
+#[derive(Serialize, Type)]
+pub struct SyncNode {
+	pub id: i32,
+	pub pub_id: Uuid,
+	pub name: String,
+	/// The locations synced with the node, `null` syncs all of them
+	pub locations: Option<Vec<location::id::Type>>,
+	/// The tags synced with the node, `null` syncs all of them
+	pub tags: Option<Vec<tag::id::Type>>,
+	pub permissions: NodePermissions,
+	/// `null` if the permissions don't match a trust level
+	pub trust_level: Option<TrustLevel>,
+}
+
+/// A field of a record that two nodes changed concurrently
+#[derive(Serialize, Type)]
+pub struct SyncConflict {
+	pub id: i32,
+	pub model: String,
+	pub record_id: Value,
+	pub field: String,
+	/// The value currently in the record
+	pub value: Value,
+	pub node_name: String,
+	/// The value that was concurrently set by the other node
+	pub other_value: Value,
+	pub other_node_name: String,
+	pub date_created: DateTime<FixedOffset>,
+	/// The value the conflict was settled with, `null` if it's pending or the record was deleted
+	pub resolved_value: Option<Value>,
+	/// `null` while the conflict is pending
+	pub date_resolved: Option<DateTime<FixedOffset>>,
+}
+
+/// How the exchanges of operations with a paired node went, to debug why they drifted apart. The
+/// metrics of the exchanges are since this node started.
+#[derive(Serialize, Type)]
+pub struct SyncPeerHealth {
+	pub id: i32,
+	pub name: String,
+	/// `null` if the node was never seen on the network
+	pub peer_id: Option<String>,
+	/// The operations within the node's sync scope it doesn't have yet
+	pub pending_operations: u32,
+	/// The node missed some operations, they're replayed once it's reachable again
+	pub behind: bool,
+	pub last_exchange: Option<DateTime<Utc>>,
+	pub round_trip_ms: Option<u32>,
+	pub bytes_sent: String,
+	pub bytes_received: String,
+	/// How far ahead the node's clock is of ours, negative if it's behind
+	pub clock_skew_ms: Option<i32>,
+}
+
 pub(crate) fn mount() -> AlphaRouter<Ctx> {
 	R.router()
 		.procedure("newMessage", {
@@ -21,8 +93,300 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					}
 				})
 		})
+		.procedure("conflicts", {
+			#[derive(Type, Deserialize)]
+			pub struct ListSyncConflictsArgs {
+				/// Only lists the conflicts waiting to be resolved
+				pub pending: bool,
+			}
+
+			R.with2(library())
+				.query(|(_, library), args: ListSyncConflictsArgs| async move {
+					Ok(library
+						.db
+						.sync_conflict()
+						.find_many(if args.pending {
+							vec![sync_conflict::date_resolved::equals(None)]
+						} else {
+							vec![]
+						})
+						.order_by(sync_conflict::date_created::order(SortOrder::Desc))
+						.include(sync_conflict::include!({
+							node: select { name }
+							other_node: select { name }
+						}))
+						.exec()
+						.await?
+						.into_iter()
+						.map(|conflict| SyncConflict {
+							id: conflict.id,
+							model: conflict.model,
+							record_id: serde_json::from_slice(&conflict.record_id)
+								.unwrap_or_default(),
+							field: conflict.field,
+							value: serde_json::from_slice(&conflict.value).unwrap_or_default(),
+							node_name: conflict.node.name,
+							other_value: serde_json::from_slice(&conflict.other_value)
+								.unwrap_or_default(),
+							other_node_name: conflict.other_node.name,
+							date_created: conflict.date_created,
+							resolved_value: conflict
+								.resolved_value
+								.and_then(|value| serde_json::from_slice(&value).ok()),
+							date_resolved: conflict.date_resolved,
+						})
+						.collect::<Vec<_>>())
+				})
+		})
+		.procedure("health", {
+			R.with2(library())
+				.query(|(ctx, library), _: ()| async move {
+					let Library { db, sync, .. } = &library;
+
+					let nodes = db
+						.node()
+						.find_many(vec![node::pub_id::not(
+							library.config.node_id.as_bytes().to_vec(),
+						)])
+						.select(node::select!({ id pub_id name node_peer_id }))
+						.exec()
+						.await?;
+
+					let mut health = Vec::with_capacity(nodes.len());
+					for node in nodes {
+						let timestamps = match &node.node_peer_id {
+							Some(peer_id) => sync.peer_timestamps(peer_id).await?,
+							None => HashMap::new(),
+						};
+
+						// The node always has its own operations
+						let pending = compact_ops(sync.get_ops_after(&timestamps).await?)
+							.into_iter()
+							.filter(|op| op.node.as_bytes()[..] != node.pub_id[..])
+							.collect();
+						let pending = SyncScope::for_node(db, node.id)
+							.await?
+							.filter(db, pending)
+							.await?;
+
+						let peer_id = node
+							.node_peer_id
+							.as_deref()
+							.and_then(|peer_id| PeerId::from_str(peer_id).ok());
+						let metrics = peer_id
+							.map(|peer_id| ctx.p2p.sync_metrics.get(library.id, peer_id))
+							.unwrap_or_default();
+
+						health.push(SyncPeerHealth {
+							id: node.id,
+							name: node.name,
+							peer_id: node.node_peer_id,
+							pending_operations: pending.len() as u32,
+							behind: peer_id
+								.map_or(false, |peer_id| ctx.p2p.sync_scheduler.is_behind(peer_id)),
+							last_exchange: metrics.last_exchange,
+							round_trip_ms: metrics
+								.round_trip
+								.map(|round_trip| round_trip.as_millis() as u32),
+							bytes_sent: metrics.bytes_sent.to_string(),
+							bytes_received: metrics.bytes_received.to_string(),
+							clock_skew_ms: metrics.clock_skew_ms.map(|skew| skew as i32),
+						});
+					}
+
+					Ok(health)
+				})
+		})
 		.procedure("messages", {
 			R.with2(library())
 				.query(|(_, library), _: ()| async move { Ok(library.sync.get_ops().await?) })
 		})
+		.procedure("nodes", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.node()
+					.find_many(vec![node::pub_id::not(
+						library.config.node_id.as_bytes().to_vec(),
+					)])
+					.include(node::include!({ sync_scopes }))
+					.exec()
+					.await?
+					.into_iter()
+					.filter_map(|node| {
+						let locations = node
+							.sync_scopes
+							.iter()
+							.filter_map(|scope| scope.location_id)
+							.collect::<Vec<_>>();
+						let tags = node
+							.sync_scopes
+							.iter()
+							.filter_map(|scope| scope.tag_id)
+							.collect::<Vec<_>>();
+
+						let permissions = NodePermissions {
+							spacedrop: node.can_spacedrop,
+							sync: node.can_sync,
+							read_files: node.can_read_files,
+							delete: node.can_delete,
+						};
+
+						Some(SyncNode {
+							id: node.id,
+							pub_id: Uuid::from_slice(&node.pub_id).ok()?,
+							name: node.name,
+							locations: (!locations.is_empty()).then_some(locations),
+							tags: (!tags.is_empty()).then_some(tags),
+							permissions,
+							trust_level: permissions.trust_level(),
+						})
+					})
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("resolveConflict", {
+			#[derive(Type, Deserialize)]
+			pub struct ResolveSyncConflictArgs {
+				pub id: i32,
+				pub resolution: ConflictResolution,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: ResolveSyncConflictArgs| async move {
+					let Library { db, sync, .. } = &library;
+
+					let conflict = db
+						.sync_conflict()
+						.find_unique(sync_conflict::id::equals(args.id))
+						.exec()
+						.await?
+						.ok_or_else(|| {
+							rspc::Error::new(
+								ErrorCode::NotFound,
+								"Sync conflict not found".to_string(),
+							)
+						})?;
+
+					if conflict.date_resolved.is_some() {
+						return Err(rspc::Error::new(
+							ErrorCode::Conflict,
+							"Sync conflict is already resolved".to_string(),
+						));
+					}
+
+					sync.resolve_conflict(conflict, args.resolution).await?;
+
+					invalidate_query!(library, "sync.conflicts");
+
+					Ok(())
+				})
+		})
+		.procedure("setScope", {
+			#[derive(Type, Deserialize)]
+			pub struct SetSyncScopeArgs {
+				pub node_id: i32,
+				/// The locations to sync with the node, `null` or an empty list syncs all of them
+				pub locations: Option<Vec<location::id::Type>>,
+				/// The tags to sync with the node, `null` or an empty list syncs all of them
+				pub tags: Option<Vec<tag::id::Type>>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SetSyncScopeArgs| async move {
+					let Library { db, .. } = &library;
+
+					let entries = args
+						.locations
+						.unwrap_or_default()
+						.into_iter()
+						.map(|location_id| sync_scope::location_id::set(Some(location_id)))
+						.chain(
+							args.tags
+								.unwrap_or_default()
+								.into_iter()
+								.map(|tag_id| sync_scope::tag_id::set(Some(tag_id))),
+						)
+						.map(|param| sync_scope::CreateUnchecked {
+							node_id: args.node_id,
+							_params: vec![param],
+						})
+						.collect();
+
+					db._batch((
+						db.sync_scope()
+							.delete_many(vec![sync_scope::node_id::equals(args.node_id)]),
+						db.sync_scope().create_many(entries),
+					))
+					.await?;
+
+					invalidate_query!(library, "sync.nodes");
+					invalidate_query!(library, "sync.status");
+
+					Ok(())
+				})
+		})
+		.procedure("setPermissions", {
+			#[derive(Type, Deserialize)]
+			pub struct SetNodePermissionsArgs {
+				pub node_id: i32,
+				pub permissions: NodePermissions,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SetNodePermissionsArgs| async move {
+					library
+						.db
+						.node()
+						.update(node::id::equals(args.node_id), args.permissions.params())
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "sync.nodes");
+
+					Ok(())
+				})
+		})
+		.procedure("setTrustLevel", {
+			#[derive(Type, Deserialize)]
+			pub struct SetNodeTrustLevelArgs {
+				pub node_id: i32,
+				pub level: TrustLevel,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SetNodeTrustLevelArgs| async move {
+					library
+						.db
+						.node()
+						.update(
+							node::id::equals(args.node_id),
+							NodePermissions::for_trust_level(args.level).params(),
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "sync.nodes");
+
+					Ok(())
+				})
+		})
+		.procedure("status", {
+			#[derive(Type, Deserialize)]
+			pub struct SyncStatusArgs {
+				pub model: SyncStatusModel,
+				pub ids: Vec<i32>,
+			}
+
+			R.with2(library())
+				.query(|(_, library), args: SyncStatusArgs| async move {
+					Ok(record_sync_status(
+						&library.db,
+						library.config.node_id,
+						args.model,
+						args.ids,
+					)
+					.await?)
+				})
+		})
 }
@@ -4,12 +4,17 @@ use serde::Deserialize;
 use specta::Type;
 
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
 	invalidate_query,
-	library::Library,
+	library::{
+		trash,
+		webhooks::{self, WebhookEvent},
+		Library,
+	},
 	object::tag::TagCreateArgs,
-	prisma::{tag, tag_on_object},
+	prisma::{object, tag, tag_on_object},
 	sync,
 };
 
@@ -19,7 +24,16 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 	R.router()
 		.procedure("list", {
 			R.with2(library()).query(|(_, library), _: ()| async move {
-				Ok(library.db.tag().find_many(vec![]).exec().await?)
+				Ok(library
+					.queries
+					.get_or_query("tags.list", &["tags.list"], || {
+						library
+							.db
+							.tag()
+							.find_many(vec![tag::date_deleted::equals(None)])
+							.exec()
+					})
+					.await?)
 			})
 		})
 		.procedure("getForObject", {
@@ -28,9 +42,12 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					Ok(library
 						.db
 						.tag()
-						.find_many(vec![tag::tag_objects::some(vec![
-							tag_on_object::object_id::equals(object_id),
-						])])
+						.find_many(vec![
+							tag::tag_objects::some(vec![tag_on_object::object_id::equals(
+								object_id,
+							)]),
+							tag::date_deleted::equals(None),
+						])
 						.exec()
 						.await?)
 				})
@@ -66,30 +83,89 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 
 			R.with2(library())
 				.mutation(|(_, library), args: TagAssignArgs| async move {
-					let Library { db, .. } = &library;
+					let Library { db, sync, .. } = &library;
+
+					let tag = db
+						.tag()
+						.find_unique(tag::id::equals(args.tag_id))
+						.select(tag::select!({ pub_id }))
+						.exec()
+						.await?
+						.ok_or(rspc::Error::new(
+							ErrorCode::NotFound,
+							"Error finding tag in db".into(),
+						))?;
+
+					let tag_pub_id = Uuid::from_slice(&tag.pub_id).map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::InternalServerError,
+							"Tag has an invalid pub_id".into(),
+							e,
+						)
+					})?;
+
+					let object_pub_ids = db
+						.object()
+						.find_many(vec![object::id::in_vec(args.object_ids.clone())])
+						.select(object::select!({ pub_id }))
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|object| Uuid::from_slice(&object.pub_id).ok())
+						.collect::<Vec<_>>();
+
+					let ops = object_pub_ids
+						.iter()
+						.map(|&object_pub_id| {
+							if args.unassign {
+								sync.relation_delete(tag_on_object::NAME, tag_pub_id, object_pub_id)
+							} else {
+								sync.relation_create(tag_on_object::NAME, tag_pub_id, object_pub_id)
+							}
+						})
+						.collect();
 
 					if args.unassign {
-						db.tag_on_object()
-							.delete_many(vec![
-								tag_on_object::tag_id::equals(args.tag_id),
-								tag_on_object::object_id::in_vec(args.object_ids),
-							])
-							.exec()
-							.await?;
+						sync.write_ops(
+							db,
+							(
+								ops,
+								db.tag_on_object().delete_many(vec![
+									tag_on_object::tag_id::equals(args.tag_id),
+									tag_on_object::object_id::in_vec(args.object_ids),
+								]),
+							),
+						)
+						.await?;
 					} else {
-						db.tag_on_object()
-							.create_many(
-								args.object_ids
-									.iter()
-									.map(|&object_id| tag_on_object::CreateUnchecked {
-										tag_id: args.tag_id,
-										object_id,
-										_params: vec![],
-									})
-									.collect(),
-							)
-							.exec()
-							.await?;
+						sync.write_ops(
+							db,
+							(
+								ops,
+								db.tag_on_object().create_many(
+									args.object_ids
+										.iter()
+										.map(|&object_id| tag_on_object::CreateUnchecked {
+											tag_id: args.tag_id,
+											object_id,
+											_params: vec![],
+										})
+										.collect(),
+								),
+							),
+						)
+						.await?;
+
+						webhooks::fire(
+							&library,
+							object_pub_ids
+								.into_iter()
+								.map(|object_pub_id| WebhookEvent::ObjectTagged {
+									object_pub_id,
+									tag_id: args.tag_id,
+								})
+								.collect(),
+						);
 					}
 
 					invalidate_query!(library, "tags.getForObject");
@@ -164,14 +240,20 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			"delete",
 			R.with2(library())
 				.mutation(|(_, library), tag_id: i32| async move {
-					library
+					let tag = library
 						.db
 						.tag()
-						.delete(tag::id::equals(tag_id))
+						.find_unique(tag::id::equals(tag_id))
+						.select(tag::select!({ pub_id }))
 						.exec()
-						.await?;
+						.await?
+						.ok_or(rspc::Error::new(
+							ErrorCode::NotFound,
+							"Error finding tag in db".into(),
+						))?;
 
-					invalidate_query!(library, "tags.list");
+					// Deleted tags go to the trash, where they can be restored or purged
+					trash::trash_tag(&library, tag.pub_id).await?;
 
 					Ok(())
 				}),
@@ -0,0 +1,25 @@
+use crate::library::trash::{self, TrashItems};
+
+use rspc::alpha::AlphaRouter;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library())
+				.query(|(_, library), _: ()| async move { Ok(trash::list(&library).await?) })
+		})
+		.procedure("restore", {
+			R.with2(library())
+				.mutation(|(_, library), items: TrashItems| async move {
+					Ok(trash::restore(&library, items).await?)
+				})
+		})
+		.procedure("purge", {
+			R.with2(library())
+				.mutation(|(_, library), items: TrashItems| async move {
+					Ok(trash::purge(&library, items).await?)
+				})
+		})
+}
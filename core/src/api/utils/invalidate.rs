@@ -38,6 +38,10 @@ impl InvalidateOperationEvent {
 	pub fn dangerously_create(key: &'static str, arg: Value, result: Option<Value>) -> Self {
 		Self { key, arg, result }
 	}
+
+	pub fn key(&self) -> &'static str {
+		self.key
+	}
 }
 
 /// a request to invalidate a specific resource
@@ -0,0 +1,126 @@
+use crate::{
+	invalidate_query,
+	prisma::{location, tag, webdav_share},
+	webdav::{generate_password, hash_password, validate_share, WebdavShareError},
+};
+
+use chrono::{DateTime, Utc};
+use rspc::alpha::AlphaRouter;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+/// A location or a tag served over WebDAV, at `/webdav/<library id>/<name>/` of the server
+#[derive(Type, Serialize)]
+pub struct WebdavShare {
+	pub id: webdav_share::id::Type,
+	pub name: String,
+	pub location_id: Option<location::id::Type>,
+	pub tag_id: Option<tag::id::Type>,
+	pub username: String,
+	pub date_created: DateTime<Utc>,
+}
+
+#[derive(Type, Serialize)]
+pub struct CreatedWebdavShare {
+	pub id: webdav_share::id::Type,
+	/// Only given back now, a share that lost it has to be made again
+	pub password: String,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.webdav_share()
+					.find_many(vec![])
+					.exec()
+					.await?
+					.into_iter()
+					.map(|share| WebdavShare {
+						id: share.id,
+						name: share.name,
+						location_id: share.location_id,
+						tag_id: share.tag_id,
+						username: share.username,
+						date_created: share.date_created.into(),
+					})
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateWebdavShareArgs {
+				pub name: String,
+				pub username: String,
+				/// Either the location or the tag
+				pub location_id: Option<location::id::Type>,
+				pub tag_id: Option<tag::id::Type>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: CreateWebdavShareArgs| async move {
+					validate_share(&args.name, &args.username, args.location_id, args.tag_id)?;
+
+					if library
+						.db
+						.webdav_share()
+						.find_unique(webdav_share::name::equals(args.name.clone()))
+						.exec()
+						.await?
+						.is_some()
+					{
+						return Err(WebdavShareError::NameTaken(args.name).into());
+					}
+
+					let password = generate_password();
+
+					let share = library
+						.db
+						.webdav_share()
+						.create(
+							Uuid::new_v4().as_bytes().to_vec(),
+							args.name,
+							args.username,
+							hash_password(&password),
+							Utc::now().into(),
+							vec![
+								webdav_share::location_id::set(args.location_id),
+								webdav_share::tag_id::set(args.tag_id),
+							],
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "webdav.list");
+
+					Ok(CreatedWebdavShare {
+						id: share.id,
+						password,
+					})
+				})
+		})
+		.procedure("delete", {
+			R.with2(library())
+				.mutation(|(_, library), id: webdav_share::id::Type| async move {
+					let deleted = library
+						.db
+						.webdav_share()
+						.delete_many(vec![webdav_share::id::equals(id)])
+						.exec()
+						.await?;
+
+					if deleted == 0 {
+						return Err(WebdavShareError::NotFound(id).into());
+					}
+
+					invalidate_query!(library, "webdav.list");
+
+					Ok(())
+				})
+		})
+}
@@ -0,0 +1,142 @@
+use crate::{
+	invalidate_query,
+	library::webhooks::{self, WebhookError, WebhookEventKind},
+	prisma::{location, tag, webhook},
+};
+
+use chrono::{DateTime, Utc};
+use rspc::alpha::AlphaRouter;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+#[derive(Type, Serialize)]
+pub struct Webhook {
+	pub id: webhook::id::Type,
+	pub url: String,
+	pub enabled: bool,
+	pub events: Vec<WebhookEventKind>,
+	pub location_id: Option<location::id::Type>,
+	pub tag_id: Option<tag::id::Type>,
+	/// The secret itself is never sent back
+	pub has_secret: bool,
+	pub date_created: DateTime<Utc>,
+	pub date_last_delivery: Option<DateTime<Utc>>,
+	pub last_status: Option<i32>,
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(library
+					.db
+					.webhook()
+					.find_many(vec![])
+					.exec()
+					.await?
+					.into_iter()
+					.filter_map(|webhook| {
+						Some(Webhook {
+							events: serde_json::from_slice(&webhook.events).ok()?,
+							id: webhook.id,
+							url: webhook.url,
+							enabled: webhook.enabled,
+							location_id: webhook.location_id,
+							tag_id: webhook.tag_id,
+							has_secret: webhook.secret.is_some(),
+							date_created: webhook.date_created.into(),
+							date_last_delivery: webhook.date_last_delivery.map(Into::into),
+							last_status: webhook.last_status,
+						})
+					})
+					.collect::<Vec<_>>())
+			})
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			pub struct CreateWebhookArgs {
+				pub url: String,
+				pub events: Vec<WebhookEventKind>,
+				/// Only the objects with a file in this location
+				pub location_id: Option<location::id::Type>,
+				/// Only the objects with this tag
+				pub tag_id: Option<tag::id::Type>,
+				/// Signs the deliveries with HMAC-SHA256
+				pub secret: Option<String>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: CreateWebhookArgs| async move {
+					let events = webhooks::validate(&args.url, &args.events)?;
+
+					let webhook = library
+						.db
+						.webhook()
+						.create(
+							Uuid::new_v4().as_bytes().to_vec(),
+							args.url,
+							events,
+							Utc::now().into(),
+							vec![
+								webhook::location_id::set(args.location_id),
+								webhook::tag_id::set(args.tag_id),
+								webhook::secret::set(
+									args.secret.filter(|secret| !secret.is_empty()),
+								),
+							],
+						)
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "webhooks.list");
+
+					Ok(webhook.id)
+				})
+		})
+		.procedure("setEnabled", {
+			#[derive(Type, Deserialize)]
+			pub struct SetWebhookEnabledArgs {
+				pub id: webhook::id::Type,
+				pub enabled: bool,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SetWebhookEnabledArgs| async move {
+					let updated = library
+						.db
+						.webhook()
+						.update_many(
+							vec![webhook::id::equals(args.id)],
+							vec![webhook::enabled::set(args.enabled)],
+						)
+						.exec()
+						.await?;
+
+					if updated == 0 {
+						return Err(WebhookError::NotFound(args.id).into());
+					}
+
+					invalidate_query!(library, "webhooks.list");
+
+					Ok(())
+				})
+		})
+		.procedure("delete", {
+			R.with2(library())
+				.mutation(|(_, library), id: webhook::id::Type| async move {
+					library
+						.db
+						.webhook()
+						.delete_many(vec![webhook::id::equals(id)])
+						.exec()
+						.await?;
+
+					invalidate_query!(library, "webhooks.list");
+
+					Ok(())
+				})
+		})
+}
@@ -1,5 +1,8 @@
 use crate::{
+	library::Library,
 	location::file_path_helper::{file_path_to_handle_custom_uri, IsolatedFilePathData},
+	object::preview::{get_sprite_path, ThumbnailFormat, THUMBNAIL_CACHE_DIR_NAME},
+	p2p::{RemoteFsError, RemoteFsRefusal},
 	prisma::{file_path, location},
 	util::{db::*, error::FileIOError},
 	Node,
@@ -23,6 +26,7 @@ use httpz::{
 };
 use mini_moka::sync::Cache;
 use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
 use prisma_client_rust::QueryError;
 use thiserror::Error;
 use tokio::{
@@ -53,12 +57,18 @@ async fn handler(node: Arc<Node>, req: Request) -> Result<Response<Vec<u8>>, Han
 
 	match path.first() {
 		Some(&"thumbnail") => handle_thumbnail(&node, &path, &req).await,
+		Some(&"sprite") => handle_sprite(&node, &path, &req).await,
 		Some(&"file") => handle_file(&node, &path, &req).await,
+		Some(&"remote") => handle_remote(&node, &path, &req).await,
 		_ => Err(HandleCustomUriError::BadRequest("Invalid operation!")),
 	}
 }
 
-async fn read_file(mut file: File, length: u64, start: Option<u64>) -> io::Result<Vec<u8>> {
+pub(crate) async fn read_file(
+	mut file: File,
+	length: u64,
+	start: Option<u64>,
+) -> io::Result<Vec<u8>> {
 	let mut buf = Vec::with_capacity(length as usize);
 	if let Some(start) = start {
 		file.seek(SeekFrom::Start(start)).await?;
@@ -106,20 +116,85 @@ async fn handle_thumbnail(
 		));
 	}
 
-	let mut thumbnail_path = node.config.data_directory().join("thumbnails");
+	let mut thumbnail_path = node.config.data_directory().join(THUMBNAIL_CACHE_DIR_NAME);
 	// if we ever wish to support multiple levels of sharding, we need only supply more params here
 	for path_part in &path[1..] {
 		thumbnail_path = thumbnail_path.join(path_part);
 	}
-	let filename = thumbnail_path.with_extension("webp");
 
-	let file = File::open(&filename).await.map_err(|err| {
-		if err.kind() == io::ErrorKind::NotFound {
-			HandleCustomUriError::NotFound("file")
+	if let Some(cas_id) = path.last() {
+		node.thumbnail_cache.accessed(*cas_id);
+	}
+
+	// The thumbnail can be in any format, depending on the library that generated it
+	let mut thumbnail = None;
+	for format in ThumbnailFormat::ALL {
+		let filename = thumbnail_path.with_extension(format.extension());
+		match File::open(&filename).await {
+			Ok(file) => {
+				thumbnail = Some((file, filename, format));
+				break;
+			}
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+			Err(err) => return Err(FileIOError::from((&filename, err)).into()),
+		}
+	}
+
+	let Some((file, filename, format)) = thumbnail else {
+		return Err(HandleCustomUriError::NotFound("file"));
+	};
+
+	let content_length = file
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((&filename, e)))?
+		.len();
+
+	Ok(builder
+		.header("Content-Type", format.content_type())
+		.header("Content-Length", content_length)
+		.status(StatusCode::OK)
+		.body(if method == Method::HEAD {
+			vec![]
 		} else {
-			FileIOError::from((&filename, err)).into()
+			read_file(file, content_length, None)
+				.await
+				.map_err(|e| FileIOError::from((&filename, e)))?
+		})?)
+}
+
+async fn handle_sprite(
+	node: &Node,
+	path: &[&str],
+	req: &Request,
+) -> Result<Response<Vec<u8>>, HandleCustomUriError> {
+	let method = req.method();
+	let mut builder = Response::builder();
+	if let Some(response) = cors(method, &mut builder) {
+		return Ok(response?);
+	}
+
+	// Same key as the thumbnails, the shard is recomputed from the cas_id
+	let [_, _shard, cas_id] = path else {
+		return Err(HandleCustomUriError::BadRequest(
+			"Invalid number of parameters!",
+		));
+	};
+
+	// Web clients add the extension to the key, like they do for thumbnails
+	let cas_id = cas_id.strip_suffix(".webp").unwrap_or(*cas_id);
+	if cas_id.is_empty() || !cas_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return Err(HandleCustomUriError::BadRequest("Invalid cas_id!"));
+	}
+
+	let filename = get_sprite_path(node.config.data_directory(), cas_id);
+	let file = match File::open(&filename).await {
+		Ok(file) => file,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => {
+			return Err(HandleCustomUriError::NotFound("file"))
 		}
-	})?;
+		Err(err) => return Err(FileIOError::from((&filename, err)).into()),
+	};
 
 	let content_length = file
 		.metadata()
@@ -194,6 +269,14 @@ async fn handle_file(
 				.ok_or_else(|| HandleCustomUriError::NotFound("object"))?;
 
 			let location = maybe_missing(&file_path.location, "file_path.location")?;
+
+			// Files on other nodes are read through them, so they aren't cached
+			if location.node_id != Some(library.node_local_id) {
+				let path = remote_path(IsolatedFilePathData::try_from((location_id, &file_path))?);
+
+				return handle_remote_file(node, &library, location_id, &path, req, builder).await;
+			}
+
 			let path = maybe_missing(&location.path, "file_path.location.path")?;
 
 			let lru_entry = (
@@ -214,76 +297,9 @@ async fn handle_file(
 		}
 	})?;
 
-	// TODO: This should be determined from magic bytes when the file is indexed and stored it in the DB on the file path
-	// https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
-	let mime_type = match extension.as_str() {
-		// AAC audio
-		"aac" => "audio/aac",
-		// Musical Instrument Digital Interface (MIDI)
-		"mid" | "midi" => "audio/midi, audio/x-midi",
-		// MP3 audio
-		"mp3" => "audio/mpeg",
-		// MP4 audio
-		"m4a" => "audio/mp4",
-		// OGG audio
-		"oga" => "audio/ogg",
-		// Opus audio
-		"opus" => "audio/opus",
-		// Waveform Audio Format
-		"wav" => "audio/wav",
-		// WEBM audio
-		"weba" => "audio/webm",
-		// AVI: Audio Video Interleave
-		"avi" => "video/x-msvideo",
-		// MP4 video
-		"mp4" | "m4v" => "video/mp4",
-		#[cfg(not(target_os = "macos"))]
-		// FIX-ME: This media types break macOS video rendering
-		// MPEG transport stream
-		"ts" => "video/mp2t",
-		#[cfg(not(target_os = "macos"))]
-		// FIX-ME: This media types break macOS video rendering
-		// MPEG Video
-		"mpeg" => "video/mpeg",
-		// OGG video
-		"ogv" => "video/ogg",
-		// WEBM video
-		"webm" => "video/webm",
-		// 3GPP audio/video container (TODO: audio/3gpp if it doesn't contain video)
-		"3gp" => "video/3gpp",
-		// 3GPP2 audio/video container (TODO: audio/3gpp2 if it doesn't contain video)
-		"3g2" => "video/3gpp2",
-		// Quicktime movies
-		"mov" => "video/quicktime",
-		// Windows OS/2 Bitmap Graphics
-		"bmp" => "image/bmp",
-		// Graphics Interchange Format (GIF)
-		"gif" => "image/gif",
-		// Icon format
-		"ico" => "image/vnd.microsoft.icon",
-		// JPEG images
-		"jpeg" | "jpg" => "image/jpeg",
-		// Portable Network Graphics
-		"png" => "image/png",
-		// Scalable Vector Graphics (SVG)
-		"svg" => "image/svg+xml",
-		// Tagged Image File Format (TIFF)
-		"tif" | "tiff" => "image/tiff",
-		// WEBP image
-		"webp" => "image/webp",
-		// PDF document
-		"pdf" => "application/pdf",
-		// HEIF/HEIC images
-		"heif" | "heifs" => "image/heif,image/heif-sequence",
-		"heic" | "heics" => "image/heic,image/heic-sequence",
-		// AVIF images
-		"avif" | "avci" | "avcs" => "image/avif",
-		_ => {
-			return Err(HandleCustomUriError::BadRequest(
-				"TODO: This filetype is not supported because of the missing mime type!",
-			));
-		}
-	};
+	let mime_type = mime_type(&extension).ok_or(HandleCustomUriError::BadRequest(
+		"TODO: This filetype is not supported because of the missing mime type!",
+	))?;
 
 	let mut content_lenght = file
 		.metadata()
@@ -375,6 +391,226 @@ async fn handle_file(
 		.body(buf)?)
 }
 
+/// The path of a file relative to its location, with the separators every node understands
+fn remote_path(path: IsolatedFilePathData<'_>) -> String {
+	path.as_ref()
+		.components()
+		.map(|component| component.as_os_str().to_string_lossy())
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+/// Reads a file in a location of another node, by its path relative to the location
+async fn handle_remote(
+	node: &Node,
+	path: &[&str],
+	req: &Request,
+) -> Result<Response<Vec<u8>>, HandleCustomUriError> {
+	let mut builder = Response::builder();
+	if let Some(response) = cors(req.method(), &mut builder) {
+		return Ok(response?);
+	}
+
+	let library_id = path
+		.get(1)
+		.and_then(|id| Uuid::from_str(id).ok())
+		.ok_or_else(|| {
+			HandleCustomUriError::BadRequest("Invalid number of parameters. Missing library_id!")
+		})?;
+
+	let location_id = path
+		.get(2)
+		.and_then(|id| id.parse::<location::id::Type>().ok())
+		.ok_or_else(|| {
+			HandleCustomUriError::BadRequest("Invalid number of parameters. Missing location_id!")
+		})?;
+
+	let relative_path = path
+		.get(3..)
+		.filter(|parts| !parts.is_empty())
+		.ok_or_else(|| {
+			HandleCustomUriError::BadRequest("Invalid number of parameters. Missing path!")
+		})?
+		.iter()
+		.map(|part| percent_decode_str(part).decode_utf8_lossy())
+		.collect::<Vec<_>>()
+		.join("/");
+
+	let library = node
+		.library_manager
+		.get_library(library_id)
+		.await
+		.ok_or_else(|| HandleCustomUriError::NotFound("library"))?;
+
+	handle_remote_file(node, &library, location_id, &relative_path, req, builder).await
+}
+
+async fn handle_remote_file(
+	node: &Node,
+	library: &Library,
+	location_id: location::id::Type,
+	relative_path: &str,
+	req: &Request,
+	mut builder: Builder,
+) -> Result<Response<Vec<u8>>, HandleCustomUriError> {
+	let method = req.method();
+
+	let extension = Path::new(relative_path)
+		.extension()
+		.map(|extension| extension.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+	let mime_type = mime_type(&extension).ok_or(HandleCustomUriError::BadRequest(
+		"TODO: This filetype is not supported because of the missing mime type!",
+	))?;
+
+	let mut remote = node.p2p.remote_fs(library, location_id).await?;
+
+	// Only asks for the size of the file
+	let (file, _) = remote.read_file(relative_path, 0, Some(0)).await?;
+	let file_size = file.size;
+
+	let range = match req.headers().get("range") {
+		Some(range) if method == Method::GET => range
+			.to_str()
+			.ok()
+			.and_then(|range| HttpRange::parse(range, file_size).ok())
+			.ok_or_else(|| {
+				HandleCustomUriError::RangeNotSatisfiable("Error decoding range header!")
+			})
+			.and_then(|range| {
+				if range.len() > 1 {
+					Err(HandleCustomUriError::RangeNotSatisfiable(
+						"Multiple ranges are not supported!",
+					))
+				} else {
+					Ok(range.first().cloned())
+				}
+			})?,
+		_ => None,
+	};
+
+	builder = builder.header("Content-type", mime_type);
+
+	let response = match range {
+		// Each read is capped, the player asks for the rest of the range afterwards
+		Some(range) => {
+			let (file, buf) = remote
+				.read_file(relative_path, range.start, Some(range.length))
+				.await?;
+
+			builder
+				.header("Connection", "Keep-Alive")
+				.header("Accept-Ranges", "bytes")
+				.header(
+					"Content-Range",
+					format!(
+						"bytes {}-{}/{file_size}",
+						file.start,
+						(file.start + file.length).max(1) - 1
+					),
+				)
+				.header("Content-Length", file.length)
+				.status(206)
+				.body(buf)?
+		}
+		None if method == Method::HEAD => builder
+			.header("Accept-Ranges", "bytes")
+			.header("Content-Length", file_size)
+			.status(StatusCode::OK)
+			.body(vec![])?,
+		None => {
+			let mut buf = Vec::with_capacity(file_size as usize);
+			while (buf.len() as u64) < file_size {
+				let (file, chunk) = remote
+					.read_file(relative_path, buf.len() as u64, None)
+					.await?;
+
+				// The file got shorter while reading it
+				if file.length == 0 {
+					break;
+				}
+				buf.extend(chunk);
+			}
+
+			builder
+				.header("Content-Length", buf.len())
+				.status(StatusCode::OK)
+				.body(buf)?
+		}
+	};
+
+	Ok(response)
+}
+
+pub(crate) fn mime_type(extension: &str) -> Option<&'static str> {
+	// TODO: This should be determined from magic bytes when the file is indexed and stored it in the DB on the file path
+	// https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
+	Some(match extension {
+		// AAC audio
+		"aac" => "audio/aac",
+		// Musical Instrument Digital Interface (MIDI)
+		"mid" | "midi" => "audio/midi, audio/x-midi",
+		// MP3 audio
+		"mp3" => "audio/mpeg",
+		// MP4 audio
+		"m4a" => "audio/mp4",
+		// OGG audio
+		"oga" => "audio/ogg",
+		// Opus audio
+		"opus" => "audio/opus",
+		// Waveform Audio Format
+		"wav" => "audio/wav",
+		// WEBM audio
+		"weba" => "audio/webm",
+		// AVI: Audio Video Interleave
+		"avi" => "video/x-msvideo",
+		// MP4 video
+		"mp4" | "m4v" => "video/mp4",
+		#[cfg(not(target_os = "macos"))]
+		// FIX-ME: This media types break macOS video rendering
+		// MPEG transport stream
+		"ts" => "video/mp2t",
+		#[cfg(not(target_os = "macos"))]
+		// FIX-ME: This media types break macOS video rendering
+		// MPEG Video
+		"mpeg" => "video/mpeg",
+		// OGG video
+		"ogv" => "video/ogg",
+		// WEBM video
+		"webm" => "video/webm",
+		// 3GPP audio/video container (TODO: audio/3gpp if it doesn't contain video)
+		"3gp" => "video/3gpp",
+		// 3GPP2 audio/video container (TODO: audio/3gpp2 if it doesn't contain video)
+		"3g2" => "video/3gpp2",
+		// Quicktime movies
+		"mov" => "video/quicktime",
+		// Windows OS/2 Bitmap Graphics
+		"bmp" => "image/bmp",
+		// Graphics Interchange Format (GIF)
+		"gif" => "image/gif",
+		// Icon format
+		"ico" => "image/vnd.microsoft.icon",
+		// JPEG images
+		"jpeg" | "jpg" => "image/jpeg",
+		// Portable Network Graphics
+		"png" => "image/png",
+		// Scalable Vector Graphics (SVG)
+		"svg" => "image/svg+xml",
+		// Tagged Image File Format (TIFF)
+		"tif" | "tiff" => "image/tiff",
+		// WEBP image
+		"webp" => "image/webp",
+		// PDF document
+		"pdf" => "application/pdf",
+		// HEIF/HEIC images
+		"heif" | "heifs" => "image/heif,image/heif-sequence",
+		"heic" | "heics" => "image/heic,image/heic-sequence",
+		// AVIF images
+		"avif" | "avci" | "avcs" => "image/avif",
+		_ => return None,
+	})
+}
+
 pub fn create_custom_uri_endpoint(node: Arc<Node>) -> Endpoint<impl HttpEndpoint> {
 	GenericEndpoint::new(
 		"/*any",
@@ -402,6 +638,8 @@ pub enum HandleCustomUriError {
 	NotFound(&'static str),
 	#[error("HandleCustomUriError::MissingField - '{0}'")]
 	MissingField(#[from] MissingFieldError),
+	#[error("HandleCustomUriError::RemoteFs - {0}")]
+	RemoteFs(#[from] RemoteFsError),
 }
 
 impl From<HandleCustomUriError> for Response<Vec<u8>> {
@@ -450,6 +688,20 @@ impl From<HandleCustomUriError> for Response<Vec<u8>> {
 					.status(StatusCode::INTERNAL_SERVER_ERROR)
 					.body(b"Internal Server Error".to_vec())
 			}
+			HandleCustomUriError::RemoteFs(err) => {
+				let status = match err {
+					RemoteFsError::LocationNotFound(_)
+					| RemoteFsError::Refused(RemoteFsRefusal::NotFound) => StatusCode::NOT_FOUND,
+					RemoteFsError::Refused(
+						RemoteFsRefusal::LocationNotShared | RemoteFsRefusal::PermissionDenied,
+					) => StatusCode::FORBIDDEN,
+					RemoteFsError::Peer(_) => StatusCode::BAD_GATEWAY,
+					_ => StatusCode::INTERNAL_SERVER_ERROR,
+				};
+
+				error!("Error reading file from another node: {err}");
+				builder.status(status).body(err.to_string().into_bytes())
+			}
 		})
 		// SAFETY: This unwrap is ok as we have an hardcoded the response builders.
 		.expect("internal error building hardcoded HTTP error response")
@@ -0,0 +1,536 @@
+//! A GraphQL API over the objects, file paths, tags and jobs of the libraries, for integrators who'd
+//! rather use GraphQL tooling than the rspc bindings. It only reads, changes still go through rspc.
+//!
+//! Lists are connections paginated forward with `first` and `after`, their cursors are the offsets
+//! of their items and a page has at most [`MAX_PAGE_SIZE`] of them.
+
+use crate::{
+	library::Library,
+	prisma::{file_path, job, object, tag, tag_on_object},
+	util::db::chain_optional_iter,
+	Node,
+};
+
+use std::sync::Arc;
+
+use async_graphql::{
+	connection::{Connection, Edge},
+	ComplexObject, Context, EmptyMutation, EmptySubscription, InputObject, Object, OutputType,
+	Result, Schema, SimpleObject,
+};
+use chrono::{DateTime, FixedOffset};
+use prisma_client_rust::Direction;
+use uuid::Uuid;
+
+pub const MAX_PAGE_SIZE: usize = 500;
+const DEFAULT_PAGE_SIZE: usize = 50;
+/// Deep enough to go from a library to the tags of the objects of a tag, not much more
+const MAX_DEPTH: usize = 8;
+
+pub type GraphQLSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema(node: Arc<Node>) -> GraphQLSchema {
+	Schema::build(Query, EmptyMutation, EmptySubscription)
+		.data(node)
+		.limit_depth(MAX_DEPTH)
+		.finish()
+}
+
+/// How many items to skip and to take from `first` and `after`
+fn page(first: Option<i32>, after: Option<String>) -> Result<(usize, usize)> {
+	let skip = match after {
+		Some(cursor) => cursor.parse::<usize>()? + 1,
+		None => 0,
+	};
+	let take = first.map_or(DEFAULT_PAGE_SIZE, |first| {
+		(first.max(0) as usize).min(MAX_PAGE_SIZE)
+	});
+
+	Ok((skip, take))
+}
+
+/// A page from `items`, fetched with one more item than the page has to know if there's a next one
+fn connection<T: OutputType>(skip: usize, take: usize, mut items: Vec<T>) -> Connection<usize, T> {
+	let has_next_page = items.len() > take;
+	items.truncate(take);
+
+	let mut connection = Connection::new(skip > 0, has_next_page);
+	connection.edges.extend(
+		items
+			.into_iter()
+			.enumerate()
+			.map(|(i, item)| Edge::new(skip + i, item)),
+	);
+
+	connection
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+	async fn libraries(&self, ctx: &Context<'_>) -> Vec<LibraryNode> {
+		ctx.data_unchecked::<Arc<Node>>()
+			.library_manager
+			.get_all_libraries()
+			.await
+			.into_iter()
+			.map(LibraryNode)
+			.collect()
+	}
+
+	async fn library(&self, ctx: &Context<'_>, id: Uuid) -> Option<LibraryNode> {
+		ctx.data_unchecked::<Arc<Node>>()
+			.library_manager
+			.get_library(id)
+			.await
+			.map(LibraryNode)
+	}
+}
+
+#[derive(InputObject, Default)]
+pub struct ObjectFilter {
+	/// From `ObjectKind`
+	kind: Option<i32>,
+	favorite: Option<bool>,
+	hidden: Option<bool>,
+	/// Only the objects with this tag
+	tag_id: Option<i32>,
+	/// Only the objects with a file in this location
+	location_id: Option<i32>,
+}
+
+impl ObjectFilter {
+	fn into_params(self) -> Vec<object::WhereParam> {
+		chain_optional_iter(
+			[],
+			[
+				self.kind.map(|kind| object::kind::equals(Some(kind))),
+				self.favorite
+					.map(|favorite| object::favorite::equals(Some(favorite))),
+				self.hidden
+					.map(|hidden| object::hidden::equals(Some(hidden))),
+				self.tag_id
+					.map(|tag_id| object::tags::some(vec![tag_on_object::tag_id::equals(tag_id)])),
+				self.location_id.map(|location_id| {
+					object::file_paths::some(vec![file_path::location_id::equals(Some(
+						location_id,
+					))])
+				}),
+			],
+		)
+	}
+}
+
+#[derive(InputObject, Default)]
+pub struct FilePathFilter {
+	location_id: Option<i32>,
+	object_id: Option<i32>,
+	is_dir: Option<bool>,
+	/// Without the dot, like `pdf`
+	extension: Option<String>,
+	/// Only the files with names containing this
+	name: Option<String>,
+	/// Only the files directly in this directory of their location, like `/photos/`
+	materialized_path: Option<String>,
+}
+
+impl FilePathFilter {
+	fn into_params(self) -> Vec<file_path::WhereParam> {
+		chain_optional_iter(
+			[],
+			[
+				self.location_id
+					.map(|location_id| file_path::location_id::equals(Some(location_id))),
+				self.object_id
+					.map(|object_id| file_path::object_id::equals(Some(object_id))),
+				self.is_dir
+					.map(|is_dir| file_path::is_dir::equals(Some(is_dir))),
+				self.extension
+					.map(|extension| file_path::extension::equals(Some(extension))),
+				self.name.map(file_path::name::contains),
+				self.materialized_path
+					.map(|path| file_path::materialized_path::equals(Some(path))),
+			],
+		)
+	}
+}
+
+pub struct LibraryNode(Library);
+
+#[Object(name = "Library")]
+impl LibraryNode {
+	async fn id(&self) -> Uuid {
+		self.0.id
+	}
+
+	async fn name(&self) -> &str {
+		&self.0.config.name
+	}
+
+	async fn object(&self, id: i32) -> Result<Option<ObjectNode>> {
+		Ok(self
+			.0
+			.db
+			.object()
+			.find_unique(object::id::equals(id))
+			.exec()
+			.await?
+			.map(|object| ObjectNode::new(&self.0, object)))
+	}
+
+	async fn objects(
+		&self,
+		filter: Option<ObjectFilter>,
+		first: Option<i32>,
+		after: Option<String>,
+	) -> Result<Connection<usize, ObjectNode>> {
+		let (skip, take) = page(first, after)?;
+
+		let objects = self
+			.0
+			.db
+			.object()
+			.find_many(filter.unwrap_or_default().into_params())
+			.order_by(object::id::order(Direction::Asc))
+			.skip(skip as i64)
+			.take(take as i64 + 1)
+			.exec()
+			.await?;
+
+		Ok(connection(
+			skip,
+			take,
+			objects
+				.into_iter()
+				.map(|object| ObjectNode::new(&self.0, object))
+				.collect(),
+		))
+	}
+
+	async fn file_paths(
+		&self,
+		filter: Option<FilePathFilter>,
+		first: Option<i32>,
+		after: Option<String>,
+	) -> Result<Connection<usize, FilePathNode>> {
+		let (skip, take) = page(first, after)?;
+
+		let file_paths = self
+			.0
+			.db
+			.file_path()
+			.find_many(filter.unwrap_or_default().into_params())
+			.order_by(file_path::id::order(Direction::Asc))
+			.skip(skip as i64)
+			.take(take as i64 + 1)
+			.exec()
+			.await?;
+
+		Ok(connection(
+			skip,
+			take,
+			file_paths
+				.into_iter()
+				.map(|file_path| FilePathNode::new(&self.0, file_path))
+				.collect(),
+		))
+	}
+
+	async fn tags(
+		&self,
+		first: Option<i32>,
+		after: Option<String>,
+	) -> Result<Connection<usize, TagNode>> {
+		let (skip, take) = page(first, after)?;
+
+		let tags = self
+			.0
+			.db
+			.tag()
+			.find_many(vec![tag::date_deleted::equals(None)])
+			.order_by(tag::id::order(Direction::Asc))
+			.skip(skip as i64)
+			.take(take as i64 + 1)
+			.exec()
+			.await?;
+
+		Ok(connection(
+			skip,
+			take,
+			tags.into_iter()
+				.map(|tag| TagNode::new(&self.0, tag))
+				.collect(),
+		))
+	}
+
+	/// The jobs of the library, the last created first
+	async fn jobs(
+		&self,
+		#[graphql(desc = "Only the jobs of this name")] name: Option<String>,
+		first: Option<i32>,
+		after: Option<String>,
+	) -> Result<Connection<usize, JobNode>> {
+		let (skip, take) = page(first, after)?;
+
+		let jobs = self
+			.0
+			.db
+			.job()
+			.find_many(chain_optional_iter(
+				[],
+				[name.map(|name| job::name::equals(Some(name)))],
+			))
+			.order_by(job::date_created::order(Direction::Desc))
+			.skip(skip as i64)
+			.take(take as i64 + 1)
+			.exec()
+			.await?;
+
+		Ok(connection(
+			skip,
+			take,
+			jobs.into_iter().map(JobNode::from).collect(),
+		))
+	}
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex, name = "Object")]
+pub struct ObjectNode {
+	id: i32,
+	pub_id: Option<Uuid>,
+	/// From `ObjectKind`
+	kind: Option<i32>,
+	favorite: Option<bool>,
+	hidden: Option<bool>,
+	important: Option<bool>,
+	note: Option<String>,
+	date_created: Option<DateTime<FixedOffset>>,
+	date_accessed: Option<DateTime<FixedOffset>>,
+	#[graphql(skip)]
+	library: Library,
+}
+
+impl ObjectNode {
+	fn new(library: &Library, object: object::Data) -> Self {
+		Self {
+			id: object.id,
+			pub_id: Uuid::from_slice(&object.pub_id).ok(),
+			kind: object.kind,
+			favorite: object.favorite,
+			hidden: object.hidden,
+			important: object.important,
+			note: object.note,
+			date_created: object.date_created,
+			date_accessed: object.date_accessed,
+			library: library.clone(),
+		}
+	}
+}
+
+#[ComplexObject]
+impl ObjectNode {
+	async fn file_paths(&self) -> Result<Vec<FilePathNode>> {
+		Ok(self
+			.library
+			.db
+			.file_path()
+			.find_many(vec![file_path::object_id::equals(Some(self.id))])
+			.exec()
+			.await?
+			.into_iter()
+			.map(|file_path| FilePathNode::new(&self.library, file_path))
+			.collect())
+	}
+
+	async fn tags(&self) -> Result<Vec<TagNode>> {
+		Ok(self
+			.library
+			.db
+			.tag()
+			.find_many(vec![
+				tag::tag_objects::some(vec![tag_on_object::object_id::equals(self.id)]),
+				tag::date_deleted::equals(None),
+			])
+			.exec()
+			.await?
+			.into_iter()
+			.map(|tag| TagNode::new(&self.library, tag))
+			.collect())
+	}
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex, name = "FilePath")]
+pub struct FilePathNode {
+	id: i32,
+	pub_id: Option<Uuid>,
+	location_id: Option<i32>,
+	object_id: Option<i32>,
+	is_dir: Option<bool>,
+	/// The directory of the file in its location, like `/photos/`
+	materialized_path: Option<String>,
+	name: Option<String>,
+	extension: Option<String>,
+	/// In bytes, as a string as it can be bigger than a GraphQL `Int`
+	size: Option<String>,
+	cas_id: Option<String>,
+	date_created: Option<DateTime<FixedOffset>>,
+	date_modified: Option<DateTime<FixedOffset>>,
+	date_indexed: Option<DateTime<FixedOffset>>,
+	#[graphql(skip)]
+	library: Library,
+}
+
+impl FilePathNode {
+	fn new(library: &Library, file_path: file_path::Data) -> Self {
+		Self {
+			id: file_path.id,
+			pub_id: Uuid::from_slice(&file_path.pub_id).ok(),
+			location_id: file_path.location_id,
+			object_id: file_path.object_id,
+			is_dir: file_path.is_dir,
+			materialized_path: file_path.materialized_path,
+			name: file_path.name,
+			extension: file_path.extension,
+			size: file_path
+				.size_in_bytes_bytes
+				.and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+				.map(|bytes| u64::from_be_bytes(bytes).to_string()),
+			cas_id: file_path.cas_id,
+			date_created: file_path.date_created,
+			date_modified: file_path.date_modified,
+			date_indexed: file_path.date_indexed,
+			library: library.clone(),
+		}
+	}
+}
+
+#[ComplexObject]
+impl FilePathNode {
+	async fn object(&self) -> Result<Option<ObjectNode>> {
+		let Some(object_id) = self.object_id else {
+			return Ok(None);
+		};
+
+		Ok(self
+			.library
+			.db
+			.object()
+			.find_unique(object::id::equals(object_id))
+			.exec()
+			.await?
+			.map(|object| ObjectNode::new(&self.library, object)))
+	}
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex, name = "Tag")]
+pub struct TagNode {
+	id: i32,
+	pub_id: Option<Uuid>,
+	name: Option<String>,
+	color: Option<String>,
+	date_created: Option<DateTime<FixedOffset>>,
+	#[graphql(skip)]
+	library: Library,
+}
+
+impl TagNode {
+	fn new(library: &Library, tag: tag::Data) -> Self {
+		Self {
+			id: tag.id,
+			pub_id: Uuid::from_slice(&tag.pub_id).ok(),
+			name: tag.name,
+			color: tag.color,
+			date_created: tag.date_created,
+			library: library.clone(),
+		}
+	}
+}
+
+#[ComplexObject]
+impl TagNode {
+	async fn objects(
+		&self,
+		first: Option<i32>,
+		after: Option<String>,
+	) -> Result<Connection<usize, ObjectNode>> {
+		let (skip, take) = page(first, after)?;
+
+		let objects = self
+			.library
+			.db
+			.object()
+			.find_many(vec![object::tags::some(vec![
+				tag_on_object::tag_id::equals(self.id),
+			])])
+			.order_by(object::id::order(Direction::Asc))
+			.skip(skip as i64)
+			.take(take as i64 + 1)
+			.exec()
+			.await?;
+
+		Ok(connection(
+			skip,
+			take,
+			objects
+				.into_iter()
+				.map(|object| ObjectNode::new(&self.library, object))
+				.collect(),
+		))
+	}
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Job")]
+pub struct JobNode {
+	id: Option<Uuid>,
+	name: Option<String>,
+	action: Option<String>,
+	/// Like `Running` or `Completed`
+	status: Option<String>,
+	errors: Vec<String>,
+	task_count: Option<i32>,
+	completed_task_count: Option<i32>,
+	date_created: Option<DateTime<FixedOffset>>,
+	date_started: Option<DateTime<FixedOffset>>,
+	date_completed: Option<DateTime<FixedOffset>>,
+}
+
+impl From<job::Data> for JobNode {
+	fn from(job: job::Data) -> Self {
+		Self {
+			id: Uuid::from_slice(&job.id).ok(),
+			name: job.name,
+			action: job.action,
+			status: job
+				.status
+				.and_then(|status| crate::job::JobStatus::try_from(status).ok())
+				.map(|status| format!("{status:?}")),
+			errors: job
+				.errors_text
+				.map(|errors| errors.split("\n\n").map(str::to_string).collect())
+				.unwrap_or_default(),
+			task_count: job.task_count,
+			completed_task_count: job.completed_task_count,
+			date_created: job.date_created,
+			date_started: job.date_started,
+			date_completed: job.date_completed,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pages_from_cursors() {
+		assert_eq!(page(None, None).unwrap(), (0, DEFAULT_PAGE_SIZE));
+		assert_eq!(page(Some(10), Some("9".to_string())).unwrap(), (10, 10));
+		assert_eq!(page(Some(100_000), None).unwrap(), (0, MAX_PAGE_SIZE));
+		assert!(page(None, Some("nope".to_string())).is_err());
+	}
+}
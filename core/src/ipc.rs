@@ -0,0 +1,249 @@
+//! The procedures of the router over a Unix domain socket, or a named pipe on Windows, for the
+//! tools running next to the node like shell extensions. They skip HTTP and never leave the
+//! machine, and the socket can only be opened by the user running the node.
+//!
+//! Messages are the JSON-RPC requests and responses of the websocket of rspc, one per line. A line
+//! can hold an array of requests, answered one by one. Subscriptions send their events on the same
+//! connection until they're stopped or it's closed.
+
+use crate::{api::Router, Node};
+
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	future::{ready, Ready},
+	io,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use rspc::internal::jsonrpc::{
+	handle_json_rpc, OwnedMpscSender, Request, RequestId, Response, Sender, SubscriptionUpgrade,
+};
+use serde_json::Value;
+use tokio::{
+	io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+	sync::oneshot,
+};
+use tracing::{debug, error, info, warn};
+
+/// Where the socket is made in the data directory of the node
+pub const IPC_SOCKET_NAME: &str = "sd.sock";
+/// Events of subscriptions waiting to be written, the subscriptions wait for the connection past it
+const RESPONSES_BUFFER: usize = 256;
+
+type Subscriptions = Arc<futures_locks::Mutex<HashMap<RequestId, oneshot::Sender<()>>>>;
+
+/// The socket of the node with this data directory. Named pipes are all in the same namespace, so
+/// the pipe is named after the user, whose nodes other users can't squat.
+pub fn default_path(data_dir: &Path) -> PathBuf {
+	if cfg!(windows) {
+		let user = std::env::var("USERNAME").unwrap_or_default();
+		PathBuf::from(format!(r"\\.\pipe\spacedrive-{user}"))
+	} else {
+		data_dir.join(IPC_SOCKET_NAME)
+	}
+}
+
+struct IpcSender<'a> {
+	resp: &'a mut Option<Response>,
+	tx: mpsc::Sender<Response>,
+	subscriptions: Subscriptions,
+}
+
+impl<'a> Sender<'a> for IpcSender<'a> {
+	type SendFut = Ready<()>;
+	type SubscriptionMap = Subscriptions;
+	type OwnedSender = OwnedMpscSender;
+
+	fn subscription(self) -> SubscriptionUpgrade<'a, Self> {
+		SubscriptionUpgrade::Supported(OwnedMpscSender::new(self.tx), self.subscriptions)
+	}
+
+	fn send(self, resp: Response) -> Self::SendFut {
+		*self.resp = Some(resp);
+		ready(())
+	}
+}
+
+/// Accepts connections until the listener fails
+#[cfg(unix)]
+pub async fn serve(node: Arc<Node>, router: Arc<Router>, path: PathBuf) -> io::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+
+	use tokio::{fs, net::UnixListener};
+
+	// Left behind by a node that didn't shut down
+	match fs::remove_file(&path).await {
+		Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+		_ => {}
+	}
+
+	// Bound in a directory only the user can open, and moved in place once only they can connect
+	let private_dir = path.with_file_name(format!(
+		".{}.{}",
+		path.file_name()
+			.map(|name| name.to_string_lossy())
+			.unwrap_or_default(),
+		std::process::id()
+	));
+	match fs::remove_dir_all(&private_dir).await {
+		Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+		_ => {}
+	}
+	fs::DirBuilder::new()
+		.mode(0o700)
+		.create(&private_dir)
+		.await?;
+
+	let bound_path = private_dir.join(IPC_SOCKET_NAME);
+	let listener = UnixListener::bind(&bound_path)?;
+	fs::set_permissions(&bound_path, std::fs::Permissions::from_mode(0o600)).await?;
+	fs::rename(&bound_path, &path).await?;
+	fs::remove_dir(&private_dir).await.ok();
+	info!("Serving the API on the socket '{}'", path.display());
+
+	loop {
+		let (stream, _) = listener.accept().await?;
+		tokio::spawn(handle_connection(node.clone(), router.clone(), stream));
+	}
+}
+
+/// Accepts connections until the pipe fails
+#[cfg(windows)]
+pub async fn serve(node: Arc<Node>, router: Arc<Router>, path: PathBuf) -> io::Result<()> {
+	use tokio::net::windows::named_pipe::ServerOptions;
+
+	let mut server = ServerOptions::new()
+		.first_pipe_instance(true)
+		.reject_remote_clients(true)
+		.create(&path)?;
+	info!("Serving the API on the pipe '{}'", path.display());
+
+	loop {
+		server.connect().await?;
+
+		// The next client connects to a new instance of the pipe
+		let connected = std::mem::replace(
+			&mut server,
+			ServerOptions::new()
+				.reject_remote_clients(true)
+				.create(&path)?,
+		);
+		tokio::spawn(handle_connection(node.clone(), router.clone(), connected));
+	}
+}
+
+async fn handle_connection(
+	node: Arc<Node>,
+	router: Arc<Router>,
+	stream: impl AsyncRead + AsyncWrite + Send + 'static,
+) {
+	let (reader, mut writer) = tokio::io::split(stream);
+	let (tx, mut rx) = mpsc::channel::<Response>(RESPONSES_BUFFER);
+	let subscriptions = Subscriptions::default();
+
+	let writes = tokio::spawn(async move {
+		while let Some(resp) = rx.next().await {
+			let mut line = match serde_json::to_vec(&resp) {
+				Ok(line) => line,
+				Err(e) => {
+					error!("Failed to serialize a response of the socket: {e}");
+					continue;
+				}
+			};
+			line.push(b'\n');
+
+			if let Err(e) = writer.write_all(&line).await {
+				debug!("Socket connection closed while writing: {e}");
+				break;
+			}
+		}
+	});
+
+	let mut lines = BufReader::new(reader).lines();
+	loop {
+		let line = match lines.next_line().await {
+			Ok(Some(line)) => line,
+			Ok(None) => break,
+			Err(e) => {
+				debug!("Socket connection closed while reading: {e}");
+				break;
+			}
+		};
+
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let requests = match parse_requests(&line) {
+			Ok(requests) => requests,
+			Err(e) => {
+				warn!("Failed to decode a JSON-RPC request of the socket: {e}");
+				continue;
+			}
+		};
+
+		// A slow request doesn't hold back the next ones
+		for request in requests {
+			tokio::spawn({
+				let (node, router, mut tx, subscriptions) = (
+					node.clone(),
+					router.clone(),
+					tx.clone(),
+					subscriptions.clone(),
+				);
+
+				async move {
+					let mut resp = None;
+					handle_json_rpc(
+						node,
+						request,
+						Cow::Borrowed(&router),
+						IpcSender {
+							resp: &mut resp,
+							tx: tx.clone(),
+							subscriptions,
+						},
+					)
+					.await;
+
+					if let Some(resp) = resp {
+						tx.send(resp).await.ok();
+					}
+				}
+			});
+		}
+	}
+
+	// Dropping their senders stops the subscriptions of the connection
+	subscriptions.lock().await.clear();
+	drop(tx);
+	writes.await.ok();
+}
+
+fn parse_requests(line: &str) -> Result<Vec<Request>, serde_json::Error> {
+	match serde_json::from_str::<Value>(line)? {
+		Value::Array(requests) => requests.into_iter().map(serde_json::from_value).collect(),
+		request => Ok(vec![serde_json::from_value(request)?]),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_single_and_batched_requests() {
+		let query = r#"{"jsonrpc":"2.0","id":1,"method":"query","params":{"path":"buildInfo","input":null}}"#;
+
+		assert_eq!(parse_requests(query).unwrap().len(), 1);
+		assert_eq!(
+			parse_requests(&format!("[{query},{query}]")).unwrap().len(),
+			2
+		);
+		assert!(parse_requests("{").is_err());
+		assert!(parse_requests(r#"{"id":1}"#).is_err());
+	}
+}
@@ -1,9 +1,19 @@
 use crate::{
+	job_message,
+	library::{
+		backup::BackupError, catalog_import::CatalogImportError, integrity::IntegrityError,
+		merge::LibraryMergeError,
+	},
 	location::{indexer::IndexerError, LocationError},
 	object::{
-		file_identifier::FileIdentifierJobError, fs::error::FileSystemJobsError,
-		preview::ThumbnailerError, validation::ValidatorError,
+		file_identifier::FileIdentifierJobError,
+		fs::{archive::ArchiveError, batch_rename::BatchRenameError, error::FileSystemJobsError},
+		metadata_export::MetadataExportError,
+		preview::ThumbnailerError,
+		validation::ValidatorError,
 	},
+	plugins::PluginManagerError,
+	sync::SyncError,
 	util::{db::MissingFieldError, error::FileIOError},
 };
 
@@ -14,11 +24,15 @@ use thiserror::Error;
 use tokio::sync::oneshot;
 use uuid::Uuid;
 
+use super::JobMessage;
+
 #[derive(Error, Debug)]
 pub enum JobError {
 	// General errors
 	#[error("database error: {0}")]
 	Database(#[from] QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
 	#[error("Failed to join Tokio spawn blocking: {0}")]
 	JoinTask(#[from] tokio::task::JoinError),
 	#[error("job state encode error: {0}")]
@@ -56,7 +70,23 @@ pub enum JobError {
 	#[error(transparent)]
 	FileSystemJobsError(#[from] FileSystemJobsError),
 	#[error(transparent)]
+	BatchRename(#[from] BatchRenameError),
+	#[error(transparent)]
+	Archive(#[from] ArchiveError),
+	#[error(transparent)]
 	CryptoError(#[from] CryptoError),
+	#[error(transparent)]
+	Backup(#[from] BackupError),
+	#[error(transparent)]
+	Merge(#[from] LibraryMergeError),
+	#[error(transparent)]
+	Integrity(#[from] IntegrityError),
+	#[error(transparent)]
+	CatalogImport(#[from] CatalogImportError),
+	#[error(transparent)]
+	MetadataExport(#[from] MetadataExportError),
+	#[error(transparent)]
+	Plugin(#[from] PluginManagerError),
 	#[error("missing-field: {0}")]
 	MissingField(#[from] MissingFieldError),
 	#[error("item of type '{0}' with id '{1}' is missing from the db")]
@@ -75,6 +105,43 @@ pub enum JobError {
 	Canceled(oneshot::Sender<()>),
 }
 
+impl JobError {
+	/// The code of the error and its values, for clients to react to it and show it translated
+	pub fn message(&self) -> JobMessage {
+		match self {
+			Self::Database(e) => job_message!("error.database", error = e),
+			Self::FileIO(e) => job_message!(
+				"error.fileIO",
+				path = e.path().display(),
+				error = e.io_error()
+			),
+			Self::Location(LocationError::PathNotFound(path)) => {
+				job_message!("error.locationNotFound", location = path.display())
+			}
+			Self::Location(LocationError::UuidNotFound(id)) => {
+				job_message!("error.locationNotFound", location = id)
+			}
+			Self::Location(LocationError::IdNotFound(id)) => {
+				job_message!("error.locationNotFound", location = id)
+			}
+			Self::Indexer(IndexerError::SubPathNotFound(path))
+			| Self::ThumbnailError(ThumbnailerError::SubPathNotFound(path))
+			| Self::IdentifierError(FileIdentifierJobError::SubPathNotFound(path))
+			| Self::Validator(ValidatorError::SubPathNotFound(path)) => {
+				job_message!("error.subPathNotFound", path = path.display())
+			}
+			Self::MissingFromDb(kind, id) => {
+				job_message!("error.missingFromDb", kind = kind, id = id)
+			}
+			Self::EarlyFinish { reason, .. } => job_message!("error.earlyFinish", reason = reason),
+			Self::JobDataNotFound(_) => job_message!("error.jobDataNotFound"),
+			Self::Paused(..) => job_message!("error.paused"),
+			Self::Canceled(_) => job_message!("error.canceled"),
+			e => job_message!("error.unknown", error = e),
+		}
+	}
+}
+
 #[derive(Error, Debug)]
 pub enum JobManagerError {
 	#[error("Tried to dispatch a job that is already running: Job <name='{name}', hash='{hash}'>")]
@@ -1,15 +1,39 @@
 use crate::{
 	job::{worker::Worker, DynJob, Job, JobError},
-	library::Library,
+	library::{
+		backup::{BackupJob, PolicyBackupJob},
+		catalog_import::CatalogImportJob,
+		cleanup::OrphanCleanupJob,
+		integrity::{IntegrityCheckJob, IntegrityRepairJob},
+		maintenance::MaintenanceJob,
+		merge::LibraryMergeJob,
+		trash::TrashPurgeJob,
+		Library,
+	},
 	location::indexer::indexer_job::IndexerJob,
 	object::{
 		file_identifier::file_identifier_job::FileIdentifierJob,
 		fs::{
-			copy::FileCopierJob, cut::FileCutterJob, delete::FileDeleterJob, erase::FileEraserJob,
+			archive::{extract::FileExtractorJob, FileArchiverJob},
+			batch_rename::BatchRenameJob,
+			convert::ImageConverterJob,
+			copy::FileCopierJob,
+			cut::FileCutterJob,
+			decrypt::FileDecryptorJob,
+			dedup::FileDeduplicatorJob,
+			delete::FileDeleterJob,
+			encrypt::FileEncryptorJob,
+			erase::FileEraserJob,
+			mirror::MirrorJob,
+			mover::FileMoverJob,
+			split::{join::FileJoinerJob, FileSplitterJob},
+			transcode::VideoTranscoderJob,
 		},
-		preview::thumbnailer_job::ThumbnailerJob,
-		validation::validator_job::ObjectValidatorJob,
+		metadata_export::MetadataExportJob,
+		preview::{integrity_job::ThumbnailIntegrityJob, thumbnailer_job::ThumbnailerJob},
+		validation::{validator_job::ObjectValidatorJob, verifier_job::ObjectVerifierJob},
 	},
+	plugins::{metadata_job::PluginMetadataJob, plugin_job::PluginJob},
 	prisma::job,
 };
 
@@ -24,10 +48,7 @@ use tokio::sync::{mpsc, oneshot, RwLock};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use super::{JobManagerError, JobReport, JobStatus};
-
-// db is single threaded, nerd
-const MAX_WORKERS: usize = 1;
+use super::{jobs_conflict, JobManagerError, JobReport, JobStatus};
 
 pub enum JobManagerEvent {
 	IngestJob(Library, Box<dyn DynJob>),
@@ -38,7 +59,8 @@ pub enum JobManagerEvent {
 ///
 pub struct JobManager {
 	current_jobs_hashes: RwLock<HashSet<u64>>,
-	job_queue: RwLock<VecDeque<Box<dyn DynJob>>>,
+	/// Jobs waiting for a library to run fewer jobs than its settings allow, with the library
+	job_queue: RwLock<VecDeque<(Library, Box<dyn DynJob>)>>,
 	running_workers: RwLock<HashMap<Uuid, Worker>>,
 	internal_sender: mpsc::UnboundedSender<JobManagerEvent>,
 }
@@ -107,7 +129,8 @@ impl JobManager {
 		Ok(())
 	}
 
-	/// Dispatches a job to a worker if under MAX_WORKERS limit, queues it otherwise.
+	/// Dispatches a job to a worker if its library runs fewer jobs than its settings allow, queues
+	/// it otherwise. Each library has its own database, so the libraries don't limit each other.
 	async fn dispatch(self: Arc<Self>, library: &Library, mut job: Box<dyn DynJob>) {
 		let mut running_workers = self.running_workers.write().await;
 		let mut job_report = job
@@ -115,7 +138,22 @@ impl JobManager {
 			.take()
 			.expect("critical error: missing job on worker");
 
-		if running_workers.len() < MAX_WORKERS {
+		let library_workers = running_workers
+			.values()
+			.filter(|worker| worker.library_id() == library.id)
+			.collect::<Vec<_>>();
+
+		// Jobs working hard on the same files as a running one wait for it
+		let can_run = !library_workers
+			.iter()
+			.any(|worker| worker.conflicts_with(job.as_ref()))
+			&& if job.is_low_priority() {
+				library_workers.is_empty()
+			} else {
+				library_workers.len() < library.config.settings.max_concurrent_jobs as usize
+			};
+
+		if can_run {
 			info!("Running job: {:?}", job.name());
 
 			let worker_id = job_report.parent_id.unwrap_or(job_report.id);
@@ -144,7 +182,10 @@ impl JobManager {
 			// Put the report back, or it will be lost forever
 			*job.report_mut() = Some(job_report);
 
-			self.job_queue.write().await.push_back(job);
+			self.job_queue
+				.write()
+				.await
+				.push_back((library.clone(), job));
 		}
 	}
 
@@ -157,24 +198,88 @@ impl JobManager {
 	) {
 		// remove worker from running workers and from current jobs hashes
 		self.current_jobs_hashes.write().await.remove(&job_hash);
-		self.running_workers.write().await.remove(&worker_id);
-		// continue queue
-		let job = if next_job.is_some() {
-			next_job
-		} else {
-			self.job_queue.write().await.pop_front()
+		let running_workers = {
+			let mut running_workers = self.running_workers.write().await;
+			running_workers.remove(&worker_id);
+			running_workers.downgrade()
 		};
+		let library_workers = running_workers
+			.values()
+			.filter(|worker| worker.library_id() == library.id)
+			.collect::<Vec<_>>();
+
+		// continue queue, only the library of the finished job has free workers
+		let mut next = next_job
+			.map(|job| (library.clone(), job))
+			.into_iter()
+			.collect::<Vec<_>>();
+		let free_workers = (library.config.settings.max_concurrent_jobs as usize)
+			.saturating_sub(library_workers.len() + next.len());
+
+		let mut job_queue = self.job_queue.write().await;
+		let mut picked = Vec::<usize>::new();
+		for (i, (queued_library, job)) in job_queue.iter().enumerate() {
+			if picked.len() >= free_workers {
+				break;
+			}
+
+			// Jobs that conflict with a running one wait for it, the ones after them can start
+			if queued_library.id == library.id
+				&& !job.is_low_priority()
+				&& !library_workers
+					.iter()
+					.any(|worker| worker.conflicts_with(job.as_ref()))
+				&& !picked.iter().any(|&picked| {
+					let picked = &job_queue[picked].1;
+					jobs_conflict(
+						(picked.load(), &picked.locations()),
+						(job.load(), &job.locations()),
+					)
+				}) {
+				picked.push(i);
+			}
+		}
+
+		// Low priority jobs only start once the library runs nothing else
+		if picked.is_empty() && next.is_empty() && library_workers.is_empty() {
+			picked.extend(
+				job_queue
+					.iter()
+					.position(|(queued_library, _)| queued_library.id == library.id),
+			);
+		}
 
-		if let Some(job) = job {
+		// Removed from the back, so the indexes of the others don't move
+		let mut started = picked
+			.into_iter()
+			.rev()
+			.filter_map(|i| job_queue.remove(i))
+			.collect::<Vec<_>>();
+		started.reverse();
+		next.extend(started);
+
+		drop(job_queue);
+		drop(library_workers);
+		drop(running_workers);
+
+		for (library, job) in next {
 			// We can't directly execute `self.ingest` here because it would cause an async cycle.
 			self.internal_sender
-				.send(JobManagerEvent::IngestJob(library.clone(), job))
+				.send(JobManagerEvent::IngestJob(library, job))
 				.unwrap_or_else(|_| {
 					error!("Failed to ingest job!");
 				});
 		}
 	}
 
+	/// How many jobs are running and how many are queued
+	pub(crate) async fn counts(&self) -> (usize, usize) {
+		(
+			self.running_workers.read().await.len(),
+			self.job_queue.read().await.len(),
+		)
+	}
+
 	/// Shutdown the job manager, signaled by core on shutdown.
 	pub async fn shutdown(&self) {
 		let (tx, rx) = oneshot::channel();
@@ -354,13 +459,39 @@ fn initialize_resumable_job(
 		},
 		jobs = [
 			ThumbnailerJob,
+			ThumbnailIntegrityJob,
 			IndexerJob,
 			FileIdentifierJob,
 			ObjectValidatorJob,
+			ObjectVerifierJob,
 			FileCutterJob,
 			FileCopierJob,
+			FileMoverJob,
+			BatchRenameJob,
+			FileArchiverJob,
+			FileExtractorJob,
+			FileEncryptorJob,
+			FileDecryptorJob,
+			FileDeduplicatorJob,
+			ImageConverterJob,
+			VideoTranscoderJob,
+			MirrorJob,
+			FileSplitterJob,
+			FileJoinerJob,
 			FileDeleterJob,
 			FileEraserJob,
+			BackupJob,
+			PolicyBackupJob,
+			MaintenanceJob,
+			LibraryMergeJob,
+			IntegrityCheckJob,
+			IntegrityRepairJob,
+			OrphanCleanupJob,
+			TrashPurgeJob,
+			PluginMetadataJob,
+			PluginJob,
+			CatalogImportJob,
+			MetadataExportJob,
 		]
 	)
 }
@@ -0,0 +1,276 @@
+//! The messages of the jobs, as codes and the values shown in them, so clients can show them in
+//! their language and tell failures apart without reading their text. The English text of the
+//! message goes along with it, for the logs and the clients without a translation of the code.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// The English text of every code, with the names of their values between braces
+pub const MESSAGES: &[(&str, &str)] = &[
+	// Progress
+	(
+		"archive.archiving",
+		"Archiving {path}, {archived} of {total} bytes in total",
+	),
+	(
+		"archive.extracting",
+		"Extracting {name}, {extracted} bytes extracted so far",
+	),
+	("backup.backingUp", "Backing up {path}"),
+	("backup.snapshotting", "Creating snapshot"),
+	("backup.uploading", "Uploading snapshot to '{target}'"),
+	("catalogImport.importing", "Importing {count} images"),
+	(
+		"catalogImport.importingBatch",
+		"Importing images {from} to {to}",
+	),
+	(
+		"cleanup.filePaths",
+		"Looking for file paths of deleted locations",
+	),
+	("cleanup.jobs", "Looking for jobs of deleted libraries"),
+	(
+		"cleanup.tagLinks",
+		"Looking for tag links of deleted objects",
+	),
+	("convert.converting", "Converting {path}"),
+	(
+		"copy.copying",
+		"Copying {path}, {copied} of {total} bytes in total",
+	),
+	(
+		"copy.copyingFile",
+		"Copying {name}: {copied} of {size} bytes, {total_copied} of {total} bytes in total",
+	),
+	("copy.copyingSparseFile", "Copying sparse file {path}"),
+	("crypto.decrypting", "Decrypting {path}"),
+	(
+		"crypto.decryptingProgress",
+		"Decrypting {path} ({percentage}%)",
+	),
+	("crypto.encrypting", "Encrypting {path}"),
+	("dedup.comparing", "Comparing {path}"),
+	(
+		"fileIdentifier.processed",
+		"Processed {processed} of {total} orphan Paths",
+	),
+	(
+		"indexer.saving",
+		"Starting saving {count} files or directories, there still {to_walk} directories to index",
+	),
+	(
+		"indexer.scanned",
+		"Scanned more {count} files or directories; {to_walk} more directories to scan",
+	),
+	(
+		"indexer.scanning",
+		"Scanning: {path}; Found: {count} entries",
+	),
+	(
+		"indexer.writingChunk",
+		"Writing chunk {chunk} of {chunks} to database",
+	),
+	("integrity.applyingRepairs", "Applying {count} repairs"),
+	(
+		"integrity.danglingTagLinks",
+		"Looking for tags linked to deleted objects",
+	),
+	(
+		"integrity.duplicatePaths",
+		"Looking for files indexed more than once",
+	),
+	(
+		"integrity.impossibleSizes",
+		"Looking for impossible file sizes",
+	),
+	(
+		"integrity.pathsWithoutLocation",
+		"Looking for file paths without a location",
+	),
+	("maintenance.analyzing", "Updating the query statistics"),
+	(
+		"maintenance.checkpointing",
+		"Checkpointing the write-ahead log",
+	),
+	("maintenance.vacuuming", "Rebuilding the database"),
+	(
+		"merge.merging",
+		"Merging {objects} objects and {file_paths} file paths",
+	),
+	(
+		"merge.mergingFilePaths",
+		"Merging file paths {from} to {to}",
+	),
+	("merge.mergingObjects", "Merging objects {from} to {to}"),
+	("metadataExport.exporting", "Exporting {count} files"),
+	("mirror.comparing", "Comparing {source} with {destination}"),
+	("mirror.copying", "Copying {path}"),
+	("move.moving", "Moving {path}"),
+	(
+		"plugins.extractingMetadata",
+		"Extracting metadata from {path}",
+	),
+	("plugins.runningStep", "Running step {step} of '{job}'"),
+	("split.joiningPart", "Joining part {part} of {parts}"),
+	("split.verifying", "Verifying {name}"),
+	("split.writingPart", "Writing part {part} of {parts}"),
+	("thumbnails.checking", "Checking {path}"),
+	("thumbnails.preparing", "Preparing to process {count} files"),
+	(
+		"thumbnails.preparingCheck",
+		"Preparing to check {count} thumbnails",
+	),
+	("thumbnails.processing", "Processing {path}"),
+	(
+		"thumbnails.removingOrphan",
+		"Removing orphan thumbnail {cas_id}",
+	),
+	("transcode.transcoding", "Transcoding {path}"),
+	(
+		"transcode.transcodingProgress",
+		"Transcoding {path} ({percentage}%)",
+	),
+	("validation.verifying", "Verifying {path}"),
+	// Failures
+	("error.canceled", "The job was canceled"),
+	("error.database", "Database error: {error}"),
+	("error.earlyFinish", "The job finished early: {reason}"),
+	("error.fileIO", "Error accessing '{path}': {error}"),
+	(
+		"error.jobDataNotFound",
+		"The data the job needs wasn't found",
+	),
+	(
+		"error.locationNotFound",
+		"The location wasn't found: {location}",
+	),
+	(
+		"error.missingFromDb",
+		"The {kind} with id '{id}' isn't in the database anymore",
+	),
+	("error.paused", "The job was paused"),
+	(
+		"error.subPathNotFound",
+		"The path '{path}' isn't indexed in the location",
+	),
+	("error.unknown", "{error}"),
+];
+
+/// A message of a job, shown while it's running or when it failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct JobMessage {
+	/// Like `copy.copying`, see [`MESSAGES`]
+	pub code: String,
+	/// The values shown in the message, by the names in its text
+	pub params: BTreeMap<String, String>,
+	/// In English
+	pub text: String,
+}
+
+impl JobMessage {
+	pub fn new(code: &str, params: impl IntoIterator<Item = (&'static str, String)>) -> Self {
+		debug_assert!(
+			template(code).is_some(),
+			"the job message '{code}' isn't in `MESSAGES`"
+		);
+
+		let params = params
+			.into_iter()
+			.map(|(name, value)| (name.to_string(), value))
+			.collect();
+
+		Self {
+			text: render(code, &params),
+			code: code.to_string(),
+			params,
+		}
+	}
+}
+
+impl fmt::Display for JobMessage {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.text)
+	}
+}
+
+/// A [`JobMessage`] from its code and its values, like
+/// `job_message!("move.moving", path = path.display())`
+#[macro_export]
+macro_rules! job_message {
+	($code:literal $(, $name:ident = $value:expr)* $(,)?) => {
+		$crate::job::JobMessage::new($code, [$((stringify!($name), ($value).to_string())),*])
+	};
+}
+
+fn template(code: &str) -> Option<&'static str> {
+	MESSAGES
+		.iter()
+		.find_map(|(message_code, text)| (*message_code == code).then_some(*text))
+}
+
+/// The text of the code with its values, the code itself if it's unknown
+fn render(code: &str, params: &BTreeMap<String, String>) -> String {
+	let Some(template) = template(code) else {
+		return code.to_string();
+	};
+
+	let mut text = String::with_capacity(template.len());
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		text.push_str(&rest[..start]);
+		rest = &rest[start + 1..];
+
+		let Some(end) = rest.find('}') else {
+			text.push('{');
+			break;
+		};
+
+		let name = &rest[..end];
+		match params.get(name) {
+			Some(value) => text.push_str(value),
+			// Left as is, so a missing value shows
+			None => {
+				text.push('{');
+				text.push_str(name);
+				text.push('}');
+			}
+		}
+		rest = &rest[end + 1..];
+	}
+	text.push_str(rest);
+
+	text
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn renders_the_values() {
+		let message = job_message!("split.writingPart", part = 2, parts = 5);
+		assert_eq!(message.code, "split.writingPart");
+		assert_eq!(message.params["part"], "2");
+		assert_eq!(message.to_string(), "Writing part 2 of 5");
+
+		assert_eq!(
+			job_message!("maintenance.vacuuming").text,
+			"Rebuilding the database"
+		);
+		assert_eq!(
+			render("split.joiningPart", &BTreeMap::new()),
+			"Joining part {part} of {parts}"
+		);
+		assert_eq!(render("unknown.code", &BTreeMap::new()), "unknown.code");
+	}
+
+	#[test]
+	fn codes_are_unique() {
+		let mut codes = MESSAGES.iter().map(|(code, _)| *code).collect::<Vec<_>>();
+		codes.sort_unstable();
+		codes.dedup();
+		assert_eq!(codes.len(), MESSAGES.len());
+	}
+}
@@ -16,12 +16,16 @@ use uuid::Uuid;
 
 mod error;
 mod manager;
+mod message;
 mod report;
+mod schedule;
 mod worker;
 
 pub use error::*;
 pub use manager::*;
+pub use message::*;
 pub use report::*;
+pub use schedule::*;
 pub use worker::*;
 
 pub type JobResult = Result<JobMetadata, JobError>;
@@ -46,6 +50,11 @@ pub struct JobRunOutput {
 pub trait JobInitData: Serialize + DeserializeOwned + Send + Sync + Hash + fmt::Debug {
 	type Job: StatefulJob;
 
+	/// The locations whose files the job works on, see [`StatefulJob::LOAD`]
+	fn locations(&self) -> JobLocations {
+		JobLocations::None
+	}
+
 	fn hash(&self) -> u64 {
 		let mut s = DefaultHasher::new();
 		<Self::Job as StatefulJob>::NAME.hash(&mut s);
@@ -74,6 +83,12 @@ pub trait StatefulJob: Send + Sync + Sized + 'static {
 	/// The name of the job is a unique human readable identifier for the job.
 	const NAME: &'static str;
 	const IS_BACKGROUND: bool = false;
+	/// Low priority jobs wait for their library to run no other job, and the jobs queued after
+	/// them start first.
+	const IS_LOW_PRIORITY: bool = false;
+	/// How hard the job works the files of its locations, the jobs that would work the same files
+	/// too hard wait for it.
+	const LOAD: JobLoad = JobLoad::Light;
 
 	/// Construct a new instance of the job. This is used so the user can pass `Self::Init` into the `spawn_job` function and we can still run the job.
 	/// This does remove the flexibility of being able to pass arguments into the job's struct but with resumable jobs I view that as an anti-pattern anyway.
@@ -108,6 +123,9 @@ pub trait DynJob: Send + Sync {
 	fn report(&self) -> &Option<JobReport>;
 	fn report_mut(&mut self) -> &mut Option<JobReport>;
 	fn name(&self) -> &'static str;
+	fn is_low_priority(&self) -> bool;
+	fn load(&self) -> JobLoad;
+	fn locations(&self) -> JobLocations;
 	async fn run(
 		&mut self,
 		ctx: WorkerContext,
@@ -415,6 +433,21 @@ impl<SJob: StatefulJob> DynJob for Job<SJob> {
 		<SJob as StatefulJob>::NAME
 	}
 
+	fn is_low_priority(&self) -> bool {
+		<SJob as StatefulJob>::IS_LOW_PRIORITY
+	}
+
+	fn load(&self) -> JobLoad {
+		<SJob as StatefulJob>::LOAD
+	}
+
+	fn locations(&self) -> JobLocations {
+		self.state
+			.as_ref()
+			.map(|state| state.init.locations())
+			.unwrap_or_default()
+	}
+
 	async fn run(
 		&mut self,
 		ctx: WorkerContext,
@@ -1,7 +1,7 @@
 use crate::{
 	library::Library,
 	prisma::{job, node},
-	util::db::{chain_optional_iter, maybe_missing, MissingFieldError},
+	util::db::{chain_optional_iter, maybe_missing, retry_if_busy, MissingFieldError},
 };
 
 use std::fmt::{Display, Formatter};
@@ -12,13 +12,13 @@ use specta::Type;
 use tracing::error;
 use uuid::Uuid;
 
-use super::JobError;
+use super::{JobError, JobMessage};
 
 #[derive(Debug)]
 pub enum JobReportUpdate {
 	TaskCount(usize),
 	CompletedTaskCount(usize),
-	Message(String),
+	Message(JobMessage),
 }
 
 job::select!(job_without_data {
@@ -28,6 +28,7 @@ job::select!(job_without_data {
 	status
 	parent_id
 	errors_text
+	error
 	metadata
 	date_created
 	date_started
@@ -46,6 +47,8 @@ pub struct JobReport {
 	pub metadata: Option<serde_json::Value>,
 	pub is_background: bool,
 	pub errors_text: Vec<String>,
+	/// Why the job failed
+	pub error: Option<JobMessage>,
 
 	pub created_at: Option<DateTime<Utc>>,
 	pub started_at: Option<DateTime<Utc>>,
@@ -57,7 +60,7 @@ pub struct JobReport {
 	pub task_count: i32,
 	pub completed_task_count: i32,
 
-	pub message: String,
+	pub message: Option<JobMessage>,
 	pub estimated_completion: DateTime<Utc>,
 }
 
@@ -92,6 +95,12 @@ impl TryFrom<job::Data> for JobReport {
 				.errors_text
 				.map(|errors_str| errors_str.split("\n\n").map(str::to_string).collect())
 				.unwrap_or_default(),
+			error: data.error.and_then(|error| {
+				serde_json::from_slice(&error).unwrap_or_else(|e| {
+					error!("Failed to deserialize job error: {}", e);
+					None
+				})
+			}),
 			created_at: data.date_created.map(DateTime::into),
 			started_at: data.date_started.map(DateTime::into),
 			completed_at: data.date_completed.map(DateTime::into),
@@ -102,7 +111,7 @@ impl TryFrom<job::Data> for JobReport {
 				.expect("corrupted database"),
 			task_count: data.task_count.unwrap_or(0),
 			completed_task_count: data.completed_task_count.unwrap_or(0),
-			message: String::new(),
+			message: None,
 			estimated_completion: data
 				.date_estimated_completion
 				.map_or(Utc::now(), DateTime::into),
@@ -133,6 +142,12 @@ impl TryFrom<job_without_data::Data> for JobReport {
 				.errors_text
 				.map(|errors_str| errors_str.split("\n\n").map(str::to_string).collect())
 				.unwrap_or_default(),
+			error: data.error.and_then(|error| {
+				serde_json::from_slice(&error).unwrap_or_else(|e| {
+					error!("Failed to deserialize job error: {}", e);
+					None
+				})
+			}),
 			created_at: data.date_created.map(DateTime::into),
 			started_at: data.date_started.map(DateTime::into),
 			completed_at: data.date_completed.map(DateTime::into),
@@ -144,7 +159,7 @@ impl TryFrom<job_without_data::Data> for JobReport {
 			task_count: data.task_count.unwrap_or(0),
 			completed_task_count: data.completed_task_count.unwrap_or(0),
 
-			message: String::new(),
+			message: None,
 			estimated_completion: data
 				.date_estimated_completion
 				.map_or(Utc::now(), DateTime::into),
@@ -164,12 +179,13 @@ impl JobReport {
 			completed_at: None,
 			status: JobStatus::Queued,
 			errors_text: vec![],
+			error: None,
 			task_count: 0,
 			data: None,
 			metadata: None,
 			parent_id: None,
 			completed_task_count: 0,
-			message: String::new(),
+			message: None,
 			estimated_completion: Utc::now(),
 		}
 	}
@@ -216,30 +232,33 @@ impl JobReport {
 	pub async fn create(&mut self, library: &Library) -> Result<(), JobError> {
 		let now = Utc::now();
 
-		library
-			.db
-			.job()
-			.create(
-				self.id.as_bytes().to_vec(),
-				chain_optional_iter(
-					[
-						job::node::connect(node::id::equals(library.node_local_id)),
-						job::name::set(Some(self.name.clone())),
-						job::action::set(self.action.clone()),
-						job::data::set(self.data.clone()),
-						job::date_created::set(Some(now.into())),
-						job::status::set(Some(self.status as i32)),
-						job::date_started::set(self.started_at.map(|d| d.into())),
-						job::task_count::set(Some(1)),
-						job::completed_task_count::set(Some(0)),
-					],
-					[self
-						.parent_id
-						.map(|id| job::parent::connect(job::id::equals(id.as_bytes().to_vec())))],
-				),
-			)
-			.exec()
-			.await?;
+		// Other jobs and the interface write at the same time, the database may be locked
+		retry_if_busy(|| {
+			library
+				.db
+				.job()
+				.create(
+					self.id.as_bytes().to_vec(),
+					chain_optional_iter(
+						[
+							job::node::connect(node::id::equals(library.node_local_id)),
+							job::name::set(Some(self.name.clone())),
+							job::action::set(self.action.clone()),
+							job::data::set(self.data.clone()),
+							job::date_created::set(Some(now.into())),
+							job::status::set(Some(self.status as i32)),
+							job::date_started::set(self.started_at.map(|d| d.into())),
+							job::task_count::set(Some(1)),
+							job::completed_task_count::set(Some(0)),
+						],
+						[self.parent_id.map(|id| {
+							job::parent::connect(job::id::equals(id.as_bytes().to_vec()))
+						})],
+					),
+				)
+				.exec()
+		})
+		.await?;
 
 		// Only setting created_at after we successfully created the job in DB
 		self.created_at = Some(now);
@@ -248,26 +267,33 @@ impl JobReport {
 	}
 
 	pub async fn update(&mut self, library: &Library) -> Result<(), JobError> {
-		library
-			.db
-			.job()
-			.update(
-				job::id::equals(self.id.as_bytes().to_vec()),
-				vec![
-					job::status::set(Some(self.status as i32)),
-					job::errors_text::set(
-						(!self.errors_text.is_empty()).then(|| self.errors_text.join("\n\n")),
-					),
-					job::data::set(self.data.clone()),
-					job::metadata::set(serde_json::to_vec(&self.metadata).ok()),
-					job::task_count::set(Some(self.task_count)),
-					job::completed_task_count::set(Some(self.completed_task_count)),
-					job::date_started::set(self.started_at.map(Into::into)),
-					job::date_completed::set(self.completed_at.map(Into::into)),
-				],
-			)
-			.exec()
-			.await?;
+		retry_if_busy(|| {
+			library
+				.db
+				.job()
+				.update(
+					job::id::equals(self.id.as_bytes().to_vec()),
+					vec![
+						job::status::set(Some(self.status as i32)),
+						job::errors_text::set(
+							(!self.errors_text.is_empty()).then(|| self.errors_text.join("\n\n")),
+						),
+						job::error::set(
+							self.error
+								.as_ref()
+								.and_then(|error| serde_json::to_vec(error).ok()),
+						),
+						job::data::set(self.data.clone()),
+						job::metadata::set(serde_json::to_vec(&self.metadata).ok()),
+						job::task_count::set(Some(self.task_count)),
+						job::completed_task_count::set(Some(self.completed_task_count)),
+						job::date_started::set(self.started_at.map(Into::into)),
+						job::date_completed::set(self.completed_at.map(Into::into)),
+					],
+				)
+				.exec()
+		})
+		.await?;
 		Ok(())
 	}
 }
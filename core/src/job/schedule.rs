@@ -0,0 +1,93 @@
+//! Which jobs of a library can run at the same time. A library runs up to as many jobs as its
+//! settings allow, as long as they don't work hard on the same locations, so identifying the files
+//! of a location on a SSD doesn't wait for the thumbnails of a location on a NAS.
+
+use crate::prisma::location;
+
+/// How hard a job works the files of its locations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobLoad {
+	/// Barely touches the files, like reading their metadata
+	Light,
+	/// Reads the contents of the files
+	ReadHeavy,
+	/// Changes the files, or their index
+	WriteHeavy,
+}
+
+impl JobLoad {
+	/// Whether jobs with these loads can't work on the same location at the same time. A job
+	/// changing the files slows down the ones reading them, which may read them half written.
+	pub fn conflicts_with(self, other: Self) -> bool {
+		matches!(
+			(self, other),
+			(Self::WriteHeavy, Self::WriteHeavy | Self::ReadHeavy)
+				| (Self::ReadHeavy, Self::WriteHeavy)
+		)
+	}
+}
+
+/// The locations whose files a job works on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum JobLocations {
+	/// The job doesn't work on the files of the locations
+	#[default]
+	None,
+	Some(Vec<location::id::Type>),
+	/// Every location of the library
+	All,
+}
+
+impl JobLocations {
+	pub fn overlap(&self, other: &Self) -> bool {
+		match (self, other) {
+			(Self::None, _) | (_, Self::None) => false,
+			(Self::All, _) | (_, Self::All) => true,
+			(Self::Some(ids), Self::Some(other_ids)) => ids.iter().any(|id| other_ids.contains(id)),
+		}
+	}
+}
+
+/// Whether two jobs can't run at the same time, by their loads and locations
+pub fn jobs_conflict(
+	(load, locations): (JobLoad, &JobLocations),
+	(other_load, other_locations): (JobLoad, &JobLocations),
+) -> bool {
+	load.conflicts_with(other_load) && locations.overlap(other_locations)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn heavy_jobs_on_the_same_locations_conflict() {
+		let ssd = JobLocations::Some(vec![1]);
+		let nas = JobLocations::Some(vec![2, 3]);
+
+		assert!(!jobs_conflict(
+			(JobLoad::ReadHeavy, &ssd),
+			(JobLoad::ReadHeavy, &ssd)
+		));
+		assert!(!jobs_conflict(
+			(JobLoad::WriteHeavy, &ssd),
+			(JobLoad::WriteHeavy, &nas)
+		));
+		assert!(jobs_conflict(
+			(JobLoad::WriteHeavy, &nas),
+			(JobLoad::ReadHeavy, &JobLocations::Some(vec![3]))
+		));
+		assert!(jobs_conflict(
+			(JobLoad::WriteHeavy, &JobLocations::All),
+			(JobLoad::WriteHeavy, &ssd)
+		));
+		assert!(!jobs_conflict(
+			(JobLoad::WriteHeavy, &JobLocations::All),
+			(JobLoad::Light, &ssd)
+		));
+		assert!(!jobs_conflict(
+			(JobLoad::WriteHeavy, &JobLocations::None),
+			(JobLoad::WriteHeavy, &JobLocations::All)
+		));
+	}
+}
@@ -1,4 +1,12 @@
-use crate::{api::CoreEvent, invalidate_query, library::Library};
+use crate::{
+	api::CoreEvent,
+	invalidate_query,
+	library::{
+		rules::{self, RuleEvent},
+		Library,
+	},
+	node::logs::Logs,
+};
 
 use std::{
 	fmt,
@@ -17,11 +25,12 @@ use tokio::{
 	sync::{mpsc, oneshot, watch},
 	time::Instant,
 };
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 use uuid::Uuid;
 
 use super::{
-	DynJob, JobError, JobManager, JobReport, JobReportUpdate, JobRunErrors, JobRunOutput, JobStatus,
+	jobs_conflict, DynJob, JobError, JobLoad, JobLocations, JobManager, JobMessage, JobReport,
+	JobReportUpdate, JobRunErrors, JobRunOutput, JobStatus,
 };
 
 #[derive(Debug, Clone, Serialize, Type)]
@@ -29,7 +38,7 @@ pub struct JobProgressEvent {
 	pub id: Uuid,
 	pub task_count: i32,
 	pub completed_task_count: i32,
-	pub message: String,
+	pub message: Option<JobMessage>,
 	pub estimated_completion: DateTime<Utc>,
 }
 
@@ -51,6 +60,8 @@ pub enum WorkerCommand {
 
 pub struct WorkerContext {
 	pub library: Library,
+	/// The id of the job's report
+	pub job_id: Uuid,
 	pub(super) events_tx: mpsc::UnboundedSender<WorkerEvent>,
 }
 
@@ -71,7 +82,7 @@ impl Drop for WorkerContext {
 	}
 }
 impl WorkerContext {
-	pub fn progress_msg(&self, msg: String) {
+	pub fn progress_msg(&self, msg: JobMessage) {
 		self.progress(vec![JobReportUpdate::Message(msg)]);
 	}
 
@@ -88,6 +99,9 @@ impl WorkerContext {
 // a worker is a dedicated thread that runs a single job
 // once the job is complete the worker will exit
 pub struct Worker {
+	library_id: Uuid,
+	load: JobLoad,
+	locations: JobLocations,
 	commands_tx: mpsc::Sender<WorkerCommand>,
 	report_watch_tx: Arc<watch::Sender<JobReport>>,
 	report_watch_rx: watch::Receiver<JobReport>,
@@ -105,6 +119,8 @@ impl Worker {
 		let (commands_tx, commands_rx) = mpsc::channel(8);
 
 		let job_hash = job.hash();
+		let library_id = library.id;
+		let (load, locations) = (job.load(), job.locations());
 
 		let start_time = Utc::now();
 
@@ -128,22 +144,36 @@ impl Worker {
 		let (report_watch_tx, report_watch_rx) = watch::channel(report.clone());
 		let report_watch_tx = Arc::new(report_watch_tx);
 
+		// The logs of the job go to the logs of its library, and are kept for the job
+		let span = info_span!(
+			"job",
+			library_id = %library_id,
+			job = %report.name,
+			job_id = %report.id
+		);
+
 		// spawn task to handle running the job
-		tokio::spawn(Self::do_work(
-			id,
-			JobWorkTable {
-				job,
-				manager: job_manager,
-				hash: job_hash,
-				report,
-			},
-			Arc::clone(&report_watch_tx),
-			start_time,
-			commands_rx,
-			library,
-		));
+		tokio::spawn(
+			Self::do_work(
+				id,
+				JobWorkTable {
+					job,
+					manager: job_manager,
+					hash: job_hash,
+					report,
+				},
+				Arc::clone(&report_watch_tx),
+				start_time,
+				commands_rx,
+				library,
+			)
+			.instrument(span),
+		);
 
 		Ok(Self {
+			library_id,
+			load,
+			locations,
 			commands_tx,
 			report_watch_tx,
 			report_watch_rx,
@@ -209,6 +239,16 @@ impl Worker {
 		}
 	}
 
+	/// The library the job runs in
+	pub fn library_id(&self) -> Uuid {
+		self.library_id
+	}
+
+	/// Whether the job can't run alongside this one, see [`jobs_conflict`]
+	pub fn conflicts_with(&self, job: &dyn DynJob) -> bool {
+		jobs_conflict((self.load, &self.locations), (job.load(), &job.locations()))
+	}
+
 	pub fn report(&self) -> JobReport {
 		self.report_watch_rx.borrow().clone()
 	}
@@ -241,7 +281,7 @@ impl Worker {
 
 				JobReportUpdate::Message(message) => {
 					trace!("job {} message: {}", report.id, message);
-					report.message = message;
+					report.message = Some(message);
 				}
 			}
 		}
@@ -300,6 +340,7 @@ impl Worker {
 		let mut job_future = job.run(
 			WorkerContext {
 				library: library.clone(),
+				job_id: report.id,
 				events_tx,
 			},
 			commands_rx,
@@ -360,12 +401,43 @@ impl Worker {
 
 		report_watch_tx.send(report.clone()).ok();
 
+		library
+			.metrics()
+			.record_job_finished(&report.name, report.status);
+		library.analytics().record_job(
+			&report.name,
+			report.status,
+			(Utc::now() - start_time).to_std().unwrap_or_default(),
+		);
+
+		if matches!(
+			report.status,
+			JobStatus::Completed | JobStatus::CompletedWithErrors
+		) {
+			rules::trigger(
+				&library,
+				RuleEvent::JobCompleted {
+					job_id: report.id,
+					name: report.name.clone(),
+					with_errors: report.status == JobStatus::CompletedWithErrors,
+				},
+			);
+		}
+
 		debug!(
 			"Worker completed Job<id='{}', name='{}'>",
 			report.id, report.name
 		);
 
+		let job_id = report.id;
 		manager.complete(&library, worker_id, hash, next_job).await;
+
+		// Last, for the tail of the job to have everything it logged
+		if let Ok(logs) = Logs::get() {
+			if let Err(e) = logs.persist_job(job_id).await {
+				warn!("Failed to save the logs of Job<id='{job_id}'>: {e}");
+			}
+		}
 	}
 
 	async fn process_job_output(
@@ -482,6 +554,8 @@ impl Worker {
 				}
 
 				report.status = JobStatus::Failed;
+				report.errors_text = vec![e.to_string()];
+				report.error = Some(e.message());
 				report.data = None;
 				if let Err(e) = report.update(library).await {
 					error!("failed to update job report: {:#?}", e);
@@ -5,15 +5,20 @@ use crate::{
 	job::JobManager,
 	library::LibraryManager,
 	location::{LocationManager, LocationManagerError},
-	node::NodeConfigManager,
+	mount::MountManager,
+	node::{Analytics, ApiTokens, NodeConfigManager, NodeMetrics, Snapshot},
+	object::preview::{ThumbnailCacheActor, ThumbnailPriorityActor},
 	p2p::P2PManager,
+	plugins::PluginManager,
 };
 
+pub use node::{ApiToken, Procedure};
 pub use sd_prisma::*;
 
 use std::{
 	path::{Path, PathBuf},
 	sync::Arc,
+	time::Instant,
 };
 
 use thiserror::Error;
@@ -23,26 +28,38 @@ use tracing_appender::{
 	non_blocking::{NonBlocking, WorkerGuard},
 	rolling::{RollingFileAppender, Rotation},
 };
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*};
 
 pub mod api;
 pub mod custom_uri;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "ipc")]
+pub mod ipc;
 pub(crate) mod job;
 pub mod library;
 pub(crate) mod location;
+pub(crate) mod mount;
 pub(crate) mod node;
 pub(crate) mod object;
 pub(crate) mod p2p;
+pub(crate) mod plugins;
 pub(crate) mod sync;
 pub(crate) mod util;
 pub(crate) mod volume;
+pub mod webdav;
 
 #[derive(Clone)]
 pub struct NodeContext {
 	pub config: Arc<NodeConfigManager>,
 	pub job_manager: Arc<JobManager>,
 	pub location_manager: Arc<LocationManager>,
+	pub thumbnail_cache: ThumbnailCacheActor,
+	pub thumbnail_priority: ThumbnailPriorityActor,
 	pub event_bus_tx: broadcast::Sender<CoreEvent>,
+	pub plugins: Arc<PluginManager>,
+	pub metrics: Arc<NodeMetrics>,
+	pub analytics: Arc<Analytics>,
 }
 
 pub struct Node {
@@ -51,7 +68,13 @@ pub struct Node {
 	pub library_manager: Arc<LibraryManager>,
 	location_manager: Arc<LocationManager>,
 	job_manager: Arc<JobManager>,
+	thumbnail_cache: ThumbnailCacheActor,
 	p2p: Arc<P2PManager>,
+	plugins: Arc<PluginManager>,
+	mounts: Arc<MountManager>,
+	metrics: Arc<NodeMetrics>,
+	analytics: Arc<Analytics>,
+	api_tokens: ApiTokens,
 	event_bus: (broadcast::Sender<CoreEvent>, broadcast::Receiver<CoreEvent>),
 	// peer_request: tokio::sync::Mutex<Option<PeerRequest>>,
 }
@@ -74,20 +97,42 @@ impl Node {
 			.map_err(NodeError::FailedToInitializeConfig)?;
 		debug!("Initialised 'NodeConfigManager'...");
 
+		let analytics =
+			Arc::new(Analytics::load(data_dir, config.get().await.analytics_enabled).await);
+		node::spawn_analytics_saver(Arc::downgrade(&analytics));
+		let api_tokens = ApiTokens::load(data_dir).await;
+
 		let job_manager = JobManager::new();
 
 		debug!("Initialised 'JobManager'...");
 
 		let location_manager = LocationManager::new();
 		debug!("Initialised 'LocationManager'...");
+
+		let thumbnail_cache = ThumbnailCacheActor::spawn(config.clone());
+		debug!("Initialised 'ThumbnailCacheActor'...");
+
+		let thumbnail_priority = ThumbnailPriorityActor::spawn(config.clone());
+		debug!("Initialised 'ThumbnailPriorityActor'...");
+
+		let plugins = PluginManager::load(&data_dir.join("plugins")).await;
+		debug!("Initialised 'PluginManager'...");
+
+		let metrics = Arc::new(NodeMetrics::default());
+
 		let library_manager = LibraryManager::new(
 			data_dir.join("libraries"),
 			NodeContext {
 				config: config.clone(),
 				job_manager: job_manager.clone(),
 				location_manager: location_manager.clone(),
+				thumbnail_cache: thumbnail_cache.clone(),
+				thumbnail_priority,
 				// p2p: p2p.clone(),
 				event_bus_tx: event_bus.0.clone(),
+				plugins: plugins.clone(),
+				metrics: metrics.clone(),
+				analytics: analytics.clone(),
 			},
 		)
 		.await?;
@@ -95,6 +140,17 @@ impl Node {
 		let p2p = P2PManager::new(config.clone(), library_manager.clone()).await?;
 		debug!("Initialised 'P2PManager'...");
 
+		library::maintenance::spawn_scheduler(
+			Arc::downgrade(&library_manager),
+			job_manager.clone(),
+			p2p.sync_scheduler.clone(),
+		);
+		object::validation::verifier_job::spawn_scheduler(
+			Arc::downgrade(&library_manager),
+			job_manager.clone(),
+			p2p.sync_scheduler.clone(),
+		);
+
 		#[cfg(debug_assertions)]
 		if let Some(init_data) = init_data {
 			init_data
@@ -109,7 +165,13 @@ impl Node {
 			library_manager,
 			location_manager,
 			job_manager,
+			thumbnail_cache,
 			p2p,
+			plugins,
+			mounts: MountManager::new(),
+			metrics,
+			analytics,
+			api_tokens,
 			event_bus,
 			// peer_request: tokio::sync::Mutex::new(None),
 		};
@@ -133,53 +195,14 @@ impl Node {
 				.expect("Error setting up log file!"),
 		);
 
+		// The filter goes first for every log to go through it
+		let (filter, lines) = node::logs::init(data_dir.as_ref().join("logs"));
+
 		let collector = tracing_subscriber::registry()
+			.with(filter)
+			.with(lines)
 			.with(fmt::Subscriber::new().with_ansi(false).with_writer(logfile))
-			.with(
-				fmt::Subscriber::new()
-					.with_writer(std::io::stdout.with_max_level(log_filter))
-					.with_filter(
-						EnvFilter::from_default_env()
-							.add_directive(
-								"warn".parse().expect("Error invalid tracing directive!"),
-							)
-							.add_directive(
-								"sd_core=debug"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							)
-							.add_directive(
-								"sd_core::location::manager=info"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							)
-							.add_directive(
-								"sd_core_mobile=debug"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							)
-							// .add_directive(
-							// 	"sd_p2p=debug"
-							// 		.parse()
-							// 		.expect("Error invalid tracing directive!"),
-							// )
-							.add_directive(
-								"server=debug"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							)
-							.add_directive(
-								"spacedrive=debug"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							)
-							.add_directive(
-								"rspc=debug"
-									.parse()
-									.expect("Error invalid tracing directive!"),
-							),
-					),
-			);
+			.with(fmt::Subscriber::new().with_writer(std::io::stdout.with_max_level(log_filter)));
 
 		tracing::collect::set_global_default(collector)
 			.map_err(|err| {
@@ -190,10 +213,45 @@ impl Node {
 		guard
 	}
 
+	/// The scoped tokens of the integrations with the server
+	pub fn api_tokens(&self) -> &ApiTokens {
+		&self.api_tokens
+	}
+
+	/// The metrics of the node, in the text format of Prometheus
+	pub async fn metrics(&self) -> String {
+		let (running_jobs, queued_jobs) = self.job_manager.counts().await;
+		let (p2p_bytes_sent, p2p_bytes_received) = self.p2p.sync_metrics.totals();
+
+		let mut db_latencies = vec![];
+		for library in self.library_manager.get_all_libraries().await {
+			let start = Instant::now();
+			match library.db.location().count(vec![]).exec().await {
+				Ok(_) => db_latencies.push((library.id.to_string(), start.elapsed())),
+				Err(e) => warn!(
+					"Failed to query library <id='{}'> for metrics: {e}",
+					library.id
+				),
+			}
+		}
+
+		self.metrics.render(&Snapshot {
+			running_jobs,
+			queued_jobs,
+			p2p_bytes_sent,
+			p2p_bytes_received,
+			db_latencies,
+		})
+	}
+
 	pub async fn shutdown(&self) {
 		info!("Spacedrive shutting down...");
+		self.mounts.shutdown().await;
 		self.job_manager.shutdown().await;
 		self.p2p.shutdown().await;
+		if let Err(e) = self.analytics.save().await {
+			error!("Failed to save the analytics: {e}");
+		}
 		info!("Spacedrive Core shutdown successful!");
 	}
 
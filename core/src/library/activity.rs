@@ -0,0 +1,140 @@
+//! A log of the significant changes made to a library, for libraries shared by several people or
+//! used for years, where nobody remembers who removed a location or when.
+//!
+//! Recording an event never fails the change it's about, a failure is only logged.
+
+use crate::{
+	invalidate_query,
+	prisma::{activity_log, node},
+};
+
+use chrono::{DateTime, Utc};
+use prisma_client_rust::{Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use tracing::warn;
+
+use super::Library;
+
+/// How many files a single deletion removes at least to be in the log
+pub const MASS_DELETION_THRESHOLD: usize = 100;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, Eq, PartialEq)]
+pub enum ActivityKind {
+	LocationAdded = 0,
+	LocationRemoved = 1,
+	SettingsChanged = 2,
+	DevicePaired = 3,
+	FilesDeleted = 4,
+}
+
+impl TryFrom<i32> for ActivityKind {
+	type Error = i32;
+
+	fn try_from(value: i32) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::LocationAdded,
+			1 => Self::LocationRemoved,
+			2 => Self::SettingsChanged,
+			3 => Self::DevicePaired,
+			4 => Self::FilesDeleted,
+			_ => return Err(value),
+		})
+	}
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct ActivityEntry {
+	pub id: activity_log::id::Type,
+	pub kind: ActivityKind,
+	pub details: Value,
+	/// The node the event happened on, if it's still in the library
+	pub node_name: Option<String>,
+	pub date_created: DateTime<Utc>,
+}
+
+/// A page of the log, newest first. `cursor` is where the next page starts, if there's one.
+#[derive(Serialize, Type, Debug)]
+pub struct ActivityPage {
+	pub items: Vec<ActivityEntry>,
+	pub cursor: Option<activity_log::id::Type>,
+}
+
+#[derive(Deserialize, Type, Debug)]
+pub struct ActivityPageArgs {
+	#[specta(optional)]
+	pub take: Option<i32>,
+	#[specta(optional)]
+	pub cursor: Option<activity_log::id::Type>,
+}
+
+/// Records that `kind` happened on this node, with `details` about it
+pub async fn record(library: &Library, kind: ActivityKind, details: Value) {
+	match library
+		.db
+		.activity_log()
+		.create(
+			kind as i32,
+			Utc::now().into(),
+			vec![
+				activity_log::details::set(serde_json::to_vec(&details).ok()),
+				activity_log::node::connect(node::id::equals(library.node_local_id)),
+			],
+		)
+		.exec()
+		.await
+	{
+		Ok(_) => invalidate_query!(library, "library.activity"),
+		Err(e) => warn!(
+			"Failed to record {kind:?} in the activity of library '{}': {e}",
+			library.id
+		),
+	}
+}
+
+pub async fn page(library: &Library, args: ActivityPageArgs) -> Result<ActivityPage, QueryError> {
+	let take = args.take.map_or(DEFAULT_PAGE_SIZE, i64::from);
+
+	let mut query = library
+		.db
+		.activity_log()
+		.find_many(vec![])
+		.order_by(activity_log::id::order(Direction::Desc))
+		.take(take + 1);
+	if let Some(cursor) = args.cursor {
+		query = query.cursor(activity_log::id::equals(cursor));
+	}
+
+	let mut entries = query
+		.include(activity_log::include!({ node: select { name } }))
+		.exec()
+		.await?;
+
+	let cursor = (entries.len() as i64 > take)
+		.then(|| entries.pop())
+		.flatten()
+		.map(|entry| entry.id);
+
+	Ok(ActivityPage {
+		items: entries
+			.into_iter()
+			.filter_map(|entry| {
+				Some(ActivityEntry {
+					id: entry.id,
+					kind: ActivityKind::try_from(entry.kind).ok()?,
+					details: entry
+						.details
+						.and_then(|details| serde_json::from_slice(&details).ok())
+						.unwrap_or_default(),
+					node_name: entry.node.map(|node| node.name),
+					date_created: entry.date_created.into(),
+				})
+			})
+			.collect(),
+		cursor,
+	})
+}
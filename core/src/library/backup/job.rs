@@ -0,0 +1,213 @@
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	util::error::FileIOError,
+};
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::fs;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{
+	copy_database, encrypt_snapshot, list_snapshots, BackupError, BackupSnapshot, BackupTarget,
+};
+
+/// Uploads an encrypted snapshot of the library to one of its backup targets, and deletes the
+/// snapshots that are older than the ones the target keeps
+pub struct BackupJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct BackupJobInit {
+	pub target_id: Uuid,
+}
+
+impl JobInitData for BackupJobInit {
+	type Job = BackupJob;
+
+	fn locations(&self) -> JobLocations {
+		// The snapshot is of the whole library
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupJobData {
+	snapshot: BackupSnapshot,
+	/// Where the snapshot is written before it's uploaded
+	path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum BackupJobStep {
+	Snapshot,
+	Upload,
+	Prune,
+}
+
+/// The target is loaded from the library config on every step, so its credentials aren't stored
+/// with the job
+fn target(library: &Library, target_id: Uuid) -> Result<&BackupTarget, BackupError> {
+	library
+		.config
+		.backup_targets
+		.iter()
+		.find(|target| target.id == target_id)
+		.ok_or(BackupError::TargetNotFound(target_id))
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for BackupJob {
+	type Init = BackupJobInit;
+	type Data = BackupJobData;
+	type Step = BackupJobStep;
+	type RunMetadata = ();
+
+	const NAME: &'static str = "library_backup";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let library = &ctx.library;
+
+		target(library, init.target_id)?;
+		if library.config.backup_key.is_none() {
+			return Err(BackupError::NoPassword.into());
+		}
+
+		let snapshot = BackupSnapshot::new(library.id);
+		let path = library
+			.config()
+			.data_directory()
+			.join("backups")
+			.join(&snapshot.name);
+
+		*data = Some(BackupJobData { snapshot, path });
+
+		Ok(vec![
+			BackupJobStep::Snapshot,
+			BackupJobStep::Upload,
+			BackupJobStep::Prune,
+		]
+		.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let library = &ctx.library;
+		let target = target(library, init.target_id)?;
+
+		match step {
+			BackupJobStep::Snapshot => {
+				ctx.progress_msg(job_message!("backup.snapshotting"));
+
+				let key = library
+					.config
+					.backup_key
+					.as_ref()
+					.ok_or(BackupError::NoPassword)?;
+
+				let libraries_dir = library.config().data_directory().join("libraries");
+				let config_path = libraries_dir.join(format!("{}.sdlibrary", library.id));
+				let mut config = serde_json::from_slice::<serde_json::Map<_, _>>(
+					&fs::read(&config_path)
+						.await
+						.map_err(|e| FileIOError::from((&config_path, e)))?,
+				)?;
+				// The identity and the secrets are in the OS keychain rather than in the config on
+				// most platforms
+				config.insert("identity".into(), json!(library.identity.to_bytes()));
+				config.insert("sync_key".into(), json!(library.config.sync_key));
+				config.insert("backup_key".into(), json!(library.config.backup_key));
+				config.insert(
+					"backup_targets".into(),
+					json!(library.config.backup_targets),
+				);
+				// The database is copied decrypted into the snapshot, which is encrypted as a whole
+				config.insert("encryption".into(), json!(null));
+				let config = serde_json::to_vec(&config)?;
+
+				if let Some(parent) = data.path.parent() {
+					fs::create_dir_all(parent)
+						.await
+						.map_err(|e| FileIOError::from((parent, e)))?;
+				}
+
+				let db_copy_path = data.path.with_extension("db");
+				copy_database(&library.db, &db_copy_path).await?;
+
+				let res = encrypt_snapshot(key, &config, &db_copy_path, &data.path).await;
+
+				fs::remove_file(&db_copy_path)
+					.await
+					.map_err(|e| FileIOError::from((&db_copy_path, e)))?;
+
+				res?;
+			}
+			BackupJobStep::Upload => {
+				ctx.progress_msg(job_message!("backup.uploading", target = target.name));
+
+				target
+					.kind
+					.store()?
+					.upload(&data.snapshot.name, &data.path)
+					.await?;
+
+				fs::remove_file(&data.path)
+					.await
+					.map_err(|e| FileIOError::from((&data.path, e)))?;
+			}
+			BackupJobStep::Prune => {
+				let store = target.kind.store()?;
+
+				let snapshots = list_snapshots(store.as_ref(), Some(library.id)).await?;
+
+				for snapshot in target.retention.prune(&snapshots) {
+					debug!("Deleting old backup snapshot '{}'", snapshot.name);
+
+					// The snapshot was uploaded, failing to clean up older ones doesn't fail the backup
+					if let Err(e) = store.delete(&snapshot.name).await {
+						warn!(
+							"Failed to delete old backup snapshot '{}': {e}",
+							snapshot.name
+						);
+					}
+				}
+			}
+		}
+
+		Ok(None.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		invalidate_query!(ctx.library, "backups.snapshots");
+
+		Ok(state
+			.data
+			.as_ref()
+			.map(|data| json!({ "snapshot": data.snapshot.name })))
+	}
+}
@@ -0,0 +1,412 @@
+//! Encrypted, versioned snapshots of a library that are uploaded to a backup target.
+//!
+//! A snapshot holds the library's config and a copy of its database, which includes the sync
+//! operations, so a library restored from it can catch up with the nodes it was paired with.
+//! Snapshots are encrypted before leaving the node, with a key that is unlocked by the backup
+//! password when restoring them.
+
+use crate::{
+	prisma::{backup_policy, PrismaClient},
+	util::error::FileIOError,
+};
+
+use std::{
+	fmt,
+	io::{Cursor, SeekFrom},
+	path::Path,
+	sync::OnceLock,
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Timelike, Utc};
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use regex::Regex;
+use sd_crypto::{
+	crypto::{Decryptor, Encryptor},
+	header::{file::FileHeader, keyslot::Keyslot},
+	primitives::{LATEST_FILE_HEADER, LATEST_KEYSLOT},
+	types::{Algorithm, HashingAlgorithm, Key, Params, Salt},
+	Protected,
+};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use uuid::Uuid;
+
+mod job;
+mod policy;
+mod schedule;
+mod target;
+
+pub use job::*;
+pub use policy::*;
+pub use schedule::*;
+pub use target::*;
+
+pub const BACKUP_EXTENSION: &str = "sdbackup";
+
+pub(super) const ALGORITHM: Algorithm = Algorithm::XChaCha20Poly1305;
+pub(super) const HASHING_ALGORITHM: HashingAlgorithm = HashingAlgorithm::Argon2id(Params::Standard);
+const SNAPSHOT_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+	#[error("no backup password was set for the library")]
+	NoPassword,
+	#[error("backup target '{0}' not found")]
+	TargetNotFound(Uuid),
+	#[error("backup policy <id='{0}'> not found")]
+	PolicyNotFound(backup_policy::id::Type),
+	#[error("a backup policy needs a tag, a location or a kind of object to back up")]
+	EmptyPolicy,
+	#[error("'{0}' isn't a library backup")]
+	InvalidSnapshotName(String),
+	#[error("the backup is corrupted or isn't a library backup")]
+	InvalidSnapshot,
+	#[error("wrong backup password")]
+	WrongPassword,
+	#[error("library '{0}' already exists, delete it before restoring it from a backup")]
+	LibraryExists(Uuid),
+	#[error("backup target responded with status {status} for '{name}'")]
+	Status { name: String, status: u16 },
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	Crypto(#[from] sd_crypto::Error),
+	#[error("S3 error: {0}")]
+	S3(#[from] s3::error::S3Error),
+	#[error("WebDAV error: {0}")]
+	WebDav(#[from] reqwest::Error),
+}
+
+impl From<BackupError> for rspc::Error {
+	fn from(e: BackupError) -> Self {
+		let code = match e {
+			BackupError::TargetNotFound(_) | BackupError::PolicyNotFound(_) => {
+				rspc::ErrorCode::NotFound
+			}
+			BackupError::NoPassword
+			| BackupError::EmptyPolicy
+			| BackupError::InvalidSnapshotName(_)
+			| BackupError::InvalidSnapshot
+			| BackupError::WrongPassword
+			| BackupError::LibraryExists(_) => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// The key snapshots are encrypted with, and a keyslot holding it encrypted with the backup
+/// password. It's kept with the library so backups can be made without asking for the password,
+/// which is only needed to restore them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BackupKey {
+	key: Vec<u8>,
+	keyslot: Vec<u8>,
+}
+
+impl fmt::Debug for BackupKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("BackupKey").finish_non_exhaustive()
+	}
+}
+
+impl BackupKey {
+	pub async fn new(password: Protected<String>) -> Result<Self, BackupError> {
+		let key = Key::generate();
+		let content_salt = Salt::generate();
+		let hashed_password = HASHING_ALGORITHM.hash(
+			Protected::new(password.expose().as_bytes().to_vec()),
+			content_salt,
+			None,
+		)?;
+
+		let keyslot = Keyslot::new(
+			LATEST_KEYSLOT,
+			ALGORITHM,
+			HASHING_ALGORITHM,
+			content_salt,
+			hashed_password,
+			key.clone(),
+		)
+		.await?;
+
+		Ok(Self {
+			key: key.expose().to_vec(),
+			keyslot: keyslot.to_bytes(),
+		})
+	}
+
+	fn key(&self) -> Result<Key, BackupError> {
+		Ok(Key::try_from(Protected::new(self.key.clone()))?)
+	}
+
+	fn keyslot(&self) -> Result<Keyslot, BackupError> {
+		Ok(Keyslot::from_reader(&mut Cursor::new(&self.keyslot))?)
+	}
+}
+
+/// A snapshot of a library on a backup target. The library and the date it was made are in its
+/// name, so snapshots can be listed without decrypting them.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq, Eq)]
+pub struct BackupSnapshot {
+	pub name: String,
+	pub library_id: Uuid,
+	pub date_created: DateTime<Utc>,
+}
+
+impl BackupSnapshot {
+	pub fn new(library_id: Uuid) -> Self {
+		let date_created = Utc::now();
+
+		Self {
+			name: format!(
+				"{library_id}_{}.{BACKUP_EXTENSION}",
+				date_created.format(SNAPSHOT_DATE_FORMAT)
+			),
+			library_id,
+			// The name doesn't have the sub-second precision
+			date_created: date_created.with_nanosecond(0).unwrap_or(date_created),
+		}
+	}
+
+	pub fn from_name(name: &str) -> Result<Self, BackupError> {
+		static SNAPSHOT_NAME: OnceLock<Regex> = OnceLock::new();
+
+		let captures = SNAPSHOT_NAME
+			.get_or_init(|| {
+				Regex::new(&format!(
+					r"^([0-9a-f-]{{36}})_(\d{{8}}T\d{{6}}Z)\.{BACKUP_EXTENSION}$"
+				))
+				.expect("snapshot name regex is valid")
+			})
+			.captures(name)
+			.ok_or_else(|| BackupError::InvalidSnapshotName(name.to_string()))?;
+
+		let library_id = captures[1]
+			.parse()
+			.map_err(|_| BackupError::InvalidSnapshotName(name.to_string()))?;
+		let date_created = NaiveDateTime::parse_from_str(&captures[2], SNAPSHOT_DATE_FORMAT)
+			.map_err(|_| BackupError::InvalidSnapshotName(name.to_string()))?;
+
+		Ok(Self {
+			name: name.to_string(),
+			library_id,
+			date_created: Utc.from_utc_datetime(&date_created),
+		})
+	}
+}
+
+/// Copies the library database into `path`. This is consistent even while the library is in use,
+/// unlike copying the file.
+pub(super) async fn copy_database(db: &PrismaClient, path: &Path) -> Result<(), BackupError> {
+	// `VACUUM INTO` fails if the file already exists
+	match fs::remove_file(path).await {
+		Ok(_) => {}
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => return Err(FileIOError::from((path, e)).into()),
+	}
+
+	db._execute_raw(raw!(
+		"VACUUM INTO {}",
+		PrismaValue::String(path.to_string_lossy().to_string())
+	))
+	.exec()
+	.await?;
+
+	Ok(())
+}
+
+/// Encrypts the library config and database into a snapshot at `output`
+async fn encrypt_snapshot(
+	key: &BackupKey,
+	config: &[u8],
+	db_path: &Path,
+	output: &Path,
+) -> Result<(), BackupError> {
+	let header = FileHeader::new(LATEST_FILE_HEADER, ALGORITHM, vec![key.keyslot()?])?;
+
+	let db = File::open(db_path)
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+	let mut writer = File::create(output)
+		.await
+		.map_err(|e| FileIOError::from((output, e)))?;
+
+	header.write(&mut writer).await?;
+
+	// The config goes first, prefixed by its length
+	let mut reader = Cursor::new(
+		(config.len() as u64)
+			.to_le_bytes()
+			.into_iter()
+			.chain(config.iter().copied())
+			.collect::<Vec<_>>(),
+	)
+	.chain(db);
+
+	Encryptor::new(key.key()?, header.nonce, header.algorithm)?
+		.encrypt_streams(&mut reader, &mut writer, &header.generate_aad())
+		.await?;
+
+	writer
+		.flush()
+		.await
+		.map_err(|e| FileIOError::from((output, e)).into())
+}
+
+/// Decrypts the snapshot at `input` with the backup password, writing the library database to
+/// `db_path` and returning the library config
+pub(super) async fn decrypt_snapshot(
+	password: Protected<String>,
+	input: &Path,
+	db_path: &Path,
+) -> Result<Vec<u8>, BackupError> {
+	let mut reader = File::open(input)
+		.await
+		.map_err(|e| FileIOError::from((input, e)))?;
+
+	let (header, aad) = FileHeader::from_reader(&mut reader)
+		.await
+		.map_err(|_| BackupError::InvalidSnapshot)?;
+	let key = header
+		.decrypt_master_key(Protected::new(password.expose().as_bytes().to_vec()))
+		.await
+		.map_err(|_| BackupError::WrongPassword)?;
+
+	// The config is split from the database once it's decrypted
+	let decrypted_path = db_path.with_extension("decrypted");
+	let res = async {
+		let mut decrypted = File::create(&decrypted_path)
+			.await
+			.map_err(|e| FileIOError::from((&decrypted_path, e)))?;
+
+		Decryptor::new(key, header.nonce, header.algorithm)?
+			.decrypt_streams(&mut reader, &mut decrypted, &aad)
+			.await
+			.map_err(|_| BackupError::InvalidSnapshot)?;
+
+		split_snapshot(&decrypted_path, db_path).await
+	}
+	.await;
+
+	fs::remove_file(&decrypted_path).await.ok();
+
+	res
+}
+
+async fn split_snapshot(decrypted_path: &Path, db_path: &Path) -> Result<Vec<u8>, BackupError> {
+	let mut decrypted = File::open(decrypted_path)
+		.await
+		.map_err(|e| FileIOError::from((decrypted_path, e)))?;
+
+	let len = decrypted
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((decrypted_path, e)))?
+		.len();
+
+	let config_len = decrypted
+		.read_u64_le()
+		.await
+		.map_err(|_| BackupError::InvalidSnapshot)?;
+	if config_len > len - 8 {
+		return Err(BackupError::InvalidSnapshot);
+	}
+
+	let mut config = vec![0; config_len as usize];
+	decrypted
+		.read_exact(&mut config)
+		.await
+		.map_err(|_| BackupError::InvalidSnapshot)?;
+
+	decrypted
+		.seek(SeekFrom::Start(8 + config_len))
+		.await
+		.map_err(|e| FileIOError::from((decrypted_path, e)))?;
+
+	let mut db = File::create(db_path)
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+	io::copy(&mut decrypted, &mut db)
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+	db.flush()
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+
+	Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn snapshot_names() {
+		let library_id = Uuid::new_v4();
+		let snapshot = BackupSnapshot::new(library_id);
+
+		assert_eq!(BackupSnapshot::from_name(&snapshot.name).unwrap(), snapshot);
+
+		for name in [
+			"notes.txt",
+			&format!("{library_id}.{BACKUP_EXTENSION}"),
+			&format!("{library_id}_20230705T1200Z.{BACKUP_EXTENSION}"),
+			&format!("{library_id}_20231305T120000Z.{BACKUP_EXTENSION}"),
+		] {
+			assert!(BackupSnapshot::from_name(name).is_err(), "{name}");
+		}
+	}
+
+	#[tokio::test]
+	async fn encrypted_snapshots() {
+		let dir = tempfile::tempdir().unwrap();
+		let db_path = dir.path().join("library.db");
+		let snapshot_path = dir.path().join("snapshot.sdbackup");
+		let restored_path = dir.path().join("restored.db");
+
+		let db = (0..3 * 1024 * 1024)
+			.map(|i| (i % 251) as u8)
+			.collect::<Vec<_>>();
+		fs::write(&db_path, &db).await.unwrap();
+
+		let key = BackupKey::new(Protected::new("password".to_string()))
+			.await
+			.unwrap();
+		encrypt_snapshot(&key, b"{\"name\":\"Library\"}", &db_path, &snapshot_path)
+			.await
+			.unwrap();
+
+		let encrypted = fs::read(&snapshot_path).await.unwrap();
+		assert!(!encrypted.windows(7).any(|w| w == b"Library"));
+
+		assert!(matches!(
+			decrypt_snapshot(
+				Protected::new("wrong".to_string()),
+				&snapshot_path,
+				&restored_path
+			)
+			.await,
+			Err(BackupError::WrongPassword)
+		));
+
+		let config = decrypt_snapshot(
+			Protected::new("password".to_string()),
+			&snapshot_path,
+			&restored_path,
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(config, b"{\"name\":\"Library\"}");
+		assert_eq!(fs::read(&restored_path).await.unwrap(), db);
+	}
+}
@@ -0,0 +1,398 @@
+//! Backups of the objects matching the rules of a backup policy, like everything tagged
+//! "Important" or all the photos of a location, to a directory of another location.
+//!
+//! Each object is copied once, from one of its files on this node, under a directory named after
+//! the location the file is in. The copies are tracked per object, so the objects of a policy that
+//! aren't backed up yet are known, and only those are copied when the policy runs again.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunErrors, JobRunMetadata,
+		JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{
+		ensure_sub_path_is_directory, file_path_for_policy_backup, push_location_relative_path,
+		IsolatedFilePathData,
+	},
+	object::fs::{error::FileSystemJobsError, get_location_path_from_location_id},
+	prisma::{backup_policy, file_path, location, object, object_backup, tag_on_object},
+	util::{
+		db::{chain_optional_iter, maybe_missing},
+		error::FileIOError,
+	},
+};
+
+use std::{
+	collections::HashSet,
+	path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use filetime::{set_file_mtime, FileTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{info, trace, warn};
+
+use super::BackupError;
+
+/// Copies the objects of a backup policy that aren't backed up yet to its target directory
+pub struct PolicyBackupJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct PolicyBackupJobInit {
+	pub policy_id: backup_policy::id::Type,
+}
+
+impl JobInitData for PolicyBackupJobInit {
+	type Job = PolicyBackupJob;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolicyBackupJobData {
+	target_directory: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolicyBackupStep {
+	object_id: object::id::Type,
+	source: PathBuf,
+	/// Where the copy goes, relative to the target directory
+	relative_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PolicyBackupJobRunMetadata {
+	objects_backed_up: u64,
+	bytes_copied: u64,
+}
+
+impl JobRunMetadata for PolicyBackupJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.objects_backed_up += new_data.objects_backed_up;
+		self.bytes_copied += new_data.bytes_copied;
+	}
+}
+
+/// The objects a policy backs up
+pub fn policy_object_filters(policy: &backup_policy::Data) -> Vec<object::WhereParam> {
+	chain_optional_iter(
+		[object::date_deleted::equals(None)],
+		[
+			policy
+				.tag_id
+				.map(|tag_id| object::tags::some(vec![tag_on_object::tag_id::equals(tag_id)])),
+			policy.location_id.map(|location_id| {
+				object::file_paths::some(vec![file_path::location_id::equals(Some(location_id))])
+			}),
+			policy.kind.map(|kind| object::kind::equals(Some(kind))),
+		],
+	)
+}
+
+/// The objects a policy backs up that it hasn't backed up yet
+pub fn unprotected_object_filters(policy: &backup_policy::Data) -> Vec<object::WhereParam> {
+	let mut filters = policy_object_filters(policy);
+	filters.push(object::backups::none(vec![
+		object_backup::policy_id::equals(policy.id),
+	]));
+
+	filters
+}
+
+/// Whether a policy is due to run again, `interval_hours` after it last did
+pub(super) fn is_policy_due(
+	date_last_run: Option<DateTime<FixedOffset>>,
+	interval_hours: i32,
+) -> bool {
+	date_last_run.map_or(true, |date_last_run| {
+		Utc::now() - date_last_run.with_timezone(&Utc) >= Duration::hours(interval_hours.into())
+	})
+}
+
+/// Where a file is copied to in the target directory, under a directory named after its location
+fn backup_relative_path(
+	location_id: location::id::Type,
+	location_name: Option<&str>,
+	iso_file_path: &IsolatedFilePathData<'_>,
+) -> PathBuf {
+	let location_directory = location_name
+		.filter(|name| !name.is_empty())
+		.map(|name| name.replace(['/', '\\'], "_"))
+		.unwrap_or_else(|| location_id.to_string());
+
+	Path::new(&location_directory).join(iso_file_path)
+}
+
+pub(super) async fn find_policy(
+	library: &Library,
+	policy_id: backup_policy::id::Type,
+) -> Result<backup_policy::Data, BackupError> {
+	library
+		.db
+		.backup_policy()
+		.find_unique(backup_policy::id::equals(policy_id))
+		.exec()
+		.await?
+		.ok_or(BackupError::PolicyNotFound(policy_id))
+}
+
+/// Copies a file through a file aside, so an interrupted copy never looks like a whole backup
+async fn copy_file(source: &Path, target: &Path) -> Result<u64, FileIOError> {
+	if let Some(parent) = target.parent() {
+		fs::create_dir_all(parent)
+			.await
+			.map_err(|e| FileIOError::from((parent, e)))?;
+	}
+
+	let mut part_path = target.to_path_buf().into_os_string();
+	part_path.push(".part");
+	let part_path = PathBuf::from(part_path);
+
+	let size = fs::copy(source, &part_path)
+		.await
+		.map_err(|e| FileIOError::from((&part_path, e)))?;
+
+	let source_metadata = fs::metadata(source)
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+	set_file_mtime(
+		&part_path,
+		FileTime::from_last_modification_time(&source_metadata),
+	)
+	.map_err(|e| FileIOError::from((&part_path, e)))?;
+
+	fs::rename(&part_path, target)
+		.await
+		.map_err(|e| FileIOError::from((target, e)))?;
+
+	Ok(size)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for PolicyBackupJob {
+	type Init = PolicyBackupJobInit;
+	type Data = PolicyBackupJobData;
+	type Step = PolicyBackupStep;
+	type RunMetadata = PolicyBackupJobRunMetadata;
+
+	const NAME: &'static str = "policy_backup";
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library {
+			db, node_local_id, ..
+		} = &ctx.library;
+
+		let policy = find_policy(&ctx.library, init.policy_id).await?;
+
+		let target_location_path =
+			get_location_path_from_location_id(db, policy.target_location_id).await?;
+		ensure_sub_path_is_directory(&target_location_path, &policy.target_path)
+			.await
+			.map_err(FileSystemJobsError::from)?;
+		let target_directory =
+			push_location_relative_path(target_location_path, &policy.target_path);
+
+		let mut objects = HashSet::new();
+		let mut steps = vec![];
+
+		for file_path in db
+			.file_path()
+			.find_many(chain_optional_iter(
+				[
+					file_path::is_dir::equals(Some(false)),
+					file_path::location::is(vec![location::node_id::equals(Some(*node_local_id))]),
+					file_path::object::is(unprotected_object_filters(&policy)),
+				],
+				[policy
+					.location_id
+					.map(|location_id| file_path::location_id::equals(Some(location_id)))],
+			))
+			.select(file_path_for_policy_backup::select())
+			.exec()
+			.await?
+		{
+			let object_id = maybe_missing(file_path.object_id, "file_path.object_id")?;
+			// Objects with many files are backed up from only one of them
+			if objects.contains(&object_id) {
+				continue;
+			}
+
+			let location = maybe_missing(&file_path.location, "file_path.location")?;
+			let location_path = maybe_missing(&location.path, "location.path")?;
+			let iso_file_path = IsolatedFilePathData::try_from((location.id, &file_path))?;
+
+			let source = Path::new(location_path).join(&iso_file_path);
+			// The backups themselves, when the target directory is in a location the policy covers
+			if source.starts_with(&target_directory) {
+				continue;
+			}
+
+			objects.insert(object_id);
+			steps.push(PolicyBackupStep {
+				object_id,
+				relative_path: backup_relative_path(
+					location.id,
+					location.name.as_deref(),
+					&iso_file_path,
+				),
+				source,
+			});
+		}
+
+		*data = Some(PolicyBackupJobData { target_directory });
+
+		Ok((PolicyBackupJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let PolicyBackupStep {
+			object_id,
+			source,
+			relative_path,
+		} = step;
+		let target = data.target_directory.join(relative_path);
+
+		let source_size = match fs::metadata(source).await {
+			Ok(metadata) => metadata.len(),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				return Ok(JobRunErrors(vec![format!(
+					"{} wasn't backed up, it no longer exists",
+					source.display()
+				)])
+				.into())
+			}
+			Err(e) => return Err(FileIOError::from((source, e)).into()),
+		};
+
+		let bytes_copied = match fs::metadata(&target).await {
+			// A copy from an earlier backup that wasn't recorded, like when the job was interrupted
+			Ok(metadata) if metadata.len() == source_size => 0,
+			Ok(_) => {
+				warn!("Skipping {} as it would be overwritten", target.display());
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					target.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				ctx.progress_msg(job_message!("backup.backingUp", path = source.display()));
+
+				match copy_file(source, &target).await {
+					Ok(size) => size,
+					Err(e) => {
+						warn!("Failed to back up {}: {e:#?}", source.display());
+
+						return Ok(JobRunErrors(vec![e.to_string()]).into());
+					}
+				}
+			}
+			Err(e) => return Err(FileIOError::from((&target, e)).into()),
+		};
+
+		trace!("Backed up {} to {}", source.display(), target.display());
+
+		ctx.library
+			.db
+			.object_backup()
+			.create_many(vec![object_backup::create_unchecked(
+				init.policy_id,
+				*object_id,
+				relative_path.to_string_lossy().into_owned(),
+				Utc::now().into(),
+				vec![],
+			)])
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		Ok(PolicyBackupJobRunMetadata {
+			objects_backed_up: 1,
+			bytes_copied,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Backed up {} objects of policy {}, {} bytes copied",
+			metadata.objects_backed_up, state.init.policy_id, metadata.bytes_copied
+		);
+
+		ctx.library
+			.db
+			.backup_policy()
+			.update_many(
+				vec![backup_policy::id::equals(state.init.policy_id)],
+				vec![backup_policy::date_last_run::set(Some(Utc::now().into()))],
+			)
+			.exec()
+			.await?;
+
+		invalidate_query!(ctx.library, "backups.policies");
+		invalidate_query!(ctx.library, "backups.unprotectedObjects");
+
+		Ok(Some(json!({
+			"init": state.init,
+			"objects_backed_up": metadata.objects_backed_up,
+			"bytes_copied": metadata.bytes_copied,
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn backups_go_under_their_location() {
+		let iso_file_path = IsolatedFilePathData::from_relative_str(1, "photos/cat.jpg");
+
+		assert_eq!(
+			backup_relative_path(1, Some("Camera Roll"), &iso_file_path),
+			Path::new("Camera Roll/photos/cat.jpg")
+		);
+		assert_eq!(
+			backup_relative_path(1, Some("a/b"), &iso_file_path),
+			Path::new("a_b/photos/cat.jpg")
+		);
+		assert_eq!(
+			backup_relative_path(7, None, &iso_file_path),
+			Path::new("7/photos/cat.jpg")
+		);
+	}
+
+	#[test]
+	fn policies_run_on_their_interval() {
+		assert!(is_policy_due(None, 24));
+		assert!(!is_policy_due(Some(Utc::now().into()), 24));
+		assert!(is_policy_due(
+			Some((Utc::now() - Duration::hours(25)).into()),
+			24
+		));
+	}
+}
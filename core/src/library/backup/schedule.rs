@@ -0,0 +1,266 @@
+use crate::library::{Library, LibraryManager};
+
+use std::{
+	collections::HashSet,
+	sync::{Arc, Weak},
+	time::Duration,
+};
+
+use chrono::{Datelike, Duration as ChronoDuration, Local, Utc};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use super::{is_policy_due, list_snapshots, BackupJobInit, BackupSnapshot, PolicyBackupJobInit};
+
+/// How often the automatic backups are checked, they're only made once a day
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How old the last snapshot on a target can be, in hours, before an automatic backup is made
+const AUTOMATIC_BACKUP_HOURS: i64 = 24;
+
+/// Which snapshots of a library are kept on a target. The newest snapshot of each of the last
+/// `daily` days and of each of the last `weekly` weeks with snapshots are kept, the rest are deleted.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupRetention {
+	pub daily: u32,
+	pub weekly: u32,
+}
+
+impl Default for BackupRetention {
+	fn default() -> Self {
+		Self {
+			daily: 7,
+			weekly: 4,
+		}
+	}
+}
+
+impl BackupRetention {
+	/// The snapshots that aren't kept, from `snapshots` sorted from newest to oldest. The newest
+	/// snapshot is always kept.
+	pub fn prune<'a>(&self, snapshots: &'a [BackupSnapshot]) -> Vec<&'a BackupSnapshot> {
+		let mut days = HashSet::new();
+		let mut weeks = HashSet::new();
+
+		snapshots
+			.iter()
+			.enumerate()
+			.filter(|(i, snapshot)| {
+				let day = snapshot.date_created.date_naive();
+				let week = day.iso_week();
+
+				// A snapshot can be kept both as the newest of its day and as the newest of its week
+				let daily = days.len() < self.daily as usize && days.insert(day);
+				let weekly =
+					weeks.len() < self.weekly as usize && weeks.insert((week.year(), week.week()));
+
+				!(*i == 0 || daily || weekly)
+			})
+			.map(|(_, snapshot)| snapshot)
+			.collect()
+	}
+}
+
+/// Whether an automatic backup is due, given the newest snapshot on the target
+fn is_backup_due(newest: Option<&BackupSnapshot>) -> bool {
+	newest.map_or(true, |snapshot| {
+		Utc::now() - snapshot.date_created >= ChronoDuration::hours(AUTOMATIC_BACKUP_HOURS)
+	})
+}
+
+/// Backs up the libraries to their targets with automatic backups, once a day, and runs their
+/// backup policies on their intervals, outside their quiet hours
+pub(crate) fn spawn_scheduler(library_manager: Weak<LibraryManager>) {
+	tokio::spawn(async move {
+		let mut tick = interval(CHECK_INTERVAL);
+		tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			tick.tick().await;
+
+			let Some(library_manager) = library_manager.upgrade() else {
+				break;
+			};
+
+			run_due_backups(&library_manager).await;
+		}
+	});
+}
+
+async fn run_due_backups(library_manager: &Arc<LibraryManager>) {
+	for library in library_manager.get_all_libraries().await {
+		if library.config.settings.is_quiet(Local::now()) {
+			continue;
+		}
+
+		if library.config.backup_key.is_some() {
+			run_due_snapshots(&library).await;
+		}
+
+		run_due_policies(&library).await;
+	}
+}
+
+async fn run_due_snapshots(library: &Library) {
+	for target in library
+		.config
+		.backup_targets
+		.iter()
+		.filter(|target| target.automatic)
+	{
+		let snapshots = match target.kind.store() {
+			Ok(store) => list_snapshots(store.as_ref(), Some(library.id)).await,
+			Err(e) => Err(e),
+		};
+
+		match snapshots {
+			Ok(snapshots) if is_backup_due(snapshots.first()) => {
+				debug!(
+					"Starting automatic backup of library '{}' to '{}'",
+					library.id, target.name
+				);
+
+				if let Err(e) = library
+					.spawn_job(BackupJobInit {
+						target_id: target.id,
+					})
+					.await
+				{
+					warn!(
+						"Failed to start the automatic backup to '{}': {e}",
+						target.name
+					);
+				}
+			}
+			Ok(_) => {}
+			Err(e) => warn!("Failed to list the snapshots on '{}': {e}", target.name),
+		}
+	}
+}
+
+async fn run_due_policies(library: &Library) {
+	let policies = match library.db.backup_policy().find_many(vec![]).exec().await {
+		Ok(policies) => policies,
+		Err(e) => {
+			warn!(
+				"Failed to read the backup policies of library '{}': {e}",
+				library.id
+			);
+			return;
+		}
+	};
+
+	for policy in policies
+		.into_iter()
+		.filter(|policy| is_policy_due(policy.date_last_run, policy.interval_hours))
+	{
+		debug!(
+			"Starting backup policy '{}' of library '{}'",
+			policy.name, library.id
+		);
+
+		if let Err(e) = library
+			.spawn_job(PolicyBackupJobInit {
+				policy_id: policy.id,
+			})
+			.await
+		{
+			warn!("Failed to start backup policy '{}': {e}", policy.name);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::{NaiveDate, TimeZone};
+	use uuid::Uuid;
+
+	fn snapshots(dates: &[(u32, u32, u32)]) -> Vec<BackupSnapshot> {
+		let library_id = Uuid::new_v4();
+
+		dates
+			.iter()
+			.map(|&(month, day, hour)| {
+				let date_created = Utc.from_utc_datetime(
+					&NaiveDate::from_ymd_opt(2023, month, day)
+						.unwrap()
+						.and_hms_opt(hour, 0, 0)
+						.unwrap(),
+				);
+
+				BackupSnapshot {
+					name: String::new(),
+					library_id,
+					date_created,
+				}
+			})
+			.collect()
+	}
+
+	fn pruned(retention: BackupRetention, snapshots: &[BackupSnapshot]) -> Vec<(u32, u32)> {
+		retention
+			.prune(snapshots)
+			.into_iter()
+			.map(|snapshot| {
+				let date = snapshot.date_created.date_naive();
+				(date.month(), date.day())
+			})
+			.collect()
+	}
+
+	#[test]
+	fn retention_keeps_daily_and_weekly_snapshots() {
+		// Two snapshots on July 10th, then one a day back to June 1st
+		let mut dates = vec![(7, 10, 18), (7, 10, 6)];
+		dates.extend((1..10).rev().map(|day| (7, day, 6)));
+		dates.extend((1..=30).rev().map(|day| (6, day, 6)));
+		let snapshots = snapshots(&dates);
+
+		let pruned = pruned(BackupRetention::default(), &snapshots);
+
+		// The older snapshot of July 10th goes, the newest one of the day is kept
+		assert!(pruned.contains(&(7, 10)));
+		// The last 7 days are kept
+		for day in 4..=9 {
+			assert!(!pruned.contains(&(7, day)), "July {day}");
+		}
+		// Along with the newest snapshot of each of the last 4 weeks, which start on Mondays. The
+		// weeks of July 10th and 9th are already covered by the daily ones.
+		assert!(!pruned.contains(&(7, 2)));
+		assert!(!pruned.contains(&(6, 25)));
+		assert!(pruned.contains(&(7, 3)));
+		assert!(pruned.contains(&(6, 18)));
+		assert!(pruned.contains(&(6, 1)));
+
+		assert_eq!(pruned.len(), snapshots.len() - 7 - 2);
+	}
+
+	#[test]
+	fn retention_always_keeps_the_newest_snapshot() {
+		let snapshots = snapshots(&[(7, 10, 6), (7, 9, 6)]);
+
+		assert_eq!(
+			pruned(
+				BackupRetention {
+					daily: 0,
+					weekly: 0
+				},
+				&snapshots
+			),
+			vec![(7, 9)]
+		);
+	}
+
+	#[test]
+	fn backups_are_due_once_a_day() {
+		let mut snapshot = BackupSnapshot::new(Uuid::new_v4());
+		assert!(!is_backup_due(Some(&snapshot)));
+		assert!(is_backup_due(None));
+
+		snapshot.date_created = Utc::now() - ChronoDuration::hours(25);
+		assert!(is_backup_due(Some(&snapshot)));
+	}
+}
@@ -0,0 +1,459 @@
+use crate::util::error::FileIOError;
+
+use std::{
+	path::{Path, PathBuf},
+	sync::OnceLock,
+};
+
+use futures::StreamExt;
+use regex::Regex;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use s3::{creds::Credentials, Bucket, Region};
+use sd_crypto::keys::secrets::{SecretKind, Secrets};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io::AsyncWriteExt,
+};
+use uuid::Uuid;
+
+use super::{BackupError, BackupRetention, BackupSnapshot};
+
+/// Where the snapshots of a library are uploaded to
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BackupTargetKind {
+	/// A directory on this node, like a mounted network drive
+	Local { path: String },
+	/// An S3 compatible object storage
+	#[serde(rename_all = "camelCase")]
+	S3 {
+		endpoint: String,
+		region: String,
+		bucket: String,
+		/// The snapshots are stored under this prefix of the bucket
+		prefix: Option<String>,
+		access_key_id: String,
+		secret_access_key: String,
+	},
+	/// A directory on a WebDAV server
+	WebDav {
+		url: String,
+		username: Option<String>,
+		password: Option<String>,
+	},
+}
+
+/// A backup target configured for a library, stored in its config with the credentials moved to
+/// the OS keychain
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupTarget {
+	pub id: Uuid,
+	pub name: String,
+	pub kind: BackupTargetKind,
+	/// Which snapshots of the library are kept on the target, the others are deleted
+	pub retention: BackupRetention,
+	/// Whether the library is backed up to the target once a day
+	pub automatic: bool,
+}
+
+/// Removes the credentials of a target from the keychain, where the library config keeps them
+pub fn forget_credentials(secrets: &Secrets, target_id: Uuid) {
+	for kind in [
+		SecretKind::BackupTargetAccessKeyId,
+		SecretKind::BackupTargetSecretAccessKey,
+		SecretKind::BackupTargetPassword,
+	] {
+		secrets.delete(target_id, kind).ok();
+	}
+}
+
+/// A backup target without its credentials
+#[derive(Serialize, Type, Debug)]
+pub struct SanitisedBackupTarget {
+	pub id: Uuid,
+	pub name: String,
+	/// `local`, `s3` or `webDav`
+	pub kind: String,
+	/// The path, bucket or URL the snapshots are stored in
+	pub location: String,
+	pub retention: BackupRetention,
+	pub automatic: bool,
+}
+
+impl From<&BackupTarget> for SanitisedBackupTarget {
+	fn from(target: &BackupTarget) -> Self {
+		let (kind, location) = match &target.kind {
+			BackupTargetKind::Local { path } => ("local", path.clone()),
+			BackupTargetKind::S3 {
+				endpoint,
+				bucket,
+				prefix,
+				..
+			} => (
+				"s3",
+				format!(
+					"{endpoint}/{bucket}/{}",
+					prefix.as_deref().unwrap_or_default()
+				),
+			),
+			BackupTargetKind::WebDav { url, .. } => ("webDav", url.clone()),
+		};
+
+		Self {
+			id: target.id,
+			name: target.name.clone(),
+			kind: kind.to_string(),
+			location,
+			retention: target.retention,
+			automatic: target.automatic,
+		}
+	}
+}
+
+impl BackupTargetKind {
+	pub fn store(&self) -> Result<Box<dyn BackupStore>, BackupError> {
+		Ok(match self {
+			Self::Local { path } => Box::new(LocalStore {
+				path: PathBuf::from(path),
+			}),
+			Self::S3 {
+				endpoint,
+				region,
+				bucket,
+				prefix,
+				access_key_id,
+				secret_access_key,
+			} => Box::new(S3Store {
+				bucket: Bucket::new(
+					bucket,
+					Region::Custom {
+						region: region.clone(),
+						endpoint: endpoint.clone(),
+					},
+					Credentials::new(
+						Some(access_key_id),
+						Some(secret_access_key),
+						None,
+						None,
+						None,
+					)
+					.map_err(s3::error::S3Error::from)?,
+				)?
+				.with_path_style(),
+				prefix: prefix
+					.as_deref()
+					.map(|prefix| prefix.trim_matches('/'))
+					.filter(|prefix| !prefix.is_empty())
+					.map(|prefix| format!("{prefix}/"))
+					.unwrap_or_default(),
+			}),
+			Self::WebDav {
+				url,
+				username,
+				password,
+			} => Box::new(WebDavStore {
+				client: Client::new(),
+				url: url.trim_end_matches('/').to_string(),
+				username: username.clone(),
+				password: password.clone(),
+			}),
+		})
+	}
+}
+
+/// Stores files by name in a backup target
+#[async_trait::async_trait]
+pub trait BackupStore: Send + Sync {
+	async fn upload(&self, name: &str, path: &Path) -> Result<(), BackupError>;
+
+	async fn download(&self, name: &str, path: &Path) -> Result<(), BackupError>;
+
+	/// The names of the files in the target
+	async fn list(&self) -> Result<Vec<String>, BackupError>;
+
+	async fn delete(&self, name: &str) -> Result<(), BackupError>;
+}
+
+/// The snapshots in the target, of a library or all of them, the newest first
+pub async fn list_snapshots(
+	store: &dyn BackupStore,
+	library_id: Option<Uuid>,
+) -> Result<Vec<BackupSnapshot>, BackupError> {
+	let mut snapshots = store
+		.list()
+		.await?
+		.iter()
+		.filter_map(|name| BackupSnapshot::from_name(name).ok())
+		.filter(|snapshot| library_id.map_or(true, |id| snapshot.library_id == id))
+		.collect::<Vec<_>>();
+
+	snapshots.sort_by(|a, b| b.date_created.cmp(&a.date_created));
+
+	Ok(snapshots)
+}
+
+fn check_status(name: &str, status: u16) -> Result<(), BackupError> {
+	if (200..300).contains(&status) {
+		Ok(())
+	} else {
+		Err(BackupError::Status {
+			name: name.to_string(),
+			status,
+		})
+	}
+}
+
+struct LocalStore {
+	path: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl BackupStore for LocalStore {
+	async fn upload(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		fs::create_dir_all(&self.path)
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)))?;
+
+		// Copied under another name first so a partial copy is never taken for a snapshot
+		let partial_path = self.path.join(format!("{name}.partial"));
+		fs::copy(path, &partial_path)
+			.await
+			.map_err(|e| FileIOError::from((&partial_path, e)))?;
+
+		let target_path = self.path.join(name);
+		fs::rename(&partial_path, &target_path)
+			.await
+			.map_err(|e| FileIOError::from((target_path, e)).into())
+	}
+
+	async fn download(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		let source_path = self.path.join(name);
+		fs::copy(&source_path, path)
+			.await
+			.map(|_| ())
+			.map_err(|e| FileIOError::from((source_path, e)).into())
+	}
+
+	async fn list(&self) -> Result<Vec<String>, BackupError> {
+		let mut read_dir = fs::read_dir(&self.path)
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)))?;
+
+		let mut names = vec![];
+		while let Some(entry) = read_dir
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)))?
+		{
+			if let Some(name) = entry.file_name().to_str() {
+				names.push(name.to_string());
+			}
+		}
+
+		Ok(names)
+	}
+
+	async fn delete(&self, name: &str) -> Result<(), BackupError> {
+		let path = self.path.join(name);
+		fs::remove_file(&path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)).into())
+	}
+}
+
+struct S3Store {
+	bucket: Bucket,
+	/// Empty or ending with a `/`
+	prefix: String,
+}
+
+#[async_trait::async_trait]
+impl BackupStore for S3Store {
+	async fn upload(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		let mut file = File::open(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let status = self
+			.bucket
+			.put_object_stream(&mut file, format!("{}{name}", self.prefix))
+			.await?;
+
+		check_status(name, status)
+	}
+
+	async fn download(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		let mut file = File::create(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let status = self
+			.bucket
+			.get_object_to_writer(format!("{}{name}", self.prefix), &mut file)
+			.await?;
+
+		check_status(name, status)
+	}
+
+	async fn list(&self) -> Result<Vec<String>, BackupError> {
+		Ok(self
+			.bucket
+			.list(self.prefix.clone(), Some("/".to_string()))
+			.await?
+			.into_iter()
+			.flat_map(|page| page.contents)
+			.filter_map(|object| object.key.strip_prefix(&self.prefix).map(str::to_string))
+			.collect())
+	}
+
+	async fn delete(&self, name: &str) -> Result<(), BackupError> {
+		let response = self
+			.bucket
+			.delete_object(format!("{}{name}", self.prefix))
+			.await?;
+
+		check_status(name, response.status_code())
+	}
+}
+
+struct WebDavStore {
+	client: Client,
+	/// Without a trailing `/`
+	url: String,
+	username: Option<String>,
+	password: Option<String>,
+}
+
+impl WebDavStore {
+	fn request(&self, method: Method, name: &str) -> RequestBuilder {
+		let request = self.client.request(method, format!("{}/{name}", self.url));
+
+		match &self.username {
+			Some(username) => request.basic_auth(username, self.password.as_ref()),
+			None => request,
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl BackupStore for WebDavStore {
+	async fn upload(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		let file = File::open(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let response = self.request(Method::PUT, name).body(file).send().await?;
+
+		check_status(name, response.status().as_u16())
+	}
+
+	async fn download(&self, name: &str, path: &Path) -> Result<(), BackupError> {
+		let response = self.request(Method::GET, name).send().await?;
+		check_status(name, response.status().as_u16())?;
+
+		let mut file = File::create(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let mut stream = response.bytes_stream();
+		while let Some(chunk) = stream.next().await {
+			file.write_all(&chunk?)
+				.await
+				.map_err(|e| FileIOError::from((path, e)))?;
+		}
+
+		file.flush()
+			.await
+			.map_err(|e| FileIOError::from((path, e)).into())
+	}
+
+	async fn list(&self) -> Result<Vec<String>, BackupError> {
+		static HREF: OnceLock<Regex> = OnceLock::new();
+
+		let response = self
+			.request(
+				Method::from_bytes(b"PROPFIND").expect("PROPFIND is a valid method"),
+				"",
+			)
+			.header("Depth", "1")
+			.send()
+			.await?;
+
+		// Nothing was backed up to the directory yet
+		if response.status() == StatusCode::NOT_FOUND {
+			return Ok(vec![]);
+		}
+		check_status("", response.status().as_u16())?;
+
+		let body = response.text().await?;
+
+		Ok(HREF
+			.get_or_init(|| {
+				Regex::new(r"(?i)<(?:[a-z]+:)?href>([^<]+)</(?:[a-z]+:)?href>")
+					.expect("href regex is valid")
+			})
+			.captures_iter(&body)
+			// The directory itself is listed too
+			.filter(|captures| !captures[1].ends_with('/'))
+			.filter_map(|captures| captures[1].rsplit('/').next().map(str::to_string))
+			.collect())
+	}
+
+	async fn delete(&self, name: &str) -> Result<(), BackupError> {
+		let response = self.request(Method::DELETE, name).send().await?;
+
+		check_status(name, response.status().as_u16())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::library::backup::BACKUP_EXTENSION;
+
+	#[tokio::test]
+	async fn local_snapshots() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = BackupTargetKind::Local {
+			path: dir.path().join("backups").to_string_lossy().to_string(),
+		}
+		.store()
+		.unwrap();
+
+		let file = dir.path().join("snapshot");
+		fs::write(&file, b"snapshot").await.unwrap();
+
+		let (library, other_library) = (Uuid::new_v4(), Uuid::new_v4());
+		let older =
+			BackupSnapshot::from_name(&format!("{library}_20230701T120000Z.{BACKUP_EXTENSION}"))
+				.unwrap();
+		let newer =
+			BackupSnapshot::from_name(&format!("{library}_20230702T120000Z.{BACKUP_EXTENSION}"))
+				.unwrap();
+		let other = BackupSnapshot::new(other_library);
+
+		for snapshot in [&older, &newer, &other] {
+			store.upload(&snapshot.name, &file).await.unwrap();
+		}
+		store.upload("notes.txt", &file).await.unwrap();
+
+		assert_eq!(
+			list_snapshots(store.as_ref(), Some(library)).await.unwrap(),
+			[newer.clone(), older.clone()]
+		);
+		assert_eq!(list_snapshots(store.as_ref(), None).await.unwrap().len(), 3);
+
+		let downloaded = dir.path().join("downloaded");
+		store.download(&newer.name, &downloaded).await.unwrap();
+		assert_eq!(fs::read(&downloaded).await.unwrap(), b"snapshot");
+
+		store.delete(&older.name).await.unwrap();
+		assert_eq!(
+			list_snapshots(store.as_ref(), Some(library)).await.unwrap(),
+			[newer]
+		);
+	}
+}
@@ -71,7 +71,10 @@ impl Category {
 
 pub async fn get_category_count(db: &Arc<PrismaClient>, category: Category) -> i32 {
 	db.object()
-		.count(vec![category.to_where_param()])
+		.count(vec![
+			category.to_where_param(),
+			object::date_deleted::equals(None),
+		])
 		.exec()
 		.await
 		.unwrap_or(0) as i32
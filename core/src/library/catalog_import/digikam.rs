@@ -0,0 +1,183 @@
+//! digiKam databases, `digikam4.db` in the root of the first collection of digiKam.
+//!
+//! An image is a row of `Images` in an album, a folder relative to an album root. Roots on
+//! removable volumes are known by the id of the volume only, their images can't be found. The tags
+//! digiKam keeps for itself, under `_Digikam_Internal_Tags_`, aren't imported, and the collection
+//! of an image is the category of its album.
+
+use crate::prisma::PrismaClient;
+
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+
+use percent_encoding::percent_decode_str;
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use serde::Deserialize;
+
+use super::{CatalogImage, CatalogLabel, Count, BATCH_SIZE};
+
+#[derive(Deserialize)]
+struct AlbumRoot {
+	id: i64,
+	identifier: Option<String>,
+	specific_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Image {
+	id: i64,
+	root: Option<i64>,
+	album_path: Option<String>,
+	name: String,
+	rating: Option<i32>,
+	edits: Option<String>,
+}
+
+/// The path of an album root, from its identifier like `volumeid:?path=%2Fhome%2Fme%2FPictures`
+fn root_path(identifier: &str, specific_path: &str) -> Option<PathBuf> {
+	let path = identifier
+		.strip_prefix("volumeid:?")?
+		.split('&')
+		.find_map(|param| param.strip_prefix("path="))?;
+
+	Some(
+		PathBuf::from(percent_decode_str(path).decode_utf8().ok()?.as_ref())
+			.join(specific_path.trim_start_matches('/')),
+	)
+}
+
+fn image_path(root: &Path, album_path: &str, name: &str) -> PathBuf {
+	root.join(album_path.trim_start_matches('/')).join(name)
+}
+
+pub(super) async fn count(catalog: &PrismaClient) -> Result<i64, QueryError> {
+	Ok(catalog
+		._query_raw::<Count>(raw!(
+			"SELECT COUNT(*) AS count FROM Images WHERE album IS NOT NULL"
+		))
+		.exec()
+		.await?
+		.first()
+		.map_or(0, |row| row.count))
+}
+
+pub(super) async fn images(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogImage>, QueryError> {
+	let roots = catalog
+		._query_raw::<AlbumRoot>(raw!(
+			"SELECT id, identifier, specificPath AS specific_path FROM AlbumRoots"
+		))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|root| {
+			let path = root_path(
+				root.identifier.as_deref()?,
+				root.specific_path.as_deref().unwrap_or_default(),
+			)?;
+
+			Some((root.id, path))
+		})
+		.collect::<HashMap<_, _>>();
+
+	Ok(catalog
+		._query_raw::<Image>(raw!(
+			"SELECT image.id AS id, album.albumRoot AS root, album.relativePath AS album_path,
+				image.name AS name,
+				CASE WHEN information.rating >= 0 THEN information.rating END AS rating,
+				history.history AS edits
+			FROM Images image
+			LEFT JOIN Albums album ON album.id = image.album
+			LEFT JOIN ImageInformation information ON information.imageid = image.id
+			LEFT JOIN ImageHistory history ON history.imageid = image.id
+			WHERE image.album IS NOT NULL
+			ORDER BY image.id LIMIT {} OFFSET {}",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|image| CatalogImage {
+			id: image.id,
+			path: image
+				.root
+				.and_then(|root| roots.get(&root))
+				.zip(image.album_path)
+				.map(|(root, album_path)| image_path(root, &album_path, &image.name)),
+			rating: image.rating,
+			edits: image.edits,
+		})
+		.collect())
+}
+
+pub(super) async fn keywords(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogLabel>, QueryError> {
+	catalog
+		._query_raw::<CatalogLabel>(raw!(
+			"SELECT image_tag.imageid AS image, tag.name AS name
+			FROM ImageTags image_tag
+			JOIN Tags tag ON tag.id = image_tag.tagid
+			WHERE tag.name != '_Digikam_Internal_Tags_'
+				AND tag.pid NOT IN (SELECT id FROM Tags WHERE name = '_Digikam_Internal_Tags_')
+				AND image_tag.imageid IN (
+					SELECT id FROM Images WHERE album IS NOT NULL
+					ORDER BY id LIMIT {} OFFSET {}
+				)",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await
+}
+
+pub(super) async fn collections(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogLabel>, QueryError> {
+	catalog
+		._query_raw::<CatalogLabel>(raw!(
+			"SELECT image.id AS image, album.collection AS name
+			FROM Images image
+			JOIN Albums album ON album.id = image.album
+			WHERE album.collection IS NOT NULL AND image.id IN (
+				SELECT id FROM Images WHERE album IS NOT NULL
+				ORDER BY id LIMIT {} OFFSET {}
+			)",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_images_under_their_root() {
+		let root = root_path("volumeid:?path=%2Fhome%2Fme%2FPictures", "/").unwrap();
+
+		assert_eq!(root, PathBuf::from("/home/me/Pictures"));
+		assert_eq!(
+			image_path(&root, "/2023/Trip", "beach.jpg"),
+			PathBuf::from("/home/me/Pictures/2023/Trip/beach.jpg")
+		);
+		assert_eq!(
+			image_path(&root, "/", "cat.png"),
+			PathBuf::from("/home/me/Pictures/cat.png")
+		);
+		assert_eq!(
+			root_path("volumeid:?uuid=1234-ABCD", "/Pictures"),
+			None,
+			"removable volumes are only known by id"
+		);
+	}
+}
@@ -0,0 +1,105 @@
+//! Lightroom Classic catalogs. The tables read here haven't changed since Lightroom 4.
+//!
+//! An image is a row of `Adobe_images`, its file is split between `AgLibraryRootFolder` (an
+//! absolute path ending with `/`), `AgLibraryFolder` (relative to the root, ending with `/` too)
+//! and `AgLibraryFile`. Only the collections made by hand are imported, not the smart ones.
+
+use crate::prisma::PrismaClient;
+
+use std::path::PathBuf;
+
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use serde::Deserialize;
+
+use super::{CatalogImage, CatalogLabel, Count, BATCH_SIZE};
+
+#[derive(Deserialize)]
+struct Image {
+	id: i64,
+	path: Option<String>,
+	rating: Option<i32>,
+	edits: Option<String>,
+}
+
+pub(super) async fn count(catalog: &PrismaClient) -> Result<i64, QueryError> {
+	Ok(catalog
+		._query_raw::<Count>(raw!("SELECT COUNT(*) AS count FROM Adobe_images"))
+		.exec()
+		.await?
+		.first()
+		.map_or(0, |row| row.count))
+}
+
+pub(super) async fn images(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogImage>, QueryError> {
+	Ok(catalog
+		._query_raw::<Image>(raw!(
+			"SELECT image.id_local AS id,
+				root.absolutePath || folder.pathFromRoot || file.baseName
+					|| CASE WHEN file.extension = '' THEN '' ELSE '.' || file.extension END AS path,
+				CAST(image.rating AS INTEGER) AS rating,
+				settings.text AS edits
+			FROM Adobe_images image
+			LEFT JOIN AgLibraryFile file ON file.id_local = image.rootFile
+			LEFT JOIN AgLibraryFolder folder ON folder.id_local = file.folder
+			LEFT JOIN AgLibraryRootFolder root ON root.id_local = folder.rootFolder
+			LEFT JOIN Adobe_imageDevelopSettings settings
+				ON settings.image = image.id_local AND settings.hasDevelopAdjustmentsEx > 0
+			ORDER BY image.id_local LIMIT {} OFFSET {}",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|image| CatalogImage {
+			id: image.id,
+			path: image.path.map(PathBuf::from),
+			rating: image.rating,
+			edits: image.edits,
+		})
+		.collect())
+}
+
+pub(super) async fn keywords(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogLabel>, QueryError> {
+	catalog
+		._query_raw::<CatalogLabel>(raw!(
+			"SELECT keyword_image.image AS image, keyword.name AS name
+			FROM AgLibraryKeywordImage keyword_image
+			JOIN AgLibraryKeyword keyword ON keyword.id_local = keyword_image.tag
+			WHERE keyword.name IS NOT NULL AND keyword_image.image IN (
+				SELECT id_local FROM Adobe_images ORDER BY id_local LIMIT {} OFFSET {}
+			)",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await
+}
+
+pub(super) async fn collections(
+	catalog: &PrismaClient,
+	skip: i64,
+) -> Result<Vec<CatalogLabel>, QueryError> {
+	catalog
+		._query_raw::<CatalogLabel>(raw!(
+			"SELECT collection_image.image AS image, collection.name AS name
+			FROM AgLibraryCollectionImage collection_image
+			JOIN AgLibraryCollection collection
+				ON collection.id_local = collection_image.collection
+			WHERE collection.creationId = 'com.adobe.ag.library.collection'
+				AND collection.name IS NOT NULL
+				AND collection_image.image IN (
+					SELECT id_local FROM Adobe_images ORDER BY id_local LIMIT {} OFFSET {}
+				)",
+			PrismaValue::Int(BATCH_SIZE),
+			PrismaValue::Int(skip)
+		))
+		.exec()
+		.await
+}
@@ -0,0 +1,587 @@
+//! Imports the metadata of photo catalogs onto the objects of a library: Lightroom Classic catalogs
+//! (`.lrcat`) and digiKam databases (`digikam4.db`), both SQLite databases read as they are.
+//!
+//! The images of the catalog are matched to objects by path, through the locations of the library,
+//! then by content when their file is still on disk but isn't indexed under that path. Keywords
+//! become tags and collections become spaces, both reused by name. Ratings and edits are kept as
+//! the catalog metadata of the object, importing the same catalog again updates them. Images
+//! without an object, like the ones of locations that weren't scanned yet, are skipped.
+
+mod digikam;
+mod lightroom;
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::IsolatedFilePathData,
+	object::{cas::generate_cas_id, tag::TagCreateArgs},
+	prisma::{
+		self, catalog_metadata, file_path, location, object, object_in_space, space, tag,
+		tag_on_object, PrismaClient,
+	},
+	sync::SyncError,
+	util::{db::db_url, error::NonUtf8PathError},
+};
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use prisma_client_rust::{NewClientError, QueryError};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, sync::OnceCell};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// How many images of the catalog are imported in a step
+const BATCH_SIZE: i64 = 500;
+
+/// The color of the tags created for keywords
+const KEYWORD_TAG_COLOR: &str = "#646278";
+
+#[repr(i32)]
+#[derive(Serialize, Deserialize, Type, Hash, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CatalogKind {
+	Lightroom = 0,
+	Digikam = 1,
+}
+
+#[derive(Error, Debug)]
+pub enum CatalogImportError {
+	#[error("catalog not found: {}", .0.display())]
+	NotFound(PathBuf),
+	#[error("failed to open the catalog: {0}")]
+	Open(#[from] Box<NewClientError>),
+	#[error("the file isn't a {0:?} catalog, or its version isn't supported: {1}")]
+	NotACatalog(CatalogKind, QueryError),
+	#[error(transparent)]
+	NonUtf8Path(#[from] NonUtf8PathError),
+}
+
+/// An image of a catalog, where its file was when the catalog last saw it
+#[derive(Debug)]
+struct CatalogImage {
+	id: i64,
+	/// Unknown when the image is on a volume the catalog knows by id only
+	path: Option<PathBuf>,
+	rating: Option<i32>,
+	edits: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Count {
+	count: i64,
+}
+
+/// A keyword or a collection of an image
+#[derive(Debug, Deserialize)]
+struct CatalogLabel {
+	image: i64,
+	name: String,
+}
+
+impl CatalogKind {
+	async fn count(self, catalog: &PrismaClient) -> Result<i64, QueryError> {
+		match self {
+			Self::Lightroom => lightroom::count(catalog).await,
+			Self::Digikam => digikam::count(catalog).await,
+		}
+	}
+
+	async fn images(
+		self,
+		catalog: &PrismaClient,
+		skip: i64,
+	) -> Result<Vec<CatalogImage>, QueryError> {
+		match self {
+			Self::Lightroom => lightroom::images(catalog, skip).await,
+			Self::Digikam => digikam::images(catalog, skip).await,
+		}
+	}
+
+	async fn keywords(
+		self,
+		catalog: &PrismaClient,
+		skip: i64,
+	) -> Result<Vec<CatalogLabel>, QueryError> {
+		match self {
+			Self::Lightroom => lightroom::keywords(catalog, skip).await,
+			Self::Digikam => digikam::keywords(catalog, skip).await,
+		}
+	}
+
+	async fn collections(
+		self,
+		catalog: &PrismaClient,
+		skip: i64,
+	) -> Result<Vec<CatalogLabel>, QueryError> {
+		match self {
+			Self::Lightroom => lightroom::collections(catalog, skip).await,
+			Self::Digikam => digikam::collections(catalog, skip).await,
+		}
+	}
+}
+
+/// Imports the catalog at `path` into the library the job runs in
+pub struct CatalogImportJob {
+	catalog: OnceCell<PrismaClient>,
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct CatalogImportJobInit {
+	pub kind: CatalogKind,
+	pub path: PathBuf,
+}
+
+impl JobInitData for CatalogImportJobInit {
+	type Job = CatalogImportJob;
+
+	fn locations(&self) -> JobLocations {
+		// The catalog can match files of any location
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CatalogImportJobStep {
+	skip: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct CatalogImportJobRunMetadata {
+	matched_by_path: u32,
+	/// Images whose file was found under another path
+	matched_by_content: u32,
+	unmatched: u32,
+	tags_created: u32,
+	spaces_created: u32,
+}
+
+impl JobRunMetadata for CatalogImportJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.matched_by_path += new_data.matched_by_path;
+		self.matched_by_content += new_data.matched_by_content;
+		self.unmatched += new_data.unmatched;
+		self.tags_created += new_data.tags_created;
+		self.spaces_created += new_data.spaces_created;
+	}
+}
+
+object::select!(object_for_catalog_import { id pub_id });
+
+enum MatchedBy {
+	Path,
+	Content,
+}
+
+/// The object of the file at `path`, indexed in a location of the library or with the same content
+async fn find_object(
+	db: &PrismaClient,
+	locations: &[location::Data],
+	path: &Path,
+) -> Result<Option<(object_for_catalog_import::Data, MatchedBy)>, JobError> {
+	for location in locations {
+		let Some(location_path) = location.path.as_deref().map(Path::new) else {
+			continue;
+		};
+		if !path.starts_with(location_path) {
+			continue;
+		}
+
+		let Ok(iso_file_path) = IsolatedFilePathData::new(location.id, location_path, path, false)
+		else {
+			continue;
+		};
+
+		if let Some(object) = db
+			.object()
+			.find_first(vec![object::file_paths::some(
+				vec![(&iso_file_path).into()],
+			)])
+			.select(object_for_catalog_import::select())
+			.exec()
+			.await?
+		{
+			return Ok(Some((object, MatchedBy::Path)));
+		}
+	}
+
+	let size = match fs::metadata(path).await {
+		Ok(metadata) if metadata.is_file() => metadata.len(),
+		_ => return Ok(None),
+	};
+
+	let cas_id = match generate_cas_id(path, size).await {
+		Ok(cas_id) => cas_id,
+		Err(e) => {
+			debug!(
+				"Failed to read '{}' to match it by content: {e}",
+				path.display()
+			);
+			return Ok(None);
+		}
+	};
+
+	Ok(db
+		.object()
+		.find_first(vec![object::file_paths::some(vec![
+			file_path::cas_id::equals(Some(cas_id)),
+		])])
+		.select(object_for_catalog_import::select())
+		.exec()
+		.await?
+		.map(|object| (object, MatchedBy::Content)))
+}
+
+/// The objects of each keyword or collection, for the images that have one
+fn by_name<'a>(
+	labels: Vec<CatalogLabel>,
+	objects: &'a HashMap<i64, object_for_catalog_import::Data>,
+) -> BTreeMap<String, Vec<&'a object_for_catalog_import::Data>> {
+	let mut by_name = BTreeMap::<_, Vec<_>>::new();
+
+	for label in labels {
+		let name = label.name.trim();
+		if name.is_empty() {
+			continue;
+		}
+
+		if let Some(object) = objects.get(&label.image) {
+			by_name.entry(name.to_string()).or_default().push(object);
+		}
+	}
+
+	by_name
+}
+
+async fn import_keywords(
+	library: &Library,
+	keywords: BTreeMap<String, Vec<&object_for_catalog_import::Data>>,
+) -> Result<u32, SyncError> {
+	let Library { db, sync, .. } = library;
+	let mut created = 0;
+
+	for (name, objects) in keywords {
+		let tag = match db
+			.tag()
+			.find_first(vec![
+				tag::name::equals(Some(name.clone())),
+				tag::date_deleted::equals(None),
+			])
+			.exec()
+			.await?
+		{
+			Some(tag) => tag,
+			None => {
+				created += 1;
+
+				TagCreateArgs {
+					name,
+					color: KEYWORD_TAG_COLOR.to_string(),
+				}
+				.exec(library)
+				.await?
+			}
+		};
+
+		let Ok(tag_pub_id) = Uuid::from_slice(&tag.pub_id) else {
+			continue;
+		};
+
+		sync.write_ops(
+			db,
+			(
+				objects
+					.iter()
+					.filter_map(|object| Uuid::from_slice(&object.pub_id).ok())
+					.map(|object_pub_id| {
+						sync.relation_create(tag_on_object::NAME, tag_pub_id, object_pub_id)
+					})
+					.collect(),
+				db.tag_on_object()
+					.create_many(
+						objects
+							.iter()
+							.map(|object| tag_on_object::CreateUnchecked {
+								tag_id: tag.id,
+								object_id: object.id,
+								_params: vec![],
+							})
+							.collect(),
+					)
+					.skip_duplicates(),
+			),
+		)
+		.await?;
+	}
+
+	Ok(created)
+}
+
+async fn import_collections(
+	library: &Library,
+	collections: BTreeMap<String, Vec<&object_for_catalog_import::Data>>,
+) -> Result<u32, QueryError> {
+	let db = &library.db;
+	let mut created = 0;
+
+	for (name, objects) in collections {
+		let space = match db
+			.space()
+			.find_first(vec![space::name::equals(Some(name.clone()))])
+			.exec()
+			.await?
+		{
+			Some(space) => space,
+			None => {
+				created += 1;
+
+				db.space()
+					.create(
+						Uuid::new_v4().as_bytes().to_vec(),
+						vec![
+							space::name::set(Some(name)),
+							space::date_created::set(Some(Utc::now().into())),
+						],
+					)
+					.exec()
+					.await?
+			}
+		};
+
+		db.object_in_space()
+			.create_many(
+				objects
+					.iter()
+					.map(|object| object_in_space::CreateUnchecked {
+						space_id: space.id,
+						object_id: object.id,
+						_params: vec![],
+					})
+					.collect(),
+			)
+			.skip_duplicates()
+			.exec()
+			.await?;
+	}
+
+	Ok(created)
+}
+
+impl CatalogImportJob {
+	async fn catalog(&self, path: &Path) -> Result<&PrismaClient, JobError> {
+		self.catalog
+			.get_or_try_init(|| async {
+				Ok::<_, JobError>(
+					prisma::new_client_with_url(&db_url(path).map_err(CatalogImportError::from)?)
+						.await
+						.map_err(|e| CatalogImportError::Open(Box::new(e)))?,
+				)
+			})
+			.await
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for CatalogImportJob {
+	type Init = CatalogImportJobInit;
+	type Data = ();
+	type Step = CatalogImportJobStep;
+	type RunMetadata = CatalogImportJobRunMetadata;
+
+	const NAME: &'static str = "catalog_import";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {
+			catalog: OnceCell::new(),
+		}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		// Opening a database that doesn't exist creates it
+		if fs::metadata(&init.path).await.is_err() {
+			return Err(CatalogImportError::NotFound(init.path.clone()).into());
+		}
+
+		let catalog = self.catalog(&init.path).await?;
+		let images = init
+			.kind
+			.count(catalog)
+			.await
+			.map_err(|e| CatalogImportError::NotACatalog(init.kind, e))?;
+
+		*data = Some(());
+
+		ctx.progress_msg(job_message!("catalogImport.importing", count = images));
+
+		Ok((
+			Default::default(),
+			(0..images)
+				.step_by(BATCH_SIZE as usize)
+				.map(|skip| CatalogImportJobStep { skip })
+				.collect::<Vec<_>>(),
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let catalog = self.catalog(&init.path).await?;
+		let library = &ctx.library;
+		let db = &library.db;
+		let mut metadata = CatalogImportJobRunMetadata::default();
+
+		ctx.progress_msg(job_message!(
+			"catalogImport.importingBatch",
+			from = step.skip,
+			to = step.skip + BATCH_SIZE
+		));
+
+		let locations = db.location().find_many(vec![]).exec().await?;
+		let mut objects = HashMap::new();
+
+		for image in init.kind.images(catalog, step.skip).await? {
+			let Some(path) = &image.path else {
+				metadata.unmatched += 1;
+				continue;
+			};
+
+			let Some((object, matched_by)) = find_object(db, &locations, path).await? else {
+				debug!("No object for '{}'", path.display());
+				metadata.unmatched += 1;
+				continue;
+			};
+
+			match matched_by {
+				MatchedBy::Path => metadata.matched_by_path += 1,
+				MatchedBy::Content => metadata.matched_by_content += 1,
+			}
+
+			db.catalog_metadata()
+				.upsert(
+					catalog_metadata::object_id_catalog(object.id, init.kind as i32),
+					catalog_metadata::create_unchecked(
+						object.id,
+						init.kind as i32,
+						Utc::now().into(),
+						vec![
+							catalog_metadata::rating::set(image.rating),
+							catalog_metadata::edits::set(image.edits.clone()),
+						],
+					),
+					vec![
+						catalog_metadata::rating::set(image.rating),
+						catalog_metadata::edits::set(image.edits),
+						catalog_metadata::date_imported::set(Utc::now().into()),
+					],
+				)
+				.exec()
+				.await?;
+
+			objects.insert(image.id, object);
+		}
+
+		if !objects.is_empty() {
+			let keywords = init.kind.keywords(catalog, step.skip).await?;
+			metadata.tags_created += import_keywords(library, by_name(keywords, &objects)).await?;
+
+			let collections = init.kind.collections(catalog, step.skip).await?;
+			metadata.spaces_created +=
+				import_collections(library, by_name(collections, &objects)).await?;
+		}
+
+		Ok(metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let library = &ctx.library;
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Imported {:?} catalog '{}': {} images matched by path, {} by content, {} unmatched",
+			state.init.kind,
+			state.init.path.display(),
+			metadata.matched_by_path,
+			metadata.matched_by_content,
+			metadata.unmatched
+		);
+
+		invalidate_query!(library, "tags.list");
+		invalidate_query!(library, "tags.getForObject");
+		invalidate_query!(library, "search.objects");
+
+		Ok(Some(serde_json::to_value(metadata)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn groups_the_labels_of_matched_images() {
+		let objects = HashMap::from([
+			(
+				1,
+				object_for_catalog_import::Data {
+					id: 10,
+					pub_id: vec![],
+				},
+			),
+			(
+				2,
+				object_for_catalog_import::Data {
+					id: 20,
+					pub_id: vec![],
+				},
+			),
+		]);
+		let label = |image, name: &str| CatalogLabel {
+			image,
+			name: name.to_string(),
+		};
+
+		let by_name = by_name(
+			vec![
+				label(1, "Holidays"),
+				label(2, " Holidays "),
+				label(3, "Holidays"),
+				label(2, "Family"),
+				label(1, ""),
+			],
+			&objects,
+		);
+
+		assert_eq!(
+			by_name
+				.iter()
+				.map(|(name, objects)| (
+					name.as_str(),
+					objects.iter().map(|object| object.id).collect::<Vec<_>>()
+				))
+				.collect::<Vec<_>>(),
+			[("Family", vec![20]), ("Holidays", vec![10, 20])]
+		);
+	}
+}
@@ -0,0 +1,245 @@
+//! Removes the records left behind by deletions that didn't go through: file paths of deleted
+//! locations, tag links of deleted objects and jobs that wait on a library that is gone.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStatus, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::merge::LibraryMergeJob,
+	prisma::job,
+};
+
+use prisma_client_rust::raw;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::fs;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Removes orphan records, or only counts them with `dry_run`
+pub struct OrphanCleanupJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Default)]
+pub struct OrphanCleanupJobInit {
+	pub dry_run: bool,
+}
+
+impl JobInitData for OrphanCleanupJobInit {
+	type Job = OrphanCleanupJob;
+
+	fn locations(&self) -> JobLocations {
+		// The orphans can be anywhere in the library
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum OrphanCleanupJobStep {
+	FilePaths,
+	TagLinks,
+	Jobs,
+}
+
+/// How many orphan records were found, and removed unless it was a dry run
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OrphanCleanupJobRunMetadata {
+	dry_run: bool,
+	file_paths: u32,
+	tag_links: u32,
+	jobs: u32,
+}
+
+impl JobRunMetadata for OrphanCleanupJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.dry_run |= new_data.dry_run;
+		self.file_paths += new_data.file_paths;
+		self.tag_links += new_data.tag_links;
+		self.jobs += new_data.jobs;
+	}
+}
+
+#[derive(Deserialize)]
+struct Count {
+	count: i32,
+}
+
+/// The merges waiting to resume from a library that was deleted from this node, they can only fail
+async fn orphan_jobs(ctx: &WorkerContext) -> Result<Vec<Vec<u8>>, JobError> {
+	let libraries_dir = ctx.library.config().data_directory().join("libraries");
+
+	let merges = ctx
+		.library
+		.db
+		.job()
+		.find_many(vec![
+			job::name::equals(Some(LibraryMergeJob::NAME.to_string())),
+			job::status::in_vec(vec![JobStatus::Queued as i32, JobStatus::Paused as i32]),
+		])
+		.select(job::select!({ id data }))
+		.exec()
+		.await?;
+
+	let mut orphans = vec![];
+	for merge in merges {
+		let Some(state) = merge.data.as_deref().and_then(|data| {
+			rmp_serde::from_slice::<JobState<LibraryMergeJob>>(data)
+				.map_err(|e| {
+					warn!(
+						"Failed to read the state of job '{:?}': {e}",
+						Uuid::from_slice(&merge.id)
+					)
+				})
+				.ok()
+		}) else {
+			continue;
+		};
+
+		let source_config_path =
+			libraries_dir.join(format!("{}.sdlibrary", state.init.source_library_id));
+		if fs::metadata(&source_config_path).await.is_err() {
+			orphans.push(merge.id);
+		}
+	}
+
+	Ok(orphans)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for OrphanCleanupJob {
+	type Init = OrphanCleanupJobInit;
+	type Data = ();
+	type Step = OrphanCleanupJobStep;
+	type RunMetadata = OrphanCleanupJobRunMetadata;
+
+	const NAME: &'static str = "library_orphan_cleanup";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		_: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		*data = Some(());
+
+		Ok((
+			OrphanCleanupJobRunMetadata {
+				dry_run: init.dry_run,
+				..Default::default()
+			},
+			vec![
+				OrphanCleanupJobStep::FilePaths,
+				OrphanCleanupJobStep::TagLinks,
+				OrphanCleanupJobStep::Jobs,
+			],
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let db = &ctx.library.db;
+		let mut metadata = OrphanCleanupJobRunMetadata::default();
+
+		match step {
+			OrphanCleanupJobStep::FilePaths => {
+				ctx.progress_msg(job_message!("cleanup.filePaths"));
+
+				metadata.file_paths = if init.dry_run {
+					db._query_raw::<Count>(raw!(
+						"SELECT count(*) AS count FROM file_path
+						WHERE location_id IS NULL OR location_id NOT IN (SELECT id FROM location)"
+					))
+					.exec()
+					.await?
+					.first()
+					.map_or(0, |row| row.count as u32)
+				} else {
+					db._execute_raw(raw!(
+						"DELETE FROM file_path
+						WHERE location_id IS NULL OR location_id NOT IN (SELECT id FROM location)"
+					))
+					.exec()
+					.await? as u32
+				};
+			}
+			OrphanCleanupJobStep::TagLinks => {
+				ctx.progress_msg(job_message!("cleanup.tagLinks"));
+
+				metadata.tag_links = if init.dry_run {
+					db._query_raw::<Count>(raw!(
+						"SELECT count(*) AS count FROM tag_on_object
+						WHERE object_id NOT IN (SELECT id FROM object)
+							OR tag_id NOT IN (SELECT id FROM tag)"
+					))
+					.exec()
+					.await?
+					.first()
+					.map_or(0, |row| row.count as u32)
+				} else {
+					db._execute_raw(raw!(
+						"DELETE FROM tag_on_object
+						WHERE object_id NOT IN (SELECT id FROM object)
+							OR tag_id NOT IN (SELECT id FROM tag)"
+					))
+					.exec()
+					.await? as u32
+				};
+			}
+			OrphanCleanupJobStep::Jobs => {
+				ctx.progress_msg(job_message!("cleanup.jobs"));
+
+				let orphans = orphan_jobs(ctx).await?;
+
+				metadata.jobs = if init.dry_run {
+					orphans.len() as u32
+				} else {
+					db.job()
+						.delete_many(vec![job::id::in_vec(orphans)])
+						.exec()
+						.await? as u32
+				};
+			}
+		}
+
+		Ok(metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let library = &ctx.library;
+		let metadata = &state.run_metadata;
+
+		info!(
+			"{} orphan records of library '{}': {} file paths, {} tag links, {} jobs",
+			if metadata.dry_run { "Found" } else { "Removed" },
+			library.id,
+			metadata.file_paths,
+			metadata.tag_links,
+			metadata.jobs
+		);
+
+		if !metadata.dry_run {
+			// The objects of the removed file paths are orphans now too
+			library.orphan_remover.invoke().await;
+
+			invalidate_query!(library, "search.paths");
+			invalidate_query!(library, "search.objects");
+			invalidate_query!(library, "jobs.reports");
+		}
+
+		Ok(Some(serde_json::to_value(metadata)?))
+	}
+}
@@ -1,21 +1,35 @@
 use crate::{
-	prisma::{file_path, indexer_rule, PrismaClient},
+	library::{
+		backup::{BackupKey, BackupTarget},
+		encryption::LibraryEncryption,
+		LibrarySettings,
+	},
+	object::preview::{ThumbnailFormat, DEFAULT_THUMBNAIL_QUALITY},
+	prisma::{file_path, indexer_rule, relation_operation, shared_operation, PrismaClient},
+	sync::{ConflictPolicy, OperationCipher},
 	util::{
 		db::{maybe_missing, uuid_to_bytes},
 		migrator::{Migrate, MigratorError},
 	},
 };
 
+use sd_crypto::{
+	keys::secrets::{SecretKind, Secrets},
+	Protected,
+};
 use sd_p2p::{spacetunnel::Identity, PeerId};
 use sd_prisma::prisma::node;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
 use prisma_client_rust::not;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Map, Value};
 use specta::Type;
-use tracing::error;
+use tracing::{debug, error, warn};
 use uuid::Uuid;
 
 /// LibraryConfig holds the configuration for a specific library. This is stored as a '{uuid}.sdlibrary' file.
@@ -25,10 +39,24 @@ pub struct LibraryConfig {
 	pub name: String,
 	/// description is a user set description of the library. This is used in the UI and is set by the user.
 	pub description: Option<String>,
-	/// P2P identity of this library.
+	/// P2P identity of this library. Empty once it's moved to the OS keychain.
 	pub identity: Vec<u8>,
 	/// Id of the current node
 	pub node_id: Uuid,
+	/// How the library behaves on this node.
+	pub settings: LibrarySettings,
+	/// How changes made concurrently by paired nodes are settled.
+	pub sync_conflict_policy: ConflictPolicy,
+	/// Where snapshots of the library are backed up to.
+	pub backup_targets: Vec<BackupTarget>,
+	/// Key the backup snapshots are encrypted with, set along with the backup password.
+	pub backup_key: Option<BackupKey>,
+	/// Key the sync operations are encrypted with in the database.
+	pub sync_key: Vec<u8>,
+	/// Set if the database is encrypted on disk.
+	pub encryption: Option<LibraryEncryption>,
+	/// The library this one is a disposable copy of, if it's a clone.
+	pub cloned_from: Option<Uuid>,
 	// /// is_encrypted is a flag that is set to true if the library is encrypted.
 	// #[serde(default)]
 	// pub is_encrypted: bool,
@@ -39,6 +67,11 @@ pub struct SanitisedLibraryConfig {
 	pub name: String,
 	pub description: Option<String>,
 	pub node_id: Uuid,
+	pub settings: LibrarySettings,
+	pub sync_conflict_policy: ConflictPolicy,
+	pub has_backup_password: bool,
+	pub encrypted: bool,
+	pub cloned_from: Option<Uuid>,
 }
 
 impl From<LibraryConfig> for SanitisedLibraryConfig {
@@ -47,6 +80,11 @@ impl From<LibraryConfig> for SanitisedLibraryConfig {
 			name: config.name,
 			description: config.description,
 			node_id: config.node_id,
+			settings: config.settings,
+			sync_conflict_policy: config.sync_conflict_policy,
+			has_backup_password: config.backup_key.is_some(),
+			encrypted: config.encryption.is_some(),
+			cloned_from: config.cloned_from,
 		}
 	}
 }
@@ -58,13 +96,20 @@ impl LibraryConfig {
 			description: None,
 			identity: Identity::new().to_bytes().to_vec(),
 			node_id,
+			settings: LibrarySettings::default(),
+			sync_conflict_policy: ConflictPolicy::default(),
+			backup_targets: vec![],
+			backup_key: None,
+			sync_key: OperationCipher::generate_key(),
+			encryption: None,
+			cloned_from: None,
 		}
 	}
 }
 
 #[async_trait::async_trait]
 impl Migrate for LibraryConfig {
-	const CURRENT_VERSION: u32 = 5;
+	const CURRENT_VERSION: u32 = 15;
 
 	type Ctx = (Uuid, PeerId, Arc<PrismaClient>);
 
@@ -187,11 +232,235 @@ impl Migrate for LibraryConfig {
 				)
 				.await?;
 			},
+			// Existing libraries keep the thumbnails they had before the format was configurable
+			6 => {
+				config.insert(
+					"thumbnail_format".into(),
+					serde_json::to_value(ThumbnailFormat::Webp)?,
+				);
+				config.insert(
+					"thumbnail_quality".into(),
+					Value::from(DEFAULT_THUMBNAIL_QUALITY),
+				);
+			}
+			7 => {
+				config.insert(
+					"sync_conflict_policy".into(),
+					serde_json::to_value(ConflictPolicy::default())?,
+				);
+			}
+			8 => {
+				config.insert("backup_targets".into(), Value::Array(vec![]));
+				config.insert("backup_key".into(), Value::Null);
+			}
+			// The sync operations stored until now are encrypted with the new key
+			9 => {
+				let key = OperationCipher::generate_key();
+				let cipher =
+					OperationCipher::new(&key).map_err(|e| MigratorError::Custom(e.to_string()))?;
+
+				let mut shared = vec![];
+				for op in db
+					.shared_operation()
+					.find_many(vec![])
+					.select(shared_operation::select!({ id data }))
+					.exec()
+					.await?
+				{
+					let data = cipher
+						.encrypt(&op.id, &op.data)
+						.await
+						.map_err(|e| MigratorError::Custom(e.to_string()))?;
+
+					shared.push(db.shared_operation().update(
+						shared_operation::id::equals(op.id),
+						vec![shared_operation::data::set(data)],
+					));
+				}
+
+				let mut relation = vec![];
+				for op in db
+					.relation_operation()
+					.find_many(vec![])
+					.select(relation_operation::select!({ id data }))
+					.exec()
+					.await?
+				{
+					let data = cipher
+						.encrypt(&op.id, &op.data)
+						.await
+						.map_err(|e| MigratorError::Custom(e.to_string()))?;
+
+					relation.push(db.relation_operation().update(
+						relation_operation::id::equals(op.id),
+						vec![relation_operation::data::set(data)],
+					));
+				}
+
+				db._batch((shared, relation)).await?;
+
+				config.insert("sync_key".into(), serde_json::to_value(key)?);
+			}
+			// The number of snapshots kept becomes the number of daily ones
+			10 => {
+				if let Some(Value::Array(targets)) = config.get_mut("backup_targets") {
+					for target in targets.iter_mut().filter_map(Value::as_object_mut) {
+						let keep = target.remove("keep").unwrap_or(Value::from(7));
+						target.insert(
+							"retention".into(),
+							serde_json::json!({ "daily": keep, "weekly": 0 }),
+						);
+						target.insert("automatic".into(), Value::Bool(false));
+					}
+				}
+			}
+			11 => {
+				config.insert("encryption".into(), Value::Null);
+			}
+			// The thumbnail settings join the new ones in the settings of the library
+			12 => {
+				let mut settings = serde_json::to_value(LibrarySettings::default())?;
+				if let Some(settings) = settings.as_object_mut() {
+					for key in ["thumbnail_format", "thumbnail_quality"] {
+						if let Some(value) = config.remove(key) {
+							settings.insert(key.into(), value);
+						}
+					}
+				}
+
+				config.insert("settings".into(), settings);
+			}
+			13 => {
+				if let Some(Value::Object(settings)) = config.get_mut("settings") {
+					settings.insert("preview_media_max_size_mb".into(), Value::Null);
+				}
+			}
+			14 => {
+				config.insert("cloned_from".into(), Value::Null);
+			}
+			15 => {
+				if let Some(Value::Object(settings)) = config.get_mut("settings") {
+					settings.insert("inbox_location_id".into(), Value::Null);
+				}
+			}
 			v => unreachable!("Missing migration for library version {}", v),
 		}
 
 		Ok(())
 	}
+
+	/// Moves the secrets into the OS keychain, leaving a reference to them in the file. They're
+	/// kept in the file on platforms without a keychain.
+	fn to_file(path: &Path, config: &mut Map<String, Value>) -> Result<(), MigratorError> {
+		let secrets = match Secrets::new() {
+			Ok(secrets) => secrets,
+			Err(e) => {
+				debug!(
+					"No keychain to keep the secrets of '{}' in: {e}",
+					path.display()
+				);
+				return Ok(());
+			}
+		};
+
+		let library_id = path
+			.file_stem()
+			.and_then(|stem| stem.to_str())
+			.and_then(|stem| Uuid::parse_str(stem).ok());
+
+		for (owner, kind, value) in secret_fields(library_id, config) {
+			let Some(owner) = owner else { continue };
+			if value.is_null() {
+				continue;
+			}
+
+			match secrets.insert(owner, kind, &Protected::new(serde_json::to_vec(&*value)?)) {
+				Ok(()) => *value = json!({ KEYCHAIN_REFERENCE: owner }),
+				Err(e) => warn!("Failed to put the {kind:?} of '{owner}' in the keychain: {e}"),
+			}
+		}
+
+		Ok(())
+	}
+
+	fn from_file(_: &Path, config: &mut Map<String, Value>) -> Result<(), MigratorError> {
+		let mut references = secret_fields(None, config)
+			.into_iter()
+			.filter_map(|(_, kind, value)| {
+				keychain_reference(value).map(|owner| (owner, kind, value))
+			})
+			.peekable();
+
+		if references.peek().is_none() {
+			return Ok(());
+		}
+
+		let secrets = Secrets::new()?;
+		for (owner, kind, value) in references {
+			*value = serde_json::from_slice(secrets.retrieve(owner, kind)?.expose())?;
+		}
+
+		Ok(())
+	}
+}
+
+/// The key of the reference left in the config file to a secret in the keychain, holding the id
+/// the secret is stored under
+const KEYCHAIN_REFERENCE: &str = "keychain";
+
+/// The secrets of a config, with the id they're stored under in the keychain if it's known. The
+/// secrets of the library are stored under its id, and the credentials of a backup target under
+/// the id of the target.
+fn secret_fields(
+	library_id: Option<Uuid>,
+	config: &mut Map<String, Value>,
+) -> Vec<(Option<Uuid>, SecretKind, &mut Value)> {
+	let mut fields = Vec::new();
+
+	for (key, value) in config.iter_mut() {
+		match key.as_str() {
+			"backup_key" => fields.push((library_id, SecretKind::LibraryBackupKey, value)),
+			"sync_key" => fields.push((library_id, SecretKind::LibrarySyncKey, value)),
+			"backup_targets" => {
+				for target in value
+					.as_array_mut()
+					.into_iter()
+					.flatten()
+					.filter_map(Value::as_object_mut)
+				{
+					let target_id = target
+						.get("id")
+						.and_then(Value::as_str)
+						.and_then(|id| Uuid::parse_str(id).ok());
+
+					let Some(Value::Object(kind)) = target.get_mut("kind") else {
+						continue;
+					};
+
+					for (field, value) in kind.iter_mut() {
+						let kind = match field.as_str() {
+							"accessKeyId" => SecretKind::BackupTargetAccessKeyId,
+							"secretAccessKey" => SecretKind::BackupTargetSecretAccessKey,
+							"password" => SecretKind::BackupTargetPassword,
+							_ => continue,
+						};
+
+						fields.push((target_id, kind, value));
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fields
+}
+
+/// The id a secret is stored under in the keychain, if `value` is a reference to it
+fn keychain_reference(value: &Value) -> Option<Uuid> {
+	let reference = value.as_object().filter(|reference| reference.len() == 1)?;
+
+	Uuid::parse_str(reference.get(KEYCHAIN_REFERENCE)?.as_str()?).ok()
 }
 
 // used to return to the frontend with uuid context
@@ -200,3 +469,109 @@ pub struct LibraryConfigWrapped {
 	pub uuid: Uuid,
 	pub config: SanitisedLibraryConfig,
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::panic)]
+mod tests {
+	use super::*;
+
+	use crate::{prisma, util::db::db_url};
+
+	use sd_p2p::Keypair;
+
+	#[tokio::test]
+	async fn migrates_from_version_13() {
+		let dir = tempfile::tempdir().unwrap();
+		let config_path = dir.path().join("library.sdlibrary");
+		let node_id = Uuid::new_v4();
+
+		std::fs::write(
+			&config_path,
+			json!({
+				"version": 13,
+				"name": "Library",
+				"description": null,
+				"identity": Identity::new().to_bytes().to_vec(),
+				"node_id": node_id,
+				"settings": {
+					"thumbnail_format": ThumbnailFormat::default(),
+					"thumbnail_quality": DEFAULT_THUMBNAIL_QUALITY,
+					"identifier_chunk_size": 100,
+					"max_concurrent_jobs": 2,
+					"quiet_hours": null,
+					"preview_media_max_size_mb": null,
+				},
+				"sync_conflict_policy": ConflictPolicy::default(),
+				"backup_targets": [],
+				"backup_key": null,
+				"sync_key": OperationCipher::generate_key(),
+				"encryption": null,
+			})
+			.to_string(),
+		)
+		.unwrap();
+
+		let db = prisma::new_client_with_url(&db_url(&dir.path().join("library.db")).unwrap())
+			.await
+			.unwrap();
+
+		let config = LibraryConfig::load_and_migrate(
+			&config_path,
+			&(node_id, Keypair::generate().peer_id(), Arc::new(db)),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(config.cloned_from, None);
+		assert_eq!(config.settings.inbox_location_id, None);
+		assert_eq!(config.settings.max_concurrent_jobs, 2);
+
+		let migrated: Value =
+			serde_json::from_slice(&std::fs::read(&config_path).unwrap()).unwrap();
+		assert_eq!(migrated["version"], json!(LibraryConfig::CURRENT_VERSION));
+	}
+
+	#[test]
+	fn secrets_are_found_with_their_owner() {
+		let library_id = Uuid::new_v4();
+		let target_id = Uuid::new_v4();
+		let mut config = json!({
+			"name": "Library",
+			"sync_key": [1, 2, 3],
+			"backup_key": null,
+			"backup_targets": [{
+				"id": target_id,
+				"kind": {
+					"type": "s3",
+					"bucket": "bucket",
+					"accessKeyId": "id",
+					"secretAccessKey": { KEYCHAIN_REFERENCE: target_id },
+				},
+			}],
+		});
+
+		let fields = secret_fields(Some(library_id), config.as_object_mut().unwrap())
+			.into_iter()
+			.map(|(owner, kind, value)| (owner, kind, keychain_reference(value)))
+			.collect::<Vec<_>>();
+
+		assert_eq!(fields.len(), 4);
+		for (owner, kind, reference) in fields {
+			match kind {
+				SecretKind::LibraryBackupKey | SecretKind::LibrarySyncKey => {
+					assert_eq!(owner, Some(library_id));
+					assert_eq!(reference, None);
+				}
+				SecretKind::BackupTargetAccessKeyId => {
+					assert_eq!(owner, Some(target_id));
+					assert_eq!(reference, None);
+				}
+				SecretKind::BackupTargetSecretAccessKey => {
+					assert_eq!(owner, Some(target_id));
+					assert_eq!(reference, Some(target_id));
+				}
+				kind => panic!("{kind:?} isn't in the config"),
+			}
+		}
+	}
+}
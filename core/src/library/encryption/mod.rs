@@ -0,0 +1,176 @@
+//! Libraries whose database is encrypted on disk.
+//!
+//! The database of an encrypted library is encrypted by SQLite itself as it reads and writes it,
+//! through the [`vfs`]. It's encrypted with a random key, held in a keyslot unlocked by the
+//! library password, and optionally remembered in the OS keychain so the library unlocks on
+//! startup.
+
+use crate::util::error::FileIOError;
+
+use std::{fmt, io::Cursor, path::Path};
+
+use sd_crypto::{
+	header::keyslot::Keyslot,
+	keys::secrets::{SecretKind, Secrets},
+	primitives::LATEST_KEYSLOT,
+	types::{Key, Salt},
+	Protected,
+};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::fs;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::backup::{ALGORITHM, HASHING_ALGORITHM};
+
+pub mod vfs;
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+	#[error("wrong library password")]
+	WrongPassword,
+	#[error(transparent)]
+	Vfs(#[from] vfs::RegisterVfsError),
+	#[error(transparent)]
+	Crypto(#[from] sd_crypto::Error),
+	#[error("error reading the library config: {0}")]
+	Json(#[from] serde_json::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<EncryptionError> for rspc::Error {
+	fn from(e: EncryptionError) -> Self {
+		let code = match e {
+			EncryptionError::WrongPassword => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Stored in the library config: the key of the database in a keyslot unlocked by the password
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryEncryption {
+	keyslot: Vec<u8>,
+}
+
+impl fmt::Debug for LibraryEncryption {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("LibraryEncryption").finish_non_exhaustive()
+	}
+}
+
+impl LibraryEncryption {
+	/// A new database key, with the keyslot unlocking it with `password`
+	pub async fn new(password: Protected<String>) -> Result<(Self, Key), EncryptionError> {
+		let key = Key::generate();
+		let content_salt = Salt::generate();
+		let hashed_password = HASHING_ALGORITHM.hash(
+			Protected::new(password.expose().as_bytes().to_vec()),
+			content_salt,
+			None,
+		)?;
+
+		let keyslot = Keyslot::new(
+			LATEST_KEYSLOT,
+			ALGORITHM,
+			HASHING_ALGORITHM,
+			content_salt,
+			hashed_password,
+			key.clone(),
+		)
+		.await?;
+
+		Ok((
+			Self {
+				keyslot: keyslot.to_bytes(),
+			},
+			key,
+		))
+	}
+
+	/// The database key, if `password` is the password of the library
+	pub async fn unlock(&self, password: Protected<String>) -> Result<Key, EncryptionError> {
+		Keyslot::from_reader(&mut Cursor::new(&self.keyslot))?
+			.decrypt_master_key(Protected::new(password.expose().as_bytes().to_vec()))
+			.await
+			.map_err(|_| EncryptionError::WrongPassword)
+	}
+}
+
+/// The part of a library config read before the library is loaded, to know if it's locked
+#[derive(Deserialize)]
+pub(super) struct EncryptedConfig {
+	pub name: String,
+	#[serde(default)]
+	pub encryption: Option<LibraryEncryption>,
+}
+
+impl EncryptedConfig {
+	pub async fn read(config_path: &Path) -> Result<Self, EncryptionError> {
+		let bytes = fs::read(config_path)
+			.await
+			.map_err(|e| FileIOError::from((config_path, e)))?;
+
+		Ok(serde_json::from_slice(&bytes)?)
+	}
+}
+
+/// Remembers the key of the library in the OS keychain, so it's unlocked without its password
+pub(super) fn remember_key(library_id: Uuid, key: &Key) -> Result<(), EncryptionError> {
+	Ok(Secrets::new()?.insert(
+		library_id,
+		SecretKind::LibraryDatabaseKey,
+		&Protected::new(key.expose().to_vec()),
+	)?)
+}
+
+/// The key of the library in the OS keychain, if it was remembered
+pub(super) fn recall_key(library_id: Uuid) -> Option<Key> {
+	Secrets::new()
+		.and_then(|secrets| secrets.retrieve(library_id, SecretKind::LibraryDatabaseKey))
+		.and_then(Key::try_from)
+		.ok()
+}
+
+pub(super) fn forget_key(library_id: Uuid) {
+	if let Err(e) = Secrets::new()
+		.and_then(|secrets| secrets.delete(library_id, SecretKind::LibraryDatabaseKey))
+	{
+		warn!("Failed to remove the key of library '{library_id}' from the keychain: {e}");
+	}
+}
+
+/// A library that can't be loaded until it's unlocked with its password
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct LockedLibrary {
+	pub uuid: Uuid,
+	pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn keyslots_unlock_with_the_password() {
+		let (encryption, key) = LibraryEncryption::new(Protected::new("password".to_string()))
+			.await
+			.unwrap();
+
+		assert!(matches!(
+			encryption.unlock(Protected::new("wrong".to_string())).await,
+			Err(EncryptionError::WrongPassword)
+		));
+
+		let unlocked = encryption
+			.unlock(Protected::new("password".to_string()))
+			.await
+			.unwrap();
+		assert_eq!(unlocked.expose(), key.expose());
+	}
+}
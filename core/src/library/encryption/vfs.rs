@@ -0,0 +1,797 @@
+//! A SQLite VFS encrypting the files of the encrypted library databases as SQLite writes them, so
+//! they're never on disk in plain text, even while the library is open.
+//!
+//! The VFS wraps the default one of the platform and becomes the default, which Prisma opens its
+//! databases with. Every 16 bytes of a file are encrypted on their own with AES-256 in XEX mode,
+//! with their position as the tweak, like disk encryption does: SQLite reads and writes at any
+//! offset, and files keep their size. The last bytes of a file whose size isn't a multiple of 16
+//! bytes are encrypted with a keystream instead, and again as a block once the file grows past
+//! them. The files of a database are found by their name, which starts with the id of the
+//! library, so the copies made while migrating it are encrypted too. Temporary files are encrypted
+//! with a key that only lives in memory.
+
+use std::{
+	collections::HashMap,
+	ffi::{c_char, c_int, c_void, CStr},
+	mem,
+	path::{Path, PathBuf},
+	ptr, slice,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, PoisonError, RwLock,
+	},
+};
+
+use aes::{
+	cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+	Aes256, Block,
+};
+use libsqlite3_sys::{
+	sqlite3_file, sqlite3_int64, sqlite3_io_methods, sqlite3_vfs, sqlite3_vfs_find,
+	sqlite3_vfs_register, SQLITE_ERROR, SQLITE_FCNTL_CHUNK_SIZE, SQLITE_FCNTL_SIZE_HINT,
+	SQLITE_IOCAP_ATOMIC, SQLITE_IOCAP_ATOMIC16K, SQLITE_IOCAP_ATOMIC1K, SQLITE_IOCAP_ATOMIC2K,
+	SQLITE_IOCAP_ATOMIC32K, SQLITE_IOCAP_ATOMIC4K, SQLITE_IOCAP_ATOMIC512, SQLITE_IOCAP_ATOMIC64K,
+	SQLITE_IOCAP_ATOMIC8K, SQLITE_IOCAP_BATCH_ATOMIC, SQLITE_IOCAP_POWERSAFE_OVERWRITE,
+	SQLITE_IOCAP_SAFE_APPEND, SQLITE_IOERR_SHORT_READ, SQLITE_OK, SQLITE_OPEN_MAIN_DB,
+	SQLITE_OPEN_MAIN_JOURNAL, SQLITE_OPEN_SUBJOURNAL, SQLITE_OPEN_TEMP_DB,
+	SQLITE_OPEN_TEMP_JOURNAL, SQLITE_OPEN_TRANSIENT_DB, SQLITE_OPEN_WAL,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use sd_crypto::types::Key;
+
+const BLOCK_LEN: u64 = 16;
+const VFS_NAME: &[u8] = b"spacedrive-encrypted\0";
+
+/// The files of a database are encrypted with different tweaks, a page is in several of them
+const MAIN_DB_NONCE: u64 = 0;
+const JOURNAL_NONCE: u64 = 1;
+const WAL_NONCE: u64 = 2;
+/// Set on the nonces of the keystreams, so they're never the tweak of a block
+const KEYSTREAM_NONCE: u64 = 1 << 63;
+
+/// The ciphers of the unlocked databases, by their directory and library id
+static CIPHERS: Lazy<RwLock<HashMap<PathBuf, Arc<FileCipher>>>> = Lazy::new(Default::default);
+
+/// The temporary files of all databases share this key, each with its own nonce
+static TEMPORARY_CIPHER: Lazy<Arc<FileCipher>> =
+	Lazy::new(|| Arc::new(FileCipher::new(&Key::generate())));
+static NEXT_TEMPORARY_NONCE: AtomicU64 = AtomicU64::new(WAL_NONCE + 1);
+
+/// The default VFS of the platform, which reads and writes the files
+struct DefaultVfs(*mut sqlite3_vfs);
+
+// SAFETY: the default VFS is static and used by SQLite from any thread
+unsafe impl Send for DefaultVfs {}
+unsafe impl Sync for DefaultVfs {}
+
+static DEFAULT_VFS: OnceCell<DefaultVfs> = OnceCell::new();
+
+#[derive(Debug, thiserror::Error)]
+#[error("failed to register the encrypted SQLite VFS, error code {0}")]
+pub struct RegisterVfsError(c_int);
+
+/// Makes the encrypting VFS the default one. It has to be registered before the databases of the
+/// encrypted libraries are opened, databases whose key isn't known are read and written as is.
+pub fn register() -> Result<(), RegisterVfsError> {
+	DEFAULT_VFS
+		.get_or_try_init(|| {
+			// SAFETY: SQLite keeps the registered VFS until it's unregistered, which it never is
+			unsafe {
+				let default = sqlite3_vfs_find(ptr::null());
+				if default.is_null() {
+					return Err(RegisterVfsError(SQLITE_ERROR));
+				}
+
+				let vfs = Box::leak(Box::new(sqlite3_vfs {
+					szOsFile: (mem::size_of::<EncryptedFile>() as c_int) + (*default).szOsFile,
+					pNext: ptr::null_mut(),
+					zName: VFS_NAME.as_ptr().cast(),
+					xOpen: Some(open),
+					..*default
+				}));
+
+				match sqlite3_vfs_register(vfs, 1) {
+					SQLITE_OK => Ok(DefaultVfs(default)),
+					rc => Err(RegisterVfsError(rc)),
+				}
+			}
+		})
+		.map(|_| ())
+}
+
+/// Encrypts the database at `db_path` with `key` from now on, along with its write-ahead log and
+/// the copies of it made next to it
+pub fn unlock(db_path: &Path, key: &Key) {
+	if let Some(id) = database_id(db_path) {
+		CIPHERS
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(id, Arc::new(FileCipher::new(key)));
+	}
+}
+
+/// Encrypts the database at `db_path` like the one at `source_path`, if it's encrypted
+pub fn unlock_like(db_path: &Path, source_path: &Path) {
+	let (Some(id), Some(source_id)) = (database_id(db_path), database_id(source_path)) else {
+		return;
+	};
+
+	let mut ciphers = CIPHERS.write().unwrap_or_else(PoisonError::into_inner);
+	if let Some(cipher) = ciphers.get(&source_id).cloned() {
+		ciphers.insert(id, cipher);
+	}
+}
+
+/// Forgets the key of the database at `db_path`, the connections open to it keep it
+pub fn lock(db_path: &Path) {
+	if let Some(id) = database_id(db_path) {
+		CIPHERS
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&id);
+	}
+}
+
+/// The directory of a database file with the id of its library, `{id}.db-wal` being a file of the
+/// `{id}` database for instance. The directory is canonical, as SQLite resolves the path it opens.
+fn database_id(path: &Path) -> Option<PathBuf> {
+	let id = path.file_name()?.to_str()?.split('.').next()?;
+
+	Some(path.parent()?.canonicalize().ok()?.join(id))
+}
+
+/// The cipher and nonce of the file SQLite opens with `flags`, if it's encrypted
+fn file_cipher(name: *const c_char, flags: c_int) -> Option<(Arc<FileCipher>, u64)> {
+	if flags
+		& (SQLITE_OPEN_TEMP_DB
+			| SQLITE_OPEN_TEMP_JOURNAL
+			| SQLITE_OPEN_SUBJOURNAL
+			| SQLITE_OPEN_TRANSIENT_DB)
+		!= 0
+	{
+		return Some((
+			TEMPORARY_CIPHER.clone(),
+			NEXT_TEMPORARY_NONCE.fetch_add(1, Ordering::Relaxed),
+		));
+	}
+
+	let nonce = if flags & SQLITE_OPEN_MAIN_DB != 0 {
+		MAIN_DB_NONCE
+	} else if flags & SQLITE_OPEN_MAIN_JOURNAL != 0 {
+		JOURNAL_NONCE
+	} else if flags & SQLITE_OPEN_WAL != 0 {
+		WAL_NONCE
+	} else {
+		// Super-journals only have the names of the journals in them
+		return None;
+	};
+
+	if name.is_null() {
+		return None;
+	}
+
+	// SAFETY: SQLite gives the name as a nul terminated string
+	let name = unsafe { CStr::from_ptr(name) }.to_str().ok()?;
+	let id = database_id(Path::new(name))?;
+
+	CIPHERS
+		.read()
+		.unwrap_or_else(PoisonError::into_inner)
+		.get(&id)
+		.map(|cipher| (cipher.clone(), nonce))
+}
+
+/// Encrypts the 16 bytes blocks of a file with AES-256 in XEX mode, their index being the tweak
+struct FileCipher {
+	data: Aes256,
+	tweak: Aes256,
+}
+
+impl FileCipher {
+	fn new(key: &Key) -> Self {
+		let data = blake3::derive_key(
+			"spacedrive 2023-08-01 library database data key",
+			key.expose(),
+		);
+		let tweak = blake3::derive_key(
+			"spacedrive 2023-08-01 library database tweak key",
+			key.expose(),
+		);
+
+		Self {
+			data: Aes256::new(&data.into()),
+			tweak: Aes256::new(&tweak.into()),
+		}
+	}
+
+	fn tweak(&self, nonce: u64, index: u64) -> Block {
+		let mut tweak = [0; BLOCK_LEN as usize];
+		tweak[..8].copy_from_slice(&nonce.to_le_bytes());
+		tweak[8..].copy_from_slice(&index.to_le_bytes());
+
+		let mut tweak = GenericArray::from(tweak);
+		self.tweak.encrypt_block(&mut tweak);
+
+		tweak
+	}
+
+	/// Encrypts `data`, which is at `offset` in the file and ends where the file does if its last
+	/// block isn't whole. The offset is the one of a block.
+	fn encrypt(&self, nonce: u64, offset: u64, data: &mut [u8]) {
+		for (i, block) in data.chunks_mut(BLOCK_LEN as usize).enumerate() {
+			let index = offset / BLOCK_LEN + i as u64;
+
+			if block.len() == BLOCK_LEN as usize {
+				let tweak = self.tweak(nonce, index);
+				let block = GenericArray::from_mut_slice(block);
+				xor(block, &tweak);
+				self.data.encrypt_block(block);
+				xor(block, &tweak);
+			} else {
+				xor(block, &self.tweak(nonce | KEYSTREAM_NONCE, index));
+			}
+		}
+	}
+
+	/// Decrypts `data`, like [`FileCipher::encrypt`] encrypted it
+	fn decrypt(&self, nonce: u64, offset: u64, data: &mut [u8]) {
+		for (i, block) in data.chunks_mut(BLOCK_LEN as usize).enumerate() {
+			let index = offset / BLOCK_LEN + i as u64;
+
+			if block.len() == BLOCK_LEN as usize {
+				let tweak = self.tweak(nonce, index);
+				let block = GenericArray::from_mut_slice(block);
+				xor(block, &tweak);
+				self.data.decrypt_block(block);
+				xor(block, &tweak);
+			} else {
+				xor(block, &self.tweak(nonce | KEYSTREAM_NONCE, index));
+			}
+		}
+	}
+}
+
+fn xor(data: &mut [u8], with: &[u8]) {
+	data.iter_mut()
+		.zip(with)
+		.for_each(|(byte, with)| *byte ^= with);
+}
+
+/// Rounds `offset` down to the start of its block
+const fn block_start(offset: u64) -> u64 {
+	offset - offset % BLOCK_LEN
+}
+
+/// Rounds `offset` up to the end of its block
+const fn block_end(offset: u64) -> u64 {
+	block_start(offset + BLOCK_LEN - 1)
+}
+
+/// A file opened by the VFS, the file of the default VFS is right after it
+#[repr(C)]
+struct EncryptedFile {
+	base: sqlite3_file,
+	inner: *mut sqlite3_file,
+	cipher: Option<(Arc<FileCipher>, u64)>,
+}
+
+/// Version 2 of the methods, as memory mapping the files would read them as they're on disk
+static METHODS: sqlite3_io_methods = sqlite3_io_methods {
+	iVersion: 2,
+	xClose: Some(close),
+	xRead: Some(read),
+	xWrite: Some(write),
+	xTruncate: Some(truncate),
+	xSync: Some(sync),
+	xFileSize: Some(file_size),
+	xLock: Some(lock_file),
+	xUnlock: Some(unlock_file),
+	xCheckReservedLock: Some(check_reserved_lock),
+	xFileControl: Some(file_control),
+	xSectorSize: Some(sector_size),
+	xDeviceCharacteristics: Some(device_characteristics),
+	xShmMap: Some(shm_map),
+	xShmLock: Some(shm_lock),
+	xShmBarrier: Some(shm_barrier),
+	xShmUnmap: Some(shm_unmap),
+	xFetch: None,
+	xUnfetch: None,
+};
+
+/// Calls a method of the file of the default VFS
+macro_rules! inner {
+	($file:expr, $method:ident $(, $arg:expr)*) => {{
+		let inner = (*($file as *mut EncryptedFile)).inner;
+		match (*(*inner).pMethods).$method {
+			Some(method) => method(inner $(, $arg)*),
+			None => SQLITE_ERROR,
+		}
+	}};
+}
+
+unsafe extern "C" fn open(
+	_vfs: *mut sqlite3_vfs,
+	name: *const c_char,
+	file: *mut sqlite3_file,
+	flags: c_int,
+	out_flags: *mut c_int,
+) -> c_int {
+	let Some(DefaultVfs(default)) = DEFAULT_VFS.get() else {
+		return SQLITE_ERROR;
+	};
+	let Some(default_open) = (**default).xOpen else {
+		return SQLITE_ERROR;
+	};
+
+	let file = file.cast::<EncryptedFile>();
+	let inner = file.add(1).cast::<sqlite3_file>();
+
+	// SQLite doesn't close a file whose methods aren't set
+	(*file).base.pMethods = ptr::null();
+
+	let rc = default_open(*default, name, inner, flags, out_flags);
+	if rc != SQLITE_OK {
+		return rc;
+	}
+
+	ptr::write(
+		file,
+		EncryptedFile {
+			base: sqlite3_file { pMethods: &METHODS },
+			inner,
+			cipher: file_cipher(name, flags),
+		},
+	);
+
+	SQLITE_OK
+}
+
+unsafe extern "C" fn close(file: *mut sqlite3_file) -> c_int {
+	let rc = inner!(file, xClose);
+	ptr::drop_in_place(&mut (*file.cast::<EncryptedFile>()).cipher);
+
+	rc
+}
+
+unsafe fn size_of_file(file: *mut sqlite3_file) -> Result<u64, c_int> {
+	let mut size: sqlite3_int64 = 0;
+	match inner!(file, xFileSize, &mut size) {
+		SQLITE_OK => Ok(size as u64),
+		rc => Err(rc),
+	}
+}
+
+unsafe extern "C" fn read(
+	file: *mut sqlite3_file,
+	buf: *mut c_void,
+	amount: c_int,
+	offset: sqlite3_int64,
+) -> c_int {
+	let Some((cipher, nonce)) = &(*file.cast::<EncryptedFile>()).cipher else {
+		return inner!(file, xRead, buf, amount, offset);
+	};
+
+	let (offset, amount) = (offset as u64, amount as usize);
+	let start = block_start(offset);
+	let end = block_end(offset + amount as u64);
+
+	let mut data = vec![0; (end - start) as usize];
+	let rc = inner!(
+		file,
+		xRead,
+		data.as_mut_ptr().cast(),
+		data.len() as c_int,
+		start as sqlite3_int64
+	);
+
+	match rc {
+		SQLITE_OK => cipher.decrypt(*nonce, start, &mut data),
+		// The bytes past the end of the file are read as zeros
+		SQLITE_IOERR_SHORT_READ => {
+			let size = match size_of_file(file) {
+				Ok(size) => size,
+				Err(rc) => return rc,
+			};
+			let read = (size.saturating_sub(start) as usize).min(data.len());
+
+			cipher.decrypt(*nonce, start, &mut data[..read]);
+			data[read..].fill(0);
+		}
+		rc => return rc,
+	}
+
+	slice::from_raw_parts_mut(buf.cast::<u8>(), amount)
+		.copy_from_slice(&data[(offset - start) as usize..][..amount]);
+
+	rc
+}
+
+/// Writes `data` at `offset` in a file of `size` bytes, which is at least `offset`
+unsafe fn write_encrypted(
+	file: *mut sqlite3_file,
+	cipher: &FileCipher,
+	nonce: u64,
+	data: &[u8],
+	offset: u64,
+	size: u64,
+) -> c_int {
+	let start = block_start(offset);
+	let new_size = size.max(offset + data.len() as u64);
+	let end = block_end(offset + data.len() as u64).min(new_size);
+
+	let mut blocks = vec![0; (end - start) as usize];
+
+	// The blocks the data is only part of are read to keep the rest of them
+	if start < offset || offset + (data.len() as u64) < end {
+		let existing = &mut blocks[..(end.min(size) - start) as usize];
+
+		let rc = inner!(
+			file,
+			xRead,
+			existing.as_mut_ptr().cast(),
+			existing.len() as c_int,
+			start as sqlite3_int64
+		);
+		if rc != SQLITE_OK {
+			return rc;
+		}
+
+		cipher.decrypt(nonce, start, existing);
+	}
+
+	blocks[(offset - start) as usize..][..data.len()].copy_from_slice(data);
+	cipher.encrypt(nonce, start, &mut blocks);
+
+	inner!(
+		file,
+		xWrite,
+		blocks.as_ptr().cast(),
+		blocks.len() as c_int,
+		start as sqlite3_int64
+	)
+}
+
+unsafe extern "C" fn write(
+	file: *mut sqlite3_file,
+	buf: *const c_void,
+	amount: c_int,
+	offset: sqlite3_int64,
+) -> c_int {
+	let Some((cipher, nonce)) = &(*file.cast::<EncryptedFile>()).cipher else {
+		return inner!(file, xWrite, buf, amount, offset);
+	};
+
+	let offset = offset as u64;
+	let data = slice::from_raw_parts(buf.cast::<u8>(), amount as usize);
+
+	let mut size = match size_of_file(file) {
+		Ok(size) => size,
+		Err(rc) => return rc,
+	};
+
+	// A gap left before the data reads as zeros, as it would in a plain file
+	if offset > size {
+		let rc = write_encrypted(
+			file,
+			cipher,
+			*nonce,
+			&vec![0; (offset - size) as usize],
+			size,
+			size,
+		);
+		if rc != SQLITE_OK {
+			return rc;
+		}
+		size = offset;
+	}
+
+	write_encrypted(file, cipher, *nonce, data, offset, size)
+}
+
+unsafe extern "C" fn truncate(file: *mut sqlite3_file, new_size: sqlite3_int64) -> c_int {
+	let Some((cipher, nonce)) = &(*file.cast::<EncryptedFile>()).cipher else {
+		return inner!(file, xTruncate, new_size);
+	};
+
+	let new_size = new_size as u64;
+	let size = match size_of_file(file) {
+		Ok(size) => size,
+		Err(rc) => return rc,
+	};
+
+	if new_size > size {
+		return write_encrypted(
+			file,
+			cipher,
+			*nonce,
+			&vec![0; (new_size - size) as usize],
+			size,
+			size,
+		);
+	}
+
+	// The block the file is cut in becomes its last bytes, which are encrypted differently
+	let start = block_start(new_size);
+	let mut last = vec![0; (size.min(start + BLOCK_LEN) - start) as usize];
+	if start < new_size {
+		let rc = inner!(
+			file,
+			xRead,
+			last.as_mut_ptr().cast(),
+			last.len() as c_int,
+			start as sqlite3_int64
+		);
+		if rc != SQLITE_OK {
+			return rc;
+		}
+
+		cipher.decrypt(*nonce, start, &mut last);
+	}
+
+	let rc = inner!(file, xTruncate, new_size as sqlite3_int64);
+	if rc != SQLITE_OK || start == new_size {
+		return rc;
+	}
+
+	let last = &mut last[..(new_size - start) as usize];
+	cipher.encrypt(*nonce, start, last);
+
+	inner!(
+		file,
+		xWrite,
+		last.as_ptr().cast(),
+		last.len() as c_int,
+		start as sqlite3_int64
+	)
+}
+
+unsafe extern "C" fn sync(file: *mut sqlite3_file, flags: c_int) -> c_int {
+	inner!(file, xSync, flags)
+}
+
+unsafe extern "C" fn file_size(file: *mut sqlite3_file, size: *mut sqlite3_int64) -> c_int {
+	inner!(file, xFileSize, size)
+}
+
+unsafe extern "C" fn lock_file(file: *mut sqlite3_file, lock: c_int) -> c_int {
+	inner!(file, xLock, lock)
+}
+
+unsafe extern "C" fn unlock_file(file: *mut sqlite3_file, lock: c_int) -> c_int {
+	inner!(file, xUnlock, lock)
+}
+
+unsafe extern "C" fn check_reserved_lock(file: *mut sqlite3_file, out: *mut c_int) -> c_int {
+	inner!(file, xCheckReservedLock, out)
+}
+
+unsafe extern "C" fn file_control(file: *mut sqlite3_file, op: c_int, arg: *mut c_void) -> c_int {
+	// Growing an encrypted file ahead of the writes would leave blocks that don't decrypt to zeros.
+	// Both are hints, that SQLite does without.
+	if (*file.cast::<EncryptedFile>()).cipher.is_some()
+		&& (op == SQLITE_FCNTL_SIZE_HINT || op == SQLITE_FCNTL_CHUNK_SIZE)
+	{
+		return SQLITE_OK;
+	}
+
+	inner!(file, xFileControl, op, arg)
+}
+
+unsafe extern "C" fn sector_size(file: *mut sqlite3_file) -> c_int {
+	let inner = (*file.cast::<EncryptedFile>()).inner;
+	match (*(*inner).pMethods).xSectorSize {
+		Some(method) => method(inner),
+		None => 0,
+	}
+}
+
+unsafe extern "C" fn device_characteristics(file: *mut sqlite3_file) -> c_int {
+	let inner = (*file.cast::<EncryptedFile>()).inner;
+	let characteristics = match (*(*inner).pMethods).xDeviceCharacteristics {
+		Some(method) => method(inner),
+		None => 0,
+	};
+
+	if (*file.cast::<EncryptedFile>()).cipher.is_none() {
+		return characteristics;
+	}
+
+	// Writes rewrite the whole blocks around them, and the end of the file when it grows
+	characteristics
+		& !(SQLITE_IOCAP_ATOMIC
+			| SQLITE_IOCAP_ATOMIC512
+			| SQLITE_IOCAP_ATOMIC1K
+			| SQLITE_IOCAP_ATOMIC2K
+			| SQLITE_IOCAP_ATOMIC4K
+			| SQLITE_IOCAP_ATOMIC8K
+			| SQLITE_IOCAP_ATOMIC16K
+			| SQLITE_IOCAP_ATOMIC32K
+			| SQLITE_IOCAP_ATOMIC64K
+			| SQLITE_IOCAP_SAFE_APPEND
+			| SQLITE_IOCAP_POWERSAFE_OVERWRITE
+			| SQLITE_IOCAP_BATCH_ATOMIC)
+}
+
+unsafe extern "C" fn shm_map(
+	file: *mut sqlite3_file,
+	region: c_int,
+	size: c_int,
+	extend: c_int,
+	out: *mut *mut c_void,
+) -> c_int {
+	inner!(file, xShmMap, region, size, extend, out)
+}
+
+unsafe extern "C" fn shm_lock(
+	file: *mut sqlite3_file,
+	offset: c_int,
+	n: c_int,
+	flags: c_int,
+) -> c_int {
+	inner!(file, xShmLock, offset, n, flags)
+}
+
+unsafe extern "C" fn shm_barrier(file: *mut sqlite3_file) {
+	let inner = (*file.cast::<EncryptedFile>()).inner;
+	if let Some(method) = (*(*inner).pMethods).xShmBarrier {
+		method(inner);
+	}
+}
+
+unsafe extern "C" fn shm_unmap(file: *mut sqlite3_file, delete: c_int) -> c_int {
+	inner!(file, xShmUnmap, delete)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::{ffi::CString, fs};
+
+	use libsqlite3_sys::{
+		sqlite3, sqlite3_close, sqlite3_column_text, sqlite3_exec, sqlite3_finalize,
+		sqlite3_open_v2, sqlite3_prepare_v2, sqlite3_step, SQLITE_OPEN_CREATE,
+		SQLITE_OPEN_READWRITE, SQLITE_ROW,
+	};
+
+	const SECRET: &str = "a file name only the library can read";
+
+	unsafe fn open_db(path: &Path) -> *mut sqlite3 {
+		let path = CString::new(path.to_str().unwrap()).unwrap();
+		let mut db = ptr::null_mut();
+		assert_eq!(
+			sqlite3_open_v2(
+				path.as_ptr(),
+				&mut db,
+				SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+				ptr::null()
+			),
+			SQLITE_OK
+		);
+
+		db
+	}
+
+	unsafe fn exec(db: *mut sqlite3, sql: &str) {
+		let sql = CString::new(sql).unwrap();
+		assert_eq!(
+			sqlite3_exec(db, sql.as_ptr(), None, ptr::null_mut(), ptr::null_mut()),
+			SQLITE_OK
+		);
+	}
+
+	unsafe fn names(db: *mut sqlite3) -> Vec<String> {
+		let sql = CString::new("SELECT name FROM file").unwrap();
+		let mut statement = ptr::null_mut();
+		assert_eq!(
+			sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut statement, ptr::null_mut()),
+			SQLITE_OK
+		);
+
+		let mut names = vec![];
+		while sqlite3_step(statement) == SQLITE_ROW {
+			names.push(
+				CStr::from_ptr(sqlite3_column_text(statement, 0).cast())
+					.to_string_lossy()
+					.to_string(),
+			);
+		}
+		sqlite3_finalize(statement);
+
+		names
+	}
+
+	fn contains(path: &Path, needle: &[u8]) -> bool {
+		fs::read(path)
+			.unwrap()
+			.windows(needle.len())
+			.any(|window| window == needle)
+	}
+
+	#[test]
+	fn databases_are_encrypted_while_open() {
+		register().unwrap();
+
+		let dir = tempfile::tempdir().unwrap();
+		let encrypted_path = dir.path().join("encrypted.db");
+		let plain_path = dir.path().join("plain.db");
+		unlock(&encrypted_path, &Key::generate());
+
+		unsafe {
+			for path in [&encrypted_path, &plain_path] {
+				let db = open_db(path);
+				exec(db, "PRAGMA journal_mode = WAL");
+				exec(db, "CREATE TABLE file (name TEXT)");
+				exec(db, &format!("INSERT INTO file VALUES ('{SECRET}')"));
+				exec(db, "PRAGMA wal_checkpoint(PASSIVE)");
+				exec(db, &format!("INSERT INTO file VALUES ('{SECRET} too')"));
+
+				let mut wal_path = path.as_os_str().to_owned();
+				wal_path.push("-wal");
+				let encrypted = path == &encrypted_path;
+				assert_eq!(contains(path, SECRET.as_bytes()), !encrypted);
+				assert_eq!(contains(Path::new(&wal_path), b"too"), !encrypted);
+				assert_eq!(contains(path, b"SQLite format 3"), !encrypted);
+
+				sqlite3_close(db);
+			}
+
+			let db = open_db(&encrypted_path);
+			assert_eq!(names(db), [SECRET.to_string(), format!("{SECRET} too")]);
+			sqlite3_close(db);
+		}
+
+		// Without the key, the database can't be read
+		lock(&encrypted_path);
+		unsafe {
+			let db = open_db(&encrypted_path);
+			let sql = CString::new("SELECT name FROM file").unwrap();
+			assert_ne!(
+				sqlite3_exec(db, sql.as_ptr(), None, ptr::null_mut(), ptr::null_mut()),
+				SQLITE_OK
+			);
+			sqlite3_close(db);
+		}
+	}
+
+	#[test]
+	fn blocks_decrypt_wherever_they_are_cut() {
+		let cipher = FileCipher::new(&Key::generate());
+		let plain = (0..100).map(|i| i as u8).collect::<Vec<_>>();
+
+		for len in [100, 96, 17, 15, 1] {
+			let mut data = plain[..len].to_vec();
+			cipher.encrypt(WAL_NONCE, 32, &mut data);
+			assert_ne!(data, plain[..len], "{len} bytes");
+
+			cipher.decrypt(WAL_NONCE, 32, &mut data);
+			assert_eq!(data, plain[..len], "{len} bytes");
+		}
+
+		// The same bytes are encrypted differently in different places and files
+		let mut first = plain[..16].to_vec();
+		let mut second = plain[..16].to_vec();
+		let mut third = plain[..16].to_vec();
+		cipher.encrypt(MAIN_DB_NONCE, 0, &mut first);
+		cipher.encrypt(MAIN_DB_NONCE, 16, &mut second);
+		cipher.encrypt(WAL_NONCE, 0, &mut third);
+		assert_ne!(first, second);
+		assert_ne!(first, third);
+	}
+
+	#[test]
+	fn database_files_share_an_id() {
+		let dir = tempfile::tempdir().unwrap();
+		let id = dir.path().canonicalize().unwrap().join("library");
+
+		for name in [
+			"library.db",
+			"library.db-wal",
+			"library.db-journal",
+			"library.db.migrating",
+		] {
+			assert_eq!(database_id(&dir.path().join(name)), Some(id.clone()));
+		}
+		assert_ne!(database_id(&dir.path().join("other.db")), Some(id));
+	}
+}
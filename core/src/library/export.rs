@@ -0,0 +1,594 @@
+//! Portable bundles of a library, to move it to another node without a backup target.
+//!
+//! A bundle is a single file holding a manifest describing the library, its config without the
+//! parts tied to the node it was exported from, a copy of its database and, optionally, the
+//! thumbnails of its files. The manifest goes first, so the locations of a bundle can be listed
+//! before importing it, to ask where they are on the new node.
+
+use crate::{
+	object::preview::{find_thumbnail, THUMBNAIL_CACHE_DIR_NAME},
+	prisma::{file_path, location},
+	util::error::{FileIOError, NonUtf8PathError},
+};
+
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+	backup::{copy_database, BackupError},
+	Library,
+};
+
+pub const EXPORT_EXTENSION: &str = "sdexport";
+
+const MAGIC: &[u8; 8] = b"SDEXPORT";
+const VERSION: u8 = 1;
+
+/// Config fields that only make sense on the node the library was exported from. Backup targets
+/// hold credentials, so they aren't carried along with the library. The database is exported
+/// decrypted, so the library isn't encrypted anymore.
+const NODE_CONFIG_FIELDS: [&str; 4] = ["node_id", "backup_targets", "backup_key", "encryption"];
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+	#[error("the file is corrupted or isn't a library export")]
+	InvalidExport,
+	#[error("library '{0}' already exists, delete it before importing it")]
+	LibraryExists(Uuid),
+	#[error("location '{0}' isn't in the library export")]
+	UnknownLocation(Uuid),
+	#[error("'{}' isn't a directory", .0.display())]
+	NotADirectory(PathBuf),
+	#[error("error copying the library database: {0}")]
+	Snapshot(#[from] BackupError),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error("error serializing or deserializing the library config: {0}")]
+	Json(#[from] serde_json::Error),
+	#[error("error reading or writing the library export: {0}")]
+	Io(io::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	NonUtf8Path(#[from] NonUtf8PathError),
+}
+
+impl From<ExportError> for rspc::Error {
+	fn from(e: ExportError) -> Self {
+		let code = match e {
+			ExportError::InvalidExport
+			| ExportError::LibraryExists(_)
+			| ExportError::UnknownLocation(_)
+			| ExportError::NotADirectory(_) => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// An export that ends early is corrupted, not unreadable
+impl From<io::Error> for ExportError {
+	fn from(e: io::Error) -> Self {
+		match e.kind() {
+			io::ErrorKind::UnexpectedEof => Self::InvalidExport,
+			_ => Self::Io(e),
+		}
+	}
+}
+
+/// What a library export holds, read without importing it
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+pub struct LibraryExport {
+	pub library_id: Uuid,
+	pub name: String,
+	pub description: Option<String>,
+	pub date_exported: DateTime<Utc>,
+	/// The locations of the library, with their path on the node it was exported from
+	pub locations: Vec<ExportedLocation>,
+	pub thumbnails: u32,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+pub struct ExportedLocation {
+	pub pub_id: Uuid,
+	pub name: Option<String>,
+	pub path: Option<String>,
+}
+
+/// The entries of an export, each prefixed by its kind and followed by its length and content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Entry {
+	Manifest = 0,
+	Config = 1,
+	Database = 2,
+	/// Prefixed by its path relative to the thumbnails directory
+	Thumbnail = 3,
+	End = u8::MAX,
+}
+
+impl TryFrom<u8> for Entry {
+	type Error = ExportError;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::Manifest,
+			1 => Self::Config,
+			2 => Self::Database,
+			3 => Self::Thumbnail,
+			u8::MAX => Self::End,
+			_ => return Err(ExportError::InvalidExport),
+		})
+	}
+}
+
+/// Writes an export of the library to `output`
+pub(crate) async fn export(
+	library: &Library,
+	output: &Path,
+	include_thumbnails: bool,
+) -> Result<LibraryExport, ExportError> {
+	let data_dir = library.config().data_directory();
+	let libraries_dir = data_dir.join("libraries");
+
+	let config_path = libraries_dir.join(format!("{}.sdlibrary", library.id));
+	let mut config = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(
+		&fs::read(&config_path)
+			.await
+			.map_err(|e| FileIOError::from((&config_path, e)))?,
+	)?;
+	for field in NODE_CONFIG_FIELDS {
+		config.remove(field);
+	}
+	// The identity and the sync key are in the OS keychain rather than in the config on most
+	// platforms
+	config.insert(
+		"identity".into(),
+		serde_json::to_value(library.identity.to_bytes())?,
+	);
+	config.insert(
+		"sync_key".into(),
+		serde_json::to_value(&library.config.sync_key)?,
+	);
+
+	let thumbnails = if include_thumbnails {
+		thumbnails(library, &data_dir.join(THUMBNAIL_CACHE_DIR_NAME)).await?
+	} else {
+		vec![]
+	};
+
+	let manifest = LibraryExport {
+		library_id: library.id,
+		name: library.config.name.clone(),
+		description: library.config.description.clone(),
+		date_exported: Utc::now(),
+		locations: library
+			.db
+			.location()
+			.find_many(vec![])
+			.select(location::select!({ pub_id name path }))
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|location| {
+				Some(ExportedLocation {
+					pub_id: Uuid::from_slice(&location.pub_id).ok()?,
+					name: location.name,
+					path: location.path,
+				})
+			})
+			.collect(),
+		thumbnails: thumbnails.len() as u32,
+	};
+
+	let db_copy_path = output.with_extension("db");
+	copy_database(&library.db, &db_copy_path).await?;
+
+	let res = write_export(output, &manifest, &config, &db_copy_path, &thumbnails).await;
+
+	fs::remove_file(&db_copy_path)
+		.await
+		.map_err(|e| FileIOError::from((&db_copy_path, e)))?;
+
+	res.map(|_| manifest)
+}
+
+/// The thumbnails of the files of the library, with their path relative to `thumbnails_dir`
+async fn thumbnails(
+	library: &Library,
+	thumbnails_dir: &Path,
+) -> Result<Vec<(String, PathBuf)>, ExportError> {
+	let mut cas_ids = library
+		.db
+		.file_path()
+		.find_many(vec![file_path::cas_id::not(None)])
+		.select(file_path::select!({ cas_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|file_path| file_path.cas_id)
+		.collect::<Vec<_>>();
+	cas_ids.sort_unstable();
+	cas_ids.dedup();
+
+	let mut thumbnails = Vec::with_capacity(cas_ids.len());
+	for cas_id in cas_ids {
+		let Some((path, _)) = find_thumbnail(thumbnails_dir, &cas_id).await? else {
+			continue;
+		};
+
+		let Some(name) = path
+			.strip_prefix(thumbnails_dir)
+			.ok()
+			.and_then(Path::to_str)
+			.map(|name| name.replace('\\', "/"))
+		else {
+			continue;
+		};
+
+		thumbnails.push((name, path));
+	}
+
+	Ok(thumbnails)
+}
+
+async fn write_export(
+	output: &Path,
+	manifest: &LibraryExport,
+	config: &serde_json::Map<String, serde_json::Value>,
+	db_path: &Path,
+	thumbnails: &[(String, PathBuf)],
+) -> Result<(), ExportError> {
+	let file = File::create(output)
+		.await
+		.map_err(|e| FileIOError::from((output, e)))?;
+	let mut writer = BufWriter::new(file);
+
+	writer.write_all(MAGIC).await?;
+	writer.write_u8(VERSION).await?;
+
+	write_bytes(&mut writer, Entry::Manifest, &serde_json::to_vec(manifest)?).await?;
+	write_bytes(&mut writer, Entry::Config, &serde_json::to_vec(config)?).await?;
+	write_file(&mut writer, Entry::Database, db_path).await?;
+
+	for (name, path) in thumbnails {
+		writer.write_u8(Entry::Thumbnail as u8).await?;
+		writer.write_u16_le(name.len() as u16).await?;
+		writer.write_all(name.as_bytes()).await?;
+
+		// Thumbnails can be removed by the thumbnail cache while exporting
+		let mut thumbnail = match File::open(path).await {
+			Ok(thumbnail) => thumbnail,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				warn!("Thumbnail '{name}' was removed while exporting the library");
+				writer.write_u64_le(0).await?;
+				continue;
+			}
+			Err(e) => return Err(FileIOError::from((path, e)).into()),
+		};
+
+		let len = thumbnail
+			.metadata()
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?
+			.len();
+		writer.write_u64_le(len).await?;
+		copy_exact(&mut thumbnail, &mut writer, len).await?;
+	}
+
+	writer.write_u8(Entry::End as u8).await?;
+	writer.flush().await?;
+
+	Ok(())
+}
+
+async fn write_bytes(
+	writer: &mut BufWriter<File>,
+	entry: Entry,
+	bytes: &[u8],
+) -> Result<(), ExportError> {
+	writer.write_u8(entry as u8).await?;
+	writer.write_u64_le(bytes.len() as u64).await?;
+	writer.write_all(bytes).await?;
+
+	Ok(())
+}
+
+async fn write_file(
+	writer: &mut BufWriter<File>,
+	entry: Entry,
+	path: &Path,
+) -> Result<(), ExportError> {
+	let mut file = File::open(path)
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?;
+	let len = file
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?
+		.len();
+
+	writer.write_u8(entry as u8).await?;
+	writer.write_u64_le(len).await?;
+	copy_exact(&mut file, writer, len).await
+}
+
+/// Copies exactly `len` bytes, failing if the reader ends before
+async fn copy_exact(
+	reader: &mut (impl AsyncReadExt + Unpin),
+	writer: &mut (impl AsyncWriteExt + Unpin),
+	len: u64,
+) -> Result<(), ExportError> {
+	let copied = io::copy(&mut reader.take(len), writer).await?;
+	if copied != len {
+		return Err(ExportError::InvalidExport);
+	}
+
+	Ok(())
+}
+
+async fn open_export(input: &Path) -> Result<BufReader<File>, ExportError> {
+	let mut reader = BufReader::new(
+		File::open(input)
+			.await
+			.map_err(|e| FileIOError::from((input, e)))?,
+	);
+
+	let mut magic = [0; MAGIC.len()];
+	reader.read_exact(&mut magic).await?;
+	if &magic != MAGIC || reader.read_u8().await? != VERSION {
+		return Err(ExportError::InvalidExport);
+	}
+
+	Ok(reader)
+}
+
+async fn read_bytes(
+	reader: &mut BufReader<File>,
+	entry: Entry,
+	max_len: u64,
+) -> Result<Vec<u8>, ExportError> {
+	if Entry::try_from(reader.read_u8().await?)? != entry {
+		return Err(ExportError::InvalidExport);
+	}
+
+	let len = reader.read_u64_le().await?;
+	if len > max_len {
+		return Err(ExportError::InvalidExport);
+	}
+
+	let mut bytes = vec![0; len as usize];
+	reader.read_exact(&mut bytes).await?;
+
+	Ok(bytes)
+}
+
+/// The manifest and config are small, anything bigger isn't an export
+const MAX_JSON_LEN: u64 = 64 * 1024 * 1024;
+
+/// Reads the manifest of the export at `input`, without importing it
+pub(crate) async fn inspect(input: &Path) -> Result<LibraryExport, ExportError> {
+	let mut reader = open_export(input).await?;
+
+	Ok(serde_json::from_slice(
+		&read_bytes(&mut reader, Entry::Manifest, MAX_JSON_LEN).await?,
+	)?)
+}
+
+/// Reads the export at `input`, writing the library database to `db_path` and the thumbnails to
+/// `thumbnails_dir`, and returning its manifest and config
+pub(super) async fn unpack(
+	input: &Path,
+	db_path: &Path,
+	thumbnails_dir: &Path,
+) -> Result<(LibraryExport, serde_json::Map<String, serde_json::Value>), ExportError> {
+	let mut reader = open_export(input).await?;
+
+	let manifest = serde_json::from_slice::<LibraryExport>(
+		&read_bytes(&mut reader, Entry::Manifest, MAX_JSON_LEN).await?,
+	)
+	.map_err(|_| ExportError::InvalidExport)?;
+	let config =
+		serde_json::from_slice(&read_bytes(&mut reader, Entry::Config, MAX_JSON_LEN).await?)
+			.map_err(|_| ExportError::InvalidExport)?;
+
+	if Entry::try_from(reader.read_u8().await?)? != Entry::Database {
+		return Err(ExportError::InvalidExport);
+	}
+	let len = reader.read_u64_le().await?;
+	let mut db = File::create(db_path)
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+	copy_exact(&mut reader, &mut db, len).await?;
+	db.flush()
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+
+	loop {
+		match Entry::try_from(reader.read_u8().await?)? {
+			Entry::End => break,
+			Entry::Thumbnail => {
+				let mut name = vec![0; reader.read_u16_le().await? as usize];
+				reader.read_exact(&mut name).await?;
+				let name = thumbnail_path(&name)?;
+				let len = reader.read_u64_le().await?;
+
+				let path = thumbnails_dir.join(name);
+				// The thumbnail cache is shared between libraries, the thumbnail may already be here
+				if len == 0 || fs::metadata(&path).await.is_ok() {
+					copy_exact(&mut reader, &mut io::sink(), len).await?;
+					continue;
+				}
+
+				if let Some(parent) = path.parent() {
+					fs::create_dir_all(parent)
+						.await
+						.map_err(|e| FileIOError::from((parent, e)))?;
+				}
+
+				let mut thumbnail = File::create(&path)
+					.await
+					.map_err(|e| FileIOError::from((&path, e)))?;
+				copy_exact(&mut reader, &mut thumbnail, len).await?;
+			}
+			_ => return Err(ExportError::InvalidExport),
+		}
+	}
+
+	Ok((manifest, config))
+}
+
+/// Thumbnails are in a shard directory of the thumbnails directory, anything else could write
+/// outside of it
+fn thumbnail_path(name: &[u8]) -> Result<PathBuf, ExportError> {
+	let name = std::str::from_utf8(name).map_err(|_| ExportError::InvalidExport)?;
+	let path = PathBuf::from_iter(name.split('/'));
+
+	let components = path.components().collect::<Vec<_>>();
+	if components.len() != 2 || !components.iter().all(|c| matches!(c, Component::Normal(_))) {
+		return Err(ExportError::InvalidExport);
+	}
+
+	Ok(path)
+}
+
+/// Puts the fields of the config that were removed when exporting back, for this node
+pub(super) fn restore_config(
+	config: &mut serde_json::Map<String, serde_json::Value>,
+	node_id: Uuid,
+) {
+	config.insert(
+		"node_id".into(),
+		serde_json::Value::String(node_id.to_string()),
+	);
+	config.insert("backup_targets".into(), serde_json::Value::Array(vec![]));
+	config.insert("backup_key".into(), serde_json::Value::Null);
+	config.insert("encryption".into(), serde_json::Value::Null);
+}
+
+/// The path a location of the export is at on this node, which must be a directory
+pub(super) async fn location_path(
+	manifest: &LibraryExport,
+	pub_id: Uuid,
+	path: &Path,
+) -> Result<String, ExportError> {
+	if !manifest
+		.locations
+		.iter()
+		.any(|location| location.pub_id == pub_id)
+	{
+		return Err(ExportError::UnknownLocation(pub_id));
+	}
+
+	match fs::metadata(path).await {
+		Ok(metadata) if metadata.is_dir() => {}
+		Ok(_) => return Err(ExportError::NotADirectory(path.to_path_buf())),
+		Err(e) => return Err(FileIOError::from((path, e)).into()),
+	}
+
+	Ok(path
+		.to_str()
+		.ok_or_else(|| NonUtf8PathError(path.into()))?
+		.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn thumbnail_paths() {
+		assert_eq!(
+			thumbnail_path(b"ab/abcdef.webp").unwrap(),
+			Path::new("ab").join("abcdef.webp")
+		);
+
+		for name in [
+			&b"abcdef.webp"[..],
+			b"../ab/abcdef.webp",
+			b"ab/../../abcdef.webp",
+			b"/ab/abcdef.webp",
+			b"ab/cd/abcdef.webp",
+			b"\xff/abcdef.webp",
+		] {
+			assert!(thumbnail_path(name).is_err(), "{name:?}");
+		}
+	}
+
+	#[tokio::test]
+	async fn exports_round_trip() {
+		let dir = tempfile::tempdir().unwrap();
+		let db_path = dir.path().join("library.db");
+		let thumbnail = dir.path().join("thumbnail.webp");
+		let export_path = dir.path().join("library.sdexport");
+
+		let db = (0..1024 * 1024)
+			.map(|i| (i % 251) as u8)
+			.collect::<Vec<_>>();
+		fs::write(&db_path, &db).await.unwrap();
+		fs::write(&thumbnail, b"thumbnail").await.unwrap();
+
+		let manifest = LibraryExport {
+			library_id: Uuid::new_v4(),
+			name: "Library".to_string(),
+			description: None,
+			date_exported: Utc::now(),
+			locations: vec![],
+			thumbnails: 1,
+		};
+		let config = serde_json::from_str(r#"{"name":"Library"}"#).unwrap();
+
+		write_export(
+			&export_path,
+			&manifest,
+			&config,
+			&db_path,
+			&[("ab/abcdef.webp".to_string(), thumbnail)],
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(
+			inspect(&export_path).await.unwrap().library_id,
+			manifest.library_id
+		);
+
+		let thumbnails_dir = dir.path().join("thumbnails");
+		let restored_path = dir.path().join("restored.db");
+		let (restored, restored_config) = unpack(&export_path, &restored_path, &thumbnails_dir)
+			.await
+			.unwrap();
+
+		assert_eq!(restored.library_id, manifest.library_id);
+		assert_eq!(restored_config, config);
+		assert_eq!(fs::read(&restored_path).await.unwrap(), db);
+		assert_eq!(
+			fs::read(thumbnails_dir.join("ab").join("abcdef.webp"))
+				.await
+				.unwrap(),
+			b"thumbnail"
+		);
+
+		// Truncated exports are rejected
+		let bytes = fs::read(&export_path).await.unwrap();
+		fs::write(&export_path, &bytes[..bytes.len() - 20])
+			.await
+			.unwrap();
+		assert!(matches!(
+			unpack(&export_path, &restored_path, &thumbnails_dir).await,
+			Err(ExportError::InvalidExport)
+		));
+	}
+}
@@ -0,0 +1,609 @@
+//! Checks the invariants of a library database that the schema can't enforce, or that older
+//! versions and interrupted jobs may have broken.
+//!
+//! The check doesn't change anything, it lists what it found along with a repair plan in the
+//! metadata of its job. Once the plan is reviewed, the repair job applies it.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStatus, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	location::file_path_helper::IsolatedFilePathData,
+	prisma::{file_path, job, location, object, tag, tag_on_object},
+	sync,
+	util::db::uuid_to_bytes,
+};
+
+use std::{borrow::Cow, path::Path};
+
+use prisma_client_rust::raw;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use thiserror::Error;
+use tokio::fs;
+use tracing::info;
+use uuid::Uuid;
+
+/// How many repairs are applied in a step
+const BATCH_SIZE: usize = 200;
+
+#[derive(Error, Debug)]
+pub enum IntegrityError {
+	#[error("integrity check '{0}' wasn't found")]
+	CheckNotFound(Uuid),
+	#[error("integrity check '{0}' didn't complete, its repair plan can't be trusted")]
+	CheckNotCompleted(Uuid),
+	#[error("job '{0}' isn't an integrity check")]
+	NotACheck(Uuid),
+}
+
+/// Something wrong in the library database
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+	/// A file path without a location, or whose location was deleted without it
+	FilePathWithoutLocation {
+		file_path_id: file_path::id::Type,
+		location_id: Option<location::id::Type>,
+	},
+	/// A size that isn't 8 bytes long, or that is too big for any file
+	ImpossibleSize {
+		file_path_id: file_path::id::Type,
+		length: i32,
+	},
+	/// File paths indexed more than once at the same path of a location
+	DuplicatePath {
+		location_id: Option<location::id::Type>,
+		materialized_path: Option<String>,
+		name: Option<String>,
+		extension: Option<String>,
+		file_path_ids: Vec<file_path::id::Type>,
+	},
+	/// A tag linked to an object that was deleted, or an object linked to a deleted tag
+	DanglingTagLink {
+		tag_id: tag::id::Type,
+		object_id: object::id::Type,
+	},
+}
+
+/// A change made by the repair job to fix an issue
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RepairAction {
+	DeleteFilePath {
+		file_path_id: file_path::id::Type,
+	},
+	/// Reads the size of the file again, or clears it if the file can't be read
+	ResetSize {
+		file_path_id: file_path::id::Type,
+	},
+	DeleteTagLink {
+		tag_id: tag::id::Type,
+		object_id: object::id::Type,
+	},
+}
+
+impl IntegrityIssue {
+	fn repairs(&self) -> Vec<RepairAction> {
+		match self {
+			Self::FilePathWithoutLocation { file_path_id, .. } => {
+				vec![RepairAction::DeleteFilePath {
+					file_path_id: *file_path_id,
+				}]
+			}
+			Self::ImpossibleSize { file_path_id, .. } => vec![RepairAction::ResetSize {
+				file_path_id: *file_path_id,
+			}],
+			// The first file path indexed at the path is kept, the others are deleted
+			Self::DuplicatePath { file_path_ids, .. } => {
+				let mut file_path_ids = file_path_ids.clone();
+				file_path_ids.sort_unstable();
+
+				file_path_ids
+					.into_iter()
+					.skip(1)
+					.map(|file_path_id| RepairAction::DeleteFilePath { file_path_id })
+					.collect()
+			}
+			Self::DanglingTagLink { tag_id, object_id } => vec![RepairAction::DeleteTagLink {
+				tag_id: *tag_id,
+				object_id: *object_id,
+			}],
+		}
+	}
+}
+
+/// The repairs fixing `issues`, each change made once even if it fixes several issues
+fn repair_plan(issues: &[IntegrityIssue]) -> Vec<RepairAction> {
+	let mut plan = issues
+		.iter()
+		.flat_map(IntegrityIssue::repairs)
+		.collect::<Vec<_>>();
+	plan.sort_unstable();
+	plan.dedup();
+
+	// A file path that is deleted doesn't need its size fixed
+	let deleted = plan
+		.iter()
+		.filter_map(|action| match action {
+			RepairAction::DeleteFilePath { file_path_id } => Some(*file_path_id),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	plan.retain(|action| match action {
+		RepairAction::ResetSize { file_path_id } => !deleted.contains(file_path_id),
+		_ => true,
+	});
+
+	plan
+}
+
+/// The metadata of a completed integrity check
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IntegrityReport {
+	pub issues: Vec<IntegrityIssue>,
+	pub repair_plan: Vec<RepairAction>,
+}
+
+/// Looks for the issues of the library database, changing nothing
+pub struct IntegrityCheckJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Default)]
+pub struct IntegrityCheckJobInit {}
+
+impl JobInitData for IntegrityCheckJobInit {
+	type Job = IntegrityCheckJob;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum IntegrityCheckJobStep {
+	FilePathsWithoutLocation,
+	ImpossibleSizes,
+	DuplicatePaths,
+	DanglingTagLinks,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct IntegrityCheckJobRunMetadata {
+	issues: Vec<IntegrityIssue>,
+}
+
+impl JobRunMetadata for IntegrityCheckJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.issues.extend(new_data.issues);
+	}
+}
+
+file_path::select!(file_path_to_reset_size {
+	pub_id
+	materialized_path
+	is_dir
+	name
+	extension
+	location: select { id path }
+});
+
+#[derive(Deserialize)]
+struct FilePathWithoutLocation {
+	id: file_path::id::Type,
+	location_id: Option<location::id::Type>,
+}
+
+#[derive(Deserialize)]
+struct ImpossibleSize {
+	id: file_path::id::Type,
+	length: i32,
+}
+
+#[derive(Deserialize)]
+struct DuplicatePath {
+	location_id: Option<location::id::Type>,
+	materialized_path: Option<String>,
+	name: Option<String>,
+	extension: Option<String>,
+	/// The ids of the file paths, separated by commas
+	ids: String,
+}
+
+#[derive(Deserialize)]
+struct DanglingTagLink {
+	tag_id: tag::id::Type,
+	object_id: object::id::Type,
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for IntegrityCheckJob {
+	type Init = IntegrityCheckJobInit;
+	type Data = ();
+	type Step = IntegrityCheckJobStep;
+	type RunMetadata = IntegrityCheckJobRunMetadata;
+
+	const NAME: &'static str = "library_integrity_check";
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		_: &WorkerContext,
+		_: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		*data = Some(());
+
+		Ok((
+			Default::default(),
+			vec![
+				IntegrityCheckJobStep::FilePathsWithoutLocation,
+				IntegrityCheckJobStep::ImpossibleSizes,
+				IntegrityCheckJobStep::DuplicatePaths,
+				IntegrityCheckJobStep::DanglingTagLinks,
+			],
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let db = &ctx.library.db;
+
+		let issues = match step {
+			IntegrityCheckJobStep::FilePathsWithoutLocation => {
+				ctx.progress_msg(job_message!("integrity.pathsWithoutLocation"));
+
+				db._query_raw::<FilePathWithoutLocation>(raw!(
+					"SELECT id, location_id FROM file_path
+					WHERE location_id IS NULL OR location_id NOT IN (SELECT id FROM location)"
+				))
+				.exec()
+				.await?
+				.into_iter()
+				.map(|row| IntegrityIssue::FilePathWithoutLocation {
+					file_path_id: row.id,
+					location_id: row.location_id,
+				})
+				.collect::<Vec<_>>()
+			}
+			IntegrityCheckJobStep::ImpossibleSizes => {
+				ctx.progress_msg(job_message!("integrity.impossibleSizes"));
+
+				// Sizes are big endian, so a size with its first bit set is over 8 EiB
+				db._query_raw::<ImpossibleSize>(raw!(
+					"SELECT id, length(size_in_bytes_bytes) AS length FROM file_path
+					WHERE size_in_bytes_bytes IS NOT NULL AND (
+						length(size_in_bytes_bytes) != 8
+						OR hex(substr(size_in_bytes_bytes, 1, 1)) >= '80'
+					)"
+				))
+				.exec()
+				.await?
+				.into_iter()
+				.map(|row| IntegrityIssue::ImpossibleSize {
+					file_path_id: row.id,
+					length: row.length,
+				})
+				.collect()
+			}
+			IntegrityCheckJobStep::DuplicatePaths => {
+				ctx.progress_msg(job_message!("integrity.duplicatePaths"));
+
+				// The unique index on the path doesn't hold for rows with a NULL in it
+				db._query_raw::<DuplicatePath>(raw!(
+					"SELECT location_id, materialized_path, name, extension,
+						group_concat(id) AS ids
+					FROM file_path
+					GROUP BY location_id, materialized_path, name, extension
+					HAVING count(*) > 1"
+				))
+				.exec()
+				.await?
+				.into_iter()
+				.map(|row| IntegrityIssue::DuplicatePath {
+					location_id: row.location_id,
+					materialized_path: row.materialized_path,
+					name: row.name,
+					extension: row.extension,
+					file_path_ids: row
+						.ids
+						.split(',')
+						.filter_map(|id| id.parse().ok())
+						.collect(),
+				})
+				.collect()
+			}
+			IntegrityCheckJobStep::DanglingTagLinks => {
+				ctx.progress_msg(job_message!("integrity.danglingTagLinks"));
+
+				db._query_raw::<DanglingTagLink>(raw!(
+					"SELECT tag_id, object_id FROM tag_on_object
+					WHERE tag_id NOT IN (SELECT id FROM tag)
+						OR object_id NOT IN (SELECT id FROM object)"
+				))
+				.exec()
+				.await?
+				.into_iter()
+				.map(|row| IntegrityIssue::DanglingTagLink {
+					tag_id: row.tag_id,
+					object_id: row.object_id,
+				})
+				.collect()
+			}
+		};
+
+		Ok(IntegrityCheckJobRunMetadata { issues }.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let issues = state.run_metadata.issues.clone();
+		let repair_plan = repair_plan(&issues);
+
+		info!(
+			"Checked the integrity of library '{}': {} issues, {} repairs planned",
+			ctx.library.id,
+			issues.len(),
+			repair_plan.len()
+		);
+
+		Ok(Some(serde_json::to_value(IntegrityReport {
+			issues,
+			repair_plan,
+		})?))
+	}
+}
+
+/// Applies the repair plan of a completed integrity check
+pub struct IntegrityRepairJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct IntegrityRepairJobInit {
+	pub check_job_id: Uuid,
+}
+
+impl JobInitData for IntegrityRepairJobInit {
+	type Job = IntegrityRepairJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct IntegrityRepairJobRunMetadata {
+	file_paths_deleted: u32,
+	sizes_reset: u32,
+	tag_links_deleted: u32,
+}
+
+impl JobRunMetadata for IntegrityRepairJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.file_paths_deleted += new_data.file_paths_deleted;
+		self.sizes_reset += new_data.sizes_reset;
+		self.tag_links_deleted += new_data.tag_links_deleted;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for IntegrityRepairJob {
+	type Init = IntegrityRepairJobInit;
+	type Data = ();
+	type Step = Vec<RepairAction>;
+	type RunMetadata = IntegrityRepairJobRunMetadata;
+
+	const NAME: &'static str = "library_integrity_repair";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let check = ctx
+			.library
+			.db
+			.job()
+			.find_unique(job::id::equals(uuid_to_bytes(init.check_job_id)))
+			.select(job::select!({ name status metadata }))
+			.exec()
+			.await?
+			.ok_or(IntegrityError::CheckNotFound(init.check_job_id))?;
+
+		if check.name.as_deref() != Some(IntegrityCheckJob::NAME) {
+			return Err(IntegrityError::NotACheck(init.check_job_id).into());
+		}
+		if check.status != Some(JobStatus::Completed as i32) {
+			return Err(IntegrityError::CheckNotCompleted(init.check_job_id).into());
+		}
+
+		let report = check
+			.metadata
+			.map(|metadata| serde_json::from_slice::<IntegrityReport>(&metadata))
+			.transpose()?
+			.ok_or(IntegrityError::CheckNotCompleted(init.check_job_id))?;
+
+		*data = Some(());
+
+		ctx.progress_msg(job_message!(
+			"integrity.applyingRepairs",
+			count = report.repair_plan.len()
+		));
+
+		Ok((
+			Default::default(),
+			report
+				.repair_plan
+				.chunks(BATCH_SIZE)
+				.map(<[_]>::to_vec)
+				.collect(),
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let library = &ctx.library;
+		let db = &library.db;
+		let mut metadata = IntegrityRepairJobRunMetadata::default();
+
+		let mut file_paths_to_delete = vec![];
+
+		for action in step {
+			match action {
+				RepairAction::DeleteFilePath { file_path_id } => {
+					file_paths_to_delete.push(*file_path_id);
+				}
+				RepairAction::ResetSize { file_path_id } => {
+					let Some(file_path) = db
+						.file_path()
+						.find_unique(file_path::id::equals(*file_path_id))
+						.select(file_path_to_reset_size::select())
+						.exec()
+						.await?
+					else {
+						continue;
+					};
+
+					let size = read_size(&file_path).await;
+
+					library
+						.sync
+						.write_op(
+							db,
+							library.sync.shared_update(
+								sync::file_path::SyncId {
+									pub_id: file_path.pub_id.clone(),
+								},
+								file_path::size_in_bytes_bytes::NAME,
+								json!(size),
+							),
+							db.file_path().update(
+								file_path::id::equals(*file_path_id),
+								vec![file_path::size_in_bytes_bytes::set(size)],
+							),
+						)
+						.await?;
+
+					metadata.sizes_reset += 1;
+				}
+				RepairAction::DeleteTagLink { tag_id, object_id } => {
+					// The tag or the object is gone, so there is no pub id to sync the change with
+					metadata.tag_links_deleted += db
+						.tag_on_object()
+						.delete_many(vec![
+							tag_on_object::tag_id::equals(*tag_id),
+							tag_on_object::object_id::equals(*object_id),
+						])
+						.exec()
+						.await? as u32;
+				}
+			}
+		}
+
+		if !file_paths_to_delete.is_empty() {
+			metadata.file_paths_deleted = db
+				.file_path()
+				.delete_many(vec![file_path::id::in_vec(file_paths_to_delete)])
+				.exec()
+				.await? as u32;
+		}
+
+		Ok(metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let library = &ctx.library;
+
+		// Objects left without file paths are removed with the other orphans
+		library.orphan_remover.invoke().await;
+
+		invalidate_query!(library, "search.paths");
+		invalidate_query!(library, "search.objects");
+		invalidate_query!(library, "locations.list");
+
+		Ok(Some(serde_json::to_value(&state.run_metadata)?))
+	}
+}
+
+/// The size of the file on disk, in the big endian bytes stored in the database
+async fn read_size(file_path: &file_path_to_reset_size::Data) -> Option<Vec<u8>> {
+	let location = file_path.location.as_ref()?;
+
+	let full_path = Path::new(location.path.as_ref()?).join(IsolatedFilePathData::from_db_data(
+		location.id,
+		file_path.is_dir?,
+		Cow::Borrowed(file_path.materialized_path.as_ref()?),
+		Cow::Borrowed(file_path.name.as_ref()?),
+		Cow::Borrowed(file_path.extension.as_ref()?),
+	));
+
+	fs::metadata(&full_path)
+		.await
+		.ok()
+		.map(|metadata| metadata.len().to_be_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn repair_plans_change_each_file_path_once() {
+		let plan = repair_plan(&[
+			IntegrityIssue::FilePathWithoutLocation {
+				file_path_id: 3,
+				location_id: None,
+			},
+			IntegrityIssue::ImpossibleSize {
+				file_path_id: 3,
+				length: 4,
+			},
+			IntegrityIssue::ImpossibleSize {
+				file_path_id: 7,
+				length: 8,
+			},
+			IntegrityIssue::DuplicatePath {
+				location_id: None,
+				materialized_path: Some("/".to_string()),
+				name: Some("photo".to_string()),
+				extension: Some("jpg".to_string()),
+				file_path_ids: vec![5, 3, 1],
+			},
+			IntegrityIssue::DanglingTagLink {
+				tag_id: 2,
+				object_id: 9,
+			},
+		]);
+
+		assert_eq!(
+			plan,
+			vec![
+				RepairAction::DeleteFilePath { file_path_id: 3 },
+				RepairAction::DeleteFilePath { file_path_id: 5 },
+				RepairAction::ResetSize { file_path_id: 7 },
+				RepairAction::DeleteTagLink {
+					tag_id: 2,
+					object_id: 9
+				},
+			]
+		);
+	}
+}
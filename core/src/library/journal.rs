@@ -0,0 +1,377 @@
+//! A journal of the changes jobs make to files. The operations of a job are a batch, and the last
+//! batch can be undone: copies and created directories are removed, moved and renamed files are
+//! moved back and trashed files are put back where they were. Deleted files are gone, their
+//! operations are only there to be reported.
+//!
+//! Nothing is undone over a change made since: a copy that was modified since is kept, and a file
+//! isn't moved back over one that took its place. Recording an operation never fails the operation,
+//! a failure is only logged.
+
+use crate::{
+	invalidate_query,
+	object::fs::os_trash,
+	prisma::{file_operation, trashed_file},
+};
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use filetime::FileTime;
+use prisma_client_rust::{Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+use super::Library;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, Eq, PartialEq)]
+pub enum FileOperationKind {
+	Copy = 0,
+	/// Moves and renames
+	Move = 1,
+	CreateDirectory = 2,
+	Trash = 3,
+	Delete = 4,
+}
+
+impl TryFrom<i32> for FileOperationKind {
+	type Error = i32;
+
+	fn try_from(value: i32) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::Copy,
+			1 => Self::Move,
+			2 => Self::CreateDirectory,
+			3 => Self::Trash,
+			4 => Self::Delete,
+			_ => return Err(value),
+		})
+	}
+}
+
+/// What a copy was like when it was made, so a copy modified since isn't removed
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+struct CopyState {
+	size: u64,
+	modified: i64,
+}
+
+impl CopyState {
+	async fn of(path: &Path) -> Option<Self> {
+		let metadata = fs::symlink_metadata(path).await.ok()?;
+
+		Some(Self {
+			size: metadata.len(),
+			modified: FileTime::from_last_modification_time(&metadata).unix_seconds(),
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct FileOperation {
+	kind: FileOperationKind,
+	source: Option<PathBuf>,
+	destination: Option<PathBuf>,
+	state: Option<CopyState>,
+}
+
+impl FileOperation {
+	/// `source` was copied to `destination`, which is the copy as it is now
+	pub async fn copied(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+		let destination = destination.into();
+
+		Self {
+			kind: FileOperationKind::Copy,
+			source: Some(source.into()),
+			state: CopyState::of(&destination).await,
+			destination: Some(destination),
+		}
+	}
+
+	pub fn moved(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+		Self {
+			kind: FileOperationKind::Move,
+			source: Some(source.into()),
+			destination: Some(destination.into()),
+			state: None,
+		}
+	}
+
+	pub fn created_directory(path: impl Into<PathBuf>) -> Self {
+		Self {
+			kind: FileOperationKind::CreateDirectory,
+			source: None,
+			destination: Some(path.into()),
+			state: None,
+		}
+	}
+
+	pub fn trashed(path: impl Into<PathBuf>, trash_path: impl Into<PathBuf>) -> Self {
+		Self {
+			kind: FileOperationKind::Trash,
+			source: Some(path.into()),
+			destination: Some(trash_path.into()),
+			state: None,
+		}
+	}
+
+	pub fn deleted(path: impl Into<PathBuf>) -> Self {
+		Self {
+			kind: FileOperationKind::Delete,
+			source: Some(path.into()),
+			destination: None,
+			state: None,
+		}
+	}
+}
+
+/// Records operations made by the job `batch_id`
+pub async fn record(library: &Library, batch_id: Uuid, operations: Vec<FileOperation>) {
+	if operations.is_empty() {
+		return;
+	}
+
+	let date_created = Utc::now();
+
+	if let Err(e) = library
+		.db
+		.file_operation()
+		.create_many(
+			operations
+				.into_iter()
+				.map(|operation| {
+					file_operation::create_unchecked(
+						batch_id.as_bytes().to_vec(),
+						operation.kind as i32,
+						date_created.into(),
+						vec![
+							file_operation::source::set(
+								operation
+									.source
+									.map(|path| path.to_string_lossy().into_owned()),
+							),
+							file_operation::destination::set(
+								operation
+									.destination
+									.map(|path| path.to_string_lossy().into_owned()),
+							),
+							file_operation::state::set(
+								operation
+									.state
+									.and_then(|state| serde_json::to_vec(&state).ok()),
+							),
+						],
+					)
+				})
+				.collect(),
+		)
+		.exec()
+		.await
+	{
+		warn!(
+			"Failed to record the file operations of job {batch_id} in library '{}': {e}",
+			library.id
+		);
+	}
+}
+
+/// An operation of the batch that was left as it is
+#[derive(Serialize, Type, Debug)]
+pub struct SkippedOperation {
+	pub kind: FileOperationKind,
+	pub path: Option<String>,
+	pub reason: String,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct UndoReport {
+	/// The job whose operations were undone, none if there was nothing left to undo
+	pub batch_id: Option<Uuid>,
+	pub undone: u32,
+	pub skipped: Vec<SkippedOperation>,
+}
+
+/// Whether anything exists at `path`, a broken symlink included
+async fn exists(path: &Path) -> io::Result<bool> {
+	match fs::symlink_metadata(path).await {
+		Ok(_) => Ok(true),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+/// Undoes an operation, or says why it can't be
+async fn undo_operation(
+	library: &Library,
+	operation: &file_operation::Data,
+	kind: FileOperationKind,
+) -> Result<(), String> {
+	let source = operation.source.as_deref().map(Path::new);
+	let destination = operation.destination.as_deref().map(Path::new);
+
+	match (kind, source, destination) {
+		(FileOperationKind::Copy, _, Some(copy)) => {
+			let state = operation
+				.state
+				.as_deref()
+				.and_then(|state| serde_json::from_slice::<CopyState>(state).ok());
+
+			match CopyState::of(copy).await {
+				None => return Err("the copy is already gone".to_string()),
+				current if current != state => {
+					return Err("the copy was modified since".to_string())
+				}
+				_ => {}
+			}
+
+			fs::remove_file(copy).await.map_err(|e| e.to_string())
+		}
+		(FileOperationKind::CreateDirectory, _, Some(directory)) => {
+			// Only when nothing was put in it since, removing a directory that isn't empty fails
+			fs::remove_dir(directory).await.map_err(|e| e.to_string())
+		}
+		(FileOperationKind::Move, Some(source), Some(destination)) => {
+			if exists(source).await.map_err(|e| e.to_string())? {
+				return Err("another file took its place".to_string());
+			}
+			if !exists(destination).await.map_err(|e| e.to_string())? {
+				return Err("it was moved or deleted since".to_string());
+			}
+
+			// Files moved to another device were copied then removed, which can't be undone by a
+			// rename, they're left for a move in the other direction
+			fs::rename(destination, source)
+				.await
+				.map_err(|e| e.to_string())
+		}
+		(FileOperationKind::Trash, Some(original_path), Some(trash_path)) => {
+			os_trash::restore(trash_path, original_path)
+				.await
+				.map_err(|e| e.to_string())?;
+
+			library
+				.db
+				.trashed_file()
+				.delete_many(vec![trashed_file::trash_path::equals(
+					trash_path.to_string_lossy().into_owned(),
+				)])
+				.exec()
+				.await
+				.map_err(|e| e.to_string())?;
+
+			Ok(())
+		}
+		(FileOperationKind::Delete, _, _) => Err("it was deleted for good".to_string()),
+		_ => Err("its paths weren't recorded".to_string()),
+	}
+}
+
+/// Undoes the last batch of operations that wasn't undone yet, last operation first
+pub async fn undo_last_batch(library: &Library) -> Result<UndoReport, QueryError> {
+	let db = &library.db;
+
+	let Some(last) = db
+		.file_operation()
+		.find_first(vec![file_operation::date_undone::equals(None)])
+		.order_by(file_operation::id::order(Direction::Desc))
+		.exec()
+		.await?
+	else {
+		return Ok(UndoReport {
+			batch_id: None,
+			undone: 0,
+			skipped: vec![],
+		});
+	};
+
+	let operations = db
+		.file_operation()
+		.find_many(vec![
+			file_operation::batch_id::equals(last.batch_id.clone()),
+			file_operation::date_undone::equals(None),
+		])
+		.order_by(file_operation::id::order(Direction::Desc))
+		.exec()
+		.await?;
+
+	let mut report = UndoReport {
+		batch_id: Uuid::from_slice(&last.batch_id).ok(),
+		undone: 0,
+		skipped: vec![],
+	};
+
+	for operation in &operations {
+		let Ok(kind) = FileOperationKind::try_from(operation.kind) else {
+			warn!("Unknown kind of file operation: {}", operation.kind);
+			continue;
+		};
+
+		match undo_operation(library, operation, kind).await {
+			Ok(()) => {
+				trace!("Undid {kind:?} of {:?}", operation.destination);
+				report.undone += 1;
+			}
+			Err(reason) => report.skipped.push(SkippedOperation {
+				kind,
+				path: operation
+					.destination
+					.clone()
+					.or_else(|| operation.source.clone()),
+				reason,
+			}),
+		}
+	}
+
+	// Skipped operations too, so the next undo goes to the batch before instead of trying again
+	db.file_operation()
+		.update_many(
+			vec![file_operation::id::in_vec(
+				operations.iter().map(|operation| operation.id).collect(),
+			)],
+			vec![file_operation::date_undone::set(Some(Utc::now().into()))],
+		)
+		.exec()
+		.await?;
+
+	invalidate_query!(library, "search.paths");
+	invalidate_query!(library, "files.trashed");
+
+	Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn kinds_round_trip() {
+		for kind in [
+			FileOperationKind::Copy,
+			FileOperationKind::Move,
+			FileOperationKind::CreateDirectory,
+			FileOperationKind::Trash,
+			FileOperationKind::Delete,
+		] {
+			assert_eq!(FileOperationKind::try_from(kind as i32), Ok(kind));
+		}
+		assert_eq!(FileOperationKind::try_from(5), Err(5));
+	}
+
+	#[tokio::test]
+	async fn copies_are_compared_with_how_they_were() {
+		let dir = tempfile::tempdir().unwrap();
+		let copy = dir.path().join("copy.txt");
+		fs::write(&copy, b"copied").await.unwrap();
+
+		let operation = FileOperation::copied(dir.path().join("source.txt"), &copy).await;
+		let state = operation.state.unwrap();
+		assert_eq!(state.size, 6);
+		assert_eq!(CopyState::of(&copy).await, Some(state));
+
+		fs::write(&copy, b"modified since").await.unwrap();
+		assert_ne!(CopyState::of(&copy).await.map(|state| state.size), Some(6));
+	}
+}
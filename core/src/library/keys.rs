@@ -0,0 +1,122 @@
+//! The keys of the key manager that are kept in the library, to be loaded back with it. Keys added
+//! as memory only are never written here, they're gone once the library is unloaded.
+
+use crate::prisma::{key, PrismaClient};
+
+use std::str::FromStr;
+
+use prisma_client_rust::QueryError;
+use sd_crypto::keys::keymanager::{KeyManager, StoredKey};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum KeysError {
+	#[error("invalid key in the library: {0}")]
+	Serialization(#[from] serde_json::Error),
+	#[error("invalid key uuid in the library: {0}")]
+	Uuid(#[from] uuid::Error),
+	#[error("key manager error: {0}")]
+	Crypto(#[from] sd_crypto::Error),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+}
+
+impl From<KeysError> for rspc::Error {
+	fn from(e: KeysError) -> Self {
+		match e {
+			KeysError::Crypto(e) => e.into(),
+			KeysError::Database(e) => e.into(),
+			e => rspc::Error::with_cause(rspc::ErrorCode::InternalServerError, e.to_string(), e),
+		}
+	}
+}
+
+/// Writes a key to the library, it doesn't check that the key isn't there already
+pub async fn write_stored_key(db: &PrismaClient, key: &StoredKey) -> Result<(), KeysError> {
+	db.key()
+		.create(
+			key.uuid.to_string(),
+			serde_json::to_string(&key.version)?,
+			serde_json::to_string(&key.key_type)?,
+			serde_json::to_string(&key.algorithm)?,
+			serde_json::to_string(&key.hashing_algorithm)?,
+			key.content_salt.to_vec(),
+			key.master_key.to_vec(),
+			key.master_key_nonce.to_vec(),
+			key.key_nonce.to_vec(),
+			key.key.clone(),
+			key.salt.to_vec(),
+			vec![key::automount::set(key.automount)],
+		)
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+fn stored_key(key: key::Data) -> Result<StoredKey, KeysError> {
+	Ok(StoredKey {
+		uuid: Uuid::from_str(&key.uuid)?,
+		version: serde_json::from_str(&key.version)?,
+		key_type: serde_json::from_str(&key.key_type)?,
+		algorithm: serde_json::from_str(&key.algorithm)?,
+		hashing_algorithm: serde_json::from_str(&key.hashing_algorithm)?,
+		content_salt: key.content_salt.try_into()?,
+		master_key: key.master_key.try_into()?,
+		master_key_nonce: key.master_key_nonce.try_into()?,
+		key_nonce: key.key_nonce.try_into()?,
+		key: key.key,
+		salt: key.salt.try_into()?,
+		memory_only: false,
+		automount: key.automount,
+	})
+}
+
+/// Fills the keystore of a library's key manager with the keys of the library. The key manager is
+/// still locked afterwards, see [`on_unlock`].
+pub async fn seed_key_manager(
+	db: &PrismaClient,
+	key_manager: &KeyManager,
+) -> Result<(), KeysError> {
+	let stored_keys = db
+		.key()
+		.find_many(vec![])
+		.exec()
+		.await?
+		.into_iter()
+		.map(stored_key)
+		.collect::<Result<Vec<_>, _>>()?;
+
+	key_manager.populate_keystore(stored_keys).await?;
+
+	Ok(())
+}
+
+/// Sets the default key and mounts the keys to be mounted automatically, which only works with an
+/// unlocked key manager
+pub async fn on_unlock(db: &PrismaClient, key_manager: &KeyManager) -> Result<(), KeysError> {
+	for key in db
+		.key()
+		.find_many(vec![key::default::equals(true)])
+		.exec()
+		.await?
+	{
+		key_manager.set_default(Uuid::from_str(&key.uuid)?).await?;
+	}
+
+	let mounted = key_manager.get_mounted_uuids();
+	for key in db
+		.key()
+		.find_many(vec![key::automount::equals(true)])
+		.exec()
+		.await?
+	{
+		let uuid = Uuid::from_str(&key.uuid)?;
+		if !mounted.contains(&uuid) {
+			key_manager.mount(uuid).await?;
+		}
+	}
+
+	Ok(())
+}
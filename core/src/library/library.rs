@@ -5,8 +5,14 @@ use crate::{
 		file_path_helper::{file_path_to_full_path, IsolatedFilePathData},
 		LocationManager,
 	},
-	node::NodeConfigManager,
-	object::{orphan_remover::OrphanRemoverActor, preview::get_thumbnail_path},
+	node::{Analytics, NodeConfigManager, NodeMetrics},
+	object::{
+		orphan_remover::OrphanRemoverActor,
+		preview::{
+			find_thumbnail, ThumbnailCacheActor, ThumbnailPriorityActor, THUMBNAIL_CACHE_DIR_NAME,
+		},
+	},
+	plugins::PluginManager,
 	prisma::{file_path, location, PrismaClient},
 	sync::SyncManager,
 	util::{db::maybe_missing, error::FileIOError},
@@ -20,12 +26,12 @@ use std::{
 	sync::Arc,
 };
 
+use sd_crypto::keys::keymanager::KeyManager;
 use sd_p2p::spacetunnel::Identity;
-use tokio::{fs, io};
 use tracing::warn;
 use uuid::Uuid;
 
-use super::{LibraryConfig, LibraryManagerError};
+use super::{LibraryConfig, LibraryManagerError, OverviewCache, QueryCache};
 
 /// LibraryContext holds context for a library which can be passed around the application.
 #[derive(Clone)]
@@ -40,7 +46,7 @@ pub struct Library {
 	pub db: Arc<PrismaClient>,
 	pub sync: Arc<SyncManager>,
 	/// key manager that provides encryption keys to functions that require them
-	// pub key_manager: Arc<KeyManager>,
+	pub key_manager: Arc<KeyManager>,
 	/// node_local_id holds the local ID of the node which is running the library.
 	pub node_local_id: i32,
 	/// node_context holds the node context for the node which this library is running on.
@@ -48,6 +54,10 @@ pub struct Library {
 	/// p2p identity
 	pub identity: Arc<Identity>,
 	pub orphan_remover: OrphanRemoverActor,
+	/// The cached figures of the overview of the library
+	pub(crate) overview: Arc<OverviewCache>,
+	/// The results of the queries shown on every navigation, kept until they're invalidated
+	pub(crate) queries: Arc<QueryCache>,
 }
 
 impl Debug for Library {
@@ -80,6 +90,11 @@ impl Library {
 	}
 
 	pub(crate) fn emit(&self, event: CoreEvent) {
+		if let CoreEvent::InvalidateOperation(operation) = &event {
+			self.overview.invalidated(operation);
+			self.queries.invalidated(operation);
+		}
+
 		if let Err(e) = self.node_context.event_bus_tx.send(event) {
 			warn!("Error sending event to event bus: {e:?}");
 		}
@@ -93,14 +108,40 @@ impl Library {
 		&self.node_context.location_manager
 	}
 
+	pub(crate) fn thumbnail_cache(&self) -> &ThumbnailCacheActor {
+		&self.node_context.thumbnail_cache
+	}
+
+	pub(crate) fn thumbnail_priority(&self) -> &ThumbnailPriorityActor {
+		&self.node_context.thumbnail_priority
+	}
+
+	pub(crate) fn plugins(&self) -> &Arc<PluginManager> {
+		&self.node_context.plugins
+	}
+
+	pub(crate) fn metrics(&self) -> &Arc<NodeMetrics> {
+		&self.node_context.metrics
+	}
+
+	pub(crate) fn analytics(&self) -> &Arc<Analytics> {
+		&self.node_context.analytics
+	}
+
 	pub async fn thumbnail_exists(&self, cas_id: &str) -> Result<bool, FileIOError> {
-		let thumb_path = get_thumbnail_path(self, cas_id);
+		Ok(self.find_thumbnail(cas_id).await?.is_some())
+	}
 
-		match fs::metadata(&thumb_path).await {
-			Ok(_) => Ok(true),
-			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
-			Err(e) => Err(FileIOError::from((thumb_path, e))),
-		}
+	/// Returns the path of the thumbnail of this cas_id in whichever format it was generated
+	pub async fn find_thumbnail(&self, cas_id: &str) -> Result<Option<PathBuf>, FileIOError> {
+		let thumbnail_dir = self
+			.config()
+			.data_directory()
+			.join(THUMBNAIL_CACHE_DIR_NAME);
+
+		Ok(find_thumbnail(thumbnail_dir, cas_id)
+			.await?
+			.map(|(path, _)| path))
 	}
 
 	/// Returns the full path of a file
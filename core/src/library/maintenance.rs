@@ -0,0 +1,263 @@
+//! Keeps the library database small and its query plans good. Deleting many files leaves the
+//! database file as big as it was, and the statistics SQLite plans queries with out of date.
+
+use crate::{
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobManager,
+		JobResult, JobState, JobStatus, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::{Library, LibraryManager},
+	p2p::SyncScheduler,
+	prisma::job,
+	util::error::FileIOError,
+};
+
+use std::{
+	path::{Path, PathBuf},
+	sync::{Arc, Weak},
+	time::Duration,
+};
+
+use chrono::{Duration as ChronoDuration, Local, Utc};
+use prisma_client_rust::{raw, Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{
+	fs, io,
+	time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+
+/// How often the scheduler checks if the device is idle
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many days go by between two maintenances of a library
+const MAINTENANCE_INTERVAL_DAYS: i64 = 7;
+
+/// Checkpoints the write-ahead log into the database, then rebuilds it without the free pages
+/// left by deleted rows and updates the statistics used to plan queries
+pub struct MaintenanceJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Default)]
+pub struct MaintenanceJobInit {}
+
+impl JobInitData for MaintenanceJobInit {
+	type Job = MaintenanceJob;
+
+	fn locations(&self) -> JobLocations {
+		// The whole database is rewritten
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaintenanceJobData {
+	db_path: PathBuf,
+	/// The size of the database and its write-ahead log before the maintenance, in bytes
+	size_before: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MaintenanceJobStep {
+	Checkpoint,
+	Vacuum,
+	Analyze,
+}
+
+/// The row returned by `PRAGMA wal_checkpoint`
+#[derive(Deserialize)]
+struct Checkpoint {
+	busy: i32,
+}
+
+/// The size of the database with its write-ahead log, which can be bigger than the database
+async fn database_size(db_path: &Path) -> Result<u64, FileIOError> {
+	let mut wal_path = db_path.as_os_str().to_owned();
+	wal_path.push("-wal");
+
+	let mut size = 0;
+	for path in [db_path, Path::new(&wal_path)] {
+		match fs::metadata(path).await {
+			Ok(metadata) => size += metadata.len(),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((path, e))),
+		}
+	}
+
+	Ok(size)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for MaintenanceJob {
+	type Init = MaintenanceJobInit;
+	type Data = MaintenanceJobData;
+	type Step = MaintenanceJobStep;
+	type RunMetadata = ();
+
+	const NAME: &'static str = "library_maintenance";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let library = &ctx.library;
+
+		let db_path = library
+			.config()
+			.data_directory()
+			.join("libraries")
+			.join(format!("{}.db", library.id));
+		let size_before = database_size(&db_path).await?;
+
+		*data = Some(MaintenanceJobData {
+			db_path,
+			size_before,
+		});
+
+		Ok(vec![
+			MaintenanceJobStep::Checkpoint,
+			MaintenanceJobStep::Vacuum,
+			MaintenanceJobStep::Analyze,
+		]
+		.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let db = &ctx.library.db;
+
+		match step {
+			MaintenanceJobStep::Checkpoint => {
+				ctx.progress_msg(job_message!("maintenance.checkpointing"));
+
+				// A busy checkpoint only moves part of the log, the vacuum still goes through
+				let checkpoints = db
+					._query_raw::<Checkpoint>(raw!("PRAGMA wal_checkpoint(TRUNCATE)"))
+					.exec()
+					.await?;
+				if checkpoints.iter().any(|checkpoint| checkpoint.busy != 0) {
+					warn!("The database was busy, the write-ahead log was partially checkpointed");
+				}
+			}
+			MaintenanceJobStep::Vacuum => {
+				ctx.progress_msg(job_message!("maintenance.vacuuming"));
+
+				db._execute_raw(raw!("VACUUM")).exec().await?;
+			}
+			MaintenanceJobStep::Analyze => {
+				ctx.progress_msg(job_message!("maintenance.analyzing"));
+
+				db._execute_raw(raw!("ANALYZE")).exec().await?;
+			}
+		}
+
+		Ok(None.into())
+	}
+
+	async fn finalize(&self, _: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let Some(data) = state.data.as_ref() else {
+			return Ok(None);
+		};
+
+		let size_after = database_size(&data.db_path).await?;
+
+		info!(
+			"Maintained library database '{}', from {} to {size_after} bytes",
+			data.db_path.display(),
+			data.size_before
+		);
+
+		Ok(Some(json!({
+			"size_before": data.size_before,
+			"size_after": size_after,
+			"reclaimed": data.size_before.saturating_sub(size_after),
+		})))
+	}
+}
+
+/// Whether the library wasn't maintained in the last [`MAINTENANCE_INTERVAL_DAYS`]
+async fn is_maintenance_due(library: &Library) -> Result<bool, QueryError> {
+	let last = library
+		.db
+		.job()
+		.find_first(vec![
+			job::name::equals(Some(MaintenanceJob::NAME.to_string())),
+			job::status::equals(Some(JobStatus::Completed as i32)),
+		])
+		.order_by(job::date_completed::order(Direction::Desc))
+		.select(job::select!({ date_completed }))
+		.exec()
+		.await?;
+
+	Ok(last
+		.and_then(|job| job.date_completed)
+		.map_or(true, |date| {
+			Utc::now() - date.with_timezone(&Utc) >= ChronoDuration::days(MAINTENANCE_INTERVAL_DAYS)
+		}))
+}
+
+/// Maintains the libraries while the device is idle and plugged in, when no other job is running.
+/// Only one library is maintained at a time, the others wait for the next check.
+pub(crate) fn spawn_scheduler(
+	library_manager: Weak<LibraryManager>,
+	job_manager: Arc<JobManager>,
+	sync_scheduler: Arc<SyncScheduler>,
+) {
+	tokio::spawn(async move {
+		let mut tick = interval(CHECK_INTERVAL);
+		tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			tick.tick().await;
+
+			let Some(library_manager) = library_manager.upgrade() else {
+				break;
+			};
+
+			let conditions = sync_scheduler.conditions();
+			if !conditions.idle || conditions.on_battery || job_manager.has_active_workers().await {
+				continue;
+			}
+
+			for library in library_manager.get_all_libraries().await {
+				if library.config.settings.is_quiet(Local::now()) {
+					continue;
+				}
+
+				match is_maintenance_due(&library).await {
+					Ok(true) => {
+						debug!("Starting the maintenance of library '{}'", library.id);
+
+						if let Err(e) = library.spawn_job(MaintenanceJobInit {}).await {
+							warn!(
+								"Failed to start the maintenance of library '{}': {e}",
+								library.id
+							);
+						}
+
+						break;
+					}
+					Ok(false) => {}
+					Err(e) => warn!(
+						"Failed to read the maintenances of library '{}': {e}",
+						library.id
+					),
+				}
+			}
+		}
+	});
+}
@@ -1,10 +1,11 @@
 use crate::{
 	invalidate_query,
+	job::JobStatus,
 	location::{indexer, LocationManagerError},
 	node::{NodeConfig, Platform},
-	object::{orphan_remover::OrphanRemoverActor, tag},
-	prisma::{location, node},
-	sync::{SyncManager, SyncMessage},
+	object::{orphan_remover::OrphanRemoverActor, preview::THUMBNAIL_CACHE_DIR_NAME, tag},
+	prisma::{job, location, node},
+	sync::{ConflictPolicy, OperationCipher, SyncError, SyncManager, SyncMessage},
 	util::{
 		db::{self, MissingFieldError},
 		error::{FileIOError, NonUtf8PathError},
@@ -15,13 +16,23 @@ use crate::{
 };
 
 use std::{
+	collections::HashMap,
 	path::{Path, PathBuf},
 	str::FromStr,
 	sync::Arc,
 };
 
 use chrono::Local;
+use prisma_client_rust::raw;
+use sd_crypto::{
+	keys::{
+		keymanager::KeyManager,
+		secrets::{SecretKind, Secrets},
+	},
+	Protected,
+};
 use sd_p2p::spacetunnel::{Identity, IdentityErr};
+use serde_json::json;
 use thiserror::Error;
 use tokio::{
 	fs, io,
@@ -31,7 +42,18 @@ use tokio::{
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use super::{Library, LibraryConfig, LibraryConfigWrapped};
+use super::{
+	activity::{self, ActivityKind},
+	backup::{
+		self, BackupError, BackupKey, BackupRetention, BackupSnapshot, BackupTarget,
+		BackupTargetKind,
+	},
+	encryption::{self, vfs, EncryptedConfig, EncryptionError, LibraryEncryption, LockedLibrary},
+	export::{self, ExportError},
+	keys::{self, KeysError},
+	trash, Library, LibraryConfig, LibraryConfigWrapped, LibrarySettings, LibrarySettingsError,
+	LibrarySettingsPatch,
+};
 
 pub enum SubscriberEvent {
 	Load(Uuid, Arc<Identity>, broadcast::Receiver<SyncMessage>),
@@ -60,6 +82,8 @@ pub struct LibraryManager {
 	node_context: NodeContext,
 	/// on load subscribers
 	subscribers: RwLock<Vec<Box<dyn SubscriberFn>>>,
+	/// Encrypted libraries that weren't loaded, waiting for their password
+	locked: RwLock<Vec<LockedLibrary>>,
 }
 
 #[derive(Error, Debug)]
@@ -70,6 +94,8 @@ pub enum LibraryManagerError {
 	Json(#[from] serde_json::Error),
 	#[error("database error: {0}")]
 	Database(#[from] prisma_client_rust::QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
 	#[error("library not found error")]
 	LibraryNotFound,
 	#[error("error migrating the config file: {0}")]
@@ -78,8 +104,8 @@ pub enum LibraryManagerError {
 	Uuid(#[from] uuid::Error),
 	#[error("failed to run indexer rules seeder: {0}")]
 	IndexerRulesSeeder(#[from] indexer::rules::seed::SeederError),
-	// #[error("failed to initialise the key manager: {0}")]
-	// KeyManager(#[from] sd_crypto::Error),
+	#[error("failed to initialise the key manager: {0}")]
+	KeyManager(#[from] KeysError),
 	#[error("failed to run library migrations: {0}")]
 	MigratorError(#[from] MigratorError),
 	#[error("error migrating the library: {0}")]
@@ -96,15 +122,31 @@ pub enum LibraryManagerError {
 	CurrentNodeNotFound(String),
 	#[error("missing-field: {0}")]
 	MissingField(#[from] MissingFieldError),
+	#[error(transparent)]
+	Backup(#[from] BackupError),
+	#[error(transparent)]
+	Export(#[from] ExportError),
+	#[error(transparent)]
+	Encryption(#[from] EncryptionError),
+	#[error(transparent)]
+	Settings(#[from] LibrarySettingsError),
+	#[error("invalid sync key: {0}")]
+	SyncKey(#[from] sd_crypto::Error),
 }
 
 impl From<LibraryManagerError> for rspc::Error {
 	fn from(error: LibraryManagerError) -> Self {
-		rspc::Error::with_cause(
-			rspc::ErrorCode::InternalServerError,
-			error.to_string(),
-			error,
-		)
+		match error {
+			LibraryManagerError::Backup(e) => e.into(),
+			LibraryManagerError::Export(e) => e.into(),
+			LibraryManagerError::Encryption(e) => e.into(),
+			LibraryManagerError::Settings(e) => e.into(),
+			error => rspc::Error::with_cause(
+				rspc::ErrorCode::InternalServerError,
+				error.to_string(),
+				error,
+			),
+		}
 	}
 }
 
@@ -117,7 +159,11 @@ impl LibraryManager {
 			.await
 			.map_err(|e| FileIOError::from((&libraries_dir, e)))?;
 
+		// Prisma opens the databases with the default VFS, which has to encrypt them from the start
+		vfs::register().map_err(EncryptionError::from)?;
+
 		let mut libraries = Vec::new();
+		let mut locked = Vec::new();
 		let subscribers = RwLock::new(Vec::new());
 		let mut read_dir = fs::read_dir(&libraries_dir)
 			.await
@@ -140,14 +186,33 @@ impl LibraryManager {
 					.unwrap_or(false)
 			{
 				let Some(Ok(library_id)) = config_path
-				.file_stem()
-				.and_then(|v| v.to_str().map(Uuid::from_str))
-			else {
-				warn!("Attempted to load library from path '{}' but it has an invalid filename. Skipping...", config_path.display());
+					.file_stem()
+					.and_then(|v| v.to_str().map(Uuid::from_str))
+				else {
+					warn!("Attempted to load library from path '{}' but it has an invalid filename. Skipping...", config_path.display());
 					continue;
-			};
+				};
 
 				let db_path = config_path.with_extension("db");
+
+				if let EncryptedConfig {
+					name,
+					encryption: Some(_),
+				} = EncryptedConfig::read(&config_path).await?
+				{
+					match encryption::recall_key(library_id) {
+						Some(key) => vfs::unlock(&db_path, &key),
+						None => {
+							info!("Library '{library_id}' is locked until it's unlocked with its password");
+							locked.push(LockedLibrary {
+								uuid: library_id,
+								name,
+							});
+							continue;
+						}
+					}
+				}
+
 				match fs::metadata(&db_path).await {
 					Ok(_) => {}
 					Err(e) if e.kind() == io::ErrorKind::NotFound => {
@@ -174,12 +239,18 @@ impl LibraryManager {
 			}
 		}
 
-		Ok(Arc::new(Self {
+		let this = Arc::new(Self {
 			libraries: RwLock::new(libraries),
 			libraries_dir,
 			node_context,
 			subscribers,
-		}))
+			locked: RwLock::new(locked),
+		});
+
+		backup::spawn_scheduler(Arc::downgrade(&this));
+		trash::spawn_scheduler(Arc::downgrade(&this));
+
+		Ok(this)
 	}
 
 	/// subscribe to library events
@@ -204,6 +275,27 @@ impl LibraryManager {
 			.await
 	}
 
+	/// Creates a new library whose database is encrypted, with a key unlocked by `password`. With
+	/// `remember`, the key is kept in the OS keychain to unlock the library on startup.
+	pub(crate) async fn create_encrypted(
+		&self,
+		mut config: LibraryConfig,
+		node_cfg: NodeConfig,
+		password: Protected<String>,
+		remember: bool,
+	) -> Result<LibraryConfigWrapped, LibraryManagerError> {
+		let id = Uuid::new_v4();
+		let (encryption, key) = LibraryEncryption::new(password).await?;
+		if remember {
+			encryption::remember_key(id, &key)?;
+		}
+
+		config.encryption = Some(encryption);
+		vfs::unlock(&self.libraries_dir.join(format!("{id}.db")), &key);
+
+		self.create_with_uuid(id, config, node_cfg).await
+	}
+
 	pub(crate) async fn create_with_uuid(
 		&self,
 		id: Uuid,
@@ -281,6 +373,7 @@ impl LibraryManager {
 		id: Uuid,
 		name: Option<String>,
 		description: MaybeUndefined<String>,
+		sync_conflict_policy: Option<ConflictPolicy>,
 	) -> Result<(), LibraryManagerError> {
 		// check library is valid
 		let mut libraries = self.libraries.write().await;
@@ -298,6 +391,10 @@ impl LibraryManager {
 			MaybeUndefined::Null => library.config.description = None,
 			MaybeUndefined::Value(description) => library.config.description = Some(description),
 		}
+		if let Some(sync_conflict_policy) = sync_conflict_policy {
+			library.config.sync_conflict_policy = sync_conflict_policy;
+			library.sync.set_conflict_policy(sync_conflict_policy);
+		}
 
 		LibraryConfig::save(
 			&library.config,
@@ -334,6 +431,521 @@ impl LibraryManager {
 		Ok(())
 	}
 
+	/// Changes the settings of the library and saves them. Only new thumbnails use the thumbnail
+	/// settings, existing ones are updated by regenerating them.
+	pub(crate) async fn patch_settings(
+		&self,
+		id: Uuid,
+		patch: LibrarySettingsPatch,
+	) -> Result<LibrarySettings, LibraryManagerError> {
+		let mut libraries = self.libraries.write().await;
+		let library = libraries
+			.iter_mut()
+			.find(|lib| lib.id == id)
+			.ok_or(LibraryManagerError::LibraryNotFound)?;
+
+		let before = library.config.settings.clone();
+		library.config.settings.patch(patch)?;
+
+		LibraryConfig::save(
+			&library.config,
+			&self.libraries_dir.join(format!("{id}.sdlibrary")),
+		)?;
+
+		activity::record(
+			library,
+			ActivityKind::SettingsChanged,
+			json!({ "before": before, "after": library.config.settings }),
+		)
+		.await;
+
+		invalidate_query!(library, "library.settings");
+		invalidate_query!(library, "library.list");
+
+		Ok(library.config.settings.clone())
+	}
+
+	/// Changes the library config and saves it
+	async fn update_config(
+		&self,
+		id: Uuid,
+		update: impl FnOnce(&mut LibraryConfig) -> Result<(), BackupError>,
+	) -> Result<(), LibraryManagerError> {
+		let mut libraries = self.libraries.write().await;
+		let library = libraries
+			.iter_mut()
+			.find(|lib| lib.id == id)
+			.ok_or(LibraryManagerError::LibraryNotFound)?;
+
+		update(&mut library.config)?;
+
+		LibraryConfig::save(
+			&library.config,
+			&self.libraries_dir.join(format!("{id}.sdlibrary")),
+		)?;
+
+		invalidate_query!(library, "library.list");
+
+		Ok(())
+	}
+
+	pub(crate) async fn add_backup_target(
+		&self,
+		id: Uuid,
+		target: BackupTarget,
+	) -> Result<(), LibraryManagerError> {
+		self.update_config(id, |config| {
+			config.backup_targets.push(target);
+			Ok(())
+		})
+		.await
+	}
+
+	pub(crate) async fn edit_backup_target(
+		&self,
+		id: Uuid,
+		target_id: Uuid,
+		retention: Option<BackupRetention>,
+		automatic: Option<bool>,
+	) -> Result<(), LibraryManagerError> {
+		self.update_config(id, |config| {
+			let target = config
+				.backup_targets
+				.iter_mut()
+				.find(|target| target.id == target_id)
+				.ok_or(BackupError::TargetNotFound(target_id))?;
+
+			if let Some(retention) = retention {
+				target.retention = retention;
+			}
+
+			if let Some(automatic) = automatic {
+				target.automatic = automatic;
+			}
+
+			Ok(())
+		})
+		.await
+	}
+
+	pub(crate) async fn remove_backup_target(
+		&self,
+		id: Uuid,
+		target_id: Uuid,
+	) -> Result<(), LibraryManagerError> {
+		self.update_config(id, |config| {
+			let len = config.backup_targets.len();
+			config
+				.backup_targets
+				.retain(|target| target.id != target_id);
+
+			if config.backup_targets.len() == len {
+				return Err(BackupError::TargetNotFound(target_id));
+			}
+
+			if let Ok(secrets) = Secrets::new() {
+				backup::forget_credentials(&secrets, target_id);
+			}
+
+			Ok(())
+		})
+		.await
+	}
+
+	/// Snapshots backed up before changing the password can still be restored with the old one
+	pub(crate) async fn set_backup_key(
+		&self,
+		id: Uuid,
+		key: BackupKey,
+	) -> Result<(), LibraryManagerError> {
+		self.update_config(id, |config| {
+			config.backup_key = Some(key);
+			Ok(())
+		})
+		.await
+	}
+
+	/// Restores a library from a backup snapshot and loads it. This node takes the place of the node
+	/// the snapshot was made on if it isn't in the library already.
+	pub(crate) async fn restore_backup(
+		&self,
+		target: &BackupTargetKind,
+		snapshot: &BackupSnapshot,
+		password: Protected<String>,
+	) -> Result<LibraryConfigWrapped, LibraryManagerError> {
+		let id = snapshot.library_id;
+		if self.get_library(id).await.is_some() {
+			return Err(BackupError::LibraryExists(id).into());
+		}
+
+		let snapshot_path = self.libraries_dir.join(&snapshot.name);
+		let db_path = self.libraries_dir.join(format!("{id}.db"));
+		let config_path = self.libraries_dir.join(format!("{id}.sdlibrary"));
+
+		let res = async {
+			target
+				.store()?
+				.download(&snapshot.name, &snapshot_path)
+				.await?;
+
+			backup::decrypt_snapshot(password, &snapshot_path, &db_path).await
+		}
+		.await;
+
+		fs::remove_file(&snapshot_path).await.ok();
+
+		let mut config =
+			serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&res?)?;
+
+		// Snapshots can only be made of libraries with a backup password, which all have an identity
+		let identity = config
+			.get("identity")
+			.cloned()
+			.and_then(|identity| serde_json::from_value::<Vec<u8>>(identity).ok())
+			.ok_or(BackupError::InvalidSnapshot)?;
+
+		let node_cfg = self.node_context.config.get().await;
+		config.insert(
+			"node_id".into(),
+			serde_json::Value::String(node_cfg.id.to_string()),
+		);
+		fs::write(&config_path, serde_json::to_vec(&config)?)
+			.await
+			.map_err(|e| FileIOError::from((&config_path, e)))?;
+
+		let db_url = format!(
+			"file:{}?socket_timeout=15",
+			db_path.as_os_str().to_str().ok_or_else(|| {
+				LibraryManagerError::NonUtf8Path(NonUtf8PathError(db_path.clone().into()))
+			})?
+		);
+		let is_node_in_library = db::load_and_migrate(&db_url)
+			.await?
+			.node()
+			.count(vec![node::pub_id::equals(node_cfg.id.as_bytes().to_vec())])
+			.exec()
+			.await? > 0;
+
+		let library = Self::load(
+			id,
+			&db_path,
+			config_path,
+			self.node_context.clone(),
+			&self.subscribers,
+			(!is_node_in_library).then(|| node::Create {
+				pub_id: node_cfg.id.as_bytes().to_vec(),
+				name: node_cfg.name.clone(),
+				platform: Platform::current() as i32,
+				date_created: Local::now().into(),
+				_params: vec![
+					node::identity::set(Some(identity)),
+					node::node_peer_id::set(Some(node_cfg.keypair.peer_id().to_string())),
+				],
+			}),
+		)
+		.await?;
+
+		info!(
+			"Restored library '{id}' from backup snapshot '{}'",
+			snapshot.name
+		);
+
+		invalidate_query!(library, "library.list");
+
+		let config = library.config.clone();
+		self.libraries.write().await.push(library);
+
+		Ok(LibraryConfigWrapped {
+			uuid: id,
+			config: config.into(),
+		})
+	}
+
+	/// Imports a library from an export and loads it. The locations in `locations` are moved to
+	/// this node, at the given paths, the others are left on the nodes they were on.
+	pub(crate) async fn import(
+		&self,
+		input: &Path,
+		locations: HashMap<Uuid, PathBuf>,
+	) -> Result<LibraryConfigWrapped, LibraryManagerError> {
+		let manifest = export::inspect(input).await?;
+		let id = manifest.library_id;
+		if self.get_library(id).await.is_some() {
+			return Err(ExportError::LibraryExists(id).into());
+		}
+
+		// The new paths are checked before unpacking anything
+		let mut location_paths = Vec::with_capacity(locations.len());
+		for (pub_id, path) in locations {
+			location_paths.push((
+				pub_id,
+				export::location_path(&manifest, pub_id, &path).await?,
+			));
+		}
+
+		let db_path = self.libraries_dir.join(format!("{id}.db"));
+		let config_path = self.libraries_dir.join(format!("{id}.sdlibrary"));
+
+		let node_cfg = self.node_context.config.get().await;
+		let (_, mut config) = export::unpack(
+			input,
+			&db_path,
+			&self
+				.node_context
+				.config
+				.data_directory()
+				.join(THUMBNAIL_CACHE_DIR_NAME),
+		)
+		.await?;
+
+		let identity = config
+			.get("identity")
+			.cloned()
+			.and_then(|identity| serde_json::from_value::<Vec<u8>>(identity).ok())
+			.ok_or(ExportError::InvalidExport)?;
+
+		export::restore_config(&mut config, node_cfg.id);
+		fs::write(&config_path, serde_json::to_vec(&config)?)
+			.await
+			.map_err(|e| FileIOError::from((&config_path, e)))?;
+
+		let db_url = format!(
+			"file:{}?socket_timeout=15",
+			db_path.as_os_str().to_str().ok_or_else(|| {
+				LibraryManagerError::NonUtf8Path(NonUtf8PathError(db_path.clone().into()))
+			})?
+		);
+		let db = db::load_and_migrate(&db_url).await?;
+
+		let node = match db
+			.node()
+			.find_unique(node::pub_id::equals(node_cfg.id.as_bytes().to_vec()))
+			.exec()
+			.await?
+		{
+			Some(node) => node,
+			None => {
+				node::Create {
+					pub_id: node_cfg.id.as_bytes().to_vec(),
+					name: node_cfg.name.clone(),
+					platform: Platform::current() as i32,
+					date_created: Local::now().into(),
+					_params: vec![
+						node::identity::set(Some(identity)),
+						node::node_peer_id::set(Some(node_cfg.keypair.peer_id().to_string())),
+					],
+				}
+				.to_query(&db)
+				.exec()
+				.await?
+			}
+		};
+
+		// Locations belong to a single node, so they aren't synced from here: the node the library
+		// was exported from keeps its own copy of them
+		db._batch(
+			location_paths
+				.into_iter()
+				.map(|(pub_id, path)| {
+					db.location().update(
+						location::pub_id::equals(pub_id.as_bytes().to_vec()),
+						vec![
+							location::path::set(Some(path)),
+							location::node::connect(node::id::equals(node.id)),
+						],
+					)
+				})
+				.collect::<Vec<_>>(),
+		)
+		.await?;
+		drop(db);
+
+		let library = Self::load(
+			id,
+			&db_path,
+			config_path,
+			self.node_context.clone(),
+			&self.subscribers,
+			None,
+		)
+		.await?;
+
+		info!(
+			"Imported library '{id}' exported on {} from '{}'",
+			manifest.date_exported,
+			input.display()
+		);
+
+		invalidate_query!(library, "library.list");
+
+		let config = library.config.clone();
+		self.libraries.write().await.push(library);
+
+		Ok(LibraryConfigWrapped {
+			uuid: id,
+			config: config.into(),
+		})
+	}
+
+	/// Copies a library into a new one, to try risky changes on a disposable copy. The clone has
+	/// its own database and identity, but shares the thumbnails of the node without removing any.
+	/// Its locations are left without a node, so they aren't watched or indexed by the clone.
+	pub(crate) async fn clone_library(
+		&self,
+		id: Uuid,
+		name: Option<String>,
+	) -> Result<LibraryConfigWrapped, LibraryManagerError> {
+		let source = self
+			.get_library(id)
+			.await
+			.ok_or(LibraryManagerError::LibraryNotFound)?;
+
+		let name = name.unwrap_or_else(|| format!("{} (clone)", source.config.name));
+		if name.chars().all(char::is_whitespace) {
+			return Err(LibraryManagerError::InvalidConfig(
+				"name cannot be empty".to_string(),
+			));
+		}
+
+		let clone_id = Uuid::new_v4();
+		let db_path = self.libraries_dir.join(format!("{clone_id}.db"));
+		let config_path = self.libraries_dir.join(format!("{clone_id}.sdlibrary"));
+
+		// The clone of an encrypted library is encrypted with the same key, and unlocked with the
+		// same password
+		if source.config.encryption.is_some() {
+			vfs::unlock_like(&db_path, &self.libraries_dir.join(format!("{id}.db")));
+			if let Some(key) = encryption::recall_key(id) {
+				encryption::remember_key(clone_id, &key)?;
+			}
+		}
+
+		backup::copy_database(&source.db, &db_path).await?;
+
+		let identity = Identity::new().to_bytes();
+
+		let db_url = format!(
+			"file:{}?socket_timeout=15",
+			db_path.as_os_str().to_str().ok_or_else(|| {
+				LibraryManagerError::NonUtf8Path(NonUtf8PathError(db_path.clone().into()))
+			})?
+		);
+		let db = db::load_and_migrate(&db_url).await?;
+
+		// The jobs waiting to resume would run on the files of the source library
+		db._batch((
+			db.node().update(
+				node::id::equals(source.node_local_id),
+				vec![node::identity::set(Some(identity.clone()))],
+			),
+			db.job().delete_many(vec![job::status::in_vec(vec![
+				JobStatus::Queued as i32,
+				JobStatus::Running as i32,
+				JobStatus::Paused as i32,
+			])]),
+		))
+		.await?;
+		db._execute_raw(raw!("UPDATE location SET node_id = NULL"))
+			.exec()
+			.await?;
+		drop(db);
+
+		let config = LibraryConfig {
+			name,
+			identity,
+			backup_targets: vec![],
+			backup_key: None,
+			cloned_from: Some(id),
+			..source.config.clone()
+		};
+		config.save(&config_path)?;
+
+		let library = Self::load(
+			clone_id,
+			&db_path,
+			config_path,
+			self.node_context.clone(),
+			&self.subscribers,
+			None,
+		)
+		.await?;
+
+		info!("Cloned library '{id}' into '{clone_id}'");
+
+		invalidate_query!(library, "library.list");
+
+		let config = library.config.clone();
+		self.libraries.write().await.push(library);
+
+		Ok(LibraryConfigWrapped {
+			uuid: clone_id,
+			config: config.into(),
+		})
+	}
+
+	/// The encrypted libraries waiting for their password
+	pub(crate) async fn get_locked(&self) -> Vec<LockedLibrary> {
+		self.locked.read().await.clone()
+	}
+
+	/// Loads a locked library, unlocking its database with `password`
+	pub(crate) async fn unlock(
+		&self,
+		id: Uuid,
+		password: Protected<String>,
+		remember: bool,
+	) -> Result<LibraryConfigWrapped, LibraryManagerError> {
+		let mut locked = self.locked.write().await;
+		if !locked.iter().any(|library| library.uuid == id) {
+			return Err(LibraryManagerError::LibraryNotFound);
+		}
+
+		let config_path = self.libraries_dir.join(format!("{id}.sdlibrary"));
+		let db_path = self.libraries_dir.join(format!("{id}.db"));
+
+		let key = EncryptedConfig::read(&config_path)
+			.await?
+			.encryption
+			.ok_or_else(|| LibraryManagerError::InvalidConfig("library isn't encrypted".into()))?
+			.unlock(password)
+			.await?;
+
+		if remember {
+			encryption::remember_key(id, &key)?;
+		}
+
+		vfs::unlock(&db_path, &key);
+		let library = match Self::load(
+			id,
+			&db_path,
+			config_path,
+			self.node_context.clone(),
+			&self.subscribers,
+			None,
+		)
+		.await
+		{
+			Ok(library) => library,
+			Err(e) => {
+				vfs::lock(&db_path);
+				return Err(e);
+			}
+		};
+
+		locked.retain(|library| library.uuid != id);
+
+		invalidate_query!(library, "library.list");
+		invalidate_query!(library, "library.locked");
+
+		let config = library.config.clone();
+		self.libraries.write().await.push(library);
+
+		Ok(LibraryConfigWrapped {
+			uuid: id,
+			config: config.into(),
+		})
+	}
+
 	pub async fn delete(&self, id: Uuid) -> Result<(), LibraryManagerError> {
 		let libraries = self.libraries.read().await;
 
@@ -349,7 +961,7 @@ impl LibraryManager {
 			async {
 				fs::remove_file(&db_path)
 					.await
-					.map_err(|e| LibraryManagerError::FileIO(FileIOError::from((db_path, e))))
+					.map_err(|e| LibraryManagerError::FileIO(FileIOError::from((&db_path, e))))
 			},
 			async {
 				fs::remove_file(&sd_lib_path)
@@ -358,6 +970,33 @@ impl LibraryManager {
 			},
 		)?;
 
+		let snapshot_path = db_path.with_extension(db::PRE_MIGRATION_EXTENSION);
+		match fs::remove_file(&snapshot_path).await {
+			Ok(()) => {}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((snapshot_path, e)).into()),
+		}
+
+		if library.config.encryption.is_some() {
+			encryption::forget_key(id);
+			vfs::lock(&db_path);
+		}
+
+		// The secrets aren't in the keychain on platforms without one
+		if let Ok(secrets) = Secrets::new() {
+			for kind in [
+				SecretKind::LibraryIdentity,
+				SecretKind::LibraryBackupKey,
+				SecretKind::LibrarySyncKey,
+			] {
+				secrets.delete(id, kind).ok();
+			}
+
+			for target in &library.config.backup_targets {
+				backup::forget_credentials(&secrets, target.id);
+			}
+		}
+
 		invalidate_query!(library, "library.list");
 
 		self.libraries.write().await.retain(|l| l.id != id);
@@ -365,6 +1004,10 @@ impl LibraryManager {
 		Ok(())
 	}
 
+	pub(crate) async fn get_all_libraries(&self) -> Vec<Library> {
+		self.libraries.read().await.clone()
+	}
+
 	// get_ctx will return the library context for the given library id.
 	pub async fn get_library(&self, library_id: Uuid) -> Option<Library> {
 		self.libraries
@@ -384,26 +1027,22 @@ impl LibraryManager {
 		subscribers: &RwLock<Vec<Box<dyn SubscriberFn>>>,
 		create: Option<node::Create>,
 	) -> Result<Library, LibraryManagerError> {
-		let db_path = db_path.as_ref();
-		let db_url = format!(
-			"file:{}?socket_timeout=15",
-			db_path.as_os_str().to_str().ok_or_else(|| {
-				LibraryManagerError::NonUtf8Path(NonUtf8PathError(db_path.into()))
-			})?
-		);
-		let db = Arc::new(db::load_and_migrate(&db_url).await?);
+		let node_config = node_context.config.get().await;
+		let db =
+			Arc::new(db::load_and_migrate_safely(db_path.as_ref(), &node_config.database).await?);
 
 		if let Some(create) = create {
 			create.to_query(&db).exec().await?;
 		}
 
-		let node_config = node_context.config.get().await;
-		let config = LibraryConfig::load_and_migrate(
+		let mut config = LibraryConfig::load_and_migrate(
 			&config_path,
 			&(node_config.id, node_config.keypair.peer_id(), db.clone()),
 		)
 		.await?;
-		let identity = Arc::new(Identity::from_bytes(&config.identity)?);
+		let identity = Arc::new(library_identity(id, &mut config, &config_path)?);
+		// Moves the secrets still held by the config, like those of a restored library, to the keychain
+		config.save(&config_path)?;
 
 		let node_data = db
 			.node()
@@ -450,14 +1089,21 @@ impl LibraryManager {
 				.await?;
 		}
 
+		let node_id = node_config.id;
+
 		drop(node_config); // Let's be sure not to cause a future deadlock
 
 		// TODO: Move this reconciliation into P2P and do reconciliation of both local and remote nodes.
 
-		// let key_manager = Arc::new(KeyManager::new(vec![]).await?);
-		// seed_keymanager(&db, &key_manager).await?;
+		let key_manager = Arc::new(KeyManager::new(vec![]).await.map_err(KeysError::from)?);
+		keys::seed_key_manager(&db, &key_manager).await?;
 
-		let (sync_manager, sync_rx) = SyncManager::new(&db, id);
+		let (sync_manager, sync_rx) = SyncManager::new(
+			&db,
+			node_id,
+			config.sync_conflict_policy,
+			OperationCipher::new(&config.sync_key)?,
+		);
 
 		Self::emit(
 			subscribers,
@@ -469,9 +1115,11 @@ impl LibraryManager {
 			id,
 			local_id: node_data.id,
 			config,
-			// key_manager,
+			key_manager,
 			sync: Arc::new(sync_manager),
 			orphan_remover: OrphanRemoverActor::spawn(db.clone()),
+			overview: Default::default(),
+			queries: Default::default(),
 			db,
 			node_local_id: node_data.id,
 			node_context,
@@ -510,3 +1158,66 @@ impl LibraryManager {
 		Ok(library)
 	}
 }
+
+/// The P2P identity of the library. It's moved from the config into the OS keychain when there's
+/// one, so the config only holds it on platforms without a keychain. A library whose identity was
+/// lost, like one copied from another device, gets a new one and has to be paired again.
+fn library_identity(
+	id: Uuid,
+	config: &mut LibraryConfig,
+	config_path: &Path,
+) -> Result<Identity, LibraryManagerError> {
+	let secrets = match Secrets::new() {
+		Ok(secrets) => secrets,
+		Err(e) => {
+			debug!("No keychain to keep the identity of library '{id}' in: {e}");
+
+			if config.identity.is_empty() {
+				warn!("The identity of library '{id}' was lost, a new one is generated");
+				config.identity = Identity::new().to_bytes();
+				config.save(config_path)?;
+			}
+
+			return Ok(Identity::from_bytes(&config.identity)?);
+		}
+	};
+
+	if config.identity.is_empty() {
+		return match secrets.retrieve(id, SecretKind::LibraryIdentity) {
+			Ok(identity) => Ok(Identity::from_bytes(identity.expose())?),
+			Err(e) => {
+				warn!("The identity of library '{id}' isn't in the keychain, a new one is generated: {e}");
+
+				let identity = Identity::new();
+				if let Err(e) = secrets.insert(
+					id,
+					SecretKind::LibraryIdentity,
+					&Protected::new(identity.to_bytes()),
+				) {
+					warn!("Failed to put the identity of library '{id}' in the keychain: {e}");
+					config.identity = identity.to_bytes();
+					config.save(config_path)?;
+				}
+
+				Ok(identity)
+			}
+		};
+	}
+
+	let identity = Identity::from_bytes(&config.identity)?;
+
+	match secrets.insert(
+		id,
+		SecretKind::LibraryIdentity,
+		&Protected::new(config.identity.clone()),
+	) {
+		Ok(()) => {
+			config.identity.clear();
+			config.save(config_path)?;
+			debug!("Moved the identity of library '{id}' to the keychain");
+		}
+		Err(e) => warn!("Failed to move the identity of library '{id}' to the keychain: {e}"),
+	}
+
+	Ok(identity)
+}
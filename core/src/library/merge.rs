@@ -0,0 +1,991 @@
+//! Merges another library of this node into a library, for catalogs that were split by accident.
+//!
+//! The locations of this node, tags, spaces, objects and file paths of the other library are
+//! created in this one, keeping their `pub_id`s. Objects with the same content are deduplicated
+//! by `cas_id`, and tags, spaces and locations already in this library by name or path. The other
+//! library isn't changed, it can be deleted once the merge is done.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	prisma::{
+		file_path, location, node, object, object_in_space, space, tag, tag_on_object, PrismaClient,
+	},
+	sync,
+	util::{
+		db::{self, uuid_to_bytes},
+		error::NonUtf8PathError,
+	},
+};
+
+use std::{collections::HashMap, path::PathBuf};
+
+use prisma_client_rust::{operator::or, Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, sync::OnceCell};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// How many objects or file paths of the other library are merged in a step
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Error, Debug)]
+pub enum LibraryMergeError {
+	#[error("a library can't be merged into itself")]
+	SameLibrary,
+	#[error("the database of library '{0}' wasn't found, it must be on this node and unlocked")]
+	SourceNotFound(Uuid),
+	#[error("failed to open the database of the library to merge: {0}")]
+	Migration(#[from] db::MigrationError),
+	#[error(transparent)]
+	NonUtf8Path(#[from] NonUtf8PathError),
+}
+
+/// Merges the library `source_library_id` into the library the job runs in
+pub struct LibraryMergeJob {
+	source: OnceCell<PrismaClient>,
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct LibraryMergeJobInit {
+	pub source_library_id: Uuid,
+}
+
+impl JobInitData for LibraryMergeJobInit {
+	type Job = LibraryMergeJob;
+
+	fn locations(&self) -> JobLocations {
+		// The other library is merged into every part of this one
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LibraryMergeJobData {
+	source_db_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum LibraryMergeJobStep {
+	Locations,
+	Tags,
+	Spaces,
+	/// Merges a batch of objects, with their tags and spaces
+	Objects {
+		skip: i64,
+	},
+	/// Merges a batch of file paths, once their locations and objects are merged
+	FilePaths {
+		skip: i64,
+	},
+}
+
+/// Summary of the merge, with what couldn't be merged as is
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct LibraryMergeJobRunMetadata {
+	locations_created: u32,
+	locations_merged: u32,
+	tags_created: u32,
+	tags_merged: u32,
+	spaces_created: u32,
+	spaces_merged: u32,
+	objects_created: u32,
+	/// Objects with the same content as one already in this library
+	objects_deduplicated: u32,
+	file_paths_created: u32,
+	conflicts: Vec<String>,
+}
+
+impl JobRunMetadata for LibraryMergeJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.locations_created += new_data.locations_created;
+		self.locations_merged += new_data.locations_merged;
+		self.tags_created += new_data.tags_created;
+		self.tags_merged += new_data.tags_merged;
+		self.spaces_created += new_data.spaces_created;
+		self.spaces_merged += new_data.spaces_merged;
+		self.objects_created += new_data.objects_created;
+		self.objects_deduplicated += new_data.objects_deduplicated;
+		self.file_paths_created += new_data.file_paths_created;
+		self.conflicts.extend(new_data.conflicts);
+	}
+}
+
+location::select!(location_to_merge {
+	pub_id
+	name
+	path
+	date_created
+	generate_preview_media
+	sync_preview_media
+	hidden
+	generate_video_thumbnails
+	node: select { pub_id }
+});
+
+object::select!(object_to_merge {
+	pub_id
+	kind
+	hidden
+	favorite
+	important
+	note
+	date_created
+	date_accessed
+	file_paths: select { cas_id }
+	tags: select { tag: select { pub_id name } }
+	spaces: select { space: select { pub_id name } }
+});
+
+file_path::select!(file_path_to_merge {
+	pub_id
+	is_dir
+	cas_id
+	integrity_checksum
+	materialized_path
+	name
+	extension
+	size_in_bytes_bytes
+	inode
+	device
+	date_created
+	date_modified
+	date_indexed
+	location: select { pub_id path }
+	object: select { pub_id }
+});
+
+/// The location of this library that a location of the other library is merged into
+async fn find_location(
+	db: &PrismaClient,
+	pub_id: &[u8],
+	path: Option<&String>,
+) -> Result<Option<location::Data>, QueryError> {
+	let mut params = vec![location::pub_id::equals(pub_id.to_vec())];
+	if let Some(path) = path {
+		params.push(location::path::equals(Some(path.clone())));
+	}
+
+	db.location().find_first(vec![or(params)]).exec().await
+}
+
+/// The object of this library that an object of the other library is merged into, the same one
+/// or one with the same content
+async fn find_object(
+	db: &PrismaClient,
+	pub_id: &[u8],
+	cas_ids: Vec<String>,
+) -> Result<Option<object::Data>, QueryError> {
+	let mut params = vec![object::pub_id::equals(pub_id.to_vec())];
+	if !cas_ids.is_empty() {
+		params.push(object::file_paths::some(vec![file_path::cas_id::in_vec(
+			cas_ids,
+		)]));
+	}
+
+	db.object().find_first(vec![or(params)]).exec().await
+}
+
+impl LibraryMergeJob {
+	async fn source(&self, data: &LibraryMergeJobData) -> Result<&PrismaClient, JobError> {
+		self.source
+			.get_or_try_init(|| async {
+				let db_url = format!(
+					"file:{}?socket_timeout=15",
+					data.source_db_path.to_str().ok_or_else(|| {
+						LibraryMergeError::NonUtf8Path(NonUtf8PathError(
+							data.source_db_path.clone().into(),
+						))
+					})?
+				);
+
+				Ok::<_, JobError>(
+					db::load_and_migrate(&db_url)
+						.await
+						.map_err(LibraryMergeError::from)?,
+				)
+			})
+			.await
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for LibraryMergeJob {
+	type Init = LibraryMergeJobInit;
+	type Data = LibraryMergeJobData;
+	type Step = LibraryMergeJobStep;
+	type RunMetadata = LibraryMergeJobRunMetadata;
+
+	const NAME: &'static str = "library_merge";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {
+			source: OnceCell::new(),
+		}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		if init.source_library_id == ctx.library.id {
+			return Err(LibraryMergeError::SameLibrary.into());
+		}
+
+		let source_db_path = ctx
+			.library
+			.config()
+			.data_directory()
+			.join("libraries")
+			.join(format!("{}.db", init.source_library_id));
+		if fs::metadata(&source_db_path).await.is_err() {
+			return Err(LibraryMergeError::SourceNotFound(init.source_library_id).into());
+		}
+
+		let data = data.insert(LibraryMergeJobData { source_db_path });
+		let source = self.source(data).await?;
+
+		let (objects, file_paths) = tokio::try_join!(
+			source.object().count(vec![]).exec(),
+			source.file_path().count(vec![]).exec(),
+		)?;
+
+		let mut steps = vec![
+			LibraryMergeJobStep::Locations,
+			LibraryMergeJobStep::Tags,
+			LibraryMergeJobStep::Spaces,
+		];
+		steps.extend(
+			(0..objects)
+				.step_by(BATCH_SIZE as usize)
+				.map(|skip| LibraryMergeJobStep::Objects { skip }),
+		);
+		steps.extend(
+			(0..file_paths)
+				.step_by(BATCH_SIZE as usize)
+				.map(|skip| LibraryMergeJobStep::FilePaths { skip }),
+		);
+
+		ctx.progress_msg(job_message!(
+			"merge.merging",
+			objects = objects,
+			file_paths = file_paths
+		));
+
+		Ok((Default::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let source = self.source(data).await?;
+		let library = &ctx.library;
+
+		let metadata = match step {
+			LibraryMergeJobStep::Locations => merge_locations(source, library).await?,
+			LibraryMergeJobStep::Tags => merge_tags(source, library).await?,
+			LibraryMergeJobStep::Spaces => merge_spaces(source, library).await?,
+			LibraryMergeJobStep::Objects { skip } => {
+				ctx.progress_msg(job_message!(
+					"merge.mergingObjects",
+					from = skip,
+					to = skip + BATCH_SIZE
+				));
+				merge_objects(source, library, *skip).await?
+			}
+			LibraryMergeJobStep::FilePaths { skip } => {
+				ctx.progress_msg(job_message!(
+					"merge.mergingFilePaths",
+					from = skip,
+					to = skip + BATCH_SIZE
+				));
+				merge_file_paths(source, library, *skip).await?
+			}
+		};
+
+		Ok(metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let library = &ctx.library;
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Merged library '{}' into '{}': {} objects created, {} deduplicated, {} conflicts",
+			state.init.source_library_id,
+			library.id,
+			metadata.objects_created,
+			metadata.objects_deduplicated,
+			metadata.conflicts.len()
+		);
+
+		invalidate_query!(library, "locations.list");
+		invalidate_query!(library, "tags.list");
+		invalidate_query!(library, "search.paths");
+		invalidate_query!(library, "search.objects");
+
+		Ok(Some(serde_json::to_value(metadata)?))
+	}
+}
+
+async fn merge_locations(
+	source: &PrismaClient,
+	library: &Library,
+) -> Result<LibraryMergeJobRunMetadata, JobError> {
+	let Library { db, sync, .. } = library;
+	let mut metadata = LibraryMergeJobRunMetadata::default();
+
+	let node_pub_id = library.config.node_id.as_bytes().to_vec();
+
+	for location in source
+		.location()
+		.find_many(vec![])
+		.select(location_to_merge::select())
+		.exec()
+		.await?
+	{
+		let name = location.name.clone().unwrap_or_default();
+
+		// Only the paths on this node can be checked, the other nodes add their locations again
+		if location.node.as_ref().map(|node| &node.pub_id) != Some(&node_pub_id) {
+			metadata.conflicts.push(format!(
+				"Location '{name}' is on another device and wasn't merged"
+			));
+			continue;
+		}
+
+		if find_location(db, &location.pub_id, location.path.as_ref())
+			.await?
+			.is_some()
+		{
+			metadata.locations_merged += 1;
+			continue;
+		}
+
+		let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+			(
+				(location::name::NAME, json!(&location.name)),
+				location::name::set(location.name.clone()),
+			),
+			(
+				(location::path::NAME, json!(&location.path)),
+				location::path::set(location.path.clone()),
+			),
+			(
+				(location::date_created::NAME, json!(&location.date_created)),
+				location::date_created::set(location.date_created),
+			),
+			(
+				(
+					location::generate_preview_media::NAME,
+					json!(location.generate_preview_media),
+				),
+				location::generate_preview_media::set(location.generate_preview_media),
+			),
+			(
+				(
+					location::sync_preview_media::NAME,
+					json!(location.sync_preview_media),
+				),
+				location::sync_preview_media::set(location.sync_preview_media),
+			),
+			(
+				(location::hidden::NAME, json!(location.hidden)),
+				location::hidden::set(location.hidden),
+			),
+			(
+				(
+					location::generate_video_thumbnails::NAME,
+					json!(location.generate_video_thumbnails),
+				),
+				location::generate_video_thumbnails::set(location.generate_video_thumbnails),
+			),
+			(
+				(
+					location::node::NAME,
+					json!(sync::node::SyncId {
+						pub_id: uuid_to_bytes(library.id)
+					}),
+				),
+				location::node::connect(node::id::equals(library.node_local_id)),
+			),
+		]
+		.into_iter()
+		.unzip();
+
+		let created = sync
+			.write_op(
+				db,
+				sync.unique_shared_create(
+					sync::location::SyncId {
+						pub_id: location.pub_id.clone(),
+					},
+					sync_params,
+				),
+				db.location().create(location.pub_id, db_params),
+			)
+			.await?;
+
+		if let Err(e) = library
+			.location_manager()
+			.add(created.id, library.clone())
+			.await
+		{
+			error!("Failed to watch merged location '{name}': {e}");
+		}
+
+		metadata.locations_created += 1;
+	}
+
+	Ok(metadata)
+}
+
+async fn merge_tags(
+	source: &PrismaClient,
+	library: &Library,
+) -> Result<LibraryMergeJobRunMetadata, JobError> {
+	let Library { db, sync, .. } = library;
+	let mut metadata = LibraryMergeJobRunMetadata::default();
+
+	for tag in source.tag().find_many(vec![]).exec().await? {
+		let mut params = vec![tag::pub_id::equals(tag.pub_id.clone())];
+		if let Some(name) = &tag.name {
+			params.push(tag::name::equals(Some(name.clone())));
+		}
+
+		if let Some(existing) = db.tag().find_first(vec![or(params)]).exec().await? {
+			if existing.color != tag.color {
+				metadata.conflicts.push(format!(
+					"Tag '{}' has another color in this library, its color was kept",
+					tag.name.unwrap_or_default()
+				));
+			}
+
+			metadata.tags_merged += 1;
+			continue;
+		}
+
+		let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+			(
+				(tag::name::NAME, json!(&tag.name)),
+				tag::name::set(tag.name),
+			),
+			(
+				(tag::color::NAME, json!(&tag.color)),
+				tag::color::set(tag.color),
+			),
+			(
+				(tag::redundancy_goal::NAME, json!(tag.redundancy_goal)),
+				tag::redundancy_goal::set(tag.redundancy_goal),
+			),
+			(
+				(tag::date_created::NAME, json!(&tag.date_created)),
+				tag::date_created::set(tag.date_created),
+			),
+			(
+				(tag::date_modified::NAME, json!(&tag.date_modified)),
+				tag::date_modified::set(tag.date_modified),
+			),
+		]
+		.into_iter()
+		.unzip();
+
+		sync.write_op(
+			db,
+			sync.unique_shared_create(
+				sync::tag::SyncId {
+					pub_id: tag.pub_id.clone(),
+				},
+				sync_params,
+			),
+			db.tag().create(tag.pub_id, db_params),
+		)
+		.await?;
+
+		metadata.tags_created += 1;
+	}
+
+	Ok(metadata)
+}
+
+async fn merge_spaces(
+	source: &PrismaClient,
+	library: &Library,
+) -> Result<LibraryMergeJobRunMetadata, JobError> {
+	let db = &library.db;
+	let mut metadata = LibraryMergeJobRunMetadata::default();
+
+	for space in source.space().find_many(vec![]).exec().await? {
+		let mut params = vec![space::pub_id::equals(space.pub_id.clone())];
+		if let Some(name) = &space.name {
+			params.push(space::name::equals(Some(name.clone())));
+		}
+
+		if let Some(existing) = db.space().find_first(vec![or(params)]).exec().await? {
+			if existing.description != space.description {
+				metadata.conflicts.push(format!(
+					"Space '{}' has another description in this library, its description was kept",
+					space.name.unwrap_or_default()
+				));
+			}
+
+			metadata.spaces_merged += 1;
+			continue;
+		}
+
+		db.space()
+			.create(
+				space.pub_id,
+				vec![
+					space::name::set(space.name),
+					space::description::set(space.description),
+					space::date_created::set(space.date_created),
+					space::date_modified::set(space.date_modified),
+				],
+			)
+			.exec()
+			.await?;
+
+		metadata.spaces_created += 1;
+	}
+
+	Ok(metadata)
+}
+
+async fn merge_objects(
+	source: &PrismaClient,
+	library: &Library,
+	skip: i64,
+) -> Result<LibraryMergeJobRunMetadata, JobError> {
+	let Library { db, sync, .. } = library;
+	let mut metadata = LibraryMergeJobRunMetadata::default();
+
+	// Tags and spaces were merged in the previous steps, by pub_id or by name
+	let (tags, spaces) = tokio::try_join!(
+		db.tag().find_many(vec![]).exec(),
+		db.space().find_many(vec![]).exec(),
+	)?;
+	let find_tag = |pub_id: &[u8], name: &Option<String>| {
+		tags.iter()
+			.find(|tag| tag.pub_id == pub_id)
+			.or_else(|| tags.iter().find(|tag| name.is_some() && &tag.name == name))
+	};
+	let find_space = |pub_id: &[u8], name: &Option<String>| {
+		spaces
+			.iter()
+			.find(|space| space.pub_id == pub_id)
+			.or_else(|| {
+				spaces
+					.iter()
+					.find(|space| name.is_some() && &space.name == name)
+			})
+	};
+
+	let objects = source
+		.object()
+		.find_many(vec![])
+		.order_by(object::id::order(Direction::Asc))
+		.skip(skip)
+		.take(BATCH_SIZE)
+		.select(object_to_merge::select())
+		.exec()
+		.await?;
+
+	for object in objects {
+		let cas_ids = object
+			.file_paths
+			.iter()
+			.filter_map(|file_path| file_path.cas_id.clone())
+			.collect::<Vec<_>>();
+
+		let (object_id, object_pub_id) = match find_object(db, &object.pub_id, cas_ids).await? {
+			Some(existing) => {
+				metadata.objects_deduplicated += 1;
+
+				// Marks set in either library are kept, notes can't be combined
+				let mut sync_params = vec![];
+				let mut db_params = vec![];
+				for (field, set, existing_set, db_param) in [
+					(
+						object::favorite::NAME,
+						object.favorite,
+						existing.favorite,
+						object::favorite::set(Some(true)),
+					),
+					(
+						object::important::NAME,
+						object.important,
+						existing.important,
+						object::important::set(Some(true)),
+					),
+					(
+						object::hidden::NAME,
+						object.hidden,
+						existing.hidden,
+						object::hidden::set(Some(true)),
+					),
+				] {
+					if set == Some(true) && existing_set != Some(true) {
+						sync_params.push((field, json!(true)));
+						db_params.push(db_param);
+					}
+				}
+
+				match (&existing.note, &object.note) {
+					(None, Some(note)) => {
+						sync_params.push((object::note::NAME, json!(note)));
+						db_params.push(object::note::set(Some(note.clone())));
+					}
+					(Some(existing_note), Some(note)) if existing_note != note => {
+						metadata.conflicts.push(format!(
+							"Object '{}' has another note in each library, the note of this library was kept",
+							Uuid::from_slice(&existing.pub_id).unwrap_or_default()
+						));
+					}
+					_ => {}
+				}
+
+				if !db_params.is_empty() {
+					sync.write_ops(
+						db,
+						(
+							sync_params
+								.into_iter()
+								.map(|(field, value)| {
+									sync.shared_update(
+										sync::object::SyncId {
+											pub_id: existing.pub_id.clone(),
+										},
+										field,
+										value,
+									)
+								})
+								.collect(),
+							db.object()
+								.update(object::id::equals(existing.id), db_params),
+						),
+					)
+					.await?;
+				}
+
+				(existing.id, existing.pub_id)
+			}
+			None => {
+				let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+					(
+						(object::kind::NAME, json!(object.kind)),
+						object::kind::set(object.kind),
+					),
+					(
+						(object::hidden::NAME, json!(object.hidden)),
+						object::hidden::set(object.hidden),
+					),
+					(
+						(object::favorite::NAME, json!(object.favorite)),
+						object::favorite::set(object.favorite),
+					),
+					(
+						(object::important::NAME, json!(object.important)),
+						object::important::set(object.important),
+					),
+					(
+						(object::note::NAME, json!(&object.note)),
+						object::note::set(object.note.clone()),
+					),
+					(
+						(object::date_created::NAME, json!(&object.date_created)),
+						object::date_created::set(object.date_created),
+					),
+					(
+						(object::date_accessed::NAME, json!(&object.date_accessed)),
+						object::date_accessed::set(object.date_accessed),
+					),
+				]
+				.into_iter()
+				.unzip();
+
+				let created = sync
+					.write_op(
+						db,
+						sync.unique_shared_create(
+							sync::object::SyncId {
+								pub_id: object.pub_id.clone(),
+							},
+							sync_params,
+						),
+						db.object().create(object.pub_id.clone(), db_params),
+					)
+					.await?;
+
+				metadata.objects_created += 1;
+
+				(created.id, created.pub_id)
+			}
+		};
+
+		let Ok(object_uuid) = Uuid::from_slice(&object_pub_id) else {
+			continue;
+		};
+
+		let object_tags = object
+			.tags
+			.iter()
+			.filter_map(|tag_on_object| {
+				find_tag(&tag_on_object.tag.pub_id, &tag_on_object.tag.name)
+			})
+			.filter_map(|tag| Some((tag.id, Uuid::from_slice(&tag.pub_id).ok()?)))
+			.collect::<Vec<_>>();
+
+		if !object_tags.is_empty() {
+			sync.write_ops(
+				db,
+				(
+					object_tags
+						.iter()
+						.map(|(_, tag_pub_id)| {
+							sync.relation_create(tag_on_object::NAME, *tag_pub_id, object_uuid)
+						})
+						.collect(),
+					db.tag_on_object()
+						.create_many(
+							object_tags
+								.iter()
+								.map(|&(tag_id, _)| tag_on_object::CreateUnchecked {
+									tag_id,
+									object_id,
+									_params: vec![],
+								})
+								.collect(),
+						)
+						.skip_duplicates(),
+				),
+			)
+			.await?;
+		}
+
+		let object_spaces = object
+			.spaces
+			.iter()
+			.filter_map(|object_in_space| {
+				find_space(&object_in_space.space.pub_id, &object_in_space.space.name)
+			})
+			.map(|space| object_in_space::CreateUnchecked {
+				space_id: space.id,
+				object_id,
+				_params: vec![],
+			})
+			.collect::<Vec<_>>();
+
+		if !object_spaces.is_empty() {
+			db.object_in_space()
+				.create_many(object_spaces)
+				.skip_duplicates()
+				.exec()
+				.await?;
+		}
+	}
+
+	Ok(metadata)
+}
+
+async fn merge_file_paths(
+	source: &PrismaClient,
+	library: &Library,
+	skip: i64,
+) -> Result<LibraryMergeJobRunMetadata, JobError> {
+	let Library { db, sync, .. } = library;
+	let mut metadata = LibraryMergeJobRunMetadata::default();
+
+	let file_paths = source
+		.file_path()
+		.find_many(vec![])
+		.order_by(file_path::id::order(Direction::Asc))
+		.skip(skip)
+		.take(BATCH_SIZE)
+		.select(file_path_to_merge::select())
+		.exec()
+		.await?;
+
+	let existing = db
+		.file_path()
+		.find_many(vec![file_path::pub_id::in_vec(
+			file_paths
+				.iter()
+				.map(|file_path| file_path.pub_id.clone())
+				.collect(),
+		)])
+		.select(file_path::select!({ pub_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|file_path| file_path.pub_id)
+		.collect::<Vec<_>>();
+
+	let mut locations = HashMap::new();
+	let mut sync_ops = vec![];
+	let mut db_creates = vec![];
+
+	for file_path in file_paths
+		.into_iter()
+		.filter(|file_path| !existing.contains(&file_path.pub_id))
+	{
+		// File paths of the locations that weren't merged stay out
+		let Some(source_location) = file_path.location else {
+			continue;
+		};
+		if !locations.contains_key(&source_location.pub_id) {
+			let location =
+				find_location(db, &source_location.pub_id, source_location.path.as_ref())
+					.await?
+					.map(|location| (location.id, location.pub_id));
+
+			locations.insert(source_location.pub_id.clone(), location);
+		}
+		let Some((location_id, location_pub_id)) = locations[&source_location.pub_id].clone()
+		else {
+			continue;
+		};
+
+		let object = match &file_path.object {
+			Some(object) => {
+				find_object(
+					db,
+					&object.pub_id,
+					file_path.cas_id.iter().cloned().collect(),
+				)
+				.await?
+			}
+			None => None,
+		};
+
+		let mut params = vec![
+			(
+				(
+					file_path::location::NAME,
+					json!(sync::location::SyncId {
+						pub_id: location_pub_id
+					}),
+				),
+				file_path::location_id::set(Some(location_id)),
+			),
+			(
+				(file_path::is_dir::NAME, json!(file_path.is_dir)),
+				file_path::is_dir::set(file_path.is_dir),
+			),
+			(
+				(file_path::cas_id::NAME, json!(&file_path.cas_id)),
+				file_path::cas_id::set(file_path.cas_id),
+			),
+			(
+				(
+					file_path::integrity_checksum::NAME,
+					json!(&file_path.integrity_checksum),
+				),
+				file_path::integrity_checksum::set(file_path.integrity_checksum),
+			),
+			(
+				(
+					file_path::materialized_path::NAME,
+					json!(&file_path.materialized_path),
+				),
+				file_path::materialized_path::set(file_path.materialized_path),
+			),
+			(
+				(file_path::name::NAME, json!(&file_path.name)),
+				file_path::name::set(file_path.name),
+			),
+			(
+				(file_path::extension::NAME, json!(&file_path.extension)),
+				file_path::extension::set(file_path.extension),
+			),
+			(
+				(
+					file_path::size_in_bytes_bytes::NAME,
+					json!(&file_path.size_in_bytes_bytes),
+				),
+				file_path::size_in_bytes_bytes::set(file_path.size_in_bytes_bytes),
+			),
+			(
+				(file_path::inode::NAME, json!(&file_path.inode)),
+				file_path::inode::set(file_path.inode),
+			),
+			(
+				(file_path::device::NAME, json!(&file_path.device)),
+				file_path::device::set(file_path.device),
+			),
+			(
+				(
+					file_path::date_created::NAME,
+					json!(&file_path.date_created),
+				),
+				file_path::date_created::set(file_path.date_created),
+			),
+			(
+				(
+					file_path::date_modified::NAME,
+					json!(&file_path.date_modified),
+				),
+				file_path::date_modified::set(file_path.date_modified),
+			),
+			(
+				(
+					file_path::date_indexed::NAME,
+					json!(&file_path.date_indexed),
+				),
+				file_path::date_indexed::set(file_path.date_indexed),
+			),
+		];
+		if let Some(object) = object {
+			params.push((
+				(
+					file_path::object::NAME,
+					json!(sync::object::SyncId {
+						pub_id: object.pub_id
+					}),
+				),
+				file_path::object_id::set(Some(object.id)),
+			));
+		}
+
+		let (sync_params, db_params): (Vec<_>, Vec<_>) = params.into_iter().unzip();
+
+		sync_ops.push(sync.unique_shared_create(
+			sync::file_path::SyncId {
+				pub_id: file_path.pub_id.clone(),
+			},
+			sync_params,
+		));
+		db_creates.push(file_path::create_unchecked(file_path.pub_id, db_params));
+	}
+
+	if !db_creates.is_empty() {
+		// Paths already indexed in a location merged by path are skipped as duplicates
+		metadata.file_paths_created = sync
+			.write_ops(
+				db,
+				(
+					sync_ops,
+					db.file_path().create_many(db_creates).skip_duplicates(),
+				),
+			)
+			.await? as u32;
+	}
+
+	Ok(metadata)
+}
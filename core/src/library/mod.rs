@@ -1,10 +1,31 @@
+pub mod activity;
+pub mod backup;
 pub(crate) mod cat;
+pub mod catalog_import;
+pub mod cleanup;
 mod config;
+pub mod encryption;
+pub mod export;
+pub mod integrity;
+pub mod journal;
+pub mod keys;
 #[allow(clippy::module_inception)]
 mod library;
+pub mod maintenance;
 mod manager;
+pub mod merge;
+pub mod notifications;
+mod overview;
+mod query_cache;
+pub mod rules;
+mod settings;
+pub mod trash;
+pub mod webhooks;
 
 pub use cat::*;
 pub use config::*;
 pub use library::*;
 pub use manager::*;
+pub use overview::*;
+pub use query_cache::*;
+pub use settings::*;
@@ -0,0 +1,44 @@
+//! Things happening in the background of a library that the user should hear about, sent to the
+//! clients listening as they're raised. They aren't kept, the jobs raising them keep their
+//! findings in their own metadata.
+
+use crate::{api::CoreEvent, prisma::location};
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::Library;
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct Notification {
+	pub library_id: Uuid,
+	pub data: NotificationData,
+	pub date_created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum NotificationData {
+	/// Files whose contents don't match their integrity checksum anymore
+	IntegrityMismatches {
+		location_id: location::id::Type,
+		/// Changed without their size or modification date changing, likely damaged on disk
+		corrupted: Vec<PathBuf>,
+		/// Changed without Spacedrive noticing, their checksums were updated
+		changed: Vec<PathBuf>,
+	},
+}
+
+impl Library {
+	pub(crate) fn notify(&self, data: NotificationData) {
+		self.emit(CoreEvent::Notification(Notification {
+			library_id: self.id,
+			data,
+			date_created: Utc::now(),
+		}));
+	}
+}
@@ -0,0 +1,322 @@
+//! The figures shown on the overview of a library, in a single payload.
+//!
+//! Counting the files and adding up their sizes means reading every file path of the library, so
+//! those figures are cached and only computed again once the files changed, and no more than once
+//! every [`MIN_REFRESH_INTERVAL_SECS`]. The jobs and sync figures are cheap and always fresh.
+
+use crate::{
+	api::utils::InvalidateOperationEvent,
+	job::JobStatus,
+	p2p::P2PManager,
+	prisma::{file_path, job, location, node, object, sync_conflict},
+	util::db::{chain_optional_iter, keyset_pages},
+};
+
+use std::{
+	collections::HashMap,
+	pin::pin,
+	str::FromStr,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use futures::TryStreamExt;
+use prisma_client_rust::{Direction, QueryError};
+use sd_p2p::PeerId;
+use serde::Serialize;
+use specta::Type;
+use tokio::sync::Mutex;
+
+use super::Library;
+
+/// The least time between two computations of the files figures, so indexing a location doesn't
+/// recompute them for every batch of files
+const MIN_REFRESH_INTERVAL_SECS: i64 = 60;
+/// How many days of activity are in the overview
+const ACTIVITY_DAYS: i64 = 14;
+/// How many days of jobs the job health is computed over
+const JOB_HEALTH_DAYS: i64 = 7;
+/// The file paths read at once, libraries can have millions of them
+const PAGE_SIZE: i64 = 10_000;
+
+/// Queries that are invalidated when the files of the library change
+const FILE_QUERIES: [&str; 4] = [
+	"search.paths",
+	"search.objects",
+	"locations.list",
+	"locations.get",
+];
+
+file_path::select!(file_path_for_overview { id location_id size_in_bytes_bytes date_indexed });
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct LibraryOverview {
+	pub files: FilesOverview,
+	pub jobs: JobsOverview,
+	pub sync: SyncOverview,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct FilesOverview {
+	pub objects: u32,
+	pub files: u32,
+	pub total_bytes: String,
+	pub locations: Vec<LocationStorage>,
+	/// The files indexed each of the last days, oldest first
+	pub activity: Vec<ActivityDay>,
+	/// When these figures were computed, they can be a little behind the files
+	pub date_computed: DateTime<Utc>,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct LocationStorage {
+	pub id: location::id::Type,
+	pub name: Option<String>,
+	pub files: u32,
+	pub total_bytes: String,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct ActivityDay {
+	pub date: NaiveDate,
+	pub files_indexed: u32,
+}
+
+/// The jobs of the last days, by how they ended
+#[derive(Serialize, Type, Debug, Clone, Default)]
+pub struct JobsOverview {
+	pub running: u32,
+	pub queued: u32,
+	pub paused: u32,
+	pub completed: u32,
+	pub completed_with_errors: u32,
+	pub failed: u32,
+	pub canceled: u32,
+}
+
+#[derive(Serialize, Type, Debug, Clone, Default)]
+pub struct SyncOverview {
+	pub paired_nodes: u32,
+	/// Paired nodes that missed some operations
+	pub behind_nodes: u32,
+	pub pending_conflicts: u32,
+	/// The last exchange of operations with any paired node
+	pub last_exchange: Option<DateTime<Utc>>,
+}
+
+/// The files figures of a library, kept until its files change
+#[derive(Debug, Default)]
+pub struct OverviewCache {
+	files: Mutex<Option<FilesOverview>>,
+	stale: AtomicBool,
+}
+
+impl OverviewCache {
+	/// Marks the files figures as out of date if the invalidated query shows the files
+	pub(super) fn invalidated(&self, event: &InvalidateOperationEvent) {
+		if FILE_QUERIES.contains(&event.key()) {
+			self.stale.store(true, Ordering::Relaxed);
+		}
+	}
+
+	async fn files(&self, library: &Library) -> Result<FilesOverview, QueryError> {
+		let mut files = self.files.lock().await;
+
+		match &*files {
+			Some(overview)
+				if !self.stale.load(Ordering::Relaxed)
+					|| Utc::now() - overview.date_computed
+						< Duration::seconds(MIN_REFRESH_INTERVAL_SECS) =>
+			{
+				Ok(overview.clone())
+			}
+			_ => {
+				// Changes made while computing mark the figures as stale again
+				self.stale.store(false, Ordering::Relaxed);
+
+				let overview = compute_files(library).await?;
+				*files = Some(overview.clone());
+
+				Ok(overview)
+			}
+		}
+	}
+}
+
+async fn compute_files(library: &Library) -> Result<FilesOverview, QueryError> {
+	let db = &library.db;
+	let now = Utc::now();
+	let first_day = (now - Duration::days(ACTIVITY_DAYS - 1)).date_naive();
+
+	let (objects, locations) = tokio::try_join!(
+		db.object()
+			.count(vec![object::date_deleted::equals(None)])
+			.exec(),
+		db.location()
+			.find_many(vec![])
+			.select(location::select!({ id name }))
+			.exec(),
+	)?;
+
+	let mut storage = locations
+		.into_iter()
+		.map(|location| (location.id, (location.name, 0u32, 0u64)))
+		.collect::<HashMap<_, _>>();
+	let mut activity = vec![0u32; ACTIVITY_DAYS as usize];
+	let mut total_bytes = 0u64;
+
+	let mut files = 0u32;
+	let mut pages = pin!(keyset_pages(
+		PAGE_SIZE,
+		|file_path: &file_path_for_overview::Data| file_path.id,
+		|after, take| {
+			db.file_path()
+				.find_many(chain_optional_iter(
+					[file_path::is_dir::equals(Some(false))],
+					[after.map(file_path::id::gt)],
+				))
+				.order_by(file_path::id::order(Direction::Asc))
+				.take(take)
+				.select(file_path_for_overview::select())
+				.exec()
+		},
+	));
+	while let Some(file_paths) = pages.try_next().await? {
+		for file_path in file_paths {
+			files += 1;
+
+			let size = file_path
+				.size_in_bytes_bytes
+				.as_deref()
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(u64::from_be_bytes)
+				.unwrap_or_default();
+			total_bytes += size;
+
+			if let Some((_, files, bytes)) = file_path
+				.location_id
+				.and_then(|location_id| storage.get_mut(&location_id))
+			{
+				*files += 1;
+				*bytes += size;
+			}
+
+			if let Some(day) = file_path
+				.date_indexed
+				.and_then(|date| usize::try_from((date.date_naive() - first_day).num_days()).ok())
+			{
+				if let Some(count) = activity.get_mut(day) {
+					*count += 1;
+				}
+			}
+		}
+	}
+
+	let mut locations = storage
+		.into_iter()
+		.map(|(id, (name, files, bytes))| LocationStorage {
+			id,
+			name,
+			files,
+			total_bytes: bytes.to_string(),
+		})
+		.collect::<Vec<_>>();
+	locations.sort_by_key(|location| location.id);
+
+	Ok(FilesOverview {
+		objects: objects as u32,
+		files,
+		total_bytes: total_bytes.to_string(),
+		locations,
+		activity: activity
+			.into_iter()
+			.enumerate()
+			.map(|(i, files_indexed)| ActivityDay {
+				date: first_day + Duration::days(i as i64),
+				files_indexed,
+			})
+			.collect(),
+		date_computed: now,
+	})
+}
+
+async fn compute_jobs(library: &Library) -> Result<JobsOverview, QueryError> {
+	let jobs = library
+		.db
+		.job()
+		.find_many(vec![job::date_created::gte(
+			(Utc::now() - Duration::days(JOB_HEALTH_DAYS)).into(),
+		)])
+		.select(job::select!({ status }))
+		.exec()
+		.await?;
+
+	let mut overview = JobsOverview::default();
+	for status in jobs.into_iter().filter_map(|job| job.status) {
+		let count = match JobStatus::try_from(status) {
+			Ok(JobStatus::Running) => &mut overview.running,
+			Ok(JobStatus::Queued) => &mut overview.queued,
+			Ok(JobStatus::Paused) => &mut overview.paused,
+			Ok(JobStatus::Completed) => &mut overview.completed,
+			Ok(JobStatus::CompletedWithErrors) => &mut overview.completed_with_errors,
+			Ok(JobStatus::Failed) => &mut overview.failed,
+			Ok(JobStatus::Canceled) => &mut overview.canceled,
+			Err(_) => continue,
+		};
+		*count += 1;
+	}
+
+	Ok(overview)
+}
+
+async fn compute_sync(library: &Library, p2p: &P2PManager) -> Result<SyncOverview, QueryError> {
+	let (nodes, pending_conflicts) = tokio::try_join!(
+		library
+			.db
+			.node()
+			.find_many(vec![node::pub_id::not(
+				library.config.node_id.as_bytes().to_vec(),
+			)])
+			.select(node::select!({ node_peer_id }))
+			.exec(),
+		library
+			.db
+			.sync_conflict()
+			.count(vec![sync_conflict::date_resolved::equals(None)])
+			.exec(),
+	)?;
+
+	let mut overview = SyncOverview {
+		paired_nodes: nodes.len() as u32,
+		pending_conflicts: pending_conflicts as u32,
+		..Default::default()
+	};
+
+	for peer_id in nodes
+		.iter()
+		.filter_map(|node| node.node_peer_id.as_deref())
+		.filter_map(|peer_id| PeerId::from_str(peer_id).ok())
+	{
+		if p2p.sync_scheduler.is_behind(peer_id) {
+			overview.behind_nodes += 1;
+		}
+
+		overview.last_exchange = overview
+			.last_exchange
+			.max(p2p.sync_metrics.get(library.id, peer_id).last_exchange);
+	}
+
+	Ok(overview)
+}
+
+impl LibraryOverview {
+	pub(crate) async fn get(library: &Library, p2p: &P2PManager) -> Result<Self, QueryError> {
+		let (files, jobs, sync) = tokio::try_join!(
+			library.overview.files(library),
+			compute_jobs(library),
+			compute_sync(library, p2p),
+		)?;
+
+		Ok(Self { files, jobs, sync })
+	}
+}
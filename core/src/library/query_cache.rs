@@ -0,0 +1,158 @@
+//! Results of the queries run on every navigation of the explorer and the overview, like the
+//! locations and the tags, kept in memory so they don't reach the database each time.
+//!
+//! A result depends on the queries whose invalidation means it changed, and is dropped as soon as
+//! one of them is invalidated. The changes that aren't announced are picked up once the result is
+//! older than [`MAX_AGE`].
+
+use crate::api::utils::InvalidateOperationEvent;
+
+use std::{
+	any::Any,
+	collections::HashMap,
+	future::Future,
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant},
+};
+
+/// How long a result is kept when nothing invalidated it
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+struct CachedResult {
+	value: Arc<dyn Any + Send + Sync>,
+	/// The queries whose invalidation drops the result
+	depends_on: &'static [&'static str],
+	date_cached: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+	results: HashMap<String, CachedResult>,
+	/// How many times each query was invalidated, so a result computed across an invalidation of
+	/// one of its queries isn't kept, it may have been read before the change
+	invalidations: HashMap<&'static str, u64>,
+}
+
+impl Entries {
+	fn invalidations_of(&self, depends_on: &[&str]) -> u64 {
+		depends_on
+			.iter()
+			.filter_map(|key| self.invalidations.get(key))
+			.sum()
+	}
+}
+
+#[derive(Default)]
+pub struct QueryCache {
+	entries: Mutex<Entries>,
+}
+
+impl QueryCache {
+	/// Drops the results depending on the invalidated query
+	pub(super) fn invalidated(&self, event: &InvalidateOperationEvent) {
+		let key = event.key();
+		let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+		*entries.invalidations.entry(key).or_default() += 1;
+		entries
+			.results
+			.retain(|_, result| !result.depends_on.contains(&key));
+	}
+
+	/// The cached result of the query with this key, or the one of `query` which is then kept until
+	/// one of the queries of `depends_on` is invalidated. Errors aren't kept.
+	pub async fn get_or_query<T, E, Fut>(
+		&self,
+		key: impl Into<String>,
+		depends_on: &'static [&'static str],
+		query: impl FnOnce() -> Fut,
+	) -> Result<T, E>
+	where
+		T: Clone + Send + Sync + 'static,
+		Fut: Future<Output = Result<T, E>>,
+	{
+		let key = key.into();
+
+		let invalidations = {
+			let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+			if let Some(value) = entries
+				.results
+				.get(&key)
+				.filter(|result| result.date_cached.elapsed() < MAX_AGE)
+				.and_then(|result| result.value.downcast_ref::<T>())
+			{
+				return Ok(value.clone());
+			}
+
+			entries.invalidations_of(depends_on)
+		};
+
+		let value = query().await?;
+
+		let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+		if entries.invalidations_of(depends_on) == invalidations {
+			entries.results.insert(
+				key,
+				CachedResult {
+					value: Arc::new(value.clone()),
+					depends_on,
+					date_cached: Instant::now(),
+				},
+			);
+		}
+
+		Ok(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::Value;
+
+	fn invalidation(key: &'static str) -> InvalidateOperationEvent {
+		InvalidateOperationEvent::dangerously_create(key, Value::Null, None)
+	}
+
+	async fn query(cache: &QueryCache, value: u32) -> u32 {
+		cache
+			.get_or_query(
+				"tags.list",
+				&["tags.list"],
+				|| async move { Ok::<_, ()>(value) },
+			)
+			.await
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn invalidations_drop_the_results() {
+		let cache = QueryCache::default();
+
+		assert_eq!(query(&cache, 1).await, 1);
+		assert_eq!(query(&cache, 2).await, 1);
+
+		cache.invalidated(&invalidation("locations.list"));
+		assert_eq!(query(&cache, 2).await, 1);
+
+		cache.invalidated(&invalidation("tags.list"));
+		assert_eq!(query(&cache, 2).await, 2);
+	}
+
+	#[tokio::test]
+	async fn results_read_across_an_invalidation_are_not_kept() {
+		let cache = QueryCache::default();
+
+		let value = cache
+			.get_or_query("tags.list", &["tags.list"], || async {
+				cache.invalidated(&invalidation("tags.list"));
+				Ok::<_, ()>(1)
+			})
+			.await
+			.unwrap();
+		assert_eq!(value, 1);
+		assert_eq!(query(&cache, 2).await, 2);
+	}
+}
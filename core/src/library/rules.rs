@@ -0,0 +1,445 @@
+//! Rules running actions when events of the library match their filters, like "when a PDF appears
+//! in this location, extract its text with a plugin then tag it `Inbox`". Files appearing are the
+//! ones the location watcher finds, not the ones a scan indexes.
+//!
+//! The actions of a rule run in order, and the first one failing stops the others. Jobs are only
+//! spawned, the next actions don't wait for them to be done. A rule run on the completion of a job
+//! that spawns a job of the same name runs again each time, forever, so such rules are refused.
+
+use crate::{
+	invalidate_query,
+	library::webhooks::{self, WebhookEvent},
+	plugins::{metadata_job::PluginMetadataJobInit, plugin_job::PluginJobInit},
+	prisma::{action_rule, file_path, location, object, tag, tag_on_object},
+	sync::SyncError,
+};
+
+use std::{path::PathBuf, process::Stdio};
+
+use chrono::Utc;
+use globset::Glob;
+use prisma_client_rust::QueryError;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::process::Command;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::Library;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, Eq, PartialEq)]
+pub enum RuleTrigger {
+	FileCreated = 0,
+	JobCompleted = 1,
+}
+
+impl TryFrom<i32> for RuleTrigger {
+	type Error = i32;
+
+	fn try_from(value: i32) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::FileCreated,
+			1 => Self::JobCompleted,
+			_ => return Err(value),
+		})
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum RuleAction {
+	/// Runs a program, without a shell. `{path}` in its arguments is replaced by the path of the
+	/// file of the event, and the event is in the `SD_EVENT` environment variable as JSON.
+	Command { program: String, args: Vec<String> },
+	/// Posts the event as JSON to the URL
+	Http { url: String },
+	/// Tags the object of the file of the event
+	Tag { tag_id: tag::id::Type },
+	/// Extracts the metadata of the files of the location of the event with plugins, or with the
+	/// plugin given
+	ExtractMetadata { plugin_id: Option<String> },
+	/// Runs a job of a plugin, with the event as its arguments
+	PluginJob { plugin_id: String, job: String },
+}
+
+/// What happened, as sent to the actions
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum RuleEvent {
+	FileCreated {
+		location_id: location::id::Type,
+		file_path_id: file_path::id::Type,
+		object_id: Option<object::id::Type>,
+		path: PathBuf,
+	},
+	JobCompleted {
+		job_id: Uuid,
+		name: String,
+		with_errors: bool,
+	},
+}
+
+impl RuleEvent {
+	fn trigger(&self) -> RuleTrigger {
+		match self {
+			Self::FileCreated { .. } => RuleTrigger::FileCreated,
+			Self::JobCompleted { .. } => RuleTrigger::JobCompleted,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum RuleError {
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
+	#[error("invalid pattern: {0}")]
+	Pattern(#[from] globset::Error),
+	#[error("invalid actions: {0}")]
+	Actions(#[from] serde_json::Error),
+	#[error("a rule needs at least one action")]
+	NoActions,
+	#[error("a rule run when a job is done can't run a job of the same name")]
+	JobLoop,
+	#[error("rule not found: {0}")]
+	NotFound(action_rule::id::Type),
+	#[error("'{0}' failed: {1}")]
+	Command(String, std::io::Error),
+	#[error("'{0}' exited with {1}")]
+	CommandStatus(String, std::process::ExitStatus),
+	#[error("request failed: {0}")]
+	Http(#[from] reqwest::Error),
+	#[error("the event has no object to tag")]
+	NoObject,
+	#[error("the event has no location")]
+	NoLocation,
+	#[error("failed to spawn the job: {0}")]
+	Job(#[from] crate::job::JobManagerError),
+}
+
+impl From<RuleError> for rspc::Error {
+	fn from(e: RuleError) -> Self {
+		let code = match e {
+			RuleError::NotFound(_) => rspc::ErrorCode::NotFound,
+			RuleError::Pattern(_)
+			| RuleError::Actions(_)
+			| RuleError::NoActions
+			| RuleError::JobLoop => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Checks a rule before it's saved, returning its actions as they're stored
+pub fn validate(
+	trigger: RuleTrigger,
+	pattern: Option<&str>,
+	job_name: Option<&str>,
+	actions: &[RuleAction],
+) -> Result<Vec<u8>, RuleError> {
+	if actions.is_empty() {
+		return Err(RuleError::NoActions);
+	}
+
+	if let Some(pattern) = pattern {
+		Glob::new(pattern)?;
+	}
+
+	if trigger == RuleTrigger::JobCompleted {
+		let spawns = |name: &str| {
+			actions.iter().any(|action| match action {
+				RuleAction::ExtractMetadata { .. } => name == "plugin_metadata",
+				RuleAction::PluginJob { .. } => name == "plugin",
+				_ => false,
+			})
+		};
+
+		// Without a job name, the rule runs on the jobs it spawns too
+		if job_name.map_or_else(|| spawns("plugin_metadata") || spawns("plugin"), spawns) {
+			return Err(RuleError::JobLoop);
+		}
+	}
+
+	Ok(serde_json::to_vec(actions)?)
+}
+
+fn matches(rule: &action_rule::Data, event: &RuleEvent) -> bool {
+	match event {
+		RuleEvent::FileCreated {
+			location_id, path, ..
+		} => {
+			rule.location_id.map_or(true, |id| id == *location_id)
+				&& rule.pattern.as_deref().map_or(true, |pattern| {
+					let name = path.file_name().unwrap_or_default();
+					Glob::new(pattern)
+						.map(|glob| glob.compile_matcher().is_match(name))
+						.unwrap_or(false)
+				})
+		}
+		RuleEvent::JobCompleted { name, .. } => rule
+			.job_name
+			.as_deref()
+			.map_or(true, |job_name| job_name == name),
+	}
+}
+
+/// Runs the enabled rules matching `event`, in the background
+pub(crate) fn trigger(library: &Library, event: RuleEvent) {
+	let library = library.clone();
+
+	tokio::spawn(async move {
+		if let Err(e) = run_matching(&library, &event).await {
+			warn!("Failed to run the rules of {:?}: {e}", event.trigger());
+		}
+	});
+}
+
+async fn run_matching(library: &Library, event: &RuleEvent) -> Result<(), RuleError> {
+	let rules = library
+		.db
+		.action_rule()
+		.find_many(vec![
+			action_rule::enabled::equals(true),
+			action_rule::trigger::equals(event.trigger() as i32),
+		])
+		.exec()
+		.await?;
+
+	for rule in rules.into_iter().filter(|rule| matches(rule, event)) {
+		debug!("Running rule '{}'", rule.name);
+
+		if let Err(e) = run(library, &rule, event).await {
+			warn!("Rule '{}' failed: {e}", rule.name);
+		}
+	}
+
+	Ok(())
+}
+
+/// Runs the actions of `rule` for `event`, stopping at the first one failing
+pub async fn run(
+	library: &Library,
+	rule: &action_rule::Data,
+	event: &RuleEvent,
+) -> Result<(), RuleError> {
+	let actions = serde_json::from_slice::<Vec<RuleAction>>(&rule.actions)?;
+
+	library
+		.db
+		.action_rule()
+		.update(
+			action_rule::id::equals(rule.id),
+			vec![action_rule::date_last_run::set(Some(Utc::now().into()))],
+		)
+		.exec()
+		.await?;
+
+	for action in actions {
+		run_action(library, action, event).await?;
+	}
+
+	Ok(())
+}
+
+async fn run_action(
+	library: &Library,
+	action: RuleAction,
+	event: &RuleEvent,
+) -> Result<(), RuleError> {
+	let event_json = serde_json::to_string(event)?;
+
+	match action {
+		RuleAction::Command { program, args } => {
+			let path = match event {
+				RuleEvent::FileCreated { path, .. } => path.to_string_lossy().into_owned(),
+				RuleEvent::JobCompleted { .. } => String::new(),
+			};
+
+			let status = Command::new(&program)
+				.args(args.iter().map(|arg| arg.replace("{path}", &path)))
+				.env("SD_EVENT", &event_json)
+				.stdin(Stdio::null())
+				.kill_on_drop(true)
+				.status()
+				.await
+				.map_err(|e| RuleError::Command(program.clone(), e))?;
+
+			if !status.success() {
+				return Err(RuleError::CommandStatus(program, status));
+			}
+		}
+		RuleAction::Http { url } => {
+			reqwest::Client::new()
+				.post(url)
+				.header(CONTENT_TYPE, "application/json")
+				.body(event_json)
+				.send()
+				.await?
+				.error_for_status()?;
+		}
+		RuleAction::Tag { tag_id } => {
+			let RuleEvent::FileCreated {
+				object_id: Some(object_id),
+				..
+			} = event
+			else {
+				return Err(RuleError::NoObject);
+			};
+
+			tag_object(library, tag_id, *object_id).await?;
+		}
+		RuleAction::ExtractMetadata { plugin_id } => {
+			let RuleEvent::FileCreated { location_id, .. } = event else {
+				return Err(RuleError::NoLocation);
+			};
+
+			library
+				.spawn_job(PluginMetadataJobInit {
+					location_id: *location_id,
+					plugin_id,
+				})
+				.await?;
+		}
+		RuleAction::PluginJob { plugin_id, job } => {
+			library
+				.spawn_job(PluginJobInit {
+					plugin_id,
+					job,
+					args: event_json,
+				})
+				.await?;
+		}
+	}
+
+	Ok(())
+}
+
+async fn tag_object(
+	library: &Library,
+	tag_id: tag::id::Type,
+	object_id: object::id::Type,
+) -> Result<(), RuleError> {
+	let Library { db, sync, .. } = library;
+
+	let tag = db
+		.tag()
+		.find_unique(tag::id::equals(tag_id))
+		.select(tag::select!({ pub_id }))
+		.exec()
+		.await?;
+	let object = db
+		.object()
+		.find_unique(object::id::equals(object_id))
+		.select(object::select!({ pub_id }))
+		.exec()
+		.await?;
+
+	// The tag or the object was deleted since
+	let (Some(tag), Some(object)) = (tag, object) else {
+		return Ok(());
+	};
+	let (Ok(tag_pub_id), Ok(object_pub_id)) = (
+		Uuid::from_slice(&tag.pub_id),
+		Uuid::from_slice(&object.pub_id),
+	) else {
+		return Ok(());
+	};
+
+	sync.write_ops(
+		db,
+		(
+			vec![sync.relation_create(tag_on_object::NAME, tag_pub_id, object_pub_id)],
+			db.tag_on_object()
+				.create_many(vec![tag_on_object::CreateUnchecked {
+					tag_id,
+					object_id,
+					_params: vec![],
+				}])
+				.skip_duplicates(),
+		),
+	)
+	.await?;
+
+	webhooks::fire(
+		library,
+		vec![WebhookEvent::ObjectTagged {
+			object_pub_id,
+			tag_id,
+		}],
+	);
+
+	invalidate_query!(library, "tags.getForObject");
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rule(pattern: Option<&str>, location_id: Option<i32>) -> action_rule::Data {
+		action_rule::Data {
+			id: 1,
+			pub_id: vec![],
+			name: "Inbox".to_string(),
+			enabled: true,
+			trigger: RuleTrigger::FileCreated as i32,
+			location_id,
+			location: None,
+			pattern: pattern.map(str::to_string),
+			job_name: None,
+			actions: vec![],
+			date_created: Utc::now().into(),
+			date_last_run: None,
+		}
+	}
+
+	fn file_created(location_id: i32, path: &str) -> RuleEvent {
+		RuleEvent::FileCreated {
+			location_id,
+			file_path_id: 1,
+			object_id: Some(1),
+			path: path.into(),
+		}
+	}
+
+	#[test]
+	fn filters_files_by_location_and_name() {
+		let pdfs = rule(Some("*.pdf"), Some(1));
+
+		assert!(matches(&pdfs, &file_created(1, "/docs/invoice.pdf")));
+		assert!(!matches(&pdfs, &file_created(1, "/docs/invoice.txt")));
+		assert!(!matches(&pdfs, &file_created(2, "/docs/invoice.pdf")));
+		assert!(matches(&rule(None, None), &file_created(2, "/a")));
+	}
+
+	#[test]
+	fn refuses_rules_spawning_themselves() {
+		let actions = [RuleAction::PluginJob {
+			plugin_id: "ocr".to_string(),
+			job: "ocr".to_string(),
+		}];
+
+		assert!(matches!(
+			validate(RuleTrigger::JobCompleted, None, None, &actions),
+			Err(RuleError::JobLoop)
+		));
+		assert!(validate(
+			RuleTrigger::JobCompleted,
+			None,
+			Some("file_identifier"),
+			&actions
+		)
+		.is_ok());
+		assert!(matches!(
+			validate(RuleTrigger::FileCreated, None, None, &[]),
+			Err(RuleError::NoActions)
+		));
+	}
+}
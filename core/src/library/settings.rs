@@ -0,0 +1,223 @@
+use crate::{
+	object::preview::{ThumbnailFormat, DEFAULT_THUMBNAIL_QUALITY},
+	prisma::location,
+	util::MaybeUndefined,
+};
+
+use chrono::{DateTime, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+
+/// How many files the file identifier reads in a step by default
+pub const DEFAULT_IDENTIFIER_CHUNK_SIZE: u32 = 100;
+/// How many jobs a library runs at once by default
+pub const DEFAULT_MAX_CONCURRENT_JOBS: u32 = 2;
+/// The most jobs a library can run at once, each job writes to the library database
+pub const MAX_CONCURRENT_JOBS: u32 = 4;
+
+const MAX_IDENTIFIER_CHUNK_SIZE: u32 = 10_000;
+
+#[derive(Error, Debug)]
+pub enum LibrarySettingsError {
+	#[error("thumbnail quality must be between 0 and 100")]
+	ThumbnailQuality,
+	#[error("identifier chunk size must be between 1 and {MAX_IDENTIFIER_CHUNK_SIZE}")]
+	IdentifierChunkSize,
+	#[error("concurrent jobs must be between 1 and {MAX_CONCURRENT_JOBS}")]
+	ConcurrentJobs,
+	#[error("quiet hours must be between 0 and 23")]
+	QuietHours,
+	#[error("preview media size limit must be at least 1 MB")]
+	PreviewMediaMaxSize,
+}
+
+impl From<LibrarySettingsError> for rspc::Error {
+	fn from(e: LibrarySettingsError) -> Self {
+		rspc::Error::with_cause(rspc::ErrorCode::BadRequest, e.to_string(), e)
+	}
+}
+
+/// How a library behaves on this node. The settings are versioned along with the library config,
+/// which migrates them.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq, Eq)]
+pub struct LibrarySettings {
+	/// Format of the thumbnails generated for this library's files
+	pub thumbnail_format: ThumbnailFormat,
+	/// Encoding quality of the thumbnails, from 0 to 100. Higher values trade cache size for fidelity.
+	pub thumbnail_quality: u8,
+	/// How many files the file identifier reads in a step. Bigger steps are faster, but the
+	/// identifier reports its progress and can be paused less often.
+	pub identifier_chunk_size: u32,
+	/// How many jobs of the library run at once, the others wait in the queue. Jobs working hard on
+	/// the same locations wait for each other, whatever this allows.
+	pub max_concurrent_jobs: u32,
+	/// Hours of the day during which the background jobs of the library don't start
+	pub quiet_hours: Option<QuietHours>,
+	/// How much space the thumbnails, waveforms and text previews of the library's files can take,
+	/// in MB. The ones of favorites and recently opened files are kept the longest.
+	pub preview_media_max_size_mb: Option<u32>,
+	/// The location the files and links shared to Spacedrive from other apps are saved to
+	pub inbox_location_id: Option<location::id::Type>,
+}
+
+impl Default for LibrarySettings {
+	fn default() -> Self {
+		Self {
+			thumbnail_format: ThumbnailFormat::default(),
+			thumbnail_quality: DEFAULT_THUMBNAIL_QUALITY,
+			identifier_chunk_size: DEFAULT_IDENTIFIER_CHUNK_SIZE,
+			max_concurrent_jobs: DEFAULT_MAX_CONCURRENT_JOBS,
+			quiet_hours: None,
+			preview_media_max_size_mb: None,
+			inbox_location_id: None,
+		}
+	}
+}
+
+/// From `start` to `end`, in local hours. A range that ends before it starts goes past midnight.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+	pub start: u8,
+	pub end: u8,
+}
+
+impl QuietHours {
+	fn contains(&self, hour: u8) -> bool {
+		if self.start <= self.end {
+			(self.start..self.end).contains(&hour)
+		} else {
+			hour >= self.start || hour < self.end
+		}
+	}
+}
+
+/// Changes to the settings of a library, the missing fields are left as they are
+#[derive(Deserialize, Type, Debug)]
+pub struct LibrarySettingsPatch {
+	pub thumbnail_format: Option<ThumbnailFormat>,
+	pub thumbnail_quality: Option<u8>,
+	pub identifier_chunk_size: Option<u32>,
+	pub max_concurrent_jobs: Option<u32>,
+	pub quiet_hours: MaybeUndefined<QuietHours>,
+	pub preview_media_max_size_mb: MaybeUndefined<u32>,
+	pub inbox_location_id: MaybeUndefined<location::id::Type>,
+}
+
+impl LibrarySettings {
+	/// Applies `patch` if all its values are valid, leaving the settings as they were otherwise
+	pub fn patch(&mut self, patch: LibrarySettingsPatch) -> Result<(), LibrarySettingsError> {
+		let mut settings = self.clone();
+
+		if let Some(thumbnail_format) = patch.thumbnail_format {
+			settings.thumbnail_format = thumbnail_format;
+		}
+		if let Some(thumbnail_quality) = patch.thumbnail_quality {
+			if thumbnail_quality > 100 {
+				return Err(LibrarySettingsError::ThumbnailQuality);
+			}
+			settings.thumbnail_quality = thumbnail_quality;
+		}
+		if let Some(identifier_chunk_size) = patch.identifier_chunk_size {
+			if !(1..=MAX_IDENTIFIER_CHUNK_SIZE).contains(&identifier_chunk_size) {
+				return Err(LibrarySettingsError::IdentifierChunkSize);
+			}
+			settings.identifier_chunk_size = identifier_chunk_size;
+		}
+		if let Some(max_concurrent_jobs) = patch.max_concurrent_jobs {
+			if !(1..=MAX_CONCURRENT_JOBS).contains(&max_concurrent_jobs) {
+				return Err(LibrarySettingsError::ConcurrentJobs);
+			}
+			settings.max_concurrent_jobs = max_concurrent_jobs;
+		}
+		match patch.quiet_hours {
+			MaybeUndefined::Undefined => {}
+			MaybeUndefined::Null => settings.quiet_hours = None,
+			MaybeUndefined::Value(quiet_hours) => {
+				if quiet_hours.start > 23 || quiet_hours.end > 23 {
+					return Err(LibrarySettingsError::QuietHours);
+				}
+				settings.quiet_hours = Some(quiet_hours);
+			}
+		}
+		match patch.preview_media_max_size_mb {
+			MaybeUndefined::Undefined => {}
+			MaybeUndefined::Null => settings.preview_media_max_size_mb = None,
+			MaybeUndefined::Value(0) => return Err(LibrarySettingsError::PreviewMediaMaxSize),
+			MaybeUndefined::Value(max_size_mb) => {
+				settings.preview_media_max_size_mb = Some(max_size_mb)
+			}
+		}
+
+		match patch.inbox_location_id {
+			MaybeUndefined::Undefined => {}
+			MaybeUndefined::Null => settings.inbox_location_id = None,
+			MaybeUndefined::Value(location_id) => settings.inbox_location_id = Some(location_id),
+		}
+
+		*self = settings;
+
+		Ok(())
+	}
+
+	/// Whether the background jobs of the library wait at `now`
+	pub fn is_quiet(&self, now: DateTime<Local>) -> bool {
+		self.quiet_hours
+			.map_or(false, |quiet_hours| quiet_hours.contains(now.hour() as u8))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn quiet_hours_go_past_midnight() {
+		let day = QuietHours { start: 9, end: 17 };
+		assert!(day.contains(9));
+		assert!(day.contains(16));
+		assert!(!day.contains(17));
+		assert!(!day.contains(3));
+
+		let night = QuietHours { start: 22, end: 6 };
+		assert!(night.contains(23));
+		assert!(night.contains(0));
+		assert!(night.contains(5));
+		assert!(!night.contains(6));
+		assert!(!night.contains(12));
+	}
+
+	#[test]
+	fn invalid_patches_change_nothing() {
+		let mut settings = LibrarySettings::default();
+
+		assert!(settings
+			.patch(LibrarySettingsPatch {
+				thumbnail_format: Some(ThumbnailFormat::Avif),
+				thumbnail_quality: Some(80),
+				identifier_chunk_size: Some(0),
+				max_concurrent_jobs: None,
+				quiet_hours: MaybeUndefined::Undefined,
+				preview_media_max_size_mb: MaybeUndefined::Undefined,
+				inbox_location_id: MaybeUndefined::Undefined,
+			})
+			.is_err());
+		assert_eq!(settings, LibrarySettings::default());
+
+		settings
+			.patch(LibrarySettingsPatch {
+				thumbnail_format: None,
+				thumbnail_quality: Some(80),
+				identifier_chunk_size: None,
+				max_concurrent_jobs: Some(2),
+				quiet_hours: MaybeUndefined::Value(QuietHours { start: 1, end: 7 }),
+				preview_media_max_size_mb: MaybeUndefined::Value(512),
+				inbox_location_id: MaybeUndefined::Undefined,
+			})
+			.unwrap();
+		assert_eq!(settings.thumbnail_quality, 80);
+		assert_eq!(settings.max_concurrent_jobs, 2);
+		assert_eq!(settings.quiet_hours, Some(QuietHours { start: 1, end: 7 }));
+		assert_eq!(settings.preview_media_max_size_mb, Some(512));
+	}
+}
@@ -0,0 +1,524 @@
+//! The trash of a library. A deleted tag, or an object whose last file is gone, is moved to the
+//! trash first: it's hidden everywhere, but keeps its links and can be restored until it's purged,
+//! by hand or [`TRASH_RETENTION_DAYS`] after it was trashed.
+//!
+//! Objects are trashed by the orphan remover, when no file path of any node links to them anymore.
+//! They can only be restored once a file path links to them again, the orphan remover would trash
+//! them right away otherwise.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::{
+		webhooks::{self, WebhookEvent},
+		Library, LibraryManager,
+	},
+	prisma::{object, tag, tag_on_object},
+	sync::{self, SyncError},
+};
+
+use std::{sync::Weak, time::Duration};
+
+use chrono::{DateTime, Duration as ChronoDuration, FixedOffset, Local, Utc};
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use thiserror::Error;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// How many days items stay in the trash before they're purged
+pub const TRASH_RETENTION_DAYS: i64 = 30;
+
+/// How often the scheduler looks for expired items
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Error, Debug)]
+pub enum TrashError {
+	#[error("object {0} has no file left to be restored with")]
+	ObjectWithoutFiles(object::id::Type),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
+}
+
+impl From<TrashError> for rspc::Error {
+	fn from(e: TrashError) -> Self {
+		match e {
+			TrashError::ObjectWithoutFiles(_) => {
+				rspc::Error::with_cause(rspc::ErrorCode::BadRequest, e.to_string(), e)
+			}
+			TrashError::Database(e) => e.into(),
+			TrashError::Sync(e) => e.into(),
+		}
+	}
+}
+
+#[derive(Serialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrashItemKind {
+	Object,
+	Tag,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct TrashItem {
+	pub kind: TrashItemKind,
+	pub id: i32,
+	/// The name of a tag, objects have none
+	pub name: Option<String>,
+	pub date_deleted: DateTime<Utc>,
+	/// When the item is purged, if it's still in the trash
+	pub date_purged: DateTime<Utc>,
+}
+
+/// The items of the trash to restore or purge
+#[derive(Deserialize, Type, Debug, Default)]
+pub struct TrashItems {
+	#[serde(default)]
+	pub objects: Vec<object::id::Type>,
+	#[serde(default)]
+	pub tags: Vec<tag::id::Type>,
+}
+
+impl TrashItems {
+	fn is_empty(&self) -> bool {
+		self.objects.is_empty() && self.tags.is_empty()
+	}
+}
+
+/// The items trashed before this date are expired
+fn expiry_cutoff() -> DateTime<FixedOffset> {
+	(Utc::now() - ChronoDuration::days(TRASH_RETENTION_DAYS)).into()
+}
+
+fn trash_item(
+	kind: TrashItemKind,
+	id: i32,
+	name: Option<String>,
+	date_deleted: DateTime<FixedOffset>,
+) -> TrashItem {
+	let date_deleted = date_deleted.with_timezone(&Utc);
+
+	TrashItem {
+		kind,
+		id,
+		name,
+		date_deleted,
+		date_purged: date_deleted + ChronoDuration::days(TRASH_RETENTION_DAYS),
+	}
+}
+
+/// The items of the trash, the most recently trashed first
+pub async fn list(library: &Library) -> Result<Vec<TrashItem>, QueryError> {
+	let db = &library.db;
+
+	let (objects, tags) = tokio::try_join!(
+		db.object()
+			.find_many(vec![object::date_deleted::not(None)])
+			.select(object::select!({ id date_deleted }))
+			.exec(),
+		db.tag()
+			.find_many(vec![tag::date_deleted::not(None)])
+			.select(tag::select!({ id name date_deleted }))
+			.exec(),
+	)?;
+
+	let mut items = objects
+		.into_iter()
+		.filter_map(|object| {
+			Some(trash_item(
+				TrashItemKind::Object,
+				object.id,
+				None,
+				object.date_deleted?,
+			))
+		})
+		.chain(tags.into_iter().filter_map(|tag| {
+			Some(trash_item(
+				TrashItemKind::Tag,
+				tag.id,
+				tag.name,
+				tag.date_deleted?,
+			))
+		}))
+		.collect::<Vec<_>>();
+
+	items.sort_by(|a, b| b.date_deleted.cmp(&a.date_deleted));
+
+	Ok(items)
+}
+
+/// Moves a tag to the trash, its objects keep their link to it until it's purged
+pub async fn trash_tag(library: &Library, pub_id: Vec<u8>) -> Result<(), SyncError> {
+	let Library { db, sync, .. } = library;
+	let now = Utc::now();
+
+	sync.write_op(
+		db,
+		sync.shared_update(
+			sync::tag::SyncId {
+				pub_id: pub_id.clone(),
+			},
+			tag::date_deleted::NAME,
+			json!(now),
+		),
+		db.tag().update(
+			tag::pub_id::equals(pub_id),
+			vec![tag::date_deleted::set(Some(now.into()))],
+		),
+	)
+	.await?;
+
+	invalidate_query!(library, "tags.list");
+	invalidate_query!(library, "trash.list");
+
+	Ok(())
+}
+
+/// Takes items out of the trash, the ones that aren't in it are left as they are
+pub async fn restore(library: &Library, items: TrashItems) -> Result<(), TrashError> {
+	let Library { db, sync, .. } = library;
+
+	if !items.objects.is_empty() {
+		if let Some(object) = db
+			.object()
+			.find_first(vec![
+				object::id::in_vec(items.objects.clone()),
+				object::file_paths::none(vec![]),
+			])
+			.select(object::select!({ id }))
+			.exec()
+			.await?
+		{
+			return Err(TrashError::ObjectWithoutFiles(object.id));
+		}
+
+		db.object()
+			.update_many(
+				vec![
+					object::id::in_vec(items.objects),
+					object::date_deleted::not(None),
+				],
+				vec![object::date_deleted::set(None)],
+			)
+			.exec()
+			.await?;
+	}
+
+	let tags = db
+		.tag()
+		.find_many(vec![
+			tag::id::in_vec(items.tags),
+			tag::date_deleted::not(None),
+		])
+		.select(tag::select!({ pub_id }))
+		.exec()
+		.await?;
+
+	for tag in tags {
+		sync.write_op(
+			db,
+			sync.shared_update(
+				sync::tag::SyncId {
+					pub_id: tag.pub_id.clone(),
+				},
+				tag::date_deleted::NAME,
+				json!(null),
+			),
+			db.tag().update(
+				tag::pub_id::equals(tag.pub_id),
+				vec![tag::date_deleted::set(None)],
+			),
+		)
+		.await?;
+	}
+
+	invalidate_query!(library, "trash.list");
+	invalidate_query!(library, "tags.list");
+	invalidate_query!(library, "search.objects");
+
+	Ok(())
+}
+
+/// Deletes trashed objects for good, with their tag links. Returns how many were deleted.
+async fn purge_objects(
+	library: &Library,
+	params: Vec<object::WhereParam>,
+) -> Result<usize, QueryError> {
+	let db = &library.db;
+
+	let objects = db
+		.object()
+		.find_many(
+			[
+				object::date_deleted::not(None),
+				object::file_paths::none(vec![]),
+			]
+			.into_iter()
+			.chain(params)
+			.collect(),
+		)
+		.select(object::select!({ id pub_id tags: select { tag_id } }))
+		.exec()
+		.await?;
+
+	if objects.is_empty() {
+		return Ok(0);
+	}
+
+	let ids = objects.iter().map(|object| object.id).collect::<Vec<_>>();
+
+	let (_, deleted) = db
+		._batch((
+			db.tag_on_object()
+				.delete_many(vec![tag_on_object::object_id::in_vec(ids.clone())]),
+			db.object().delete_many(vec![object::id::in_vec(ids)]),
+		))
+		.await?;
+
+	webhooks::fire(
+		library,
+		objects
+			.into_iter()
+			.filter_map(|object| {
+				Some(WebhookEvent::ObjectDeleted {
+					object_pub_id: Uuid::from_slice(&object.pub_id).ok()?,
+					tag_ids: object.tags.into_iter().map(|tag| tag.tag_id).collect(),
+				})
+			})
+			.collect(),
+	);
+
+	Ok(deleted as usize)
+}
+
+/// Deletes trashed tags for good, with their links to objects. Returns how many were deleted.
+async fn purge_tags(library: &Library, params: Vec<tag::WhereParam>) -> Result<usize, SyncError> {
+	let Library { db, sync, .. } = library;
+
+	let tags = db
+		.tag()
+		.find_many(
+			[tag::date_deleted::not(None)]
+				.into_iter()
+				.chain(params)
+				.collect(),
+		)
+		.select(tag::select!({ id pub_id }))
+		.exec()
+		.await?;
+
+	for tag in &tags {
+		db.tag_on_object()
+			.delete_many(vec![tag_on_object::tag_id::equals(tag.id)])
+			.exec()
+			.await?;
+
+		sync.write_op(
+			db,
+			sync.shared_delete(sync::tag::SyncId {
+				pub_id: tag.pub_id.clone(),
+			}),
+			db.tag().delete(tag::id::equals(tag.id)),
+		)
+		.await?;
+	}
+
+	Ok(tags.len())
+}
+
+/// Deletes items of the trash for good, the ones that aren't in it are left as they are
+pub async fn purge(library: &Library, items: TrashItems) -> Result<(), SyncError> {
+	if items.is_empty() {
+		return Ok(());
+	}
+
+	purge_objects(library, vec![object::id::in_vec(items.objects)]).await?;
+	purge_tags(library, vec![tag::id::in_vec(items.tags)]).await?;
+
+	invalidate_query!(library, "trash.list");
+
+	Ok(())
+}
+
+/// Purges the items that were trashed more than [`TRASH_RETENTION_DAYS`] ago
+pub struct TrashPurgeJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Default)]
+pub struct TrashPurgeJobInit {}
+
+impl JobInitData for TrashPurgeJobInit {
+	type Job = TrashPurgeJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum TrashPurgeJobStep {
+	Objects,
+	Tags,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TrashPurgeJobRunMetadata {
+	objects: usize,
+	tags: usize,
+}
+
+impl JobRunMetadata for TrashPurgeJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.objects += new_data.objects;
+		self.tags += new_data.tags;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for TrashPurgeJob {
+	type Init = TrashPurgeJobInit;
+	type Data = ();
+	type Step = TrashPurgeJobStep;
+	type RunMetadata = TrashPurgeJobRunMetadata;
+
+	const NAME: &'static str = "library_trash_purge";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		_: &WorkerContext,
+		_: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		*data = Some(());
+
+		Ok(vec![TrashPurgeJobStep::Objects, TrashPurgeJobStep::Tags].into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let mut metadata = TrashPurgeJobRunMetadata::default();
+
+		match step {
+			TrashPurgeJobStep::Objects => {
+				ctx.progress_msg(job_message!("trash.purgingObjects"));
+
+				metadata.objects = purge_objects(
+					&ctx.library,
+					vec![object::date_deleted::lt(expiry_cutoff())],
+				)
+				.await?;
+			}
+			TrashPurgeJobStep::Tags => {
+				ctx.progress_msg(job_message!("trash.purgingTags"));
+
+				metadata.tags =
+					purge_tags(&ctx.library, vec![tag::date_deleted::lt(expiry_cutoff())]).await?;
+			}
+		}
+
+		Ok(metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let library = &ctx.library;
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Purged {} objects and {} tags from the trash of library '{}'",
+			metadata.objects, metadata.tags, library.id
+		);
+
+		invalidate_query!(library, "trash.list");
+
+		Ok(Some(serde_json::to_value(metadata)?))
+	}
+}
+
+/// Whether the trash has items to purge
+async fn has_expired_items(library: &Library) -> Result<bool, QueryError> {
+	let db = &library.db;
+
+	let (objects, tags) = tokio::try_join!(
+		db.object()
+			.count(vec![object::date_deleted::lt(expiry_cutoff())])
+			.exec(),
+		db.tag()
+			.count(vec![tag::date_deleted::lt(expiry_cutoff())])
+			.exec(),
+	)?;
+
+	Ok(objects + tags > 0)
+}
+
+/// Purges the expired items of the libraries outside their quiet hours
+pub(crate) fn spawn_scheduler(library_manager: Weak<LibraryManager>) {
+	tokio::spawn(async move {
+		let mut tick = interval(CHECK_INTERVAL);
+		tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			tick.tick().await;
+
+			let Some(library_manager) = library_manager.upgrade() else {
+				break;
+			};
+
+			for library in library_manager.get_all_libraries().await {
+				if library.config.settings.is_quiet(Local::now()) {
+					continue;
+				}
+
+				match has_expired_items(&library).await {
+					Ok(true) => {
+						debug!("Purging the trash of library '{}'", library.id);
+
+						if let Err(e) = library.spawn_job(TrashPurgeJobInit {}).await {
+							warn!(
+								"Failed to start purging the trash of library '{}': {e}",
+								library.id
+							);
+						}
+					}
+					Ok(false) => {}
+					Err(e) => warn!("Failed to read the trash of library '{}': {e}", library.id),
+				}
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn items_are_purged_after_the_retention() {
+		let date_deleted = DateTime::parse_from_rfc3339("2023-07-01T10:00:00+02:00").unwrap();
+
+		let item = trash_item(TrashItemKind::Tag, 1, None, date_deleted);
+
+		assert_eq!(item.date_deleted, date_deleted.with_timezone(&Utc));
+		assert_eq!(
+			item.date_purged.to_rfc3339(),
+			"2023-07-31T08:00:00+00:00".to_string()
+		);
+	}
+}
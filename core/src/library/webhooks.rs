@@ -0,0 +1,358 @@
+//! Webhooks posting the events of the objects of the library to a URL, like pushing the scans added
+//! to a location to a document management system.
+//!
+//! A delivery is a `POST` of the event as JSON, with the library and the date it happened. When the
+//! webhook has a secret, the `X-Spacedrive-Signature` header of the delivery is `sha256=` followed
+//! by the hex HMAC-SHA256 of the body with the secret as key. Deliveries aren't retried, the status
+//! of the last one is kept with the webhook.
+//!
+//! Deleted objects have no files left, so webhooks filtered by location never get their deletion.
+
+use crate::{
+	invalidate_query,
+	prisma::{location, object, tag, webhook},
+};
+
+use std::{path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use prisma_client_rust::QueryError;
+use reqwest::{header::CONTENT_TYPE, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use specta::Type;
+use thiserror::Error;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::Library;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, Eq, PartialEq)]
+pub enum WebhookEventKind {
+	ObjectCreated,
+	ObjectTagged,
+	ObjectDeleted,
+}
+
+/// What happened, as posted to the webhooks
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum WebhookEvent {
+	/// An object was created for a file that didn't match any object of the library
+	ObjectCreated {
+		object_pub_id: Uuid,
+		location_id: location::id::Type,
+		path: PathBuf,
+	},
+	ObjectTagged {
+		object_pub_id: Uuid,
+		tag_id: tag::id::Type,
+	},
+	/// An object was purged from the trash, with the tags it had
+	ObjectDeleted {
+		object_pub_id: Uuid,
+		tag_ids: Vec<tag::id::Type>,
+	},
+}
+
+impl WebhookEvent {
+	fn kind(&self) -> WebhookEventKind {
+		match self {
+			Self::ObjectCreated { .. } => WebhookEventKind::ObjectCreated,
+			Self::ObjectTagged { .. } => WebhookEventKind::ObjectTagged,
+			Self::ObjectDeleted { .. } => WebhookEventKind::ObjectDeleted,
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct Delivery<'a> {
+	library_id: Uuid,
+	date: DateTime<Utc>,
+	#[serde(flatten)]
+	event: &'a WebhookEvent,
+}
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error("invalid events: {0}")]
+	Events(#[from] serde_json::Error),
+	#[error("a webhook needs at least one event")]
+	NoEvents,
+	#[error("invalid url, it must be http or https: {0}")]
+	Url(String),
+	#[error("webhook not found: {0}")]
+	NotFound(webhook::id::Type),
+	#[error("failed to build the http client: {0}")]
+	Client(#[from] reqwest::Error),
+}
+
+impl From<WebhookError> for rspc::Error {
+	fn from(e: WebhookError) -> Self {
+		let code = match e {
+			WebhookError::NotFound(_) => rspc::ErrorCode::NotFound,
+			WebhookError::Events(_) | WebhookError::NoEvents | WebhookError::Url(_) => {
+				rspc::ErrorCode::BadRequest
+			}
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Checks a webhook before it's saved, returning its events as they're stored
+pub fn validate(url: &str, events: &[WebhookEventKind]) -> Result<Vec<u8>, WebhookError> {
+	if events.is_empty() {
+		return Err(WebhookError::NoEvents);
+	}
+
+	match Url::parse(url) {
+		Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+		_ => return Err(WebhookError::Url(url.to_string())),
+	}
+
+	Ok(serde_json::to_vec(events)?)
+}
+
+/// Where the object of an event is, to filter the webhooks by location and tag
+#[derive(Debug, Default)]
+struct ObjectScope {
+	locations: Vec<location::id::Type>,
+	tags: Vec<tag::id::Type>,
+}
+
+impl ObjectScope {
+	async fn of(library: &Library, event: &WebhookEvent) -> Result<Self, QueryError> {
+		let object_pub_id = match event {
+			WebhookEvent::ObjectCreated { object_pub_id, .. }
+			| WebhookEvent::ObjectTagged { object_pub_id, .. } => object_pub_id,
+			WebhookEvent::ObjectDeleted { tag_ids, .. } => {
+				return Ok(Self {
+					locations: vec![],
+					tags: tag_ids.clone(),
+				})
+			}
+		};
+
+		let Some(object) = library
+			.db
+			.object()
+			.find_unique(object::pub_id::equals(object_pub_id.as_bytes().to_vec()))
+			.select(object::select!({
+				file_paths: select { location_id }
+				tags: select { tag_id }
+			}))
+			.exec()
+			.await?
+		else {
+			return Ok(Self::default());
+		};
+
+		Ok(Self {
+			locations: object
+				.file_paths
+				.into_iter()
+				.filter_map(|file_path| file_path.location_id)
+				.collect(),
+			tags: object.tags.into_iter().map(|tag| tag.tag_id).collect(),
+		})
+	}
+
+	fn matches(&self, webhook: &webhook::Data) -> bool {
+		webhook
+			.location_id
+			.map_or(true, |id| self.locations.contains(&id))
+			&& webhook.tag_id.map_or(true, |id| self.tags.contains(&id))
+	}
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+	let mut mac =
+		Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+	mac.update(body);
+
+	format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Posts `events` to the enabled webhooks they match, in the background
+pub(crate) fn fire(library: &Library, events: Vec<WebhookEvent>) {
+	if events.is_empty() {
+		return;
+	}
+
+	let library = library.clone();
+
+	tokio::spawn(async move {
+		if let Err(e) = deliver_all(&library, &events).await {
+			warn!(
+				"Failed to deliver the webhooks of {} events: {e}",
+				events.len()
+			);
+		}
+	});
+}
+
+async fn deliver_all(library: &Library, events: &[WebhookEvent]) -> Result<(), WebhookError> {
+	let webhooks = library
+		.db
+		.webhook()
+		.find_many(vec![webhook::enabled::equals(true)])
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|webhook| {
+			let kinds = serde_json::from_slice::<Vec<WebhookEventKind>>(&webhook.events).ok()?;
+			Some((webhook, kinds))
+		})
+		.collect::<Vec<_>>();
+
+	if webhooks.is_empty() {
+		return Ok(());
+	}
+
+	let client = reqwest::Client::builder()
+		.timeout(DELIVERY_TIMEOUT)
+		.build()?;
+
+	for event in events {
+		let kind = event.kind();
+		let mut interested = webhooks
+			.iter()
+			.filter(|(_, kinds)| kinds.contains(&kind))
+			.map(|(webhook, _)| webhook)
+			.peekable();
+
+		if interested.peek().is_none() {
+			continue;
+		}
+
+		let scope = ObjectScope::of(library, event).await?;
+		let body = serde_json::to_vec(&Delivery {
+			library_id: library.id,
+			date: Utc::now(),
+			event,
+		})?;
+
+		for webhook in interested.filter(|webhook| scope.matches(webhook)) {
+			deliver(library, &client, webhook, kind, &body).await?;
+		}
+	}
+
+	invalidate_query!(library, "webhooks.list");
+
+	Ok(())
+}
+
+async fn deliver(
+	library: &Library,
+	client: &reqwest::Client,
+	webhook: &webhook::Data,
+	kind: WebhookEventKind,
+	body: &[u8],
+) -> Result<(), QueryError> {
+	let mut request = client
+		.post(&webhook.url)
+		.header(CONTENT_TYPE, "application/json")
+		.header("X-Spacedrive-Event", format!("{kind:?}"));
+
+	if let Some(secret) = &webhook.secret {
+		request = request.header("X-Spacedrive-Signature", signature(secret, body));
+	}
+
+	let status = match request.body(body.to_vec()).send().await {
+		Ok(response) => {
+			debug!("Webhook {} answered {}", webhook.url, response.status());
+			Some(response.status().as_u16() as i32)
+		}
+		Err(e) => {
+			warn!("Failed to deliver webhook {}: {e}", webhook.url);
+			None
+		}
+	};
+
+	library
+		.db
+		.webhook()
+		.update(
+			webhook::id::equals(webhook.id),
+			vec![
+				webhook::date_last_delivery::set(Some(Utc::now().into())),
+				webhook::last_status::set(status),
+			],
+		)
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn webhook(location_id: Option<i32>, tag_id: Option<i32>) -> webhook::Data {
+		webhook::Data {
+			id: 1,
+			pub_id: vec![],
+			url: "https://dms.example.com/hook".to_string(),
+			enabled: true,
+			events: vec![],
+			location_id,
+			location: None,
+			tag_id,
+			tag: None,
+			secret: None,
+			date_created: Utc::now().into(),
+			date_last_delivery: None,
+			last_status: None,
+		}
+	}
+
+	#[test]
+	fn filters_objects_by_location_and_tag() {
+		let scope = ObjectScope {
+			locations: vec![1],
+			tags: vec![3],
+		};
+
+		assert!(scope.matches(&webhook(None, None)));
+		assert!(scope.matches(&webhook(Some(1), Some(3))));
+		assert!(!scope.matches(&webhook(Some(2), None)));
+		assert!(!scope.matches(&webhook(Some(1), Some(4))));
+		assert!(!ObjectScope::default().matches(&webhook(Some(1), None)));
+	}
+
+	#[test]
+	fn signs_with_hmac_sha256() {
+		// RFC 4231, test case 2
+		assert_eq!(
+			signature("Jefe", b"what do ya want for nothing?"),
+			"sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+		);
+	}
+
+	#[test]
+	fn refuses_invalid_webhooks() {
+		let events = [WebhookEventKind::ObjectCreated];
+
+		assert!(validate("https://dms.example.com/hook", &events).is_ok());
+		assert!(matches!(
+			validate("ftp://dms.example.com", &events),
+			Err(WebhookError::Url(_))
+		));
+		assert!(matches!(
+			validate("not a url", &events),
+			Err(WebhookError::Url(_))
+		));
+		assert!(matches!(
+			validate("https://dms.example.com/hook", &[]),
+			Err(WebhookError::NoEvents)
+		));
+	}
+}
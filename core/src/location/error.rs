@@ -1,5 +1,6 @@
 use crate::{
 	prisma::location,
+	sync::SyncError,
 	util::{db::MissingFieldError, error::FileIOError},
 };
 
@@ -64,6 +65,8 @@ pub enum LocationError {
 	#[error("database error: {0}")]
 	Database(#[from] prisma_client_rust::QueryError),
 	#[error(transparent)]
+	Sync(#[from] SyncError),
+	#[error(transparent)]
 	LocationManager(#[from] LocationManagerError),
 	#[error(transparent)]
 	FilePath(#[from] FilePathError),
@@ -14,9 +14,10 @@ use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 
 use super::{
-	file_path_for_file_identifier, file_path_for_object_validator, file_path_for_thumbnailer,
-	file_path_to_full_path, file_path_to_handle_custom_uri, file_path_to_isolate,
-	file_path_to_isolate_with_id, file_path_with_object, FilePathError,
+	file_path_for_deduplicator, file_path_for_file_identifier, file_path_for_object_validator,
+	file_path_for_object_verifier, file_path_for_plugin_metadata, file_path_for_policy_backup,
+	file_path_for_thumbnailer, file_path_to_full_path, file_path_to_handle_custom_uri,
+	file_path_to_isolate, file_path_to_isolate_with_id, file_path_with_object, FilePathError,
 };
 
 static FORBIDDEN_FILE_NAMES: OnceLock<RegexSet> = OnceLock::new();
@@ -439,7 +440,8 @@ impl_from_db!(
 	file_path,
 	file_path_to_isolate,
 	file_path_to_isolate_with_id,
-	file_path_with_object
+	file_path_with_object,
+	file_path_for_deduplicator
 );
 
 impl_from_db_without_location_id!(
@@ -447,6 +449,9 @@ impl_from_db_without_location_id!(
 	file_path_to_full_path,
 	file_path_for_thumbnailer,
 	file_path_for_object_validator,
+	file_path_for_object_verifier,
+	file_path_for_policy_backup,
+	file_path_for_plugin_metadata,
 	file_path_to_handle_custom_uri
 );
 
@@ -1,5 +1,6 @@
 use crate::{
 	prisma::{file_path, location, PrismaClient},
+	sync::SyncError,
 	util::error::{FileIOError, NonUtf8PathError},
 };
 
@@ -45,6 +46,41 @@ file_path::select!(file_path_for_object_validator {
 	extension
 	integrity_checksum
 });
+file_path::select!(file_path_for_object_verifier {
+	id
+	pub_id
+	materialized_path
+	is_dir
+	name
+	extension
+	integrity_checksum
+	size_in_bytes_bytes
+	date_modified
+});
+file_path::select!(file_path_for_deduplicator {
+	id
+	pub_id
+	location_id
+	materialized_path
+	is_dir
+	name
+	extension
+	location: select {
+		path
+	}
+});
+file_path::select!(file_path_for_policy_backup {
+	object_id
+	materialized_path
+	is_dir
+	name
+	extension
+	location: select {
+		id
+		name
+		path
+	}
+});
 file_path::select!(file_path_for_thumbnailer {
 	materialized_path
 	is_dir
@@ -52,6 +88,13 @@ file_path::select!(file_path_for_thumbnailer {
 	extension
 	cas_id
 });
+file_path::select!(file_path_for_plugin_metadata {
+	object_id
+	materialized_path
+	is_dir
+	name
+	extension
+});
 file_path::select!(file_path_to_isolate {
 	location_id
 	materialized_path
@@ -75,6 +118,7 @@ file_path::select!(file_path_to_handle_custom_uri {
 	location: select {
 		id
 		path
+		node_id
 	}
 });
 file_path::select!(file_path_to_full_path {
@@ -132,6 +176,8 @@ pub enum FilePathError {
 	},
 	#[error("database error: {0}")]
 	Database(#[from] QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
 
 	#[error(transparent)]
 	FileIO(#[from] FileIOError),
@@ -141,7 +187,6 @@ pub enum FilePathError {
 	InvalidFilenameAndExtension(String),
 }
 
-#[cfg(feature = "location-watcher")]
 pub async fn create_file_path(
 	crate::location::Library { db, sync, .. }: &crate::location::Library,
 	IsolatedFilePathData {
@@ -174,7 +219,7 @@ pub async fn create_file_path(
 
 		vec![
 			(
-				location::NAME,
+				file_path::location::NAME,
 				json!(sync::location::SyncId {
 					pub_id: location.pub_id
 				}),
@@ -1,9 +1,11 @@
 use crate::{
 	file_paths_db_fetcher_fn, invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobReportUpdate, JobResult,
-		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobMessage,
+		JobReportUpdate, JobResult, JobRunMetadata, JobState, JobStepOutput, StatefulJob,
+		WorkerContext,
 	},
+	job_message,
 	location::{
 		file_path_helper::{
 			ensure_file_path_exists, ensure_sub_path_is_directory, ensure_sub_path_is_in_location,
@@ -93,7 +95,7 @@ impl JobRunMetadata for IndexerJobRunMetadata {
 pub enum ScanProgress {
 	ChunkCount(usize),
 	SavedChunks(usize),
-	Message(String),
+	Message(JobMessage),
 }
 
 impl IndexerJobData {
@@ -113,6 +115,10 @@ impl IndexerJobData {
 
 impl JobInitData for IndexerJobInit {
 	type Job = IndexerJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location.id])
+	}
 }
 
 /// `IndexerJobStepInput` defines the action that should be executed in the current step
@@ -131,6 +137,7 @@ impl StatefulJob for IndexerJob {
 	type RunMetadata = IndexerJobRunMetadata;
 
 	const NAME: &'static str = "indexer";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -228,9 +235,10 @@ impl StatefulJob for IndexerJob {
 			ctx,
 			vec![
 				ScanProgress::ChunkCount(steps.len() - to_walk_count),
-				ScanProgress::Message(format!(
-					"Starting saving {total_paths} files or directories, \
-					there still {to_walk_count} directories to index",
+				ScanProgress::Message(job_message!(
+					"indexer.saving",
+					count = total_paths,
+					to_walk = to_walk_count
 				)),
 			],
 		);
@@ -277,9 +285,10 @@ impl StatefulJob for IndexerJob {
 					ctx,
 					vec![
 						ScanProgress::SavedChunks(step.chunk_idx + 1),
-						ScanProgress::Message(format!(
-							"Writing chunk {} of {} to database",
-							step.chunk_idx, run_metadata.total_save_steps
+						ScanProgress::Message(job_message!(
+							"indexer.writingChunk",
+							chunk = step.chunk_idx,
+							chunks = run_metadata.total_save_steps
 						)),
 					],
 				);
@@ -347,9 +356,10 @@ impl StatefulJob for IndexerJob {
 					ctx,
 					vec![
 						ScanProgress::ChunkCount(more_steps.len() - to_walk_count),
-						ScanProgress::Message(format!(
-							"Scanned more {} files or directories; {} more directories to scan",
-							new_metadata.total_paths, to_walk_count
+						ScanProgress::Message(job_message!(
+							"indexer.scanned",
+							count = new_metadata.total_paths,
+							to_walk = to_walk_count
 						)),
 					],
 				);
@@ -391,9 +401,13 @@ fn update_notifier_fn(ctx: &WorkerContext) -> impl FnMut(&Path, usize) + '_ {
 	move |path, total_entries| {
 		IndexerJobData::on_scan_progress(
 			ctx,
-			vec![ScanProgress::Message(format!(
-				"Scanning: {:?}; Found: {total_entries} entries",
-				path.file_name().unwrap_or(path.as_os_str())
+			vec![ScanProgress::Message(job_message!(
+				"indexer.scanning",
+				path = path
+					.file_name()
+					.unwrap_or(path.as_os_str())
+					.to_string_lossy(),
+				count = total_entries
 			))],
 		);
 	}
@@ -1,7 +1,7 @@
 use crate::{
 	library::Library,
 	prisma::{file_path, location, PrismaClient},
-	sync,
+	sync::{self, SyncError},
 	util::{db::uuid_to_bytes, error::FileIOError},
 };
 
@@ -50,6 +50,8 @@ pub enum IndexerError {
 	#[error("Database Error: {}", .0.to_string())]
 	Database(#[from] prisma_client_rust::QueryError),
 	#[error(transparent)]
+	Sync(#[from] SyncError),
+	#[error(transparent)]
 	FileIO(#[from] FileIOError),
 	#[error(transparent)]
 	FilePath(#[from] FilePathError),
@@ -99,9 +101,9 @@ async fn execute_indexer_save_step(
 			let (sync_params, db_params): (Vec<_>, Vec<_>) = [
 				(
 					(
-						location::NAME,
+						file_path::location::NAME,
 						json!(prisma_sync::location::SyncId {
-							pub_id: pub_id.clone()
+							pub_id: location.pub_id.clone()
 						}),
 					),
 					location_id::set(Some(location.id)),
@@ -2,6 +2,7 @@ use crate::{
 	job::JobManagerError,
 	library::Library,
 	prisma::location,
+	sync::SyncError,
 	util::{db::MissingFieldError, error::FileIOError},
 };
 
@@ -101,6 +102,8 @@ pub enum LocationManagerError {
 	UpdateNonExistingFile(PathBuf),
 	#[error("Database error: {0}")]
 	DatabaseError(#[from] prisma_client_rust::QueryError),
+	#[error("Sync error: {0}")]
+	SyncError(#[from] SyncError),
 	#[error("File path related error (error: {0})")]
 	FilePathError(#[from] FilePathError),
 	#[error("Corrupted location pub_id on database: (error: {0})")]
@@ -15,7 +15,7 @@ use tokio::{
 	task::{block_in_place, JoinHandle},
 	time::{interval_at, Instant, MissedTickBehavior},
 };
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info_span, warn, Instrument};
 use uuid::Uuid;
 
 use super::LocationManagerError;
@@ -97,14 +97,21 @@ impl LocationWatcher {
 			Config::default(),
 		)?;
 
-		let handle = tokio::spawn(Self::handle_watch_events(
-			location.id,
-			Uuid::from_slice(&location.pub_id)?,
-			library,
-			events_rx,
-			ignore_path_rx,
-			stop_rx,
-		));
+		// The logs of the watcher go to the logs of its library
+		let span =
+			info_span!("location_watcher", library_id = %library.id, location_id = location.id);
+
+		let handle = tokio::spawn(
+			Self::handle_watch_events(
+				location.id,
+				Uuid::from_slice(&location.pub_id)?,
+				library,
+				events_rx,
+				ignore_path_rx,
+				stop_rx,
+			)
+			.instrument(span),
+		);
 
 		Ok(Self {
 			id: location.id,
@@ -137,6 +144,8 @@ impl LocationWatcher {
 				Some(event) = events_rx.recv() => {
 					match event {
 						Ok(event) => {
+							library.metrics().record_watcher_event(&event.kind);
+
 							if let Err(e) = Self::handle_single_event(
 								location_id,
 								location_pub_id,
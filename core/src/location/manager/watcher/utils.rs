@@ -1,6 +1,10 @@
 use crate::{
 	invalidate_query,
-	library::Library,
+	library::{
+		rules::{self, RuleEvent},
+		webhooks::{self, WebhookEvent},
+		Library,
+	},
 	location::{
 		delete_directory,
 		file_path_helper::{
@@ -16,7 +20,10 @@ use crate::{
 	},
 	object::{
 		file_identifier::FileMetadata,
-		preview::{can_generate_thumbnail_for_image, generate_image_thumbnail, get_thumbnail_path},
+		preview::{
+			can_generate_thumbnail_for_image, generate_image_thumbnail, get_thumbnail_path,
+			ThumbnailerOptions, THUMBNAIL_CACHE_DIR_NAME,
+		},
 		validation::hash::file_checksum,
 	},
 	prisma::{file_path, location, object},
@@ -214,12 +221,15 @@ pub(super) async fn create_file(
 		.exec()
 		.await?;
 
-	let object = if let Some(object) = existing_object {
-		object
+	let (object, created_object_pub_id) = if let Some(object) = existing_object {
+		(object, None)
 	} else {
-		db.object()
+		let object_pub_id = Uuid::new_v4();
+
+		let object = db
+			.object()
 			.create(
-				Uuid::new_v4().as_bytes().to_vec(),
+				object_pub_id.as_bytes().to_vec(),
 				vec![
 					object::date_created::set(Some(
 						DateTime::<Local>::from(fs_metadata.created_or_now()).into(),
@@ -229,7 +239,9 @@ pub(super) async fn create_file(
 			)
 			.select(object_just_id::select())
 			.exec()
-			.await?
+			.await?;
+
+		(object, Some(object_pub_id))
 	};
 
 	db.file_path()
@@ -240,6 +252,27 @@ pub(super) async fn create_file(
 		.exec()
 		.await?;
 
+	rules::trigger(
+		library,
+		RuleEvent::FileCreated {
+			location_id,
+			file_path_id: created_file.id,
+			object_id: Some(object.id),
+			path: path.to_path_buf(),
+		},
+	);
+
+	if let Some(object_pub_id) = created_object_pub_id {
+		webhooks::fire(
+			library,
+			vec![WebhookEvent::ObjectCreated {
+				object_pub_id,
+				location_id,
+				path: path.to_path_buf(),
+			}],
+		);
+	}
+
 	if !extension.is_empty() {
 		// Running in a detached task as thumbnail generation can take a while and we don't want to block the watcher
 		let path = path.to_path_buf();
@@ -407,12 +440,11 @@ async fn inner_update_file(
 
 			if let Some(ref object) = file_path.object {
 				// if this file had a thumbnail previously, we update it to match the new content
-				if library.thumbnail_exists(old_cas_id).await? {
+				if let Some(thumb_path) = library.find_thumbnail(old_cas_id).await? {
 					if let Some(ext) = &file_path.extension {
 						generate_thumbnail(ext, &cas_id, full_path, library).await;
 
 						// remove the old thumbnail as we're generating a new one
-						let thumb_path = get_thumbnail_path(library, old_cas_id);
 						fs::remove_file(&thumb_path)
 							.await
 							.map_err(|e| FileIOError::from((thumb_path, e)))?;
@@ -539,14 +571,16 @@ pub(super) async fn remove(
 	let location_path = extract_location_path(location_id, library).await?;
 
 	// if it doesn't exist either way, then we don't care
-	let Some(file_path) = library.db
+	let Some(file_path) = library
+		.db
 		.file_path()
 		.find_first(loose_find_existing_file_path_params(
 			&IsolatedFilePathData::new(location_id, &location_path, full_path, false)?,
 		))
 		.exec()
-		.await? else {
-			return Ok(());
+		.await?
+	else {
+		return Ok(());
 	};
 
 	remove_by_file_path(location_id, full_path, &file_path, library).await
@@ -611,26 +645,40 @@ async fn generate_thumbnail(
 	library: &Library,
 ) {
 	let path = path.as_ref();
-	let output_path = get_thumbnail_path(library, cas_id);
+	let options = ThumbnailerOptions::for_library(library, false).await;
+	let output_path = get_thumbnail_path(
+		library
+			.config()
+			.data_directory()
+			.join(THUMBNAIL_CACHE_DIR_NAME),
+		cas_id,
+		options.format,
+	);
 
-	if let Err(e) = fs::metadata(&output_path).await {
-		if e.kind() != ErrorKind::NotFound {
-			error!(
-				"Failed to check if thumbnail exists, but we will try to generate it anyway: {e}"
+	match library.find_thumbnail(cas_id).await {
+		Ok(Some(_)) => {
+			debug!(
+				"Skipping thumbnail generation for {} because it already exists",
+				path.display()
 			);
+			return;
+		}
+		// Otherwise we good, thumbnail doesn't exist so we can generate it
+		Ok(None) => {}
+		Err(e) => error!(
+			"Failed to check if thumbnail exists, but we will try to generate it anyway: {e}"
+		),
+	}
+
+	if let Some(output_dir) = output_path.parent() {
+		if let Err(e) = fs::create_dir_all(output_dir).await {
+			error!("Failed to create thumbnail directory: {e:#?}");
 		}
-	// Otherwise we good, thumbnail doesn't exist so we can generate it
-	} else {
-		debug!(
-			"Skipping thumbnail generation for {} because it already exists",
-			path.display()
-		);
-		return;
 	}
 
 	if let Ok(extension) = ImageExtension::from_str(extension) {
 		if can_generate_thumbnail_for_image(&extension) {
-			if let Err(e) = generate_image_thumbnail(path, &output_path).await {
+			if let Err(e) = generate_image_thumbnail(path, &output_path, options).await {
 				error!("Failed to image thumbnail on location manager: {e:#?}");
 			}
 		}
@@ -643,7 +691,7 @@ async fn generate_thumbnail(
 
 		if let Ok(extension) = VideoExtension::from_str(extension) {
 			if can_generate_thumbnail_for_video(&extension) {
-				if let Err(e) = generate_video_thumbnail(path, &output_path).await {
+				if let Err(e) = generate_video_thumbnail(path, &output_path, options).await {
 					error!("Failed to video thumbnail on location manager: {e:#?}");
 				}
 			}
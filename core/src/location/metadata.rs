@@ -10,7 +10,7 @@ use tokio::{fs, io};
 use tracing::error;
 use uuid::Uuid;
 
-static SPACEDRIVE_LOCATION_METADATA_FILE: &str = ".spacedrive";
+pub(crate) static SPACEDRIVE_LOCATION_METADATA_FILE: &str = ".spacedrive";
 
 pub(super) type LibraryId = Uuid;
 pub(super) type LocationPubId = Uuid;
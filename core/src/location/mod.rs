@@ -1,7 +1,10 @@
 use crate::{
 	invalidate_query,
 	job::{Job, JobError, JobManagerError},
-	library::Library,
+	library::{
+		activity::{self, ActivityKind},
+		Library,
+	},
 	location::file_path_helper::filter_existing_file_path_params,
 	object::{
 		file_identifier::{self, file_identifier_job::FileIdentifierJobInit},
@@ -41,6 +44,7 @@ pub use error::LocationError;
 use indexer::IndexerJobInit;
 pub use manager::{LocationManager, LocationManagerError};
 use metadata::SpacedriveLocationMetadataFile;
+pub(crate) use metadata::SPACEDRIVE_LOCATION_METADATA_FILE;
 
 use file_path_helper::IsolatedFilePathData;
 
@@ -220,6 +224,7 @@ pub struct LocationUpdateArgs {
 	pub name: Option<String>,
 	pub generate_preview_media: Option<bool>,
 	pub sync_preview_media: Option<bool>,
+	pub generate_video_thumbnails: Option<bool>,
 	pub hidden: Option<bool>,
 	pub indexer_rules_ids: Vec<i32>,
 }
@@ -256,6 +261,12 @@ impl LocationUpdateArgs {
 					location::sync_preview_media::set(Some(v)),
 				)
 			}),
+			self.generate_video_thumbnails.map(|v| {
+				(
+					(location::generate_video_thumbnails::NAME, json!(v)),
+					location::generate_video_thumbnails::set(Some(v)),
+				)
+			}),
 			self.hidden.map(|v| {
 				(
 					(location::hidden::NAME, json!(v)),
@@ -397,6 +408,7 @@ pub async fn scan_location(
 			.queue_next(ThumbnailerJobInit {
 				location: location_base_data,
 				sub_path: None,
+				regenerate: false,
 			}),
 		)
 		.await
@@ -431,6 +443,7 @@ pub async fn scan_location_sub_path(
 			.queue_next(ThumbnailerJobInit {
 				location: location_base_data,
 				sub_path: Some(sub_path),
+				regenerate: false,
 			}),
 		)
 		.await
@@ -551,7 +564,8 @@ async fn create_location(
 		.location()
 		.count(vec![location::path::equals(Some(location_path.clone()))])
 		.exec()
-		.await? > 0
+		.await?
+		> 0
 	{
 		return Err(LocationError::LocationAlreadyExists(path));
 	}
@@ -624,6 +638,13 @@ async fn create_location(
 		.await?
 		.ok_or(LocationError::IdNotFound(location.id))?;
 
+	activity::record(
+		library,
+		ActivityKind::LocationAdded,
+		json!({ "name": &location.name, "path": &location.path }),
+	)
+	.await;
+
 	invalidate_query!(library, "locations.list");
 
 	Ok(Some(CreatedLocationResult {
@@ -666,6 +687,13 @@ pub async fn delete_location(
 		}
 	}
 
+	activity::record(
+		library,
+		ActivityKind::LocationRemoved,
+		json!({ "name": &location.name, "path": &location.path }),
+	)
+	.await;
+
 	invalidate_query!(library, "locations.list");
 
 	info!("Location {} deleted", location_id);
@@ -721,6 +749,7 @@ impl From<location_with_indexer_rules::Data> for location::Data {
 			sync_preview_media: data.sync_preview_media,
 			hidden: data.hidden,
 			date_created: data.date_created,
+			generate_video_thumbnails: data.generate_video_thumbnails,
 			node: None,
 			file_paths: None,
 			indexer_rules: None,
@@ -743,6 +772,7 @@ impl From<&location_with_indexer_rules::Data> for location::Data {
 			sync_preview_media: data.sync_preview_media,
 			hidden: data.hidden,
 			date_created: data.date_created,
+			generate_video_thumbnails: data.generate_video_thumbnails,
 			node: None,
 			file_paths: None,
 			indexer_rules: None,
@@ -0,0 +1,207 @@
+use std::{
+	collections::HashMap,
+	ffi::OsStr,
+	fs::File,
+	io,
+	os::unix::fs::FileExt,
+	path::Path,
+	sync::{Arc, RwLock, RwLockReadGuard},
+	time::{Duration, SystemTime},
+};
+
+use fuser::{
+	BackgroundSession, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+	ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, Request,
+};
+use libc::{EIO, ENOENT, EROFS};
+use tracing::warn;
+
+use super::tree::VirtualTree;
+
+/// How long the kernel keeps what it's told about a file, a little less than between refreshes
+const TTL: Duration = Duration::from_secs(30);
+const BLOCK_SIZE: u32 = 4096;
+
+pub(super) fn spawn(tree: Arc<RwLock<VirtualTree>>, path: &Path) -> io::Result<BackgroundSession> {
+	fuser::spawn_mount2(
+		SpacedriveFs {
+			tree,
+			open_files: HashMap::new(),
+			next_fh: 1,
+		},
+		path,
+		&[
+			MountOption::RO,
+			MountOption::NoExec,
+			MountOption::FSName("spacedrive".to_string()),
+		],
+	)
+}
+
+/// The filesystem of a mount, its calls are made one at a time on the thread of the session
+struct SpacedriveFs {
+	tree: Arc<RwLock<VirtualTree>>,
+	open_files: HashMap<u64, File>,
+	next_fh: u64,
+}
+
+impl SpacedriveFs {
+	fn tree(&self) -> RwLockReadGuard<'_, VirtualTree> {
+		// A refresh panicking leaves the last tree, which is still good to read
+		self.tree.read().unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn attr(&self, req: &Request<'_>, inode: u64) -> Option<FileAttr> {
+		let tree = self.tree();
+
+		let (kind, size, modified, perm) = if tree.dir(inode).is_some() {
+			(FileType::Directory, 0, SystemTime::UNIX_EPOCH, 0o555)
+		} else {
+			let file = tree.file(inode)?;
+			(FileType::RegularFile, file.size, file.modified, 0o444)
+		};
+
+		Some(FileAttr {
+			ino: inode,
+			size,
+			blocks: (size + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64,
+			atime: modified,
+			mtime: modified,
+			ctime: modified,
+			crtime: modified,
+			kind,
+			perm,
+			nlink: 1,
+			uid: req.uid(),
+			gid: req.gid(),
+			rdev: 0,
+			blksize: BLOCK_SIZE,
+			flags: 0,
+		})
+	}
+}
+
+impl Filesystem for SpacedriveFs {
+	fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+		let inode = name
+			.to_str()
+			.and_then(|name| self.tree().lookup(parent, name));
+
+		match inode.and_then(|inode| self.attr(req, inode)) {
+			Some(attr) => reply.entry(&TTL, &attr, 0),
+			None => reply.error(ENOENT),
+		}
+	}
+
+	fn getattr(&mut self, req: &Request<'_>, inode: u64, reply: ReplyAttr) {
+		match self.attr(req, inode) {
+			Some(attr) => reply.attr(&TTL, &attr),
+			None => reply.error(ENOENT),
+		}
+	}
+
+	fn open(&mut self, _: &Request<'_>, inode: u64, flags: i32, reply: ReplyOpen) {
+		if flags & libc::O_ACCMODE != libc::O_RDONLY {
+			return reply.error(EROFS);
+		}
+
+		let Some(path) = self.tree().file(inode).map(|file| file.path.clone()) else {
+			return reply.error(ENOENT);
+		};
+
+		match File::open(&path) {
+			Ok(file) => {
+				let fh = self.next_fh;
+				self.next_fh += 1;
+				self.open_files.insert(fh, file);
+
+				reply.opened(fh, 0);
+			}
+			Err(e) => {
+				warn!("Failed to open {} for a mount: {e}", path.display());
+				reply.error(e.raw_os_error().unwrap_or(EIO));
+			}
+		}
+	}
+
+	fn read(
+		&mut self,
+		_: &Request<'_>,
+		_: u64,
+		fh: u64,
+		offset: i64,
+		size: u32,
+		_: i32,
+		_: Option<u64>,
+		reply: ReplyData,
+	) {
+		let Some(file) = self.open_files.get(&fh) else {
+			return reply.error(EIO);
+		};
+
+		let mut buf = vec![0; size as usize];
+		let mut read = 0;
+		// Short reads are only allowed at the end of the file
+		while read < buf.len() {
+			match file.read_at(&mut buf[read..], offset as u64 + read as u64) {
+				Ok(0) => break,
+				Ok(n) => read += n,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(e) => return reply.error(e.raw_os_error().unwrap_or(EIO)),
+			}
+		}
+
+		reply.data(&buf[..read]);
+	}
+
+	fn release(
+		&mut self,
+		_: &Request<'_>,
+		_: u64,
+		fh: u64,
+		_: i32,
+		_: Option<u64>,
+		_: bool,
+		reply: ReplyEmpty,
+	) {
+		self.open_files.remove(&fh);
+		reply.ok();
+	}
+
+	fn readdir(
+		&mut self,
+		_: &Request<'_>,
+		inode: u64,
+		_: u64,
+		offset: i64,
+		mut reply: ReplyDirectory,
+	) {
+		let tree = self.tree();
+		let Some(dir) = tree.dir(inode) else {
+			return reply.error(ENOENT);
+		};
+
+		let entries = [
+			(inode, FileType::Directory, "."),
+			(dir.parent, FileType::Directory, ".."),
+		]
+		.into_iter()
+		.chain(dir.entries.iter().map(|(name, &inode)| {
+			let kind = if tree.dir(inode).is_some() {
+				FileType::Directory
+			} else {
+				FileType::RegularFile
+			};
+			(inode, kind, name.as_str())
+		}));
+
+		for (i, (inode, kind, name)) in entries.enumerate().skip(offset as usize) {
+			// The offset of an entry is where the next call starts
+			if reply.add(inode, i as i64 + 1, kind, name) {
+				break;
+			}
+		}
+
+		reply.ok();
+	}
+}
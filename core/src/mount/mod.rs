@@ -0,0 +1,227 @@
+//! Mounts, read-only filesystems showing the objects of a library in virtual directories by tag, by
+//! kind, by album or all of them once, whatever location their files are in. Reading a file of a
+//! mount reads the file of the object on this node.
+//!
+//! Mounts use FUSE, only with the `fuse` feature and on Linux and macOS (with macFUSE), without it
+//! the node can't mount libraries. They're rebuilt from the library every minute, so they show the
+//! objects indexed since a little after, and they're unmounted when the node shuts down.
+
+use crate::{library::Library, util::error::FileIOError};
+
+use std::{
+	collections::{HashMap, HashSet},
+	path::{Path, PathBuf},
+	sync::{Arc, RwLock},
+	time::Duration,
+};
+
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, sync::Mutex, task::JoinHandle};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[cfg(all(feature = "fuse", unix))]
+mod fuse;
+// Only the FUSE filesystem reads the trees
+#[cfg_attr(not(all(feature = "fuse", unix)), allow(dead_code))]
+mod tree;
+
+use tree::VirtualTree;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A directory at the root of a mount
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MountView {
+	/// A directory per tag
+	Tags,
+	/// A directory per kind of object, like `Image`
+	Kinds,
+	/// A directory per space
+	Albums,
+	/// Every object once, however many files it has
+	All,
+}
+
+impl MountView {
+	fn dir_name(&self) -> &'static str {
+		match self {
+			Self::Tags => "Tags",
+			Self::Kinds => "Kinds",
+			Self::Albums => "Albums",
+			Self::All => "All",
+		}
+	}
+}
+
+#[derive(Deserialize, Type, Debug)]
+pub struct MountArgs {
+	/// An empty directory of this node the library is mounted on
+	pub path: PathBuf,
+	pub views: Vec<MountView>,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct MountInfo {
+	pub library_id: Uuid,
+	pub path: PathBuf,
+	pub views: Vec<MountView>,
+}
+
+#[derive(Error, Debug)]
+pub enum MountError {
+	#[error("this build of Spacedrive can't mount libraries")]
+	Unavailable,
+	#[error("a mount needs at least one view")]
+	NoViews,
+	#[error("a library is already mounted there: {}", .0.display())]
+	AlreadyMounted(PathBuf),
+	#[error("nothing is mounted there: {}", .0.display())]
+	NotMounted(PathBuf),
+	#[error("libraries can only be mounted on an empty directory: {}", .0.display())]
+	NotAnEmptyDirectory(PathBuf),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<MountError> for rspc::Error {
+	fn from(e: MountError) -> Self {
+		let code = match e {
+			MountError::NotMounted(_) => rspc::ErrorCode::NotFound,
+			MountError::AlreadyMounted(_) => rspc::ErrorCode::Conflict,
+			MountError::Unavailable | MountError::NoViews | MountError::NotAnEmptyDirectory(_) => {
+				rspc::ErrorCode::BadRequest
+			}
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+struct Mount {
+	info: MountInfo,
+	refresher: JoinHandle<()>,
+	// Unmounts when it's dropped
+	#[cfg(all(feature = "fuse", unix))]
+	_session: fuser::BackgroundSession,
+}
+
+/// The libraries mounted by this node, by the directory they're mounted on
+#[derive(Default)]
+pub struct MountManager {
+	mounts: Mutex<HashMap<PathBuf, Mount>>,
+}
+
+impl MountManager {
+	pub fn new() -> Arc<Self> {
+		Arc::new(Self::default())
+	}
+
+	pub async fn list(&self) -> Vec<MountInfo> {
+		self.mounts
+			.lock()
+			.await
+			.values()
+			.map(|mount| mount.info.clone())
+			.collect()
+	}
+
+	pub async fn mount(&self, library: Library, args: MountArgs) -> Result<MountInfo, MountError> {
+		if cfg!(not(all(feature = "fuse", unix))) {
+			return Err(MountError::Unavailable);
+		}
+
+		let mut views = args.views;
+		let mut seen = HashSet::new();
+		views.retain(|view| seen.insert(*view));
+		if views.is_empty() {
+			return Err(MountError::NoViews);
+		}
+
+		let mut mounts = self.mounts.lock().await;
+		if mounts.contains_key(&args.path) {
+			return Err(MountError::AlreadyMounted(args.path));
+		}
+
+		let is_empty = fs::read_dir(&args.path)
+			.await
+			.map_err(|e| FileIOError::from((&args.path, e)))?
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((&args.path, e)))?
+			.is_none();
+		if !is_empty {
+			return Err(MountError::NotAnEmptyDirectory(args.path));
+		}
+
+		let tree = Arc::new(RwLock::new(VirtualTree::load(&library, &views).await?));
+		#[cfg(all(feature = "fuse", unix))]
+		let session = fuse::spawn(tree.clone(), &args.path)
+			.map_err(|e| FileIOError::from((&args.path, e)))?;
+
+		info!(
+			"Mounted library <id='{}'> on {}",
+			library.id,
+			args.path.display()
+		);
+
+		let info = MountInfo {
+			library_id: library.id,
+			path: args.path.clone(),
+			views: views.clone(),
+		};
+
+		let refresher = tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(REFRESH_INTERVAL).await;
+
+				match VirtualTree::load(&library, &views).await {
+					Ok(new_tree) => match tree.write() {
+						Ok(mut tree) => *tree = new_tree,
+						Err(e) => warn!("Failed to refresh a mount of the library: {e}"),
+					},
+					Err(e) => warn!("Failed to refresh a mount of the library: {e}"),
+				}
+			}
+		});
+
+		mounts.insert(
+			args.path,
+			Mount {
+				info: info.clone(),
+				refresher,
+				#[cfg(all(feature = "fuse", unix))]
+				_session: session,
+			},
+		);
+
+		Ok(info)
+	}
+
+	pub async fn unmount(&self, path: &Path) -> Result<(), MountError> {
+		let mount = self
+			.mounts
+			.lock()
+			.await
+			.remove(path)
+			.ok_or_else(|| MountError::NotMounted(path.to_path_buf()))?;
+
+		mount.refresher.abort();
+
+		info!("Unmounted {}", path.display());
+
+		Ok(())
+	}
+
+	pub async fn shutdown(&self) {
+		for (_, mount) in self.mounts.lock().await.drain() {
+			mount.refresher.abort();
+		}
+	}
+}
@@ -0,0 +1,355 @@
+use crate::{
+	library::Library,
+	prisma::{file_path, location, object, space, tag},
+};
+
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+use prisma_client_rust::{Direction, QueryError};
+use sd_file_ext::kind::ObjectKind;
+
+use super::MountView;
+
+/// How many file paths are read at once when the tree is built
+const PAGE_SIZE: i64 = 1000;
+
+pub(super) const ROOT_INODE: u64 = 1;
+
+/// The inodes are made from what they show, so they stay the same when the tree is rebuilt. The
+/// same object has the same inode in every directory it's in.
+#[derive(Clone, Copy)]
+enum InodeKind {
+	View = 1,
+	Tag,
+	Kind,
+	Album,
+	Object,
+}
+
+fn inode(kind: InodeKind, id: u64) -> u64 {
+	((kind as u64) << 48) | id
+}
+
+file_path::select!(file_path_for_mount {
+	materialized_path
+	name
+	extension
+	size_in_bytes_bytes
+	date_modified
+	location: select { path }
+	object: select {
+		id
+		kind
+		tags: select { tag: select { id name date_deleted } }
+		spaces: select { space: select { id name } }
+	}
+});
+
+/// An object as the mounts show it, with the file its reads go to
+pub(super) struct MountedObject {
+	pub id: object::id::Type,
+	pub kind: i32,
+	pub name: String,
+	pub size: u64,
+	pub modified: SystemTime,
+	pub path: PathBuf,
+	pub tags: Vec<(tag::id::Type, String)>,
+	pub albums: Vec<(space::id::Type, String)>,
+}
+
+impl MountedObject {
+	fn from_file_path(file_path: file_path_for_mount::Data) -> Option<Self> {
+		let object = file_path.object?;
+		let name = match file_path.extension.as_deref() {
+			Some(extension) if !extension.is_empty() => {
+				format!("{}.{extension}", file_path.name?)
+			}
+			_ => file_path.name?,
+		};
+
+		Some(Self {
+			id: object.id,
+			kind: object.kind.unwrap_or(ObjectKind::Unknown as i32),
+			path: Path::new(&file_path.location?.path?)
+				.join(file_path.materialized_path?.trim_start_matches('/'))
+				.join(&name),
+			name,
+			size: file_path
+				.size_in_bytes_bytes
+				.as_deref()
+				.and_then(|bytes| bytes.try_into().ok())
+				.map_or(0, u64::from_be_bytes),
+			modified: file_path
+				.date_modified
+				.map_or(SystemTime::UNIX_EPOCH, Into::into),
+			tags: object
+				.tags
+				.into_iter()
+				.filter(|tag_on_object| tag_on_object.tag.date_deleted.is_none())
+				.map(|tag_on_object| {
+					let tag = tag_on_object.tag;
+					(
+						tag.id,
+						tag.name.unwrap_or_else(|| format!("Tag {}", tag.id)),
+					)
+				})
+				.collect(),
+			albums: object
+				.spaces
+				.into_iter()
+				.map(|object_in_space| {
+					let space = object_in_space.space;
+					(
+						space.id,
+						space.name.unwrap_or_else(|| format!("Album {}", space.id)),
+					)
+				})
+				.collect(),
+		})
+	}
+}
+
+pub(super) struct VirtualDir {
+	pub parent: u64,
+	pub entries: BTreeMap<String, u64>,
+}
+
+pub(super) struct VirtualFile {
+	pub size: u64,
+	pub modified: SystemTime,
+	pub path: PathBuf,
+}
+
+/// What a mount shows, rebuilt from the library every now and then. Files are shown by the first
+/// of their file paths on this node.
+pub(super) struct VirtualTree {
+	dirs: HashMap<u64, VirtualDir>,
+	files: HashMap<u64, VirtualFile>,
+}
+
+impl VirtualTree {
+	pub(super) async fn load(library: &Library, views: &[MountView]) -> Result<Self, QueryError> {
+		let mut objects = vec![];
+		let mut seen = HashSet::new();
+
+		for skip in (0..).step_by(PAGE_SIZE as usize) {
+			let page = library
+				.db
+				.file_path()
+				.find_many(vec![
+					file_path::is_dir::equals(Some(false)),
+					file_path::location::is(vec![location::node_id::equals(Some(
+						library.node_local_id,
+					))]),
+					file_path::object::is(vec![object::date_deleted::equals(None)]),
+				])
+				.order_by(file_path::id::order(Direction::Asc))
+				.skip(skip)
+				.take(PAGE_SIZE)
+				.select(file_path_for_mount::select())
+				.exec()
+				.await?;
+			let last_page = (page.len() as i64) < PAGE_SIZE;
+
+			objects.extend(
+				page.into_iter()
+					.filter_map(MountedObject::from_file_path)
+					.filter(|object| seen.insert(object.id)),
+			);
+
+			if last_page {
+				break;
+			}
+		}
+
+		Ok(Self::build(views, objects))
+	}
+
+	pub(super) fn build(views: &[MountView], mut objects: Vec<MountedObject>) -> Self {
+		let mut tree = Self {
+			dirs: HashMap::from([(
+				ROOT_INODE,
+				VirtualDir {
+					parent: ROOT_INODE,
+					entries: BTreeMap::new(),
+				},
+			)]),
+			files: HashMap::new(),
+		};
+
+		for &view in views {
+			tree.add_dir(
+				ROOT_INODE,
+				view.dir_name(),
+				inode(InodeKind::View, view as u64),
+			);
+		}
+
+		// Names taken by another object get a number, the oldest objects keep theirs
+		objects.sort_by_key(|object| object.id);
+
+		for object in objects {
+			let file_inode = inode(InodeKind::Object, object.id as u64);
+
+			for &view in views {
+				let view_inode = inode(InodeKind::View, view as u64);
+
+				match view {
+					MountView::All => tree.add_file(view_inode, &object.name, file_inode),
+					MountView::Tags => {
+						for (tag_id, tag_name) in &object.tags {
+							let tag_inode = inode(InodeKind::Tag, *tag_id as u64);
+							tree.add_dir(view_inode, tag_name, tag_inode);
+							tree.add_file(tag_inode, &object.name, file_inode);
+						}
+					}
+					MountView::Kinds => {
+						let kind_inode = inode(InodeKind::Kind, object.kind as u64);
+						let kind_name = ObjectKind::from_repr(object.kind).map_or_else(
+							|| format!("Kind {}", object.kind),
+							|kind| format!("{kind:?}"),
+						);
+						tree.add_dir(view_inode, &kind_name, kind_inode);
+						tree.add_file(kind_inode, &object.name, file_inode);
+					}
+					MountView::Albums => {
+						for (space_id, space_name) in &object.albums {
+							let album_inode = inode(InodeKind::Album, *space_id as u64);
+							tree.add_dir(view_inode, space_name, album_inode);
+							tree.add_file(album_inode, &object.name, file_inode);
+						}
+					}
+				}
+			}
+
+			tree.files.insert(
+				file_inode,
+				VirtualFile {
+					size: object.size,
+					modified: object.modified,
+					path: object.path,
+				},
+			);
+		}
+
+		tree
+	}
+
+	fn add_dir(&mut self, parent: u64, name: &str, dir_inode: u64) {
+		if self.dirs.contains_key(&dir_inode) {
+			return;
+		}
+
+		if let Some(parent_dir) = self.dirs.get_mut(&parent) {
+			let name = free_name(&parent_dir.entries, name);
+			parent_dir.entries.insert(name, dir_inode);
+		}
+
+		self.dirs.insert(
+			dir_inode,
+			VirtualDir {
+				parent,
+				entries: BTreeMap::new(),
+			},
+		);
+	}
+
+	fn add_file(&mut self, dir_inode: u64, name: &str, file_inode: u64) {
+		if let Some(dir) = self.dirs.get_mut(&dir_inode) {
+			let name = free_name(&dir.entries, name);
+			dir.entries.insert(name, file_inode);
+		}
+	}
+
+	pub(super) fn dir(&self, inode: u64) -> Option<&VirtualDir> {
+		self.dirs.get(&inode)
+	}
+
+	pub(super) fn file(&self, inode: u64) -> Option<&VirtualFile> {
+		self.files.get(&inode)
+	}
+
+	pub(super) fn lookup(&self, parent: u64, name: &str) -> Option<u64> {
+		self.dirs.get(&parent)?.entries.get(name).copied()
+	}
+}
+
+/// `name` without what a file name can't have, with a number if it's taken in `entries`
+fn free_name(entries: &BTreeMap<String, u64>, name: &str) -> String {
+	let name = name.replace(['/', '\0'], "_");
+	let name = match name.as_str() {
+		"" | "." | ".." => "_".to_string(),
+		_ => name,
+	};
+
+	if !entries.contains_key(&name) {
+		return name;
+	}
+
+	let (stem, extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+		_ => (name.as_str(), String::new()),
+	};
+
+	(2..)
+		.map(|i| format!("{stem} ({i}){extension}"))
+		.find(|name| !entries.contains_key(name))
+		.expect("there's always a free name")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn object(id: i32, name: &str, tags: &[(i32, &str)]) -> MountedObject {
+		MountedObject {
+			id,
+			kind: ObjectKind::Image as i32,
+			name: name.to_string(),
+			size: 0,
+			modified: SystemTime::UNIX_EPOCH,
+			path: PathBuf::from(format!("/photos/{id}/{name}")),
+			tags: tags
+				.iter()
+				.map(|&(id, name)| (id, name.to_string()))
+				.collect(),
+			albums: vec![],
+		}
+	}
+
+	#[test]
+	fn shows_objects_once_per_directory() {
+		let tree = VirtualTree::build(
+			&[MountView::All, MountView::Tags, MountView::Kinds],
+			vec![
+				object(2, "beach.jpg", &[(1, "Holidays")]),
+				object(1, "beach.jpg", &[(1, "Holidays"), (2, "AC/DC")]),
+			],
+		);
+
+		let all = tree.lookup(ROOT_INODE, "All").unwrap();
+		let first = tree.lookup(all, "beach.jpg").unwrap();
+		let second = tree.lookup(all, "beach (2).jpg").unwrap();
+		assert_eq!(first, inode(InodeKind::Object, 1));
+		assert_eq!(second, inode(InodeKind::Object, 2));
+		assert_eq!(
+			tree.file(second).unwrap().path,
+			PathBuf::from("/photos/2/beach.jpg")
+		);
+
+		let tags = tree.lookup(ROOT_INODE, "Tags").unwrap();
+		let holidays = tree.lookup(tags, "Holidays").unwrap();
+		assert_eq!(tree.dir(holidays).unwrap().entries.len(), 2);
+		assert_eq!(tree.dir(holidays).unwrap().parent, tags);
+		let acdc = tree.lookup(tags, "AC_DC").unwrap();
+		assert_eq!(tree.lookup(acdc, "beach.jpg"), Some(first));
+
+		let kinds = tree.lookup(ROOT_INODE, "Kinds").unwrap();
+		assert!(tree.lookup(kinds, "Image").is_some());
+		assert_eq!(tree.lookup(ROOT_INODE, "Albums"), None);
+	}
+}
@@ -0,0 +1,324 @@
+//! Analytics of how the node is used, only counted once turned on in the settings of the node and
+//! never sent anywhere: they're kept in `analytics.json` in the data directory for users to look at
+//! their own usage, and to export them to share with a bug report if they want to.
+//!
+//! Jobs are counted by the node, with how long they took. The features of the app and its timings
+//! are counted when the app records them, by names it chooses.
+
+use crate::{job::JobStatus, util::error::FileIOError};
+
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex, Weak,
+	},
+	time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::fs;
+use tracing::{error, warn};
+
+pub const ANALYTICS_FILE_NAME: &str = "analytics.json";
+/// How long the figures stay only in memory, they're written when the node shuts down too
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum AnalyticsError {
+	#[error("analytics names can only have letters, digits, '.', '-' and '_', up to {MAX_NAME_LENGTH} of them: {0}")]
+	InvalidName(String),
+	#[error("failed to serialize the analytics: {0}")]
+	Serde(#[from] serde_json::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<AnalyticsError> for rspc::Error {
+	fn from(e: AnalyticsError) -> Self {
+		let code = match e {
+			AnalyticsError::InvalidName(_) => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Default, PartialEq)]
+pub struct Timing {
+	pub count: u32,
+	pub total_ms: f64,
+	pub max_ms: f64,
+}
+
+impl Timing {
+	fn record(&mut self, ms: f64) {
+		self.count = self.count.saturating_add(1);
+		self.total_ms += ms;
+		self.max_ms = self.max_ms.max(ms);
+	}
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Default, PartialEq)]
+pub struct JobAnalytics {
+	pub completed: u32,
+	pub completed_with_errors: u32,
+	pub failed: u32,
+	pub canceled: u32,
+	/// How long the jobs took, finished or not
+	pub duration: Timing,
+}
+
+/// Everything counted since `since`, as it's exported
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct AnalyticsReport {
+	pub since: DateTime<Utc>,
+	/// By the name of the job, like `indexer`
+	pub jobs: BTreeMap<String, JobAnalytics>,
+	/// How many times each feature was used, by the names the app records them with
+	pub features: BTreeMap<String, u32>,
+	pub timings: BTreeMap<String, Timing>,
+}
+
+impl Default for AnalyticsReport {
+	fn default() -> Self {
+		Self {
+			since: Utc::now(),
+			jobs: BTreeMap::new(),
+			features: BTreeMap::new(),
+			timings: BTreeMap::new(),
+		}
+	}
+}
+
+/// What the app records
+#[derive(Deserialize, Type, Debug)]
+#[serde(tag = "type")]
+pub enum AnalyticsEvent {
+	Feature { name: String },
+	Timing { name: String, ms: f64 },
+}
+
+pub struct Analytics {
+	enabled: AtomicBool,
+	path: PathBuf,
+	report: Mutex<AnalyticsReport>,
+	/// Whether there's something not written yet
+	dirty: AtomicBool,
+}
+
+impl Analytics {
+	/// Loads what was counted before, an unreadable file starts over
+	pub(crate) async fn load(data_dir: &Path, enabled: bool) -> Self {
+		let path = data_dir.join(ANALYTICS_FILE_NAME);
+
+		let report = match fs::read(&path).await {
+			Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+				warn!("Failed to read the analytics, starting over: {e}");
+				AnalyticsReport::default()
+			}),
+			Err(_) => AnalyticsReport::default(),
+		};
+
+		Self {
+			enabled: AtomicBool::new(enabled),
+			path,
+			report: Mutex::new(report),
+			dirty: AtomicBool::new(false),
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.enabled.load(Ordering::Relaxed)
+	}
+
+	/// Counting stops when they're turned off, what was counted stays until it's cleared
+	pub(crate) fn set_enabled(&self, enabled: bool) {
+		self.enabled.store(enabled, Ordering::Relaxed);
+	}
+
+	fn update(&self, f: impl FnOnce(&mut AnalyticsReport)) {
+		if !self.is_enabled() {
+			return;
+		}
+
+		f(&mut self.report.lock().unwrap_or_else(|e| e.into_inner()));
+		self.dirty.store(true, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_job(&self, name: &str, status: JobStatus, duration: Duration) {
+		// Paused jobs are counted when they finish
+		if !status.is_finished() || status == JobStatus::Paused {
+			return;
+		}
+
+		self.update(|report| {
+			let job = report.jobs.entry(name.to_string()).or_default();
+			let count = match status {
+				JobStatus::Completed => &mut job.completed,
+				JobStatus::CompletedWithErrors => &mut job.completed_with_errors,
+				JobStatus::Failed => &mut job.failed,
+				_ => &mut job.canceled,
+			};
+			*count = count.saturating_add(1);
+			job.duration.record(duration.as_secs_f64() * 1000.0);
+		});
+	}
+
+	pub fn record(&self, event: AnalyticsEvent) -> Result<(), AnalyticsError> {
+		match event {
+			AnalyticsEvent::Feature { name } => {
+				validate_name(&name)?;
+				self.update(|report| {
+					let count = report.features.entry(name).or_default();
+					*count = count.saturating_add(1);
+				});
+			}
+			AnalyticsEvent::Timing { name, ms } => {
+				validate_name(&name)?;
+				if ms.is_finite() && ms >= 0.0 {
+					self.update(|report| report.timings.entry(name).or_default().record(ms));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	pub fn report(&self) -> AnalyticsReport {
+		self.report
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.clone()
+	}
+
+	pub async fn clear(&self) -> Result<(), AnalyticsError> {
+		*self.report.lock().unwrap_or_else(|e| e.into_inner()) = AnalyticsReport::default();
+
+		match fs::remove_file(&self.path).await {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+				Err(FileIOError::from((&self.path, e)).into())
+			}
+			_ => {
+				self.dirty.store(false, Ordering::Relaxed);
+				Ok(())
+			}
+		}
+	}
+
+	/// Writes the figures if they changed since they were last written
+	pub(crate) async fn save(&self) -> Result<(), AnalyticsError> {
+		if !self.dirty.swap(false, Ordering::Relaxed) {
+			return Ok(());
+		}
+
+		let json = serde_json::to_vec_pretty(&self.report())?;
+		fs::write(&self.path, json)
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)).into())
+	}
+
+	/// Writes the figures to `path`, to share them
+	pub async fn export(&self, path: &Path) -> Result<(), AnalyticsError> {
+		let json = serde_json::to_vec_pretty(&self.report())?;
+		fs::write(path, json)
+			.await
+			.map_err(|e| FileIOError::from((path, e)).into())
+	}
+}
+
+/// Writes the figures every now and then, until the node is dropped
+pub(crate) fn spawn_analytics_saver(analytics: Weak<Analytics>) {
+	tokio::spawn(async move {
+		loop {
+			tokio::time::sleep(SAVE_INTERVAL).await;
+
+			let Some(analytics) = analytics.upgrade() else {
+				break;
+			};
+			if let Err(e) = analytics.save().await {
+				error!("Failed to save the analytics: {e}");
+			}
+		}
+	});
+}
+
+fn validate_name(name: &str) -> Result<(), AnalyticsError> {
+	if name.is_empty()
+		|| name.len() > MAX_NAME_LENGTH
+		|| !name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+	{
+		return Err(AnalyticsError::InvalidName(name.to_string()));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn analytics(enabled: bool) -> Analytics {
+		Analytics {
+			enabled: AtomicBool::new(enabled),
+			path: PathBuf::new(),
+			report: Mutex::new(AnalyticsReport::default()),
+			dirty: AtomicBool::new(false),
+		}
+	}
+
+	#[test]
+	fn counts_only_when_enabled() {
+		let analytics = analytics(false);
+		analytics.record_job("indexer", JobStatus::Completed, Duration::from_secs(1));
+		assert!(analytics.report().jobs.is_empty());
+
+		analytics.set_enabled(true);
+		analytics.record_job("indexer", JobStatus::Completed, Duration::from_secs(1));
+		analytics.record_job("indexer", JobStatus::Failed, Duration::from_secs(3));
+		analytics.record_job("indexer", JobStatus::Paused, Duration::from_secs(3));
+		analytics
+			.record(AnalyticsEvent::Feature {
+				name: "search".to_string(),
+			})
+			.unwrap();
+
+		let report = analytics.report();
+		let indexer = &report.jobs["indexer"];
+		assert_eq!((indexer.completed, indexer.failed), (1, 1));
+		assert_eq!(indexer.duration.count, 2);
+		assert_eq!(indexer.duration.max_ms, 3000.0);
+		assert_eq!(report.features["search"], 1);
+	}
+
+	#[test]
+	fn rejects_invalid_names() {
+		let analytics = analytics(true);
+		assert!(analytics
+			.record(AnalyticsEvent::Timing {
+				name: "explorer.open".to_string(),
+				ms: 12.5,
+			})
+			.is_ok());
+		assert!(analytics
+			.record(AnalyticsEvent::Feature {
+				name: "/home/me/secret.txt".to_string(),
+			})
+			.is_err());
+		assert!(analytics
+			.record(AnalyticsEvent::Timing {
+				name: "explorer.open".to_string(),
+				ms: f64::NAN,
+			})
+			.is_ok());
+		assert_eq!(analytics.report().timings["explorer.open"].count, 1);
+	}
+}
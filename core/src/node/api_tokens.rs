@@ -0,0 +1,381 @@
+//! Tokens for the integrations of other apps with the server, each one limited to what it was made
+//! for and revocable on its own, unlike the token of `$SD_AUTH_TOKEN` which can do everything.
+//!
+//! Only the hashes of the tokens are kept, in `api_tokens.json` in the data directory. The server
+//! checks their scopes on the procedures called over HTTP and on the files it serves. The
+//! WebSocket of rspc can call any procedure, so it's refused to them. Paired nodes don't use
+//! tokens, what they can do is set by their [`NodePermissions`](crate::p2p::NodePermissions).
+
+use crate::{
+	util::error::FileIOError,
+	webdav::{generate_password, hash_password, hashes_match},
+};
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, sync::RwLock};
+use tracing::warn;
+use uuid::Uuid;
+
+pub const API_TOKENS_FILE_NAME: &str = "api_tokens.json";
+/// So the tokens can be told apart from other secrets, like by secret scanners
+const TOKEN_PREFIX: &str = "sdt_";
+const MAX_NAME_LENGTH: usize = 64;
+/// What every token can call, to find the ids of the libraries for the other procedures
+const ALWAYS_ALLOWED: [&str; 2] = ["buildInfo", "library.list"];
+/// Whatever its scopes, a token can't touch keys or tokens, which would give it more than them
+const NEVER_ALLOWED: [&str; 2] = ["keys.", "apiTokens."];
+/// The prefixes of the keys of the queries reading the library and the node, none of them gives
+/// back credentials
+const READ_ONLY_QUERIES: [&str; 20] = [
+	"analytics.",
+	"backups.",
+	"categories.",
+	"files.",
+	"jobs.",
+	"library.",
+	"locations.",
+	"mounts.",
+	"nodeState",
+	"nodes.",
+	"plugins.",
+	"rules.",
+	"search.",
+	"sharing.",
+	"sync.",
+	"tags.",
+	"trash.",
+	"volumes.",
+	"webdav.",
+	"webhooks.",
+];
+
+#[derive(Error, Debug)]
+pub enum ApiTokenError {
+	#[error("the name of a token can't be empty or longer than {MAX_NAME_LENGTH} characters")]
+	InvalidName,
+	#[error("a token needs at least one scope")]
+	NoScopes,
+	#[error("token not found <id='{0}'>")]
+	NotFound(Uuid),
+	#[error("failed to serialize the tokens: {0}")]
+	Serde(#[from] serde_json::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<ApiTokenError> for rspc::Error {
+	fn from(e: ApiTokenError) -> Self {
+		let code = match e {
+			ApiTokenError::InvalidName | ApiTokenError::NoScopes => rspc::ErrorCode::BadRequest,
+			ApiTokenError::NotFound(_) => rspc::ErrorCode::NotFound,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum TokenScope {
+	/// The queries reading the library and the node, but no mutation
+	ReadOnly,
+	/// The queries of the search
+	Search,
+	/// The queries and mutations of the jobs
+	Jobs,
+	/// The files and thumbnails served at `/spacedrive`
+	FileContent,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+pub struct ApiToken {
+	pub id: Uuid,
+	pub name: String,
+	pub scopes: Vec<TokenScope>,
+	pub date_created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug)]
+pub struct CreatedApiToken {
+	pub id: Uuid,
+	/// Only given back now, a token that lost it has to be made again
+	pub token: String,
+}
+
+impl TokenScope {
+	/// The prefixes of the keys of the procedures it allows
+	fn allowed(self, procedure: Procedure) -> &'static [&'static str] {
+		match (self, procedure) {
+			(Self::ReadOnly, Procedure::Query(_)) => &READ_ONLY_QUERIES,
+			(Self::Search, Procedure::Query(_)) => &["search."],
+			(Self::Jobs, _) => &["jobs."],
+			_ => &[],
+		}
+	}
+}
+
+/// A procedure of the router, by its key
+#[derive(Debug, Clone, Copy)]
+pub enum Procedure<'a> {
+	Query(&'a str),
+	Mutation(&'a str),
+}
+
+impl Procedure<'_> {
+	fn key(&self) -> &str {
+		match self {
+			Self::Query(key) | Self::Mutation(key) => key,
+		}
+	}
+}
+
+impl ApiToken {
+	fn has(&self, scope: TokenScope) -> bool {
+		self.scopes.contains(&scope)
+	}
+
+	pub fn allows(&self, procedure: Procedure) -> bool {
+		let key = procedure.key();
+
+		if NEVER_ALLOWED.iter().any(|prefix| key.starts_with(prefix)) {
+			return false;
+		}
+
+		matches!(procedure, Procedure::Query(key) if ALWAYS_ALLOWED.contains(&key))
+			|| self.scopes.iter().any(|scope| {
+				scope
+					.allowed(procedure)
+					.iter()
+					.any(|prefix| key.starts_with(prefix))
+			})
+	}
+
+	/// The files themselves, thumbnails only need the token to read the library
+	pub fn allows_file_content(&self, thumbnail: bool) -> bool {
+		self.has(TokenScope::FileContent) || (thumbnail && self.has(TokenScope::ReadOnly))
+	}
+
+	pub fn allows_graphql(&self) -> bool {
+		// The GraphQL API has no mutations
+		self.has(TokenScope::ReadOnly)
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+	#[serde(flatten)]
+	token: ApiToken,
+	hash: Vec<u8>,
+}
+
+pub struct ApiTokens {
+	path: PathBuf,
+	tokens: RwLock<Vec<StoredToken>>,
+}
+
+impl ApiTokens {
+	/// An unreadable file is ignored with a warning, its tokens stop working
+	pub(crate) async fn load(data_dir: &Path) -> Self {
+		let path = data_dir.join(API_TOKENS_FILE_NAME);
+
+		let tokens = match fs::read(&path).await {
+			Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+				warn!("Failed to read the API tokens, they were ignored: {e}");
+				vec![]
+			}),
+			Err(_) => vec![],
+		};
+
+		Self {
+			path,
+			tokens: RwLock::new(tokens),
+		}
+	}
+
+	pub async fn list(&self) -> Vec<ApiToken> {
+		self.tokens
+			.read()
+			.await
+			.iter()
+			.map(|stored| stored.token.clone())
+			.collect()
+	}
+
+	pub async fn create(
+		&self,
+		name: String,
+		mut scopes: Vec<TokenScope>,
+	) -> Result<CreatedApiToken, ApiTokenError> {
+		let name = name.trim().to_string();
+		if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+			return Err(ApiTokenError::InvalidName);
+		}
+
+		scopes.sort_unstable();
+		scopes.dedup();
+		if scopes.is_empty() {
+			return Err(ApiTokenError::NoScopes);
+		}
+
+		let id = Uuid::new_v4();
+		let token = format!("{TOKEN_PREFIX}{}", generate_password());
+
+		let mut tokens = self.tokens.write().await;
+		tokens.push(StoredToken {
+			token: ApiToken {
+				id,
+				name,
+				scopes,
+				date_created: Utc::now(),
+			},
+			hash: hash_password(&token),
+		});
+
+		if let Err(e) = self.save(&tokens).await {
+			tokens.pop();
+			return Err(e);
+		}
+
+		Ok(CreatedApiToken { id, token })
+	}
+
+	/// The token stops working right away
+	pub async fn revoke(&self, id: Uuid) -> Result<(), ApiTokenError> {
+		let mut tokens = self.tokens.write().await;
+
+		let i = tokens
+			.iter()
+			.position(|stored| stored.token.id == id)
+			.ok_or(ApiTokenError::NotFound(id))?;
+		let revoked = tokens.remove(i);
+
+		if let Err(e) = self.save(&tokens).await {
+			tokens.insert(i, revoked);
+			return Err(e);
+		}
+
+		Ok(())
+	}
+
+	/// The token with this secret, if there's one
+	pub async fn authenticate(&self, token: &str) -> Option<ApiToken> {
+		if !token.starts_with(TOKEN_PREFIX) {
+			return None;
+		}
+
+		let hash = hash_password(token);
+		self.tokens
+			.read()
+			.await
+			.iter()
+			.find(|stored| hashes_match(&stored.hash, &hash))
+			.map(|stored| stored.token.clone())
+	}
+
+	async fn save(&self, tokens: &[StoredToken]) -> Result<(), ApiTokenError> {
+		let json = serde_json::to_vec_pretty(tokens)?;
+		fs::write(&self.path, json)
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)).into())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn token(scopes: &[TokenScope]) -> ApiToken {
+		ApiToken {
+			id: Uuid::new_v4(),
+			name: "test".to_string(),
+			scopes: scopes.to_vec(),
+			date_created: Utc::now(),
+		}
+	}
+
+	#[test]
+	fn scopes_limit_procedures() {
+		let search = token(&[TokenScope::Search]);
+		assert!(search.allows(Procedure::Query("search.paths")));
+		assert!(search.allows(Procedure::Query("library.list")));
+		assert!(!search.allows(Procedure::Query("locations.list")));
+		assert!(!search.allows(Procedure::Mutation("search.saved.create")));
+		assert!(!search.allows_file_content(true));
+
+		let read_only = token(&[TokenScope::ReadOnly]);
+		assert!(read_only.allows(Procedure::Query("locations.list")));
+		assert!(!read_only.allows(Procedure::Mutation("tags.create")));
+		assert!(read_only.allows_file_content(true));
+		assert!(!read_only.allows_file_content(false));
+
+		let jobs = token(&[TokenScope::Jobs, TokenScope::FileContent]);
+		assert!(jobs.allows(Procedure::Query("jobs.reports")));
+		assert!(jobs.allows(Procedure::Mutation("jobs.pause")));
+		assert!(!jobs.allows(Procedure::Mutation("locations.delete")));
+		assert!(jobs.allows_file_content(false));
+		assert!(!jobs.allows_graphql());
+	}
+
+	#[test]
+	fn keys_and_tokens_are_never_allowed() {
+		let all = token(&[
+			TokenScope::ReadOnly,
+			TokenScope::Search,
+			TokenScope::Jobs,
+			TokenScope::FileContent,
+		]);
+
+		for procedure in [
+			Procedure::Query("keys.getKey"),
+			Procedure::Query("keys.getSecretKey"),
+			Procedure::Query("keys.list"),
+			Procedure::Query("apiTokens.list"),
+			Procedure::Mutation("apiTokens.create"),
+			Procedure::Mutation("keys.mount"),
+			// Not in the allowlist of any scope
+			Procedure::Query("logs.recent"),
+		] {
+			assert!(!all.allows(procedure), "{procedure:?}");
+		}
+
+		assert!(all.allows(Procedure::Query("nodeState")));
+		assert!(all.allows(Procedure::Mutation("jobs.resume")));
+		assert!(!all.allows(Procedure::Mutation("library.delete")));
+	}
+
+	#[tokio::test]
+	async fn revoked_tokens_stop_working() {
+		let dir = tempfile::tempdir().unwrap();
+		let tokens = ApiTokens::load(dir.path()).await;
+
+		assert!(matches!(
+			tokens.create("ci".to_string(), vec![]).await,
+			Err(ApiTokenError::NoScopes)
+		));
+
+		let created = tokens
+			.create(" ci ".to_string(), vec![TokenScope::Jobs, TokenScope::Jobs])
+			.await
+			.unwrap();
+		let token = tokens.authenticate(&created.token).await.unwrap();
+		assert_eq!((token.name.as_str(), token.scopes.len()), ("ci", 1));
+		assert!(tokens.authenticate("sdt_wrong").await.is_none());
+
+		// They're kept across restarts
+		let tokens = ApiTokens::load(dir.path()).await;
+		assert!(tokens.authenticate(&created.token).await.is_some());
+
+		tokens.revoke(created.id).await.unwrap();
+		assert!(tokens.authenticate(&created.token).await.is_none());
+		assert!(matches!(
+			tokens.revoke(created.id).await,
+			Err(ApiTokenError::NotFound(_))
+		));
+	}
+}
@@ -9,7 +9,14 @@ use std::{
 use tokio::sync::{RwLock, RwLockWriteGuard};
 use uuid::Uuid;
 
-use crate::util::migrator::{Migrate, MigratorError};
+use crate::{
+	object::preview::ThumbnailSize,
+	p2p::{BandwidthLimits, SyncSchedule},
+	util::{
+		db::DatabaseSettings,
+		migrator::{Migrate, MigratorError},
+	},
+};
 
 /// NODE_STATE_CONFIG_NAME is the name of the file which stores the NodeState
 pub const NODE_STATE_CONFIG_NAME: &str = "node_state.sdconfig";
@@ -29,6 +36,33 @@ pub struct NodeConfig {
 	// TODO: These will probs be replaced by your Spacedrive account in the near future.
 	pub p2p_email: Option<String>,
 	pub p2p_img_url: Option<String>,
+	/// Size of the generated thumbnails, shared by all libraries on this node.
+	#[serde(default)]
+	pub thumbnail_size: ThumbnailSize,
+	/// Maximum size in MiB of the thumbnail cache, least recently used thumbnails are evicted
+	/// when it's exceeded. `None` means unlimited.
+	#[serde(default)]
+	pub thumbnail_cache_max_size_mb: Option<u32>,
+	/// Speed limits of the transfers with other nodes, like Spacedrop
+	#[serde(default)]
+	pub p2p_bandwidth_limits: BandwidthLimits,
+	/// Address of the relay used to reach the nodes that aren't on the local network. Anyone can
+	/// host one, the relay can't read what goes through it as connections are end-to-end encrypted.
+	#[serde(default)]
+	pub p2p_relay: Option<String>,
+	/// Addresses of the nodes to connect to directly, for when they can't be discovered on the
+	/// local network. Each is a hostname or IP address followed by a port.
+	#[serde(default)]
+	pub p2p_manual_peers: Vec<String>,
+	/// When the changes made on this node are sent to the paired nodes
+	#[serde(default)]
+	pub p2p_sync_schedule: SyncSchedule,
+	/// Whether the usage of the node is counted, only ever kept on this node
+	#[serde(default)]
+	pub analytics_enabled: bool,
+	/// How the databases of the libraries are opened
+	#[serde(default)]
+	pub database: DatabaseSettings,
 }
 
 // A version of [NodeConfig] that is safe to share with the frontend
@@ -43,6 +77,14 @@ pub struct SanitisedNodeConfig {
 	// TODO: These will probs be replaced by your Spacedrive account in the near future.
 	pub p2p_email: Option<String>,
 	pub p2p_img_url: Option<String>,
+	pub thumbnail_size: ThumbnailSize,
+	pub thumbnail_cache_max_size_mb: Option<u32>,
+	pub p2p_bandwidth_limits: BandwidthLimits,
+	pub p2p_relay: Option<String>,
+	pub p2p_manual_peers: Vec<String>,
+	pub p2p_sync_schedule: SyncSchedule,
+	pub analytics_enabled: bool,
+	pub database: DatabaseSettings,
 }
 
 impl From<NodeConfig> for SanitisedNodeConfig {
@@ -53,6 +95,14 @@ impl From<NodeConfig> for SanitisedNodeConfig {
 			p2p_port: value.p2p_port,
 			p2p_email: value.p2p_email,
 			p2p_img_url: value.p2p_img_url,
+			thumbnail_size: value.thumbnail_size,
+			thumbnail_cache_max_size_mb: value.thumbnail_cache_max_size_mb,
+			p2p_bandwidth_limits: value.p2p_bandwidth_limits,
+			p2p_relay: value.p2p_relay,
+			p2p_manual_peers: value.p2p_manual_peers,
+			p2p_sync_schedule: value.p2p_sync_schedule,
+			analytics_enabled: value.analytics_enabled,
+			database: value.database,
 		}
 	}
 }
@@ -78,6 +128,14 @@ impl Migrate for NodeConfig {
 			keypair: Keypair::generate(),
 			p2p_email: None,
 			p2p_img_url: None,
+			thumbnail_size: ThumbnailSize::default(),
+			thumbnail_cache_max_size_mb: None,
+			p2p_bandwidth_limits: BandwidthLimits::default(),
+			p2p_relay: None,
+			p2p_manual_peers: Vec::new(),
+			p2p_sync_schedule: SyncSchedule::default(),
+			analytics_enabled: false,
+			database: DatabaseSettings::default(),
 		})
 	}
 
@@ -109,6 +167,14 @@ impl Default for NodeConfig {
 			keypair: Keypair::generate(),
 			p2p_email: None,
 			p2p_img_url: None,
+			thumbnail_size: ThumbnailSize::default(),
+			thumbnail_cache_max_size_mb: None,
+			p2p_bandwidth_limits: BandwidthLimits::default(),
+			p2p_relay: None,
+			p2p_manual_peers: Vec::new(),
+			p2p_sync_schedule: SyncSchedule::default(),
+			analytics_enabled: false,
+			database: DatabaseSettings::default(),
 		}
 	}
 }
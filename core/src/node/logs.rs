@@ -0,0 +1,528 @@
+//! The logs of the node, with filters that can be changed while it runs.
+//!
+//! Besides the text logs of the node and its standard output, the lines logged while working on a
+//! library, in the spans of its jobs and location watchers or with a `library_id` field, are written
+//! as JSON Lines to `logs/libraries/<library id>/` in the data directory, rotated daily. The latest
+//! lines are kept in memory for the app to show without reading the files.
+//!
+//! The last lines of each job are kept too, and streamed while it runs. They're written to
+//! `logs/jobs/<job id>.jsonl` when the job stops, for the app to show them afterwards.
+
+use crate::util::error::FileIOError;
+
+use std::{
+	collections::{BTreeMap, HashMap, VecDeque},
+	fmt::Debug,
+	io::Write,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, sync::broadcast};
+use tracing::{
+	field::{Field, Visit},
+	span, Collect, Event, Level,
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+	registry::LookupSpan,
+	reload,
+	subscribe::{Context, Subscribe},
+	EnvFilter, Registry,
+};
+use uuid::Uuid;
+
+/// What's logged when `$RUST_LOG` doesn't say otherwise
+const DEFAULT_DIRECTIVES: &str = "warn,sd_core=debug,sd_core::location::manager=info,\
+	sd_core_mobile=debug,server=debug,spacedrive=debug,rspc=debug";
+/// How many of the latest lines are kept in memory
+const RECENT_LINES: usize = 1000;
+const MAX_LIBRARY_LOG_FILES: usize = 4;
+/// How many of the last lines of each job are kept
+const JOB_TAIL_LINES: usize = 500;
+/// How long the last lines of the jobs are kept once they stopped
+const JOB_LOGS_MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+static LOGS: OnceCell<Logs> = OnceCell::new();
+
+#[derive(Error, Debug)]
+pub enum LogsError {
+	#[error("the logger of the node isn't running")]
+	NotInitialized,
+	#[error("invalid log filter: {0}")]
+	InvalidFilter(String),
+	#[error("failed to change the log filter: {0}")]
+	Reload(#[from] reload::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<LogsError> for rspc::Error {
+	fn from(e: LogsError) -> Self {
+		let code = match e {
+			LogsError::InvalidFilter(_) => rspc::ErrorCode::BadRequest,
+			LogsError::NotInitialized | LogsError::Reload(_) | LogsError::FileIO(_) => {
+				rspc::ErrorCode::InternalServerError
+			}
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Ordered from the most verbose
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+	Trace,
+	Debug,
+	Info,
+	Warn,
+	Error,
+}
+
+impl From<&Level> for LogLevel {
+	fn from(level: &Level) -> Self {
+		match *level {
+			Level::TRACE => Self::Trace,
+			Level::DEBUG => Self::Debug,
+			Level::INFO => Self::Info,
+			Level::WARN => Self::Warn,
+			_ => Self::Error,
+		}
+	}
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct LogLine {
+	pub timestamp: DateTime<Utc>,
+	pub level: LogLevel,
+	/// The module it was logged from, like `sd_core::job::worker`
+	pub target: String,
+	pub message: String,
+	pub fields: BTreeMap<String, String>,
+	pub library_id: Option<Uuid>,
+	/// The job it was logged by, which is in a library too
+	pub job_id: Option<Uuid>,
+}
+
+struct Shared {
+	recent: Mutex<VecDeque<LogLine>>,
+	libraries_dir: PathBuf,
+	library_files: Mutex<HashMap<Uuid, RollingFileAppender>>,
+	jobs_dir: PathBuf,
+	job_tails: Mutex<HashMap<Uuid, VecDeque<LogLine>>>,
+	job_lines_tx: broadcast::Sender<LogLine>,
+}
+
+impl Shared {
+	fn push(&self, line: LogLine) {
+		if let Some(library_id) = line.library_id {
+			self.write_library_line(library_id, &line);
+		}
+
+		if let Some(job_id) = line.job_id {
+			let mut job_tails = self.job_tails.lock().unwrap_or_else(|e| e.into_inner());
+			let tail = job_tails.entry(job_id).or_default();
+			if tail.len() == JOB_TAIL_LINES {
+				tail.pop_front();
+			}
+			tail.push_back(line.clone());
+
+			// Nobody watching the job is fine
+			self.job_lines_tx.send(line.clone()).ok();
+		}
+
+		let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+		if recent.len() == RECENT_LINES {
+			recent.pop_front();
+		}
+		recent.push_back(line);
+	}
+
+	fn write_library_line(&self, library_id: Uuid, line: &LogLine) {
+		let Ok(mut json) = serde_json::to_vec(line) else {
+			return;
+		};
+		json.push(b'\n');
+
+		let mut files = self.library_files.lock().unwrap_or_else(|e| e.into_inner());
+		let file = match files.get_mut(&library_id) {
+			Some(file) => file,
+			None => {
+				let appender = RollingFileAppender::builder()
+					.filename_prefix("sd.json.log")
+					.rotation(Rotation::DAILY)
+					.max_log_files(MAX_LIBRARY_LOG_FILES)
+					.build(self.libraries_dir.join(library_id.to_string()));
+
+				match appender {
+					Ok(appender) => files.entry(library_id).or_insert(appender),
+					// Not logging it, that would come back here
+					Err(e) => return eprintln!("Failed to open the logs of a library: {e}"),
+				}
+			}
+		};
+
+		if let Err(e) = file.write_all(&json) {
+			eprintln!("Failed to write to the logs of a library: {e}");
+		}
+	}
+}
+
+/// Changes the filter of the logs and gives the latest lines, once the logger is initialized
+pub struct Logs {
+	filter: reload::Handle<EnvFilter, Registry>,
+	directives: Mutex<String>,
+	shared: Arc<Shared>,
+}
+
+impl Logs {
+	pub fn get() -> Result<&'static Self, LogsError> {
+		LOGS.get().ok_or(LogsError::NotInitialized)
+	}
+
+	pub fn directives(&self) -> String {
+		self.directives
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.clone()
+	}
+
+	/// Replaces the filter with `directives`, like `warn,sd_core=debug,sd_core::job=trace`. An
+	/// empty filter goes back to the one the node started with.
+	pub fn set_directives(&self, directives: &str) -> Result<(), LogsError> {
+		let directives = match directives.trim() {
+			"" => default_directives(),
+			directives => directives.to_string(),
+		};
+
+		let filter =
+			EnvFilter::try_new(&directives).map_err(|e| LogsError::InvalidFilter(e.to_string()))?;
+		self.filter.reload(filter)?;
+
+		*self.directives.lock().unwrap_or_else(|e| e.into_inner()) = directives;
+
+		Ok(())
+	}
+
+	/// The last lines of a job, from the oldest, while it runs and after it stopped
+	pub async fn job_tail(&self, job_id: Uuid) -> Result<Vec<LogLine>, LogsError> {
+		if let Some(tail) = self
+			.shared
+			.job_tails
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.get(&job_id)
+		{
+			return Ok(tail.iter().cloned().collect());
+		}
+
+		let path = job_log_path(&self.shared.jobs_dir, job_id);
+		match fs::read(&path).await {
+			Ok(bytes) => Ok(bytes
+				.split(|&b| b == b'\n')
+				.filter_map(|line| serde_json::from_slice(line).ok())
+				.collect()),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+			Err(e) => Err(FileIOError::from((&path, e)).into()),
+		}
+	}
+
+	/// The lines of every job from now on, as they're logged
+	pub fn job_lines(&self) -> broadcast::Receiver<LogLine> {
+		self.shared.job_lines_tx.subscribe()
+	}
+
+	/// Writes the last lines of a job that stopped, and removes those of the jobs that stopped long
+	/// ago
+	pub(crate) async fn persist_job(&self, job_id: Uuid) -> Result<(), LogsError> {
+		let Some(tail) = self
+			.shared
+			.job_tails
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.remove(&job_id)
+		else {
+			return Ok(());
+		};
+
+		let jobs_dir = &self.shared.jobs_dir;
+		fs::create_dir_all(jobs_dir)
+			.await
+			.map_err(|e| FileIOError::from((jobs_dir, e)))?;
+
+		let mut json = vec![];
+		for line in &tail {
+			if let Ok(line) = serde_json::to_vec(line) {
+				json.extend(line);
+				json.push(b'\n');
+			}
+		}
+
+		let path = job_log_path(jobs_dir, job_id);
+		fs::write(&path, json)
+			.await
+			.map_err(|e| FileIOError::from((&path, e)))?;
+
+		remove_old_job_logs(jobs_dir).await
+	}
+
+	/// The latest lines, from the oldest, at `min_level` or above and of `library_id` if given
+	pub fn recent(
+		&self,
+		library_id: Option<Uuid>,
+		min_level: LogLevel,
+		limit: usize,
+	) -> Vec<LogLine> {
+		let recent = self.shared.recent.lock().unwrap_or_else(|e| e.into_inner());
+
+		let mut lines = recent
+			.iter()
+			.rev()
+			.filter(|line| line.level >= min_level)
+			.filter(|line| library_id.is_none() || line.library_id == library_id)
+			.take(limit)
+			.cloned()
+			.collect::<Vec<_>>();
+		lines.reverse();
+
+		lines
+	}
+}
+
+fn job_log_path(jobs_dir: &Path, job_id: Uuid) -> PathBuf {
+	jobs_dir.join(format!("{job_id}.jsonl"))
+}
+
+async fn remove_old_job_logs(jobs_dir: &Path) -> Result<(), LogsError> {
+	let mut read_dir = fs::read_dir(jobs_dir)
+		.await
+		.map_err(|e| FileIOError::from((jobs_dir, e)))?;
+
+	while let Some(entry) = read_dir
+		.next_entry()
+		.await
+		.map_err(|e| FileIOError::from((jobs_dir, e)))?
+	{
+		let is_old = entry
+			.metadata()
+			.await
+			.and_then(|metadata| metadata.modified())
+			.ok()
+			.and_then(|modified| SystemTime::now().duration_since(modified).ok())
+			.map_or(false, |age| age > JOB_LOGS_MAX_AGE);
+
+		if is_old {
+			let path = entry.path();
+			fs::remove_file(&path)
+				.await
+				.map_err(|e| FileIOError::from((&path, e)))?;
+		}
+	}
+
+	Ok(())
+}
+
+fn default_directives() -> String {
+	match std::env::var("RUST_LOG") {
+		// After the defaults, for the variable to win
+		Ok(env) if !env.trim().is_empty() => format!("{DEFAULT_DIRECTIVES},{env}"),
+		_ => DEFAULT_DIRECTIVES.to_string(),
+	}
+}
+
+/// The filter of every log, which `Logs` can change, and the subscriber keeping the latest lines
+/// and writing those of the libraries
+pub(crate) fn init(logs_dir: PathBuf) -> (reload::Subscriber<EnvFilter, Registry>, LineSubscriber) {
+	let directives = default_directives();
+	let filter = EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+		eprintln!("Invalid $RUST_LOG, using the default log filter: {e}");
+		EnvFilter::new(DEFAULT_DIRECTIVES)
+	});
+	let (filter, handle) = reload::Subscriber::new(filter);
+
+	let shared = Arc::new(Shared {
+		recent: Mutex::new(VecDeque::with_capacity(RECENT_LINES)),
+		libraries_dir: logs_dir.join("libraries"),
+		library_files: Mutex::new(HashMap::new()),
+		jobs_dir: logs_dir.join("jobs"),
+		job_tails: Mutex::new(HashMap::new()),
+		job_lines_tx: broadcast::channel(1024).0,
+	});
+
+	LOGS.set(Logs {
+		filter: handle,
+		directives: Mutex::new(directives),
+		shared: shared.clone(),
+	})
+	.ok();
+
+	(filter, LineSubscriber { shared })
+}
+
+/// The library and the job a span is working on, kept in the extensions of the span
+struct SpanContext {
+	library_id: Option<Uuid>,
+	job_id: Option<Uuid>,
+}
+
+pub(crate) struct LineSubscriber {
+	shared: Arc<Shared>,
+}
+
+impl<C> Subscribe<C> for LineSubscriber
+where
+	C: Collect + for<'a> LookupSpan<'a>,
+{
+	fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, C>) {
+		let mut visitor = FieldVisitor::default();
+		attrs.record(&mut visitor);
+
+		if visitor.library_id.is_none() && visitor.job_id.is_none() {
+			return;
+		}
+
+		if let Some(span) = ctx.span(id) {
+			span.extensions_mut().insert(SpanContext {
+				library_id: visitor.library_id,
+				job_id: visitor.job_id,
+			});
+		}
+	}
+
+	fn on_event(&self, event: &Event<'_>, ctx: Context<'_, C>) {
+		let mut visitor = FieldVisitor::default();
+		event.record(&mut visitor);
+
+		let (mut library_id, mut job_id) = (visitor.library_id, visitor.job_id);
+		// The closest spans tell first
+		if let Some(scope) = ctx.event_scope(event) {
+			for span in scope {
+				if library_id.is_some() && job_id.is_some() {
+					break;
+				}
+
+				if let Some(context) = span.extensions().get::<SpanContext>() {
+					library_id = library_id.or(context.library_id);
+					job_id = job_id.or(context.job_id);
+				}
+			}
+		}
+
+		let metadata = event.metadata();
+		self.shared.push(LogLine {
+			timestamp: Utc::now(),
+			level: metadata.level().into(),
+			target: metadata.target().to_string(),
+			message: visitor.message,
+			fields: visitor.fields,
+			library_id,
+			job_id,
+		});
+	}
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+	message: String,
+	fields: BTreeMap<String, String>,
+	library_id: Option<Uuid>,
+	job_id: Option<Uuid>,
+}
+
+impl Visit for FieldVisitor {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		self.record(field, value.to_string());
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+		self.record(field, format!("{value:?}"));
+	}
+}
+
+impl FieldVisitor {
+	fn record(&mut self, field: &Field, value: String) {
+		match field.name() {
+			"message" => self.message = value,
+			"library_id" => self.library_id = value.parse().ok(),
+			"job_id" => self.job_id = value.parse().ok(),
+			name => {
+				self.fields.insert(name.to_string(), value);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn line(level: LogLevel, job_id: Option<Uuid>) -> LogLine {
+		LogLine {
+			timestamp: Utc::now(),
+			level,
+			target: "sd_core".to_string(),
+			message: format!("{level:?}"),
+			fields: BTreeMap::new(),
+			library_id: None,
+			job_id,
+		}
+	}
+
+	fn shared() -> Shared {
+		Shared {
+			recent: Mutex::new(VecDeque::new()),
+			libraries_dir: PathBuf::new(),
+			library_files: Mutex::new(HashMap::new()),
+			jobs_dir: PathBuf::new(),
+			job_tails: Mutex::new(HashMap::new()),
+			job_lines_tx: broadcast::channel(JOB_TAIL_LINES * 2).0,
+		}
+	}
+
+	#[test]
+	fn keeps_the_latest_lines() {
+		let shared = shared();
+
+		for _ in 0..RECENT_LINES {
+			shared.push(line(LogLevel::Debug, None));
+		}
+		shared.push(line(LogLevel::Warn, None));
+
+		let recent = shared.recent.lock().unwrap();
+		assert_eq!(recent.len(), RECENT_LINES);
+		assert_eq!(recent.back().unwrap().level, LogLevel::Warn);
+	}
+
+	#[test]
+	fn keeps_the_last_lines_of_each_job() {
+		let shared = shared();
+		let mut job_lines = shared.job_lines_tx.subscribe();
+		let (job_id, other_job_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+		for _ in 0..=JOB_TAIL_LINES {
+			shared.push(line(LogLevel::Debug, Some(job_id)));
+		}
+		shared.push(line(LogLevel::Info, Some(other_job_id)));
+		shared.push(line(LogLevel::Info, None));
+
+		let job_tails = shared.job_tails.lock().unwrap();
+		assert_eq!(job_tails[&job_id].len(), JOB_TAIL_LINES);
+		assert_eq!(job_tails[&other_job_id].len(), 1);
+		assert_eq!(job_tails.len(), 2);
+		assert_eq!(job_lines.try_recv().unwrap().job_id, Some(job_id));
+	}
+
+	#[test]
+	fn orders_levels_by_severity() {
+		assert!(LogLevel::Error > LogLevel::Warn);
+		assert!(LogLevel::Trace < LogLevel::Info);
+		assert_eq!(LogLevel::from(&Level::WARN), LogLevel::Warn);
+	}
+}
@@ -0,0 +1,215 @@
+//! Metrics of the node in the text format of Prometheus, for the nodes running on servers.
+//!
+//! Counters are kept in memory since the node started. The latency of the databases is measured
+//! when the metrics are scraped, with a query as cheap as they get, so it shows how long queries
+//! wait for their database rather than how long the queries of the node take to run.
+
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+	time::Duration,
+};
+
+#[cfg(feature = "location-watcher")]
+use notify::EventKind;
+
+use crate::job::JobStatus;
+
+/// Counters updated by the job workers and the location watchers
+#[derive(Debug, Default)]
+pub struct NodeMetrics {
+	/// Finished jobs, by name and status
+	jobs_finished: Mutex<BTreeMap<(String, &'static str), u64>>,
+	/// File system events received by the watchers, by kind
+	watcher_events: [AtomicU64; 4],
+}
+
+const WATCHER_EVENT_KINDS: [&str; 4] = ["create", "modify", "remove", "other"];
+
+impl NodeMetrics {
+	pub(crate) fn record_job_finished(&self, name: &str, status: JobStatus) {
+		let status = match status {
+			JobStatus::Completed => "completed",
+			JobStatus::CompletedWithErrors => "completed_with_errors",
+			JobStatus::Canceled => "canceled",
+			JobStatus::Failed => "failed",
+			JobStatus::Paused => "paused",
+			// Not a job that finished
+			JobStatus::Queued | JobStatus::Running => return,
+		};
+
+		*self
+			.jobs_finished
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.entry((name.to_string(), status))
+			.or_default() += 1;
+	}
+
+	#[cfg(feature = "location-watcher")]
+	pub(crate) fn record_watcher_event(&self, kind: &EventKind) {
+		let i = match kind {
+			EventKind::Create(_) => 0,
+			EventKind::Modify(_) => 1,
+			EventKind::Remove(_) => 2,
+			_ => 3,
+		};
+
+		self.watcher_events[i].fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+/// What's only known when the metrics are scraped
+pub(crate) struct Snapshot {
+	pub running_jobs: usize,
+	pub queued_jobs: usize,
+	pub p2p_bytes_sent: u64,
+	pub p2p_bytes_received: u64,
+	/// The latency of the database of each library, by id
+	pub db_latencies: Vec<(String, Duration)>,
+}
+
+impl NodeMetrics {
+	pub(crate) fn render(&self, snapshot: &Snapshot) -> String {
+		let mut out = String::new();
+
+		metric(
+			&mut out,
+			"sd_jobs_finished_total",
+			"counter",
+			"Jobs finished since the node started, by name and status",
+		);
+		for ((name, status), count) in self
+			.jobs_finished
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.iter()
+		{
+			let _ = writeln!(
+				out,
+				"sd_jobs_finished_total{{name=\"{}\",status=\"{status}\"}} {count}",
+				escape_label(name)
+			);
+		}
+
+		metric(&mut out, "sd_jobs_running", "gauge", "Jobs running now");
+		let _ = writeln!(out, "sd_jobs_running {}", snapshot.running_jobs);
+
+		metric(
+			&mut out,
+			"sd_jobs_queued",
+			"gauge",
+			"Jobs waiting for their library to run fewer jobs",
+		);
+		let _ = writeln!(out, "sd_jobs_queued {}", snapshot.queued_jobs);
+
+		metric(
+			&mut out,
+			"sd_watcher_events_total",
+			"counter",
+			"File system events received by the location watchers, by kind",
+		);
+		for (kind, count) in WATCHER_EVENT_KINDS.iter().zip(&self.watcher_events) {
+			let _ = writeln!(
+				out,
+				"sd_watcher_events_total{{kind=\"{kind}\"}} {}",
+				count.load(Ordering::Relaxed)
+			);
+		}
+
+		metric(
+			&mut out,
+			"sd_p2p_sync_bytes_sent_total",
+			"counter",
+			"Bytes of sync operations sent to peers",
+		);
+		let _ = writeln!(
+			out,
+			"sd_p2p_sync_bytes_sent_total {}",
+			snapshot.p2p_bytes_sent
+		);
+
+		metric(
+			&mut out,
+			"sd_p2p_sync_bytes_received_total",
+			"counter",
+			"Bytes of sync operations received from peers",
+		);
+		let _ = writeln!(
+			out,
+			"sd_p2p_sync_bytes_received_total {}",
+			snapshot.p2p_bytes_received
+		);
+
+		metric(
+			&mut out,
+			"sd_db_query_latency_seconds",
+			"gauge",
+			"How long a trivial query to the database of each library took while scraping",
+		);
+		for (library_id, latency) in &snapshot.db_latencies {
+			let _ = writeln!(
+				out,
+				"sd_db_query_latency_seconds{{library=\"{library_id}\"}} {}",
+				latency.as_secs_f64()
+			);
+		}
+
+		out
+	}
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {name} {help}");
+	let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn renders_counters() {
+		let metrics = NodeMetrics::default();
+		metrics.record_job_finished("indexer", JobStatus::Completed);
+		metrics.record_job_finished("indexer", JobStatus::Completed);
+		metrics.record_job_finished("file_identifier", JobStatus::Failed);
+		metrics.record_job_finished("indexer", JobStatus::Running);
+		metrics.watcher_events[0].fetch_add(1, Ordering::Relaxed);
+
+		let out = metrics.render(&Snapshot {
+			running_jobs: 1,
+			queued_jobs: 2,
+			p2p_bytes_sent: 10,
+			p2p_bytes_received: 20,
+			db_latencies: vec![("lib".to_string(), Duration::from_millis(5))],
+		});
+
+		assert!(out.contains("sd_jobs_finished_total{name=\"indexer\",status=\"completed\"} 2\n"));
+		assert!(
+			out.contains("sd_jobs_finished_total{name=\"file_identifier\",status=\"failed\"} 1\n")
+		);
+		assert!(!out.contains("status=\"running\""));
+		assert!(out.contains("sd_jobs_queued 2\n"));
+		assert!(out.contains("sd_watcher_events_total{kind=\"create\"} 1\n"));
+		assert!(out.contains("sd_watcher_events_total{kind=\"remove\"} 0\n"));
+		assert!(out.contains("sd_db_query_latency_seconds{library=\"lib\"} 0.005\n"));
+		assert!(out.contains("# TYPE sd_p2p_sync_bytes_sent_total counter\n"));
+	}
+
+	#[test]
+	fn escapes_labels() {
+		assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+	}
+}
@@ -2,9 +2,16 @@ use crate::NodeError;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
+mod analytics;
+mod api_tokens;
 mod config;
+pub mod logs;
+mod metrics;
 
+pub use analytics::*;
+pub use api_tokens::*;
 pub use config::*;
+pub use metrics::*;
 
 #[allow(clippy::upper_case_acronyms)]
 #[repr(u8)]
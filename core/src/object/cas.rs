@@ -1,3 +1,5 @@
+use crate::util::sparse;
+
 use std::path::Path;
 
 use blake3::Hasher;
@@ -20,6 +22,12 @@ const_assert!((HEADER_OR_FOOTER_SIZE * 2 + SAMPLE_COUNT * SAMPLE_SIZE) < MINIMUM
 // Asserting that the sample size is larger than header/footer size, as the same buffer is used for both
 const_assert!(SAMPLE_SIZE > HEADER_OR_FOOTER_SIZE);
 
+/// Whether a sample is in a hole of a sparse file, like the empty space of a disk image, so it's
+/// hashed as the zeros it would read as instead of being read
+fn in_hole(file: &File, is_sparse: bool, offset: u64, len: u64) -> Result<bool, io::Error> {
+	Ok(is_sparse && sparse::is_hole(file, offset..offset + len)?)
+}
+
 pub async fn generate_cas_id(path: impl AsRef<Path>, size: u64) -> Result<String, io::Error> {
 	let mut hasher = Hasher::new();
 	hasher.update(&size.to_le_bytes());
@@ -29,33 +37,46 @@ pub async fn generate_cas_id(path: impl AsRef<Path>, size: u64) -> Result<String
 		hasher.update(&fs::read(path).await?);
 	} else {
 		let mut file = File::open(path).await?;
+		let is_sparse = sparse::is_sparse(&file.metadata().await?);
 		let mut buf = vec![0; SAMPLE_SIZE as usize].into_boxed_slice();
 
 		// Hashing the header
-		let mut current_pos = file
-			.read_exact(&mut buf[..HEADER_OR_FOOTER_SIZE as usize])
-			.await? as u64;
-		hasher.update(&buf[..HEADER_OR_FOOTER_SIZE as usize]);
+		if in_hole(&file, is_sparse, 0, HEADER_OR_FOOTER_SIZE)? {
+			sparse::hash_zeros(&mut hasher, HEADER_OR_FOOTER_SIZE);
+		} else {
+			file.seek(SeekFrom::Start(0)).await?;
+			file.read_exact(&mut buf[..HEADER_OR_FOOTER_SIZE as usize])
+				.await?;
+			hasher.update(&buf[..HEADER_OR_FOOTER_SIZE as usize]);
+		}
 
 		// Sample hashing the inner content of the file
 		let seek_jump = (size - HEADER_OR_FOOTER_SIZE * 2) / SAMPLE_COUNT;
-		loop {
-			file.read_exact(&mut buf).await?;
-			hasher.update(&buf);
+		for sample in 0..SAMPLE_COUNT {
+			let offset = HEADER_OR_FOOTER_SIZE + seek_jump * sample;
 
-			if current_pos >= (HEADER_OR_FOOTER_SIZE + seek_jump * (SAMPLE_COUNT - 1)) {
-				break;
+			if in_hole(&file, is_sparse, offset, SAMPLE_SIZE)? {
+				sparse::hash_zeros(&mut hasher, SAMPLE_SIZE);
+			} else {
+				file.seek(SeekFrom::Start(offset)).await?;
+				file.read_exact(&mut buf).await?;
+				hasher.update(&buf);
 			}
-
-			current_pos = file.seek(SeekFrom::Start(current_pos + seek_jump)).await?;
 		}
 
 		// Hashing the footer
-		file.seek(SeekFrom::End(-(HEADER_OR_FOOTER_SIZE as i64)))
-			.await?;
-		file.read_exact(&mut buf[..HEADER_OR_FOOTER_SIZE as usize])
+		let offset = file
+			.seek(SeekFrom::End(-(HEADER_OR_FOOTER_SIZE as i64)))
 			.await?;
-		hasher.update(&buf[..HEADER_OR_FOOTER_SIZE as usize]);
+		if in_hole(&file, is_sparse, offset, HEADER_OR_FOOTER_SIZE)? {
+			sparse::hash_zeros(&mut hasher, HEADER_OR_FOOTER_SIZE);
+		} else {
+			// Looking for holes moves the file's position
+			file.seek(SeekFrom::Start(offset)).await?;
+			file.read_exact(&mut buf[..HEADER_OR_FOOTER_SIZE as usize])
+				.await?;
+			hasher.update(&buf[..HEADER_OR_FOOTER_SIZE as usize]);
+		}
 	}
 
 	Ok(hasher.finalize().to_hex()[..16].to_string())
@@ -1,8 +1,9 @@
 use crate::{
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunMetadata, JobState,
-		JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
 	},
+	job_message,
 	library::Library,
 	location::file_path_helper::{
 		ensure_file_path_exists, ensure_sub_path_is_directory, ensure_sub_path_is_in_location,
@@ -20,7 +21,7 @@ use std::{
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use super::{process_identifier_file_paths, FileIdentifierJobError, CHUNK_SIZE};
+use super::{default_chunk_size, process_identifier_file_paths, FileIdentifierJobError};
 
 pub struct FileIdentifierJob {}
 
@@ -48,6 +49,9 @@ impl Hash for FileIdentifierJobInit {
 pub struct FileIdentifierJobData {
 	location_path: PathBuf,
 	maybe_sub_iso_file_path: Option<IsolatedFilePathData<'static>>,
+	/// How many files are identified in a step, from the library settings when the job started
+	#[serde(default = "default_chunk_size")]
+	chunk_size: usize,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -76,6 +80,10 @@ pub struct FileIdentifierReport {
 
 impl JobInitData for FileIdentifierJobInit {
 	type Job = FileIdentifierJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location.id])
+	}
 }
 
 #[async_trait::async_trait]
@@ -86,6 +94,7 @@ impl StatefulJob for FileIdentifierJob {
 	type RunMetadata = FileIdentifierJobRunMetadata;
 
 	const NAME: &'static str = "file_identifier";
+	const LOAD: JobLoad = JobLoad::ReadHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -138,6 +147,7 @@ impl StatefulJob for FileIdentifierJob {
 		*data = Some(FileIdentifierJobData {
 			location_path: location_path.to_path_buf(),
 			maybe_sub_iso_file_path,
+			chunk_size: ctx.library.config.settings.identifier_chunk_size as usize,
 		});
 
 		let data = data.as_ref().expect("we just set it");
@@ -151,7 +161,7 @@ impl StatefulJob for FileIdentifierJob {
 
 		info!("Found {} orphan file paths", orphan_count);
 
-		let task_count = (orphan_count as f64 / CHUNK_SIZE as f64).ceil() as usize;
+		let task_count = (orphan_count as f64 / data.chunk_size as f64).ceil() as usize;
 		info!(
 			"Found {} orphan Paths. Will execute {} tasks...",
 			orphan_count, task_count
@@ -200,6 +210,7 @@ impl StatefulJob for FileIdentifierJob {
 			location.id,
 			run_metadata.cursor,
 			&data.maybe_sub_iso_file_path,
+			data.chunk_size,
 		)
 		.await?;
 
@@ -228,10 +239,10 @@ impl StatefulJob for FileIdentifierJob {
 		new_metadata.report.total_objects_linked = total_objects_linked;
 		new_metadata.cursor = new_cursor;
 
-		ctx.progress_msg(format!(
-			"Processed {} of {} orphan Paths",
-			step_number * CHUNK_SIZE,
-			run_metadata.report.total_orphan_paths
+		ctx.progress_msg(job_message!(
+			"fileIdentifier.processed",
+			processed = step_number * data.chunk_size,
+			total = run_metadata.report.total_orphan_paths
 		));
 
 		Ok(new_metadata.into())
@@ -293,10 +304,11 @@ async fn get_orphan_file_paths(
 	location_id: location::id::Type,
 	file_path_id: file_path::id::Type,
 	maybe_sub_materialized_path: &Option<IsolatedFilePathData<'_>>,
+	chunk_size: usize,
 ) -> Result<Vec<file_path_for_file_identifier::Data>, prisma_client_rust::QueryError> {
 	info!(
 		"Querying {} orphan Paths at cursor: {:?}",
-		CHUNK_SIZE, file_path_id
+		chunk_size, file_path_id
 	);
 	db.file_path()
 		.find_many(orphan_path_filters(
@@ -305,7 +317,7 @@ async fn get_orphan_file_paths(
 			maybe_sub_materialized_path,
 		))
 		.order_by(file_path::id::order(SortOrder::Asc))
-		.take(CHUNK_SIZE as i64)
+		.take(chunk_size as i64)
 		// .skip(1)
 		.select(file_path_for_file_identifier::select())
 		.exec()
@@ -1,28 +1,27 @@
 use crate::{
 	job::JobError,
-	library::Library,
+	library::{
+		webhooks::{self, WebhookEvent},
+		Library, DEFAULT_IDENTIFIER_CHUNK_SIZE,
+	},
 	location::file_path_helper::{
 		file_path_for_file_identifier, FilePathError, IsolatedFilePathData,
 	},
-	object::{cas::generate_cas_id, object_for_file_identifier},
-	prisma::{file_path, location, object, PrismaClient},
+	object::cas::generate_cas_id,
+	prisma::{file_path, location, object},
 	sync,
-	sync::SyncManager,
 	util::{
-		db::{maybe_missing, uuid_to_bytes},
+		db::{chain_optional_iter, keyset_pages, maybe_missing, uuid_to_bytes},
 		error::FileIOError,
 	},
 };
 
 use sd_file_ext::{extensions::Extension, kind::ObjectKind};
-use sd_sync::CRDTOperation;
 
-use std::{
-	collections::{HashMap, HashSet},
-	path::Path,
-};
+use std::{collections::HashMap, path::Path, pin::pin};
 
-use futures::future::join_all;
+use futures::{future::join_all, TryStreamExt};
+use prisma_client_rust::Direction;
 use serde_json::json;
 use thiserror::Error;
 use tokio::fs;
@@ -34,8 +33,20 @@ mod shallow;
 
 pub use shallow::*;
 
-// we break these jobs into chunks of 100 to improve performance
-const CHUNK_SIZE: usize = 100;
+/// The file paths already linked to the objects of a chunk are read in pages this big
+const PAGE_SIZE: i64 = 10_000;
+
+file_path::select!(file_path_with_object_ids {
+	id
+	cas_id
+	object: select { id pub_id }
+});
+
+/// The number of files identified in a step of the libraries with the default settings, kept for
+/// identifier jobs started before the chunk size was a setting
+fn default_chunk_size() -> usize {
+	DEFAULT_IDENTIFIER_CHUNK_SIZE as usize
+}
 
 #[derive(Error, Debug)]
 pub enum FileIdentifierJobError {
@@ -94,10 +105,11 @@ impl FileMetadata {
 }
 
 async fn identifier_job_step(
-	Library { db, sync, .. }: &Library,
+	library: &Library,
 	location: &location::Data,
 	file_paths: &[file_path_for_file_identifier::Data],
 ) -> Result<(usize, usize), JobError> {
+	let Library { db, sync, .. } = library;
 	let location_path = maybe_missing(&location.path, "location.path").map(Path::new)?;
 
 	let file_path_metas = join_all(file_paths.iter().map(|file_path| async move {
@@ -125,211 +137,208 @@ async fn identifier_job_step(
 	})
 	.collect::<HashMap<Uuid, (FileMetadata, &file_path_for_file_identifier::Data)>>();
 
-	let unique_cas_ids = file_path_metas
-		.values()
-		.map(|(meta, _)| meta.cas_id.clone())
-		.collect::<HashSet<_>>()
-		.into_iter()
-		.collect();
-
-	// Assign cas_id to each file path
-	sync.write_ops(
-		db,
-		file_path_metas
-			.iter()
-			.map(|(pub_id, (meta, _))| {
-				(
-					sync.shared_update(
-						sync::file_path::SyncId {
-							pub_id: uuid_to_bytes(*pub_id),
-						},
-						file_path::cas_id::NAME,
-						json!(&meta.cas_id),
-					),
-					db.file_path().update(
-						file_path::pub_id::equals(uuid_to_bytes(*pub_id)),
-						vec![file_path::cas_id::set(Some(meta.cas_id.clone()))],
-					),
-				)
-			})
-			.unzip::<_, _, _, Vec<_>>(),
-	)
-	.await?;
-
-	// Retrieves objects that are already connected to file paths with the same id
-	let existing_objects = db
-		.object()
-		.find_many(vec![object::file_paths::some(vec![
-			file_path::cas_id::in_vec(unique_cas_ids),
-		])])
-		.select(object_for_file_identifier::select())
-		.exec()
-		.await?;
+	// The file paths of the chunk with the same content share an object
+	let mut by_cas_id = HashMap::<String, Vec<_>>::new();
+	for (pub_id, (meta, file_path)) in &file_path_metas {
+		by_cas_id
+			.entry(meta.cas_id.clone())
+			.or_default()
+			.push((*pub_id, meta, *file_path));
+	}
+
+	// The object of each cas_id, as its id and pub_id, from the file paths with the same cas_id
+	// that are already linked to one. There can be many of them, for files copied many times.
+	let mut objects = HashMap::new();
+	{
+		let cas_ids = by_cas_id.keys().cloned().collect::<Vec<_>>();
+		let mut pages = pin!(keyset_pages(
+			PAGE_SIZE,
+			|file_path: &file_path_with_object_ids::Data| file_path.id,
+			|after, take| {
+				db.file_path()
+					.find_many(chain_optional_iter(
+						[
+							file_path::cas_id::in_vec(cas_ids.clone()),
+							file_path::object_id::not(None),
+						],
+						[after.map(file_path::id::gt)],
+					))
+					.order_by(file_path::id::order(Direction::Asc))
+					.take(take)
+					.select(file_path_with_object_ids::select())
+					.exec()
+			},
+		));
+		while let Some(file_paths) = pages.try_next().await? {
+			for file_path in file_paths {
+				if let (Some(cas_id), Some(object)) = (file_path.cas_id, file_path.object) {
+					objects.entry(cas_id).or_insert((object.id, object.pub_id));
+				}
+			}
+		}
+	}
 
-	let existing_object_cas_ids = existing_objects
+	let total_linked = by_cas_id
 		.iter()
-		.flat_map(|o| o.file_paths.iter().filter_map(|fp| fp.cas_id.as_ref()))
-		.collect::<HashSet<_>>();
-
-	// Attempt to associate each file path with an object that has been
-	// connected to file paths with the same cas_id
-	let updated_file_paths = sync
-		.write_ops(
-			db,
-			file_path_metas
-				.iter()
-				.flat_map(|(pub_id, (meta, _))| {
-					existing_objects
-						.iter()
-						.find(|o| {
-							o.file_paths
-								.iter()
-								.any(|fp| fp.cas_id.as_ref() == Some(&meta.cas_id))
-						})
-						.map(|o| (*pub_id, o))
-				})
-				.map(|(pub_id, object)| {
-					let (crdt_op, db_op) = file_path_object_connect_ops(
-						pub_id,
-						// SAFETY: This pub_id is generated by the uuid lib, but we have to store bytes in sqlite
-						Uuid::from_slice(&object.pub_id).expect("uuid bytes are invalid"),
-						sync,
-						db,
-					);
-
-					(crdt_op, db_op.select(file_path::select!({ pub_id })))
-				})
-				.unzip::<_, _, Vec<_>, Vec<_>>(),
-		)
-		.await?;
+		.filter(|(cas_id, _)| objects.contains_key(*cas_id))
+		.map(|(_, file_paths)| file_paths.len())
+		.sum::<usize>();
 
 	info!(
-		"Found {} existing Objects in Library, linking file paths...",
-		existing_objects.len()
+		"Found {} existing Objects in Library, linking {total_linked} file paths...",
+		objects.len()
 	);
 
-	// extract objects that don't already exist in the database
-	let file_paths_requiring_new_object = file_path_metas
-		.into_iter()
-		.filter(|(_, (meta, _))| !existing_object_cas_ids.contains(&meta.cas_id))
-		.collect::<Vec<_>>();
-
-	let total_created = if !file_paths_requiring_new_object.is_empty() {
-		let new_objects_cas_ids = file_paths_requiring_new_object
-			.iter()
-			.map(|(_, (meta, _))| &meta.cas_id)
-			.collect::<HashSet<_>>();
-
-		info!(
-			"Creating {} new Objects in Library... {:#?}",
-			file_paths_requiring_new_object.len(),
-			new_objects_cas_ids
-		);
-
-		let (object_create_args, file_path_update_args): (Vec<_>, Vec<_>) =
-			file_paths_requiring_new_object
-				.iter()
-				.map(|(file_path_pub_id, (meta, fp))| {
-					let object_pub_id = Uuid::new_v4();
-
-					let sync_id = || sync::object::SyncId {
-						pub_id: uuid_to_bytes(object_pub_id),
-					};
-
-					let kind = meta.kind as i32;
-
-					let (sync_params, db_params): (Vec<_>, Vec<_>) = [
-						(
-							(object::date_created::NAME, json!(fp.date_created)),
-							object::date_created::set(fp.date_created),
-						),
-						(
-							(object::kind::NAME, json!(kind)),
-							object::kind::set(Some(kind)),
-						),
-					]
-					.into_iter()
-					.unzip();
-
-					let object_creation_args = (
-						sync.unique_shared_create(sync_id(), sync_params),
-						object::create_unchecked(uuid_to_bytes(object_pub_id), db_params),
-					);
-
-					(object_creation_args, {
-						let (crdt_op, db_op) = file_path_object_connect_ops(
-							*file_path_pub_id,
-							object_pub_id,
-							sync,
-							db,
-						);
-
-						(crdt_op, db_op.select(file_path::select!({ pub_id })))
-					})
-				})
-				.unzip();
+	let mut created_events = vec![];
+	let mut new_objects = HashMap::new();
+	let (object_sync_ops, object_creates): (Vec<_>, Vec<_>) = by_cas_id
+		.iter()
+		.filter(|(cas_id, _)| !objects.contains_key(*cas_id))
+		.map(|(cas_id, file_paths)| {
+			let object_pub_id = Uuid::new_v4();
+			let (_, meta, fp) = file_paths[0];
 
-		// create new object records with assembled values
-		let total_created_files = sync
-			.write_ops(db, {
-				let (sync, db_params): (Vec<_>, Vec<_>) = object_create_args.into_iter().unzip();
+			if let Ok(iso_file_path) = IsolatedFilePathData::try_from((location.id, fp)) {
+				created_events.push(WebhookEvent::ObjectCreated {
+					object_pub_id,
+					location_id: location.id,
+					path: location_path.join(iso_file_path),
+				});
+			}
 
-				(sync, db.object().create_many(db_params))
-			})
-			.await
-			.unwrap_or_else(|e| {
-				error!("Error inserting files: {:#?}", e);
-				0
-			});
+			new_objects.insert(uuid_to_bytes(object_pub_id), cas_id.clone());
 
-		info!("Created {} new Objects in Library", total_created_files);
+			let kind = meta.kind as i32;
 
-		if total_created_files > 0 {
-			info!("Updating file paths with created objects");
+			let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+				(
+					(object::date_created::NAME, json!(fp.date_created)),
+					object::date_created::set(fp.date_created),
+				),
+				(
+					(object::kind::NAME, json!(kind)),
+					object::kind::set(Some(kind)),
+				),
+			]
+			.into_iter()
+			.unzip();
+
+			(
+				sync.unique_shared_create(
+					sync::object::SyncId {
+						pub_id: uuid_to_bytes(object_pub_id),
+					},
+					sync_params,
+				),
+				object::create_unchecked(uuid_to_bytes(object_pub_id), db_params),
+			)
+		})
+		.unzip();
 
-			sync.write_ops(db, {
-				let data: (Vec<_>, Vec<_>) = file_path_update_args.into_iter().unzip();
+	// Creating the objects and linking the file paths is a few batched queries in one transaction,
+	// instead of a query for each file path. Their sync operations are broadcast once it's committed.
+	let (total_created, written_ops) = db
+		._transaction()
+		.run(|tx| async move {
+			let mut written_ops = vec![];
 
-				data
-			})
-			.await?;
+			let total_created = if object_creates.is_empty() {
+				0
+			} else {
+				info!(
+					"Creating {} new Objects in Library...",
+					object_creates.len()
+				);
+
+				let (total_created, ops) = sync
+					.write_ops_unbroadcast(
+						&tx,
+						(object_sync_ops, tx.object().create_many(object_creates)),
+					)
+					.await?;
+				written_ops.extend(ops);
+
+				total_created
+			};
+
+			if total_created > 0 {
+				for object in tx
+					.object()
+					.find_many(vec![object::pub_id::in_vec(
+						new_objects.keys().cloned().collect(),
+					)])
+					.select(object::select!({ id pub_id }))
+					.exec()
+					.await?
+				{
+					if let Some(cas_id) = new_objects.remove(&object.pub_id) {
+						objects.insert(cas_id, (object.id, object.pub_id));
+					}
+				}
+			}
+
+			// Assign cas_id to each file path and connect it to the object of its cas_id
+			let (sync_ops, updates): (Vec<_>, Vec<_>) = by_cas_id
+				.into_iter()
+				.map(|(cas_id, file_paths)| {
+					let object = objects.get(&cas_id);
+
+					let mut sync_ops = Vec::with_capacity(file_paths.len() * 2);
+					let mut pub_ids = Vec::with_capacity(file_paths.len());
+					for (pub_id, _, _) in file_paths {
+						let sync_id = || sync::file_path::SyncId {
+							pub_id: uuid_to_bytes(pub_id),
+						};
+
+						sync_ops.push(sync.shared_update(
+							sync_id(),
+							file_path::cas_id::NAME,
+							json!(&cas_id),
+						));
+						if let Some((_, object_pub_id)) = object {
+							sync_ops.push(sync.shared_update(
+								sync_id(),
+								file_path::object::NAME,
+								json!(sync::object::SyncId {
+									pub_id: object_pub_id.clone()
+								}),
+							));
+						}
+
+						pub_ids.push(uuid_to_bytes(pub_id));
+					}
+
+					let mut params = vec![file_path::cas_id::set(Some(cas_id))];
+					if let Some((object_id, _)) = object {
+						params.push(file_path::object_id::set(Some(*object_id)));
+					}
+
+					(
+						sync_ops,
+						tx.file_path()
+							.update_many(vec![file_path::pub_id::in_vec(pub_ids)], params),
+					)
+				})
+				.unzip();
 
-			info!("Updated file paths with created objects");
-		}
+			let (_, ops) = sync
+				.write_ops_unbroadcast(&tx, (sync_ops.into_iter().flatten().collect(), updates))
+				.await?;
+			written_ops.extend(ops);
 
-		total_created_files as usize
-	} else {
-		0
-	};
+			Ok::<_, sync::SyncError>((total_created, written_ops))
+		})
+		.await?;
 
-	Ok((total_created, updated_file_paths.len()))
-}
+	sync.broadcast_ops(written_ops);
 
-fn file_path_object_connect_ops<'db>(
-	file_path_id: Uuid,
-	object_id: Uuid,
-	sync: &SyncManager,
-	db: &'db PrismaClient,
-) -> (CRDTOperation, file_path::UpdateQuery<'db>) {
-	info!("Connecting <FilePath id={file_path_id}> to <Object pub_id={object_id}'>");
+	info!("Created {total_created} new Objects in Library");
 
-	let vec_id = object_id.as_bytes().to_vec();
+	if total_created > 0 {
+		webhooks::fire(library, created_events);
+	}
 
-	(
-		sync.shared_update(
-			sync::file_path::SyncId {
-				pub_id: uuid_to_bytes(file_path_id),
-			},
-			file_path::object::NAME,
-			json!(sync::object::SyncId {
-				pub_id: vec_id.clone()
-			}),
-		),
-		db.file_path().update(
-			file_path::pub_id::equals(uuid_to_bytes(file_path_id)),
-			vec![file_path::object::connect(object::pub_id::equals(vec_id))],
-		),
-	)
+	Ok((total_created as usize, total_linked))
 }
 
 async fn process_identifier_file_paths(
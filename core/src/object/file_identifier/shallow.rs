@@ -15,7 +15,7 @@ use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use super::{process_identifier_file_paths, FileIdentifierJobError, CHUNK_SIZE};
+use super::{process_identifier_file_paths, FileIdentifierJobError};
 
 #[derive(Serialize, Deserialize)]
 pub struct ShallowFileIdentifierJobState {
@@ -67,7 +67,8 @@ pub async fn shallow(
 		return Ok(());
 	}
 
-	let task_count = (orphan_count as f64 / CHUNK_SIZE as f64).ceil() as usize;
+	let chunk_size = library.config.settings.identifier_chunk_size as usize;
+	let task_count = (orphan_count as f64 / chunk_size as f64).ceil() as usize;
 	info!(
 		"Found {} orphan Paths. Will execute {} tasks...",
 		orphan_count, task_count
@@ -95,8 +96,14 @@ pub async fn shallow(
 		} = &mut data;
 
 		// get chunk of orphans to process
-		let file_paths =
-			get_orphan_file_paths(&library.db, location.id, *cursor, sub_iso_file_path).await?;
+		let file_paths = get_orphan_file_paths(
+			&library.db,
+			location.id,
+			*cursor,
+			sub_iso_file_path,
+			chunk_size,
+		)
+		.await?;
 
 		let (_, _, new_cursor) = process_identifier_file_paths(
 			location,
@@ -152,10 +159,11 @@ async fn get_orphan_file_paths(
 	location_id: location::id::Type,
 	file_path_id_cursor: file_path::id::Type,
 	sub_iso_file_path: &IsolatedFilePathData<'_>,
+	chunk_size: usize,
 ) -> Result<Vec<file_path_for_file_identifier::Data>, prisma_client_rust::QueryError> {
 	info!(
 		"Querying {} orphan Paths at cursor: {:?}",
-		CHUNK_SIZE, file_path_id_cursor
+		chunk_size, file_path_id_cursor
 	);
 	db.file_path()
 		.find_many(orphan_path_filters(
@@ -165,7 +173,7 @@ async fn get_orphan_file_paths(
 		))
 		.order_by(file_path::id::order(SortOrder::Asc))
 		// .cursor(cursor.into())
-		.take(CHUNK_SIZE as i64)
+		.take(chunk_size as i64)
 		// .skip(1)
 		.select(file_path_for_file_identifier::select())
 		.exec()
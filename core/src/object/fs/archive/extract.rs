@@ -0,0 +1,659 @@
+//! Extracting zip, 7z and rar archives, and tar archives compressed with gzip, zstd or not at all,
+//! into a directory of a location. The extracted files are indexed once they're all written.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	location::{
+		file_path_helper::{ensure_sub_path_is_directory, push_location_relative_path},
+		find_location, location_with_indexer_rules, scan_location, scan_location_sub_path,
+		LocationError,
+	},
+	object::fs::{
+		available_path, error::FileSystemJobsError, get_location_path_from_location_id,
+		get_many_files_datas,
+	},
+	prisma::{file_path, location},
+	util::error::FileIOError,
+};
+
+use std::{
+	fs::{self, File},
+	io::{self, BufReader, Read, Write},
+	path::{Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use sevenz_rust::{Password, SevenZReader};
+use specta::Type;
+use tokio::{io::AsyncReadExt, task::block_in_place};
+use tracing::{error, info, trace};
+use zip::{result::ZipError, ZipArchive};
+
+use super::ArchiveError;
+
+const WRITE_CHUNK_SIZE: usize = 64 * 1024;
+const UNIX_FILE_TYPE_MASK: u32 = 0o170000;
+const UNIX_SYMLINK_TYPE: u32 = 0o120000;
+
+/// The formats archives are extracted from, told apart by their first bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+	Zip,
+	Tar,
+	TarGz,
+	TarZst,
+	SevenZip,
+	Rar,
+}
+
+impl ArchiveKind {
+	/// `start` is the first 512 bytes of the archive, or all of it when it's smaller
+	pub fn detect(start: &[u8]) -> Result<Self, ArchiveError> {
+		if start.starts_with(b"PK\x03\x04") || start.starts_with(b"PK\x05\x06") {
+			Ok(Self::Zip)
+		} else if start.starts_with(&[0x1F, 0x8B]) {
+			Ok(Self::TarGz)
+		} else if start.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+			Ok(Self::TarZst)
+		} else if start.get(257..262) == Some(&b"ustar"[..]) {
+			Ok(Self::Tar)
+		} else if start.starts_with(b"7z\xBC\xAF\x27\x1C") {
+			Ok(Self::SevenZip)
+		} else if start.starts_with(b"Rar!\x1A\x07") {
+			Ok(Self::Rar)
+		} else {
+			Err(ArchiveError::UnsupportedFormat("unknown".to_string()))
+		}
+	}
+}
+
+/// What's done with an extracted file whose path is already taken
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+	/// Keeps the existing file, the archived one isn't extracted
+	Skip,
+	/// Replaces the existing file, directories are never replaced
+	Overwrite,
+	/// Extracts the archived file with a number after its name, as in "photo (1).jpg"
+	Rename,
+}
+
+pub struct FileExtractorJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileExtractorJobInit {
+	pub location_id: location::id::Type,
+	/// The archive to extract
+	pub file_path_id: file_path::id::Type,
+	pub target_location_id: location::id::Type,
+	/// An existing directory of the target location, the archive is extracted in it
+	pub target_location_relative_directory_path: PathBuf,
+	pub collision: CollisionPolicy,
+}
+
+impl JobInitData for FileExtractorJobInit {
+	type Job = FileExtractorJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id, self.target_location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileExtractorJobData {
+	archive_path: PathBuf,
+	kind: ArchiveKind,
+	target_directory_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileExtractorJobRunMetadata {
+	files_extracted: u64,
+	dirs_extracted: u64,
+	bytes_extracted: u64,
+	/// Not extracted as their path was taken
+	files_skipped: u64,
+	/// Extracted with another name as their path was taken
+	files_renamed: u64,
+}
+
+impl JobRunMetadata for FileExtractorJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.files_extracted += new_data.files_extracted;
+		self.dirs_extracted += new_data.dirs_extracted;
+		self.bytes_extracted += new_data.bytes_extracted;
+		self.files_skipped += new_data.files_skipped;
+		self.files_renamed += new_data.files_renamed;
+	}
+}
+
+fn archive_error(archive_path: &Path, e: io::Error) -> ArchiveError {
+	match e.kind() {
+		io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+			ArchiveError::Corrupted(e.to_string())
+		}
+		io::ErrorKind::Unsupported => ArchiveError::UnsupportedFormat(e.to_string()),
+		_ => FileIOError::from((archive_path, e)).into(),
+	}
+}
+
+fn zip_error(archive_path: &Path, e: ZipError) -> ArchiveError {
+	match e {
+		ZipError::Io(e) => archive_error(archive_path, e),
+		ZipError::UnsupportedArchive(reason) => ArchiveError::UnsupportedFormat(reason.to_string()),
+		e => ArchiveError::Corrupted(e.to_string()),
+	}
+}
+
+/// The path of an entry in the target directory, `None` for the paths that would lead out of it
+fn entry_path(name: &str) -> Option<PathBuf> {
+	let mut path = PathBuf::new();
+
+	// Archives made on windows can have backslashes as separators
+	for component in name.split(['/', '\\']) {
+		match component {
+			"" | "." => continue,
+			".." => return None,
+			component if component.contains(['\0', ':']) => return None,
+			component => path.push(component),
+		}
+	}
+
+	(!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Writes the entries of an archive out, with blocking I/O as the archive is read synchronously.
+/// Problems with single entries are kept as errors of the job run, problems with the archive
+/// itself stop the extraction.
+struct Extractor<'a> {
+	ctx: &'a WorkerContext,
+	archive_path: &'a Path,
+	target_directory_path: &'a Path,
+	collision: CollisionPolicy,
+	run_metadata: FileExtractorJobRunMetadata,
+	errors: Vec<String>,
+}
+
+impl Extractor<'_> {
+	fn extract(
+		mut self,
+		kind: ArchiveKind,
+	) -> Result<(FileExtractorJobRunMetadata, Vec<String>), ArchiveError> {
+		let archive_path = self.archive_path;
+		let open = || File::open(archive_path).map_err(|e| FileIOError::from((archive_path, e)));
+
+		match kind {
+			ArchiveKind::Zip => self.zip(open()?)?,
+			ArchiveKind::Tar => self.tar(BufReader::new(open()?))?,
+			ArchiveKind::TarGz => self.tar(MultiGzDecoder::new(BufReader::new(open()?)))?,
+			ArchiveKind::TarZst => self.tar(
+				zstd::stream::read::Decoder::new(open()?)
+					.map_err(|e| archive_error(archive_path, e))?,
+			)?,
+			ArchiveKind::SevenZip => self.seven_zip()?,
+			ArchiveKind::Rar => self.rar()?,
+		}
+
+		Ok((self.run_metadata, self.errors))
+	}
+
+	fn zip(&mut self, archive: File) -> Result<(), ArchiveError> {
+		let mut archive = ZipArchive::new(BufReader::new(archive))
+			.map_err(|e| zip_error(self.archive_path, e))?;
+
+		for i in 0..archive.len() {
+			let name = archive
+				.by_index_raw(i)
+				.map_err(|e| zip_error(self.archive_path, e))?
+				.name()
+				.to_string();
+
+			let mut entry = match archive.by_index(i) {
+				Ok(entry) => entry,
+				// Like encrypted entries, the others can still be extracted
+				Err(ZipError::UnsupportedArchive(reason)) => {
+					self.errors
+						.push(format!("skipped '{name}' from the archive: {reason}"));
+					continue;
+				}
+				Err(e) => return Err(zip_error(self.archive_path, e)),
+			};
+
+			let mode = entry.unix_mode();
+			if entry.is_dir() {
+				self.dir(&name);
+			} else if mode.map_or(false, |mode| {
+				mode & UNIX_FILE_TYPE_MASK == UNIX_SYMLINK_TYPE
+			}) {
+				self.skip_unsupported(&name);
+			} else {
+				self.file(&name, &mut entry, mode)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn tar(&mut self, reader: impl Read) -> Result<(), ArchiveError> {
+		let mut archive = tar::Archive::new(reader);
+
+		for entry in archive
+			.entries()
+			.map_err(|e| archive_error(self.archive_path, e))?
+		{
+			let mut entry = entry.map_err(|e| archive_error(self.archive_path, e))?;
+			let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+			let entry_type = entry.header().entry_type();
+
+			if entry_type.is_dir() {
+				self.dir(&name);
+			} else if entry_type.is_file() {
+				let mode = entry.header().mode().ok();
+				self.file(&name, &mut entry, mode)?;
+			} else if !entry_type.is_pax_global_extensions() {
+				self.skip_unsupported(&name);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn seven_zip(&mut self) -> Result<(), ArchiveError> {
+		let mut archive = SevenZReader::open(self.archive_path, Password::empty())
+			.map_err(|e| ArchiveError::Read(e.to_string()))?;
+
+		// Our errors can't go through the callback, they stop the reading and are returned after
+		let mut res = Ok(());
+		archive
+			.for_each_entries(|entry, reader| {
+				if entry.is_directory() {
+					self.dir(entry.name());
+				} else if let Err(e) = self.file(entry.name(), reader, None) {
+					res = Err(e);
+					return Ok(false);
+				}
+
+				// What wasn't extracted is read anyway, as the next entries come after it
+				io::copy(reader, &mut io::sink())?;
+
+				Ok(true)
+			})
+			.map_err(|e| ArchiveError::Read(e.to_string()))?;
+
+		res
+	}
+
+	/// Rar archives can only be extracted straight to the files, by the rar library
+	fn rar(&mut self) -> Result<(), ArchiveError> {
+		let rar_error = |e: unrar::error::UnrarError| ArchiveError::Read(e.to_string());
+
+		let mut archive = unrar::Archive::new(self.archive_path)
+			.open_for_processing()
+			.map_err(rar_error)?;
+
+		while let Some(header) = archive.read_header().map_err(rar_error)? {
+			let entry = header.entry();
+			let name = entry.filename.to_string_lossy().to_string();
+
+			archive = if entry.is_directory() {
+				self.dir(&name);
+				header.skip()
+			} else if let Some(target_path) = self.file_target(&name) {
+				let size = entry.unpacked_size;
+				self.progress(&name);
+
+				match header.extract_to(&target_path) {
+					Ok(archive) => {
+						self.run_metadata.files_extracted += 1;
+						self.run_metadata.bytes_extracted += size;
+						Ok(archive)
+					}
+					Err(e) => {
+						fs::remove_file(&target_path).ok();
+						Err(e)
+					}
+				}
+			} else {
+				header.skip()
+			}
+			.map_err(rar_error)?;
+		}
+
+		Ok(())
+	}
+
+	fn target_path(&mut self, name: &str) -> Option<PathBuf> {
+		let path = entry_path(name).map(|path| self.target_directory_path.join(path));
+		if path.is_none() {
+			self.errors.push(format!(
+				"skipped '{name}' from the archive, its path leads out of the target directory"
+			));
+		}
+
+		path
+	}
+
+	fn skip_unsupported(&mut self, name: &str) {
+		self.errors.push(format!(
+			"skipped '{name}' from the archive, only files and directories are extracted"
+		));
+	}
+
+	fn dir(&mut self, name: &str) {
+		let Some(target_path) = self.target_path(name) else {
+			return;
+		};
+
+		match fs::create_dir_all(&target_path) {
+			Ok(()) => self.run_metadata.dirs_extracted += 1,
+			Err(e) => self
+				.errors
+				.push(FileIOError::from((&target_path, e)).to_string()),
+		}
+	}
+
+	fn progress(&self, name: &str) {
+		self.ctx.progress_msg(job_message!(
+			"archive.extracting",
+			name = name,
+			extracted = self.run_metadata.bytes_extracted
+		));
+	}
+
+	/// Where the file is extracted to, following the collision policy, `None` if it isn't
+	fn file_target(&mut self, name: &str) -> Option<PathBuf> {
+		let target_path = self.target_path(name)?;
+
+		if let Some(parent) = target_path.parent() {
+			if let Err(e) = fs::create_dir_all(parent) {
+				self.errors.push(FileIOError::from((parent, e)).to_string());
+				return None;
+			}
+		}
+
+		match fs::symlink_metadata(&target_path) {
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Some(target_path),
+			Err(e) => {
+				self.errors
+					.push(FileIOError::from((&target_path, e)).to_string());
+				None
+			}
+			Ok(metadata) => match self.collision {
+				CollisionPolicy::Skip => {
+					trace!("Skipping {} as it already exists", target_path.display());
+					self.run_metadata.files_skipped += 1;
+					None
+				}
+				CollisionPolicy::Overwrite if metadata.is_dir() => {
+					self.errors.push(format!(
+						"not overwriting the directory {} with a file from the archive",
+						target_path.display()
+					));
+					None
+				}
+				CollisionPolicy::Overwrite => {
+					// Not writing through a symlink, to whatever it points to
+					if let Err(e) = fs::remove_file(&target_path) {
+						self.errors
+							.push(FileIOError::from((&target_path, e)).to_string());
+						return None;
+					}
+					Some(target_path)
+				}
+				CollisionPolicy::Rename => {
+					self.run_metadata.files_renamed += 1;
+					Some(available_path(&target_path))
+				}
+			},
+		}
+	}
+
+	fn file(
+		&mut self,
+		name: &str,
+		reader: &mut dyn Read,
+		mode: Option<u32>,
+	) -> Result<(), ArchiveError> {
+		let Some(target_path) = self.file_target(name) else {
+			return Ok(());
+		};
+
+		self.progress(name);
+
+		match self.write_file(&target_path, reader, mode) {
+			Ok(Ok(written)) => {
+				self.run_metadata.files_extracted += 1;
+				self.run_metadata.bytes_extracted += written;
+				Ok(())
+			}
+			Ok(Err(e)) => {
+				self.errors.push(e.to_string());
+				fs::remove_file(&target_path).ok();
+				Ok(())
+			}
+			Err(e) => {
+				fs::remove_file(&target_path).ok();
+				Err(e)
+			}
+		}
+	}
+
+	/// Copies an entry to `target_path`. The outer error is the archive's, which stops the
+	/// extraction, the inner one is the target file's.
+	fn write_file(
+		&self,
+		target_path: &Path,
+		reader: &mut dyn Read,
+		mode: Option<u32>,
+	) -> Result<Result<u64, FileIOError>, ArchiveError> {
+		let mut file = match File::create(target_path) {
+			Ok(file) => file,
+			Err(e) => return Ok(Err(FileIOError::from((target_path, e)))),
+		};
+
+		let mut buffer = vec![0; WRITE_CHUNK_SIZE];
+		let mut written = 0;
+		loop {
+			let read = reader
+				.read(&mut buffer)
+				.map_err(|e| archive_error(self.archive_path, e))?;
+			if read == 0 {
+				break;
+			}
+
+			if let Err(e) = file.write_all(&buffer[..read]) {
+				return Ok(Err(FileIOError::from((target_path, e))));
+			}
+			written += read as u64;
+		}
+
+		#[cfg(unix)]
+		if let Some(mode) = mode {
+			use std::os::unix::fs::PermissionsExt;
+
+			// Only the permissions, no setuid and such from an archive
+			if let Err(e) =
+				fs::set_permissions(target_path, fs::Permissions::from_mode(mode & 0o777))
+			{
+				return Ok(Err(FileIOError::from((target_path, e))));
+			}
+		}
+
+		#[cfg(not(unix))]
+		let _ = mode; // Permissions are only kept on unix
+
+		Ok(Ok(written))
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileExtractorJob {
+	type Init = FileExtractorJobInit;
+	type Data = FileExtractorJobData;
+	type Step = ();
+	type RunMetadata = FileExtractorJobRunMetadata;
+
+	const NAME: &'static str = "file_extractor";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+		let archive = get_many_files_datas(db, &location_path, &[init.file_path_id])
+			.await?
+			.pop()
+			.ok_or(FileSystemJobsError::FilePathIdNotFound(init.file_path_id))?;
+
+		let target_location_path =
+			get_location_path_from_location_id(db, init.target_location_id).await?;
+		ensure_sub_path_is_directory(
+			&target_location_path,
+			&init.target_location_relative_directory_path,
+		)
+		.await
+		.map_err(ArchiveError::from)?;
+
+		let mut start = vec![];
+		tokio::fs::File::open(&archive.full_path)
+			.await
+			.map_err(|e| FileIOError::from((&archive.full_path, e)))?
+			.take(512)
+			.read_to_end(&mut start)
+			.await
+			.map_err(|e| FileIOError::from((&archive.full_path, e)))?;
+
+		*data = Some(FileExtractorJobData {
+			kind: ArchiveKind::detect(&start)?,
+			archive_path: archive.full_path,
+			target_directory_path: push_location_relative_path(
+				target_location_path,
+				&init.target_location_relative_directory_path,
+			),
+		});
+
+		Ok((FileExtractorJobRunMetadata::default(), vec![()]).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		_: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let (run_metadata, errors) = block_in_place(|| {
+			Extractor {
+				ctx,
+				archive_path: &data.archive_path,
+				target_directory_path: &data.target_directory_path,
+				collision: init.collision,
+				run_metadata: FileExtractorJobRunMetadata::default(),
+				errors: vec![],
+			}
+			.extract(data.kind)
+		})?;
+
+		Ok((vec![], run_metadata, JobRunErrors(errors)).into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let init = &state.init;
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Extracted {} files and {} directories, {} bytes, {} files skipped and {} renamed",
+			metadata.files_extracted,
+			metadata.dirs_extracted,
+			metadata.bytes_extracted,
+			metadata.files_skipped,
+			metadata.files_renamed
+		);
+
+		let location = find_location(&ctx.library, init.target_location_id)
+			.include(location_with_indexer_rules::include())
+			.exec()
+			.await?
+			.ok_or(LocationError::IdNotFound(init.target_location_id))?;
+
+		// Not failing the extraction, the files are there, they'll be indexed on the next scan
+		let sub_path = &init.target_location_relative_directory_path;
+		let scanned = if sub_path == Path::new("") || sub_path == Path::new("/") {
+			scan_location(&ctx.library, location).await
+		} else {
+			scan_location_sub_path(&ctx.library, location, sub_path).await
+		};
+		if let Err(e) = scanned {
+			error!("Failed to index the extracted files: {e:#?}");
+		}
+
+		invalidate_query!(ctx.library, "search.paths");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detects_archive_formats() {
+		let mut tar = vec![0; 512];
+		tar[257..263].copy_from_slice(b"ustar\0");
+
+		assert_eq!(ArchiveKind::detect(&tar).unwrap(), ArchiveKind::Tar);
+		assert_eq!(
+			ArchiveKind::detect(b"PK\x03\x04\x14\x00").unwrap(),
+			ArchiveKind::Zip
+		);
+		assert_eq!(
+			ArchiveKind::detect(&[0x28, 0xB5, 0x2F, 0xFD, 0x00]).unwrap(),
+			ArchiveKind::TarZst
+		);
+		assert_eq!(
+			ArchiveKind::detect(b"7z\xBC\xAF\x27\x1C\x00\x04").unwrap(),
+			ArchiveKind::SevenZip
+		);
+		assert_eq!(
+			ArchiveKind::detect(b"Rar!\x1A\x07\x01\x00").unwrap(),
+			ArchiveKind::Rar
+		);
+		assert!(matches!(
+			ArchiveKind::detect(b"not an archive"),
+			Err(ArchiveError::UnsupportedFormat(_))
+		));
+	}
+
+	#[test]
+	fn keeps_entries_in_the_target_directory() {
+		assert_eq!(
+			entry_path("photos/./2023/a.jpg"),
+			Some(PathBuf::from("photos/2023/a.jpg"))
+		);
+		assert_eq!(entry_path("/etc/passwd"), Some(PathBuf::from("etc/passwd")));
+		assert_eq!(
+			entry_path("windows\\path.txt"),
+			Some(PathBuf::from("windows/path.txt"))
+		);
+		assert_eq!(entry_path("photos/../../escape"), None);
+		assert_eq!(entry_path("C:/escape"), None);
+		assert_eq!(entry_path("./"), None);
+	}
+}
@@ -0,0 +1,475 @@
+//! Archives of a selection of files, as a zip or a tar compressed with gzip or zstd, written next to
+//! the first of them.
+//! The archive is indexed once it's written, and its object is linked to the objects of the files
+//! it was made from. Extracting archives is in [`extract`].
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{FilePathError, IsolatedFilePathData, MetadataExt},
+	prisma::{archive_source, file_path, location, object},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	collections::HashSet,
+	fs::Metadata,
+	path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, io, task::block_in_place};
+use tracing::{info, trace, warn};
+
+use super::{
+	error::FileSystemJobsError, get_location_path_from_location_id, get_many_files_datas,
+	index_new_file,
+};
+
+pub mod extract;
+
+mod writer;
+
+use writer::ArchiveWriter;
+
+/// Levels go from 0, only storing the files, to this, the smallest and slowest archives
+pub const MAX_LEVEL: u8 = 9;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+	#[error("invalid compression level: {0}, levels go from 0 to {MAX_LEVEL}")]
+	InvalidLevel(u8),
+	#[error("invalid archive name: '{0}'")]
+	InvalidName(String),
+	#[error("no files to archive")]
+	NoSources,
+	#[error("file changed while it was archived: {0}")]
+	FileChanged(String),
+	#[error("unsupported archive format: {0}")]
+	UnsupportedFormat(String),
+	#[error("corrupted archive: {0}")]
+	Corrupted(String),
+	#[error("failed to read the archive: {0}")]
+	Read(String),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	FilePath(#[from] FilePathError),
+	#[error(transparent)]
+	FileSystem(#[from] FileSystemJobsError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+	Zip,
+	/// A tar archive compressed with gzip
+	TarGz,
+	/// A tar archive compressed with zstd, faster and smaller than gzip
+	TarZst,
+}
+
+impl ArchiveFormat {
+	fn extension(&self) -> &'static str {
+		match self {
+			Self::Zip => "zip",
+			Self::TarGz => "tar.gz",
+			Self::TarZst => "tar.zst",
+		}
+	}
+}
+
+pub struct FileArchiverJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileArchiverJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_ids: Vec<file_path::id::Type>,
+	/// The name of the archive, without its extension
+	pub name: String,
+	pub format: ArchiveFormat,
+	/// From 0, only storing the files, to 9, the smallest and slowest archives
+	pub compression_level: u8,
+}
+
+impl JobInitData for FileArchiverJobInit {
+	type Job = FileArchiverJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchiveEntry {
+	full_path: PathBuf,
+	/// Its path in the archive
+	name: String,
+	is_dir: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileArchiverJobData {
+	location_path: PathBuf,
+	archive_path: PathBuf,
+	entries: Vec<ArchiveEntry>,
+	total_bytes: u64,
+	/// The objects of the archived files, the archive's object is linked to them
+	source_object_ids: Vec<object::id::Type>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileArchiverJobRunMetadata {
+	files_archived: u64,
+	bytes_archived: u64,
+	archive_size: u64,
+}
+
+impl JobRunMetadata for FileArchiverJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.files_archived += new_data.files_archived;
+		self.bytes_archived += new_data.bytes_archived;
+		self.archive_size += new_data.archive_size;
+	}
+}
+
+/// The entries for `full_path` and everything in it, returning their size. Symlinks are skipped,
+/// they could point outside of the selection, or to one of its parents.
+async fn walk(
+	full_path: PathBuf,
+	name: String,
+	archive_path: &Path,
+	entries: &mut Vec<ArchiveEntry>,
+) -> Result<u64, FileIOError> {
+	let mut total_bytes = 0;
+	let mut pending = vec![(full_path, name)];
+
+	while let Some((full_path, name)) = pending.pop() {
+		if full_path == archive_path {
+			continue;
+		}
+
+		let metadata = fs::symlink_metadata(&full_path)
+			.await
+			.map_err(|e| FileIOError::from((&full_path, e)))?;
+
+		if metadata.is_symlink() {
+			warn!("Skipping symlink {} in archive", full_path.display());
+			continue;
+		}
+
+		if metadata.is_dir() {
+			let mut read_dir = fs::read_dir(&full_path)
+				.await
+				.map_err(|e| FileIOError::from((&full_path, e)))?;
+
+			while let Some(entry) = read_dir
+				.next_entry()
+				.await
+				.map_err(|e| FileIOError::from((&full_path, e)))?
+			{
+				pending.push((
+					entry.path(),
+					format!("{name}/{}", entry.file_name().to_string_lossy()),
+				));
+			}
+		} else {
+			total_bytes += metadata.len();
+		}
+
+		entries.push(ArchiveEntry {
+			full_path,
+			name,
+			is_dir: metadata.is_dir(),
+		});
+	}
+
+	Ok(total_bytes)
+}
+
+/// The permission bits stored in the archive
+fn mode(metadata: &Metadata) -> u32 {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+
+		metadata.permissions().mode() & 0o7777
+	}
+
+	#[cfg(not(unix))]
+	{
+		if metadata.is_dir() {
+			0o755
+		} else {
+			0o644
+		}
+	}
+}
+
+/// Compression takes a while, so it runs on the blocking threads, with [`block_in_place`]
+fn write_archive(
+	ctx: &WorkerContext,
+	init: &FileArchiverJobInit,
+	data: &FileArchiverJobData,
+	path: &Path,
+) -> Result<FileArchiverJobRunMetadata, ArchiveError> {
+	let mut writer = ArchiveWriter::new(init.format, init.compression_level, path)?;
+	let mut run_metadata = FileArchiverJobRunMetadata::default();
+
+	for entry in &data.entries {
+		let metadata = std::fs::metadata(&entry.full_path)
+			.map_err(|e| FileIOError::from((&entry.full_path, e)))?;
+		let modified = DateTime::<Local>::from(metadata.modified_or_now());
+
+		if entry.is_dir {
+			writer.add_dir(&entry.name, modified, mode(&metadata))?;
+			continue;
+		}
+
+		ctx.progress_msg(job_message!(
+			"archive.archiving",
+			path = entry.full_path.display(),
+			archived = run_metadata.bytes_archived,
+			total = data.total_bytes
+		));
+
+		writer.add_file(
+			&entry.name,
+			&entry.full_path,
+			metadata.len(),
+			modified,
+			mode(&metadata),
+		)?;
+
+		run_metadata.files_archived += 1;
+		run_metadata.bytes_archived += metadata.len();
+	}
+
+	writer.finish()?;
+
+	run_metadata.archive_size = std::fs::metadata(path)
+		.map_err(|e| FileIOError::from((path, e)))?
+		.len();
+
+	Ok(run_metadata)
+}
+
+/// Indexes the archive, if the watcher didn't already, and links its object to its sources
+async fn register_archive(
+	library: &Library,
+	location_id: location::id::Type,
+	data: &FileArchiverJobData,
+) -> Result<object::id::Type, ArchiveError> {
+	let object_id = index_new_file(
+		library,
+		location_id,
+		&data.location_path,
+		&data.archive_path,
+	)
+	.await?;
+
+	library
+		.db
+		.archive_source()
+		.create_many(
+			data.source_object_ids
+				.iter()
+				.map(|source_id| archive_source::create_unchecked(object_id, *source_id, vec![]))
+				.collect(),
+		)
+		.skip_duplicates()
+		.exec()
+		.await?;
+
+	Ok(object_id)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileArchiverJob {
+	type Init = FileArchiverJobInit;
+	type Data = FileArchiverJobData;
+	type Step = ();
+	type RunMetadata = FileArchiverJobRunMetadata;
+
+	const NAME: &'static str = "file_archiver";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		if init.compression_level > MAX_LEVEL {
+			return Err(ArchiveError::InvalidLevel(init.compression_level).into());
+		}
+
+		let archive_name = format!("{}.{}", init.name, init.format.extension());
+		if init.name.is_empty() || !IsolatedFilePathData::accept_file_name(&archive_name) {
+			return Err(ArchiveError::InvalidName(init.name.clone()).into());
+		}
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+		let files_datas = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		let Some(first) = files_datas.first() else {
+			return Err(ArchiveError::NoSources.into());
+		};
+
+		let archive_path = first
+			.full_path
+			.parent()
+			.unwrap_or(&location_path)
+			.join(archive_name);
+
+		match fs::symlink_metadata(&archive_path).await {
+			Ok(_) => {
+				return Err(
+					FileSystemJobsError::WouldOverwrite(archive_path.into_boxed_path()).into(),
+				)
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((archive_path, e)).into()),
+		}
+
+		let mut entries = vec![];
+		let mut total_bytes = 0;
+		let mut source_object_ids = HashSet::new();
+
+		for file_data in &files_datas {
+			let name = file_data
+				.full_path
+				.file_name()
+				.unwrap_or_default()
+				.to_string_lossy()
+				.to_string();
+
+			total_bytes += walk(
+				file_data.full_path.clone(),
+				name,
+				&archive_path,
+				&mut entries,
+			)
+			.await?;
+
+			source_object_ids.extend(file_data.file_path.object_id);
+
+			if maybe_missing(file_data.file_path.is_dir, "file_path.is_dir")? {
+				let iso_file_path = IsolatedFilePathData::new(
+					init.location_id,
+					&location_path,
+					&file_data.full_path,
+					true,
+				)
+				.map_err(ArchiveError::from)?;
+
+				if let Some(children_prefix) = iso_file_path.materialized_path_for_children() {
+					source_object_ids.extend(
+						db.file_path()
+							.find_many(vec![
+								file_path::location_id::equals(Some(init.location_id)),
+								file_path::materialized_path::starts_with(children_prefix),
+								file_path::object_id::not(None),
+							])
+							.select(file_path::select!({ object_id }))
+							.exec()
+							.await?
+							.into_iter()
+							.filter_map(|file_path| file_path.object_id),
+					);
+				}
+			}
+		}
+
+		trace!(
+			"Archiving {} entries, {total_bytes} bytes, to {}",
+			entries.len(),
+			archive_path.display()
+		);
+
+		*data = Some(FileArchiverJobData {
+			location_path,
+			archive_path,
+			entries,
+			total_bytes,
+			source_object_ids: source_object_ids.into_iter().collect(),
+		});
+
+		Ok((FileArchiverJobRunMetadata::default(), vec![()]).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		_: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		// Written under another name, so a failed job doesn't leave a broken archive behind
+		let mut part_path = data.archive_path.clone().into_os_string();
+		part_path.push(".part");
+		let part_path = PathBuf::from(part_path);
+
+		match block_in_place(|| write_archive(ctx, init, data, &part_path)) {
+			Ok(run_metadata) => {
+				fs::rename(&part_path, &data.archive_path)
+					.await
+					.map_err(|e| FileIOError::from((&data.archive_path, e)))?;
+
+				Ok(run_metadata.into())
+			}
+			Err(e) => {
+				if let Err(e) = fs::remove_file(&part_path).await {
+					warn!(
+						"Failed to remove the unfinished archive {}: {e:#?}",
+						part_path.display()
+					);
+				}
+
+				Err(e.into())
+			}
+		}
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let Some(data) = state.data.as_ref() else {
+			return Ok(None);
+		};
+
+		let object_id = register_archive(&ctx.library, state.init.location_id, data).await?;
+
+		info!(
+			"Archived {} files, {} bytes, to {}, {} bytes",
+			state.run_metadata.files_archived,
+			state.run_metadata.bytes_archived,
+			data.archive_path.display(),
+			state.run_metadata.archive_size
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::json!({
+			"archive_path": data.archive_path,
+			"object_id": object_id,
+			"sources": data.source_object_ids.len(),
+		})))
+	}
+}
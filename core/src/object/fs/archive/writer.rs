@@ -0,0 +1,311 @@
+use crate::util::error::FileIOError;
+
+use std::{
+	fs::File,
+	io::{self, BufWriter, Read, Take, Write},
+	path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::{write::GzEncoder, Compression};
+use tar::{Builder, EntryType, Header};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{ArchiveError, ArchiveFormat};
+
+enum Inner {
+	Zip(ZipWriter<BufWriter<File>>),
+	TarGz(Builder<GzEncoder<BufWriter<File>>>),
+	TarZst(Builder<zstd::Encoder<'static, BufWriter<File>>>),
+}
+
+/// Writes an archive to a file, an entry at a time. It blocks, so it's used from blocking threads.
+pub struct ArchiveWriter {
+	archive_path: PathBuf,
+	level: u8,
+	inner: Inner,
+}
+
+impl ArchiveWriter {
+	pub fn new(
+		format: ArchiveFormat,
+		level: u8,
+		archive_path: &Path,
+	) -> Result<Self, ArchiveError> {
+		let archive_error = |e: io::Error| FileIOError::from((archive_path, e));
+		let file = BufWriter::new(File::create(archive_path).map_err(archive_error)?);
+
+		let inner = match format {
+			ArchiveFormat::Zip => Inner::Zip(ZipWriter::new(file)),
+			ArchiveFormat::TarGz => Inner::TarGz(Builder::new(GzEncoder::new(
+				file,
+				Compression::new(level.into()),
+			))),
+			// zstd always compresses, level 0 gets its fastest level instead
+			ArchiveFormat::TarZst => Inner::TarZst(Builder::new(
+				zstd::Encoder::new(file, level.max(1).into()).map_err(archive_error)?,
+			)),
+		};
+
+		Ok(Self {
+			archive_path: archive_path.to_path_buf(),
+			level,
+			inner,
+		})
+	}
+
+	pub fn add_dir(
+		&mut self,
+		name: &str,
+		modified: DateTime<Local>,
+		mode: u32,
+	) -> Result<(), ArchiveError> {
+		match &mut self.inner {
+			Inner::Zip(zip) => zip
+				.add_directory(name, zip_options(self.level, modified, mode))
+				.map_err(io::Error::from),
+			Inner::TarGz(tar) => tar.append_data(
+				&mut tar_header(0, modified, mode, EntryType::Directory),
+				name,
+				io::empty(),
+			),
+			Inner::TarZst(tar) => tar.append_data(
+				&mut tar_header(0, modified, mode, EntryType::Directory),
+				name,
+				io::empty(),
+			),
+		}
+		.map_err(|e| FileIOError::from((&self.archive_path, e)).into())
+	}
+
+	/// Fails if the file isn't `size` bytes anymore, as tar archives have the size before the data
+	pub fn add_file(
+		&mut self,
+		name: &str,
+		full_path: &Path,
+		size: u64,
+		modified: DateTime<Local>,
+		mode: u32,
+	) -> Result<(), ArchiveError> {
+		let file = File::open(full_path).map_err(|e| FileIOError::from((full_path, e)))?;
+		let mut source = Source {
+			file: file.take(size),
+			read: 0,
+			error: None,
+		};
+
+		let res = match &mut self.inner {
+			Inner::Zip(zip) => zip
+				.start_file(
+					name,
+					zip_options(self.level, modified, mode).large_file(size > u32::MAX.into()),
+				)
+				.map_err(io::Error::from)
+				.and_then(|()| io::copy(&mut source, zip).map(|_| ())),
+			Inner::TarGz(tar) => tar.append_data(
+				&mut tar_header(size, modified, mode, EntryType::Regular),
+				name,
+				&mut source,
+			),
+			Inner::TarZst(tar) => tar.append_data(
+				&mut tar_header(size, modified, mode, EntryType::Regular),
+				name,
+				&mut source,
+			),
+		};
+
+		if let Some(e) = source.error.take() {
+			return Err(FileIOError::from((full_path, e)).into());
+		}
+		res.map_err(|e| FileIOError::from((&self.archive_path, e)))?;
+
+		let grown = source
+			.file
+			.into_inner()
+			.read(&mut [0])
+			.map_err(|e| FileIOError::from((full_path, e)))?
+			!= 0;
+		if source.read != size || grown {
+			return Err(ArchiveError::FileChanged(name.to_string()));
+		}
+
+		Ok(())
+	}
+
+	pub fn finish(self) -> Result<(), ArchiveError> {
+		match self.inner {
+			Inner::Zip(mut zip) => zip.finish().map_err(io::Error::from),
+			Inner::TarGz(tar) => tar.into_inner().and_then(GzEncoder::finish),
+			Inner::TarZst(tar) => tar.into_inner().and_then(zstd::Encoder::finish),
+		}
+		.and_then(|mut file| file.flush())
+		.map_err(|e| FileIOError::from((&self.archive_path, e)).into())
+	}
+}
+
+/// Reads a file being archived, keeping its errors apart from the archive's ones
+struct Source {
+	file: Take<File>,
+	read: u64,
+	error: Option<io::Error>,
+}
+
+impl Read for Source {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self.file.read(buf) {
+			Ok(read) => {
+				self.read += read as u64;
+				Ok(read)
+			}
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => Err(e),
+			Err(e) => {
+				let kind = e.kind();
+				self.error = Some(e);
+				Err(kind.into())
+			}
+		}
+	}
+}
+
+fn zip_options(level: u8, modified: DateTime<Local>, mode: u32) -> FileOptions {
+	let options = FileOptions::default().unix_permissions(mode);
+
+	// Zip archives can't have dates before 1980, those are left at the default one
+	let options = match zip_date_time(modified) {
+		Some(date_time) => options.last_modified_time(date_time),
+		None => options,
+	};
+
+	if level == 0 {
+		options.compression_method(CompressionMethod::Stored)
+	} else {
+		options
+			.compression_method(CompressionMethod::Deflated)
+			.compression_level(Some(level.into()))
+	}
+}
+
+fn zip_date_time(date_time: DateTime<Local>) -> Option<zip::DateTime> {
+	zip::DateTime::from_date_and_time(
+		u16::try_from(date_time.year()).ok()?,
+		date_time.month() as u8,
+		date_time.day() as u8,
+		date_time.hour() as u8,
+		date_time.minute() as u8,
+		date_time.second() as u8,
+	)
+	.ok()
+}
+
+fn tar_header(size: u64, modified: DateTime<Local>, mode: u32, entry_type: EntryType) -> Header {
+	let mut header = Header::new_gnu();
+	header.set_entry_type(entry_type);
+	header.set_size(size);
+	header.set_mtime(modified.timestamp().max(0) as u64);
+	header.set_mode(mode);
+
+	header
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::fs;
+
+	#[test]
+	fn writes_readable_archives() {
+		let dir = tempfile::tempdir().unwrap();
+		let source_path = dir.path().join("source.txt");
+		let data = b"hello archives, ".repeat(1000);
+		fs::write(&source_path, &data).unwrap();
+
+		for format in [
+			ArchiveFormat::Zip,
+			ArchiveFormat::TarGz,
+			ArchiveFormat::TarZst,
+		] {
+			for level in [0, 9] {
+				let archive_path = dir.path().join(format!("archive.{}", format.extension()));
+
+				let mut writer = ArchiveWriter::new(format, level, &archive_path).unwrap();
+				writer.add_dir("dir", Local::now(), 0o755).unwrap();
+				writer
+					.add_file(
+						"dir/source.txt",
+						&source_path,
+						data.len() as u64,
+						Local::now(),
+						0o644,
+					)
+					.unwrap();
+				writer.finish().unwrap();
+
+				let archive = File::open(&archive_path).unwrap();
+				let mut content = vec![];
+				match format {
+					ArchiveFormat::Zip => {
+						zip::ZipArchive::new(archive)
+							.unwrap()
+							.by_name("dir/source.txt")
+							.unwrap()
+							.read_to_end(&mut content)
+							.unwrap();
+					}
+					ArchiveFormat::TarGz => {
+						let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+						let mut entries = archive.entries().unwrap();
+						assert!(entries
+							.next()
+							.unwrap()
+							.unwrap()
+							.header()
+							.entry_type()
+							.is_dir());
+						entries
+							.next()
+							.unwrap()
+							.unwrap()
+							.read_to_end(&mut content)
+							.unwrap();
+					}
+					ArchiveFormat::TarZst => {
+						let mut archive = tar::Archive::new(zstd::Decoder::new(archive).unwrap());
+						let mut entries = archive.entries().unwrap();
+						assert!(entries
+							.next()
+							.unwrap()
+							.unwrap()
+							.header()
+							.entry_type()
+							.is_dir());
+						entries
+							.next()
+							.unwrap()
+							.unwrap()
+							.read_to_end(&mut content)
+							.unwrap();
+					}
+				}
+
+				assert_eq!(content, data, "{format:?} at level {level}");
+			}
+		}
+	}
+
+	#[test]
+	fn fails_on_changed_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let source_path = dir.path().join("source.txt");
+		fs::write(&source_path, b"longer than said").unwrap();
+
+		let mut writer =
+			ArchiveWriter::new(ArchiveFormat::TarGz, 6, &dir.path().join("archive.tar.gz"))
+				.unwrap();
+
+		assert!(matches!(
+			writer.add_file("source.txt", &source_path, 4, Local::now(), 0o644),
+			Err(ArchiveError::FileChanged(_))
+		));
+	}
+}
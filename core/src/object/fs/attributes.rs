@@ -0,0 +1,301 @@
+//! What a file is besides its contents: its timestamps, its permissions and its extended
+//! attributes, which on macOS include resource forks and Finder info. Copies get each of them where
+//! the filesystem of the copy supports it, the ones that can't be set are reported instead of
+//! failing the copy. Creation dates aren't copied, most platforms can't set them.
+
+use std::{collections::BTreeMap, path::Path};
+
+use filetime::{set_file_times, FileTime};
+use serde::{Deserialize, Serialize};
+use tokio::{io, task::spawn_blocking};
+use tracing::trace;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FileAttribute {
+	Timestamps,
+	Permissions,
+	ExtendedAttributes,
+}
+
+/// How many files each attribute couldn't be preserved for, for the report of a job
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(transparent)]
+pub(crate) struct AttributesNotPreserved(BTreeMap<FileAttribute, u32>);
+
+impl AttributesNotPreserved {
+	pub(crate) fn add(&mut self, attributes: impl IntoIterator<Item = FileAttribute>) {
+		for attribute in attributes {
+			*self.0.entry(attribute).or_default() += 1;
+		}
+	}
+
+	pub(crate) fn merge(&mut self, other: Self) {
+		for (attribute, count) in other.0 {
+			*self.0.entry(attribute).or_default() += count;
+		}
+	}
+}
+
+/// Gives `target` the attributes of `source`, its timestamps too with `with_timestamps`. Returns the
+/// attributes that couldn't be preserved, it only fails when `source` can't be read.
+pub(crate) async fn preserve_attributes(
+	source: impl AsRef<Path>,
+	target: impl AsRef<Path>,
+	with_timestamps: bool,
+) -> io::Result<Vec<FileAttribute>> {
+	let source = source.as_ref().to_path_buf();
+	let target = target.as_ref().to_path_buf();
+
+	spawn_blocking(move || copy_attributes(&source, &target, with_timestamps))
+		.await
+		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+}
+
+fn copy_attributes(
+	source: &Path,
+	target: &Path,
+	with_timestamps: bool,
+) -> io::Result<Vec<FileAttribute>> {
+	let metadata = std::fs::metadata(source)?;
+	let mut not_preserved = vec![];
+
+	// First, as a read-only permission could stop the extended attributes from being set
+	if let Err(e) = xattr::copy_all(source, target) {
+		trace!(
+			"Couldn't copy the extended attributes of {} to {}: {e}",
+			source.display(),
+			target.display()
+		);
+		not_preserved.push(FileAttribute::ExtendedAttributes);
+	}
+
+	if let Err(e) = std::fs::set_permissions(target, metadata.permissions()) {
+		trace!(
+			"Couldn't copy the permissions of {} to {}: {e}",
+			source.display(),
+			target.display()
+		);
+		not_preserved.push(FileAttribute::Permissions);
+	}
+
+	// Last, as setting the others could touch them
+	if with_timestamps {
+		if let Err(e) = set_file_times(
+			target,
+			FileTime::from_last_access_time(&metadata),
+			FileTime::from_last_modification_time(&metadata),
+		) {
+			trace!(
+				"Couldn't copy the timestamps of {} to {}: {e}",
+				source.display(),
+				target.display()
+			);
+			not_preserved.push(FileAttribute::Timestamps);
+		}
+	}
+
+	Ok(not_preserved)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod xattr {
+	use std::{
+		ffi::CString,
+		io,
+		os::{
+			raw::{c_int, c_void},
+			unix::ffi::OsStrExt,
+		},
+		path::Path,
+		ptr,
+	};
+
+	#[cfg(target_os = "linux")]
+	unsafe fn list(path: &CString, list: *mut c_void, size: usize) -> isize {
+		libc::listxattr(path.as_ptr(), list.cast(), size)
+	}
+
+	#[cfg(target_os = "macos")]
+	unsafe fn list(path: &CString, list: *mut c_void, size: usize) -> isize {
+		libc::listxattr(path.as_ptr(), list.cast(), size, 0)
+	}
+
+	#[cfg(target_os = "linux")]
+	unsafe fn get(path: &CString, name: &CString, value: *mut c_void, size: usize) -> isize {
+		libc::getxattr(path.as_ptr(), name.as_ptr(), value, size)
+	}
+
+	#[cfg(target_os = "macos")]
+	unsafe fn get(path: &CString, name: &CString, value: *mut c_void, size: usize) -> isize {
+		libc::getxattr(path.as_ptr(), name.as_ptr(), value, size, 0, 0)
+	}
+
+	#[cfg(target_os = "linux")]
+	unsafe fn set(path: &CString, name: &CString, value: &[u8]) -> c_int {
+		libc::setxattr(
+			path.as_ptr(),
+			name.as_ptr(),
+			value.as_ptr().cast(),
+			value.len(),
+			0,
+		)
+	}
+
+	#[cfg(target_os = "macos")]
+	unsafe fn set(path: &CString, name: &CString, value: &[u8]) -> c_int {
+		libc::setxattr(
+			path.as_ptr(),
+			name.as_ptr(),
+			value.as_ptr().cast(),
+			value.len(),
+			0,
+			0,
+		)
+	}
+
+	/// Asks `read` how big a buffer it needs, then reads into one that big
+	fn read_sized(mut read: impl FnMut(*mut c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+		loop {
+			let size = read(ptr::null_mut(), 0);
+			if size < 0 {
+				return Err(io::Error::last_os_error());
+			}
+
+			let mut buf = vec![0_u8; size as usize];
+			let read_size = read(buf.as_mut_ptr().cast(), buf.len());
+			if read_size >= 0 {
+				buf.truncate(read_size as usize);
+				return Ok(buf);
+			}
+
+			let e = io::Error::last_os_error();
+			// The value grew between asking for its size and reading it
+			if e.raw_os_error() != Some(libc::ERANGE) {
+				return Err(e);
+			}
+		}
+	}
+
+	fn c_path(path: &Path) -> io::Result<CString> {
+		CString::new(path.as_os_str().as_bytes())
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+	}
+
+	/// Copies every extended attribute it can, failing with the last one that couldn't be
+	pub(super) fn copy_all(source: &Path, target: &Path) -> io::Result<()> {
+		let source = c_path(source)?;
+		let target = c_path(target)?;
+
+		// SAFETY: the buffers are as big as the sizes given with them
+		let names = read_sized(|buf, size| unsafe { list(&source, buf, size) })?;
+
+		let mut res = Ok(());
+		// The names are each followed by a nul
+		for name in names
+			.split(|byte| *byte == 0)
+			.filter(|name| !name.is_empty())
+		{
+			let name = CString::new(name).expect("names were split on nuls");
+
+			// SAFETY: the buffers are as big as the sizes given with them
+			match read_sized(|buf, size| unsafe { get(&source, &name, buf, size) }) {
+				Ok(value) => {
+					// SAFETY: the strings are nul terminated and the value is a whole slice
+					if unsafe { set(&target, &name, &value) } == -1 {
+						res = Err(io::Error::last_os_error());
+					}
+				}
+				Err(e) => res = Err(e),
+			}
+		}
+
+		res
+	}
+
+	#[cfg(test)]
+	pub(super) fn set_one(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+		let name = CString::new(name).expect("no nuls in test names");
+
+		// SAFETY: the strings are nul terminated and the value is a whole slice
+		match unsafe { set(&c_path(path)?, &name, value) } {
+			-1 => Err(io::Error::last_os_error()),
+			_ => Ok(()),
+		}
+	}
+
+	#[cfg(test)]
+	pub(super) fn get_one(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+		let path = c_path(path)?;
+		let name = CString::new(name).expect("no nuls in test names");
+
+		// SAFETY: the buffers are as big as the sizes given with them
+		read_sized(|buf, size| unsafe { get(&path, &name, buf, size) })
+	}
+}
+
+/// Other platforms have no extended attributes to copy
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod xattr {
+	use std::{io, path::Path};
+
+	pub(super) fn copy_all(_: &Path, _: &Path) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::fs;
+
+	#[tokio::test]
+	async fn copies_keep_their_attributes() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("photo.jpg");
+		let target = dir.path().join("photo copy.jpg");
+		fs::write(&source, b"photo").unwrap();
+		fs::write(&target, b"photo").unwrap();
+
+		let modified = FileTime::from_unix_time(1_500_000_000, 0);
+		set_file_times(&source, modified, modified).unwrap();
+
+		let mut permissions = fs::metadata(&source).unwrap().permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&source, permissions).unwrap();
+
+		// Not every filesystem a temporary directory can be on has extended attributes
+		#[cfg(any(target_os = "linux", target_os = "macos"))]
+		let has_xattrs = xattr::set_one(&source, "user.sd.test", b"value").is_ok();
+
+		let not_preserved = preserve_attributes(&source, &target, true).await.unwrap();
+		assert!(!not_preserved.contains(&FileAttribute::Timestamps));
+		assert!(!not_preserved.contains(&FileAttribute::Permissions));
+
+		let metadata = fs::metadata(&target).unwrap();
+		assert_eq!(FileTime::from_last_modification_time(&metadata), modified);
+		assert!(metadata.permissions().readonly());
+
+		#[cfg(any(target_os = "linux", target_os = "macos"))]
+		if has_xattrs {
+			assert_eq!(xattr::get_one(&target, "user.sd.test").unwrap(), b"value");
+		}
+	}
+
+	#[test]
+	fn counts_the_files_of_each_attribute() {
+		let mut report = AttributesNotPreserved::default();
+		report.add([FileAttribute::Permissions]);
+		report.add([FileAttribute::Permissions, FileAttribute::Timestamps]);
+
+		let mut other = AttributesNotPreserved::default();
+		other.add([FileAttribute::Timestamps]);
+		report.merge(other);
+
+		assert_eq!(
+			serde_json::to_value(&report).unwrap(),
+			serde_json::json!({ "timestamps": 2, "permissions": 2 })
+		);
+	}
+}
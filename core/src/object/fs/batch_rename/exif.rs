@@ -0,0 +1,174 @@
+//! Just enough of EXIF to tell when a photo was taken, from JPEGs and TIFF based raw files
+
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// EXIF data is at the start of the file, this is plenty for its dates
+const EXIF_READ_LIMIT: u64 = 128 * 1024;
+
+const EXIF_IFD_POINTER_TAG: u16 = 0x8769;
+const DATE_TIME_TAG: u16 = 0x0132;
+const DATE_TIME_ORIGINAL_TAG: u16 = 0x9003;
+const ASCII_TYPE: u16 = 2;
+
+const EXIF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+/// When the photo at `path` was taken, if it has EXIF data with a date
+pub async fn date_taken(path: impl AsRef<Path>) -> Option<NaiveDateTime> {
+	let mut data = vec![];
+	File::open(path)
+		.await
+		.ok()?
+		.take(EXIF_READ_LIMIT)
+		.read_to_end(&mut data)
+		.await
+		.ok()?;
+
+	let tiff = Tiff::new(tiff_data(&data)?)?;
+	let ifd0 = tiff.u32(4)? as usize;
+
+	tiff.ifd_entry(ifd0, EXIF_IFD_POINTER_TAG)
+		.and_then(|exif_ifd| tiff.ascii_date(tiff.ifd_entry(exif_ifd, DATE_TIME_ORIGINAL_TAG)?))
+		.or_else(|| tiff.ascii_date(tiff.ifd_entry(ifd0, DATE_TIME_TAG)?))
+}
+
+/// The TIFF structure holding the EXIF data, in the APP1 segment of a JPEG or the whole file
+fn tiff_data(data: &[u8]) -> Option<&[u8]> {
+	if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+		return Some(data);
+	}
+
+	if !data.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+
+	let mut pos = 2;
+	while pos + 4 <= data.len() && data[pos] == 0xFF {
+		let marker = data[pos + 1];
+		// Start of the image data, no metadata after it
+		if marker == 0xDA || marker == 0xD9 {
+			return None;
+		}
+
+		let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+		let segment = data.get(pos + 4..pos + 2 + len)?;
+		if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+			return Some(&segment[6..]);
+		}
+
+		pos += 2 + len;
+	}
+
+	None
+}
+
+struct Tiff<'a> {
+	data: &'a [u8],
+	little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+	fn new(data: &'a [u8]) -> Option<Self> {
+		let little_endian = match data.get(..2)? {
+			b"II" => true,
+			b"MM" => false,
+			_ => return None,
+		};
+
+		Some(Self {
+			data,
+			little_endian,
+		})
+	}
+
+	fn u16(&self, at: usize) -> Option<u16> {
+		let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+		Some(if self.little_endian {
+			u16::from_le_bytes(bytes)
+		} else {
+			u16::from_be_bytes(bytes)
+		})
+	}
+
+	fn u32(&self, at: usize) -> Option<u32> {
+		let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+		Some(if self.little_endian {
+			u32::from_le_bytes(bytes)
+		} else {
+			u32::from_be_bytes(bytes)
+		})
+	}
+
+	/// Where the value of `tag` is in the IFD at `ifd`, for the types that don't fit in an entry
+	fn ifd_entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+		let count = self.u16(ifd)? as usize;
+
+		(0..count)
+			.map(|i| ifd + 2 + i * 12)
+			.find(|&entry| self.u16(entry) == Some(tag))
+			.and_then(|entry| {
+				if tag != EXIF_IFD_POINTER_TAG && self.u16(entry + 2)? != ASCII_TYPE {
+					return None;
+				}
+
+				self.u32(entry + 8).map(|offset| offset as usize)
+			})
+	}
+
+	fn ascii_date(&self, at: usize) -> Option<NaiveDateTime> {
+		let date = std::str::from_utf8(self.data.get(at..at + 19)?).ok()?;
+		NaiveDateTime::parse_from_str(date, EXIF_DATE_FORMAT).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A little endian TIFF with an EXIF IFD holding only a DateTimeOriginal
+	fn tiff_with_date(date: &str) -> Vec<u8> {
+		let mut tiff = b"II*\0".to_vec();
+		tiff.extend(8u32.to_le_bytes());
+		// IFD0 at 8, one entry pointing to the EXIF IFD at 26
+		tiff.extend(1u16.to_le_bytes());
+		tiff.extend(EXIF_IFD_POINTER_TAG.to_le_bytes());
+		tiff.extend(4u16.to_le_bytes());
+		tiff.extend(1u32.to_le_bytes());
+		tiff.extend(26u32.to_le_bytes());
+		tiff.extend(0u32.to_le_bytes());
+		// EXIF IFD at 26, the date right after it at 44
+		tiff.extend(1u16.to_le_bytes());
+		tiff.extend(DATE_TIME_ORIGINAL_TAG.to_le_bytes());
+		tiff.extend(ASCII_TYPE.to_le_bytes());
+		tiff.extend(20u32.to_le_bytes());
+		tiff.extend(44u32.to_le_bytes());
+		tiff.extend(0u32.to_le_bytes());
+		tiff.extend(date.as_bytes());
+		tiff.push(0);
+
+		tiff
+	}
+
+	#[test]
+	fn reads_date_from_jpeg() {
+		let tiff = tiff_with_date("2023:07:12 09:30:00");
+
+		let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+		jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+		jpeg.extend(b"Exif\0\0");
+		jpeg.extend(&tiff);
+		jpeg.extend([0xFF, 0xDA]);
+
+		let tiff_data = tiff_data(&jpeg).unwrap();
+		let tiff = Tiff::new(tiff_data).unwrap();
+		let exif_ifd = tiff.ifd_entry(8, EXIF_IFD_POINTER_TAG).unwrap();
+		let date = tiff
+			.ascii_date(tiff.ifd_entry(exif_ifd, DATE_TIME_ORIGINAL_TAG).unwrap())
+			.unwrap();
+
+		assert_eq!(date.to_string(), "2023-07-12 09:30:00");
+		assert!(tiff_data(b"not an image").is_none());
+	}
+}
@@ -0,0 +1,404 @@
+//! Renaming many files at once, from a [`RenameTemplate`]. The new names can be previewed before
+//! the job renames the files, and the file paths of all the renamed files are updated together
+//! once they're renamed.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	library::journal::{self, FileOperation},
+	location::file_path_helper::IsolatedFilePathData,
+	prisma::{file_path, location, PrismaClient},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	collections::HashMap,
+	hash::Hash,
+	path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, io};
+use tracing::{info, trace, warn};
+
+use super::{
+	construct_target_filename, error::FileSystemJobsError, get_location_path_from_location_id,
+	get_many_files_datas,
+};
+
+mod exif;
+mod template;
+
+pub use template::{RenameTemplate, TemplateError};
+
+use template::TemplateValues;
+
+#[derive(Error, Debug)]
+pub enum BatchRenameError {
+	#[error(transparent)]
+	Template(#[from] TemplateError),
+	#[error("invalid pattern: {0}")]
+	Pattern(#[from] regex::Error),
+	#[error(transparent)]
+	FileSystem(#[from] FileSystemJobsError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<BatchRenameError> for rspc::Error {
+	fn from(e: BatchRenameError) -> Self {
+		let code = match e {
+			BatchRenameError::Template(_) | BatchRenameError::Pattern(_) => {
+				rspc::ErrorCode::BadRequest
+			}
+			BatchRenameError::FileSystem(_) | BatchRenameError::FileIO(_) => {
+				rspc::ErrorCode::InternalServerError
+			}
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+pub struct BatchRenameJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct BatchRenameJobInit {
+	pub location_id: location::id::Type,
+	/// The files to rename, `{counter}` counts them in this order
+	pub file_path_ids: Vec<file_path::id::Type>,
+	/// The new names, see [`RenameTemplate`]
+	pub template: String,
+	/// A regex for the capture groups of the template, files it doesn't match keep their name
+	pub pattern: Option<String>,
+	/// The value of `{counter}` for the first file
+	pub counter_start: u32,
+}
+
+impl JobInitData for BatchRenameJobInit {
+	type Job = BatchRenameJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+/// Why a file of the batch won't be renamed
+#[derive(Serialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchRenameProblem {
+	/// The pattern doesn't match its name
+	NoMatch,
+	/// Directories keep their names, templates are made for files
+	Directory,
+	/// The new name is empty or not allowed by the file system
+	InvalidName,
+	/// Another file has, or would get, the new name
+	Conflict,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct BatchRenamePreview {
+	pub file_path_id: file_path::id::Type,
+	pub from: String,
+	pub to: String,
+	pub problem: Option<BatchRenameProblem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchRenameJobStep {
+	file_path_id: file_path::id::Type,
+	source_full_path: PathBuf,
+	target_full_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RenamedFilePath {
+	file_path_id: file_path::id::Type,
+	name: String,
+	extension: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct BatchRenameJobRunMetadata {
+	/// The file paths to update once every file is renamed
+	renamed: Vec<RenamedFilePath>,
+}
+
+impl JobRunMetadata for BatchRenameJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.renamed.extend(new_data.renamed);
+	}
+}
+
+fn is_valid_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name != "."
+		&& name != ".."
+		&& IsolatedFilePathData::accept_file_name(name)
+		&& IsolatedFilePathData::separate_name_and_extension_from_str(name).is_ok()
+}
+
+/// The new name of each file of the batch, with the step to rename the ones without problems
+async fn plan(
+	db: &PrismaClient,
+	init: &BatchRenameJobInit,
+) -> Result<Vec<(BatchRenamePreview, Option<BatchRenameJobStep>)>, BatchRenameError> {
+	let template = RenameTemplate::parse(&init.template)?;
+	let pattern = init.pattern.as_deref().map(Regex::new).transpose()?;
+
+	let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+	let files_datas = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+	let mut planned = Vec::with_capacity(files_datas.len());
+	for (counter, file_data) in (init.counter_start..).zip(files_datas) {
+		let from =
+			construct_target_filename(&file_data, &None).map_err(FileSystemJobsError::from)?;
+		let file_path = &file_data.file_path;
+
+		let preview = |to: String, problem| BatchRenamePreview {
+			file_path_id: file_path.id,
+			from: from.clone(),
+			to,
+			problem,
+		};
+
+		if maybe_missing(file_path.is_dir, "file_path.is_dir").map_err(FileSystemJobsError::from)? {
+			planned.push((
+				preview(from.clone(), Some(BatchRenameProblem::Directory)),
+				None,
+			));
+			continue;
+		}
+
+		let captures = pattern.as_ref().map(|pattern| pattern.captures(&from));
+		if let Some(None) = captures {
+			planned.push((
+				preview(from.clone(), Some(BatchRenameProblem::NoMatch)),
+				None,
+			));
+			continue;
+		}
+
+		let date = if template.uses_date() {
+			exif::date_taken(&file_data.full_path)
+				.await
+				.or_else(|| file_path.date_created.map(|date| date.naive_local()))
+		} else {
+			None
+		};
+
+		let to = template.render(&TemplateValues {
+			name: file_path.name.as_deref().unwrap_or_default(),
+			extension: file_path.extension.as_deref().unwrap_or_default(),
+			counter,
+			date,
+			captures: captures.flatten().as_ref(),
+		});
+
+		if !is_valid_name(&to) {
+			planned.push((preview(to, Some(BatchRenameProblem::InvalidName)), None));
+			continue;
+		}
+
+		let target_full_path = file_data.full_path.with_file_name(&to);
+		let step = (to != from).then(|| BatchRenameJobStep {
+			file_path_id: file_path.id,
+			source_full_path: file_data.full_path.clone(),
+			target_full_path,
+		});
+
+		planned.push((preview(to, None), step));
+	}
+
+	// Two files of the batch getting the same name, or a file already having it
+	let mut targets = HashMap::<PathBuf, usize>::new();
+	for (_, step) in &planned {
+		if let Some(step) = step {
+			*targets.entry(step.target_full_path.clone()).or_default() += 1;
+		}
+	}
+
+	for (preview, step) in &mut planned {
+		let Some(target_full_path) = step.as_ref().map(|step| step.target_full_path.clone()) else {
+			continue;
+		};
+
+		if targets[&target_full_path] > 1 || exists(&target_full_path).await? {
+			preview.problem = Some(BatchRenameProblem::Conflict);
+			*step = None;
+		}
+	}
+
+	Ok(planned)
+}
+
+async fn exists(path: &Path) -> Result<bool, FileIOError> {
+	match fs::symlink_metadata(path).await {
+		Ok(_) => Ok(true),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+		Err(e) => Err(FileIOError::from((path, e))),
+	}
+}
+
+/// The new names the batch would give to its files, and why some would keep theirs
+pub async fn preview(
+	db: &PrismaClient,
+	init: &BatchRenameJobInit,
+) -> Result<Vec<BatchRenamePreview>, BatchRenameError> {
+	Ok(plan(db, init)
+		.await?
+		.into_iter()
+		.map(|(preview, _)| preview)
+		.collect())
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for BatchRenameJob {
+	type Init = BatchRenameJobInit;
+	type Data = ();
+	type Step = BatchRenameJobStep;
+	type RunMetadata = BatchRenameJobRunMetadata;
+
+	const NAME: &'static str = "batch_rename";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let mut steps = vec![];
+		let mut errors = vec![];
+
+		for (preview, step) in plan(&ctx.library.db, init).await? {
+			match (preview.problem, step) {
+				(None, Some(step)) => steps.push(step),
+				(Some(BatchRenameProblem::InvalidName), _) => errors.push(format!(
+					"invalid new name for {}: '{}'",
+					preview.from, preview.to
+				)),
+				(Some(BatchRenameProblem::Conflict), _) => errors.push(format!(
+					"another file has or would get the new name of {}: '{}'",
+					preview.from, preview.to
+				)),
+				_ => {}
+			}
+		}
+
+		// Must fill in the data, otherwise the job will not run
+		*data = Some(());
+
+		Ok((
+			BatchRenameJobRunMetadata::default(),
+			steps,
+			JobRunErrors(errors),
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let BatchRenameJobStep {
+			file_path_id,
+			source_full_path,
+			target_full_path,
+		} = step;
+
+		// Another file could have taken the name since the job started
+		if exists(target_full_path).await? {
+			warn!(
+				"Skipping {} as it would be overwritten",
+				target_full_path.display()
+			);
+
+			return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+				target_full_path.clone().into_boxed_path(),
+			)
+			.to_string()])
+			.into());
+		}
+
+		trace!(
+			"Renaming {} to {}",
+			source_full_path.display(),
+			target_full_path.display()
+		);
+
+		// Not failing the job, the file paths of the files already renamed must still be updated
+		if let Err(e) = fs::rename(source_full_path, target_full_path).await {
+			return Ok(
+				JobRunErrors(vec![FileIOError::from((source_full_path, e)).to_string()]).into(),
+			);
+		}
+
+		journal::record(
+			&ctx.library,
+			ctx.job_id,
+			vec![FileOperation::moved(source_full_path, target_full_path)],
+		)
+		.await;
+
+		let full_name = target_full_path
+			.file_name()
+			.unwrap_or_default()
+			.to_string_lossy();
+		let (name, extension) =
+			IsolatedFilePathData::separate_name_and_extension_from_str(&full_name)
+				.map_err(FileSystemJobsError::from)?;
+
+		Ok(BatchRenameJobRunMetadata {
+			renamed: vec![RenamedFilePath {
+				file_path_id: *file_path_id,
+				name: name.to_string(),
+				extension: extension.to_string(),
+			}],
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let db = &ctx.library.db;
+		let renamed = &state.run_metadata.renamed;
+
+		// A single batch, so the file paths are all updated or none are
+		db._batch(
+			renamed
+				.iter()
+				.map(|renamed| {
+					db.file_path().update(
+						file_path::id::equals(renamed.file_path_id),
+						vec![
+							file_path::name::set(Some(renamed.name.clone())),
+							file_path::extension::set(Some(renamed.extension.clone())),
+						],
+					)
+				})
+				.collect::<Vec<_>>(),
+		)
+		.await?;
+
+		info!("Renamed {} files", renamed.len());
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
@@ -0,0 +1,275 @@
+use chrono::{
+	format::{Item, StrftimeItems},
+	NaiveDateTime,
+};
+use regex::Captures;
+use thiserror::Error;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+	#[error("unclosed '{{' in the template")]
+	UnclosedBrace,
+	#[error("unexpected '}}' in the template, write '}}}}' for a literal one")]
+	UnexpectedBrace,
+	#[error("unknown placeholder: {{{0}}}")]
+	UnknownPlaceholder(String),
+	#[error("invalid placeholder argument: {{{0}}}")]
+	InvalidArgument(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+	Literal(String),
+	/// Characters of the original name, without its extension, negative bounds count from its end
+	Name {
+		start: Option<i64>,
+		end: Option<i64>,
+	},
+	Extension,
+	Counter {
+		width: usize,
+	},
+	Date(String),
+	Capture(usize),
+}
+
+/// What a template is rendered with, for one file
+pub struct TemplateValues<'a> {
+	pub name: &'a str,
+	pub extension: &'a str,
+	pub counter: u32,
+	/// When the photo was taken, or the file created
+	pub date: Option<NaiveDateTime>,
+	pub captures: Option<&'a Captures<'a>>,
+}
+
+/// The new name of each file of a batch rename, written with placeholders:
+/// - `{name}`, the original name without its extension, or `{name:START:END}` for a slice of its
+///   characters, where a negative bound counts from the end and a missing one means the edge
+/// - `{ext}`, the original extension, without the dot
+/// - `{counter}`, the position of the file in the batch, `{counter:3}` pads it to 3 digits
+/// - `{date}`, when the photo was taken per its EXIF data, or when the file was created, with
+///   `{date:%Y%m%d}` for another `strftime` format than `%Y-%m-%d`
+/// - `{0}`, `{1}`..., the capture groups of the batch's pattern in the original name
+///
+/// `{{` and `}}` are literal braces.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RenameTemplate(Vec<Segment>);
+
+impl RenameTemplate {
+	pub fn parse(template: &str) -> Result<Self, TemplateError> {
+		let mut segments = vec![];
+		let mut literal = String::new();
+		let mut chars = template.chars().peekable();
+
+		while let Some(c) = chars.next() {
+			match c {
+				'{' if chars.peek() == Some(&'{') => {
+					chars.next();
+					literal.push('{');
+				}
+				'}' if chars.peek() == Some(&'}') => {
+					chars.next();
+					literal.push('}');
+				}
+				'}' => return Err(TemplateError::UnexpectedBrace),
+				'{' => {
+					let mut placeholder = String::new();
+					loop {
+						match chars.next() {
+							Some('}') => break,
+							Some(c) => placeholder.push(c),
+							None => return Err(TemplateError::UnclosedBrace),
+						}
+					}
+
+					if !literal.is_empty() {
+						segments.push(Segment::Literal(std::mem::take(&mut literal)));
+					}
+					segments.push(Self::parse_placeholder(&placeholder)?);
+				}
+				c => literal.push(c),
+			}
+		}
+
+		if !literal.is_empty() {
+			segments.push(Segment::Literal(literal));
+		}
+
+		Ok(Self(segments))
+	}
+
+	fn parse_placeholder(placeholder: &str) -> Result<Segment, TemplateError> {
+		let invalid = || TemplateError::InvalidArgument(placeholder.to_string());
+
+		let (kind, argument) = match placeholder.split_once(':') {
+			Some((kind, argument)) => (kind, Some(argument)),
+			None => (placeholder, None),
+		};
+
+		Ok(match (kind, argument) {
+			("name", None) => Segment::Name {
+				start: None,
+				end: None,
+			},
+			("name", Some(argument)) => {
+				let (start, end) = argument.split_once(':').ok_or_else(invalid)?;
+				let bound = |bound: &str| {
+					(!bound.is_empty())
+						.then(|| bound.parse::<i64>().map_err(|_| invalid()))
+						.transpose()
+				};
+
+				Segment::Name {
+					start: bound(start)?,
+					end: bound(end)?,
+				}
+			}
+			("ext", None) => Segment::Extension,
+			("counter", None) => Segment::Counter { width: 0 },
+			("counter", Some(width)) => Segment::Counter {
+				width: width.parse().map_err(|_| invalid())?,
+			},
+			("date", None) => Segment::Date(DEFAULT_DATE_FORMAT.to_string()),
+			("date", Some(format)) => {
+				if format.is_empty()
+					|| StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+				{
+					return Err(invalid());
+				}
+
+				Segment::Date(format.to_string())
+			}
+			(group, None) => Segment::Capture(
+				group
+					.parse()
+					.map_err(|_| TemplateError::UnknownPlaceholder(placeholder.to_string()))?,
+			),
+			_ => return Err(TemplateError::UnknownPlaceholder(placeholder.to_string())),
+		})
+	}
+
+	/// Whether the template uses the date, which is read from the files
+	pub fn uses_date(&self) -> bool {
+		self.0
+			.iter()
+			.any(|segment| matches!(segment, Segment::Date(_)))
+	}
+
+	/// The new name, trailing dots are dropped for files without an extension
+	pub fn render(&self, values: &TemplateValues<'_>) -> String {
+		let mut rendered = String::new();
+
+		for segment in &self.0 {
+			match segment {
+				Segment::Literal(literal) => rendered.push_str(literal),
+				Segment::Name { start, end } => {
+					let chars = values.name.chars().collect::<Vec<_>>();
+					let len = chars.len() as i64;
+					let index =
+						|bound: i64| (if bound < 0 { len + bound } else { bound }).clamp(0, len);
+
+					let start = start.map(index).unwrap_or(0) as usize;
+					let end = end.map(index).unwrap_or(len) as usize;
+					if start < end {
+						rendered.extend(&chars[start..end]);
+					}
+				}
+				Segment::Extension => rendered.push_str(values.extension),
+				Segment::Counter { width } => {
+					rendered.push_str(&format!("{:0width$}", values.counter, width = *width))
+				}
+				Segment::Date(format) => {
+					if let Some(date) = values.date {
+						rendered.push_str(&date.format(format).to_string());
+					}
+				}
+				Segment::Capture(group) => {
+					if let Some(capture) = values.captures.and_then(|captures| captures.get(*group))
+					{
+						rendered.push_str(capture.as_str());
+					}
+				}
+			}
+		}
+
+		rendered.trim_end_matches('.').to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::NaiveDate;
+	use regex::Regex;
+
+	#[test]
+	fn renders_placeholders() {
+		let date = NaiveDate::from_ymd_opt(2023, 7, 12)
+			.unwrap()
+			.and_hms_opt(9, 30, 0)
+			.unwrap();
+		let regex = Regex::new(r"IMG_(\d+)").unwrap();
+		let captures = regex.captures("IMG_0042.jpg").unwrap();
+
+		let values = TemplateValues {
+			name: "IMG_0042",
+			extension: "jpg",
+			counter: 7,
+			date: Some(date),
+			captures: Some(&captures),
+		};
+
+		let render = |template: &str| RenameTemplate::parse(template).unwrap().render(&values);
+
+		assert_eq!(render("{date}_{counter:3}.{ext}"), "2023-07-12_007.jpg");
+		assert_eq!(
+			render("{date:%Y%m%d-%H%M} {1}.{ext}"),
+			"20230712-0930 0042.jpg"
+		);
+		assert_eq!(render("{name:0:3}-{name:-2:}"), "IMG-42");
+		assert_eq!(render("{{{counter}}}"), "{7}");
+		assert_eq!(
+			render("{name}.{ext}"),
+			RenameTemplate::parse("{0}.jpg").unwrap().render(&values)
+		);
+
+		let no_extension = TemplateValues {
+			extension: "",
+			..values
+		};
+		assert_eq!(
+			RenameTemplate::parse("{name}.{ext}")
+				.unwrap()
+				.render(&no_extension),
+			"IMG_0042"
+		);
+	}
+
+	#[test]
+	fn rejects_bad_templates() {
+		assert_eq!(
+			RenameTemplate::parse("{name"),
+			Err(TemplateError::UnclosedBrace)
+		);
+		assert_eq!(
+			RenameTemplate::parse("name}"),
+			Err(TemplateError::UnexpectedBrace)
+		);
+		assert_eq!(
+			RenameTemplate::parse("{size}"),
+			Err(TemplateError::UnknownPlaceholder("size".to_string()))
+		);
+		assert_eq!(
+			RenameTemplate::parse("{counter:wide}"),
+			Err(TemplateError::InvalidArgument("counter:wide".to_string()))
+		);
+		assert_eq!(
+			RenameTemplate::parse("{date:%Q}"),
+			Err(TemplateError::InvalidArgument("date:%Q".to_string()))
+		);
+	}
+}
@@ -0,0 +1,400 @@
+//! Converting images to another format, keeping the originals. The object of each converted file is
+//! linked to the object of its original, as one derived from the other.
+//!
+//! Camera RAW files are converted from the largest preview embedded in them, the way their
+//! thumbnails are made, and there's no encoder for them to be converted to DNG.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{ensure_sub_path_is_directory, push_location_relative_path},
+	object::preview::{decode_image, encode_webp},
+	prisma::{derived_object, file_path, location, PrismaClient},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+};
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{fs, io, task::block_in_place};
+use tracing::{info, trace, warn};
+
+use super::{
+	error::FileSystemJobsError, get_location_path_from_location_id, get_many_files_datas,
+	index_new_file, FileData,
+};
+
+const DEFAULT_CONVERSION_QUALITY: u8 = 90;
+
+pub struct ImageConverterJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+	Jpeg,
+	Webp,
+}
+
+impl ImageFormat {
+	const fn extension(&self) -> &'static str {
+		match self {
+			Self::Jpeg => "jpg",
+			Self::Webp => "webp",
+		}
+	}
+
+	fn is_format_of(&self, extension: &str) -> bool {
+		let extension = extension.to_ascii_lowercase();
+
+		match self {
+			Self::Jpeg => matches!(extension.as_str(), "jpg" | "jpeg"),
+			Self::Webp => extension == "webp",
+		}
+	}
+}
+
+/// Where converted images, transcoded videos, or the parts of split files and joined files, are
+/// written
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+#[serde(tag = "type")]
+pub enum ConversionTarget {
+	/// Next to their original files
+	SameDirectory,
+	/// To an existing directory of a location
+	Directory {
+		location_id: location::id::Type,
+		relative_directory_path: PathBuf,
+	},
+}
+
+impl ConversionTarget {
+	/// The location written to, its path and the directory written to, which is the directory of
+	/// each original file when not set
+	pub(super) async fn resolve(
+		&self,
+		db: &PrismaClient,
+		location_id: location::id::Type,
+		location_path: &Path,
+	) -> Result<(location::id::Type, PathBuf, Option<PathBuf>), FileSystemJobsError> {
+		match self {
+			Self::SameDirectory => Ok((location_id, location_path.to_path_buf(), None)),
+			Self::Directory {
+				location_id,
+				relative_directory_path,
+			} => {
+				let target_location_path =
+					get_location_path_from_location_id(db, *location_id).await?;
+				ensure_sub_path_is_directory(&target_location_path, relative_directory_path)
+					.await?;
+
+				let target_directory_path = push_location_relative_path(
+					target_location_path.clone(),
+					relative_directory_path,
+				);
+
+				Ok((
+					*location_id,
+					target_location_path,
+					Some(target_directory_path),
+				))
+			}
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct ImageConverterJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_ids: Vec<file_path::id::Type>,
+	pub format: ImageFormat,
+	/// Encoding quality in the range `1..=100`, 90 when not set
+	pub quality: Option<u8>,
+	pub target: ConversionTarget,
+}
+
+impl JobInitData for ImageConverterJobInit {
+	type Job = ImageConverterJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImageConverterJobData {
+	location_path: PathBuf,
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	/// Where the converted images go, next to their original images when not set
+	target_directory_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ImageConverterJobRunMetadata {
+	images_converted: u64,
+	bytes_written: u64,
+}
+
+impl JobRunMetadata for ImageConverterJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.images_converted += new_data.images_converted;
+		self.bytes_written += new_data.bytes_written;
+	}
+}
+
+/// Decodes the image at `path` and encodes it to `format`, blocking while doing so
+fn convert_image(path: &Path, format: ImageFormat, quality: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+	let img = decode_image(path)?;
+
+	match format {
+		ImageFormat::Jpeg => {
+			// JPEG has no alpha channel, transparent pixels get the color under them
+			let img = DynamicImage::ImageRgb8(img.to_rgb8());
+
+			let mut jpeg = vec![];
+			JpegEncoder::new_with_quality(&mut jpeg, quality.clamp(1, 100)).encode_image(&img)?;
+
+			Ok(jpeg)
+		}
+		ImageFormat::Webp => encode_webp(&img, quality.clamp(1, 100)),
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for ImageConverterJob {
+	type Init = ImageConverterJobInit;
+	type Data = ImageConverterJobData;
+	type Step = FileData;
+	type RunMetadata = ImageConverterJobRunMetadata;
+
+	const NAME: &'static str = "image_converter";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+
+		let (target_location_id, target_location_path, target_directory_path) = init
+			.target
+			.resolve(db, init.location_id, &location_path)
+			.await?;
+
+		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		*data = Some(ImageConverterJobData {
+			location_path,
+			target_location_id,
+			target_location_path,
+			target_directory_path,
+		});
+
+		Ok((ImageConverterJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, .. } = &ctx.library;
+		let FileData {
+			file_path,
+			full_path,
+		} = step;
+
+		if maybe_missing(file_path.is_dir, "file_path.is_dir")? {
+			warn!("Skipping {} as it's a directory", full_path.display());
+
+			return Ok(JobRunErrors(vec![format!(
+				"{} wasn't converted, only images are",
+				full_path.display()
+			)])
+			.into());
+		}
+
+		let name = maybe_missing(&file_path.name, "file_path.name")?;
+		let extension = maybe_missing(&file_path.extension, "file_path.extension")?;
+
+		if init.format.is_format_of(extension) {
+			return Ok(JobRunErrors(vec![format!(
+				"{} wasn't converted, it's already a {:?} image",
+				full_path.display(),
+				init.format
+			)])
+			.into());
+		}
+
+		let converted_name = format!("{name}.{}", init.format.extension());
+		let output_path = match &data.target_directory_path {
+			Some(target_directory_path) => target_directory_path.join(converted_name),
+			None => full_path.with_file_name(converted_name),
+		};
+
+		match fs::metadata(&output_path).await {
+			Ok(_) => {
+				warn!(
+					"Skipping {} as it would be overwritten",
+					output_path.display()
+				);
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					output_path.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((&output_path, e)).into()),
+		}
+
+		ctx.progress_msg(job_message!(
+			"convert.converting",
+			path = full_path.display()
+		));
+
+		// Decoding and encoding images has blocking code
+		let converted = match block_in_place(|| {
+			convert_image(
+				full_path,
+				init.format,
+				init.quality.unwrap_or(DEFAULT_CONVERSION_QUALITY),
+			)
+			.map_err(|e| e.to_string())
+		}) {
+			Ok(converted) => converted,
+			Err(e) => {
+				warn!("Failed to convert {}: {e}", full_path.display());
+
+				return Ok(JobRunErrors(vec![format!(
+					"{} couldn't be converted: {e}",
+					full_path.display()
+				)])
+				.into());
+			}
+		};
+
+		// Written aside first, a file interrupted halfway never looks like a whole image
+		let mut part_path = output_path.clone().into_os_string();
+		part_path.push(".part");
+		let part_path = PathBuf::from(part_path);
+
+		fs::write(&part_path, &converted)
+			.await
+			.map_err(|e| FileIOError::from((&part_path, e)))?;
+		fs::rename(&part_path, &output_path)
+			.await
+			.map_err(|e| FileIOError::from((&output_path, e)))?;
+
+		trace!(
+			"Converted {} to {}",
+			full_path.display(),
+			output_path.display()
+		);
+
+		// The original image may not have been identified yet, this gives it its object
+		let original_id = index_new_file(
+			&ctx.library,
+			init.location_id,
+			&data.location_path,
+			full_path,
+		)
+		.await?;
+		let derived_id = index_new_file(
+			&ctx.library,
+			data.target_location_id,
+			&data.target_location_path,
+			&output_path,
+		)
+		.await?;
+
+		db.derived_object()
+			.create_many(vec![derived_object::create_unchecked(
+				derived_id,
+				original_id,
+				vec![],
+			)])
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		Ok(ImageConverterJobRunMetadata {
+			images_converted: 1,
+			bytes_written: converted.len() as u64,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Converted {} images, {} bytes written",
+			metadata.images_converted, metadata.bytes_written
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use image::{GenericImageView, Rgba, RgbaImage};
+
+	#[test]
+	fn converts_to_both_formats() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("pixel.png");
+		RgbaImage::from_pixel(3, 2, Rgba([200, 40, 40, 128]))
+			.save(&source)
+			.unwrap();
+
+		let jpeg = convert_image(&source, ImageFormat::Jpeg, 80).unwrap();
+		assert_eq!(
+			image::guess_format(&jpeg).unwrap(),
+			image::ImageFormat::Jpeg
+		);
+		assert_eq!(image::load_from_memory(&jpeg).unwrap().dimensions(), (3, 2));
+
+		let webp = convert_image(&source, ImageFormat::Webp, 80).unwrap();
+		assert_eq!(
+			image::guess_format(&webp).unwrap(),
+			image::ImageFormat::WebP
+		);
+	}
+
+	#[test]
+	fn recognizes_its_own_formats() {
+		assert!(ImageFormat::Jpeg.is_format_of("JPEG"));
+		assert!(ImageFormat::Jpeg.is_format_of("jpg"));
+		assert!(!ImageFormat::Jpeg.is_format_of("heic"));
+		assert!(ImageFormat::Webp.is_format_of("webp"));
+		assert!(!ImageFormat::Webp.is_format_of("png"));
+	}
+}
@@ -1,30 +1,57 @@
 use crate::{
 	invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunErrors, JobState,
-		JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::{
+		journal::{self, FileOperation},
+		Library,
 	},
-	library::Library,
 	location::file_path_helper::{join_location_relative_path, IsolatedFilePathData},
+	object::cas::generate_cas_id,
 	prisma::{file_path, location},
 	util::{
 		db::{maybe_missing, MissingFieldError},
 		error::FileIOError,
+		sparse,
 	},
 };
 
-use std::{hash::Hash, path::PathBuf};
+use std::{
+	ffi::OsString,
+	hash::Hash,
+	io::SeekFrom,
+	path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use specta::Type;
-use tokio::{fs, io};
-use tracing::{trace, warn};
+use tokio::{
+	fs::{self, File, OpenOptions},
+	io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::{info, trace, warn};
 
 use super::{
-	construct_target_filename, error::FileSystemJobsError, fetch_source_and_target_location_paths,
-	get_file_data_from_isolated_file_path, get_many_files_datas, FileData,
+	attributes::{preserve_attributes, AttributesNotPreserved},
+	construct_target_filename,
+	error::FileSystemJobsError,
+	fetch_source_and_target_location_paths, get_file_data_from_isolated_file_path,
+	get_many_files_datas,
+	reflink::reflink,
+	FileData,
 };
 
+/// Files at least this big are copied in chunks through a partial file, so a copy interrupted by a
+/// shutdown resumes where it stopped
+const RESUMABLE_COPY_MIN_SIZE: u64 = 64 * 1024 * 1024;
+const COPY_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// Appended to the name of a file being copied in chunks, until it's complete
+const PARTIAL_COPY_EXTENSION: &str = "sdpart";
+
 pub struct FileCopierJob {}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -39,6 +66,15 @@ pub struct FileCopierJobInit {
 	pub sources_file_path_ids: Vec<file_path::id::Type>,
 	pub target_location_relative_directory_path: PathBuf,
 	pub target_file_name_suffix: Option<String>,
+	/// Checks that each copy has the content id of its source, removing the copies that don't
+	#[serde(default)]
+	#[specta(optional)]
+	pub verify: bool,
+	/// Gives the copies the timestamps, permissions and extended attributes of their sources,
+	/// where the filesystem of the copies supports them
+	#[serde(default)]
+	#[specta(optional)]
+	pub preserve_metadata: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -49,6 +85,149 @@ pub struct FileCopierJobStep {
 
 impl JobInitData for FileCopierJobInit {
 	type Job = FileCopierJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.source_location_id, self.target_location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileCopierJobRunMetadata {
+	/// The size of the files found so far, the files of directories are found as they're copied
+	total_bytes: u64,
+	copied_bytes: u64,
+	files_copied: u32,
+	files_verified: u32,
+	/// Partial copies of big files that were resumed after an interruption
+	files_resumed: u32,
+	/// Files cloned by a copy-on-write filesystem instead of having their contents copied
+	#[serde(default)]
+	files_cloned: u32,
+	/// Sparse files, like disk images, copied without writing their holes
+	#[serde(default)]
+	files_sparse: u32,
+	/// How many copies each attribute couldn't be given to, when preserving metadata
+	#[serde(default)]
+	attributes_not_preserved: AttributesNotPreserved,
+}
+
+impl JobRunMetadata for FileCopierJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.total_bytes += new_data.total_bytes;
+		self.copied_bytes += new_data.copied_bytes;
+		self.files_copied += new_data.files_copied;
+		self.files_verified += new_data.files_verified;
+		self.files_resumed += new_data.files_resumed;
+		self.files_cloned += new_data.files_cloned;
+		self.files_sparse += new_data.files_sparse;
+		self.attributes_not_preserved
+			.merge(new_data.attributes_not_preserved);
+	}
+}
+
+/// How the files of a job were copied, for its report
+fn copy_method(files_copied: u32, files_cloned: u32) -> &'static str {
+	match files_cloned {
+		0 => "stream",
+		cloned if cloned == files_copied => "reflink",
+		_ => "mixed",
+	}
+}
+
+/// Where a big file is copied to until it's complete
+fn partial_copy_path(target: &Path) -> PathBuf {
+	let mut file_name = target.file_name().map(OsString::from).unwrap_or_default();
+	file_name.push(".");
+	file_name.push(PARTIAL_COPY_EXTENSION);
+
+	target.with_file_name(file_name)
+}
+
+/// Copies `source` to `target` in chunks through a partial file, continuing a partial copy left by
+/// an interrupted run. Returns whether a partial copy was resumed.
+async fn resumable_copy(
+	ctx: &WorkerContext,
+	source: &Path,
+	target: &Path,
+	size: u64,
+	run_metadata: &FileCopierJobRunMetadata,
+) -> Result<bool, FileIOError> {
+	let partial_path = partial_copy_path(target);
+
+	let mut partial = OpenOptions::new()
+		.create(true)
+		.write(true)
+		.open(&partial_path)
+		.await
+		.map_err(|e| FileIOError::from((&partial_path, e)))?;
+
+	let mut copied = partial
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((&partial_path, e)))?
+		.len();
+
+	// A partial copy bigger than its source is of another version of the file
+	if copied > size {
+		copied = 0;
+	}
+	let resumed = copied > 0;
+
+	partial
+		.set_len(copied)
+		.await
+		.map_err(|e| FileIOError::from((&partial_path, e)))?;
+	partial
+		.seek(SeekFrom::Start(copied))
+		.await
+		.map_err(|e| FileIOError::from((&partial_path, e)))?;
+
+	let mut source_file = File::open(source)
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+	source_file
+		.seek(SeekFrom::Start(copied))
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+
+	let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+	let mut buf = vec![0; COPY_CHUNK_SIZE];
+	loop {
+		let read = source_file
+			.read(&mut buf)
+			.await
+			.map_err(|e| FileIOError::from((source, e)))?;
+		if read == 0 {
+			break;
+		}
+
+		partial
+			.write_all(&buf[..read])
+			.await
+			.map_err(|e| FileIOError::from((&partial_path, e)))?;
+		copied += read as u64;
+
+		ctx.progress_msg(job_message!(
+			"copy.copyingFile",
+			name = file_name,
+			copied = copied,
+			size = size,
+			total_copied = run_metadata.copied_bytes + copied,
+			total = run_metadata.total_bytes
+		));
+	}
+
+	partial
+		.sync_all()
+		.await
+		.map_err(|e| FileIOError::from((&partial_path, e)))?;
+	drop(partial);
+
+	fs::rename(&partial_path, target)
+		.await
+		.map_err(|e| FileIOError::from((target, e)))?;
+
+	Ok(resumed)
 }
 
 #[async_trait::async_trait]
@@ -56,9 +235,10 @@ impl StatefulJob for FileCopierJob {
 	type Init = FileCopierJobInit;
 	type Data = FileCopierJobData;
 	type Step = FileCopierJobStep;
-	type RunMetadata = ();
+	type RunMetadata = FileCopierJobRunMetadata;
 
 	const NAME: &'static str = "file_copier";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -80,8 +260,19 @@ impl StatefulJob for FileCopierJob {
 			)
 			.await?;
 
-		let steps = get_many_files_datas(db, &sources_location_path, &init.sources_file_path_ids)
-			.await?
+		let files_datas =
+			get_many_files_datas(db, &sources_location_path, &init.sources_file_path_ids).await?;
+
+		let mut run_metadata = FileCopierJobRunMetadata::default();
+		for file_data in &files_datas {
+			if let Ok(metadata) = fs::metadata(&file_data.full_path).await {
+				if metadata.is_file() {
+					run_metadata.total_bytes += metadata.len();
+				}
+			}
+		}
+
+		let steps = files_datas
 			.into_iter()
 			.flat_map(|file_data| {
 				// add the currently viewed subdirectory to the location root
@@ -106,7 +297,7 @@ impl StatefulJob for FileCopierJob {
 			sources_location_path,
 		});
 
-		Ok(steps.into())
+		Ok((run_metadata, steps).into())
 	}
 
 	async fn execute_step(
@@ -121,15 +312,37 @@ impl StatefulJob for FileCopierJob {
 			..
 		}: CurrentStep<'_, Self::Step>,
 		data: &Self::Data,
-		_: &Self::RunMetadata,
+		run_metadata: &Self::RunMetadata,
 	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
 		let res = if maybe_missing(source_file_data.file_path.is_dir, "file_path.is_dir")? {
 			let mut more_steps = Vec::new();
+			let mut new_metadata = FileCopierJobRunMetadata::default();
 
+			let created = fs::metadata(target_full_path).await.is_err();
 			fs::create_dir_all(target_full_path)
 				.await
 				.map_err(|e| FileIOError::from((target_full_path, e)))?;
 
+			// Only a directory the job created is removed when it's undone
+			if created {
+				journal::record(
+					&ctx.library,
+					ctx.job_id,
+					vec![FileOperation::created_directory(target_full_path)],
+				)
+				.await;
+			}
+
+			// Not the timestamps, the modification date of the directory changes as its
+			// contents are copied into it
+			if init.preserve_metadata {
+				new_metadata.attributes_not_preserved.add(
+					preserve_attributes(&source_file_data.full_path, target_full_path, false)
+						.await
+						.map_err(|e| FileIOError::from((&source_file_data.full_path, e)))?,
+				);
+			}
+
 			let mut read_dir = fs::read_dir(&source_file_data.full_path)
 				.await
 				.map_err(|e| FileIOError::from((&source_file_data.full_path, e)))?;
@@ -141,6 +354,14 @@ impl StatefulJob for FileCopierJob {
 				.map_err(|e| FileIOError::from((&source_file_data.full_path, e)))?
 			{
 				let children_path = children_entry.path();
+				let children_metadata = children_entry
+					.metadata()
+					.await
+					.map_err(|e| FileIOError::from((&children_path, e)))?;
+				if children_metadata.is_file() {
+					new_metadata.total_bytes += children_metadata.len();
+				}
+
 				let target_children_full_path = target_full_path.join(
 					children_path
 						.strip_prefix(&source_file_data.full_path)
@@ -157,11 +378,7 @@ impl StatefulJob for FileCopierJob {
 							init.source_location_id,
 							&data.sources_location_path,
 							&children_path,
-							children_entry
-								.metadata()
-								.await
-								.map_err(|e| FileIOError::from((&children_path, e)))?
-								.is_dir(),
+							children_metadata.is_dir(),
 						)
 						.map_err(FileSystemJobsError::from)?,
 					)
@@ -169,10 +386,10 @@ impl StatefulJob for FileCopierJob {
 				});
 			}
 
-			Ok(more_steps.into())
+			Ok((more_steps, new_metadata).into())
 		} else if &source_file_data.full_path == target_full_path {
 			// File is already here, do nothing
-			Ok(().into())
+			Ok(None.into())
 		} else {
 			match fs::metadata(target_full_path).await {
 				Ok(_) => {
@@ -195,13 +412,130 @@ impl StatefulJob for FileCopierJob {
 						target_full_path.display()
 					);
 
-					fs::copy(&source_file_data.full_path, &target_full_path)
+					let source_path = &source_file_data.full_path;
+					let source_metadata = fs::metadata(source_path)
 						.await
-						// Using the ? here because we don't want to increase the completed task
-						// count in case of file system errors
-						.map_err(|e| FileIOError::from((target_full_path, e)))?;
+						.map_err(|e| FileIOError::from((source_path, e)))?;
+					let size = source_metadata.len();
+
+					let mut new_metadata = FileCopierJobRunMetadata {
+						copied_bytes: size,
+						files_copied: 1,
+						..Default::default()
+					};
+
+					// Cloning is instant and takes no space, on filesystems that can clone files
+					// on the same filesystem, the contents are copied otherwise
+					let cloned = match reflink(source_path, target_full_path).await {
+						Ok(()) => true,
+						Err(e) if e.kind() == io::ErrorKind::Unsupported => false,
+						Err(e) => return Err(FileIOError::from((target_full_path, e)).into()),
+					};
+
+					// Using the ? here because we don't want to increase the completed task
+					// count in case of file system errors
+					if cloned {
+						trace!("Cloned {}", source_path.display());
+						new_metadata.files_cloned = 1;
+
+						// A partial copy left by an interrupted run isn't needed anymore
+						if size >= RESUMABLE_COPY_MIN_SIZE {
+							fs::remove_file(partial_copy_path(target_full_path))
+								.await
+								.ok();
+						}
+					} else if sparse::is_sparse(&source_metadata) {
+						ctx.progress_msg(job_message!(
+							"copy.copyingSparseFile",
+							path = source_path.display()
+						));
+
+						// Holes are kept by seeking over them, which a resumed copy couldn't tell
+						// apart from data that wasn't copied yet, so the copy starts over instead
+						let partial_path = partial_copy_path(target_full_path);
+						let data_size = sparse::copy_sparse(source_path, &partial_path)
+							.await
+							.map_err(|e| FileIOError::from((&partial_path, e)))?;
+						fs::rename(&partial_path, target_full_path)
+							.await
+							.map_err(|e| FileIOError::from((target_full_path, e)))?;
+
+						trace!(
+							"Copied {} bytes of data of sparse file {}",
+							data_size,
+							source_path.display()
+						);
+						new_metadata.files_sparse = 1;
+					} else if size >= RESUMABLE_COPY_MIN_SIZE {
+						if resumable_copy(ctx, source_path, target_full_path, size, run_metadata)
+							.await?
+						{
+							new_metadata.files_resumed = 1;
+						}
+					} else {
+						ctx.progress_msg(job_message!(
+							"copy.copying",
+							path = source_path.display(),
+							copied = run_metadata.copied_bytes,
+							total = run_metadata.total_bytes
+						));
+
+						fs::copy(source_path, target_full_path)
+							.await
+							.map_err(|e| FileIOError::from((target_full_path, e)))?;
+					}
 
-					Ok(().into())
+					if init.preserve_metadata {
+						new_metadata.attributes_not_preserved.add(
+							preserve_attributes(source_path, target_full_path, true)
+								.await
+								.map_err(|e| FileIOError::from((source_path, e)))?,
+						);
+					}
+
+					if init.verify {
+						let source_cas_id = match &source_file_data.file_path.cas_id {
+							Some(cas_id) => cas_id.clone(),
+							None => generate_cas_id(source_path, size)
+								.await
+								.map_err(|e| FileIOError::from((source_path, e)))?,
+						};
+						let target_cas_id = generate_cas_id(target_full_path, size)
+							.await
+							.map_err(|e| FileIOError::from((target_full_path, e)))?;
+
+						if source_cas_id != target_cas_id {
+							warn!(
+								"Removing {} as it doesn't match its source",
+								target_full_path.display()
+							);
+
+							fs::remove_file(target_full_path)
+								.await
+								.map_err(|e| FileIOError::from((target_full_path, e)))?;
+
+							return Ok((
+								vec![],
+								FileCopierJobRunMetadata::default(),
+								JobRunErrors(vec![FileSystemJobsError::CopyVerification(
+									target_full_path.clone().into_boxed_path(),
+								)
+								.to_string()]),
+							)
+								.into());
+						}
+
+						new_metadata.files_verified = 1;
+					}
+
+					journal::record(
+						&ctx.library,
+						ctx.job_id,
+						vec![FileOperation::copied(source_path, target_full_path).await],
+					)
+					.await;
+
+					Ok(new_metadata.into())
 				}
 				Err(e) => return Err(FileIOError::from((target_full_path, e)).into()),
 			}
@@ -211,8 +545,40 @@ impl StatefulJob for FileCopierJob {
 	}
 
 	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Copied {} files, {} bytes; {} cloned, {} sparse, {} verified, {} resumed",
+			metadata.files_copied,
+			metadata.copied_bytes,
+			metadata.files_cloned,
+			metadata.files_sparse,
+			metadata.files_verified,
+			metadata.files_resumed
+		);
+
 		invalidate_query!(ctx.library, "search.paths");
 
-		Ok(Some(serde_json::to_value(&state.init)?))
+		Ok(Some(json!({
+			"init": state.init,
+			"files_copied": metadata.files_copied,
+			"files_cloned": metadata.files_cloned,
+			"files_sparse": metadata.files_sparse,
+			"attributes_not_preserved": metadata.attributes_not_preserved,
+			"copy_method": copy_method(metadata.files_copied, metadata.files_cloned),
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reports_how_files_were_copied() {
+		assert_eq!(copy_method(0, 0), "stream");
+		assert_eq!(copy_method(3, 0), "stream");
+		assert_eq!(copy_method(3, 3), "reflink");
+		assert_eq!(copy_method(3, 1), "mixed");
 	}
 }
@@ -1,10 +1,13 @@
 use crate::{
 	invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunErrors, JobState,
-		JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	library::{
+		journal::{self, FileOperation},
+		Library,
 	},
-	library::Library,
 	location::file_path_helper::push_location_relative_path,
 	object::fs::{construct_target_filename, error::FileSystemJobsError},
 	prisma::{file_path, location},
@@ -37,6 +40,10 @@ pub struct FileCutterJobData {
 
 impl JobInitData for FileCutterJobInit {
 	type Job = FileCutterJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.source_location_id, self.target_location_id])
+	}
 }
 
 #[async_trait::async_trait]
@@ -47,6 +54,7 @@ impl StatefulJob for FileCutterJob {
 	type RunMetadata = ();
 
 	const NAME: &'static str = "file_cutter";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -85,7 +93,7 @@ impl StatefulJob for FileCutterJob {
 
 	async fn execute_step(
 		&self,
-		_: &WorkerContext,
+		ctx: &WorkerContext,
 		_: &Self::Init,
 		CurrentStep {
 			step: file_data, ..
@@ -125,6 +133,13 @@ impl StatefulJob for FileCutterJob {
 						.await
 						.map_err(|e| FileIOError::from((&file_data.full_path, e)))?;
 
+					journal::record(
+						&ctx.library,
+						ctx.job_id,
+						vec![FileOperation::moved(&file_data.full_path, &full_output)],
+					)
+					.await;
+
 					Ok(().into())
 				}
 
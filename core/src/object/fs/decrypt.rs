@@ -1,159 +1,600 @@
-// use crate::{
-// 	invalidate_query,
-// 	job::{
-// 		JobError, JobInitData, JobReportUpdate, JobResult, JobState, StatefulJob, WorkerContext,
-// 	},
-// 	library::Library,
-// 	location::{file_path_helper:: location::id::Type},
-// 	util::error::FileIOError,
-// };
-
-// use sd_crypto::{crypto::Decryptor, header::file::FileHeader, Protected};
-
-// use serde::{Deserialize, Serialize};
-// use specta::Type;
-// use tokio::fs::File;
-
-// use super::{get_location_path_from_location_id, get_many_files_datas, FileData, BYTES_EXT};
-// pub struct FileDecryptorJob;
-
-// // decrypt could have an option to restore metadata (and another specific option for file name? - would turn "output file" into "output path" in the UI)
-// #[derive(Serialize, Deserialize, Debug, Type, Hash)]
-// pub struct FileDecryptorJobInit {
-// 	pub location_id: location::id::Type,
-// 	pub file_path_ids: Vec<file_path::id::Type>,
-// 	pub mount_associated_key: bool,
-// 	pub password: Option<String>, // if this is set, we can assume the user chose password decryption
-// 	pub save_to_library: Option<bool>,
-// }
-
-// impl JobInitData for FileDecryptorJobInit {
-// 	type Job = FileDecryptorJob;
-// }
-
-// #[async_trait::async_trait]
-// impl StatefulJob for FileDecryptorJob {
-// 	type Init = FileDecryptorJobInit;
-// 	type Data = ();
-// 	type Step = FileData;
-
-// 	const NAME: &'static str = "file_decryptor";
-
-// 	fn new() -> Self {
-// 		Self {}
-// 	}
-
-// 	async fn init(&self, ctx: WorkerContext, state: &mut JobState<Self>) -> Result<(), JobError> {
-// 		let Library { db, .. } = &ctx.library;
-
-// 		state.steps = get_many_files_datas(
-// 			db,
-// 			get_location_path_from_location_id(db, state.init.location_id).await?,
-// 			&state.init.file_path_ids,
-// 		)
-// 		.await?
-// 		.into();
-
-// 		ctx.progress(vec![JobReportUpdate::TaskCount(state.steps.len())]);
-
-// 		Ok(())
-// 	}
-
-// 	async fn execute_step(
-// 		&self,
-// 		ctx: WorkerContext,
-// 		state: &mut JobState<Self>,
-// 	) -> Result<(), JobError> {
-// 		let step = &state.steps[0];
-// 		let key_manager = &ctx.library.key_manager;
-
-// 		// handle overwriting checks, and making sure there's enough available space
-// 		let output_path = {
-// 			let mut path = step.full_path.clone();
-// 			let extension = path.extension().map_or("decrypted", |ext| {
-// 				if ext == BYTES_EXT {
-// 					""
-// 				} else {
-// 					"decrypted"
-// 				}
-// 			});
-// 			path.set_extension(extension);
-// 			path
-// 		};
-
-// 		let mut reader = File::open(&step.full_path)
-// 			.await
-// 			.map_err(|e| FileIOError::from((&step.full_path, e)))?;
-// 		let mut writer = File::create(&output_path)
-// 			.await
-// 			.map_err(|e| FileIOError::from((output_path, e)))?;
-
-// 		let (header, aad) = FileHeader::from_reader(&mut reader).await?;
-
-// 		let master_key = if let Some(password) = state.init.password.clone() {
-// 			if let Some(save_to_library) = state.init.save_to_library {
-// 				// we can do this first, as `find_key_index` requires a successful decryption (just like `decrypt_master_key`)
-// 				let password_bytes = Protected::new(password.as_bytes().to_vec());
-
-// 				if save_to_library {
-// 					let index = header.find_key_index(password_bytes.clone()).await?;
-
-// 					// inherit the encryption algorithm from the keyslot
-// 					key_manager
-// 						.add_to_keystore(
-// 							Protected::new(password),
-// 							header.algorithm,
-// 							header.keyslots[index].hashing_algorithm,
-// 							false,
-// 							false,
-// 							Some(header.keyslots[index].salt),
-// 						)
-// 						.await?;
-// 				}
-
-// 				header.decrypt_master_key(password_bytes).await?
-// 			} else {
-// 				return Err(JobError::JobDataNotFound(String::from(
-// 					"Password decryption selected, but save to library boolean was not included",
-// 				)));
-// 			}
-// 		} else {
-// 			if state.init.mount_associated_key {
-// 				for key in key_manager.dump_keystore().iter().filter(|x| {
-// 					header
-// 						.keyslots
-// 						.iter()
-// 						.any(|k| k.content_salt == x.content_salt)
-// 				}) {
-// 					key_manager.mount(key.uuid).await.ok();
-// 				}
-// 			}
-
-// 			let keys = key_manager.enumerate_hashed_keys();
-
-// 			header.decrypt_master_key_from_prehashed(keys).await?
-// 		};
-
-// 		let decryptor = Decryptor::new(master_key, header.nonce, header.algorithm)?;
-
-// 		decryptor
-// 			.decrypt_streams(&mut reader, &mut writer, &aad)
-// 			.await?;
-
-// 		// need to decrypt preview media/metadata, and maybe add an option in the UI so the user can chosoe to restore these values
-// 		// for now this can't easily be implemented, as we don't know what the new object id for the file will be (we know the old one, but it may differ)
-
-// 		ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
-// 			state.step_number + 1,
-// 		)]);
-
-// 		Ok(())
-// 	}
-
-// 	async fn finalize(&self, ctx: WorkerContext, state: &mut JobState<Self>) -> JobResult {
-// 		invalidate_query!(ctx.library, "search.paths");
-
-// 		// mark job as successful
-// 		Ok(Some(serde_json::to_value(&state.init)?))
-// 	}
-// }
+//! Decrypting files written by the [encryptor](super::encrypt). The key that opens a file is the
+//! one its object is linked to, mounted on the way if it isn't yet; files without one are tried
+//! with the mounted keys and the keys of the keystore made for them. Every block is authenticated
+//! as it's decrypted, a file that was altered is never written out whole.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{ensure_sub_path_is_directory, push_location_relative_path},
+	prisma::{file_path, key, location, object},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	ffi::OsStr,
+	path::{Path, PathBuf},
+	pin::Pin,
+	str::FromStr,
+	task::{ready, Context, Poll},
+};
+
+use sd_crypto::{crypto::Decryptor, header::file::FileHeader, types::Key};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncRead, ReadBuf},
+};
+use tracing::{info, trace, warn};
+use uuid::Uuid;
+
+use super::{
+	construct_target_filename,
+	encrypt::{EncryptedFileMetadata, EncryptionTarget},
+	error::FileSystemJobsError,
+	get_location_path_from_location_id, get_many_files_datas, index_new_file, FileData, BYTES_EXT,
+};
+
+/// How often the progress of decrypting a file is reported, in bytes read
+const PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+pub struct FileDecryptorJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileDecryptorJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_ids: Vec<file_path::id::Type>,
+	pub target: EncryptionTarget,
+}
+
+impl JobInitData for FileDecryptorJobInit {
+	type Job = FileDecryptorJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileDecryptorJobData {
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	/// Where the decrypted files go, next to their encrypted files when not set
+	target_directory_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileDecryptorJobRunMetadata {
+	files_decrypted: u64,
+	bytes_decrypted: u64,
+}
+
+impl JobRunMetadata for FileDecryptorJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.files_decrypted += new_data.files_decrypted;
+		self.bytes_decrypted += new_data.bytes_decrypted;
+	}
+}
+
+/// Reports how many bytes were read so far, every [`PROGRESS_INTERVAL`] bytes
+struct ProgressReader<R, F> {
+	inner: R,
+	read: u64,
+	on_progress: F,
+}
+
+impl<R: AsyncRead + Unpin, F: FnMut(u64) + Unpin> AsyncRead for ProgressReader<R, F> {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let filled = buf.filled().len();
+		ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+
+		let read = self.read + (buf.filled().len() - filled) as u64;
+		if read / PROGRESS_INTERVAL != self.read / PROGRESS_INTERVAL {
+			(self.on_progress)(read);
+		}
+		self.read = read;
+
+		Poll::Ready(Ok(()))
+	}
+}
+
+/// The keys to try on a file's header. The key linked to the file's object is the only one when
+/// there's one, otherwise the keys of the keystore made for the file are mounted and all the
+/// mounted keys are tried.
+async fn hashed_keys_for(
+	library: &Library,
+	header: &FileHeader,
+	key_id: Option<key::id::Type>,
+) -> Result<Vec<Key>, JobError> {
+	let Library {
+		db, key_manager, ..
+	} = library;
+	let mounted = key_manager.get_mounted_uuids();
+
+	if let Some(key_id) = key_id {
+		if let Some(uuid) = db
+			.key()
+			.find_unique(key::id::equals(key_id))
+			.select(key::select!({ uuid }))
+			.exec()
+			.await?
+			.and_then(|key| Uuid::from_str(&key.uuid).ok())
+		{
+			if !mounted.contains(&uuid) {
+				key_manager.mount(uuid).await?;
+			}
+
+			return Ok(vec![key_manager.access_keymount(uuid).await?.hashed_key]);
+		}
+	}
+
+	for stored_key in key_manager
+		.dump_keystore()
+		.into_iter()
+		.filter(|stored_key| {
+			!mounted.contains(&stored_key.uuid)
+				&& header
+					.keyslots
+					.iter()
+					.any(|keyslot| keyslot.content_salt == stored_key.content_salt)
+		}) {
+		if let Err(e) = key_manager.mount(stored_key.uuid).await {
+			warn!("Failed to mount key {}: {e}", stored_key.uuid);
+		}
+	}
+
+	Ok(key_manager.enumerate_hashed_keys())
+}
+
+/// The name of the original file as kept in the header, or the name of the encrypted file without
+/// its [`BYTES_EXT`] for headers without one
+async fn restored_name(
+	header: &FileHeader,
+	hashed_keys: Vec<Key>,
+	encrypted_name: &str,
+) -> Result<String, JobError> {
+	let fallback = || {
+		encrypted_name
+			.strip_suffix(BYTES_EXT)
+			.filter(|name| !name.is_empty())
+			.map_or_else(
+				|| format!("{encrypted_name}.decrypted"),
+				ToString::to_string,
+			)
+	};
+
+	match header
+		.decrypt_metadata_from_prehashed::<EncryptedFileMetadata>(hashed_keys)
+		.await
+	{
+		// A name with separators would place the file elsewhere
+		Ok(EncryptedFileMetadata { name })
+			if Path::new(&name).file_name() == Some(OsStr::new(&name)) =>
+		{
+			Ok(name)
+		}
+		Ok(EncryptedFileMetadata { name }) => {
+			warn!("Ignoring the invalid file name {name:?} kept in an encrypted file");
+			Ok(fallback())
+		}
+		Err(sd_crypto::Error::NoMetadata) => Ok(fallback()),
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Decrypts what's left of `reader` after the header to `target`. It fails with
+/// [`sd_crypto::Error::Decrypt`] as soon as a block doesn't authenticate.
+async fn decrypt_to(
+	reader: impl AsyncRead + Unpin + Send,
+	target: &Path,
+	master_key: Key,
+	header: &FileHeader,
+	aad: &[u8],
+) -> Result<(), JobError> {
+	let mut writer = File::create(target)
+		.await
+		.map_err(|e| FileIOError::from((target, e)))?;
+
+	Decryptor::new(master_key, header.nonce, header.algorithm)?
+		.decrypt_streams(reader, &mut writer, aad)
+		.await?;
+
+	writer
+		.sync_all()
+		.await
+		.map_err(|e| FileIOError::from((target, e)).into())
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileDecryptorJob {
+	type Init = FileDecryptorJobInit;
+	type Data = FileDecryptorJobData;
+	type Step = FileData;
+	type RunMetadata = FileDecryptorJobRunMetadata;
+
+	const NAME: &'static str = "file_decryptor";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &ctx.library;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+
+		let (target_location_id, target_location_path, target_directory_path) = match &init.target {
+			EncryptionTarget::InPlace => (init.location_id, location_path.clone(), None),
+			EncryptionTarget::Directory {
+				location_id,
+				relative_directory_path,
+			} => {
+				let target_location_path =
+					get_location_path_from_location_id(db, *location_id).await?;
+				ensure_sub_path_is_directory(&target_location_path, relative_directory_path)
+					.await
+					.map_err(FileSystemJobsError::from)?;
+
+				let target_directory_path = push_location_relative_path(
+					target_location_path.clone(),
+					relative_directory_path,
+				);
+
+				(
+					*location_id,
+					target_location_path,
+					Some(target_directory_path),
+				)
+			}
+		};
+
+		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		*data = Some(FileDecryptorJobData {
+			target_location_id,
+			target_location_path,
+			target_directory_path,
+		});
+
+		Ok((FileDecryptorJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, .. } = &ctx.library;
+		let FileData {
+			file_path,
+			full_path,
+		} = step;
+
+		if maybe_missing(file_path.is_dir, "file_path.is_dir")? {
+			warn!("Skipping {} as it's a directory", full_path.display());
+
+			return Ok(JobRunErrors(vec![format!(
+				"{} wasn't decrypted, only files are",
+				full_path.display()
+			)])
+			.into());
+		}
+
+		let mut reader = File::open(full_path)
+			.await
+			.map_err(|e| FileIOError::from((full_path, e)))?;
+		let size = reader
+			.metadata()
+			.await
+			.map_err(|e| FileIOError::from((full_path, e)))?
+			.len();
+
+		let (header, aad) = match FileHeader::from_reader(&mut reader).await {
+			Ok(header) => header,
+			Err(e) => {
+				warn!("Skipping {}: {e}", full_path.display());
+
+				return Ok(JobRunErrors(vec![format!(
+					"{} isn't an encrypted file: {e}",
+					full_path.display()
+				)])
+				.into());
+			}
+		};
+
+		let hashed_keys = hashed_keys_for(
+			&ctx.library,
+			&header,
+			file_path.object.as_ref().and_then(|object| object.key_id),
+		)
+		.await?;
+
+		let master_key = match header
+			.decrypt_master_key_from_prehashed(hashed_keys.clone())
+			.await
+		{
+			Ok(master_key) => master_key,
+			Err(sd_crypto::Error::IncorrectPassword) => {
+				return Ok(JobRunErrors(vec![format!(
+					"{} wasn't decrypted, none of the mounted keys opens it",
+					full_path.display()
+				)])
+				.into())
+			}
+			Err(e) => return Err(e.into()),
+		};
+
+		let name = restored_name(
+			&header,
+			hashed_keys,
+			&construct_target_filename(step, &None)?,
+		)
+		.await?;
+		let output_path = match &data.target_directory_path {
+			Some(target_directory_path) => target_directory_path.join(name),
+			None => full_path.with_file_name(name),
+		};
+
+		match fs::metadata(&output_path).await {
+			Ok(_) => {
+				warn!(
+					"Skipping {} as it would be overwritten",
+					output_path.display()
+				);
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					output_path.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((&output_path, e)).into()),
+		}
+
+		ctx.progress_msg(job_message!(
+			"crypto.decrypting",
+			path = full_path.display()
+		));
+
+		// Written aside first, so a file that fails to authenticate halfway is never left behind
+		let mut part_path = output_path.clone().into_os_string();
+		part_path.push(".part");
+		let part_path = PathBuf::from(part_path);
+
+		let reader = ProgressReader {
+			inner: reader,
+			read: 0,
+			on_progress: |read| {
+				ctx.progress_msg(job_message!(
+					"crypto.decryptingProgress",
+					path = full_path.display(),
+					percentage = read * 100 / size.max(1)
+				))
+			},
+		};
+
+		if let Err(e) = decrypt_to(reader, &part_path, master_key, &header, &aad).await {
+			if let Err(e) = fs::remove_file(&part_path).await {
+				warn!(
+					"Failed to remove the partly decrypted file {}: {e}",
+					part_path.display()
+				);
+			}
+
+			return match e {
+				JobError::CryptoError(sd_crypto::Error::Decrypt) => {
+					Ok(JobRunErrors(vec![format!(
+						"{} wasn't decrypted, it was altered or is damaged",
+						full_path.display()
+					)])
+					.into())
+				}
+				e => Err(e),
+			};
+		}
+
+		fs::rename(&part_path, &output_path)
+			.await
+			.map_err(|e| FileIOError::from((&output_path, e)))?;
+
+		trace!(
+			"Decrypted {} to {}",
+			full_path.display(),
+			output_path.display()
+		);
+
+		if matches!(init.target, EncryptionTarget::InPlace) {
+			fs::remove_file(full_path)
+				.await
+				.map_err(|e| FileIOError::from((full_path, e)))?;
+
+			db.file_path()
+				.delete(file_path::id::equals(file_path.id))
+				.exec()
+				.await?;
+		}
+
+		let object_id = index_new_file(
+			&ctx.library,
+			data.target_location_id,
+			&data.target_location_path,
+			&output_path,
+		)
+		.await?;
+
+		db.object()
+			.update(
+				object::id::equals(object_id),
+				vec![object::key::disconnect()],
+			)
+			.exec()
+			.await?;
+
+		Ok(FileDecryptorJobRunMetadata {
+			files_decrypted: 1,
+			bytes_decrypted: size,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Decrypted {} files, {} bytes",
+			metadata.files_decrypted, metadata.bytes_decrypted
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{super::encrypt::encrypt_file, *};
+
+	use sd_crypto::{
+		primitives::KEY_LEN,
+		types::{HashingAlgorithm, Params, Salt},
+	};
+	use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+	async fn encrypted_file(dir: &Path, hashed_key: Key) -> PathBuf {
+		let source = dir.join("report.pdf");
+		let target = dir.join("report.pdf.bytes");
+		fs::write(&source, vec![42u8; 3 * 1024 * 1024])
+			.await
+			.unwrap();
+
+		encrypt_file(
+			&source,
+			&target,
+			hashed_key,
+			HashingAlgorithm::Argon2id(Params::Standard),
+			Salt::generate(),
+			&EncryptedFileMetadata {
+				name: "report.pdf".to_string(),
+			},
+		)
+		.await
+		.unwrap();
+
+		target
+	}
+
+	#[tokio::test]
+	async fn restores_the_file_and_its_name() {
+		let dir = tempfile::tempdir().unwrap();
+		let hashed_key = Key::new([3; KEY_LEN]);
+		let encrypted = encrypted_file(dir.path(), hashed_key.clone()).await;
+
+		let mut reader = File::open(&encrypted).await.unwrap();
+		let (header, aad) = FileHeader::from_reader(&mut reader).await.unwrap();
+
+		assert!(matches!(
+			header
+				.decrypt_master_key_from_prehashed(vec![Key::new([4; KEY_LEN])])
+				.await,
+			Err(sd_crypto::Error::IncorrectPassword)
+		));
+
+		let master_key = header
+			.decrypt_master_key_from_prehashed(vec![hashed_key.clone()])
+			.await
+			.unwrap();
+		assert_eq!(
+			restored_name(&header, vec![hashed_key], "report.pdf.bytes")
+				.await
+				.unwrap(),
+			"report.pdf"
+		);
+
+		let target = dir.path().join("decrypted.pdf");
+		decrypt_to(&mut reader, &target, master_key, &header, &aad)
+			.await
+			.unwrap();
+		assert_eq!(fs::read(target).await.unwrap(), vec![42u8; 3 * 1024 * 1024]);
+	}
+
+	#[tokio::test]
+	async fn rejects_an_altered_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let hashed_key = Key::new([5; KEY_LEN]);
+		let encrypted = encrypted_file(dir.path(), hashed_key.clone()).await;
+
+		let mut file = fs::OpenOptions::new()
+			.write(true)
+			.open(&encrypted)
+			.await
+			.unwrap();
+		file.seek(io::SeekFrom::End(-100)).await.unwrap();
+		file.write_all(&[0]).await.unwrap();
+		drop(file);
+
+		let mut reader = File::open(&encrypted).await.unwrap();
+		let (header, aad) = FileHeader::from_reader(&mut reader).await.unwrap();
+		let master_key = header
+			.decrypt_master_key_from_prehashed(vec![hashed_key])
+			.await
+			.unwrap();
+
+		assert!(matches!(
+			decrypt_to(
+				&mut reader,
+				&dir.path().join("decrypted.pdf"),
+				master_key,
+				&header,
+				&aad
+			)
+			.await,
+			Err(JobError::CryptoError(sd_crypto::Error::Decrypt))
+		));
+	}
+
+	#[tokio::test]
+	async fn falls_back_to_the_encrypted_name() {
+		let dir = tempfile::tempdir().unwrap();
+		let hashed_key = Key::new([6; KEY_LEN]);
+		let encrypted = encrypted_file(dir.path(), hashed_key.clone()).await;
+
+		let mut reader = File::open(&encrypted).await.unwrap();
+		let (mut header, _) = FileHeader::from_reader(&mut reader).await.unwrap();
+		header.metadata = None;
+
+		assert_eq!(
+			restored_name(&header, vec![hashed_key.clone()], "report.pdf.bytes")
+				.await
+				.unwrap(),
+			"report.pdf"
+		);
+		assert_eq!(
+			restored_name(&header, vec![hashed_key], "report")
+				.await
+				.unwrap(),
+			"report.decrypted"
+		);
+	}
+}
@@ -0,0 +1,500 @@
+//! Replacing the copies of a file with links to a single one, to get their space back. Files are
+//! duplicates when they're paths of the same object, and they're only replaced after their whole
+//! contents were compared, on the same filesystem.
+//!
+//! Hard links make every path the same file, a change through one of them shows in all of them.
+//! A location can't index the same file twice, so they're only made across locations. Reflinks
+//! (copy-on-write clones) stay separate files sharing their blocks until one is written to, but
+//! only some filesystems (APFS, Btrfs, XFS) have them.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{
+		file_path_for_deduplicator, get_inode_and_device, IsolatedFilePathData, MetadataExt,
+	},
+	object::validation::hash::file_checksum,
+	prisma::{file_path, location, object},
+	sync,
+	util::{
+		db::{chain_optional_iter, keyset_pages, maybe_missing},
+		error::FileIOError,
+	},
+};
+
+use std::{
+	collections::{BTreeSet, HashMap},
+	fs::Metadata,
+	path::{Path, PathBuf},
+	pin::pin,
+};
+
+use chrono::{DateTime, Local};
+use filetime::{set_file_mtime, FileTime};
+use futures::TryStreamExt;
+use prisma_client_rust::Direction;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{debug, info, trace};
+
+use super::{error::FileSystemJobsError, reflink::reflink};
+
+/// The file paths read at once to find the objects with copies
+const PAGE_SIZE: i64 = 10_000;
+
+file_path::select!(file_path_for_copies { id object_id device });
+
+pub struct FileDeduplicatorJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMethod {
+	Hardlink,
+	Reflink,
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileDeduplicatorJobInit {
+	/// The locations whose duplicates are replaced, all the locations of this device when empty
+	pub location_ids: Vec<location::id::Type>,
+	pub method: LinkMethod,
+}
+
+impl JobInitData for FileDeduplicatorJobInit {
+	type Job = FileDeduplicatorJob;
+
+	fn locations(&self) -> JobLocations {
+		// None of them means all the locations of this device
+		if self.location_ids.is_empty() {
+			JobLocations::All
+		} else {
+			JobLocations::Some(self.location_ids.clone())
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileDeduplicatorJobData {
+	/// The locations of this device among the chosen ones
+	location_ids: Vec<location::id::Type>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileDeduplicatorJobRunMetadata {
+	replaced: u64,
+	bytes_reclaimed: u64,
+	/// Paths of the same object whose contents weren't the same after all
+	different: u64,
+	/// Copies the filesystem, or the index, can't have linked
+	unlinkable: u64,
+}
+
+impl JobRunMetadata for FileDeduplicatorJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.replaced += new_data.replaced;
+		self.bytes_reclaimed += new_data.bytes_reclaimed;
+		self.different += new_data.different;
+		self.unlinkable += new_data.unlinkable;
+	}
+}
+
+/// Replaces `duplicate` with a link to `original`. The link is made aside and renamed over the
+/// duplicate, which is left as it was when it fails.
+async fn replace_with_link(
+	original: &Path,
+	duplicate: &Path,
+	duplicate_metadata: &Metadata,
+	method: LinkMethod,
+) -> io::Result<()> {
+	let mut link_path = duplicate.as_os_str().to_owned();
+	link_path.push(".sd-dedup");
+	let link_path = PathBuf::from(link_path);
+
+	match method {
+		LinkMethod::Hardlink => fs::hard_link(original, &link_path).await?,
+		LinkMethod::Reflink => reflink(original, &link_path).await?,
+	}
+
+	let res = async {
+		// A clone is a file of its own, it keeps what the duplicate had
+		if method == LinkMethod::Reflink {
+			fs::set_permissions(&link_path, duplicate_metadata.permissions()).await?;
+			set_file_mtime(
+				&link_path,
+				FileTime::from_last_modification_time(duplicate_metadata),
+			)?;
+		}
+
+		fs::rename(&link_path, duplicate).await
+	}
+	.await;
+
+	if res.is_err() {
+		fs::remove_file(&link_path).await.ok();
+	}
+
+	res
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileDeduplicatorJob {
+	type Init = FileDeduplicatorJobInit;
+	type Data = FileDeduplicatorJobData;
+	type Step = object::id::Type;
+	type RunMetadata = FileDeduplicatorJobRunMetadata;
+
+	const NAME: &'static str = "file_deduplicator";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library {
+			db, node_local_id, ..
+		} = &ctx.library;
+
+		let location_ids = db
+			.location()
+			.find_many(chain_optional_iter(
+				[location::node_id::equals(Some(*node_local_id))],
+				[(!init.location_ids.is_empty())
+					.then(|| location::id::in_vec(init.location_ids.clone()))],
+			))
+			.select(location::select!({ id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|location| location.id)
+			.collect::<Vec<_>>();
+
+		// An object with more than one path on the same device has copies to replace
+		let mut copies = HashMap::<_, usize>::new();
+		{
+			let mut pages = pin!(keyset_pages(
+				PAGE_SIZE,
+				|file_path: &file_path_for_copies::Data| file_path.id,
+				|after, take| {
+					db.file_path()
+						.find_many(chain_optional_iter(
+							[
+								file_path::location_id::in_vec(location_ids.clone()),
+								file_path::is_dir::equals(Some(false)),
+								file_path::object_id::not(None),
+								file_path::device::not(None),
+							],
+							[after.map(file_path::id::gt)],
+						))
+						.order_by(file_path::id::order(Direction::Asc))
+						.take(take)
+						.select(file_path_for_copies::select())
+						.exec()
+				},
+			));
+			while let Some(file_paths) = pages.try_next().await? {
+				for file_path in file_paths {
+					*copies
+						.entry((file_path.object_id, file_path.device))
+						.or_default() += 1;
+				}
+			}
+		}
+
+		let steps = copies
+			.into_iter()
+			.filter(|(_, count)| *count > 1)
+			.filter_map(|((object_id, _), _)| object_id)
+			.collect::<BTreeSet<_>>()
+			.into_iter()
+			.collect::<Vec<_>>();
+
+		*data = Some(FileDeduplicatorJobData { location_ids });
+
+		Ok((FileDeduplicatorJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep {
+			step: object_id, ..
+		}: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, sync, .. } = &ctx.library;
+
+		let mut run_metadata = FileDeduplicatorJobRunMetadata::default();
+		let mut errors = vec![];
+
+		// The paths that exist, with their files, grouped by the device they're on
+		let mut devices = HashMap::<_, Vec<_>>::new();
+		for file_path in db
+			.file_path()
+			.find_many(vec![
+				file_path::object_id::equals(Some(*object_id)),
+				file_path::location_id::in_vec(data.location_ids.clone()),
+				file_path::is_dir::equals(Some(false)),
+			])
+			.order_by(file_path::id::order(Direction::Asc))
+			.select(file_path_for_deduplicator::select())
+			.exec()
+			.await?
+		{
+			let location_path = maybe_missing(
+				file_path
+					.location
+					.as_ref()
+					.and_then(|location| location.path.as_ref()),
+				"location.path",
+			)?;
+			let full_path =
+				Path::new(location_path).join(IsolatedFilePathData::try_from(&file_path)?);
+
+			match fs::metadata(&full_path).await {
+				Ok(metadata) => {
+					let (inode, device) =
+						get_inode_and_device(&metadata).map_err(FileSystemJobsError::from)?;
+
+					devices
+						.entry(device)
+						.or_default()
+						.push((file_path, full_path, metadata, inode));
+				}
+				// The indexer takes care of files that are gone
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+				Err(e) => return Err(FileIOError::from((full_path, e)).into()),
+			}
+		}
+
+		for (device, mut copies) in devices.into_iter().filter(|(_, copies)| copies.len() > 1) {
+			// The oldest path is kept, the others become links to it
+			let (original, original_path, original_metadata, original_inode) = copies.remove(0);
+			let mut original_checksum = None;
+
+			for (duplicate, duplicate_path, duplicate_metadata, duplicate_inode) in copies {
+				if duplicate_inode == original_inode {
+					// Already the same file
+					continue;
+				}
+
+				if duplicate_metadata.len() != original_metadata.len() {
+					run_metadata.different += 1;
+					continue;
+				}
+
+				if init.method == LinkMethod::Hardlink
+					&& (duplicate.location_id == original.location_id
+						|| db
+							.file_path()
+							.count(vec![
+								file_path::location_id::equals(duplicate.location_id),
+								file_path::inode::equals(Some(
+									original_inode.to_le_bytes().to_vec(),
+								)),
+								file_path::device::equals(Some(device.to_le_bytes().to_vec())),
+							])
+							.exec()
+							.await? > 0)
+				{
+					trace!(
+						"Skipping {} as its location already has the file it would link to",
+						duplicate_path.display()
+					);
+					run_metadata.unlinkable += 1;
+					continue;
+				}
+
+				ctx.progress_msg(job_message!(
+					"dedup.comparing",
+					path = duplicate_path.display()
+				));
+
+				if original_checksum.is_none() {
+					original_checksum = Some(
+						file_checksum(&original_path)
+							.await
+							.map_err(|e| FileIOError::from((&original_path, e)))?,
+					);
+				}
+				let duplicate_checksum = file_checksum(&duplicate_path)
+					.await
+					.map_err(|e| FileIOError::from((&duplicate_path, e)))?;
+				if Some(&duplicate_checksum) != original_checksum.as_ref() {
+					debug!(
+						"{} and {} have the same object but different contents",
+						original_path.display(),
+						duplicate_path.display()
+					);
+					run_metadata.different += 1;
+					continue;
+				}
+
+				match replace_with_link(
+					&original_path,
+					&duplicate_path,
+					&duplicate_metadata,
+					init.method,
+				)
+				.await
+				{
+					Ok(()) => {}
+					Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+						trace!("Can't link {}: {e}", duplicate_path.display());
+						run_metadata.unlinkable += 1;
+						continue;
+					}
+					Err(e) => {
+						errors.push(format!(
+							"{} wasn't replaced by a link to {}: {e}",
+							duplicate_path.display(),
+							original_path.display()
+						));
+						continue;
+					}
+				}
+
+				trace!(
+					"Replaced {} by a link to {}",
+					duplicate_path.display(),
+					original_path.display()
+				);
+
+				// The path is another file now, with another inode and maybe another date
+				let metadata = fs::metadata(&duplicate_path)
+					.await
+					.map_err(|e| FileIOError::from((&duplicate_path, e)))?;
+				let (inode, _) =
+					get_inode_and_device(&metadata).map_err(FileSystemJobsError::from)?;
+				let date_modified = DateTime::<Local>::from(metadata.modified_or_now()).into();
+
+				let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+					(
+						(file_path::inode::NAME, json!(inode.to_le_bytes())),
+						file_path::inode::set(Some(inode.to_le_bytes().into())),
+					),
+					(
+						(file_path::date_modified::NAME, json!(date_modified)),
+						file_path::date_modified::set(Some(date_modified)),
+					),
+				]
+				.into_iter()
+				.unzip();
+
+				sync.write_ops(
+					db,
+					(
+						sync_params
+							.into_iter()
+							.map(|(field, value)| {
+								sync.shared_update(
+									sync::file_path::SyncId {
+										pub_id: duplicate.pub_id.clone(),
+									},
+									field,
+									value,
+								)
+							})
+							.collect(),
+						db.file_path().update(
+							file_path::pub_id::equals(duplicate.pub_id.clone()),
+							db_params,
+						),
+					),
+				)
+				.await?;
+
+				run_metadata.replaced += 1;
+				run_metadata.bytes_reclaimed += duplicate_metadata.len();
+			}
+		}
+
+		Ok((vec![], run_metadata, JobRunErrors(errors)).into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Replaced {} duplicates by links, reclaiming {} bytes ({} had different contents, {} couldn't be linked)",
+			metadata.replaced, metadata.bytes_reclaimed, metadata.different, metadata.unlinkable
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+
+		Ok(Some(json!({
+			"init": state.init,
+			"replaced": metadata.replaced,
+			"bytes_reclaimed": metadata.bytes_reclaimed,
+			"different": metadata.different,
+			"unlinkable": metadata.unlinkable,
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	async fn copies(dir: &Path) -> (PathBuf, PathBuf, Metadata) {
+		let original = dir.join("holiday.mov");
+		let duplicate = dir.join("holiday (1).mov");
+		fs::write(&original, b"same frames").await.unwrap();
+		fs::write(&duplicate, b"same frames").await.unwrap();
+		let metadata = fs::metadata(&duplicate).await.unwrap();
+
+		(original, duplicate, metadata)
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn replaces_a_copy_with_a_hard_link() {
+		use std::os::unix::fs::MetadataExt;
+
+		let dir = tempfile::tempdir().unwrap();
+		let (original, duplicate, metadata) = copies(dir.path()).await;
+
+		replace_with_link(&original, &duplicate, &metadata, LinkMethod::Hardlink)
+			.await
+			.unwrap();
+
+		assert_eq!(
+			fs::metadata(&original).await.unwrap().ino(),
+			fs::metadata(&duplicate).await.unwrap().ino()
+		);
+		assert_eq!(fs::read(&duplicate).await.unwrap(), b"same frames");
+		assert!(fs::metadata(dir.path().join("holiday (1).mov.sd-dedup"))
+			.await
+			.is_err());
+	}
+
+	#[tokio::test]
+	async fn keeps_the_copy_when_it_cant_be_cloned() {
+		let dir = tempfile::tempdir().unwrap();
+		let (original, duplicate, metadata) = copies(dir.path()).await;
+
+		match replace_with_link(&original, &duplicate, &metadata, LinkMethod::Reflink).await {
+			Ok(()) => {}
+			Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+		}
+
+		// Whether it was cloned or not, the path has the same contents and nothing is left aside
+		assert_eq!(fs::read(&duplicate).await.unwrap(), b"same frames");
+		assert!(fs::metadata(dir.path().join("holiday (1).mov.sd-dedup"))
+			.await
+			.is_err());
+	}
+}
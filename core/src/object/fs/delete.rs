@@ -1,22 +1,31 @@
 use crate::{
 	invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobState, JobStepOutput,
-		StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	library::{
+		activity::{self, ActivityKind, MASS_DELETION_THRESHOLD},
+		journal::{self, FileOperation},
+		Library,
 	},
-	library::Library,
 	prisma::{file_path, location},
 	util::{db::maybe_missing, error::FileIOError},
 };
 
-use std::hash::Hash;
+use std::{hash::Hash, path::PathBuf};
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use specta::Type;
 use tokio::{fs, io};
 use tracing::warn;
 
-use super::{get_location_path_from_location_id, get_many_files_datas, FileData};
+use super::{
+	error::FileSystemJobsError, get_location_path_from_location_id, get_many_files_datas, os_trash,
+	FileData,
+};
 
 pub struct FileDeleterJob {}
 
@@ -24,20 +33,34 @@ pub struct FileDeleterJob {}
 pub struct FileDeleterJobInit {
 	pub location_id: location::id::Type,
 	pub file_path_ids: Vec<file_path::id::Type>,
+	/// Deletes the files for good, instead of moving them to the trash of the operating system
+	#[serde(default)]
+	#[specta(optional)]
+	pub permanent: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileDeleterJobData {
+	location_path: PathBuf,
 }
 
 impl JobInitData for FileDeleterJobInit {
 	type Job = FileDeleterJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
 }
 
 #[async_trait::async_trait]
 impl StatefulJob for FileDeleterJob {
 	type Init = FileDeleterJobInit;
-	type Data = ();
+	type Data = FileDeleterJobData;
 	type Step = FileData;
 	type RunMetadata = ();
 
 	const NAME: &'static str = "file_deleter";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -51,15 +74,11 @@ impl StatefulJob for FileDeleterJob {
 	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
 		let Library { db, .. } = &ctx.library;
 
-		let steps = get_many_files_datas(
-			db,
-			get_location_path_from_location_id(db, init.location_id).await?,
-			&init.file_path_ids,
-		)
-		.await?;
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
 
-		// Must fill in the data, otherwise the job will not run
-		*data = Some(());
+		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		*data = Some(FileDeleterJobData { location_path });
 
 		Ok(steps.into())
 	}
@@ -67,20 +86,63 @@ impl StatefulJob for FileDeleterJob {
 	async fn execute_step(
 		&self,
 		ctx: &WorkerContext,
-		_: &Self::Init,
+		init: &Self::Init,
 		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
-		_: &Self::Data,
+		data: &Self::Data,
 		_: &Self::RunMetadata,
 	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
 		// need to handle stuff such as querying prisma for all paths of a file, and deleting all of those if requested (with a checkbox in the ui)
 		// maybe a files.countOccurances/and or files.getPath(location_id, path_id) to show how many of these files would be deleted (and where?)
 
-		match if maybe_missing(step.file_path.is_dir, "file_path.is_dir")? {
-			fs::remove_dir_all(&step.full_path).await
-		} else {
-			fs::remove_file(&step.full_path).await
-		} {
-			Ok(()) => { /*	Everything is awesome! */ }
+		let is_dir = maybe_missing(step.file_path.is_dir, "file_path.is_dir")?;
+
+		match fs::symlink_metadata(&step.full_path).await {
+			Ok(_) if !init.permanent => {
+				let trash_path = os_trash::trash(&step.full_path)
+					.await
+					.map_err(FileSystemJobsError::from)?;
+
+				let original_path = step
+					.full_path
+					.strip_prefix(&data.location_path)
+					.unwrap_or(&step.full_path);
+
+				ctx.library
+					.db
+					.trashed_file()
+					.create(
+						location::id::equals(init.location_id),
+						original_path.to_string_lossy().to_string(),
+						trash_path.to_string_lossy().to_string(),
+						is_dir,
+						Utc::now().into(),
+						vec![],
+					)
+					.exec()
+					.await?;
+
+				journal::record(
+					&ctx.library,
+					ctx.job_id,
+					vec![FileOperation::trashed(&step.full_path, trash_path)],
+				)
+				.await;
+			}
+			Ok(_) => {
+				if is_dir {
+					fs::remove_dir_all(&step.full_path).await
+				} else {
+					fs::remove_file(&step.full_path).await
+				}
+				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
+
+				journal::record(
+					&ctx.library,
+					ctx.job_id,
+					vec![FileOperation::deleted(&step.full_path)],
+				)
+				.await;
+			}
 			Err(e) if e.kind() == io::ErrorKind::NotFound => {
 				warn!(
 					"File not found in the file system, will remove from database: {}",
@@ -102,7 +164,22 @@ impl StatefulJob for FileDeleterJob {
 	}
 
 	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		if state.init.file_path_ids.len() >= MASS_DELETION_THRESHOLD {
+			activity::record(
+				&ctx.library,
+				ActivityKind::FilesDeleted,
+				json!({
+					"location_id": state.init.location_id,
+					"count": state.init.file_path_ids.len(),
+				}),
+			)
+			.await;
+		}
+
 		invalidate_query!(ctx.library, "search.paths");
+		if !state.init.permanent {
+			invalidate_query!(ctx.library, "files.trashed");
+		}
 
 		Ok(Some(serde_json::to_value(&state.init)?))
 	}
@@ -1,261 +1,436 @@
-// use crate::{
-// 	invalidate_query,
-// 	job::*,
-// 	library::Library,
-// 	location::{file_path_helper:: location::id::Type},
-// 	util::error::{FileIOError, NonUtf8PathError},
-// };
-
-// use sd_crypto::{
-// 	crypto::Encryptor,
-// 	header::{file::FileHeader, keyslot::Keyslot},
-// 	primitives::{LATEST_FILE_HEADER, LATEST_KEYSLOT, LATEST_METADATA, LATEST_PREVIEW_MEDIA},
-// 	types::{Algorithm, Key},
-// };
-
-// use chrono::FixedOffset;
-// use serde::{Deserialize, Serialize};
-// use specta::Type;
-// use tokio::{
-// 	fs::{self, File},
-// 	io,
-// };
-// use tracing::{error, warn};
-// use uuid::Uuid;
-
-// use super::{
-// 	error::FileSystemJobsError, get_location_path_from_location_id, get_many_files_datas, FileData,
-// 	BYTES_EXT,
-// };
-
-// pub struct FileEncryptorJob;
-
-// #[derive(Serialize, Deserialize, Type, Hash)]
-// pub struct FileEncryptorJobInit {
-// 	pub location_id: location::id::Type,
-// 	pub file_path_ids: Vec<file_path::id::Type>,
-// 	pub key_uuid: Uuid,
-// 	pub algorithm: Algorithm,
-// 	pub metadata: bool,
-// 	pub preview_media: bool,
-// }
-
-// #[derive(Serialize, Deserialize)]
-// pub struct Metadata {
-// 	pub file_path_id: file_path::id::Type,
-// 	pub name: String,
-// 	pub hidden: bool,
-// 	pub favorite: bool,
-// 	pub important: bool,
-// 	pub note: Option<String>,
-// 	pub date_created: chrono::DateTime<FixedOffset>,
-// }
-
-// impl JobInitData for FileEncryptorJobInit {
-// 	type Job = FileEncryptorJob;
-// }
-
-// #[async_trait::async_trait]
-// impl StatefulJob for FileEncryptorJob {
-// 	type Init = FileEncryptorJobInit;
-// 	type Data = ();
-// 	type Step = FileData;
-
-// 	const NAME: &'static str = "file_encryptor";
-
-// 	fn new() -> Self {
-// 		Self {}
-// 	}
-
-// 	async fn init(&self, ctx: WorkerContext, state: &mut JobState<Self>) -> Result<(), JobError> {
-// 		let Library { db, .. } = &ctx.library;
-
-// 		state.steps = get_many_files_datas(
-// 			db,
-// 			get_location_path_from_location_id(db, state.init.location_id).await?,
-// 			&state.init.file_path_ids,
-// 		)
-// 		.await?
-// 		.into();
-
-// 		ctx.progress(vec![JobReportUpdate::TaskCount(state.steps.len())]);
-
-// 		Ok(())
-// 	}
-
-// 	async fn execute_step(
-// 		&self,
-// 		ctx: WorkerContext,
-// 		state: &mut JobState<Self>,
-// 	) -> Result<(), JobError> {
-// 		let step = &state.steps[0];
-
-// 		let Library { key_manager, .. } = &ctx.library;
-
-// 		if !step.file_path.is_dir {
-// 			// handle overwriting checks, and making sure there's enough available space
-
-// 			let user_key = key_manager
-// 				.access_keymount(state.init.key_uuid)
-// 				.await?
-// 				.hashed_key;
-
-// 			let user_key_details = key_manager.access_keystore(state.init.key_uuid).await?;
-
-// 			let output_path = {
-// 				let mut path = step.full_path.clone();
-// 				let extension = path.extension().map_or_else(
-// 					|| Ok("bytes".to_string()),
-// 					|extension| {
-// 						Ok::<String, JobError>(format!(
-// 							"{}{BYTES_EXT}",
-// 							extension.to_str().ok_or(FileSystemJobsError::FilePath(
-// 								NonUtf8PathError(step.full_path.clone().into_boxed_path()).into()
-// 							))?
-// 						))
-// 					},
-// 				)?;
-
-// 				path.set_extension(extension);
-// 				path
-// 			};
-
-// 			let _guard = ctx
-// 				.library
-// 				.location_manager()
-// 				.temporary_ignore_events_for_path(
-// 					state.init.location_id,
-// 					ctx.library.clone(),
-// 					&output_path,
-// 				)
-// 				.await
-// 				.map_or_else(
-// 					|e| {
-// 						error!(
-// 							"Failed to make location manager ignore the path {}; Error: {e:#?}",
-// 							output_path.display()
-// 						);
-// 						None
-// 					},
-// 					Some,
-// 				);
-
-// 			let mut reader = File::open(&step.full_path)
-// 				.await
-// 				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
-// 			let mut writer = File::create(&output_path)
-// 				.await
-// 				.map_err(|e| FileIOError::from((output_path, e)))?;
-
-// 			let master_key = Key::generate();
-
-// 			let mut header = FileHeader::new(
-// 				LATEST_FILE_HEADER,
-// 				state.init.algorithm,
-// 				vec![
-// 					Keyslot::new(
-// 						LATEST_KEYSLOT,
-// 						state.init.algorithm,
-// 						user_key_details.hashing_algorithm,
-// 						user_key_details.content_salt,
-// 						user_key,
-// 						master_key.clone(),
-// 					)
-// 					.await?,
-// 				],
-// 			)?;
-
-// 			if state.init.metadata || state.init.preview_media {
-// 				// if any are requested, we can make the query as it'll be used at least once
-// 				if let Some(ref object) = step.file_path.object {
-// 					if state.init.metadata {
-// 						let metadata = Metadata {
-// 							file_path_id: step.file_path.id,
-// 							name: step.file_path.materialized_path.clone(),
-// 							hidden: object.hidden,
-// 							favorite: object.favorite,
-// 							important: object.important,
-// 							note: object.note.clone(),
-// 							date_created: object.date_created,
-// 						};
-
-// 						header
-// 							.add_metadata(
-// 								LATEST_METADATA,
-// 								state.init.algorithm,
-// 								master_key.clone(),
-// 								&metadata,
-// 							)
-// 							.await?;
-// 					}
-
-// 					// if state.init.preview_media
-// 					// 	&& (object.has_thumbnail
-// 					// 		|| object.has_video_preview || object.has_thumbstrip)
-
-// 					// may not be the best - preview media (thumbnail) isn't guaranteed to be webp
-// 					let thumbnail_path = ctx
-// 						.library
-// 						.config()
-// 						.data_directory()
-// 						.join("thumbnails")
-// 						.join(
-// 							step.file_path
-// 								.cas_id
-// 								.as_ref()
-// 								.ok_or(JobError::MissingCasId)?,
-// 						)
-// 						.with_extension("wepb");
-
-// 					match fs::read(&thumbnail_path).await {
-// 						Ok(thumbnail_bytes) => {
-// 							header
-// 								.add_preview_media(
-// 									LATEST_PREVIEW_MEDIA,
-// 									state.init.algorithm,
-// 									master_key.clone(),
-// 									&thumbnail_bytes,
-// 								)
-// 								.await?;
-// 						}
-// 						Err(e) if e.kind() == io::ErrorKind::NotFound => {
-// 							// If the file just doesn't exist, then we don't care
-// 						}
-// 						Err(e) => {
-// 							return Err(FileIOError::from((thumbnail_path, e)).into());
-// 						}
-// 					}
-// 				} else {
-// 					// should use container encryption if it's a directory
-// 					warn!("skipping metadata/preview media inclusion, no associated object found")
-// 				}
-// 			}
-
-// 			header.write(&mut writer).await?;
-
-// 			let encryptor = Encryptor::new(master_key, header.nonce, header.algorithm)?;
-
-// 			encryptor
-// 				.encrypt_streams(&mut reader, &mut writer, &header.generate_aad())
-// 				.await?;
-// 		} else {
-// 			warn!(
-// 				"encryption is skipping {}/{} as it isn't a file",
-// 				step.file_path.materialized_path, step.file_path.name
-// 			)
-// 		}
-
-// 		ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
-// 			state.step_number + 1,
-// 		)]);
-
-// 		Ok(())
-// 	}
-
-// 	async fn finalize(&self, ctx: WorkerContext, state: &mut JobState<Self>) -> JobResult {
-// 		invalidate_query!(ctx.library, "search.paths");
-
-// 		// mark job as successful
-// 		Ok(Some(serde_json::to_value(&state.init)?))
-// 	}
-// }
+//! Encrypting files with a key of the library's key manager. Each file gets a master key of its own,
+//! kept in the header of the encrypted file in a keyslot that only the chosen key opens, along with
+//! the name of the file so decrypting it gives the name back. The object of an encrypted file is
+//! linked to the key, when the key is kept in the library.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::{ensure_sub_path_is_directory, push_location_relative_path},
+	prisma::{file_path, key, location, object},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::path::{Path, PathBuf};
+
+use sd_crypto::{
+	crypto::Encryptor,
+	header::{file::FileHeader, keyslot::Keyslot},
+	primitives::{LATEST_FILE_HEADER, LATEST_KEYSLOT, LATEST_METADATA},
+	types::{Algorithm, HashingAlgorithm, Key, Salt},
+};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io,
+};
+use tracing::{info, trace, warn};
+use uuid::Uuid;
+
+use super::{
+	construct_target_filename, error::FileSystemJobsError, get_location_path_from_location_id,
+	get_many_files_datas, index_new_file, FileData, BYTES_EXT,
+};
+
+/// Its 192-bit nonces are random, without any risk of picking the same one twice
+const ALGORITHM: Algorithm = Algorithm::XChaCha20Poly1305;
+
+pub struct FileEncryptorJob {}
+
+/// Where encrypted files, or decrypted ones, are written
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+#[serde(tag = "type")]
+pub enum EncryptionTarget {
+	/// Each written file replaces its original file
+	InPlace,
+	/// The written files go to an existing directory of a location, the original files are kept
+	Directory {
+		location_id: location::id::Type,
+		relative_directory_path: PathBuf,
+	},
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileEncryptorJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_ids: Vec<file_path::id::Type>,
+	/// A mounted key of the key manager, the default key when not set
+	pub key_uuid: Option<Uuid>,
+	pub target: EncryptionTarget,
+}
+
+impl JobInitData for FileEncryptorJobInit {
+	type Job = FileEncryptorJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+/// What's kept, encrypted, in the header of an encrypted file
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct EncryptedFileMetadata {
+	/// The name of the original file, with its extension
+	pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileEncryptorJobData {
+	key_uuid: Uuid,
+	/// The key in the library, keys only kept in memory have none
+	key_id: Option<key::id::Type>,
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	/// Where the encrypted files go, next to their original files when not set
+	target_directory_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileEncryptorJobRunMetadata {
+	files_encrypted: u64,
+	bytes_encrypted: u64,
+}
+
+impl JobRunMetadata for FileEncryptorJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.files_encrypted += new_data.files_encrypted;
+		self.bytes_encrypted += new_data.bytes_encrypted;
+	}
+}
+
+/// Encrypts `source` to `target` with a new master key, kept in the header in a keyslot opened by
+/// `hashed_key`. The file is read and encrypted a block at a time, so it's never all in memory.
+pub(super) async fn encrypt_file(
+	source: &Path,
+	target: &Path,
+	hashed_key: Key,
+	hashing_algorithm: HashingAlgorithm,
+	content_salt: Salt,
+	metadata: &EncryptedFileMetadata,
+) -> Result<(), JobError> {
+	let master_key = Key::generate();
+
+	let mut header = FileHeader::new(
+		LATEST_FILE_HEADER,
+		ALGORITHM,
+		vec![
+			Keyslot::new(
+				LATEST_KEYSLOT,
+				ALGORITHM,
+				hashing_algorithm,
+				content_salt,
+				hashed_key,
+				master_key.clone(),
+			)
+			.await?,
+		],
+	)?;
+
+	header
+		.add_metadata(LATEST_METADATA, ALGORITHM, master_key.clone(), metadata)
+		.await?;
+
+	let mut reader = File::open(source)
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+	let mut writer = File::create(target)
+		.await
+		.map_err(|e| FileIOError::from((target, e)))?;
+
+	header.write(&mut writer).await?;
+
+	Encryptor::new(master_key, header.nonce, header.algorithm)?
+		.encrypt_streams(&mut reader, &mut writer, &header.generate_aad())
+		.await?;
+
+	writer
+		.sync_all()
+		.await
+		.map_err(|e| FileIOError::from((target, e)).into())
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileEncryptorJob {
+	type Init = FileEncryptorJobInit;
+	type Data = FileEncryptorJobData;
+	type Step = FileData;
+	type RunMetadata = FileEncryptorJobRunMetadata;
+
+	const NAME: &'static str = "file_encryptor";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library {
+			db, key_manager, ..
+		} = &ctx.library;
+
+		let key_uuid = match init.key_uuid {
+			Some(key_uuid) => key_uuid,
+			None => key_manager.get_default().await?,
+		};
+		// Failing here if the key isn't mounted, instead of on every file
+		key_manager.access_keymount(key_uuid).await?;
+
+		let key_id = db
+			.key()
+			.find_unique(key::uuid::equals(key_uuid.to_string()))
+			.select(key::select!({ id }))
+			.exec()
+			.await?
+			.map(|key| key.id);
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+
+		let (target_location_id, target_location_path, target_directory_path) = match &init.target {
+			EncryptionTarget::InPlace => (init.location_id, location_path.clone(), None),
+			EncryptionTarget::Directory {
+				location_id,
+				relative_directory_path,
+			} => {
+				let target_location_path =
+					get_location_path_from_location_id(db, *location_id).await?;
+				ensure_sub_path_is_directory(&target_location_path, relative_directory_path)
+					.await
+					.map_err(FileSystemJobsError::from)?;
+
+				let target_directory_path = push_location_relative_path(
+					target_location_path.clone(),
+					relative_directory_path,
+				);
+
+				(
+					*location_id,
+					target_location_path,
+					Some(target_directory_path),
+				)
+			}
+		};
+
+		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		*data = Some(FileEncryptorJobData {
+			key_uuid,
+			key_id,
+			target_location_id,
+			target_location_path,
+			target_directory_path,
+		});
+
+		Ok((FileEncryptorJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library {
+			db, key_manager, ..
+		} = &ctx.library;
+		let FileData {
+			file_path,
+			full_path,
+		} = step;
+
+		if maybe_missing(file_path.is_dir, "file_path.is_dir")? {
+			warn!("Skipping {} as it's a directory", full_path.display());
+
+			return Ok(JobRunErrors(vec![format!(
+				"{} wasn't encrypted, only files are",
+				full_path.display()
+			)])
+			.into());
+		}
+
+		let name = construct_target_filename(step, &None)?;
+		let encrypted_name = format!("{name}{BYTES_EXT}");
+		let output_path = match &data.target_directory_path {
+			Some(target_directory_path) => target_directory_path.join(encrypted_name),
+			None => full_path.with_file_name(encrypted_name),
+		};
+
+		match fs::metadata(&output_path).await {
+			Ok(_) => {
+				warn!(
+					"Skipping {} as it would be overwritten",
+					output_path.display()
+				);
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					output_path.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((&output_path, e)).into()),
+		}
+
+		let size = fs::metadata(full_path)
+			.await
+			.map_err(|e| FileIOError::from((full_path, e)))?
+			.len();
+
+		ctx.progress_msg(job_message!(
+			"crypto.encrypting",
+			path = full_path.display()
+		));
+
+		let hashed_key = key_manager.access_keymount(data.key_uuid).await?.hashed_key;
+		let stored_key = key_manager.access_keystore(data.key_uuid).await?;
+
+		// Written aside first, a file interrupted halfway never looks like a whole encrypted file
+		let mut part_path = output_path.clone().into_os_string();
+		part_path.push(".part");
+		let part_path = PathBuf::from(part_path);
+
+		if let Err(e) = encrypt_file(
+			full_path,
+			&part_path,
+			hashed_key,
+			stored_key.hashing_algorithm,
+			stored_key.content_salt,
+			&EncryptedFileMetadata { name },
+		)
+		.await
+		{
+			if let Err(e) = fs::remove_file(&part_path).await {
+				warn!(
+					"Failed to remove the partly encrypted file {}: {e}",
+					part_path.display()
+				);
+			}
+
+			return Err(e);
+		}
+
+		fs::rename(&part_path, &output_path)
+			.await
+			.map_err(|e| FileIOError::from((&output_path, e)))?;
+
+		trace!(
+			"Encrypted {} to {}",
+			full_path.display(),
+			output_path.display()
+		);
+
+		if matches!(init.target, EncryptionTarget::InPlace) {
+			fs::remove_file(full_path)
+				.await
+				.map_err(|e| FileIOError::from((full_path, e)))?;
+
+			db.file_path()
+				.delete(file_path::id::equals(file_path.id))
+				.exec()
+				.await?;
+		}
+
+		let object_id = index_new_file(
+			&ctx.library,
+			data.target_location_id,
+			&data.target_location_path,
+			&output_path,
+		)
+		.await?;
+
+		db.object()
+			.update(
+				object::id::equals(object_id),
+				vec![match data.key_id {
+					Some(key_id) => object::key::connect(key::id::equals(key_id)),
+					None => object::key::disconnect(),
+				}],
+			)
+			.exec()
+			.await?;
+
+		Ok(FileEncryptorJobRunMetadata {
+			files_encrypted: 1,
+			bytes_encrypted: size,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Encrypted {} files, {} bytes",
+			metadata.files_encrypted, metadata.bytes_encrypted
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_crypto::{primitives::KEY_LEN, types::Params};
+	use tokio::io::AsyncReadExt;
+
+	#[tokio::test]
+	async fn keeps_the_name_in_the_header() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("notes.txt");
+		let target = dir.path().join("notes.txt.bytes");
+		fs::write(&source, b"Spacedrive keeps secrets")
+			.await
+			.unwrap();
+
+		let hashed_key = Key::new([7; KEY_LEN]);
+		encrypt_file(
+			&source,
+			&target,
+			hashed_key.clone(),
+			HashingAlgorithm::Argon2id(Params::Standard),
+			Salt::generate(),
+			&EncryptedFileMetadata {
+				name: "notes.txt".to_string(),
+			},
+		)
+		.await
+		.unwrap();
+
+		let mut reader = File::open(&target).await.unwrap();
+		let (header, _) = FileHeader::from_reader(&mut reader).await.unwrap();
+		assert_eq!(
+			header
+				.decrypt_metadata_from_prehashed::<EncryptedFileMetadata>(vec![hashed_key])
+				.await
+				.unwrap(),
+			EncryptedFileMetadata {
+				name: "notes.txt".to_string()
+			}
+		);
+
+		// The contents are encrypted
+		let mut encrypted = vec![];
+		reader.read_to_end(&mut encrypted).await.unwrap();
+		assert!(!encrypted
+			.windows(b"secrets".len())
+			.any(|window| window == b"secrets"));
+	}
+}
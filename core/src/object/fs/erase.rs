@@ -1,26 +1,43 @@
+//! Erasing files by overwriting their contents with random data before removing them, for files
+//! that mustn't be recovered. Overwriting only reaches the blocks a file is stored in when the
+//! storage writes in place, which flash storage and copy-on-write filesystems don't do. The job
+//! can't get around those, it tells which of them the files were on in its report instead.
+
 use crate::{
 	invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunMetadata, JobState,
-		JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	library::{
+		activity::{self, ActivityKind, MASS_DELETION_THRESHOLD},
+		Library,
 	},
-	library::Library,
 	location::file_path_helper::IsolatedFilePathData,
+	object::preview::{get_sprite_path, get_text_preview_path, get_waveform_path},
 	prisma::{file_path, location},
 	util::{db::maybe_missing, error::FileIOError},
+	volume::{get_volumes, DiskType, Volume},
 };
 
-use std::{hash::Hash, path::PathBuf};
+use std::{
+	fmt,
+	hash::Hash,
+	path::{Path, PathBuf},
+};
 
 use futures::future::try_join_all;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use serde_with::{serde_as, DisplayFromStr};
 use specta::Type;
 use tokio::{
 	fs::{self, OpenOptions},
-	io::AsyncWriteExt,
+	io::{self, AsyncWriteExt},
+	task::spawn_blocking,
 };
-use tracing::trace;
+use tracing::{error, trace, warn};
+use uuid::Uuid;
 
 use super::{
 	error::FileSystemJobsError, get_file_data_from_isolated_file_path,
@@ -41,22 +58,127 @@ pub struct FileEraserJobInit {
 
 impl JobInitData for FileEraserJobInit {
 	type Job = FileEraserJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+/// Filesystems that write changed blocks somewhere else, keeping the old ones until they're reused
+const COPY_ON_WRITE_FILESYSTEMS: [&str; 5] = ["apfs", "btrfs", "zfs", "refs", "bcachefs"];
+
+/// Why erased files may still be recoverable from the storage they were on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EraseCaveat {
+	FlashStorage,
+	CopyOnWrite,
+	UnknownStorage,
+}
+
+impl fmt::Display for EraseCaveat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::FlashStorage => "the files were on flash storage, which spreads writes across its cells, so the old contents may remain in cells that weren't overwritten",
+			Self::CopyOnWrite => "the files were on a copy-on-write filesystem, which writes new contents to new blocks, so the old blocks and any snapshots keep the old contents",
+			Self::UnknownStorage => "the storage of the files couldn't be identified, so it may be flash storage or a copy-on-write filesystem, where overwriting doesn't reach the old contents",
+		})
+	}
+}
+
+/// The volume a path is on, the one with the longest mount point containing it
+fn volume_of<'a>(volumes: &'a [Volume], path: &Path) -> Option<&'a Volume> {
+	volumes
+		.iter()
+		.filter(|volume| path.starts_with(&volume.mount_point))
+		.max_by_key(|volume| volume.mount_point.len())
+}
+
+fn erase_caveats(volume: Option<&Volume>) -> Vec<EraseCaveat> {
+	let Some(volume) = volume else {
+		return vec![EraseCaveat::UnknownStorage];
+	};
+
+	let mut caveats = vec![];
+
+	// Removable drives are nearly all flash, and unknown drives are reported as removable
+	if matches!(
+		volume.disk_type,
+		None | Some(DiskType::SSD | DiskType::Removable)
+	) {
+		caveats.push(EraseCaveat::FlashStorage);
+	}
+
+	if volume.file_system.as_ref().map_or(true, |file_system| {
+		COPY_ON_WRITE_FILESYSTEMS.contains(&file_system.to_ascii_lowercase().as_str())
+	}) {
+		caveats.push(EraseCaveat::CopyOnWrite);
+	}
+
+	caveats
+}
+
+/// Whether other hard links share the contents of a file, which overwriting it would erase too
+fn has_other_links(metadata: &std::fs::Metadata) -> bool {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+
+		metadata.nlink() > 1
+	}
+
+	#[cfg(not(unix))]
+	{
+		let _ = metadata;
+		false
+	}
+}
+
+/// Removes the previews made from an erased file, unless other files have the same contents
+async fn remove_previews(library: &Library, cas_id: &str) -> Result<(), JobError> {
+	let others = library
+		.db
+		.file_path()
+		.count(vec![file_path::cas_id::equals(Some(cas_id.to_string()))])
+		.exec()
+		.await?;
+	if others > 0 {
+		return Ok(());
+	}
+
+	let data_dir = library.config().data_directory();
+
+	for path in library.find_thumbnail(cas_id).await?.into_iter().chain([
+		get_text_preview_path(&data_dir, cas_id),
+		get_waveform_path(&data_dir, cas_id),
+		get_sprite_path(&data_dir, cas_id),
+	]) {
+		match fs::remove_file(&path).await {
+			Ok(()) => trace!("Removed the preview {}", path.display()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((path, e)).into()),
+		}
+	}
+
+	Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FileEraserJobData {
 	location_path: PathBuf,
+	caveats: Vec<EraseCaveat>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct FileEraserJobRunMetadata {
 	diretories_to_remove: Vec<PathBuf>,
+	files_erased: u64,
 }
 
 impl JobRunMetadata for FileEraserJobRunMetadata {
 	fn update(&mut self, new_data: Self) {
 		self.diretories_to_remove
 			.extend(new_data.diretories_to_remove);
+		self.files_erased += new_data.files_erased;
 	}
 }
 
@@ -68,6 +190,7 @@ impl StatefulJob for FileEraserJob {
 	type RunMetadata = FileEraserJobRunMetadata;
 
 	const NAME: &'static str = "file_eraser";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -85,7 +208,22 @@ impl StatefulJob for FileEraserJob {
 
 		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
 
-		*data = Some(FileEraserJobData { location_path });
+		let caveats = {
+			let location_path = location_path.clone();
+			spawn_blocking(move || match get_volumes() {
+				Ok(volumes) => erase_caveats(volume_of(&volumes, &location_path)),
+				Err(e) => {
+					error!("Failed to get the volumes: {e:#?}");
+					erase_caveats(None)
+				}
+			})
+			.await?
+		};
+
+		*data = Some(FileEraserJobData {
+			location_path,
+			caveats,
+		});
 
 		Ok((Default::default(), steps).into())
 	}
@@ -144,35 +282,81 @@ impl StatefulJob for FileEraserJob {
 
 			Ok((more_steps, new_metadata).into())
 		} else {
-			let mut file = OpenOptions::new()
-				.read(true)
-				.write(true)
-				.open(&step.full_path)
-				.await
-				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
-			let file_len = file
-				.metadata()
+			let path = &step.full_path;
+			let metadata = fs::symlink_metadata(path)
 				.await
-				.map_err(|e| FileIOError::from((&step.full_path, e)))?
-				.len();
+				.map_err(|e| FileIOError::from((path, e)))?;
+
+			if metadata.is_symlink() {
+				// Overwriting would go through the link, to a file that isn't being erased
+				fs::remove_file(path)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+			} else if has_other_links(&metadata) {
+				warn!(
+					"Skipping {} as it has other hard links",
+					step.full_path.display()
+				);
 
-			sd_crypto::fs::erase::erase(&mut file, file_len as usize, init.passes).await?;
+				return Ok(JobRunErrors(vec![format!(
+					"{} wasn't erased, its contents are shared with other hard links to it, which erasing it would erase too",
+					path.display()
+				)])
+				.into());
+			} else {
+				let mut file = OpenOptions::new()
+					.read(true)
+					.write(true)
+					.open(path)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+
+				trace!("Erasing file: {}", path.display());
+
+				// Each pass is flushed to the disk, or the later ones would just replace the
+				// earlier ones in the page cache
+				for _ in 0..init.passes.max(1) {
+					sd_crypto::fs::erase::erase(&mut file, metadata.len() as usize, 1).await?;
+					file.sync_data()
+						.await
+						.map_err(|e| FileIOError::from((path, e)))?;
+				}
+
+				file.set_len(0)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+				file.flush()
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+				file.sync_all()
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+				drop(file);
+
+				// Renamed before being removed, so the directory doesn't keep its name either
+				let renamed_path = path.with_file_name(Uuid::new_v4().simple().to_string());
+				fs::rename(path, &renamed_path)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+				fs::remove_file(&renamed_path)
+					.await
+					.map_err(|e| FileIOError::from((&renamed_path, e)))?;
+			}
 
-			file.set_len(0)
-				.await
-				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
-			file.flush()
-				.await
-				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
-			drop(file);
+			ctx.library
+				.db
+				.file_path()
+				.delete(file_path::id::equals(step.file_path.id))
+				.exec()
+				.await?;
 
-			trace!("Erasing file: {}", step.full_path.display());
+			if let Some(cas_id) = &step.file_path.cas_id {
+				remove_previews(&ctx.library, cas_id).await?;
+			}
 
-			fs::remove_file(&step.full_path)
-				.await
-				.map_err(|e| FileIOError::from((&step.full_path, e)))?;
+			new_metadata.files_erased = 1;
 
-			Ok(None.into())
+			Ok(new_metadata.into())
 		};
 
 		res
@@ -193,8 +377,70 @@ impl StatefulJob for FileEraserJob {
 		)
 		.await?;
 
+		if state.init.file_path_ids.len() >= MASS_DELETION_THRESHOLD {
+			activity::record(
+				&ctx.library,
+				ActivityKind::FilesDeleted,
+				json!({
+					"location_id": state.init.location_id,
+					"count": state.init.file_path_ids.len(),
+				}),
+			)
+			.await;
+		}
+
 		invalidate_query!(ctx.library, "search.paths");
 
-		Ok(Some(serde_json::to_value(&state.init)?))
+		let caveats = state
+			.data
+			.as_ref()
+			.map(|data| data.caveats.as_slice())
+			.unwrap_or_default();
+		for caveat in caveats {
+			warn!("Erased files may still be recoverable: {caveat}");
+		}
+
+		Ok(Some(json!({
+			"init": state.init,
+			"files_erased": state.run_metadata.files_erased,
+			"caveats": caveats.iter().map(ToString::to_string).collect::<Vec<_>>(),
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn volume(mount_point: &str, disk_type: DiskType, file_system: &str) -> Volume {
+		Volume {
+			name: mount_point.to_string(),
+			mount_point: mount_point.to_string(),
+			total_capacity: 0,
+			available_capacity: 0,
+			is_removable: false,
+			disk_type: Some(disk_type),
+			file_system: Some(file_system.to_string()),
+			is_root_filesystem: mount_point == "/",
+		}
+	}
+
+	#[test]
+	fn tells_the_caveats_of_the_volume_of_the_location() {
+		let volumes = [
+			volume("/", DiskType::SSD, "btrfs"),
+			volume("/mnt/archive", DiskType::HDD, "ext4"),
+		];
+
+		assert_eq!(
+			erase_caveats(volume_of(&volumes, Path::new("/home/me/Documents"))),
+			[EraseCaveat::FlashStorage, EraseCaveat::CopyOnWrite]
+		);
+		// The deepest mount point wins over the root one
+		assert!(erase_caveats(volume_of(&volumes, Path::new("/mnt/archive/taxes"))).is_empty());
+		assert_eq!(
+			erase_caveats(volume_of(&volumes[1..], Path::new("/home"))),
+			[EraseCaveat::UnknownStorage]
+		);
 	}
 }
@@ -1,5 +1,6 @@
 use crate::{
 	location::{file_path_helper::FilePathError, LocationError},
+	object::fs::os_trash::OsTrashError,
 	prisma::file_path,
 	util::{db::MissingFieldError, error::FileIOError},
 };
@@ -26,6 +27,29 @@ pub enum FileSystemJobsError {
 	FilePath(#[from] FilePathError),
 	#[error("action would overwrite another file: {}", .0.display())]
 	WouldOverwrite(Box<Path>),
+	#[error("copied file doesn't match its source: {}", .0.display())]
+	CopyVerification(Box<Path>),
+	#[error("only files can be split: {}", .0.display())]
+	NotAFile(Box<Path>),
+	#[error("split files' parts must be at least 1 MiB, not {0} bytes")]
+	InvalidPartSize(u64),
+	#[error("invalid split file manifest: {}: {1}", .0.display())]
+	InvalidSplitManifest(Box<Path>, String),
+	#[error("part of a split file is missing or doesn't match its manifest: {}", .0.display())]
+	SplitPartMismatch(Box<Path>),
+	#[error("joined file doesn't match the file that was split: {}", .0.display())]
+	JoinVerification(Box<Path>),
+	#[error(transparent)]
+	OsTrash(#[from] OsTrashError),
 	#[error("missing-field: {0}")]
 	MissingField(#[from] MissingFieldError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error("videos can't be transcoded, Spacedrive was built without FFmpeg")]
+	TranscodingUnavailable,
+	#[error("can't mirror a directory into itself: <source='{}', destination='{}'>", .source_path.display(), .destination_path.display())]
+	MirrorOverlap {
+		source_path: Box<Path>,
+		destination_path: Box<Path>,
+	},
 }
@@ -0,0 +1,378 @@
+//! The inbox of a library, where the files and links shared to Spacedrive from other apps land, like
+//! from the share sheets of iOS and Android or "Send to Spacedrive" on desktop.
+//!
+//! The inbox is a location of the library on this node, set in the settings of the library. Shared
+//! files are copied to its root and shared links are downloaded there, with "name (1).ext" names
+//! when theirs are taken. They're identified right away, so they can be tagged on their way in.
+
+use crate::{
+	invalidate_query,
+	library::{
+		webhooks::{self, WebhookEvent},
+		Library,
+	},
+	location::{find_location, LocationError},
+	prisma::{location, object, tag, tag_on_object},
+	sync::SyncError,
+	util::{
+		db::{maybe_missing, MissingFieldError},
+		error::FileIOError,
+	},
+};
+
+use std::{
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use futures::StreamExt;
+use percent_encoding::percent_decode_str;
+use prisma_client_rust::QueryError;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::debug;
+use uuid::Uuid;
+
+use super::{available_path, error::FileSystemJobsError, index_new_file};
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// The name of a downloaded file when its link doesn't tell one
+const DEFAULT_DOWNLOAD_NAME: &str = "download";
+
+#[derive(Error, Debug)]
+pub enum InboxError {
+	#[error("the library has no inbox location")]
+	NotConfigured,
+	#[error("the inbox location isn't on this node <id='{0}'>")]
+	NotLocal(location::id::Type),
+	#[error("only files can be shared to the inbox: {}", .0.display())]
+	NotAFile(PathBuf),
+	#[error("invalid url, it must be http or https: {0}")]
+	Url(String),
+	#[error("failed to download {url}, the server answered {status}")]
+	DownloadStatus { url: String, status: u16 },
+	#[error("failed to download: {0}")]
+	Download(#[from] reqwest::Error),
+	#[error("tag not found: {0}")]
+	TagNotFound(tag::id::Type),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
+	#[error(transparent)]
+	Location(#[from] LocationError),
+	#[error(transparent)]
+	FileSystem(#[from] FileSystemJobsError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error("missing-field: {0}")]
+	MissingField(#[from] MissingFieldError),
+}
+
+impl From<InboxError> for rspc::Error {
+	fn from(e: InboxError) -> Self {
+		let code = match e {
+			InboxError::NotConfigured
+			| InboxError::NotLocal(_)
+			| InboxError::NotAFile(_)
+			| InboxError::Url(_) => rspc::ErrorCode::BadRequest,
+			InboxError::TagNotFound(_) | InboxError::Location(LocationError::IdNotFound(_)) => {
+				rspc::ErrorCode::NotFound
+			}
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+#[derive(Deserialize, Type, Debug)]
+#[serde(tag = "type")]
+pub enum InboxItem {
+	/// A file of this node, it's copied and left where it is
+	File { path: PathBuf },
+	/// Downloaded, named after the last segment of its path unless `name` is given
+	Url { url: String, name: Option<String> },
+}
+
+#[derive(Deserialize, Type, Debug)]
+pub struct InboxArgs {
+	pub items: Vec<InboxItem>,
+	/// Tags given to all of the items
+	#[serde(default)]
+	pub tag_ids: Vec<tag::id::Type>,
+}
+
+/// An item saved to the inbox
+#[derive(Serialize, Type, Debug)]
+pub struct InboxEntry {
+	pub object_id: object::id::Type,
+	pub path: PathBuf,
+}
+
+/// Saves `args.items` to the inbox of the library, in order. An item that fails stops the ones
+/// after it, the ones before it stay in the inbox.
+pub async fn ingest(library: &Library, args: InboxArgs) -> Result<Vec<InboxEntry>, InboxError> {
+	let location_id = library
+		.config
+		.settings
+		.inbox_location_id
+		.ok_or(InboxError::NotConfigured)?;
+
+	let location = find_location(library, location_id)
+		.select(location::select!({ path node_id }))
+		.exec()
+		.await?
+		.ok_or(LocationError::IdNotFound(location_id))?;
+	if location.node_id != Some(library.node_local_id) {
+		return Err(InboxError::NotLocal(location_id));
+	}
+	let location_path = PathBuf::from(maybe_missing(location.path, "location.path")?);
+
+	// Checking the tags first, not to leave untagged items in the inbox
+	let tags = library
+		.db
+		.tag()
+		.find_many(vec![tag::id::in_vec(args.tag_ids.clone())])
+		.select(tag::select!({ id pub_id }))
+		.exec()
+		.await?;
+	if let Some(&tag_id) = args
+		.tag_ids
+		.iter()
+		.find(|&&tag_id| !tags.iter().any(|tag| tag.id == tag_id))
+	{
+		return Err(InboxError::TagNotFound(tag_id));
+	}
+
+	let client = reqwest::Client::builder()
+		.timeout(DOWNLOAD_TIMEOUT)
+		.build()?;
+
+	let mut entries = Vec::with_capacity(args.items.len());
+	for item in args.items {
+		let path = match item {
+			InboxItem::File { path } => copy_file(&path, &location_path).await?,
+			InboxItem::Url { url, name } => {
+				download(&client, &url, name.as_deref(), &location_path).await?
+			}
+		};
+
+		debug!("Saved {} to the inbox", path.display());
+
+		let object_id = index_new_file(library, location_id, &location_path, &path).await?;
+
+		entries.push(InboxEntry { object_id, path });
+	}
+
+	let tags = tags
+		.into_iter()
+		.filter_map(|tag| Some((tag.id, Uuid::from_slice(&tag.pub_id).ok()?)))
+		.collect::<Vec<_>>();
+	if !tags.is_empty() && !entries.is_empty() {
+		tag_entries(library, &tags, &entries).await?;
+	}
+
+	invalidate_query!(library, "search.paths");
+
+	Ok(entries)
+}
+
+async fn copy_file(source: &Path, location_path: &Path) -> Result<PathBuf, InboxError> {
+	let metadata = fs::metadata(source)
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+	if !metadata.is_file() {
+		return Err(InboxError::NotAFile(source.to_path_buf()));
+	}
+
+	let name = source
+		.file_name()
+		.ok_or_else(|| InboxError::NotAFile(source.to_path_buf()))?;
+	let target = free_path(location_path.join(name)).await;
+
+	fs::copy(source, &target)
+		.await
+		.map_err(|e| FileIOError::from((&target, e)))?;
+
+	Ok(target)
+}
+
+async fn download(
+	client: &reqwest::Client,
+	url: &str,
+	name: Option<&str>,
+	location_path: &Path,
+) -> Result<PathBuf, InboxError> {
+	let parsed = match Url::parse(url) {
+		Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+		_ => return Err(InboxError::Url(url.to_string())),
+	};
+
+	let response = client.get(parsed.clone()).send().await?;
+	if !response.status().is_success() {
+		return Err(InboxError::DownloadStatus {
+			url: url.to_string(),
+			status: response.status().as_u16(),
+		});
+	}
+
+	let target = free_path(location_path.join(download_name(&parsed, name))).await;
+
+	let mut file = fs::File::create(&target)
+		.await
+		.map_err(|e| FileIOError::from((&target, e)))?;
+
+	let mut stream = response.bytes_stream();
+	while let Some(chunk) = stream.next().await {
+		let chunk = match chunk {
+			Ok(chunk) => chunk,
+			Err(e) => {
+				// Not leaving half of the file in the inbox
+				drop(file);
+				fs::remove_file(&target).await.ok();
+				return Err(e.into());
+			}
+		};
+
+		file.write_all(&chunk)
+			.await
+			.map_err(|e| FileIOError::from((&target, e)))?;
+	}
+
+	file.flush()
+		.await
+		.map_err(|e| FileIOError::from((&target, e)))?;
+
+	Ok(target)
+}
+
+/// `path`, or the first "name (1).ext" like name not taken yet
+async fn free_path(path: PathBuf) -> PathBuf {
+	if fs::symlink_metadata(&path).await.is_err() {
+		path
+	} else {
+		available_path(&path)
+	}
+}
+
+/// The name a download is saved under: `name` if given, the last segment of the path of its link
+/// otherwise, without anything that would put it outside of the inbox
+fn download_name(url: &Url, name: Option<&str>) -> String {
+	let name = name.map(str::to_string).or_else(|| {
+		url.path_segments()?
+			.filter(|segment| !segment.is_empty())
+			.last()
+			.and_then(|segment| percent_decode_str(segment).decode_utf8().ok())
+			.map(|segment| segment.into_owned())
+	});
+
+	let name = name
+		.unwrap_or_default()
+		.chars()
+		.filter(|c| !matches!(c, '/' | '\\' | ':' | '\0') && !c.is_control())
+		.collect::<String>();
+	let name = name.trim();
+
+	if name.is_empty() || name.chars().all(|c| c == '.') {
+		DEFAULT_DOWNLOAD_NAME.to_string()
+	} else {
+		name.to_string()
+	}
+}
+
+async fn tag_entries(
+	library: &Library,
+	tags: &[(tag::id::Type, Uuid)],
+	entries: &[InboxEntry],
+) -> Result<(), InboxError> {
+	let Library { db, sync, .. } = library;
+
+	let objects = db
+		.object()
+		.find_many(vec![object::id::in_vec(
+			entries.iter().map(|entry| entry.object_id).collect(),
+		)])
+		.select(object::select!({ id pub_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|object| Some((object.id, Uuid::from_slice(&object.pub_id).ok()?)))
+		.collect::<Vec<_>>();
+
+	let mut ops = vec![];
+	let mut tags_on_objects = vec![];
+	let mut events = vec![];
+	for &(tag_id, tag_pub_id) in tags {
+		for &(object_id, object_pub_id) in &objects {
+			ops.push(sync.relation_create(tag_on_object::NAME, tag_pub_id, object_pub_id));
+			tags_on_objects.push(tag_on_object::CreateUnchecked {
+				tag_id,
+				object_id,
+				_params: vec![],
+			});
+			events.push(WebhookEvent::ObjectTagged {
+				object_pub_id,
+				tag_id,
+			});
+		}
+	}
+
+	sync.write_ops(
+		db,
+		(
+			ops,
+			db.tag_on_object()
+				.create_many(tags_on_objects)
+				.skip_duplicates(),
+		),
+	)
+	.await?;
+
+	webhooks::fire(library, events);
+
+	invalidate_query!(library, "tags.getForObject");
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn names_downloads_inside_the_inbox() {
+		let url = |url| Url::parse(url).unwrap();
+
+		assert_eq!(
+			download_name(
+				&url("https://example.com/photos/IMG%201.jpg?size=full"),
+				None
+			),
+			"IMG 1.jpg"
+		);
+		assert_eq!(
+			download_name(&url("https://example.com/docs/"), None),
+			"docs"
+		);
+		assert_eq!(
+			download_name(&url("https://example.com"), None),
+			DEFAULT_DOWNLOAD_NAME
+		);
+		assert_eq!(
+			download_name(&url("https://example.com/a/..%2F..%2Fetc%2Fpasswd"), None),
+			"....etcpasswd"
+		);
+		assert_eq!(
+			download_name(&url("https://example.com/x"), Some("../")),
+			DEFAULT_DOWNLOAD_NAME
+		);
+		assert_eq!(
+			download_name(&url("https://example.com/x"), Some("Receipt.pdf")),
+			"Receipt.pdf"
+		);
+	}
+}
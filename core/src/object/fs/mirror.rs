@@ -0,0 +1,625 @@
+//! One-way mirroring of a directory of a location to another directory, of a location or anywhere
+//! else like an external drive. New files and files changed since they were last mirrored are
+//! copied, and the files the source doesn't have are removed from the destination if asked to.
+//!
+//! Files are compared by their size and modification date, which the copies keep. The differences
+//! are worked out before anything changes, so a dry run reports them without changing anything.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	location::{
+		file_path_helper::{ensure_sub_path_is_directory, push_location_relative_path},
+		SPACEDRIVE_LOCATION_METADATA_FILE,
+	},
+	prisma::location,
+	util::error::FileIOError,
+};
+
+use std::{
+	collections::BTreeMap,
+	ffi::{OsStr, OsString},
+	fs::Metadata,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use filetime::{set_file_mtime, FileTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{info, trace, warn};
+
+use super::{error::FileSystemJobsError, get_location_path_from_location_id};
+
+/// Appended to the name of a file being copied, until it replaces the file it's named after
+const MIRROR_COPY_EXTENSION: &str = "sd-mirror";
+/// Modification dates closer than this are the same, as FAT filesystems only keep them to 2 seconds
+const MODIFIED_TOLERANCE: Duration = Duration::from_secs(2);
+/// How many paths of each kind of change are listed in the report, all of them are counted
+const REPORTED_PATHS_LIMIT: usize = 1000;
+
+pub struct MirrorJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+#[serde(tag = "type")]
+pub enum MirrorDestination {
+	/// A directory of a location, created if it doesn't exist
+	Location {
+		location_id: location::id::Type,
+		sub_path: PathBuf,
+	},
+	/// A directory outside of the library's locations, created if it doesn't exist
+	Path { path: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct MirrorJobInit {
+	pub location_id: location::id::Type,
+	/// The directory of the location that's mirrored, all of the location when not set
+	pub sub_path: Option<PathBuf>,
+	pub destination: MirrorDestination,
+	/// Removes the files and directories of the destination that the source doesn't have
+	pub delete_extraneous: bool,
+	/// Only reports what would change, without changing anything
+	pub dry_run: bool,
+}
+
+impl JobInitData for MirrorJobInit {
+	type Job = MirrorJob;
+
+	fn locations(&self) -> JobLocations {
+		match &self.destination {
+			MirrorDestination::Location { location_id, .. } => {
+				JobLocations::Some(vec![self.location_id, *location_id])
+			}
+			MirrorDestination::Path { .. } => JobLocations::Some(vec![self.location_id]),
+		}
+	}
+}
+
+/// A change to the destination, with the path relative to the mirrored directories
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MirrorAction {
+	CreateDirectory(PathBuf),
+	Copy {
+		relative_path: PathBuf,
+		size: u64,
+		/// If it replaces an older version of the file
+		is_update: bool,
+	},
+	Delete {
+		relative_path: PathBuf,
+		is_dir: bool,
+	},
+}
+
+/// Paths, relative to the mirrored directories, the mirror doesn't touch
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct MirrorSkipped {
+	/// A file on one side and a directory on the other, without extraneous paths being deleted
+	conflicts: Vec<PathBuf>,
+	/// Symbolic links of the source aren't followed
+	symlinks: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MirrorJobData {
+	source_path: PathBuf,
+	destination_path: PathBuf,
+	/// The differences that were found, listed in the report
+	diff_report: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MirrorJobRunMetadata {
+	directories_created: u64,
+	files_copied: u64,
+	files_updated: u64,
+	paths_deleted: u64,
+	bytes_copied: u64,
+}
+
+impl JobRunMetadata for MirrorJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.directories_created += new_data.directories_created;
+		self.files_copied += new_data.files_copied;
+		self.files_updated += new_data.files_updated;
+		self.paths_deleted += new_data.paths_deleted;
+		self.bytes_copied += new_data.bytes_copied;
+	}
+}
+
+/// Whether a file changed since it was mirrored, the copies keep the modification date of the source
+fn is_changed(source: &Metadata, destination: &Metadata) -> bool {
+	if source.len() != destination.len() {
+		return true;
+	}
+
+	match (source.modified(), destination.modified()) {
+		(Ok(source), Ok(destination)) => {
+			source
+				.duration_since(destination)
+				.or_else(|_| destination.duration_since(source))
+				.unwrap_or_default()
+				> MODIFIED_TOLERANCE
+		}
+		_ => true,
+	}
+}
+
+fn is_mirror_copy(name: &OsStr) -> bool {
+	Path::new(name)
+		.extension()
+		.map_or(false, |extension| extension == MIRROR_COPY_EXTENSION)
+}
+
+/// The entries of a directory by name, none if it doesn't exist
+async fn read_entries(path: &Path) -> Result<BTreeMap<OsString, Metadata>, FileIOError> {
+	let mut entries = BTreeMap::new();
+
+	let mut read_dir = match fs::read_dir(path).await {
+		Ok(read_dir) => read_dir,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+		Err(e) => return Err(FileIOError::from((path, e))),
+	};
+
+	while let Some(entry) = read_dir
+		.next_entry()
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?
+	{
+		let metadata = fs::symlink_metadata(entry.path())
+			.await
+			.map_err(|e| FileIOError::from((entry.path(), e)))?;
+
+		entries.insert(entry.file_name(), metadata);
+	}
+
+	Ok(entries)
+}
+
+/// Works out what must change for `destination` to mirror `source`, parent directories coming
+/// before their contents and deletions before what replaces them
+async fn diff(
+	source: &Path,
+	destination: &Path,
+	delete_extraneous: bool,
+) -> Result<(Vec<MirrorAction>, MirrorSkipped), FileIOError> {
+	let mut actions = vec![];
+	let mut skipped = MirrorSkipped::default();
+	let mut directories = vec![PathBuf::new()];
+
+	while let Some(relative_dir) = directories.pop() {
+		let mut source_entries = read_entries(&source.join(&relative_dir)).await?;
+		let mut destination_entries = read_entries(&destination.join(&relative_dir)).await?;
+
+		if relative_dir.as_os_str().is_empty() {
+			// Each location has its own metadata file at its root
+			source_entries.remove(OsStr::new(SPACEDRIVE_LOCATION_METADATA_FILE));
+			destination_entries.remove(OsStr::new(SPACEDRIVE_LOCATION_METADATA_FILE));
+		}
+		// Left by an interrupted run, they're replaced by the next copy
+		destination_entries.retain(|name, _| !is_mirror_copy(name));
+
+		for (name, source_metadata) in &source_entries {
+			let relative_path = relative_dir.join(name);
+			let destination_metadata = destination_entries.remove(name);
+
+			if source_metadata.is_symlink() {
+				skipped.symlinks.push(relative_path);
+				continue;
+			}
+
+			let is_dir = source_metadata.is_dir();
+
+			match destination_metadata {
+				Some(destination_metadata) if destination_metadata.is_dir() == is_dir => {
+					if is_dir {
+						directories.push(relative_path);
+					} else if is_changed(source_metadata, &destination_metadata) {
+						actions.push(MirrorAction::Copy {
+							relative_path,
+							size: source_metadata.len(),
+							is_update: true,
+						});
+					}
+
+					continue;
+				}
+				Some(destination_metadata) => {
+					if !delete_extraneous {
+						skipped.conflicts.push(relative_path);
+						continue;
+					}
+
+					actions.push(MirrorAction::Delete {
+						relative_path: relative_path.clone(),
+						is_dir: destination_metadata.is_dir(),
+					});
+				}
+				None => {}
+			}
+
+			if is_dir {
+				actions.push(MirrorAction::CreateDirectory(relative_path.clone()));
+				directories.push(relative_path);
+			} else {
+				actions.push(MirrorAction::Copy {
+					relative_path,
+					size: source_metadata.len(),
+					is_update: false,
+				});
+			}
+		}
+
+		if delete_extraneous {
+			actions.extend(destination_entries.into_iter().map(|(name, metadata)| {
+				MirrorAction::Delete {
+					relative_path: relative_dir.join(name),
+					is_dir: metadata.is_dir(),
+				}
+			}));
+		}
+	}
+
+	Ok((actions, skipped))
+}
+
+/// The differences for the report, each kind of change counted and its first paths listed
+fn diff_report(actions: &[MirrorAction], skipped: &MirrorSkipped) -> serde_json::Value {
+	let paths = |filter: fn(&MirrorAction) -> Option<&PathBuf>| {
+		let paths = actions.iter().filter_map(filter).collect::<Vec<_>>();
+		json!({
+			"count": paths.len(),
+			"paths": paths.into_iter().take(REPORTED_PATHS_LIMIT).collect::<Vec<_>>(),
+		})
+	};
+
+	json!({
+		"new_directories": paths(|action| match action {
+			MirrorAction::CreateDirectory(relative_path) => Some(relative_path),
+			_ => None,
+		}),
+		"new_files": paths(|action| match action {
+			MirrorAction::Copy { relative_path, is_update: false, .. } => Some(relative_path),
+			_ => None,
+		}),
+		"changed_files": paths(|action| match action {
+			MirrorAction::Copy { relative_path, is_update: true, .. } => Some(relative_path),
+			_ => None,
+		}),
+		"deleted": paths(|action| match action {
+			MirrorAction::Delete { relative_path, .. } => Some(relative_path),
+			_ => None,
+		}),
+		"bytes_to_copy": actions
+			.iter()
+			.map(|action| match action {
+				MirrorAction::Copy { size, .. } => *size,
+				_ => 0,
+			})
+			.sum::<u64>(),
+		"conflicts": skipped.conflicts.iter().take(REPORTED_PATHS_LIMIT).collect::<Vec<_>>(),
+		"skipped_symlinks": skipped.symlinks.iter().take(REPORTED_PATHS_LIMIT).collect::<Vec<_>>(),
+	})
+}
+
+/// Copies a file through a file aside, so an interrupted copy never replaces the older version
+async fn copy_file(source: &Path, target: &Path) -> Result<u64, FileIOError> {
+	let mut copy_name = target.file_name().map(OsString::from).unwrap_or_default();
+	copy_name.push(".");
+	copy_name.push(MIRROR_COPY_EXTENSION);
+	let copy_path = target.with_file_name(copy_name);
+
+	let size = fs::copy(source, &copy_path)
+		.await
+		.map_err(|e| FileIOError::from((&copy_path, e)))?;
+
+	let source_metadata = fs::metadata(source)
+		.await
+		.map_err(|e| FileIOError::from((source, e)))?;
+	set_file_mtime(
+		&copy_path,
+		FileTime::from_last_modification_time(&source_metadata),
+	)
+	.map_err(|e| FileIOError::from((&copy_path, e)))?;
+
+	fs::rename(&copy_path, target)
+		.await
+		.map_err(|e| FileIOError::from((target, e)))?;
+
+	Ok(size)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for MirrorJob {
+	type Init = MirrorJobInit;
+	type Data = MirrorJobData;
+	type Step = MirrorAction;
+	type RunMetadata = MirrorJobRunMetadata;
+
+	const NAME: &'static str = "mirror";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+		let source_path = match &init.sub_path {
+			Some(sub_path) => {
+				ensure_sub_path_is_directory(&location_path, sub_path)
+					.await
+					.map_err(FileSystemJobsError::from)?;
+
+				push_location_relative_path(location_path, sub_path)
+			}
+			None => location_path,
+		};
+
+		let destination_path = match &init.destination {
+			MirrorDestination::Location {
+				location_id,
+				sub_path,
+			} => push_location_relative_path(
+				get_location_path_from_location_id(db, *location_id).await?,
+				sub_path,
+			),
+			MirrorDestination::Path { path } => path.clone(),
+		};
+
+		// Mirroring a directory into itself would never end, and the other way around would
+		// delete the source
+		if destination_path.starts_with(&source_path) || source_path.starts_with(&destination_path)
+		{
+			return Err(FileSystemJobsError::MirrorOverlap {
+				source_path: source_path.into_boxed_path(),
+				destination_path: destination_path.into_boxed_path(),
+			}
+			.into());
+		}
+
+		ctx.progress_msg(job_message!(
+			"mirror.comparing",
+			source = source_path.display(),
+			destination = destination_path.display()
+		));
+
+		let (actions, skipped) =
+			diff(&source_path, &destination_path, init.delete_extraneous).await?;
+
+		let diff_report = diff_report(&actions, &skipped);
+
+		let steps = if init.dry_run {
+			vec![]
+		} else {
+			fs::create_dir_all(&destination_path)
+				.await
+				.map_err(|e| FileIOError::from((&destination_path, e)))?;
+
+			actions
+		};
+
+		*data = Some(MirrorJobData {
+			source_path,
+			destination_path,
+			diff_report,
+		});
+
+		Ok((MirrorJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let mut new_metadata = MirrorJobRunMetadata::default();
+
+		let result = match step {
+			MirrorAction::CreateDirectory(relative_path) => {
+				let path = data.destination_path.join(relative_path);
+				new_metadata.directories_created = 1;
+
+				fs::create_dir_all(&path)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))
+			}
+			MirrorAction::Copy {
+				relative_path,
+				is_update,
+				..
+			} => {
+				let source = data.source_path.join(relative_path);
+				let target = data.destination_path.join(relative_path);
+
+				ctx.progress_msg(job_message!(
+					"mirror.copying",
+					path = relative_path.display()
+				));
+
+				copy_file(&source, &target).await.map(|size| {
+					trace!("Mirrored {} to {}", source.display(), target.display());
+
+					if *is_update {
+						new_metadata.files_updated = 1;
+					} else {
+						new_metadata.files_copied = 1;
+					}
+					new_metadata.bytes_copied = size;
+				})
+			}
+			MirrorAction::Delete {
+				relative_path,
+				is_dir,
+			} => {
+				let path = data.destination_path.join(relative_path);
+				new_metadata.paths_deleted = 1;
+
+				let removed = if *is_dir {
+					fs::remove_dir_all(&path).await
+				} else {
+					fs::remove_file(&path).await
+				};
+
+				match removed {
+					Ok(()) => Ok(()),
+					Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+					Err(e) => Err(FileIOError::from((path, e))),
+				}
+			}
+		};
+
+		// A path that can't be mirrored doesn't stop the others from being mirrored
+		Ok(match result {
+			Ok(()) => new_metadata.into(),
+			Err(e) => {
+				warn!("Failed to mirror: {e:#?}");
+				JobRunErrors(vec![e.to_string()]).into()
+			}
+		})
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		if state.init.dry_run {
+			info!("Worked out the differences of a mirror without changing anything");
+		} else {
+			info!(
+				"Mirrored {} new files and {} changed ones, {} bytes, created {} directories and deleted {} paths",
+				metadata.files_copied,
+				metadata.files_updated,
+				metadata.bytes_copied,
+				metadata.directories_created,
+				metadata.paths_deleted
+			);
+
+			invalidate_query!(ctx.library, "search.paths");
+		}
+
+		Ok(Some(json!({
+			"init": state.init,
+			"diff": state.data.as_ref().map(|data| &data.diff_report),
+			"files_copied": metadata.files_copied,
+			"files_updated": metadata.files_updated,
+			"bytes_copied": metadata.bytes_copied,
+			"directories_created": metadata.directories_created,
+			"paths_deleted": metadata.paths_deleted,
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn finds_what_changed() {
+		let source = tempfile::tempdir().unwrap();
+		let destination = tempfile::tempdir().unwrap();
+		let (source, destination) = (source.path(), destination.path());
+
+		fs::write(source.join(SPACEDRIVE_LOCATION_METADATA_FILE), b"source")
+			.await
+			.unwrap();
+		fs::write(source.join("same.txt"), b"same").await.unwrap();
+		fs::write(source.join("changed.txt"), b"new contents")
+			.await
+			.unwrap();
+		fs::create_dir(source.join("photos")).await.unwrap();
+		fs::write(source.join("photos/cat.jpg"), b"meow")
+			.await
+			.unwrap();
+
+		fs::write(
+			destination.join(SPACEDRIVE_LOCATION_METADATA_FILE),
+			b"destination",
+		)
+		.await
+		.unwrap();
+		copy_file(&source.join("same.txt"), &destination.join("same.txt"))
+			.await
+			.unwrap();
+		fs::write(destination.join("changed.txt"), b"old")
+			.await
+			.unwrap();
+		fs::write(destination.join("extra.txt"), b"extra")
+			.await
+			.unwrap();
+
+		let (actions, skipped) = diff(source, destination, false).await.unwrap();
+		assert_eq!(skipped, MirrorSkipped::default());
+		assert_eq!(
+			actions,
+			[
+				MirrorAction::Copy {
+					relative_path: "changed.txt".into(),
+					size: 12,
+					is_update: true,
+				},
+				MirrorAction::CreateDirectory("photos".into()),
+				MirrorAction::Copy {
+					relative_path: "photos/cat.jpg".into(),
+					size: 4,
+					is_update: false,
+				},
+			]
+		);
+
+		let (actions, _) = diff(source, destination, true).await.unwrap();
+		assert!(actions.contains(&MirrorAction::Delete {
+			relative_path: "extra.txt".into(),
+			is_dir: false,
+		}));
+		// The metadata files of the locations are never touched
+		assert_eq!(actions.len(), 4);
+	}
+
+	#[tokio::test]
+	async fn keeps_conflicts_unless_deleting() {
+		let source = tempfile::tempdir().unwrap();
+		let destination = tempfile::tempdir().unwrap();
+		let (source, destination) = (source.path(), destination.path());
+
+		fs::write(source.join("notes"), b"a file").await.unwrap();
+		fs::create_dir(destination.join("notes")).await.unwrap();
+
+		let (actions, skipped) = diff(source, destination, false).await.unwrap();
+		assert!(actions.is_empty());
+		assert_eq!(skipped.conflicts, [PathBuf::from("notes")]);
+
+		let (actions, _) = diff(source, destination, true).await.unwrap();
+		assert_eq!(
+			actions,
+			[
+				MirrorAction::Delete {
+					relative_path: "notes".into(),
+					is_dir: true,
+				},
+				MirrorAction::Copy {
+					relative_path: "notes".into(),
+					size: 6,
+					is_update: false,
+				},
+			]
+		);
+	}
+}
@@ -1,31 +1,50 @@
 use crate::{
+	library::Library,
 	location::{
-		file_path_helper::{file_path_with_object, IsolatedFilePathData},
+		file_path_helper::{
+			create_file_path, file_path_with_object, filter_existing_file_path_params,
+			get_inode_and_device_from_path, FilePathMetadata, IsolatedFilePathData, MetadataExt,
+		},
 		LocationError,
 	},
-	prisma::{file_path, location, PrismaClient},
+	object::file_identifier::FileMetadata,
+	prisma::{file_path, location, object, PrismaClient},
 	util::db::{maybe_missing, MissingFieldError},
 };
 
 use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
+pub mod archive;
+mod attributes;
+pub mod batch_rename;
+pub mod convert;
 pub mod create;
 pub mod delete;
 pub mod erase;
+pub mod inbox;
 
 pub mod copy;
 pub mod cut;
+pub mod dedup;
+pub mod mirror;
+pub mod mover;
+pub mod os_trash;
+mod reflink;
+pub mod split;
+pub mod transcode;
 
-// pub mod decrypt;
-// pub mod encrypt;
+pub mod decrypt;
+pub mod encrypt;
 
 pub mod error;
 
 use error::FileSystemJobsError;
 
-// pub const BYTES_EXT: &str = ".bytes";
+pub const BYTES_EXT: &str = ".bytes";
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum ObjectType {
@@ -140,6 +159,99 @@ pub async fn fetch_source_and_target_location_paths(
 	}
 }
 
+/// Indexes a file a job wrote to a location, if the watcher didn't already, and gives it an object
+/// if it has none. Returns the id of its object.
+pub(super) async fn index_new_file(
+	library: &Library,
+	location_id: location::id::Type,
+	location_path: impl AsRef<Path>,
+	full_path: impl AsRef<Path>,
+) -> Result<object::id::Type, FileSystemJobsError> {
+	let db = &library.db;
+	let location_path = location_path.as_ref();
+	let full_path = full_path.as_ref();
+
+	let iso_file_path = IsolatedFilePathData::new(location_id, location_path, full_path, false)?;
+
+	let FileMetadata {
+		cas_id,
+		kind,
+		fs_metadata,
+	} = FileMetadata::new(location_path, &iso_file_path).await?;
+
+	let existing_file_path = db
+		.file_path()
+		.find_first(filter_existing_file_path_params(&iso_file_path))
+		.exec()
+		.await?;
+
+	let (file_path_id, object_id) = match existing_file_path {
+		Some(file_path) => (file_path.id, file_path.object_id),
+		None => {
+			let (inode, device) = get_inode_and_device_from_path(full_path).await?;
+
+			let file_path = create_file_path(
+				library,
+				iso_file_path,
+				Some(cas_id),
+				FilePathMetadata {
+					inode,
+					device,
+					size_in_bytes: fs_metadata.len(),
+					created_at: fs_metadata.created_or_now().into(),
+					modified_at: fs_metadata.modified_or_now().into(),
+				},
+			)
+			.await?;
+
+			(file_path.id, None)
+		}
+	};
+
+	if let Some(object_id) = object_id {
+		return Ok(object_id);
+	}
+
+	let object = db
+		.object()
+		.create(
+			Uuid::new_v4().as_bytes().to_vec(),
+			vec![
+				object::date_created::set(Some(
+					DateTime::<Local>::from(fs_metadata.created_or_now()).into(),
+				)),
+				object::kind::set(Some(kind as i32)),
+			],
+		)
+		.select(object::select!({ id }))
+		.exec()
+		.await?;
+
+	db.file_path()
+		.update(
+			file_path::id::equals(file_path_id),
+			vec![file_path::object::connect(object::id::equals(object.id))],
+		)
+		.exec()
+		.await?;
+
+	Ok(object.id)
+}
+
+/// The first of "name (1).ext", "name (2).ext"... not taken yet
+pub(super) fn available_path(path: &Path) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let extension = path
+		.extension()
+		.map(|extension| format!(".{}", extension.to_string_lossy()))
+		.unwrap_or_default();
+
+	(1..)
+		.map(|i| path.with_file_name(format!("{stem} ({i}){extension}")))
+		.find(|path| std::fs::symlink_metadata(path).is_err())
+		.expect("there's always a free name")
+}
+
 fn construct_target_filename(
 	source_file_data: &FileData,
 	target_file_name_suffix: &Option<String>,
@@ -0,0 +1,460 @@
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::{
+		journal::{self, FileOperation},
+		Library,
+	},
+	location::file_path_helper::{
+		file_path_with_object, isolated_file_path_data::extract_normalized_materialized_path_str,
+		push_location_relative_path, IsolatedFilePathData,
+	},
+	object::{
+		cas::generate_cas_id,
+		fs::{construct_target_filename, error::FileSystemJobsError},
+	},
+	prisma::{file_path, location, PrismaClient},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	hash::Hash,
+	path::{Path, PathBuf},
+};
+
+use prisma_client_rust::{raw, PrismaValue};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{info, trace, warn};
+
+use super::{
+	attributes::{preserve_attributes, AttributesNotPreserved},
+	fetch_source_and_target_location_paths, get_many_files_datas, FileData,
+};
+
+/// `EXDEV`, a rename can't move a file to another file system
+#[cfg(not(windows))]
+const CROSS_DEVICE_ERROR: i32 = 18;
+/// `ERROR_NOT_SAME_DEVICE`, a rename can't move a file to another drive
+#[cfg(windows)]
+const CROSS_DEVICE_ERROR: i32 = 17;
+
+/// Moves files to another directory, maybe of another location, keeping their file_path rows.
+/// Unlike the cutter job, which leaves the watchers to index the files again at their new place,
+/// the objects of the moved files keep their tags, notes and history.
+pub struct FileMoverJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileMoverJobInit {
+	pub source_location_id: location::id::Type,
+	pub target_location_id: location::id::Type,
+	pub sources_file_path_ids: Vec<file_path::id::Type>,
+	pub target_location_relative_directory_path: PathBuf,
+	/// Gives files moved to another device the timestamps, permissions and extended attributes
+	/// they had, where the filesystem they're moved to supports them. Renamed files keep them all.
+	#[serde(default)]
+	#[specta(optional)]
+	pub preserve_metadata: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileMoverJobData {
+	sources_location_path: PathBuf,
+	targets_location_path: PathBuf,
+	full_target_directory_path: PathBuf,
+}
+
+impl JobInitData for FileMoverJobInit {
+	type Job = FileMoverJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.source_location_id, self.target_location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileMoverJobRunMetadata {
+	/// Moved by a rename, on the same device
+	files_renamed: u32,
+	/// Copied to another device, verified, then removed from the source
+	files_copied: u32,
+	/// file_path rows moved along with the files, including the ones inside moved directories
+	file_paths_moved: u64,
+	/// How many files each attribute couldn't be preserved for, when preserving metadata
+	#[serde(default)]
+	attributes_not_preserved: AttributesNotPreserved,
+}
+
+impl JobRunMetadata for FileMoverJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.files_renamed += new_data.files_renamed;
+		self.files_copied += new_data.files_copied;
+		self.file_paths_moved += new_data.file_paths_moved;
+		self.attributes_not_preserved
+			.merge(new_data.attributes_not_preserved);
+	}
+}
+
+/// Copies `source` to `target`, checking the content id of every file copied. Returns the first
+/// copy that doesn't match its source, if any. With `attributes_not_preserved`, the copies get the
+/// attributes of their sources, the ones that couldn't be preserved are counted in it.
+async fn copy_verified(
+	source: &Path,
+	target: &Path,
+	mut attributes_not_preserved: Option<&mut AttributesNotPreserved>,
+) -> Result<Option<PathBuf>, FileIOError> {
+	let mut pending = vec![(source.to_path_buf(), target.to_path_buf())];
+	let mut directories = vec![];
+
+	while let Some((source, target)) = pending.pop() {
+		let metadata = fs::metadata(&source)
+			.await
+			.map_err(|e| FileIOError::from((&source, e)))?;
+
+		if metadata.is_dir() {
+			fs::create_dir_all(&target)
+				.await
+				.map_err(|e| FileIOError::from((&target, e)))?;
+
+			let mut read_dir = fs::read_dir(&source)
+				.await
+				.map_err(|e| FileIOError::from((&source, e)))?;
+
+			while let Some(entry) = read_dir
+				.next_entry()
+				.await
+				.map_err(|e| FileIOError::from((&source, e)))?
+			{
+				pending.push((entry.path(), target.join(entry.file_name())));
+			}
+
+			directories.push((source, target));
+		} else {
+			fs::copy(&source, &target)
+				.await
+				.map_err(|e| FileIOError::from((&target, e)))?;
+
+			let size = metadata.len();
+			let source_cas_id = generate_cas_id(&source, size)
+				.await
+				.map_err(|e| FileIOError::from((&source, e)))?;
+			let target_cas_id = generate_cas_id(&target, size)
+				.await
+				.map_err(|e| FileIOError::from((&target, e)))?;
+
+			if source_cas_id != target_cas_id {
+				return Ok(Some(target));
+			}
+
+			if let Some(report) = attributes_not_preserved.as_deref_mut() {
+				report.add(
+					preserve_attributes(&source, &target, true)
+						.await
+						.map_err(|e| FileIOError::from((&source, e)))?,
+				);
+			}
+		}
+	}
+
+	// Once everything is copied, as the modification dates of directories change with their contents
+	if let Some(report) = attributes_not_preserved {
+		for (source, target) in directories.into_iter().rev() {
+			report.add(
+				preserve_attributes(&source, &target, true)
+					.await
+					.map_err(|e| FileIOError::from((&source, e)))?,
+			);
+		}
+	}
+
+	Ok(None)
+}
+
+async fn remove_path(path: &Path, is_dir: bool) -> Result<(), FileIOError> {
+	if is_dir {
+		fs::remove_dir_all(path).await
+	} else {
+		fs::remove_file(path).await
+	}
+	.map_err(|e| FileIOError::from((path, e)))
+}
+
+/// Points the file_path of a moved file, and the ones inside it for a directory, to their new
+/// place. The rows of a directory are rewritten by a single statement, so they all move or none.
+/// Returns how many rows were moved.
+async fn move_file_paths(
+	db: &PrismaClient,
+	file_path: &file_path_with_object::Data,
+	target_location_id: location::id::Type,
+	source_iso_file_path: &IsolatedFilePathData<'_>,
+	target_iso_file_path: &IsolatedFilePathData<'_>,
+	target_materialized_path: String,
+) -> Result<u64, FileSystemJobsError> {
+	// The watcher of the target location may have indexed the new files before we got here, their
+	// rows would be duplicates of the ones we are moving
+	db.file_path()
+		.delete_many(vec![
+			file_path::location_id::equals(Some(target_location_id)),
+			file_path::materialized_path::equals(Some(target_materialized_path.clone())),
+			file_path::name::equals(file_path.name.clone()),
+			file_path::extension::equals(file_path.extension.clone()),
+		])
+		.exec()
+		.await?;
+
+	let (Some(source_prefix), Some(target_prefix)) = (
+		source_iso_file_path.materialized_path_for_children(),
+		target_iso_file_path.materialized_path_for_children(),
+	) else {
+		db.file_path()
+			.update(
+				file_path::id::equals(file_path.id),
+				vec![
+					file_path::location::connect(location::id::equals(target_location_id)),
+					file_path::materialized_path::set(Some(target_materialized_path)),
+				],
+			)
+			.exec()
+			.await?;
+
+		return Ok(1);
+	};
+
+	db.file_path()
+		.delete_many(vec![
+			file_path::location_id::equals(Some(target_location_id)),
+			file_path::materialized_path::starts_with(target_prefix.clone()),
+		])
+		.exec()
+		.await?;
+
+	let source_location_id = maybe_missing(file_path.location_id, "file_path.location_id")?;
+
+	let moved = db
+		._execute_raw(raw!(
+			"UPDATE file_path \
+				SET location_id = {}, \
+					materialized_path = CASE WHEN id = {} THEN {} \
+						ELSE {} || substr(materialized_path, {}) END \
+				WHERE id = {} OR (location_id = {} AND substr(materialized_path, 1, {}) = {})",
+			PrismaValue::Int(target_location_id as i64),
+			PrismaValue::Int(file_path.id as i64),
+			PrismaValue::String(target_materialized_path),
+			PrismaValue::String(target_prefix),
+			// SQLite counts characters, not bytes, and starts at 1
+			PrismaValue::Int(source_prefix.chars().count() as i64 + 1),
+			PrismaValue::Int(file_path.id as i64),
+			PrismaValue::Int(source_location_id as i64),
+			PrismaValue::Int(source_prefix.chars().count() as i64),
+			PrismaValue::String(source_prefix)
+		))
+		.exec()
+		.await?;
+
+	Ok(moved as u64)
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileMoverJob {
+	type Init = FileMoverJobInit;
+	type Data = FileMoverJobData;
+	type Step = FileData;
+	type RunMetadata = FileMoverJobRunMetadata;
+
+	const NAME: &'static str = "file_mover";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &ctx.library;
+
+		let (sources_location_path, targets_location_path) =
+			fetch_source_and_target_location_paths(
+				db,
+				init.source_location_id,
+				init.target_location_id,
+			)
+			.await?;
+
+		let full_target_directory_path = push_location_relative_path(
+			targets_location_path.clone(),
+			&init.target_location_relative_directory_path,
+		);
+
+		let steps =
+			get_many_files_datas(db, &sources_location_path, &init.sources_file_path_ids).await?;
+
+		*data = Some(FileMoverJobData {
+			sources_location_path,
+			targets_location_path,
+			full_target_directory_path,
+		});
+
+		Ok((FileMoverJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep {
+			step: file_data, ..
+		}: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let is_dir = maybe_missing(file_data.file_path.is_dir, "file_path.is_dir")?;
+
+		let full_output = data
+			.full_target_directory_path
+			.join(construct_target_filename(file_data, &None)?);
+
+		if file_data.full_path == full_output {
+			// File is already here, do nothing
+			return Ok(None.into());
+		}
+
+		match fs::metadata(&full_output).await {
+			Ok(_) => {
+				warn!(
+					"Skipping {} as it would be overwritten",
+					full_output.display()
+				);
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					full_output.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((&full_output, e)).into()),
+		}
+
+		// Before touching the disk, so a bad path fails the step with nothing moved
+		let source_iso_file_path = IsolatedFilePathData::new(
+			init.source_location_id,
+			&data.sources_location_path,
+			&file_data.full_path,
+			is_dir,
+		)
+		.map_err(FileSystemJobsError::from)?;
+		let target_iso_file_path = IsolatedFilePathData::new(
+			init.target_location_id,
+			&data.targets_location_path,
+			&full_output,
+			is_dir,
+		)
+		.map_err(FileSystemJobsError::from)?;
+		let target_materialized_path = extract_normalized_materialized_path_str(
+			init.target_location_id,
+			&data.targets_location_path,
+			&full_output,
+		)
+		.map_err(FileSystemJobsError::from)?;
+
+		let mut new_metadata = FileMoverJobRunMetadata::default();
+
+		match fs::rename(&file_data.full_path, &full_output).await {
+			Ok(()) => {
+				trace!(
+					"Renamed {} to {}",
+					file_data.full_path.display(),
+					full_output.display()
+				);
+
+				new_metadata.files_renamed = 1;
+			}
+			Err(e) if e.raw_os_error() == Some(CROSS_DEVICE_ERROR) => {
+				trace!(
+					"Moving {} to {} on another device",
+					file_data.full_path.display(),
+					full_output.display()
+				);
+
+				ctx.progress_msg(job_message!(
+					"move.moving",
+					path = file_data.full_path.display()
+				));
+
+				if let Some(mismatch) = copy_verified(
+					&file_data.full_path,
+					&full_output,
+					init.preserve_metadata
+						.then_some(&mut new_metadata.attributes_not_preserved),
+				)
+				.await?
+				{
+					warn!(
+						"Removing {} as {} doesn't match its source",
+						full_output.display(),
+						mismatch.display()
+					);
+
+					remove_path(&full_output, is_dir).await?;
+
+					return Ok(JobRunErrors(vec![FileSystemJobsError::CopyVerification(
+						mismatch.into_boxed_path(),
+					)
+					.to_string()])
+					.into());
+				}
+
+				remove_path(&file_data.full_path, is_dir).await?;
+
+				new_metadata.files_copied = 1;
+			}
+			Err(e) => return Err(FileIOError::from((&file_data.full_path, e)).into()),
+		}
+
+		journal::record(
+			&ctx.library,
+			ctx.job_id,
+			vec![FileOperation::moved(&file_data.full_path, &full_output)],
+		)
+		.await;
+
+		new_metadata.file_paths_moved = move_file_paths(
+			&ctx.library.db,
+			&file_data.file_path,
+			init.target_location_id,
+			&source_iso_file_path,
+			&target_iso_file_path,
+			target_materialized_path,
+		)
+		.await?;
+
+		Ok(new_metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Moved {} files by renaming and {} by copying, {} file_paths moved along",
+			metadata.files_renamed, metadata.files_copied, metadata.file_paths_moved
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(json!({
+			"init": state.init,
+			"attributes_not_preserved": metadata.attributes_not_preserved,
+		})))
+	}
+}
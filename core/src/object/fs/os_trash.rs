@@ -0,0 +1,397 @@
+//! Deleting files to the trash of the operating system, so they can be put back from there or from
+//! Spacedrive. Linux and the other freedesktop systems follow the trash specification, macOS uses
+//! the `.Trash` of the user. Files on another volume than the user's home go to the trash at the
+//! root of their volume, like the file managers do.
+//!
+//! The Recycle Bin of Windows is only reachable through the shell API, which we don't bind, so
+//! files can only be deleted permanently there.
+
+use crate::util::error::FileIOError;
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum OsTrashError {
+	#[error("the trash of the operating system isn't supported on this platform")]
+	Unsupported,
+	#[error("no trash can hold: {}", .0.display())]
+	NoTrash(Box<Path>),
+	#[error("a file already exists where the trashed file was: {}", .0.display())]
+	WouldOverwrite(Box<Path>),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<OsTrashError> for rspc::Error {
+	fn from(e: OsTrashError) -> Self {
+		let code = match e {
+			OsTrashError::Unsupported => rspc::ErrorCode::MethodNotSupported,
+			OsTrashError::WouldOverwrite(_) => rspc::ErrorCode::Conflict,
+			OsTrashError::NoTrash(_) | OsTrashError::FileIO(_) => {
+				rspc::ErrorCode::InternalServerError
+			}
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Moves `path` to the trash, returning where it is now
+pub async fn trash(path: &Path) -> Result<PathBuf, OsTrashError> {
+	platform::trash(path).await
+}
+
+/// Moves a file back from the trash to where it was
+pub async fn restore(trash_path: &Path, original_path: &Path) -> Result<(), OsTrashError> {
+	platform::restore(trash_path, original_path).await
+}
+
+#[cfg(unix)]
+mod unix {
+	use super::OsTrashError;
+
+	use crate::util::error::FileIOError;
+
+	use std::{
+		env,
+		os::unix::fs::MetadataExt,
+		path::{Path, PathBuf},
+	};
+
+	use tokio::{fs, io};
+
+	pub fn home_dir() -> Option<PathBuf> {
+		env::var_os("HOME").map(PathBuf::from)
+	}
+
+	/// The directory `path` is mounted under, the furthest ancestor on the same device
+	pub async fn mount_root(path: &Path, device: u64) -> PathBuf {
+		let mut root = path.to_path_buf();
+		for ancestor in path.ancestors().skip(1) {
+			match fs::metadata(ancestor).await {
+				Ok(metadata) if metadata.dev() == device => root = ancestor.to_path_buf(),
+				_ => break,
+			}
+		}
+
+		root
+	}
+
+	/// The trash to use for `path`: `home_trash` if they are on the same device, or a trash at the
+	/// root of the volume of `path`, named by `volume_trash_name` from the user id
+	pub async fn trash_dir_for(
+		path: &Path,
+		home_trash: PathBuf,
+		volume_trash_name: impl FnOnce(u32) -> PathBuf,
+	) -> Result<PathBuf, OsTrashError> {
+		let device = fs::symlink_metadata(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?
+			.dev();
+
+		fs::create_dir_all(&home_trash)
+			.await
+			.map_err(|e| FileIOError::from((&home_trash, e)))?;
+		let home_trash_metadata = fs::metadata(&home_trash)
+			.await
+			.map_err(|e| FileIOError::from((&home_trash, e)))?;
+
+		if home_trash_metadata.dev() == device {
+			return Ok(home_trash);
+		}
+
+		// We own our home trash, it tells our user id without reaching for libc
+		let trash_dir = mount_root(path, device)
+			.await
+			.join(volume_trash_name(home_trash_metadata.uid()));
+
+		match fs::create_dir_all(&trash_dir).await {
+			Ok(()) => Ok(trash_dir),
+			Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+				Err(OsTrashError::NoTrash(path.into()))
+			}
+			Err(e) => Err(FileIOError::from((trash_dir, e)).into()),
+		}
+	}
+
+	/// Moves a file back from the trash, refusing to overwrite a file that took its place
+	pub async fn move_back(trash_path: &Path, original_path: &Path) -> Result<(), OsTrashError> {
+		match fs::symlink_metadata(original_path).await {
+			Ok(_) => return Err(OsTrashError::WouldOverwrite(original_path.into())),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((original_path, e)).into()),
+		}
+
+		if let Some(parent) = original_path.parent() {
+			fs::create_dir_all(parent)
+				.await
+				.map_err(|e| FileIOError::from((parent, e)))?;
+		}
+
+		fs::rename(trash_path, original_path)
+			.await
+			.map_err(|e| FileIOError::from((trash_path, e)).into())
+	}
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+	use super::{unix, OsTrashError};
+
+	use crate::util::error::FileIOError;
+
+	use std::{
+		env,
+		ffi::OsString,
+		os::unix::ffi::OsStrExt,
+		path::{Path, PathBuf},
+	};
+
+	use chrono::Local;
+	use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+	use tokio::{
+		fs::{self, OpenOptions},
+		io::{self, AsyncWriteExt},
+	};
+
+	/// The characters left as they are in the paths of trash info files, like in URIs
+	const PATH_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+		.remove(b'/')
+		.remove(b'-')
+		.remove(b'_')
+		.remove(b'.')
+		.remove(b'~');
+
+	fn home_trash() -> Option<PathBuf> {
+		env::var_os("XDG_DATA_HOME")
+			.map(PathBuf::from)
+			.or_else(|| unix::home_dir().map(|home| home.join(".local").join("share")))
+			.map(|data_home| data_home.join("Trash"))
+	}
+
+	fn trash_info_path(trash_dir: &Path, name: &OsString) -> PathBuf {
+		let mut info_name = name.clone();
+		info_name.push(".trashinfo");
+
+		trash_dir.join("info").join(info_name)
+	}
+
+	pub async fn trash(path: &Path) -> Result<PathBuf, OsTrashError> {
+		let home_trash = home_trash().ok_or_else(|| OsTrashError::NoTrash(path.into()))?;
+		trash_to(path, home_trash).await
+	}
+
+	/// Moves `path` to `home_trash`, or to the trash of its volume when it's on another one
+	pub async fn trash_to(path: &Path, home_trash: PathBuf) -> Result<PathBuf, OsTrashError> {
+		let trash_dir = unix::trash_dir_for(path, home_trash.clone(), |uid| {
+			PathBuf::from(format!(".Trash-{uid}"))
+		})
+		.await?;
+
+		for dir in [trash_dir.join("files"), trash_dir.join("info")] {
+			fs::create_dir_all(&dir)
+				.await
+				.map_err(|e| FileIOError::from((&dir, e)))?;
+		}
+
+		// The trash at the root of a volume holds paths relative to that root
+		let info_file_path = if trash_dir == home_trash {
+			path
+		} else {
+			trash_dir
+				.parent()
+				.and_then(|root| path.strip_prefix(root).ok())
+				.unwrap_or(path)
+		};
+		let info = format!(
+			"[Trash Info]\nPath={}\nDeletionDate={}\n",
+			utf8_percent_encode(
+				&String::from_utf8_lossy(info_file_path.as_os_str().as_bytes()),
+				PATH_ENCODE_SET
+			),
+			Local::now().format("%Y-%m-%dT%H:%M:%S")
+		);
+
+		let file_name = path
+			.file_name()
+			.ok_or_else(|| OsTrashError::NoTrash(path.into()))?;
+
+		// Creating the info file claims the name in the trash, as the specification asks
+		let mut attempt = 0;
+		let (name, info_path, mut info_file) = loop {
+			let mut name = file_name.to_os_string();
+			if attempt > 0 {
+				name.push(format!(".{attempt}"));
+			}
+			attempt += 1;
+
+			let info_path = trash_info_path(&trash_dir, &name);
+			match OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.open(&info_path)
+				.await
+			{
+				Ok(file) => {
+					if fs::symlink_metadata(trash_dir.join("files").join(&name))
+						.await
+						.is_err()
+					{
+						break (name, info_path, file);
+					}
+
+					// A file left in the trash without its info file
+					drop(file);
+					fs::remove_file(&info_path)
+						.await
+						.map_err(|e| FileIOError::from((&info_path, e)))?;
+				}
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+				Err(e) => return Err(FileIOError::from((info_path, e)).into()),
+			}
+		};
+
+		info_file
+			.write_all(info.as_bytes())
+			.await
+			.map_err(|e| FileIOError::from((&info_path, e)))?;
+
+		let trash_path = trash_dir.join("files").join(name);
+		if let Err(e) = fs::rename(path, &trash_path).await {
+			fs::remove_file(&info_path).await.ok();
+			return Err(FileIOError::from((path, e)).into());
+		}
+
+		Ok(trash_path)
+	}
+
+	pub async fn restore(trash_path: &Path, original_path: &Path) -> Result<(), OsTrashError> {
+		unix::move_back(trash_path, original_path).await?;
+
+		// trash_path is `{trash_dir}/files/{name}`
+		if let (Some(name), Some(trash_dir)) = (
+			trash_path.file_name(),
+			trash_path.parent().and_then(Path::parent),
+		) {
+			let info_path = trash_info_path(trash_dir, &name.to_os_string());
+			match fs::remove_file(&info_path).await {
+				Ok(()) => {}
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+				Err(e) => return Err(FileIOError::from((info_path, e)).into()),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+	use super::{unix, OsTrashError};
+
+	use crate::util::error::FileIOError;
+
+	use std::path::{Path, PathBuf};
+
+	use tokio::fs;
+
+	pub async fn trash(path: &Path) -> Result<PathBuf, OsTrashError> {
+		let home_trash = unix::home_dir()
+			.ok_or_else(|| OsTrashError::NoTrash(path.into()))?
+			.join(".Trash");
+		let trash_dir = unix::trash_dir_for(path, home_trash, |uid| {
+			PathBuf::from(".Trashes").join(uid.to_string())
+		})
+		.await?;
+
+		let file_name = path
+			.file_name()
+			.ok_or_else(|| OsTrashError::NoTrash(path.into()))?;
+
+		// Same naming as the Finder for a name already in the trash
+		let mut trash_path = trash_dir.join(file_name);
+		let mut attempt = 1;
+		while fs::symlink_metadata(&trash_path).await.is_ok() {
+			attempt += 1;
+			let mut name = file_name.to_os_string();
+			name.push(format!(" {attempt}"));
+			trash_path = trash_dir.join(name);
+		}
+
+		fs::rename(path, &trash_path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		Ok(trash_path)
+	}
+
+	pub async fn restore(trash_path: &Path, original_path: &Path) -> Result<(), OsTrashError> {
+		unix::move_back(trash_path, original_path).await
+	}
+}
+
+#[cfg(not(unix))]
+mod platform {
+	use super::OsTrashError;
+
+	use std::path::{Path, PathBuf};
+
+	pub async fn trash(_: &Path) -> Result<PathBuf, OsTrashError> {
+		Err(OsTrashError::Unsupported)
+	}
+
+	pub async fn restore(_: &Path, _: &Path) -> Result<(), OsTrashError> {
+		Err(OsTrashError::Unsupported)
+	}
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+	use super::*;
+
+	use tempfile::tempdir;
+	use tokio::fs;
+
+	#[tokio::test]
+	async fn trash_and_restore() {
+		let dir = tempdir().unwrap();
+		let home_trash = dir.path().join("Trash");
+
+		let file = dir.path().join("notes.txt");
+		fs::write(&file, b"hello").await.unwrap();
+
+		let trash_path = platform::trash_to(&file, home_trash.clone()).await.unwrap();
+		assert_eq!(trash_path, dir.path().join("Trash/files/notes.txt"));
+		assert!(fs::metadata(&file).await.is_err());
+
+		let info = fs::read_to_string(dir.path().join("Trash/info/notes.txt.trashinfo"))
+			.await
+			.unwrap();
+		assert!(info.starts_with("[Trash Info]\nPath=/"));
+
+		// A second file with the same name doesn't replace the first one
+		fs::write(&file, b"again").await.unwrap();
+		let second_trash_path = platform::trash_to(&file, home_trash).await.unwrap();
+		assert_eq!(
+			second_trash_path,
+			dir.path().join("Trash/files/notes.txt.1")
+		);
+
+		fs::write(&file, b"in the way").await.unwrap();
+		assert!(matches!(
+			restore(&trash_path, &file).await,
+			Err(OsTrashError::WouldOverwrite(_))
+		));
+
+		fs::remove_file(&file).await.unwrap();
+		restore(&trash_path, &file).await.unwrap();
+		assert_eq!(fs::read(&file).await.unwrap(), b"hello");
+		assert!(
+			fs::metadata(dir.path().join("Trash/info/notes.txt.trashinfo"))
+				.await
+				.is_err()
+		);
+	}
+}
@@ -0,0 +1,106 @@
+//! Copy-on-write clones of files, sharing their blocks with the original until either is written
+//! to. They only exist on filesystems that support them (APFS, Btrfs, XFS), for files on the same
+//! filesystem, anything else fails with [`io::ErrorKind::Unsupported`].
+
+use std::path::Path;
+
+use tokio::{io, task::spawn_blocking};
+
+/// Clones `source` to `target`, which mustn't exist. Nothing is left at `target` when it fails.
+pub(crate) async fn reflink(source: impl AsRef<Path>, target: impl AsRef<Path>) -> io::Result<()> {
+	let source = source.as_ref().to_path_buf();
+	let target = target.as_ref().to_path_buf();
+
+	spawn_blocking(move || clone_file(&source, &target))
+		.await
+		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+}
+
+/// Whether the error means the filesystem, or the pair of files, can't be cloned
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn unsupported(e: io::Error) -> io::Error {
+	// ENOTSUP is EOPNOTSUPP on Linux, macOS has both and clonefile returns ENOTSUP
+	match e.raw_os_error() {
+		Some(libc::EXDEV | libc::EINVAL | libc::ENOTTY | libc::ENOTSUP) => {
+			io::Error::new(io::ErrorKind::Unsupported, e)
+		}
+		_ => e,
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+	use std::{
+		fs::{self, File, OpenOptions},
+		os::fd::AsRawFd,
+	};
+
+	let source = File::open(source)?;
+	let target_file = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(target)?;
+
+	// SAFETY: both file descriptors stay open for the whole call
+	if unsafe { libc::ioctl(target_file.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == -1 {
+		let e = io::Error::last_os_error();
+		drop(target_file);
+		fs::remove_file(target).ok();
+
+		return Err(unsupported(e));
+	}
+
+	Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+	use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+	let source = CString::new(source.as_os_str().as_bytes())?;
+	let target = CString::new(target.as_os_str().as_bytes())?;
+
+	// SAFETY: both paths are nul terminated and outlive the call, clonefile doesn't keep them
+	if unsafe { libc::clonefile(source.as_ptr(), target.as_ptr(), 0) } == -1 {
+		return Err(unsupported(io::Error::last_os_error()));
+	}
+
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_: &Path, _: &Path) -> io::Result<()> {
+	Err(io::Error::new(
+		io::ErrorKind::Unsupported,
+		"cloning files isn't supported on this platform",
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::fs;
+
+	#[tokio::test]
+	async fn clones_or_leaves_nothing_behind() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("disk.img");
+		let target = dir.path().join("disk copy.img");
+		fs::write(&source, b"cloned blocks").await.unwrap();
+
+		// Whether the filesystem of the temporary directory can clone files depends on the machine
+		match reflink(&source, &target).await {
+			Ok(()) => assert_eq!(fs::read(&target).await.unwrap(), b"cloned blocks"),
+			Err(e) => {
+				assert_eq!(e.kind(), io::ErrorKind::Unsupported, "{e}");
+				assert!(fs::metadata(&target).await.is_err());
+			}
+		}
+
+		// The target is never overwritten
+		fs::write(&target, b"taken").await.unwrap();
+		assert!(reflink(&source, &target).await.is_err());
+		assert_eq!(fs::read(&target).await.unwrap(), b"taken");
+	}
+}
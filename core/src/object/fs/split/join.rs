@@ -0,0 +1,410 @@
+//! Joining the parts of a split file back, from its manifest. Each part is checked against the
+//! checksum the manifest has for it as it's appended, and the joined file against the content id
+//! of the file that was split.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	object::{
+		cas::generate_cas_id,
+		fs::{
+			convert::ConversionTarget, error::FileSystemJobsError,
+			get_location_path_from_location_id, get_many_files_datas, index_new_file,
+		},
+	},
+	prisma::{file_path, location},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	io::SeekFrom,
+	path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{
+	fs::{self, File, OpenOptions},
+	io::{self, AsyncSeekExt},
+};
+use tracing::{info, trace, warn};
+
+use super::{
+	copy_hashed, in_progress_path, link_derived_objects, SplitManifest, SPLIT_MANIFEST_VERSION,
+};
+
+pub struct FileJoinerJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileJoinerJobInit {
+	pub location_id: location::id::Type,
+	/// The manifest of the split file, its parts are in the same directory
+	pub file_path_id: file_path::id::Type,
+	pub target: ConversionTarget,
+}
+
+impl JobInitData for FileJoinerJobInit {
+	type Job = FileJoinerJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileJoinerJobData {
+	location_path: PathBuf,
+	parts_directory_path: PathBuf,
+	manifest: SplitManifest,
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	output_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum FileJoinerJobStep {
+	Part(usize),
+	Finish,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileJoinerJobRunMetadata {
+	parts_joined: u64,
+	bytes_written: u64,
+}
+
+impl JobRunMetadata for FileJoinerJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.parts_joined += new_data.parts_joined;
+		self.bytes_written += new_data.bytes_written;
+	}
+}
+
+/// Whether `name` is the name of a file, and not a path leading somewhere else
+fn is_plain_file_name(name: &str) -> bool {
+	let mut components = Path::new(name).components();
+
+	matches!(
+		(components.next(), components.next()),
+		(Some(Component::Normal(_)), None)
+	) && !name.contains(['/', '\\'])
+}
+
+/// Checks that the manifest is one this version can join, and that it can't write outside of the
+/// target directory or read outside of the directory of the parts
+fn validate_manifest(manifest: &SplitManifest) -> Result<(), String> {
+	if manifest.version > SPLIT_MANIFEST_VERSION {
+		return Err(format!("unknown version {}", manifest.version));
+	}
+
+	if !is_plain_file_name(&manifest.name) {
+		return Err(format!("invalid file name '{}'", manifest.name));
+	}
+
+	if let Some(part) = manifest
+		.parts
+		.iter()
+		.find(|part| !is_plain_file_name(&part.name))
+	{
+		return Err(format!("invalid part name '{}'", part.name));
+	}
+
+	if manifest.parts.iter().map(|part| part.size).sum::<u64>() != manifest.size {
+		return Err("the parts don't add up to the size of the file".to_string());
+	}
+
+	Ok(())
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileJoinerJob {
+	type Init = FileJoinerJobInit;
+	type Data = FileJoinerJobData;
+	type Step = FileJoinerJobStep;
+	type RunMetadata = FileJoinerJobRunMetadata;
+
+	const NAME: &'static str = "file_joiner";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+		let file_data = get_many_files_datas(db, &location_path, &[init.file_path_id])
+			.await?
+			.pop()
+			.ok_or(FileSystemJobsError::FilePathIdNotFound(init.file_path_id))?;
+
+		if maybe_missing(file_data.file_path.is_dir, "file_path.is_dir")? {
+			return Err(
+				FileSystemJobsError::NotAFile(file_data.full_path.into_boxed_path()).into(),
+			);
+		}
+
+		let manifest_path = file_data.full_path;
+		let manifest = serde_json::from_slice::<SplitManifest>(
+			&fs::read(&manifest_path)
+				.await
+				.map_err(|e| FileIOError::from((&manifest_path, e)))?,
+		)
+		.map_err(|e| e.to_string())
+		.and_then(|manifest| validate_manifest(&manifest).map(|()| manifest))
+		.map_err(|e| FileSystemJobsError::InvalidSplitManifest(manifest_path.clone().into(), e))?;
+
+		let parts_directory_path = manifest_path
+			.parent()
+			.map(Path::to_path_buf)
+			.unwrap_or_default();
+
+		// Missing parts are found before anything is written
+		for part in &manifest.parts {
+			let part_path = parts_directory_path.join(&part.name);
+			match fs::metadata(&part_path).await {
+				Ok(metadata) if metadata.len() == part.size => {}
+				Ok(_) => {
+					return Err(FileSystemJobsError::SplitPartMismatch(part_path.into()).into())
+				}
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {
+					return Err(FileSystemJobsError::SplitPartMismatch(part_path.into()).into())
+				}
+				Err(e) => return Err(FileIOError::from((part_path, e)).into()),
+			}
+		}
+
+		let (target_location_id, target_location_path, target_directory_path) = init
+			.target
+			.resolve(db, init.location_id, &location_path)
+			.await?;
+		let output_path = target_directory_path
+			.unwrap_or_else(|| parts_directory_path.clone())
+			.join(&manifest.name);
+
+		match fs::metadata(&output_path).await {
+			Ok(_) => {
+				return Err(
+					FileSystemJobsError::WouldOverwrite(output_path.into_boxed_path()).into(),
+				)
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((output_path, e)).into()),
+		}
+
+		let steps = (0..manifest.parts.len())
+			.map(FileJoinerJobStep::Part)
+			.chain([FileJoinerJobStep::Finish])
+			.collect::<Vec<_>>();
+
+		*data = Some(FileJoinerJobData {
+			location_path,
+			parts_directory_path,
+			manifest,
+			target_location_id,
+			target_location_path,
+			output_path,
+		});
+
+		Ok((FileJoinerJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let in_progress_path = in_progress_path(&data.output_path);
+
+		match step {
+			FileJoinerJobStep::Part(index) => {
+				let part = &data.manifest.parts[*index];
+				let part_path = data.parts_directory_path.join(&part.name);
+
+				ctx.progress_msg(job_message!(
+					"split.joiningPart",
+					part = index + 1,
+					parts = data.manifest.parts.len()
+				));
+
+				// Cut back to where this part starts, so a part interrupted halfway is appended
+				// again from its start
+				let offset = data.manifest.parts[..*index]
+					.iter()
+					.map(|part| part.size)
+					.sum::<u64>();
+
+				let mut output = OpenOptions::new()
+					.create(true)
+					.write(true)
+					.open(&in_progress_path)
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+				output
+					.set_len(offset)
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+				output
+					.seek(SeekFrom::Start(offset))
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+
+				let mut part_file = File::open(&part_path)
+					.await
+					.map_err(|e| FileIOError::from((&part_path, e)))?;
+
+				let checksum = copy_hashed(
+					&mut part_file,
+					&part_path,
+					&mut output,
+					&in_progress_path,
+					part.size,
+				)
+				.await?;
+
+				if checksum != part.checksum {
+					warn!("{} doesn't match its checksum", part_path.display());
+					drop(output);
+					fs::remove_file(&in_progress_path).await.ok();
+
+					return Err(FileSystemJobsError::SplitPartMismatch(part_path.into()).into());
+				}
+
+				output
+					.sync_all()
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+
+				trace!("Joined {}", part_path.display());
+
+				Ok(FileJoinerJobRunMetadata {
+					parts_joined: 1,
+					bytes_written: part.size,
+				}
+				.into())
+			}
+			FileJoinerJobStep::Finish => {
+				// Files that were split before being identified have nothing to be checked against
+				if let Some(cas_id) = &data.manifest.cas_id {
+					ctx.progress_msg(job_message!("split.verifying", name = data.manifest.name));
+
+					let joined_cas_id = generate_cas_id(&in_progress_path, data.manifest.size)
+						.await
+						.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+
+					if &joined_cas_id != cas_id {
+						fs::remove_file(&in_progress_path).await.ok();
+
+						return Err(FileSystemJobsError::JoinVerification(
+							data.output_path.clone().into_boxed_path(),
+						)
+						.into());
+					}
+				}
+
+				fs::rename(&in_progress_path, &data.output_path)
+					.await
+					.map_err(|e| FileIOError::from((&data.output_path, e)))?;
+
+				let mut part_ids = vec![];
+				for part in &data.manifest.parts {
+					part_ids.push(
+						index_new_file(
+							&ctx.library,
+							init.location_id,
+							&data.location_path,
+							data.parts_directory_path.join(&part.name),
+						)
+						.await?,
+					);
+				}
+				let joined_id = index_new_file(
+					&ctx.library,
+					data.target_location_id,
+					&data.target_location_path,
+					&data.output_path,
+				)
+				.await?;
+
+				link_derived_objects(&ctx.library, &[joined_id], &part_ids).await?;
+
+				Ok(None.into())
+			}
+		}
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Joined {} parts, {} bytes written",
+			metadata.parts_joined, metadata.bytes_written
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(json!({
+			"init": state.init,
+			"parts_joined": metadata.parts_joined,
+			"bytes_written": metadata.bytes_written,
+			"verified": state
+				.data
+				.as_ref()
+				.map_or(false, |data| data.manifest.cas_id.is_some()),
+		})))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{super::SplitPart, *};
+
+	fn manifest(name: &str, part_names: &[&str]) -> SplitManifest {
+		SplitManifest {
+			version: 1,
+			name: name.to_string(),
+			size: part_names.len() as u64,
+			part_size: 1,
+			cas_id: None,
+			parts: part_names
+				.iter()
+				.map(|name| SplitPart {
+					name: name.to_string(),
+					size: 1,
+					checksum: String::new(),
+				})
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn manifests_stay_in_their_directories() {
+		assert!(
+			validate_manifest(&manifest("movie.mkv", &["movie.mkv.001", "movie.mkv.002"])).is_ok()
+		);
+
+		assert!(validate_manifest(&manifest("../movie.mkv", &["movie.mkv.001"])).is_err());
+		assert!(validate_manifest(&manifest("movie.mkv", &["/etc/passwd"])).is_err());
+		assert!(validate_manifest(&manifest("movie.mkv", &["a\\..\\b"])).is_err());
+		assert!(validate_manifest(&manifest("..", &["movie.mkv.001"])).is_err());
+
+		let mut wrong_size = manifest("movie.mkv", &["movie.mkv.001"]);
+		wrong_size.size = 2;
+		assert!(validate_manifest(&wrong_size).is_err());
+	}
+}
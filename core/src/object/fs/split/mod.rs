@@ -0,0 +1,477 @@
+//! Splitting files too big for where they're going, like FAT32 drives or services with upload
+//! limits, into parts of a fixed size. A manifest written with the parts lists them with their
+//! checksums, and joining the parts back in [`join`] checks them against it.
+//!
+//! The objects of the parts and of the manifest are linked to the object of the split file, as
+//! derived from it, and the object of a joined file to the objects of its parts.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	prisma::{derived_object, file_path, location, object},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	io::SeekFrom,
+	path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::{info, trace};
+
+use super::{
+	convert::ConversionTarget, error::FileSystemJobsError, get_location_path_from_location_id,
+	get_many_files_datas, index_new_file,
+};
+
+pub mod join;
+
+/// The extension of the manifests of split files
+pub const SPLIT_MANIFEST_EXTENSION: &str = "sdsplit";
+const SPLIT_MANIFEST_VERSION: u32 = 1;
+/// Smaller parts would make thousands of files out of an ordinary video
+pub const MIN_PART_SIZE: u64 = 1024 * 1024;
+/// Parts are read and written in chunks this big
+const SPLIT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// What a split file was split into, written next to its parts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SplitManifest {
+	pub version: u32,
+	/// The name of the split file, with its extension
+	pub name: String,
+	pub size: u64,
+	pub part_size: u64,
+	/// The content id of the split file, when it was identified, to check the joined file against
+	pub cas_id: Option<String>,
+	pub parts: Vec<SplitPart>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SplitPart {
+	/// The name of the part, in the directory of the manifest
+	pub name: String,
+	pub size: u64,
+	/// The BLAKE3 checksum of the part, in hex
+	pub checksum: String,
+}
+
+/// The names of the parts of `name`, numbered from 1 with at least 3 digits, so they sort in order
+fn part_names(name: &str, count: u64) -> Vec<String> {
+	let width = count.to_string().len().max(3);
+
+	(1..=count)
+		.map(|index| format!("{name}.{index:0width$}"))
+		.collect()
+}
+
+/// Where a file is written until it's complete
+fn in_progress_path(path: &Path) -> PathBuf {
+	let mut in_progress_path = path.to_path_buf().into_os_string();
+	in_progress_path.push(".part");
+
+	PathBuf::from(in_progress_path)
+}
+
+/// Copies `size` bytes from `reader` to `writer`, returning the BLAKE3 checksum of what was copied
+async fn copy_hashed(
+	reader: &mut File,
+	reader_path: &Path,
+	writer: &mut File,
+	writer_path: &Path,
+	size: u64,
+) -> Result<String, FileIOError> {
+	let mut hasher = blake3::Hasher::new();
+	let mut buf = vec![0; SPLIT_CHUNK_SIZE];
+	let mut remaining = size;
+
+	while remaining > 0 {
+		let chunk = &mut buf[..remaining.min(SPLIT_CHUNK_SIZE as u64) as usize];
+		reader
+			.read_exact(chunk)
+			.await
+			.map_err(|e| FileIOError::from((reader_path, e)))?;
+		hasher.update(chunk);
+		writer
+			.write_all(chunk)
+			.await
+			.map_err(|e| FileIOError::from((writer_path, e)))?;
+
+		remaining -= chunk.len() as u64;
+	}
+
+	Ok(hasher.finalize().to_hex().to_string())
+}
+
+pub struct FileSplitterJob {}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct FileSplitterJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_id: file_path::id::Type,
+	/// The size of each part in bytes, at least 1 MiB, the last part has what's left
+	#[specta(type = String)]
+	#[serde_as(as = "DisplayFromStr")]
+	pub part_size: u64,
+	pub target: ConversionTarget,
+}
+
+impl JobInitData for FileSplitterJobInit {
+	type Job = FileSplitterJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileSplitterJobData {
+	location_path: PathBuf,
+	source_path: PathBuf,
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	target_directory_path: PathBuf,
+	name: String,
+	size: u64,
+	cas_id: Option<String>,
+	part_names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum FileSplitterJobStep {
+	Part(usize),
+	Manifest,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FileSplitterJobRunMetadata {
+	/// The parts written so far, in order
+	parts: Vec<SplitPart>,
+	bytes_written: u64,
+}
+
+impl JobRunMetadata for FileSplitterJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.parts.extend(new_data.parts);
+		self.bytes_written += new_data.bytes_written;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for FileSplitterJob {
+	type Init = FileSplitterJobInit;
+	type Data = FileSplitterJobData;
+	type Step = FileSplitterJobStep;
+	type RunMetadata = FileSplitterJobRunMetadata;
+
+	const NAME: &'static str = "file_splitter";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let db = &ctx.library.db;
+
+		if init.part_size < MIN_PART_SIZE {
+			return Err(FileSystemJobsError::InvalidPartSize(init.part_size).into());
+		}
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+		let file_data = get_many_files_datas(db, &location_path, &[init.file_path_id])
+			.await?
+			.pop()
+			.ok_or(FileSystemJobsError::FilePathIdNotFound(init.file_path_id))?;
+
+		if maybe_missing(file_data.file_path.is_dir, "file_path.is_dir")? {
+			return Err(
+				FileSystemJobsError::NotAFile(file_data.full_path.into_boxed_path()).into(),
+			);
+		}
+
+		let source_path = file_data.full_path;
+		let name = source_path
+			.file_name()
+			.unwrap_or_default()
+			.to_string_lossy()
+			.to_string();
+		let size = fs::metadata(&source_path)
+			.await
+			.map_err(|e| FileIOError::from((&source_path, e)))?
+			.len();
+
+		let (target_location_id, target_location_path, target_directory_path) = init
+			.target
+			.resolve(db, init.location_id, &location_path)
+			.await?;
+		let target_directory_path = target_directory_path.unwrap_or_else(|| {
+			source_path
+				.parent()
+				.map(Path::to_path_buf)
+				.unwrap_or_default()
+		});
+
+		let part_names = part_names(&name, ((size + init.part_size - 1) / init.part_size).max(1));
+
+		// Nothing is overwritten, the parts of an earlier split included
+		for file_name in part_names
+			.iter()
+			.cloned()
+			.chain([format!("{name}.{SPLIT_MANIFEST_EXTENSION}")])
+		{
+			let path = target_directory_path.join(file_name);
+			match fs::metadata(&path).await {
+				Ok(_) => {
+					return Err(FileSystemJobsError::WouldOverwrite(path.into_boxed_path()).into())
+				}
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+				Err(e) => return Err(FileIOError::from((path, e)).into()),
+			}
+		}
+
+		let steps = (0..part_names.len())
+			.map(FileSplitterJobStep::Part)
+			.chain([FileSplitterJobStep::Manifest])
+			.collect::<Vec<_>>();
+
+		*data = Some(FileSplitterJobData {
+			location_path,
+			source_path,
+			target_location_id,
+			target_location_path,
+			target_directory_path,
+			name,
+			size,
+			cas_id: file_data.file_path.cas_id,
+			part_names,
+		});
+
+		Ok((FileSplitterJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		run_metadata: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		match step {
+			FileSplitterJobStep::Part(index) => {
+				let part_name = &data.part_names[*index];
+				let part_path = data.target_directory_path.join(part_name);
+				let offset = *index as u64 * init.part_size;
+				let size = init.part_size.min(data.size - offset);
+
+				ctx.progress_msg(job_message!(
+					"split.writingPart",
+					part = index + 1,
+					parts = data.part_names.len()
+				));
+
+				let mut source = File::open(&data.source_path)
+					.await
+					.map_err(|e| FileIOError::from((&data.source_path, e)))?;
+				source
+					.seek(SeekFrom::Start(offset))
+					.await
+					.map_err(|e| FileIOError::from((&data.source_path, e)))?;
+
+				// Written aside first, a part interrupted halfway is written again from its start
+				let in_progress_path = in_progress_path(&part_path);
+				let mut part = File::create(&in_progress_path)
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+
+				let checksum = copy_hashed(
+					&mut source,
+					&data.source_path,
+					&mut part,
+					&in_progress_path,
+					size,
+				)
+				.await?;
+
+				part.sync_all()
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+				drop(part);
+				fs::rename(&in_progress_path, &part_path)
+					.await
+					.map_err(|e| FileIOError::from((&part_path, e)))?;
+
+				trace!("Wrote part {}", part_path.display());
+
+				Ok(FileSplitterJobRunMetadata {
+					parts: vec![SplitPart {
+						name: part_name.clone(),
+						size,
+						checksum,
+					}],
+					bytes_written: size,
+				}
+				.into())
+			}
+			FileSplitterJobStep::Manifest => {
+				let manifest = SplitManifest {
+					version: SPLIT_MANIFEST_VERSION,
+					name: data.name.clone(),
+					size: data.size,
+					part_size: init.part_size,
+					cas_id: data.cas_id.clone(),
+					parts: run_metadata.parts.clone(),
+				};
+
+				let manifest_path = data
+					.target_directory_path
+					.join(format!("{}.{SPLIT_MANIFEST_EXTENSION}", data.name));
+				let in_progress_path = in_progress_path(&manifest_path);
+				fs::write(&in_progress_path, serde_json::to_vec_pretty(&manifest)?)
+					.await
+					.map_err(|e| FileIOError::from((&in_progress_path, e)))?;
+				fs::rename(&in_progress_path, &manifest_path)
+					.await
+					.map_err(|e| FileIOError::from((&manifest_path, e)))?;
+
+				// The split file may not have been identified yet, this gives it its object
+				let original_id = index_new_file(
+					&ctx.library,
+					init.location_id,
+					&data.location_path,
+					&data.source_path,
+				)
+				.await?;
+
+				let mut derived_ids = vec![];
+				for path in data
+					.part_names
+					.iter()
+					.map(|part_name| data.target_directory_path.join(part_name))
+					.chain([manifest_path])
+				{
+					derived_ids.push(
+						index_new_file(
+							&ctx.library,
+							data.target_location_id,
+							&data.target_location_path,
+							path,
+						)
+						.await?,
+					);
+				}
+
+				link_derived_objects(&ctx.library, &derived_ids, &[original_id]).await?;
+
+				Ok(None.into())
+			}
+		}
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Split a file into {} parts, {} bytes written",
+			metadata.parts.len(),
+			metadata.bytes_written
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(json!({
+			"init": state.init,
+			"parts": metadata.parts.len(),
+			"bytes_written": metadata.bytes_written,
+		})))
+	}
+}
+
+/// Links each of `derived_ids` to each of `original_ids`, as derived from them
+async fn link_derived_objects(
+	library: &Library,
+	derived_ids: &[object::id::Type],
+	original_ids: &[object::id::Type],
+) -> Result<(), JobError> {
+	library
+		.db
+		.derived_object()
+		.create_many(
+			derived_ids
+				.iter()
+				.flat_map(|derived_id| {
+					original_ids.iter().map(|original_id| {
+						derived_object::create_unchecked(*derived_id, *original_id, vec![])
+					})
+				})
+				.collect(),
+		)
+		.skip_duplicates()
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parts_are_numbered_in_order() {
+		assert_eq!(
+			part_names("movie.mkv", 3),
+			["movie.mkv.001", "movie.mkv.002", "movie.mkv.003"]
+		);
+
+		let names = part_names("disk.img", 1200);
+		assert_eq!(names[0], "disk.img.0001");
+		assert_eq!(names[1199], "disk.img.1200");
+	}
+
+	#[tokio::test]
+	async fn copies_and_hashes_a_range() {
+		let dir = tempfile::tempdir().unwrap();
+		let source_path = dir.path().join("source");
+		let part_path = dir.path().join("part");
+		let contents = (0..3 * SPLIT_CHUNK_SIZE)
+			.map(|i| i as u8)
+			.collect::<Vec<_>>();
+		fs::write(&source_path, &contents).await.unwrap();
+
+		let mut source = File::open(&source_path).await.unwrap();
+		source.seek(SeekFrom::Start(10)).await.unwrap();
+		let mut part = File::create(&part_path).await.unwrap();
+
+		let size = SPLIT_CHUNK_SIZE as u64 + 5;
+		let checksum = copy_hashed(&mut source, &source_path, &mut part, &part_path, size)
+			.await
+			.unwrap();
+		part.flush().await.unwrap();
+
+		let expected = &contents[10..10 + size as usize];
+		assert_eq!(fs::read(&part_path).await.unwrap(), expected);
+		assert_eq!(checksum, blake3::hash(expected).to_hex().to_string());
+	}
+}
@@ -0,0 +1,386 @@
+//! Transcoding videos to MP4 files with FFmpeg, keeping the originals. Each video is a step of its
+//! own, so an interrupted job picks up again from the video it was transcoding. The job is low
+//! priority, it waits for the library to run nothing else.
+//!
+//! Like converted images, the object of each transcoded video is linked to the object of its
+//! original, as one derived from the other.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	prisma::{derived_object, file_path, location},
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{fs, io};
+use tracing::{info, trace, warn};
+
+use super::{
+	convert::ConversionTarget, error::FileSystemJobsError, get_location_path_from_location_id,
+	get_many_files_datas, index_new_file, FileData,
+};
+
+pub struct VideoTranscoderJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+	H264,
+	H265,
+	Av1,
+}
+
+impl VideoCodec {
+	/// Part of the names of transcoded videos, so they don't take the names of MP4 originals
+	const fn suffix(&self) -> &'static str {
+		match self {
+			Self::H264 => "h264",
+			Self::H265 => "h265",
+			Self::Av1 => "av1",
+		}
+	}
+}
+
+/// Videos taller than these are scaled down to them
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionCap {
+	P480,
+	P720,
+	P1080,
+	P2160,
+}
+
+impl ResolutionCap {
+	pub const fn height(&self) -> u32 {
+		match self {
+			Self::P480 => 480,
+			Self::P720 => 720,
+			Self::P1080 => 1080,
+			Self::P2160 => 2160,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug, Clone, Copy)]
+pub struct TranscodePreset {
+	pub codec: VideoCodec,
+	/// The videos keep their resolution when not set
+	pub resolution_cap: Option<ResolutionCap>,
+}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct VideoTranscoderJobInit {
+	pub location_id: location::id::Type,
+	pub file_path_ids: Vec<file_path::id::Type>,
+	pub preset: TranscodePreset,
+	/// Encoding on the GPU when it can, falling back to software encoders otherwise
+	pub hardware_acceleration: bool,
+	pub target: ConversionTarget,
+}
+
+impl JobInitData for VideoTranscoderJobInit {
+	type Job = VideoTranscoderJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VideoTranscoderJobData {
+	location_path: PathBuf,
+	target_location_id: location::id::Type,
+	target_location_path: PathBuf,
+	/// Where the transcoded videos go, next to their original videos when not set
+	target_directory_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct VideoTranscoderJobRunMetadata {
+	videos_transcoded: u64,
+	/// Videos transcoded by a hardware encoder
+	hardware_encoded: u64,
+	bytes_written: u64,
+}
+
+impl JobRunMetadata for VideoTranscoderJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.videos_transcoded += new_data.videos_transcoded;
+		self.hardware_encoded += new_data.hardware_encoded;
+		self.bytes_written += new_data.bytes_written;
+	}
+}
+
+#[cfg(feature = "ffmpeg")]
+impl From<VideoCodec> for sd_ffmpeg::VideoCodec {
+	fn from(codec: VideoCodec) -> Self {
+		match codec {
+			VideoCodec::H264 => Self::H264,
+			VideoCodec::H265 => Self::H265,
+			VideoCodec::Av1 => Self::Av1,
+		}
+	}
+}
+
+/// Transcodes `source` to `target`, reporting the progress on the job. Returns the name of the
+/// encoder used, or why the video couldn't be transcoded.
+#[cfg(feature = "ffmpeg")]
+async fn transcode_video(
+	ctx: &WorkerContext,
+	source: &Path,
+	target: &Path,
+	init: &VideoTranscoderJobInit,
+) -> Result<&'static str, String> {
+	let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+
+	let options = sd_ffmpeg::TranscodeOptions {
+		codec: init.preset.codec.into(),
+		max_height: init.preset.resolution_cap.map(|cap| cap.height()),
+		hardware_acceleration: init.hardware_acceleration,
+	};
+
+	let (result, _) = tokio::join!(
+		sd_ffmpeg::to_transcoded_video(source, target, options, move |percentage| {
+			progress_tx.send(percentage).ok();
+		}),
+		async {
+			// Ends when the transcoding does, dropping the sender
+			while let Some(percentage) = progress_rx.recv().await {
+				ctx.progress_msg(job_message!(
+					"transcode.transcodingProgress",
+					path = source.display(),
+					percentage = percentage
+				));
+			}
+		}
+	);
+
+	result.map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+async fn transcode_video(
+	_: &WorkerContext,
+	_: &Path,
+	_: &Path,
+	_: &VideoTranscoderJobInit,
+) -> Result<&'static str, String> {
+	Err(FileSystemJobsError::TranscodingUnavailable.to_string())
+}
+
+/// Whether FFmpeg picked a hardware encoder, they're the ones not from the software libraries
+fn is_hardware_encoder(name: &str) -> bool {
+	!name.starts_with("lib")
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for VideoTranscoderJob {
+	type Init = VideoTranscoderJobInit;
+	type Data = VideoTranscoderJobData;
+	type Step = FileData;
+	type RunMetadata = VideoTranscoderJobRunMetadata;
+
+	const NAME: &'static str = "video_transcoder";
+	const IS_LOW_PRIORITY: bool = true;
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		if cfg!(not(feature = "ffmpeg")) {
+			return Err(FileSystemJobsError::TranscodingUnavailable.into());
+		}
+
+		let db = &ctx.library.db;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+
+		let (target_location_id, target_location_path, target_directory_path) = init
+			.target
+			.resolve(db, init.location_id, &location_path)
+			.await?;
+
+		let steps = get_many_files_datas(db, &location_path, &init.file_path_ids).await?;
+
+		*data = Some(VideoTranscoderJobData {
+			location_path,
+			target_location_id,
+			target_location_path,
+			target_directory_path,
+		});
+
+		Ok((VideoTranscoderJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, .. } = &ctx.library;
+		let FileData {
+			file_path,
+			full_path,
+		} = step;
+
+		if maybe_missing(file_path.is_dir, "file_path.is_dir")? {
+			warn!("Skipping {} as it's a directory", full_path.display());
+
+			return Ok(JobRunErrors(vec![format!(
+				"{} wasn't transcoded, only videos are",
+				full_path.display()
+			)])
+			.into());
+		}
+
+		let name = maybe_missing(&file_path.name, "file_path.name")?;
+		let transcoded_name = format!("{name}.{}.mp4", init.preset.codec.suffix());
+		let output_path = match &data.target_directory_path {
+			Some(target_directory_path) => target_directory_path.join(transcoded_name),
+			None => full_path.with_file_name(transcoded_name),
+		};
+
+		match fs::metadata(&output_path).await {
+			Ok(_) => {
+				warn!(
+					"Skipping {} as it would be overwritten",
+					output_path.display()
+				);
+
+				return Ok(JobRunErrors(vec![FileSystemJobsError::WouldOverwrite(
+					output_path.into_boxed_path(),
+				)
+				.to_string()])
+				.into());
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((&output_path, e)).into()),
+		}
+
+		ctx.progress_msg(job_message!(
+			"transcode.transcoding",
+			path = full_path.display()
+		));
+
+		// Written aside first, a video interrupted halfway never looks like a whole one. When the
+		// job is resumed, the video is transcoded again from its start.
+		let mut part_path = output_path.clone().into_os_string();
+		part_path.push(".part");
+		let part_path = PathBuf::from(part_path);
+
+		let encoder = match transcode_video(ctx, full_path, &part_path, init).await {
+			Ok(encoder) => encoder,
+			Err(e) => {
+				warn!("Failed to transcode {}: {e}", full_path.display());
+
+				if let Err(e) = fs::remove_file(&part_path).await {
+					if e.kind() != io::ErrorKind::NotFound {
+						warn!(
+							"Failed to remove the partly transcoded video {}: {e}",
+							part_path.display()
+						);
+					}
+				}
+
+				return Ok(JobRunErrors(vec![format!(
+					"{} couldn't be transcoded: {e}",
+					full_path.display()
+				)])
+				.into());
+			}
+		};
+
+		fs::rename(&part_path, &output_path)
+			.await
+			.map_err(|e| FileIOError::from((&output_path, e)))?;
+
+		let size = fs::metadata(&output_path)
+			.await
+			.map_err(|e| FileIOError::from((&output_path, e)))?
+			.len();
+
+		trace!(
+			"Transcoded {} to {} with {encoder}",
+			full_path.display(),
+			output_path.display()
+		);
+
+		// The original video may not have been identified yet, this gives it its object
+		let original_id = index_new_file(
+			&ctx.library,
+			init.location_id,
+			&data.location_path,
+			full_path,
+		)
+		.await?;
+		let derived_id = index_new_file(
+			&ctx.library,
+			data.target_location_id,
+			&data.target_location_path,
+			&output_path,
+		)
+		.await?;
+
+		db.derived_object()
+			.create_many(vec![derived_object::create_unchecked(
+				derived_id,
+				original_id,
+				vec![],
+			)])
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		Ok(VideoTranscoderJobRunMetadata {
+			videos_transcoded: 1,
+			hardware_encoded: is_hardware_encoder(encoder) as u64,
+			bytes_written: size,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Transcoded {} videos, {} of them by a hardware encoder, {} bytes written",
+			metadata.videos_transcoded, metadata.hardware_encoded, metadata.bytes_written
+		);
+
+		invalidate_query!(ctx.library, "search.paths");
+		invalidate_query!(ctx.library, "search.objects");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tells_hardware_encoders_apart() {
+		assert!(is_hardware_encoder("hevc_videotoolbox"));
+		assert!(is_hardware_encoder("av1_nvenc"));
+		assert!(!is_hardware_encoder("libx264"));
+		assert!(!is_hardware_encoder("libaom-av1"));
+	}
+}
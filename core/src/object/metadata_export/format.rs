@@ -0,0 +1,131 @@
+use crate::util::error::FileIOError;
+
+use std::{
+	fs::File,
+	io::{BufRead, BufReader, BufWriter, Write},
+	path::Path,
+};
+
+use serde_json::{json, Value};
+
+use super::{ExportColumn, ExportFormat, MetadataExportError};
+
+/// Writes the rows of the partial export at `partial_path` to `path`, in `format`
+pub(super) fn write(
+	partial_path: &Path,
+	path: &Path,
+	format: ExportFormat,
+	columns: &[ExportColumn],
+) -> Result<(), MetadataExportError> {
+	let partial = File::open(partial_path).map_err(|e| FileIOError::from((partial_path, e)))?;
+	let rows = BufReader::new(partial).lines().map(|line| {
+		let line = line.map_err(|e| FileIOError::from((partial_path, e)))?;
+
+		Ok::<Vec<Value>, MetadataExportError>(serde_json::from_str(&line)?)
+	});
+
+	let line: fn(&[ExportColumn], &[Value]) -> String = match format {
+		ExportFormat::Csv => csv_line,
+		ExportFormat::JsonLines => json_line,
+		#[cfg(feature = "parquet")]
+		ExportFormat::Parquet => return super::parquet::write(rows, path, columns),
+		#[cfg(not(feature = "parquet"))]
+		ExportFormat::Parquet => return Err(MetadataExportError::ParquetUnavailable),
+	};
+
+	let file = File::create(path).map_err(|e| FileIOError::from((path, e)))?;
+	let mut out = BufWriter::new(file);
+
+	let mut write_line =
+		|line: String| writeln!(out, "{line}").map_err(|e| FileIOError::from((path, e)));
+
+	if format == ExportFormat::Csv {
+		write_line(
+			columns
+				.iter()
+				.map(|column| csv_field(&json!(column.name())))
+				.collect::<Vec<_>>()
+				.join(","),
+		)?;
+	}
+
+	for row in rows {
+		write_line(line(columns, &row?))?;
+	}
+
+	out.flush().map_err(|e| FileIOError::from((path, e)))?;
+
+	Ok(())
+}
+
+/// Joins the tags of a row, for the formats without lists
+pub(super) fn join_list(values: &[Value]) -> String {
+	values
+		.iter()
+		.filter_map(Value::as_str)
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+fn csv_field(value: &Value) -> String {
+	let text = match value {
+		Value::Null => return String::new(),
+		Value::String(text) => text.clone(),
+		Value::Array(values) => join_list(values),
+		value => value.to_string(),
+	};
+
+	if text.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", text.replace('"', "\"\""))
+	} else {
+		text
+	}
+}
+
+fn csv_line(_: &[ExportColumn], row: &[Value]) -> String {
+	row.iter().map(csv_field).collect::<Vec<_>>().join(",")
+}
+
+/// An object per line, with the keys in the order of the columns
+fn json_line(columns: &[ExportColumn], row: &[Value]) -> String {
+	let fields = columns
+		.iter()
+		.zip(row)
+		.map(|(column, value)| format!("{}:{value}", json!(column.name())))
+		.collect::<Vec<_>>();
+
+	format!("{{{}}}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn escapes_csv_fields() {
+		let row = [
+			json!("IMG_0001.jpg"),
+			json!("Holidays, 2023"),
+			json!("a \"quote\""),
+			json!(["Family", "Beach"]),
+			json!(null),
+			json!(1024),
+		];
+
+		assert_eq!(
+			csv_line(&[], &row),
+			"IMG_0001.jpg,\"Holidays, 2023\",\"a \"\"quote\"\"\",\"Family, Beach\",,1024"
+		);
+	}
+
+	#[test]
+	fn keeps_the_order_of_the_columns_in_json() {
+		let line = json_line(
+			&[ExportColumn::Size, ExportColumn::Name, ExportColumn::Tags],
+			&[json!(1024), json!("a.jpg"), json!(["Beach"])],
+		);
+
+		assert_eq!(line, r#"{"size":1024,"name":"a.jpg","tags":["Beach"]}"#);
+		assert!(serde_json::from_str::<Value>(&line).is_ok());
+	}
+}
@@ -0,0 +1,495 @@
+//! Exports the metadata of objects to a file, for spreadsheets and data analysis tools: a row per
+//! file of the objects matching the filter, with the columns asked for, in CSV, JSON Lines or
+//! Parquet.
+//!
+//! The rows are appended to a `.partial` file next to the export as the job goes, so it can be
+//! paused and resumed, and are only written in the format asked for once they're all there. Tags
+//! are a list in JSON Lines, and are joined by `, ` in the other formats.
+
+mod format;
+#[cfg(feature = "parquet")]
+mod parquet;
+
+use crate::{
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	prisma::{file_path, location, object, tag, tag_on_object},
+	util::error::FileIOError,
+};
+
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::info;
+use uuid::Uuid;
+
+/// How many file paths are exported in a step
+const BATCH_SIZE: i64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum MetadataExportError {
+	#[error("an export needs at least one column")]
+	NoColumns,
+	#[error("this build can't write Parquet files")]
+	ParquetUnavailable,
+	#[error("invalid row in the partial export: {0}")]
+	Row(#[from] serde_json::Error),
+	#[cfg(feature = "parquet")]
+	#[error("failed to write the Parquet file: {0}")]
+	Parquet(#[from] ::parquet::errors::ParquetError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+#[derive(Serialize, Deserialize, Type, Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+	Csv,
+	JsonLines,
+	Parquet,
+}
+
+#[derive(Serialize, Deserialize, Type, Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportColumn {
+	ObjectId,
+	ObjectPubId,
+	/// From `ObjectKind`
+	Kind,
+	/// The absolute path of the file, on the node it was indexed on
+	Path,
+	LocationId,
+	Name,
+	Extension,
+	Size,
+	CasId,
+	IntegrityChecksum,
+	DateCreated,
+	DateModified,
+	Tags,
+	Favorite,
+	Note,
+	PixelWidth,
+	PixelHeight,
+	Latitude,
+	Longitude,
+	CaptureDeviceMake,
+	CaptureDeviceModel,
+	CaptureDeviceSoftware,
+	DurationSeconds,
+}
+
+/// How a column is typed in the formats that have types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+	Int,
+	Float,
+	Bool,
+	Text,
+	TextList,
+}
+
+file_path::select!(file_path_for_metadata_export {
+	id
+	location_id
+	materialized_path
+	name
+	extension
+	size_in_bytes_bytes
+	cas_id
+	integrity_checksum
+	date_created
+	date_modified
+	object: select {
+		id
+		pub_id
+		kind
+		favorite
+		note
+		tags: select { tag: select { name } }
+		media_data
+	}
+});
+
+impl ExportColumn {
+	/// The name of the column in the header of the export
+	fn name(self) -> &'static str {
+		match self {
+			Self::ObjectId => "object_id",
+			Self::ObjectPubId => "object_pub_id",
+			Self::Kind => "kind",
+			Self::Path => "path",
+			Self::LocationId => "location_id",
+			Self::Name => "name",
+			Self::Extension => "extension",
+			Self::Size => "size",
+			Self::CasId => "cas_id",
+			Self::IntegrityChecksum => "integrity_checksum",
+			Self::DateCreated => "date_created",
+			Self::DateModified => "date_modified",
+			Self::Tags => "tags",
+			Self::Favorite => "favorite",
+			Self::Note => "note",
+			Self::PixelWidth => "pixel_width",
+			Self::PixelHeight => "pixel_height",
+			Self::Latitude => "latitude",
+			Self::Longitude => "longitude",
+			Self::CaptureDeviceMake => "capture_device_make",
+			Self::CaptureDeviceModel => "capture_device_model",
+			Self::CaptureDeviceSoftware => "capture_device_software",
+			Self::DurationSeconds => "duration_seconds",
+		}
+	}
+
+	fn column_type(self) -> ColumnType {
+		match self {
+			Self::ObjectId
+			| Self::Kind
+			| Self::LocationId
+			| Self::Size
+			| Self::PixelWidth
+			| Self::PixelHeight
+			| Self::DurationSeconds => ColumnType::Int,
+			Self::Latitude | Self::Longitude => ColumnType::Float,
+			Self::Favorite => ColumnType::Bool,
+			Self::Tags => ColumnType::TextList,
+			_ => ColumnType::Text,
+		}
+	}
+
+	fn value(
+		self,
+		file_path: &file_path_for_metadata_export::Data,
+		location_path: Option<&String>,
+	) -> Value {
+		let object = file_path.object.as_ref();
+		let media_data = object.and_then(|object| object.media_data.as_ref());
+		let date = |date: Option<DateTime<FixedOffset>>| json!(date.map(|date| date.to_rfc3339()));
+
+		match self {
+			Self::ObjectId => json!(object.map(|object| object.id)),
+			Self::ObjectPubId => json!(object
+				.and_then(|object| Uuid::from_slice(&object.pub_id).ok())
+				.map(|pub_id| pub_id.to_string())),
+			Self::Kind => json!(object.and_then(|object| object.kind)),
+			Self::Path => json!(location_path.and_then(|location_path| {
+				let materialized_path = file_path.materialized_path.as_deref()?;
+
+				Some(
+					Path::new(location_path)
+						.join(materialized_path.trim_start_matches('/'))
+						.join(file_name(file_path)?)
+						.to_string_lossy()
+						.into_owned(),
+				)
+			})),
+			Self::LocationId => json!(file_path.location_id),
+			Self::Name => json!(file_path.name),
+			Self::Extension => json!(file_path.extension),
+			Self::Size => json!(file_path
+				.size_in_bytes_bytes
+				.as_deref()
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(u64::from_be_bytes)),
+			Self::CasId => json!(file_path.cas_id),
+			Self::IntegrityChecksum => json!(file_path.integrity_checksum),
+			Self::DateCreated => date(file_path.date_created),
+			Self::DateModified => date(file_path.date_modified),
+			Self::Tags => json!(object
+				.map(|object| object
+					.tags
+					.iter()
+					.filter_map(|tag_on_object| tag_on_object.tag.name.clone())
+					.collect::<Vec<_>>())
+				.unwrap_or_default()),
+			Self::Favorite => json!(object.and_then(|object| object.favorite)),
+			Self::Note => json!(object.and_then(|object| object.note.clone())),
+			Self::PixelWidth => json!(media_data.and_then(|media_data| media_data.pixel_width)),
+			Self::PixelHeight => json!(media_data.and_then(|media_data| media_data.pixel_height)),
+			Self::Latitude => json!(media_data.and_then(|media_data| media_data.latitude)),
+			Self::Longitude => json!(media_data.and_then(|media_data| media_data.longitude)),
+			Self::CaptureDeviceMake => {
+				json!(media_data.and_then(|media_data| media_data.capture_device_make.clone()))
+			}
+			Self::CaptureDeviceModel => {
+				json!(media_data.and_then(|media_data| media_data.capture_device_model.clone()))
+			}
+			Self::CaptureDeviceSoftware => {
+				json!(media_data.and_then(|media_data| media_data.capture_device_software.clone()))
+			}
+			Self::DurationSeconds => {
+				json!(media_data.and_then(|media_data| media_data.duration_seconds))
+			}
+		}
+	}
+}
+
+fn file_name(file_path: &file_path_for_metadata_export::Data) -> Option<String> {
+	let name = file_path.name.as_deref()?;
+
+	Some(match file_path.extension.as_deref() {
+		Some(extension) if !extension.is_empty() => format!("{name}.{extension}"),
+		_ => name.to_string(),
+	})
+}
+
+/// Which objects are exported, all of the filters have to match
+#[derive(Serialize, Deserialize, Type, Hash, Debug, Default)]
+pub struct MetadataExportFilter {
+	/// Only these objects, like the ones selected in the explorer
+	#[serde(default)]
+	pub object_ids: Vec<object::id::Type>,
+	pub location_id: Option<location::id::Type>,
+	/// Only the objects with one of these tags
+	#[serde(default)]
+	pub tag_ids: Vec<tag::id::Type>,
+	/// Only the objects of these kinds, from `ObjectKind`
+	#[serde(default)]
+	pub kinds: Vec<i32>,
+	/// Only the files with these extensions, without the dot
+	#[serde(default)]
+	pub extensions: Vec<String>,
+}
+
+impl MetadataExportFilter {
+	fn params(&self) -> Vec<file_path::WhereParam> {
+		let mut object_params = vec![object::date_deleted::equals(None)];
+		if !self.object_ids.is_empty() {
+			object_params.push(object::id::in_vec(self.object_ids.clone()));
+		}
+		if !self.tag_ids.is_empty() {
+			object_params.push(object::tags::some(vec![tag_on_object::tag_id::in_vec(
+				self.tag_ids.clone(),
+			)]));
+		}
+		if !self.kinds.is_empty() {
+			object_params.push(object::kind::in_vec(self.kinds.clone()));
+		}
+
+		let mut params = vec![
+			file_path::is_dir::equals(Some(false)),
+			file_path::object::is(object_params),
+		];
+		if let Some(location_id) = self.location_id {
+			params.push(file_path::location_id::equals(Some(location_id)));
+		}
+		if !self.extensions.is_empty() {
+			params.push(file_path::extension::in_vec(
+				self.extensions
+					.iter()
+					.map(|extension| extension.trim_start_matches('.').to_lowercase())
+					.collect(),
+			));
+		}
+
+		params
+	}
+}
+
+pub struct MetadataExportJob {}
+
+#[derive(Serialize, Deserialize, Type, Hash, Debug)]
+pub struct MetadataExportJobInit {
+	/// The file to write the export to, it's replaced if it exists
+	pub path: PathBuf,
+	pub format: ExportFormat,
+	/// In the order they're exported
+	pub columns: Vec<ExportColumn>,
+	#[serde(default)]
+	pub filter: MetadataExportFilter,
+}
+
+impl JobInitData for MetadataExportJobInit {
+	type Job = MetadataExportJob;
+
+	fn locations(&self) -> JobLocations {
+		// The rows of every location are exported
+		JobLocations::All
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MetadataExportJobData {
+	/// The rows exported so far, a JSON array per line
+	partial_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MetadataExportJobStep {
+	skip: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MetadataExportJobRunMetadata {
+	rows: u32,
+}
+
+impl JobRunMetadata for MetadataExportJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.rows += new_data.rows;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for MetadataExportJob {
+	type Init = MetadataExportJobInit;
+	type Data = MetadataExportJobData;
+	type Step = MetadataExportJobStep;
+	type RunMetadata = MetadataExportJobRunMetadata;
+
+	const NAME: &'static str = "metadata_export";
+	const LOAD: JobLoad = JobLoad::WriteHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		if init.columns.is_empty() {
+			return Err(MetadataExportError::NoColumns.into());
+		}
+		if cfg!(not(feature = "parquet")) && init.format == ExportFormat::Parquet {
+			return Err(MetadataExportError::ParquetUnavailable.into());
+		}
+
+		let file_paths = ctx
+			.library
+			.db
+			.file_path()
+			.count(init.filter.params())
+			.exec()
+			.await?;
+
+		let partial_path = {
+			let mut path = OsString::from(&init.path);
+			path.push(".partial");
+			PathBuf::from(path)
+		};
+		fs::write(&partial_path, [])
+			.await
+			.map_err(|e| FileIOError::from((&partial_path, e)))?;
+
+		*data = Some(MetadataExportJobData { partial_path });
+
+		ctx.progress_msg(job_message!("metadataExport.exporting", count = file_paths));
+
+		Ok((
+			Default::default(),
+			(0..file_paths)
+				.step_by(BATCH_SIZE as usize)
+				.map(|skip| MetadataExportJobStep { skip })
+				.collect::<Vec<_>>(),
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let db = &ctx.library.db;
+
+		let (locations, file_paths) = tokio::try_join!(
+			db.location()
+				.find_many(vec![])
+				.select(location::select!({ id path }))
+				.exec(),
+			db.file_path()
+				.find_many(init.filter.params())
+				.order_by(file_path::id::order(prisma_client_rust::Direction::Asc))
+				.skip(step.skip)
+				.take(BATCH_SIZE)
+				.select(file_path_for_metadata_export::select())
+				.exec(),
+		)?;
+
+		let location_paths = locations
+			.into_iter()
+			.filter_map(|location| Some((location.id, location.path?)))
+			.collect::<HashMap<_, _>>();
+
+		let mut rows = String::new();
+		for file_path in &file_paths {
+			let location_path = file_path
+				.location_id
+				.and_then(|location_id| location_paths.get(&location_id));
+
+			let row = init
+				.columns
+				.iter()
+				.map(|column| column.value(file_path, location_path))
+				.collect::<Vec<_>>();
+
+			rows.push_str(&serde_json::to_string(&row)?);
+			rows.push('\n');
+		}
+
+		let mut partial = fs::OpenOptions::new()
+			.append(true)
+			.open(&data.partial_path)
+			.await
+			.map_err(|e| FileIOError::from((&data.partial_path, e)))?;
+		partial
+			.write_all(rows.as_bytes())
+			.await
+			.map_err(|e| FileIOError::from((&data.partial_path, e)))?;
+
+		Ok(MetadataExportJobRunMetadata {
+			rows: file_paths.len() as u32,
+		}
+		.into())
+	}
+
+	async fn finalize(&self, _: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let init = &state.init;
+		let data = state
+			.data
+			.as_ref()
+			.expect("critical error: missing data on job state");
+
+		let partial_path = data.partial_path.clone();
+		let path = init.path.clone();
+		let format = init.format;
+		let columns = init.columns.clone();
+
+		tokio::task::spawn_blocking(move || -> Result<(), MetadataExportError> {
+			format::write(&partial_path, &path, format, &columns)?;
+
+			std::fs::remove_file(&partial_path)
+				.map_err(|e| FileIOError::from((&partial_path, e)))?;
+
+			Ok(())
+		})
+		.await??;
+
+		info!(
+			"Exported the metadata of {} files to '{}'",
+			state.run_metadata.rows,
+			init.path.display()
+		);
+
+		Ok(Some(json!({
+			"rows": state.run_metadata.rows,
+			"path": init.path,
+		})))
+	}
+}
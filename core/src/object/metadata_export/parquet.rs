@@ -0,0 +1,154 @@
+//! Parquet files, written with the column API of `parquet` to do without Arrow. Every column is
+//! optional, a missing value is a null.
+
+use crate::util::error::FileIOError;
+
+use std::{fs::File, path::Path, sync::Arc};
+
+use parquet::{
+	data_type::{BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int64Type},
+	errors::ParquetError,
+	file::{
+		properties::WriterProperties,
+		writer::{SerializedColumnWriter, SerializedFileWriter},
+	},
+	schema::parser::parse_message_type,
+};
+use serde_json::Value;
+
+use super::{format::join_list, ColumnType, ExportColumn, MetadataExportError};
+
+/// How many rows are in a row group of the file
+const ROW_GROUP_SIZE: usize = 10_000;
+
+fn schema(columns: &[ExportColumn]) -> String {
+	let fields = columns
+		.iter()
+		.map(|column| {
+			let field_type = match column.column_type() {
+				ColumnType::Int => "INT64",
+				ColumnType::Float => "DOUBLE",
+				ColumnType::Bool => "BOOLEAN",
+				ColumnType::Text | ColumnType::TextList => "BYTE_ARRAY",
+			};
+			let annotation = if field_type == "BYTE_ARRAY" {
+				" (UTF8)"
+			} else {
+				""
+			};
+
+			format!("OPTIONAL {field_type} {}{annotation};", column.name())
+		})
+		.collect::<Vec<_>>();
+
+	format!("message metadata_export {{ {} }}", fields.join(" "))
+}
+
+fn write_column<T: DataType>(
+	column_writer: &mut SerializedColumnWriter<'_>,
+	values: Vec<Option<T::T>>,
+) -> Result<(), ParquetError> {
+	let levels = values
+		.iter()
+		.map(|value| i16::from(value.is_some()))
+		.collect::<Vec<_>>();
+	let values = values.into_iter().flatten().collect::<Vec<_>>();
+
+	column_writer
+		.typed::<T>()
+		.write_batch(&values, Some(&levels), None)?;
+
+	Ok(())
+}
+
+pub(super) fn write(
+	rows: impl Iterator<Item = Result<Vec<Value>, MetadataExportError>>,
+	path: &Path,
+	columns: &[ExportColumn],
+) -> Result<(), MetadataExportError> {
+	let file = File::create(path).map_err(|e| FileIOError::from((path, e)))?;
+	let mut writer = SerializedFileWriter::new(
+		file,
+		Arc::new(parse_message_type(&schema(columns))?),
+		Arc::new(WriterProperties::builder().build()),
+	)?;
+
+	let mut rows = rows.peekable();
+	while rows.peek().is_some() {
+		let group = rows
+			.by_ref()
+			.take(ROW_GROUP_SIZE)
+			.collect::<Result<Vec<_>, _>>()?;
+		let mut row_group = writer.next_row_group()?;
+
+		for (index, column) in columns.iter().enumerate() {
+			let Some(mut column_writer) = row_group.next_column()? else {
+				break;
+			};
+			let values = group
+				.iter()
+				.map(|row| row.get(index).unwrap_or(&Value::Null));
+
+			match column.column_type() {
+				ColumnType::Int => write_column::<Int64Type>(
+					&mut column_writer,
+					values.map(Value::as_i64).collect(),
+				)?,
+				ColumnType::Float => write_column::<DoubleType>(
+					&mut column_writer,
+					values.map(Value::as_f64).collect(),
+				)?,
+				ColumnType::Bool => write_column::<BoolType>(
+					&mut column_writer,
+					values.map(Value::as_bool).collect(),
+				)?,
+				ColumnType::Text => write_column::<ByteArrayType>(
+					&mut column_writer,
+					values
+						.map(|value| value.as_str().map(ByteArray::from))
+						.collect(),
+				)?,
+				ColumnType::TextList => write_column::<ByteArrayType>(
+					&mut column_writer,
+					values
+						.map(|value| {
+							value
+								.as_array()
+								.map(|values| ByteArray::from(join_list(values).as_str()))
+						})
+						.collect(),
+				)?,
+			}
+
+			column_writer.close()?;
+		}
+
+		row_group.close()?;
+	}
+
+	writer.close()?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn types_the_columns() {
+		let schema = schema(&[
+			ExportColumn::Size,
+			ExportColumn::Path,
+			ExportColumn::Latitude,
+			ExportColumn::Favorite,
+		]);
+
+		assert_eq!(
+			schema,
+			"message metadata_export { OPTIONAL INT64 size; OPTIONAL BYTE_ARRAY path (UTF8); \
+			OPTIONAL DOUBLE latitude; OPTIONAL BOOLEAN favorite; }"
+		);
+		assert!(parse_message_type(&schema).is_ok());
+	}
+}
@@ -6,6 +6,7 @@ use specta::Type;
 pub mod cas;
 pub mod file_identifier;
 pub mod fs;
+pub mod metadata_export;
 pub mod orphan_remover;
 pub mod preview;
 pub mod tag;
@@ -17,6 +18,7 @@ pub mod validation;
 
 // Object selectables!
 object::select!(object_for_file_identifier {
+	id
 	pub_id
 	file_paths: select { pub_id cas_id }
 });
@@ -1,10 +1,13 @@
 use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
 use tokio::sync::mpsc::*;
 use tracing::{debug, error};
 
 use crate::prisma::*;
 
-// Actor that can be invoked to find and delete objects with no matching file paths
+// Actor that can be invoked to find objects with no matching file paths and move them to the trash,
+// where they keep their tags until they're purged
 #[derive(Clone)]
 pub struct OrphanRemoverActor {
 	tx: Sender<()>,
@@ -26,7 +29,10 @@ impl OrphanRemoverActor {
 					loop {
 						let objs = match db
 							.object()
-							.find_many(vec![object::file_paths::none(vec![])])
+							.find_many(vec![
+								object::file_paths::none(vec![]),
+								object::date_deleted::equals(None),
+							])
 							.take(512)
 							.select(object::select!({ id pub_id }))
 							.exec()
@@ -43,20 +49,21 @@ impl OrphanRemoverActor {
 							break;
 						}
 
-						debug!("Removing {} orphaned objects", objs.len());
+						debug!("Trashing {} orphaned objects", objs.len());
 
 						let ids: Vec<_> = objs.iter().map(|o| o.id).collect();
 
 						if let Err(e) = db
-							._batch((
-								db.tag_on_object().delete_many(vec![
-									tag_on_object::object_id::in_vec(ids.clone()),
-								]),
-								db.object().delete_many(vec![object::id::in_vec(ids)]),
-							))
+							.object()
+							.update_many(
+								vec![object::id::in_vec(ids)],
+								vec![object::date_deleted::set(Some(Utc::now().into()))],
+							)
+							.exec()
 							.await
 						{
-							error!("Failed to remove orphaned objects: {e}");
+							error!("Failed to trash orphaned objects: {e}");
+							break;
 						}
 					}
 				}
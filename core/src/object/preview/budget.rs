@@ -0,0 +1,290 @@
+use crate::{library::Library, prisma::file_path, util::error::FileIOError};
+
+use std::{
+	cmp::Reverse,
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset};
+use prisma_client_rust::QueryError;
+use tokio::{fs, io};
+use tracing::{error, info};
+
+use super::{
+	get_sprite_path, get_text_preview_path, get_thumbnail_path, get_waveform_path, ThumbnailFormat,
+	THUMBNAIL_CACHE_DIR_NAME,
+};
+
+/// When evicting, we go a bit below the cap so we don't have to evict again after the next job
+const EVICTION_TARGET_RATIO: f64 = 0.9;
+
+/// The kinds of preview media, from the first to be evicted to the last. Text previews and
+/// waveforms are quick to generate again, thumbnails are on screen all the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PreviewMediaKind {
+	TextPreview,
+	Waveform,
+	VideoSprite,
+	Thumbnail,
+}
+
+/// How much space the preview media of the files of a library take, in bytes. Preview media are
+/// shared by the libraries of the node, a file in two libraries counts in both.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewMediaUsage {
+	pub thumbnails: u64,
+	pub video_sprites: u64,
+	pub waveforms: u64,
+	pub text_previews: u64,
+}
+
+impl PreviewMediaUsage {
+	pub fn total(&self) -> u64 {
+		self.thumbnails + self.video_sprites + self.waveforms + self.text_previews
+	}
+
+	fn add(&mut self, kind: PreviewMediaKind, size: u64) {
+		match kind {
+			PreviewMediaKind::Thumbnail => self.thumbnails += size,
+			PreviewMediaKind::VideoSprite => self.video_sprites += size,
+			PreviewMediaKind::Waveform => self.waveforms += size,
+			PreviewMediaKind::TextPreview => self.text_previews += size,
+		}
+	}
+}
+
+/// How much the user cares about the object of a file, the ones they care less about lose their
+/// preview media first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct ObjectPriority {
+	favorite: bool,
+	date_accessed: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug)]
+struct PreviewMedia {
+	kind: PreviewMediaKind,
+	cas_id: String,
+	path: PathBuf,
+	size: u64,
+	priority: ObjectPriority,
+}
+
+/// The content ids of the files of the library, with the highest priority of their objects
+async fn library_cas_ids(library: &Library) -> Result<HashMap<String, ObjectPriority>, QueryError> {
+	let file_paths = library
+		.db
+		.file_path()
+		.find_many(vec![file_path::cas_id::not(None)])
+		.select(file_path::select!({ cas_id object: select { favorite date_accessed } }))
+		.exec()
+		.await?;
+
+	let mut cas_ids = HashMap::with_capacity(file_paths.len());
+	for file_path in file_paths {
+		let Some(cas_id) = file_path.cas_id else {
+			continue;
+		};
+
+		let priority = file_path
+			.object
+			.map(|object| ObjectPriority {
+				favorite: object.favorite.unwrap_or(false),
+				date_accessed: object.date_accessed,
+			})
+			.unwrap_or_default();
+
+		let entry = cas_ids.entry(cas_id).or_default();
+		*entry = priority.max(*entry);
+	}
+
+	Ok(cas_ids)
+}
+
+async fn file_size(path: &Path) -> Option<u64> {
+	match fs::metadata(path).await {
+		Ok(metadata) => Some(metadata.len()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+		Err(e) => {
+			error!("{}", FileIOError::from((path, e)));
+			None
+		}
+	}
+}
+
+/// The preview media generated for the files of the library
+async fn library_preview_media(library: &Library) -> Result<Vec<PreviewMedia>, QueryError> {
+	let data_dir = library.config().data_directory();
+	let thumbnail_dir = data_dir.join(THUMBNAIL_CACHE_DIR_NAME);
+
+	let mut media = vec![];
+	for (cas_id, priority) in library_cas_ids(library).await? {
+		let paths = ThumbnailFormat::ALL
+			.into_iter()
+			.map(|format| {
+				(
+					PreviewMediaKind::Thumbnail,
+					get_thumbnail_path(&thumbnail_dir, &cas_id, format),
+				)
+			})
+			.chain([
+				(
+					PreviewMediaKind::VideoSprite,
+					get_sprite_path(&data_dir, &cas_id),
+				),
+				(
+					PreviewMediaKind::Waveform,
+					get_waveform_path(&data_dir, &cas_id),
+				),
+				(
+					PreviewMediaKind::TextPreview,
+					get_text_preview_path(&data_dir, &cas_id),
+				),
+			]);
+
+		for (kind, path) in paths {
+			if let Some(size) = file_size(&path).await {
+				media.push(PreviewMedia {
+					kind,
+					cas_id: cas_id.clone(),
+					path,
+					size,
+					priority,
+				});
+			}
+		}
+	}
+
+	Ok(media)
+}
+
+/// The preview media to remove for the rest to fit in `max_size`, the ones of objects that aren't
+/// favorites first, then by kind and the least recently accessed first
+fn eviction_candidates(mut media: Vec<PreviewMedia>, max_size: u64) -> Vec<PreviewMedia> {
+	let mut size = media.iter().map(|media| media.size).sum::<u64>();
+	if size <= max_size {
+		return vec![];
+	}
+
+	let target = (max_size as f64 * EVICTION_TARGET_RATIO) as u64;
+
+	// Sorted in reverse, so the next candidate is popped from the end
+	media.sort_by_key(|media| {
+		Reverse((
+			media.priority.favorite,
+			media.kind,
+			media.priority.date_accessed,
+		))
+	});
+
+	let mut candidates = vec![];
+	while size > target {
+		let Some(media) = media.pop() else {
+			break;
+		};
+
+		size -= media.size;
+		candidates.push(media);
+	}
+
+	candidates
+}
+
+/// How much space the preview media of the library take
+pub async fn preview_media_usage(library: &Library) -> Result<PreviewMediaUsage, QueryError> {
+	let mut usage = PreviewMediaUsage::default();
+	for media in library_preview_media(library).await? {
+		usage.add(media.kind, media.size);
+	}
+
+	Ok(usage)
+}
+
+/// Removes preview media of the library until they fit in `max_size_mb` again. The media of other
+/// libraries aren't touched, but the ones shared with them can be removed. Clones never remove
+/// any, they only borrow the media of their source library.
+pub async fn enforce_preview_media_budget(
+	library: &Library,
+	max_size_mb: u32,
+) -> Result<(), QueryError> {
+	if library.config.cloned_from.is_some() {
+		return Ok(());
+	}
+
+	let max_size = max_size_mb as u64 * 1024 * 1024;
+
+	let candidates = eviction_candidates(library_preview_media(library).await?, max_size);
+	if candidates.is_empty() {
+		return Ok(());
+	}
+
+	info!(
+		"Preview media of library '{}' over its {max_size} bytes limit, evicting {} files",
+		library.id,
+		candidates.len()
+	);
+
+	for media in candidates {
+		match fs::remove_file(&media.path).await {
+			Ok(()) => {
+				if media.kind == PreviewMediaKind::Thumbnail {
+					library.thumbnail_cache().removed(media.cas_id).await;
+				}
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => error!(
+				"Failed to evict preview media: {}",
+				FileIOError::from((media.path, e))
+			),
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn media(kind: PreviewMediaKind, cas_id: &str, favorite: bool) -> PreviewMedia {
+		PreviewMedia {
+			kind,
+			cas_id: cas_id.to_string(),
+			path: PathBuf::from(cas_id),
+			size: 100,
+			priority: ObjectPriority {
+				favorite,
+				date_accessed: None,
+			},
+		}
+	}
+
+	#[test]
+	fn evicts_by_priority() {
+		let all = || {
+			vec![
+				media(PreviewMediaKind::Thumbnail, "a", false),
+				media(PreviewMediaKind::TextPreview, "b", true),
+				media(PreviewMediaKind::Waveform, "c", false),
+				media(PreviewMediaKind::TextPreview, "d", false),
+			]
+		};
+
+		assert!(eviction_candidates(all(), 400).is_empty());
+
+		// 90% of 300 is 270, so two of them have to go
+		let evicted = eviction_candidates(all(), 300)
+			.into_iter()
+			.map(|media| media.cas_id)
+			.collect::<Vec<_>>();
+		assert_eq!(evicted, vec!["d", "c"]);
+
+		// 90% of 200 is 180, only the favorite is kept, whatever its kind
+		let evicted = eviction_candidates(all(), 200)
+			.into_iter()
+			.map(|media| media.cas_id)
+			.collect::<Vec<_>>();
+		assert_eq!(evicted, vec!["d", "c", "a"]);
+	}
+}
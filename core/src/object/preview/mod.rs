@@ -1,5 +1,13 @@
+mod budget;
 mod media_data;
+mod sprite;
+mod text;
 mod thumbnail;
+mod waveform;
 
+pub use budget::*;
 pub use media_data::*;
+pub use sprite::*;
+pub use text::*;
 pub use thumbnail::*;
+pub use waveform::*;
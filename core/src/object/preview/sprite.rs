@@ -0,0 +1,82 @@
+use crate::{library::Library, util::error::FileIOError};
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+#[cfg(feature = "ffmpeg")]
+use tokio::{fs, io};
+
+use super::get_shard_hex;
+
+#[cfg(feature = "ffmpeg")]
+use super::ThumbnailerOptions;
+
+pub const SPRITE_DIR_NAME: &str = "sprites";
+
+/// Frames are only shown while hovering a thumbnail, so they are a lot smaller than thumbnails
+pub const SPRITE_FRAME_SIZE: u32 = 160;
+
+/// Layout of a video's sprite sheet, so clients know which part of the image to show for each
+/// position of the cursor. Frames are laid out left to right and top to bottom.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Type)]
+pub struct VideoSprite {
+	pub columns: u32,
+	pub rows: u32,
+	pub frame_count: u32,
+}
+
+/// Sprite sheets are stored alongside thumbnails in the node's data directory, sharded the same way
+pub fn get_sprite_path(data_dir: impl AsRef<Path>, cas_id: &str) -> PathBuf {
+	data_dir
+		.as_ref()
+		.join(SPRITE_DIR_NAME)
+		.join(get_shard_hex(cas_id))
+		.join(cas_id)
+		.with_extension("webp")
+}
+
+#[cfg(feature = "ffmpeg")]
+pub async fn generate_video_sprite<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+	options: ThumbnailerOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+	sd_ffmpeg::to_sprite_sheet(
+		file_path,
+		output_path,
+		SPRITE_FRAME_SIZE,
+		options.quality.min(100) as f32,
+	)
+	.await
+	.map_err(Into::into)
+}
+
+/// Returns the sprite sheet layout of an object if it was already generated by the thumbnailer
+#[cfg(feature = "ffmpeg")]
+pub async fn get_video_sprite(
+	library: &Library,
+	cas_id: &str,
+) -> Result<Option<VideoSprite>, FileIOError> {
+	let path = get_sprite_path(library.config().data_directory(), cas_id);
+
+	match fs::metadata(&path).await {
+		Ok(_) => Ok(Some(VideoSprite {
+			columns: sd_ffmpeg::SPRITE_COLUMNS,
+			rows: sd_ffmpeg::SPRITE_ROWS,
+			frame_count: sd_ffmpeg::SPRITE_FRAME_COUNT,
+		})),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(FileIOError::from((path, e))),
+	}
+}
+
+/// Sprite sheets are only generated with ffmpeg
+#[cfg(not(feature = "ffmpeg"))]
+pub async fn get_video_sprite(
+	_library: &Library,
+	_cas_id: &str,
+) -> Result<Option<VideoSprite>, FileIOError> {
+	Ok(None)
+}
@@ -0,0 +1,292 @@
+use crate::{library::Library, util::error::FileIOError};
+
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+};
+
+use sd_file_ext::extensions::{Extension, TextExtension};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncReadExt},
+};
+
+use super::get_shard_hex;
+
+pub const TEXT_PREVIEW_DIR_NAME: &str = "text_previews";
+
+/// Amount of bytes read from the start of the file, enough to fill the inspector
+pub const TEXT_PREVIEW_SIZE: usize = 16 * 1024;
+
+pub(super) static FILTERED_TEXT_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
+	sd_file_ext::extensions::ALL_TEXT_EXTENSIONS
+		.iter()
+		.map(Clone::clone)
+		.filter(can_generate_preview_for_text)
+		.map(Extension::Text)
+		.chain(
+			sd_file_ext::extensions::ALL_CODE_EXTENSIONS
+				.iter()
+				.map(Clone::clone)
+				.map(Extension::Code),
+		)
+		.collect()
+});
+
+pub const fn can_generate_preview_for_text(text_extension: &TextExtension) -> bool {
+	use TextExtension::*;
+	// Rich text files are mostly markup, a snippet of them isn't useful
+	!matches!(text_extension, Rtf)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TextEncoding {
+	Utf8,
+	Utf16Le,
+	Utf16Be,
+	/// Fallback for files that aren't valid UTF-8, every byte maps to a character
+	Latin1,
+}
+
+/// The start of a text file, so clients can show it without reading arbitrary paths themselves
+#[derive(Debug, Serialize, Deserialize, Clone, Type)]
+pub struct TextPreview {
+	/// Language identifier to highlight the content with, `None` for plain text
+	pub language: Option<String>,
+	pub encoding: TextEncoding,
+	pub content: String,
+	/// If the file is bigger than the preview, so the content was cut short
+	pub truncated: bool,
+}
+
+/// Text previews are stored alongside thumbnails in the node's data directory, sharded the same way
+pub fn get_text_preview_path(data_dir: impl AsRef<Path>, cas_id: &str) -> PathBuf {
+	data_dir
+		.as_ref()
+		.join(TEXT_PREVIEW_DIR_NAME)
+		.join(get_shard_hex(cas_id))
+		.join(cas_id)
+		.with_extension("json")
+}
+
+pub async fn generate_text_preview<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+) -> Result<(), Box<dyn Error>> {
+	let file_path = file_path.as_ref();
+
+	let mut buffer = Vec::with_capacity(TEXT_PREVIEW_SIZE);
+	// Reading one byte more than we keep, to know if the file was truncated
+	File::open(file_path)
+		.await?
+		.take(TEXT_PREVIEW_SIZE as u64 + 1)
+		.read_to_end(&mut buffer)
+		.await?;
+
+	let truncated = buffer.len() > TEXT_PREVIEW_SIZE;
+	buffer.truncate(TEXT_PREVIEW_SIZE);
+
+	let (content, encoding) =
+		decode_text(&buffer, truncated).ok_or("file doesn't look like text")?;
+
+	let preview = TextPreview {
+		language: detect_language(file_path, &content).map(str::to_string),
+		encoding,
+		content,
+		truncated,
+	};
+
+	fs::write(output_path, serde_json::to_vec(&preview)?)
+		.await
+		.map_err(Into::into)
+}
+
+/// Returns the text preview of an object if it was already generated by the thumbnailer
+pub async fn get_text_preview(
+	library: &Library,
+	cas_id: &str,
+) -> Result<Option<TextPreview>, FileIOError> {
+	let path = get_text_preview_path(library.config().data_directory(), cas_id);
+
+	match fs::read(&path).await {
+		// A corrupted preview is the same as a missing one, it will be generated again
+		Ok(data) => Ok(serde_json::from_slice(&data).ok()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(FileIOError::from((path, e))),
+	}
+}
+
+/// Decodes the start of a text file, detecting its encoding from the byte order mark or falling
+/// back to Latin-1 if it isn't valid UTF-8. `truncated` signals that the data was cut at an
+/// arbitrary point, so an incomplete character at the end is dropped instead of rejected.
+/// Returns `None` for binary data.
+fn decode_text(data: &[u8], truncated: bool) -> Option<(String, TextEncoding)> {
+	if let Some(data) = data.strip_prefix(&[0xFF, 0xFE]) {
+		return Some((
+			decode_utf16(data, u16::from_le_bytes),
+			TextEncoding::Utf16Le,
+		));
+	}
+
+	if let Some(data) = data.strip_prefix(&[0xFE, 0xFF]) {
+		return Some((
+			decode_utf16(data, u16::from_be_bytes),
+			TextEncoding::Utf16Be,
+		));
+	}
+
+	let data = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+
+	// Text files don't have null bytes, this is the same heuristic used by git and grep
+	if data.contains(&0) {
+		return None;
+	}
+
+	match std::str::from_utf8(data) {
+		Ok(text) => Some((text.to_string(), TextEncoding::Utf8)),
+		// The error has no length when the data ends in the middle of a character
+		Err(e) if truncated && e.error_len().is_none() => Some((
+			String::from_utf8_lossy(&data[..e.valid_up_to()]).into_owned(),
+			TextEncoding::Utf8,
+		)),
+		Err(_) => Some((
+			data.iter().map(|&byte| byte as char).collect(),
+			TextEncoding::Latin1,
+		)),
+	}
+}
+
+fn decode_utf16(data: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+	char::decode_utf16(
+		data.chunks_exact(2)
+			.map(|bytes| from_bytes([bytes[0], bytes[1]])),
+	)
+	.map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+	.collect()
+}
+
+/// Detects the language of the file from its extension, or from the shebang of scripts
+/// without one. The identifiers are the ones used by most syntax highlighters.
+fn detect_language(path: &Path, content: &str) -> Option<&'static str> {
+	let extension = path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.map(str::to_ascii_lowercase);
+
+	let language = match extension.as_deref() {
+		Some("rs") => "rust",
+		Some("ts") => "typescript",
+		Some("tsx") => "tsx",
+		Some("js") => "javascript",
+		Some("jsx") => "jsx",
+		Some("vue") => "vue",
+		Some("php") => "php",
+		Some("py") => "python",
+		Some("rb") => "ruby",
+		Some("sh" | "bash" | "zsh") => "bash",
+		Some("html") => "html",
+		Some("css") => "css",
+		Some("sass") => "sass",
+		Some("scss") => "scss",
+		Some("less") => "less",
+		Some("c" | "h") => "c",
+		Some("cpp" | "hpp") => "cpp",
+		Some("java") => "java",
+		Some("scala") => "scala",
+		Some("go") => "go",
+		Some("dart") => "dart",
+		Some("swift") => "swift",
+		Some("mdx") => "mdx",
+		Some("astro") => "astro",
+		Some("md") => "markdown",
+		Some("json") => "json",
+		Some("yaml" | "yml") => "yaml",
+		Some("toml") => "toml",
+		Some("xml") => "xml",
+		Some("csv") => "csv",
+		Some("cfg") => "ini",
+		_ => return detect_shebang(content),
+	};
+
+	Some(language)
+}
+
+fn detect_shebang(content: &str) -> Option<&'static str> {
+	let interpreter = content
+		.lines()
+		.next()?
+		.strip_prefix("#!")?
+		.split_whitespace()
+		// `#!/usr/bin/env python3` has the interpreter as the argument
+		.find(|part| !part.ends_with("/env"))?
+		.rsplit('/')
+		.next()?;
+
+	match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+		"sh" | "bash" | "zsh" | "dash" => Some("bash"),
+		"python" => Some("python"),
+		"node" => Some("javascript"),
+		"ruby" => Some("ruby"),
+		"php" => Some("php"),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decodes_encodings() {
+		assert_eq!(
+			decode_text("olá".as_bytes(), false),
+			Some(("olá".to_string(), TextEncoding::Utf8))
+		);
+		assert_eq!(
+			decode_text(&[0xEF, 0xBB, 0xBF, b'h', b'i'], false),
+			Some(("hi".to_string(), TextEncoding::Utf8))
+		);
+		assert_eq!(
+			decode_text(&[0xFF, 0xFE, b'h', 0, b'i', 0], false),
+			Some(("hi".to_string(), TextEncoding::Utf16Le))
+		);
+		assert_eq!(
+			decode_text(&[0xFE, 0xFF, 0, b'h', 0, b'i'], false),
+			Some(("hi".to_string(), TextEncoding::Utf16Be))
+		);
+		assert_eq!(
+			decode_text(&[b'o', b'l', 0xE1], false),
+			Some(("olá".to_string(), TextEncoding::Latin1))
+		);
+		assert_eq!(decode_text(&[0x7F, b'E', b'L', b'F', 0, 0], false), None);
+	}
+
+	#[test]
+	fn truncated_character_is_dropped() {
+		// "á" is 0xC3 0xA1 in UTF-8, cut in the middle
+		assert_eq!(
+			decode_text(&[b'o', b'l', 0xC3], true),
+			Some(("ol".to_string(), TextEncoding::Utf8))
+		);
+	}
+
+	#[test]
+	fn detects_languages() {
+		assert_eq!(detect_language(Path::new("main.RS"), ""), Some("rust"));
+		assert_eq!(detect_language(Path::new("notes.txt"), "hello"), None);
+		assert_eq!(
+			detect_language(Path::new("script"), "#!/usr/bin/env python3\nprint()"),
+			Some("python")
+		);
+		assert_eq!(
+			detect_language(Path::new("build"), "#!/bin/bash -e\necho"),
+			Some("bash")
+		);
+		assert_eq!(detect_language(Path::new("README"), "# Title"), None);
+	}
+}
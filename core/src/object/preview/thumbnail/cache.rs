@@ -0,0 +1,269 @@
+use crate::node::NodeConfigManager;
+
+use std::{collections::HashMap, path::Path, sync::Arc, time::SystemTime};
+
+use tokio::{fs, sync::mpsc};
+use tracing::{debug, error, info};
+
+use super::{
+	directory::list_thumbnails, find_thumbnail, get_thumbnail_path, ThumbnailFormat,
+	THUMBNAIL_CACHE_DIR_NAME,
+};
+
+/// When evicting, we go a bit below the cap so we don't have to evict again on the next insert
+const EVICTION_TARGET_RATIO: f64 = 0.9;
+
+enum ThumbnailCacheEvent {
+	Accessed(String),
+	Created(String),
+	Removed(String),
+	Evict,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+	size: u64,
+	last_access: u64,
+}
+
+/// Keeps track of the thumbnails in the cache ordered by recency of use, so the least recently
+/// used ones can be evicted when the cache grows over the configured size.
+#[derive(Debug, Default)]
+struct LruIndex {
+	entries: HashMap<String, CacheEntry>,
+	total_size: u64,
+	clock: u64,
+}
+
+impl LruIndex {
+	fn insert(&mut self, cas_id: String, size: u64) {
+		self.clock += 1;
+		if let Some(old) = self.entries.insert(
+			cas_id,
+			CacheEntry {
+				size,
+				last_access: self.clock,
+			},
+		) {
+			self.total_size -= old.size;
+		}
+		self.total_size += size;
+	}
+
+	fn touch(&mut self, cas_id: &str) {
+		if let Some(entry) = self.entries.get_mut(cas_id) {
+			self.clock += 1;
+			entry.last_access = self.clock;
+		}
+	}
+
+	fn remove(&mut self, cas_id: &str) {
+		if let Some(entry) = self.entries.remove(cas_id) {
+			self.total_size -= entry.size;
+		}
+	}
+
+	/// Returns the least recently used thumbnails that must be removed to fit in `max_size`
+	fn eviction_candidates(&self, max_size: u64) -> Vec<String> {
+		if self.total_size <= max_size {
+			return vec![];
+		}
+
+		let target = (max_size as f64 * EVICTION_TARGET_RATIO) as u64;
+
+		let mut entries = self.entries.iter().collect::<Vec<_>>();
+		entries.sort_by_key(|(_, entry)| entry.last_access);
+
+		let mut size = self.total_size;
+		entries
+			.into_iter()
+			.take_while(|(_, entry)| {
+				let keep_going = size > target;
+				size -= entry.size;
+				keep_going
+			})
+			.map(|(cas_id, _)| cas_id.clone())
+			.collect()
+	}
+}
+
+/// Actor that tracks thumbnail usage and evicts the least recently used thumbnails when the cache
+/// is bigger than the size configured on the node. Thumbnails are shared by all libraries, so a
+/// single actor exists per node.
+#[derive(Clone)]
+pub struct ThumbnailCacheActor {
+	tx: mpsc::Sender<ThumbnailCacheEvent>,
+}
+
+impl ThumbnailCacheActor {
+	pub fn spawn(config: Arc<NodeConfigManager>) -> Self {
+		let (tx, mut rx) = mpsc::channel(1024);
+
+		tokio::spawn(async move {
+			let thumbnail_dir = config.data_directory().join(THUMBNAIL_CACHE_DIR_NAME);
+			let mut index = load_index(&thumbnail_dir).await;
+
+			debug!(
+				"Thumbnail cache has {} thumbnails using {} bytes",
+				index.entries.len(),
+				index.total_size
+			);
+
+			while let Some(event) = rx.recv().await {
+				match event {
+					ThumbnailCacheEvent::Accessed(cas_id) => {
+						index.touch(&cas_id);
+						continue;
+					}
+					ThumbnailCacheEvent::Created(cas_id) => {
+						match find_thumbnail(&thumbnail_dir, &cas_id).await {
+							Ok(Some((path, _))) => match fs::metadata(&path).await {
+								Ok(metadata) => index.insert(cas_id, metadata.len()),
+								Err(e) => {
+									error!("Failed to read thumbnail metadata {path:?}: {e:#?}")
+								}
+							},
+							Ok(None) => {}
+							Err(e) => error!("Failed to find thumbnail: {e:#?}"),
+						}
+					}
+					ThumbnailCacheEvent::Removed(cas_id) => {
+						index.remove(&cas_id);
+						continue;
+					}
+					ThumbnailCacheEvent::Evict => {}
+				}
+
+				let Some(max_size) = config
+					.get()
+					.await
+					.thumbnail_cache_max_size_mb
+					.map(|max_size_mb| max_size_mb as u64 * 1024 * 1024)
+				else {
+					continue;
+				};
+
+				let candidates = index.eviction_candidates(max_size);
+				if candidates.is_empty() {
+					continue;
+				}
+
+				info!(
+					"Thumbnail cache over its {max_size} bytes limit, evicting {} thumbnails",
+					candidates.len()
+				);
+
+				for cas_id in candidates {
+					let mut evicted = true;
+					for format in ThumbnailFormat::ALL {
+						let path = get_thumbnail_path(&thumbnail_dir, &cas_id, format);
+						match fs::remove_file(&path).await {
+							Ok(()) => {}
+							Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+							Err(e) => {
+								error!("Failed to evict thumbnail {path:?}: {e:#?}");
+								evicted = false;
+							}
+						}
+					}
+
+					if evicted {
+						index.remove(&cas_id);
+					}
+				}
+			}
+		});
+
+		Self { tx }
+	}
+
+	/// Marks a thumbnail as recently used, called when a thumbnail is served to the frontend
+	pub fn accessed(&self, cas_id: impl Into<String>) {
+		// We don't want to slow down serving thumbnails, so if the actor is busy we just skip it
+		self.tx
+			.try_send(ThumbnailCacheEvent::Accessed(cas_id.into()))
+			.ok();
+	}
+
+	/// Registers a newly generated thumbnail, evicting old ones if the cache is over its limit
+	pub async fn created(&self, cas_id: impl Into<String>) {
+		self.tx
+			.send(ThumbnailCacheEvent::Created(cas_id.into()))
+			.await
+			.ok();
+	}
+
+	/// Forgets a thumbnail that was removed from disk outside of the cache
+	pub async fn removed(&self, cas_id: impl Into<String>) {
+		self.tx
+			.send(ThumbnailCacheEvent::Removed(cas_id.into()))
+			.await
+			.ok();
+	}
+
+	/// Evicts thumbnails if the cache is over its limit, used after changing the limit
+	pub async fn evict(&self) {
+		self.tx.send(ThumbnailCacheEvent::Evict).await.ok();
+	}
+}
+
+/// Builds the index from the thumbnails on disk, using their modification date as the initial
+/// recency, as we don't persist access times between runs
+async fn load_index(thumbnail_dir: &Path) -> LruIndex {
+	let mut thumbnails = list_thumbnails(thumbnail_dir)
+		.await
+		.into_iter()
+		.map(|(cas_id, _, metadata)| {
+			(
+				cas_id,
+				metadata.len(),
+				metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+			)
+		})
+		.collect::<Vec<_>>();
+
+	thumbnails.sort_by_key(|(_, _, modified)| *modified);
+
+	let mut index = LruIndex::default();
+	for (cas_id, size, _) in thumbnails {
+		index.insert(cas_id, size);
+	}
+
+	index
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_least_recently_used_first() {
+		let mut index = LruIndex::default();
+		index.insert("a".into(), 100);
+		index.insert("b".into(), 100);
+		index.insert("c".into(), 100);
+		index.insert("d".into(), 100);
+
+		assert!(index.eviction_candidates(400).is_empty());
+
+		index.touch("a");
+
+		// 90% of 300 is 270, so two thumbnails have to go
+		assert_eq!(index.eviction_candidates(300), vec!["b", "c"]);
+
+		index.remove("b");
+		index.remove("c");
+		assert_eq!(index.total_size, 200);
+		assert_eq!(index.eviction_candidates(150), vec!["d"]);
+	}
+
+	#[test]
+	fn reinserting_replaces_size() {
+		let mut index = LruIndex::default();
+		index.insert("a".into(), 100);
+		index.insert("a".into(), 40);
+
+		assert_eq!(index.total_size, 40);
+		assert_eq!(index.entries.len(), 1);
+	}
+}
@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+	fs::Metadata,
+	path::{Path, PathBuf},
+};
 use tokio::fs as async_fs;
 
 use int_enum::IntEnum;
@@ -6,7 +9,7 @@ use tracing::{error, info};
 
 use crate::util::{error::FileIOError, version_manager::VersionManager};
 
-use super::{get_shard_hex, ThumbnailerError, THUMBNAIL_CACHE_DIR_NAME};
+use super::{get_shard_hex, ThumbnailFormat, ThumbnailerError, THUMBNAIL_CACHE_DIR_NAME};
 
 #[derive(IntEnum, Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(i32)]
@@ -100,3 +103,44 @@ async fn move_webp_files(dir: &PathBuf) -> Result<(), ThumbnailerError> {
 	);
 	Ok(())
 }
+
+/// Lists the thumbnails of every shard in the thumbnail directory, with their cas_id and metadata.
+/// Unreadable entries are skipped, as well as files that aren't thumbnails.
+pub(super) async fn list_thumbnails(thumbnail_dir: &Path) -> Vec<(String, PathBuf, Metadata)> {
+	let mut thumbnails = vec![];
+
+	let Ok(mut shards) = async_fs::read_dir(thumbnail_dir).await else {
+		return thumbnails;
+	};
+
+	while let Ok(Some(shard)) = shards.next_entry().await {
+		let Ok(mut entries) = async_fs::read_dir(shard.path()).await else {
+			continue;
+		};
+
+		while let Ok(Some(entry)) = entries.next_entry().await {
+			let path = entry.path();
+			if path
+				.extension()
+				.and_then(|ext| ext.to_str())
+				.and_then(ThumbnailFormat::from_extension)
+				.is_none()
+			{
+				continue;
+			}
+
+			let (Some(cas_id), Ok(metadata)) = (
+				path.file_stem()
+					.and_then(|stem| stem.to_str())
+					.map(str::to_string),
+				entry.metadata().await,
+			) else {
+				continue;
+			};
+
+			thumbnails.push((cas_id, path, metadata));
+		}
+	}
+
+	thumbnails
+}
@@ -0,0 +1,401 @@
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunMetadata, JobState,
+		JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::Library,
+	location::file_path_helper::file_path_for_thumbnailer,
+	prisma::{file_path, location},
+	util::{
+		db::{chain_optional_iter, keyset_pages, maybe_missing},
+		error::FileIOError,
+	},
+};
+
+use std::{
+	collections::{HashMap, HashSet},
+	hash::Hash,
+	path::PathBuf,
+	pin::pin,
+};
+
+use futures::TryStreamExt;
+use prisma_client_rust::Direction;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, task::block_in_place};
+use tracing::{info, warn};
+
+use super::{
+	directory::{init_thumbnail_dir, list_thumbnails},
+	find_thumbnail, inner_process_step, ThumbnailFormat, ThumbnailerError, ThumbnailerJobStep,
+	ThumbnailerJobStepKind, ThumbnailerOptions,
+};
+
+/// Orphan candidates are looked up in the database in chunks, to stay under SQLite's limit of
+/// variables in a single query
+const ORPHAN_QUERY_CHUNK_SIZE: usize = 1000;
+/// The file paths read at once to find the thumbnails to check
+const PAGE_SIZE: i64 = 10_000;
+
+file_path::select!(file_path_to_check {
+	id
+	materialized_path
+	is_dir
+	name
+	extension
+	cas_id
+});
+
+pub struct ThumbnailIntegrityJob {}
+
+#[derive(Serialize, Deserialize, Debug, Hash)]
+pub struct ThumbnailIntegrityJobInit {
+	/// Remove thumbnails that no file of the library refers to. Thumbnails are shared by all
+	/// libraries of the node, so this is only safe when the library is the only one.
+	pub prune_orphans: bool,
+}
+
+impl JobInitData for ThumbnailIntegrityJobInit {
+	type Job = ThumbnailIntegrityJob;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThumbnailIntegrityJobData {
+	thumbnail_dir: PathBuf,
+	/// Locations of the files to check, with their path on this node
+	locations: HashMap<location::id::Type, (location::Data, PathBuf)>,
+	options: ThumbnailerOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ThumbnailIntegrityJobStep {
+	/// The thumbnail of this file must exist and be readable, or it's generated again
+	Check {
+		location_id: location::id::Type,
+		step: ThumbnailerJobStep,
+	},
+	/// A thumbnail that no file refers to
+	Prune { cas_id: String, path: PathBuf },
+}
+
+/// Summary of the check, shown once the job is done
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ThumbnailIntegrityJobRunMetadata {
+	thumbnails_checked: u32,
+	missing_regenerated: u32,
+	corrupted_regenerated: u32,
+	/// Thumbnails that were missing or corrupted and couldn't be generated again
+	regeneration_failed: u32,
+	orphans_pruned: u32,
+}
+
+impl JobRunMetadata for ThumbnailIntegrityJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.thumbnails_checked += new_data.thumbnails_checked;
+		self.missing_regenerated += new_data.missing_regenerated;
+		self.corrupted_regenerated += new_data.corrupted_regenerated;
+		self.regeneration_failed += new_data.regeneration_failed;
+		self.orphans_pruned += new_data.orphans_pruned;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for ThumbnailIntegrityJob {
+	type Init = ThumbnailIntegrityJobInit;
+	type Data = ThumbnailIntegrityJobData;
+	type Step = ThumbnailIntegrityJobStep;
+	type RunMetadata = ThumbnailIntegrityJobRunMetadata;
+
+	const NAME: &'static str = "thumbnail_integrity";
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &ctx.library;
+
+		let thumbnail_dir = init_thumbnail_dir(ctx.library.config().data_directory()).await?;
+
+		let mut locations = HashMap::new();
+		let mut checked_cas_ids = HashSet::new();
+		let mut steps = vec![];
+
+		for location in db.location().find_many(vec![]).exec().await? {
+			// Locations on disconnected devices have no path, their files can't be read anyway
+			let Ok(location_path) =
+				maybe_missing(&location.path, "location.path").map(PathBuf::from)
+			else {
+				continue;
+			};
+
+			{
+				let mut pages = pin!(keyset_pages(
+					PAGE_SIZE,
+					|file_path: &file_path_to_check::Data| file_path.id,
+					|after, take| {
+						db.file_path()
+							.find_many(chain_optional_iter(
+								[
+									file_path::location_id::equals(Some(location.id)),
+									file_path::is_dir::equals(Some(false)),
+									file_path::cas_id::not(None),
+								],
+								[after.map(file_path::id::gt)],
+							))
+							.order_by(file_path::id::order(Direction::Asc))
+							.take(take)
+							.select(file_path_to_check::select())
+							.exec()
+					},
+				));
+				while let Some(file_paths) = pages.try_next().await? {
+					for file_path in file_paths {
+						let file_path = file_path_for_thumbnailer::Data {
+							materialized_path: file_path.materialized_path,
+							is_dir: file_path.is_dir,
+							name: file_path.name,
+							extension: file_path.extension,
+							cas_id: file_path.cas_id,
+						};
+
+						let Some(kind) = file_path
+							.extension
+							.as_deref()
+							.and_then(|extension| {
+								ThumbnailerJobStepKind::from_extension(extension, &location)
+							})
+							.filter(ThumbnailerJobStepKind::is_thumbnail)
+						else {
+							continue;
+						};
+
+						// Many file paths can share a cas_id, one check is enough for all of them
+						match &file_path.cas_id {
+							Some(cas_id) if checked_cas_ids.insert(cas_id.clone()) => {}
+							_ => continue,
+						}
+
+						steps.push(ThumbnailIntegrityJobStep::Check {
+							location_id: location.id,
+							step: ThumbnailerJobStep { file_path, kind },
+						});
+					}
+				}
+			}
+
+			locations.insert(location.id, (location, location_path));
+		}
+
+		info!("Checking {} thumbnails", steps.len());
+
+		// The thumbnails of the node are read-only to clones, which share them with their source
+		if init.prune_orphans && ctx.library.config.cloned_from.is_none() {
+			let orphans = list_thumbnails(&thumbnail_dir)
+				.await
+				.into_iter()
+				.filter(|(cas_id, _, _)| !checked_cas_ids.contains(cas_id))
+				.collect::<Vec<_>>();
+
+			// Files of offline locations still refer to their thumbnails, so they aren't orphans
+			let mut referenced = HashSet::new();
+			for chunk in orphans.chunks(ORPHAN_QUERY_CHUNK_SIZE) {
+				referenced.extend(
+					db.file_path()
+						.find_many(vec![file_path::cas_id::in_vec(
+							chunk.iter().map(|(cas_id, _, _)| cas_id.clone()).collect(),
+						)])
+						.select(file_path::select!({ cas_id }))
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|file_path| file_path.cas_id),
+				);
+			}
+
+			let orphan_steps = orphans
+				.into_iter()
+				.filter(|(cas_id, _, _)| !referenced.contains(cas_id))
+				.map(|(cas_id, path, _)| ThumbnailIntegrityJobStep::Prune { cas_id, path })
+				.collect::<Vec<_>>();
+
+			info!("Found {} orphan thumbnails", orphan_steps.len());
+
+			steps.extend(orphan_steps);
+		}
+
+		ctx.progress_msg(job_message!(
+			"thumbnails.preparingCheck",
+			count = steps.len()
+		));
+
+		*data = Some(ThumbnailIntegrityJobData {
+			thumbnail_dir,
+			locations,
+			options: ThumbnailerOptions::for_library(&ctx.library, false).await,
+		});
+
+		Ok((Default::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		_: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let mut new_metadata = Self::RunMetadata::default();
+
+		match step {
+			ThumbnailIntegrityJobStep::Check { location_id, step } => {
+				let Some(cas_id) = &step.file_path.cas_id else {
+					return Ok(new_metadata.into());
+				};
+
+				ctx.progress_msg(job_message!(
+					"thumbnails.checking",
+					path = maybe_missing(
+						&step.file_path.materialized_path,
+						"file_path.materialized_path"
+					)?
+				));
+
+				new_metadata.thumbnails_checked += 1;
+
+				let corrupted = match find_thumbnail(&data.thumbnail_dir, cas_id)
+					.await
+					.map_err(ThumbnailerError::from)?
+				{
+					Some((path, format)) => {
+						let content = fs::read(&path)
+							.await
+							.map_err(|e| ThumbnailerError::from(FileIOError::from((&path, e))))?;
+
+						if block_in_place(|| is_valid_thumbnail(&content, format)) {
+							return Ok(new_metadata.into());
+						}
+
+						warn!("Removing corrupted thumbnail {}", path.display());
+						fs::remove_file(&path)
+							.await
+							.map_err(|e| ThumbnailerError::from(FileIOError::from((&path, e))))?;
+						ctx.library.thumbnail_cache().removed(cas_id).await;
+
+						true
+					}
+					None => false,
+				};
+
+				let Some((location, location_path)) = data.locations.get(location_id) else {
+					return Ok(new_metadata.into());
+				};
+
+				inner_process_step(
+					step,
+					location_path,
+					&data.thumbnail_dir,
+					data.options,
+					location,
+					&ctx.library,
+				)
+				.await?;
+
+				// Generation errors are only logged, so we check if the thumbnail is there now
+				if find_thumbnail(&data.thumbnail_dir, cas_id)
+					.await
+					.map_err(ThumbnailerError::from)?
+					.is_none()
+				{
+					new_metadata.regeneration_failed += 1;
+				} else if corrupted {
+					new_metadata.corrupted_regenerated += 1;
+				} else {
+					new_metadata.missing_regenerated += 1;
+				}
+			}
+			ThumbnailIntegrityJobStep::Prune { cas_id, path } => {
+				ctx.progress_msg(job_message!("thumbnails.removingOrphan", cas_id = cas_id));
+
+				match fs::remove_file(path).await {
+					Ok(()) => new_metadata.orphans_pruned += 1,
+					// Already gone, nothing to do
+					Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+					Err(e) => {
+						return Err(ThumbnailerError::from(FileIOError::from((path, e))).into())
+					}
+				}
+
+				ctx.library.thumbnail_cache().removed(cas_id).await;
+			}
+		}
+
+		Ok(new_metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let metadata = &state.run_metadata;
+
+		info!(
+			"Finished checking {} thumbnails: {} missing and {} corrupted regenerated, {} failed, \
+			{} orphans pruned",
+			metadata.thumbnails_checked,
+			metadata.missing_regenerated,
+			metadata.corrupted_regenerated,
+			metadata.regeneration_failed,
+			metadata.orphans_pruned
+		);
+
+		if metadata.missing_regenerated > 0 || metadata.corrupted_regenerated > 0 {
+			invalidate_query!(ctx.library, "search.paths");
+		}
+
+		Ok(Some(serde_json::to_value(metadata)?))
+	}
+}
+
+/// Checks that the thumbnail can be decoded. AVIF decoding isn't available, so for those only the
+/// container header is checked, catching empty and truncated files.
+fn is_valid_thumbnail(content: &[u8], format: ThumbnailFormat) -> bool {
+	match format {
+		ThumbnailFormat::Webp => {
+			image::load_from_memory_with_format(content, image::ImageFormat::WebP).is_ok()
+		}
+		ThumbnailFormat::Avif => {
+			content.len() > 12
+				&& &content[4..8] == b"ftyp"
+				&& matches!(&content[8..12], b"avif" | b"avis")
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejects_broken_thumbnails() {
+		assert!(!is_valid_thumbnail(&[], ThumbnailFormat::Webp));
+		assert!(!is_valid_thumbnail(
+			b"RIFF\0\0\0\0WEBP",
+			ThumbnailFormat::Webp
+		));
+		assert!(!is_valid_thumbnail(&[], ThumbnailFormat::Avif));
+		assert!(!is_valid_thumbnail(
+			b"\0\0\0\x1cftypheic\0\0\0\0",
+			ThumbnailFormat::Avif
+		));
+		assert!(is_valid_thumbnail(
+			b"\0\0\0\x1cftypavif\0\0\0\0",
+			ThumbnailFormat::Avif
+		));
+	}
+}
@@ -3,6 +3,7 @@ use crate::{
 	job::JobError,
 	library::Library,
 	location::file_path_helper::{file_path_for_thumbnailer, FilePathError, IsolatedFilePathData},
+	object::preview::{generate_text_preview, get_text_preview_path, get_waveform_path},
 	prisma::location,
 	util::{db::maybe_missing, error::FileIOError, version_manager::VersionManagerError},
 };
@@ -18,36 +19,158 @@ use sd_file_ext::extensions::{Extension, ImageExtension};
 #[cfg(feature = "ffmpeg")]
 use sd_file_ext::extensions::VideoExtension;
 
-use image::{self, imageops, DynamicImage, GenericImageView};
+#[cfg(feature = "ffmpeg")]
+use super::{
+	sprite::{generate_video_sprite, get_sprite_path},
+	waveform::generate_waveform,
+};
+
+#[cfg(feature = "pdf")]
+use sd_file_ext::extensions::DocumentExtension;
+
+#[cfg(feature = "model")]
+use sd_file_ext::extensions::MeshExtension;
+
+#[cfg(feature = "book")]
+use sd_file_ext::extensions::BookExtension;
+
+use image::{
+	self, codecs::avif::AvifEncoder, imageops, ColorType, DynamicImage, GenericImageView,
+	ImageEncoder,
+};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use specta::Type;
 use thiserror::Error;
 use tokio::{fs, io, task::block_in_place};
 use tracing::{error, info, trace, warn};
 use webp::Encoder;
 
+mod cache;
 mod directory;
+pub mod integrity_job;
+mod priority;
+mod raw;
 mod shallow;
 mod shard;
 pub mod thumbnailer_job;
+mod vector;
 
+pub use cache::*;
 pub use directory::*;
+pub use priority::*;
 pub use shallow::*;
 pub use shard::*;
 
-const THUMBNAIL_SIZE_FACTOR: f32 = 0.2;
-const THUMBNAIL_QUALITY: f32 = 30.0;
+/// Quality used when the library didn't choose one, in the range `0..=100`
+pub const DEFAULT_THUMBNAIL_QUALITY: u8 = 30;
 pub const THUMBNAIL_CACHE_DIR_NAME: &str = "thumbnails";
 
+/// AVIF encoding is slow, so we use the fastest speed that still compresses well (range `1..=10`)
+const AVIF_ENCODER_SPEED: u8 = 8;
+
+/// The size of the generated thumbnails, configured per node as the thumbnail cache is shared
+/// between all libraries. Changing it only affects new thumbnails, existing ones must be
+/// regenerated with the thumbnailer job.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ThumbnailSize {
+	Small,
+	#[default]
+	Medium,
+	Large,
+}
+
+impl ThumbnailSize {
+	/// The maximum size in pixels of the thumbnail's largest side
+	pub const fn max_dimension(&self) -> u32 {
+		match self {
+			Self::Small => 256,
+			Self::Medium => 512,
+			Self::Large => 1024,
+		}
+	}
+
+	/// Dimensions that fit in `max_dimension` while keeping the aspect ratio, never upscaling
+	fn scale(&self, width: u32, height: u32) -> (u32, u32) {
+		let max = self.max_dimension();
+		if width <= max && height <= max {
+			return (width, height);
+		}
+
+		let factor = max as f32 / width.max(height) as f32;
+		(
+			((width as f32 * factor).round() as u32).max(1),
+			((height as f32 * factor).round() as u32).max(1),
+		)
+	}
+}
+
+/// The image format of the generated thumbnails, chosen per library. AVIF thumbnails are smaller
+/// than WebP ones at the same quality, but are much slower to encode.
+///
+/// Thumbnails are shared by all libraries, so a thumbnail is only generated in another format when
+/// it's regenerated. Thumbnails of any format are served, so changing it doesn't hide existing ones.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ThumbnailFormat {
+	#[default]
+	Webp,
+	Avif,
+}
+
+impl ThumbnailFormat {
+	pub const ALL: [Self; 2] = [Self::Webp, Self::Avif];
+
+	pub const fn extension(&self) -> &'static str {
+		match self {
+			Self::Webp => "webp",
+			Self::Avif => "avif",
+		}
+	}
+
+	pub const fn content_type(&self) -> &'static str {
+		match self {
+			Self::Webp => "image/webp",
+			Self::Avif => "image/avif",
+		}
+	}
+
+	pub fn from_extension(extension: &str) -> Option<Self> {
+		Self::ALL
+			.into_iter()
+			.find(|format| format.extension() == extension)
+	}
+}
+
 /// This does not check if a thumbnail exists, it just returns the path that it would exist at
-pub fn get_thumbnail_path(library: &Library, cas_id: &str) -> PathBuf {
-	library
-		.config()
-		.data_directory()
-		.join(THUMBNAIL_CACHE_DIR_NAME)
+pub fn get_thumbnail_path(
+	thumbnail_dir: impl AsRef<Path>,
+	cas_id: &str,
+	format: ThumbnailFormat,
+) -> PathBuf {
+	thumbnail_dir
+		.as_ref()
 		.join(get_shard_hex(cas_id))
 		.join(cas_id)
-		.with_extension("webp")
+		.with_extension(format.extension())
+}
+
+/// Returns the path of the thumbnail of this cas_id in whichever format it was generated, if any
+pub async fn find_thumbnail(
+	thumbnail_dir: impl AsRef<Path>,
+	cas_id: &str,
+) -> Result<Option<(PathBuf, ThumbnailFormat)>, FileIOError> {
+	for format in ThumbnailFormat::ALL {
+		let path = get_thumbnail_path(&thumbnail_dir, cas_id, format);
+		match fs::metadata(&path).await {
+			Ok(_) => return Ok(Some((path, format))),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((path, e))),
+		}
+	}
+
+	Ok(None)
 }
 
 // this is used to pass the relevant data to the frontend so it can request the thumbnail
@@ -66,6 +189,36 @@ static FILTERED_VIDEO_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
 		.collect()
 });
 
+#[cfg(feature = "pdf")]
+static FILTERED_DOCUMENT_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
+	sd_file_ext::extensions::ALL_DOCUMENT_EXTENSIONS
+		.iter()
+		.map(Clone::clone)
+		.filter(can_generate_thumbnail_for_document)
+		.map(Extension::Document)
+		.collect()
+});
+
+#[cfg(feature = "model")]
+static FILTERED_MODEL_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
+	sd_file_ext::extensions::ALL_MESH_EXTENSIONS
+		.iter()
+		.map(Clone::clone)
+		.filter(can_generate_thumbnail_for_model)
+		.map(Extension::Mesh)
+		.collect()
+});
+
+#[cfg(feature = "book")]
+static FILTERED_BOOK_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
+	sd_file_ext::extensions::ALL_BOOK_EXTENSIONS
+		.iter()
+		.map(Clone::clone)
+		.filter(can_generate_thumbnail_for_book)
+		.map(Extension::Book)
+		.collect()
+});
+
 static FILTERED_IMAGE_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
 	sd_file_ext::extensions::ALL_IMAGE_EXTENSIONS
 		.iter()
@@ -95,6 +248,77 @@ enum ThumbnailerJobStepKind {
 	Image,
 	#[cfg(feature = "ffmpeg")]
 	Video,
+	/// Audio files get a waveform instead of a thumbnail
+	#[cfg(feature = "ffmpeg")]
+	Audio,
+	/// Videos also get a sprite sheet of frames across their timeline, for hover scrubbing
+	#[cfg(feature = "ffmpeg")]
+	VideoSprite,
+	#[cfg(feature = "pdf")]
+	Document,
+	#[cfg(feature = "model")]
+	Model,
+	/// Ebooks get their cover as thumbnail, and comic archives their first page
+	#[cfg(feature = "book")]
+	Book,
+	/// Text and code files get a snippet of their content instead of a thumbnail
+	Text,
+}
+
+impl ThumbnailerJobStepKind {
+	/// If the step generates an image thumbnail, instead of another kind of preview
+	fn is_thumbnail(&self) -> bool {
+		#[cfg(feature = "ffmpeg")]
+		if matches!(self, Self::Audio | Self::VideoSprite) {
+			return false;
+		}
+
+		!matches!(self, Self::Text)
+	}
+
+	/// The kind of preview generated for files with this extension in the location, the same ones
+	/// the thumbnailer job would pick. Returns `None` if no preview is generated for it.
+	fn from_extension(extension: &str, location: &location::Data) -> Option<Self> {
+		let contains = |extensions: &[Extension]| {
+			extensions
+				.iter()
+				.any(|ext| ext.to_string().eq_ignore_ascii_case(extension))
+		};
+
+		if contains(&FILTERED_IMAGE_EXTENSIONS) {
+			return Some(Self::Image);
+		}
+
+		#[cfg(feature = "ffmpeg")]
+		{
+			if video_thumbnails_enabled(location) && contains(&FILTERED_VIDEO_EXTENSIONS) {
+				return Some(Self::Video);
+			}
+
+			if contains(&super::waveform::FILTERED_AUDIO_EXTENSIONS) {
+				return Some(Self::Audio);
+			}
+		}
+		#[cfg(not(feature = "ffmpeg"))]
+		let _ = location; // To avoid unused variable warning
+
+		#[cfg(feature = "pdf")]
+		if contains(&FILTERED_DOCUMENT_EXTENSIONS) {
+			return Some(Self::Document);
+		}
+
+		#[cfg(feature = "model")]
+		if contains(&FILTERED_MODEL_EXTENSIONS) {
+			return Some(Self::Model);
+		}
+
+		#[cfg(feature = "book")]
+		if contains(&FILTERED_BOOK_EXTENSIONS) {
+			return Some(Self::Book);
+		}
+
+		contains(&super::text::FILTERED_TEXT_EXTENSIONS).then_some(Self::Text)
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -103,71 +327,282 @@ pub struct ThumbnailerJobStep {
 	kind: ThumbnailerJobStepKind,
 }
 
+#[cfg(feature = "ffmpeg")]
+impl ThumbnailerJobStep {
+	/// Steps generating the sprite sheets of the videos among these steps
+	fn sprite_steps(steps: &[Self]) -> Vec<Self> {
+		steps
+			.iter()
+			.filter(|step| matches!(step.kind, ThumbnailerJobStepKind::Video))
+			.map(|step| Self {
+				file_path: step.file_path.clone(),
+				kind: ThumbnailerJobStepKind::VideoSprite,
+			})
+			.collect()
+	}
+}
+
+/// Options shared by all steps of a thumbnail generation run
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ThumbnailerOptions {
+	pub size: ThumbnailSize,
+	#[serde(default)]
+	pub format: ThumbnailFormat,
+	/// Encoding quality in the range `0..=100`
+	#[serde(default = "default_thumbnail_quality")]
+	pub quality: u8,
+	/// Overwrite existing thumbnails instead of skipping them, used after changing the size or
+	/// the format
+	pub regenerate: bool,
+}
+
+impl Default for ThumbnailerOptions {
+	fn default() -> Self {
+		Self {
+			size: ThumbnailSize::default(),
+			format: ThumbnailFormat::default(),
+			quality: DEFAULT_THUMBNAIL_QUALITY,
+			regenerate: false,
+		}
+	}
+}
+
+impl ThumbnailerOptions {
+	/// The size is configured on the node, while the format and quality are set per library
+	pub async fn for_library(library: &Library, regenerate: bool) -> Self {
+		Self {
+			size: library.config().get().await.thumbnail_size,
+			format: library.config.settings.thumbnail_format,
+			quality: library.config.settings.thumbnail_quality,
+			regenerate,
+		}
+	}
+}
+
+const fn default_thumbnail_quality() -> u8 {
+	DEFAULT_THUMBNAIL_QUALITY
+}
+
+const RAW_EXTENSIONS: [&str; 5] = ["cr2", "cr3", "nef", "arw", "dng"];
+
 // TOOD(brxken128): validate avci and avcs
-#[cfg(all(feature = "heif", not(target_os = "linux")))]
+#[cfg(feature = "heif")]
 const HEIF_EXTENSIONS: [&str; 7] = ["heif", "heifs", "heic", "heics", "avif", "avci", "avcs"];
 
 pub async fn generate_image_thumbnail<P: AsRef<Path>>(
 	file_path: P,
 	output_path: P,
+	options: ThumbnailerOptions,
 ) -> Result<(), Box<dyn Error>> {
-	// Webp creation has blocking code
-	let webp = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
-		#[cfg(all(feature = "heif", not(target_os = "linux")))]
-		let img = {
-			let ext = file_path
-				.as_ref()
-				.extension()
-				.unwrap_or_default()
-				.to_ascii_lowercase();
-			if HEIF_EXTENSIONS
-				.iter()
-				.any(|e| ext == std::ffi::OsStr::new(e))
-			{
-				sd_heif::heif_to_dynamic_image(file_path.as_ref())?
-			} else {
-				image::open(file_path)?
-			}
+	// Thumbnail encoding has blocking code
+	let thumbnail = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
+		let img = open_image(file_path.as_ref(), options.size)?;
+
+		let (w, h) = img.dimensions();
+		let (thumb_w, thumb_h) = options.size.scale(w, h);
+		// Optionally, resize the existing photo and convert back into DynamicImage
+		let img = if (thumb_w, thumb_h) != (w, h) {
+			DynamicImage::ImageRgba8(imageops::resize(
+				&img,
+				thumb_w,
+				thumb_h,
+				imageops::FilterType::Triangle,
+			))
+		} else {
+			img
+		};
+
+		encode_thumbnail(&img, options)
+	})?;
+
+	fs::write(output_path, &thumbnail).await.map_err(Into::into)
+}
+
+fn open_image(file_path: &Path, size: ThumbnailSize) -> Result<DynamicImage, Box<dyn Error>> {
+	let ext = file_path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.unwrap_or_default()
+		.to_ascii_lowercase();
+
+	if vector::is_svg(&ext) {
+		// Rendered straight at the thumbnail size, so it won't be resized afterwards
+		return vector::svg_to_dynamic_image(file_path, size.max_dimension());
+	}
+
+	if vector::is_postscript(&ext) {
+		return vector::postscript_to_dynamic_image(file_path);
+	}
+
+	decode_image(file_path)
+}
+
+/// Decodes a raster image at its full size, camera RAW files from their largest embedded preview
+pub(crate) fn decode_image(file_path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+	let ext = file_path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.unwrap_or_default()
+		.to_ascii_lowercase();
+
+	if RAW_EXTENSIONS.contains(&ext.as_str()) {
+		return raw::raw_to_dynamic_image(file_path);
+	}
+
+	#[cfg(feature = "heif")]
+	if HEIF_EXTENSIONS.contains(&ext.as_str()) {
+		return Ok(sd_heif::heif_to_dynamic_image(file_path)?);
+	}
+
+	Ok(image::open(file_path)?)
+}
+
+#[cfg(feature = "pdf")]
+pub async fn generate_document_thumbnail<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+	options: ThumbnailerOptions,
+) -> Result<(), Box<dyn Error>> {
+	// Rendering the document and the thumbnail encoding have blocking code
+	let thumbnail = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
+		let file_path = file_path.as_ref();
+
+		// The first page is rendered already at the thumbnail size, so no need to resize it
+		let target_size = options.size.max_dimension() as u16;
+		let img = if file_path
+			.extension()
+			.and_then(|ext| ext.to_str())
+			.map(sd_pdf::is_office_extension)
+			.unwrap_or(false)
+		{
+			sd_pdf::office_to_dynamic_image(file_path, target_size)?
+		} else {
+			sd_pdf::pdf_to_dynamic_image(file_path, target_size)?
 		};
 
-		#[cfg(not(all(feature = "heif", not(target_os = "linux"))))]
-		let img = image::open(file_path)?;
+		encode_thumbnail(&img, options)
+	})?;
+
+	fs::write(output_path, &thumbnail).await.map_err(Into::into)
+}
+
+#[cfg(feature = "model")]
+pub async fn generate_model_thumbnail<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+	options: ThumbnailerOptions,
+) -> Result<(), Box<dyn Error>> {
+	// Rendering the model and the thumbnail encoding have blocking code
+	let thumbnail = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
+		// The model is rendered already at the thumbnail size, so no need to resize it
+		let img =
+			sd_model::model_to_dynamic_image(file_path.as_ref(), options.size.max_dimension())?;
+
+		encode_thumbnail(&img, options)
+	})?;
+
+	fs::write(output_path, &thumbnail).await.map_err(Into::into)
+}
+
+#[cfg(feature = "book")]
+pub async fn generate_book_thumbnail<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+	options: ThumbnailerOptions,
+) -> Result<(), Box<dyn Error>> {
+	// Extracting the cover and the thumbnail encoding have blocking code
+	let thumbnail = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
+		let img = sd_book::book_to_dynamic_image(file_path.as_ref())?;
 
 		let (w, h) = img.dimensions();
-		// Optionally, resize the existing photo and convert back into DynamicImage
-		let img = DynamicImage::ImageRgba8(imageops::resize(
-			&img,
-			// FIXME : Think of a better heuristic to get the thumbnail size
-			(w as f32 * THUMBNAIL_SIZE_FACTOR) as u32,
-			(h as f32 * THUMBNAIL_SIZE_FACTOR) as u32,
-			imageops::FilterType::Triangle,
-		));
-		// Create the WebP encoder for the above image
-		let encoder = Encoder::from_image(&img)?;
-
-		// Encode the image at a specified quality 0-100
-
-		// Type WebPMemory is !Send, which makes the Future in this function !Send,
-		// this make us `deref` to have a `&[u8]` and then `to_owned` to make a Vec<u8>
-		// which implies on a unwanted clone...
-		Ok(encoder.encode(THUMBNAIL_QUALITY).deref().to_owned())
+		let (thumb_w, thumb_h) = options.size.scale(w, h);
+		let img = if (thumb_w, thumb_h) != (w, h) {
+			DynamicImage::ImageRgba8(imageops::resize(
+				&img,
+				thumb_w,
+				thumb_h,
+				imageops::FilterType::Triangle,
+			))
+		} else {
+			img
+		};
+
+		encode_thumbnail(&img, options)
 	})?;
 
-	fs::write(output_path, &webp).await.map_err(Into::into)
+	fs::write(output_path, &thumbnail).await.map_err(Into::into)
+}
+
+fn encode_thumbnail(
+	img: &DynamicImage,
+	options: ThumbnailerOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+	match options.format {
+		ThumbnailFormat::Webp => encode_webp(img, options.quality),
+		ThumbnailFormat::Avif => encode_avif(img, options.quality),
+	}
+}
+
+pub(crate) fn encode_webp(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+	// Create the WebP encoder for the above image
+	let encoder = Encoder::from_image(img)?;
+
+	// Encode the image at a specified quality 0-100
+
+	// Type WebPMemory is !Send, which makes the Future in this function !Send,
+	// this make us `deref` to have a `&[u8]` and then `to_owned` to make a Vec<u8>
+	// which implies on a unwanted clone...
+	Ok(encoder.encode(quality.min(100) as f32).deref().to_owned())
+}
+
+fn encode_avif(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+	// The encoder only takes 8 bit RGB(A), keeping the alpha channel of transparent images
+	let img = img.to_rgba8();
+
+	let mut avif = vec![];
+	AvifEncoder::new_with_speed_quality(&mut avif, AVIF_ENCODER_SPEED, quality.min(100))
+		.write_image(img.as_raw(), img.width(), img.height(), ColorType::Rgba8)?;
+
+	Ok(avif)
 }
 
 #[cfg(feature = "ffmpeg")]
 pub async fn generate_video_thumbnail<P: AsRef<Path>>(
 	file_path: P,
 	output_path: P,
+	options: ThumbnailerOptions,
 ) -> Result<(), Box<dyn Error>> {
-	use sd_ffmpeg::to_thumbnail;
+	use sd_ffmpeg::{to_thumbnail, to_webp_bytes};
+
+	let size = options.size.max_dimension();
+	let quality = options.quality.min(100) as f32;
+
+	match options.format {
+		ThumbnailFormat::Webp => to_thumbnail(file_path, output_path, size, quality).await?,
+		ThumbnailFormat::Avif => {
+			// The ffmpeg thumbnailer only outputs WebP, so the frame is re-encoded losslessly
+			// first, to not compress it twice
+			let webp = to_webp_bytes(file_path, size, 100.0).await?;
 
-	to_thumbnail(file_path, output_path, 256, THUMBNAIL_QUALITY).await?;
+			let avif = block_in_place(|| -> Result<Vec<u8>, Box<dyn Error>> {
+				let img = image::load_from_memory_with_format(&webp, image::ImageFormat::WebP)?;
+				encode_avif(&img, options.quality)
+			})?;
+
+			fs::write(output_path, &avif).await?;
+		}
+	}
 
 	Ok(())
 }
 
+/// Video thumbnails are CPU heavy, so locations can opt out of them. Locations that never
+/// changed this setting will have video thumbnails enabled.
+#[cfg(feature = "ffmpeg")]
+pub fn video_thumbnails_enabled(location: &location::Data) -> bool {
+	location.generate_video_thumbnails.unwrap_or(true)
+}
+
 #[cfg(feature = "ffmpeg")]
 pub const fn can_generate_thumbnail_for_video(video_extension: &VideoExtension) -> bool {
 	use VideoExtension::*;
@@ -175,25 +610,110 @@ pub const fn can_generate_thumbnail_for_video(video_extension: &VideoExtension)
 	!matches!(video_extension, Mpg | Swf | M2v | Hevc | M2ts | Mts | Ts)
 }
 
+#[cfg(feature = "pdf")]
+pub const fn can_generate_thumbnail_for_document(document_extension: &DocumentExtension) -> bool {
+	use DocumentExtension::*;
+
+	matches!(
+		document_extension,
+		Pdf | Doc | Docx | Odt | Ppt | Pptx | Odp | Xls | Xlsx | Ods
+	)
+}
+
+#[cfg(feature = "model")]
+pub const fn can_generate_thumbnail_for_model(mesh_extension: &MeshExtension) -> bool {
+	use MeshExtension::*;
+
+	matches!(mesh_extension, Gltf | Glb | Obj | Stl)
+}
+
+#[cfg(feature = "book")]
+pub const fn can_generate_thumbnail_for_book(book_extension: &BookExtension) -> bool {
+	use BookExtension::*;
+
+	matches!(book_extension, Epub | Mobi | Azw | Azw3 | Cbz | Cbr)
+}
+
 pub const fn can_generate_thumbnail_for_image(image_extension: &ImageExtension) -> bool {
 	use ImageExtension::*;
 
-	#[cfg(all(feature = "heif", not(target_os = "linux")))]
+	#[cfg(feature = "heif")]
 	let res = matches!(
 		image_extension,
 		Jpg | Jpeg | Png | Webp | Gif | Heic | Heics | Heif | Heifs | Avif
 	);
 
-	#[cfg(not(all(feature = "heif", not(target_os = "linux"))))]
+	#[cfg(not(feature = "heif"))]
 	let res = matches!(image_extension, Jpg | Jpeg | Png | Webp | Gif);
 
-	res
+	// Camera RAW files have their embedded previews extracted, and vector files are rasterized
+	res || matches!(
+		image_extension,
+		Cr2 | Cr3 | Nef | Arw | Dng | Svg | Eps | Ai
+	)
+}
+
+async fn generate_thumbnail(
+	kind: ThumbnailerJobStepKind,
+	path: &PathBuf,
+	output_path: &PathBuf,
+	options: ThumbnailerOptions,
+) {
+	match kind {
+		ThumbnailerJobStepKind::Image => {
+			if let Err(e) = generate_image_thumbnail(path, output_path, options).await {
+				error!("Error generating thumb for image {:#?}", e);
+			}
+		}
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::Video => {
+			if let Err(e) = generate_video_thumbnail(path, output_path, options).await {
+				error!("Error generating thumb for video: {:?} {:#?}", path, e);
+			}
+		}
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::Audio => {
+			if let Err(e) = generate_waveform(path, output_path).await {
+				error!("Error generating waveform for audio: {:?} {:#?}", path, e);
+			}
+		}
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::VideoSprite => {
+			if let Err(e) = generate_video_sprite(path, output_path, options).await {
+				error!("Error generating sprite for video: {:?} {:#?}", path, e);
+			}
+		}
+		#[cfg(feature = "pdf")]
+		ThumbnailerJobStepKind::Document => {
+			if let Err(e) = generate_document_thumbnail(path, output_path, options).await {
+				error!("Error generating thumb for document: {:?} {:#?}", path, e);
+			}
+		}
+		ThumbnailerJobStepKind::Text => {
+			if let Err(e) = generate_text_preview(path, output_path).await {
+				error!("Error generating preview for text: {:?} {:#?}", path, e);
+			}
+		}
+		#[cfg(feature = "model")]
+		ThumbnailerJobStepKind::Model => {
+			if let Err(e) = generate_model_thumbnail(path, output_path, options).await {
+				error!("Error generating thumb for model: {:?} {:#?}", path, e);
+			}
+		}
+		#[cfg(feature = "book")]
+		ThumbnailerJobStepKind::Book => {
+			if let Err(e) = generate_book_thumbnail(path, output_path, options).await {
+				error!("Error generating thumb for book: {:?} {:#?}", path, e);
+			}
+		}
+	}
 }
 
 pub async fn inner_process_step(
 	step: &ThumbnailerJobStep,
 	location_path: impl AsRef<Path>,
 	thumbnail_dir: impl AsRef<Path>,
+	options: ThumbnailerOptions,
 	location: &location::Data,
 	library: &Library,
 ) -> Result<bool, JobError> {
@@ -214,47 +734,76 @@ pub async fn inner_process_step(
 		return Ok(false);
 	};
 
-	let thumb_dir = thumbnail_dir.join(get_shard_hex(cas_id));
+	// Define the path to write the encoded thumbnail, or the preview for audio, video sprites and
+	// text files
+	let output_path = match kind {
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::Audio => get_waveform_path(library.config().data_directory(), cas_id),
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::VideoSprite => get_sprite_path(library.config().data_directory(), cas_id),
+		ThumbnailerJobStepKind::Text => {
+			get_text_preview_path(library.config().data_directory(), cas_id)
+		}
+		_ => get_thumbnail_path(thumbnail_dir, cas_id, options.format),
+	};
 
 	// Create the directory if it doesn't exist
-	if let Err(e) = fs::create_dir_all(&thumb_dir).await {
-		error!("Error creating thumbnail directory {:#?}", e);
+	if let Some(output_dir) = output_path.parent() {
+		if let Err(e) = fs::create_dir_all(output_dir).await {
+			error!("Error creating thumbnail directory {:#?}", e);
+		}
 	}
 
-	// Define and write the WebP-encoded file to a given path
-	let output_path = thumb_dir.join(format!("{cas_id}.webp"));
+	// Thumbnails in any format count as existing, changing the format only applies to new ones
+	let existing_path = if kind.is_thumbnail() {
+		find_thumbnail(thumbnail_dir, cas_id)
+			.await
+			.map_err(ThumbnailerError::from)?
+			.map(|(path, _)| path)
+	} else {
+		match fs::metadata(&output_path).await {
+			Ok(_) => Some(output_path.clone()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+			Err(e) => {
+				return Err(ThumbnailerError::from(FileIOError::from((output_path, e))).into())
+			}
+		}
+	};
 
-	match fs::metadata(&output_path).await {
-		Ok(_) => {
+	match existing_path {
+		Some(existing_path) if !options.regenerate => {
 			info!(
 				"Thumb already exists, skipping generation for {}",
-				output_path.display()
+				existing_path.display()
 			);
 			return Ok(false);
 		}
-		Err(e) if e.kind() == io::ErrorKind::NotFound => {
-			info!("Writing {:?} to {:?}", path, output_path);
-
-			match kind {
-				ThumbnailerJobStepKind::Image => {
-					if let Err(e) = generate_image_thumbnail(&path, &output_path).await {
-						error!("Error generating thumb for image {:#?}", e);
-					}
-				}
-				#[cfg(feature = "ffmpeg")]
-				ThumbnailerJobStepKind::Video => {
-					if let Err(e) = generate_video_thumbnail(&path, &output_path).await {
-						error!("Error generating thumb for video: {:?} {:#?}", &path, e);
-					}
+		Some(existing_path) => {
+			info!("Regenerating {:?} to {:?}", path, output_path);
+			generate_thumbnail(*kind, &path, &output_path, options).await;
+
+			// The thumbnail in the previous format must go, or it could be served instead of
+			// the new one. It's kept if the new one failed, as it's better than nothing.
+			if existing_path != output_path && fs::metadata(&output_path).await.is_ok() {
+				if let Err(e) = fs::remove_file(&existing_path).await {
+					error!("Error removing old thumbnail {:?}: {:#?}", existing_path, e);
 				}
 			}
-
-			info!("Emitting new thumbnail event");
-			library.emit(CoreEvent::NewThumbnail {
-				thumb_key: get_thumb_key(cas_id),
-			});
 		}
-		Err(e) => return Err(ThumbnailerError::from(FileIOError::from((output_path, e))).into()),
+		None => {
+			info!("Writing {:?} to {:?}", path, output_path);
+			generate_thumbnail(*kind, &path, &output_path, options).await;
+		}
+	}
+
+	// Other previews aren't served as thumbnails, so they're kept out of the thumbnail cache
+	if kind.is_thumbnail() {
+		library.thumbnail_cache().created(cas_id).await;
+
+		info!("Emitting new thumbnail event");
+		library.emit(CoreEvent::NewThumbnail {
+			thumb_key: get_thumb_key(cas_id),
+		});
 	}
 
 	Ok(true)
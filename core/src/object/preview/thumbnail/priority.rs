@@ -0,0 +1,198 @@
+use crate::{
+	library::Library,
+	location::file_path_helper::file_path_for_thumbnailer,
+	node::NodeConfigManager,
+	prisma::{file_path, location},
+	util::db::maybe_missing,
+};
+
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+use super::{
+	inner_process_step, ThumbnailerJobStep, ThumbnailerJobStepKind, ThumbnailerOptions,
+	THUMBNAIL_CACHE_DIR_NAME,
+};
+
+/// Only the most recent requests are kept, older ones are for items that were probably scrolled
+/// out of view already and the thumbnailer job will get to them anyway
+const MAX_PENDING_BATCHES: usize = 8;
+
+struct PriorityRequest {
+	library: Library,
+	cas_ids: Vec<String>,
+}
+
+/// Files of a single location waiting for their thumbnails, in the order they were requested
+struct PriorityBatch {
+	library: Library,
+	location: location::Data,
+	location_path: PathBuf,
+	/// Stored in reverse, so the next step is popped from the end
+	steps: Vec<ThumbnailerJobStep>,
+}
+
+/// Actor that generates thumbnails requested by clients ahead of the thumbnailer jobs, so the
+/// items visible on screen get their thumbnails first. The most recent request is always handled
+/// first, as it has the items currently in view. Thumbnails are generated one at a time for all
+/// libraries, so a single actor exists per node.
+#[derive(Clone)]
+pub struct ThumbnailPriorityActor {
+	tx: mpsc::Sender<PriorityRequest>,
+}
+
+impl ThumbnailPriorityActor {
+	pub fn spawn(config: Arc<NodeConfigManager>) -> Self {
+		let (tx, mut rx) = mpsc::channel(32);
+
+		tokio::spawn(async move {
+			let thumbnail_dir = config.data_directory().join(THUMBNAIL_CACHE_DIR_NAME);
+
+			let mut batches: Vec<PriorityBatch> = vec![];
+
+			loop {
+				// Waits for requests if there is nothing to do, otherwise just checks for new ones
+				// between each thumbnail so they are handled as soon as possible
+				let request = if batches.is_empty() {
+					match rx.recv().await {
+						Some(request) => Some(request),
+						None => break,
+					}
+				} else {
+					rx.try_recv().ok()
+				};
+
+				if let Some(PriorityRequest { library, cas_ids }) = request {
+					match resolve_batches(&library, &cas_ids).await {
+						Ok(new_batches) => batches.extend(new_batches),
+						Err(e) => error!("Failed to fetch files for priority thumbnails: {e:#?}"),
+					}
+
+					if batches.len() > MAX_PENDING_BATCHES {
+						batches.drain(..batches.len() - MAX_PENDING_BATCHES);
+					}
+
+					continue;
+				}
+
+				let Some(batch) = batches.last_mut() else {
+					continue;
+				};
+
+				let Some(step) = batch.steps.pop() else {
+					batches.pop();
+					continue;
+				};
+
+				let options = ThumbnailerOptions::for_library(&batch.library, false).await;
+
+				// The new thumbnail is announced to the clients by `inner_process_step` itself
+				if let Err(e) = inner_process_step(
+					&step,
+					&batch.location_path,
+					&thumbnail_dir,
+					options,
+					&batch.location,
+					&batch.library,
+				)
+				.await
+				{
+					error!("Failed to generate priority thumbnail: {e:#?}");
+				}
+			}
+		});
+
+		Self { tx }
+	}
+
+	/// Requests thumbnails for the objects with these cas_ids, in order of importance. Files that
+	/// already have a thumbnail are skipped.
+	pub async fn request(&self, library: Library, cas_ids: Vec<String>) {
+		if cas_ids.is_empty() {
+			return;
+		}
+
+		self.tx
+			.send(PriorityRequest { library, cas_ids })
+			.await
+			.ok();
+	}
+}
+
+/// Finds a file for each cas_id to generate its thumbnail from, grouped by location. Only the
+/// files on this node can be read, the thumbnails of the others are fetched from their nodes.
+async fn resolve_batches(
+	library: &Library,
+	cas_ids: &[String],
+) -> Result<Vec<PriorityBatch>, prisma_client_rust::QueryError> {
+	let locations = library
+		.db
+		.location()
+		.find_many(vec![
+			location::node_id::equals(Some(library.node_local_id)),
+			location::file_paths::some(vec![file_path::cas_id::in_vec(cas_ids.to_vec())]),
+		])
+		.exec()
+		.await?;
+
+	let mut batches = Vec::with_capacity(locations.len());
+
+	for location in locations {
+		// Locations on disconnected devices have no path
+		let Ok(location_path) = maybe_missing(&location.path, "location.path").map(PathBuf::from)
+		else {
+			continue;
+		};
+
+		let mut steps = library
+			.db
+			.file_path()
+			.find_many(vec![
+				file_path::location_id::equals(Some(location.id)),
+				file_path::cas_id::in_vec(cas_ids.to_vec()),
+				file_path::is_dir::equals(Some(false)),
+			])
+			.select(file_path_for_thumbnailer::select())
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|file_path| {
+				let kind = ThumbnailerJobStepKind::from_extension(
+					file_path.extension.as_deref()?,
+					&location,
+				)?;
+
+				Some(ThumbnailerJobStep { file_path, kind })
+			})
+			.collect::<Vec<_>>();
+
+		// Many file paths can share a cas_id, one thumbnail is enough for all of them
+		steps.sort_by_key(|step| request_position(cas_ids, step));
+		steps.dedup_by_key(|step| step.file_path.cas_id.clone());
+		steps.reverse();
+
+		debug!(
+			"Prioritizing {} thumbnails in location {}",
+			steps.len(),
+			location.id
+		);
+
+		batches.push(PriorityBatch {
+			library: library.clone(),
+			location,
+			location_path,
+			steps,
+		});
+	}
+
+	Ok(batches)
+}
+
+fn request_position(cas_ids: &[String], step: &ThumbnailerJobStep) -> usize {
+	cas_ids
+		.iter()
+		.position(|cas_id| step.file_path.cas_id.as_ref() == Some(cas_id))
+		.unwrap_or(usize::MAX)
+}
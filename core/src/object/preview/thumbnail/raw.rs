@@ -0,0 +1,410 @@
+//! Camera RAW files are huge and decoding them is slow, but nearly every camera embeds a full
+//! (or at least screen sized) JPEG preview inside the RAW container. So instead of demosaicing the
+//! sensor data, we look for the biggest embedded JPEG and use it as the thumbnail source.
+//!
+//! TIFF based formats (CR2, NEF, ARW, DNG, ...) have their previews referenced from IFD entries,
+//! while ISO BMFF based formats (CR3) are scanned for JPEG streams directly.
+
+use std::{error::Error, fmt, path::Path};
+
+use image::DynamicImage;
+
+/// The maximum file size that a RAW file can be in order to have a thumbnail generated.
+///
+/// This value is in MiB.
+const RAW_MAXIMUM_FILE_SIZE: u64 = 1048576 * 200;
+
+/// Previews smaller than this are usually just the tiny EXIF thumbnail, which looks terrible
+/// when scaled up, so we only use them as a last resort.
+const MINIMUM_PREFERRED_PREVIEW_SIZE: usize = 32 * 1024;
+
+/// Limits how many IFDs we'll visit, protecting us from malformed files with cyclic offsets.
+const MAX_IFDS: usize = 32;
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const TAG_JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+
+const COMPRESSION_OLD_JPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+
+#[derive(Debug)]
+pub enum RawPreviewError {
+	TooLarge,
+	PreviewNotFound,
+}
+
+impl fmt::Display for RawPreviewError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::TooLarge => write!(f, "the raw file provided is too large (over 200MiB)"),
+			Self::PreviewNotFound => write!(f, "no embedded preview found in raw file"),
+		}
+	}
+}
+
+impl Error for RawPreviewError {}
+
+/// Loads the largest embedded preview from a camera RAW file.
+pub fn raw_to_dynamic_image(path: impl AsRef<Path>) -> Result<DynamicImage, Box<dyn Error>> {
+	let path = path.as_ref();
+
+	if std::fs::metadata(path)?.len() > RAW_MAXIMUM_FILE_SIZE {
+		return Err(RawPreviewError::TooLarge.into());
+	}
+
+	let data = std::fs::read(path)?;
+
+	let mut previews = tiff_embedded_previews(&data);
+	if previews.is_empty() {
+		previews = scan_jpeg_streams(&data);
+	}
+
+	// Biggest previews first, as they have more chances to be the full sized one
+	previews.sort_by(|a, b| b.len().cmp(&a.len()));
+
+	let mut last_error = None;
+	for preview in previews {
+		match image::load_from_memory_with_format(&data[preview], image::ImageFormat::Jpeg) {
+			Ok(img) => return Ok(img),
+			Err(e) => last_error = Some(e),
+		}
+	}
+
+	Err(last_error.map_or_else(|| RawPreviewError::PreviewNotFound.into(), Into::into))
+}
+
+#[derive(Clone, Copy)]
+enum ByteOrder {
+	Little,
+	Big,
+}
+
+struct TiffReader<'data> {
+	data: &'data [u8],
+	order: ByteOrder,
+}
+
+impl<'data> TiffReader<'data> {
+	fn new(data: &'data [u8]) -> Option<Self> {
+		let order = match data.get(0..4)? {
+			[0x49, 0x49, 0x2A, 0x00] => ByteOrder::Little,
+			[0x4D, 0x4D, 0x00, 0x2A] => ByteOrder::Big,
+			// Panasonic RW2 and Olympus ORF use custom magic numbers with little endian order
+			[0x49, 0x49, 0x55, 0x00] | [0x49, 0x49, 0x52, 0x4F] => ByteOrder::Little,
+			_ => return None,
+		};
+
+		Some(Self { data, order })
+	}
+
+	fn u16_at(&self, offset: usize) -> Option<u16> {
+		let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+		Some(match self.order {
+			ByteOrder::Little => u16::from_le_bytes(bytes),
+			ByteOrder::Big => u16::from_be_bytes(bytes),
+		})
+	}
+
+	fn u32_at(&self, offset: usize) -> Option<u32> {
+		let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+		Some(match self.order {
+			ByteOrder::Little => u32::from_le_bytes(bytes),
+			ByteOrder::Big => u32::from_be_bytes(bytes),
+		})
+	}
+
+	/// Reads the values of an IFD entry as u32, following the offset when they don't fit inline
+	fn entry_values(&self, entry_offset: usize) -> Option<Vec<u32>> {
+		let field_type = self.u16_at(entry_offset + 2)?;
+		let count = self.u32_at(entry_offset + 4)? as usize;
+
+		let value_size = match field_type {
+			3 => 2,      // SHORT
+			4 | 13 => 4, // LONG | IFD
+			_ => return None,
+		};
+
+		let values_offset = if value_size * count <= 4 {
+			entry_offset + 8
+		} else {
+			self.u32_at(entry_offset + 8)? as usize
+		};
+
+		(0..count.min(64))
+			.map(|i| match value_size {
+				2 => self.u16_at(values_offset + i * 2).map(u32::from),
+				_ => self.u32_at(values_offset + i * 4),
+			})
+			.collect()
+	}
+}
+
+fn tiff_embedded_previews(data: &[u8]) -> Vec<std::ops::Range<usize>> {
+	let Some(reader) = TiffReader::new(data) else {
+		return vec![];
+	};
+
+	let mut previews = vec![];
+	let mut pending_ifds = reader.u32_at(4).into_iter().collect::<Vec<_>>();
+	let mut visited = Vec::with_capacity(MAX_IFDS);
+
+	while let Some(ifd_offset) = pending_ifds.pop() {
+		let ifd_offset = ifd_offset as usize;
+		if ifd_offset == 0 || visited.contains(&ifd_offset) || visited.len() >= MAX_IFDS {
+			continue;
+		}
+		visited.push(ifd_offset);
+
+		let Some(entries_count) = reader.u16_at(ifd_offset) else {
+			continue;
+		};
+
+		let mut compression = None;
+		let mut jpeg_offset = None;
+		let mut jpeg_length = None;
+		let mut strip_offsets = vec![];
+		let mut strip_byte_counts = vec![];
+
+		for i in 0..entries_count as usize {
+			let entry_offset = ifd_offset + 2 + i * 12;
+			let Some(tag) = reader.u16_at(entry_offset) else {
+				break;
+			};
+
+			let first_value = || {
+				reader
+					.entry_values(entry_offset)
+					.and_then(|values| values.first().copied())
+			};
+
+			match tag {
+				TAG_COMPRESSION => compression = first_value(),
+				TAG_JPEG_INTERCHANGE_FORMAT => jpeg_offset = first_value(),
+				TAG_JPEG_INTERCHANGE_FORMAT_LENGTH => jpeg_length = first_value(),
+				TAG_STRIP_OFFSETS => {
+					strip_offsets = reader.entry_values(entry_offset).unwrap_or_default()
+				}
+				TAG_STRIP_BYTE_COUNTS => {
+					strip_byte_counts = reader.entry_values(entry_offset).unwrap_or_default()
+				}
+				TAG_SUB_IFDS | TAG_EXIF_IFD => {
+					pending_ifds.extend(reader.entry_values(entry_offset).unwrap_or_default())
+				}
+				_ => {}
+			}
+		}
+
+		if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+			previews.extend(jpeg_range(data, offset as usize, length as usize));
+		}
+
+		// Single strip JPEG compressed images, used by DNG and NEF for their previews
+		if matches!(compression, Some(COMPRESSION_OLD_JPEG | COMPRESSION_JPEG))
+			&& strip_offsets.len() == 1
+			&& strip_byte_counts.len() == 1
+		{
+			previews.extend(jpeg_range(
+				data,
+				strip_offsets[0] as usize,
+				strip_byte_counts[0] as usize,
+			));
+		}
+
+		// Next IFD in the chain
+		if let Some(next) = reader.u32_at(ifd_offset + 2 + entries_count as usize * 12) {
+			pending_ifds.push(next);
+		}
+	}
+
+	// Lossless JPEG (used for the raw sensor data itself) is not decodable by the image crate,
+	// so we only keep baseline/progressive JPEGs
+	previews.retain(|range| is_decodable_jpeg(&data[range.clone()]));
+
+	prefer_large_previews(previews)
+}
+
+/// Fallback for containers that we don't parse, looks for JPEG streams by walking the JPEG
+/// segments from every start of image marker found in the file.
+fn scan_jpeg_streams(data: &[u8]) -> Vec<std::ops::Range<usize>> {
+	let mut previews = vec![];
+	let mut position = 0;
+
+	while let Some(start) = data[position..]
+		.windows(3)
+		.position(|window| window == [0xFF, 0xD8, 0xFF])
+		.map(|relative| relative + position)
+	{
+		match jpeg_stream_end(data, start) {
+			Some(end) => {
+				let range = start..end;
+				if is_decodable_jpeg(&data[range.clone()]) {
+					previews.push(range);
+				}
+				position = end;
+			}
+			None => position = start + 3,
+		}
+	}
+
+	prefer_large_previews(previews)
+}
+
+fn prefer_large_previews(previews: Vec<std::ops::Range<usize>>) -> Vec<std::ops::Range<usize>> {
+	if previews
+		.iter()
+		.any(|range| range.len() >= MINIMUM_PREFERRED_PREVIEW_SIZE)
+	{
+		previews
+			.into_iter()
+			.filter(|range| range.len() >= MINIMUM_PREFERRED_PREVIEW_SIZE)
+			.collect()
+	} else {
+		previews
+	}
+}
+
+fn jpeg_range(data: &[u8], offset: usize, length: usize) -> Option<std::ops::Range<usize>> {
+	let end = offset.checked_add(length)?;
+	(length > 0 && end <= data.len() && data[offset..].starts_with(&[0xFF, 0xD8]))
+		.then_some(offset..end)
+}
+
+/// Checks for a SOF0 (baseline) or SOF2 (progressive) frame header before the scan starts
+fn is_decodable_jpeg(jpeg: &[u8]) -> bool {
+	let mut position = 2;
+	while let Some(&[0xFF, marker]) = jpeg.get(position..position + 2) {
+		match marker {
+			0xC0 | 0xC1 | 0xC2 => return true,
+			0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xDA => return false,
+			_ => {}
+		}
+
+		let Some(&[high, low]) = jpeg.get(position + 2..position + 4) else {
+			return false;
+		};
+		position += 2 + u16::from_be_bytes([high, low]) as usize;
+	}
+
+	false
+}
+
+/// Walks the JPEG segments from `start`, returning the position after the end of image marker
+fn jpeg_stream_end(data: &[u8], start: usize) -> Option<usize> {
+	let mut position = start + 2;
+
+	// Header segments, they all have a length field
+	loop {
+		let &[0xFF, marker] = data.get(position..position + 2)? else {
+			return None;
+		};
+		let &[high, low] = data.get(position + 2..position + 4)? else {
+			return None;
+		};
+		position += 2 + u16::from_be_bytes([high, low]) as usize;
+
+		if marker == 0xDA {
+			break;
+		}
+	}
+
+	// Entropy coded data, ends at the first marker that isn't a stuffed byte or a restart marker
+	while position + 1 < data.len() {
+		if data[position] == 0xFF {
+			match data[position + 1] {
+				0x00 | 0xD0..=0xD7 | 0xFF => {}
+				0xD9 => return Some(position + 2),
+				// Progressive JPEGs have many scans
+				0xDA | 0xC4 | 0xDB | 0xDD => {
+					let &[high, low] = data.get(position + 2..position + 4)? else {
+						return None;
+					};
+					position += 2 + u16::from_be_bytes([high, low]) as usize;
+					continue;
+				}
+				_ => return None,
+			}
+		}
+		position += 1;
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A minimal baseline JPEG structure, not decodable but enough to be identified
+	fn fake_jpeg(payload_size: usize) -> Vec<u8> {
+		let mut jpeg = vec![0xFF, 0xD8];
+		// SOF0 with a bogus 8 bytes header
+		jpeg.extend([0xFF, 0xC0, 0x00, 0x08, 0, 0, 0, 0, 0, 0]);
+		// SOS with a bogus 4 bytes header
+		jpeg.extend([0xFF, 0xDA, 0x00, 0x04, 0, 0]);
+		jpeg.extend(std::iter::repeat(0x42).take(payload_size));
+		jpeg.extend([0xFF, 0xD9]);
+		jpeg
+	}
+
+	#[test]
+	fn finds_previews_in_tiff_ifd() {
+		let preview = fake_jpeg(64);
+
+		// little endian header, first IFD right after it
+		let mut tiff = vec![0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00];
+		let preview_offset = 8 + 2 + 2 * 12 + 4;
+
+		tiff.extend(2u16.to_le_bytes());
+		for (tag, value) in [
+			(TAG_JPEG_INTERCHANGE_FORMAT, preview_offset as u32),
+			(TAG_JPEG_INTERCHANGE_FORMAT_LENGTH, preview.len() as u32),
+		] {
+			tiff.extend(tag.to_le_bytes());
+			tiff.extend(4u16.to_le_bytes());
+			tiff.extend(1u32.to_le_bytes());
+			tiff.extend(value.to_le_bytes());
+		}
+		tiff.extend(0u32.to_le_bytes());
+		tiff.extend(&preview);
+
+		assert_eq!(
+			tiff_embedded_previews(&tiff),
+			vec![preview_offset..preview_offset + preview.len()]
+		);
+	}
+
+	#[test]
+	fn scans_jpeg_streams_in_unknown_containers() {
+		let small = fake_jpeg(16);
+		let big = fake_jpeg(MINIMUM_PREFERRED_PREVIEW_SIZE);
+
+		let mut container = b"....ftypcrx ....".to_vec();
+		let small_start = container.len();
+		container.extend(&small);
+		container.extend(b"moov....");
+		let big_start = container.len();
+		container.extend(&big);
+
+		assert_eq!(
+			scan_jpeg_streams(&container),
+			vec![big_start..big_start + big.len()]
+		);
+
+		container.truncate(big_start);
+		assert_eq!(
+			scan_jpeg_streams(&container),
+			vec![small_start..small_start + small.len()]
+		);
+	}
+
+	#[test]
+	fn ignores_non_tiff_data() {
+		assert!(tiff_embedded_previews(b"not a tiff file").is_empty());
+		assert!(scan_jpeg_streams(b"no jpeg here").is_empty());
+	}
+}
@@ -1,5 +1,6 @@
 use super::{
-	ThumbnailerError, ThumbnailerJobStep, ThumbnailerJobStepKind, FILTERED_IMAGE_EXTENSIONS,
+	super::text::FILTERED_TEXT_EXTENSIONS, ThumbnailerError, ThumbnailerJobStep,
+	ThumbnailerJobStepKind, ThumbnailerOptions, FILTERED_IMAGE_EXTENSIONS,
 };
 use crate::{
 	invalidate_query,
@@ -20,7 +21,18 @@ use tokio::fs;
 use tracing::info;
 
 #[cfg(feature = "ffmpeg")]
-use super::FILTERED_VIDEO_EXTENSIONS;
+use super::{
+	super::waveform::FILTERED_AUDIO_EXTENSIONS, video_thumbnails_enabled, FILTERED_VIDEO_EXTENSIONS,
+};
+
+#[cfg(feature = "pdf")]
+use super::FILTERED_DOCUMENT_EXTENSIONS;
+
+#[cfg(feature = "model")]
+use super::FILTERED_MODEL_EXTENSIONS;
+
+#[cfg(feature = "book")]
+use super::FILTERED_BOOK_EXTENSIONS;
 
 pub async fn shallow_thumbnailer(
 	location: &location::Data,
@@ -91,31 +103,145 @@ pub async fn shallow_thumbnailer(
 	#[cfg(feature = "ffmpeg")]
 	let video_files = {
 		// query database for all video files in this location that need thumbnails
-		let video_files = get_files_by_extensions(
+		let video_files = if video_thumbnails_enabled(location) {
+			get_files_by_extensions(
+				&library.db,
+				location_id,
+				&iso_file_path,
+				&FILTERED_VIDEO_EXTENSIONS,
+				ThumbnailerJobStepKind::Video,
+			)
+			.await?
+		} else {
+			vec![]
+		};
+
+		info!("Found {:?} video files", video_files.len());
+
+		video_files
+	};
+
+	#[cfg(feature = "ffmpeg")]
+	let audio_files = {
+		// query database for all audio files in this location that need waveforms
+		let audio_files = get_files_by_extensions(
 			&library.db,
 			location_id,
 			&iso_file_path,
-			&FILTERED_VIDEO_EXTENSIONS,
-			ThumbnailerJobStepKind::Video,
+			&FILTERED_AUDIO_EXTENSIONS,
+			ThumbnailerJobStepKind::Audio,
 		)
 		.await?;
 
-		info!("Found {:?} video files", video_files.len());
+		info!("Found {:?} audio files", audio_files.len());
 
-		video_files
+		audio_files
+	};
+
+	#[cfg(feature = "pdf")]
+	let document_files = {
+		// query database for all document files in this location that need thumbnails
+		let document_files = get_files_by_extensions(
+			&library.db,
+			location_id,
+			&iso_file_path,
+			&FILTERED_DOCUMENT_EXTENSIONS,
+			ThumbnailerJobStepKind::Document,
+		)
+		.await?;
+
+		info!("Found {:?} document files", document_files.len());
+
+		document_files
+	};
+
+	#[cfg(feature = "model")]
+	let model_files = {
+		// query database for all 3D model files in this location that need thumbnails
+		let model_files = get_files_by_extensions(
+			&library.db,
+			location_id,
+			&iso_file_path,
+			&FILTERED_MODEL_EXTENSIONS,
+			ThumbnailerJobStepKind::Model,
+		)
+		.await?;
+
+		info!("Found {:?} model files", model_files.len());
+
+		model_files
+	};
+
+	#[cfg(feature = "book")]
+	let book_files = {
+		// query database for all ebooks and comic archives in this location that need thumbnails
+		let book_files = get_files_by_extensions(
+			&library.db,
+			location_id,
+			&iso_file_path,
+			&FILTERED_BOOK_EXTENSIONS,
+			ThumbnailerJobStepKind::Book,
+		)
+		.await?;
+
+		info!("Found {:?} book files", book_files.len());
+
+		book_files
 	};
 
+	// query database for all text and code files in this location that need previews
+	let text_files = get_files_by_extensions(
+		&library.db,
+		location_id,
+		&iso_file_path,
+		&FILTERED_TEXT_EXTENSIONS,
+		ThumbnailerJobStepKind::Text,
+	)
+	.await?;
+
+	info!("Found {:?} text files", text_files.len());
+
 	let all_files = [
 		image_files,
+		text_files,
 		#[cfg(feature = "ffmpeg")]
 		video_files,
+		#[cfg(feature = "ffmpeg")]
+		audio_files,
+		#[cfg(feature = "pdf")]
+		document_files,
+		#[cfg(feature = "model")]
+		model_files,
+		#[cfg(feature = "book")]
+		book_files,
 	]
 	.into_iter()
-	.flatten();
+	.flatten()
+	.collect::<Vec<_>>();
+
+	// Sprite sheets are only needed when hovering a video, so they come after every preview
+	#[cfg(feature = "ffmpeg")]
+	let all_files = {
+		let sprite_files = ThumbnailerJobStep::sprite_steps(&all_files);
+
+		all_files
+			.into_iter()
+			.chain(sprite_files.into_iter())
+			.collect::<Vec<_>>()
+	};
+
+	let options = ThumbnailerOptions::for_library(library, false).await;
 
 	for file in all_files {
-		thumbnail::inner_process_step(&file, &location_path, &thumbnail_dir, location, library)
-			.await?;
+		thumbnail::inner_process_step(
+			&file,
+			&location_path,
+			&thumbnail_dir,
+			options,
+			location,
+			library,
+		)
+		.await?;
 	}
 
 	invalidate_query!(library, "search.paths");
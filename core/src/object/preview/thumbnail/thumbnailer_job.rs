@@ -1,17 +1,18 @@
 use crate::{
 	invalidate_query,
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunMetadata, JobState,
-		JobStepOutput, StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
 	},
+	job_message,
 	library::Library,
 	location::file_path_helper::{
 		ensure_file_path_exists, ensure_sub_path_is_directory, ensure_sub_path_is_in_location,
 		file_path_for_thumbnailer, IsolatedFilePathData,
 	},
-	object::preview::thumbnail::directory::init_thumbnail_dir,
-	prisma::{file_path, location, PrismaClient},
-	util::db::maybe_missing,
+	object::preview::{enforce_preview_media_budget, thumbnail::directory::init_thumbnail_dir},
+	prisma::{file_path, location},
+	util::db::{chain_optional_iter, maybe_missing},
 };
 
 use std::{
@@ -21,17 +22,41 @@ use std::{
 
 use sd_file_ext::extensions::Extension;
 
+use prisma_client_rust::Direction;
 use serde::{Deserialize, Serialize};
 
-use tracing::info;
+use tracing::{info, warn};
 
 use super::{
-	inner_process_step, ThumbnailerError, ThumbnailerJobStep, ThumbnailerJobStepKind,
-	FILTERED_IMAGE_EXTENSIONS,
+	super::text::FILTERED_TEXT_EXTENSIONS, inner_process_step, ThumbnailerError,
+	ThumbnailerJobStep, ThumbnailerJobStepKind, ThumbnailerOptions, FILTERED_IMAGE_EXTENSIONS,
 };
 
 #[cfg(feature = "ffmpeg")]
-use super::FILTERED_VIDEO_EXTENSIONS;
+use super::{
+	super::waveform::FILTERED_AUDIO_EXTENSIONS, video_thumbnails_enabled, FILTERED_VIDEO_EXTENSIONS,
+};
+
+#[cfg(feature = "pdf")]
+use super::FILTERED_DOCUMENT_EXTENSIONS;
+
+#[cfg(feature = "model")]
+use super::FILTERED_MODEL_EXTENSIONS;
+
+#[cfg(feature = "book")]
+use super::FILTERED_BOOK_EXTENSIONS;
+
+/// The file paths needing previews are read in pages this big, a page per step
+const PAGE_SIZE: i64 = 100;
+
+file_path::select!(file_path_to_thumbnail {
+	id
+	materialized_path
+	is_dir
+	name
+	extension
+	cas_id
+});
 
 pub struct ThumbnailerJob {}
 
@@ -39,6 +64,9 @@ pub struct ThumbnailerJob {}
 pub struct ThumbnailerJobInit {
 	pub location: location::Data,
 	pub sub_path: Option<PathBuf>,
+	/// Overwrite existing thumbnails, used to apply a new thumbnail size to old thumbnails
+	#[serde(default)]
+	pub regenerate: bool,
 }
 
 impl Hash for ThumbnailerJobInit {
@@ -47,11 +75,16 @@ impl Hash for ThumbnailerJobInit {
 		if let Some(ref sub_path) = self.sub_path {
 			sub_path.hash(state);
 		}
+		self.regenerate.hash(state);
 	}
 }
 
 impl JobInitData for ThumbnailerJobInit {
 	type Job = ThumbnailerJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location.id])
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,6 +92,17 @@ pub struct ThumbnailerJobData {
 	thumbnail_dir: PathBuf,
 	location_path: PathBuf,
 	path: PathBuf,
+	options: ThumbnailerOptions,
+	/// The file paths under this materialized path get previews
+	materialized_path: String,
+}
+
+/// A page of the files needing a kind of preview, read when the step runs
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ThumbnailerJobPage {
+	kind: ThumbnailerJobStepKind,
+	/// The id of the last file path of the previous page
+	cursor: Option<file_path::id::Type>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -78,10 +122,11 @@ impl JobRunMetadata for ThumbnailerJobRunMetadata {
 impl StatefulJob for ThumbnailerJob {
 	type Init = ThumbnailerJobInit;
 	type Data = ThumbnailerJobData;
-	type Step = ThumbnailerJobStep;
+	type Step = ThumbnailerJobPage;
 	type RunMetadata = ThumbnailerJobRunMetadata;
 
 	const NAME: &'static str = "thumbnailer";
+	const LOAD: JobLoad = JobLoad::ReadHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -131,44 +176,54 @@ impl StatefulJob for ThumbnailerJob {
 			),
 		};
 
-		info!("Searching for images in location {location_id} at directory {iso_file_path}");
-
-		// query database for all image files in this location that need thumbnails
-		let image_files = get_files_by_extensions(
-			db,
-			&iso_file_path,
-			&FILTERED_IMAGE_EXTENSIONS,
-			ThumbnailerJobStepKind::Image,
-		)
-		.await?;
-		info!("Found {:?} image files", image_files.len());
-
-		#[cfg(feature = "ffmpeg")]
-		let all_files = {
-			// query database for all video files in this location that need thumbnails
-			let video_files = get_files_by_extensions(
-				db,
-				&iso_file_path,
-				&FILTERED_VIDEO_EXTENSIONS,
-				ThumbnailerJobStepKind::Video,
-			)
-			.await?;
-			info!("Found {:?} video files", video_files.len());
+		info!("Searching for files needing previews in location {location_id} at directory {iso_file_path}");
+
+		let materialized_path = iso_file_path
+			.materialized_path_for_children()
+			.expect("sub path iso_file_path must be a directory");
+
+		let kinds = [
+			Some(ThumbnailerJobStepKind::Image),
+			#[cfg(feature = "ffmpeg")]
+			video_thumbnails_enabled(&init.location).then_some(ThumbnailerJobStepKind::Video),
+			#[cfg(feature = "ffmpeg")]
+			Some(ThumbnailerJobStepKind::Audio),
+			#[cfg(feature = "pdf")]
+			Some(ThumbnailerJobStepKind::Document),
+			#[cfg(feature = "model")]
+			Some(ThumbnailerJobStepKind::Model),
+			#[cfg(feature = "book")]
+			Some(ThumbnailerJobStepKind::Book),
+			Some(ThumbnailerJobStepKind::Text),
+			// Sprite sheets are only needed when hovering a video, so they come after every preview
+			#[cfg(feature = "ffmpeg")]
+			video_thumbnails_enabled(&init.location).then_some(ThumbnailerJobStepKind::VideoSprite),
+		];
+
+		let mut steps = vec![];
+		let mut total_files = 0;
+		for kind in kinds.into_iter().flatten() {
+			let count = db
+				.file_path()
+				.count(page_filters(location_id, &materialized_path, kind, None))
+				.exec()
+				.await?;
+			info!("Found {count} files needing a {kind:?} preview");
 
-			image_files
-				.into_iter()
-				.chain(video_files.into_iter())
-				.collect::<Vec<_>>()
-		};
-		#[cfg(not(feature = "ffmpeg"))]
-		let all_files = { image_files.into_iter().collect::<Vec<_>>() };
+			if count > 0 {
+				total_files += count;
+				steps.push(ThumbnailerJobPage { kind, cursor: None });
+			}
+		}
 
-		ctx.progress_msg(format!("Preparing to process {} files", all_files.len()));
+		ctx.progress_msg(job_message!("thumbnails.preparing", count = total_files));
 
 		*data = Some(ThumbnailerJobData {
 			thumbnail_dir,
 			location_path,
 			path,
+			options: ThumbnailerOptions::for_library(&ctx.library, init.regenerate).await,
+			materialized_path,
 		});
 
 		Ok((
@@ -176,7 +231,7 @@ impl StatefulJob for ThumbnailerJob {
 				thumbnails_created: 0,
 				thumbnails_skipped: 0,
 			},
-			all_files,
+			steps,
 		)
 			.into())
 	}
@@ -189,34 +244,73 @@ impl StatefulJob for ThumbnailerJob {
 		data: &Self::Data,
 		_: &Self::RunMetadata,
 	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
-		ctx.progress_msg(format!(
-			"Processing {}",
-			maybe_missing(
-				&step.file_path.materialized_path,
-				"file_path.materialized_path"
-			)?
-		));
+		let ThumbnailerJobPage { kind, cursor } = *step;
+
+		let file_paths = ctx
+			.library
+			.db
+			.file_path()
+			.find_many(page_filters(
+				init.location.id,
+				&data.materialized_path,
+				kind,
+				cursor,
+			))
+			.order_by(file_path::id::order(Direction::Asc))
+			.take(PAGE_SIZE)
+			.select(file_path_to_thumbnail::select())
+			.exec()
+			.await?;
+
+		// A full page may not be the last one, the next step reads the following page
+		let next_page = (file_paths.len() == PAGE_SIZE as usize).then(|| ThumbnailerJobPage {
+			kind,
+			cursor: file_paths.last().map(|file_path| file_path.id),
+		});
 
 		let mut new_metadata = Self::RunMetadata::default();
 
-		let step_result = inner_process_step(
-			step,
-			&data.location_path,
-			&data.thumbnail_dir,
-			&init.location,
-			&ctx.library,
-		)
-		.await;
+		for file_path in file_paths {
+			let step = ThumbnailerJobStep {
+				file_path: file_path_for_thumbnailer::Data {
+					materialized_path: file_path.materialized_path,
+					is_dir: file_path.is_dir,
+					name: file_path.name,
+					extension: file_path.extension,
+					cas_id: file_path.cas_id,
+				},
+				kind,
+			};
+
+			ctx.progress_msg(job_message!(
+				"thumbnails.processing",
+				path = maybe_missing(
+					&step.file_path.materialized_path,
+					"file_path.materialized_path"
+				)?
+			));
+
+			let thumbnail_was_created = inner_process_step(
+				&step,
+				&data.location_path,
+				&data.thumbnail_dir,
+				data.options,
+				&init.location,
+				&ctx.library,
+			)
+			.await?;
 
-		step_result.map(|thumbnail_was_created| {
 			if thumbnail_was_created {
 				new_metadata.thumbnails_created += 1;
 			} else {
 				new_metadata.thumbnails_skipped += 1;
 			}
-		})?;
+		}
 
-		Ok(new_metadata.into())
+		Ok(match next_page {
+			Some(next_page) => (vec![next_page], new_metadata).into(),
+			None => new_metadata.into(),
+		})
 	}
 
 	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
@@ -233,33 +327,46 @@ impl StatefulJob for ThumbnailerJob {
 
 		if state.run_metadata.thumbnails_created > 0 {
 			invalidate_query!(ctx.library, "search.paths");
+
+			if let Some(max_size_mb) = ctx.library.config.settings.preview_media_max_size_mb {
+				if let Err(e) = enforce_preview_media_budget(&ctx.library, max_size_mb).await {
+					warn!("Failed to keep the preview media under their size limit: {e}");
+				}
+			}
 		}
 
 		Ok(Some(serde_json::to_value(&state.run_metadata)?))
 	}
 }
 
-async fn get_files_by_extensions(
-	db: &PrismaClient,
-	iso_file_path: &IsolatedFilePathData<'_>,
-	extensions: &[Extension],
+/// The file paths of the location needing this kind of preview, after the cursor
+fn page_filters(
+	location_id: location::id::Type,
+	materialized_path: &str,
 	kind: ThumbnailerJobStepKind,
-) -> Result<Vec<ThumbnailerJobStep>, JobError> {
-	Ok(db
-		.file_path()
-		.find_many(vec![
-			file_path::location_id::equals(Some(iso_file_path.location_id())),
+	cursor: Option<file_path::id::Type>,
+) -> Vec<file_path::WhereParam> {
+	let extensions: &[Extension] = match kind {
+		ThumbnailerJobStepKind::Image => &FILTERED_IMAGE_EXTENSIONS,
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::Video | ThumbnailerJobStepKind::VideoSprite => &FILTERED_VIDEO_EXTENSIONS,
+		#[cfg(feature = "ffmpeg")]
+		ThumbnailerJobStepKind::Audio => &FILTERED_AUDIO_EXTENSIONS,
+		#[cfg(feature = "pdf")]
+		ThumbnailerJobStepKind::Document => &FILTERED_DOCUMENT_EXTENSIONS,
+		#[cfg(feature = "model")]
+		ThumbnailerJobStepKind::Model => &FILTERED_MODEL_EXTENSIONS,
+		#[cfg(feature = "book")]
+		ThumbnailerJobStepKind::Book => &FILTERED_BOOK_EXTENSIONS,
+		ThumbnailerJobStepKind::Text => &FILTERED_TEXT_EXTENSIONS,
+	};
+
+	chain_optional_iter(
+		[
+			file_path::location_id::equals(Some(location_id)),
 			file_path::extension::in_vec(extensions.iter().map(ToString::to_string).collect()),
-			file_path::materialized_path::starts_with(
-				iso_file_path
-					.materialized_path_for_children()
-					.expect("sub path iso_file_path must be a directory"),
-			),
-		])
-		.select(file_path_for_thumbnailer::select())
-		.exec()
-		.await?
-		.into_iter()
-		.map(|file_path| ThumbnailerJobStep { file_path, kind })
-		.collect())
+			file_path::materialized_path::starts_with(materialized_path.to_string()),
+		],
+		[cursor.map(file_path::id::gt)],
+	)
 }
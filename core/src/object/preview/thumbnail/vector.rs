@@ -0,0 +1,209 @@
+//! Vector images have no intrinsic resolution, so they're rasterized straight to the thumbnail
+//! size. SVGs are rendered in process with resvg, while PostScript based formats (EPS and AI) are
+//! rasterized by Ghostscript, which must be installed and available in `PATH`.
+//!
+//! Both keep the transparent background, so the thumbnails look right on any theme.
+
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+	process::Command,
+};
+
+use image::{DynamicImage, RgbaImage};
+use once_cell::sync::Lazy;
+use resvg::{
+	tiny_skia::{Pixmap, Transform},
+	usvg::{self, fontdb, TreeParsing, TreeTextToPath},
+};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// The maximum file size that a vector file can be in order to have a thumbnail generated.
+///
+/// This value is in MiB.
+const VECTOR_MAXIMUM_FILE_SIZE: u64 = 1048576 * 50;
+
+const POSTSCRIPT_CONVERTER_BINARY: &str = "gs";
+
+/// Resolution used to rasterize PostScript files, the result is scaled down to the thumbnail size
+/// afterwards. At 150 DPI an A4 sized artboard is roughly 1240x1754 pixels.
+const POSTSCRIPT_RENDER_DPI: u32 = 150;
+
+pub const SVG_EXTENSIONS: [&str; 1] = ["svg"];
+pub const POSTSCRIPT_EXTENSIONS: [&str; 2] = ["eps", "ai"];
+
+/// Loading system fonts is slow, so we do it once and share the database between renders
+static FONT_DATABASE: Lazy<fontdb::Database> = Lazy::new(|| {
+	let mut fontdb = fontdb::Database::new();
+	fontdb.load_system_fonts();
+	fontdb
+});
+
+#[derive(Error, Debug)]
+pub enum VectorError {
+	#[error("the vector file provided is too large (over 50MiB)")]
+	TooLarge,
+	#[error("failed to parse svg: {0}")]
+	Svg(#[from] usvg::Error),
+	#[error("the svg has an invalid size")]
+	InvalidSize,
+	#[error("failed to rasterize postscript file: {0}")]
+	PostScriptConversion(String),
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error(transparent)]
+	Image(#[from] image::ImageError),
+}
+
+pub fn is_svg(extension: &str) -> bool {
+	SVG_EXTENSIONS
+		.iter()
+		.any(|ext| extension.eq_ignore_ascii_case(ext))
+}
+
+pub fn is_postscript(extension: &str) -> bool {
+	POSTSCRIPT_EXTENSIONS
+		.iter()
+		.any(|ext| extension.eq_ignore_ascii_case(ext))
+}
+
+/// Renders an SVG file with its largest side scaled to `target_size` pixels.
+pub fn svg_to_dynamic_image(
+	path: impl AsRef<Path>,
+	target_size: u32,
+) -> Result<DynamicImage, Box<dyn Error>> {
+	let path = path.as_ref();
+	check_file_size(path)?;
+
+	let data = std::fs::read(path)?;
+
+	let options = usvg::Options {
+		// Relative paths to images inside the svg are resolved from its directory
+		resources_dir: path.parent().map(Path::to_path_buf),
+		..Default::default()
+	};
+
+	let mut tree = usvg::Tree::from_data(&data, &options).map_err(VectorError::from)?;
+	tree.convert_text(&FONT_DATABASE);
+	let tree = resvg::Tree::from_usvg(&tree);
+
+	let (width, height) = fit_to_size(tree.size.width(), tree.size.height(), target_size)
+		.ok_or(VectorError::InvalidSize)?;
+
+	let mut pixmap = Pixmap::new(width, height).ok_or(VectorError::InvalidSize)?;
+	tree.render(
+		Transform::from_scale(
+			width as f32 / tree.size.width(),
+			height as f32 / tree.size.height(),
+		),
+		&mut pixmap.as_mut(),
+	);
+
+	// tiny-skia works with premultiplied alpha, while image expects straight alpha
+	let pixels = pixmap
+		.pixels()
+		.iter()
+		.flat_map(|pixel| {
+			let color = pixel.demultiply();
+			[color.red(), color.green(), color.blue(), color.alpha()]
+		})
+		.collect::<Vec<_>>();
+
+	RgbaImage::from_raw(width, height, pixels)
+		.map(DynamicImage::ImageRgba8)
+		.ok_or_else(|| VectorError::InvalidSize.into())
+}
+
+/// Rasterizes the first page of an EPS or AI file using Ghostscript, keeping the transparency.
+/// The image is rendered at a fixed resolution and must be scaled down to the thumbnail size.
+pub fn postscript_to_dynamic_image(path: impl AsRef<Path>) -> Result<DynamicImage, Box<dyn Error>> {
+	let path = path.as_ref();
+	check_file_size(path)?;
+
+	let output_path = TempFile(std::env::temp_dir().join(format!("sd-{}.png", Uuid::new_v4())));
+
+	let output = Command::new(POSTSCRIPT_CONVERTER_BINARY)
+		.args([
+			"-q",
+			"-dSAFER",
+			"-dBATCH",
+			"-dNOPAUSE",
+			// Crops to the artwork's bounding box instead of a whole page
+			"-dEPSCrop",
+			"-dFirstPage=1",
+			"-dLastPage=1",
+			"-dTextAlphaBits=4",
+			"-dGraphicsAlphaBits=4",
+			"-sDEVICE=pngalpha",
+		])
+		.arg(format!("-r{POSTSCRIPT_RENDER_DPI}"))
+		.arg(format!("-sOutputFile={}", output_path.0.display()))
+		.arg(path)
+		.output()
+		.map_err(VectorError::from)?;
+
+	if !output.status.success() || !output_path.0.exists() {
+		return Err(VectorError::PostScriptConversion(
+			String::from_utf8_lossy(&output.stderr).into_owned(),
+		)
+		.into());
+	}
+
+	Ok(image::open(&output_path.0).map_err(VectorError::from)?)
+}
+
+fn check_file_size(path: &Path) -> Result<(), VectorError> {
+	if std::fs::metadata(path)?.len() > VECTOR_MAXIMUM_FILE_SIZE {
+		return Err(VectorError::TooLarge);
+	}
+
+	Ok(())
+}
+
+/// Scales the dimensions so the largest side is `target_size`. Vector images can be scaled up
+/// without any loss, so unlike raster images small ones are enlarged too.
+fn fit_to_size(width: f32, height: f32, target_size: u32) -> Option<(u32, u32)> {
+	if !(width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite()) {
+		return None;
+	}
+
+	let factor = target_size as f32 / width.max(height);
+
+	Some((
+		((width * factor).round() as u32).max(1),
+		((height * factor).round() as u32).max(1),
+	))
+}
+
+/// Removes the rasterized file when dropped, even if decoding it failed
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		std::fs::remove_file(&self.0).ok();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fits_largest_side() {
+		assert_eq!(fit_to_size(100.0, 50.0, 512), Some((512, 256)));
+		assert_eq!(fit_to_size(3000.0, 6000.0, 256), Some((128, 256)));
+		// Very thin images still get at least a pixel
+		assert_eq!(fit_to_size(10000.0, 1.0, 256), Some((256, 1)));
+		assert_eq!(fit_to_size(0.0, 50.0, 256), None);
+		assert_eq!(fit_to_size(f32::NAN, 50.0, 256), None);
+	}
+
+	#[test]
+	fn matches_extensions() {
+		assert!(is_svg("SVG"));
+		assert!(is_postscript("eps"));
+		assert!(is_postscript("Ai"));
+		assert!(!is_postscript("pdf"));
+	}
+}
@@ -0,0 +1,66 @@
+use crate::{library::Library, util::error::FileIOError};
+
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "ffmpeg")]
+use sd_file_ext::extensions::{AudioExtension, Extension};
+
+#[cfg(feature = "ffmpeg")]
+use once_cell::sync::Lazy;
+use tokio::{fs, io};
+
+use super::get_shard_hex;
+
+pub const WAVEFORM_DIR_NAME: &str = "waveforms";
+
+/// Amount of peaks stored for each audio file, each peak is a single byte so waveforms are
+/// small enough to be sent to the frontend as is
+pub const WAVEFORM_PEAKS: usize = 256;
+
+#[cfg(feature = "ffmpeg")]
+pub(super) static FILTERED_AUDIO_EXTENSIONS: Lazy<Vec<Extension>> = Lazy::new(|| {
+	sd_file_ext::extensions::ALL_AUDIO_EXTENSIONS
+		.iter()
+		.map(Clone::clone)
+		.filter(can_generate_waveform_for_audio)
+		.map(Extension::Audio)
+		.collect()
+});
+
+#[cfg(feature = "ffmpeg")]
+pub const fn can_generate_waveform_for_audio(audio_extension: &AudioExtension) -> bool {
+	use AudioExtension::*;
+	// File extensions that are specifically not supported by the waveform generation
+	!matches!(audio_extension, Aptx | Loas | Ast)
+}
+
+/// Waveforms are stored alongside thumbnails in the node's data directory, sharded the same way
+pub fn get_waveform_path(data_dir: impl AsRef<Path>, cas_id: &str) -> PathBuf {
+	data_dir
+		.as_ref()
+		.join(WAVEFORM_DIR_NAME)
+		.join(get_shard_hex(cas_id))
+		.join(cas_id)
+		.with_extension("bin")
+}
+
+#[cfg(feature = "ffmpeg")]
+pub async fn generate_waveform<P: AsRef<Path>>(
+	file_path: P,
+	output_path: P,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let peaks = sd_ffmpeg::to_waveform_peaks(file_path, WAVEFORM_PEAKS).await?;
+
+	fs::write(output_path, peaks).await.map_err(Into::into)
+}
+
+/// Returns the waveform peaks of an object if they were already generated by the thumbnailer
+pub async fn get_waveform(library: &Library, cas_id: &str) -> Result<Option<Vec<u8>>, FileIOError> {
+	let path = get_waveform_path(library.config().data_directory(), cas_id);
+
+	match fs::read(&path).await {
+		Ok(peaks) => Ok(Some(peaks)),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(FileIOError::from((path, e))),
+	}
+}
@@ -7,7 +7,11 @@ use specta::Type;
 
 use uuid::Uuid;
 
-use crate::{library::Library, prisma::tag, sync};
+use crate::{
+	library::Library,
+	prisma::tag,
+	sync::{self, SyncError},
+};
 
 #[derive(Type, Deserialize, Clone)]
 pub struct TagCreateArgs {
@@ -16,10 +20,7 @@ pub struct TagCreateArgs {
 }
 
 impl TagCreateArgs {
-	pub async fn exec(
-		self,
-		Library { db, sync, .. }: &Library,
-	) -> prisma_client_rust::Result<tag::Data> {
+	pub async fn exec(self, Library { db, sync, .. }: &Library) -> Result<tag::Data, SyncError> {
 		let pub_id = Uuid::new_v4().as_bytes().to_vec();
 		let date_created: DateTime<FixedOffset> = Utc::now().into();
 
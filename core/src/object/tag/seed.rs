@@ -1,9 +1,9 @@
 use super::TagCreateArgs;
-use crate::library::Library;
+use crate::{library::Library, sync::SyncError};
 
 /// Seeds tags in a new library.
 /// Shouldn't be called more than once!
-pub async fn new_library(library: &Library) -> prisma_client_rust::Result<()> {
+pub async fn new_library(library: &Library) -> Result<(), SyncError> {
 	// remove type after tags are added
 
 	let tags = [
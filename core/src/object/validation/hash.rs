@@ -1,3 +1,5 @@
+use crate::util::sparse;
+
 use blake3::Hasher;
 use std::path::Path;
 use tokio::{
@@ -9,6 +11,15 @@ const BLOCK_LEN: usize = 1048576;
 
 pub async fn file_checksum(path: impl AsRef<Path>) -> Result<String, io::Error> {
 	let mut reader = File::open(path).await?;
+
+	// The holes of sparse files hash as the zeros they read as, without being read
+	let metadata = reader.metadata().await?;
+	if sparse::is_sparse(&metadata) {
+		let context = sparse::hash_sparse(&mut reader, metadata.len()).await?;
+
+		return Ok(context.finalize().to_hex().to_string());
+	}
+
 	let mut context = Hasher::new();
 	let mut buffer = vec![0; BLOCK_LEN].into_boxed_slice();
 	loop {
@@ -6,6 +6,7 @@ use thiserror::Error;
 
 pub mod hash;
 pub mod validator_job;
+pub mod verifier_job;
 
 #[derive(Error, Debug)]
 pub enum ValidatorError {
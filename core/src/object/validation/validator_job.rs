@@ -1,7 +1,7 @@
 use crate::{
 	job::{
-		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobState, JobStepOutput,
-		StatefulJob, WorkerContext,
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobState, JobStepOutput, StatefulJob, WorkerContext,
 	},
 	library::Library,
 	location::file_path_helper::{
@@ -57,6 +57,10 @@ impl Hash for ObjectValidatorJobInit {
 
 impl JobInitData for ObjectValidatorJobInit {
 	type Job = ObjectValidatorJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location.id])
+	}
 }
 
 #[async_trait::async_trait]
@@ -67,6 +71,7 @@ impl StatefulJob for ObjectValidatorJob {
 	type RunMetadata = ();
 
 	const NAME: &'static str = "object_validator";
+	const LOAD: JobLoad = JobLoad::ReadHeavy;
 
 	fn new() -> Self {
 		Self {}
@@ -0,0 +1,439 @@
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobManager,
+		JobManagerError, JobResult, JobRunMetadata, JobState, JobStatus, JobStepOutput,
+		StatefulJob, WorkerContext,
+	},
+	job_message,
+	library::{notifications::NotificationData, Library, LibraryManager},
+	location::file_path_helper::{
+		file_path_for_object_verifier, IsolatedFilePathData, MetadataExt,
+	},
+	object::fs::get_location_path_from_location_id,
+	p2p::SyncScheduler,
+	prisma::{file_path, job, location},
+	sync,
+	util::{db::maybe_missing, error::FileIOError},
+};
+
+use std::{
+	path::PathBuf,
+	sync::{Arc, Weak},
+	time::Duration,
+};
+
+use chrono::{DateTime, Duration as ChronoDuration, FixedOffset, Local, Utc};
+use prisma_client_rust::{Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use tokio::{
+	fs, io,
+	time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+
+use super::{hash::file_checksum, ValidatorError};
+
+/// How often the scheduler checks if the device is idle
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many hours go by between two verifications of the locations of a library
+const VERIFICATION_INTERVAL_HOURS: i64 = 24;
+/// How many files of each location a scheduled verification re-hashes, those verified the
+/// longest ago, so every file gets its turn over the days
+const SCHEDULED_SAMPLE_SIZE: u32 = 1000;
+
+// The Verifier re-hashes files that already have an integrity checksum and compares them, to find
+// files damaged on disk (bit rot) and files changed without Spacedrive noticing
+pub struct ObjectVerifierJob {}
+
+#[derive(Serialize, Deserialize, Hash, Type, Debug)]
+pub struct ObjectVerifierJobInit {
+	pub location_id: location::id::Type,
+	/// How many of the files verified the longest ago are verified, all of them when not set
+	pub sample_size: Option<u32>,
+}
+
+impl JobInitData for ObjectVerifierJobInit {
+	type Job = ObjectVerifierJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ObjectVerifierJobData {
+	location_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ObjectVerifierJobRunMetadata {
+	verified: u64,
+	corrupted: Vec<PathBuf>,
+	changed: Vec<PathBuf>,
+}
+
+impl JobRunMetadata for ObjectVerifierJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.verified += new_data.verified;
+		self.corrupted.extend(new_data.corrupted);
+		self.changed.extend(new_data.changed);
+	}
+}
+
+/// Why a file doesn't match its integrity checksum anymore
+#[derive(Debug, PartialEq, Eq)]
+enum Mismatch {
+	/// Same size and modification date as when it was indexed, its contents changed on their own
+	Corrupted,
+	/// It was written to since it was indexed
+	Changed,
+}
+
+impl Mismatch {
+	fn of(
+		indexed_size: Option<&[u8]>,
+		indexed_modified_at: Option<DateTime<FixedOffset>>,
+		size: u64,
+		modified_at: DateTime<Utc>,
+	) -> Self {
+		let same_size = indexed_size == Some(&size.to_be_bytes()[..]);
+		// Some filesystems keep modification dates to the second, or even two
+		let same_modified_at = indexed_modified_at.map_or(false, |indexed_modified_at| {
+			(indexed_modified_at.with_timezone(&Utc) - modified_at)
+				.num_seconds()
+				.abs() < 2
+		});
+
+		if same_size && same_modified_at {
+			Self::Corrupted
+		} else {
+			Self::Changed
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for ObjectVerifierJob {
+	type Init = ObjectVerifierJobInit;
+	type Data = ObjectVerifierJobData;
+	type Step = file_path_for_object_verifier::Data;
+	type RunMetadata = ObjectVerifierJobRunMetadata;
+
+	const NAME: &'static str = "object_verifier";
+	const LOAD: JobLoad = JobLoad::ReadHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &ctx.library;
+
+		let location_path = get_location_path_from_location_id(db, init.location_id).await?;
+
+		// Files never verified come first, as SQLite sorts nulls first
+		let mut query = db
+			.file_path()
+			.find_many(vec![
+				file_path::location_id::equals(Some(init.location_id)),
+				file_path::is_dir::equals(Some(false)),
+				file_path::integrity_checksum::not(None),
+			])
+			.order_by(file_path::date_verified::order(Direction::Asc))
+			.order_by(file_path::id::order(Direction::Asc));
+		if let Some(sample_size) = init.sample_size {
+			query = query.take(sample_size as i64);
+		}
+
+		let steps = query
+			.select(file_path_for_object_verifier::select())
+			.exec()
+			.await?;
+
+		*data = Some(ObjectVerifierJobData { location_path });
+
+		Ok((ObjectVerifierJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep {
+			step: file_path, ..
+		}: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, sync, .. } = &ctx.library;
+
+		let full_path = data.location_path.join(IsolatedFilePathData::try_from((
+			init.location_id,
+			file_path,
+		))?);
+
+		let metadata = match fs::metadata(&full_path).await {
+			Ok(metadata) => metadata,
+			// The indexer takes care of files that are gone
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None.into()),
+			Err(e) => return Err(ValidatorError::from(FileIOError::from((full_path, e))).into()),
+		};
+
+		ctx.progress_msg(job_message!(
+			"validation.verifying",
+			path = full_path.display()
+		));
+
+		let checksum = file_checksum(&full_path)
+			.await
+			.map_err(|e| ValidatorError::FileIO(FileIOError::from((&full_path, e))))?;
+
+		let mut run_metadata = ObjectVerifierJobRunMetadata {
+			verified: 1,
+			..Default::default()
+		};
+
+		if Some(&checksum) != file_path.integrity_checksum.as_ref() {
+			match Mismatch::of(
+				file_path.size_in_bytes_bytes.as_deref(),
+				file_path.date_modified,
+				metadata.len(),
+				metadata.modified_or_now().into(),
+			) {
+				Mismatch::Corrupted => {
+					warn!("{} doesn't match its checksum", full_path.display());
+
+					// The checksum is kept, so the file is reported until it's restored
+					run_metadata.corrupted.push(full_path);
+				}
+				Mismatch::Changed => {
+					debug!("{} changed since it was indexed", full_path.display());
+
+					sync.write_op(
+						db,
+						sync.shared_update(
+							sync::file_path::SyncId {
+								pub_id: file_path.pub_id.clone(),
+							},
+							file_path::integrity_checksum::NAME,
+							json!(&checksum),
+						),
+						db.file_path().update(
+							file_path::pub_id::equals(file_path.pub_id.clone()),
+							vec![file_path::integrity_checksum::set(Some(checksum))],
+						),
+					)
+					.await?;
+
+					run_metadata.changed.push(full_path);
+				}
+			}
+		}
+
+		// When this device verified its own files, other devices have nothing to do with it
+		db.file_path()
+			.update(
+				file_path::id::equals(file_path.id),
+				vec![file_path::date_verified::set(Some(Utc::now().into()))],
+			)
+			.exec()
+			.await?;
+
+		Ok(run_metadata.into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		let ObjectVerifierJobRunMetadata {
+			verified,
+			corrupted,
+			changed,
+		} = &state.run_metadata;
+
+		info!(
+			"Verified {verified} files of location {}: {} corrupted, {} changed",
+			state.init.location_id,
+			corrupted.len(),
+			changed.len()
+		);
+
+		if !corrupted.is_empty() || !changed.is_empty() {
+			ctx.library.notify(NotificationData::IntegrityMismatches {
+				location_id: state.init.location_id,
+				corrupted: corrupted.clone(),
+				changed: changed.clone(),
+			});
+		}
+
+		if !changed.is_empty() {
+			invalidate_query!(ctx.library, "search.paths");
+		}
+
+		Ok(Some(json!({
+			"init": state.init,
+			"verified": verified,
+			"corrupted": corrupted,
+			"changed": changed,
+		})))
+	}
+}
+
+/// Whether the locations of the library weren't verified in the last
+/// [`VERIFICATION_INTERVAL_HOURS`]
+async fn is_verification_due(library: &Library) -> Result<bool, QueryError> {
+	let last = library
+		.db
+		.job()
+		.find_first(vec![
+			job::name::equals(Some(ObjectVerifierJob::NAME.to_string())),
+			job::status::equals(Some(JobStatus::Completed as i32)),
+		])
+		.order_by(job::date_completed::order(Direction::Desc))
+		.select(job::select!({ date_completed }))
+		.exec()
+		.await?;
+
+	Ok(last
+		.and_then(|job| job.date_completed)
+		.map_or(true, |date| {
+			Utc::now() - date.with_timezone(&Utc)
+				>= ChronoDuration::hours(VERIFICATION_INTERVAL_HOURS)
+		}))
+}
+
+/// Verifies a sample of the files of each location of this device while it's idle and plugged
+/// in, when no other job is running. Only one library is verified at a time, the others wait
+/// for the next check.
+pub(crate) fn spawn_scheduler(
+	library_manager: Weak<LibraryManager>,
+	job_manager: Arc<JobManager>,
+	sync_scheduler: Arc<SyncScheduler>,
+) {
+	tokio::spawn(async move {
+		let mut tick = interval(CHECK_INTERVAL);
+		tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			tick.tick().await;
+
+			let Some(library_manager) = library_manager.upgrade() else {
+				break;
+			};
+
+			let conditions = sync_scheduler.conditions();
+			if !conditions.idle || conditions.on_battery || job_manager.has_active_workers().await {
+				continue;
+			}
+
+			for library in library_manager.get_all_libraries().await {
+				if library.config.settings.is_quiet(Local::now()) {
+					continue;
+				}
+
+				match is_verification_due(&library).await {
+					Ok(true) => {
+						debug!("Verifying the files of library '{}'", library.id);
+
+						if let Err(e) = spawn_verifications(&library).await {
+							warn!(
+								"Failed to start verifying the files of library '{}': {e}",
+								library.id
+							);
+						}
+
+						break;
+					}
+					Ok(false) => {}
+					Err(e) => warn!(
+						"Failed to read the verifications of library '{}': {e}",
+						library.id
+					),
+				}
+			}
+		}
+	});
+}
+
+async fn spawn_verifications(library: &Library) -> Result<(), JobManagerError> {
+	for location in library
+		.db
+		.location()
+		.find_many(vec![location::node_id::equals(Some(library.node_local_id))])
+		.select(location::select!({ id path }))
+		.exec()
+		.await?
+	{
+		// Locations on drives that aren't plugged in are left for another time
+		if fs::metadata(maybe_missing(&location.path, "location.path")?)
+			.await
+			.is_err()
+		{
+			continue;
+		}
+
+		library
+			.spawn_job(ObjectVerifierJobInit {
+				location_id: location.id,
+				sample_size: Some(SCHEDULED_SAMPLE_SIZE),
+			})
+			.await?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tells_corrupted_files_from_changed_ones() {
+		let modified_at = Utc::now();
+		let indexed_modified_at = modified_at.into();
+
+		assert_eq!(
+			Mismatch::of(
+				Some(&42u64.to_be_bytes()),
+				Some(indexed_modified_at),
+				42,
+				modified_at
+			),
+			Mismatch::Corrupted
+		);
+		assert_eq!(
+			Mismatch::of(
+				Some(&42u64.to_be_bytes()),
+				Some(indexed_modified_at),
+				42,
+				modified_at + ChronoDuration::milliseconds(1500)
+			),
+			Mismatch::Corrupted
+		);
+		assert_eq!(
+			Mismatch::of(
+				Some(&42u64.to_be_bytes()),
+				Some(indexed_modified_at),
+				43,
+				modified_at
+			),
+			Mismatch::Changed
+		);
+		assert_eq!(
+			Mismatch::of(
+				Some(&42u64.to_be_bytes()),
+				Some(indexed_modified_at),
+				42,
+				modified_at + ChronoDuration::minutes(5)
+			),
+			Mismatch::Changed
+		);
+		assert_eq!(Mismatch::of(None, None, 42, modified_at), Mismatch::Changed);
+	}
+}
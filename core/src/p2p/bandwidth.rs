@@ -0,0 +1,158 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+};
+
+use sd_p2p::{spaceblock::RateLimiter, PeerId};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// Upload and download limits in KiB/s, `None` is unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct RateLimits {
+	pub upload: Option<u32>,
+	pub download: Option<u32>,
+}
+
+/// The speed limits of the transfers with other nodes, like Spacedrop
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct BandwidthLimits {
+	/// Shared by all the transfers
+	#[serde(default)]
+	pub global: RateLimits,
+	/// Shared by the transfers with a peer, by the id of the peer
+	#[serde(default)]
+	pub peers: HashMap<String, RateLimits>,
+}
+
+fn bytes_per_second(kib: Option<u32>) -> Option<u64> {
+	kib.map(|kib| kib as u64 * 1024)
+}
+
+#[derive(Debug)]
+struct Limiters {
+	upload: Arc<RateLimiter>,
+	download: Arc<RateLimiter>,
+}
+
+impl Limiters {
+	fn new(limits: RateLimits) -> Self {
+		Self {
+			upload: Arc::new(RateLimiter::new(bytes_per_second(limits.upload))),
+			download: Arc::new(RateLimiter::new(bytes_per_second(limits.download))),
+		}
+	}
+
+	fn set(&self, limits: RateLimits) {
+		self.upload.set_limit(bytes_per_second(limits.upload));
+		self.download.set_limit(bytes_per_second(limits.download));
+	}
+}
+
+/// Applies the [`BandwidthLimits`] of the node to the transfers, the limits can be changed while
+/// they're running
+#[derive(Debug)]
+pub struct Bandwidth {
+	global: Limiters,
+	peers: Mutex<(BandwidthLimits, HashMap<PeerId, Limiters>)>,
+}
+
+impl Bandwidth {
+	pub fn new(limits: BandwidthLimits) -> Self {
+		Self {
+			global: Limiters::new(limits.global),
+			peers: Mutex::new((limits, HashMap::new())),
+		}
+	}
+
+	pub fn set_limits(&self, limits: BandwidthLimits) {
+		self.global.set(limits.global);
+
+		let mut peers = self.peers.lock().unwrap();
+		for (peer_id, limiters) in &peers.1 {
+			limiters.set(
+				limits
+					.peers
+					.get(&peer_id.to_string())
+					.copied()
+					.unwrap_or_default(),
+			);
+		}
+		peers.0 = limits;
+	}
+
+	/// The limiters of sending data to the peer
+	pub(super) fn upload(&self, peer_id: PeerId) -> Vec<Arc<RateLimiter>> {
+		vec![
+			self.global.upload.clone(),
+			self.with_peer(peer_id, |limiters| limiters.upload.clone()),
+		]
+	}
+
+	/// The limiters of receiving data from the peer
+	pub(super) fn download(&self, peer_id: PeerId) -> Vec<Arc<RateLimiter>> {
+		vec![
+			self.global.download.clone(),
+			self.with_peer(peer_id, |limiters| limiters.download.clone()),
+		]
+	}
+
+	fn with_peer<T>(&self, peer_id: PeerId, f: impl FnOnce(&Limiters) -> T) -> T {
+		let mut peers = self.peers.lock().unwrap();
+		let (limits, peers) = &mut *peers;
+
+		// Kept for every peer we transfer with, so changing the limits applies to running transfers
+		f(peers.entry(peer_id).or_insert_with(|| {
+			Limiters::new(
+				limits
+					.peers
+					.get(&peer_id.to_string())
+					.copied()
+					.unwrap_or_default(),
+			)
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+
+	#[test]
+	fn limits_apply_to_known_peers() {
+		let peer_id = Keypair::generate().peer_id();
+
+		let bandwidth = Bandwidth::new(BandwidthLimits {
+			global: RateLimits {
+				upload: Some(1),
+				download: None,
+			},
+			peers: HashMap::new(),
+		});
+
+		let upload = bandwidth.upload(peer_id);
+		assert_eq!(upload[0].limit(), Some(1024));
+		assert_eq!(upload[1].limit(), None);
+
+		bandwidth.set_limits(BandwidthLimits {
+			global: RateLimits::default(),
+			peers: HashMap::from([(
+				peer_id.to_string(),
+				RateLimits {
+					upload: Some(2),
+					download: Some(3),
+				},
+			)]),
+		});
+
+		assert_eq!(upload[0].limit(), None);
+		assert_eq!(upload[1].limit(), Some(2048));
+		assert_eq!(bandwidth.download(peer_id)[1].limit(), Some(3072));
+		assert_eq!(
+			bandwidth.download(Keypair::generate().peer_id())[1].limit(),
+			None
+		);
+	}
+}
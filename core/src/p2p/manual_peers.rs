@@ -0,0 +1,239 @@
+use std::{
+	collections::HashMap,
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use sd_p2p::{Manager, PeerId};
+use serde::Serialize;
+use specta::Type;
+use thiserror::Error;
+use tokio::{net::lookup_host, sync::watch, time::timeout};
+use tracing::{debug, info, warn};
+
+use super::PeerMetadata;
+
+/// How long to wait for a connection to a manual peer to be established
+const DIAL_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often we check that we're still connected to a manual peer
+const CONNECTED_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Error)]
+pub enum ManualPeerError {
+	#[error("invalid address '{0}', it must be an IP address or hostname followed by a port, eg. '192.168.1.2:7373'")]
+	InvalidAddress(String),
+	#[error("failed to resolve '{0}'")]
+	Unresolved(String),
+	#[error("timed out connecting to the peer")]
+	Timeout,
+	#[error(transparent)]
+	Dial(#[from] sd_p2p::DialAddressError),
+}
+
+impl From<ManualPeerError> for rspc::Error {
+	fn from(e: ManualPeerError) -> Self {
+		rspc::Error::with_cause(rspc::ErrorCode::BadRequest, e.to_string(), e)
+	}
+}
+
+/// A peer that was added by its address, for when it can't be discovered on the local network
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ManualPeer {
+	pub address: String,
+	/// Known once we connected to it once
+	pub peer_id: Option<PeerId>,
+	pub connected: bool,
+	/// Why the last attempt to connect failed
+	pub error: Option<String>,
+}
+
+/// Checks the address is a host and a port, without resolving it
+pub fn validate_address(address: &str) -> Result<(), ManualPeerError> {
+	match address.rsplit_once(':') {
+		Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+		_ => Err(ManualPeerError::InvalidAddress(address.to_string())),
+	}
+}
+
+/// Doubles the delay between attempts after every failure, up to [`MAX_BACKOFF`]
+fn next_backoff(backoff: Duration) -> Duration {
+	(backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF)
+}
+
+/// Keeps a connection to every manual peer, reconnecting with backoff when it's lost
+pub struct ManualPeers {
+	manager: Arc<Manager<PeerMetadata>>,
+	peers: Mutex<HashMap<String, (ManualPeer, watch::Sender<()>)>>,
+	/// Called with the id of a peer every time we connect to it
+	on_connect: Arc<dyn Fn(PeerId) + Send + Sync>,
+}
+
+impl ManualPeers {
+	pub fn new(
+		manager: Arc<Manager<PeerMetadata>>,
+		on_connect: impl Fn(PeerId) + Send + Sync + 'static,
+	) -> Arc<Self> {
+		Arc::new(Self {
+			manager,
+			peers: Mutex::new(HashMap::new()),
+			on_connect: Arc::new(on_connect),
+		})
+	}
+
+	pub fn list(&self) -> Vec<ManualPeer> {
+		let mut peers = self
+			.peers
+			.lock()
+			.unwrap()
+			.values()
+			.map(|(peer, _)| peer.clone())
+			.collect::<Vec<_>>();
+		peers.sort_by(|a, b| a.address.cmp(&b.address));
+		peers
+	}
+
+	pub fn add(self: &Arc<Self>, address: String) {
+		let (stop_tx, stop_rx) = watch::channel(());
+
+		{
+			let mut peers = self.peers.lock().unwrap();
+			if peers.contains_key(&address) {
+				return;
+			}
+
+			peers.insert(
+				address.clone(),
+				(
+					ManualPeer {
+						address: address.clone(),
+						peer_id: None,
+						connected: false,
+						error: None,
+					},
+					stop_tx,
+				),
+			);
+		}
+
+		tokio::spawn({
+			let this = self.clone();
+			async move { this.keep_connected(address, stop_rx).await }
+		});
+	}
+
+	pub fn remove(&self, address: &str) {
+		// Dropping the sender stops the reconnection loop
+		self.peers.lock().unwrap().remove(address);
+	}
+
+	fn update(&self, address: &str, f: impl FnOnce(&mut ManualPeer)) {
+		if let Some((peer, _)) = self.peers.lock().unwrap().get_mut(address) {
+			f(peer);
+		}
+	}
+
+	async fn keep_connected(&self, address: String, mut stop_rx: watch::Receiver<()>) {
+		let mut backoff = Duration::ZERO;
+
+		loop {
+			let delay = match self.connect(&address).await {
+				Ok(_) => {
+					backoff = Duration::ZERO;
+					CONNECTED_CHECK_INTERVAL
+				}
+				Err(e) => {
+					backoff = next_backoff(backoff);
+					debug!("Failed to connect to manual peer '{address}', retrying in {backoff:?}: {e}");
+					self.update(&address, |peer| {
+						peer.connected = false;
+						peer.error = Some(e.to_string());
+					});
+					backoff
+				}
+			};
+
+			tokio::select! {
+				_ = tokio::time::sleep(delay) => {}
+				// Errors once the peer was removed
+				Err(_) = stop_rx.changed() => {
+					info!("Stopped connecting to manual peer '{address}'");
+					return;
+				}
+			}
+		}
+	}
+
+	/// Connects to the peer if we aren't already
+	async fn connect(&self, address: &str) -> Result<PeerId, ManualPeerError> {
+		let connected_peers = self.manager.get_connected_peers().await.unwrap_or_default();
+		let known_peer_id = self
+			.peers
+			.lock()
+			.unwrap()
+			.get(address)
+			.and_then(|(peer, _)| peer.peer_id);
+
+		if let Some(peer_id) = known_peer_id.filter(|id| connected_peers.contains(id)) {
+			return Ok(peer_id);
+		}
+
+		let addr = resolve(address).await?;
+		let peer_id = timeout(DIAL_TIMEOUT, self.manager.dial_address(addr))
+			.await
+			.map_err(|_| ManualPeerError::Timeout)??;
+
+		info!("Connected to manual peer '{address}' with id '{peer_id}'");
+		self.update(address, |peer| {
+			peer.peer_id = Some(peer_id);
+			peer.connected = true;
+			peer.error = None;
+		});
+		(self.on_connect)(peer_id);
+
+		Ok(peer_id)
+	}
+}
+
+async fn resolve(address: &str) -> Result<SocketAddr, ManualPeerError> {
+	validate_address(address)?;
+
+	lookup_host(address)
+		.await
+		.map_err(|e| {
+			warn!("Failed to resolve manual peer '{address}': {e}");
+			ManualPeerError::Unresolved(address.to_string())
+		})?
+		.next()
+		.ok_or_else(|| ManualPeerError::Unresolved(address.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn addresses() {
+		assert!(validate_address("192.168.1.2:7373").is_ok());
+		assert!(validate_address("[::1]:7373").is_ok());
+		assert!(validate_address("my-nas.local:7373").is_ok());
+
+		assert!(validate_address("192.168.1.2").is_err());
+		assert!(validate_address(":7373").is_err());
+		assert!(validate_address("my-nas.local:port").is_err());
+	}
+
+	#[test]
+	fn backoff() {
+		let mut backoff = Duration::ZERO;
+		let mut delays = vec![];
+		for _ in 0..12 {
+			backoff = next_backoff(backoff);
+			delays.push(backoff.as_secs());
+		}
+
+		assert_eq!(delays, [1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300, 300]);
+	}
+}
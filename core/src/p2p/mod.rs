@@ -1,11 +1,37 @@
 #![allow(clippy::unwrap_used, clippy::panic)] // TODO: Remove once this is fully stablised
 
+mod bandwidth;
+mod manual_peers;
 mod p2p_manager;
+mod pairing;
 mod peer_metadata;
+mod permissions;
 mod protocol;
+mod queue;
+mod remote_fs;
+mod share_links;
+mod sharing;
+mod spacedrop;
+mod sync_metrics;
+mod sync_scheduler;
+mod thumbnail;
 
+pub use bandwidth::*;
+pub use manual_peers::*;
 pub use p2p_manager::*;
+pub use pairing::*;
 pub use peer_metadata::*;
+pub use permissions::*;
 pub use protocol::*;
+pub use queue::*;
+pub use remote_fs::*;
+pub use share_links::{ShareLinkError, ShareToken, SharedObject, LINKS_DIR};
+pub use sharing::{
+	CollectionEdit, CollectionRefusal, Shares, SharingAccess, SharingError, SHARED_DIR,
+};
+pub use spacedrop::*;
+pub use sync_metrics::*;
+pub use sync_scheduler::*;
+pub use thumbnail::ThumbnailRequestError;
 
 pub(super) const SPACEDRIVE_APP_ID: &str = "spacedrive";
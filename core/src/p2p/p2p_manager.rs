@@ -1,47 +1,66 @@
 use std::{
-	borrow::Cow,
-	collections::HashMap,
-	path::PathBuf,
+	collections::{HashMap, HashSet},
+	future, io, mem,
+	path::{Path, PathBuf},
 	str::FromStr,
-	sync::{
-		atomic::{AtomicU16, Ordering},
-		Arc,
-	},
+	sync::Arc,
 	time::{Duration, Instant},
 };
 
 use chrono::Utc;
 use futures::Stream;
 use sd_p2p::{
-	spaceblock::{BlockSize, SpaceblockRequest, Transfer},
-	spacetime::SpaceTimeStream,
-	spacetunnel::{Identity, Tunnel},
+	spacetime::{SpaceTimeStream, UnicastStream},
+	spacetunnel::{Identity, RemoteIdentity, Tunnel},
 	Event, Manager, ManagerError, MetadataManager, PeerId,
 };
-use sd_prisma::prisma::node;
+use sd_prisma::prisma::{location, node, shared_collection, shared_collection_item, tag};
 use sd_sync::CRDTOperation;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use specta::Type;
 use tokio::{
-	fs::File,
-	io::{AsyncReadExt, AsyncWriteExt, BufReader},
-	sync::{broadcast, oneshot, Mutex},
-	time::sleep,
+	fs,
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	sync::{broadcast, broadcast::error::RecvError, mpsc, oneshot, Mutex},
+	time::{sleep, timeout},
 };
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
+	invalidate_query,
 	library::{Library, LibraryManager, SubscriberEvent},
-	node::{NodeConfig, NodeConfigManager, Platform},
-	p2p::{NodeInformation, OperatingSystem, SyncRequestError, SPACEDRIVE_APP_ID},
-	sync::SyncMessage,
+	node::{NodeConfig, NodeConfigManager},
+	object::preview::THUMBNAIL_CACHE_DIR_NAME,
+	p2p::{
+		queue::QUEUE_INTERVAL,
+		remote_fs, share_links,
+		sharing::{self, CollectionRequest},
+		spacedrop::{self, Direction, SpacedropState, SPACEDROP_DIR},
+		thumbnail::{
+			self, MAX_THUMBNAILS_PER_REQUEST, THUMBNAIL_REQUEST_TIMEOUT, THUMBNAIL_RETRY_INTERVAL,
+		},
+		Bandwidth, BandwidthLimits, CollectionEdit, CollectionRefusal, ManualPeers,
+		NodePermissions, OperatingSystem, PairingError, PairingPayload, PairingStatus, Pairings,
+		Permission, QueuedTransfer, RemoteFs, RemoteFsError, ShareLinkError, ShareToken,
+		SharedObject, Shares, SharingAccess, SharingError, SpacedropError, SyncCatchUpError,
+		SyncCatchUpRequest, SyncMetrics, SyncSchedule, SyncScheduler, ThumbnailRequestError,
+		TransferQueue, TransferQueueError, LINKS_DIR, SHARED_DIR, SPACEDRIVE_APP_ID,
+	},
+	sync::{compact_ops, latest_timestamps, sort_causally, SyncMessage, SyncScope},
 };
 
 use super::{Header, PeerMetadata};
 
+/// The other streams of the Spacedrops being received, by their id, with the peer sending them
+type SpacedropStreams = Mutex<HashMap<Uuid, (PeerId, mpsc::Sender<UnicastStream>)>>;
+
 /// The amount of time to wait for a Spacedrop request to be accepted or rejected before it's automatically rejected
 const SPACEDROP_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for a peer to tell us which operations it has after we sent it some
+const SYNC_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+/// The largest payload a peer may send, checked before its buffer is allocated
+const MAX_PAYLOAD_SIZE: u32 = 16 * 1024 * 1024;
 
 /// TODO: P2P event for the frontend
 #[derive(Debug, Clone, Type, Serialize)]
@@ -55,7 +74,33 @@ pub enum P2PEvent {
 		id: Uuid,
 		peer_id: PeerId,
 		name: String,
+		/// If the peer is a node paired with one of our libraries, otherwise we know nothing about who sent it
+		paired: bool,
+	},
+	/// The user has to check the code is the same on both nodes before they're paired
+	PairingRequest {
+		id: u16,
+		peer_id: PeerId,
+		name: String,
+		library_id: Uuid,
+		code: String,
+	},
+	PairingProgress {
+		id: u16,
+		status: PairingStatus,
+	},
+	/// Another user's library invites us to one of its collections, the user picks the library
+	/// to add it to
+	ShareRequest {
+		id: Uuid,
+		peer_id: PeerId,
+		library_name: String,
+		name: String,
+		access: SharingAccess,
+		include_files: bool,
 	},
+	/// A transfer of the queue was started or finished
+	TransferQueueChanged,
 	// TODO: Expire peer + connection/disconnect
 }
 
@@ -65,8 +110,20 @@ pub struct P2PManager {
 	spacedrop_pairing_reqs: Arc<Mutex<HashMap<Uuid, oneshot::Sender<Option<String>>>>>,
 	pub metadata_manager: Arc<MetadataManager<PeerMetadata>>,
 	pub spacedrop_progress: Arc<Mutex<HashMap<Uuid, broadcast::Sender<u8>>>>,
-	pairing_id: AtomicU16,
+	spacedrop_streams: Arc<SpacedropStreams>,
+	/// Where the state of the Spacedrops in progress is saved, so they can be resumed
+	spacedrop_dir: PathBuf,
+	bandwidth: Arc<Bandwidth>,
+	pub manual_peers: Arc<ManualPeers>,
+	pairing: Arc<Pairings>,
 	library_manager: Arc<LibraryManager>,
+	thumbnail_dir: PathBuf,
+	/// When we last asked the peers for each thumbnail
+	thumbnail_requests: Mutex<HashMap<String, Instant>>,
+	pub transfer_queue: Arc<TransferQueue>,
+	pub sync_scheduler: Arc<SyncScheduler>,
+	pub sync_metrics: Arc<SyncMetrics>,
+	shares: Arc<Shares>,
 }
 
 impl P2PManager {
@@ -74,15 +131,32 @@ impl P2PManager {
 		node_config: Arc<NodeConfigManager>,
 		library_manager: Arc<LibraryManager>,
 	) -> Result<Arc<Self>, ManagerError> {
-		let (config, keypair) = {
+		let (config, keypair, port, bandwidth, relay, manual_peers, sync_scheduler) = {
 			let config = node_config.get().await;
-			(Self::config_to_metadata(&config), config.keypair)
+			(
+				Self::config_to_metadata(&config),
+				config.keypair,
+				config.p2p_port.and_then(|port| u16::try_from(port).ok()),
+				Arc::new(Bandwidth::new(config.p2p_bandwidth_limits)),
+				config.p2p_relay,
+				config.p2p_manual_peers,
+				Arc::new(SyncScheduler::new(config.p2p_sync_schedule)),
+			)
 		};
+		let spacedrop_dir = node_config.data_directory().join(SPACEDROP_DIR);
+		let thumbnail_dir = node_config.data_directory().join(THUMBNAIL_CACHE_DIR_NAME);
+		let sync_metrics = Arc::new(SyncMetrics::default());
+		let transfer_queue = Arc::new(TransferQueue::load(&spacedrop_dir).await.unwrap_or_else(
+			|e| {
+				error!("Failed to load the transfer queue: {e}");
+				TransferQueue::new(&spacedrop_dir)
+			},
+		));
 
 		let metadata_manager = MetadataManager::new(config);
 
 		let (manager, mut stream) =
-			Manager::new(SPACEDRIVE_APP_ID, &keypair, metadata_manager.clone()).await?;
+			Manager::new(SPACEDRIVE_APP_ID, &keypair, metadata_manager.clone(), port).await?;
 
 		info!(
 			"Node '{}' is now online listening at addresses: {:?}",
@@ -95,12 +169,28 @@ impl P2PManager {
 
 		let spacedrop_pairing_reqs = Arc::new(Mutex::new(HashMap::new()));
 		let spacedrop_progress = Arc::new(Mutex::new(HashMap::new()));
+		let spacedrop_streams = Arc::new(Mutex::new(HashMap::new()));
+		let pairing = Arc::new(Pairings::new(tx.clone()));
+		let shares = Arc::new(Shares::new(
+			tx.clone(),
+			node_config.data_directory().join(SHARED_DIR),
+		));
+		let collection_changes = shares.subscribe_changes();
 
 		tokio::spawn({
 			let events = tx.clone();
 			let spacedrop_pairing_reqs = spacedrop_pairing_reqs.clone();
 			let spacedrop_progress = spacedrop_progress.clone();
+			let spacedrop_streams = spacedrop_streams.clone();
 			let library_manager = library_manager.clone();
+			let manager = manager.clone();
+			let spacedrop_dir = spacedrop_dir.clone();
+			let thumbnail_dir = thumbnail_dir.clone();
+			let bandwidth = bandwidth.clone();
+			let pairing = pairing.clone();
+			let shares = shares.clone();
+			let sync_scheduler = sync_scheduler.clone();
+			let sync_metrics = sync_metrics.clone();
 
 			async move {
 				let mut shutdown = false;
@@ -123,12 +213,70 @@ impl P2PManager {
 							// TODO: Don't just connect to everyone when we find them. We should only do it if we know them.
 							// TODO(Spacedrop): Disable Spacedrop for now
 							// event.dial().await;
+
+							// Catch up with the changes the peer made while we couldn't reach it,
+							// and replay the ones it missed from us
+							sync_scheduler.reconnected(event.peer_id);
+							tokio::spawn({
+								let manager = manager.clone();
+								let library_manager = library_manager.clone();
+								let sync_metrics = sync_metrics.clone();
+								let peer_id = event.peer_id;
+
+								async move {
+									Self::request_sync(
+										&manager,
+										&library_manager,
+										&sync_metrics,
+										peer_id,
+									)
+									.await;
+								}
+							});
+
+							// Get the changes to the collections the peer shares with us
+							tokio::spawn({
+								let library_manager = library_manager.clone();
+								let shares = shares.clone();
+								let peer_id = event.peer_id;
+
+								async move {
+									shares.peer_discovered(&library_manager, peer_id).await;
+								}
+							});
+
+							// Continue the Spacedrops to the peer that were interrupted
+							tokio::spawn({
+								let manager = manager.clone();
+								let spacedrop_dir = spacedrop_dir.clone();
+								let spacedrop_progress = spacedrop_progress.clone();
+								let bandwidth = bandwidth.clone();
+								let peer_id = event.peer_id;
+
+								async move {
+									Self::resume_spacedrops(
+										&manager,
+										&spacedrop_dir,
+										&spacedrop_progress,
+										&bandwidth,
+										peer_id,
+									)
+									.await;
+								}
+							});
 						}
 						Event::PeerMessage(mut event) => {
 							let events = events.clone();
 							let spacedrop_pairing_reqs = spacedrop_pairing_reqs.clone();
 							let spacedrop_progress = spacedrop_progress.clone();
+							let spacedrop_streams = spacedrop_streams.clone();
 							let library_manager = library_manager.clone();
+							let spacedrop_dir = spacedrop_dir.clone();
+							let thumbnail_dir = thumbnail_dir.clone();
+							let bandwidth = bandwidth.clone();
+							let pairing = pairing.clone();
+							let shares = shares.clone();
+							let sync_metrics = sync_metrics.clone();
 
 							tokio::spawn(async move {
 								let header = Header::from_stream(&mut event.stream).await.unwrap();
@@ -146,53 +294,101 @@ impl P2PManager {
 												return;
 											}
 										};
-										let id = Uuid::new_v4();
-										let (tx, rx) = oneshot::channel();
+										let id = req.id;
 
-										info!("spacedrop({id}): received from peer '{}' for file '{}' with file length '{}'", event.peer_id, req.name, req.size);
-
-										spacedrop_pairing_reqs.lock().await.insert(id, tx);
+										if !Self::is_allowed_to_spacedrop(
+											&library_manager,
+											event.peer_id,
+										)
+										.await
+										{
+											info!("spacedrop({id}): peer '{}' isn't allowed to Spacedrop, rejecting!", event.peer_id);
+											stream.write_all(&[0]).await.ok();
+											return;
+										}
 
 										let (process_tx, _) = broadcast::channel(100);
 										spacedrop_progress
 											.lock()
 											.await
 											.insert(id, process_tx.clone());
+										let on_progress = |percent| {
+											process_tx.send(percent).ok();
+										};
+
+										// A Spacedrop that was already accepted continues from where it stopped
+										match SpacedropState::load(&spacedrop_dir, Direction::Incoming, id).await {
+											Ok(Some(state)) if state.matches(&req, event.peer_id) => {
+												info!("spacedrop({id}): resuming from peer '{}'", event.peer_id);
+
+												match Self::receive_spacedrop(&spacedrop_dir, &spacedrop_streams, &bandwidth, event.peer_id, &mut stream, state, on_progress).await {
+													Ok(()) => info!("spacedrop({id}): complete"),
+													Err(e) => error!("spacedrop({id}): failed to resume: {e}"),
+												}
+
+												return;
+											}
+											Ok(_) => {}
+											Err(e) => warn!("spacedrop({id}): failed to load the saved state: {e}"),
+										}
+
+										let (tx, rx) = oneshot::channel();
+
+										info!(
+											"spacedrop({id}): received from peer '{}' for {} file(s) with total length '{}'",
+											event.peer_id,
+											req.files.len(),
+											req.size()
+										);
+
+										spacedrop_pairing_reqs.lock().await.insert(id, tx);
+
+										let paired = Self::is_paired_with_any_library(
+											&library_manager,
+											event.peer_id,
+										)
+										.await;
 
 										if events
 											.send(P2PEvent::SpacedropRequest {
 												id,
 												peer_id: event.peer_id,
-												name: req.name.clone(),
+												name: spacedrop::display_name(&req),
+												paired,
 											})
 											.is_err()
 										{
-											// No frontend's are active
-
-											todo!("Outright reject Spacedrop");
+											// No frontend is active so no one can approve it
+											info!(
+												"spacedrop({id}): no one to approve it, rejecting!"
+											);
+											spacedrop_pairing_reqs.lock().await.remove(&id);
+											stream.write_all(&[0]).await.ok();
+											return;
 										}
 
 										tokio::select! {
 											_ = sleep(SPACEDROP_TIMEOUT) => {
 												info!("spacedrop({id}): timeout, rejecting!");
+
+												stream.write_all(&[0]).await.ok();
 											}
 											file_path = rx => {
 												match file_path {
 													Ok(Some(file_path)) => {
 														info!("spacedrop({id}): accepted saving to '{:?}'", file_path);
 
-														stream.write_all(&[1]).await.unwrap();
+														let state = SpacedropState::incoming(&req, event.peer_id, PathBuf::from(file_path));
 
-														let f = File::create(file_path).await.unwrap();
-
-														Transfer::new(&req, |percent| {
-															process_tx.send(percent).ok();
-														}).receive(&mut stream, f).await;
-
-														info!("spacedrop({id}): complete");
+														match Self::receive_spacedrop(&spacedrop_dir, &spacedrop_streams, &bandwidth, event.peer_id, &mut stream, state, on_progress).await {
+															Ok(()) => info!("spacedrop({id}): complete"),
+															Err(e) => error!("spacedrop({id}): failed: {e}"),
+														}
 													}
 													Ok(None) => {
 														info!("spacedrop({id}): rejected");
+
+														stream.write_all(&[0]).await.ok();
 													}
 													Err(_) => {
 														info!("spacedrop({id}): error with Spacedrop pairing request receiver!");
@@ -201,12 +397,39 @@ impl P2PManager {
 											}
 										};
 									}
+									Header::SpacedropChunks(id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received Spacedrop stream from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
+										};
+
+										let tx = spacedrop_streams
+											.lock()
+											.await
+											.get(&id)
+											.filter(|(peer_id, _)| *peer_id == event.peer_id)
+											.map(|(_, tx)| tx.clone());
+
+										match tx {
+											Some(tx) => {
+												tx.send(stream).await.ok();
+											}
+											None => debug!(
+												"spacedrop({id}): received a stream from peer '{}' but the Spacedrop isn't being received",
+												event.peer_id
+											),
+										}
+									}
 									Header::Pair(library_id) => {
 										let mut stream = match event.stream {
 											SpaceTimeStream::Unicast(stream) => stream,
 											_ => {
 												// TODO: Return an error to the remote client
-												error!("Received Spacedrop request from peer '{}' but it's not a unicast stream!", event.peer_id);
+												error!("Received pairing request from peer '{}' but it's not a unicast stream!", event.peer_id);
 												return;
 											}
 										};
@@ -216,98 +439,280 @@ impl P2PManager {
 											event.peer_id
 										);
 
-										// TODO: Authentication and security stuff
+										let Some(library) =
+											library_manager.get_library(library_id).await
+										else {
+											warn!("Received pairing request from peer '{}' for unknown library '{library_id}'", event.peer_id);
+											return;
+										};
+
+										pairing.respond(&mut stream, event.peer_id, &library).await;
+									}
+									Header::Sync(library_id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received sync messages from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
+										};
+
+										let Some(library) =
+											library_manager.get_library(library_id).await
+										else {
+											warn!("error ingesting sync messages. no library by id '{library_id}' found!");
+											return;
+										};
+
+										let (mut tunnel, operations) = match Self::receive_sync(
+											stream,
+											&library,
+											&sync_metrics,
+											event.peer_id,
+										)
+										.await
+										{
+											Ok(operations) => operations,
+											Err(e) => {
+												warn!("Ignoring sync messages from peer '{}' for library '{library_id}': {e}", event.peer_id);
+												return;
+											}
+										};
+
+										debug!("ingesting sync events for library '{library_id}': {operations:?}");
+
+										let scope = match SyncScope::for_peer(
+											&library.db,
+											&event.peer_id.to_string(),
+										)
+										.await
+										{
+											Ok(scope) => scope,
+											Err(e) => {
+												error!("error loading the sync scope of peer '{}': {e}", event.peer_id);
+												return;
+											}
+										};
+
+										// The peer has the operations it sent us
+										let received = latest_timestamps(&operations);
 
-										let library =
-											library_manager.get_library(library_id).await.unwrap();
+										for op in operations {
+											let res = match scope.contains(&library.db, &op).await {
+												Ok(true) => library.sync.ingest_op(op).await,
+												Ok(false) => continue,
+												Err(e) => Err(e.into()),
+											};
+
+											res.unwrap_or_else(|err| {
+												error!(
+													"error ingesting operation for library '{}': {err:?}",
+													library.id
+												);
+											});
+										}
 
-										debug!("Waiting for nodeinfo from the remote node");
-										let remote_info = NodeInformation::from_stream(&mut stream)
+										if let Err(e) = library
+											.sync
+											.record_peer_timestamps(
+												&event.peer_id.to_string(),
+												&received,
+											)
 											.await
-											.unwrap();
-										debug!(
-											"Received nodeinfo from the remote node: {:?}",
-											remote_info
-										);
+										{
+											warn!("Failed to record the sync progress of peer '{}': {e}", event.peer_id);
+										}
 
-										debug!("Creating node in database");
-										node::Create {
-											pub_id: remote_info.pub_id.as_bytes().to_vec(),
-											name: remote_info.name,
-											platform: remote_info.platform as i32,
-											date_created: Utc::now().into(),
-											_params: vec![
-												node::identity::set(Some(
-													remote_info.public_key.to_bytes().to_vec(),
-												)),
-												node::node_peer_id::set(Some(
-													event.peer_id.to_string(),
-												)),
-											],
+										// Lets the peer know which operations we have now
+										match library.sync.timestamps().await {
+											Ok(timestamps) => {
+												match write_payload(&mut tunnel, &timestamps).await {
+													Ok(bytes) => {
+														sync_metrics.record_sent(library_id, event.peer_id, bytes);
+														sync_metrics.record_exchange(library_id, event.peer_id, None);
+													}
+													Err(e) => debug!("Failed to acknowledge sync messages from peer '{}': {e}", event.peer_id),
+												}
+											}
+											Err(e) => error!("error loading the sync timestamps of library '{library_id}': {e}"),
 										}
-										// TODO: Should this be in a transaction in case it fails?
-										.to_query(&library.db)
-										.exec()
-										.await
-										.unwrap();
 
-										let info = NodeInformation {
-											pub_id: library.config.node_id,
-											name: library.config.name,
-											public_key: library.identity.to_remote_identity(),
-											platform: Platform::current(),
+										invalidate_synced_queries(&library);
+									}
+									Header::SyncRequest(library_id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received sync request from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
 										};
 
-										debug!("Sending nodeinfo to the remote node");
-										stream.write_all(&info.to_bytes()).await.unwrap();
+										let Some(library) =
+											library_manager.get_library(library_id).await
+										else {
+											warn!("error responding to sync request. no library by id '{library_id}' found!");
+											return;
+										};
 
-										info!(
-											"Paired with '{}' for library '{library_id}'",
-											remote_info.pub_id
-										); // TODO: Use hash of identity cert here cause pub_id can be forged
+										if let Err(e) = Self::respond_sync(
+											stream,
+											&library,
+											&sync_metrics,
+											event.peer_id,
+										)
+										.await
+										{
+											error!(
+												"error responding to sync request from peer '{}' for library '{library_id}': {e}",
+												event.peer_id
+											);
+										}
 									}
-									Header::Sync(library_id) => {
+									Header::Thumbnail(library_id) => {
 										let stream = match event.stream {
 											SpaceTimeStream::Unicast(stream) => stream,
 											_ => {
 												// TODO: Return an error to the remote client
-												error!("Received Spacedrop request from peer '{}' but it's not a unicast stream!", event.peer_id);
+												error!("Received thumbnail request from peer '{}' but it's not a unicast stream!", event.peer_id);
 												return;
 											}
 										};
 
-										let mut stream = Tunnel::from_stream(stream).await.unwrap();
+										let Some(library) =
+											library_manager.get_library(library_id).await
+										else {
+											warn!("error responding to thumbnail request. no library by id '{library_id}' found!");
+											return;
+										};
+
+										if let Err(e) = Self::respond_thumbnails(
+											stream,
+											&library,
+											event.peer_id,
+											&thumbnail_dir,
+										)
+										.await
+										{
+											debug!(
+												"error responding to thumbnail request from peer '{}' for library '{library_id}': {e}",
+												event.peer_id
+											);
+										}
+									}
+									Header::RemoteFs(library_id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received remote file system request from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
+										};
 
-										let mut len = [0; 4];
-										stream
-											.read_exact(&mut len)
-											.await
-											.map_err(SyncRequestError::PayloadLenIoError)
-											.unwrap();
-										let len = u32::from_le_bytes(len);
+										let Some(library) =
+											library_manager.get_library(library_id).await
+										else {
+											warn!("error responding to remote file system request. no library by id '{library_id}' found!");
+											return;
+										};
 
-										let mut buf = vec![0; len as usize]; // TODO: Designed for easily being able to be DOS the current Node
-										stream.read_exact(&mut buf).await.unwrap();
+										// Only the nodes paired with the library can browse its locations
+										let res = match Self::accept_tunnel(
+											stream,
+											&library,
+											event.peer_id,
+											Permission::ReadFiles,
+										)
+										.await
+										{
+											Ok(mut tunnel) => {
+												remote_fs::respond(
+													&mut tunnel,
+													&library,
+													event.peer_id,
+													bandwidth.upload(event.peer_id),
+												)
+												.await
+											}
+											Err(e) => Err(e),
+										};
 
-										let mut buf: &[u8] = &buf;
-										let operations: Vec<CRDTOperation> =
-											rmp_serde::from_read(&mut buf).unwrap();
+										if let Err(e) = res {
+											debug!(
+												"error responding to remote file system request from peer '{}' for library '{library_id}': {e}",
+												event.peer_id
+											);
+										}
+									}
+									Header::Share => {
+										let mut stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received collection invitation from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
+										};
 
-										debug!("ingesting sync events for library '{library_id}': {operations:?}");
+										if let Err(e) = shares
+											.receive(&mut stream, event.peer_id, &library_manager)
+											.await
+										{
+											warn!(
+												"error receiving collection invitation from peer '{}': {e}",
+												event.peer_id
+											);
+										}
+									}
+									Header::Collection(collection_pub_id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received shared collection request from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
+										};
 
-										let Some(library) = library_manager.get_library(library_id).await else {
-											warn!("error ingesting sync messages. no library by id '{library_id}' found!");
-											return;
+										if let Err(e) = shares
+											.respond(
+												stream,
+												&library_manager,
+												collection_pub_id,
+												bandwidth.upload(event.peer_id),
+											)
+											.await
+										{
+											debug!(
+												"error responding to request from peer '{}' for collection '{collection_pub_id}': {e}",
+												event.peer_id
+											);
+										}
+									}
+									Header::ShareLink(library_id) => {
+										let stream = match event.stream {
+											SpaceTimeStream::Unicast(stream) => stream,
+											_ => {
+												// TODO: Return an error to the remote client
+												error!("Received share link from peer '{}' but it's not a unicast stream!", event.peer_id);
+												return;
+											}
 										};
 
-										for op in operations {
-											library.sync.ingest_op(op).await.unwrap_or_else(
-												|err| {
-													error!(
-														"error ingesting operation for library '{}': {err:?}",
-														library.id
-													);
-												},
+										if let Err(e) = share_links::respond(
+											stream,
+											&library_manager,
+											library_id,
+											&bandwidth.upload(event.peer_id),
+										)
+										.await
+										{
+											debug!(
+												"error responding to share link from peer '{}' for library '{library_id}': {e}",
+												event.peer_id
 											);
 										}
 									}
@@ -334,30 +739,84 @@ impl P2PManager {
 		// https://docs.rs/ctrlc/latest/ctrlc/
 		// https://docs.rs/system_shutdown/latest/system_shutdown/
 
+		// Manual peers aren't discovered, so we catch up with them when connecting instead
+		let manual_peers = {
+			let manager_ref = manager.clone();
+			let library_manager = library_manager.clone();
+			let spacedrop_dir = spacedrop_dir.clone();
+			let spacedrop_progress = spacedrop_progress.clone();
+			let bandwidth = bandwidth.clone();
+			let sync_scheduler = sync_scheduler.clone();
+			let sync_metrics = sync_metrics.clone();
+			let shares = shares.clone();
+
+			let this = ManualPeers::new(manager.clone(), move |peer_id| {
+				sync_scheduler.reconnected(peer_id);
+				tokio::spawn({
+					let manager = manager_ref.clone();
+					let library_manager = library_manager.clone();
+					let sync_metrics = sync_metrics.clone();
+					let shares = shares.clone();
+					let spacedrop_dir = spacedrop_dir.clone();
+					let spacedrop_progress = spacedrop_progress.clone();
+					let bandwidth = bandwidth.clone();
+
+					async move {
+						Self::request_sync(&manager, &library_manager, &sync_metrics, peer_id)
+							.await;
+						shares.peer_discovered(&library_manager, peer_id).await;
+						Self::resume_spacedrops(
+							&manager,
+							&spacedrop_dir,
+							&spacedrop_progress,
+							&bandwidth,
+							peer_id,
+						)
+						.await;
+					}
+				});
+			});
+
+			for address in manual_peers {
+				this.add(address);
+			}
+
+			this
+		};
+
 		let this = Arc::new(Self {
 			events: (tx, rx),
 			manager,
 			spacedrop_pairing_reqs,
 			metadata_manager,
 			spacedrop_progress,
-			pairing_id: AtomicU16::new(0),
+			spacedrop_streams,
+			spacedrop_dir,
+			bandwidth,
+			manual_peers,
+			pairing,
 			library_manager: library_manager.clone(),
+			thumbnail_dir,
+			thumbnail_requests: Default::default(),
+			transfer_queue,
+			sync_scheduler,
+			sync_metrics,
+			shares,
 		});
 
+		tokio::spawn(this.clone().run_transfer_queue());
+		tokio::spawn(this.clone().run_collection_refreshes(collection_changes));
+
 		library_manager
 			.subscribe({
 				let this = this.clone();
 				move |event| match event {
-					SubscriberEvent::Load(library_id, library_identity, mut sync_rx) => {
-						let this = this.clone();
-						tokio::spawn(async move {
-							while let Ok(op) = sync_rx.recv().await {
-								let SyncMessage::Created(op) = op else { continue; };
-
-								this.broadcast_sync_events(library_id, &library_identity, vec![op])
-									.await;
-							}
-						});
+					SubscriberEvent::Load(library_id, library_identity, sync_rx) => {
+						tokio::spawn(this.clone().run_sync_batches(
+							library_id,
+							library_identity,
+							sync_rx,
+						));
 					}
 				}
 			})
@@ -374,6 +833,10 @@ impl P2PManager {
 			}
 		});
 
+		if let Err(e) = this.set_relay(relay.as_deref()).await {
+			warn!("Failed to set the P2P relay: {e}");
+		}
+
 		Ok(this)
 	}
 
@@ -393,6 +856,120 @@ impl P2PManager {
 			.update(Self::config_to_metadata(&node_config_manager.get().await));
 	}
 
+	/// Sets the relay used to reach the nodes that aren't on the local network, then catches up
+	/// with the paired ones through it
+	pub async fn set_relay(&self, relay: Option<&str>) -> Result<(), ManagerError> {
+		self.manager.set_relay(relay).await?;
+
+		if relay.is_some() {
+			tokio::spawn({
+				let manager = self.manager.clone();
+				let library_manager = self.library_manager.clone();
+				let spacedrop_dir = self.spacedrop_dir.clone();
+				let spacedrop_progress = self.spacedrop_progress.clone();
+				let bandwidth = self.bandwidth.clone();
+				let sync_metrics = self.sync_metrics.clone();
+
+				async move {
+					for peer_id in Self::undiscovered_paired_peers(&manager, &library_manager).await
+					{
+						Self::request_sync(&manager, &library_manager, &sync_metrics, peer_id)
+							.await;
+						Self::resume_spacedrops(
+							&manager,
+							&spacedrop_dir,
+							&spacedrop_progress,
+							&bandwidth,
+							peer_id,
+						)
+						.await;
+					}
+				}
+			});
+		}
+
+		Ok(())
+	}
+
+	/// The paired nodes of every library that weren't discovered on the local network
+	async fn undiscovered_paired_peers(
+		manager: &Manager<PeerMetadata>,
+		library_manager: &LibraryManager,
+	) -> HashSet<PeerId> {
+		let discovered = manager
+			.get_discovered_peers()
+			.await
+			.into_iter()
+			.map(|peer| peer.peer_id)
+			.collect::<HashSet<_>>();
+
+		let mut peers = HashSet::new();
+		for library in library_manager.get_all_libraries().await {
+			match library
+				.db
+				.node()
+				.find_many(vec![node::node_peer_id::not(None)])
+				.select(node::select!({ node_peer_id }))
+				.exec()
+				.await
+			{
+				Ok(nodes) => peers.extend(
+					nodes
+						.into_iter()
+						.filter_map(|node| PeerId::from_str(&node.node_peer_id?).ok())
+						.filter(|peer_id| {
+							*peer_id != manager.peer_id() && !discovered.contains(peer_id)
+						}),
+				),
+				Err(e) => error!(
+					"Failed to get the paired nodes of library '{}': {e}",
+					library.id
+				),
+			}
+		}
+
+		peers
+	}
+
+	async fn is_paired_with_any_library(library_manager: &LibraryManager, peer_id: PeerId) -> bool {
+		for library in library_manager.get_all_libraries().await {
+			if paired_identity(&library, peer_id).await.is_ok() {
+				return true;
+			}
+		}
+
+		false
+	}
+
+	/// Nodes that aren't paired can Spacedrop as the user approves each one, but a library can
+	/// forbid the nodes paired with it
+	async fn is_allowed_to_spacedrop(library_manager: &LibraryManager, peer_id: PeerId) -> bool {
+		for library in library_manager.get_all_libraries().await {
+			match NodePermissions::for_peer(&library.db, peer_id).await {
+				Ok(Some(permissions)) if !permissions.allows(Permission::Spacedrop) => {
+					return false
+				}
+				Ok(_) => {}
+				Err(e) => error!(
+					"Failed to get the permissions of peer '{peer_id}' in library '{}': {e}",
+					library.id
+				),
+			}
+		}
+
+		true
+	}
+
+	/// Applies a new sync schedule, including to the batches waiting to be sent
+	pub fn set_sync_schedule(&self, schedule: SyncSchedule) {
+		self.sync_scheduler.set_schedule(schedule);
+	}
+
+	/// Applies new bandwidth limits, including to the transfers that are running
+	pub fn set_bandwidth_limits(&self, limits: BandwidthLimits) {
+		self.bandwidth.set_limits(limits);
+	}
+
 	pub async fn accept_spacedrop(&self, id: Uuid, path: String) {
 		if let Some(chan) = self.spacedrop_pairing_reqs.lock().await.remove(&id) {
 			chan.send(Some(path)).unwrap();
@@ -409,175 +986,1280 @@ impl P2PManager {
 		self.events.0.subscribe()
 	}
 
+	/// Starts pairing with the peer, both users have to confirm the code shown in the
+	/// [`P2PEvent::PairingRequest`]
 	pub fn pair(&self, peer_id: PeerId, lib: Library) -> u16 {
-		let pairing_id = self.pairing_id.fetch_add(1, Ordering::SeqCst);
+		self.start_pairing(peer_id, lib, None)
+	}
+
+	/// Pairs with the node that showed the payload, it's trusted without comparing codes as the
+	/// payload contains its identity
+	pub fn pair_with_payload(&self, payload: &str, lib: Library) -> Result<u16, PairingError> {
+		let payload = PairingPayload::from_str(payload)?;
+		if payload.library_id != lib.id {
+			return Err(PairingError::WrongLibrary);
+		}
+
+		Ok(self.start_pairing(payload.peer_id, lib, Some(payload)))
+	}
+
+	/// The payload to show as a QR code for other nodes to pair with the library
+	pub fn pairing_payload(&self, lib: &Library) -> String {
+		self.pairing
+			.payload(self.manager.peer_id(), lib)
+			.to_string()
+	}
+
+	pub fn confirm_pairing(&self, id: u16, accepted: bool) {
+		self.pairing.confirm(id, accepted);
+	}
+
+	fn start_pairing(&self, peer_id: PeerId, lib: Library, payload: Option<PairingPayload>) -> u16 {
+		let pairing_id = self.pairing.next_id();
 
 		let manager = self.manager.clone();
+		let pairing = self.pairing.clone();
 		tokio::spawn(async move {
 			info!(
 				"Started pairing session '{pairing_id}' with peer '{peer_id}' for library '{}'",
 				lib.id
 			);
 
-			let mut stream = manager.stream(peer_id).await.unwrap();
-
-			let header = Header::Pair(lib.id);
-			stream.write_all(&header.to_bytes()).await.unwrap();
-
-			// TODO: Apply some security here cause this is so open to MITM
-			// TODO: Signing and a SPAKE style pin prompt
-
-			let info = NodeInformation {
-				pub_id: lib.config.node_id,
-				name: lib.config.name,
-				public_key: lib.identity.to_remote_identity(),
-				platform: Platform::current(),
+			let mut stream = match manager.stream(peer_id).await {
+				Ok(stream) => stream,
+				Err(e) => {
+					error!("Failed to open a stream to peer '{peer_id}' for pairing: {e:?}");
+					pairing.report(
+						pairing_id,
+						peer_id,
+						&lib,
+						Err(PairingError::PeerUnreachable),
+					);
+					return;
+				}
 			};
 
-			debug!("Sending nodeinfo to remote node");
-			stream.write_all(&info.to_bytes()).await.unwrap();
-
-			debug!("Waiting for nodeinfo from the remote node");
-			let remote_info = NodeInformation::from_stream(&mut stream).await.unwrap();
-			debug!("Received nodeinfo from the remote node: {:?}", remote_info);
-
-			node::Create {
-				pub_id: remote_info.pub_id.as_bytes().to_vec(),
-				name: remote_info.name,
-				platform: remote_info.platform as i32,
-				date_created: Utc::now().into(),
-				_params: vec![
-					node::identity::set(Some(remote_info.public_key.to_bytes().to_vec())),
-					node::node_peer_id::set(Some(peer_id.to_string())),
-				],
-			}
-			// TODO: Should this be in a transaction in case it fails?
-			.to_query(&lib.db)
-			.exec()
-			.await
-			.unwrap();
+			if let Err(e) = stream.write_all(&Header::Pair(lib.id).to_bytes()).await {
+				pairing.report(pairing_id, peer_id, &lib, Err(e.into()));
+				return;
+			}
 
-			info!(
-				"Paired with '{}' for library '{}'",
-				remote_info.pub_id, lib.id
-			); // TODO: Use hash of identity cert here cause pub_id can be forged
+			pairing
+				.initiate(pairing_id, &mut stream, peer_id, &lib, payload.as_ref())
+				.await;
 		});
 
 		pairing_id
 	}
 
-	pub async fn broadcast_sync_events(
-		&self,
+	/// Sends the operations made in a library to the paired nodes in batches, as soon as the
+	/// [`SyncScheduler`] allows
+	async fn run_sync_batches(
+		self: Arc<Self>,
 		library_id: Uuid,
-		_identity: &Identity,
-		event: Vec<CRDTOperation>,
+		identity: Identity,
+		mut sync_rx: broadcast::Receiver<SyncMessage>,
 	) {
-		let mut buf = match rmp_serde::to_vec_named(&event) {
-			Ok(buf) => buf,
-			Err(e) => {
-				error!("Failed to serialize sync event: {:?}", e);
-				return;
+		let mut batch = Vec::new();
+		// Set when operations were dropped, every peer then gets the ones newer than what it
+		// acknowledged with the next batch
+		let mut catch_up = false;
+		let mut since = Instant::now();
+		let mut replays = self.sync_scheduler.subscribe_replays();
+
+		loop {
+			let waiting = !batch.is_empty() || catch_up;
+			let delay = self.sync_scheduler.batch_delay();
+			let full = batch.len() >= self.sync_scheduler.max_batch_size();
+
+			if let Some(delay) = delay {
+				if waiting && (full || since.elapsed() >= delay) {
+					// Other users' libraries get the changes to the collections shared with them
+					tokio::spawn({
+						let this = self.clone();
+						let ops = batch.clone();
+						async move { this.notify_collections(library_id, &ops).await }
+					});
+
+					self.broadcast_sync_events(
+						library_id,
+						&identity,
+						mem::take(&mut batch),
+						mem::take(&mut catch_up),
+						None,
+					)
+					.await;
+					continue;
+				}
+			} else if full {
+				// Operations held until the conditions change are read from the database instead
+				batch.clear();
+				catch_up = true;
 			}
-		};
-		let mut head_buf = Header::Sync(library_id).to_bytes(); // Max Sync payload is like 4GB
-		head_buf.extend_from_slice(&(buf.len() as u32).to_le_bytes());
-		head_buf.append(&mut buf);
 
+			let remaining = delay.map(|delay| delay.saturating_sub(since.elapsed()));
+
+			tokio::select! {
+				msg = sync_rx.recv() => match msg {
+					Ok(SyncMessage::Created(op)) => {
+						if !waiting {
+							since = Instant::now();
+						}
+						batch.push(op);
+					}
+					Ok(SyncMessage::Ingested(_)) => {}
+					Err(RecvError::Lagged(_)) => {
+						if !waiting {
+							since = Instant::now();
+						}
+						catch_up = true;
+					}
+					Err(RecvError::Closed) => break,
+				},
+				_ = async {
+					match remaining {
+						Some(remaining) if waiting => sleep(remaining).await,
+						_ => future::pending().await,
+					}
+				} => {}
+				replay = replays.recv() => match replay {
+					Ok(peer_id) => {
+						self.broadcast_sync_events(library_id, &identity, vec![], true, Some(peer_id))
+							.await;
+					}
+					Err(_) => {
+						if !waiting {
+							since = Instant::now();
+						}
+						catch_up = true;
+					}
+				},
+				_ = self.sync_scheduler.changed() => {}
+			}
+		}
+	}
+
+	/// Sends operations to the paired nodes of the library, or only to `peer`. Peers that missed
+	/// some, or every peer with `catch_up`, get the ones newer than what they acknowledged instead.
+	pub async fn broadcast_sync_events(
+		&self,
+		library_id: Uuid,
+		identity: &Identity,
+		event: Vec<CRDTOperation>,
+		catch_up: bool,
+		peer: Option<PeerId>,
+	) {
 		// TODO: Determine which clients we share that library with
 
 		// TODO: Establish a connection to them
 
-		let library = self.library_manager.get_library(library_id).await.unwrap();
+		let Some(library) = self.library_manager.get_library(library_id).await else {
+			warn!("error broadcasting sync messages. no library by id '{library_id}' found!");
+			return;
+		};
+
 		// TODO: probs cache this query in memory cause this is gonna be stupid frequent
-		let target_nodes = library
+		let target_nodes = match library
 			.db
 			.node()
-			.find_many(vec![])
+			.find_many(
+				[
+					Some(node::pub_id::not(
+						library.config.node_id.as_bytes().to_vec(),
+					)),
+					Some(node::can_sync::equals(true)),
+					peer.map(|peer_id| node::node_peer_id::equals(Some(peer_id.to_string()))),
+				]
+				.into_iter()
+				.flatten()
+				.collect(),
+			)
 			.exec()
 			.await
-			.unwrap()
-			.into_iter()
-			.map(|n| {
-				PeerId::from_str(&n.node_peer_id.expect("Node was missing 'node_peer_id'!"))
-					.unwrap()
-			})
-			.collect::<Vec<_>>();
+		{
+			Ok(nodes) => nodes
+				.into_iter()
+				.filter_map(|n| {
+					Some((
+						n.id,
+						PeerId::from_str(n.node_peer_id.as_deref()?).ok()?,
+						RemoteIdentity::from_bytes(n.identity.as_deref()?).ok()?,
+					))
+				})
+				.collect::<Vec<_>>(),
+			Err(e) => {
+				error!("Failed to find the nodes of library '{library_id}': {e}");
+				return;
+			}
+		};
 
 		info!(
 			"Sending sync messages for library '{}' to nodes with peer id's '{:?}'",
-			library_id, target_nodes
+			library_id,
+			target_nodes
+				.iter()
+				.map(|(_, peer_id, _)| peer_id)
+				.collect::<Vec<_>>()
 		);
 
 		// TODO: Do in parallel
-		for peer_id in target_nodes {
-			let stream = self.manager.stream(peer_id).await.map_err(|_| ()).unwrap(); // TODO: handle providing incorrect peer id
+		for (node_id, peer_id, paired_identity) in target_nodes {
+			// Peers with a poor connection get what they missed once they're done backing off
+			if !self.sync_scheduler.can_send(peer_id) {
+				continue;
+			}
+
+			let operations = if catch_up || self.sync_scheduler.is_behind(peer_id) {
+				match library.sync.peer_timestamps(&peer_id.to_string()).await {
+					Ok(timestamps) => library.sync.get_ops_after(&timestamps).await,
+					Err(e) => Err(e),
+				}
+			} else {
+				Ok(event.clone())
+			};
+
+			// Every node only gets the operations within its sync scope, without the ones that
+			// were replaced since
+			let operations = match operations.map(compact_ops) {
+				Ok(operations) => match SyncScope::for_node(&library.db, node_id).await {
+					Ok(scope) => scope.filter(&library.db, operations).await,
+					Err(e) => Err(e),
+				},
+				Err(e) => Err(e),
+			};
+			let operations = match operations {
+				Ok(operations) if operations.is_empty() => continue,
+				Ok(operations) => operations,
+				Err(e) => {
+					error!("Failed to apply the sync scope of peer '{peer_id}': {e}");
+					continue;
+				}
+			};
+
+			let started = Instant::now();
+
+			// Nodes that are offline get these operations replayed once they're back
+			let Ok(mut stream) = self.manager.stream(peer_id).await else {
+				debug!("Peer '{peer_id}' is unreachable, it will catch up later");
+				self.sync_scheduler.mark_behind(peer_id);
+				continue;
+			};
+
+			if let Err(e) = stream.write_all(&Header::Sync(library_id).to_bytes()).await {
+				warn!("Failed to send sync header to peer '{peer_id}': {e}");
+				self.sync_scheduler.record_delivery(peer_id, None);
+				continue;
+			}
+
+			let mut tunnel = match Tunnel::initiator(stream, identity).await {
+				Ok(tunnel) => tunnel,
+				Err(e) => {
+					warn!("Failed to establish a tunnel with peer '{peer_id}': {e}");
+					self.sync_scheduler.record_delivery(peer_id, None);
+					continue;
+				}
+			};
+
+			// Someone else could be using the peer id, only the paired node can read the operations
+			if tunnel.remote_identity() != &paired_identity {
+				warn!("Peer '{peer_id}' doesn't have the identity it was paired with, not sending it sync messages");
+				continue;
+			}
+
+			match write_payload(&mut tunnel, &operations).await {
+				Ok(bytes) => self.sync_metrics.record_sent(library_id, peer_id, bytes),
+				Err(e) => {
+					warn!("Failed to send sync messages to peer '{peer_id}': {e}");
+					self.sync_scheduler.record_delivery(peer_id, None);
+					continue;
+				}
+			}
+
+			// The peer replies with the operations it has once it ingested them
+			let timestamps: HashMap<_, _> =
+				match timeout(SYNC_ACK_TIMEOUT, read_sized_payload(&mut tunnel)).await {
+					Ok(Ok((timestamps, bytes))) => {
+						self.sync_metrics
+							.record_received(library_id, peer_id, bytes);
+						timestamps
+					}
+					Ok(Err(e)) => {
+						debug!("Peer '{peer_id}' didn't acknowledge the sync messages: {e}");
+						self.sync_scheduler.record_delivery(peer_id, None);
+						continue;
+					}
+					Err(_) => {
+						debug!("Timed out waiting for peer '{peer_id}' to acknowledge the sync messages");
+						self.sync_scheduler.record_delivery(peer_id, None);
+						continue;
+					}
+				};
+
+			self.sync_scheduler
+				.record_delivery(peer_id, Some(started.elapsed()));
+			self.sync_metrics
+				.record_exchange(library_id, peer_id, Some(started.elapsed()));
+
+			match library
+				.sync
+				.record_peer_timestamps(&peer_id.to_string(), &timestamps)
+				.await
+			{
+				Ok(_) => invalidate_query!(library, "sync.status"),
+				Err(e) => warn!("Failed to record the sync progress of peer '{peer_id}': {e}"),
+			}
+		}
+	}
+
+	/// Asks the peer for the operations we're missing of every library that we share with it
+	async fn request_sync(
+		manager: &Manager<PeerMetadata>,
+		library_manager: &LibraryManager,
+		sync_metrics: &SyncMetrics,
+		peer_id: PeerId,
+	) {
+		for library in library_manager.get_all_libraries().await {
+			match Self::request_library_sync(manager, &library, sync_metrics, peer_id).await {
+				Ok(0) => {}
+				Ok(count) => {
+					info!(
+						"Caught up with {count} operations from peer '{peer_id}' for library '{}'",
+						library.id
+					);
+
+					invalidate_synced_queries(&library);
+				}
+				Err(SyncCatchUpError::NotPaired | SyncCatchUpError::Forbidden(_)) => {}
+				Err(e) => warn!(
+					"Failed to catch up with peer '{peer_id}' for library '{}': {e}",
+					library.id
+				),
+			}
+		}
+	}
+
+	async fn request_library_sync(
+		manager: &Manager<PeerMetadata>,
+		library: &Library,
+		sync_metrics: &SyncMetrics,
+		peer_id: PeerId,
+	) -> Result<usize, SyncCatchUpError> {
+		let permissions = NodePermissions::for_peer(&library.db, peer_id)
+			.await?
+			.ok_or(SyncCatchUpError::NotPaired)?;
+		permissions.require(Permission::Sync)?;
+
+		let paired_identity = paired_identity(library, peer_id).await?;
+
+		let mut stream = manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+		stream
+			.write_all(&Header::SyncRequest(library.id).to_bytes())
+			.await?;
+
+		let mut tunnel = Tunnel::initiator(stream, &library.identity).await?;
+		if tunnel.remote_identity() != &paired_identity {
+			return Err(SyncCatchUpError::IdentityMismatch);
+		}
+
+		// The peer only sends what's in our scope, but we check it again as we can't trust it
+		let scope = SyncScope::for_peer(&library.db, &peer_id.to_string()).await?;
+
+		let started = Instant::now();
+		let sent = write_payload(
+			&mut tunnel,
+			&SyncCatchUpRequest {
+				timestamps: library.sync.timestamps().await?,
+				scope: scope.clone(),
+				sent_at: Some(Utc::now()),
+			},
+		)
+		.await?;
+		sync_metrics.record_sent(library.id, peer_id, sent);
+
+		let (mut operations, received): (Vec<CRDTOperation>, _) =
+			read_sized_payload(&mut tunnel).await?;
+		sync_metrics.record_received(library.id, peer_id, received);
+		sync_metrics.record_exchange(library.id, peer_id, Some(started.elapsed()));
+
+		let received = latest_timestamps(&operations);
+		let mut count = 0;
+
+		debug!(
+			"ingesting {} sync events from peer '{peer_id}' for library '{}'",
+			operations.len(),
+			library.id
+		);
+
+		// Applied in the order they happened
+		sort_causally(&mut operations);
+		for op in operations {
+			if permissions.allows_op(&op) && scope.contains(&library.db, &op).await? {
+				library.sync.ingest_op(op).await?;
+				count += 1;
+			}
+		}
+
+		library
+			.sync
+			.record_peer_timestamps(&peer_id.to_string(), &received)
+			.await?;
+
+		Ok(count)
+	}
+
+	async fn respond_sync(
+		stream: UnicastStream,
+		library: &Library,
+		sync_metrics: &SyncMetrics,
+		peer_id: PeerId,
+	) -> Result<(), SyncCatchUpError> {
+		// Only nodes we paired with get the library's operations
+		let mut tunnel = Self::accept_tunnel(stream, library, peer_id, Permission::Sync).await?;
+
+		let (request, received): (SyncCatchUpRequest, _) = read_sized_payload(&mut tunnel).await?;
+		sync_metrics.record_received(library.id, peer_id, received);
+		if let Some(sent_at) = request.sent_at {
+			sync_metrics.record_clock(library.id, peer_id, sent_at);
+		}
+
+		// The peer tells us which operations it has to only get the ones it's missing
+		library
+			.sync
+			.record_peer_timestamps(&peer_id.to_string(), &request.timestamps)
+			.await?;
+		invalidate_query!(library, "sync.status");
+
+		// Both nodes can restrict what they sync with each other
+		let scope = SyncScope::for_peer(&library.db, &peer_id.to_string())
+			.await?
+			.intersect(request.scope);
+
+		let operations = scope
+			.filter(
+				&library.db,
+				compact_ops(library.sync.get_ops_after(&request.timestamps).await?),
+			)
+			.await?;
+
+		debug!(
+			"Sending {} sync events to peer '{peer_id}' for library '{}'",
+			operations.len(),
+			library.id
+		);
+
+		let sent = write_payload(&mut tunnel, &operations).await?;
+		sync_metrics.record_sent(library.id, peer_id, sent);
+		sync_metrics.record_exchange(library.id, peer_id, None);
+
+		Ok(())
+	}
+
+	/// Receives the operations the peer sent with a [`Header::Sync`]. The tunnel is returned to
+	/// acknowledge them once they're ingested.
+	async fn receive_sync(
+		stream: UnicastStream,
+		library: &Library,
+		sync_metrics: &SyncMetrics,
+		peer_id: PeerId,
+	) -> Result<(Tunnel, Vec<CRDTOperation>), SyncCatchUpError> {
+		// Anything discovered on the network could send us operations
+		let mut tunnel = Self::accept_tunnel(stream, library, peer_id, Permission::Sync).await?;
+		let (mut operations, received): (Vec<CRDTOperation>, _) =
+			read_sized_payload(&mut tunnel).await?;
+		sync_metrics.record_received(library.id, peer_id, received);
+
+		if let Some(permissions) = NodePermissions::for_peer(&library.db, peer_id).await? {
+			operations.retain(|op| permissions.allows_op(op));
+		}
+
+		// The peer could have sent them in any order
+		sort_causally(&mut operations);
+
+		Ok((tunnel, operations))
+	}
+
+	/// Fetches the thumbnails of files that are only on other nodes from the paired peers that have
+	/// them, so they don't show as placeholders. Thumbnails we already asked for recently are
+	/// skipped, whether or not a peer had them.
+	pub fn request_thumbnails(self: &Arc<Self>, library: Library, cas_ids: Vec<String>) {
+		let this = self.clone();
+
+		tokio::spawn(async move {
+			let cas_ids = {
+				let mut requests = this.thumbnail_requests.lock().await;
+				requests
+					.retain(|_, requested_at| requested_at.elapsed() < THUMBNAIL_RETRY_INTERVAL);
+
+				let mut new = Vec::with_capacity(cas_ids.len());
+				for cas_id in cas_ids {
+					if !requests.contains_key(&cas_id)
+						&& !library.thumbnail_exists(&cas_id).await.unwrap_or(true)
+					{
+						requests.insert(cas_id.clone(), Instant::now());
+						new.push(cas_id);
+					}
+				}
+
+				new
+			};
+
+			if cas_ids.is_empty() {
+				return;
+			}
+
+			let pending = match thumbnail::thumbnail_peers(&library, &cas_ids).await {
+				Ok(pending) => pending,
+				Err(e) => {
+					error!("Failed to find the peers with thumbnails: {e:#?}");
+					return;
+				}
+			};
+
+			let peers = pending.values().flatten().copied().collect::<HashSet<_>>();
+
+			for peer_id in peers {
+				// In the order they were requested, skipping the ones another peer already sent us
+				let mut peer_cas_ids = vec![];
+				for cas_id in &cas_ids {
+					if pending
+						.get(cas_id)
+						.map_or(false, |peers| peers.contains(&peer_id))
+						&& !library.thumbnail_exists(cas_id).await.unwrap_or(true)
+					{
+						peer_cas_ids.push(cas_id.clone());
+					}
+				}
+
+				for cas_ids in peer_cas_ids.chunks(MAX_THUMBNAILS_PER_REQUEST) {
+					match timeout(
+						THUMBNAIL_REQUEST_TIMEOUT,
+						this.request_thumbnails_from(&library, peer_id, cas_ids),
+					)
+					.await
+					{
+						Ok(Ok(())) => {}
+						Ok(Err(e)) => {
+							debug!("Failed to get thumbnails from peer '{peer_id}': {e}");
+							break;
+						}
+						Err(_) => {
+							debug!("Timed out getting thumbnails from peer '{peer_id}'");
+							break;
+						}
+					}
+				}
+			}
+		});
+	}
+
+	async fn request_thumbnails_from(
+		&self,
+		library: &Library,
+		peer_id: PeerId,
+		cas_ids: &[String],
+	) -> Result<(), ThumbnailRequestError> {
+		let paired_identity = paired_identity(library, peer_id).await?;
+
+		let mut stream = self
+			.manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+		stream
+			.write_all(&Header::Thumbnail(library.id).to_bytes())
+			.await
+			.map_err(SyncCatchUpError::from)?;
+
+		let mut tunnel = Tunnel::initiator(stream, &library.identity)
+			.await
+			.map_err(SyncCatchUpError::from)?;
+		if tunnel.remote_identity() != &paired_identity {
+			return Err(SyncCatchUpError::IdentityMismatch.into());
+		}
+
+		thumbnail::request_thumbnails(&mut tunnel, library, &self.thumbnail_dir, cas_ids).await
+	}
+
+	async fn respond_thumbnails(
+		stream: UnicastStream,
+		library: &Library,
+		peer_id: PeerId,
+		thumbnail_dir: &Path,
+	) -> Result<(), ThumbnailRequestError> {
+		// Only the nodes paired with the library get the thumbnails of its files
+		let mut tunnel =
+			Self::accept_tunnel(stream, library, peer_id, Permission::ReadFiles).await?;
+
+		thumbnail::respond_thumbnails(&mut tunnel, library, thumbnail_dir).await
+	}
+
+	/// Opens a location of another node to read its directories and files, which is only allowed
+	/// if the node syncs the location with us
+	pub async fn remote_fs(
+		&self,
+		library: &Library,
+		location_id: location::id::Type,
+	) -> Result<RemoteFs, RemoteFsError> {
+		let location = library
+			.db
+			.location()
+			.find_unique(location::id::equals(location_id))
+			.select(location::select!({ pub_id node: select { id node_peer_id } }))
+			.exec()
+			.await?
+			.ok_or(RemoteFsError::LocationNotFound(location_id))?;
+
+		let peer_id = match location.node {
+			Some(node) if node.id == library.node_local_id => {
+				return Err(RemoteFsError::LocalLocation)
+			}
+			Some(node) => node
+				.node_peer_id
+				.and_then(|peer_id| PeerId::from_str(&peer_id).ok())
+				.ok_or(SyncCatchUpError::NotPaired)?,
+			None => return Err(SyncCatchUpError::NotPaired.into()),
+		};
+
+		let paired_identity = paired_identity(library, peer_id).await?;
+
+		let mut stream = self
+			.manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+		stream
+			.write_all(&Header::RemoteFs(library.id).to_bytes())
+			.await?;
+
+		let tunnel = Tunnel::initiator(stream, &library.identity)
+			.await
+			.map_err(SyncCatchUpError::from)?;
+		if tunnel.remote_identity() != &paired_identity {
+			return Err(SyncCatchUpError::IdentityMismatch.into());
+		}
+
+		Ok(RemoteFs {
+			tunnel,
+			location_pub_id: Uuid::from_slice(&location.pub_id)
+				.map_err(|_| RemoteFsError::LocationNotFound(location_id))?,
+			limiters: self.bandwidth.download(peer_id),
+		})
+	}
+
+	/// Invites the library of another user on the peer to a tag of this library, which is shared
+	/// once the other user accepts it
+	pub async fn share_collection(
+		&self,
+		library: &Library,
+		tag_id: tag::id::Type,
+		peer_id: PeerId,
+		access: SharingAccess,
+		include_files: bool,
+	) -> Result<shared_collection::Data, SharingError> {
+		let mut stream = self
+			.manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+		stream.write_all(&Header::Share.to_bytes()).await?;
+
+		let collection =
+			sharing::invite(&mut stream, library, tag_id, peer_id, access, include_files).await?;
+
+		info!(
+			"Shared tag '{tag_id}' of library '{}' with library '{}' as collection '{}'",
+			library.id, collection.library_name, collection.name
+		);
+		invalidate_query!(library, "sharing.list");
+
+		Ok(collection)
+	}
+
+	pub fn accept_share(&self, id: Uuid, library_id: Uuid) {
+		self.shares.answer(id, Some(library_id));
+	}
+
+	pub fn reject_share(&self, id: Uuid) {
+		self.shares.answer(id, None);
+	}
+
+	/// Fetches the items of a collection shared with this library from the library that owns it,
+	/// and downloads the files it doesn't have yet if the collection includes them
+	pub async fn refresh_collection(
+		&self,
+		library: &Library,
+		collection_id: shared_collection::id::Type,
+	) -> Result<(), SharingError> {
+		let collection = find_collection(library, collection_id).await?;
+		if collection.tag_id.is_some() {
+			return Err(SharingError::OwnedCollection);
+		}
+
+		let mut tunnel = self.collection_tunnel(library, &collection).await?;
+		let snapshot = sharing::request(&mut tunnel, &CollectionRequest::Items).await?;
+
+		let collection_pub_id = Uuid::from_slice(&collection.pub_id)
+			.map_err(|_| SharingError::CollectionNotFound(collection_id))?;
+
+		for object_pub_id in sharing::apply_snapshot(library, collection_id, snapshot).await? {
+			let dir = self
+				.shares
+				.shared_dir
+				.join(collection_pub_id.to_string())
+				.join(object_pub_id.to_string());
+			if let Err(e) = fs::remove_dir_all(&dir).await {
+				if e.kind() != io::ErrorKind::NotFound {
+					warn!(
+						"Failed to remove the file of a removed item at '{}': {e}",
+						dir.display()
+					);
+				}
+			}
+		}
+
+		invalidate_query!(library, "sharing.list");
+		invalidate_query!(library, "sharing.items");
+
+		if !collection.include_files {
+			return Ok(());
+		}
+
+		let limiters = match PeerId::from_str(&collection.peer_id) {
+			Ok(peer_id) => self.bandwidth.download(peer_id),
+			Err(_) => return Err(SyncCatchUpError::NotPaired.into()),
+		};
+
+		let items = library
+			.db
+			.shared_collection_item()
+			.find_many(vec![
+				shared_collection_item::collection_id::equals(collection_id),
+				shared_collection_item::local_path::equals(None),
+			])
+			.exec()
+			.await?;
+
+		for item in items {
+			let Ok(object_pub_id) = Uuid::from_slice(&item.object_pub_id) else {
+				continue;
+			};
+
+			let path = sharing::download_path(
+				&self.shares.shared_dir,
+				collection_pub_id,
+				object_pub_id,
+				&item.name,
+				item.extension.as_deref(),
+			);
+
+			match sharing::download(&mut tunnel, object_pub_id, &path, &limiters).await {
+				Ok(()) => {}
+				// The owner doesn't have this file on the node anymore
+				Err(SharingError::Refused(CollectionRefusal::NotFound)) => continue,
+				Err(e) => return Err(e),
+			}
+
+			library
+				.db
+				.shared_collection_item()
+				.update(
+					shared_collection_item::id::equals(item.id),
+					vec![shared_collection_item::local_path::set(Some(
+						path.to_string_lossy().to_string(),
+					))],
+				)
+				.exec()
+				.await?;
+		}
+
+		invalidate_query!(library, "sharing.items");
+
+		Ok(())
+	}
+
+	/// Changes a collection shared read-write with this library, through the library that owns
+	/// it. The owner changes its tag instead.
+	pub async fn edit_collection(
+		&self,
+		library: &Library,
+		collection_id: shared_collection::id::Type,
+		edit: CollectionEdit,
+	) -> Result<(), SharingError> {
+		let collection = find_collection(library, collection_id).await?;
+		if collection.tag_id.is_some() {
+			return Err(SharingError::OwnedCollection);
+		}
+
+		let mut tunnel = self.collection_tunnel(library, &collection).await?;
+		sharing::request::<()>(&mut tunnel, &CollectionRequest::Edit(edit)).await?;
+
+		self.refresh_collection(library, collection_id).await
+	}
+
+	/// Stops sharing a collection, from either library. The other library is told if it can be
+	/// reached.
+	pub async fn unshare_collection(
+		&self,
+		library: &Library,
+		collection_id: shared_collection::id::Type,
+	) -> Result<(), SharingError> {
+		let collection = find_collection(library, collection_id).await?;
+
+		let notified = async {
+			let mut tunnel = self.collection_tunnel(library, &collection).await?;
+			sharing::request::<()>(&mut tunnel, &CollectionRequest::Unshare).await
+		}
+		.await;
+		if let Err(e) = notified {
+			warn!(
+				"Failed to tell library '{}' that collection '{}' isn't shared anymore: {e}",
+				collection.library_name, collection.name
+			);
+		}
 
-			let mut tunnel = Tunnel::from_stream(stream).await.unwrap();
+		library
+			.db
+			.shared_collection()
+			.delete(shared_collection::id::equals(collection_id))
+			.exec()
+			.await?;
 
-			tunnel.write_all(&head_buf).await.unwrap();
+		if collection.tag_id.is_none() {
+			self.shares.remove_files(&collection).await;
 		}
+
+		invalidate_query!(library, "sharing.list");
+
+		Ok(())
+	}
+
+	/// Gets the object of a share link from the node of the library that created it. The file of
+	/// the object is saved in `dir`, or in the shared directory if it's `None`.
+	pub async fn redeem_share_link(
+		&self,
+		token: &str,
+		dir: Option<PathBuf>,
+	) -> Result<SharedObject, ShareLinkError> {
+		let token = token.parse::<ShareToken>()?;
+		let dir = dir.unwrap_or_else(|| {
+			self.shares
+				.shared_dir
+				.join(LINKS_DIR)
+				.join(token.link_pub_id.to_string())
+		});
+
+		let object = share_links::redeem(
+			&self.manager,
+			&token,
+			&dir,
+			&self.bandwidth.download(token.peer_id),
+		)
+		.await?;
+
+		info!(
+			"Redeemed share link '{}' from peer '{}'",
+			token.link_pub_id, token.peer_id
+		);
+
+		Ok(object)
+	}
+
+	/// Opens a tunnel with the other library of a collection, which has to prove it's the one the
+	/// collection is shared between
+	async fn collection_tunnel(
+		&self,
+		library: &Library,
+		collection: &shared_collection::Data,
+	) -> Result<Tunnel, SharingError> {
+		let peer_id =
+			PeerId::from_str(&collection.peer_id).map_err(|_| SyncCatchUpError::NotPaired)?;
+		let identity = RemoteIdentity::from_bytes(&collection.identity)
+			.map_err(|_| SyncCatchUpError::NotPaired)?;
+		let collection_pub_id = Uuid::from_slice(&collection.pub_id)
+			.map_err(|_| SharingError::CollectionNotFound(collection.id))?;
+
+		let mut stream = self
+			.manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+		stream
+			.write_all(&Header::Collection(collection_pub_id).to_bytes())
+			.await?;
+
+		let tunnel = Tunnel::initiator(stream, &library.identity)
+			.await
+			.map_err(SyncCatchUpError::from)?;
+		if tunnel.remote_identity() != &identity {
+			return Err(SyncCatchUpError::IdentityMismatch.into());
+		}
+
+		Ok(tunnel)
+	}
+
+	/// Tells the libraries of other users that the collections the operations changed are shared
+	/// with to fetch them again
+	async fn notify_collections(&self, library_id: Uuid, ops: &[CRDTOperation]) {
+		let Some(library) = self.library_manager.get_library(library_id).await else {
+			return;
+		};
+
+		let collections = match sharing::changed_collections(&library, ops).await {
+			Ok(collections) => collections,
+			Err(e) => {
+				error!("Failed to find the shared collections of library '{library_id}': {e}");
+				return;
+			}
+		};
+
+		for collection in collections {
+			let notified = async {
+				let mut tunnel = self.collection_tunnel(&library, &collection).await?;
+				sharing::request::<()>(&mut tunnel, &CollectionRequest::Changed).await
+			}
+			.await;
+
+			if let Err(e) = notified {
+				debug!(
+					"Failed to tell library '{}' that collection '{}' changed: {e}",
+					collection.library_name, collection.name
+				);
+			}
+		}
+	}
+
+	/// Fetches the collections shared with this node again when they change, for as long as the
+	/// app runs. Changes that are missed are fetched when the peer is discovered again.
+	async fn run_collection_refreshes(
+		self: Arc<Self>,
+		mut changes: broadcast::Receiver<(Uuid, shared_collection::id::Type)>,
+	) {
+		loop {
+			match changes.recv().await {
+				Ok((library_id, collection_id)) => {
+					let Some(library) = self.library_manager.get_library(library_id).await else {
+						continue;
+					};
+
+					if let Err(e) = self.refresh_collection(&library, collection_id).await {
+						debug!("Failed to refresh shared collection '{collection_id}' of library '{library_id}': {e}");
+					}
+				}
+				Err(RecvError::Lagged(_)) => {}
+				Err(RecvError::Closed) => break,
+			}
+		}
+	}
+
+	/// Accepts a tunnel from the peer if it's the node that was paired with the library
+	async fn accept_tunnel(
+		stream: UnicastStream,
+		library: &Library,
+		peer_id: PeerId,
+		permission: Permission,
+	) -> Result<Tunnel, SyncCatchUpError> {
+		NodePermissions::for_peer(&library.db, peer_id)
+			.await?
+			.ok_or(SyncCatchUpError::NotPaired)?
+			.require(permission)?;
+
+		let paired_identity = paired_identity(library, peer_id).await?;
+
+		let tunnel = Tunnel::responder(stream, &library.identity).await?;
+		if tunnel.remote_identity() != &paired_identity {
+			return Err(SyncCatchUpError::IdentityMismatch);
+		}
+
+		Ok(tunnel)
 	}
 
 	pub async fn ping(&self) {
 		self.manager.broadcast(Header::Ping.to_bytes()).await;
 	}
 
-	// TODO: Proper error handling
 	pub async fn big_bad_spacedrop(
 		&self,
 		peer_id: PeerId,
-		path: PathBuf,
-	) -> Result<Option<Uuid>, ()> {
-		let id = Uuid::new_v4();
-		let (tx, _) = broadcast::channel(25);
-		let mut stream = self.manager.stream(peer_id).await.map_err(|_| ())?; // TODO: handle providing incorrect peer id
-
-		let file = File::open(&path).await.map_err(|_| ())?;
-		let metadata = file.metadata().await.map_err(|_| ())?;
-
-		let header = Header::Spacedrop(SpaceblockRequest {
-			name: path
-				.file_name()
-				.map(|v| v.to_string_lossy())
-				.unwrap_or(Cow::Borrowed(""))
-				.to_string(),
-			size: metadata.len(),
-			block_size: BlockSize::from_size(metadata.len()), // TODO: This should be dynamic
-		});
-		stream.write_all(&header.to_bytes()).await.map_err(|_| ())?;
+		paths: Vec<PathBuf>,
+	) -> Result<Option<Uuid>, SpacedropError> {
+		let state = SpacedropState::outgoing(Uuid::new_v4(), peer_id, paths).await?;
+
+		// Saved before anything is sent so the Spacedrop can be resumed if the connection drops
+		state.save(&self.spacedrop_dir, Direction::Outgoing).await?;
+
+		let id = state.id;
+		let accepted = Self::send_spacedrop(
+			&self.manager,
+			&self.spacedrop_dir,
+			&self.spacedrop_progress,
+			&self.bandwidth,
+			peer_id,
+			&state,
+		)
+		.await?;
 
-		debug!("Waiting for Spacedrop to be accepted from peer '{peer_id}'");
-		let mut buf = [0; 1];
-		// TODO: Add timeout so the connection is dropped if they never response
-		stream.read_exact(&mut buf).await.map_err(|_| ())?;
-		if buf[0] != 1 {
-			debug!("Spacedrop was rejected from peer '{peer_id}'");
-			return Ok(None);
-		}
+		Ok(accepted.then_some(id))
+	}
+
+	async fn send_spacedrop(
+		manager: &Manager<PeerMetadata>,
+		spacedrop_dir: &Path,
+		spacedrop_progress: &Mutex<HashMap<Uuid, broadcast::Sender<u8>>>,
+		bandwidth: &Bandwidth,
+		peer_id: PeerId,
+		state: &SpacedropState,
+	) -> Result<bool, SpacedropError> {
+		let mut stream = manager
+			.stream(peer_id)
+			.await
+			.map_err(|_| SpacedropError::PeerUnreachable)?;
 
-		debug!("Starting Spacedrop to peer '{peer_id}'");
+		stream
+			.write_all(&Header::Spacedrop(state.request()).to_bytes())
+			.await?;
+
+		let (tx, _) = broadcast::channel(25);
+		spacedrop_progress.lock().await.insert(state.id, tx.clone());
+
+		debug!(
+			"Waiting for Spacedrop '{}' to be accepted from peer '{peer_id}'",
+			state.id
+		);
 		let i = Instant::now();
 
-		let file = BufReader::new(file);
-		self.spacedrop_progress.lock().await.insert(id, tx.clone());
-		Transfer::new(
-			&match header {
-				Header::Spacedrop(req) => req,
-				_ => unreachable!(),
-			},
+		// The receiver asks for more streams when it's worth sending the files over them
+		let open_streams = |count: usize| async move {
+			let mut streams = Vec::with_capacity(count);
+			for _ in 0..count {
+				let Ok(mut stream) = manager.stream(peer_id).await else {
+					break;
+				};
+
+				if let Err(e) = stream
+					.write_all(&Header::SpacedropChunks(state.id).to_bytes())
+					.await
+				{
+					warn!(
+						"Failed to open another stream for Spacedrop '{}': {e}",
+						state.id
+					);
+					break;
+				}
+
+				streams.push(stream);
+			}
+
+			streams
+		};
+
+		// TODO: Add timeout so the connection is dropped if they never response
+		let accepted = spacedrop::send(
+			spacedrop_dir,
+			&mut stream,
+			state,
+			bandwidth.upload(peer_id),
+			open_streams,
 			|percent| {
 				tx.send(percent).ok();
 			},
 		)
-		.send(&mut stream, file)
+		.await?;
+
+		if accepted {
+			debug!(
+				"Finished Spacedrop '{}' to peer '{peer_id}' after '{:?}",
+				state.id,
+				i.elapsed()
+			);
+		} else {
+			debug!(
+				"Spacedrop '{}' was rejected from peer '{peer_id}'",
+				state.id
+			);
+		}
+
+		Ok(accepted)
+	}
+
+	/// Receives a Spacedrop over the stream of its request, along with the other streams the peer
+	/// opens to send the files over
+	async fn receive_spacedrop(
+		spacedrop_dir: &Path,
+		spacedrop_streams: &SpacedropStreams,
+		bandwidth: &Bandwidth,
+		peer_id: PeerId,
+		stream: &mut UnicastStream,
+		state: SpacedropState,
+		on_progress: impl Fn(u8),
+	) -> Result<(), SpacedropError> {
+		let id = state.id;
+		let (tx, rx) = mpsc::channel(8);
+		spacedrop_streams.lock().await.insert(id, (peer_id, tx));
+
+		let result = spacedrop::receive(
+			spacedrop_dir,
+			stream,
+			state,
+			bandwidth.download(peer_id),
+			rx,
+			on_progress,
+		)
 		.await;
 
-		debug!(
-			"Finished Spacedrop to peer '{peer_id}' after '{:?}",
-			i.elapsed()
-		);
+		spacedrop_streams.lock().await.remove(&id);
 
-		Ok(Some(id))
+		result
+	}
+
+	/// Continues the Spacedrops to a peer that didn't complete, as long as the files didn't change
+	async fn resume_spacedrops(
+		manager: &Manager<PeerMetadata>,
+		spacedrop_dir: &Path,
+		spacedrop_progress: &Mutex<HashMap<Uuid, broadcast::Sender<u8>>>,
+		bandwidth: &Bandwidth,
+		peer_id: PeerId,
+	) {
+		let states = match SpacedropState::list(spacedrop_dir, Direction::Outgoing).await {
+			Ok(states) => states,
+			Err(e) => {
+				error!("Failed to list the Spacedrops to resume: {e}");
+				return;
+			}
+		};
+
+		for state in states
+			.into_iter()
+			.filter(|state| state.peer_id == peer_id.to_string() && !state.queued)
+		{
+			let id = state.id;
+
+			let result = match SpacedropState::outgoing(
+				id,
+				peer_id,
+				state.files.iter().map(|file| file.path.clone()).collect(),
+			)
+			.await
+			{
+				Ok(current) if current.files == state.files => {
+					info!("Resuming Spacedrop '{id}' to peer '{peer_id}'");
+
+					Self::send_spacedrop(
+						manager,
+						spacedrop_dir,
+						spacedrop_progress,
+						bandwidth,
+						peer_id,
+						&state,
+					)
+					.await
+					.map(|_| ())
+				}
+				Ok(_) => Err(SpacedropError::FilesChanged),
+				Err(e) => Err(e),
+			};
+
+			if let Err(e) = result {
+				warn!("Failed to resume Spacedrop '{id}' to peer '{peer_id}': {e}");
+
+				// Files that changed or can't be read anymore can't be resumed
+				if matches!(e, SpacedropError::FilesChanged | SpacedropError::FileIO(_)) {
+					SpacedropState::remove(spacedrop_dir, Direction::Outgoing, id)
+						.await
+						.ok();
+				}
+			}
+		}
+	}
+
+	/// Starts the queued transfers as they become due, for as long as the app runs
+	async fn run_transfer_queue(self: Arc<Self>) {
+		loop {
+			match self.transfer_queue.start_due().await {
+				Ok(transfers) if transfers.is_empty() => {}
+				Ok(transfers) => {
+					self.events.0.send(P2PEvent::TransferQueueChanged).ok();
+
+					for transfer in transfers {
+						tokio::spawn({
+							let this = self.clone();
+							async move { this.send_queued(transfer).await }
+						});
+					}
+				}
+				Err(e) => error!("Failed to start the queued transfers: {e}"),
+			}
+
+			tokio::select! {
+				_ = sleep(QUEUE_INTERVAL) => {}
+				_ = self.transfer_queue.notify.notified() => {}
+			}
+		}
+	}
+
+	async fn send_queued(&self, transfer: QueuedTransfer) {
+		let QueuedTransfer {
+			id,
+			peer_id,
+			paths,
+			attempts,
+			..
+		} = transfer;
+
+		info!("Sending queued transfer '{id}' to peer '{peer_id}', attempt {attempts}");
+
+		let result: Result<bool, SpacedropError> = async {
+			// Every attempt uses the same id, so the peer resumes it if the files didn't change
+			let mut state = SpacedropState::outgoing(id, peer_id, paths).await?;
+			state.queued = true;
+			state.save(&self.spacedrop_dir, Direction::Outgoing).await?;
+
+			Self::send_spacedrop(
+				&self.manager,
+				&self.spacedrop_dir,
+				&self.spacedrop_progress,
+				&self.bandwidth,
+				peer_id,
+				&state,
+			)
+			.await
+		}
+		.await;
+
+		if let Err(e) = &result {
+			warn!("Failed to send queued transfer '{id}' to peer '{peer_id}': {e}");
+		}
+
+		if let Err(e) = self
+			.transfer_queue
+			.finish(id, result.map_err(|e| e.to_string()))
+			.await
+		{
+			error!("Failed to update queued transfer '{id}': {e}");
+		}
+
+		self.events.0.send(P2PEvent::TransferQueueChanged).ok();
+
+		// The next transfer to the peer can start
+		self.transfer_queue.notify.notify_one();
+	}
+
+	/// Removes a transfer from the queue, with what was saved to resume it
+	pub async fn remove_queued_transfer(&self, id: Uuid) -> Result<(), TransferQueueError> {
+		self.transfer_queue.remove(id).await?;
+
+		if let Err(e) = SpacedropState::remove(&self.spacedrop_dir, Direction::Outgoing, id).await {
+			warn!("Failed to remove the state of queued transfer '{id}': {e}");
+		}
+
+		Ok(())
 	}
 
 	pub async fn spacedrop_progress(&self, id: Uuid) -> Option<impl Stream<Item = u8>> {
@@ -595,3 +2277,92 @@ impl P2PManager {
 		self.manager.shutdown().await;
 	}
 }
+
+/// The identity the peer was paired with, which it has to prove it holds to sync the library
+async fn paired_identity(
+	library: &Library,
+	peer_id: PeerId,
+) -> Result<RemoteIdentity, SyncCatchUpError> {
+	library
+		.db
+		.node()
+		.find_first(vec![node::node_peer_id::equals(Some(peer_id.to_string()))])
+		.select(node::select!({ identity }))
+		.exec()
+		.await?
+		.and_then(|node| RemoteIdentity::from_bytes(&node.identity?).ok())
+		.ok_or(SyncCatchUpError::NotPaired)
+}
+
+async fn find_collection(
+	library: &Library,
+	collection_id: shared_collection::id::Type,
+) -> Result<shared_collection::Data, SharingError> {
+	library
+		.db
+		.shared_collection()
+		.find_unique(shared_collection::id::equals(collection_id))
+		.exec()
+		.await?
+		.ok_or(SharingError::CollectionNotFound(collection_id))
+}
+
+/// Payloads are prefixed by their length, the max is like 4GB. Returns the number of bytes
+/// written.
+pub(super) async fn write_payload(
+	stream: &mut (impl AsyncWrite + Unpin),
+	payload: &impl Serialize,
+) -> Result<usize, SyncCatchUpError> {
+	let buf = rmp_serde::to_vec_named(payload)?;
+
+	stream.write_all(&(buf.len() as u32).to_le_bytes()).await?;
+	stream.write_all(&buf).await?;
+
+	Ok(buf.len() + 4)
+}
+
+pub(super) async fn read_payload<T: DeserializeOwned>(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> Result<T, SyncCatchUpError> {
+	read_sized_payload(stream).await.map(|(payload, _)| payload)
+}
+
+/// Like [`read_payload`], with the number of bytes read
+pub(super) async fn read_sized_payload<T: DeserializeOwned>(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> Result<(T, usize), SyncCatchUpError> {
+	let len = stream.read_u32_le().await?;
+	if len > MAX_PAYLOAD_SIZE {
+		return Err(SyncCatchUpError::PayloadTooLarge(len));
+	}
+
+	let mut buf = vec![0; len as usize];
+	stream.read_exact(&mut buf).await?;
+
+	Ok((rmp_serde::from_slice(&buf)?, buf.len() + 4))
+}
+
+/// Ingested operations can change anything shown by the frontend
+fn invalidate_synced_queries(library: &Library) {
+	invalidate_query!(library, "search.paths");
+	invalidate_query!(library, "search.objects");
+	invalidate_query!(library, "tags.list");
+	invalidate_query!(library, "tags.getForObject");
+	invalidate_query!(library, "locations.list");
+	invalidate_query!(library, "sync.status");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn oversized_payloads_are_rejected_before_reading() {
+		let mut stream = std::io::Cursor::new(u32::MAX.to_le_bytes().to_vec());
+
+		assert!(matches!(
+			read_sized_payload::<Vec<u8>>(&mut stream).await,
+			Err(SyncCatchUpError::PayloadTooLarge(u32::MAX))
+		));
+	}
+}
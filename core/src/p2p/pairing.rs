@@ -0,0 +1,622 @@
+use std::{
+	collections::HashMap,
+	fmt,
+	str::FromStr,
+	sync::{
+		atomic::{AtomicU16, Ordering},
+		Mutex,
+	},
+	time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use sd_p2p::PeerId;
+use sd_prisma::prisma::node;
+use serde::Serialize;
+use serde_json::json;
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	sync::{broadcast, oneshot},
+	time::timeout,
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+	library::{
+		activity::{self, ActivityKind},
+		Library,
+	},
+	node::Platform,
+};
+
+use super::{NodeInformation, NodeInformationError, P2PEvent};
+
+const PAYLOAD_PREFIX: &str = "spacedrive-pair:";
+/// How long a pairing payload can be scanned for after it was shown
+const PAYLOAD_EXPIRY: Duration = Duration::from_secs(5 * 60);
+/// How long to wait for the user to confirm the code before pairing is rejected
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Error)]
+pub enum PairingError {
+	#[error("the pairing payload is invalid")]
+	InvalidPayload,
+	#[error("the pairing payload is for another library")]
+	WrongLibrary,
+	#[error("the pairing payload expired or was already used")]
+	ExpiredPayload,
+	#[error("the peer's identity doesn't match the one of the pairing payload")]
+	IdentityMismatch,
+	#[error(
+		"the peer changed the value it committed to, the connection may have been tampered with"
+	)]
+	CommitmentMismatch,
+	#[error("pairing was rejected")]
+	Rejected,
+	#[error("timed out waiting for the pairing code to be confirmed")]
+	Timeout,
+	#[error("failed to open a stream to the peer")]
+	PeerUnreachable,
+	#[error("library not found")]
+	LibraryNotFound,
+	#[error("io error during pairing: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("error reading the peer's node information: {0}")]
+	NodeInformation(#[from] NodeInformationError),
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+}
+
+impl From<PairingError> for rspc::Error {
+	fn from(e: PairingError) -> Self {
+		let code = match e {
+			PairingError::InvalidPayload
+			| PairingError::WrongLibrary
+			| PairingError::ExpiredPayload => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Shared out of band, shown as a QR code, so the node scanning it can pair without comparing codes.
+///
+/// It contains the identity of the library on the node that shows it, so it can't be impersonated,
+/// and a one-time secret that proves the node which scanned it was in front of the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingPayload {
+	pub peer_id: PeerId,
+	pub library_id: Uuid,
+	pub identity: [u8; 32],
+	pub secret: [u8; 32],
+}
+
+impl fmt::Display for PairingPayload {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut bytes = Vec::with_capacity(80);
+		bytes.extend_from_slice(self.library_id.as_bytes());
+		bytes.extend_from_slice(&self.identity);
+		bytes.extend_from_slice(&self.secret);
+
+		write!(f, "{PAYLOAD_PREFIX}{}:{}", self.peer_id, hex::encode(bytes))
+	}
+}
+
+impl FromStr for PairingPayload {
+	type Err = PairingError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (peer_id, bytes) = s
+			.trim()
+			.strip_prefix(PAYLOAD_PREFIX)
+			.and_then(|s| s.split_once(':'))
+			.ok_or(PairingError::InvalidPayload)?;
+
+		let bytes = hex::decode(bytes).map_err(|_| PairingError::InvalidPayload)?;
+		if bytes.len() != 80 {
+			return Err(PairingError::InvalidPayload);
+		}
+
+		Ok(Self {
+			peer_id: PeerId::from_str(peer_id).map_err(|_| PairingError::InvalidPayload)?,
+			library_id: Uuid::from_slice(&bytes[..16]).map_err(|_| PairingError::InvalidPayload)?,
+			identity: bytes[16..48].try_into().expect("checked the length above"),
+			secret: bytes[48..].try_into().expect("checked the length above"),
+		})
+	}
+}
+
+/// The result of the key exchange, the nodes are only paired once both users confirmed the code
+#[derive(Debug)]
+pub(super) struct Handshake {
+	pub remote: NodeInformation,
+	/// The same on both nodes unless someone is in the middle of the connection
+	pub code: String,
+	/// The peer proved it scanned our pairing payload, so the code doesn't need to be compared
+	pub verified: bool,
+}
+
+pub(super) fn random_secret() -> [u8; 32] {
+	let mut secret = [0u8; 32];
+	secret[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+	secret[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+	secret
+}
+
+fn commitment(nonce: &[u8; 32]) -> [u8; 32] {
+	*blake3::hash(nonce).as_bytes()
+}
+
+fn payload_proof(secret: &[u8; 32], library_id: Uuid, initiator: &[u8; 32]) -> [u8; 32] {
+	let mut input = Vec::with_capacity(48);
+	input.extend_from_slice(initiator);
+	input.extend_from_slice(library_id.as_bytes());
+
+	*blake3::keyed_hash(secret, &input).as_bytes()
+}
+
+/// The 6 digits the users compare, derived from both identities and both nonces.
+///
+/// The initiator commits to its nonce before it learns the responder's one, so someone in the
+/// middle can't pick values that make both codes match.
+fn pairing_code(
+	library_id: Uuid,
+	initiator: &[u8; 32],
+	responder: &[u8; 32],
+	initiator_nonce: &[u8; 32],
+	responder_nonce: &[u8; 32],
+) -> String {
+	let mut hasher = blake3::Hasher::new();
+	hasher.update(library_id.as_bytes());
+	hasher.update(initiator);
+	hasher.update(responder);
+	hasher.update(initiator_nonce);
+	hasher.update(responder_nonce);
+
+	let hash = hasher.finalize();
+	let n = u32::from_le_bytes(hash.as_bytes()[..4].try_into().expect("hash is 32 bytes"));
+
+	format!("{:06}", n % 1_000_000)
+}
+
+async fn read_array(stream: &mut (impl AsyncRead + Unpin)) -> Result<[u8; 32], PairingError> {
+	let mut buf = [0u8; 32];
+	stream.read_exact(&mut buf).await?;
+	Ok(buf)
+}
+
+/// Run by the node that started pairing, after sending the [`Header::Pair`](super::Header::Pair)
+pub(super) async fn initiate(
+	stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+	local: &NodeInformation,
+	library_id: Uuid,
+	payload: Option<&PairingPayload>,
+) -> Result<Handshake, PairingError> {
+	let local_key = local.public_key.to_bytes();
+	let nonce = random_secret();
+
+	stream.write_all(&local.to_bytes()).await?;
+	stream.write_all(&commitment(&nonce)).await?;
+	match payload {
+		Some(payload) => {
+			stream.write_all(&[1]).await?;
+			stream
+				.write_all(&payload_proof(&payload.secret, library_id, &local_key))
+				.await?;
+		}
+		None => stream.write_all(&[0]).await?,
+	}
+
+	let remote = NodeInformation::from_stream(stream).await?;
+	let remote_key = remote.public_key.to_bytes();
+	if payload.map_or(false, |payload| payload.identity != remote_key) {
+		return Err(PairingError::IdentityMismatch);
+	}
+
+	let remote_nonce = read_array(stream).await?;
+	stream.write_all(&nonce).await?;
+
+	Ok(Handshake {
+		code: pairing_code(library_id, &local_key, &remote_key, &nonce, &remote_nonce),
+		remote,
+		verified: payload.is_some(),
+	})
+}
+
+/// Run by the node that received the [`Header::Pair`](super::Header::Pair), `secret` is the one of
+/// the pairing payload it's currently showing
+pub(super) async fn respond(
+	stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+	local: &NodeInformation,
+	library_id: Uuid,
+	secret: Option<&[u8; 32]>,
+) -> Result<Handshake, PairingError> {
+	let local_key = local.public_key.to_bytes();
+
+	let remote = NodeInformation::from_stream(stream).await?;
+	let remote_key = remote.public_key.to_bytes();
+	let remote_commitment = read_array(stream).await?;
+
+	let verified = match stream.read_u8().await? {
+		0 => false,
+		_ => {
+			let proof = read_array(stream).await?;
+			match secret {
+				Some(secret) if payload_proof(secret, library_id, &remote_key) == proof => true,
+				_ => return Err(PairingError::ExpiredPayload),
+			}
+		}
+	};
+
+	let nonce = random_secret();
+	stream.write_all(&local.to_bytes()).await?;
+	stream.write_all(&nonce).await?;
+
+	let remote_nonce = read_array(stream).await?;
+	if commitment(&remote_nonce) != remote_commitment {
+		return Err(PairingError::CommitmentMismatch);
+	}
+
+	Ok(Handshake {
+		code: pairing_code(library_id, &remote_key, &local_key, &remote_nonce, &nonce),
+		remote,
+		verified,
+	})
+}
+
+/// Tells the peer whether the user confirmed the code and returns if both of them did
+pub(super) async fn confirm(
+	stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+	accepted: bool,
+) -> Result<(), PairingError> {
+	stream.write_all(&[accepted as u8]).await?;
+
+	match (accepted, stream.read_u8().await?) {
+		(true, 1) => Ok(()),
+		_ => Err(PairingError::Rejected),
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type")]
+pub enum PairingStatus {
+	Paired,
+	Rejected,
+	Failed { error: String },
+}
+
+/// The pairing sessions of the node, in both directions
+pub struct Pairings {
+	events: broadcast::Sender<P2PEvent>,
+	next_id: AtomicU16,
+	/// Waiting for the user to confirm the code
+	confirmations: Mutex<HashMap<u16, oneshot::Sender<bool>>>,
+	/// The secret of the pairing payload shown for each library
+	secrets: Mutex<HashMap<Uuid, ([u8; 32], Instant)>>,
+}
+
+impl Pairings {
+	pub fn new(events: broadcast::Sender<P2PEvent>) -> Self {
+		Self {
+			events,
+			next_id: AtomicU16::new(0),
+			confirmations: Mutex::new(HashMap::new()),
+			secrets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub fn next_id(&self) -> u16 {
+		self.next_id.fetch_add(1, Ordering::SeqCst)
+	}
+
+	/// Creates a new payload for the library, the previous one can't be used anymore
+	pub fn payload(&self, peer_id: PeerId, library: &Library) -> PairingPayload {
+		let secret = random_secret();
+		self.secrets
+			.lock()
+			.unwrap()
+			.insert(library.id, (secret, Instant::now()));
+
+		PairingPayload {
+			peer_id,
+			library_id: library.id,
+			identity: library.identity.to_remote_identity().to_bytes(),
+			secret,
+		}
+	}
+
+	pub fn confirm(&self, id: u16, accepted: bool) {
+		if let Some(tx) = self.confirmations.lock().unwrap().remove(&id) {
+			tx.send(accepted).ok();
+		}
+	}
+
+	fn secret(&self, library_id: Uuid) -> Option<[u8; 32]> {
+		self.secrets
+			.lock()
+			.unwrap()
+			.get(&library_id)
+			.filter(|(_, created)| created.elapsed() < PAYLOAD_EXPIRY)
+			.map(|(secret, _)| *secret)
+	}
+
+	/// Pairs with the peer that we opened the `stream` to
+	pub(super) async fn initiate(
+		&self,
+		id: u16,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		peer_id: PeerId,
+		library: &Library,
+		payload: Option<&PairingPayload>,
+	) {
+		let result = async {
+			let handshake =
+				initiate(stream, &node_information(library), library.id, payload).await?;
+			self.finish(id, stream, peer_id, library, handshake).await
+		}
+		.await;
+
+		self.report(id, peer_id, library, result);
+	}
+
+	/// Pairs with the peer that opened the `stream` to us
+	pub(super) async fn respond(
+		&self,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		peer_id: PeerId,
+		library: &Library,
+	) {
+		let id = self.next_id();
+		let result = async {
+			let secret = self.secret(library.id);
+			let handshake = respond(
+				stream,
+				&node_information(library),
+				library.id,
+				secret.as_ref(),
+			)
+			.await?;
+
+			// The payload can only be used once
+			if handshake.verified {
+				self.secrets.lock().unwrap().remove(&library.id);
+			}
+
+			self.finish(id, stream, peer_id, library, handshake).await
+		}
+		.await;
+
+		self.report(id, peer_id, library, result);
+	}
+
+	async fn finish(
+		&self,
+		id: u16,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		peer_id: PeerId,
+		library: &Library,
+		handshake: Handshake,
+	) -> Result<(), PairingError> {
+		let accepted = if handshake.verified {
+			true
+		} else {
+			let (tx, rx) = oneshot::channel();
+			self.confirmations.lock().unwrap().insert(id, tx);
+
+			self.events
+				.send(P2PEvent::PairingRequest {
+					id,
+					peer_id,
+					name: handshake.remote.name.clone(),
+					library_id: library.id,
+					code: handshake.code.clone(),
+				})
+				.ok();
+
+			let accepted = timeout(CONFIRM_TIMEOUT, rx).await;
+			self.confirmations.lock().unwrap().remove(&id);
+
+			match accepted {
+				Ok(accepted) => accepted.unwrap_or(false),
+				Err(_) => {
+					stream.write_all(&[0]).await.ok();
+					return Err(PairingError::Timeout);
+				}
+			}
+		};
+
+		confirm(stream, accepted).await?;
+		save_node(library, &handshake.remote, peer_id).await
+	}
+
+	pub(super) fn report(
+		&self,
+		id: u16,
+		peer_id: PeerId,
+		library: &Library,
+		result: Result<(), PairingError>,
+	) {
+		let status = match result {
+			Ok(()) => {
+				info!("Paired with '{peer_id}' for library '{}'", library.id);
+				PairingStatus::Paired
+			}
+			Err(PairingError::Rejected) => {
+				info!(
+					"Pairing with '{peer_id}' for library '{}' was rejected",
+					library.id
+				);
+				PairingStatus::Rejected
+			}
+			Err(e) => {
+				error!(
+					"Failed to pair with '{peer_id}' for library '{}': {e}",
+					library.id
+				);
+				PairingStatus::Failed {
+					error: e.to_string(),
+				}
+			}
+		};
+
+		self.events
+			.send(P2PEvent::PairingProgress { id, status })
+			.ok();
+	}
+}
+
+fn node_information(library: &Library) -> NodeInformation {
+	NodeInformation {
+		pub_id: library.config.node_id,
+		name: library.config.name.clone(),
+		public_key: library.identity.to_remote_identity(),
+		platform: Platform::current(),
+	}
+}
+
+/// Trusts the node from now on, pairing again updates its record
+async fn save_node(
+	library: &Library,
+	remote: &NodeInformation,
+	peer_id: PeerId,
+) -> Result<(), PairingError> {
+	let pub_id = remote.pub_id.as_bytes().to_vec();
+	let params = vec![
+		node::identity::set(Some(remote.public_key.to_bytes().to_vec())),
+		node::node_peer_id::set(Some(peer_id.to_string())),
+	];
+
+	library
+		.db
+		.node()
+		.upsert(
+			node::pub_id::equals(pub_id.clone()),
+			node::create(
+				pub_id,
+				remote.name.clone(),
+				remote.platform as i32,
+				Utc::now().into(),
+				params.clone(),
+			),
+			[
+				vec![
+					node::name::set(remote.name.clone()),
+					node::platform::set(remote.platform as i32),
+				],
+				params,
+			]
+			.concat(),
+		)
+		.exec()
+		.await?;
+
+	activity::record(
+		library,
+		ActivityKind::DevicePaired,
+		json!({ "name": &remote.name, "platform": remote.platform as i32 }),
+	)
+	.await;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::{spacetunnel::Identity, Keypair};
+
+	fn node_info(identity: &Identity) -> NodeInformation {
+		NodeInformation {
+			pub_id: Uuid::new_v4(),
+			name: "Node".into(),
+			public_key: identity.to_remote_identity(),
+			platform: Platform::current(),
+		}
+	}
+
+	#[tokio::test]
+	async fn both_nodes_derive_the_same_code() {
+		let (initiator, responder) = (Identity::new(), Identity::new());
+		let library_id = Uuid::new_v4();
+		let (mut a, mut b) = tokio::io::duplex(1024);
+
+		let (a, b) = tokio::join!(
+			initiate(&mut a, &node_info(&initiator), library_id, None),
+			respond(&mut b, &node_info(&responder), library_id, None),
+		);
+		let (a, b) = (a.unwrap(), b.unwrap());
+
+		assert_eq!(a.code, b.code);
+		assert_eq!(a.code.len(), 6);
+		assert!(!a.verified && !b.verified);
+		assert_eq!(a.remote.public_key, responder.to_remote_identity());
+		assert_eq!(b.remote.public_key, initiator.to_remote_identity());
+	}
+
+	#[tokio::test]
+	async fn payload_skips_the_code() {
+		let (initiator, responder) = (Identity::new(), Identity::new());
+		let library_id = Uuid::new_v4();
+		let payload = PairingPayload {
+			peer_id: Keypair::generate().peer_id(),
+			library_id,
+			identity: responder.to_remote_identity().to_bytes(),
+			secret: random_secret(),
+		};
+		assert_eq!(
+			payload.to_string().parse::<PairingPayload>().unwrap(),
+			payload
+		);
+
+		let (mut a, mut b) = tokio::io::duplex(1024);
+		let (a, b) = tokio::join!(
+			initiate(&mut a, &node_info(&initiator), library_id, Some(&payload)),
+			respond(
+				&mut b,
+				&node_info(&responder),
+				library_id,
+				Some(&payload.secret)
+			),
+		);
+		assert!(a.unwrap().verified && b.unwrap().verified);
+
+		// Someone who didn't scan the payload can't forge the proof
+		let (mut a, mut b) = tokio::io::duplex(1024);
+		let (_, b) = tokio::join!(
+			initiate(&mut a, &node_info(&initiator), library_id, Some(&payload)),
+			// Moved in so the stream is closed when the responder gives up
+			async move {
+				respond(
+					&mut b,
+					&node_info(&responder),
+					library_id,
+					Some(&random_secret()),
+				)
+				.await
+			},
+		);
+		assert!(matches!(b, Err(PairingError::ExpiredPayload)));
+	}
+
+	#[test]
+	fn code_depends_on_every_input() {
+		let (a, b, c) = (random_secret(), random_secret(), random_secret());
+		let library_id = Uuid::new_v4();
+
+		let code = pairing_code(library_id, &a, &b, &a, &b);
+		assert_eq!(code, pairing_code(library_id, &a, &b, &a, &b));
+		assert!(code.chars().all(|c| c.is_ascii_digit()));
+		assert_eq!(commitment(&a), commitment(&a));
+		assert_ne!(commitment(&a), commitment(&b));
+
+		// Could collide one time in a million
+		assert_ne!(code, pairing_code(Uuid::new_v4(), &a, &b, &a, &b));
+		assert_ne!(code, pairing_code(library_id, &c, &b, &a, &b));
+		assert_ne!(code, pairing_code(library_id, &a, &b, &c, &b));
+	}
+}
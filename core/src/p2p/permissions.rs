@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::prisma::{node, PrismaClient};
+
+use sd_p2p::PeerId;
+use sd_sync::{CRDTOperation, CRDTOperationType, RelationOperationData, SharedOperationData};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use super::SyncCatchUpError;
+
+/// Something a paired node can be allowed to do with a library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+	Spacedrop,
+	Sync,
+	ReadFiles,
+	Delete,
+}
+
+impl fmt::Display for Permission {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Spacedrop => "send files with Spacedrop",
+			Self::Sync => "sync the library",
+			Self::ReadFiles => "read files",
+			Self::Delete => "delete records",
+		})
+	}
+}
+
+/// What a paired node is allowed to do with a library. They are only enforced by this node, the
+/// other node has its own permissions for us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct NodePermissions {
+	/// Send us files with Spacedrop, they're rejected without asking otherwise
+	pub spacedrop: bool,
+	/// Exchange the library's operations with us
+	pub sync: bool,
+	/// Browse the locations shared with it, read their files and get their thumbnails
+	pub read_files: bool,
+	/// Delete records of the library through sync
+	pub delete: bool,
+}
+
+/// Presets of [`NodePermissions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TrustLevel {
+	/// Everything is allowed, like for the user's own devices
+	Full,
+	/// Syncs and sends files but can't read or delete ours
+	Limited,
+	/// Nothing is allowed, the node stays paired
+	Blocked,
+}
+
+impl NodePermissions {
+	pub const fn for_trust_level(level: TrustLevel) -> Self {
+		match level {
+			TrustLevel::Full => Self {
+				spacedrop: true,
+				sync: true,
+				read_files: true,
+				delete: true,
+			},
+			TrustLevel::Limited => Self {
+				spacedrop: true,
+				sync: true,
+				read_files: false,
+				delete: false,
+			},
+			TrustLevel::Blocked => Self {
+				spacedrop: false,
+				sync: false,
+				read_files: false,
+				delete: false,
+			},
+		}
+	}
+
+	/// The preset these permissions match, `None` if they were customised
+	pub fn trust_level(&self) -> Option<TrustLevel> {
+		[TrustLevel::Full, TrustLevel::Limited, TrustLevel::Blocked]
+			.into_iter()
+			.find(|level| Self::for_trust_level(*level) == *self)
+	}
+
+	pub fn allows(&self, permission: Permission) -> bool {
+		match permission {
+			Permission::Spacedrop => self.spacedrop,
+			Permission::Sync => self.sync,
+			Permission::ReadFiles => self.read_files,
+			Permission::Delete => self.delete,
+		}
+	}
+
+	pub fn require(&self, permission: Permission) -> Result<(), SyncCatchUpError> {
+		if self.allows(permission) {
+			Ok(())
+		} else {
+			Err(SyncCatchUpError::Forbidden(permission))
+		}
+	}
+
+	/// Deletions are dropped from the nodes that aren't allowed to make them, their other changes
+	/// are still applied
+	pub fn allows_op(&self, op: &CRDTOperation) -> bool {
+		self.delete || !is_deletion(op)
+	}
+
+	/// The permissions of the node with this peer id, `None` if it isn't paired with the library
+	pub async fn for_peer(
+		db: &PrismaClient,
+		peer_id: PeerId,
+	) -> Result<Option<Self>, prisma_client_rust::QueryError> {
+		Ok(db
+			.node()
+			.find_first(vec![node::node_peer_id::equals(Some(peer_id.to_string()))])
+			.select(node::select!({ can_spacedrop can_sync can_read_files can_delete }))
+			.exec()
+			.await?
+			.map(|node| Self {
+				spacedrop: node.can_spacedrop,
+				sync: node.can_sync,
+				read_files: node.can_read_files,
+				delete: node.can_delete,
+			}))
+	}
+
+	pub fn params(self) -> Vec<node::SetParam> {
+		vec![
+			node::can_spacedrop::set(self.spacedrop),
+			node::can_sync::set(self.sync),
+			node::can_read_files::set(self.read_files),
+			node::can_delete::set(self.delete),
+		]
+	}
+}
+
+fn is_deletion(op: &CRDTOperation) -> bool {
+	match &op.typ {
+		CRDTOperationType::Shared(shared_op) => {
+			matches!(shared_op.data, SharedOperationData::Delete)
+		}
+		CRDTOperationType::Relation(relation_op) => {
+			matches!(relation_op.data, RelationOperationData::Delete)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn trust_levels_round_trip() {
+		for level in [TrustLevel::Full, TrustLevel::Limited, TrustLevel::Blocked] {
+			assert_eq!(
+				NodePermissions::for_trust_level(level).trust_level(),
+				Some(level)
+			);
+		}
+
+		let custom = NodePermissions {
+			read_files: true,
+			..NodePermissions::for_trust_level(TrustLevel::Blocked)
+		};
+		assert_eq!(custom.trust_level(), None);
+		assert!(custom.allows(Permission::ReadFiles));
+		assert!(matches!(
+			custom.require(Permission::Sync),
+			Err(SyncCatchUpError::Forbidden(Permission::Sync))
+		));
+	}
+}
@@ -1,16 +1,24 @@
-use std::string::FromUtf8Error;
+use std::{collections::HashMap, string::FromUtf8Error};
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
+use uhlc::NTP64;
 use uuid::Uuid;
 
 use sd_p2p::{
 	spaceblock::{SpaceblockRequest, SpacedropRequestError},
 	spacetime::SpaceTimeStream,
-	spacetunnel::{IdentityErr, RemoteIdentity},
+	spacetunnel::{IdentityErr, RemoteIdentity, TunnelError},
 };
 
-use crate::node::Platform;
+use crate::{
+	node::Platform,
+	sync::{SyncError, SyncScope},
+};
+
+use super::Permission;
 
 /// TODO
 #[derive(Debug, PartialEq, Eq)]
@@ -19,6 +27,20 @@ pub enum Header {
 	Spacedrop(SpaceblockRequest),
 	Pair(Uuid),
 	Sync(Uuid),
+	/// Asks for the sync operations of a library that are newer than the ones we have
+	SyncRequest(Uuid),
+	/// Asks for the thumbnails of files of a library that are on the peer
+	Thumbnail(Uuid),
+	/// Reads the directories and files of the locations of a library that are on the peer
+	RemoteFs(Uuid),
+	/// Invites the peer to a collection of another user's library
+	Share,
+	/// Uses a shared collection, by its pub id, with the other library it's shared between
+	Collection(Uuid),
+	/// Another stream of a Spacedrop, by its id, to send its chunks over multiple streams at once
+	SpacedropChunks(Uuid),
+	/// Redeems a share link of a library, by its id, to get the object it links to
+	ShareLink(Uuid),
 }
 
 #[derive(Debug, Error)]
@@ -31,6 +53,43 @@ pub enum SyncRequestError {
 	PayloadLenIoError(std::io::Error),
 }
 
+/// Sent after a [`Header::SyncRequest`], the peer replies with the operations that are newer than
+/// `timestamps` and within `scope`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncCatchUpRequest {
+	pub timestamps: HashMap<Uuid, NTP64>,
+	pub scope: SyncScope,
+	/// When the request was sent, to tell how far apart the clocks of the nodes are
+	#[serde(default)]
+	pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Error)]
+pub enum SyncCatchUpError {
+	#[error("failed to open a stream to the peer")]
+	PeerUnreachable,
+	#[error("failed to establish a tunnel with the peer: {0}")]
+	Tunnel(#[from] TunnelError),
+	#[error("the peer isn't paired with this library")]
+	NotPaired,
+	#[error("the peer doesn't have the identity it was paired with")]
+	IdentityMismatch,
+	#[error("the peer isn't allowed to {0}")]
+	Forbidden(Permission),
+	#[error("io error with sync payload: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("error encoding sync payload: {0}")]
+	Encode(#[from] rmp_serde::encode::Error),
+	#[error("error decoding sync payload: {0}")]
+	Decode(#[from] rmp_serde::decode::Error),
+	#[error("sync payload of {0} bytes is too large")]
+	PayloadTooLarge(u32),
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+	#[error(transparent)]
+	Sync(#[from] SyncError),
+}
+
 #[derive(Debug, Error)]
 pub enum HeaderError {
 	#[error("io error reading discriminator: {0}")]
@@ -82,6 +141,77 @@ impl Header {
 					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
 				))
 			}
+			4 => {
+				let mut uuid = [0u8; 16];
+				stream
+					.read_exact(&mut uuid)
+					.await
+					.map_err(SyncRequestError::LibraryIdIoError)?;
+
+				Ok(Self::SyncRequest(
+					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+				))
+			}
+			5 => {
+				let mut uuid = [0u8; 16];
+				stream
+					.read_exact(&mut uuid)
+					.await
+					.map_err(SyncRequestError::LibraryIdIoError)?;
+
+				Ok(Self::Thumbnail(
+					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+				))
+			}
+			6 => {
+				let mut uuid = [0u8; 16];
+				stream
+					.read_exact(&mut uuid)
+					.await
+					.map_err(SyncRequestError::LibraryIdIoError)?;
+
+				Ok(Self::RemoteFs(
+					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+				))
+			}
+			7 => Ok(Self::Share),
+			8 => {
+				let mut uuid = [0u8; 16];
+				stream
+					.read_exact(&mut uuid)
+					.await
+					.map_err(SyncRequestError::LibraryIdIoError)?;
+
+				Ok(Self::Collection(
+					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+				))
+			}
+			9 => match stream {
+				SpaceTimeStream::Unicast(stream) => {
+					let mut uuid = [0u8; 16];
+					stream
+						.read_exact(&mut uuid)
+						.await
+						.map_err(SyncRequestError::LibraryIdIoError)?;
+
+					Ok(Self::SpacedropChunks(
+						Uuid::from_slice(&uuid)
+							.map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+					))
+				}
+				_ => Err(HeaderError::SpacedropOverMulticastIsForbidden),
+			},
+			10 => {
+				let mut uuid = [0u8; 16];
+				stream
+					.read_exact(&mut uuid)
+					.await
+					.map_err(SyncRequestError::LibraryIdIoError)?;
+
+				Ok(Self::ShareLink(
+					Uuid::from_slice(&uuid).map_err(SyncRequestError::ErrorDecodingLibraryId)?,
+				))
+			}
 			d => Err(HeaderError::InvalidDiscriminator(d)),
 		}
 	}
@@ -104,6 +234,37 @@ impl Header {
 				bytes.extend_from_slice(uuid.as_bytes());
 				bytes
 			}
+			Self::SyncRequest(uuid) => {
+				let mut bytes = vec![4];
+				bytes.extend_from_slice(uuid.as_bytes());
+				bytes
+			}
+			Self::Thumbnail(uuid) => {
+				let mut bytes = vec![5];
+				bytes.extend_from_slice(uuid.as_bytes());
+				bytes
+			}
+			Self::RemoteFs(uuid) => {
+				let mut bytes = vec![6];
+				bytes.extend_from_slice(uuid.as_bytes());
+				bytes
+			}
+			Self::Share => vec![7],
+			Self::Collection(uuid) => {
+				let mut bytes = vec![8];
+				bytes.extend_from_slice(uuid.as_bytes());
+				bytes
+			}
+			Self::SpacedropChunks(id) => {
+				let mut bytes = vec![9];
+				bytes.extend_from_slice(id.as_bytes());
+				bytes
+			}
+			Self::ShareLink(library_id) => {
+				let mut bytes = vec![10];
+				bytes.extend_from_slice(library_id.as_bytes());
+				bytes
+			}
 		}
 	}
 }
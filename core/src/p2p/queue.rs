@@ -0,0 +1,510 @@
+use crate::util::error::FileIOError;
+
+use std::{
+	io,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use chrono::{DateTime, Local, Timelike, Utc};
+use sd_p2p::PeerId;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs,
+	sync::{Mutex, Notify},
+};
+use uuid::Uuid;
+
+const QUEUE_FILE: &str = "queue.json";
+
+/// How often the queue is checked for transfers to start, as their window opens or their retry
+/// delay ends
+pub(super) const QUEUE_INTERVAL: Duration = Duration::from_secs(30);
+/// The longest a failed transfer waits before being retried
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Error)]
+pub enum TransferQueueError {
+	#[error("queued transfer '{0}' not found")]
+	NotFound(Uuid),
+	#[error("the transfer is being sent")]
+	Sending,
+	#[error("no files to send")]
+	NoFiles,
+	#[error("invalid transfer window, the times must be minutes of a day")]
+	InvalidWindow,
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error("error with the transfer queue: {0}")]
+	Serde(#[from] serde_json::Error),
+}
+
+impl From<TransferQueueError> for rspc::Error {
+	fn from(e: TransferQueueError) -> Self {
+		let code = match e {
+			TransferQueueError::NotFound(_) => rspc::ErrorCode::NotFound,
+			TransferQueueError::Sending => rspc::ErrorCode::Conflict,
+			TransferQueueError::NoFiles | TransferQueueError::InvalidWindow => {
+				rspc::ErrorCode::BadRequest
+			}
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Transfers to the same peer are sent one at a time, highest priority first
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferPriority {
+	Low,
+	#[default]
+	Normal,
+	High,
+}
+
+/// How a transfer that failed is retried, waiting twice as long after every failed attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct RetryPolicy {
+	/// How many times the transfer is attempted before giving up
+	pub max_attempts: u32,
+	/// How long to wait after the first failed attempt, in seconds
+	pub delay: u32,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 5,
+			delay: 60,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// How long to wait before the next attempt, after `attempts` failed ones
+	fn delay(&self, attempts: u32) -> Duration {
+		let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+		Duration::from_secs((self.delay as u64).saturating_mul(factor)).min(MAX_RETRY_DELAY)
+	}
+}
+
+/// The time of the day transfers are started at, in minutes after midnight in local time. A window
+/// that ends before it starts spans midnight, like overnight from 22:00 to 6:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct TransferWindow {
+	pub start: u16,
+	pub end: u16,
+}
+
+impl TransferWindow {
+	const MINUTES_PER_DAY: u16 = 24 * 60;
+
+	fn is_valid(&self) -> bool {
+		self.start < Self::MINUTES_PER_DAY && self.end <= Self::MINUTES_PER_DAY
+	}
+
+	fn contains(&self, minute: u16) -> bool {
+		if self.start <= self.end {
+			(self.start..self.end).contains(&minute)
+		} else {
+			minute >= self.start || minute < self.end
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum QueuedTransferStatus {
+	/// Waiting for its window, its retry delay or the transfers before it to the peer
+	Queued,
+	Sending,
+	Done,
+	/// The peer didn't accept it
+	Rejected,
+	/// Every attempt failed
+	Failed,
+}
+
+/// A Spacedrop waiting in the queue to be sent
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct QueuedTransfer {
+	/// Also the id of the Spacedrop, so an interrupted attempt is resumed by the next one
+	pub id: Uuid,
+	pub peer_id: PeerId,
+	pub paths: Vec<PathBuf>,
+	pub priority: TransferPriority,
+	pub retry: RetryPolicy,
+	/// `null` sends the transfer at any time
+	pub window: Option<TransferWindow>,
+	pub status: QueuedTransferStatus,
+	pub attempts: u32,
+	/// Why the last attempt failed
+	pub error: Option<String>,
+	/// When the transfer is retried after failing
+	pub next_attempt: Option<DateTime<Utc>>,
+	pub date_created: DateTime<Utc>,
+}
+
+impl QueuedTransfer {
+	fn is_due(&self, now: DateTime<Utc>, minute: u16) -> bool {
+		self.status == QueuedTransferStatus::Queued
+			&& self.next_attempt.map_or(true, |next| next <= now)
+			&& self.window.map_or(true, |window| window.contains(minute))
+	}
+}
+
+/// Spacedrops to send later, saved so they're sent even if the app is restarted
+pub struct TransferQueue {
+	path: PathBuf,
+	transfers: Mutex<Vec<QueuedTransfer>>,
+	/// Wakes up the scheduler when a transfer is queued or changed
+	pub(super) notify: Notify,
+}
+
+impl TransferQueue {
+	pub(super) fn new(spacedrop_dir: &Path) -> Self {
+		Self {
+			path: spacedrop_dir.join(QUEUE_FILE),
+			transfers: Mutex::new(vec![]),
+			notify: Notify::new(),
+		}
+	}
+
+	pub(super) async fn load(spacedrop_dir: &Path) -> Result<Self, TransferQueueError> {
+		let path = spacedrop_dir.join(QUEUE_FILE);
+
+		let mut transfers: Vec<QueuedTransfer> = match fs::read(&path).await {
+			Ok(data) => serde_json::from_slice(&data)?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+			Err(e) => return Err(FileIOError::from((path, e)).into()),
+		};
+
+		// The app was closed while they were sent, they continue from where they stopped
+		for transfer in &mut transfers {
+			if transfer.status == QueuedTransferStatus::Sending {
+				transfer.status = QueuedTransferStatus::Queued;
+			}
+		}
+
+		Ok(Self {
+			path,
+			transfers: Mutex::new(transfers),
+			notify: Notify::new(),
+		})
+	}
+
+	async fn save(&self, transfers: &[QueuedTransfer]) -> Result<(), TransferQueueError> {
+		if let Some(parent) = self.path.parent() {
+			fs::create_dir_all(parent)
+				.await
+				.map_err(|e| FileIOError::from((parent, e)))?;
+		}
+
+		fs::write(&self.path, serde_json::to_vec(transfers)?)
+			.await
+			.map_err(|e| FileIOError::from((&self.path, e)).into())
+	}
+
+	/// Every transfer in the queue, in the order they're sent
+	pub async fn list(&self) -> Vec<QueuedTransfer> {
+		let mut transfers = self.transfers.lock().await.clone();
+		transfers.sort_by(|a, b| {
+			b.priority
+				.cmp(&a.priority)
+				.then(a.date_created.cmp(&b.date_created))
+		});
+		transfers
+	}
+
+	pub async fn enqueue(
+		&self,
+		peer_id: PeerId,
+		paths: Vec<PathBuf>,
+		priority: TransferPriority,
+		retry: RetryPolicy,
+		window: Option<TransferWindow>,
+	) -> Result<Uuid, TransferQueueError> {
+		if paths.is_empty() {
+			return Err(TransferQueueError::NoFiles);
+		}
+		if window.map_or(false, |window| !window.is_valid()) {
+			return Err(TransferQueueError::InvalidWindow);
+		}
+
+		let id = Uuid::new_v4();
+
+		let mut transfers = self.transfers.lock().await;
+		transfers.push(QueuedTransfer {
+			id,
+			peer_id,
+			paths,
+			priority,
+			retry,
+			window,
+			status: QueuedTransferStatus::Queued,
+			attempts: 0,
+			error: None,
+			next_attempt: None,
+			date_created: Utc::now(),
+		});
+		self.save(&transfers).await?;
+
+		self.notify.notify_one();
+
+		Ok(id)
+	}
+
+	async fn update(
+		&self,
+		id: Uuid,
+		f: impl FnOnce(&mut QueuedTransfer) -> Result<(), TransferQueueError>,
+	) -> Result<QueuedTransfer, TransferQueueError> {
+		let mut transfers = self.transfers.lock().await;
+		let transfer = transfers
+			.iter_mut()
+			.find(|transfer| transfer.id == id)
+			.ok_or(TransferQueueError::NotFound(id))?;
+
+		f(transfer)?;
+		let transfer = transfer.clone();
+
+		self.save(&transfers).await?;
+
+		Ok(transfer)
+	}
+
+	/// Changes when a transfer is sent. A transfer that's being sent isn't stopped, this applies if
+	/// it's retried.
+	pub async fn schedule(
+		&self,
+		id: Uuid,
+		priority: TransferPriority,
+		window: Option<TransferWindow>,
+	) -> Result<(), TransferQueueError> {
+		if window.map_or(false, |window| !window.is_valid()) {
+			return Err(TransferQueueError::InvalidWindow);
+		}
+
+		self.update(id, |transfer| {
+			transfer.priority = priority;
+			transfer.window = window;
+			Ok(())
+		})
+		.await?;
+
+		self.notify.notify_one();
+
+		Ok(())
+	}
+
+	/// Queues a transfer that failed or was rejected again, with all of its attempts
+	pub async fn retry(&self, id: Uuid) -> Result<(), TransferQueueError> {
+		self.update(id, |transfer| {
+			if transfer.status == QueuedTransferStatus::Sending {
+				return Err(TransferQueueError::Sending);
+			}
+
+			transfer.status = QueuedTransferStatus::Queued;
+			transfer.attempts = 0;
+			transfer.error = None;
+			transfer.next_attempt = None;
+			Ok(())
+		})
+		.await?;
+
+		self.notify.notify_one();
+
+		Ok(())
+	}
+
+	/// Removes a transfer from the queue, unless it's being sent
+	pub async fn remove(&self, id: Uuid) -> Result<(), TransferQueueError> {
+		let mut transfers = self.transfers.lock().await;
+
+		match transfers.iter().find(|transfer| transfer.id == id) {
+			None => return Err(TransferQueueError::NotFound(id)),
+			Some(transfer) if transfer.status == QueuedTransferStatus::Sending => {
+				return Err(TransferQueueError::Sending)
+			}
+			Some(_) => {}
+		}
+
+		transfers.retain(|transfer| transfer.id != id);
+		self.save(&transfers).await
+	}
+
+	/// Marks the next transfer to every peer that isn't already sending one as being sent, and
+	/// returns them
+	pub(super) async fn start_due(&self) -> Result<Vec<QueuedTransfer>, TransferQueueError> {
+		let now = Local::now();
+		let minute = (now.hour() * 60 + now.minute()) as u16;
+
+		let mut transfers = self.transfers.lock().await;
+		let due = due(&transfers, now.with_timezone(&Utc), minute);
+		if due.is_empty() {
+			return Ok(vec![]);
+		}
+
+		let mut started = Vec::with_capacity(due.len());
+		for transfer in transfers.iter_mut().filter(|t| due.contains(&t.id)) {
+			transfer.status = QueuedTransferStatus::Sending;
+			transfer.attempts += 1;
+			started.push(transfer.clone());
+		}
+
+		self.save(&transfers).await?;
+
+		Ok(started)
+	}
+
+	/// Records the result of an attempt, scheduling the next one if it failed and it has attempts
+	/// left
+	pub(super) async fn finish(
+		&self,
+		id: Uuid,
+		result: Result<bool, String>,
+	) -> Result<(), TransferQueueError> {
+		self.update(id, |transfer| {
+			match result {
+				Ok(true) => {
+					transfer.status = QueuedTransferStatus::Done;
+					transfer.error = None;
+					transfer.next_attempt = None;
+				}
+				Ok(false) => transfer.status = QueuedTransferStatus::Rejected,
+				Err(error) if transfer.attempts >= transfer.retry.max_attempts => {
+					transfer.status = QueuedTransferStatus::Failed;
+					transfer.error = Some(error);
+				}
+				Err(error) => {
+					transfer.status = QueuedTransferStatus::Queued;
+					transfer.error = Some(error);
+					transfer.next_attempt =
+						chrono::Duration::from_std(transfer.retry.delay(transfer.attempts))
+							.ok()
+							.map(|delay| Utc::now() + delay);
+				}
+			}
+
+			Ok(())
+		})
+		.await?;
+
+		Ok(())
+	}
+}
+
+/// The ids of the next transfer to send to every peer that isn't already sending one
+fn due(transfers: &[QueuedTransfer], now: DateTime<Utc>, minute: u16) -> Vec<Uuid> {
+	let mut next = Vec::<&QueuedTransfer>::new();
+
+	for transfer in transfers.iter().filter(|t| t.is_due(now, minute)) {
+		if transfers
+			.iter()
+			.any(|t| t.peer_id == transfer.peer_id && t.status == QueuedTransferStatus::Sending)
+		{
+			continue;
+		}
+
+		match next.iter_mut().find(|t| t.peer_id == transfer.peer_id) {
+			Some(t) => {
+				if (transfer.priority, t.date_created) > (t.priority, transfer.date_created) {
+					*t = transfer;
+				}
+			}
+			None => next.push(transfer),
+		}
+	}
+
+	next.into_iter().map(|transfer| transfer.id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+
+	fn transfer(peer_id: PeerId, priority: TransferPriority, age: i64) -> QueuedTransfer {
+		QueuedTransfer {
+			id: Uuid::new_v4(),
+			peer_id,
+			paths: vec![PathBuf::from("file")],
+			priority,
+			retry: RetryPolicy::default(),
+			window: None,
+			status: QueuedTransferStatus::Queued,
+			attempts: 0,
+			error: None,
+			next_attempt: None,
+			date_created: Utc::now() - chrono::Duration::minutes(age),
+		}
+	}
+
+	#[test]
+	fn windows_can_span_midnight() {
+		let overnight = TransferWindow {
+			start: 22 * 60,
+			end: 6 * 60,
+		};
+		assert!(overnight.contains(23 * 60));
+		assert!(overnight.contains(60));
+		assert!(!overnight.contains(12 * 60));
+
+		let afternoon = TransferWindow {
+			start: 12 * 60,
+			end: 18 * 60,
+		};
+		assert!(afternoon.contains(12 * 60));
+		assert!(!afternoon.contains(18 * 60));
+		assert!(!afternoon.contains(60));
+	}
+
+	#[test]
+	fn retries_back_off() {
+		let retry = RetryPolicy {
+			max_attempts: 10,
+			delay: 60,
+		};
+		assert_eq!(retry.delay(1), Duration::from_secs(60));
+		assert_eq!(retry.delay(3), Duration::from_secs(240));
+		assert_eq!(retry.delay(100), MAX_RETRY_DELAY);
+	}
+
+	#[test]
+	fn one_transfer_per_peer_by_priority() {
+		let peer = Keypair::generate().peer_id();
+		let other_peer = Keypair::generate().peer_id();
+
+		let old = transfer(peer, TransferPriority::Normal, 10);
+		let new = transfer(peer, TransferPriority::Normal, 5);
+		let high = transfer(peer, TransferPriority::High, 1);
+		let other = transfer(other_peer, TransferPriority::Low, 1);
+
+		let now = Utc::now();
+		assert_eq!(
+			due(&[old.clone(), new.clone(), other.clone()], now, 0),
+			[old.id, other.id]
+		);
+		assert_eq!(due(&[old.clone(), new, high.clone()], now, 0), [high.id]);
+
+		// Nothing else is sent to a peer while a transfer to it is being sent
+		let mut sending = old;
+		sending.status = QueuedTransferStatus::Sending;
+		assert_eq!(due(&[sending, high, other.clone()], now, 0), [other.id]);
+
+		let mut closed = other;
+		closed.window = Some(TransferWindow {
+			start: 60,
+			end: 120,
+		});
+		assert!(due(&[closed.clone()], now, 0).is_empty());
+		assert_eq!(due(&[closed.clone()], now, 90), [closed.id]);
+	}
+}
@@ -0,0 +1,415 @@
+use crate::{library::Library, prisma::location, sync::SyncScope, util::db::maybe_missing};
+
+use std::{
+	cmp::min,
+	io,
+	path::{Component, Path, PathBuf},
+	sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use sd_p2p::{spaceblock::RateLimiter, spacetunnel::Tunnel, PeerId};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs::{self, File},
+	io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+};
+use uuid::Uuid;
+
+use super::{read_payload, write_payload, SyncCatchUpError};
+
+/// Files are sent in chunks so the rate limits apply while they're being read
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The most bytes of a file sent for a single read, larger files are read in ranges like
+/// media players do
+pub const MAX_REMOTE_READ: u64 = 8 * 1024 * 1024;
+
+/// Sent after a [`Header::RemoteFs`](super::Header::RemoteFs), any number of them can be sent
+/// through the same tunnel. Paths are relative to the location.
+#[derive(Debug, Serialize, Deserialize)]
+enum RemoteFsRequest {
+	ReadDir {
+		location_pub_id: Uuid,
+		path: String,
+	},
+	/// A `length` of `0` only returns the size of the file
+	ReadFile {
+		location_pub_id: Uuid,
+		path: String,
+		start: u64,
+		length: Option<u64>,
+	},
+}
+
+/// A file or directory in a location of another node
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct RemoteEntry {
+	pub name: String,
+	pub is_dir: bool,
+	#[specta(type = String)]
+	#[serde_as(as = "DisplayFromStr")]
+	pub size: u64,
+	pub date_modified: Option<DateTime<Utc>>,
+}
+
+/// The part of a file that is sent after it, `length` bytes from `start`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RemoteFile {
+	pub size: u64,
+	pub start: u64,
+	pub length: u64,
+}
+
+/// Why the node that has the location didn't do what was asked for
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum RemoteFsRefusal {
+	#[error("the location isn't shared with this node")]
+	LocationNotShared,
+	#[error("the path isn't in the location")]
+	InvalidPath,
+	#[error("the file or directory doesn't exist")]
+	NotFound,
+	#[error("the node isn't allowed to read the file or directory")]
+	PermissionDenied,
+	#[error("error reading the file or directory: {0}")]
+	Io(String),
+}
+
+impl From<io::Error> for RemoteFsRefusal {
+	fn from(e: io::Error) -> Self {
+		match e.kind() {
+			io::ErrorKind::NotFound => Self::NotFound,
+			io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+			_ => Self::Io(e.to_string()),
+		}
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum RemoteFsError {
+	#[error("location not found <id='{0}'>")]
+	LocationNotFound(location::id::Type),
+	#[error("the location is on this node")]
+	LocalLocation,
+	#[error(transparent)]
+	Peer(#[from] SyncCatchUpError),
+	#[error(transparent)]
+	Refused(#[from] RemoteFsRefusal),
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+}
+
+impl From<io::Error> for RemoteFsError {
+	fn from(e: io::Error) -> Self {
+		Self::Peer(e.into())
+	}
+}
+
+impl From<RemoteFsError> for rspc::Error {
+	fn from(e: RemoteFsError) -> Self {
+		let code = match e {
+			RemoteFsError::LocationNotFound(_)
+			| RemoteFsError::Refused(RemoteFsRefusal::NotFound) => rspc::ErrorCode::NotFound,
+			RemoteFsError::LocalLocation | RemoteFsError::Refused(RemoteFsRefusal::InvalidPath) => {
+				rspc::ErrorCode::BadRequest
+			}
+			RemoteFsError::Refused(
+				RemoteFsRefusal::LocationNotShared | RemoteFsRefusal::PermissionDenied,
+			) => rspc::ErrorCode::Forbidden,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// The locations of another node, read through a tunnel with it
+pub struct RemoteFs {
+	pub(super) tunnel: Tunnel,
+	pub(super) location_pub_id: Uuid,
+	/// The download limits of the peer
+	pub(super) limiters: Vec<Arc<RateLimiter>>,
+}
+
+impl RemoteFs {
+	pub async fn read_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>, RemoteFsError> {
+		write_payload(
+			&mut self.tunnel,
+			&RemoteFsRequest::ReadDir {
+				location_pub_id: self.location_pub_id,
+				path: path.to_string(),
+			},
+		)
+		.await?;
+
+		Ok(read_payload::<Result<_, RemoteFsRefusal>>(&mut self.tunnel).await??)
+	}
+
+	/// Reads up to [`MAX_REMOTE_READ`] bytes of the file from `start`, or until its end if
+	/// `length` is `None`
+	pub async fn read_file(
+		&mut self,
+		path: &str,
+		start: u64,
+		length: Option<u64>,
+	) -> Result<(RemoteFile, Vec<u8>), RemoteFsError> {
+		write_payload(
+			&mut self.tunnel,
+			&RemoteFsRequest::ReadFile {
+				location_pub_id: self.location_pub_id,
+				path: path.to_string(),
+				start,
+				length,
+			},
+		)
+		.await?;
+
+		let file = read_payload::<Result<RemoteFile, RemoteFsRefusal>>(&mut self.tunnel).await??;
+
+		let mut buf = vec![0; min(file.length, MAX_REMOTE_READ) as usize];
+		for chunk in buf.chunks_mut(CHUNK_SIZE) {
+			for limiter in &self.limiters {
+				limiter.acquire(chunk.len() as u64).await;
+			}
+
+			self.tunnel.read_exact(chunk).await?;
+		}
+
+		Ok((file, buf))
+	}
+}
+
+/// Answers the requests of the peer until it closes the tunnel. Only the locations of this node
+/// that are synced with the peer can be read.
+pub(super) async fn respond(
+	tunnel: &mut Tunnel,
+	library: &Library,
+	peer_id: PeerId,
+	limiters: Vec<Arc<RateLimiter>>,
+) -> Result<(), SyncCatchUpError> {
+	let scope = SyncScope::for_peer(&library.db, &peer_id.to_string()).await?;
+
+	loop {
+		let request = match read_payload::<RemoteFsRequest>(tunnel).await {
+			Ok(request) => request,
+			// The peer is done
+			Err(SyncCatchUpError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+				return Ok(())
+			}
+			Err(e) => return Err(e),
+		};
+
+		match request {
+			RemoteFsRequest::ReadDir {
+				location_pub_id,
+				path,
+			} => {
+				let entries = match resolve(library, &scope, location_pub_id, &path).await {
+					Ok(path) => read_dir(&path).await,
+					Err(refusal) => Err(refusal),
+				};
+
+				write_payload(tunnel, &entries).await?;
+			}
+			RemoteFsRequest::ReadFile {
+				location_pub_id,
+				path,
+				start,
+				length,
+			} => {
+				let file = match resolve(library, &scope, location_pub_id, &path).await {
+					Ok(path) => open_file(&path, start, length).await,
+					Err(refusal) => Err(refusal),
+				};
+
+				match file {
+					Ok((mut file, remote_file)) => {
+						write_payload(tunnel, &Ok::<_, RemoteFsRefusal>(remote_file)).await?;
+
+						let mut remaining = remote_file.length as usize;
+						let mut buf = vec![0; CHUNK_SIZE];
+						while remaining > 0 {
+							let chunk = &mut buf[..min(remaining, CHUNK_SIZE)];
+							for limiter in &limiters {
+								limiter.acquire(chunk.len() as u64).await;
+							}
+
+							// The peer expects exactly `length` bytes, if the file was truncated
+							// while sending it the tunnel can't be used anymore
+							file.read_exact(chunk).await?;
+							tunnel.write_all(chunk).await?;
+							remaining -= chunk.len();
+						}
+					}
+					Err(refusal) => {
+						write_payload(tunnel, &Err::<RemoteFile, _>(refusal)).await?;
+					}
+				}
+			}
+		}
+	}
+}
+
+/// The path on this node of a path in one of its locations, if the peer is allowed to read it
+async fn resolve(
+	library: &Library,
+	scope: &SyncScope,
+	location_pub_id: Uuid,
+	path: &str,
+) -> Result<PathBuf, RemoteFsRefusal> {
+	if !scope
+		.locations
+		.as_ref()
+		.map_or(true, |locations| locations.contains(&location_pub_id))
+	{
+		return Err(RemoteFsRefusal::LocationNotShared);
+	}
+
+	let location = library
+		.db
+		.location()
+		.find_unique(location::pub_id::equals(
+			location_pub_id.as_bytes().to_vec(),
+		))
+		.select(location::select!({ node_id path }))
+		.exec()
+		.await
+		.map_err(|e| RemoteFsRefusal::Io(e.to_string()))?
+		.filter(|location| location.node_id == Some(library.node_local_id))
+		.ok_or(RemoteFsRefusal::LocationNotShared)?;
+
+	let location_path = maybe_missing(location.path, "location.path")
+		.map_err(|_| RemoteFsRefusal::LocationNotShared)?;
+
+	let full_path = join_relative(Path::new(&location_path), path)?;
+
+	// Symlinks could point outside of the location
+	let (location_path, full_path) = (
+		fs::canonicalize(&location_path).await?,
+		fs::canonicalize(&full_path).await?,
+	);
+	if !full_path.starts_with(location_path) {
+		return Err(RemoteFsRefusal::InvalidPath);
+	}
+
+	Ok(full_path)
+}
+
+/// Joins a relative path sent by a peer, which can't leave the base path
+fn join_relative(base: &Path, path: &str) -> Result<PathBuf, RemoteFsRefusal> {
+	let mut full_path = base.to_path_buf();
+
+	for component in Path::new(path).components() {
+		match component {
+			Component::Normal(part) => full_path.push(part),
+			Component::CurDir => {}
+			Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+				return Err(RemoteFsRefusal::InvalidPath)
+			}
+		}
+	}
+
+	Ok(full_path)
+}
+
+async fn read_dir(path: &Path) -> Result<Vec<RemoteEntry>, RemoteFsRefusal> {
+	let mut read_dir = fs::read_dir(path).await?;
+
+	let mut entries = vec![];
+	while let Some(entry) = read_dir.next_entry().await? {
+		let Ok(metadata) = entry.metadata().await else {
+			continue;
+		};
+
+		entries.push(RemoteEntry {
+			name: entry.file_name().to_string_lossy().to_string(),
+			is_dir: metadata.is_dir(),
+			size: metadata.len(),
+			date_modified: metadata.modified().ok().map(Into::into),
+		});
+	}
+
+	// Directories first, like the explorer shows them
+	entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+	Ok(entries)
+}
+
+async fn open_file(
+	path: &Path,
+	start: u64,
+	length: Option<u64>,
+) -> Result<(File, RemoteFile), RemoteFsRefusal> {
+	let mut file = File::open(path).await?;
+
+	let metadata = file.metadata().await?;
+	if metadata.is_dir() {
+		return Err(RemoteFsRefusal::InvalidPath);
+	}
+
+	let size = metadata.len();
+	let start = min(start, size);
+	let length = min(
+		length.unwrap_or(u64::MAX),
+		min(size - start, MAX_REMOTE_READ),
+	);
+
+	file.seek(SeekFrom::Start(start)).await?;
+
+	Ok((
+		file,
+		RemoteFile {
+			size,
+			start,
+			length,
+		},
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn paths_stay_in_the_location() {
+		let base = Path::new("/location");
+
+		assert_eq!(
+			join_relative(base, "photos/2023/a.jpg").unwrap(),
+			Path::new("/location/photos/2023/a.jpg")
+		);
+		assert_eq!(join_relative(base, "").unwrap(), base);
+		assert_eq!(
+			join_relative(base, "./photos").unwrap(),
+			Path::new("/location/photos")
+		);
+		assert!(join_relative(base, "../etc/passwd").is_err());
+		assert!(join_relative(base, "photos/../../etc").is_err());
+		assert!(join_relative(base, "/etc/passwd").is_err());
+	}
+
+	#[tokio::test]
+	async fn reads_are_capped() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("file");
+		fs::write(&path, vec![0; 100]).await.unwrap();
+
+		let (_, file) = open_file(&path, 10, None).await.unwrap();
+		assert_eq!((file.size, file.start, file.length), (100, 10, 90));
+
+		let (_, file) = open_file(&path, 10, Some(20)).await.unwrap();
+		assert_eq!(file.length, 20);
+
+		let (_, file) = open_file(&path, 200, Some(20)).await.unwrap();
+		assert_eq!((file.start, file.length), (100, 0));
+
+		assert!(matches!(
+			open_file(dir.path(), 0, None).await,
+			Err(RemoteFsRefusal::InvalidPath)
+		));
+	}
+}
@@ -0,0 +1,451 @@
+use std::{
+	fmt,
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::Arc,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use prisma_client_rust::QueryError;
+use sd_p2p::{
+	spaceblock::RateLimiter,
+	spacetime::UnicastStream,
+	spacetunnel::{Identity, RemoteIdentity, Tunnel},
+	Manager, PeerId,
+};
+use sd_prisma::prisma::{object, share_link};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+	invalidate_query,
+	library::{Library, LibraryManager},
+};
+
+use super::{
+	read_payload,
+	sharing::{file_name, open_first_file, receive_file, send_file},
+	write_payload, Header, PeerMetadata, SyncCatchUpError,
+};
+
+const TOKEN_PREFIX: &str = "spacedrive-share:";
+/// The length of the part of a token that is signed
+const SIGNED_LEN: usize = 16 + 16 + 32 + 8;
+const TOKEN_LEN: usize = SIGNED_LEN + 64;
+/// The directory in the shared directory where the objects of the redeemed links are saved to
+pub const LINKS_DIR: &str = "links";
+
+/// Why the library that created a link didn't give the object
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ShareLinkRefusal {
+	#[error("the share link isn't valid")]
+	Invalid,
+	#[error("the share link expired")]
+	Expired,
+	#[error("the share link was revoked")]
+	Revoked,
+	#[error("error reading the shared object: {0}")]
+	Internal(String),
+}
+
+impl From<QueryError> for ShareLinkRefusal {
+	fn from(e: QueryError) -> Self {
+		Self::Internal(e.to_string())
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum ShareLinkError {
+	#[error("the share link token is invalid")]
+	InvalidToken,
+	#[error("object not found <id='{0}'>")]
+	ObjectNotFound(object::id::Type),
+	#[error("share link not found <id='{0}'>")]
+	LinkNotFound(share_link::id::Type),
+	#[error("the expiry of the share link is in the past")]
+	ExpiryInPast,
+	#[error(transparent)]
+	Refused(#[from] ShareLinkRefusal),
+	#[error(transparent)]
+	Peer(#[from] SyncCatchUpError),
+	#[error("error saving the shared object: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+}
+
+impl From<ShareLinkError> for rspc::Error {
+	fn from(e: ShareLinkError) -> Self {
+		let code = match e {
+			ShareLinkError::ObjectNotFound(_) | ShareLinkError::LinkNotFound(_) => {
+				rspc::ErrorCode::NotFound
+			}
+			ShareLinkError::InvalidToken
+			| ShareLinkError::ExpiryInPast
+			| ShareLinkError::Refused(
+				ShareLinkRefusal::Invalid | ShareLinkRefusal::Expired | ShareLinkRefusal::Revoked,
+			) => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Given to someone else so their node can get an object of a library from the node that created
+/// it, over P2P. It's signed by the library, so it can't be changed to get another object or to
+/// live longer, and the node redeeming it checks it's talking to that library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareToken {
+	pub peer_id: PeerId,
+	pub library_id: Uuid,
+	pub link_pub_id: Uuid,
+	pub identity: [u8; 32],
+	pub expires: Option<DateTime<Utc>>,
+	pub signature: [u8; 64],
+}
+
+impl ShareToken {
+	pub fn new(
+		library: &Library,
+		peer_id: PeerId,
+		link_pub_id: Uuid,
+		expires: Option<DateTime<Utc>>,
+	) -> Self {
+		let mut token = Self {
+			peer_id,
+			library_id: library.id,
+			link_pub_id,
+			identity: library.identity.to_remote_identity().to_bytes(),
+			expires,
+			signature: [0; 64],
+		};
+		token.signature = library.identity.sign(&token.signed_bytes());
+
+		token
+	}
+
+	fn signed_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(SIGNED_LEN);
+		bytes.extend_from_slice(self.library_id.as_bytes());
+		bytes.extend_from_slice(self.link_pub_id.as_bytes());
+		bytes.extend_from_slice(&self.identity);
+		bytes.extend_from_slice(
+			&self
+				.expires
+				.map_or(0, |expires| expires.timestamp_millis())
+				.to_le_bytes(),
+		);
+		bytes
+	}
+
+	/// Whether the token was signed by the library with the identity
+	pub fn is_signed_by(&self, identity: &RemoteIdentity) -> bool {
+		identity.to_bytes() == self.identity
+			&& identity
+				.verify(&self.signed_bytes(), &self.signature)
+				.is_ok()
+	}
+}
+
+impl fmt::Display for ShareToken {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut bytes = self.signed_bytes();
+		bytes.extend_from_slice(&self.signature);
+
+		write!(f, "{TOKEN_PREFIX}{}:{}", self.peer_id, hex::encode(bytes))
+	}
+}
+
+impl FromStr for ShareToken {
+	type Err = ShareLinkError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (peer_id, bytes) = s
+			.trim()
+			.strip_prefix(TOKEN_PREFIX)
+			.and_then(|s| s.split_once(':'))
+			.ok_or(ShareLinkError::InvalidToken)?;
+
+		let bytes = hex::decode(bytes).map_err(|_| ShareLinkError::InvalidToken)?;
+		if bytes.len() != TOKEN_LEN {
+			return Err(ShareLinkError::InvalidToken);
+		}
+
+		let expires =
+			i64::from_le_bytes(bytes[64..72].try_into().expect("checked the length above"));
+
+		Ok(Self {
+			peer_id: PeerId::from_str(peer_id).map_err(|_| ShareLinkError::InvalidToken)?,
+			library_id: Uuid::from_slice(&bytes[..16]).map_err(|_| ShareLinkError::InvalidToken)?,
+			link_pub_id: Uuid::from_slice(&bytes[16..32])
+				.map_err(|_| ShareLinkError::InvalidToken)?,
+			identity: bytes[32..64].try_into().expect("checked the length above"),
+			expires: match expires {
+				0 => None,
+				millis => Some(
+					Utc.timestamp_millis_opt(millis)
+						.single()
+						.ok_or(ShareLinkError::InvalidToken)?,
+				),
+			},
+			signature: bytes[SIGNED_LEN..]
+				.try_into()
+				.expect("checked the length above"),
+		})
+	}
+}
+
+/// An object of another library that was got with a share link, as that library describes it
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct SharedObject {
+	pub object_pub_id: Uuid,
+	pub name: String,
+	pub extension: Option<String>,
+	pub kind: i32,
+	pub size_in_bytes: String,
+	pub note: Option<String>,
+	pub favorite: bool,
+	pub tags: Vec<String>,
+	pub date_created: Option<DateTime<Utc>>,
+	pub date_modified: Option<DateTime<Utc>>,
+	/// Where the file of the object was saved to, `null` if the file isn't on the node that
+	/// shared it
+	pub local_path: Option<PathBuf>,
+}
+
+/// Redeems the link of the token with the library that created it, saving the file of the object
+/// in `dir`
+pub(super) async fn redeem(
+	manager: &Manager<PeerMetadata>,
+	token: &ShareToken,
+	dir: &Path,
+	limiters: &[Arc<RateLimiter>],
+) -> Result<SharedObject, ShareLinkError> {
+	let mut stream = manager
+		.stream(token.peer_id)
+		.await
+		.map_err(|_| SyncCatchUpError::PeerUnreachable)?;
+
+	stream
+		.write_all(&Header::ShareLink(token.library_id).to_bytes())
+		.await?;
+
+	// The node redeeming the link doesn't need to be known by the library, but the library must be
+	// the one that signed the token
+	let mut tunnel = Tunnel::initiator(stream, &Identity::new())
+		.await
+		.map_err(SyncCatchUpError::from)?;
+	if tunnel.remote_identity().to_bytes() != token.identity {
+		return Err(SyncCatchUpError::IdentityMismatch.into());
+	}
+
+	write_payload(&mut tunnel, &token.to_string()).await?;
+
+	let (mut object, size) =
+		read_payload::<Result<(SharedObject, Option<u64>), ShareLinkRefusal>>(&mut tunnel)
+			.await??;
+
+	if let Some(size) = size {
+		let path = dir.join(file_name(
+			object.object_pub_id,
+			&object.name,
+			object.extension.as_deref(),
+		));
+
+		receive_file(&mut tunnel, &path, size, limiters).await?;
+		object.local_path = Some(path);
+	}
+
+	Ok(object)
+}
+
+/// Gives the object of a link of the library to the node that redeems it, if the link is still
+/// valid
+pub(super) async fn respond(
+	stream: UnicastStream,
+	library_manager: &LibraryManager,
+	library_id: Uuid,
+	limiters: &[Arc<RateLimiter>],
+) -> Result<(), ShareLinkError> {
+	let library = library_manager
+		.get_library(library_id)
+		.await
+		.ok_or(ShareLinkRefusal::Invalid)?;
+
+	let mut tunnel = Tunnel::responder(stream, &library.identity)
+		.await
+		.map_err(SyncCatchUpError::from)?;
+
+	let token = read_payload::<String>(&mut tunnel).await?;
+	let result = match token.parse::<ShareToken>() {
+		Ok(token) => shared_object(&library, &token).await,
+		Err(_) => Err(ShareLinkRefusal::Invalid),
+	};
+
+	match result {
+		Ok((object, file)) => {
+			let size = file.as_ref().map(|(_, size)| *size);
+			write_payload(&mut tunnel, &Ok::<_, ShareLinkRefusal>((object, size))).await?;
+
+			if let Some((mut file, size)) = file {
+				send_file(&mut tunnel, &mut file, size, limiters).await?;
+			}
+
+			tunnel.flush().await?;
+		}
+		Err(refusal) => {
+			info!("Refused to redeem a share link of library '{library_id}': {refusal}");
+			write_payload(&mut tunnel, &Err::<(SharedObject, Option<u64>), _>(refusal)).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// The object of the link of the token if it's still valid, with its file if it's on this node
+async fn shared_object(
+	library: &Library,
+	token: &ShareToken,
+) -> Result<(SharedObject, Option<(tokio::fs::File, u64)>), ShareLinkRefusal> {
+	if token.library_id != library.id || !token.is_signed_by(&library.identity.to_remote_identity())
+	{
+		return Err(ShareLinkRefusal::Invalid);
+	}
+
+	let link = library
+		.db
+		.share_link()
+		.find_unique(share_link::pub_id::equals(
+			token.link_pub_id.as_bytes().to_vec(),
+		))
+		.include(share_link::include!({
+			object: select {
+				pub_id
+				kind
+				note
+				favorite
+				date_created
+				file_paths: select { id name extension size_in_bytes_bytes date_modified }
+				tags: select { tag: select { name } }
+			}
+		}))
+		.exec()
+		.await?
+		.ok_or(ShareLinkRefusal::Invalid)?;
+
+	if link.date_revoked.is_some() {
+		return Err(ShareLinkRefusal::Revoked);
+	}
+
+	if matches!(link.date_expires, Some(expires) if expires < Utc::now()) {
+		return Err(ShareLinkRefusal::Expired);
+	}
+
+	let object = link.object;
+	let file_path = object.file_paths.first();
+
+	let paths = library
+		.get_file_paths(
+			object
+				.file_paths
+				.iter()
+				.map(|file_path| file_path.id)
+				.collect(),
+		)
+		.await
+		.map_err(|e| ShareLinkRefusal::Internal(e.to_string()))?;
+	let file = open_first_file(paths.into_values().flatten())
+		.await
+		.map_err(|e| ShareLinkRefusal::Internal(e.to_string()))?;
+
+	library
+		.db
+		.share_link()
+		.update(
+			share_link::id::equals(link.id),
+			vec![
+				share_link::redemptions::increment(1),
+				share_link::date_redeemed::set(Some(Utc::now().into())),
+			],
+		)
+		.exec()
+		.await?;
+
+	invalidate_query!(library, "shareLinks.list");
+
+	Ok((
+		SharedObject {
+			object_pub_id: Uuid::from_slice(&object.pub_id)
+				.map_err(|e| ShareLinkRefusal::Internal(e.to_string()))?,
+			name: file_path
+				.and_then(|file_path| file_path.name.clone())
+				.unwrap_or_default(),
+			extension: file_path.and_then(|file_path| file_path.extension.clone()),
+			kind: object.kind.unwrap_or_default(),
+			size_in_bytes: file_path
+				.and_then(|file_path| file_path.size_in_bytes_bytes.clone())
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(u64::from_be_bytes)
+				.unwrap_or_default()
+				.to_string(),
+			note: object.note,
+			favorite: object.favorite.unwrap_or_default(),
+			tags: object
+				.tags
+				.into_iter()
+				.filter_map(|tag_on_object| tag_on_object.tag.name)
+				.collect(),
+			date_created: object.date_created.map(Into::into),
+			date_modified: file_path
+				.and_then(|file_path| file_path.date_modified)
+				.map(Into::into),
+			local_path: None,
+		},
+		file,
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+
+	fn token(identity: &Identity, expires: Option<DateTime<Utc>>) -> ShareToken {
+		let mut token = ShareToken {
+			peer_id: Keypair::generate().peer_id(),
+			library_id: Uuid::new_v4(),
+			link_pub_id: Uuid::new_v4(),
+			identity: identity.to_remote_identity().to_bytes(),
+			expires,
+			signature: [0; 64],
+		};
+		token.signature = identity.sign(&token.signed_bytes());
+		token
+	}
+
+	#[test]
+	fn tokens_round_trip_and_are_signed() {
+		let identity = Identity::new();
+		let expires = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+
+		for token in [token(&identity, None), token(&identity, Some(expires))] {
+			let parsed = token.to_string().parse::<ShareToken>().unwrap();
+			assert_eq!(parsed, token);
+			assert!(parsed.is_signed_by(&identity.to_remote_identity()));
+			assert!(!parsed.is_signed_by(&Identity::new().to_remote_identity()));
+		}
+
+		// Making the link live longer invalidates the signature
+		let mut tampered = token(&identity, Some(expires));
+		tampered.expires = None;
+		assert!(!tampered.is_signed_by(&identity.to_remote_identity()));
+
+		assert!("spacedrive-share:nope".parse::<ShareToken>().is_err());
+	}
+}
@@ -0,0 +1,1064 @@
+use std::{
+	collections::{HashMap, HashSet},
+	io,
+	path::{Component, Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use prisma_client_rust::QueryError;
+use sd_p2p::{
+	spaceblock::RateLimiter,
+	spacetime::UnicastStream,
+	spacetunnel::{RemoteIdentity, Tunnel},
+	PeerId,
+};
+use sd_sync::{CRDTOperation, CRDTOperationType};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs::{self, File},
+	io::{AsyncReadExt, AsyncWriteExt},
+	sync::{broadcast, oneshot},
+	time::timeout,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+	invalidate_query,
+	library::{Library, LibraryManager},
+	prisma::{object, shared_collection, shared_collection_item, tag, tag_on_object},
+	sync::{self, ModelSyncData},
+};
+
+use super::{read_payload, write_payload, P2PEvent, SyncCatchUpError};
+
+/// Files are sent in chunks so the rate limits apply while they're being read
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How long to wait for the user to accept a collection before the invitation is rejected
+const INVITATION_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+/// The directory in the data directory where the files of the collections shared with this node
+/// are downloaded to
+pub const SHARED_DIR: &str = "shared";
+
+/// What the library a collection is shared with can do with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum SharingAccess {
+	/// Only sees the items of the collection
+	ReadOnly = 0,
+	/// Can also rename the collection, change its color and remove items from it
+	ReadWrite = 1,
+}
+
+impl From<i32> for SharingAccess {
+	fn from(access: i32) -> Self {
+		match access {
+			1 => Self::ReadWrite,
+			_ => Self::ReadOnly,
+		}
+	}
+}
+
+/// Sent after a [`Header::Share`](super::Header::Share) by the library that shares one of its tags
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ShareInvitation {
+	pub collection_pub_id: Uuid,
+	pub name: String,
+	pub color: Option<String>,
+	pub library_id: Uuid,
+	pub library_name: String,
+	pub identity: Vec<u8>,
+	pub access: SharingAccess,
+	pub include_files: bool,
+}
+
+/// The reply to a [`ShareInvitation`] once the user accepted it, with the library they picked
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ShareAcceptance {
+	pub library_id: Uuid,
+	pub library_name: String,
+	pub identity: Vec<u8>,
+}
+
+/// Sent after a [`Header::Collection`](super::Header::Collection) through a tunnel between the two
+/// libraries, any number of them can be sent through the same tunnel
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum CollectionRequest {
+	/// Asks the owner for the items of the collection
+	Items,
+	/// Asks the owner for the file of an item, for the collections that include files
+	File { object_pub_id: Uuid },
+	/// Asks the owner to change the collection, for the collections shared read-write
+	Edit(CollectionEdit),
+	/// Tells the library the collection is shared with that it changed
+	Changed,
+	/// Either library stops sharing the collection
+	Unshare,
+}
+
+/// A change that the library a collection is shared with read-write can make to it
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(tag = "type")]
+pub enum CollectionEdit {
+	Update {
+		name: Option<String>,
+		color: Option<String>,
+	},
+	RemoveItem {
+		object_pub_id: Uuid,
+	},
+}
+
+/// An object of a shared collection, described with a file it's linked to in the owner's library
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct CollectionItem {
+	pub object_pub_id: Uuid,
+	pub name: String,
+	pub extension: Option<String>,
+	pub kind: i32,
+	pub size_in_bytes: u64,
+	pub date_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CollectionSnapshot {
+	pub name: String,
+	pub color: Option<String>,
+	pub items: Vec<CollectionItem>,
+}
+
+/// Why the other library didn't do what was asked for with a collection
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum CollectionRefusal {
+	#[error("the collection isn't shared anymore")]
+	NotShared,
+	#[error("the collection is shared read-only")]
+	ReadOnly,
+	#[error("the collection doesn't include its files")]
+	NoFiles,
+	#[error("the item isn't in the collection or its file isn't on the node")]
+	NotFound,
+	#[error("error reading the collection: {0}")]
+	Internal(String),
+}
+
+impl From<QueryError> for CollectionRefusal {
+	fn from(e: QueryError) -> Self {
+		Self::Internal(e.to_string())
+	}
+}
+
+impl From<io::Error> for CollectionRefusal {
+	fn from(e: io::Error) -> Self {
+		match e.kind() {
+			io::ErrorKind::NotFound => Self::NotFound,
+			_ => Self::Internal(e.to_string()),
+		}
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum SharingError {
+	#[error("tag not found <id='{0}'>")]
+	TagNotFound(tag::id::Type),
+	#[error("shared collection not found <id='{0}'>")]
+	CollectionNotFound(shared_collection::id::Type),
+	#[error("the collection is owned by this library")]
+	OwnedCollection,
+	#[error("the collection was rejected")]
+	Rejected,
+	#[error("timed out waiting for the collection to be accepted")]
+	Timeout,
+	#[error(transparent)]
+	Refused(#[from] CollectionRefusal),
+	#[error(transparent)]
+	Peer(#[from] SyncCatchUpError),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+}
+
+impl From<io::Error> for SharingError {
+	fn from(e: io::Error) -> Self {
+		Self::Peer(e.into())
+	}
+}
+
+impl From<SharingError> for rspc::Error {
+	fn from(e: SharingError) -> Self {
+		let code = match e {
+			SharingError::TagNotFound(_)
+			| SharingError::CollectionNotFound(_)
+			| SharingError::Refused(CollectionRefusal::NotFound) => rspc::ErrorCode::NotFound,
+			SharingError::OwnedCollection => rspc::ErrorCode::BadRequest,
+			SharingError::Refused(CollectionRefusal::ReadOnly | CollectionRefusal::NoFiles) => {
+				rspc::ErrorCode::Forbidden
+			}
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// The invitations to collections waiting for the user, and the collections shared with this
+/// node that have to be fetched again
+pub struct Shares {
+	events: broadcast::Sender<P2PEvent>,
+	/// Waiting for the user to pick the library to add the collection to, `None` to reject it
+	invitations: Mutex<HashMap<Uuid, oneshot::Sender<Option<Uuid>>>>,
+	changes: broadcast::Sender<(Uuid, shared_collection::id::Type)>,
+	/// Where the files of the collections shared with this node are downloaded to
+	pub(super) shared_dir: PathBuf,
+}
+
+impl Shares {
+	pub fn new(events: broadcast::Sender<P2PEvent>, shared_dir: PathBuf) -> Self {
+		Self {
+			events,
+			invitations: Mutex::new(HashMap::new()),
+			changes: broadcast::channel(64).0,
+			shared_dir,
+		}
+	}
+
+	pub fn answer(&self, id: Uuid, library_id: Option<Uuid>) {
+		if let Some(tx) = self.invitations.lock().unwrap().remove(&id) {
+			tx.send(library_id).ok();
+		}
+	}
+
+	/// The collections of a library, by its id, that have to be fetched from their owner again
+	pub fn subscribe_changes(&self) -> broadcast::Receiver<(Uuid, shared_collection::id::Type)> {
+		self.changes.subscribe()
+	}
+
+	/// The peer is back, the collections it shares with us could have changed since
+	pub(super) async fn peer_discovered(&self, library_manager: &LibraryManager, peer_id: PeerId) {
+		for library in library_manager.get_all_libraries().await {
+			match library
+				.db
+				.shared_collection()
+				.find_many(vec![
+					shared_collection::peer_id::equals(peer_id.to_string()),
+					shared_collection::tag_id::equals(None),
+				])
+				.select(shared_collection::select!({ id }))
+				.exec()
+				.await
+			{
+				Ok(collections) => {
+					for collection in collections {
+						self.changes.send((library.id, collection.id)).ok();
+					}
+				}
+				Err(e) => debug!(
+					"Failed to find the collections shared by peer '{peer_id}' with library '{}': {e}",
+					library.id
+				),
+			}
+		}
+	}
+
+	/// Asks the user whether to add the collection the peer invites us to, and to which library
+	pub(super) async fn receive(
+		&self,
+		stream: &mut UnicastStream,
+		peer_id: PeerId,
+		library_manager: &LibraryManager,
+	) -> Result<(), SharingError> {
+		let invitation = read_payload::<ShareInvitation>(stream).await?;
+		let id = Uuid::new_v4();
+
+		let (tx, rx) = oneshot::channel();
+		self.invitations.lock().unwrap().insert(id, tx);
+
+		if self
+			.events
+			.send(P2PEvent::ShareRequest {
+				id,
+				peer_id,
+				library_name: invitation.library_name.clone(),
+				name: invitation.name.clone(),
+				access: invitation.access,
+				include_files: invitation.include_files,
+			})
+			.is_err()
+		{
+			// No frontend is active so no one can accept it
+			self.invitations.lock().unwrap().remove(&id);
+		}
+
+		let library = match timeout(INVITATION_TIMEOUT, rx).await {
+			Ok(Ok(Some(library_id))) => library_manager.get_library(library_id).await,
+			Ok(_) => None,
+			Err(_) => {
+				self.invitations.lock().unwrap().remove(&id);
+				None
+			}
+		};
+
+		let Some(library) = library else {
+			info!(
+				"Collection '{}' from peer '{peer_id}' was rejected",
+				invitation.name
+			);
+			write_payload(stream, &None::<ShareAcceptance>).await?;
+			return Ok(());
+		};
+
+		let collection = library
+			.db
+			.shared_collection()
+			.create(
+				invitation.collection_pub_id.as_bytes().to_vec(),
+				invitation.name,
+				invitation.library_id.as_bytes().to_vec(),
+				invitation.library_name,
+				peer_id.to_string(),
+				invitation.identity,
+				invitation.access as i32,
+				invitation.include_files,
+				Utc::now().into(),
+				vec![shared_collection::color::set(invitation.color)],
+			)
+			.exec()
+			.await?;
+
+		write_payload(
+			stream,
+			&Some(ShareAcceptance {
+				library_id: library.id,
+				library_name: library.config.name.clone(),
+				identity: library.identity.to_remote_identity().to_bytes().to_vec(),
+			}),
+		)
+		.await?;
+
+		info!(
+			"Added collection '{}' from peer '{peer_id}' to library '{}'",
+			collection.name, library.id
+		);
+		invalidate_query!(library, "sharing.list");
+
+		// The items are fetched like for any change to the collection
+		self.changes.send((library.id, collection.id)).ok();
+
+		Ok(())
+	}
+
+	/// Removes the files downloaded for a collection that isn't shared with this node anymore
+	pub(super) async fn remove_files(&self, collection: &shared_collection::Data) {
+		let Ok(collection_pub_id) = Uuid::from_slice(&collection.pub_id) else {
+			return;
+		};
+
+		let dir = self.shared_dir.join(collection_pub_id.to_string());
+		if let Err(e) = fs::remove_dir_all(&dir).await {
+			if e.kind() != io::ErrorKind::NotFound {
+				warn!(
+					"Failed to remove the files of collection '{}' at '{}': {e}",
+					collection.name,
+					dir.display()
+				);
+			}
+		}
+	}
+
+	/// Answers the requests of the other library of a collection until it closes the tunnel
+	pub(super) async fn respond(
+		&self,
+		stream: UnicastStream,
+		library_manager: &LibraryManager,
+		collection_pub_id: Uuid,
+		limiters: Vec<Arc<RateLimiter>>,
+	) -> Result<(), SharingError> {
+		let mut found = None;
+		for library in library_manager.get_all_libraries().await {
+			if let Some(collection) = library
+				.db
+				.shared_collection()
+				.find_unique(shared_collection::pub_id::equals(
+					collection_pub_id.as_bytes().to_vec(),
+				))
+				.exec()
+				.await?
+			{
+				found = Some((library, collection));
+				break;
+			}
+		}
+
+		let Some((library, collection)) = found else {
+			return Err(SyncCatchUpError::NotPaired.into());
+		};
+
+		// Only the library the collection was shared with can use it
+		let mut tunnel = Tunnel::responder(stream, &library.identity)
+			.await
+			.map_err(SyncCatchUpError::from)?;
+		if RemoteIdentity::from_bytes(&collection.identity)
+			.ok()
+			.as_ref() != Some(tunnel.remote_identity())
+		{
+			return Err(SyncCatchUpError::IdentityMismatch.into());
+		}
+
+		loop {
+			let request = match read_payload::<CollectionRequest>(&mut tunnel).await {
+				Ok(request) => request,
+				// The peer is done
+				Err(SyncCatchUpError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+					return Ok(())
+				}
+				Err(e) => return Err(e.into()),
+			};
+
+			match (request, collection.tag_id) {
+				(CollectionRequest::Items, Some(tag_id)) => {
+					let snapshot = snapshot(&library, tag_id, &collection).await;
+					write_payload(&mut tunnel, &snapshot.map_err(CollectionRefusal::from)).await?;
+				}
+				(CollectionRequest::File { object_pub_id }, Some(tag_id)) => {
+					let file = if collection.include_files {
+						open_item(&library, tag_id, object_pub_id).await
+					} else {
+						Err(CollectionRefusal::NoFiles)
+					};
+
+					match file {
+						Ok((mut file, size)) => {
+							write_payload(&mut tunnel, &Ok::<_, CollectionRefusal>(size)).await?;
+							send_file(&mut tunnel, &mut file, size, &limiters).await?;
+						}
+						Err(refusal) => {
+							write_payload(&mut tunnel, &Err::<u64, _>(refusal)).await?;
+						}
+					}
+				}
+				(CollectionRequest::Edit(edit), Some(tag_id)) => {
+					let result = match SharingAccess::from(collection.access) {
+						SharingAccess::ReadWrite => {
+							apply_edit(&library, tag_id, &collection, edit).await
+						}
+						SharingAccess::ReadOnly => Err(CollectionRefusal::ReadOnly),
+					};
+
+					write_payload(&mut tunnel, &result).await?;
+				}
+				(CollectionRequest::Changed, None) => {
+					self.changes.send((library.id, collection.id)).ok();
+					write_payload(&mut tunnel, &Ok::<_, CollectionRefusal>(())).await?;
+				}
+				(CollectionRequest::Unshare, _) => {
+					library
+						.db
+						.shared_collection()
+						.delete(shared_collection::id::equals(collection.id))
+						.exec()
+						.await?;
+
+					if collection.tag_id.is_none() {
+						self.remove_files(&collection).await;
+					}
+
+					info!(
+						"Collection '{}' isn't shared between libraries '{}' and '{}' anymore",
+						collection.name, library.id, collection.library_name
+					);
+					invalidate_query!(library, "sharing.list");
+
+					write_payload(&mut tunnel, &Ok::<_, CollectionRefusal>(())).await?;
+					return Ok(());
+				}
+				// Asked for something only the other side of the collection does
+				_ => {
+					write_payload(&mut tunnel, &Err::<(), _>(CollectionRefusal::NotShared)).await?;
+				}
+			}
+		}
+	}
+}
+
+/// Run by the library that shares the tag after sending the [`Header::Share`](super::Header::Share).
+/// The collection is only saved once the other user accepted it.
+pub(super) async fn invite(
+	stream: &mut UnicastStream,
+	library: &Library,
+	tag_id: tag::id::Type,
+	peer_id: PeerId,
+	access: SharingAccess,
+	include_files: bool,
+) -> Result<shared_collection::Data, SharingError> {
+	let tag = library
+		.db
+		.tag()
+		.find_unique(tag::id::equals(tag_id))
+		.select(tag::select!({ name color }))
+		.exec()
+		.await?
+		.ok_or(SharingError::TagNotFound(tag_id))?;
+
+	let collection_pub_id = Uuid::new_v4();
+	let name = tag.name.unwrap_or_default();
+
+	write_payload(
+		stream,
+		&ShareInvitation {
+			collection_pub_id,
+			name: name.clone(),
+			color: tag.color.clone(),
+			library_id: library.id,
+			library_name: library.config.name.clone(),
+			identity: library.identity.to_remote_identity().to_bytes().to_vec(),
+			access,
+			include_files,
+		},
+	)
+	.await?;
+
+	// The other user has as long as the invitation is shown to answer
+	let acceptance = timeout(
+		INVITATION_TIMEOUT + Duration::from_secs(10),
+		read_payload::<Option<ShareAcceptance>>(stream),
+	)
+	.await
+	.map_err(|_| SharingError::Timeout)??
+	.ok_or(SharingError::Rejected)?;
+
+	Ok(library
+		.db
+		.shared_collection()
+		.create(
+			collection_pub_id.as_bytes().to_vec(),
+			name,
+			acceptance.library_id.as_bytes().to_vec(),
+			acceptance.library_name,
+			peer_id.to_string(),
+			acceptance.identity,
+			access as i32,
+			include_files,
+			Utc::now().into(),
+			vec![
+				shared_collection::color::set(tag.color),
+				shared_collection::tag::connect(tag::id::equals(tag_id)),
+			],
+		)
+		.exec()
+		.await?)
+}
+
+/// Sends a request through the tunnel of a collection and reads the reply of the other library
+pub(super) async fn request<T: serde::de::DeserializeOwned>(
+	tunnel: &mut Tunnel,
+	request: &CollectionRequest,
+) -> Result<T, SharingError> {
+	write_payload(tunnel, request).await?;
+
+	Ok(read_payload::<Result<T, CollectionRefusal>>(tunnel).await??)
+}
+
+/// Downloads the file of an item from the library that owns the collection to `path`
+pub(super) async fn download(
+	tunnel: &mut Tunnel,
+	object_pub_id: Uuid,
+	path: &Path,
+	limiters: &[Arc<RateLimiter>],
+) -> Result<(), SharingError> {
+	let size: u64 = request(tunnel, &CollectionRequest::File { object_pub_id }).await?;
+
+	Ok(receive_file(tunnel, path, size, limiters).await?)
+}
+
+/// Sends the first `size` bytes of a file through the tunnel
+pub(super) async fn send_file(
+	tunnel: &mut Tunnel,
+	file: &mut File,
+	size: u64,
+	limiters: &[Arc<RateLimiter>],
+) -> io::Result<()> {
+	let mut remaining = size as usize;
+	let mut buf = vec![0; CHUNK_SIZE];
+	while remaining > 0 {
+		let chunk = &mut buf[..remaining.min(CHUNK_SIZE)];
+		for limiter in limiters {
+			limiter.acquire(chunk.len() as u64).await;
+		}
+
+		// The peer expects exactly `size` bytes, if the file was truncated while sending it the
+		// tunnel can't be used anymore
+		file.read_exact(chunk).await?;
+		tunnel.write_all(chunk).await?;
+		remaining -= chunk.len();
+	}
+
+	Ok(())
+}
+
+/// Writes the `size` bytes of a file the peer sends through the tunnel to `path`
+pub(super) async fn receive_file(
+	tunnel: &mut Tunnel,
+	path: &Path,
+	size: u64,
+	limiters: &[Arc<RateLimiter>],
+) -> io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).await?;
+	}
+
+	let mut file = File::create(path).await?;
+	let mut remaining = size as usize;
+	let mut buf = vec![0; CHUNK_SIZE];
+	while remaining > 0 {
+		let chunk = &mut buf[..remaining.min(CHUNK_SIZE)];
+		for limiter in limiters {
+			limiter.acquire(chunk.len() as u64).await;
+		}
+
+		tunnel.read_exact(chunk).await?;
+		file.write_all(chunk).await?;
+		remaining -= chunk.len();
+	}
+
+	file.flush().await
+}
+
+/// Where the file of an item of a collection shared with this node is downloaded to. The name
+/// comes from the other node, so it can't be used if it isn't a plain file name.
+pub(super) fn download_path(
+	shared_dir: &Path,
+	collection_pub_id: Uuid,
+	object_pub_id: Uuid,
+	name: &str,
+	extension: Option<&str>,
+) -> PathBuf {
+	shared_dir
+		.join(collection_pub_id.to_string())
+		.join(object_pub_id.to_string())
+		.join(file_name(object_pub_id, name, extension))
+}
+
+/// The name of a file sent by another node, or the pub id of its object if it isn't a plain name
+pub(super) fn file_name(object_pub_id: Uuid, name: &str, extension: Option<&str>) -> String {
+	let file_name = match extension {
+		Some(extension) if !extension.is_empty() => format!("{name}.{extension}"),
+		_ => name.to_string(),
+	};
+
+	let mut components = Path::new(&file_name).components();
+	match (components.next(), components.next()) {
+		(Some(Component::Normal(_)), None) => file_name,
+		_ => object_pub_id.to_string(),
+	}
+}
+
+/// Replaces the items of a collection shared with this library with the ones its owner sent,
+/// returning the pub ids of the objects that aren't in it anymore
+pub(super) async fn apply_snapshot(
+	library: &Library,
+	collection_id: shared_collection::id::Type,
+	snapshot: CollectionSnapshot,
+) -> Result<Vec<Uuid>, QueryError> {
+	let db = &library.db;
+
+	let current = snapshot
+		.items
+		.iter()
+		.map(|item| item.object_pub_id)
+		.collect::<HashSet<_>>();
+
+	let removed = db
+		.shared_collection_item()
+		.find_many(vec![shared_collection_item::collection_id::equals(
+			collection_id,
+		)])
+		.select(shared_collection_item::select!({ object_pub_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|item| Uuid::from_slice(&item.object_pub_id).ok())
+		.filter(|object_pub_id| !current.contains(object_pub_id))
+		.collect::<Vec<_>>();
+
+	db._batch((
+		db.shared_collection().update(
+			shared_collection::id::equals(collection_id),
+			vec![
+				shared_collection::name::set(snapshot.name),
+				shared_collection::color::set(snapshot.color),
+				shared_collection::date_synced::set(Some(Utc::now().into())),
+			],
+		),
+		db.shared_collection_item().delete_many(vec![
+			shared_collection_item::collection_id::equals(collection_id),
+			shared_collection_item::object_pub_id::in_vec(
+				removed
+					.iter()
+					.map(|pub_id| pub_id.as_bytes().to_vec())
+					.collect(),
+			),
+		]),
+	))
+	.await?;
+
+	db._batch(
+		snapshot
+			.items
+			.into_iter()
+			.map(|item| {
+				let object_pub_id = item.object_pub_id.as_bytes().to_vec();
+				let size_in_bytes = item.size_in_bytes.to_string();
+				let params = vec![
+					shared_collection_item::extension::set(item.extension),
+					shared_collection_item::date_modified::set(item.date_modified.map(Into::into)),
+				];
+
+				db.shared_collection_item().upsert(
+					shared_collection_item::collection_id_object_pub_id(
+						collection_id,
+						object_pub_id.clone(),
+					),
+					shared_collection_item::create(
+						shared_collection::id::equals(collection_id),
+						object_pub_id,
+						item.name.clone(),
+						item.kind,
+						size_in_bytes.clone(),
+						params.clone(),
+					),
+					[
+						params,
+						vec![
+							shared_collection_item::name::set(item.name),
+							shared_collection_item::kind::set(item.kind),
+							shared_collection_item::size_in_bytes::set(size_in_bytes),
+						],
+					]
+					.concat(),
+				)
+			})
+			.collect::<Vec<_>>(),
+	)
+	.await?;
+
+	Ok(removed)
+}
+
+/// The owned collections of the library whose tag is changed by the operations
+pub(super) async fn changed_collections(
+	library: &Library,
+	ops: &[CRDTOperation],
+) -> Result<Vec<shared_collection::Data>, QueryError> {
+	let tags = ops.iter().filter_map(changed_tag).collect::<HashSet<_>>();
+	if tags.is_empty() {
+		return Ok(vec![]);
+	}
+
+	library
+		.db
+		.shared_collection()
+		.find_many(vec![shared_collection::tag::is(vec![tag::pub_id::in_vec(
+			tags.into_iter().collect(),
+		)])])
+		.exec()
+		.await
+}
+
+/// The pub id of the tag the operation changes, or assigns to or removes from an object
+fn changed_tag(op: &CRDTOperation) -> Option<Vec<u8>> {
+	match &op.typ {
+		CRDTOperationType::Shared(_) => match ModelSyncData::from_op(op.typ.clone()) {
+			Some(ModelSyncData::Tag(id, _)) => Some(id.pub_id),
+			_ => None,
+		},
+		CRDTOperationType::Relation(relation_op) if relation_op.relation == tag_on_object::NAME => {
+			Some(relation_op.relation_item.as_bytes().to_vec())
+		}
+		CRDTOperationType::Relation(_) => None,
+	}
+}
+
+/// The objects of the tag, with a file that is linked to each of them
+async fn snapshot(
+	library: &Library,
+	tag_id: tag::id::Type,
+	collection: &shared_collection::Data,
+) -> Result<CollectionSnapshot, QueryError> {
+	let tag = library
+		.db
+		.tag()
+		.find_unique(tag::id::equals(tag_id))
+		.select(tag::select!({ name color }))
+		.exec()
+		.await?;
+
+	let objects = library
+		.db
+		.object()
+		.find_many(vec![object::tags::some(vec![
+			tag_on_object::tag_id::equals(tag_id),
+		])])
+		.select(object::select!({
+			pub_id
+			kind
+			file_paths: select { name extension size_in_bytes_bytes date_modified }
+		}))
+		.exec()
+		.await?;
+
+	Ok(CollectionSnapshot {
+		name: tag
+			.as_ref()
+			.and_then(|tag| tag.name.clone())
+			.unwrap_or_else(|| collection.name.clone()),
+		color: tag.and_then(|tag| tag.color),
+		items: objects
+			.into_iter()
+			.filter_map(|object| {
+				let file_path = object.file_paths.into_iter().next()?;
+
+				Some(CollectionItem {
+					object_pub_id: Uuid::from_slice(&object.pub_id).ok()?,
+					name: file_path.name.unwrap_or_default(),
+					extension: file_path.extension,
+					kind: object.kind.unwrap_or_default(),
+					size_in_bytes: file_path
+						.size_in_bytes_bytes
+						.and_then(|bytes| bytes.try_into().ok())
+						.map(u64::from_be_bytes)
+						.unwrap_or_default(),
+					date_modified: file_path.date_modified.map(Into::into),
+				})
+			})
+			.collect(),
+	})
+}
+
+/// Opens the file of an item of the tag that is on this node, with its size
+async fn open_item(
+	library: &Library,
+	tag_id: tag::id::Type,
+	object_pub_id: Uuid,
+) -> Result<(File, u64), CollectionRefusal> {
+	let object = library
+		.db
+		.object()
+		.find_first(vec![
+			object::pub_id::equals(object_pub_id.as_bytes().to_vec()),
+			object::tags::some(vec![tag_on_object::tag_id::equals(tag_id)]),
+		])
+		.select(object::select!({ file_paths: select { id } }))
+		.exec()
+		.await?
+		.ok_or(CollectionRefusal::NotFound)?;
+
+	let paths = library
+		.get_file_paths(
+			object
+				.file_paths
+				.into_iter()
+				.map(|file_path| file_path.id)
+				.collect(),
+		)
+		.await
+		.map_err(|e| CollectionRefusal::Internal(e.to_string()))?;
+
+	open_first_file(paths.into_values().flatten())
+		.await?
+		.ok_or(CollectionRefusal::NotFound)
+}
+
+/// Opens the first of the paths that is a file on this node, with its size
+pub(super) async fn open_first_file(
+	paths: impl IntoIterator<Item = PathBuf>,
+) -> io::Result<Option<(File, u64)>> {
+	for path in paths {
+		let Ok(file) = File::open(&path).await else {
+			continue;
+		};
+
+		let metadata = file.metadata().await?;
+		if metadata.is_file() {
+			return Ok(Some((file, metadata.len())));
+		}
+	}
+
+	Ok(None)
+}
+
+/// Applies a change made by the library the collection is shared with, through sync like the
+/// user's own changes to the tag
+async fn apply_edit(
+	library: &Library,
+	tag_id: tag::id::Type,
+	collection: &shared_collection::Data,
+	edit: CollectionEdit,
+) -> Result<(), CollectionRefusal> {
+	let Library { db, sync, .. } = library;
+
+	let tag_pub_id = db
+		.tag()
+		.find_unique(tag::id::equals(tag_id))
+		.select(tag::select!({ pub_id }))
+		.exec()
+		.await?
+		.ok_or(CollectionRefusal::NotShared)?
+		.pub_id;
+
+	match edit {
+		CollectionEdit::Update { name, color } => {
+			sync.write_ops(
+				db,
+				(
+					[
+						name.as_ref().map(|v| (tag::name::NAME, json!(v))),
+						color.as_ref().map(|v| (tag::color::NAME, json!(v))),
+					]
+					.into_iter()
+					.flatten()
+					.map(|(k, v)| {
+						sync.shared_update(
+							sync::tag::SyncId {
+								pub_id: tag_pub_id.clone(),
+							},
+							k,
+							v,
+						)
+					})
+					.collect(),
+					db.tag().update(
+						tag::id::equals(tag_id),
+						[
+							name.clone().map(|name| tag::name::set(Some(name))),
+							color.clone().map(|color| tag::color::set(Some(color))),
+							Some(tag::date_modified::set(Some(Utc::now().into()))),
+						]
+						.into_iter()
+						.flatten()
+						.collect(),
+					),
+				),
+			)
+			.await?;
+
+			db.shared_collection()
+				.update(
+					shared_collection::id::equals(collection.id),
+					[
+						name.map(shared_collection::name::set),
+						color.map(|color| shared_collection::color::set(Some(color))),
+					]
+					.into_iter()
+					.flatten()
+					.collect(),
+				)
+				.exec()
+				.await?;
+
+			invalidate_query!(library, "tags.list");
+		}
+		CollectionEdit::RemoveItem { object_pub_id } => {
+			let tag_pub_id = Uuid::from_slice(&tag_pub_id)
+				.map_err(|e| CollectionRefusal::Internal(e.to_string()))?;
+
+			sync.write_ops(
+				db,
+				(
+					vec![sync.relation_delete(tag_on_object::NAME, tag_pub_id, object_pub_id)],
+					db.tag_on_object().delete_many(vec![
+						tag_on_object::tag_id::equals(tag_id),
+						tag_on_object::object::is(vec![object::pub_id::equals(
+							object_pub_id.as_bytes().to_vec(),
+						)]),
+					]),
+				),
+			)
+			.await?;
+
+			invalidate_query!(library, "tags.getForObject");
+		}
+	}
+
+	invalidate_query!(library, "sharing.list");
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_sync::{RelationOperation, RelationOperationData, SharedOperation, SharedOperationData};
+	use uhlc::NTP64;
+
+	fn op(typ: CRDTOperationType) -> CRDTOperation {
+		CRDTOperation {
+			id: Uuid::new_v4(),
+			node: Uuid::new_v4(),
+			timestamp: NTP64(0),
+			typ,
+		}
+	}
+
+	#[test]
+	fn changes_to_tags_are_found() {
+		let (tag, object) = (Uuid::new_v4(), Uuid::new_v4());
+
+		let rename = op(CRDTOperationType::Shared(SharedOperation {
+			record_id: json!({ "pub_id": tag.as_bytes() }),
+			model: "Tag".into(),
+			data: SharedOperationData::Update {
+				field: tag::name::NAME.into(),
+				value: json!("Holidays"),
+				base: None,
+			},
+		}));
+		assert_eq!(changed_tag(&rename), Some(tag.as_bytes().to_vec()));
+
+		let assign = op(CRDTOperationType::Relation(RelationOperation {
+			relation_item: tag,
+			relation_group: object,
+			relation: tag_on_object::NAME.into(),
+			data: RelationOperationData::Create,
+		}));
+		assert_eq!(changed_tag(&assign), Some(tag.as_bytes().to_vec()));
+
+		let favorite = op(CRDTOperationType::Shared(SharedOperation {
+			record_id: json!({ "pub_id": object.as_bytes() }),
+			model: "Object".into(),
+			data: SharedOperationData::Update {
+				field: "favorite".into(),
+				value: json!(true),
+				base: None,
+			},
+		}));
+		assert_eq!(changed_tag(&favorite), None);
+	}
+
+	#[test]
+	fn downloads_stay_in_the_collection() {
+		let (dir, collection, object) = (Path::new("/shared"), Uuid::new_v4(), Uuid::new_v4());
+		let base = dir.join(collection.to_string()).join(object.to_string());
+
+		assert_eq!(
+			download_path(dir, collection, object, "beach", Some("jpg")),
+			base.join("beach.jpg")
+		);
+		assert_eq!(
+			download_path(dir, collection, object, "README", None),
+			base.join("README")
+		);
+		assert_eq!(
+			download_path(dir, collection, object, "../../etc/passwd", None),
+			base.join(object.to_string())
+		);
+		assert_eq!(
+			download_path(dir, collection, object, "..", Some("")),
+			base.join(object.to_string())
+		);
+	}
+}
@@ -0,0 +1,952 @@
+use crate::util::error::FileIOError;
+
+use std::{
+	collections::BTreeSet,
+	fs::Metadata,
+	future::Future,
+	io, iter,
+	path::{Component, Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::{Duration, UNIX_EPOCH},
+};
+
+use filetime::{set_file_mtime, FileTime};
+use sd_p2p::{
+	spaceblock::{
+		checksum, fingerprint, BlockSize, ChunkFingerprint, RateLimiter, SpaceblockDirectory,
+		SpaceblockError, SpaceblockFile, SpaceblockRequest, SpaceblockResume, Transfer,
+		CHECKSUM_SIZE, MAX_DIRECTORIES, MAX_FILES, MAX_NAME_LEN,
+	},
+	PeerId,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+	fs::{self, File, OpenOptions},
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	sync::mpsc,
+	time::{interval, timeout},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+pub(super) const SPACEDROP_DIR: &str = "spacedrop";
+const INCOMING_DIR: &str = "incoming";
+const OUTGOING_DIR: &str = "outgoing";
+/// Where the chunk fingerprints of the files received are stored
+const CHUNKS_DIR: &str = "chunks";
+
+/// Files received over a copy at least this big only receive the chunks that changed
+const DELTA_MIN_SIZE: u64 = 8 * 1024 * 1024;
+/// Appended to the name of the copy of a file that's being received again, while it's received
+const BASIS_EXTENSION: &str = "sdbasis";
+
+/// How often the progress of a Spacedrop being received is saved
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many streams the files are received over when there's enough left of them to receive
+const PARALLEL_STREAMS: u8 = 4;
+const PARALLEL_MIN_SIZE: u64 = 32 * 1024 * 1024;
+/// How long to wait for each of the other streams the sender said it opened
+const STREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent by the receiver once every file was received and verified
+const TRANSFER_VERIFIED: u8 = 1;
+const TRANSFER_FAILED: u8 = 0;
+
+#[derive(Debug, Error)]
+pub enum SpacedropError {
+	#[error("no files to send")]
+	NoFiles,
+	#[error("too many files to send, at most {} can be sent at once", MAX_FILES)]
+	TooManyFiles,
+	#[error(
+		"too many folders to send, at most {} can be sent at once",
+		MAX_DIRECTORIES
+	)]
+	TooManyDirectories,
+	#[error("the name of '{}' is too long to be sent", .0.display())]
+	NameTooLong(PathBuf),
+	#[error("failed to open a stream to the peer")]
+	PeerUnreachable,
+	#[error("the files changed since the Spacedrop started")]
+	FilesChanged,
+	#[error("the peer couldn't verify the files it received")]
+	VerificationFailed,
+	#[error("the peer didn't open the streams to send the files over")]
+	MissingStreams,
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error("io error: {0}")]
+	Io(#[from] io::Error),
+	#[error("error with the Spacedrop state: {0}")]
+	State(#[from] serde_json::Error),
+	#[error("error storing the chunks of a file: {0}")]
+	Chunks(#[from] rmp_serde::encode::Error),
+	#[error(transparent)]
+	Spaceblock(#[from] SpaceblockError),
+}
+
+impl From<SpacedropError> for rspc::Error {
+	fn from(e: SpacedropError) -> Self {
+		let code = match e {
+			SpacedropError::NoFiles
+			| SpacedropError::TooManyFiles
+			| SpacedropError::TooManyDirectories
+			| SpacedropError::NameTooLong(_) => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Direction {
+	Incoming,
+	Outgoing,
+}
+
+impl Direction {
+	fn dir(&self, spacedrop_dir: &Path) -> PathBuf {
+		spacedrop_dir.join(match self {
+			Self::Incoming => INCOMING_DIR,
+			Self::Outgoing => OUTGOING_DIR,
+		})
+	}
+}
+
+/// A file of a Spacedrop, with where it's read from or written to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(super) struct SpacedropFile {
+	pub path: PathBuf,
+	/// The path relative to what's being sent, with `/` separators
+	pub name: String,
+	pub size: u64,
+	/// Milliseconds since the unix epoch, `0` if unknown
+	#[serde(default)]
+	pub modified: u64,
+	pub checksum: [u8; CHECKSUM_SIZE],
+	/// How much of the file was received and verified, always 0 when sending
+	pub offset: u64,
+	/// The copy of the file that was already at the path when receiving it, moved aside so only
+	/// the chunks that changed are received
+	#[serde(default)]
+	pub basis: Option<PathBuf>,
+}
+
+/// A directory of a Spacedrop, sent so the structure of folders is kept even if they're empty
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(super) struct SpacedropDirectory {
+	pub path: PathBuf,
+	pub name: String,
+	pub modified: u64,
+}
+
+/// A Spacedrop in progress, saved so it can be resumed from the last verified block of every file
+/// when the connection drops or the app is closed
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct SpacedropState {
+	pub id: Uuid,
+	pub peer_id: String,
+	pub files: Vec<SpacedropFile>,
+	#[serde(default)]
+	pub directories: Vec<SpacedropDirectory>,
+	/// Sent from the transfer queue, which retries it instead of it being resumed when the peer
+	/// is found again
+	#[serde(default)]
+	pub queued: bool,
+}
+
+fn modified_millis(metadata: &Metadata) -> u64 {
+	metadata
+		.modified()
+		.ok()
+		.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+		.map(|duration| duration.as_millis() as u64)
+		.unwrap_or(0)
+}
+
+/// Turns a name from the peer into a relative path, dropping anything that could point outside of
+/// the directory the Spacedrop is saved in
+fn safe_relative_path(name: &str) -> PathBuf {
+	let path = name
+		.split(['/', '\\'])
+		.map(Path::new)
+		.flat_map(|part| part.components())
+		.filter_map(|component| match component {
+			Component::Normal(part) => Some(part),
+			_ => None,
+		})
+		.collect::<PathBuf>();
+
+	if path.as_os_str().is_empty() {
+		PathBuf::from("file")
+	} else {
+		path
+	}
+}
+
+/// The chunk fingerprints of a file that was received, so the chunks of its copy are known without
+/// reading it the next time it's received
+#[derive(Serialize, Deserialize)]
+struct StoredChunks {
+	size: u64,
+	modified: u64,
+	chunks: Vec<ChunkFingerprint>,
+}
+
+fn chunks_path(spacedrop_dir: &Path, path: &Path) -> PathBuf {
+	spacedrop_dir.join(CHUNKS_DIR).join(
+		blake3::hash(path.to_string_lossy().as_bytes())
+			.to_hex()
+			.as_str(),
+	)
+}
+
+/// Fingerprints the chunks of a file that was received and stores them
+async fn store_chunks(spacedrop_dir: &Path, path: &Path) -> Result<(), SpacedropError> {
+	let file = File::open(path)
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?;
+	let metadata = file
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?;
+	let chunks = fingerprint(BufReader::new(file))
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?;
+
+	let dir = spacedrop_dir.join(CHUNKS_DIR);
+	fs::create_dir_all(&dir)
+		.await
+		.map_err(|e| FileIOError::from((&dir, e)))?;
+
+	let stored = StoredChunks {
+		size: metadata.len(),
+		modified: modified_millis(&metadata),
+		chunks,
+	};
+
+	let chunks_path = chunks_path(spacedrop_dir, path);
+	fs::write(&chunks_path, rmp_serde::to_vec(&stored)?)
+		.await
+		.map_err(|e| FileIOError::from((chunks_path, e)).into())
+}
+
+/// The chunks of the copy of a file, from the ones stored when it was received if it wasn't
+/// modified since, or by reading it otherwise
+async fn basis_chunks(
+	spacedrop_dir: &Path,
+	path: &Path,
+	basis: &mut File,
+) -> Result<Vec<ChunkFingerprint>, io::Error> {
+	let metadata = basis.metadata().await?;
+
+	if let Ok(data) = fs::read(chunks_path(spacedrop_dir, path)).await {
+		if let Ok(stored) = rmp_serde::from_slice::<StoredChunks>(&data) {
+			let modified = modified_millis(&metadata);
+			if stored.size == metadata.len() && modified != 0 && stored.modified == modified {
+				return Ok(stored.chunks);
+			}
+		}
+	}
+
+	fingerprint(BufReader::new(basis)).await
+}
+
+/// Moves the copy of a file at its path aside so it can be received over it, if it's big enough
+/// for only receiving the chunks that changed to be worth it
+async fn move_aside(path: &Path) -> Result<Option<PathBuf>, SpacedropError> {
+	match fs::metadata(path).await {
+		Ok(metadata) if metadata.is_file() && metadata.len() >= DELTA_MIN_SIZE => {}
+		_ => return Ok(None),
+	}
+
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(".");
+	name.push(BASIS_EXTENSION);
+	let basis = path.with_file_name(name);
+
+	fs::rename(path, &basis)
+		.await
+		.map_err(|e| FileIOError::from((path, e)))?;
+
+	Ok(Some(basis))
+}
+
+async fn hash_file(path: PathBuf, name: String) -> Result<SpacedropFile, SpacedropError> {
+	let file = File::open(&path)
+		.await
+		.map_err(|e| FileIOError::from((&path, e)))?;
+	let metadata = file
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((&path, e)))?;
+	let checksum = checksum(BufReader::new(file))
+		.await
+		.map_err(|e| FileIOError::from((&path, e)))?;
+
+	Ok(SpacedropFile {
+		path,
+		name,
+		size: metadata.len(),
+		modified: modified_millis(&metadata),
+		checksum,
+		offset: 0,
+		basis: None,
+	})
+}
+
+impl SpacedropState {
+	/// Prepares sending files, hashing them so the receiver can verify them once received
+	pub async fn outgoing(
+		id: Uuid,
+		peer_id: PeerId,
+		paths: Vec<PathBuf>,
+	) -> Result<Self, SpacedropError> {
+		if paths.is_empty() {
+			return Err(SpacedropError::NoFiles);
+		}
+
+		let mut files = Vec::with_capacity(paths.len());
+		let mut directories = Vec::new();
+
+		// Folders are sent with everything in them, named by their path inside of the folder
+		let mut pending = paths
+			.into_iter()
+			.map(|path| {
+				let name = path
+					.file_name()
+					.map(|name| name.to_string_lossy().to_string())
+					.unwrap_or_default();
+				(path, name)
+			})
+			.collect::<Vec<_>>();
+		pending.reverse();
+
+		while let Some((path, name)) = pending.pop() {
+			if name.len() > MAX_NAME_LEN {
+				return Err(SpacedropError::NameTooLong(path));
+			}
+
+			// Symlinks aren't followed so a folder can't contain itself
+			let metadata = fs::symlink_metadata(&path)
+				.await
+				.map_err(|e| FileIOError::from((&path, e)))?;
+
+			if metadata.is_dir() {
+				if directories.len() == MAX_DIRECTORIES {
+					return Err(SpacedropError::TooManyDirectories);
+				}
+
+				let mut entries = Vec::new();
+				let mut read_dir = fs::read_dir(&path)
+					.await
+					.map_err(|e| FileIOError::from((&path, e)))?;
+				while let Some(entry) = read_dir
+					.next_entry()
+					.await
+					.map_err(|e| FileIOError::from((&path, e)))?
+				{
+					entries.push((
+						entry.path(),
+						format!("{name}/{}", entry.file_name().to_string_lossy()),
+					));
+				}
+
+				// Sorted so the same folder is always sent in the same order, which resuming relies on
+				entries.sort();
+				pending.extend(entries.into_iter().rev());
+
+				directories.push(SpacedropDirectory {
+					path,
+					name,
+					modified: modified_millis(&metadata),
+				});
+			} else if metadata.is_file() {
+				// Checked while walking the folders, so a huge folder isn't hashed before failing
+				if files.len() == MAX_FILES {
+					return Err(SpacedropError::TooManyFiles);
+				}
+
+				files.push(hash_file(path, name).await?);
+			} else {
+				warn!(
+					"Not sending '{}' as it isn't a file or folder",
+					path.display()
+				);
+			}
+		}
+
+		if files.is_empty() && directories.is_empty() {
+			return Err(SpacedropError::NoFiles);
+		}
+
+		Ok(Self {
+			id,
+			peer_id: peer_id.to_string(),
+			files,
+			directories,
+			queued: false,
+		})
+	}
+
+	/// Prepares receiving the files of a request. `target` is where the file is saved when there's
+	/// only one, and the directory everything is saved in otherwise.
+	pub fn incoming(req: &SpaceblockRequest, peer_id: PeerId, target: PathBuf) -> Self {
+		let single_file = req.files.len() == 1 && req.directories.is_empty();
+
+		Self {
+			id: req.id,
+			peer_id: peer_id.to_string(),
+			files: req
+				.files
+				.iter()
+				.map(|file| SpacedropFile {
+					path: if single_file {
+						target.clone()
+					} else {
+						target.join(safe_relative_path(&file.name))
+					},
+					name: file.name.clone(),
+					size: file.size,
+					modified: file.modified,
+					checksum: file.checksum,
+					offset: 0,
+					basis: None,
+				})
+				.collect(),
+			directories: req
+				.directories
+				.iter()
+				.map(|directory| SpacedropDirectory {
+					path: target.join(safe_relative_path(&directory.name)),
+					name: directory.name.clone(),
+					modified: directory.modified,
+				})
+				.collect(),
+			queued: false,
+		}
+	}
+
+	pub fn request(&self) -> SpaceblockRequest {
+		let files = self
+			.files
+			.iter()
+			.map(|file| SpaceblockFile {
+				name: file.name.clone(),
+				size: file.size,
+				modified: file.modified,
+				checksum: file.checksum,
+			})
+			.collect::<Vec<_>>();
+
+		SpaceblockRequest {
+			id: self.id,
+			block_size: BlockSize::from_size(files.iter().map(|file| file.size).sum()), // TODO: This should be dynamic
+			files,
+			directories: self
+				.directories
+				.iter()
+				.map(|directory| SpaceblockDirectory {
+					name: directory.name.clone(),
+					modified: directory.modified,
+				})
+				.collect(),
+		}
+	}
+
+	/// Sets the modified time of what was received to the one it had on the sender. Directories
+	/// are done last and deepest first, as creating what's in them changes it.
+	fn restore_timestamps(&self) {
+		let files = self.files.iter().map(|file| (&file.path, file.modified));
+		let mut directories = self
+			.directories
+			.iter()
+			.map(|directory| (&directory.path, directory.modified))
+			.collect::<Vec<_>>();
+		directories.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+
+		for (path, modified) in files.chain(directories) {
+			if modified == 0 {
+				continue;
+			}
+
+			let time = FileTime::from_system_time(UNIX_EPOCH + Duration::from_millis(modified));
+			if let Err(e) = set_file_mtime(path, time) {
+				warn!(
+					"Failed to set the modified time of '{}': {e}",
+					path.display()
+				);
+			}
+		}
+	}
+
+	/// Deletes the copies the files were received over, and stores the chunks of the files so
+	/// they can be received over again. The chunks are stored in the background, as it means
+	/// reading every file.
+	fn finish_deltas(&self, spacedrop_dir: &Path) {
+		let mut paths = Vec::new();
+
+		for file in &self.files {
+			if let Some(basis) = &file.basis {
+				if let Err(e) = std::fs::remove_file(basis) {
+					warn!("Failed to remove '{}': {e}", basis.display());
+				}
+			}
+
+			if file.size >= DELTA_MIN_SIZE {
+				paths.push(file.path.clone());
+			}
+		}
+
+		if paths.is_empty() {
+			return;
+		}
+
+		let spacedrop_dir = spacedrop_dir.to_path_buf();
+		tokio::spawn(async move {
+			for path in paths {
+				if let Err(e) = store_chunks(&spacedrop_dir, &path).await {
+					warn!("Failed to store the chunks of '{}': {e}", path.display());
+				}
+			}
+		});
+	}
+
+	/// Checks if this is the state of a transfer being requested again, so it can be resumed
+	pub fn matches(&self, req: &SpaceblockRequest, peer_id: PeerId) -> bool {
+		self.peer_id == peer_id.to_string() && self.request() == *req
+	}
+
+	fn set_offsets(&mut self, offsets: &[u64]) {
+		for (file, offset) in self.files.iter_mut().zip(offsets) {
+			file.offset = *offset;
+		}
+	}
+
+	fn path(spacedrop_dir: &Path, direction: Direction, id: Uuid) -> PathBuf {
+		direction
+			.dir(spacedrop_dir)
+			.join(id.to_string())
+			.with_extension("json")
+	}
+
+	pub async fn load(
+		spacedrop_dir: &Path,
+		direction: Direction,
+		id: Uuid,
+	) -> Result<Option<Self>, SpacedropError> {
+		let path = Self::path(spacedrop_dir, direction, id);
+
+		match fs::read(&path).await {
+			Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(FileIOError::from((path, e)).into()),
+		}
+	}
+
+	/// Every saved transfer going in this direction, used to resume them
+	pub async fn list(
+		spacedrop_dir: &Path,
+		direction: Direction,
+	) -> Result<Vec<Self>, SpacedropError> {
+		let dir = direction.dir(spacedrop_dir);
+
+		let mut read_dir = match fs::read_dir(&dir).await {
+			Ok(read_dir) => read_dir,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(FileIOError::from((dir, e)).into()),
+		};
+
+		let mut states = vec![];
+		while let Some(entry) = read_dir
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((&dir, e)))?
+		{
+			let path = entry.path();
+			match fs::read(&path).await {
+				Ok(data) => match serde_json::from_slice(&data) {
+					Ok(state) => states.push(state),
+					Err(e) => warn!("Ignoring invalid Spacedrop state '{}': {e}", path.display()),
+				},
+				Err(e) => warn!("Failed to read Spacedrop state '{}': {e}", path.display()),
+			}
+		}
+
+		Ok(states)
+	}
+
+	pub async fn save(
+		&self,
+		spacedrop_dir: &Path,
+		direction: Direction,
+	) -> Result<(), SpacedropError> {
+		let dir = direction.dir(spacedrop_dir);
+		fs::create_dir_all(&dir)
+			.await
+			.map_err(|e| FileIOError::from((&dir, e)))?;
+
+		let path = Self::path(spacedrop_dir, direction, self.id);
+		fs::write(&path, serde_json::to_vec(self)?)
+			.await
+			.map_err(|e| FileIOError::from((path, e)).into())
+	}
+
+	pub async fn remove(
+		spacedrop_dir: &Path,
+		direction: Direction,
+		id: Uuid,
+	) -> Result<(), SpacedropError> {
+		let path = Self::path(spacedrop_dir, direction, id);
+
+		match fs::remove_file(&path).await {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => {
+				Err(FileIOError::from((path, e)).into())
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// The top level files and folders of a request, to show what's being sent
+pub(super) fn display_name(req: &SpaceblockRequest) -> String {
+	req.files
+		.iter()
+		.map(|file| &file.name)
+		.chain(req.directories.iter().map(|directory| &directory.name))
+		.filter_map(|name| name.split('/').next())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Sends the files of a Spacedrop from where the receiver asks for, once the header with the
+/// request was written to the stream. Returns `false` if the receiver rejected it.
+/// `open_streams` opens up to the number of other streams it's given to the receiver, when it
+/// asks for the files to be sent over multiple streams.
+pub(super) async fn send<S, Fut>(
+	spacedrop_dir: &Path,
+	stream: &mut S,
+	state: &SpacedropState,
+	rate_limiters: Vec<Arc<RateLimiter>>,
+	open_streams: impl FnOnce(usize) -> Fut,
+	on_progress: impl Fn(u8),
+) -> Result<bool, SpacedropError>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+	Fut: Future<Output = Vec<S>>,
+{
+	if stream.read_u8().await? != 1 {
+		SpacedropState::remove(spacedrop_dir, Direction::Outgoing, state.id).await?;
+		return Ok(false);
+	}
+
+	let req = state.request();
+	let resume = SpaceblockResume::from_stream(stream, &req).await?;
+
+	let mut files = Vec::with_capacity(state.files.len());
+	for file in &state.files {
+		files.push(
+			File::open(&file.path)
+				.await
+				.map_err(|e| FileIOError::from((&file.path, e)))?,
+		);
+	}
+
+	let transfer = Transfer::new(&req, on_progress).with_rate_limiters(rate_limiters);
+	if resume.streams > 1 {
+		let mut others = open_streams(resume.streams as usize - 1).await;
+		// The receiver only waits for the streams that could be opened
+		stream.write_all(&[others.len() as u8]).await?;
+
+		let streams = iter::once(&mut *stream)
+			.chain(others.iter_mut())
+			.collect::<Vec<_>>();
+		transfer.send_parallel(streams, files, &resume).await?;
+	} else {
+		transfer.send(stream, files, &resume).await?;
+	}
+
+	if stream.read_u8().await? != TRANSFER_VERIFIED {
+		return Err(SpacedropError::VerificationFailed);
+	}
+
+	SpacedropState::remove(spacedrop_dir, Direction::Outgoing, state.id).await?;
+
+	Ok(true)
+}
+
+/// Receives the files of an accepted Spacedrop, continuing from the last verified block of each
+/// of them. The progress is saved while receiving, so the transfer can be resumed if it fails.
+/// The other streams the sender opens to send the files over are received from `other_streams`.
+pub(super) async fn receive<S>(
+	spacedrop_dir: &Path,
+	stream: &mut S,
+	mut state: SpacedropState,
+	rate_limiters: Vec<Arc<RateLimiter>>,
+	mut other_streams: mpsc::Receiver<S>,
+	on_progress: impl Fn(u8),
+) -> Result<(), SpacedropError>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	for file in &mut state.files {
+		if file.offset == 0 && file.basis.is_none() && file.size >= DELTA_MIN_SIZE {
+			file.basis = move_aside(&file.path).await?;
+		}
+	}
+
+	state.save(spacedrop_dir, Direction::Incoming).await?;
+
+	// The chunks of the copies are sent to the peer, so it only sends the ones that changed
+	let mut bases = Vec::with_capacity(state.files.len());
+	let mut chunks = Vec::with_capacity(state.files.len());
+	for file in &mut state.files {
+		let Some(basis) = file.basis.clone() else {
+			bases.push(None);
+			chunks.push(vec![]);
+			continue;
+		};
+
+		let mut f = match File::open(&basis).await {
+			Ok(f) => f,
+			// Deleted while the transfer was interrupted, so the whole file is received again
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				file.basis = None;
+				file.offset = 0;
+				bases.push(None);
+				chunks.push(vec![]);
+				continue;
+			}
+			Err(e) => return Err(FileIOError::from((&basis, e)).into()),
+		};
+
+		chunks.push(
+			basis_chunks(spacedrop_dir, &file.path, &mut f)
+				.await
+				.map_err(|e| FileIOError::from((&basis, e)))?,
+		);
+		bases.push(Some(f));
+	}
+
+	for directory in &state.directories {
+		fs::create_dir_all(&directory.path)
+			.await
+			.map_err(|e| FileIOError::from((&directory.path, e)))?;
+	}
+
+	let mut files = Vec::with_capacity(state.files.len());
+	for file in &state.files {
+		if let Some(parent) = file.path.parent() {
+			fs::create_dir_all(parent)
+				.await
+				.map_err(|e| FileIOError::from((parent, e)))?;
+		}
+
+		let f = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.open(&file.path)
+			.await
+			.map_err(|e| FileIOError::from((&file.path, e)))?;
+
+		// Anything after the last verified block could be partially written
+		f.set_len(file.offset)
+			.await
+			.map_err(|e| FileIOError::from((&file.path, e)))?;
+
+		files.push(f);
+	}
+
+	let req = state.request();
+	// Files received as a delta need the blocks in order
+	let remaining = state
+		.files
+		.iter()
+		.map(|file| file.size - file.offset)
+		.sum::<u64>();
+	let streams = if chunks.iter().all(Vec::is_empty) && remaining >= PARALLEL_MIN_SIZE {
+		PARALLEL_STREAMS
+	} else {
+		1
+	};
+
+	let resume = SpaceblockResume {
+		offsets: state.files.iter().map(|file| file.offset).collect(),
+		bases: chunks,
+		streams,
+	};
+
+	stream.write_all(&[1]).await?;
+	stream.write_all(&resume.to_bytes()).await?;
+
+	let mut others = Vec::new();
+	if resume.streams > 1 {
+		let count = stream.read_u8().await?;
+		if count >= resume.streams {
+			return Err(SpacedropError::MissingStreams);
+		}
+
+		for _ in 0..count {
+			match timeout(STREAM_TIMEOUT, other_streams.recv()).await {
+				Ok(Some(other)) => others.push(other),
+				_ => return Err(SpacedropError::MissingStreams),
+			}
+		}
+	}
+
+	let offsets = Mutex::new(resume.offsets.clone());
+	let on_verified = |i: usize, offset| {
+		offsets.lock().unwrap()[i] = offset;
+	};
+
+	let result = {
+		let transfer = Transfer::new(&req, on_progress).with_rate_limiters(rate_limiters);
+		let receive = async {
+			if resume.streams > 1 {
+				let streams = iter::once(&mut *stream)
+					.chain(others.iter_mut())
+					.collect::<Vec<_>>();
+				transfer
+					.receive_parallel(streams, &mut files, &resume, on_verified)
+					.await
+			} else {
+				transfer
+					.receive(&mut *stream, &mut files, &mut bases, &resume, on_verified)
+					.await
+			}
+		};
+		tokio::pin!(receive);
+
+		let mut checkpoint = interval(CHECKPOINT_INTERVAL);
+		loop {
+			tokio::select! {
+				result = &mut receive => break result,
+				_ = checkpoint.tick() => {
+					let offsets = offsets.lock().unwrap().clone();
+					state.set_offsets(&offsets);
+					if let Err(e) = state.save(spacedrop_dir, Direction::Incoming).await {
+						warn!("Failed to save the progress of Spacedrop '{}': {e}", state.id);
+					}
+				}
+			}
+		}
+	};
+
+	state.set_offsets(&offsets.into_inner().unwrap());
+
+	match result {
+		Ok(()) => {
+			// Closed first so writing them doesn't change the times again
+			drop(files);
+			drop(bases);
+			state.restore_timestamps();
+			state.finish_deltas(spacedrop_dir);
+
+			stream.write_all(&[TRANSFER_VERIFIED]).await?;
+			SpacedropState::remove(spacedrop_dir, Direction::Incoming, state.id).await
+		}
+		Err(e) => {
+			// A file that doesn't match its checksum is received again from the start
+			if let SpaceblockError::FileChecksumMismatch(name) = &e {
+				if let Some(file) = state.files.iter_mut().find(|file| &file.name == name) {
+					file.offset = 0;
+				}
+
+				stream.write_all(&[TRANSFER_FAILED]).await.ok();
+			}
+
+			// The stored chunks of the copy were wrong, so it's read the next time
+			if let SpaceblockError::BasisMismatch(name) = &e {
+				if let Some(file) = state.files.iter_mut().find(|file| &file.name == name) {
+					file.offset = 0;
+					fs::remove_file(chunks_path(spacedrop_dir, &file.path))
+						.await
+						.ok();
+				}
+			}
+
+			state.save(spacedrop_dir, Direction::Incoming).await?;
+
+			Err(e.into())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+	use tempfile::tempdir;
+
+	#[test]
+	fn names_stay_inside_the_target() {
+		assert_eq!(safe_relative_path("a/b.txt"), Path::new("a").join("b.txt"));
+		assert_eq!(
+			safe_relative_path("../../etc/passwd"),
+			Path::new("etc").join("passwd")
+		);
+		assert_eq!(
+			safe_relative_path("/abs\\..\\file"),
+			Path::new("abs").join("file")
+		);
+		assert_eq!(safe_relative_path(".."), Path::new("file"));
+	}
+
+	#[tokio::test]
+	async fn folders_are_sent_with_their_structure() {
+		let dir = tempdir().unwrap();
+		let folder = dir.path().join("Photos");
+		fs::create_dir_all(folder.join("2023")).await.unwrap();
+		fs::create_dir_all(folder.join("Empty")).await.unwrap();
+		fs::write(folder.join("2023").join("a.jpg"), b"a")
+			.await
+			.unwrap();
+		fs::write(dir.path().join("notes.txt"), b"notes")
+			.await
+			.unwrap();
+
+		let peer_id = Keypair::generate().peer_id();
+		let state = SpacedropState::outgoing(
+			Uuid::new_v4(),
+			peer_id,
+			vec![folder, dir.path().join("notes.txt")],
+		)
+		.await
+		.unwrap();
+
+		let req = state.request();
+		assert_eq!(
+			req.files
+				.iter()
+				.map(|f| f.name.as_str())
+				.collect::<Vec<_>>(),
+			["Photos/2023/a.jpg", "notes.txt"]
+		);
+		assert_eq!(
+			req.directories
+				.iter()
+				.map(|d| d.name.as_str())
+				.collect::<Vec<_>>(),
+			["Photos", "Photos/2023", "Photos/Empty"]
+		);
+		assert_eq!(display_name(&req), "Photos, notes.txt");
+
+		let target = dir.path().join("received");
+		let incoming = SpacedropState::incoming(&req, peer_id, target.clone());
+		assert_eq!(
+			incoming.files[0].path,
+			target.join("Photos").join("2023").join("a.jpg")
+		);
+		assert_eq!(
+			incoming.directories[2].path,
+			target.join("Photos").join("Empty")
+		);
+	}
+}
@@ -0,0 +1,117 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use sd_p2p::PeerId;
+use uuid::Uuid;
+
+/// How the exchanges of a library's operations with a peer went since the node started
+#[derive(Debug, Default, Clone)]
+pub struct PeerSyncMetrics {
+	/// When operations were last sent to or received from the peer
+	pub last_exchange: Option<DateTime<Utc>>,
+	/// How long the last exchange took, from sending the operations or the request to the reply
+	pub round_trip: Option<Duration>,
+	pub bytes_sent: u64,
+	pub bytes_received: u64,
+	/// How far ahead the peer's clock is of ours, negative if it's behind
+	pub clock_skew_ms: Option<i64>,
+}
+
+/// Metrics of the sync exchanges with each peer, kept in memory to debug why nodes drifted apart
+#[derive(Debug, Default)]
+pub struct SyncMetrics {
+	peers: Mutex<HashMap<(Uuid, PeerId), PeerSyncMetrics>>,
+}
+
+impl SyncMetrics {
+	fn update(&self, library_id: Uuid, peer_id: PeerId, f: impl FnOnce(&mut PeerSyncMetrics)) {
+		f(self
+			.peers
+			.lock()
+			.unwrap()
+			.entry((library_id, peer_id))
+			.or_default())
+	}
+
+	pub fn record_sent(&self, library_id: Uuid, peer_id: PeerId, bytes: usize) {
+		self.update(library_id, peer_id, |peer| peer.bytes_sent += bytes as u64);
+	}
+
+	pub fn record_received(&self, library_id: Uuid, peer_id: PeerId, bytes: usize) {
+		self.update(library_id, peer_id, |peer| {
+			peer.bytes_received += bytes as u64
+		});
+	}
+
+	/// An exchange with the peer succeeded. `round_trip` is `None` when we only replied to it.
+	pub fn record_exchange(&self, library_id: Uuid, peer_id: PeerId, round_trip: Option<Duration>) {
+		self.update(library_id, peer_id, |peer| {
+			peer.last_exchange = Some(Utc::now());
+			if let Some(round_trip) = round_trip {
+				peer.round_trip = Some(round_trip);
+			}
+		});
+	}
+
+	/// Compares the time the peer told us it sent a request at with ours. The time the request
+	/// took to arrive is counted as skew, which is fine for the drifts worth reporting.
+	pub fn record_clock(&self, library_id: Uuid, peer_id: PeerId, peer_time: DateTime<Utc>) {
+		self.update(library_id, peer_id, |peer| {
+			peer.clock_skew_ms = Some((peer_time - Utc::now()).num_milliseconds())
+		});
+	}
+
+	/// The bytes sent and received with every peer, for every library
+	pub fn totals(&self) -> (u64, u64) {
+		self.peers
+			.lock()
+			.unwrap()
+			.values()
+			.fold((0, 0), |(sent, received), peer| {
+				(sent + peer.bytes_sent, received + peer.bytes_received)
+			})
+	}
+
+	pub fn get(&self, library_id: Uuid, peer_id: PeerId) -> PeerSyncMetrics {
+		self.peers
+			.lock()
+			.unwrap()
+			.get(&(library_id, peer_id))
+			.cloned()
+			.unwrap_or_default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+
+	#[test]
+	fn metrics_are_kept_per_library() {
+		let metrics = SyncMetrics::default();
+		let peer_id = Keypair::generate().peer_id();
+		let (library_id, other_library_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+		metrics.record_sent(library_id, peer_id, 100);
+		metrics.record_sent(library_id, peer_id, 50);
+		metrics.record_received(other_library_id, peer_id, 10);
+		metrics.record_exchange(library_id, peer_id, Some(Duration::from_millis(80)));
+		metrics.record_exchange(library_id, peer_id, None);
+		metrics.record_clock(
+			library_id,
+			peer_id,
+			Utc::now() + chrono::Duration::seconds(30),
+		);
+
+		let peer = metrics.get(library_id, peer_id);
+		assert_eq!(peer.bytes_sent, 150);
+		assert_eq!(peer.bytes_received, 0);
+		assert_eq!(peer.round_trip, Some(Duration::from_millis(80)));
+		assert!(peer.last_exchange.is_some());
+		assert!((29_000..=30_000).contains(&peer.clock_skew_ms.unwrap()));
+
+		assert_eq!(metrics.get(other_library_id, peer_id).bytes_received, 10);
+	}
+}
@@ -0,0 +1,337 @@
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use sd_p2p::PeerId;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::sync::{broadcast, Notify};
+
+/// How long we wait before sending operations to a peer again after its first poor delivery,
+/// doubled after each one that follows
+const BACKOFF_BASE: Duration = Duration::from_secs(30);
+
+/// When the operations made on this device are sent to the paired nodes, configured per device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct SyncSchedule {
+	/// How long to wait for more operations before sending them together, in milliseconds
+	pub batch_delay_ms: u32,
+	/// Operations are sent without waiting once this many are waiting
+	pub max_batch_size: u32,
+	/// How long to wait on a metered connection, in seconds. `None` doesn't send anything until
+	/// the connection isn't metered anymore.
+	pub metered_delay_secs: Option<u32>,
+	/// A peer that takes longer than this to acknowledge operations has a poor connection, in
+	/// milliseconds
+	pub poor_latency_ms: u32,
+	/// The longest we wait before sending operations to a peer with a poor connection again, in
+	/// seconds
+	pub max_backoff_secs: u32,
+	/// Sends operations as soon as they're made while the device is idle and plugged in
+	pub eager_when_idle: bool,
+}
+
+impl Default for SyncSchedule {
+	fn default() -> Self {
+		Self {
+			batch_delay_ms: 2000,
+			max_batch_size: 1000,
+			metered_delay_secs: Some(600),
+			poor_latency_ms: 5000,
+			max_backoff_secs: 900,
+			eager_when_idle: true,
+		}
+	}
+}
+
+/// What the app knows about the device, the core can't tell it on every platform
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct DeviceConditions {
+	pub metered: bool,
+	pub on_battery: bool,
+	pub idle: bool,
+}
+
+/// How long to wait before sending a batch of operations, `None` to hold them
+fn batch_delay(schedule: &SyncSchedule, conditions: &DeviceConditions) -> Option<Duration> {
+	if schedule.eager_when_idle && conditions.idle && !conditions.on_battery {
+		Some(Duration::ZERO)
+	} else if conditions.metered {
+		schedule
+			.metered_delay_secs
+			.map(|secs| Duration::from_secs(secs as u64))
+	} else {
+		Some(Duration::from_millis(schedule.batch_delay_ms as u64))
+	}
+}
+
+#[derive(Debug, Default)]
+struct PeerSync {
+	/// Poor deliveries in a row
+	poor_deliveries: u32,
+	retry_at: Option<Instant>,
+	/// The peer missed some operations, it gets everything newer than what it acknowledged next
+	behind: bool,
+}
+
+/// Decides when operations are sent to the paired nodes, from the [`SyncSchedule`] of the device
+/// and how the deliveries to each peer went
+#[derive(Debug)]
+pub struct SyncScheduler {
+	schedule: Mutex<SyncSchedule>,
+	conditions: Mutex<DeviceConditions>,
+	peers: Mutex<HashMap<PeerId, PeerSync>>,
+	/// Notified when the schedule or the conditions change, so the batches waiting are sent
+	/// with the new delay
+	changed: Notify,
+	/// Peers that are back after missing some operations, they're sent them right away
+	replays: broadcast::Sender<PeerId>,
+}
+
+impl SyncScheduler {
+	pub fn new(schedule: SyncSchedule) -> Self {
+		Self {
+			schedule: Mutex::new(schedule),
+			conditions: Default::default(),
+			peers: Default::default(),
+			changed: Notify::new(),
+			replays: broadcast::channel(16).0,
+		}
+	}
+
+	pub fn set_schedule(&self, schedule: SyncSchedule) {
+		*self.schedule.lock().unwrap() = schedule;
+		self.changed.notify_waiters();
+	}
+
+	pub fn set_conditions(&self, conditions: DeviceConditions) {
+		*self.conditions.lock().unwrap() = conditions;
+		self.changed.notify_waiters();
+	}
+
+	pub fn conditions(&self) -> DeviceConditions {
+		*self.conditions.lock().unwrap()
+	}
+
+	pub async fn changed(&self) {
+		self.changed.notified().await
+	}
+
+	pub fn batch_delay(&self) -> Option<Duration> {
+		batch_delay(
+			&self.schedule.lock().unwrap(),
+			&self.conditions.lock().unwrap(),
+		)
+	}
+
+	pub fn max_batch_size(&self) -> usize {
+		self.schedule.lock().unwrap().max_batch_size.max(1) as usize
+	}
+
+	/// Whether operations can be sent to the peer now. The ones it doesn't get are sent once it's
+	/// done backing off.
+	pub fn can_send(&self, peer_id: PeerId) -> bool {
+		let mut peers = self.peers.lock().unwrap();
+		let peer = peers.entry(peer_id).or_default();
+
+		match peer.retry_at {
+			Some(retry_at) if retry_at > Instant::now() => {
+				peer.behind = true;
+				false
+			}
+			_ => true,
+		}
+	}
+
+	/// The peer couldn't be reached, the operations it missed are replayed once it's back
+	pub fn mark_behind(&self, peer_id: PeerId) {
+		self.peers
+			.lock()
+			.unwrap()
+			.entry(peer_id)
+			.or_default()
+			.behind = true;
+	}
+
+	/// Called when the peer is discovered or connected to again. It's done backing off, and gets
+	/// the operations it missed if there are any.
+	pub fn reconnected(&self, peer_id: PeerId) {
+		let mut peers = self.peers.lock().unwrap();
+		let Some(peer) = peers.get_mut(&peer_id) else {
+			return;
+		};
+
+		peer.poor_deliveries = 0;
+		peer.retry_at = None;
+
+		if peer.behind {
+			self.replays.send(peer_id).ok();
+		}
+	}
+
+	pub fn subscribe_replays(&self) -> broadcast::Receiver<PeerId> {
+		self.replays.subscribe()
+	}
+
+	pub fn is_behind(&self, peer_id: PeerId) -> bool {
+		self.peers
+			.lock()
+			.unwrap()
+			.get(&peer_id)
+			.map_or(false, |peer| peer.behind)
+	}
+
+	/// Records how long the peer took to acknowledge the operations we sent it, `None` if it
+	/// didn't. Slow or failed deliveries back off exponentially.
+	pub fn record_delivery(&self, peer_id: PeerId, latency: Option<Duration>) {
+		let (poor_latency, max_backoff) = {
+			let schedule = self.schedule.lock().unwrap();
+			(
+				Duration::from_millis(schedule.poor_latency_ms as u64),
+				Duration::from_secs(schedule.max_backoff_secs as u64),
+			)
+		};
+
+		let mut peers = self.peers.lock().unwrap();
+		let peer = peers.entry(peer_id).or_default();
+
+		match latency {
+			Some(latency) if latency <= poor_latency => *peer = PeerSync::default(),
+			_ => {
+				peer.behind |= latency.is_none();
+				peer.poor_deliveries += 1;
+
+				let backoff = BACKOFF_BASE
+					.saturating_mul(2u32.saturating_pow(peer.poor_deliveries - 1))
+					.min(max_backoff);
+				peer.retry_at = Some(Instant::now() + backoff);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_p2p::Keypair;
+
+	#[test]
+	fn delay_follows_the_conditions() {
+		let schedule = SyncSchedule::default();
+
+		assert_eq!(
+			batch_delay(&schedule, &DeviceConditions::default()),
+			Some(Duration::from_secs(2))
+		);
+		assert_eq!(
+			batch_delay(
+				&schedule,
+				&DeviceConditions {
+					metered: true,
+					..Default::default()
+				}
+			),
+			Some(Duration::from_secs(600))
+		);
+		assert_eq!(
+			batch_delay(
+				&SyncSchedule {
+					metered_delay_secs: None,
+					..Default::default()
+				},
+				&DeviceConditions {
+					metered: true,
+					..Default::default()
+				}
+			),
+			None
+		);
+
+		// Idle on AC power is eager even on a metered connection
+		assert_eq!(
+			batch_delay(
+				&schedule,
+				&DeviceConditions {
+					metered: true,
+					on_battery: false,
+					idle: true,
+				}
+			),
+			Some(Duration::ZERO)
+		);
+		assert_eq!(
+			batch_delay(
+				&schedule,
+				&DeviceConditions {
+					metered: false,
+					on_battery: true,
+					idle: true,
+				}
+			),
+			Some(Duration::from_secs(2))
+		);
+	}
+
+	#[test]
+	fn poor_deliveries_back_off() {
+		let scheduler = SyncScheduler::new(SyncSchedule::default());
+		let peer_id = Keypair::generate().peer_id();
+
+		assert!(scheduler.can_send(peer_id));
+
+		scheduler.record_delivery(peer_id, Some(Duration::from_millis(100)));
+		assert!(scheduler.can_send(peer_id));
+		assert!(!scheduler.is_behind(peer_id));
+
+		// Slow but delivered, the peer has the operations
+		scheduler.record_delivery(peer_id, Some(Duration::from_secs(10)));
+		assert!(!scheduler.can_send(peer_id));
+
+		scheduler.record_delivery(peer_id, None);
+		let backoff = scheduler.peers.lock().unwrap()[&peer_id]
+			.retry_at
+			.unwrap()
+			.duration_since(Instant::now());
+		assert!(backoff > BACKOFF_BASE && backoff <= BACKOFF_BASE * 2);
+		assert!(scheduler.is_behind(peer_id));
+
+		for _ in 0..20 {
+			scheduler.record_delivery(peer_id, None);
+		}
+		let backoff = scheduler.peers.lock().unwrap()[&peer_id]
+			.retry_at
+			.unwrap()
+			.duration_since(Instant::now());
+		assert!(backoff <= Duration::from_secs(900));
+
+		scheduler.record_delivery(peer_id, Some(Duration::from_millis(100)));
+		assert!(scheduler.can_send(peer_id));
+		assert!(!scheduler.is_behind(peer_id));
+	}
+
+	#[test]
+	fn missed_operations_are_replayed_on_reconnect() {
+		let scheduler = SyncScheduler::new(SyncSchedule::default());
+		let mut replays = scheduler.subscribe_replays();
+		let (peer_id, other_peer_id) =
+			(Keypair::generate().peer_id(), Keypair::generate().peer_id());
+
+		scheduler.reconnected(peer_id);
+		assert!(replays.try_recv().is_err());
+
+		scheduler.record_delivery(peer_id, None);
+		scheduler.mark_behind(other_peer_id);
+		assert!(!scheduler.can_send(peer_id));
+
+		scheduler.reconnected(peer_id);
+		assert!(scheduler.can_send(peer_id));
+		assert_eq!(replays.try_recv().unwrap(), peer_id);
+
+		scheduler.reconnected(other_peer_id);
+		assert_eq!(replays.try_recv().unwrap(), other_peer_id);
+	}
+}
@@ -0,0 +1,199 @@
+use crate::{
+	api::CoreEvent,
+	library::Library,
+	object::preview::{find_thumbnail, get_thumb_key, get_thumbnail_path, ThumbnailFormat},
+	prisma::{file_path, location, node},
+	util::error::FileIOError,
+};
+
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+	str::FromStr,
+	time::Duration,
+};
+
+use sd_p2p::{spacetunnel::Tunnel, PeerId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs;
+use tracing::debug;
+
+use super::{read_payload, write_payload, SyncCatchUpError};
+
+/// How long to wait for a peer to send a batch of thumbnails before asking another one
+pub(super) const THUMBNAIL_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait before asking for a thumbnail again, so items scrolling in and out of view
+/// don't ask the peers for thumbnails they don't have over and over
+pub(super) const THUMBNAIL_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// The most thumbnails asked for at once, the peer only answers for these
+pub(super) const MAX_THUMBNAILS_PER_REQUEST: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum ThumbnailRequestError {
+	#[error(transparent)]
+	Peer(#[from] SyncCatchUpError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+}
+
+/// Sent for each of the cas_ids asked for after a [`Header::Thumbnail`](super::Header::Thumbnail),
+/// `None` if the peer doesn't have it
+#[derive(Serialize, Deserialize)]
+struct RemoteThumbnail {
+	format: ThumbnailFormat,
+	data: Vec<u8>,
+}
+
+/// cas_ids are hex, anything else could escape the thumbnail directory
+pub(super) fn is_valid_cas_id(cas_id: &str) -> bool {
+	cas_id.len() > 3 && cas_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The peers we can ask for the thumbnails of files that only exist on other nodes. Files we have
+/// get their thumbnails generated locally instead.
+pub(super) async fn thumbnail_peers(
+	library: &Library,
+	cas_ids: &[String],
+) -> Result<HashMap<String, Vec<PeerId>>, prisma_client_rust::QueryError> {
+	let local = library
+		.db
+		.file_path()
+		.find_many(vec![
+			file_path::cas_id::in_vec(cas_ids.to_vec()),
+			file_path::location::is(vec![location::node_id::equals(Some(library.node_local_id))]),
+		])
+		.select(file_path::select!({ cas_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|file_path| file_path.cas_id)
+		.collect::<HashSet<_>>();
+
+	let mut peers = HashMap::new();
+
+	for cas_id in cas_ids {
+		if local.contains(cas_id) || !is_valid_cas_id(cas_id) {
+			continue;
+		}
+
+		let nodes = library
+			.db
+			.node()
+			.find_many(vec![
+				node::id::not(library.node_local_id),
+				node::location::some(vec![location::file_paths::some(vec![
+					file_path::cas_id::equals(Some(cas_id.clone())),
+				])]),
+			])
+			.select(node::select!({ node_peer_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|node| PeerId::from_str(&node.node_peer_id?).ok())
+			.collect::<Vec<_>>();
+
+		if !nodes.is_empty() {
+			peers.insert(cas_id.clone(), nodes);
+		}
+	}
+
+	Ok(peers)
+}
+
+/// Asks the peer for the thumbnails of these cas_ids and saves the ones it has. Each thumbnail is
+/// announced to the clients as soon as it's saved.
+pub(super) async fn request_thumbnails(
+	tunnel: &mut Tunnel,
+	library: &Library,
+	thumbnail_dir: &Path,
+	cas_ids: &[String],
+) -> Result<(), ThumbnailRequestError> {
+	write_payload(tunnel, &cas_ids).await?;
+
+	for cas_id in cas_ids {
+		let Some(thumbnail) = read_payload::<Option<RemoteThumbnail>>(tunnel).await? else {
+			continue;
+		};
+
+		let path = get_thumbnail_path(thumbnail_dir, cas_id, thumbnail.format);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)
+				.await
+				.map_err(|e| FileIOError::from((parent, e)))?;
+		}
+		fs::write(&path, &thumbnail.data)
+			.await
+			.map_err(|e| FileIOError::from((&path, e)))?;
+
+		debug!("Received thumbnail '{cas_id}' from a peer");
+
+		library.thumbnail_cache().created(cas_id).await;
+		library.emit(CoreEvent::NewThumbnail {
+			thumb_key: get_thumb_key(cas_id),
+		});
+	}
+
+	Ok(())
+}
+
+/// Sends the thumbnails the peer asked for, in the order it asked for them
+pub(super) async fn respond_thumbnails(
+	tunnel: &mut Tunnel,
+	library: &Library,
+	thumbnail_dir: &Path,
+) -> Result<(), ThumbnailRequestError> {
+	let mut cas_ids: Vec<String> = read_payload(tunnel).await?;
+	cas_ids.truncate(MAX_THUMBNAILS_PER_REQUEST);
+
+	// The thumbnail cache is shared by all libraries, we only share the ones of this library's files
+	let shared = library
+		.db
+		.file_path()
+		.find_many(vec![
+			file_path::cas_id::in_vec(cas_ids.clone()),
+			file_path::location::is(vec![location::node_id::equals(Some(library.node_local_id))]),
+		])
+		.select(file_path::select!({ cas_id }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|file_path| file_path.cas_id)
+		.collect::<HashSet<_>>();
+
+	for cas_id in &cas_ids {
+		let thumbnail = if is_valid_cas_id(cas_id) && shared.contains(cas_id) {
+			match find_thumbnail(thumbnail_dir, cas_id).await? {
+				Some((path, format)) => Some(RemoteThumbnail {
+					format,
+					data: fs::read(&path)
+						.await
+						.map_err(|e| FileIOError::from((&path, e)))?,
+				}),
+				None => None,
+			}
+		} else {
+			None
+		};
+
+		write_payload(tunnel, &thumbnail).await?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cas_ids_stay_in_the_thumbnail_directory() {
+		assert!(is_valid_cas_id("0a1b2c3d4e5f6789"));
+		assert!(!is_valid_cas_id("../../etc/passwd"));
+		assert!(!is_valid_cas_id("0a1/../.."));
+		assert!(!is_valid_cas_id("0a"));
+		assert!(!is_valid_cas_id(""));
+	}
+}
@@ -0,0 +1,233 @@
+//! Extracting the metadata of the identified files of a location with the plugins extracting
+//! metadata from them. Each file is a step, and what a plugin extracts from it is kept for its
+//! object, replacing what it extracted before.
+
+use crate::{
+	invalidate_query,
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobLoad, JobLocations, JobResult,
+		JobRunErrors, JobRunMetadata, JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+	location::file_path_helper::{file_path_for_plugin_metadata, IsolatedFilePathData},
+	object::fs::get_location_path_from_location_id,
+	prisma::{file_path, location, plugin_metadata},
+	util::db::maybe_missing,
+};
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tracing::{info, warn};
+
+use super::PluginManagerError;
+
+pub struct PluginMetadataJob {}
+
+#[derive(Serialize, Deserialize, Type, Hash, Debug)]
+pub struct PluginMetadataJobInit {
+	pub location_id: location::id::Type,
+	/// Only the extractors of this plugin, all of them when not set
+	#[serde(default)]
+	#[specta(optional)]
+	pub plugin_id: Option<String>,
+}
+
+impl JobInitData for PluginMetadataJobInit {
+	type Job = PluginMetadataJob;
+
+	fn locations(&self) -> JobLocations {
+		JobLocations::Some(vec![self.location_id])
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginMetadataJobData {
+	location_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginMetadataJobStep {
+	file_path: file_path_for_plugin_metadata::Data,
+	/// The plugins extracting metadata from the file
+	plugin_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PluginMetadataJobRunMetadata {
+	extracted: u32,
+	/// Files a plugin found no metadata in
+	empty: u32,
+}
+
+impl JobRunMetadata for PluginMetadataJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.extracted += new_data.extracted;
+		self.empty += new_data.empty;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for PluginMetadataJob {
+	type Init = PluginMetadataJobInit;
+	type Data = PluginMetadataJobData;
+	type Step = PluginMetadataJobStep;
+	type RunMetadata = PluginMetadataJobRunMetadata;
+
+	const NAME: &'static str = "plugin_metadata";
+	const IS_LOW_PRIORITY: bool = true;
+	const LOAD: JobLoad = JobLoad::ReadHeavy;
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let plugins = ctx.library.plugins();
+
+		let extensions = plugins
+			.list()
+			.into_iter()
+			.filter(|plugin| init.plugin_id.as_ref().map_or(true, |id| &plugin.id == id))
+			.flat_map(|plugin| plugin.extensions)
+			.map(|extension| extension.to_lowercase())
+			.collect::<Vec<_>>();
+
+		if extensions.is_empty() {
+			return Err(match &init.plugin_id {
+				Some(plugin_id) => PluginManagerError::NotFound(plugin_id.clone()),
+				None => PluginManagerError::Unavailable,
+			}
+			.into());
+		}
+
+		let location_path =
+			get_location_path_from_location_id(&ctx.library.db, init.location_id).await?;
+
+		let steps = ctx
+			.library
+			.db
+			.file_path()
+			.find_many(vec![
+				file_path::location_id::equals(Some(init.location_id)),
+				file_path::is_dir::equals(Some(false)),
+				file_path::object_id::not(None),
+				file_path::extension::in_vec(extensions),
+			])
+			.select(file_path_for_plugin_metadata::select())
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|file_path| {
+				let extension = file_path.extension.as_deref()?;
+				let plugin_ids = plugins
+					.extractors(extension)
+					.into_iter()
+					.filter(|id| init.plugin_id.as_ref().map_or(true, |only| only == id))
+					.collect::<Vec<_>>();
+
+				(!plugin_ids.is_empty()).then_some(PluginMetadataJobStep {
+					file_path,
+					plugin_ids,
+				})
+			})
+			.collect::<Vec<_>>();
+
+		*data = Some(PluginMetadataJobData { location_path });
+
+		Ok((PluginMetadataJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let PluginMetadataJobStep {
+			file_path,
+			plugin_ids,
+		} = step;
+
+		let object_id = maybe_missing(file_path.object_id, "file_path.object_id")?;
+		let extension = maybe_missing(&file_path.extension, "file_path.extension")?;
+		let full_path = data.location_path.join(IsolatedFilePathData::try_from((
+			init.location_id,
+			file_path,
+		))?);
+
+		ctx.progress_msg(job_message!(
+			"plugins.extractingMetadata",
+			path = full_path.display()
+		));
+
+		let mut metadata = PluginMetadataJobRunMetadata::default();
+		let mut errors = vec![];
+
+		for plugin_id in plugin_ids {
+			match ctx
+				.library
+				.plugins()
+				.extract_metadata(plugin_id, &full_path, extension)
+				.await
+			{
+				Ok(Some(extracted)) => {
+					let data = serde_json::to_vec(&extracted)?;
+
+					ctx.library
+						.db
+						.plugin_metadata()
+						.upsert(
+							plugin_metadata::object_id_plugin_id(object_id, plugin_id.clone()),
+							plugin_metadata::create_unchecked(
+								object_id,
+								plugin_id.clone(),
+								data.clone(),
+								Utc::now().into(),
+								vec![],
+							),
+							vec![
+								plugin_metadata::data::set(data),
+								plugin_metadata::date_created::set(Utc::now().into()),
+							],
+						)
+						.exec()
+						.await?;
+
+					metadata.extracted += 1;
+				}
+				Ok(None) => metadata.empty += 1,
+				Err(e) => {
+					let error = format!(
+						"Plugin '{plugin_id}' failed to extract metadata from {}: {e}",
+						full_path.display()
+					);
+					warn!("{error}");
+					errors.push(error);
+				}
+			}
+		}
+
+		Ok((vec![], metadata, JobRunErrors(errors)).into())
+	}
+
+	async fn finalize(&self, ctx: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		info!(
+			"Extracted metadata {} times with plugins, {} files had none",
+			state.run_metadata.extracted, state.run_metadata.empty
+		);
+
+		invalidate_query!(ctx.library, "plugins.metadata");
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
@@ -0,0 +1,285 @@
+//! Plugins, WebAssembly modules in the `plugins` directory of the node adding metadata extractors,
+//! jobs and search providers, see the `sd-plugins` crate for what they look like. They're loaded
+//! when the node starts and only with the `plugins` feature, without it the node has none.
+
+use std::{path::Path, sync::Arc};
+
+use serde::Serialize;
+use serde_json::Value;
+use specta::Type;
+use thiserror::Error;
+
+pub mod metadata_job;
+pub mod plugin_job;
+
+#[derive(Serialize, Type, Clone, Debug)]
+pub struct PluginInfo {
+	pub id: String,
+	pub name: String,
+	pub version: String,
+	pub description: Option<String>,
+	/// What the plugin can reach of the node, like `ReadFile`
+	pub capabilities: Vec<String>,
+	/// The extensions of the files it extracts metadata from
+	pub extensions: Vec<String>,
+	pub search: bool,
+	pub jobs: Vec<String>,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct PluginSearchResult {
+	pub plugin_id: String,
+	pub title: String,
+	pub subtitle: Option<String>,
+	pub uri: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum PluginManagerError {
+	#[error("plugin not found: {0}")]
+	NotFound(String),
+	#[error("plugins aren't available in this build of Spacedrive")]
+	Unavailable,
+	#[cfg(feature = "plugins")]
+	#[error(transparent)]
+	Plugin(#[from] sd_plugins::PluginError),
+	#[error("plugin call panicked: {0}")]
+	Join(#[from] tokio::task::JoinError),
+}
+
+impl From<PluginManagerError> for rspc::Error {
+	fn from(e: PluginManagerError) -> Self {
+		let code = match e {
+			PluginManagerError::NotFound(_) => rspc::ErrorCode::NotFound,
+			PluginManagerError::Unavailable => rspc::ErrorCode::BadRequest,
+			_ => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// The plugins of the node. Calling a plugin blocks while it runs, so calls are made on the
+/// blocking threads of tokio.
+pub struct PluginManager {
+	#[cfg(feature = "plugins")]
+	plugins: Vec<Arc<sd_plugins::Plugin>>,
+}
+
+#[cfg(feature = "plugins")]
+impl PluginManager {
+	pub async fn load(plugins_dir: &Path) -> Arc<Self> {
+		let plugins_dir = plugins_dir.to_path_buf();
+		let (plugins, errors) =
+			tokio::task::spawn_blocking(move || sd_plugins::load_all(&plugins_dir))
+				.await
+				.unwrap_or_default();
+
+		for e in errors {
+			tracing::error!("Failed to load a plugin: {e}");
+		}
+		for plugin in &plugins {
+			let manifest = plugin.manifest();
+			tracing::info!("Loaded plugin '{}' {}", manifest.id, manifest.version);
+		}
+
+		Arc::new(Self {
+			plugins: plugins.into_iter().map(Arc::new).collect(),
+		})
+	}
+
+	fn get(&self, plugin_id: &str) -> Result<Arc<sd_plugins::Plugin>, PluginManagerError> {
+		self.plugins
+			.iter()
+			.find(|plugin| plugin.id() == plugin_id)
+			.cloned()
+			.ok_or_else(|| PluginManagerError::NotFound(plugin_id.to_string()))
+	}
+
+	pub fn list(&self) -> Vec<PluginInfo> {
+		self.plugins
+			.iter()
+			.map(|plugin| {
+				let manifest = plugin.manifest();
+				PluginInfo {
+					id: manifest.id.clone(),
+					name: manifest.name.clone(),
+					version: manifest.version.clone(),
+					description: manifest.description.clone(),
+					capabilities: manifest
+						.capabilities
+						.iter()
+						.map(|capability| format!("{capability:?}"))
+						.collect(),
+					extensions: manifest.provides.extensions.clone(),
+					search: manifest.provides.search,
+					jobs: manifest.provides.jobs.clone(),
+				}
+			})
+			.collect()
+	}
+
+	/// The ids of the plugins extracting metadata from files with this extension
+	pub fn extractors(&self, extension: &str) -> Vec<String> {
+		self.plugins
+			.iter()
+			.filter(|plugin| plugin.extracts(extension))
+			.map(|plugin| plugin.id().to_string())
+			.collect()
+	}
+
+	pub async fn extract_metadata(
+		&self,
+		plugin_id: &str,
+		path: &Path,
+		extension: &str,
+	) -> Result<Option<Value>, PluginManagerError> {
+		let plugin = self.get(plugin_id)?;
+		let path = path.to_path_buf();
+		let extension = extension.to_string();
+
+		tokio::task::spawn_blocking(move || {
+			let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+			let name = path
+				.file_stem()
+				.map(|name| name.to_string_lossy().into_owned())
+				.unwrap_or_default();
+
+			plugin.extract_metadata(
+				&path,
+				&sd_plugins::ExtractInput {
+					name: &name,
+					extension: &extension,
+					size,
+				},
+			)
+		})
+		.await?
+		.map_err(Into::into)
+	}
+
+	/// Searches with every plugin providing search, a plugin failing only loses its own results
+	pub async fn search(&self, query: &str, take: u32) -> Vec<PluginSearchResult> {
+		let mut results = vec![];
+
+		for plugin in self.plugins.iter().filter(|p| p.manifest().provides.search) {
+			let plugin = plugin.clone();
+			let query = query.to_string();
+
+			match tokio::task::spawn_blocking(move || {
+				let found = plugin.search(&sd_plugins::SearchInput {
+					query: &query,
+					take,
+				});
+				(plugin.id().to_string(), found)
+			})
+			.await
+			{
+				Ok((plugin_id, Ok(found))) => {
+					results.extend(found.into_iter().take(take as usize).map(|result| {
+						PluginSearchResult {
+							plugin_id: plugin_id.clone(),
+							title: result.title,
+							subtitle: result.subtitle,
+							uri: result.uri,
+						}
+					}))
+				}
+				Ok((_, Err(e))) => tracing::warn!("Plugin search failed: {e}"),
+				Err(e) => tracing::warn!("Plugin search panicked: {e}"),
+			}
+		}
+
+		results
+	}
+
+	/// The steps of a job of a plugin
+	pub async fn job_init(
+		&self,
+		plugin_id: &str,
+		job: &str,
+		args: Value,
+	) -> Result<Vec<Value>, PluginManagerError> {
+		let plugin = self.get(plugin_id)?;
+		let job = job.to_string();
+
+		tokio::task::spawn_blocking(move || {
+			plugin
+				.job_init(&sd_plugins::JobInitInput {
+					job: &job,
+					args: &args,
+				})
+				.map(|output| output.steps)
+		})
+		.await?
+		.map_err(Into::into)
+	}
+
+	/// Runs a step of a job of a plugin, returning the errors it didn't stop at
+	pub async fn job_step(
+		&self,
+		plugin_id: &str,
+		job: &str,
+		step: Value,
+	) -> Result<Vec<String>, PluginManagerError> {
+		let plugin = self.get(plugin_id)?;
+		let job = job.to_string();
+
+		tokio::task::spawn_blocking(move || {
+			plugin
+				.job_step(&sd_plugins::JobStepInput {
+					job: &job,
+					step: &step,
+				})
+				.map(|output| output.errors)
+		})
+		.await?
+		.map_err(Into::into)
+	}
+}
+
+#[cfg(not(feature = "plugins"))]
+impl PluginManager {
+	pub async fn load(_: &Path) -> Arc<Self> {
+		Arc::new(Self {})
+	}
+
+	pub fn list(&self) -> Vec<PluginInfo> {
+		vec![]
+	}
+
+	pub fn extractors(&self, _: &str) -> Vec<String> {
+		vec![]
+	}
+
+	pub async fn extract_metadata(
+		&self,
+		_: &str,
+		_: &Path,
+		_: &str,
+	) -> Result<Option<Value>, PluginManagerError> {
+		Err(PluginManagerError::Unavailable)
+	}
+
+	pub async fn search(&self, _: &str, _: u32) -> Vec<PluginSearchResult> {
+		vec![]
+	}
+
+	pub async fn job_init(
+		&self,
+		_: &str,
+		_: &str,
+		_: Value,
+	) -> Result<Vec<Value>, PluginManagerError> {
+		Err(PluginManagerError::Unavailable)
+	}
+
+	pub async fn job_step(
+		&self,
+		_: &str,
+		_: &str,
+		_: Value,
+	) -> Result<Vec<String>, PluginManagerError> {
+		Err(PluginManagerError::Unavailable)
+	}
+}
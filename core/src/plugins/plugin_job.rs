@@ -0,0 +1,111 @@
+//! Running a job a plugin provides. The plugin turns the arguments of the job into its steps, then
+//! runs them one at a time, so the job can be paused and resumed like any other.
+
+use crate::{
+	job::{
+		CurrentStep, JobError, JobInitData, JobInitOutput, JobResult, JobRunErrors, JobRunMetadata,
+		JobState, JobStepOutput, StatefulJob, WorkerContext,
+	},
+	job_message,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use tracing::info;
+
+pub struct PluginJob {}
+
+#[derive(Serialize, Deserialize, Type, Hash, Debug)]
+pub struct PluginJobInit {
+	pub plugin_id: String,
+	/// The name of the job, one of the jobs of the plugin
+	pub job: String,
+	/// The arguments of the job as JSON, passed to the plugin as they are
+	pub args: String,
+}
+
+impl JobInitData for PluginJobInit {
+	type Job = PluginJob;
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PluginJobRunMetadata {
+	steps_run: u32,
+}
+
+impl JobRunMetadata for PluginJobRunMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.steps_run += new_data.steps_run;
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for PluginJob {
+	type Init = PluginJobInit;
+	type Data = ();
+	type Step = Value;
+	type RunMetadata = PluginJobRunMetadata;
+
+	const NAME: &'static str = "plugin";
+
+	fn new() -> Self {
+		Self {}
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let args = serde_json::from_str(&init.args)?;
+
+		let steps = ctx
+			.library
+			.plugins()
+			.job_init(&init.plugin_id, &init.job, args)
+			.await?;
+
+		*data = Some(());
+
+		Ok((PluginJobRunMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		init: &Self::Init,
+		CurrentStep { step, step_number }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		ctx.progress_msg(job_message!(
+			"plugins.runningStep",
+			step = step_number + 1,
+			job = init.job
+		));
+
+		let errors = ctx
+			.library
+			.plugins()
+			.job_step(&init.plugin_id, &init.job, step.clone())
+			.await?;
+
+		Ok((
+			vec![],
+			PluginJobRunMetadata { steps_run: 1 },
+			JobRunErrors(errors),
+		)
+			.into())
+	}
+
+	async fn finalize(&self, _: &WorkerContext, state: &JobState<Self>) -> JobResult {
+		info!(
+			"Ran {} steps of the job '{}' of the plugin '{}'",
+			state.run_metadata.steps_run, state.init.job, state.init.plugin_id
+		);
+
+		Ok(Some(serde_json::to_value(&state.init)?))
+	}
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use sd_crypto::{
+	crypto::{Decryptor, Encryptor},
+	types::{Algorithm, Key, Nonce},
+	Protected,
+};
+
+const ALGORITHM: Algorithm = Algorithm::XChaCha20Poly1305;
+
+/// Encrypts the data of the sync operations stored in the library's database, which holds the
+/// names and metadata of its files. The key is kept in the library's config, so a copy of the
+/// database alone can't be read. Operations are decrypted when they are read back, whether to
+/// apply or to send them.
+#[derive(Clone)]
+pub struct OperationCipher {
+	key: Key,
+}
+
+impl fmt::Debug for OperationCipher {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("OperationCipher").finish_non_exhaustive()
+	}
+}
+
+impl OperationCipher {
+	pub fn new(key: &[u8]) -> Result<Self, sd_crypto::Error> {
+		Ok(Self {
+			key: Key::try_from(Protected::new(key.to_vec()))?,
+		})
+	}
+
+	/// A new random key for a library
+	pub fn generate_key() -> Vec<u8> {
+		Key::generate().expose().to_vec()
+	}
+
+	/// The nonce is prepended to the encrypted data. The id of the operation is authenticated with
+	/// it, so the data of an operation can't be swapped with another's.
+	pub async fn encrypt(&self, op_id: &[u8], data: &[u8]) -> Result<Vec<u8>, sd_crypto::Error> {
+		let nonce = Nonce::generate(ALGORITHM)?;
+
+		let mut encrypted = nonce.to_vec();
+		encrypted.extend(
+			Encryptor::encrypt_bytes(self.key.clone(), nonce, ALGORITHM, data, op_id).await?,
+		);
+
+		Ok(encrypted)
+	}
+
+	pub async fn decrypt(&self, op_id: &[u8], data: &[u8]) -> Result<Vec<u8>, sd_crypto::Error> {
+		let nonce_len = ALGORITHM.nonce_len();
+		if data.len() < nonce_len {
+			return Err(sd_crypto::Error::Decrypt);
+		}
+
+		let (nonce, data) = data.split_at(nonce_len);
+
+		Ok(Decryptor::decrypt_bytes(
+			self.key.clone(),
+			Nonce::try_from(nonce.to_vec())?,
+			ALGORITHM,
+			data,
+			op_id,
+		)
+		.await?
+		.expose()
+		.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn operations_round_trip() {
+		let cipher = OperationCipher::new(&OperationCipher::generate_key()).unwrap();
+		let data = br#"{"u":{"field":"name","value":"tax return 2023.pdf"}}"#;
+
+		let encrypted = cipher.encrypt(b"op", data).await.unwrap();
+		assert!(!encrypted.windows(10).any(|w| w == b"tax return"));
+
+		assert_eq!(cipher.decrypt(b"op", &encrypted).await.unwrap(), data);
+
+		// Bound to the operation it was encrypted for
+		assert!(cipher.decrypt(b"other op", &encrypted).await.is_err());
+
+		let other = OperationCipher::new(&OperationCipher::generate_key()).unwrap();
+		assert!(other.decrypt(b"op", &encrypted).await.is_err());
+	}
+}
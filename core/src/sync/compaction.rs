@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+
+use sd_sync::{CRDTOperation, CRDTOperationType, RelationOperationData, SharedOperationData};
+use uuid::Uuid;
+
+/// A field of a record, or of a relation, that an update changes
+#[derive(PartialEq, Eq, Hash)]
+enum UpdatedField<'a> {
+	Shared {
+		model: &'a str,
+		/// Values can't be hashed, the id is compared serialized
+		record_id: String,
+		field: &'a str,
+	},
+	Relation {
+		relation: &'a str,
+		item: Uuid,
+		group: Uuid,
+		field: &'a str,
+	},
+}
+
+fn updated_field(op: &CRDTOperation) -> Option<UpdatedField<'_>> {
+	match &op.typ {
+		CRDTOperationType::Shared(shared_op) => match &shared_op.data {
+			SharedOperationData::Update { field, .. } => Some(UpdatedField::Shared {
+				model: &shared_op.model,
+				record_id: shared_op.record_id.to_string(),
+				field,
+			}),
+			_ => None,
+		},
+		CRDTOperationType::Relation(relation_op) => match &relation_op.data {
+			RelationOperationData::Update { field, .. } => Some(UpdatedField::Relation {
+				relation: &relation_op.relation,
+				item: relation_op.relation_item,
+				group: relation_op.relation_group,
+				field,
+			}),
+			_ => None,
+		},
+	}
+}
+
+/// Orders operations so they're applied after the ones they could depend on. Timestamps of the
+/// hybrid logical clock are always greater than the ones of the operations the node knew about.
+pub fn sort_causally(ops: &mut [CRDTOperation]) {
+	ops.sort_by_key(|op| (op.timestamp, op.node));
+}
+
+/// Drops the updates that a newer update of the same field from the same node replaces, so nodes
+/// catching up don't apply every intermediate value. Other operations are kept, as the node
+/// receiving them could refuse the ones that would replace them (e.g. deletions).
+pub fn compact_ops(mut ops: Vec<CRDTOperation>) -> Vec<CRDTOperation> {
+	sort_causally(&mut ops);
+
+	let superseded = {
+		let mut updated = HashSet::new();
+
+		ops.iter()
+			.enumerate()
+			.rev()
+			.filter(|(_, op)| {
+				updated_field(op).map_or(false, |field| !updated.insert((op.node, field)))
+			})
+			.map(|(i, _)| i)
+			.collect::<HashSet<_>>()
+	};
+
+	ops.into_iter()
+		.enumerate()
+		.filter(|(i, _)| !superseded.contains(i))
+		.map(|(_, op)| op)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_sync::SharedOperation;
+	use serde_json::json;
+	use uhlc::NTP64;
+
+	fn update(node: Uuid, timestamp: u64, field: &str, value: &str) -> CRDTOperation {
+		CRDTOperation {
+			id: Uuid::new_v4(),
+			node,
+			timestamp: NTP64(timestamp),
+			typ: CRDTOperationType::Shared(SharedOperation {
+				record_id: json!({ "pub_id": [1] }),
+				model: "Object".into(),
+				data: SharedOperationData::Update {
+					field: field.into(),
+					value: json!(value),
+					base: None,
+				},
+			}),
+		}
+	}
+
+	#[test]
+	fn superseded_updates_are_dropped() {
+		let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+		let ops = compact_ops(vec![
+			update(a, 3, "note", "third"),
+			update(a, 1, "note", "first"),
+			update(b, 2, "note", "concurrent"),
+			update(a, 2, "favorite", "true"),
+		]);
+
+		let values = ops
+			.iter()
+			.map(|op| match &op.typ {
+				CRDTOperationType::Shared(SharedOperation {
+					data: SharedOperationData::Update { value, .. },
+					..
+				}) => value.as_str().unwrap(),
+				_ => unreachable!(),
+			})
+			.collect::<Vec<_>>();
+
+		// Another node's change to the field could conflict with it, so it's kept
+		assert_eq!(values.len(), 3);
+		assert!(!values.contains(&"first"));
+		assert_eq!(values.last(), Some(&"third"));
+	}
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use uhlc::NTP64;
+use uuid::Uuid;
+
+/// How a node settles two paired nodes changing the same field of a record concurrently, meaning
+/// neither of them knew about the other's change when making theirs. Every node of a library should
+/// use the same policy, or they can end up keeping different values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+	/// The change with the newest timestamp is kept
+	#[default]
+	LastWriterWins,
+	/// Changes made by this node are kept over the ones of other nodes, falling back to
+	/// last-writer-wins when neither change is from it
+	PreferNode(Uuid),
+	/// The change picked by last-writer-wins is kept, and the conflict is recorded so the user
+	/// can choose which value to keep
+	Manual,
+}
+
+/// A change to a field made by a node
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct FieldChange {
+	pub node: Uuid,
+	pub timestamp: NTP64,
+	pub value: Value,
+}
+
+impl ConflictPolicy {
+	/// Picks which of two concurrent changes to the same field is kept
+	pub(super) fn pick<'a>(&self, a: &'a FieldChange, b: &'a FieldChange) -> &'a FieldChange {
+		match self {
+			Self::PreferNode(node) if a.node == *node => a,
+			Self::PreferNode(node) if b.node == *node => b,
+			_ if a.timestamp >= b.timestamp => a,
+			_ => b,
+		}
+	}
+}
+
+/// How the user settles a pending conflict
+#[derive(Debug, Deserialize, Clone, PartialEq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictResolution {
+	/// Keeps the value currently in the record
+	KeepCurrent,
+	/// Keeps the value that was concurrently set by the other node
+	UseOther,
+	/// Sets the field to a value combining both sides, like the union of two lists
+	Merge(Value),
+}
+
+impl ConflictResolution {
+	/// The value the field is set to
+	pub fn value(self, current: Value, other: Value) -> Value {
+		match self {
+			Self::KeepCurrent => current,
+			Self::UseOther => other,
+			Self::Merge(value) => value,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	fn change(node: Uuid, timestamp: u64, value: Value) -> FieldChange {
+		FieldChange {
+			node,
+			timestamp: NTP64(timestamp),
+			value,
+		}
+	}
+
+	#[test]
+	fn newest_change_wins() {
+		let older = change(Uuid::new_v4(), 1, json!("older"));
+		let newer = change(Uuid::new_v4(), 2, json!("newer"));
+
+		for policy in [ConflictPolicy::LastWriterWins, ConflictPolicy::Manual] {
+			assert_eq!(policy.pick(&older, &newer), &newer);
+			assert_eq!(policy.pick(&newer, &older), &newer);
+		}
+	}
+
+	#[test]
+	fn preferred_node_wins() {
+		let preferred = Uuid::new_v4();
+
+		let older = change(preferred, 1, json!("older"));
+		let newer = change(Uuid::new_v4(), 2, json!("newer"));
+		let newest = change(Uuid::new_v4(), 3, json!("newest"));
+
+		let policy = ConflictPolicy::PreferNode(preferred);
+
+		assert_eq!(policy.pick(&older, &newer), &older);
+		assert_eq!(policy.pick(&newer, &older), &older);
+		assert_eq!(policy.pick(&newer, &newest), &newest);
+	}
+
+	#[test]
+	fn resolutions_pick_the_value() {
+		let current = json!(["a"]);
+		let other = json!(["b"]);
+
+		assert_eq!(
+			ConflictResolution::KeepCurrent.value(current.clone(), other.clone()),
+			current
+		);
+		assert_eq!(
+			ConflictResolution::UseOther.value(current.clone(), other.clone()),
+			other
+		);
+		assert_eq!(
+			ConflictResolution::Merge(json!(["a", "b"])).value(current, other),
+			json!(["a", "b"])
+		);
+	}
+}
@@ -2,16 +2,41 @@
 
 use crate::prisma::*;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{Arc, RwLock},
+};
 
 use sd_sync::*;
 
+use chrono::Utc;
+use serde::de::DeserializeOwned;
 use serde_json::{json, to_vec, Value};
+use thiserror::Error;
 use tokio::sync::broadcast::{self, Receiver, Sender};
-use uhlc::{HLCBuilder, HLC, NTP64};
+use tracing::{debug, warn};
+use uhlc::{HLCBuilder, Timestamp, HLC, NTP64};
 use uuid::Uuid;
 
-use super::ModelSyncData;
+use super::{
+	sort_causally, ConflictPolicy, ConflictResolution, FieldChange, ModelSyncData, OperationCipher,
+};
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+	#[error("error serializing or deserializing sync data: {0}")]
+	Json(#[from] serde_json::Error),
+	#[error("error encrypting a sync operation: {0}")]
+	Cipher(#[from] sd_crypto::Error),
+}
+
+impl From<SyncError> for rspc::Error {
+	fn from(e: SyncError) -> Self {
+		rspc::Error::with_cause(rspc::ErrorCode::InternalServerError, e.to_string(), e)
+	}
+}
 
 #[derive(Clone)]
 pub enum SyncMessage {
@@ -24,11 +49,18 @@ pub struct SyncManager {
 	node: Uuid,
 	_clocks: HashMap<Uuid, NTP64>,
 	clock: HLC,
+	conflict_policy: RwLock<ConflictPolicy>,
+	cipher: OperationCipher,
 	pub tx: Sender<SyncMessage>,
 }
 
 impl SyncManager {
-	pub fn new(db: &Arc<PrismaClient>, node: Uuid) -> (Self, Receiver<SyncMessage>) {
+	pub fn new(
+		db: &Arc<PrismaClient>,
+		node: Uuid,
+		conflict_policy: ConflictPolicy,
+		cipher: OperationCipher,
+	) -> (Self, Receiver<SyncMessage>) {
 		let (tx, rx) = broadcast::channel(64);
 
 		(
@@ -37,92 +69,104 @@ impl SyncManager {
 				node,
 				clock: HLCBuilder::new().with_id(node.into()).build(),
 				_clocks: Default::default(),
+				conflict_policy: RwLock::new(conflict_policy),
+				cipher,
 				tx,
 			},
 			rx,
 		)
 	}
 
+	pub fn set_conflict_policy(&self, conflict_policy: ConflictPolicy) {
+		*self.conflict_policy.write().unwrap() = conflict_policy;
+	}
+
 	pub async fn write_ops<'item, I: prisma_client_rust::BatchItem<'item>>(
+		&self,
+		tx: &PrismaClient,
+		ops_and_queries: (Vec<CRDTOperation>, I),
+	) -> Result<<I as prisma_client_rust::BatchItemParent>::ReturnValue, SyncError> {
+		let (res, ops) = self.write_ops_unbroadcast(tx, ops_and_queries).await?;
+		self.broadcast_ops(ops);
+
+		Ok(res)
+	}
+
+	/// Like [`SyncManager::write_ops`], but the written operations are given back instead of being
+	/// broadcast, for writes in a transaction. They're broadcast with [`SyncManager::broadcast_ops`]
+	/// once it's committed, a rolled back transaction mustn't have sent them to other nodes.
+	pub async fn write_ops_unbroadcast<'item, I: prisma_client_rust::BatchItem<'item>>(
 		&self,
 		tx: &PrismaClient,
 		(_ops, queries): (Vec<CRDTOperation>, I),
-	) -> prisma_client_rust::Result<<I as prisma_client_rust::BatchItemParent>::ReturnValue> {
+	) -> Result<
+		(
+			<I as prisma_client_rust::BatchItemParent>::ReturnValue,
+			Vec<CRDTOperation>,
+		),
+		SyncError,
+	> {
 		#[cfg(feature = "sync-messages")]
 		let res = {
-			let shared = _ops
-				.iter()
-				.filter_map(|op| match &op.typ {
+			let _ops = with_bases(tx, &self.cipher, _ops).await?;
+
+			let mut shared = vec![];
+			let mut relation = vec![];
+			for op in &_ops {
+				match &op.typ {
 					CRDTOperationType::Shared(shared_op) => {
-						let kind = match &shared_op.data {
-							SharedOperationData::Create(_) => "c",
-							SharedOperationData::Update { .. } => "u",
-							SharedOperationData::Delete => "d",
-						};
-
-						Some(tx.shared_operation().create(
-							op.id.as_bytes().to_vec(),
-							op.timestamp.0 as i64,
-							shared_op.model.to_string(),
-							to_vec(&shared_op.record_id).unwrap(),
-							kind.to_string(),
-							to_vec(&shared_op.data).unwrap(),
-							node::pub_id::equals(op.node.as_bytes().to_vec()),
-							vec![],
-						))
+						shared.push(shared_op_create(tx, &self.cipher, op, shared_op).await?)
 					}
-					_ => None,
-				})
-				.collect::<Vec<_>>();
-
-			let (res, _) = tx._batch((queries, shared)).await?;
-
-			for op in _ops {
-				self.tx.send(SyncMessage::Created(op)).ok();
+					CRDTOperationType::Relation(relation_op) => {
+						relation.push(relation_op_create(tx, &self.cipher, op, relation_op).await?)
+					}
+				}
 			}
 
-			res
+			let (res, _, _) = tx._batch((queries, shared, relation)).await?;
+
+			(res, _ops)
 		};
 		#[cfg(not(feature = "sync-messages"))]
-		let res = tx._batch([queries]).await?.remove(0);
+		let res = (tx._batch([queries]).await?.remove(0), vec![]);
 
 		Ok(res)
 	}
 
+	pub fn broadcast_ops(&self, ops: Vec<CRDTOperation>) {
+		for op in ops {
+			self.tx.send(SyncMessage::Created(op)).ok();
+		}
+	}
+
 	#[allow(unused_variables)]
 	pub async fn write_op<'item, Q: prisma_client_rust::BatchItem<'item>>(
 		&self,
 		tx: &PrismaClient,
 		op: CRDTOperation,
 		query: Q,
-	) -> prisma_client_rust::Result<<Q as prisma_client_rust::BatchItemParent>::ReturnValue> {
+	) -> Result<<Q as prisma_client_rust::BatchItemParent>::ReturnValue, SyncError> {
 		#[cfg(feature = "sync-messages")]
 		let ret = {
+			let op = with_bases(tx, &self.cipher, vec![op]).await?.remove(0);
+
 			let ret = match &op.typ {
 				CRDTOperationType::Shared(shared_op) => {
-					let kind = match &shared_op.data {
-						SharedOperationData::Create(_) => "c",
-						SharedOperationData::Update { .. } => "u",
-						SharedOperationData::Delete => "d",
-					};
-
 					tx._batch((
-						tx.shared_operation().create(
-							op.id.as_bytes().to_vec(),
-							op.timestamp.0 as i64,
-							shared_op.model.to_string(),
-							to_vec(&shared_op.record_id).unwrap(),
-							kind.to_string(),
-							to_vec(&shared_op.data).unwrap(),
-							node::pub_id::equals(op.node.as_bytes().to_vec()),
-							vec![],
-						),
+						shared_op_create(tx, &self.cipher, &op, shared_op).await?,
+						query,
+					))
+					.await?
+					.1
+				}
+				CRDTOperationType::Relation(relation_op) => {
+					tx._batch((
+						relation_op_create(tx, &self.cipher, &op, relation_op).await?,
 						query,
 					))
 					.await?
 					.1
 				}
-				_ => todo!(),
 			};
 
 			self.tx.send(SyncMessage::Created(op)).ok();
@@ -136,33 +180,243 @@ impl SyncManager {
 	}
 
 	pub async fn get_ops(&self) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
-		Ok(self
-			.db
-			.shared_operation()
+		self.get_ops_after(&HashMap::new()).await
+	}
+
+	/// Returns the operations of every node that are newer than the given timestamps, so a node
+	/// can catch up with the changes made while it was offline. Nodes missing from `timestamps`
+	/// get all of their operations.
+	pub async fn get_ops_after(
+		&self,
+		timestamps: &HashMap<Uuid, NTP64>,
+	) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
+		let db = &self.db;
+
+		let mut ops = vec![];
+
+		for node in db
+			.node()
 			.find_many(vec![])
-			.order_by(shared_operation::timestamp::order(SortOrder::Asc))
-			.include(shared_operation::include!({ node: select {
-                pub_id
-            } }))
+			.select(node::select!({ id pub_id }))
 			.exec()
 			.await?
-			.into_iter()
-			.flat_map(|op| {
-				Some(CRDTOperation {
-					id: Uuid::from_slice(&op.id).ok()?,
-					node: Uuid::from_slice(&op.node.pub_id).ok()?,
+		{
+			let Ok(node_pub_id) = Uuid::from_slice(&node.pub_id) else {
+				continue;
+			};
+
+			let after = timestamps.get(&node_pub_id).map(|ts| ts.0 as i64);
+
+			let shared_ops = db
+				.shared_operation()
+				.find_many(
+					[Some(shared_operation::node_id::equals(node.id))]
+						.into_iter()
+						.chain([after.map(shared_operation::timestamp::gt)])
+						.flatten()
+						.collect(),
+				)
+				.exec()
+				.await?;
+
+			let relation_ops = db
+				.relation_operation()
+				.find_many(
+					[Some(relation_operation::node_id::equals(node.id))]
+						.into_iter()
+						.chain([after.map(relation_operation::timestamp::gt)])
+						.flatten()
+						.collect(),
+				)
+				.exec()
+				.await?;
+
+			for op in shared_ops {
+				let (Ok(id), Ok(record_id), Some(data)) = (
+					Uuid::from_slice(&op.id),
+					serde_json::from_slice(&op.record_id),
+					read_op_data(&self.cipher, &op.id, &op.data).await,
+				) else {
+					continue;
+				};
+
+				ops.push(CRDTOperation {
+					id,
+					node: node_pub_id,
 					timestamp: NTP64(op.timestamp as u64),
 					typ: CRDTOperationType::Shared(SharedOperation {
-						record_id: serde_json::from_slice(&op.record_id).ok()?,
+						record_id,
 						model: op.model,
-						data: serde_json::from_slice(&op.data).ok()?,
+						data,
 					}),
-				})
+				});
+			}
+
+			for op in relation_ops {
+				let (Ok(id), Ok(relation_item), Ok(relation_group), Some(data)) = (
+					Uuid::from_slice(&op.id),
+					Uuid::from_slice(&op.item_id),
+					Uuid::from_slice(&op.group_id),
+					read_op_data(&self.cipher, &op.id, &op.data).await,
+				) else {
+					continue;
+				};
+
+				ops.push(CRDTOperation {
+					id,
+					node: node_pub_id,
+					timestamp: NTP64(op.timestamp as u64),
+					typ: CRDTOperationType::Relation(RelationOperation {
+						relation_item,
+						relation_group,
+						relation: op.relation,
+						data,
+					}),
+				});
+			}
+		}
+
+		sort_causally(&mut ops);
+
+		Ok(ops)
+	}
+
+	/// The timestamp of the newest operation we have from each node
+	pub async fn timestamps(&self) -> prisma_client_rust::Result<HashMap<Uuid, NTP64>> {
+		let db = &self.db;
+
+		let mut timestamps = HashMap::new();
+
+		for node in db
+			.node()
+			.find_many(vec![])
+			.select(node::select!({ id pub_id }))
+			.exec()
+			.await?
+		{
+			let Ok(node_pub_id) = Uuid::from_slice(&node.pub_id) else {
+				continue;
+			};
+
+			let shared = db
+				.shared_operation()
+				.find_first(vec![shared_operation::node_id::equals(node.id)])
+				.order_by(shared_operation::timestamp::order(SortOrder::Desc))
+				.select(shared_operation::select!({ timestamp }))
+				.exec()
+				.await?
+				.map(|op| op.timestamp);
+
+			let relation = db
+				.relation_operation()
+				.find_first(vec![relation_operation::node_id::equals(node.id)])
+				.order_by(relation_operation::timestamp::order(SortOrder::Desc))
+				.select(relation_operation::select!({ timestamp }))
+				.exec()
+				.await?
+				.map(|op| op.timestamp);
+
+			if let Some(timestamp) = shared.into_iter().chain(relation).max() {
+				timestamps.insert(node_pub_id, NTP64(timestamp as u64));
+			}
+		}
+
+		Ok(timestamps)
+	}
+
+	/// The timestamp of the newest operation of each node that the node with this peer id told us
+	/// it has
+	pub async fn peer_timestamps(
+		&self,
+		peer_id: &str,
+	) -> prisma_client_rust::Result<HashMap<Uuid, NTP64>> {
+		Ok(self
+			.db
+			.sync_watermark()
+			.find_many(vec![sync_watermark::node::is(vec![
+				node::node_peer_id::equals(Some(peer_id.to_string())),
+			])])
+			.include(sync_watermark::include!({ origin: select { pub_id } }))
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|watermark| {
+				Some((
+					Uuid::from_slice(&watermark.origin.pub_id).ok()?,
+					NTP64(watermark.timestamp as u64),
+				))
 			})
 			.collect())
 	}
 
-	pub async fn ingest_op(&self, op: CRDTOperation) -> prisma_client_rust::Result<()> {
+	/// Remembers that the node with this peer id has the operations of each node up to the given
+	/// timestamps, so we can tell which records it has. Watermarks only ever move forward.
+	pub async fn record_peer_timestamps(
+		&self,
+		peer_id: &str,
+		timestamps: &HashMap<Uuid, NTP64>,
+	) -> prisma_client_rust::Result<()> {
+		let db = &self.db;
+
+		let Some(peer) = db
+			.node()
+			.find_first(vec![node::node_peer_id::equals(Some(peer_id.to_string()))])
+			.select(node::select!({ id }))
+			.exec()
+			.await?
+		else {
+			return Ok(());
+		};
+
+		for (origin, timestamp) in timestamps {
+			let Some(origin) = db
+				.node()
+				.find_unique(node::pub_id::equals(origin.as_bytes().to_vec()))
+				.select(node::select!({ id }))
+				.exec()
+				.await?
+			else {
+				continue;
+			};
+
+			let timestamp = timestamp.0 as i64;
+
+			let current = db
+				.sync_watermark()
+				.find_unique(sync_watermark::node_id_origin_id(peer.id, origin.id))
+				.select(sync_watermark::select!({ timestamp }))
+				.exec()
+				.await?;
+
+			match current {
+				Some(current) if current.timestamp >= timestamp => {}
+				Some(_) => {
+					db.sync_watermark()
+						.update(
+							sync_watermark::node_id_origin_id(peer.id, origin.id),
+							vec![sync_watermark::timestamp::set(timestamp)],
+						)
+						.exec()
+						.await?;
+				}
+				None => {
+					db.sync_watermark()
+						.create(
+							node::id::equals(peer.id),
+							node::id::equals(origin.id),
+							timestamp,
+							vec![],
+						)
+						.exec()
+						.await?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	pub async fn ingest_op(&self, op: CRDTOperation) -> Result<(), SyncError> {
 		let db = &self.db;
 
 		if db
@@ -172,12 +426,246 @@ impl SyncManager {
 			.await?
 			.is_none()
 		{
-			panic!("Node is not paired!")
+			warn!(
+				"Ignoring operation from node '{}' as it's not paired",
+				op.node
+			);
+			return Ok(());
+		}
+
+		// The same operation can be received from a broadcast and when catching up
+		if self.is_ingested(&op).await? {
+			debug!("Skipping operation '{}' as it was already ingested", op.id);
+			return Ok(());
+		}
+
+		// Keep our clock ahead of every operation we know about, so our next changes win
+		if let Err(e) = self
+			.clock
+			.update_with_timestamp(&Timestamp::new(op.timestamp, op.node.into()))
+		{
+			warn!("Failed to update the clock with operation '{}': {e}", op.id);
 		}
 
 		let msg = SyncMessage::Ingested(op.clone());
 
-		match ModelSyncData::from_op(op.typ.clone()).unwrap() {
+		match &op.typ {
+			CRDTOperationType::Shared(shared_op) => {
+				self.apply_shared_op(&op, shared_op).await?;
+
+				shared_op_create(db, &self.cipher, &op, shared_op)
+					.await?
+					.exec()
+					.await?;
+			}
+			CRDTOperationType::Relation(relation_op) => {
+				self.apply_relation_op(relation_op, op.timestamp).await?;
+
+				relation_op_create(db, &self.cipher, &op, relation_op)
+					.await?
+					.exec()
+					.await?;
+			}
+		}
+
+		self.tx.send(msg).ok();
+
+		Ok(())
+	}
+
+	async fn is_ingested(&self, op: &CRDTOperation) -> prisma_client_rust::Result<bool> {
+		let id = op.id.as_bytes().to_vec();
+
+		let count = match &op.typ {
+			CRDTOperationType::Shared(_) => {
+				self.db
+					.shared_operation()
+					.count(vec![shared_operation::id::equals(id)])
+					.exec()
+					.await?
+			}
+			CRDTOperationType::Relation(_) => {
+				self.db
+					.relation_operation()
+					.count(vec![relation_operation::id::equals(id)])
+					.exec()
+					.await?
+			}
+		};
+
+		Ok(count > 0)
+	}
+
+	/// Applies a shared operation with last-write-wins semantics: fields updated by a newer
+	/// operation are left alone, and nothing is applied to records deleted by a newer operation.
+	/// Updates made concurrently with another node's change to the same field are settled by the
+	/// conflict policy instead.
+	async fn apply_shared_op(
+		&self,
+		op: &CRDTOperation,
+		shared_op: &SharedOperation,
+	) -> prisma_client_rust::Result<()> {
+		let db = &self.db;
+		let timestamp = op.timestamp;
+
+		let mut newer_ops = vec![];
+		for op in db
+			.shared_operation()
+			.find_many(vec![
+				shared_operation::model::equals(shared_op.model.clone()),
+				shared_operation::record_id::equals(to_vec(&shared_op.record_id).unwrap()),
+				shared_operation::timestamp::gt(timestamp.0 as i64),
+			])
+			.select(shared_operation::select!({ id data }))
+			.exec()
+			.await?
+		{
+			newer_ops
+				.extend(read_op_data::<SharedOperationData>(&self.cipher, &op.id, &op.data).await);
+		}
+
+		if newer_ops
+			.iter()
+			.any(|data| matches!(data, SharedOperationData::Delete))
+		{
+			return Ok(());
+		}
+
+		let mut won_conflict = false;
+
+		if let SharedOperationData::Update {
+			field,
+			value,
+			base: Some(base),
+		} = &shared_op.data
+		{
+			let changes = field_changes(
+				db,
+				&self.cipher,
+				&shared_op.model,
+				&shared_op.record_id,
+				field,
+			)
+			.await?;
+
+			// A newer change from the same node already replaced this one
+			if changes
+				.iter()
+				.any(|change| change.node == op.node && change.timestamp > timestamp)
+			{
+				return Ok(());
+			}
+
+			let policy = *self.conflict_policy.read().unwrap();
+
+			let incoming = FieldChange {
+				node: op.node,
+				timestamp,
+				value: value.clone(),
+			};
+
+			match changes
+				.iter()
+				.filter(|change| {
+					change.node != op.node && change.timestamp > *base && change.value != *value
+				})
+				.reduce(|a, b| policy.pick(a, b))
+			{
+				Some(existing) => {
+					won_conflict = policy.pick(&incoming, existing) == &incoming;
+
+					let (kept, other) = if won_conflict {
+						(&incoming, existing)
+					} else {
+						(existing, &incoming)
+					};
+
+					// Only the manual policy leaves the conflict for the user to resolve
+					self.record_conflict(
+						shared_op,
+						field,
+						kept,
+						other,
+						policy != ConflictPolicy::Manual,
+					)
+					.await?;
+
+					if !won_conflict {
+						return Ok(());
+					}
+				}
+				// The node knew about every other change to the field, so its change settles
+				// any conflict pending for it
+				None => {
+					db.sync_conflict()
+						.update_many(
+							vec![
+								sync_conflict::model::equals(shared_op.model.clone()),
+								sync_conflict::record_id::equals(
+									to_vec(&shared_op.record_id).unwrap(),
+								),
+								sync_conflict::field::equals(field.clone()),
+								sync_conflict::date_resolved::equals(None),
+							],
+							vec![
+								sync_conflict::resolved_value::set(Some(to_vec(value).unwrap())),
+								sync_conflict::date_resolved::set(Some(Utc::now().into())),
+							],
+						)
+						.exec()
+						.await?;
+				}
+			}
+		}
+
+		let overridden_fields = newer_ops
+			.iter()
+			.filter_map(|data| match data {
+				SharedOperationData::Update { field, .. } => Some(field.as_str()),
+				_ => None,
+			})
+			.collect::<HashSet<_>>();
+
+		let data = match &shared_op.data {
+			SharedOperationData::Create(data) => SharedOperationData::Create(
+				data.iter()
+					.filter(|(field, _)| !overridden_fields.contains(field.as_str()))
+					.map(|(field, value)| (field.clone(), value.clone()))
+					.collect(),
+			),
+			SharedOperationData::Update { field, .. }
+				if overridden_fields.contains(field.as_str()) && !won_conflict =>
+			{
+				return Ok(());
+			}
+			SharedOperationData::Delete => {
+				// There's nothing left to choose between, but the conflicts stay in the log
+				db.sync_conflict()
+					.update_many(
+						vec![
+							sync_conflict::model::equals(shared_op.model.clone()),
+							sync_conflict::record_id::equals(to_vec(&shared_op.record_id).unwrap()),
+							sync_conflict::date_resolved::equals(None),
+						],
+						vec![sync_conflict::date_resolved::set(Some(Utc::now().into()))],
+					)
+					.exec()
+					.await?;
+
+				SharedOperationData::Delete
+			}
+			data => data.clone(),
+		};
+
+		let Some(model_data) = ModelSyncData::from_op(CRDTOperationType::Shared(SharedOperation {
+			data,
+			..shared_op.clone()
+		})) else {
+			warn!("Ignoring operation for unknown model '{}'", shared_op.model);
+			return Ok(());
+		};
+
+		match model_data {
 			ModelSyncData::FilePath(id, shared_op) => match shared_op {
 				SharedOperationData::Create(data) => {
 					let data: Vec<_> = data
@@ -194,7 +682,7 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				SharedOperationData::Update { field, value } => {
+				SharedOperationData::Update { field, value, .. } => {
 					let data = vec![file_path::SetParam::deserialize(&field, value).unwrap()];
 
 					db.file_path()
@@ -206,7 +694,12 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				_ => todo!(),
+				SharedOperationData::Delete => {
+					db.file_path()
+						.delete_many(vec![file_path::pub_id::equals(id.pub_id)])
+						.exec()
+						.await?;
+				}
 			},
 			ModelSyncData::Location(id, shared_op) => match shared_op {
 				SharedOperationData::Create(data) => {
@@ -224,7 +717,7 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				SharedOperationData::Update { field, value } => {
+				SharedOperationData::Update { field, value, .. } => {
 					let data = vec![location::SetParam::deserialize(&field, value).unwrap()];
 
 					db.location()
@@ -236,7 +729,12 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				_ => todo!(),
+				SharedOperationData::Delete => {
+					db.location()
+						.delete_many(vec![location::pub_id::equals(id.pub_id)])
+						.exec()
+						.await?;
+				}
 			},
 			ModelSyncData::Object(id, shared_op) => match shared_op {
 				SharedOperationData::Create(data) => {
@@ -254,7 +752,7 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				SharedOperationData::Update { field, value } => {
+				SharedOperationData::Update { field, value, .. } => {
 					let data = vec![object::SetParam::deserialize(&field, value).unwrap()];
 
 					db.object()
@@ -266,7 +764,17 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				_ => todo!(),
+				SharedOperationData::Delete => {
+					db._batch((
+						db.tag_on_object()
+							.delete_many(vec![tag_on_object::object::is(vec![
+								object::pub_id::equals(id.pub_id.clone()),
+							])]),
+						db.object()
+							.delete_many(vec![object::pub_id::equals(id.pub_id)]),
+					))
+					.await?;
+				}
 			},
 			ModelSyncData::Tag(id, shared_op) => match shared_op {
 				SharedOperationData::Create(data) => {
@@ -284,7 +792,7 @@ impl SyncManager {
 						.exec()
 						.await?;
 				}
-				SharedOperationData::Update { field, value } => {
+				SharedOperationData::Update { field, value, .. } => {
 					let data = vec![tag::SetParam::deserialize(&field, value).unwrap()];
 
 					db.tag()
@@ -297,37 +805,202 @@ impl SyncManager {
 						.await?;
 				}
 				SharedOperationData::Delete => {
-					db.tag()
-						.delete(tag::pub_id::equals(id.pub_id))
-						.exec()
-						.await?;
+					db._batch((
+						db.tag_on_object()
+							.delete_many(vec![tag_on_object::tag::is(vec![tag::pub_id::equals(
+								id.pub_id.clone(),
+							)])]),
+						db.tag().delete_many(vec![tag::pub_id::equals(id.pub_id)]),
+					))
+					.await?;
 				}
 			},
 		}
 
-		if let CRDTOperationType::Shared(shared_op) = op.typ {
-			let kind = match &shared_op.data {
-				SharedOperationData::Create(_) => "c",
-				SharedOperationData::Update { .. } => "u",
-				SharedOperationData::Delete => "d",
-			};
+		Ok(())
+	}
 
-			db.shared_operation()
-				.create(
-					op.id.as_bytes().to_vec(),
-					op.timestamp.0 as i64,
-					shared_op.model.to_string(),
-					to_vec(&shared_op.record_id).unwrap(),
-					kind.to_string(),
-					to_vec(&shared_op.data).unwrap(),
-					node::pub_id::equals(op.node.as_bytes().to_vec()),
-					vec![],
-				)
-				.exec()
-				.await?;
+	/// Adds a conflict to the log. Conflicts settled by the policy are logged as resolved with the
+	/// kept value, and a pending conflict for the same field is replaced by the newer one.
+	async fn record_conflict(
+		&self,
+		shared_op: &SharedOperation,
+		field: &str,
+		kept: &FieldChange,
+		other: &FieldChange,
+		resolved: bool,
+	) -> prisma_client_rust::Result<()> {
+		let db = &self.db;
+
+		let record_id = to_vec(&shared_op.record_id).unwrap();
+		let value = to_vec(&kept.value).unwrap();
+		let other_value = to_vec(&other.value).unwrap();
+
+		let (resolved_value, date_resolved) = if resolved {
+			(Some(value.clone()), Some(Utc::now().into()))
+		} else {
+			(None, None)
+		};
+
+		let params = vec![
+			sync_conflict::resolved_value::set(resolved_value),
+			sync_conflict::date_resolved::set(date_resolved),
+		];
+
+		let pending = db
+			.sync_conflict()
+			.find_first(vec![
+				sync_conflict::model::equals(shared_op.model.clone()),
+				sync_conflict::record_id::equals(record_id.clone()),
+				sync_conflict::field::equals(field.to_string()),
+				sync_conflict::date_resolved::equals(None),
+			])
+			.select(sync_conflict::select!({ id }))
+			.exec()
+			.await?;
+
+		match pending {
+			Some(pending) => {
+				db.sync_conflict()
+					.update(
+						sync_conflict::id::equals(pending.id),
+						[
+							vec![
+								sync_conflict::value::set(value),
+								sync_conflict::node::connect(node::pub_id::equals(
+									kept.node.as_bytes().to_vec(),
+								)),
+								sync_conflict::other_value::set(other_value),
+								sync_conflict::other_node::connect(node::pub_id::equals(
+									other.node.as_bytes().to_vec(),
+								)),
+								sync_conflict::date_created::set(Utc::now().into()),
+							],
+							params,
+						]
+						.concat(),
+					)
+					.exec()
+					.await?;
+			}
+			None => {
+				db.sync_conflict()
+					.create(
+						shared_op.model.clone(),
+						record_id,
+						field.to_string(),
+						value,
+						node::pub_id::equals(kept.node.as_bytes().to_vec()),
+						other_value,
+						node::pub_id::equals(other.node.as_bytes().to_vec()),
+						Utc::now().into(),
+						params,
+					)
+					.exec()
+					.await?;
+			}
 		}
 
-		self.tx.send(msg).ok();
+		Ok(())
+	}
+
+	/// Settles a pending conflict by setting the field to the value of the resolution. This is a
+	/// regular change to the field, so it's synced and settles the conflict on the other nodes too.
+	pub async fn resolve_conflict(
+		&self,
+		conflict: sync_conflict::Data,
+		resolution: ConflictResolution,
+	) -> Result<(), SyncError> {
+		let db = &self.db;
+
+		let value = resolution.value(
+			serde_json::from_slice(&conflict.value)?,
+			serde_json::from_slice(&conflict.other_value)?,
+		);
+
+		let op = self.new_op(CRDTOperationType::Shared(SharedOperation {
+			model: conflict.model,
+			record_id: serde_json::from_slice(&conflict.record_id)?,
+			data: SharedOperationData::Update {
+				field: conflict.field,
+				value,
+				base: None,
+			},
+		}));
+		let op = with_bases(db, &self.cipher, vec![op]).await?.remove(0);
+
+		let CRDTOperationType::Shared(shared_op) = &op.typ else {
+			unreachable!("Conflicts are only recorded for shared operations");
+		};
+
+		self.apply_shared_op(&op, shared_op).await?;
+		shared_op_create(db, &self.cipher, &op, shared_op)
+			.await?
+			.exec()
+			.await?;
+
+		self.tx.send(SyncMessage::Created(op)).ok();
+
+		Ok(())
+	}
+
+	/// Applies a relation operation, unless a newer one was already applied to the same relation
+	async fn apply_relation_op(
+		&self,
+		relation_op: &RelationOperation,
+		timestamp: NTP64,
+	) -> prisma_client_rust::Result<()> {
+		let db = &self.db;
+
+		if db
+			.relation_operation()
+			.count(vec![
+				relation_operation::relation::equals(relation_op.relation.clone()),
+				relation_operation::item_id::equals(relation_op.relation_item.as_bytes().to_vec()),
+				relation_operation::group_id::equals(
+					relation_op.relation_group.as_bytes().to_vec(),
+				),
+				relation_operation::timestamp::gt(timestamp.0 as i64),
+			])
+			.exec()
+			.await? > 0
+		{
+			return Ok(());
+		}
+
+		match relation_op.relation.as_str() {
+			tag_on_object::NAME => {
+				let tag_id = relation_op.relation_item.as_bytes().to_vec();
+				let object_id = relation_op.relation_group.as_bytes().to_vec();
+
+				let delete_existing = || {
+					db.tag_on_object().delete_many(vec![
+						tag_on_object::tag::is(vec![tag::pub_id::equals(tag_id.clone())]),
+						tag_on_object::object::is(vec![object::pub_id::equals(object_id.clone())]),
+					])
+				};
+
+				match relation_op.data {
+					RelationOperationData::Create => {
+						db._batch((
+							delete_existing(),
+							db.tag_on_object().create(
+								tag::pub_id::equals(tag_id.clone()),
+								object::pub_id::equals(object_id.clone()),
+								vec![],
+							),
+						))
+						.await?;
+					}
+					RelationOperationData::Delete => {
+						delete_existing().exec().await?;
+					}
+					// Tags on objects have no data of their own
+					RelationOperationData::Update { .. } => {}
+				}
+			}
+			relation => warn!("Ignoring operation for unknown relation '{relation}'"),
+		}
 
 		Ok(())
 	}
@@ -377,7 +1050,186 @@ impl SyncManager {
 			data: SharedOperationData::Update {
 				field: field.to_string(),
 				value,
+				base: None,
 			},
 		}))
 	}
+	pub fn shared_delete<
+		TSyncId: SyncId<ModelTypes = TModel>,
+		TModel: SyncType<Marker = SharedSyncType>,
+	>(
+		&self,
+		id: TSyncId,
+	) -> CRDTOperation {
+		self.new_op(CRDTOperationType::Shared(SharedOperation {
+			model: TModel::MODEL.to_string(),
+			record_id: json!(id),
+			data: SharedOperationData::Delete,
+		}))
+	}
+
+	/// `item` and `group` are the pub ids of the records linked by the relation, like the tag and
+	/// the object of `tag_on_object`
+	pub fn relation_create(&self, relation: &str, item: Uuid, group: Uuid) -> CRDTOperation {
+		self.new_op(CRDTOperationType::Relation(RelationOperation {
+			relation_item: item,
+			relation_group: group,
+			relation: relation.to_string(),
+			data: RelationOperationData::Create,
+		}))
+	}
+	pub fn relation_delete(&self, relation: &str, item: Uuid, group: Uuid) -> CRDTOperation {
+		self.new_op(CRDTOperationType::Relation(RelationOperation {
+			relation_item: item,
+			relation_group: group,
+			relation: relation.to_string(),
+			data: RelationOperationData::Delete,
+		}))
+	}
+}
+
+/// Fills in the base of the field updates made by this node, so the nodes ingesting them can tell
+/// which changes were made concurrently
+async fn with_bases(
+	db: &PrismaClient,
+	cipher: &OperationCipher,
+	mut ops: Vec<CRDTOperation>,
+) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
+	for op in &mut ops {
+		let CRDTOperationType::Shared(SharedOperation {
+			model,
+			record_id,
+			data: SharedOperationData::Update { field, base, .. },
+		}) = &mut op.typ
+		else {
+			continue;
+		};
+
+		*base = Some(
+			field_changes(db, cipher, model, record_id, field)
+				.await?
+				.into_iter()
+				.map(|change| change.timestamp)
+				.max()
+				.unwrap_or(NTP64(0)),
+		);
+	}
+
+	Ok(ops)
+}
+
+/// Every change made to a field of a record, by creating the record or by updating the field
+async fn field_changes(
+	db: &PrismaClient,
+	cipher: &OperationCipher,
+	model: &str,
+	record_id: &Value,
+	field: &str,
+) -> prisma_client_rust::Result<Vec<FieldChange>> {
+	let mut changes = vec![];
+
+	for op in db
+		.shared_operation()
+		.find_many(vec![
+			shared_operation::model::equals(model.to_string()),
+			shared_operation::record_id::equals(to_vec(record_id).unwrap()),
+			shared_operation::kind::in_vec(vec!["c".to_string(), "u".to_string()]),
+		])
+		.include(shared_operation::include!({ node: select { pub_id } }))
+		.exec()
+		.await?
+	{
+		let value = match read_op_data(cipher, &op.id, &op.data).await {
+			Some(SharedOperationData::Create(mut data)) => data.remove(field),
+			Some(SharedOperationData::Update {
+				field: updated_field,
+				value,
+				..
+			}) if updated_field == field => Some(value),
+			_ => None,
+		};
+
+		let (Some(value), Ok(node)) = (value, Uuid::from_slice(&op.node.pub_id)) else {
+			continue;
+		};
+
+		changes.push(FieldChange {
+			node,
+			timestamp: NTP64(op.timestamp as u64),
+			value,
+		});
+	}
+
+	Ok(changes)
+}
+
+/// Decrypts and parses the data of a stored operation, `None` if it can't be read
+async fn read_op_data<T: DeserializeOwned>(
+	cipher: &OperationCipher,
+	id: &[u8],
+	data: &[u8],
+) -> Option<T> {
+	match cipher.decrypt(id, data).await {
+		Ok(data) => serde_json::from_slice(&data).ok(),
+		Err(e) => {
+			warn!(
+				"Failed to decrypt the sync operation '{}': {e}",
+				Uuid::from_slice(id).unwrap_or_default()
+			);
+			None
+		}
+	}
+}
+
+async fn shared_op_create<'a>(
+	db: &'a PrismaClient,
+	cipher: &OperationCipher,
+	op: &CRDTOperation,
+	shared_op: &SharedOperation,
+) -> Result<shared_operation::CreateQuery<'a>, SyncError> {
+	let kind = match &shared_op.data {
+		SharedOperationData::Create(_) => "c",
+		SharedOperationData::Update { .. } => "u",
+		SharedOperationData::Delete => "d",
+	};
+
+	Ok(db.shared_operation().create(
+		op.id.as_bytes().to_vec(),
+		op.timestamp.0 as i64,
+		shared_op.model.to_string(),
+		to_vec(&shared_op.record_id)?,
+		kind.to_string(),
+		cipher
+			.encrypt(op.id.as_bytes(), &to_vec(&shared_op.data)?)
+			.await?,
+		node::pub_id::equals(op.node.as_bytes().to_vec()),
+		vec![],
+	))
+}
+
+async fn relation_op_create<'a>(
+	db: &'a PrismaClient,
+	cipher: &OperationCipher,
+	op: &CRDTOperation,
+	relation_op: &RelationOperation,
+) -> Result<relation_operation::CreateQuery<'a>, SyncError> {
+	let kind = match &relation_op.data {
+		RelationOperationData::Create => "c",
+		RelationOperationData::Update { .. } => "u",
+		RelationOperationData::Delete => "d",
+	};
+
+	Ok(db.relation_operation().create(
+		op.id.as_bytes().to_vec(),
+		op.timestamp.0 as i64,
+		relation_op.relation.clone(),
+		relation_op.relation_item.as_bytes().to_vec(),
+		relation_op.relation_group.as_bytes().to_vec(),
+		kind.to_string(),
+		cipher
+			.encrypt(op.id.as_bytes(), &to_vec(&relation_op.data)?)
+			.await?,
+		node::pub_id::equals(op.node.as_bytes().to_vec()),
+		vec![],
+	))
 }
@@ -1,4 +1,14 @@
+mod cipher;
+mod compaction;
+mod conflict;
 mod manager;
+mod scope;
+mod status;
 
 pub use crate::prisma_sync::*;
+pub use cipher::*;
+pub use compaction::*;
+pub use conflict::*;
 pub use manager::*;
+pub use scope::*;
+pub use status::*;
@@ -0,0 +1,284 @@
+use crate::prisma::{
+	file_path, location, node, object, sync_scope, tag, tag_on_object, PrismaClient,
+};
+
+use std::collections::{HashMap, HashSet};
+
+use prisma_client_rust::QueryError;
+use sd_sync::{CRDTOperation, CRDTOperationType, SharedOperationData};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{ModelSyncData, SyncStatusModel};
+
+/// The locations and tags that are synced with a paired node, by their pub ids so both nodes can
+/// agree on them. `None` means every location or tag is synced.
+///
+/// File paths follow their location, and objects are synced if any of their file paths is in a
+/// synced location or if they have a synced tag.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncScope {
+	pub locations: Option<HashSet<Uuid>>,
+	pub tags: Option<HashSet<Uuid>>,
+}
+
+impl SyncScope {
+	pub fn is_everything(&self) -> bool {
+		self.locations.is_none() && self.tags.is_none()
+	}
+
+	/// Loads the scope configured for the node with this peer id, nodes we don't know about get
+	/// the default scope that syncs everything
+	pub async fn for_peer(db: &PrismaClient, peer_id: &str) -> Result<Self, QueryError> {
+		match db
+			.node()
+			.find_first(vec![node::node_peer_id::equals(Some(peer_id.to_string()))])
+			.select(node::select!({ id }))
+			.exec()
+			.await?
+		{
+			Some(node) => Self::for_node(db, node.id).await,
+			None => Ok(Self::default()),
+		}
+	}
+
+	pub async fn for_node(db: &PrismaClient, node_id: node::id::Type) -> Result<Self, QueryError> {
+		let entries = db
+			.sync_scope()
+			.find_many(vec![sync_scope::node_id::equals(node_id)])
+			.include(sync_scope::include!({
+				location: select { pub_id }
+				tag: select { pub_id }
+			}))
+			.exec()
+			.await?;
+
+		let mut scope = Self::default();
+
+		for entry in entries {
+			if let Some(location) = entry.location {
+				if let Ok(pub_id) = Uuid::from_slice(&location.pub_id) {
+					scope
+						.locations
+						.get_or_insert_with(HashSet::new)
+						.insert(pub_id);
+				}
+			}
+
+			if let Some(tag) = entry.tag {
+				if let Ok(pub_id) = Uuid::from_slice(&tag.pub_id) {
+					scope.tags.get_or_insert_with(HashSet::new).insert(pub_id);
+				}
+			}
+		}
+
+		Ok(scope)
+	}
+
+	/// The scope that is within both scopes, used when both nodes restrict what they sync
+	pub fn intersect(self, other: Self) -> Self {
+		fn intersect(a: Option<HashSet<Uuid>>, b: Option<HashSet<Uuid>>) -> Option<HashSet<Uuid>> {
+			match (a, b) {
+				(Some(a), Some(b)) => Some(a.intersection(&b).copied().collect()),
+				(a, b) => a.or(b),
+			}
+		}
+
+		Self {
+			locations: intersect(self.locations, other.locations),
+			tags: intersect(self.tags, other.tags),
+		}
+	}
+
+	/// Keeps the operations that are within the scope
+	pub async fn filter(
+		&self,
+		db: &PrismaClient,
+		ops: Vec<CRDTOperation>,
+	) -> Result<Vec<CRDTOperation>, QueryError> {
+		if self.is_everything() {
+			return Ok(ops);
+		}
+
+		let mut in_scope = Vec::with_capacity(ops.len());
+		for op in ops {
+			if self.contains(db, &op).await? {
+				in_scope.push(op);
+			}
+		}
+
+		Ok(in_scope)
+	}
+
+	/// Checks if the operation is within the scope. Operations being ingested must be checked one
+	/// at a time right before ingesting them, as they can depend on records created by earlier ones.
+	pub async fn contains(
+		&self,
+		db: &PrismaClient,
+		op: &CRDTOperation,
+	) -> Result<bool, QueryError> {
+		match &op.typ {
+			CRDTOperationType::Shared(_) => match ModelSyncData::from_op(op.typ.clone()) {
+				Some(ModelSyncData::Location(id, _)) => Ok(self.has_location(&id.pub_id)),
+				Some(ModelSyncData::FilePath(id, data)) => {
+					self.has_file_path(db, id.pub_id, &data).await
+				}
+				Some(ModelSyncData::Object(id, _)) => self.has_object(db, id.pub_id).await,
+				Some(ModelSyncData::Tag(id, _)) => Ok(self.has_tag(&id.pub_id)),
+				None => Ok(true),
+			},
+			CRDTOperationType::Relation(relation_op)
+				if relation_op.relation == tag_on_object::NAME =>
+			{
+				Ok(self.has_tag(relation_op.relation_item.as_bytes())
+					&& self
+						.has_object(db, relation_op.relation_group.as_bytes().to_vec())
+						.await?)
+			}
+			CRDTOperationType::Relation(_) => Ok(true),
+		}
+	}
+
+	/// Checks if the record of the model with this pub id is within the scope
+	pub(super) async fn has_record(
+		&self,
+		db: &PrismaClient,
+		model: SyncStatusModel,
+		pub_id: &[u8],
+	) -> Result<bool, QueryError> {
+		match model {
+			SyncStatusModel::Object => self.has_object(db, pub_id.to_vec()).await,
+			SyncStatusModel::Tag => Ok(self.has_tag(pub_id)),
+		}
+	}
+
+	fn has_location(&self, pub_id: &[u8]) -> bool {
+		contains(&self.locations, pub_id)
+	}
+
+	fn has_tag(&self, pub_id: &[u8]) -> bool {
+		contains(&self.tags, pub_id)
+	}
+
+	/// File paths are in the scope of their location, which is in the operation when the file path
+	/// is created and in the database for the rest of them
+	async fn has_file_path(
+		&self,
+		db: &PrismaClient,
+		pub_id: Vec<u8>,
+		data: &SharedOperationData,
+	) -> Result<bool, QueryError> {
+		if self.locations.is_none() {
+			return Ok(true);
+		}
+
+		let location_pub_id = match data {
+			SharedOperationData::Create(data) => data
+				.get(file_path::location::NAME)
+				.cloned()
+				.and_then(|value| serde_json::from_value::<HashMap<String, Vec<u8>>>(value).ok())
+				.and_then(|mut id| id.remove(location::pub_id::NAME)),
+			_ => None,
+		};
+
+		let location_pub_id = match location_pub_id {
+			Some(location_pub_id) => Some(location_pub_id),
+			None => db
+				.file_path()
+				.find_unique(file_path::pub_id::equals(pub_id))
+				.select(file_path::select!({ location: select { pub_id } }))
+				.exec()
+				.await?
+				.and_then(|file_path| file_path.location)
+				.map(|location| location.pub_id),
+		};
+
+		Ok(location_pub_id
+			.map(|pub_id| self.has_location(&pub_id))
+			.unwrap_or(false))
+	}
+
+	/// Objects we don't know about yet are let through, as we can't tell where they belong until
+	/// a file path is linked to them. The orphan remover cleans up the ones that never get linked.
+	async fn has_object(&self, db: &PrismaClient, pub_id: Vec<u8>) -> Result<bool, QueryError> {
+		if self.locations.is_none() {
+			return Ok(true);
+		}
+
+		let Some(object) = db
+			.object()
+			.find_unique(object::pub_id::equals(pub_id))
+			.select(object::select!({
+				file_paths: select { location: select { pub_id } }
+				tags: select { tag: select { pub_id } }
+			}))
+			.exec()
+			.await?
+		else {
+			return Ok(true);
+		};
+
+		let in_location = object
+			.file_paths
+			.iter()
+			.filter_map(|file_path| file_path.location.as_ref())
+			.any(|location| self.has_location(&location.pub_id));
+
+		let has_synced_tag = self.tags.is_some()
+			&& object
+				.tags
+				.iter()
+				.any(|tag_on_object| self.has_tag(&tag_on_object.tag.pub_id));
+
+		Ok(in_location || has_synced_tag)
+	}
+}
+
+fn contains(pub_ids: &Option<HashSet<Uuid>>, pub_id: &[u8]) -> bool {
+	match pub_ids {
+		Some(pub_ids) => Uuid::from_slice(pub_id)
+			.map(|pub_id| pub_ids.contains(&pub_id))
+			.unwrap_or(false),
+		None => true,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn intersecting_scopes() {
+		let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+		let everything = SyncScope::default();
+		let some = SyncScope {
+			locations: Some([a, b].into()),
+			tags: None,
+		};
+		let other = SyncScope {
+			locations: Some([b, c].into()),
+			tags: Some([c].into()),
+		};
+
+		assert_eq!(everything.clone().intersect(some.clone()), some);
+		assert_eq!(
+			some.intersect(other),
+			SyncScope {
+				locations: Some([b].into()),
+				tags: Some([c].into()),
+			}
+		);
+		assert!(everything.is_everything());
+	}
+
+	#[test]
+	fn pub_ids_in_scope() {
+		let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+		assert!(contains(&None, a.as_bytes()));
+		assert!(contains(&Some([a].into()), a.as_bytes()));
+		assert!(!contains(&Some([a].into()), b.as_bytes()));
+		assert!(!contains(&Some([a].into()), b"not a uuid"));
+	}
+}
@@ -0,0 +1,257 @@
+use crate::{
+	prisma::{
+		node, object, relation_operation, shared_operation, tag, tag_on_object, PrismaClient,
+	},
+	prisma_sync,
+};
+
+use std::collections::HashMap;
+
+use prisma_client_rust::QueryError;
+use sd_sync::CRDTOperation;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use specta::Type;
+use uhlc::NTP64;
+use uuid::Uuid;
+
+use super::SyncScope;
+
+/// The kinds of records we can tell the sync status of
+#[derive(Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncStatusModel {
+	Object,
+	Tag,
+}
+
+#[derive(Serialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncState {
+	/// No paired node syncs the record, or it was never changed in a way that is synced
+	LocalOnly,
+	/// Some paired nodes that sync the record don't have its latest changes yet
+	Pending,
+	/// Every paired node that syncs the record has its latest changes
+	Synced,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct SyncStatusNode {
+	pub id: i32,
+	pub name: String,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct RecordSyncStatus {
+	pub id: i32,
+	pub state: SyncState,
+	/// The paired nodes that have the latest changes of the record
+	pub synced_with: Vec<SyncStatusNode>,
+	/// The paired nodes that sync the record but haven't received its latest changes
+	pub pending_with: Vec<SyncStatusNode>,
+}
+
+/// The newest operation of each node in `ops`
+pub fn latest_timestamps(ops: &[CRDTOperation]) -> HashMap<Uuid, NTP64> {
+	let mut timestamps = HashMap::new();
+
+	for op in ops {
+		let timestamp = timestamps.entry(op.node).or_insert(op.timestamp);
+		*timestamp = (*timestamp).max(op.timestamp);
+	}
+
+	timestamps
+}
+
+/// A node has a record if, for every node that changed it, it knows about that node's newest
+/// change to it. Nodes always have their own changes.
+fn has_changes(
+	node_id: node::id::Type,
+	changes: &HashMap<node::id::Type, i64>,
+	watermarks: &HashMap<node::id::Type, i64>,
+) -> bool {
+	changes.iter().all(|(origin, timestamp)| {
+		*origin == node_id
+			|| watermarks
+				.get(origin)
+				.map_or(false, |watermark| watermark >= timestamp)
+	})
+}
+
+/// Tells which of the paired nodes have the latest changes of each record, using the operations
+/// that changed the record and the watermarks recorded while syncing with them
+pub async fn record_sync_status(
+	db: &PrismaClient,
+	local_node: Uuid,
+	model: SyncStatusModel,
+	ids: Vec<i32>,
+) -> Result<Vec<RecordSyncStatus>, QueryError> {
+	let records = match model {
+		SyncStatusModel::Object => db
+			.object()
+			.find_many(vec![object::id::in_vec(ids)])
+			.select(object::select!({ id pub_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|o| (o.id, o.pub_id))
+			.collect::<Vec<_>>(),
+		SyncStatusModel::Tag => db
+			.tag()
+			.find_many(vec![tag::id::in_vec(ids)])
+			.select(tag::select!({ id pub_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|t| (t.id, t.pub_id))
+			.collect::<Vec<_>>(),
+	};
+
+	let mut nodes = vec![];
+	for node in db
+		.node()
+		.find_many(vec![node::pub_id::not(local_node.as_bytes().to_vec())])
+		.include(node::include!({ sync_watermarks }))
+		.exec()
+		.await?
+	{
+		let scope = SyncScope::for_node(db, node.id).await?;
+		let watermarks = node
+			.sync_watermarks
+			.iter()
+			.map(|watermark| (watermark.origin_id, watermark.timestamp))
+			.collect::<HashMap<_, _>>();
+
+		nodes.push((
+			SyncStatusNode {
+				id: node.id,
+				name: node.name,
+			},
+			scope,
+			watermarks,
+		));
+	}
+
+	let mut statuses = Vec::with_capacity(records.len());
+
+	for (id, pub_id) in records {
+		let changes = record_changes(db, model, &pub_id).await?;
+
+		let mut synced_with = vec![];
+		let mut pending_with = vec![];
+
+		if !changes.is_empty() {
+			for (node, scope, watermarks) in &nodes {
+				if !scope.has_record(db, model, &pub_id).await? {
+					continue;
+				}
+
+				if has_changes(node.id, &changes, watermarks) {
+					synced_with.push(node.clone());
+				} else {
+					pending_with.push(node.clone());
+				}
+			}
+		}
+
+		let state = match (synced_with.is_empty(), pending_with.is_empty()) {
+			(true, true) => SyncState::LocalOnly,
+			(_, false) => SyncState::Pending,
+			(false, true) => SyncState::Synced,
+		};
+
+		statuses.push(RecordSyncStatus {
+			id,
+			state,
+			synced_with,
+			pending_with,
+		});
+	}
+
+	Ok(statuses)
+}
+
+/// The timestamp of the newest change each node made to the record, including the changes to
+/// the tags assigned to objects
+async fn record_changes(
+	db: &PrismaClient,
+	model: SyncStatusModel,
+	pub_id: &[u8],
+) -> Result<HashMap<node::id::Type, i64>, QueryError> {
+	let (model_name, relation_where) = match model {
+		SyncStatusModel::Object => (
+			object::NAME,
+			relation_operation::group_id::equals(pub_id.to_vec()),
+		),
+		SyncStatusModel::Tag => (
+			tag::NAME,
+			relation_operation::item_id::equals(pub_id.to_vec()),
+		),
+	};
+
+	let record_id = match model {
+		SyncStatusModel::Object => json!(prisma_sync::object::SyncId {
+			pub_id: pub_id.to_vec()
+		}),
+		SyncStatusModel::Tag => json!(prisma_sync::tag::SyncId {
+			pub_id: pub_id.to_vec()
+		}),
+	};
+	let record_id = serde_json::to_vec(&record_id).unwrap_or_default();
+
+	let shared = db
+		.shared_operation()
+		.find_many(vec![
+			shared_operation::model::equals(model_name.to_string()),
+			shared_operation::record_id::equals(record_id),
+		])
+		.select(shared_operation::select!({ node_id timestamp }))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|op| (op.node_id, op.timestamp));
+
+	let relation = db
+		.relation_operation()
+		.find_many(vec![
+			relation_operation::relation::equals(tag_on_object::NAME.to_string()),
+			relation_where,
+		])
+		.select(relation_operation::select!({ node_id timestamp }))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|op| (op.node_id, op.timestamp));
+
+	let mut changes = HashMap::new();
+	for (node_id, timestamp) in shared.chain(relation) {
+		let newest = changes.entry(node_id).or_insert(timestamp);
+		*newest = (*newest).max(timestamp);
+	}
+
+	Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nodes_have_their_own_changes() {
+		let (us, peer, other) = (1, 2, 3);
+
+		let changes = HashMap::from([(us, 10), (peer, 20)]);
+
+		assert!(has_changes(peer, &changes, &HashMap::from([(us, 10)])));
+		assert!(has_changes(peer, &changes, &HashMap::from([(us, 15)])));
+		assert!(!has_changes(peer, &changes, &HashMap::from([(us, 5)])));
+		assert!(!has_changes(peer, &changes, &HashMap::new()));
+		assert!(!has_changes(other, &changes, &HashMap::from([(us, 10)])));
+		assert!(has_changes(
+			other,
+			&changes,
+			&HashMap::from([(us, 10), (peer, 20)])
+		));
+	}
+}
@@ -1,8 +1,139 @@
-use crate::prisma::{self, PrismaClient};
-use prisma_client_rust::{migrations::*, NewClientError};
+use crate::{
+	prisma::{self, PrismaClient},
+	util::error::{FileIOError, NonUtf8PathError},
+};
+
+use std::{error::Error as StdError, future::Future, iter, path::Path, time::Duration};
+
+use futures::{stream, Stream};
+use prisma_client_rust::{migrations::*, raw, NewClientError, QueryError};
+use serde::{Deserialize, Serialize};
+use specta::Type;
 use thiserror::Error;
+use tokio::{fs, time::sleep};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Extension of the copy of a database taken before migrating it, kept until the next migration
+pub const PRE_MIGRATION_EXTENSION: &str = "db.pre-migration";
+/// Extension of the copy of a database the migrations are tried on
+const MIGRATING_EXTENSION: &str = "db.migrating";
+
+/// Size of the pages of the databases, the default of SQLite
+const PAGE_SIZE: u32 = 4096;
+/// The messages of the errors of SQLite and Prisma for a database locked by another connection,
+/// `SQLITE_BUSY` and nothing else
+const BUSY_MESSAGES: [&str; 2] = ["database is locked", "DatabaseBusy"];
+/// How many times a write failing because the database stayed locked is tried again
+const BUSY_RETRIES: u32 = 4;
+/// How long to wait before trying a write again, doubled on each try
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How SQLite waits for the writes to reach the disk, a trade between speed and durability
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub enum SynchronousMode {
+	/// Doesn't wait, the last changes may be lost or the database corrupted on a power loss
+	Off,
+	/// Waits at the checkpoints of the write-ahead log, the last changes may be lost on a power
+	/// loss but the database isn't corrupted
+	Normal,
+	/// Waits on every transaction
+	#[default]
+	Full,
+}
+
+impl SynchronousMode {
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Off => "OFF",
+			Self::Normal => "NORMAL",
+			Self::Full => "FULL",
+		}
+	}
+}
+
+/// How the databases of the libraries are opened, shared by all libraries on this node and applied
+/// when a library is loaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct DatabaseSettings {
+	/// How long a query waits for another connection to release the database before failing with
+	/// "database is locked"
+	pub busy_timeout_secs: u32,
+	/// Size the write-ahead log grows to before it's moved into the database
+	pub wal_size_mb: u32,
+	pub synchronous: SynchronousMode,
+	/// Memory used to keep pages of the database
+	pub cache_size_mb: u32,
+}
+
+impl Default for DatabaseSettings {
+	fn default() -> Self {
+		Self {
+			busy_timeout_secs: 15,
+			wal_size_mb: 4,
+			synchronous: SynchronousMode::default(),
+			cache_size_mb: 2,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum DatabaseSettingsError {
+	#[error("the busy timeout must be between 1 and 600 seconds")]
+	BusyTimeout,
+	#[error("the write-ahead log size must be between 1 and 1024 MiB")]
+	WalSize,
+	#[error("the cache size must be between 1 and 1024 MiB")]
+	CacheSize,
+}
+
+impl DatabaseSettings {
+	pub fn validate(&self) -> Result<(), DatabaseSettingsError> {
+		if !(1..=600).contains(&self.busy_timeout_secs) {
+			return Err(DatabaseSettingsError::BusyTimeout);
+		}
+		if !(1..=1024).contains(&self.wal_size_mb) {
+			return Err(DatabaseSettingsError::WalSize);
+		}
+		if !(1..=1024).contains(&self.cache_size_mb) {
+			return Err(DatabaseSettingsError::CacheSize);
+		}
+
+		Ok(())
+	}
+
+	/// The pragmas of the settings, the busy timeout is given by the url of the database instead
+	fn pragmas(&self) -> [String; 5] {
+		let wal_size_bytes = self.wal_size_mb as u64 * 1024 * 1024;
+
+		[
+			"PRAGMA journal_mode = WAL".to_string(),
+			format!("PRAGMA synchronous = {}", self.synchronous.as_str()),
+			// A negative cache size is in KiB instead of pages
+			format!("PRAGMA cache_size = -{}", self.cache_size_mb as u64 * 1024),
+			format!(
+				"PRAGMA wal_autocheckpoint = {}",
+				wal_size_bytes / PAGE_SIZE as u64
+			),
+			format!("PRAGMA journal_size_limit = {wal_size_bytes}"),
+		]
+	}
+
+	/// Most of the pragmas only apply to the connection they're run on, the client of a library has
+	/// a single connection for them to apply to all of its queries
+	async fn apply(&self, client: &PrismaClient) -> Result<(), QueryError> {
+		for pragma in self.pragmas() {
+			client
+				._query_raw::<serde_json::Value>(raw!(&pragma))
+				.exec()
+				.await?;
+		}
+
+		Ok(())
+	}
+}
+
 /// MigrationError represents an error that occurring while opening a initialising and running migrations on the database.
 #[derive(Error, Debug)]
 pub enum MigrationError {
@@ -14,6 +145,14 @@ pub enum MigrationError {
 	#[cfg(not(debug_assertions))]
 	#[error("An error occurred during migration: {0}")]
 	MigrateFailed(#[from] MigrateDeployError),
+	#[error("the migrated database is corrupted, the database was left as it was: {0}")]
+	Validation(String),
+	#[error("An error occurred while checking the database: {0}")]
+	Query(#[from] QueryError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	NonUtf8Path(#[from] NonUtf8PathError),
 }
 
 /// load_and_migrate will load the database from the given path and migrate it to the latest version of the schema.
@@ -58,6 +197,249 @@ pub async fn load_and_migrate(db_url: &str) -> Result<PrismaClient, MigrationErr
 	Ok(client)
 }
 
+pub(crate) fn db_url(db_path: &Path) -> Result<String, NonUtf8PathError> {
+	db_url_with_busy_timeout(db_path, DatabaseSettings::default().busy_timeout_secs)
+}
+
+/// The url of the database, whose `socket_timeout` is the busy timeout of SQLite. The client has a
+/// single connection, SQLite only runs a write at a time anyway, and the pragmas of the
+/// [`DatabaseSettings`] are set on that connection.
+fn db_url_with_busy_timeout(
+	db_path: &Path,
+	busy_timeout_secs: u32,
+) -> Result<String, NonUtf8PathError> {
+	Ok(format!(
+		"file:{}?socket_timeout={busy_timeout_secs}&connection_limit=1",
+		db_path
+			.to_str()
+			.ok_or_else(|| NonUtf8PathError(db_path.into()))?
+	))
+}
+
+/// Whether the error, or one of its sources, is a query that failed because another connection
+/// kept the database locked for longer than the busy timeout
+pub fn is_database_busy(error: &(dyn StdError + 'static)) -> bool {
+	iter::successors(Some(error), |error| error.source()).any(|error| {
+		error.downcast_ref::<QueryError>().map_or(false, |error| {
+			let message = error.to_string();
+			BUSY_MESSAGES
+				.iter()
+				.any(|busy_message| message.contains(busy_message))
+		})
+	})
+}
+
+/// Runs the write again while it fails because the database is locked, waiting a bit longer each
+/// time, so jobs and the interface writing at the same time don't fail when the busy timeout
+/// isn't enough. `write` is run as a whole each time, it must be a single query or a transaction.
+pub async fn retry_if_busy<T, E, Fut>(mut write: impl FnMut() -> Fut) -> Result<T, E>
+where
+	E: StdError + 'static,
+	Fut: Future<Output = Result<T, E>>,
+{
+	let mut delay = BUSY_RETRY_DELAY;
+
+	for _ in 0..BUSY_RETRIES {
+		match write().await {
+			Err(e) if is_database_busy(&e) => {
+				warn!("The database is locked, trying again in {delay:?}: {e}");
+				sleep(delay).await;
+				delay *= 2;
+			}
+			res => return res,
+		}
+	}
+
+	write().await
+}
+
+#[derive(Deserialize)]
+struct AppliedMigration {
+	migration_name: String,
+}
+
+/// The row returned by `PRAGMA wal_checkpoint`, whose columns aren't needed
+#[derive(Deserialize)]
+struct Checkpoint {}
+
+#[derive(Deserialize)]
+struct IntegrityCheck {
+	integrity_check: String,
+}
+
+#[derive(Deserialize)]
+struct ForeignKeyViolation {
+	table: String,
+	parent: String,
+}
+
+/// The migrations that weren't applied to the database yet. A database without the migrations
+/// table is new, or was pushed by a debug build, and has nothing to lose.
+async fn pending_migrations(client: &PrismaClient) -> Vec<String> {
+	let Ok(applied) = client
+		._query_raw::<AppliedMigration>(raw!(
+			"SELECT migration_name FROM _prisma_migrations
+			WHERE finished_at IS NOT NULL AND rolled_back_at IS NULL"
+		))
+		.exec()
+		.await
+	else {
+		return vec![];
+	};
+
+	prisma::MIGRATIONS_DIR
+		.dirs()
+		.filter_map(|dir| dir.path().file_name()?.to_str())
+		.filter(|name| {
+			!applied
+				.iter()
+				.any(|migration| migration.migration_name == *name)
+		})
+		.map(str::to_string)
+		.collect()
+}
+
+/// Checks that the migrated database isn't corrupted and that its rows still reference each other
+async fn validate(client: &PrismaClient) -> Result<(), MigrationError> {
+	let checks = client
+		._query_raw::<IntegrityCheck>(raw!("PRAGMA integrity_check"))
+		.exec()
+		.await?;
+	if let Some(check) = checks.iter().find(|check| check.integrity_check != "ok") {
+		return Err(MigrationError::Validation(check.integrity_check.clone()));
+	}
+
+	let violations = client
+		._query_raw::<ForeignKeyViolation>(raw!("PRAGMA foreign_key_check"))
+		.exec()
+		.await?;
+	if let Some(violation) = violations.first() {
+		return Err(MigrationError::Validation(format!(
+			"{} rows of '{}' reference missing rows, like one of '{}'",
+			violations.len(),
+			violation.table,
+			violation.parent
+		)));
+	}
+
+	Ok(())
+}
+
+/// Moves the write-ahead log into the database, so the database file can be copied on its own
+async fn checkpoint(client: &PrismaClient) -> Result<(), QueryError> {
+	client
+		._query_raw::<Checkpoint>(raw!("PRAGMA wal_checkpoint(TRUNCATE)"))
+		.exec()
+		.await
+		.map(|_| ())
+}
+
+/// Removes a database with its write-ahead log, if it exists
+async fn remove_database(db_path: &Path) -> Result<(), FileIOError> {
+	for suffix in ["", "-wal", "-shm"] {
+		let mut path = db_path.as_os_str().to_owned();
+		path.push(suffix);
+
+		match fs::remove_file(&path).await {
+			Ok(_) => {}
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+			Err(e) => return Err(FileIOError::from((path, e))),
+		}
+	}
+
+	Ok(())
+}
+
+/// Migrates the database at `db_path` like [`load_and_migrate`], without risking the database.
+///
+/// When migrations are pending, they are first tried on a copy of the database, which is checked
+/// once migrated. The copy then replaces the database, which is kept next to it with the
+/// [`PRE_MIGRATION_EXTENSION`] to roll back to by hand. If a migration fails or the migrated copy
+/// is corrupted, the copy is removed and the database is left as it was.
+///
+/// The database is then opened with the `settings`.
+pub async fn load_and_migrate_safely(
+	db_path: &Path,
+	settings: &DatabaseSettings,
+) -> Result<PrismaClient, MigrationError> {
+	let client = migrate_safely(db_path, settings.busy_timeout_secs).await?;
+	settings.apply(&client).await?;
+
+	Ok(client)
+}
+
+async fn migrate_safely(
+	db_path: &Path,
+	busy_timeout_secs: u32,
+) -> Result<PrismaClient, MigrationError> {
+	let db_url = db_url_with_busy_timeout(db_path, busy_timeout_secs)?;
+
+	if fs::metadata(db_path).await.is_err() {
+		return load_and_migrate(&db_url).await;
+	}
+
+	let client = prisma::new_client_with_url(&db_url)
+		.await
+		.map_err(Box::new)?;
+	let pending = pending_migrations(&client).await;
+	if pending.is_empty() {
+		drop(client);
+		return load_and_migrate(&db_url).await;
+	}
+
+	info!(
+		"Trying {} pending migrations on a copy of '{}': {}",
+		pending.len(),
+		db_path.display(),
+		pending.join(", ")
+	);
+
+	checkpoint(&client).await?;
+	drop(client);
+
+	let migrating_path = db_path.with_extension(MIGRATING_EXTENSION);
+	remove_database(&migrating_path).await?;
+	fs::copy(db_path, &migrating_path)
+		.await
+		.map_err(|e| FileIOError::from((&migrating_path, e)))?;
+
+	let res = async {
+		let client = load_and_migrate(&db_url(&migrating_path)?).await?;
+		validate(&client).await?;
+		checkpoint(&client).await?;
+
+		Ok::<_, MigrationError>(())
+	}
+	.await;
+
+	if let Err(e) = res {
+		warn!(
+			"Migrating '{}' failed, the database was left as it was: {e}",
+			db_path.display()
+		);
+		remove_database(&migrating_path).await?;
+		return Err(e);
+	}
+
+	let snapshot_path = db_path.with_extension(PRE_MIGRATION_EXTENSION);
+	fs::rename(db_path, &snapshot_path)
+		.await
+		.map_err(|e| FileIOError::from((&snapshot_path, e)))?;
+	remove_database(db_path).await?;
+	fs::rename(&migrating_path, db_path)
+		.await
+		.map_err(|e| FileIOError::from((db_path, e)))?;
+	remove_database(&migrating_path).await?;
+
+	info!(
+		"Migrated '{}', the database before the migration is at '{}'",
+		db_path.display(),
+		snapshot_path.display()
+	);
+
+	load_and_migrate(&db_url).await
+}
+
 /// Combines an iterator of `T` and an iterator of `Option<T>`,
 /// removing any `None` values in the process
 pub fn chain_optional_iter<T>(
@@ -72,6 +454,38 @@ pub fn chain_optional_iter<T>(
 		.collect()
 }
 
+/// The rows of a query, a page at a time, for the queries with too many rows to read them all.
+///
+/// Each page starts after the id of the last row of the previous one, so only a page is kept in
+/// memory and the last pages are as fast to read as the first ones, unlike with `skip`. `fetch` is
+/// given the id to start after, `None` for the first page, and the size of the pages, and returns
+/// the rows with a greater id ordered by their id.
+pub fn keyset_pages<T, Fetch, Fut>(
+	page_size: i64,
+	id: fn(&T) -> i32,
+	fetch: Fetch,
+) -> impl Stream<Item = Result<Vec<T>, QueryError>>
+where
+	Fetch: FnMut(Option<i32>, i64) -> Fut,
+	Fut: Future<Output = Result<Vec<T>, QueryError>>,
+{
+	stream::try_unfold((fetch, Some(None)), move |(mut fetch, after)| async move {
+		let Some(after) = after else {
+			return Ok(None);
+		};
+
+		let page = fetch(after, page_size).await?;
+		if page.is_empty() {
+			return Ok(None);
+		}
+
+		// A page that isn't full is the last one
+		let next = (page.len() as i64 >= page_size).then(|| Some(id(&page[page.len() - 1])));
+
+		Ok(Some((page, (fetch, next))))
+	})
+}
+
 pub fn uuid_to_bytes(uuid: Uuid) -> Vec<u8> {
 	uuid.as_bytes().to_vec()
 }
@@ -107,3 +521,67 @@ pub fn maybe_missing<T: OptionalField>(
 ) -> Result<T::Out, MissingFieldError> {
 	data.transform().ok_or(MissingFieldError(field))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::TryStreamExt;
+
+	#[test]
+	fn database_settings_are_validated() {
+		assert!(DatabaseSettings::default().validate().is_ok());
+		assert!(matches!(
+			DatabaseSettings {
+				busy_timeout_secs: 0,
+				..Default::default()
+			}
+			.validate(),
+			Err(DatabaseSettingsError::BusyTimeout)
+		));
+		assert!(matches!(
+			DatabaseSettings {
+				wal_size_mb: 2048,
+				..Default::default()
+			}
+			.validate(),
+			Err(DatabaseSettingsError::WalSize)
+		));
+	}
+
+	#[test]
+	fn wal_size_is_in_pages() {
+		let pragmas = DatabaseSettings::default().pragmas();
+
+		assert!(pragmas.contains(&"PRAGMA wal_autocheckpoint = 1024".to_string()));
+		assert!(pragmas.contains(&"PRAGMA cache_size = -2048".to_string()));
+	}
+
+	#[tokio::test]
+	async fn pages_start_after_the_last_row() {
+		let rows = (1..=7).collect::<Vec<i32>>();
+		let mut queries = 0;
+
+		let pages = keyset_pages(
+			3,
+			|row| *row,
+			|after, take| {
+				queries += 1;
+				let page = rows
+					.iter()
+					.copied()
+					.filter(|row| after.map_or(true, |after| *row > after))
+					.take(take as usize)
+					.collect::<Vec<_>>();
+
+				async move { Ok(page) }
+			},
+		)
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap();
+
+		assert_eq!(pages, vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+		assert_eq!(queries, 3);
+	}
+}
@@ -8,12 +8,13 @@ use std::{
 
 use crate::{
 	job::JobManagerError,
-	library::{LibraryConfig, LibraryManagerError},
+	library::{LibraryConfig, LibraryManagerError, LibrarySettings},
 	location::{
 		delete_location, scan_location, LocationCreateArgs, LocationError, LocationManagerError,
 	},
 	node::NodeConfig,
 	prisma::location,
+	sync::{ConflictPolicy, OperationCipher},
 	util::AbortOnDrop,
 };
 use prisma_client_rust::QueryError;
@@ -123,6 +124,13 @@ impl InitConfig {
 								description: lib.description,
 								identity: Identity::new().to_bytes(),
 								node_id: node_pub_id,
+								settings: LibrarySettings::default(),
+								sync_conflict_policy: ConflictPolicy::default(),
+								backup_targets: vec![],
+								backup_key: None,
+								sync_key: OperationCipher::generate_key(),
+								encryption: None,
+								cloned_from: None,
 							},
 							node_cfg.clone(),
 						)
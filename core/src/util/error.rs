@@ -19,6 +19,16 @@ impl<P: AsRef<Path>> From<(P, io::Error)> for FileIOError {
 	}
 }
 
+impl FileIOError {
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn io_error(&self) -> &io::Error {
+		&self.source
+	}
+}
+
 #[derive(Debug, Error)]
 #[error("received a non UTF-8 path: <lossy_path='{}'>", .0.to_string_lossy())]
 pub struct NonUtf8PathError(pub Box<Path>);
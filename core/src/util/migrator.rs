@@ -38,6 +38,16 @@ pub trait Migrate: Sized + DeserializeOwned + Serialize {
 		ctx: &Self::Ctx,
 	) -> Result<(), MigratorError>;
 
+	/// Called on the config before it's written to `path`, to keep parts of it out of the file
+	fn to_file(_path: &Path, _config: &mut Map<String, Value>) -> Result<(), MigratorError> {
+		Ok(())
+	}
+
+	/// Called on the config read from `path`, to put back what [`Migrate::to_file`] kept out of it
+	fn from_file(_path: &Path, _config: &mut Map<String, Value>) -> Result<(), MigratorError> {
+		Ok(())
+	}
+
 	async fn load_and_migrate(path: &Path, ctx: &Self::Ctx) -> Result<Self, MigratorError> {
 		match path.try_exists()? {
 			true => {
@@ -91,6 +101,8 @@ pub trait Migrate: Sized + DeserializeOwned + Serialize {
 					file.write_all(serde_json::to_string(&cfg)?.as_bytes())?; // Writes updated version
 				}
 
+				Self::from_file(path, &mut cfg.other)?;
+
 				Ok(serde_json::from_value(Value::Object(cfg.other))?)
 			}
 			false => Ok(serde_json::from_value(Value::Object(
@@ -110,8 +122,11 @@ pub trait Migrate: Sized + DeserializeOwned + Serialize {
 			},
 		};
 
+		let mut file_config = config.clone();
+		Self::to_file(path, &mut file_config.other)?;
+
 		let mut file = File::create(path)?;
-		file.write_all(serde_json::to_string(&config)?.as_bytes())?;
+		file.write_all(serde_json::to_string(&file_config)?.as_bytes())?;
 		Ok(config)
 	}
 }
@@ -138,6 +153,8 @@ pub enum MigratorError {
 	MissingField(#[from] MissingFieldError),
 	#[error("custom migration error: {0}")]
 	Custom(String),
+	#[error("error reading a secret of the config from the keychain: {0}")]
+	Keychain(#[from] sd_crypto::Error),
 }
 
 #[cfg(test)]
@@ -5,6 +5,7 @@ pub mod debug_initializer;
 pub mod error;
 mod maybe_undefined;
 pub mod migrator;
+pub mod sparse;
 pub mod version_manager;
 
 pub use abort_on_drop::*;
@@ -0,0 +1,247 @@
+//! Sparse files, like disk images and the disks of virtual machines, have holes: ranges that were
+//! never written, that read as zeros and take no space on the disk. Their data is found with
+//! `SEEK_DATA` and `SEEK_HOLE` on Linux and macOS, so copies keep the holes and hashing doesn't
+//! read gigabytes of zeros. Anywhere else, a file is a single range of data.
+
+use std::{fs::Metadata, ops::Range, path::Path};
+
+use tokio::{
+	fs::{File, OpenOptions},
+	io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+};
+
+/// Data is read, and zeros are hashed, in chunks this big
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+static ZEROS: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+
+/// Whether a file takes less space on the disk than its size, so it has holes
+#[cfg(unix)]
+pub fn is_sparse(metadata: &Metadata) -> bool {
+	use std::os::unix::fs::MetadataExt;
+
+	// Blocks are always counted in 512 bytes units, whatever the block size of the filesystem
+	metadata.is_file() && metadata.blocks() * 512 < metadata.len()
+}
+
+#[cfg(not(unix))]
+pub fn is_sparse(_: &Metadata) -> bool {
+	false
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod seek {
+	use std::os::{fd::AsRawFd, raw::c_int};
+
+	use tokio::{fs::File, io};
+
+	fn seek(file: &File, offset: u64, whence: c_int) -> io::Result<Option<u64>> {
+		// SAFETY: the file descriptor stays open for the whole call
+		match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+			-1 => {
+				let e = io::Error::last_os_error();
+				match e.raw_os_error() {
+					// No data after the offset
+					Some(libc::ENXIO) => Ok(None),
+					_ => Err(e),
+				}
+			}
+			offset => Ok(Some(offset as u64)),
+		}
+	}
+
+	/// Where the data after `offset` starts, `None` if there's only a hole after it
+	pub(super) fn next_data(file: &File, offset: u64) -> io::Result<Option<u64>> {
+		seek(file, offset, libc::SEEK_DATA)
+	}
+
+	/// Where the hole after `offset` starts, files end with one
+	pub(super) fn next_hole(file: &File, offset: u64) -> io::Result<Option<u64>> {
+		seek(file, offset, libc::SEEK_HOLE)
+	}
+
+	/// The filesystem doesn't know where the holes are
+	pub(super) fn is_unsupported(e: &io::Error) -> bool {
+		e.raw_os_error() == Some(libc::EINVAL)
+	}
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod seek {
+	use tokio::{fs::File, io};
+
+	pub(super) fn next_data(_: &File, offset: u64) -> io::Result<Option<u64>> {
+		Ok(Some(offset))
+	}
+
+	pub(super) fn next_hole(_: &File, _: u64) -> io::Result<Option<u64>> {
+		Ok(None)
+	}
+
+	pub(super) fn is_unsupported(_: &io::Error) -> bool {
+		false
+	}
+}
+
+/// The ranges of the first `size` bytes of `file` that have data, in order. The file's position is
+/// left anywhere, so it must be seeked before reading it.
+pub fn data_ranges(file: &File, size: u64) -> io::Result<Vec<Range<u64>>> {
+	let mut ranges = vec![];
+	let mut offset = 0;
+
+	while offset < size {
+		let start = match seek::next_data(file, offset) {
+			Ok(Some(start)) if start < size => start,
+			Ok(_) => break,
+			Err(e) if seek::is_unsupported(&e) => return Ok(vec![0..size]),
+			Err(e) => return Err(e),
+		};
+		let end = seek::next_hole(file, start)?.unwrap_or(size).min(size);
+
+		ranges.push(start..end);
+		offset = end;
+	}
+
+	Ok(ranges)
+}
+
+/// Whether `range` of `file` is all in a hole, so it would read as zeros
+pub fn is_hole(file: &File, range: Range<u64>) -> io::Result<bool> {
+	match seek::next_data(file, range.start) {
+		Ok(Some(start)) => Ok(start >= range.end),
+		Ok(None) => Ok(true),
+		Err(e) if seek::is_unsupported(&e) => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+/// Feeds `len` zeros to `hasher`, as the hole they're in would read
+pub fn hash_zeros(hasher: &mut blake3::Hasher, mut len: u64) {
+	while len > 0 {
+		let chunk = len.min(CHUNK_SIZE as u64) as usize;
+		hasher.update(&ZEROS[..chunk]);
+		len -= chunk as u64;
+	}
+}
+
+/// Copies `len` bytes from the position of `source` to the position of `target`
+async fn copy_range(source: &mut File, target: &mut File, mut len: u64) -> io::Result<()> {
+	let mut buf = vec![0; CHUNK_SIZE];
+
+	while len > 0 {
+		let chunk = &mut buf[..len.min(CHUNK_SIZE as u64) as usize];
+		source.read_exact(chunk).await?;
+		target.write_all(chunk).await?;
+		len -= chunk.len() as u64;
+	}
+
+	Ok(())
+}
+
+/// Copies a sparse file to `target`, replacing it, writing only its data so the copy keeps its
+/// holes. Returns how many bytes of data were copied.
+pub async fn copy_sparse(source: &Path, target: &Path) -> io::Result<u64> {
+	let mut source = File::open(source).await?;
+	let size = source.metadata().await?.len();
+
+	let mut target = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(target)
+		.await?;
+
+	let mut copied = 0;
+	for range in data_ranges(&source, size)? {
+		source.seek(SeekFrom::Start(range.start)).await?;
+		target.seek(SeekFrom::Start(range.start)).await?;
+		copy_range(&mut source, &mut target, range.end - range.start).await?;
+
+		copied += range.end - range.start;
+	}
+
+	// A hole at the end isn't written, only the size is set
+	target.set_len(size).await?;
+	target.sync_all().await?;
+
+	Ok(copied)
+}
+
+/// The BLAKE3 checksum of the first `size` bytes of a sparse file, with zeros hashed for its holes
+/// instead of reading them
+pub async fn hash_sparse(file: &mut File, size: u64) -> io::Result<blake3::Hasher> {
+	let mut hasher = blake3::Hasher::new();
+	let mut buf = vec![0; CHUNK_SIZE];
+	let mut offset = 0;
+
+	for range in data_ranges(file, size)? {
+		hash_zeros(&mut hasher, range.start - offset);
+
+		file.seek(SeekFrom::Start(range.start)).await?;
+		let mut len = range.end - range.start;
+		while len > 0 {
+			let chunk = &mut buf[..len.min(CHUNK_SIZE as u64) as usize];
+			file.read_exact(chunk).await?;
+			hasher.update(chunk);
+			len -= chunk.len() as u64;
+		}
+
+		offset = range.end;
+	}
+	hash_zeros(&mut hasher, size - offset);
+
+	Ok(hasher)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::fs;
+
+	/// A file with data at its start and in its middle, and holes between and after them
+	async fn sparse_file(path: &Path) -> Vec<u8> {
+		let size = 64 * CHUNK_SIZE as u64;
+		let mut file = File::create(path).await.unwrap();
+		file.set_len(size).await.unwrap();
+		file.write_all(b"disk header").await.unwrap();
+		file.seek(SeekFrom::Start(size / 2)).await.unwrap();
+		file.write_all(b"partition").await.unwrap();
+		file.sync_all().await.unwrap();
+
+		fs::read(path).await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn copies_and_hashes_like_the_contents() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("disk.img");
+		let target = dir.path().join("disk copy.img");
+		let contents = sparse_file(&source).await;
+
+		// Whether the filesystem of the temporary directory keeps holes depends on the machine,
+		// the results are the same either way
+		let copied = copy_sparse(&source, &target).await.unwrap();
+		assert!(copied <= contents.len() as u64);
+		assert_eq!(fs::read(&target).await.unwrap(), contents);
+
+		let mut file = File::open(&source).await.unwrap();
+		let hasher = hash_sparse(&mut file, contents.len() as u64).await.unwrap();
+		assert_eq!(hasher.finalize(), blake3::hash(&contents));
+
+		let ranges = data_ranges(&file, contents.len() as u64).unwrap();
+		assert!(ranges.iter().any(|range| range.contains(&0)));
+		assert!(!is_hole(&file, 0..11).unwrap());
+	}
+
+	#[test]
+	fn hashes_zeros_in_chunks() {
+		let mut hasher = blake3::Hasher::new();
+		hash_zeros(&mut hasher, CHUNK_SIZE as u64 * 2 + 3);
+
+		assert_eq!(
+			hasher.finalize(),
+			blake3::hash(&vec![0; CHUNK_SIZE * 2 + 3])
+		);
+	}
+}
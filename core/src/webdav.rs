@@ -0,0 +1,697 @@
+//! A read-only WebDAV server over the locations and tags of the libraries, so other devices and apps
+//! can browse them without Spacedrive, like the file managers of phones and TVs.
+//!
+//! What's served is chosen with shares, each with its own credentials: a share of a location serves
+//! its directories as they're indexed, a share of a tag serves a directory of the files of its
+//! objects. A share is at `<base>/<library id>/<share name>/` and asks for its credentials with
+//! basic authentication, so it should only be reached over HTTPS from outside the local network.
+//! Only the files on this node are served.
+
+use crate::{
+	custom_uri::{mime_type, read_file},
+	library::Library,
+	prisma::{file_path, location, object, tag, tag_on_object, webdav_share},
+	util::error::FileIOError,
+	Node,
+};
+
+use std::{
+	collections::HashSet,
+	fmt::Write,
+	io,
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use http_range::HttpRange;
+use httpz::{
+	http::{header, Method, Response, StatusCode},
+	Endpoint, GenericEndpoint, HttpEndpoint, Request,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use prisma_client_rust::{Direction, QueryError};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::fs::File;
+use tracing::error;
+use uuid::Uuid;
+
+const REALM: &str = "Spacedrive";
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+/// What's escaped in the segments of the hrefs, all but the unreserved characters of URLs
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'_')
+	.remove(b'~');
+const MAX_SHARE_NAME_LENGTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum WebdavShareError {
+	#[error("share names can only have letters, digits, '-' and '_', up to {MAX_SHARE_NAME_LENGTH} of them: {0}")]
+	InvalidName(String),
+	#[error("a share already has this name: {0}")]
+	NameTaken(String),
+	#[error("a share is either of a location or of a tag")]
+	InvalidTarget,
+	#[error("usernames can't be empty or have a ':'")]
+	InvalidUsername,
+	#[error("share not found: {0}")]
+	NotFound(webdav_share::id::Type),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+}
+
+impl From<WebdavShareError> for rspc::Error {
+	fn from(e: WebdavShareError) -> Self {
+		let code = match e {
+			WebdavShareError::NotFound(_) => rspc::ErrorCode::NotFound,
+			WebdavShareError::NameTaken(_) => rspc::ErrorCode::Conflict,
+			WebdavShareError::InvalidName(_)
+			| WebdavShareError::InvalidTarget
+			| WebdavShareError::InvalidUsername => rspc::ErrorCode::BadRequest,
+			WebdavShareError::Database(_) => rspc::ErrorCode::InternalServerError,
+		};
+
+		rspc::Error::with_cause(code, e.to_string(), e)
+	}
+}
+
+/// Checks a share before it's created
+pub fn validate_share(
+	name: &str,
+	username: &str,
+	location_id: Option<location::id::Type>,
+	tag_id: Option<tag::id::Type>,
+) -> Result<(), WebdavShareError> {
+	if name.is_empty()
+		|| name.len() > MAX_SHARE_NAME_LENGTH
+		|| !name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+	{
+		return Err(WebdavShareError::InvalidName(name.to_string()));
+	}
+
+	if username.is_empty() || username.contains(':') {
+		return Err(WebdavShareError::InvalidUsername);
+	}
+
+	if location_id.is_some() == tag_id.is_some() {
+		return Err(WebdavShareError::InvalidTarget);
+	}
+
+	Ok(())
+}
+
+/// The passwords of the shares are made by the node, random enough for a fast hash to do
+pub fn generate_password() -> String {
+	Uuid::new_v4().simple().to_string()
+}
+
+pub fn hash_password(password: &str) -> Vec<u8> {
+	Sha256::digest(password.as_bytes()).to_vec()
+}
+
+/// Compares in a time that doesn't depend on where the hashes differ
+pub(crate) fn hashes_match(expected: &[u8], given: &[u8]) -> bool {
+	expected.len() == given.len()
+		&& expected
+			.iter()
+			.zip(given)
+			.fold(0, |diff, (a, b)| diff | (a ^ b))
+			== 0
+}
+
+#[derive(Error, Debug)]
+enum WebdavError {
+	#[error("unauthorized")]
+	Unauthorized,
+	#[error("not found")]
+	NotFound,
+	#[error("method not allowed")]
+	MethodNotAllowed,
+	#[error("bad request: {0}")]
+	BadRequest(&'static str),
+	#[error("invalid range")]
+	RangeNotSatisfiable,
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	Http(#[from] httpz::http::Error),
+}
+
+impl From<WebdavError> for Response<Vec<u8>> {
+	fn from(e: WebdavError) -> Self {
+		let builder = Response::builder().header(header::CONTENT_TYPE, "text/plain");
+
+		let builder = match e {
+			WebdavError::Unauthorized => builder
+				.status(StatusCode::UNAUTHORIZED)
+				.header(header::WWW_AUTHENTICATE, format!("Basic realm=\"{REALM}\"")),
+			WebdavError::NotFound => builder.status(StatusCode::NOT_FOUND),
+			WebdavError::MethodNotAllowed => builder
+				.status(StatusCode::METHOD_NOT_ALLOWED)
+				.header(header::ALLOW, ALLOWED_METHODS),
+			WebdavError::BadRequest(_) => builder.status(StatusCode::BAD_REQUEST),
+			WebdavError::RangeNotSatisfiable => builder.status(StatusCode::RANGE_NOT_SATISFIABLE),
+			WebdavError::Database(_) | WebdavError::FileIO(_) | WebdavError::Http(_) => {
+				error!("Failed to answer a WebDAV request: {e}");
+				builder.status(StatusCode::INTERNAL_SERVER_ERROR)
+			}
+		};
+
+		builder
+			.body(e.to_string().into_bytes())
+			.expect("the response is valid")
+	}
+}
+
+file_path::select!(file_path_for_webdav {
+	object_id
+	materialized_path
+	name
+	extension
+	is_dir
+	size_in_bytes_bytes
+	date_modified
+	location: select { path }
+});
+
+/// A file or a directory of a share
+struct Entry {
+	name: String,
+	is_dir: bool,
+	size: u64,
+	modified: Option<DateTime<Utc>>,
+	extension: String,
+	path: PathBuf,
+}
+
+impl Entry {
+	fn from_file_path(file_path: file_path_for_webdav::Data) -> Option<Self> {
+		let extension = file_path.extension.unwrap_or_default();
+		let name = match file_path.name? {
+			name if extension.is_empty() => name,
+			name => format!("{name}.{extension}"),
+		};
+
+		Some(Self {
+			path: Path::new(&file_path.location?.path?)
+				.join(file_path.materialized_path?.trim_start_matches('/'))
+				.join(&name),
+			name,
+			is_dir: file_path.is_dir.unwrap_or(false),
+			size: file_path
+				.size_in_bytes_bytes
+				.as_deref()
+				.and_then(|bytes| bytes.try_into().ok())
+				.map_or(0, u64::from_be_bytes),
+			modified: file_path.date_modified.map(Into::into),
+			extension,
+		})
+	}
+}
+
+enum Resource {
+	/// The root of the share has no entry
+	Dir {
+		entry: Option<Entry>,
+		children: Vec<Entry>,
+	},
+	File(Entry),
+}
+
+/// What a share serves
+enum ShareRoot {
+	Location(location::id::Type),
+	Tag(tag::id::Type),
+}
+
+impl ShareRoot {
+	async fn list(&self, library: &Library, dir: &[String]) -> Result<Vec<Entry>, QueryError> {
+		let on_this_node =
+			file_path::location::is(vec![location::node_id::equals(Some(library.node_local_id))]);
+
+		match *self {
+			Self::Location(location_id) => {
+				let materialized_path = if dir.is_empty() {
+					"/".to_string()
+				} else {
+					format!("/{}/", dir.join("/"))
+				};
+
+				Ok(library
+					.db
+					.file_path()
+					.find_many(vec![
+						file_path::location_id::equals(Some(location_id)),
+						file_path::materialized_path::equals(Some(materialized_path)),
+						on_this_node,
+					])
+					.select(file_path_for_webdav::select())
+					.exec()
+					.await?
+					.into_iter()
+					.filter_map(Entry::from_file_path)
+					.collect())
+			}
+			// A flat directory, the objects are shown by one of their files
+			Self::Tag(_) if !dir.is_empty() => Ok(vec![]),
+			Self::Tag(tag_id) => {
+				let file_paths = library
+					.db
+					.file_path()
+					.find_many(vec![
+						file_path::is_dir::equals(Some(false)),
+						file_path::object::is(vec![
+							object::date_deleted::equals(None),
+							object::tags::some(vec![tag_on_object::tag_id::equals(tag_id)]),
+						]),
+						on_this_node,
+					])
+					.order_by(file_path::id::order(Direction::Asc))
+					.select(file_path_for_webdav::select())
+					.exec()
+					.await?;
+
+				let mut objects = HashSet::new();
+				let mut names = HashSet::new();
+
+				Ok(file_paths
+					.into_iter()
+					.filter(|file_path| objects.insert(file_path.object_id))
+					.filter_map(Entry::from_file_path)
+					.map(|mut entry| {
+						entry.name = free_name(&names, &entry.name);
+						names.insert(entry.name.clone());
+						entry
+					})
+					.collect())
+			}
+		}
+	}
+
+	async fn resolve(
+		&self,
+		library: &Library,
+		path: &[String],
+	) -> Result<Option<Resource>, QueryError> {
+		let Some((name, parent)) = path.split_last() else {
+			return Ok(Some(Resource::Dir {
+				entry: None,
+				children: self.list(library, path).await?,
+			}));
+		};
+
+		let Some(entry) = self
+			.list(library, parent)
+			.await?
+			.into_iter()
+			.find(|entry| &entry.name == name)
+		else {
+			return Ok(None);
+		};
+
+		Ok(Some(if entry.is_dir {
+			Resource::Dir {
+				entry: Some(entry),
+				children: self.list(library, path).await?,
+			}
+		} else {
+			Resource::File(entry)
+		}))
+	}
+}
+
+/// `name`, with a number if another file of the directory has it
+fn free_name(names: &HashSet<String>, name: &str) -> String {
+	if !names.contains(name) {
+		return name.to_string();
+	}
+
+	let (stem, extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+		_ => (name, String::new()),
+	};
+
+	(2..)
+		.map(|i| format!("{stem} ({i}){extension}"))
+		.find(|name| !names.contains(name))
+		.expect("there's always a free name")
+}
+
+fn authorized(share: &webdav_share::Data, req: &Request) -> bool {
+	let Some(credentials) = req
+		.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Basic "))
+		.and_then(|value| STANDARD.decode(value.trim()).ok())
+		.and_then(|value| String::from_utf8(value).ok())
+	else {
+		return false;
+	};
+
+	credentials
+		.split_once(':')
+		.map_or(false, |(username, password)| {
+			username == share.username
+				&& hashes_match(&share.password_hash, &hash_password(password))
+		})
+}
+
+fn escape_xml(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
+}
+
+fn href(base: &str, path: &[String], is_dir: bool) -> String {
+	let mut href = base.to_string();
+	for segment in path {
+		href.push('/');
+		href.extend(utf8_percent_encode(segment, SEGMENT));
+	}
+	if is_dir {
+		href.push('/');
+	}
+
+	href
+}
+
+fn write_response(xml: &mut String, href: &str, name: &str, entry: Option<&Entry>) {
+	let is_dir = entry.map_or(true, |entry| entry.is_dir);
+
+	xml.push_str("<D:response>");
+	let _ = write!(xml, "<D:href>{}</D:href>", escape_xml(href));
+	xml.push_str("<D:propstat><D:prop>");
+	let _ = write!(xml, "<D:displayname>{}</D:displayname>", escape_xml(name));
+
+	if is_dir {
+		xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+	} else {
+		xml.push_str("<D:resourcetype/>");
+	}
+
+	if let Some(entry) = entry {
+		if !entry.is_dir {
+			let _ = write!(
+				xml,
+				"<D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+				entry.size,
+				mime_type(&entry.extension).unwrap_or("application/octet-stream")
+			);
+		}
+		if let Some(modified) = entry.modified {
+			let _ = write!(
+				xml,
+				"<D:getlastmodified>{}</D:getlastmodified>",
+				modified.format("%a, %d %b %Y %H:%M:%S GMT")
+			);
+		}
+	}
+
+	xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+}
+
+/// Answers with the properties of the resource, and of its children with a depth of 1. Deeper
+/// requests are answered as if they were of depth 1, like most servers do.
+fn propfind(
+	req: &Request,
+	base: &str,
+	share_name: &str,
+	path: &[String],
+	resource: &Resource,
+) -> Result<Response<Vec<u8>>, WebdavError> {
+	let depth_zero = req
+		.headers()
+		.get("depth")
+		.and_then(|depth| depth.to_str().ok())
+		.map_or(false, |depth| depth.trim() == "0");
+
+	let mut xml =
+		String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+
+	match resource {
+		Resource::File(entry) => {
+			write_response(&mut xml, &href(base, path, false), &entry.name, Some(entry))
+		}
+		Resource::Dir { entry, children } => {
+			write_response(
+				&mut xml,
+				&href(base, path, true),
+				entry.as_ref().map_or(share_name, |entry| &entry.name),
+				entry.as_ref(),
+			);
+
+			if !depth_zero {
+				for child in children {
+					let mut child_path = path.to_vec();
+					child_path.push(child.name.clone());
+
+					write_response(
+						&mut xml,
+						&href(base, &child_path, child.is_dir),
+						&child.name,
+						Some(child),
+					);
+				}
+			}
+		}
+	}
+
+	xml.push_str("</D:multistatus>");
+
+	Ok(Response::builder()
+		// 207 Multi-Status
+		.status(StatusCode::MULTI_STATUS)
+		.header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+		.body(xml.into_bytes())?)
+}
+
+async fn get(req: &Request, entry: &Entry) -> Result<Response<Vec<u8>>, WebdavError> {
+	let file = File::open(&entry.path).await.map_err(|e| {
+		if e.kind() == io::ErrorKind::NotFound {
+			WebdavError::NotFound
+		} else {
+			FileIOError::from((&entry.path, e)).into()
+		}
+	})?;
+	let size = file
+		.metadata()
+		.await
+		.map_err(|e| FileIOError::from((&entry.path, e)))?
+		.len();
+
+	let mut builder = Response::builder()
+		.header(header::ACCEPT_RANGES, "bytes")
+		.header(
+			header::CONTENT_TYPE,
+			mime_type(&entry.extension).unwrap_or("application/octet-stream"),
+		);
+
+	if let Some(modified) = entry.modified {
+		builder = builder.header(
+			header::LAST_MODIFIED,
+			modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+		);
+	}
+
+	if req.method() == Method::HEAD {
+		return Ok(builder
+			.header(header::CONTENT_LENGTH, size)
+			.status(StatusCode::OK)
+			.body(vec![])?);
+	}
+
+	let range = match req.headers().get(header::RANGE) {
+		Some(range) => {
+			let ranges = range
+				.to_str()
+				.ok()
+				.and_then(|range| HttpRange::parse(range, size).ok())
+				.ok_or(WebdavError::RangeNotSatisfiable)?;
+			// Only single ranges, like the other endpoints
+			match ranges.as_slice() {
+				[range] => Some(*range),
+				_ => return Err(WebdavError::RangeNotSatisfiable),
+			}
+		}
+		None => None,
+	};
+
+	match range {
+		Some(range) => {
+			let body = read_file(file, range.length, Some(range.start))
+				.await
+				.map_err(|e| FileIOError::from((&entry.path, e)))?;
+
+			Ok(builder
+				.header(
+					header::CONTENT_RANGE,
+					format!(
+						"bytes {}-{}/{size}",
+						range.start,
+						range.start + range.length - 1
+					),
+				)
+				.header(header::CONTENT_LENGTH, body.len())
+				.status(StatusCode::PARTIAL_CONTENT)
+				.body(body)?)
+		}
+		None => {
+			let body = read_file(file, size, None)
+				.await
+				.map_err(|e| FileIOError::from((&entry.path, e)))?;
+
+			Ok(builder
+				.header(header::CONTENT_LENGTH, body.len())
+				.status(StatusCode::OK)
+				.body(body)?)
+		}
+	}
+}
+
+async fn handler(
+	node: &Node,
+	base_path: &str,
+	req: &Request,
+) -> Result<Response<Vec<u8>>, WebdavError> {
+	let segments = req
+		.uri()
+		.path()
+		.split('/')
+		.filter(|segment| !segment.is_empty())
+		.map(|segment| {
+			percent_decode_str(segment)
+				.decode_utf8()
+				.map(|segment| segment.into_owned())
+		})
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(|_| WebdavError::BadRequest("the path isn't valid UTF-8"))?;
+
+	let [library_id, share_name, path @ ..] = segments.as_slice() else {
+		return Err(WebdavError::NotFound);
+	};
+	if path.iter().any(|segment| segment == "." || segment == "..") {
+		return Err(WebdavError::BadRequest("the path can't have '.' or '..'"));
+	}
+
+	let library = match Uuid::from_str(library_id) {
+		Ok(library_id) => node.library_manager.get_library(library_id).await,
+		Err(_) => None,
+	};
+	let Some(library) = library else {
+		return Err(WebdavError::NotFound);
+	};
+
+	// The shares that don't exist ask for credentials too, not to tell which ones do
+	let share = library
+		.db
+		.webdav_share()
+		.find_unique(webdav_share::name::equals(share_name.clone()))
+		.exec()
+		.await?
+		.filter(|share| authorized(share, req))
+		.ok_or(WebdavError::Unauthorized)?;
+
+	let method = req.method();
+	if method == Method::OPTIONS {
+		return Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header("DAV", "1")
+			.header(header::ALLOW, ALLOWED_METHODS)
+			.body(vec![])?);
+	}
+
+	let root = match (share.location_id, share.tag_id) {
+		(Some(location_id), _) => ShareRoot::Location(location_id),
+		(None, Some(tag_id)) => ShareRoot::Tag(tag_id),
+		(None, None) => return Err(WebdavError::NotFound),
+	};
+
+	let resource = root
+		.resolve(&library, path)
+		.await?
+		.ok_or(WebdavError::NotFound)?;
+
+	let base = href(
+		base_path.trim_end_matches('/'),
+		&[library_id.clone(), share_name.clone()],
+		false,
+	);
+
+	match (method.as_str(), &resource) {
+		("PROPFIND", _) => propfind(req, &base, share_name, path, &resource),
+		("GET" | "HEAD", Resource::File(entry)) => get(req, entry).await,
+		_ => Err(WebdavError::MethodNotAllowed),
+	}
+}
+
+/// The WebDAV server, for `base_path` where it's served from, which the hrefs of its answers start
+/// with
+pub fn create_webdav_endpoint(
+	node: Arc<Node>,
+	base_path: &'static str,
+) -> Endpoint<impl HttpEndpoint> {
+	GenericEndpoint::new(
+		"/*any",
+		[
+			Method::OPTIONS,
+			Method::GET,
+			Method::HEAD,
+			Method::from_bytes(b"PROPFIND").expect("PROPFIND is a valid method"),
+		],
+		move |req: Request| {
+			let node = node.clone();
+			async move {
+				handler(&node, base_path, &req)
+					.await
+					.unwrap_or_else(Into::into)
+			}
+		},
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn validates_shares() {
+		assert!(validate_share("photos_2023", "me", Some(1), None).is_ok());
+		assert!(validate_share("", "me", Some(1), None).is_err());
+		assert!(validate_share("../photos", "me", Some(1), None).is_err());
+		assert!(validate_share("photos", "me:you", None, Some(1)).is_err());
+		assert!(validate_share("photos", "me", Some(1), Some(1)).is_err());
+		assert!(validate_share("photos", "me", None, None).is_err());
+	}
+
+	#[test]
+	fn escapes_hrefs() {
+		assert_eq!(
+			href(
+				"/webdav",
+				&["lib".to_string(), "a b&c".to_string(), "d.txt".to_string()],
+				false
+			),
+			"/webdav/lib/a%20b%26c/d.txt"
+		);
+		assert_eq!(href("/webdav", &["dir".to_string()], true), "/webdav/dir/");
+	}
+
+	#[test]
+	fn numbers_taken_names() {
+		let names = HashSet::from(["beach.jpg".to_string(), "beach (2).jpg".to_string()]);
+		assert_eq!(free_name(&names, "beach.jpg"), "beach (3).jpg");
+		assert_eq!(free_name(&names, "sea.jpg"), "sea.jpg");
+	}
+}
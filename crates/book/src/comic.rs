@@ -0,0 +1,107 @@
+use std::{
+	io::{Read, Seek},
+	path::Path,
+};
+
+use unrar::Archive;
+use zip::ZipArchive;
+
+use crate::{is_page, natural_cmp, BookError, BookResult, COVER_MAXIMUM_SIZE};
+
+/// Reads the first page of a CBZ, comics have no cover metadata so it's the first image by name
+pub(crate) fn cbz_cover(reader: impl Read + Seek) -> BookResult<Vec<u8>> {
+	let mut archive = ZipArchive::new(reader)?;
+
+	let first_page = archive
+		.file_names()
+		.filter(|name| is_page(name))
+		.min_by(|a, b| natural_cmp(a, b))
+		.map(str::to_string)
+		.ok_or(BookError::NoCover)?;
+
+	read_zip_entry(&mut archive, &first_page)
+}
+
+/// Reads the first page of a CBR. RAR archives can only be read sequentially, so we look for the
+/// first page while listing the entries and then read the archive again to extract it.
+pub(crate) fn cbr_cover(path: &Path) -> BookResult<Vec<u8>> {
+	let first_page = Archive::new(path)
+		.open_for_listing()?
+		.collect::<Result<Vec<_>, _>>()?
+		.into_iter()
+		.filter(|header| header.is_file() && is_page(&header.filename.to_string_lossy()))
+		.min_by(|a, b| natural_cmp(&a.filename.to_string_lossy(), &b.filename.to_string_lossy()))
+		.ok_or(BookError::NoCover)?;
+
+	if first_page.unpacked_size > COVER_MAXIMUM_SIZE {
+		return Err(BookError::TooLarge);
+	}
+
+	let mut archive = Archive::new(path).open_for_processing()?;
+	while let Some(header) = archive.read_header()? {
+		archive = if header.entry().filename == first_page.filename {
+			return Ok(header.read()?.0);
+		} else {
+			header.skip()?
+		};
+	}
+
+	Err(BookError::NoCover)
+}
+
+/// Reads an entry of a zip archive, used by the zip based formats
+pub(crate) fn read_zip_entry(
+	archive: &mut ZipArchive<impl Read + Seek>,
+	name: &str,
+) -> BookResult<Vec<u8>> {
+	let entry = archive.by_name(name)?;
+	if entry.size() > COVER_MAXIMUM_SIZE {
+		return Err(BookError::TooLarge);
+	}
+
+	let mut content = Vec::with_capacity(entry.size() as usize);
+	entry.take(COVER_MAXIMUM_SIZE).read_to_end(&mut content)?;
+
+	Ok(content)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	use std::io::{Cursor, Write};
+
+	use zip::{write::FileOptions, ZipWriter};
+
+	pub(crate) fn zip(entries: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+		let mut writer = ZipWriter::new(Cursor::new(vec![]));
+		for (name, content) in entries {
+			writer.start_file(*name, FileOptions::default()).unwrap();
+			writer.write_all(content).unwrap();
+		}
+
+		let mut cursor = writer.finish().unwrap();
+		cursor.set_position(0);
+		cursor
+	}
+
+	#[test]
+	fn cbz_cover_is_first_page() {
+		let archive = zip(&[
+			("ComicInfo.xml", b"<ComicInfo/>"),
+			("__MACOSX/._page1.jpg", b"resource fork"),
+			("page10.jpg", b"tenth"),
+			("page2.jpg", b"second"),
+			("page1.jpg", b"first"),
+		]);
+
+		assert_eq!(cbz_cover(archive).unwrap(), b"first");
+	}
+
+	#[test]
+	fn cbz_without_pages_has_no_cover() {
+		let archive = zip(&[("ComicInfo.xml", b"<ComicInfo/>")]);
+
+		assert!(matches!(cbz_cover(archive), Err(BookError::NoCover)));
+	}
+}
@@ -0,0 +1,282 @@
+use std::{
+	collections::HashMap,
+	io::{Read, Seek},
+};
+
+use zip::ZipArchive;
+
+use crate::{comic::read_zip_entry, is_page, natural_cmp, BookError, BookResult};
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Reads the cover of an EPUB, as declared in its package document. Books that don't declare one
+/// get the first image of the archive, which is usually the cover anyway.
+pub(crate) fn cover(reader: impl Read + Seek) -> BookResult<Vec<u8>> {
+	let mut archive = ZipArchive::new(reader)?;
+
+	if let Some(cover_path) = declared_cover_path(&mut archive)? {
+		if archive.file_names().any(|name| name == cover_path) {
+			return read_zip_entry(&mut archive, &cover_path);
+		}
+	}
+
+	let first_image = archive
+		.file_names()
+		.filter(|name| is_page(name))
+		.min_by(|a, b| natural_cmp(a, b))
+		.map(str::to_string)
+		.ok_or(BookError::NoCover)?;
+
+	read_zip_entry(&mut archive, &first_image)
+}
+
+/// Finds the path of the cover image in the archive, from the package document pointed to by the
+/// container file
+fn declared_cover_path(archive: &mut ZipArchive<impl Read + Seek>) -> BookResult<Option<String>> {
+	let container = read_text(archive, CONTAINER_PATH)?;
+	let Some(package_path) = elements(&container, "rootfile")
+		.into_iter()
+		.find_map(|mut attributes| attributes.remove("full-path"))
+	else {
+		return Err(BookError::Invalid {
+			format: "epub",
+			reason: "missing package document",
+		});
+	};
+
+	let package = read_text(archive, &package_path)?;
+
+	Ok(cover_href(&package).map(|href| {
+		// Hrefs are relative to the package document
+		let base = package_path
+			.rsplit_once('/')
+			.map(|(dir, _)| dir)
+			.unwrap_or_default();
+
+		resolve_path(base, &percent_decode(&href))
+	}))
+}
+
+/// Finds the href of the cover image in the package document. EPUB 3 marks it with the
+/// `cover-image` property, while EPUB 2 points to it from a `cover` meta element. Books following
+/// neither usually still name the cover image "cover".
+fn cover_href(package: &str) -> Option<String> {
+	let items = elements(package, "item");
+
+	let is_image = |item: &&HashMap<&str, String>| {
+		item.get("media-type")
+			.map(|media_type| media_type.starts_with("image/"))
+			.unwrap_or(false)
+	};
+
+	let by_property = || {
+		items.iter().find(|item| {
+			item.get("properties")
+				.map(|properties| properties.split_whitespace().any(|p| p == "cover-image"))
+				.unwrap_or(false)
+		})
+	};
+
+	let by_meta = || {
+		let id = elements(package, "meta")
+			.into_iter()
+			.find(|meta| meta.get("name").map(String::as_str) == Some("cover"))
+			.and_then(|mut meta| meta.remove("content"))?;
+
+		items
+			.iter()
+			.find(|item| item.get("id") == Some(&id))
+			.filter(is_image)
+	};
+
+	let by_name = || {
+		items.iter().filter(is_image).find(|item| {
+			["id", "href"].iter().any(|key| {
+				item.get(key)
+					.map(|value| value.to_ascii_lowercase().contains("cover"))
+					.unwrap_or(false)
+			})
+		})
+	};
+
+	by_property()
+		.or_else(by_meta)
+		.or_else(by_name)
+		.and_then(|item| item.get("href").cloned())
+}
+
+fn read_text(archive: &mut ZipArchive<impl Read + Seek>, name: &str) -> BookResult<String> {
+	let content = read_zip_entry(archive, name)?;
+
+	Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Returns the attributes of every `name` element in the document, ignoring namespace prefixes.
+/// It's only meant for the small package files of EPUBs, not for arbitrary XML.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<HashMap<&'a str, String>> {
+	let mut elements = vec![];
+
+	let mut rest = xml;
+	while let Some(start) = rest.find('<') {
+		rest = &rest[start + 1..];
+		let Some(end) = rest.find('>') else {
+			break;
+		};
+
+		let tag = rest[..end].trim_end_matches('/');
+		rest = &rest[end + 1..];
+
+		let (tag_name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+
+		if local_name(tag_name) == name {
+			elements.push(parse_attributes(attributes));
+		}
+	}
+
+	elements
+}
+
+fn parse_attributes(mut attributes: &str) -> HashMap<&str, String> {
+	let mut parsed = HashMap::new();
+
+	while let Some((key, rest)) = attributes.split_once('=') {
+		let rest = rest.trim_start();
+		let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+			break;
+		};
+		let Some((value, rest)) = rest[1..].split_once(quote) else {
+			break;
+		};
+
+		parsed.insert(local_name(key.trim()), unescape(value));
+		attributes = rest;
+	}
+
+	parsed
+}
+
+fn local_name(name: &str) -> &str {
+	name.rsplit_once(':').map(|(_, name)| name).unwrap_or(name)
+}
+
+fn unescape(value: &str) -> String {
+	value
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+}
+
+fn percent_decode(value: &str) -> String {
+	let bytes = value.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = bytes
+			.get(i + 1..i + 3)
+			.and_then(|hex| std::str::from_utf8(hex).ok())
+			.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+		match (bytes[i], hex) {
+			(b'%', Some(byte)) => {
+				decoded.push(byte);
+				i += 3;
+			}
+			(byte, _) => {
+				decoded.push(byte);
+				i += 1;
+			}
+		}
+	}
+
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Joins a relative path to its base directory inside the archive, resolving `.` and `..`
+fn resolve_path(base: &str, relative: &str) -> String {
+	let mut parts = base
+		.split('/')
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<_>>();
+
+	for part in relative.split('/') {
+		match part {
+			"" | "." => {}
+			".." => {
+				parts.pop();
+			}
+			part => parts.push(part),
+		}
+	}
+
+	parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::comic::tests::zip;
+
+	const CONTAINER: &[u8] = br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+	<rootfiles>
+		<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+	</rootfiles>
+</container>"#;
+
+	#[test]
+	fn epub3_cover_property() {
+		let package = r#"<package><manifest>
+			<item id="c1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+			<item id="img" href="images/front%20page.jpg" media-type="image/jpeg" properties="cover-image"/>
+		</manifest></package>"#;
+
+		assert_eq!(
+			cover_href(package).as_deref(),
+			Some("images/front%20page.jpg")
+		);
+	}
+
+	#[test]
+	fn epub2_cover_meta() {
+		let package = r#"<opf:package><opf:metadata>
+			<opf:meta name="cover" content="cover-img" />
+		</opf:metadata><opf:manifest>
+			<opf:item id="title" href="title.jpg" media-type="image/jpeg"/>
+			<opf:item id="cover-img" href="front.png" media-type="image/png"/>
+		</opf:manifest></opf:package>"#;
+
+		assert_eq!(cover_href(package).as_deref(), Some("front.png"));
+	}
+
+	#[test]
+	fn cover_is_read_relative_to_package() {
+		let package = br#"<package><manifest>
+			<item id="cover" href="../Images/Cover%201.png" media-type="image/png"/>
+		</manifest></package>"#;
+
+		let archive = zip(&[
+			("META-INF/container.xml", CONTAINER),
+			("OEBPS/content.opf", package),
+			("Images/Cover 1.png", b"cover"),
+			("Images/another.png", b"another"),
+		]);
+
+		assert_eq!(cover(archive).unwrap(), b"cover");
+	}
+
+	#[test]
+	fn first_image_without_declared_cover() {
+		let archive = zip(&[
+			("META-INF/container.xml", CONTAINER),
+			("OEBPS/content.opf", b"<package/>"),
+			("OEBPS/img/b.jpg", b"b"),
+			("OEBPS/img/a.jpg", b"a"),
+		]);
+
+		assert_eq!(cover(archive).unwrap(), b"a");
+	}
+}
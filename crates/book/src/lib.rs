@@ -0,0 +1,149 @@
+use std::{cmp::Ordering, fs::File, path::Path};
+
+use image::DynamicImage;
+use thiserror::Error;
+
+mod comic;
+mod epub;
+mod mobi;
+
+type BookResult<T> = Result<T, BookError>;
+
+/// The maximum size that a cover image can be in order to be decoded, so a broken archive can't
+/// make us read a huge entry into memory.
+///
+/// This value is in MiB.
+const COVER_MAXIMUM_SIZE: u64 = 1048576 * 50;
+
+/// Extensions of the ebook and comic formats that covers can be extracted from.
+pub const BOOK_EXTENSIONS: [&str; 6] = ["epub", "mobi", "azw", "azw3", "cbz", "cbr"];
+
+/// Extensions of the images used as covers and pages, the ones `image` can decode.
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+#[derive(Error, Debug)]
+pub enum BookError {
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("error with zip archive: {0}")]
+	Zip(#[from] zip::result::ZipError),
+	#[error("error with rar archive: {0}")]
+	Rar(#[from] unrar::error::UnrarError),
+	#[error("error decoding the cover: {0}")]
+	Image(#[from] image::ImageError),
+	#[error("invalid {format} file: {reason}")]
+	Invalid {
+		format: &'static str,
+		reason: &'static str,
+	},
+	#[error("the book has no cover")]
+	NoCover,
+	#[error("the cover is too large (over 50MiB)")]
+	TooLarge,
+	#[error("unsupported book format: {0}")]
+	UnsupportedFormat(String),
+}
+
+/// Checks if the provided extension is an ebook or comic format that covers can be extracted from.
+pub fn is_book_extension(extension: &str) -> bool {
+	BOOK_EXTENSIONS
+		.iter()
+		.any(|ext| extension.eq_ignore_ascii_case(ext))
+}
+
+/// Extracts the cover of an ebook, or the first page of a comic archive.
+pub fn book_to_dynamic_image(path: &Path) -> BookResult<DynamicImage> {
+	let extension = path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.unwrap_or_default()
+		.to_ascii_lowercase();
+
+	let cover = match extension.as_str() {
+		"epub" => epub::cover(File::open(path)?)?,
+		"mobi" | "azw" | "azw3" => mobi::cover(File::open(path)?)?,
+		"cbz" => comic::cbz_cover(File::open(path)?)?,
+		"cbr" => comic::cbr_cover(path)?,
+		_ => return Err(BookError::UnsupportedFormat(extension)),
+	};
+
+	Ok(image::load_from_memory(&cover)?)
+}
+
+/// If the archive entry is an image that can be used as a page, skipping the hidden files and
+/// metadata folders that some archivers add.
+fn is_page(name: &str) -> bool {
+	let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+
+	!name.starts_with("__MACOSX")
+		&& !file_name.starts_with('.')
+		&& file_name
+			.rsplit_once('.')
+			.map(|(_, ext)| IMAGE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+			.unwrap_or(false)
+}
+
+/// Orders names the way pages are numbered, so `page2` comes before `page10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+	let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+
+	loop {
+		match (a.peek().copied(), b.peek().copied()) {
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+				let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+					let mut digits = String::new();
+					while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+						digits.push(c);
+						chars.next();
+					}
+					digits
+				};
+
+				let (x, y) = (take_number(&mut a), take_number(&mut b));
+				let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+
+				match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+					Ordering::Equal => {}
+					ordering => return ordering,
+				}
+			}
+			(Some(x), Some(y)) => {
+				match x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase()) {
+					Ordering::Equal => {}
+					ordering => return ordering,
+				}
+				a.next();
+				b.next();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pages_are_images() {
+		assert!(is_page("Comic/001.JPG"));
+		assert!(is_page("cover.webp"));
+		assert!(!is_page("ComicInfo.xml"));
+		assert!(!is_page("__MACOSX/Comic/._001.jpg"));
+		assert!(!is_page("Comic/.thumbnail.png"));
+		assert!(!is_page("Comic/jpg"));
+	}
+
+	#[test]
+	fn pages_are_sorted_naturally() {
+		let mut pages = vec!["page10.jpg", "Page2.jpg", "page1.jpg", "page01b.jpg"];
+		pages.sort_by(|a, b| natural_cmp(a, b));
+
+		assert_eq!(
+			pages,
+			["page1.jpg", "page01b.jpg", "Page2.jpg", "page10.jpg"]
+		);
+	}
+}
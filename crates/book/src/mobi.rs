@@ -0,0 +1,206 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{BookError, BookResult, COVER_MAXIMUM_SIZE};
+
+/// Offset of the amount of records in the Palm database header, followed by the record list
+const RECORD_COUNT_OFFSET: u64 = 76;
+/// Every record in the list has its offset followed by 4 bytes of attributes and id
+const RECORD_INFO_SIZE: usize = 8;
+
+/// The MOBI header starts after the 16 bytes of the PalmDOC header, in the first record
+const MOBI_HEADER_OFFSET: usize = 16;
+const MOBI_HEADER_LENGTH_OFFSET: usize = MOBI_HEADER_OFFSET + 4;
+const FIRST_IMAGE_INDEX_OFFSET: usize = MOBI_HEADER_OFFSET + 0x5C;
+const EXTH_FLAGS_OFFSET: usize = MOBI_HEADER_OFFSET + 0x70;
+const EXTH_PRESENT_FLAG: u32 = 0x40;
+
+/// EXTH record with the index of the cover, relative to the first image record
+const EXTH_COVER_OFFSET: u32 = 201;
+
+/// Reads the cover of a MOBI book, including the AZW formats Kindle uses. Images are stored in
+/// their own records, the cover being pointed to by the EXTH metadata. Books without it get the
+/// first image, which is usually the cover anyway.
+pub(crate) fn cover<R: Read + Seek>(mut reader: R) -> BookResult<Vec<u8>> {
+	let records = record_offsets(&mut reader)?;
+
+	let header = read_record(&mut reader, &records, 0)?;
+	if header.get(MOBI_HEADER_OFFSET..MOBI_HEADER_OFFSET + 4) != Some(&b"MOBI"[..]) {
+		return Err(invalid("missing MOBI header"));
+	}
+
+	let first_image = read_u32(&header, FIRST_IMAGE_INDEX_OFFSET)
+		.ok_or(invalid("truncated MOBI header"))? as usize;
+
+	// 0xFFFFFFFF means the book has no images
+	if first_image == u32::MAX as usize {
+		return Err(BookError::NoCover);
+	}
+
+	let cover_offset = read_u32(&header, EXTH_FLAGS_OFFSET)
+		.filter(|flags| flags & EXTH_PRESENT_FLAG != 0)
+		.and_then(|_| read_u32(&header, MOBI_HEADER_LENGTH_OFFSET))
+		.and_then(|length| exth_cover_offset(&header, MOBI_HEADER_OFFSET + length as usize))
+		.unwrap_or(0) as usize;
+
+	read_record(&mut reader, &records, first_image + cover_offset)
+		.or_else(|_| read_record(&mut reader, &records, first_image))
+}
+
+/// Reads the start of every record from the Palm database header
+fn record_offsets(reader: &mut (impl Read + Seek)) -> BookResult<Vec<u64>> {
+	reader.seek(SeekFrom::Start(RECORD_COUNT_OFFSET))?;
+
+	let mut count = [0; 2];
+	reader.read_exact(&mut count)?;
+
+	let mut list = vec![0; u16::from_be_bytes(count) as usize * RECORD_INFO_SIZE];
+	reader.read_exact(&mut list)?;
+
+	Ok(list
+		.chunks_exact(RECORD_INFO_SIZE)
+		.map(|info| u32::from_be_bytes([info[0], info[1], info[2], info[3]]) as u64)
+		.collect())
+}
+
+/// Reads a record, which spans until the start of the next one or the end of the file
+fn read_record(
+	reader: &mut (impl Read + Seek),
+	records: &[u64],
+	index: usize,
+) -> BookResult<Vec<u8>> {
+	let start = *records.get(index).ok_or(invalid("missing record"))?;
+	let end = match records.get(index + 1) {
+		Some(end) => *end,
+		None => reader.seek(SeekFrom::End(0))?,
+	};
+
+	let length = end
+		.checked_sub(start)
+		.ok_or(invalid("records out of order"))?;
+	if length > COVER_MAXIMUM_SIZE {
+		return Err(BookError::TooLarge);
+	}
+
+	reader.seek(SeekFrom::Start(start))?;
+
+	let mut record = vec![0; length as usize];
+	reader.read_exact(&mut record)?;
+
+	Ok(record)
+}
+
+/// Finds the cover offset in the EXTH header, a list of typed metadata records
+fn exth_cover_offset(header: &[u8], exth_start: usize) -> Option<u32> {
+	if header.get(exth_start..exth_start + 4)? != b"EXTH" {
+		return None;
+	}
+
+	let count = read_u32(header, exth_start + 8)?;
+	let mut offset = exth_start + 12;
+
+	for _ in 0..count {
+		let kind = read_u32(header, offset)?;
+		let length = read_u32(header, offset + 4)? as usize;
+
+		if kind == EXTH_COVER_OFFSET {
+			return read_u32(header, offset + 8);
+		}
+
+		// The length includes the type and length fields, so it's never under 8
+		offset += length.max(8);
+	}
+
+	None
+}
+
+fn invalid(reason: &'static str) -> BookError {
+	BookError::Invalid {
+		format: "mobi",
+		reason,
+	}
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+	data.get(offset..offset + 4)
+		.map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::Cursor;
+
+	/// Builds a book with a MOBI header record followed by the given image records
+	fn book(cover_offset: Option<u32>, images: &[&[u8]]) -> Cursor<Vec<u8>> {
+		let mut header = vec![0; MOBI_HEADER_OFFSET];
+		header.extend_from_slice(b"MOBI");
+
+		let mobi_header_length = 0xE8u32;
+		header.extend_from_slice(&mobi_header_length.to_be_bytes());
+		header.resize(MOBI_HEADER_OFFSET + mobi_header_length as usize, 0);
+
+		// The image records come right after the header one
+		header[FIRST_IMAGE_INDEX_OFFSET..FIRST_IMAGE_INDEX_OFFSET + 4]
+			.copy_from_slice(&1u32.to_be_bytes());
+
+		if let Some(cover_offset) = cover_offset {
+			header[EXTH_FLAGS_OFFSET..EXTH_FLAGS_OFFSET + 4]
+				.copy_from_slice(&EXTH_PRESENT_FLAG.to_be_bytes());
+
+			header.extend_from_slice(b"EXTH");
+			header.extend_from_slice(&36u32.to_be_bytes());
+			header.extend_from_slice(&2u32.to_be_bytes());
+			// An unrelated record first, the author
+			header.extend_from_slice(&100u32.to_be_bytes());
+			header.extend_from_slice(&12u32.to_be_bytes());
+			header.extend_from_slice(b"Anon");
+			header.extend_from_slice(&EXTH_COVER_OFFSET.to_be_bytes());
+			header.extend_from_slice(&12u32.to_be_bytes());
+			header.extend_from_slice(&cover_offset.to_be_bytes());
+		}
+
+		let records = [&header[..]]
+			.into_iter()
+			.chain(images.iter().copied())
+			.collect::<Vec<_>>();
+
+		let mut file = vec![0; RECORD_COUNT_OFFSET as usize];
+		file[60..68].copy_from_slice(b"BOOKMOBI");
+		file.extend_from_slice(&(records.len() as u16).to_be_bytes());
+
+		let mut offset = file.len() + records.len() * RECORD_INFO_SIZE;
+		for record in &records {
+			file.extend_from_slice(&(offset as u32).to_be_bytes());
+			file.extend_from_slice(&[0; 4]);
+			offset += record.len();
+		}
+
+		for record in records {
+			file.extend_from_slice(record);
+		}
+
+		Cursor::new(file)
+	}
+
+	#[test]
+	fn cover_from_exth() {
+		let book = book(Some(1), &[b"first", b"cover", b"last"]);
+
+		assert_eq!(cover(book).unwrap(), b"cover");
+	}
+
+	#[test]
+	fn first_image_without_exth() {
+		let book = book(None, &[b"first", b"last"]);
+
+		assert_eq!(cover(book).unwrap(), b"first");
+	}
+
+	#[test]
+	fn not_a_mobi() {
+		let file = Cursor::new(vec![0; 200]);
+
+		assert!(cover(file).is_err());
+	}
+}
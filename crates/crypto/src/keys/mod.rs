@@ -7,3 +7,6 @@ pub mod keymanager;
 
 #[cfg(feature = "os-keyrings")]
 pub mod keyring;
+
+#[cfg(feature = "os-keyrings")]
+pub mod secrets;
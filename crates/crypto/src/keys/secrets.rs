@@ -0,0 +1,89 @@
+//! The secrets of Spacedrive kept in the OS keyring, each stored under what it's used for and the
+//! library or node it belongs to. Secrets are hex encoded, as the keyrings store strings.
+
+use super::keyring::{Identifier, KeyringInterface};
+use crate::{types::SecretKeyString, Error, Protected, Result};
+
+use uuid::Uuid;
+
+const APPLICATION: &str = "Spacedrive";
+
+/// What a secret is used for, secrets of different kinds of the same owner don't overwrite each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecretKind {
+	/// The access key id of an S3 backup target
+	BackupTargetAccessKeyId,
+	/// The password of a WebDAV backup target
+	BackupTargetPassword,
+	/// The secret access key of an S3 backup target
+	BackupTargetSecretAccessKey,
+	/// The key the backup snapshots of a library are encrypted with
+	LibraryBackupKey,
+	/// The key an encrypted library database is encrypted with
+	LibraryDatabaseKey,
+	/// The private key a library identifies itself with to other nodes
+	LibraryIdentity,
+	/// The key the sync operations of a library are encrypted with
+	LibrarySyncKey,
+}
+
+impl SecretKind {
+	const fn usage(self) -> &'static str {
+		match self {
+			Self::BackupTargetAccessKeyId => "Backup target access key id",
+			Self::BackupTargetPassword => "Backup target password",
+			Self::BackupTargetSecretAccessKey => "Backup target secret access key",
+			Self::LibraryBackupKey => "Library backup key",
+			Self::LibraryDatabaseKey => "Library database key",
+			Self::LibraryIdentity => "Library P2P identity",
+			Self::LibrarySyncKey => "Library sync key",
+		}
+	}
+}
+
+/// This should be used to store secrets in the OS keyring, instead of [`KeyringInterface`].
+pub struct Secrets {
+	keyring: KeyringInterface,
+}
+
+impl Secrets {
+	/// Fails with [`Error::KeyringNotSupported`] on platforms without a supported keyring
+	pub fn new() -> Result<Self> {
+		Ok(Self {
+			keyring: KeyringInterface::new()?,
+		})
+	}
+
+	pub fn insert(&self, owner: Uuid, kind: SecretKind, secret: &Protected<Vec<u8>>) -> Result<()> {
+		let owner = owner.to_string();
+
+		self.keyring.insert(
+			identifier(&owner, kind),
+			SecretKeyString(Protected::new(hex::encode(secret.expose()))),
+		)
+	}
+
+	pub fn retrieve(&self, owner: Uuid, kind: SecretKind) -> Result<Protected<Vec<u8>>> {
+		let owner = owner.to_string();
+
+		let encoded = self.keyring.retrieve(identifier(&owner, kind))?;
+
+		hex::decode(encoded.expose())
+			.map(Protected::new)
+			.map_err(|_| Error::Serialization)
+	}
+
+	pub fn delete(&self, owner: Uuid, kind: SecretKind) -> Result<()> {
+		let owner = owner.to_string();
+
+		self.keyring.delete(identifier(&owner, kind))
+	}
+}
+
+fn identifier(owner: &str, kind: SecretKind) -> Identifier<'_> {
+	Identifier {
+		application: APPLICATION,
+		library_uuid: owner,
+		usage: kind.usage(),
+	}
+}
@@ -103,6 +103,8 @@ pub enum FfmpegError {
 	FrameAllocation,
 	#[error("Video Codec allocation error")]
 	VideoCodecAllocation,
+	#[error("Audio Codec allocation error")]
+	AudioCodecAllocation,
 	#[error("Filter Graph allocation error")]
 	FilterGraphAllocation,
 	#[error("Codec Open Error")]
@@ -4,17 +4,25 @@ use crate::{
 	video_frame::VideoFrame,
 };
 
-use std::path::Path;
+use std::{ops::Deref, path::Path};
+
+use tokio::{fs, task::spawn_blocking};
+use webp::Encoder;
 
 mod error;
 mod film_strip;
 mod movie_decoder;
+mod sprite;
 mod thumbnailer;
+mod transcode;
 mod utils;
 mod video_frame;
+mod waveform;
 
 pub use error::ThumbnailerError;
+pub use sprite::{SPRITE_COLUMNS, SPRITE_FRAME_COUNT, SPRITE_ROWS};
 pub use thumbnailer::{Thumbnailer, ThumbnailerBuilder};
+pub use transcode::{TranscodeOptions, VideoCodec};
 
 /// Helper function to generate a thumbnail file from a video file with reasonable defaults
 pub async fn to_thumbnail(
@@ -46,6 +54,68 @@ pub async fn to_webp_bytes(
 		.await
 }
 
+/// Helper function to generate a webp sprite sheet with frames across the whole video, used to
+/// preview the video when scrubbing over its thumbnail. The frames are laid out in a grid of
+/// `SPRITE_COLUMNS` by `SPRITE_ROWS`, each fitting in `frame_size` pixels.
+pub async fn to_sprite_sheet(
+	video_file_path: impl AsRef<Path>,
+	output_sprite_path: impl AsRef<Path>,
+	frame_size: u32,
+	quality: f32,
+) -> Result<(), ThumbnailerError> {
+	if !(0.0..=100.0).contains(&quality) {
+		return Err(ThumbnailerError::InvalidQuality(quality));
+	}
+
+	let video_file_path = video_file_path.as_ref().to_path_buf();
+
+	let webp = spawn_blocking(move || -> Result<Vec<u8>, ThumbnailerError> {
+		let sheet = sprite::extract_sprite_sheet(video_file_path, frame_size)?;
+
+		// Type WebPMemory is !Send, so we copy the encoded bytes out of it
+		Ok(
+			Encoder::from_rgb(&sheet.data, sheet.width(), sheet.height())
+				.encode(quality)
+				.deref()
+				.to_vec(),
+		)
+	})
+	.await??;
+
+	fs::write(output_sprite_path, webp)
+		.await
+		.map_err(Into::into)
+}
+
+/// Helper function to extract the waveform peaks of an audio file, each peak is the loudest
+/// amplitude of its slice of the audio in the range `0..=255`
+pub async fn to_waveform_peaks(
+	audio_file_path: impl AsRef<Path>,
+	num_peaks: usize,
+) -> Result<Vec<u8>, ThumbnailerError> {
+	let audio_file_path = audio_file_path.as_ref().to_path_buf();
+
+	spawn_blocking(move || waveform::extract_peaks(audio_file_path, num_peaks)).await?
+}
+
+/// Helper function to transcode a video file to an MP4 file, copying its audio. `progress` is
+/// called with the percentage done as it grows. Returns the name of the encoder that was used,
+/// a hardware one if asked for and available.
+pub async fn to_transcoded_video(
+	video_file_path: impl AsRef<Path>,
+	output_video_path: impl AsRef<Path>,
+	options: TranscodeOptions,
+	progress: impl FnMut(u8) + Send + 'static,
+) -> Result<&'static str, ThumbnailerError> {
+	let video_file_path = video_file_path.as_ref().to_path_buf();
+	let output_video_path = output_video_path.as_ref().to_path_buf();
+
+	spawn_blocking(move || {
+		transcode::transcode(video_file_path, output_video_path, options, progress)
+	})
+	.await?
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
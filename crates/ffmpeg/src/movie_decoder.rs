@@ -334,11 +334,11 @@ impl MovieDecoder {
 						if unsafe {
 							CString::from_raw((*tag).key)
 								.to_str()
-								.expect("Found non-UTF-8 path") == "filename"
-								&& CString::from_raw((*tag).value)
-									.to_str()
-									.expect("Found non-UTF-8 path")
-									.starts_with("cover.")
+								.expect("Found non-UTF-8 path")
+								== "filename" && CString::from_raw((*tag).value)
+								.to_str()
+								.expect("Found non-UTF-8 path")
+								.starts_with("cover.")
 						} {
 							if embedded_data_streams.is_empty() {
 								embedded_data_streams.push(stream_idx);
@@ -672,27 +672,33 @@ impl MovieDecoder {
 	}
 
 	fn get_stream_rotation(&self) -> i32 {
-		let matrix = unsafe {
-			av_stream_get_side_data(
-				self.video_stream,
-				AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
-				std::ptr::null_mut(),
-			)
-		} as *const i32;
-
-		if !matrix.is_null() {
-			let angle = (unsafe { av_display_rotation_get(matrix) }).round();
-			if angle < -135.0 {
-				return 3;
-			} else if angle > 45.0 && angle < 135.0 {
-				return 2;
-			} else if angle < -45.0 && angle > -135.0 {
-				return 1;
-			}
-		}
+		stream_rotation(self.video_stream)
+	}
+}
+
+/// The rotation of a video stream from its display matrix, as the argument of the `transpose`
+/// filter: 1 and 2 for a quarter turn either way, 3 for a half turn, -1 when it isn't rotated
+pub(crate) fn stream_rotation(stream: *mut AVStream) -> i32 {
+	let matrix = unsafe {
+		av_stream_get_side_data(
+			stream,
+			AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
+			std::ptr::null_mut(),
+		)
+	} as *const i32;
 
-		-1
+	if !matrix.is_null() {
+		let angle = (unsafe { av_display_rotation_get(matrix) }).round();
+		if angle < -135.0 {
+			return 3;
+		} else if angle > 45.0 && angle < 135.0 {
+			return 2;
+		} else if angle < -45.0 && angle > -135.0 {
+			return 1;
+		}
 	}
+
+	-1
 }
 
 impl Drop for MovieDecoder {
@@ -730,7 +736,7 @@ impl Drop for MovieDecoder {
 	}
 }
 
-fn check_error(return_code: i32, error_message: &str) -> Result<(), ThumbnailerError> {
+pub(crate) fn check_error(return_code: i32, error_message: &str) -> Result<(), ThumbnailerError> {
 	if return_code < 0 {
 		Err(ThumbnailerError::FfmpegWithReason(
 			FfmpegError::from(return_code),
@@ -741,7 +747,7 @@ fn check_error(return_code: i32, error_message: &str) -> Result<(), ThumbnailerE
 	}
 }
 
-fn setup_filter(
+pub(crate) fn setup_filter(
 	filter_ctx: *mut *mut AVFilterContext,
 	filter_name: &str,
 	filter_setup_name: &str,
@@ -768,7 +774,7 @@ fn setup_filter(
 	)
 }
 
-fn setup_filter_without_args(
+pub(crate) fn setup_filter_without_args(
 	filter_ctx: *mut *mut AVFilterContext,
 	filter_name: &str,
 	filter_setup_name: &str,
@@ -0,0 +1,150 @@
+use crate::{
+	movie_decoder::{MovieDecoder, ThumbnailSize},
+	video_frame::VideoFrame,
+	ThumbnailerError,
+};
+
+use std::path::Path;
+
+/// Amount of frames in each row of the sprite sheet
+pub const SPRITE_COLUMNS: u32 = 5;
+/// Amount of rows in the sprite sheet
+pub const SPRITE_ROWS: u32 = 4;
+/// Total amount of frames in the sprite sheet, evenly spread across the video's timeline
+pub const SPRITE_FRAME_COUNT: u32 = SPRITE_COLUMNS * SPRITE_ROWS;
+
+/// A grid of RGB24 frames, filled left to right and top to bottom in timeline order
+#[derive(Debug)]
+pub(crate) struct SpriteSheet {
+	pub frame_width: u32,
+	pub frame_height: u32,
+	pub data: Vec<u8>,
+}
+
+impl SpriteSheet {
+	fn new(frame_width: u32, frame_height: u32) -> Self {
+		Self {
+			frame_width,
+			frame_height,
+			data: vec![0; (frame_width * SPRITE_COLUMNS * frame_height * SPRITE_ROWS * 3) as usize],
+		}
+	}
+
+	pub fn width(&self) -> u32 {
+		self.frame_width * SPRITE_COLUMNS
+	}
+
+	pub fn height(&self) -> u32 {
+		self.frame_height * SPRITE_ROWS
+	}
+
+	/// Copies the frame into its cell of the grid. Frames with a different size than the first one
+	/// (streams can change resolution midway) are cropped to fit the cell.
+	fn draw(&mut self, index: u32, frame: &VideoFrame) {
+		let sheet_row_len = self.width() as usize * 3;
+		let cell_row_len = (self.frame_width.min(frame.width) * 3) as usize;
+		let cell_x = (index % SPRITE_COLUMNS * self.frame_width * 3) as usize;
+		let cell_y = (index / SPRITE_COLUMNS * self.frame_height) as usize;
+
+		for (y, row) in frame
+			.data
+			.chunks(frame.line_size as usize)
+			.take(self.frame_height.min(frame.height) as usize)
+			.enumerate()
+		{
+			let start = (cell_y + y) * sheet_row_len + cell_x;
+			let len = cell_row_len.min(row.len());
+			self.data[start..start + len].copy_from_slice(&row[..len]);
+		}
+	}
+}
+
+/// Decodes `SPRITE_FRAME_COUNT` frames from the middle of evenly sized slices of the video, each
+/// scaled to fit in `frame_size` pixels, and lays them out in a grid
+pub(crate) fn extract_sprite_sheet(
+	filename: impl AsRef<Path>,
+	frame_size: u32,
+) -> Result<SpriteSheet, ThumbnailerError> {
+	// Embedded cover art is a single picture, so it's useless for scrubbing
+	let mut decoder = MovieDecoder::new(filename.as_ref(), false)?;
+	// We actually have to decode a frame to get some metadata before we can start decoding for real
+	decoder.decode_video_frame()?;
+
+	let duration_secs = decoder.get_video_duration().as_secs() as f32;
+
+	let mut sheet = None;
+	let mut video_frame = VideoFrame::default();
+
+	for index in 0..SPRITE_FRAME_COUNT {
+		let position = duration_secs * (index as f32 + 0.5) / SPRITE_FRAME_COUNT as f32;
+
+		// If we can't seek any further, the last decoded frame is repeated
+		if let Err(e) = decoder.seek(position as i64) {
+			if index == 0 {
+				return Err(e);
+			}
+		}
+
+		decoder.get_scaled_video_frame(
+			Some(ThumbnailSize::Size(frame_size)),
+			true,
+			&mut video_frame,
+		)?;
+
+		sheet
+			.get_or_insert_with(|| SpriteSheet::new(video_frame.width, video_frame.height))
+			.draw(index, &video_frame);
+	}
+
+	sheet.ok_or(ThumbnailerError::SeekError)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn solid_frame(width: u32, height: u32, padding: u32, color: u8) -> VideoFrame {
+		let line_size = width * 3 + padding;
+		VideoFrame {
+			width,
+			height,
+			line_size,
+			data: vec![color; (line_size * height) as usize],
+			source: None,
+		}
+	}
+
+	fn pixel(sheet: &SpriteSheet, x: u32, y: u32) -> u8 {
+		sheet.data[((y * sheet.width() + x) * 3) as usize]
+	}
+
+	#[test]
+	fn frames_fill_their_cells() {
+		let mut sheet = SpriteSheet::new(4, 2);
+		assert_eq!((sheet.width(), sheet.height()), (20, 8));
+
+		sheet.draw(0, &solid_frame(4, 2, 2, 10));
+		// Last frame of the first row and first frame of the second one
+		sheet.draw(SPRITE_COLUMNS - 1, &solid_frame(4, 2, 0, 20));
+		sheet.draw(SPRITE_COLUMNS, &solid_frame(4, 2, 0, 30));
+
+		assert_eq!(pixel(&sheet, 0, 0), 10);
+		assert_eq!(pixel(&sheet, 3, 1), 10);
+		assert_eq!(pixel(&sheet, 4, 0), 0);
+		assert_eq!(pixel(&sheet, 16, 1), 20);
+		assert_eq!(pixel(&sheet, 19, 0), 20);
+		assert_eq!(pixel(&sheet, 0, 2), 30);
+		assert_eq!(pixel(&sheet, 3, 3), 30);
+		assert_eq!(pixel(&sheet, 0, 4), 0);
+	}
+
+	#[test]
+	fn bigger_frames_are_cropped() {
+		let mut sheet = SpriteSheet::new(4, 2);
+		sheet.draw(0, &solid_frame(6, 3, 0, 10));
+
+		assert_eq!(pixel(&sheet, 3, 1), 10);
+		assert_eq!(pixel(&sheet, 4, 0), 0);
+		assert_eq!(pixel(&sheet, 0, 2), 0);
+	}
+}
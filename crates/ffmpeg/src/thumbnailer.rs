@@ -4,6 +4,15 @@ use std::{ops::Deref, path::Path};
 use tokio::{fs, task::spawn_blocking};
 use webp::Encoder;
 
+/// Max luma value for a pixel to be considered black
+const BLACK_FRAME_LUMA_THRESHOLD: u8 = 24;
+/// Ratio of black pixels needed for a frame to be considered a black frame
+const BLACK_FRAME_RATIO: f32 = 0.98;
+/// How many frames we try before settling with a black frame
+const BLACK_FRAME_MAX_ATTEMPTS: usize = 5;
+/// How much of the video we skip ahead after finding a black frame
+const BLACK_FRAME_SEEK_STEP: f32 = 0.1;
+
 /// `Thumbnailer` struct holds data from a `ThumbnailerBuilder`, exposing methods
 /// to generate thumbnails from video files.
 #[derive(Debug, Clone)]
@@ -37,6 +46,7 @@ impl Thumbnailer {
 		let size = self.builder.size;
 		let maintain_aspect_ratio = self.builder.maintain_aspect_ratio;
 		let with_film_strip = self.builder.with_film_strip;
+		let skip_black_frames = self.builder.skip_black_frames;
 		let quality = self.builder.quality;
 
 		spawn_blocking(move || -> Result<Vec<u8>, ThumbnailerError> {
@@ -44,16 +54,47 @@ impl Thumbnailer {
 			// We actually have to decode a frame to get some metadata before we can start decoding for real
 			decoder.decode_video_frame()?;
 
-			if !decoder.embedded_metadata_is_available() {
-				decoder.seek(
-					(decoder.get_video_duration().as_secs() as f32 * seek_percentage).round()
-						as i64,
-				)?;
-			}
-
 			let mut video_frame = VideoFrame::default();
 
-			decoder.get_scaled_video_frame(Some(size), maintain_aspect_ratio, &mut video_frame)?;
+			if decoder.embedded_metadata_is_available() {
+				decoder.get_scaled_video_frame(
+					Some(size),
+					maintain_aspect_ratio,
+					&mut video_frame,
+				)?;
+			} else {
+				let duration_secs = decoder.get_video_duration().as_secs() as f32;
+				let attempts = if skip_black_frames {
+					BLACK_FRAME_MAX_ATTEMPTS
+				} else {
+					1
+				};
+
+				for attempt in 0..attempts {
+					let percentage =
+						(seek_percentage + attempt as f32 * BLACK_FRAME_SEEK_STEP).min(1.0);
+
+					if let Err(e) = decoder.seek((duration_secs * percentage).round() as i64) {
+						// A frame was already decoded on a previous attempt, so we keep it
+						if attempt > 0 {
+							break;
+						}
+						return Err(e);
+					}
+
+					decoder.get_scaled_video_frame(
+						Some(size),
+						maintain_aspect_ratio,
+						&mut video_frame,
+					)?;
+
+					if !video_frame.is_mostly_black(BLACK_FRAME_LUMA_THRESHOLD, BLACK_FRAME_RATIO)
+						|| percentage >= 1.0
+					{
+						break;
+					}
+				}
+			}
 
 			if with_film_strip {
 				film_strip_filter(&mut video_frame);
@@ -83,6 +124,7 @@ pub struct ThumbnailerBuilder {
 	quality: f32,
 	prefer_embedded_metadata: bool,
 	with_film_strip: bool,
+	skip_black_frames: bool,
 }
 
 impl Default for ThumbnailerBuilder {
@@ -94,6 +136,7 @@ impl Default for ThumbnailerBuilder {
 			quality: 80.0,
 			prefer_embedded_metadata: true,
 			with_film_strip: true,
+			skip_black_frames: true,
 		}
 	}
 }
@@ -106,6 +149,7 @@ impl ThumbnailerBuilder {
 	/// - `quality`: 80
 	/// - `prefer_embedded_metadata`: true
 	/// - `with_film_strip`: true
+	/// - `skip_black_frames`: true
 	pub fn new() -> Self {
 		Default::default()
 	}
@@ -159,6 +203,13 @@ impl ThumbnailerBuilder {
 		self
 	}
 
+	/// If `skip_black_frames` is true, mostly black frames (fades, title cards, etc) will be skipped
+	/// by seeking further into the video, up to a few attempts
+	pub fn skip_black_frames(mut self, skip_black_frames: bool) -> Self {
+		self.skip_black_frames = skip_black_frames;
+		self
+	}
+
 	/// Builds a `Thumbnailer` struct
 	pub fn build(self) -> Thumbnailer {
 		Thumbnailer { builder: self }
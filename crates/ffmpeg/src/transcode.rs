@@ -0,0 +1,827 @@
+use crate::{
+	error::{FfmpegError, ThumbnailerError},
+	movie_decoder::{check_error, setup_filter, setup_filter_without_args, stream_rotation},
+	utils::from_path,
+};
+
+use ffmpeg_sys_next::{
+	av_buffersink_get_frame, av_buffersrc_add_frame_flags, av_dict_free, av_dict_set,
+	av_find_best_stream, av_frame_alloc, av_frame_free, av_frame_unref, av_guess_frame_rate,
+	av_interleaved_write_frame, av_packet_alloc, av_packet_free, av_packet_rescale_ts,
+	av_packet_unref, av_read_frame, av_write_trailer, avcodec_alloc_context3,
+	avcodec_find_encoder_by_name, avcodec_free_context, avcodec_open2, avcodec_parameters_copy,
+	avcodec_parameters_from_context, avcodec_parameters_to_context, avcodec_receive_frame,
+	avcodec_receive_packet, avcodec_send_frame, avcodec_send_packet, avfilter_graph_alloc,
+	avfilter_graph_config, avfilter_graph_free, avfilter_link, avformat_alloc_output_context2,
+	avformat_close_input, avformat_find_stream_info, avformat_free_context, avformat_new_stream,
+	avformat_open_input, avformat_query_codec, avformat_write_header, avio_closep, avio_open,
+	AVCodec, AVCodecContext, AVDictionary, AVFilterContext, AVFilterGraph, AVFormatContext,
+	AVFrame, AVMediaType, AVPacket, AVPictureType, AVPixelFormat, AVStream, AVERROR, AVERROR_EOF,
+	AVFMT_GLOBALHEADER, AVFMT_NOFILE, AVIO_FLAG_WRITE, AV_CODEC_FLAG_GLOBAL_HEADER, AV_TIME_BASE,
+	EAGAIN,
+};
+use std::{
+	ffi::{c_int, CString},
+	path::Path,
+};
+
+const AVERROR_EAGAIN: c_int = AVERROR(EAGAIN);
+
+/// `FF_COMPLIANCE_NORMAL`, streams are only copied to the output if its container fully supports
+/// their codec
+const COMPLIANCE_NORMAL: c_int = 0;
+
+/// The video codecs videos can be transcoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+	H264,
+	H265,
+	Av1,
+}
+
+impl VideoCodec {
+	/// Hardware encoders, then software ones, in the order they're tried. Hardware encoders fail
+	/// to open when their hardware isn't there, so the first one that opens is used.
+	fn encoders(self, hardware_acceleration: bool) -> Vec<&'static str> {
+		let (hardware, software): (&[&str], &[&str]) = match self {
+			Self::H264 => (
+				&[
+					#[cfg(target_os = "macos")]
+					"h264_videotoolbox",
+					"h264_nvenc",
+					"h264_qsv",
+					"h264_amf",
+				],
+				&["libx264"],
+			),
+			Self::H265 => (
+				&[
+					#[cfg(target_os = "macos")]
+					"hevc_videotoolbox",
+					"hevc_nvenc",
+					"hevc_qsv",
+					"hevc_amf",
+				],
+				&["libx265"],
+			),
+			Self::Av1 => (
+				&["av1_nvenc", "av1_qsv", "av1_amf"],
+				&["libsvtav1", "libaom-av1"],
+			),
+		};
+
+		hardware
+			.iter()
+			.filter(|_| hardware_acceleration)
+			.chain(software)
+			.copied()
+			.collect()
+	}
+
+	/// The constant rate factor of software encoders, visually close to the source for each codec
+	const fn crf(self) -> &'static str {
+		match self {
+			Self::H264 => "23",
+			Self::H265 => "28",
+			Self::Av1 => "32",
+		}
+	}
+
+	/// Bits per pixel of each frame for hardware encoders, which don't all take a rate factor
+	const fn bits_per_pixel(self) -> f64 {
+		match self {
+			Self::H264 => 0.1,
+			Self::H265 | Self::Av1 => 0.06,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TranscodeOptions {
+	pub codec: VideoCodec,
+	/// Videos taller than this are scaled down to it, keeping their aspect ratio
+	pub max_height: Option<u32>,
+	pub hardware_acceleration: bool,
+}
+
+/// The size of the transcoded frames, even as the chroma of 4:2:0 video is subsampled by 2
+pub(crate) fn output_size(width: u32, height: u32, max_height: Option<u32>) -> (u32, u32) {
+	let (width, height) = match max_height {
+		Some(max_height) if height > max_height => (
+			(width as f64 * max_height as f64 / height as f64).round() as u32,
+			max_height,
+		),
+		_ => (width, height),
+	};
+
+	((width & !1).max(2), (height & !1).max(2))
+}
+
+/// Transcodes the best video stream of `input` to an MP4 file at `output`, copying the audio
+/// streams the MP4 container supports. `progress` is called with the percentage done as it grows.
+///
+/// Returns the name of the encoder used.
+pub(crate) fn transcode(
+	input: impl AsRef<Path>,
+	output: impl AsRef<Path>,
+	options: TranscodeOptions,
+	mut progress: impl FnMut(u8),
+) -> Result<&'static str, ThumbnailerError> {
+	let mut transcoder = Transcoder::new(input, output, options)?;
+	let encoder_name = transcoder.encoder_name;
+
+	transcoder.run(&mut progress)?;
+
+	Ok(encoder_name)
+}
+
+struct Transcoder {
+	input_context: *mut AVFormatContext,
+	output_context: *mut AVFormatContext,
+	decoder_context: *mut AVCodecContext,
+	encoder_context: *mut AVCodecContext,
+	encoder_name: &'static str,
+	filter_graph: *mut AVFilterGraph,
+	filter_source: *mut AVFilterContext,
+	filter_sink: *mut AVFilterContext,
+	video_stream_index: c_int,
+	output_video_stream: *mut AVStream,
+	/// The output stream of each input stream, if it's copied
+	copied_streams: Vec<Option<*mut AVStream>>,
+	packet: *mut AVPacket,
+	frame: *mut AVFrame,
+	filtered_frame: *mut AVFrame,
+	encoded_packet: *mut AVPacket,
+}
+
+impl Transcoder {
+	fn new(
+		input: impl AsRef<Path>,
+		output: impl AsRef<Path>,
+		options: TranscodeOptions,
+	) -> Result<Self, ThumbnailerError> {
+		let mut transcoder = Self {
+			input_context: std::ptr::null_mut(),
+			output_context: std::ptr::null_mut(),
+			decoder_context: std::ptr::null_mut(),
+			encoder_context: std::ptr::null_mut(),
+			encoder_name: "",
+			filter_graph: std::ptr::null_mut(),
+			filter_source: std::ptr::null_mut(),
+			filter_sink: std::ptr::null_mut(),
+			video_stream_index: -1,
+			output_video_stream: std::ptr::null_mut(),
+			copied_streams: vec![],
+			packet: std::ptr::null_mut(),
+			frame: std::ptr::null_mut(),
+			filtered_frame: std::ptr::null_mut(),
+			encoded_packet: std::ptr::null_mut(),
+		};
+
+		let input_cstring = from_path(input)?;
+		check_error(
+			unsafe {
+				avformat_open_input(
+					&mut transcoder.input_context,
+					input_cstring.as_ptr(),
+					std::ptr::null_mut(),
+					std::ptr::null_mut(),
+				)
+			},
+			"Failed to open input",
+		)?;
+		check_error(
+			unsafe { avformat_find_stream_info(transcoder.input_context, std::ptr::null_mut()) },
+			"Failed to get stream info",
+		)?;
+
+		let video_stream = transcoder.open_decoder()?;
+
+		let output_cstring = from_path(output)?;
+		let format_cstring = CString::new("mp4").unwrap();
+		check_error(
+			unsafe {
+				avformat_alloc_output_context2(
+					&mut transcoder.output_context,
+					std::ptr::null(),
+					format_cstring.as_ptr(),
+					output_cstring.as_ptr(),
+				)
+			},
+			"Failed to create output context",
+		)?;
+
+		let rotation = stream_rotation(video_stream);
+		let (width, height) = unsafe {
+			match rotation {
+				// A quarter turn swaps the sides
+				1 | 2 => (
+					(*transcoder.decoder_context).height,
+					(*transcoder.decoder_context).width,
+				),
+				_ => (
+					(*transcoder.decoder_context).width,
+					(*transcoder.decoder_context).height,
+				),
+			}
+		};
+		let (width, height) = output_size(width as u32, height as u32, options.max_height);
+
+		let pixel_format = transcoder.open_encoder(video_stream, width, height, options)?;
+		transcoder.initialize_filter_graph(video_stream, rotation, width, height, pixel_format)?;
+		transcoder.create_output_streams()?;
+
+		unsafe {
+			if (*(*transcoder.output_context).oformat).flags & AVFMT_NOFILE as c_int == 0 {
+				check_error(
+					avio_open(
+						&mut (*transcoder.output_context).pb,
+						output_cstring.as_ptr(),
+						AVIO_FLAG_WRITE as c_int,
+					),
+					"Failed to open output",
+				)?;
+			}
+
+			check_error(
+				avformat_write_header(transcoder.output_context, std::ptr::null_mut()),
+				"Failed to write output header",
+			)?;
+		}
+
+		transcoder.packet = unsafe { av_packet_alloc() };
+		transcoder.encoded_packet = unsafe { av_packet_alloc() };
+		transcoder.frame = unsafe { av_frame_alloc() };
+		transcoder.filtered_frame = unsafe { av_frame_alloc() };
+		if transcoder.packet.is_null()
+			|| transcoder.encoded_packet.is_null()
+			|| transcoder.frame.is_null()
+			|| transcoder.filtered_frame.is_null()
+		{
+			return Err(FfmpegError::FrameAllocation.into());
+		}
+
+		Ok(transcoder)
+	}
+
+	fn open_decoder(&mut self) -> Result<*mut AVStream, ThumbnailerError> {
+		let mut decoder: *const AVCodec = std::ptr::null();
+		self.video_stream_index = unsafe {
+			av_find_best_stream(
+				self.input_context,
+				AVMediaType::AVMEDIA_TYPE_VIDEO,
+				-1,
+				-1,
+				&mut decoder,
+				0,
+			)
+		};
+		check_error(self.video_stream_index, "Failed to find a video stream")?;
+		if decoder.is_null() {
+			return Err(FfmpegError::DecoderNotFound.into());
+		}
+
+		let video_stream = unsafe {
+			*(*self.input_context)
+				.streams
+				.offset(self.video_stream_index as isize)
+		};
+
+		self.decoder_context = unsafe { avcodec_alloc_context3(decoder) };
+		if self.decoder_context.is_null() {
+			return Err(FfmpegError::VideoCodecAllocation.into());
+		}
+
+		unsafe {
+			check_error(
+				avcodec_parameters_to_context(self.decoder_context, (*video_stream).codecpar),
+				"Failed to get parameters from context",
+			)?;
+
+			(*self.decoder_context).pkt_timebase = (*video_stream).time_base;
+			(*self.decoder_context).framerate =
+				av_guess_frame_rate(self.input_context, video_stream, std::ptr::null_mut());
+
+			check_error(
+				avcodec_open2(self.decoder_context, decoder, std::ptr::null_mut()),
+				"Failed to open video decoder",
+			)?;
+		}
+
+		Ok(video_stream)
+	}
+
+	/// Opens the first encoder of the codec that's available, returning the name of the pixel
+	/// format it takes
+	fn open_encoder(
+		&mut self,
+		video_stream: *mut AVStream,
+		width: u32,
+		height: u32,
+		options: TranscodeOptions,
+	) -> Result<&'static str, ThumbnailerError> {
+		let frame_rate = unsafe { (*self.decoder_context).framerate };
+		let frames_per_second = if frame_rate.num > 0 && frame_rate.den > 0 {
+			frame_rate.num as f64 / frame_rate.den as f64
+		} else {
+			30.0
+		};
+
+		for name in options.codec.encoders(options.hardware_acceleration) {
+			let name_cstring = CString::new(name).unwrap();
+			let encoder = unsafe { avcodec_find_encoder_by_name(name_cstring.as_ptr()) };
+			if encoder.is_null() {
+				continue;
+			}
+
+			let Some((pixel_format, pixel_format_name)) = supported_pixel_format(encoder) else {
+				continue;
+			};
+
+			let mut encoder_context = unsafe { avcodec_alloc_context3(encoder) };
+			if encoder_context.is_null() {
+				return Err(FfmpegError::VideoCodecAllocation.into());
+			}
+
+			let mut encoder_options: *mut AVDictionary = std::ptr::null_mut();
+
+			let opened = unsafe {
+				(*encoder_context).width = width as c_int;
+				(*encoder_context).height = height as c_int;
+				(*encoder_context).pix_fmt = pixel_format;
+				(*encoder_context).sample_aspect_ratio =
+					(*self.decoder_context).sample_aspect_ratio;
+				// Frames keep the timestamps of the input stream, the filters don't change them
+				(*encoder_context).time_base = (*video_stream).time_base;
+				(*encoder_context).framerate = frame_rate;
+
+				if (*(*self.output_context).oformat).flags & AVFMT_GLOBALHEADER as c_int != 0 {
+					(*encoder_context).flags |= AV_CODEC_FLAG_GLOBAL_HEADER as c_int;
+				}
+
+				if name.starts_with("lib") {
+					let key = CString::new("crf").unwrap();
+					let value = CString::new(options.codec.crf()).unwrap();
+					av_dict_set(&mut encoder_options, key.as_ptr(), value.as_ptr(), 0);
+				} else {
+					(*encoder_context).bit_rate = (width as f64
+						* height as f64 * frames_per_second
+						* options.codec.bits_per_pixel()) as i64;
+				}
+
+				let opened = avcodec_open2(encoder_context, encoder, &mut encoder_options);
+				av_dict_free(&mut encoder_options);
+
+				opened
+			};
+
+			if opened < 0 {
+				// Hardware encoders fail here when their hardware isn't there
+				unsafe { avcodec_free_context(&mut encoder_context) };
+				continue;
+			}
+
+			self.encoder_name = name;
+			self.encoder_context = encoder_context;
+
+			return Ok(pixel_format_name);
+		}
+
+		Err(FfmpegError::EncoderNotFound.into())
+	}
+
+	fn initialize_filter_graph(
+		&mut self,
+		video_stream: *mut AVStream,
+		rotation: i32,
+		width: u32,
+		height: u32,
+		pixel_format: &str,
+	) -> Result<(), ThumbnailerError> {
+		self.filter_graph = unsafe { avfilter_graph_alloc() };
+		if self.filter_graph.is_null() {
+			return Err(FfmpegError::FilterGraphAllocation.into());
+		}
+
+		let args = unsafe {
+			format!(
+				"video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+				(*self.decoder_context).width,
+				(*self.decoder_context).height,
+				(*self.decoder_context).pix_fmt as i32,
+				(*video_stream).time_base.num,
+				(*video_stream).time_base.den,
+				(*self.decoder_context).sample_aspect_ratio.num,
+				i32::max((*self.decoder_context).sample_aspect_ratio.den, 1)
+			)
+		};
+
+		setup_filter(
+			&mut self.filter_source,
+			"buffer",
+			"transcode_buffer",
+			&args,
+			self.filter_graph,
+			"Failed to create filter source",
+		)?;
+
+		setup_filter_without_args(
+			&mut self.filter_sink,
+			"buffersink",
+			"transcode_buffersink",
+			self.filter_graph,
+			"Failed to create filter sink",
+		)?;
+
+		// The rotation is applied to the frames, as the display matrix isn't copied to the output
+		let mut rotate_filter = std::ptr::null_mut();
+		if rotation == 3 {
+			setup_filter(
+				&mut rotate_filter,
+				"rotate",
+				"transcode_rotate",
+				"PI",
+				self.filter_graph,
+				"Failed to create rotate filter",
+			)?;
+		} else if rotation != -1 {
+			setup_filter(
+				&mut rotate_filter,
+				"transpose",
+				"transcode_transpose",
+				&rotation.to_string(),
+				self.filter_graph,
+				"Failed to create transpose filter",
+			)?;
+		}
+
+		let mut scale_filter = std::ptr::null_mut();
+		setup_filter(
+			&mut scale_filter,
+			"scale",
+			"transcode_scale",
+			&format!("w={width}:h={height}"),
+			self.filter_graph,
+			"Failed to create scale filter",
+		)?;
+
+		let mut format_filter = std::ptr::null_mut();
+		setup_filter(
+			&mut format_filter,
+			"format",
+			"transcode_format",
+			&format!("pix_fmts={pixel_format}"),
+			self.filter_graph,
+			"Failed to create format filter",
+		)?;
+
+		check_error(
+			unsafe {
+				avfilter_link(
+					self.filter_source,
+					0,
+					if !rotate_filter.is_null() {
+						rotate_filter
+					} else {
+						scale_filter
+					},
+					0,
+				)
+			},
+			"Failed to link source filter",
+		)?;
+
+		if !rotate_filter.is_null() {
+			check_error(
+				unsafe { avfilter_link(rotate_filter, 0, scale_filter, 0) },
+				"Failed to link rotate filter",
+			)?;
+		}
+
+		check_error(
+			unsafe { avfilter_link(scale_filter, 0, format_filter, 0) },
+			"Failed to link scale filter",
+		)?;
+
+		check_error(
+			unsafe { avfilter_link(format_filter, 0, self.filter_sink, 0) },
+			"Failed to link format filter",
+		)?;
+
+		check_error(
+			unsafe { avfilter_graph_config(self.filter_graph, std::ptr::null_mut()) },
+			"Failed to configure filter graph",
+		)
+	}
+
+	/// Creates the output video stream, and the streams of the audio copied as it is
+	fn create_output_streams(&mut self) -> Result<(), ThumbnailerError> {
+		unsafe {
+			self.output_video_stream = avformat_new_stream(self.output_context, std::ptr::null());
+			if self.output_video_stream.is_null() {
+				return Err(FfmpegError::VideoCodecAllocation.into());
+			}
+
+			check_error(
+				avcodec_parameters_from_context(
+					(*self.output_video_stream).codecpar,
+					self.encoder_context,
+				),
+				"Failed to get parameters from encoder",
+			)?;
+			(*self.output_video_stream).time_base = (*self.encoder_context).time_base;
+
+			for stream_idx in 0..(*self.input_context).nb_streams as isize {
+				let stream = *(*self.input_context).streams.offset(stream_idx);
+				let codec_params = (*stream).codecpar;
+
+				if (*codec_params).codec_type != AVMediaType::AVMEDIA_TYPE_AUDIO
+					|| avformat_query_codec(
+						(*self.output_context).oformat,
+						(*codec_params).codec_id,
+						COMPLIANCE_NORMAL,
+					) != 1
+				{
+					self.copied_streams.push(None);
+					continue;
+				}
+
+				let output_stream = avformat_new_stream(self.output_context, std::ptr::null());
+				if output_stream.is_null() {
+					return Err(FfmpegError::AudioCodecAllocation.into());
+				}
+
+				check_error(
+					avcodec_parameters_copy((*output_stream).codecpar, codec_params),
+					"Failed to copy audio stream parameters",
+				)?;
+				// The tag of the input container may mean something else in the output one
+				(*(*output_stream).codecpar).codec_tag = 0;
+
+				self.copied_streams.push(Some(output_stream));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn run(&mut self, progress: &mut impl FnMut(u8)) -> Result<(), ThumbnailerError> {
+		let duration = unsafe { (*self.input_context).duration };
+		let mut last_percentage = 0;
+
+		while unsafe { av_read_frame(self.input_context, self.packet) } == 0 {
+			let stream_index = unsafe { (*self.packet).stream_index };
+
+			let result = if stream_index == self.video_stream_index {
+				if duration > 0 {
+					let percentage = self.packet_percentage(duration);
+					if percentage > last_percentage {
+						last_percentage = percentage;
+						progress(percentage);
+					}
+				}
+
+				self.decode_packet(self.packet)
+			} else {
+				self.copy_packet(stream_index)
+			};
+
+			unsafe { av_packet_unref(self.packet) };
+			result?;
+		}
+
+		// Draining the decoder, then the filters, then the encoder
+		self.decode_packet(std::ptr::null_mut())?;
+		self.filter_frame(std::ptr::null_mut())?;
+		self.encode_frame(std::ptr::null_mut())?;
+
+		check_error(
+			unsafe { av_write_trailer(self.output_context) },
+			"Failed to write output trailer",
+		)
+	}
+
+	fn packet_percentage(&self, duration: i64) -> u8 {
+		let (pts, time_base) = unsafe {
+			let stream = *(*self.input_context)
+				.streams
+				.offset(self.video_stream_index as isize);
+
+			((*self.packet).pts, (*stream).time_base)
+		};
+
+		if time_base.den == 0 || pts < 0 {
+			return 0;
+		}
+
+		let position = pts as f64 * time_base.num as f64 / time_base.den as f64;
+		let duration = duration as f64 / AV_TIME_BASE as f64;
+
+		((position / duration * 100.0) as u8).min(100)
+	}
+
+	fn copy_packet(&mut self, stream_index: c_int) -> Result<(), ThumbnailerError> {
+		let Some(Some(output_stream)) = self.copied_streams.get(stream_index as usize).copied()
+		else {
+			return Ok(());
+		};
+
+		unsafe {
+			let input_stream = *(*self.input_context).streams.offset(stream_index as isize);
+
+			av_packet_rescale_ts(
+				self.packet,
+				(*input_stream).time_base,
+				(*output_stream).time_base,
+			);
+			(*self.packet).stream_index = (*output_stream).index;
+			(*self.packet).pos = -1;
+
+			check_error(
+				av_interleaved_write_frame(self.output_context, self.packet),
+				"Failed to write audio packet",
+			)
+		}
+	}
+
+	/// Sends a packet to the decoder, a null one to drain it, and filters the decoded frames
+	fn decode_packet(&mut self, packet: *mut AVPacket) -> Result<(), ThumbnailerError> {
+		check_error(
+			unsafe { avcodec_send_packet(self.decoder_context, packet) },
+			"Failed to send packet to decoder",
+		)?;
+
+		loop {
+			match unsafe { avcodec_receive_frame(self.decoder_context, self.frame) } {
+				0 => {
+					unsafe { (*self.frame).pts = (*self.frame).best_effort_timestamp };
+					self.filter_frame(self.frame)?;
+				}
+				AVERROR_EAGAIN | AVERROR_EOF => return Ok(()),
+				e => {
+					return Err(ThumbnailerError::FfmpegWithReason(
+						FfmpegError::from(e),
+						"Failed to receive frame from decoder".to_string(),
+					))
+				}
+			}
+		}
+	}
+
+	/// Sends a frame through the filters, a null one to drain them, and encodes the filtered frames
+	fn filter_frame(&mut self, frame: *mut AVFrame) -> Result<(), ThumbnailerError> {
+		check_error(
+			unsafe { av_buffersrc_add_frame_flags(self.filter_source, frame, 0) },
+			"Failed to write frame to filter graph",
+		)?;
+
+		loop {
+			match unsafe { av_buffersink_get_frame(self.filter_sink, self.filtered_frame) } {
+				0 => {
+					unsafe {
+						// Letting the encoder pick the frame types
+						(*self.filtered_frame).pict_type = AVPictureType::AV_PICTURE_TYPE_NONE;
+					}
+
+					let result = self.encode_frame(self.filtered_frame);
+					unsafe { av_frame_unref(self.filtered_frame) };
+					result?;
+				}
+				AVERROR_EAGAIN | AVERROR_EOF => return Ok(()),
+				e => {
+					return Err(ThumbnailerError::FfmpegWithReason(
+						FfmpegError::from(e),
+						"Failed to get frame from filter graph".to_string(),
+					))
+				}
+			}
+		}
+	}
+
+	/// Sends a frame to the encoder, a null one to drain it, and writes the encoded packets
+	fn encode_frame(&mut self, frame: *mut AVFrame) -> Result<(), ThumbnailerError> {
+		check_error(
+			unsafe { avcodec_send_frame(self.encoder_context, frame) },
+			"Failed to send frame to encoder",
+		)?;
+
+		loop {
+			match unsafe { avcodec_receive_packet(self.encoder_context, self.encoded_packet) } {
+				0 => unsafe {
+					(*self.encoded_packet).stream_index = (*self.output_video_stream).index;
+					av_packet_rescale_ts(
+						self.encoded_packet,
+						(*self.encoder_context).time_base,
+						(*self.output_video_stream).time_base,
+					);
+
+					// Takes the packet's reference, leaving it blank for the next one
+					check_error(
+						av_interleaved_write_frame(self.output_context, self.encoded_packet),
+						"Failed to write video packet",
+					)?;
+				},
+				AVERROR_EAGAIN | AVERROR_EOF => return Ok(()),
+				e => {
+					return Err(ThumbnailerError::FfmpegWithReason(
+						FfmpegError::from(e),
+						"Failed to receive packet from encoder".to_string(),
+					))
+				}
+			}
+		}
+	}
+}
+
+/// The pixel format an encoder takes, 8 bit 4:2:0 as it plays everywhere
+fn supported_pixel_format(encoder: *const AVCodec) -> Option<(AVPixelFormat, &'static str)> {
+	let mut pixel_formats = unsafe { (*encoder).pix_fmts };
+	if pixel_formats.is_null() {
+		// Encoders that don't list their pixel formats take any of them
+		return Some((AVPixelFormat::AV_PIX_FMT_YUV420P, "yuv420p"));
+	}
+
+	let mut nv12 = None;
+	unsafe {
+		while *pixel_formats != AVPixelFormat::AV_PIX_FMT_NONE {
+			match *pixel_formats {
+				AVPixelFormat::AV_PIX_FMT_YUV420P => {
+					return Some((AVPixelFormat::AV_PIX_FMT_YUV420P, "yuv420p"))
+				}
+				AVPixelFormat::AV_PIX_FMT_NV12 => {
+					nv12 = Some((AVPixelFormat::AV_PIX_FMT_NV12, "nv12"));
+				}
+				_ => {}
+			}
+			pixel_formats = pixel_formats.offset(1);
+		}
+	}
+
+	nv12
+}
+
+impl Drop for Transcoder {
+	fn drop(&mut self) {
+		unsafe {
+			if !self.filter_graph.is_null() {
+				avfilter_graph_free(&mut self.filter_graph);
+			}
+
+			if !self.decoder_context.is_null() {
+				avcodec_free_context(&mut self.decoder_context);
+			}
+
+			if !self.encoder_context.is_null() {
+				avcodec_free_context(&mut self.encoder_context);
+			}
+
+			if !self.input_context.is_null() {
+				avformat_close_input(&mut self.input_context);
+			}
+
+			if !self.output_context.is_null() {
+				if (*(*self.output_context).oformat).flags & AVFMT_NOFILE as c_int == 0 {
+					avio_closep(&mut (*self.output_context).pb);
+				}
+				avformat_free_context(self.output_context);
+				self.output_context = std::ptr::null_mut();
+			}
+
+			for packet in [&mut self.packet, &mut self.encoded_packet] {
+				if !packet.is_null() {
+					av_packet_unref(*packet);
+					av_packet_free(packet);
+				}
+			}
+
+			for frame in [&mut self.frame, &mut self.filtered_frame] {
+				if !frame.is_null() {
+					av_frame_free(frame);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn scales_down_to_even_sizes() {
+		assert_eq!(output_size(3840, 2160, Some(1080)), (1920, 1080));
+		// Portrait videos are capped by their height too
+		assert_eq!(output_size(1080, 1920, Some(720)), (404, 720));
+		assert_eq!(output_size(1281, 719, Some(1080)), (1280, 718));
+		assert_eq!(output_size(640, 480, None), (640, 480));
+	}
+
+	#[test]
+	fn software_encoders_come_last() {
+		assert_eq!(VideoCodec::H264.encoders(false), ["libx264"]);
+		assert_eq!(
+			VideoCodec::Av1.encoders(true),
+			["av1_nvenc", "av1_qsv", "av1_amf", "libsvtav1", "libaom-av1"]
+		);
+	}
+}
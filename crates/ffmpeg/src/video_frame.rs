@@ -16,6 +16,32 @@ pub(crate) struct VideoFrame {
 	pub source: Option<FrameSource>,
 }
 
+impl VideoFrame {
+	/// Checks if the frame is mostly made of dark pixels, which is a strong indicator of a fade
+	/// in/out or a black title card that wouldn't make for a representative thumbnail.
+	/// The frame data is expected to be in RGB24, with rows padded up to `line_size` bytes.
+	pub(crate) fn is_mostly_black(&self, luma_threshold: u8, black_ratio: f32) -> bool {
+		let row_len = self.width as usize * 3;
+		if row_len == 0 || self.line_size == 0 {
+			return false;
+		}
+
+		let (dark_pixels, total_pixels) = self
+			.data
+			.chunks(self.line_size as usize)
+			.take(self.height as usize)
+			.flat_map(|row| row[..row_len.min(row.len())].chunks_exact(3))
+			.fold((0usize, 0usize), |(dark, total), pixel| {
+				// Rec. 601 luma approximation, using integer math
+				let luma =
+					(299 * pixel[0] as u32 + 587 * pixel[1] as u32 + 114 * pixel[2] as u32) / 1000;
+				(dark + (luma <= luma_threshold as u32) as usize, total + 1)
+			});
+
+		total_pixels != 0 && dark_pixels as f32 / total_pixels as f32 >= black_ratio
+	}
+}
+
 pub(crate) struct FfmpegFrame {
 	data: *mut AVFrame,
 }
@@ -40,3 +66,44 @@ impl Drop for FfmpegFrame {
 		self.data = std::ptr::null_mut();
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn frame_with(pixels: &[[u8; 3]], width: u32, padding: u32) -> VideoFrame {
+		let line_size = width * 3 + padding;
+		let height = pixels.len() as u32 / width;
+		let mut data = Vec::with_capacity((line_size * height) as usize);
+		for row in pixels.chunks(width as usize) {
+			row.iter().for_each(|pixel| data.extend_from_slice(pixel));
+			data.extend(std::iter::repeat(255).take(padding as usize));
+		}
+
+		VideoFrame {
+			width,
+			height,
+			line_size,
+			data,
+			source: None,
+		}
+	}
+
+	#[test]
+	fn black_frame_detection() {
+		let black = frame_with(&[[0, 0, 0]; 16], 4, 4);
+		assert!(black.is_mostly_black(24, 0.98));
+
+		let mut pixels = [[3, 2, 1]; 16];
+		pixels[5] = [200, 180, 120];
+		pixels[10] = [90, 90, 90];
+		let mostly_dark = frame_with(&pixels, 4, 0);
+		assert!(mostly_dark.is_mostly_black(24, 0.85));
+		assert!(!mostly_dark.is_mostly_black(24, 0.98));
+
+		let bright = frame_with(&[[120, 130, 140]; 16], 4, 2);
+		assert!(!bright.is_mostly_black(24, 0.98));
+
+		assert!(!VideoFrame::default().is_mostly_black(24, 0.98));
+	}
+}
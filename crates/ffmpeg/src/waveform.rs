@@ -0,0 +1,410 @@
+use crate::{
+	error::{FfmpegError, ThumbnailerError},
+	utils::from_path,
+};
+
+use ffmpeg_sys_next::{
+	av_find_best_stream, av_frame_alloc, av_frame_free, av_packet_alloc, av_packet_free,
+	av_packet_unref, av_read_frame, avcodec_alloc_context3, avcodec_free_context, avcodec_open2,
+	avcodec_parameters_to_context, avcodec_receive_frame, avcodec_send_packet,
+	avformat_close_input, avformat_find_stream_info, avformat_open_input, AVCodec, AVCodecContext,
+	AVFormatContext, AVFrame, AVMediaType, AVPacket, AVSampleFormat, AVERROR, AVERROR_EOF, EAGAIN,
+};
+use std::{ffi::c_int, path::Path};
+
+const AVERROR_EAGAIN: c_int = AVERROR(EAGAIN);
+
+/// Amount of samples reduced to a single peak while decoding, the final peaks are computed from
+/// these blocks so we don't need to know the duration of the audio beforehand
+const SAMPLES_PER_BLOCK: usize = 256;
+
+/// Extracts `num_peaks` peaks from the first audio stream of the file, each peak being the
+/// maximum absolute amplitude of its slice of the audio, scaled to `0..=255`.
+/// Short files may produce fewer peaks than requested.
+pub(crate) fn extract_peaks(
+	filename: impl AsRef<Path>,
+	num_peaks: usize,
+) -> Result<Vec<u8>, ThumbnailerError> {
+	let mut decoder = AudioDecoder::new(filename)?;
+	let mut blocks = PeakBlocks::default();
+
+	decoder.decode(|amplitude| blocks.push(amplitude))?;
+
+	Ok(blocks.into_peaks(num_peaks))
+}
+
+struct AudioDecoder {
+	audio_stream_index: c_int,
+	format_context: *mut AVFormatContext,
+	audio_codec_context: *mut AVCodecContext,
+	frame: *mut AVFrame,
+	packet: *mut AVPacket,
+}
+
+impl AudioDecoder {
+	fn new(filename: impl AsRef<Path>) -> Result<Self, ThumbnailerError> {
+		let mut decoder = Self {
+			audio_stream_index: -1,
+			format_context: std::ptr::null_mut(),
+			audio_codec_context: std::ptr::null_mut(),
+			frame: std::ptr::null_mut(),
+			packet: std::ptr::null_mut(),
+		};
+
+		let input_file_cstring = from_path(filename)?;
+		check_error(
+			unsafe {
+				avformat_open_input(
+					&mut decoder.format_context,
+					input_file_cstring.as_ptr(),
+					std::ptr::null_mut(),
+					std::ptr::null_mut(),
+				)
+			},
+			"Failed to open input",
+		)?;
+		check_error(
+			unsafe { avformat_find_stream_info(decoder.format_context, std::ptr::null_mut()) },
+			"Failed to get stream info",
+		)?;
+
+		let mut audio_codec: *const AVCodec = std::ptr::null();
+		decoder.audio_stream_index = unsafe {
+			av_find_best_stream(
+				decoder.format_context,
+				AVMediaType::AVMEDIA_TYPE_AUDIO,
+				-1,
+				-1,
+				&mut audio_codec,
+				0,
+			)
+		};
+		check_error(decoder.audio_stream_index, "Failed to find an audio stream")?;
+		if audio_codec.is_null() {
+			return Err(FfmpegError::DecoderNotFound.into());
+		}
+
+		decoder.audio_codec_context = unsafe { avcodec_alloc_context3(audio_codec) };
+		if decoder.audio_codec_context.is_null() {
+			return Err(FfmpegError::AudioCodecAllocation.into());
+		}
+
+		check_error(
+			unsafe {
+				let stream = *(*decoder.format_context)
+					.streams
+					.offset(decoder.audio_stream_index as isize);
+				avcodec_parameters_to_context(decoder.audio_codec_context, (*stream).codecpar)
+			},
+			"Failed to get parameters from context",
+		)?;
+
+		check_error(
+			unsafe {
+				avcodec_open2(
+					decoder.audio_codec_context,
+					audio_codec,
+					std::ptr::null_mut(),
+				)
+			},
+			"Failed to open audio codec",
+		)?;
+
+		decoder.frame = unsafe { av_frame_alloc() };
+		decoder.packet = unsafe { av_packet_alloc() };
+		if decoder.frame.is_null() || decoder.packet.is_null() {
+			return Err(FfmpegError::FrameAllocation.into());
+		}
+
+		Ok(decoder)
+	}
+
+	/// Decodes the whole audio stream, calling `on_sample` with the amplitude of every sample,
+	/// being the loudest channel of each sample in the range `0.0..=1.0`
+	fn decode(&mut self, mut on_sample: impl FnMut(f32)) -> Result<(), ThumbnailerError> {
+		while unsafe { av_read_frame(self.format_context, self.packet) } == 0 {
+			if unsafe { (*self.packet).stream_index } == self.audio_stream_index {
+				let ret = unsafe { avcodec_send_packet(self.audio_codec_context, self.packet) };
+				// Corrupted packets are skipped, we'll just have a gap in the waveform
+				if ret < 0 && ret != AVERROR_EAGAIN && ret != AVERROR_EOF {
+					unsafe { av_packet_unref(self.packet) };
+					continue;
+				}
+
+				self.receive_frames(&mut on_sample)?;
+			}
+
+			unsafe { av_packet_unref(self.packet) };
+		}
+
+		// Flushing the decoder to get the last buffered frames
+		unsafe { avcodec_send_packet(self.audio_codec_context, std::ptr::null()) };
+		self.receive_frames(&mut on_sample)
+	}
+
+	fn receive_frames(&mut self, on_sample: &mut impl FnMut(f32)) -> Result<(), ThumbnailerError> {
+		loop {
+			match unsafe { avcodec_receive_frame(self.audio_codec_context, self.frame) } {
+				0 => unsafe { read_frame_amplitudes(self.frame, on_sample) },
+				AVERROR_EAGAIN | AVERROR_EOF => return Ok(()),
+				e => {
+					return Err(ThumbnailerError::FfmpegWithReason(
+						FfmpegError::from(e),
+						"Failed to receive frame from decoder".to_string(),
+					))
+				}
+			}
+		}
+	}
+}
+
+impl Drop for AudioDecoder {
+	fn drop(&mut self) {
+		unsafe {
+			if !self.audio_codec_context.is_null() {
+				avcodec_free_context(&mut self.audio_codec_context);
+			}
+
+			if !self.format_context.is_null() {
+				avformat_close_input(&mut self.format_context);
+			}
+
+			if !self.packet.is_null() {
+				av_packet_free(&mut self.packet);
+			}
+
+			if !self.frame.is_null() {
+				av_frame_free(&mut self.frame);
+			}
+		}
+	}
+}
+
+/// Reads every sample of a decoded frame, taking the loudest channel as the sample's amplitude.
+///
+/// # Safety
+/// `frame` must point to a valid decoded audio frame.
+unsafe fn read_frame_amplitudes(frame: *const AVFrame, on_sample: &mut impl FnMut(f32)) {
+	let Some(format) = SampleFormat::from_raw((*frame).format) else {
+		return;
+	};
+
+	let channels = (*frame).ch_layout.nb_channels.max(0) as usize;
+	let samples = (*frame).nb_samples.max(0) as usize;
+	if channels == 0 || samples == 0 {
+		return;
+	}
+
+	let bytes_per_sample = format.bytes_per_sample();
+	let planes = if format.planar {
+		(0..channels)
+			.map(|channel| {
+				std::slice::from_raw_parts(
+					*(*frame).extended_data.add(channel),
+					samples * bytes_per_sample,
+				)
+			})
+			.collect::<Vec<_>>()
+	} else {
+		vec![std::slice::from_raw_parts(
+			*(*frame).extended_data,
+			samples * channels * bytes_per_sample,
+		)]
+	};
+
+	for sample in 0..samples {
+		let amplitude = (0..channels)
+			.map(|channel| {
+				let (plane, index) = if format.planar {
+					(planes[channel], sample)
+				} else {
+					(planes[0], sample * channels + channel)
+				};
+
+				format.amplitude(&plane[index * bytes_per_sample..][..bytes_per_sample])
+			})
+			.fold(0.0, f32::max);
+
+		on_sample(amplitude);
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SampleKind {
+	U8,
+	S16,
+	S32,
+	S64,
+	F32,
+	F64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SampleFormat {
+	kind: SampleKind,
+	planar: bool,
+}
+
+impl SampleFormat {
+	fn from_raw(format: c_int) -> Option<Self> {
+		use AVSampleFormat::*;
+
+		let (kind, planar) = match format {
+			f if f == AV_SAMPLE_FMT_U8 as c_int => (SampleKind::U8, false),
+			f if f == AV_SAMPLE_FMT_S16 as c_int => (SampleKind::S16, false),
+			f if f == AV_SAMPLE_FMT_S32 as c_int => (SampleKind::S32, false),
+			f if f == AV_SAMPLE_FMT_S64 as c_int => (SampleKind::S64, false),
+			f if f == AV_SAMPLE_FMT_FLT as c_int => (SampleKind::F32, false),
+			f if f == AV_SAMPLE_FMT_DBL as c_int => (SampleKind::F64, false),
+			f if f == AV_SAMPLE_FMT_U8P as c_int => (SampleKind::U8, true),
+			f if f == AV_SAMPLE_FMT_S16P as c_int => (SampleKind::S16, true),
+			f if f == AV_SAMPLE_FMT_S32P as c_int => (SampleKind::S32, true),
+			f if f == AV_SAMPLE_FMT_S64P as c_int => (SampleKind::S64, true),
+			f if f == AV_SAMPLE_FMT_FLTP as c_int => (SampleKind::F32, true),
+			f if f == AV_SAMPLE_FMT_DBLP as c_int => (SampleKind::F64, true),
+			_ => return None,
+		};
+
+		Some(Self { kind, planar })
+	}
+
+	const fn bytes_per_sample(&self) -> usize {
+		match self.kind {
+			SampleKind::U8 => 1,
+			SampleKind::S16 => 2,
+			SampleKind::S32 | SampleKind::F32 => 4,
+			SampleKind::S64 | SampleKind::F64 => 8,
+		}
+	}
+
+	/// Absolute amplitude of a single sample in native endianness, in the range `0.0..=1.0`
+	fn amplitude(&self, bytes: &[u8]) -> f32 {
+		let value = match self.kind {
+			// Unsigned 8 bits samples are centered at 128
+			SampleKind::U8 => (bytes[0] as f32 - 128.0) / 128.0,
+			SampleKind::S16 => i16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32,
+			SampleKind::S32 => {
+				i32::from_ne_bytes(bytes[..4].try_into().expect("4 bytes sample")) as f32
+					/ i32::MAX as f32
+			}
+			SampleKind::S64 => {
+				i64::from_ne_bytes(bytes[..8].try_into().expect("8 bytes sample")) as f32
+					/ i64::MAX as f32
+			}
+			SampleKind::F32 => f32::from_ne_bytes(bytes[..4].try_into().expect("4 bytes sample")),
+			SampleKind::F64 => {
+				f64::from_ne_bytes(bytes[..8].try_into().expect("8 bytes sample")) as f32
+			}
+		};
+
+		value.abs().min(1.0)
+	}
+}
+
+#[derive(Debug, Default)]
+struct PeakBlocks {
+	blocks: Vec<f32>,
+	current_peak: f32,
+	current_len: usize,
+}
+
+impl PeakBlocks {
+	fn push(&mut self, amplitude: f32) {
+		self.current_peak = self.current_peak.max(amplitude);
+		self.current_len += 1;
+
+		if self.current_len == SAMPLES_PER_BLOCK {
+			self.blocks.push(self.current_peak);
+			self.current_peak = 0.0;
+			self.current_len = 0;
+		}
+	}
+
+	fn into_peaks(mut self, num_peaks: usize) -> Vec<u8> {
+		if self.current_len > 0 {
+			self.blocks.push(self.current_peak);
+		}
+
+		let len = self.blocks.len();
+		let num_peaks = num_peaks.min(len);
+
+		(0..num_peaks)
+			.map(|i| {
+				let peak = self.blocks[i * len / num_peaks..(i + 1) * len / num_peaks]
+					.iter()
+					.copied()
+					.fold(0.0, f32::max);
+
+				(peak * u8::MAX as f32).round() as u8
+			})
+			.collect()
+	}
+}
+
+fn check_error(return_code: c_int, error_message: &str) -> Result<(), ThumbnailerError> {
+	if return_code < 0 {
+		Err(ThumbnailerError::FfmpegWithReason(
+			FfmpegError::from(return_code),
+			error_message.to_string(),
+		))
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sample_amplitudes() {
+		let s16 = SampleFormat {
+			kind: SampleKind::S16,
+			planar: false,
+		};
+		assert_eq!(s16.amplitude(&i16::MIN.to_ne_bytes()), 1.0);
+		assert_eq!(s16.amplitude(&0i16.to_ne_bytes()), 0.0);
+
+		let u8 = SampleFormat {
+			kind: SampleKind::U8,
+			planar: true,
+		};
+		assert_eq!(u8.amplitude(&[128]), 0.0);
+		assert_eq!(u8.amplitude(&[0]), 1.0);
+
+		let f32 = SampleFormat {
+			kind: SampleKind::F32,
+			planar: true,
+		};
+		assert_eq!(f32.amplitude(&(-0.5f32).to_ne_bytes()), 0.5);
+		// Float samples may clip above 1.0
+		assert_eq!(f32.amplitude(&1.5f32.to_ne_bytes()), 1.0);
+	}
+
+	#[test]
+	fn peaks_are_downsampled() {
+		let mut blocks = PeakBlocks::default();
+		for block in 0..4 {
+			for sample in 0..SAMPLES_PER_BLOCK {
+				blocks.push(if sample == 0 { block as f32 / 4.0 } else { 0.0 });
+			}
+		}
+		// A partial block at the end also counts
+		blocks.push(1.0);
+
+		assert_eq!(blocks.blocks.len(), 4);
+
+		let peaks = blocks.into_peaks(2);
+		assert_eq!(peaks, vec![64, 255]);
+	}
+
+	#[test]
+	fn short_audio_has_fewer_peaks() {
+		let mut blocks = PeakBlocks::default();
+		for _ in 0..SAMPLES_PER_BLOCK * 3 {
+			blocks.push(0.5);
+		}
+
+		assert_eq!(blocks.into_peaks(100), vec![128, 128, 128]);
+	}
+}
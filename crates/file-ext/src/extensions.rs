@@ -74,6 +74,8 @@ extension_category_enum! {
 		Tiff = [0x49, 0x49, 0x2A, 0x00],
 		Webp = [0x52, 0x49, 0x46, 0x46, _, _, _, _, 0x57, 0x45, 0x42, 0x50],
 		Svg = [0x3C, 0x73, 0x76, 0x67],
+		Eps = [0x25, 0x21, 0x50, 0x53] | [0xC5, 0xD0, 0xD3, 0xC6],
+		Ai = [],
 		Ico = [0x00, 0x00, 0x01, 0x00],
 		Heic = [0x00, 0x00, 0x00, 0x18, 0x66, 0x74, 0x79, 0x70, 0x68, 0x65, 0x69, 0x63],
 		Heics  = [0x00, 0x00, 0x00, 0x18, 0x66, 0x74, 0x79, 0x70, 0x68, 0x65, 0x69, 0x63],
@@ -86,6 +88,7 @@ extension_category_enum! {
 		Akw = [0x41, 0x4B, 0x57, 0x42],
 		Dng = [0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, 0x44, 0x4E, 0x47, 0x00],
 		Cr2 = [0x49, 0x49, 0x2A, 0x00, 0x10, 0x00, 0x00, 0x00, 0x43, 0x52, 0x02, 0x00],
+		Cr3 = [0x66, 0x74, 0x79, 0x70, 0x63, 0x72, 0x78, 0x20] + 4,
 		Dcr = [0x49, 0x49, 0x2A, 0x00, 0x10, 0x00, 0x00, 0x00, 0x44, 0x43, 0x52, 0x00],
 		Nwr = [0x49, 0x49, 0x2A, 0x00, 0x10, 0x00, 0x00, 0x00, 0x4E, 0x57, 0x52, 0x00],
 		Nef = [0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, 0x4E, 0x45, 0x46, 0x00],
@@ -96,7 +99,7 @@ extension_category_enum! {
 
 // audio extensions
 extension_category_enum! {
-	AudioExtension _ALL_AUDIO_EXTENSIONS {
+	AudioExtension ALL_AUDIO_EXTENSIONS {
 		Mp3 = [0x49, 0x44, 0x33],
 		Mp2 = [0xFF, 0xFB] | [0xFF, 0xFD],
 		M4a = [0x66, 0x74, 0x79, 0x70, 0x4D, 0x34, 0x41, 0x20] + 4,
@@ -154,7 +157,7 @@ extension_category_enum! {
 
 // document extensions
 extension_category_enum! {
-	DocumentExtension _ALL_DOCUMENT_EXTENSIONS {
+	DocumentExtension ALL_DOCUMENT_EXTENSIONS {
 		Pdf = [0x25, 0x50, 0x44, 0x46, 0x2D],
 		Key = [0x50, 0x4B, 0x03, 0x04],
 		Pages = [0x50, 0x4B, 0x03, 0x04],
@@ -175,7 +178,7 @@ extension_category_enum! {
 
 // text file extensions
 extension_category_enum! {
-	TextExtension _ALL_TEXT_EXTENSIONS {
+	TextExtension ALL_TEXT_EXTENSIONS {
 		Txt,
 		Rtf,
 		Md,
@@ -225,15 +228,18 @@ extension_category_enum! {
 
 // font extensions
 extension_category_enum! {
-	MeshExtension _ALL_MESH_EXTENSIONS {
+	MeshExtension ALL_MESH_EXTENSIONS {
 		Fbx = [0x46, 0x42, 0x58, 0x20],
 		Obj = [0x6F, 0x62, 0x6A],
+		Gltf = [],
+		Glb = [0x67, 0x6C, 0x54, 0x46],
+		Stl = [],
 	}
 }
 
 // code extensions
 extension_category_enum! {
-	CodeExtension _ALL_CODE_EXTENSIONS {
+	CodeExtension ALL_CODE_EXTENSIONS {
 		Rs,
 		Ts,
 		Tsx,
@@ -275,11 +281,13 @@ extension_category_enum! {
 
 // book extensions
 extension_category_enum! {
-	BookExtension _ALL_BOOK_EXTENSIONS {
+	BookExtension ALL_BOOK_EXTENSIONS {
 		Azw = [0x52, 0x49, 0x46, 0x46],
 		Azw3 = [0x52, 0x49, 0x46, 0x46],
 		Epub = [0x50, 0x4B, 0x03, 0x04],
 		Mobi = [0x4D, 0x4F, 0x42, 0x49],
+		Cbz = [0x50, 0x4B, 0x03, 0x04],
+		Cbr = [0x52, 0x61, 0x72, 0x21, 0x1A, 0x07],
 	}
 }
 
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 #[repr(i32)]
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, strum::FromRepr)]
 pub enum ObjectKind {
 	/// A file that can not be identified by the indexer
 	Unknown = 0,
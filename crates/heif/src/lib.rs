@@ -1,8 +1,4 @@
-use std::{
-	fs,
-	io::{Cursor, Read, Seek, SeekFrom},
-	path::Path,
-};
+use std::{fs, path::Path};
 
 use image::DynamicImage;
 use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
@@ -40,44 +36,73 @@ pub fn heif_to_dynamic_image(path: &Path) -> HeifResult<DynamicImage> {
 		return Err(HeifError::TooLarge);
 	}
 
-	let img = {
+	let (img, has_alpha, bit_depth) = {
 		// do this in a separate block so we drop the raw (potentially huge) image handle
 		let ctx = HeifContext::read_from_file(path.to_str().ok_or(HeifError::InvalidPath)?)?;
 		let heif = LibHeif::new();
 		let handle = ctx.primary_image_handle()?;
 
-		heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?
+		let has_alpha = handle.has_alpha_channel();
+		let bit_depth = handle.luma_bits_per_pixel();
+
+		// HDR images (10 and 12 bits, common on AVIF and on newer phones) are decoded with 16 bits
+		// per channel and scaled down to 8 bits afterwards
+		let chroma = match (has_alpha, bit_depth > 8) {
+			(false, false) => RgbChroma::Rgb,
+			(true, false) => RgbChroma::Rgba,
+			(false, true) => RgbChroma::HdrRgbLe,
+			(true, true) => RgbChroma::HdrRgbaLe,
+		};
+
+		(
+			heif.decode(&handle, ColorSpace::Rgb(chroma), None)?,
+			has_alpha,
+			bit_depth,
+		)
 	};
 
 	// TODO(brxken128): add support for images with individual r/g/b channels
 	// i'm unable to find a sample to test with, but it should follow the same principles as this one
-	if let Some(i) = img.planes().interleaved {
-		if i.bits_per_pixel != 8 {
-			return Err(HeifError::InvalidBitDepth);
-		}
+	let Some(i) = img.planes().interleaved else {
+		return Err(HeifError::Unsupported);
+	};
 
-		let data = i.data.to_vec();
-		let mut reader = Cursor::new(data);
+	let channels = if has_alpha { 4 } else { 3 };
+	let bytes_per_sample = match bit_depth {
+		8 => 1,
+		9..=16 => 2,
+		_ => return Err(HeifError::InvalidBitDepth),
+	};
 
-		let mut sequence = vec![];
-		let mut buffer = [0u8; 3]; // [r, g, b]
+	let row_len = img.width() as usize * channels * bytes_per_sample;
+	let mut sequence = Vec::with_capacity(img.width() as usize * img.height() as usize * channels);
 
-		// this is the interpolation stuff, it essentially just makes the image correct
-		// in regards to stretching/resolution, etc
-		for y in 0..img.height() {
-			reader.seek(SeekFrom::Start((i.stride * y as usize) as u64))?;
+	// this is the interpolation stuff, it essentially just makes the image correct
+	// in regards to stretching/resolution, etc
+	for y in 0..img.height() as usize {
+		let row = i
+			.data
+			.get(i.stride * y..i.stride * y + row_len)
+			.ok_or(HeifError::RgbImageConversion)?;
 
-			for _ in 0..img.width() {
-				reader.read_exact(&mut buffer)?;
-				sequence.extend_from_slice(&buffer);
-			}
+		if bytes_per_sample == 1 {
+			sequence.extend_from_slice(row);
+		} else {
+			let shift = bit_depth - 8;
+			sequence.extend(
+				row.chunks_exact(2)
+					.map(|sample| (u16::from_le_bytes([sample[0], sample[1]]) >> shift) as u8),
+			);
 		}
+	}
 
-		let rgb_img = image::RgbImage::from_raw(img.width(), img.height(), sequence)
-			.ok_or(HeifError::RgbImageConversion)?;
-
-		Ok(DynamicImage::ImageRgb8(rgb_img))
+	if has_alpha {
+		image::RgbaImage::from_raw(img.width(), img.height(), sequence)
+			.map(DynamicImage::ImageRgba8)
+			.ok_or(HeifError::RgbImageConversion)
 	} else {
-		Err(HeifError::Unsupported)
+		image::RgbImage::from_raw(img.width(), img.height(), sequence)
+			.map(DynamicImage::ImageRgb8)
+			.ok_or(HeifError::RgbImageConversion)
 	}
 }
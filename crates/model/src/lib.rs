@@ -0,0 +1,85 @@
+use std::{fs, path::Path};
+
+use image::DynamicImage;
+use thiserror::Error;
+
+mod mesh;
+mod render;
+
+pub use render::TurntableCamera;
+
+type ModelResult<T> = Result<T, ModelError>;
+
+/// The maximum file size that a model can be in order to have a preview generated.
+///
+/// This value is in MiB.
+const MODEL_MAXIMUM_FILE_SIZE: u64 = 1048576 * 200;
+
+/// Extensions of the model formats that can be rendered.
+pub const MODEL_EXTENSIONS: [&str; 4] = ["gltf", "glb", "obj", "stl"];
+
+#[derive(Error, Debug)]
+pub enum ModelError {
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("error with gltf: {0}")]
+	Gltf(#[from] gltf::Error),
+	#[error("error with obj: {0}")]
+	Obj(#[from] tobj::LoadError),
+	#[error("the model provided is too large (over 200MiB)")]
+	TooLarge,
+	#[error("the model has no triangles to render")]
+	Empty,
+	#[error("the model references a vertex that doesn't exist")]
+	InvalidIndex,
+	#[error("unsupported model format: {0}")]
+	UnsupportedFormat(String),
+}
+
+/// Checks if the provided extension is a model format that can be rendered.
+pub fn is_model_extension(extension: &str) -> bool {
+	MODEL_EXTENSIONS
+		.iter()
+		.any(|ext| extension.eq_ignore_ascii_case(ext))
+}
+
+/// Renders a `target_size` square preview of a 3D model, seen from the default turntable angle.
+pub fn model_to_dynamic_image(path: &Path, target_size: u32) -> ModelResult<DynamicImage> {
+	let mut frames = model_to_turntable_frames(path, target_size, &[TurntableCamera::default()])?;
+
+	Ok(frames.remove(0))
+}
+
+/// Renders a `target_size` square preview of a 3D model for each camera, loading the model only
+/// once. Used to build turntables, rotating the camera around the model.
+pub fn model_to_turntable_frames(
+	path: &Path,
+	target_size: u32,
+	cameras: &[TurntableCamera],
+) -> ModelResult<Vec<DynamicImage>> {
+	if fs::metadata(path)?.len() > MODEL_MAXIMUM_FILE_SIZE {
+		return Err(ModelError::TooLarge);
+	}
+
+	let extension = path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.unwrap_or_default()
+		.to_ascii_lowercase();
+
+	let triangles = match extension.as_str() {
+		"gltf" | "glb" => mesh::load_gltf(path)?,
+		"obj" => mesh::load_obj(path)?,
+		"stl" => mesh::load_stl(path)?,
+		_ => return Err(ModelError::UnsupportedFormat(extension)),
+	};
+
+	cameras
+		.iter()
+		.map(|camera| {
+			render::render(&triangles, target_size, *camera)
+				.map(DynamicImage::ImageRgba8)
+				.ok_or(ModelError::Empty)
+		})
+		.collect()
+}
@@ -0,0 +1,189 @@
+use crate::{
+	render::{Triangle, Vec3},
+	ModelError, ModelResult,
+};
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use gltf::{mesh::Mode, Gltf};
+
+/// Column major 4x4 matrix, as used by glTF
+type Matrix = [[f32; 4]; 4];
+
+const IDENTITY: Matrix = [
+	[1.0, 0.0, 0.0, 0.0],
+	[0.0, 1.0, 0.0, 0.0],
+	[0.0, 0.0, 1.0, 0.0],
+	[0.0, 0.0, 0.0, 1.0],
+];
+
+pub(crate) fn load_stl(path: &Path) -> ModelResult<Vec<Triangle>> {
+	let mesh = stl_io::read_stl(&mut BufReader::new(File::open(path)?))?;
+
+	let vertices = mesh
+		.vertices
+		.iter()
+		.map(|vertex| Vec3::new(vertex[0], vertex[1], vertex[2]))
+		.collect::<Vec<_>>();
+
+	mesh.faces
+		.iter()
+		.map(|face| {
+			let [a, b, c] = face.vertices;
+			Ok([
+				*vertices.get(a).ok_or(ModelError::InvalidIndex)?,
+				*vertices.get(b).ok_or(ModelError::InvalidIndex)?,
+				*vertices.get(c).ok_or(ModelError::InvalidIndex)?,
+			])
+		})
+		.collect()
+}
+
+pub(crate) fn load_obj(path: &Path) -> ModelResult<Vec<Triangle>> {
+	let (models, _materials) = tobj::load_obj(
+		path,
+		&tobj::LoadOptions {
+			triangulate: true,
+			single_index: true,
+			// Loose points and lines have no surface to render
+			ignore_points: true,
+			ignore_lines: true,
+		},
+	)?;
+
+	let mut triangles = vec![];
+	for model in models {
+		let vertex = |index: u32| {
+			let index = index as usize * 3;
+			model
+				.mesh
+				.positions
+				.get(index..index + 3)
+				.map(|position| Vec3::new(position[0], position[1], position[2]))
+				.ok_or(ModelError::InvalidIndex)
+		};
+
+		for face in model.mesh.indices.chunks_exact(3) {
+			triangles.push([vertex(face[0])?, vertex(face[1])?, vertex(face[2])?]);
+		}
+	}
+
+	Ok(triangles)
+}
+
+/// Loads the triangles of the default scene of a glTF (or binary glTF) file, with the transforms
+/// of their nodes applied. Textures are never loaded as the preview doesn't use them.
+pub(crate) fn load_gltf(path: &Path) -> ModelResult<Vec<Triangle>> {
+	let Gltf { document, blob } = Gltf::open(path)?;
+	let buffers = gltf::import_buffers(&document, path.parent(), blob)?;
+
+	let scene = document
+		.default_scene()
+		.or_else(|| document.scenes().next())
+		.ok_or(ModelError::Empty)?;
+
+	let mut triangles = vec![];
+	for node in scene.nodes() {
+		collect_node_triangles(&node, IDENTITY, &buffers, &mut triangles)?;
+	}
+
+	Ok(triangles)
+}
+
+fn collect_node_triangles(
+	node: &gltf::Node<'_>,
+	parent_transform: Matrix,
+	buffers: &[gltf::buffer::Data],
+	triangles: &mut Vec<Triangle>,
+) -> ModelResult<()> {
+	let transform = multiply(&parent_transform, &node.transform().matrix());
+
+	if let Some(mesh) = node.mesh() {
+		for primitive in mesh.primitives() {
+			if primitive.mode() != Mode::Triangles {
+				continue;
+			}
+
+			let reader = primitive
+				.reader(|buffer| buffers.get(buffer.index()).map(|data| data.0.as_slice()));
+			let Some(positions) = reader.read_positions() else {
+				continue;
+			};
+			let positions = positions
+				.map(|[x, y, z]| transform_point(&transform, Vec3::new(x, y, z)))
+				.collect::<Vec<_>>();
+
+			let indices = match reader.read_indices() {
+				Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+				// Non indexed primitives have their vertices in order
+				None => (0..positions.len() as u32).collect(),
+			};
+
+			for face in indices.chunks_exact(3) {
+				let vertex = |index: u32| {
+					positions
+						.get(index as usize)
+						.copied()
+						.ok_or(ModelError::InvalidIndex)
+				};
+				triangles.push([vertex(face[0])?, vertex(face[1])?, vertex(face[2])?]);
+			}
+		}
+	}
+
+	for child in node.children() {
+		collect_node_triangles(&child, transform, buffers, triangles)?;
+	}
+
+	Ok(())
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+	let mut result = [[0.0; 4]; 4];
+	for (column, result_column) in result.iter_mut().enumerate() {
+		for (row, value) in result_column.iter_mut().enumerate() {
+			*value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+		}
+	}
+	result
+}
+
+fn transform_point(matrix: &Matrix, point: Vec3) -> Vec3 {
+	let [x, y, z] = [0, 1, 2].map(|row| {
+		matrix[0][row] * point.x
+			+ matrix[1][row] * point.y
+			+ matrix[2][row] * point.z
+			+ matrix[3][row]
+	});
+	Vec3::new(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn translation(x: f32, y: f32, z: f32) -> Matrix {
+		let mut matrix = IDENTITY;
+		matrix[3] = [x, y, z, 1.0];
+		matrix
+	}
+
+	#[test]
+	fn transforms_compose() {
+		let scale = [
+			[2.0, 0.0, 0.0, 0.0],
+			[0.0, 2.0, 0.0, 0.0],
+			[0.0, 0.0, 2.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		];
+
+		// The child is scaled first, then translated by the parent
+		let transform = multiply(&translation(1.0, 0.0, 0.0), &scale);
+		assert_eq!(
+			transform_point(&transform, Vec3::new(1.0, 1.0, 1.0)),
+			Vec3::new(3.0, 2.0, 2.0)
+		);
+
+		assert_eq!(multiply(&IDENTITY, &scale), scale);
+	}
+}
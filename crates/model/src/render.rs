@@ -0,0 +1,345 @@
+use std::ops::{Add, Mul, Sub};
+
+use image::RgbaImage;
+
+/// The model is rendered at a higher resolution and scaled down, smoothing out the edges
+const SUPERSAMPLING: u32 = 2;
+
+/// How much of the image the model's bounding sphere takes, leaving some padding around it
+const FILL_RATIO: f32 = 0.9;
+
+const BASE_COLOR: [f32; 3] = [200.0, 205.0, 215.0];
+const AMBIENT_LIGHT: f32 = 0.25;
+const KEY_LIGHT: f32 = 0.55;
+const HEAD_LIGHT: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Vec3 {
+	pub x: f32,
+	pub y: f32,
+	pub z: f32,
+}
+
+impl Vec3 {
+	pub const fn new(x: f32, y: f32, z: f32) -> Self {
+		Self { x, y, z }
+	}
+
+	fn dot(self, other: Self) -> f32 {
+		self.x * other.x + self.y * other.y + self.z * other.z
+	}
+
+	fn cross(self, other: Self) -> Self {
+		Self::new(
+			self.y * other.z - self.z * other.y,
+			self.z * other.x - self.x * other.z,
+			self.x * other.y - self.y * other.x,
+		)
+	}
+
+	fn length(self) -> f32 {
+		self.dot(self).sqrt()
+	}
+
+	fn normalize(self) -> Option<Self> {
+		let length = self.length();
+		(length > f32::EPSILON).then(|| self * (1.0 / length))
+	}
+
+	fn min(self, other: Self) -> Self {
+		Self::new(
+			self.x.min(other.x),
+			self.y.min(other.y),
+			self.z.min(other.z),
+		)
+	}
+
+	fn max(self, other: Self) -> Self {
+		Self::new(
+			self.x.max(other.x),
+			self.y.max(other.y),
+			self.z.max(other.z),
+		)
+	}
+
+	fn is_finite(self) -> bool {
+		self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+	}
+}
+
+impl Add for Vec3 {
+	type Output = Self;
+
+	fn add(self, other: Self) -> Self {
+		Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+	}
+}
+
+impl Sub for Vec3 {
+	type Output = Self;
+
+	fn sub(self, other: Self) -> Self {
+		Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+	}
+}
+
+impl Mul<f32> for Vec3 {
+	type Output = Self;
+
+	fn mul(self, factor: f32) -> Self {
+		Self::new(self.x * factor, self.y * factor, self.z * factor)
+	}
+}
+
+pub(crate) type Triangle = [Vec3; 3];
+
+/// Camera orbiting around the model, like a turntable. The camera always looks at the center of
+/// the model's bounding sphere, so every angle of a turntable has the same framing.
+#[derive(Debug, Clone, Copy)]
+pub struct TurntableCamera {
+	/// Rotation around the vertical axis, in degrees
+	pub yaw: f32,
+	/// Elevation of the camera, in degrees
+	pub pitch: f32,
+}
+
+impl Default for TurntableCamera {
+	/// Three-quarter view slightly from above, showing the front, a side and the top of the model
+	fn default() -> Self {
+		Self {
+			yaw: 35.0,
+			pitch: 25.0,
+		}
+	}
+}
+
+impl TurntableCamera {
+	fn to_view(self, point: Vec3) -> Vec3 {
+		let (yaw_sin, yaw_cos) = self.yaw.to_radians().sin_cos();
+		let (pitch_sin, pitch_cos) = self.pitch.to_radians().sin_cos();
+
+		let x = point.x * yaw_cos - point.z * yaw_sin;
+		let z = point.x * yaw_sin + point.z * yaw_cos;
+
+		Vec3::new(
+			x,
+			point.y * pitch_cos - z * pitch_sin,
+			point.y * pitch_sin + z * pitch_cos,
+		)
+	}
+}
+
+/// Renders the triangles with an orthographic projection on a transparent background, returning
+/// `None` if there is nothing to render.
+pub(crate) fn render(
+	triangles: &[Triangle],
+	size: u32,
+	camera: TurntableCamera,
+) -> Option<RgbaImage> {
+	let (center, radius) = bounding_sphere(triangles)?;
+
+	let render_size = size * SUPERSAMPLING;
+	let half_size = render_size as f32 / 2.0;
+	let scale = half_size * FILL_RATIO / radius;
+	let light = Vec3::new(-0.4, 0.6, 0.7)
+		.normalize()
+		.expect("light direction isn't zero");
+
+	let mut depth = vec![f32::NEG_INFINITY; (render_size * render_size) as usize];
+	let mut colors = vec![[0u8; 3]; (render_size * render_size) as usize];
+
+	for triangle in triangles {
+		let view = triangle.map(|vertex| camera.to_view(vertex - center));
+
+		let Some(normal) = (view[1] - view[0]).cross(view[2] - view[0]).normalize() else {
+			continue;
+		};
+
+		// Meshes in the wild often have inconsistent winding, so both sides are lit the same
+		let intensity =
+			AMBIENT_LIGHT + KEY_LIGHT * normal.dot(light).abs() + HEAD_LIGHT * normal.z.abs();
+		let color = BASE_COLOR.map(|channel| (channel * intensity).min(255.0) as u8);
+
+		// View space y points up while image rows go down
+		let screen = view.map(|vertex| {
+			Vec3::new(
+				half_size + vertex.x * scale,
+				half_size - vertex.y * scale,
+				vertex.z,
+			)
+		});
+
+		rasterize(&screen, render_size, |index, z| {
+			if z > depth[index] {
+				depth[index] = z;
+				colors[index] = color;
+			}
+		});
+	}
+
+	Some(downsample(&depth, &colors, size))
+}
+
+fn bounding_sphere(triangles: &[Triangle]) -> Option<(Vec3, f32)> {
+	let mut vertices = triangles
+		.iter()
+		.flatten()
+		.copied()
+		.filter(|vertex| vertex.is_finite());
+
+	let first = vertices.next()?;
+	let (min, max) = vertices.clone().fold((first, first), |(min, max), vertex| {
+		(min.min(vertex), max.max(vertex))
+	});
+
+	let center = (min + max) * 0.5;
+	let radius = std::iter::once(first)
+		.chain(vertices)
+		.map(|vertex| (vertex - center).length())
+		.fold(0.0, f32::max);
+
+	(radius > f32::EPSILON).then_some((center, radius))
+}
+
+/// Calls `plot` with the pixel index and the interpolated depth of every pixel covered by the
+/// triangle, already in screen coordinates
+fn rasterize(triangle: &Triangle, size: u32, mut plot: impl FnMut(usize, f32)) {
+	let [a, b, c] = *triangle;
+
+	let area = edge(a, b, c);
+	if area.abs() <= f32::EPSILON || !area.is_finite() {
+		return;
+	}
+
+	let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+	let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+	let max_x = (a.x.max(b.x).max(c.x).ceil().max(0.0) as u32).min(size);
+	let max_y = (a.y.max(b.y).max(c.y).ceil().max(0.0) as u32).min(size);
+
+	for y in min_y..max_y {
+		for x in min_x..max_x {
+			let point = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+
+			// Dividing by the signed area makes the weights positive for both windings
+			let w0 = edge(b, c, point) / area;
+			let w1 = edge(c, a, point) / area;
+			let w2 = edge(a, b, point) / area;
+
+			if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
+				plot((y * size + x) as usize, w0 * a.z + w1 * b.z + w2 * c.z);
+			}
+		}
+	}
+}
+
+fn edge(a: Vec3, b: Vec3, point: Vec3) -> f32 {
+	(b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+/// Averages each block of supersampled pixels, the coverage of the block becomes the alpha
+fn downsample(depth: &[f32], colors: &[[u8; 3]], size: u32) -> RgbaImage {
+	let render_size = size * SUPERSAMPLING;
+	let samples = SUPERSAMPLING * SUPERSAMPLING;
+
+	RgbaImage::from_fn(size, size, |x, y| {
+		let mut sum = [0u32; 3];
+		let mut covered = 0;
+
+		for sample_y in 0..SUPERSAMPLING {
+			for sample_x in 0..SUPERSAMPLING {
+				let index = ((y * SUPERSAMPLING + sample_y) * render_size
+					+ x * SUPERSAMPLING
+					+ sample_x) as usize;
+
+				if depth[index].is_finite() {
+					covered += 1;
+					for (sum, channel) in sum.iter_mut().zip(colors[index]) {
+						*sum += channel as u32;
+					}
+				}
+			}
+		}
+
+		if covered == 0 {
+			return image::Rgba([0, 0, 0, 0]);
+		}
+
+		image::Rgba([
+			(sum[0] / covered) as u8,
+			(sum[1] / covered) as u8,
+			(sum[2] / covered) as u8,
+			(covered * 255 / samples) as u8,
+		])
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn square(z: f32) -> [Triangle; 2] {
+		let a = Vec3::new(-1.0, -1.0, z);
+		let b = Vec3::new(1.0, -1.0, z);
+		let c = Vec3::new(1.0, 1.0, z);
+		let d = Vec3::new(-1.0, 1.0, z);
+		[[a, b, c], [a, c, d]]
+	}
+
+	#[test]
+	fn renders_centered_on_transparent_background() {
+		let camera = TurntableCamera {
+			yaw: 0.0,
+			pitch: 0.0,
+		};
+		let img = render(&square(0.0), 64, camera).unwrap();
+
+		assert_eq!(img.dimensions(), (64, 64));
+		assert_eq!(img.get_pixel(32, 32)[3], 255);
+		assert_eq!(img.get_pixel(0, 0)[3], 0);
+		assert_eq!(img.get_pixel(63, 63)[3], 0);
+	}
+
+	#[test]
+	fn both_windings_are_rendered() {
+		let camera = TurntableCamera {
+			yaw: 0.0,
+			pitch: 0.0,
+		};
+		let flipped = square(0.0).map(|[a, b, c]| [a, c, b]);
+
+		let img = render(&flipped, 32, camera).unwrap();
+		assert_eq!(img.get_pixel(16, 16)[3], 255);
+	}
+
+	#[test]
+	fn closest_triangle_wins() {
+		let mut triangles = square(-1.0).to_vec();
+		// Smaller triangle closer to the camera, the color will be the same but the depth test
+		// must keep the front one regardless of the drawing order
+		triangles.insert(
+			0,
+			[
+				Vec3::new(-0.5, -0.5, 1.0),
+				Vec3::new(0.5, -0.5, 1.0),
+				Vec3::new(0.0, 0.5, 1.0),
+			],
+		);
+
+		let mut depth = vec![f32::NEG_INFINITY; 16];
+		for triangle in &triangles {
+			let screen = triangle.map(|v| Vec3::new(2.0 + v.x * 2.0, 2.0 - v.y * 2.0, v.z));
+			rasterize(&screen, 4, |index, z| depth[index] = depth[index].max(z));
+		}
+
+		assert_eq!(depth[2 * 4 + 2], 1.0);
+		assert_eq!(depth[0], -1.0);
+	}
+
+	#[test]
+	fn empty_or_degenerate_models_are_not_rendered() {
+		assert!(render(&[], 32, TurntableCamera::default()).is_none());
+
+		let point = Vec3::new(1.0, 1.0, 1.0);
+		assert!(render(&[[point, point, point]], 32, TurntableCamera::default()).is_none());
+	}
+}
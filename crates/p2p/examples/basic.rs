@@ -48,7 +48,7 @@ async fn main() {
 		name: "TODO".to_string(),
 	});
 
-	let (manager, mut stream) = Manager::new("p2p-demo", &keypair, metadata_manager)
+	let (manager, mut stream) = Manager::new("p2p-demo", &keypair, metadata_manager, None)
 		.await
 		.unwrap();
 
@@ -0,0 +1,86 @@
+//! A relay that Spacedrive nodes which aren't on the same network can use to reach each other.
+//! Run it on a server with a public IP address and set the address it prints as the relay of the nodes.
+//!
+//! `cargo run -p sd-p2p --example relay -- <port>`
+
+use std::env;
+
+use libp2p::{
+	core::muxing::StreamMuxerBox,
+	futures::StreamExt,
+	identify,
+	multiaddr::Protocol,
+	relay,
+	swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
+	Multiaddr, Transport,
+};
+use sd_p2p::Keypair;
+use tracing::info;
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+	relay: relay::Behaviour,
+	identify: identify::Behaviour,
+}
+
+#[tokio::main]
+async fn main() {
+	tracing_subscriber::fmt()
+		.with_env_filter(
+			tracing_subscriber::EnvFilter::from_default_env()
+				.add_directive("relay=trace".parse().unwrap())
+				.add_directive("info".parse().unwrap()),
+		)
+		.try_init()
+		.unwrap();
+
+	let port = env::args()
+		.nth(1)
+		.map(|port| port.parse::<u16>().expect("The port must be a number!"))
+		.unwrap_or(4001);
+
+	// TODO: Load the keypair from disk so the relay keeps the same address between restarts
+	let keypair = Keypair::generate();
+	let peer_id = keypair.raw_peer_id();
+
+	let transport = libp2p_quic::GenTransport::<libp2p_quic::tokio::Provider>::new(
+		libp2p_quic::Config::new(&keypair.inner()),
+	)
+	.map(|(p, c), _| (p, StreamMuxerBox::new(c)))
+	.boxed();
+
+	let mut swarm = SwarmBuilder::with_tokio_executor(
+		transport,
+		Behaviour {
+			relay: relay::Behaviour::new(peer_id, Default::default()),
+			identify: identify::Behaviour::new(identify::Config::new(
+				"/spacedrive/identify/1.0.0".into(),
+				keypair.inner().public(),
+			)),
+		},
+		peer_id,
+	)
+	.build();
+
+	swarm
+		.listen_on(
+			Multiaddr::empty()
+				.with(Protocol::Ip4([0, 0, 0, 0].into()))
+				.with(Protocol::Udp(port))
+				.with(Protocol::QuicV1),
+		)
+		.unwrap();
+
+	loop {
+		match swarm.select_next_some().await {
+			SwarmEvent::NewListenAddr { address, .. } => {
+				info!(
+					"Relay listening at '{}'",
+					address.with(Protocol::P2p(peer_id.into()))
+				);
+			}
+			SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => info!("{:?}", event),
+			_ => {}
+		}
+	}
+}
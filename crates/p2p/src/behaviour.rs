@@ -0,0 +1,17 @@
+use libp2p::{dcutr, identify, relay, swarm::NetworkBehaviour};
+
+use crate::{spacetime::SpaceTime, Metadata};
+
+/// The [`NetworkBehaviour`] of the [`Manager`](crate::Manager)'s swarm.
+///
+/// Peers that aren't on the same network are connected through a relay, the connection is still
+/// end-to-end encrypted as the relay only forwards the bytes of the Noise session between them.
+/// DCUtR then tries to upgrade it to a direct connection by hole punching.
+#[derive(NetworkBehaviour)]
+pub(crate) struct Behaviour<TMetadata: Metadata> {
+	pub space_time: SpaceTime<TMetadata>,
+	pub relay: relay::client::Behaviour,
+	pub dcutr: dcutr::Behaviour,
+	// Required by the relay to know our addresses and by DCUtR to learn our public address
+	pub identify: identify::Behaviour,
+}
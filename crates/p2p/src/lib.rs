@@ -1,5 +1,6 @@
 //! Rust Peer to Peer Networking Library
 
+mod behaviour;
 mod event;
 mod manager;
 mod manager_stream;
@@ -7,12 +7,21 @@ use std::{
 	},
 };
 
-use libp2p::{core::muxing::StreamMuxerBox, swarm::SwarmBuilder, Transport};
+use libp2p::{
+	core::{muxing::StreamMuxerBox, upgrade},
+	dcutr,
+	futures::future::Either,
+	identify, noise, relay,
+	swarm::SwarmBuilder,
+	yamux, Multiaddr, Transport,
+};
 use thiserror::Error;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, warn};
 
 use crate::{
+	behaviour::Behaviour,
+	is_valid_relay, socketaddr_to_quic_multiaddr,
 	spacetime::{SpaceTime, UnicastStream},
 	DiscoveredPeer, Keypair, ManagerStream, ManagerStreamAction, Mdns, MdnsState, Metadata,
 	MetadataManager, PeerId,
@@ -30,10 +39,12 @@ pub struct Manager<TMetadata: Metadata> {
 
 impl<TMetadata: Metadata> Manager<TMetadata> {
 	/// create a new P2P manager. Please do your best to make the callback closures as fast as possible because they will slow the P2P event loop!
+	/// A random free port is used when `port` is `None`, a fixed one lets peers that can't discover this node add it by its address.
 	pub async fn new(
 		application_name: &'static str,
 		keypair: &Keypair,
 		metadata_manager: Arc<MetadataManager<TMetadata>>,
+		port: Option<u16>,
 	) -> Result<(Arc<Self>, ManagerStream<TMetadata>), ManagerError> {
 		application_name
 			.chars()
@@ -60,26 +71,55 @@ impl<TMetadata: Metadata> Manager<TMetadata> {
 			event_stream_tx,
 		});
 
-		let mut swarm = SwarmBuilder::with_tokio_executor(
-			libp2p_quic::GenTransport::<libp2p_quic::tokio::Provider>::new(
-				libp2p_quic::Config::new(&keypair.inner()),
+		// Connections through a relay aren't QUIC connections so they are secured and multiplexed on top
+		let (relay_transport, relay) = relay::client::new(keypair.raw_peer_id());
+		let transport = relay_transport
+			.upgrade(upgrade::Version::V1)
+			.authenticate(noise::Config::new(&keypair.inner()).expect(
+				"Error creating the Noise config. This should be impossible with a valid keypair.",
+			))
+			.multiplex(yamux::YamuxConfig::default())
+			.or_transport(
+				libp2p_quic::GenTransport::<libp2p_quic::tokio::Provider>::new(
+					libp2p_quic::Config::new(&keypair.inner()),
+				),
 			)
-			.map(|(p, c), _| (p, StreamMuxerBox::new(c)))
-			.boxed(),
-			SpaceTime::new(this.clone()),
+			.map(|either, _| match either {
+				Either::Left((p, m)) => (p, StreamMuxerBox::new(m)),
+				Either::Right((p, c)) => (p, StreamMuxerBox::new(c)),
+			})
+			.boxed();
+
+		let mut swarm = SwarmBuilder::with_tokio_executor(
+			transport,
+			Behaviour {
+				space_time: SpaceTime::new(this.clone()),
+				relay,
+				dcutr: dcutr::Behaviour::new(keypair.raw_peer_id()),
+				identify: identify::Behaviour::new(identify::Config::new(
+					"/spacedrive/identify/1.0.0".into(),
+					keypair.inner().public(),
+				)),
+			},
 			keypair.raw_peer_id(),
 		)
 		.build();
 		{
 			let listener_id = swarm
-            .listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse().expect("Error passing libp2p multiaddr. This value is hardcoded so this should be impossible."))
-            .unwrap();
+				.listen_on(socketaddr_to_quic_multiaddr(&SocketAddr::from((
+					[0u8; 4],
+					port.unwrap_or(0),
+				))))
+				.unwrap();
 			debug!("created ipv4 listener with id '{:?}'", listener_id);
 		}
 		{
 			let listener_id = swarm
-        .listen_on("/ip6/::/udp/0/quic-v1".parse().expect("Error passing libp2p multiaddr. This value is hardcoded so this should be impossible."))
-        .unwrap();
+				.listen_on(socketaddr_to_quic_multiaddr(&SocketAddr::from((
+					[0u16; 8],
+					port.unwrap_or(0),
+				))))
+				.unwrap();
 			debug!("created ipv4 listener with id '{:?}'", listener_id);
 		}
 
@@ -93,6 +133,9 @@ impl<TMetadata: Metadata> Manager<TMetadata> {
 				queued_events: Default::default(),
 				shutdown: AtomicBool::new(false),
 				on_establish_streams: HashMap::new(),
+				relay: None,
+				pending_dials: HashMap::new(),
+				known_addresses: HashMap::new(),
 			},
 		))
 	}
@@ -145,6 +188,34 @@ impl<TMetadata: Metadata> Manager<TMetadata> {
 		Ok(stream)
 	}
 
+	/// Connects to the peer at the address, for peers that can't be discovered on the local network.
+	/// Returns the id of the peer once connected, streams to it can then be opened as usual.
+	pub async fn dial_address(&self, addr: SocketAddr) -> Result<PeerId, DialAddressError> {
+		let (tx, rx) = oneshot::channel();
+		self.emit(ManagerStreamAction::DialAddress(addr, tx)).await;
+		rx.await
+			.map_err(|_| DialAddressError::Dropped)?
+			.map_err(DialAddressError::Connection)
+	}
+
+	/// Sets the relay used to reach the peers that aren't on the local network, and that they use to
+	/// reach us. The address must end with the peer id of the relay, eg.
+	/// `/ip4/1.2.3.4/udp/4001/quic-v1/p2p/12D3KooW...`. `None` stops using a relay.
+	pub async fn set_relay(&self, relay: Option<&str>) -> Result<(), ManagerError> {
+		let relay = relay
+			.map(|relay| {
+				relay
+					.parse::<Multiaddr>()
+					.ok()
+					.filter(is_valid_relay)
+					.ok_or_else(|| ManagerError::InvalidRelay(relay.to_string()))
+			})
+			.transpose()?;
+
+		self.emit(ManagerStreamAction::SetRelay(relay)).await;
+		Ok(())
+	}
+
 	pub async fn broadcast(&self, data: Vec<u8>) {
 		self.emit(ManagerStreamAction::BroadcastData(data)).await;
 	}
@@ -161,6 +232,14 @@ impl<TMetadata: Metadata> Manager<TMetadata> {
 	}
 }
 
+#[derive(Error, Debug)]
+pub enum DialAddressError {
+	#[error("the dial was dropped before the connection was established")]
+	Dropped,
+	#[error("error establishing connection: {0}")]
+	Connection(String),
+}
+
 #[derive(Error, Debug)]
 pub enum ManagerError {
 	#[error(
@@ -169,4 +248,6 @@ pub enum ManagerError {
 	InvalidAppName,
 	#[error("error with mdns discovery: {0}")]
 	Mdns(#[from] mdns_sd::Error),
+	#[error("invalid relay address '{0}'. It must end with the peer id of the relay")]
+	InvalidRelay(String),
 }
@@ -9,19 +9,22 @@ use std::{
 };
 
 use libp2p::{
+	core::{transport::ListenerId, ConnectedPoint},
 	futures::StreamExt,
+	multiaddr::Protocol,
 	swarm::{
 		dial_opts::{DialOpts, PeerCondition},
-		NotifyHandler, SwarmEvent, ToSwarm,
+		DialError, NotifyHandler, SwarmEvent, ToSwarm,
 	},
-	Swarm,
+	Multiaddr, Swarm,
 };
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-	quic_multiaddr_to_socketaddr, socketaddr_to_quic_multiaddr,
-	spacetime::{OutboundRequest, SpaceTime, UnicastStream},
+	behaviour::{Behaviour, BehaviourEvent},
+	is_relayed, quic_multiaddr_to_socketaddr, relayed_multiaddr, socketaddr_to_quic_multiaddr,
+	spacetime::{OutboundRequest, UnicastStream},
 	Event, Manager, Mdns, Metadata, PeerId,
 };
 
@@ -36,10 +39,14 @@ pub enum ManagerStreamAction<TMetadata: Metadata> {
 		peer_id: PeerId,
 		addresses: Vec<SocketAddr>,
 	},
+	/// Establish a connection to whichever peer is at the address, responding with its id once connected.
+	DialAddress(SocketAddr, oneshot::Sender<Result<PeerId, String>>),
 	/// TODO
 	StartStream(PeerId, oneshot::Sender<UnicastStream>),
 	/// TODO
 	BroadcastData(Vec<u8>),
+	/// Set the relay used to reach peers that aren't on the local network, `None` stops using it.
+	SetRelay(Option<Multiaddr>),
 	/// the node is shutting down. The `ManagerStream` should convert this into `Event::Shutdown`
 	Shutdown(oneshot::Sender<()>),
 }
@@ -60,11 +67,17 @@ impl<TMetadata: Metadata> From<Event<TMetadata>> for ManagerStreamAction<TMetada
 pub struct ManagerStream<TMetadata: Metadata> {
 	pub(crate) manager: Arc<Manager<TMetadata>>,
 	pub(crate) event_stream_rx: mpsc::Receiver<ManagerStreamAction<TMetadata>>,
-	pub(crate) swarm: Swarm<SpaceTime<TMetadata>>,
+	pub(crate) swarm: Swarm<Behaviour<TMetadata>>,
 	pub(crate) mdns: Mdns<TMetadata>,
 	pub(crate) queued_events: VecDeque<Event<TMetadata>>,
 	pub(crate) shutdown: AtomicBool,
 	pub(crate) on_establish_streams: HashMap<libp2p::PeerId, Vec<OutboundRequest>>,
+	/// The relay in use and the listener for the connections coming through it
+	pub(crate) relay: Option<(Multiaddr, ListenerId)>,
+	/// Dials to an address that are waiting for the connection to be established
+	pub(crate) pending_dials: HashMap<SocketAddr, oneshot::Sender<Result<PeerId, String>>>,
+	/// Addresses of the peers that were dialed by address, as they may not be discovered
+	pub(crate) known_addresses: HashMap<libp2p::PeerId, SocketAddr>,
 }
 
 impl<TMetadata> ManagerStream<TMetadata>
@@ -98,7 +111,10 @@ where
 				}
 				event = self.swarm.select_next_some() => {
 					match event {
-						SwarmEvent::Behaviour(event) => {
+						SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => debug!("relay event: {:?}", event),
+						SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => debug!("hole punching event: {:?}", event),
+						SwarmEvent::Behaviour(BehaviourEvent::Identify(_)) => {},
+						SwarmEvent::Behaviour(BehaviourEvent::SpaceTime(event)) => {
 							if let Some(event) = self.handle_manager_stream_action(event).await {
 								if let Event::Shutdown { .. } = event {
 									self.shutdown.store(true, Ordering::Relaxed);
@@ -107,11 +123,22 @@ where
 								return Some(event);
 							}
 						},
-						SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+						SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+							if let ConnectedPoint::Dialer { address, .. } = endpoint {
+								if let Some(tx) = quic_multiaddr_to_socketaddr(address).ok().and_then(|addr| {
+									let tx = self.pending_dials.remove(&addr)?;
+									self.known_addresses.insert(peer_id, addr);
+									Some(tx)
+								}) {
+									tx.send(Ok(PeerId(peer_id))).ok();
+								}
+							}
+
 							if let Some(streams) = self.on_establish_streams.remove(&peer_id) {
 								for event in streams {
 									self.swarm
 										.behaviour_mut()
+										.space_time
 										.pending_events
 										.push_back(ToSwarm::NotifyHandler {
 											peer_id,
@@ -124,7 +151,18 @@ where
 						SwarmEvent::ConnectionClosed { .. } => {},
 						SwarmEvent::IncomingConnection { local_addr, .. } => debug!("incoming connection from '{}'", local_addr),
 						SwarmEvent::IncomingConnectionError { local_addr, error, .. } => warn!("handshake error with incoming connection from '{}': {}", local_addr, error),
-						SwarmEvent::OutgoingConnectionError { peer_id, error } => warn!("error establishing connection with '{:?}': {}", peer_id, error),
+						SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+							if let DialError::Transport(errors) = &error {
+								for (address, err) in errors {
+									if let Some(tx) = quic_multiaddr_to_socketaddr(address.clone()).ok().and_then(|addr| self.pending_dials.remove(&addr)) {
+										tx.send(Err(err.to_string())).ok();
+									}
+								}
+							}
+
+							warn!("error establishing connection with '{:?}': {}", peer_id, error);
+						},
+						SwarmEvent::NewListenAddr { address, .. } if is_relayed(&address) => info!("reachable through the relay at '{}'", address),
 						SwarmEvent::NewListenAddr { address, .. } => {
 							match quic_multiaddr_to_socketaddr(address) {
 								Ok(addr) => {
@@ -138,6 +176,7 @@ where
 								}
 							}
 						},
+						SwarmEvent::ExpiredListenAddr { address, .. } if is_relayed(&address) => info!("no longer reachable through the relay at '{}'", address),
 						SwarmEvent::ExpiredListenAddr { address, .. } => {
 							match quic_multiaddr_to_socketaddr(address) {
 								Ok(addr) => {
@@ -153,7 +192,7 @@ where
 						}
 						SwarmEvent::ListenerClosed { listener_id, addresses, reason } => {
 							debug!("listener '{:?}' was closed due to: {:?}", listener_id, reason);
-							for address in addresses {
+							for address in addresses.into_iter().filter(|address| !is_relayed(address)) {
 								match quic_multiaddr_to_socketaddr(address) {
 									Ok(addr) => {
 										debug!("listen address added: {}", addr);
@@ -213,23 +252,58 @@ where
 					),
 				}
 			}
+			ManagerStreamAction::DialAddress(addr, tx) => {
+				match self.swarm.dial(
+					DialOpts::unknown_peer_id()
+						.address(socketaddr_to_quic_multiaddr(&addr))
+						.build(),
+				) {
+					Ok(()) => {
+						self.pending_dials.insert(addr, tx);
+					}
+					Err(err) => {
+						warn!("error dialing address '{}': {}", addr, err);
+						tx.send(Err(err.to_string())).ok();
+					}
+				}
+			}
 			ManagerStreamAction::StartStream(peer_id, rx) => {
 				if !self.swarm.connected_peers().any(|v| *v == peer_id.0) {
-					let addresses = self
-						.mdns
-						.state
-						.discovered
-						.read()
-						.await
-						.get(&peer_id)
-						.unwrap()
-						.addresses
-						.clone();
+					let discovered =
+						self.mdns
+							.state
+							.discovered
+							.read()
+							.await
+							.get(&peer_id)
+							.map(|peer| {
+								peer.addresses
+									.iter()
+									.map(socketaddr_to_quic_multiaddr)
+									.collect::<Vec<_>>()
+							});
+
+					let discovered = discovered.or_else(|| {
+						self.known_addresses
+							.get(&peer_id.0)
+							.map(|addr| vec![socketaddr_to_quic_multiaddr(addr)])
+					});
+
+					let addresses = match (discovered, &self.relay) {
+						(Some(addresses), _) => addresses,
+						// Peers that aren't on the local network are reached through the relay
+						(None, Some((relay, _))) => vec![relayed_multiaddr(relay, peer_id)],
+						(None, None) => {
+							// Dropping `rx` makes the stream fail to be established
+							warn!("unable to reach peer '{}' as it wasn't discovered and no relay is set", peer_id);
+							return None;
+						}
+					};
 
 					match self.swarm.dial(
 						DialOpts::peer_id(peer_id.0)
 							.condition(PeerCondition::Disconnected)
-							.addresses(addresses.iter().map(socketaddr_to_quic_multiaddr).collect())
+							.addresses(addresses.clone())
 							.build(),
 					) {
 						Ok(()) => {}
@@ -246,6 +320,7 @@ where
 				} else {
 					self.swarm
 						.behaviour_mut()
+						.space_time
 						.pending_events
 						.push_back(ToSwarm::NotifyHandler {
 							peer_id: peer_id.0,
@@ -256,7 +331,7 @@ where
 			}
 			ManagerStreamAction::BroadcastData(data) => {
 				let connected_peers = self.swarm.connected_peers().copied().collect::<Vec<_>>();
-				let behaviour = self.swarm.behaviour_mut();
+				let behaviour = &mut self.swarm.behaviour_mut().space_time;
 				debug!("Broadcasting message to '{:?}'", connected_peers);
 				for peer_id in connected_peers {
 					behaviour.pending_events.push_back(ToSwarm::NotifyHandler {
@@ -266,6 +341,7 @@ where
 					});
 				}
 			}
+			ManagerStreamAction::SetRelay(relay) => self.set_relay(relay),
 			ManagerStreamAction::Shutdown(tx) => {
 				info!("Shutting down P2P Manager...");
 				self.mdns.shutdown().await;
@@ -279,4 +355,25 @@ where
 
 		None
 	}
+
+	fn set_relay(&mut self, relay: Option<Multiaddr>) {
+		if let Some((relay, listener_id)) = self.relay.take() {
+			debug!("stopped using relay '{}'", relay);
+			self.swarm.remove_listener(listener_id);
+		}
+
+		if let Some(relay) = relay {
+			// Listening on the relay makes a reservation on it so peers can connect to us through it
+			match self
+				.swarm
+				.listen_on(relay.clone().with(Protocol::P2pCircuit))
+			{
+				Ok(listener_id) => {
+					info!("using relay '{}'", relay);
+					self.relay = Some((relay, listener_id));
+				}
+				Err(err) => warn!("error listening on relay '{}': {}", relay, err),
+			}
+		}
+	}
 }
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, io};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{SpaceblockError, CHECKSUM_SIZE};
+
+/// Chunks are never cut before this size, except at the end of a file
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks are always cut at this size, so a copy of a chunk fits in the receiver's buffer
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// A chunk is cut when the top 16 bits of the rolling hash are zero, about every 64 KiB after the
+/// minimum size
+const CUT_MASK: u64 = 0xFFFF_0000_0000_0000;
+/// The most chunks a receiver can send for the copy of a file it already has, about 64 GB of data
+pub const MAX_BASIS_CHUNKS: u32 = 1_000_000;
+
+/// Random values for each byte of the gear hash, generated at compile time so every node cuts the
+/// same content at the same places
+const GEAR: [u64; 256] = {
+	let mut table = [0u64; 256];
+	let mut state = 0x5370_6163_6564_7269u64; // "Spacedri"
+	let mut i = 0;
+	while i < 256 {
+		// splitmix64
+		state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+};
+
+/// Where a chunk ends in `data`, which must hold [`MAX_CHUNK_SIZE`] bytes unless it's the end of
+/// the file. The cut only depends on the content before it, so an insertion or deletion in a file
+/// only changes the chunks around it.
+fn cut_point(data: &[u8]) -> usize {
+	if data.len() <= MIN_CHUNK_SIZE {
+		return data.len();
+	}
+
+	let end = data.len().min(MAX_CHUNK_SIZE);
+	let mut hash = 0u64;
+	for (i, byte) in data[MIN_CHUNK_SIZE..end].iter().enumerate() {
+		hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+		if hash & CUT_MASK == 0 {
+			return MIN_CHUNK_SIZE + i + 1;
+		}
+	}
+
+	end
+}
+
+/// Splits what's read into content-defined chunks
+pub struct Chunker<R> {
+	reader: R,
+	buf: Vec<u8>,
+	eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Chunker<R> {
+	pub fn new(reader: R) -> Self {
+		Self {
+			reader,
+			buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+			eof: false,
+		}
+	}
+
+	pub async fn next(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+		while !self.eof && self.buf.len() < MAX_CHUNK_SIZE {
+			let start = self.buf.len();
+			self.buf.resize(MAX_CHUNK_SIZE, 0);
+			let read = self.reader.read(&mut self.buf[start..]).await?;
+			self.buf.truncate(start + read);
+			self.eof = read == 0;
+		}
+
+		if self.buf.is_empty() {
+			return Ok(None);
+		}
+
+		let len = cut_point(&self.buf);
+		Ok(Some(self.buf.drain(..len).collect()))
+	}
+}
+
+/// A chunk of the copy of a file the receiver already has, so the sender can tell it to copy the
+/// chunk instead of sending it again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkFingerprint {
+	pub size: u32,
+	pub checksum: [u8; CHECKSUM_SIZE],
+}
+
+impl ChunkFingerprint {
+	pub(super) async fn from_stream(
+		stream: &mut (impl AsyncRead + Unpin),
+	) -> Result<Self, SpaceblockError> {
+		let size = stream.read_u32_le().await?;
+		if size == 0 || size as usize > MAX_CHUNK_SIZE {
+			return Err(SpaceblockError::BlockTooLarge(size as u64));
+		}
+
+		let mut checksum = [0u8; CHECKSUM_SIZE];
+		stream.read_exact(&mut checksum).await?;
+
+		Ok(Self { size, checksum })
+	}
+
+	pub(super) fn to_bytes(&self, buf: &mut Vec<u8>) {
+		buf.extend_from_slice(&self.size.to_le_bytes());
+		buf.extend_from_slice(&self.checksum);
+	}
+}
+
+/// Fingerprints every chunk of a file
+pub async fn fingerprint(
+	reader: impl AsyncRead + Unpin,
+) -> Result<Vec<ChunkFingerprint>, io::Error> {
+	let mut chunker = Chunker::new(reader);
+	let mut chunks = Vec::new();
+
+	while let Some(chunk) = chunker.next().await? {
+		chunks.push(ChunkFingerprint {
+			size: chunk.len() as u32,
+			checksum: *blake3::hash(&chunk).as_bytes(),
+		});
+	}
+
+	Ok(chunks)
+}
+
+/// Where each chunk of the receiver's copy starts in it, by checksum
+pub(super) fn chunk_offsets(basis: &[ChunkFingerprint]) -> HashMap<[u8; CHECKSUM_SIZE], u64> {
+	let mut offsets = HashMap::with_capacity(basis.len());
+	let mut offset = 0;
+
+	for chunk in basis {
+		offsets.entry(chunk.checksum).or_insert(offset);
+		offset += chunk.size as u64;
+	}
+
+	offsets
+}
+
+/// Tells the receiver to copy a chunk from its copy of the file instead of sending it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct BlockCopy {
+	/// Where the chunk goes in the file being received
+	pub offset: u64,
+	/// Where the chunk is in the receiver's copy
+	pub source: u64,
+	pub size: u32,
+	pub checksum: [u8; CHECKSUM_SIZE],
+}
+
+impl BlockCopy {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		buf.extend_from_slice(&self.offset.to_le_bytes());
+		buf.extend_from_slice(&self.source.to_le_bytes());
+		buf.extend_from_slice(&self.size.to_le_bytes());
+		buf.extend_from_slice(&self.checksum);
+		buf
+	}
+
+	pub async fn from_stream(
+		stream: &mut (impl AsyncRead + Unpin),
+	) -> Result<Self, SpaceblockError> {
+		let offset = stream.read_u64_le().await?;
+		let source = stream.read_u64_le().await?;
+		let size = stream.read_u32_le().await?;
+
+		let mut checksum = [0u8; CHECKSUM_SIZE];
+		stream.read_exact(&mut checksum).await?;
+
+		if size as usize > MAX_CHUNK_SIZE {
+			return Err(SpaceblockError::BlockTooLarge(size as u64));
+		}
+
+		Ok(Self {
+			offset,
+			source,
+			size,
+			checksum,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Deterministic data that doesn't repeat, so every chunk is different
+	fn data(len: usize, seed: u64) -> Vec<u8> {
+		let mut state = seed;
+		(0..len)
+			.map(|_| {
+				state ^= state << 13;
+				state ^= state >> 7;
+				state ^= state << 17;
+				state as u8
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn chunks_cover_the_file() {
+		let data = data(2 * 1024 * 1024, 1);
+		let chunks = fingerprint(&data[..]).await.unwrap();
+
+		assert_eq!(
+			chunks
+				.iter()
+				.map(|chunk| chunk.size as usize)
+				.sum::<usize>(),
+			data.len()
+		);
+		assert!(chunks
+			.iter()
+			.all(|chunk| chunk.size as usize <= MAX_CHUNK_SIZE));
+		assert!(chunks[..chunks.len() - 1]
+			.iter()
+			.all(|chunk| chunk.size as usize >= MIN_CHUNK_SIZE));
+	}
+
+	#[tokio::test]
+	async fn insertions_only_change_nearby_chunks() {
+		let old = data(4 * 1024 * 1024, 2);
+		let mut new = old.clone();
+		new.splice(1_000_000..1_000_000, data(5000, 3));
+
+		let old_chunks = chunk_offsets(&fingerprint(&old[..]).await.unwrap());
+		let changed = fingerprint(&new[..])
+			.await
+			.unwrap()
+			.into_iter()
+			.filter(|chunk| !old_chunks.contains_key(&chunk.checksum))
+			.count();
+
+		assert!(changed <= 2, "{changed} chunks changed");
+	}
+}
@@ -1,23 +1,64 @@
 //! Spaceblock is a file transfer protocol that uses a block based system to transfer files.
 //! This protocol is modelled after SyncThing's BEP protocol. A huge thanks to it's original authors!
 //! You can read more about it here: https://docs.syncthing.net/specs/bep-v1.html
+//!
+//! Every block is sent with its checksum so the receiver only keeps verified blocks. A transfer
+//! that was interrupted can be resumed by the receiver asking for each file from the end of the
+//! last block it verified, and every file is checked against the checksum of the whole file once
+//! it's complete.
+//!
+//! When the receiver already has a copy of a file, like an older version of it, it sends the
+//! fingerprints of the content-defined chunks of that copy. The sender then only sends the chunks
+//! of the file that aren't in it, and tells the receiver to copy the others from its copy.
+//!
+//! Otherwise the receiver can ask for the files to be sent as chunks over multiple streams at once,
+//! which is much faster when the latency between the peers is high.
 #![allow(unused)] // TODO: This module is still in heavy development!
 
 use std::{
+	io::SeekFrom,
 	marker::PhantomData,
 	path::{Path, PathBuf},
 	string::FromUtf8Error,
+	sync::Arc,
 };
 
 use thiserror::Error;
 use tokio::{
 	fs::File,
-	io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	io::{
+		AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+		BufReader,
+	},
 };
 use tracing::debug;
+use uuid::Uuid;
 
 use crate::spacetime::{SpaceTimeStream, UnicastStream};
 
+mod delta;
+mod parallel;
+mod rate_limiter;
+
+pub use delta::*;
+pub use rate_limiter::*;
+
+/// Sent before every block of a file sent as a delta, so the receiver knows what follows
+const DELTA_DATA: u8 = 0;
+const DELTA_COPY: u8 = 1;
+
+/// The size of a BLAKE3 checksum
+pub const CHECKSUM_SIZE: usize = 32;
+
+/// The most files a [`SpaceblockRequest`] can hold, their count is sent as a `u16`
+pub const MAX_FILES: usize = u16::MAX as usize;
+
+/// The most directories a [`SpaceblockRequest`] can hold, their count is sent as a `u16`
+pub const MAX_DIRECTORIES: usize = u16::MAX as usize;
+
+/// The longest name in bytes of a file or directory of a [`SpaceblockRequest`], sent as a `u16`
+pub const MAX_NAME_LEN: usize = u16::MAX as usize;
+
 /// TODO
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockSize(u32); // Max block size is gonna be 3.9GB which is stupidly overkill
@@ -41,17 +82,43 @@ impl BlockSize {
 	}
 }
 
-/// TODO
+/// A file being sent, with the checksum of its whole content
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SpaceblockRequest {
+pub struct SpaceblockFile {
+	/// The path of the file relative to what's being sent, with `/` separators
 	pub name: String,
 	pub size: u64,
+	/// Milliseconds since the unix epoch, `0` if unknown
+	pub modified: u64,
 	// TODO: Include file permissions
+	pub checksum: [u8; CHECKSUM_SIZE],
+}
+
+/// A directory being sent, so the receiver recreates it even if it's empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceblockDirectory {
+	/// The path of the directory relative to what's being sent, with `/` separators
+	pub name: String,
+	/// Milliseconds since the unix epoch, `0` if unknown
+	pub modified: u64,
+}
+
+/// TODO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceblockRequest {
+	/// Identifies the transfer across connections, so the receiver can tell it's resuming one
+	pub id: Uuid,
+	pub files: Vec<SpaceblockFile>,
+	pub directories: Vec<SpaceblockDirectory>,
 	pub block_size: BlockSize,
 }
 
 #[derive(Debug, Error)]
 pub enum SpacedropRequestError {
+	#[error("io error reading transfer id: {0}")]
+	IdIoError(std::io::Error),
+	#[error("io error reading file count: {0}")]
+	FileCountIoError(std::io::Error),
 	#[error("io error reading name len: {0}")]
 	NameLenIoError(std::io::Error),
 	#[error("io error reading name: {0}")]
@@ -60,36 +127,87 @@ pub enum SpacedropRequestError {
 	NameFormatError(FromUtf8Error),
 	#[error("io error reading file size: {0}")]
 	SizeIoError(std::io::Error),
+	#[error("io error reading modified time: {0}")]
+	ModifiedIoError(std::io::Error),
+	#[error("io error reading directory count: {0}")]
+	DirectoryCountIoError(std::io::Error),
+	#[error("io error reading file checksum: {0}")]
+	ChecksumIoError(std::io::Error),
+	#[error("the sizes of the files overflow a u64")]
+	SizeOverflow,
 }
 
 impl SpaceblockRequest {
 	pub async fn from_stream(
 		stream: &mut (impl AsyncRead + Unpin),
 	) -> Result<Self, SpacedropRequestError> {
-		let name = {
-			let len = stream
-				.read_u16_le()
+		let mut id = [0u8; 16];
+		stream
+			.read_exact(&mut id)
+			.await
+			.map_err(SpacedropRequestError::IdIoError)?;
+
+		let count = stream
+			.read_u16_le()
+			.await
+			.map_err(SpacedropRequestError::FileCountIoError)?;
+
+		let mut files = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let name = read_name(stream).await?;
+
+			let size = stream
+				.read_u64_le()
 				.await
-				.map_err(SpacedropRequestError::NameLenIoError)?;
+				.map_err(SpacedropRequestError::SizeIoError)?;
 
-			let mut buf = vec![0u8; len as usize];
+			let modified = stream
+				.read_u64_le()
+				.await
+				.map_err(SpacedropRequestError::ModifiedIoError)?;
+
+			let mut checksum = [0u8; CHECKSUM_SIZE];
 			stream
-				.read_exact(&mut buf)
+				.read_exact(&mut checksum)
 				.await
-				.map_err(SpacedropRequestError::NameIoError)?;
+				.map_err(SpacedropRequestError::ChecksumIoError)?;
 
-			String::from_utf8(buf).map_err(SpacedropRequestError::NameFormatError)?
-		};
+			files.push(SpaceblockFile {
+				name,
+				size,
+				modified,
+				checksum,
+			});
+		}
 
-		let size = stream
-			.read_u64_le()
+		let count = stream
+			.read_u16_le()
 			.await
-			.map_err(SpacedropRequestError::SizeIoError)?;
+			.map_err(SpacedropRequestError::DirectoryCountIoError)?;
+
+		let mut directories = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let name = read_name(stream).await?;
+
+			let modified = stream
+				.read_u64_le()
+				.await
+				.map_err(SpacedropRequestError::ModifiedIoError)?;
+
+			directories.push(SpaceblockDirectory { name, modified });
+		}
+
+		// The sizes come from the peer, a sum that overflows can't be a real transfer
+		let size = files
+			.iter()
+			.try_fold(0u64, |size, file| size.checked_add(file.size))
+			.ok_or(SpacedropRequestError::SizeOverflow)?;
 		let block_size = BlockSize::from_size(size); // TODO: Get from stream: stream.read_u8().await.map_err(|_| ())?; // TODO: Error handling
 
 		Ok(Self {
-			name,
-			size,
+			id: Uuid::from_bytes(id),
+			files,
+			directories,
 			block_size,
 		})
 	}
@@ -97,68 +215,251 @@ impl SpaceblockRequest {
 	pub fn to_bytes(&self) -> Vec<u8> {
 		let mut buf = Vec::new();
 
-		let len_buf = (self.name.len() as u16).to_le_bytes();
-		if self.name.len() > u16::MAX as usize {
-			panic!("Name is too long!"); // TODO: Error handling
+		buf.extend_from_slice(self.id.as_bytes());
+
+		// Requests are checked against `MAX_FILES` before being built
+		if self.files.len() > MAX_FILES {
+			panic!("Too many files!");
 		}
-		buf.extend_from_slice(&len_buf);
-		buf.extend(self.name.as_bytes());
+		buf.extend_from_slice(&(self.files.len() as u16).to_le_bytes());
 
-		buf.extend_from_slice(&self.size.to_le_bytes());
+		for file in &self.files {
+			write_name(&mut buf, &file.name);
+			buf.extend_from_slice(&file.size.to_le_bytes());
+			buf.extend_from_slice(&file.modified.to_le_bytes());
+			buf.extend_from_slice(&file.checksum);
+		}
+
+		// Requests are checked against `MAX_DIRECTORIES` before being built
+		if self.directories.len() > MAX_DIRECTORIES {
+			panic!("Too many directories!");
+		}
+		buf.extend_from_slice(&(self.directories.len() as u16).to_le_bytes());
+
+		for directory in &self.directories {
+			write_name(&mut buf, &directory.name);
+			buf.extend_from_slice(&directory.modified.to_le_bytes());
+		}
 
 		buf
 	}
+
+	/// The total size of the files being sent
+	pub fn size(&self) -> u64 {
+		self.files.iter().map(|file| file.size).sum()
+	}
+}
+
+async fn read_name(stream: &mut (impl AsyncRead + Unpin)) -> Result<String, SpacedropRequestError> {
+	let len = stream
+		.read_u16_le()
+		.await
+		.map_err(SpacedropRequestError::NameLenIoError)?;
+
+	let mut buf = vec![0u8; len as usize];
+	stream
+		.read_exact(&mut buf)
+		.await
+		.map_err(SpacedropRequestError::NameIoError)?;
+
+	String::from_utf8(buf).map_err(SpacedropRequestError::NameFormatError)
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+	// Requests are checked against `MAX_NAME_LEN` before being built
+	if name.len() > MAX_NAME_LEN {
+		panic!("Name is too long!");
+	}
+	buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+	buf.extend(name.as_bytes());
+}
+
+/// Reply of the receiver to a [`SpaceblockRequest`] it accepted, with the offset each file should
+/// be sent from. New transfers start every file from the beginning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceblockResume {
+	pub offsets: Vec<u64>,
+	/// The chunks of the copy the receiver already has of each file, empty to receive all of it
+	pub bases: Vec<Vec<ChunkFingerprint>>,
+	/// How many streams the files should be sent over, `1` for them to be sent in order over the
+	/// stream of the request. Files can only be sent as a delta over a single stream.
+	pub streams: u8,
+}
+
+impl SpaceblockResume {
+	pub async fn from_stream(
+		stream: &mut (impl AsyncRead + Unpin),
+		req: &SpaceblockRequest,
+	) -> Result<Self, SpaceblockError> {
+		let mut offsets = Vec::with_capacity(req.files.len());
+		for file in &req.files {
+			let offset = stream.read_u64_le().await?;
+			if offset > file.size {
+				return Err(SpaceblockError::InvalidOffset(offset));
+			}
+
+			offsets.push(offset);
+		}
+
+		let mut bases = Vec::with_capacity(req.files.len());
+		for _ in &req.files {
+			let count = stream.read_u32_le().await?;
+			if count > MAX_BASIS_CHUNKS {
+				return Err(SpaceblockError::TooManyChunks(count));
+			}
+
+			let mut chunks = Vec::with_capacity(count as usize);
+			for _ in 0..count {
+				chunks.push(ChunkFingerprint::from_stream(stream).await?);
+			}
+
+			bases.push(chunks);
+		}
+
+		let streams = stream.read_u8().await?.max(1);
+		if streams > 1 && bases.iter().any(|chunks| !chunks.is_empty()) {
+			return Err(SpaceblockError::ParallelDelta);
+		}
+
+		Ok(Self {
+			offsets,
+			bases,
+			streams,
+		})
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut buf = self
+			.offsets
+			.iter()
+			.flat_map(|offset| offset.to_le_bytes())
+			.collect::<Vec<_>>();
+
+		for chunks in &self.bases {
+			buf.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+			for chunk in chunks {
+				chunk.to_bytes(&mut buf);
+			}
+		}
+
+		buf.push(self.streams);
+
+		buf
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum SpaceblockError {
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("received a block at offset '{received}' when expecting one at '{expected}'")]
+	UnexpectedBlock { expected: u64, received: u64 },
+	#[error("received a block of '{0}' bytes which is bigger than the block size")]
+	BlockTooLarge(u64),
+	#[error("received a block at offset '{0}' which doesn't match its checksum")]
+	BlockChecksumMismatch(u64),
+	#[error("file '{0}' doesn't match its checksum once complete")]
+	FileChecksumMismatch(String),
+	#[error("file '{0}' ended before its expected size")]
+	UnexpectedEof(String),
+	#[error("invalid offset '{0}' to resume from")]
+	InvalidOffset(u64),
+	#[error("the copy of a file has '{0}' chunks which is more than allowed")]
+	TooManyChunks(u32),
+	#[error("received unknown delta message '{0}'")]
+	UnknownDeltaMessage(u8),
+	#[error("the copy of file '{0}' changed while receiving it")]
+	BasisMismatch(String),
+	#[error("received unknown chunk message '{0}'")]
+	UnknownChunkMessage(u8),
+	#[error("the chunk at offset '{0}' couldn't be verified after sending it again")]
+	ChunkRejected(u64),
+	#[error("files sent as a delta can't be sent over multiple streams")]
+	ParallelDelta,
 }
 
 /// TODO
 pub struct Block<'a> {
-	// TODO: File content, checksum, source location so it can be resent!
+	// TODO: Source location so it can be resent!
 	pub offset: u64,
 	pub size: u64,
+	pub checksum: [u8; CHECKSUM_SIZE],
 	pub data: &'a [u8],
-	// TODO: Checksum?
 }
 
 impl<'a> Block<'a> {
+	pub fn new(offset: u64, data: &'a [u8]) -> Self {
+		Self {
+			offset,
+			size: data.len() as u64,
+			checksum: *blake3::hash(data).as_bytes(),
+			data,
+		}
+	}
+
 	pub fn to_bytes(&self) -> Vec<u8> {
 		let mut buf = Vec::new();
 		buf.extend_from_slice(&self.offset.to_le_bytes());
 		buf.extend_from_slice(&self.size.to_le_bytes());
+		buf.extend_from_slice(&self.checksum);
 		buf.extend_from_slice(self.data);
 		buf
 	}
 
+	/// Reads a block into `data_buf` and checks it against its checksum
 	pub async fn from_stream(
 		stream: &mut (impl AsyncReadExt + Unpin),
 		data_buf: &mut [u8],
-	) -> Result<Block<'a>, ()> {
-		let mut offset = [0; 8];
-		stream.read_exact(&mut offset).await.map_err(|_| ())?; // TODO: Error handling
-		let offset = u64::from_le_bytes(offset);
+	) -> Result<Block<'a>, SpaceblockError> {
+		let offset = stream.read_u64_le().await?;
+		let size = stream.read_u64_le().await?;
 
-		let mut size = [0; 8];
-		stream.read_exact(&mut size).await.map_err(|_| ())?; // TODO: Error handling
-		let size = u64::from_le_bytes(size);
+		let mut checksum = [0; CHECKSUM_SIZE];
+		stream.read_exact(&mut checksum).await?;
 
-		// TODO: Ensure `size` is `block_size` or smaller else buffer overflow
+		if size > data_buf.len() as u64 {
+			return Err(SpaceblockError::BlockTooLarge(size));
+		}
 
-		stream
-			.read_exact(&mut data_buf[..size as usize])
-			.await
-			.map_err(|_| ())?; // TODO: Error handling
+		stream.read_exact(&mut data_buf[..size as usize]).await?;
+
+		if blake3::hash(&data_buf[..size as usize]).as_bytes() != &checksum {
+			return Err(SpaceblockError::BlockChecksumMismatch(offset));
+		}
 
 		Ok(Self {
 			offset,
 			size,
+			checksum,
 			data: &[], // TODO: This is super cringe. Data should be decoded here but lifetimes and extra allocations become a major concern.
 		})
 	}
 }
 
+/// Computes the checksum of a whole file, which the receiver verifies it against once complete
+pub async fn checksum(
+	mut file: impl AsyncRead + Unpin,
+) -> Result<[u8; CHECKSUM_SIZE], std::io::Error> {
+	let mut hasher = blake3::Hasher::new();
+	let mut buf = vec![0u8; BlockSize::from_size(0).size() as usize];
+
+	loop {
+		let read = file.read(&mut buf).await?;
+		if read == 0 {
+			break;
+		}
+
+		hasher.update(&buf[..read]);
+	}
+
+	Ok(*hasher.finalize().as_bytes())
+}
+
 /// TODO
 pub struct Transfer<'a, F> {
 	req: &'a SpaceblockRequest,
 	on_progress: F,
+	rate_limiters: Vec<Arc<RateLimiter>>,
 }
 
 impl<'a, F> Transfer<'a, F>
@@ -166,73 +467,287 @@ where
 	F: Fn(u8) + 'a,
 {
 	pub fn new(req: &'a SpaceblockRequest, on_progress: F) -> Self {
-		Self { req, on_progress }
+		Self {
+			req,
+			on_progress,
+			rate_limiters: Vec::new(),
+		}
+	}
+
+	/// Limits the speed of the transfer with every one of the limiters
+	pub fn with_rate_limiters(mut self, rate_limiters: Vec<Arc<RateLimiter>>) -> Self {
+		self.rate_limiters = rate_limiters;
+		self
+	}
+
+	async fn throttle(&self, bytes: u64) {
+		for limiter in &self.rate_limiters {
+			limiter.acquire(bytes).await;
+		}
 	}
 
+	fn progress(&self, transferred: u64) {
+		let size = self.req.size();
+		(self.on_progress)(if size == 0 {
+			100
+		} else {
+			((transferred * 100) / size) as u8 // SAFETY: Percent must be between 0 and 100
+		});
+	}
+
+	/// Sends every file from the offset the receiver asked for
 	pub async fn send(
 		&self,
 		stream: &mut (impl AsyncWrite + Unpin),
-		mut file: (impl AsyncBufRead + Unpin),
-	) {
+		files: Vec<impl AsyncRead + AsyncSeek + Unpin>,
+		resume: &SpaceblockResume,
+	) -> Result<(), SpaceblockError> {
 		// We manually implement what is basically a `BufReader` so we have more control
 		let mut buf = vec![0u8; self.req.block_size.size() as usize];
-		let mut offset: u64 = 0;
+		let mut transferred: u64 = resume.offsets.iter().sum();
+
+		for ((info, mut file), (&start, basis)) in self
+			.req
+			.files
+			.iter()
+			.zip(files)
+			.zip(resume.offsets.iter().zip(&resume.bases))
+		{
+			let mut offset = file.seek(SeekFrom::Start(start)).await?;
 
-		loop {
-			let read = file.read(&mut buf[..]).await.unwrap(); // TODO: Error handling
-			offset += read as u64;
-			(self.on_progress)(((self.req.size / offset) * 100) as u8); // SAFETY: Percent must be between 0 and 100
+			if !basis.is_empty() {
+				self.send_delta(stream, file, info, offset, basis, &mut transferred)
+					.await?;
+				continue;
+			}
 
-			if read == 0 {
-				if offset != self.req.size {
-					panic!("U dun goofed"); // TODO: Error handling
+			while offset < info.size {
+				let max = buf.len().min((info.size - offset) as usize);
+				let read = file.read(&mut buf[..max]).await?;
+				if read == 0 {
+					return Err(SpaceblockError::UnexpectedEof(info.name.clone()));
 				}
 
-				break;
+				self.throttle(read as u64).await;
+
+				let block = Block::new(offset, &buf[..read]);
+				debug!(
+					"Sending block at offset {} of size {}",
+					block.offset, block.size
+				);
+				stream.write_all(&block.to_bytes()).await?;
+
+				offset += read as u64;
+				transferred += read as u64;
+				self.progress(transferred);
 			}
+		}
 
-			let block = Block {
-				offset,
-				size: read as u64,
-				data: &buf[..read],
-			};
-			debug!(
-				"Sending block at offset {} of size {}",
-				block.offset, block.size
-			);
-			stream.write_all(&block.to_bytes()).await.unwrap(); // TODO: Error handling
+		stream.flush().await?;
+
+		Ok(())
+	}
+
+	/// Sends a file from `offset` as the chunks the receiver doesn't have in its copy of it
+	async fn send_delta(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin),
+		mut file: impl AsyncRead + Unpin,
+		info: &SpaceblockFile,
+		mut offset: u64,
+		basis: &[ChunkFingerprint],
+		transferred: &mut u64,
+	) -> Result<(), SpaceblockError> {
+		let sources = chunk_offsets(basis);
+		let mut chunker = Chunker::new((&mut file).take(info.size - offset));
+
+		while let Some(chunk) = chunker.next().await? {
+			let checksum = *blake3::hash(&chunk).as_bytes();
+
+			match sources.get(&checksum) {
+				Some(&source) => {
+					debug!(
+						"Sending copy of block at offset {} of size {}",
+						offset,
+						chunk.len()
+					);
+
+					let copy = delta::BlockCopy {
+						offset,
+						source,
+						size: chunk.len() as u32,
+						checksum,
+					};
+					stream.write_all(&[DELTA_COPY]).await?;
+					stream.write_all(&copy.to_bytes()).await?;
+				}
+				None => {
+					for (i, data) in chunk
+						.chunks(self.req.block_size.size() as usize)
+						.enumerate()
+					{
+						self.throttle(data.len() as u64).await;
+
+						let block_offset =
+							offset + (i * self.req.block_size.size() as usize) as u64;
+						debug!(
+							"Sending block at offset {} of size {}",
+							block_offset,
+							data.len()
+						);
+						stream.write_all(&[DELTA_DATA]).await?;
+						stream
+							.write_all(&Block::new(block_offset, data).to_bytes())
+							.await?;
+					}
+				}
+			}
+
+			offset += chunk.len() as u64;
+			*transferred += chunk.len() as u64;
+			self.progress(*transferred);
 		}
+
+		if offset < info.size {
+			return Err(SpaceblockError::UnexpectedEof(info.name.clone()));
+		}
+
+		Ok(())
 	}
 
-	pub async fn receive(
+	/// Receives every file from the offset in `resume`, the data before it must already be in the
+	/// files. `bases` are the copies of the files whose chunks are in `resume`, the chunks the
+	/// sender doesn't send are copied from them. `on_verified` is called with the index of the file
+	/// and the offset up to which it has been verified and written after every block, so the
+	/// transfer can be resumed from there.
+	pub async fn receive<W, B>(
 		&self,
 		stream: &mut (impl AsyncReadExt + Unpin),
-		mut file: (impl AsyncWrite + Unpin),
-	) {
+		files: &mut [W],
+		bases: &mut [Option<B>],
+		resume: &SpaceblockResume,
+		mut on_verified: impl FnMut(usize, u64),
+	) -> Result<(), SpaceblockError>
+	where
+		W: AsyncRead + AsyncWrite + AsyncSeek + Unpin,
+		B: AsyncRead + AsyncSeek + Unpin,
+	{
 		// We manually implement what is basically a `BufReader` so we have more control
 		let mut data_buf = vec![0u8; self.req.block_size.size() as usize];
-		let mut offset: u64 = 0;
-
-		// TODO: Prevent loop being a DOS vector
-		loop {
-			// TODO: Timeout if nothing is being received
-			let block = Block::from_stream(stream, &mut data_buf).await.unwrap(); // TODO: Error handling
-			offset += block.size;
-			(self.on_progress)(((self.req.size / offset) * 100) as u8); // SAFETY: Percent must be between 0 and 100
-
-			debug!(
-				"Received block at offset {} of size {}",
-				block.offset, block.size
-			);
-			file.write_all(&data_buf[..block.size as usize])
-				.await
-				.unwrap(); // TODO: Error handling
+		let mut transferred: u64 = resume.offsets.iter().sum();
+
+		for (i, ((info, file), &start)) in self
+			.req
+			.files
+			.iter()
+			.zip(files.iter_mut())
+			.zip(&resume.offsets)
+			.enumerate()
+		{
+			let mut offset = file.seek(SeekFrom::Start(start)).await?;
+
+			if !resume.bases[i].is_empty() {
+				let Some(basis) = bases.get_mut(i).and_then(Option::as_mut) else {
+					return Err(SpaceblockError::BasisMismatch(info.name.clone()));
+				};
+
+				while offset < info.size {
+					let size = self
+						.receive_delta_block(stream, &mut *file, basis, info, offset, &mut data_buf)
+						.await?;
+
+					offset += size;
+					transferred += size;
+					on_verified(i, offset);
+					self.progress(transferred);
+				}
+			}
+
+			while offset < info.size {
+				// TODO: Timeout if nothing is being received
+				let block = Block::from_stream(stream, &mut data_buf).await?;
+				// Not reading from the stream slows down the sender too
+				self.throttle(block.size).await;
+
+				if block.offset != offset || block.size == 0 || offset + block.size > info.size {
+					return Err(SpaceblockError::UnexpectedBlock {
+						expected: offset,
+						received: block.offset,
+					});
+				}
+
+				debug!(
+					"Received block at offset {} of size {}",
+					block.offset, block.size
+				);
+				file.write_all(&data_buf[..block.size as usize]).await?;
+				file.flush().await?;
+
+				offset += block.size;
+				transferred += block.size;
+				on_verified(i, offset);
+				self.progress(transferred);
+			}
+
+			file.seek(SeekFrom::Start(0)).await?;
+			if checksum(&mut *file).await? != info.checksum {
+				return Err(SpaceblockError::FileChecksumMismatch(info.name.clone()));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Receives a block of a file sent as a delta, either with its data or copied from the
+	/// receiver's copy of the file, and returns its size
+	async fn receive_delta_block(
+		&self,
+		stream: &mut (impl AsyncReadExt + Unpin),
+		file: &mut (impl AsyncWrite + Unpin),
+		basis: &mut (impl AsyncRead + AsyncSeek + Unpin),
+		info: &SpaceblockFile,
+		offset: u64,
+		data_buf: &mut Vec<u8>,
+	) -> Result<u64, SpaceblockError> {
+		let (block_offset, size) = match stream.read_u8().await? {
+			DELTA_DATA => {
+				let block = Block::from_stream(stream, data_buf).await?;
+				// Not reading from the stream slows down the sender too
+				self.throttle(block.size).await;
+
+				(block.offset, block.size)
+			}
+			DELTA_COPY => {
+				let copy = delta::BlockCopy::from_stream(stream).await?;
+				if data_buf.len() < copy.size as usize {
+					data_buf.resize(copy.size as usize, 0);
+				}
 
-			// TODO: Should this be `read == 0`
-			if offset == self.req.size {
-				break;
+				let data = &mut data_buf[..copy.size as usize];
+				basis.seek(SeekFrom::Start(copy.source)).await?;
+				basis.read_exact(data).await?;
+
+				if blake3::hash(data).as_bytes() != &copy.checksum {
+					return Err(SpaceblockError::BasisMismatch(info.name.clone()));
+				}
+
+				(copy.offset, copy.size as u64)
 			}
+			message => return Err(SpaceblockError::UnknownDeltaMessage(message)),
+		};
+
+		if block_offset != offset || size == 0 || offset + size > info.size {
+			return Err(SpaceblockError::UnexpectedBlock {
+				expected: offset,
+				received: block_offset,
+			});
 		}
+
+		debug!("Received delta block at offset {offset} of size {size}");
+		file.write_all(&data_buf[..size as usize]).await?;
+		file.flush().await?;
+
+		Ok(size)
 	}
 }
 
@@ -244,86 +759,289 @@ mod tests {
 
 	use super::*;
 
-	#[tokio::test]
-	async fn test_spaceblock_request() {
-		let req = SpaceblockRequest {
-			name: "Demo".to_string(),
-			size: 42069,
-			block_size: BlockSize::from_size(42069),
-		};
+	async fn request(files: &[(&str, &[u8])], block_size: BlockSize) -> SpaceblockRequest {
+		let mut spaceblock_files = Vec::new();
+		for (name, data) in files {
+			spaceblock_files.push(SpaceblockFile {
+				name: name.to_string(),
+				size: data.len() as u64,
+				modified: 1_688_000_000_000,
+				checksum: checksum(*data).await.unwrap(),
+			});
+		}
 
-		let bytes = req.to_bytes();
-		let req2 = SpaceblockRequest::from_stream(&mut Cursor::new(bytes))
-			.await
-			.unwrap();
-		assert_eq!(req, req2);
+		SpaceblockRequest {
+			id: Uuid::from_u128(42069),
+			files: spaceblock_files,
+			directories: vec![],
+			block_size,
+		}
 	}
 
-	#[tokio::test]
-	async fn test_spaceblock_single_block() {
-		let (mut client, mut server) = tokio::io::duplex(64);
+	async fn transfer(
+		req: &SpaceblockRequest,
+		data: Vec<Vec<u8>>,
+		received: Vec<Cursor<Vec<u8>>>,
+		resume: SpaceblockResume,
+	) -> (Result<(), SpaceblockError>, Vec<Vec<u8>>) {
+		transfer_with_bases(req, data, received, vec![], resume).await
+	}
 
-		// This is sent out of band of Spaceblock
-		let data = b"Spacedrive".to_vec();
-		let req = SpaceblockRequest {
-			name: "Demo".to_string(),
-			size: data.len() as u64,
-			block_size: BlockSize::from_size(data.len() as u64),
-		};
+	async fn transfer_with_bases(
+		req: &SpaceblockRequest,
+		data: Vec<Vec<u8>>,
+		mut received: Vec<Cursor<Vec<u8>>>,
+		mut bases: Vec<Option<Cursor<Vec<u8>>>>,
+		resume: SpaceblockResume,
+	) -> (Result<(), SpaceblockError>, Vec<Vec<u8>>) {
+		let (mut client, mut server) = tokio::io::duplex(64);
 
 		let (tx, rx) = oneshot::channel();
 		tokio::spawn({
 			let req = req.clone();
-			let data = data.clone();
+			let resume = resume.clone();
 			async move {
-				let file = BufReader::new(Cursor::new(data));
+				let files = data.into_iter().map(Cursor::new).collect::<Vec<_>>();
 				tx.send(()).unwrap();
-				Transfer::new(&req, |_| {}).send(&mut client, file).await;
+				Transfer::new(&req, |_| {})
+					.send(&mut client, files, &resume)
+					.await
+					.ok();
 			}
 		});
 
 		rx.await.unwrap();
 
-		let mut result = Vec::new();
-		Transfer::new(&req, |_| {})
-			.receive(&mut server, &mut result)
+		let result = Transfer::new(req, |_| {})
+			.receive(&mut server, &mut received, &mut bases, &resume, |_, _| {})
 			.await;
-		assert_eq!(result, data);
+
+		(
+			result,
+			received.into_iter().map(Cursor::into_inner).collect(),
+		)
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_request() {
+		let mut req = request(
+			&[("Demo", &b"Spacedrive"[..]), ("Folder/Other", &b""[..])],
+			BlockSize(131072),
+		)
+		.await;
+		req.directories = vec![
+			SpaceblockDirectory {
+				name: "Folder".into(),
+				modified: 1_688_000_000_000,
+			},
+			SpaceblockDirectory {
+				name: "Folder/Empty".into(),
+				modified: 0,
+			},
+		];
+
+		let bytes = req.to_bytes();
+		let req2 = SpaceblockRequest::from_stream(&mut Cursor::new(bytes))
+			.await
+			.unwrap();
+		assert_eq!(req, req2);
+
+		let resume = SpaceblockResume {
+			offsets: vec![4, 0],
+			bases: vec![
+				vec![ChunkFingerprint {
+					size: 10,
+					checksum: [1; CHECKSUM_SIZE],
+				}],
+				vec![],
+			],
+			streams: 1,
+		};
+		let resume2 = SpaceblockResume::from_stream(&mut Cursor::new(resume.to_bytes()), &req)
+			.await
+			.unwrap();
+		assert_eq!(resume, resume2);
+
+		// A delta can't be sent over multiple streams
+		let resume = SpaceblockResume {
+			streams: 4,
+			..resume
+		};
+		assert!(matches!(
+			SpaceblockResume::from_stream(&mut Cursor::new(resume.to_bytes()), &req).await,
+			Err(SpaceblockError::ParallelDelta)
+		));
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_single_block() {
+		// This is sent out of band of Spaceblock
+		let data = b"Spacedrive".to_vec();
+		let req = request(
+			&[("Demo", &data[..])],
+			BlockSize::from_size(data.len() as u64),
+		)
+		.await;
+
+		let (result, received) = transfer(
+			&req,
+			vec![data.clone()],
+			vec![Cursor::new(vec![])],
+			SpaceblockResume {
+				offsets: vec![0],
+				bases: vec![vec![]],
+				streams: 1,
+			},
+		)
+		.await;
+
+		result.unwrap();
+		assert_eq!(received, vec![data]);
 	}
 
 	// https://github.com/spacedriveapp/spacedrive/pull/942
 	#[tokio::test]
 	async fn test_spaceblock_multiple_blocks() {
-		let (mut client, mut server) = tokio::io::duplex(64);
-
 		// This is sent out of band of Spaceblock
 		let block_size = 131072u32;
 		let data = vec![0u8; block_size as usize * 4]; // Let's pacman some RAM
-		let block_size = BlockSize::dangerously_new(block_size);
+		let other = vec![1u8; block_size as usize + 1];
 
-		let req = SpaceblockRequest {
-			name: "Demo".to_string(),
-			size: data.len() as u64,
-			block_size,
-		};
+		let req = request(
+			&[("Demo", &data[..]), ("Other", &other[..])],
+			BlockSize::dangerously_new(block_size),
+		)
+		.await;
 
-		let (tx, rx) = oneshot::channel();
-		tokio::spawn({
-			let req = req.clone();
-			let data = data.clone();
-			async move {
-				let file = BufReader::new(Cursor::new(data));
-				tx.send(()).unwrap();
-				Transfer::new(&req, |_| {}).send(&mut client, file).await;
-			}
-		});
+		let (result, received) = transfer(
+			&req,
+			vec![data.clone(), other.clone()],
+			vec![Cursor::new(vec![]), Cursor::new(vec![])],
+			SpaceblockResume {
+				offsets: vec![0, 0],
+				bases: vec![vec![], vec![]],
+				streams: 1,
+			},
+		)
+		.await;
 
-		rx.await.unwrap();
+		result.unwrap();
+		assert_eq!(received, vec![data, other]);
+	}
 
-		let mut result = Vec::new();
-		Transfer::new(&req, |_| {})
-			.receive(&mut server, &mut result)
-			.await;
-		assert_eq!(result, data);
+	#[tokio::test]
+	async fn test_spaceblock_resume() {
+		let data = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+		let req = request(&[("Demo", &data[..])], BlockSize::dangerously_new(64)).await;
+
+		// The first 300 bytes were received before the transfer was interrupted
+		let (result, received) = transfer(
+			&req,
+			vec![data.clone()],
+			vec![Cursor::new(data[..300].to_vec())],
+			SpaceblockResume {
+				offsets: vec![300],
+				bases: vec![vec![]],
+				streams: 1,
+			},
+		)
+		.await;
+
+		result.unwrap();
+		assert_eq!(received, vec![data]);
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_corrupted_file() {
+		let data = b"Spacedrive".to_vec();
+		let req = request(&[("Demo", &data[..])], BlockSize::dangerously_new(4)).await;
+
+		// Resuming on top of data that doesn't match what was sent
+		let (result, _) = transfer(
+			&req,
+			vec![data],
+			vec![Cursor::new(b"Spxce".to_vec())],
+			SpaceblockResume {
+				offsets: vec![4],
+				bases: vec![vec![]],
+				streams: 1,
+			},
+		)
+		.await;
+
+		assert!(matches!(
+			result,
+			Err(SpaceblockError::FileChecksumMismatch(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_delta() {
+		let old = (0..600_000u32)
+			.map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+			.collect::<Vec<_>>();
+		let mut data = old.clone();
+		data.splice(300_000..300_100, b"Spacedrive".repeat(50));
+
+		let req = request(&[("Demo", &data[..])], BlockSize::dangerously_new(4096)).await;
+		let basis = fingerprint(&old[..]).await.unwrap();
+
+		let (result, received) = transfer_with_bases(
+			&req,
+			vec![data.clone()],
+			vec![Cursor::new(vec![])],
+			vec![Some(Cursor::new(old.clone()))],
+			SpaceblockResume {
+				offsets: vec![0],
+				bases: vec![basis.clone()],
+				streams: 1,
+			},
+		)
+		.await;
+
+		result.unwrap();
+		assert_eq!(received, vec![data.clone()]);
+
+		// A copy that doesn't match the chunks it was fingerprinted with isn't trusted
+		let (result, _) = transfer_with_bases(
+			&req,
+			vec![data],
+			vec![Cursor::new(vec![])],
+			vec![Some(Cursor::new(vec![0; old.len()]))],
+			SpaceblockResume {
+				offsets: vec![0],
+				bases: vec![basis],
+				streams: 1,
+			},
+		)
+		.await;
+
+		assert!(matches!(result, Err(SpaceblockError::BasisMismatch(_))));
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_corrupted_block() {
+		let data = b"Spacedrive";
+		let mut block = Block::new(0, data).to_bytes();
+		*block.last_mut().unwrap() ^= 1;
+
+		let mut buf = vec![0u8; 64];
+		assert!(matches!(
+			Block::from_stream(&mut Cursor::new(block), &mut buf).await,
+			Err(SpaceblockError::BlockChecksumMismatch(0))
+		));
+	}
+
+	#[tokio::test]
+	async fn requests_with_overflowing_sizes_are_rejected() {
+		let mut req = request(&[("a", b""), ("b", b"")], BlockSize::from_size(0)).await;
+		for file in &mut req.files {
+			file.size = u64::MAX;
+		}
+
+		assert!(matches!(
+			SpaceblockRequest::from_stream(&mut Cursor::new(req.to_bytes())).await,
+			Err(SpacedropRequestError::SizeOverflow)
+		));
 	}
 }
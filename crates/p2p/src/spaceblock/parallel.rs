@@ -0,0 +1,545 @@
+//! Sends the files of a transfer as chunks over multiple streams at once. A single stream is
+//! limited by how much data can be in flight before it's acknowledged, so on links with a high
+//! latency using more of them is much faster.
+//!
+//! Every chunk is sent with its checksum, and the receiver replies to each of them to say if it
+//! was verified. A chunk that wasn't is sent again, over any of the streams.
+
+use std::{
+	collections::{BTreeMap, VecDeque},
+	future::{poll_fn, Future},
+	io::SeekFrom,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+	task::Poll,
+};
+
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+	sync::{Mutex as AsyncMutex, Notify},
+};
+use tracing::debug;
+
+use super::{checksum, SpaceblockError, SpaceblockResume, Transfer, CHECKSUM_SIZE};
+
+/// How many blocks are in a chunk, so they're big enough for the checksum and the reply to it to
+/// not slow the transfer down
+const CHUNK_BLOCKS: u64 = 32;
+/// How many times a chunk is sent before giving up on it
+const MAX_CHUNK_ATTEMPTS: u8 = 3;
+
+/// Sent before every chunk, or once every chunk was verified
+const CHUNK_DATA: u8 = 0;
+const CHUNK_END: u8 = 1;
+
+/// Replies of the receiver to each chunk, in the order they were received in
+const CHUNK_VERIFIED: u8 = 0;
+const CHUNK_REJECTED: u8 = 1;
+
+/// A range of a file sent as a whole over one of the streams
+#[derive(Debug, Clone)]
+struct Chunk {
+	file: usize,
+	offset: u64,
+	size: u32,
+	attempts: u8,
+}
+
+struct ChunkHeader {
+	file: u16,
+	offset: u64,
+	size: u32,
+	checksum: [u8; CHECKSUM_SIZE],
+}
+
+impl ChunkHeader {
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(2 + 8 + 4 + CHECKSUM_SIZE);
+		buf.extend_from_slice(&self.file.to_le_bytes());
+		buf.extend_from_slice(&self.offset.to_le_bytes());
+		buf.extend_from_slice(&self.size.to_le_bytes());
+		buf.extend_from_slice(&self.checksum);
+		buf
+	}
+
+	async fn from_stream(stream: &mut (impl AsyncRead + Unpin)) -> Result<Self, SpaceblockError> {
+		let file = stream.read_u16_le().await?;
+		let offset = stream.read_u64_le().await?;
+		let size = stream.read_u32_le().await?;
+
+		let mut checksum = [0; CHECKSUM_SIZE];
+		stream.read_exact(&mut checksum).await?;
+
+		Ok(Self {
+			file,
+			offset,
+			size,
+			checksum,
+		})
+	}
+}
+
+/// The chunks that are left to send, shared between the streams
+struct Pending {
+	queue: VecDeque<Chunk>,
+	/// Chunks that weren't verified yet, including the ones being sent
+	remaining: usize,
+}
+
+/// The chunks of a file that were verified after the offset up to which all of them were
+#[derive(Debug, Default)]
+struct FileProgress {
+	verified: u64,
+	/// The size of each chunk by its offset
+	chunks: BTreeMap<u64, u64>,
+}
+
+impl FileProgress {
+	/// Returns the offset up to which the file is verified once the chunk is
+	fn complete(&mut self, offset: u64, size: u64) -> u64 {
+		if offset >= self.verified {
+			self.chunks.insert(offset, size);
+		}
+
+		while let Some(size) = self.chunks.remove(&self.verified) {
+			self.verified += size;
+		}
+
+		self.verified
+	}
+}
+
+/// Polls the futures concurrently until they're all done, or one of them fails
+async fn try_join_all<E>(
+	mut futures: Vec<Pin<Box<impl Future<Output = Result<(), E>>>>>,
+) -> Result<(), E> {
+	poll_fn(|cx| {
+		let mut i = 0;
+		while i < futures.len() {
+			match futures[i].as_mut().poll(cx) {
+				Poll::Ready(Ok(())) => {
+					futures.swap_remove(i);
+				}
+				Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+				Poll::Pending => i += 1,
+			}
+		}
+
+		if futures.is_empty() {
+			Poll::Ready(Ok(()))
+		} else {
+			Poll::Pending
+		}
+	})
+	.await
+}
+
+impl<'a, F> Transfer<'a, F>
+where
+	F: Fn(u8) + 'a,
+{
+	fn chunk_size(&self) -> u64 {
+		self.req.block_size.size() as u64 * CHUNK_BLOCKS
+	}
+
+	/// Sends every file from the offset the receiver asked for, split in chunks that are sent
+	/// concurrently over all the `streams`. Files can't be sent as a delta this way.
+	pub async fn send_parallel<S, R>(
+		&self,
+		streams: Vec<S>,
+		files: Vec<R>,
+		resume: &SpaceblockResume,
+	) -> Result<(), SpaceblockError>
+	where
+		S: AsyncRead + AsyncWrite + Unpin,
+		R: AsyncRead + AsyncSeek + Unpin,
+	{
+		let chunk_size = self.chunk_size();
+		let mut queue = VecDeque::new();
+		for (file, (info, &start)) in self.req.files.iter().zip(&resume.offsets).enumerate() {
+			let mut offset = start;
+			while offset < info.size {
+				let size = chunk_size.min(info.size - offset);
+				queue.push_back(Chunk {
+					file,
+					offset,
+					size: size as u32,
+					attempts: 0,
+				});
+				offset += size;
+			}
+		}
+
+		let pending = Mutex::new(Pending {
+			remaining: queue.len(),
+			queue,
+		});
+		let notify = Notify::new();
+		let files = files.into_iter().map(AsyncMutex::new).collect::<Vec<_>>();
+		let transferred = AtomicU64::new(resume.offsets.iter().sum());
+
+		try_join_all(
+			streams
+				.into_iter()
+				.map(|stream| {
+					Box::pin(self.send_stream(stream, &files, &pending, &notify, &transferred))
+				})
+				.collect(),
+		)
+		.await
+	}
+
+	async fn send_stream<R>(
+		&self,
+		stream: impl AsyncRead + AsyncWrite + Unpin,
+		files: &[AsyncMutex<R>],
+		pending: &Mutex<Pending>,
+		notify: &Notify,
+		transferred: &AtomicU64,
+	) -> Result<(), SpaceblockError>
+	where
+		R: AsyncRead + AsyncSeek + Unpin,
+	{
+		let (mut reader, mut writer) = tokio::io::split(stream);
+		// The chunks sent over this stream that the receiver didn't reply to yet
+		let in_flight = Mutex::new(VecDeque::<Chunk>::new());
+
+		let send = async {
+			let mut buf = vec![0u8; self.chunk_size() as usize];
+
+			loop {
+				let notified = notify.notified();
+				let next = {
+					let mut pending = pending.lock().unwrap();
+					match pending.queue.pop_front() {
+						Some(chunk) => Some(chunk),
+						None if pending.remaining == 0 => break,
+						None => None,
+					}
+				};
+
+				// A chunk sent over another stream could still be rejected and sent again
+				let Some(chunk) = next else {
+					notified.await;
+					continue;
+				};
+
+				let data = &mut buf[..chunk.size as usize];
+				{
+					let mut file = files[chunk.file].lock().await;
+					file.seek(SeekFrom::Start(chunk.offset)).await?;
+					file.read_exact(data).await.map_err(|e| match e.kind() {
+						std::io::ErrorKind::UnexpectedEof => {
+							SpaceblockError::UnexpectedEof(self.req.files[chunk.file].name.clone())
+						}
+						_ => e.into(),
+					})?;
+				}
+
+				self.throttle(chunk.size as u64).await;
+
+				let header = ChunkHeader {
+					file: chunk.file as u16,
+					offset: chunk.offset,
+					size: chunk.size,
+					checksum: *blake3::hash(data).as_bytes(),
+				};
+				debug!(
+					"Sending chunk at offset {} of size {}",
+					chunk.offset, chunk.size
+				);
+
+				in_flight.lock().unwrap().push_back(chunk);
+				writer.write_all(&[CHUNK_DATA]).await?;
+				writer.write_all(&header.to_bytes()).await?;
+				writer.write_all(data).await?;
+				writer.flush().await?;
+			}
+
+			writer.write_all(&[CHUNK_END]).await?;
+			writer.flush().await?;
+
+			Ok::<_, SpaceblockError>(())
+		};
+
+		let acknowledge = async {
+			loop {
+				let reply = reader.read_u8().await?;
+				if reply == CHUNK_END {
+					break;
+				}
+
+				let Some(mut chunk) = in_flight.lock().unwrap().pop_front() else {
+					return Err(SpaceblockError::UnknownChunkMessage(reply));
+				};
+
+				match reply {
+					CHUNK_VERIFIED => {
+						let total = transferred.fetch_add(chunk.size as u64, Ordering::Relaxed)
+							+ chunk.size as u64;
+						self.progress(total);
+
+						let mut pending = pending.lock().unwrap();
+						pending.remaining -= 1;
+						if pending.remaining == 0 {
+							notify.notify_waiters();
+						}
+					}
+					CHUNK_REJECTED => {
+						chunk.attempts += 1;
+						if chunk.attempts >= MAX_CHUNK_ATTEMPTS {
+							return Err(SpaceblockError::ChunkRejected(chunk.offset));
+						}
+
+						debug!(
+							"Chunk at offset {} was rejected, resending it",
+							chunk.offset
+						);
+						pending.lock().unwrap().queue.push_back(chunk);
+						notify.notify_waiters();
+					}
+					reply => return Err(SpaceblockError::UnknownChunkMessage(reply)),
+				}
+			}
+
+			Ok::<_, SpaceblockError>(())
+		};
+
+		tokio::try_join!(send, acknowledge).map(|_| ())
+	}
+
+	/// Receives every file from the offset in `resume` as chunks sent concurrently over all the
+	/// `streams`, the data before it must already be in the files. `on_verified` is called with the
+	/// index of the file and the offset up to which all of its chunks were verified and written.
+	pub async fn receive_parallel<S, W>(
+		&self,
+		streams: Vec<S>,
+		files: &mut [W],
+		resume: &SpaceblockResume,
+		on_verified: impl Fn(usize, u64),
+	) -> Result<(), SpaceblockError>
+	where
+		S: AsyncRead + AsyncWrite + Unpin,
+		W: AsyncRead + AsyncWrite + AsyncSeek + Unpin,
+	{
+		let progress = Mutex::new(
+			resume
+				.offsets
+				.iter()
+				.map(|&verified| FileProgress {
+					verified,
+					..Default::default()
+				})
+				.collect::<Vec<_>>(),
+		);
+		let transferred = AtomicU64::new(resume.offsets.iter().sum());
+
+		{
+			let files = files.iter_mut().map(AsyncMutex::new).collect::<Vec<_>>();
+
+			try_join_all(
+				streams
+					.into_iter()
+					.map(|stream| {
+						Box::pin(self.receive_stream(
+							stream,
+							&files,
+							resume,
+							&progress,
+							&transferred,
+							&on_verified,
+						))
+					})
+					.collect(),
+			)
+			.await?;
+		}
+
+		let progress = progress.into_inner().unwrap();
+		for ((info, file), progress) in self.req.files.iter().zip(files.iter_mut()).zip(progress) {
+			if progress.verified != info.size {
+				return Err(SpaceblockError::UnexpectedEof(info.name.clone()));
+			}
+
+			file.seek(SeekFrom::Start(0)).await?;
+			if checksum(&mut *file).await? != info.checksum {
+				return Err(SpaceblockError::FileChecksumMismatch(info.name.clone()));
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn receive_stream<W>(
+		&self,
+		mut stream: impl AsyncRead + AsyncWrite + Unpin,
+		files: &[AsyncMutex<&mut W>],
+		resume: &SpaceblockResume,
+		progress: &Mutex<Vec<FileProgress>>,
+		transferred: &AtomicU64,
+		on_verified: &impl Fn(usize, u64),
+	) -> Result<(), SpaceblockError>
+	where
+		W: AsyncWrite + AsyncSeek + Unpin,
+	{
+		let chunk_size = self.chunk_size();
+		let mut buf = vec![0u8; chunk_size as usize];
+
+		loop {
+			match stream.read_u8().await? {
+				CHUNK_DATA => {}
+				CHUNK_END => {
+					stream.write_all(&[CHUNK_END]).await?;
+					stream.flush().await?;
+					return Ok(());
+				}
+				message => return Err(SpaceblockError::UnknownChunkMessage(message)),
+			}
+
+			let header = ChunkHeader::from_stream(&mut stream).await?;
+			let file = header.file as usize;
+			let (offset, size) = (header.offset, header.size as u64);
+
+			let Some((info, &start)) = self.req.files.get(file).zip(resume.offsets.get(file))
+			else {
+				return Err(SpaceblockError::UnexpectedBlock {
+					expected: 0,
+					received: offset,
+				});
+			};
+
+			// Chunks start at the offset the file is resumed from, so they never overlap
+			if size == 0
+				|| size > chunk_size
+				|| offset < start
+				|| (offset - start) % chunk_size != 0
+				|| offset + size > info.size
+			{
+				return Err(SpaceblockError::UnexpectedBlock {
+					expected: start,
+					received: offset,
+				});
+			}
+
+			let data = &mut buf[..size as usize];
+			stream.read_exact(data).await?;
+			// Not reading from the stream slows down the sender too
+			self.throttle(size).await;
+
+			if blake3::hash(data).as_bytes() != &header.checksum {
+				debug!("Received chunk at offset {offset} which doesn't match its checksum");
+				stream.write_all(&[CHUNK_REJECTED]).await?;
+				stream.flush().await?;
+				continue;
+			}
+
+			debug!("Received chunk at offset {offset} of size {size}");
+			{
+				let mut file = files[file].lock().await;
+				file.seek(SeekFrom::Start(offset)).await?;
+				file.write_all(data).await?;
+				file.flush().await?;
+			}
+
+			let verified = progress.lock().unwrap()[file].complete(offset, size);
+			on_verified(file, verified);
+			self.progress(transferred.fetch_add(size, Ordering::Relaxed) + size);
+
+			stream.write_all(&[CHUNK_VERIFIED]).await?;
+			stream.flush().await?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use uuid::Uuid;
+
+	use super::*;
+	use crate::spaceblock::{BlockSize, SpaceblockFile, SpaceblockRequest};
+
+	#[test]
+	fn verified_offset_only_covers_contiguous_chunks() {
+		let mut progress = FileProgress {
+			verified: 10,
+			..Default::default()
+		};
+
+		assert_eq!(progress.complete(30, 20), 10);
+		assert_eq!(progress.complete(50, 5), 10);
+		assert_eq!(progress.complete(10, 20), 55);
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_parallel() {
+		let data = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		let other = b"Spacedrive".to_vec();
+
+		let mut files = Vec::new();
+		for (name, data) in [("Demo", &data), ("Other", &other)] {
+			files.push(SpaceblockFile {
+				name: name.into(),
+				size: data.len() as u64,
+				modified: 0,
+				checksum: checksum(&data[..]).await.unwrap(),
+			});
+		}
+		let req = SpaceblockRequest {
+			id: Uuid::from_u128(42069),
+			files,
+			directories: vec![],
+			block_size: BlockSize::dangerously_new(256),
+		};
+
+		// The first chunks of the first file were received before the transfer was interrupted
+		let resume = SpaceblockResume {
+			offsets: vec![1000, 0],
+			bases: vec![vec![], vec![]],
+			streams: 3,
+		};
+
+		let (senders, receivers): (Vec<_>, Vec<_>) =
+			(0..3).map(|_| tokio::io::duplex(1024)).unzip();
+
+		tokio::spawn({
+			let (req, resume, data, other) =
+				(req.clone(), resume.clone(), data.clone(), other.clone());
+			async move {
+				Transfer::new(&req, |_| {})
+					.send_parallel(
+						senders,
+						vec![Cursor::new(data), Cursor::new(other)],
+						&resume,
+					)
+					.await
+					.unwrap();
+			}
+		});
+
+		let mut received = vec![Cursor::new(data[..1000].to_vec()), Cursor::new(vec![])];
+		let verified = Mutex::new(vec![0; 2]);
+		Transfer::new(&req, |_| {})
+			.receive_parallel(receivers, &mut received, &resume, |i, offset| {
+				verified.lock().unwrap()[i] = offset;
+			})
+			.await
+			.unwrap();
+
+		assert_eq!(
+			received
+				.into_iter()
+				.map(Cursor::into_inner)
+				.collect::<Vec<_>>(),
+			vec![data.clone(), other.clone()]
+		);
+		assert_eq!(
+			verified.into_inner().unwrap(),
+			vec![data.len() as u64, other.len() as u64]
+		);
+	}
+}
@@ -0,0 +1,107 @@
+use std::{
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+use tokio::{
+	sync::Mutex,
+	time::{sleep, Instant},
+};
+
+/// Limits the bytes per second of every transfer it's given to, so they share the limit between
+/// them. The limit can be changed while transfers are running.
+#[derive(Debug)]
+pub struct RateLimiter {
+	/// Bytes per second, `0` is unlimited
+	limit: AtomicU64,
+	bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	/// Bytes that can be sent right away, negative when a transfer went over the limit and
+	/// the next one has to wait for it
+	available: f64,
+	last_refill: Instant,
+}
+
+impl RateLimiter {
+	/// Creates a limiter for `limit` bytes per second, `None` is unlimited
+	pub fn new(limit: Option<u64>) -> Self {
+		Self {
+			limit: AtomicU64::new(limit.unwrap_or(0)),
+			bucket: Mutex::new(Bucket {
+				available: 0.0,
+				last_refill: Instant::now(),
+			}),
+		}
+	}
+
+	pub fn limit(&self) -> Option<u64> {
+		match self.limit.load(Ordering::Relaxed) {
+			0 => None,
+			limit => Some(limit),
+		}
+	}
+
+	pub fn set_limit(&self, limit: Option<u64>) {
+		self.limit.store(limit.unwrap_or(0), Ordering::Relaxed);
+	}
+
+	/// Waits until `bytes` can go through without exceeding the limit
+	pub async fn acquire(&self, bytes: u64) {
+		let Some(limit) = self.limit() else {
+			return;
+		};
+		let limit = limit as f64;
+
+		// The lock is held while waiting so transfers sharing the limiter take turns
+		let mut bucket = self.bucket.lock().await;
+
+		// Allows bursts of up to a second worth of data
+		let now = Instant::now();
+		let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+		bucket.available = (bucket.available + elapsed * limit).min(limit) - bytes as f64;
+		bucket.last_refill = now;
+
+		if bucket.available < 0.0 {
+			sleep(Duration::from_secs_f64(-bucket.available / limit)).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn unlimited() {
+		let limiter = RateLimiter::new(None);
+
+		let start = Instant::now();
+		limiter.acquire(u64::MAX).await;
+		limiter.acquire(u64::MAX).await;
+
+		assert!(start.elapsed() < Duration::from_millis(100));
+	}
+
+	#[tokio::test]
+	async fn limited() {
+		let limiter = RateLimiter::new(Some(10_000));
+
+		let start = Instant::now();
+		for _ in 0..5 {
+			limiter.acquire(1_000).await;
+		}
+
+		// 5KB at 10KB/s
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+
+		limiter.set_limit(None);
+
+		let start = Instant::now();
+		limiter.acquire(1_000_000).await;
+		assert!(start.elapsed() < Duration::from_millis(100));
+	}
+}
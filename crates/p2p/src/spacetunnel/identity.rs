@@ -1,4 +1,4 @@
-use ed25519_dalek::PublicKey;
+use ed25519_dalek::{PublicKey, Signature, Signer};
 use rand_core::OsRng;
 use thiserror::Error;
 
@@ -35,6 +35,10 @@ impl Identity {
 	pub fn to_remote_identity(&self) -> RemoteIdentity {
 		RemoteIdentity(self.0.public)
 	}
+
+	pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+		self.0.sign(message).to_bytes()
+	}
 }
 #[derive(Debug, PartialEq, Eq)]
 pub struct RemoteIdentity(ed25519_dalek::PublicKey);
@@ -51,4 +55,10 @@ impl RemoteIdentity {
 	pub fn public_key(&self) -> PublicKey {
 		self.0
 	}
+
+	/// Checks the `signature` of `message` was made by this identity
+	pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), IdentityErr> {
+		let signature = Signature::try_from(signature)?;
+		Ok(self.0.verify_strict(message, &signature)?)
+	}
 }
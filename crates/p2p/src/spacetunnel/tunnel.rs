@@ -1,64 +1,368 @@
 use std::{
 	io,
 	pin::Pin,
-	task::{Context, Poll},
+	task::{ready, Context, Poll},
 };
 
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
 use crate::spacetime::UnicastStream;
 
-pub struct Tunnel {
-	stream: UnicastStream,
+use super::{Identity, IdentityErr, RemoteIdentity};
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Signed with the identity so the Noise session is bound to it
+const STATIC_KEY_DOMAIN: &[u8] = b"spacetunnel-noise-static-key:";
+/// The max size of a Noise message
+const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+const TAG_LEN: usize = 16;
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+/// The identity's public key followed by its signature of our Noise static key
+const AUTH_PAYLOAD_LEN: usize = 32 + 64;
+
+#[derive(Debug, Error)]
+pub enum TunnelError {
+	#[error("io error: {0}")]
+	Io(#[from] io::Error),
+	#[error("invalid discriminator. Is this stream actually a tunnel?")]
+	InvalidDiscriminator,
+	#[error("noise error: {0}")]
+	Noise(#[from] snow::Error),
+	#[error("invalid identity: {0}")]
+	InvalidIdentity(#[from] IdentityErr),
+	#[error("the peer's identity didn't sign the session")]
+	InvalidSignature,
+}
+
+/// An end-to-end encrypted stream between two identities.
+///
+/// The peers do a Noise XX handshake and each of them signs its Noise static key with its
+/// [`Identity`], so the session can only be established by the holder of the identity. It's up to
+/// the caller to check [`Tunnel::remote_identity`] is one it trusts.
+pub struct Tunnel<S = UnicastStream> {
+	stream: S,
+	noise: snow::TransportState,
+	remote_identity: RemoteIdentity,
+	/// The frame being read, its length once we know it
+	frame: Vec<u8>,
+	frame_len: Option<usize>,
+	filled: usize,
+	/// Decrypted data that wasn't read yet
+	plaintext: Vec<u8>,
+	plaintext_pos: usize,
+	/// The encrypted frame being written and how many bytes of the caller's buffer it holds
+	write_frame: Vec<u8>,
+	write_pos: usize,
+	write_len: usize,
 }
 
-impl Tunnel {
-	// TODO: Proper errors
-	pub async fn from_stream(mut stream: UnicastStream) -> Result<Self, &'static str> {
-		let discriminator = stream
-			.read_u8()
-			.await
-			.map_err(|_| "Error reading discriminator. Is this stream actually a tunnel?")?;
-		if discriminator != b'T' {
-			return Err("Invalid discriminator. Is this stream actually a tunnel?");
+impl<S: AsyncRead + AsyncWrite + Unpin> Tunnel<S> {
+	/// Opens a tunnel over a stream we started, the remote peer accepts it with [`Tunnel::responder`]
+	pub async fn initiator(mut stream: S, identity: &Identity) -> Result<Self, TunnelError> {
+		stream.write_all(&[b'T']).await?;
+
+		let keypair = snow::Builder::new(params()).generate_keypair()?;
+		let mut noise = snow::Builder::new(params())
+			.local_private_key(&keypair.private)
+			.build_initiator()?;
+
+		// -> e
+		write_handshake(&mut stream, &mut noise, &[]).await?;
+		// <- e, ee, s, es
+		let payload = read_handshake(&mut stream, &mut noise).await?;
+		let remote_identity = verify_auth_payload(&noise, &payload)?;
+		// -> s, se
+		let payload = auth_payload(identity, &keypair.public);
+		write_handshake(&mut stream, &mut noise, &payload).await?;
+
+		Ok(Self::new(
+			stream,
+			noise.into_transport_mode()?,
+			remote_identity,
+		))
+	}
+
+	/// Accepts a tunnel opened by the remote peer with [`Tunnel::initiator`]
+	pub async fn responder(mut stream: S, identity: &Identity) -> Result<Self, TunnelError> {
+		if stream.read_u8().await? != b'T' {
+			return Err(TunnelError::InvalidDiscriminator);
+		}
+
+		let keypair = snow::Builder::new(params()).generate_keypair()?;
+		let mut noise = snow::Builder::new(params())
+			.local_private_key(&keypair.private)
+			.build_responder()?;
+
+		// -> e
+		read_handshake(&mut stream, &mut noise).await?;
+		// <- e, ee, s, es
+		let payload = auth_payload(identity, &keypair.public);
+		write_handshake(&mut stream, &mut noise, &payload).await?;
+		// -> s, se
+		let payload = read_handshake(&mut stream, &mut noise).await?;
+		let remote_identity = verify_auth_payload(&noise, &payload)?;
+
+		Ok(Self::new(
+			stream,
+			noise.into_transport_mode()?,
+			remote_identity,
+		))
+	}
+
+	fn new(stream: S, noise: snow::TransportState, remote_identity: RemoteIdentity) -> Self {
+		Self {
+			stream,
+			noise,
+			remote_identity,
+			frame: Vec::new(),
+			frame_len: None,
+			filled: 0,
+			plaintext: Vec::new(),
+			plaintext_pos: 0,
+			write_frame: Vec::new(),
+			write_pos: 0,
+			write_len: 0,
 		}
+	}
+
+	/// The identity the peer proved it holds
+	pub fn remote_identity(&self) -> &RemoteIdentity {
+		&self.remote_identity
+	}
+
+	/// Writes the rest of the frame that's being written
+	fn poll_write_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while self.write_pos < self.write_frame.len() {
+			let n = ready!(
+				Pin::new(&mut self.stream).poll_write(cx, &self.write_frame[self.write_pos..])
+			)?;
+			if n == 0 {
+				return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+			}
+			self.write_pos += n;
+		}
+
+		self.write_frame.clear();
+		self.write_pos = 0;
+		Poll::Ready(Ok(()))
+	}
+}
 
-		// TODO: Do pairing
+fn params() -> snow::params::NoiseParams {
+	NOISE_PARAMS.parse().expect("valid noise params")
+}
 
-		Ok(Self { stream })
+fn auth_payload(identity: &Identity, static_key: &[u8]) -> Vec<u8> {
+	let mut payload = Vec::with_capacity(AUTH_PAYLOAD_LEN);
+	payload.extend_from_slice(&identity.to_remote_identity().to_bytes());
+	payload.extend_from_slice(&identity.sign(&[STATIC_KEY_DOMAIN, static_key].concat()));
+	payload
+}
+
+fn verify_auth_payload(
+	noise: &snow::HandshakeState,
+	payload: &[u8],
+) -> Result<RemoteIdentity, TunnelError> {
+	if payload.len() != AUTH_PAYLOAD_LEN {
+		return Err(TunnelError::InvalidSignature);
 	}
+
+	let static_key = noise
+		.get_remote_static()
+		.ok_or(TunnelError::InvalidSignature)?;
+	let identity = RemoteIdentity::from_bytes(&payload[..32])?;
+	identity
+		.verify(&[STATIC_KEY_DOMAIN, static_key].concat(), &payload[32..])
+		.map_err(|_| TunnelError::InvalidSignature)?;
+
+	Ok(identity)
 }
 
-impl AsyncRead for Tunnel {
+async fn write_handshake(
+	stream: &mut (impl AsyncWrite + Unpin),
+	noise: &mut snow::HandshakeState,
+	payload: &[u8],
+) -> Result<(), TunnelError> {
+	let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+	let len = noise.write_message(payload, &mut buf)?;
+
+	stream.write_all(&(len as u16).to_be_bytes()).await?;
+	stream.write_all(&buf[..len]).await?;
+	Ok(())
+}
+
+async fn read_handshake(
+	stream: &mut (impl AsyncRead + Unpin),
+	noise: &mut snow::HandshakeState,
+) -> Result<Vec<u8>, TunnelError> {
+	let len = stream.read_u16().await? as usize;
+	let mut message = vec![0u8; len];
+	stream.read_exact(&mut message).await?;
+
+	let mut payload = vec![0u8; len];
+	let len = noise.read_message(&message, &mut payload)?;
+	payload.truncate(len);
+	Ok(payload)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Tunnel<S> {
 	fn poll_read(
 		self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
 		buf: &mut ReadBuf<'_>,
 	) -> Poll<io::Result<()>> {
-		// TODO: Do decryption
+		let this = self.get_mut();
+
+		loop {
+			if this.plaintext_pos < this.plaintext.len() {
+				let n = buf
+					.remaining()
+					.min(this.plaintext.len() - this.plaintext_pos);
+				buf.put_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + n]);
+				this.plaintext_pos += n;
+				return Poll::Ready(Ok(()));
+			}
 
-		Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+			// Frames are the length of the message followed by the message
+			let needed = this.frame_len.unwrap_or(2);
+			if this.frame.len() < needed {
+				this.frame.resize(needed, 0);
+			}
+
+			while this.filled < needed {
+				let mut read_buf = ReadBuf::new(&mut this.frame[this.filled..needed]);
+				ready!(Pin::new(&mut this.stream).poll_read(cx, &mut read_buf))?;
+
+				match read_buf.filled().len() {
+					// The stream was closed in between frames
+					0 if this.filled == 0 && this.frame_len.is_none() => {
+						return Poll::Ready(Ok(()))
+					}
+					0 => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+					n => this.filled += n,
+				}
+			}
+			this.filled = 0;
+
+			match this.frame_len.take() {
+				None => {
+					this.frame_len =
+						Some(u16::from_be_bytes([this.frame[0], this.frame[1]]) as usize)
+				}
+				Some(len) => {
+					this.plaintext.resize(len, 0);
+					let len = this
+						.noise
+						.read_message(&this.frame[..len], &mut this.plaintext)
+						.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+					this.plaintext.truncate(len);
+					this.plaintext_pos = 0;
+				}
+			}
+		}
 	}
 }
 
-impl AsyncWrite for Tunnel {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Tunnel<S> {
 	fn poll_write(
 		self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
 		buf: &[u8],
 	) -> Poll<io::Result<usize>> {
-		// TODO: Do encryption
+		let this = self.get_mut();
+
+		// If the last call returned pending, `buf` starts with the data that's already encrypted
+		if this.write_frame.is_empty() {
+			if buf.is_empty() {
+				return Poll::Ready(Ok(0));
+			}
+			let len = buf.len().min(MAX_PLAINTEXT_LEN);
+
+			this.write_frame.resize(2 + len + TAG_LEN, 0);
+			let len = this
+				.noise
+				.write_message(&buf[..len], &mut this.write_frame[2..])
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+			this.write_frame[..2].copy_from_slice(&(len as u16).to_be_bytes());
+			this.write_frame.truncate(2 + len);
+			this.write_len = len - TAG_LEN;
+		}
 
-		Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+		ready!(this.poll_write_frame(cx))?;
+		Poll::Ready(Ok(this.write_len))
 	}
 
 	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+		let this = self.get_mut();
+		ready!(this.poll_write_frame(cx))?;
+		Pin::new(&mut this.stream).poll_flush(cx)
 	}
 
 	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+		let this = self.get_mut();
+		ready!(this.poll_write_frame(cx))?;
+		Pin::new(&mut this.stream).poll_shutdown(cx)
 	}
 }
 
-// TODO: Unit tests
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn tunnel() {
+		let (initiator, responder) = (Identity::new(), Identity::new());
+		let (a, b) = tokio::io::duplex(64);
+
+		let (a, b) = tokio::join!(
+			Tunnel::initiator(a, &initiator),
+			Tunnel::responder(b, &responder)
+		);
+		let (mut a, mut b) = (a.unwrap(), b.unwrap());
+
+		assert_eq!(a.remote_identity(), &responder.to_remote_identity());
+		assert_eq!(b.remote_identity(), &initiator.to_remote_identity());
+
+		// Bigger than a frame
+		let data = (0..200_000).map(|i| i as u8).collect::<Vec<_>>();
+		let (_, received) = tokio::join!(
+			async {
+				a.write_all(&data).await.unwrap();
+				a.shutdown().await.unwrap();
+			},
+			async {
+				let mut received = Vec::new();
+				b.read_to_end(&mut received).await.unwrap();
+				received
+			}
+		);
+
+		assert_eq!(received, data);
+	}
+
+	#[tokio::test]
+	async fn forged_identity() {
+		let (a, mut b) = tokio::io::duplex(1024);
+
+		let initiator = tokio::spawn(async move { Tunnel::initiator(a, &Identity::new()).await });
+
+		// Pretends to be an identity without its private key
+		assert_eq!(b.read_u8().await.unwrap(), b'T');
+		let keypair = snow::Builder::new(params()).generate_keypair().unwrap();
+		let mut noise = snow::Builder::new(params())
+			.local_private_key(&keypair.private)
+			.build_responder()
+			.unwrap();
+		read_handshake(&mut b, &mut noise).await.unwrap();
+
+		let mut payload = auth_payload(&Identity::new(), &keypair.public);
+		payload[..32].copy_from_slice(&Identity::new().to_remote_identity().to_bytes());
+		write_handshake(&mut b, &mut noise, &payload).await.unwrap();
+
+		assert!(matches!(
+			initiator.await.unwrap(),
+			Err(TunnelError::InvalidSignature)
+		));
+	}
+}
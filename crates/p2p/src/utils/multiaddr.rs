@@ -2,6 +2,8 @@ use std::net::{IpAddr, SocketAddr};
 
 use libp2p::{multiaddr::Protocol, Multiaddr};
 
+use crate::PeerId;
+
 // TODO: Turn these into From/Into impls on a wrapper type
 
 pub(crate) fn quic_multiaddr_to_socketaddr(m: Multiaddr) -> Result<SocketAddr, String> {
@@ -43,3 +45,21 @@ pub(crate) fn socketaddr_to_quic_multiaddr(m: &SocketAddr) -> Multiaddr {
 	addr.push(Protocol::QuicV1);
 	addr
 }
+
+/// Checks if the address is one of a peer we reach through a relay
+pub(crate) fn is_relayed(m: &Multiaddr) -> bool {
+	m.iter().any(|proto| proto == Protocol::P2pCircuit)
+}
+
+/// A relay must be addressed with its peer id, so the connection to it can be authenticated
+pub(crate) fn is_valid_relay(m: &Multiaddr) -> bool {
+	matches!(m.iter().last(), Some(Protocol::P2p(_))) && !is_relayed(m)
+}
+
+/// The address of a peer that's reached through the relay
+pub(crate) fn relayed_multiaddr(relay: &Multiaddr, peer_id: PeerId) -> Multiaddr {
+	relay
+		.clone()
+		.with(Protocol::P2pCircuit)
+		.with(Protocol::P2p(peer_id.0.into()))
+}
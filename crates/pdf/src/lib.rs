@@ -0,0 +1,174 @@
+use std::{
+	env, fs,
+	io::Read,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+	thread,
+	time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use pdfium_render::prelude::{PdfRenderConfig, Pdfium, PdfiumError};
+use thiserror::Error;
+
+type PdfResult<T> = Result<T, PdfError>;
+
+/// The maximum file size that a document can be in order to have a preview generated.
+///
+/// This value is in MiB.
+const PDF_MAXIMUM_FILE_SIZE: u64 = 1048576 * 100;
+
+/// Office documents are converted to PDF by LibreOffice before being rendered.
+const OFFICE_CONVERTER_BINARY: &str = "soffice";
+
+/// How long LibreOffice is given to convert a document before it's killed.
+const OFFICE_CONVERSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the conversion is checked for completion.
+const OFFICE_CONVERSION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Extensions that LibreOffice knows how to convert to PDF.
+pub const OFFICE_EXTENSIONS: [&str; 9] = [
+	"doc", "docx", "odt", "ppt", "pptx", "odp", "xls", "xlsx", "ods",
+];
+
+#[derive(Error, Debug)]
+pub enum PdfError {
+	#[error("error with pdfium: {0}")]
+	Pdfium(#[from] PdfiumError),
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("the document has no pages")]
+	NoPages,
+	#[error("the document provided is too large (over 100MiB)")]
+	TooLarge,
+	#[error("failed to convert office document to pdf: {0}")]
+	OfficeConversion(String),
+	#[error("converting the office document to pdf took longer than {0:?}")]
+	OfficeConversionTimeout(Duration),
+}
+
+/// The directories bundled with the app that may hold the pdfium library, next to the executable
+/// or in the `Frameworks` of the app bundle on macOS. The working directory is never searched, as
+/// anyone able to write there could make us load their library.
+fn bundled_library_dirs() -> Vec<PathBuf> {
+	let Some(exe_dir) = env::current_exe()
+		.ok()
+		.and_then(|exe| exe.parent().map(Path::to_path_buf))
+	else {
+		return vec![];
+	};
+
+	vec![
+		#[cfg(target_os = "macos")]
+		exe_dir.join("../Frameworks"),
+		exe_dir,
+	]
+}
+
+/// Binds to the pdfium library bundled with the app, or to the one of the system.
+fn bind_pdfium() -> PdfResult<Pdfium> {
+	let bindings = bundled_library_dirs()
+		.into_iter()
+		.find_map(|dir| {
+			Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(dir.to_str()?))
+				.ok()
+		})
+		.map_or_else(Pdfium::bind_to_system_library, Ok)?;
+
+	Ok(Pdfium::new(bindings))
+}
+
+/// Renders the first page of a PDF file, with its largest side scaled to `target_size` pixels.
+pub fn pdf_to_dynamic_image(path: &Path, target_size: u16) -> PdfResult<DynamicImage> {
+	if fs::metadata(path)?.len() > PDF_MAXIMUM_FILE_SIZE {
+		return Err(PdfError::TooLarge);
+	}
+
+	let pdfium = bind_pdfium()?;
+
+	let document = pdfium.load_pdf_from_file(path, None)?;
+	let page = document.pages().first().map_err(|_| PdfError::NoPages)?;
+
+	let config = PdfRenderConfig::new()
+		.set_target_width(target_size)
+		.set_maximum_height(target_size)
+		.render_form_data(true);
+
+	Ok(page.render_with_config(&config)?.as_image())
+}
+
+/// Converts an office document (docx, pptx, odt, ...) to PDF using LibreOffice, then renders its
+/// first page. LibreOffice must be installed and available in `PATH`.
+pub fn office_to_dynamic_image(path: &Path, target_size: u16) -> PdfResult<DynamicImage> {
+	if fs::metadata(path)?.len() > PDF_MAXIMUM_FILE_SIZE {
+		return Err(PdfError::TooLarge);
+	}
+
+	// the temporary directory (and the converted pdf) is removed when dropped
+	let out_dir = tempfile::tempdir()?;
+
+	let mut child = Command::new(OFFICE_CONVERTER_BINARY)
+		.args(["--headless", "--convert-to", "pdf", "--outdir"])
+		.arg(out_dir.path())
+		.arg(path)
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.spawn()?;
+
+	// Read while converting, as LibreOffice would block on a full pipe until it's killed
+	let stderr_reader = child.stderr.take().map(|mut pipe| {
+		thread::spawn(move || {
+			let mut stderr = String::new();
+			pipe.read_to_string(&mut stderr).ok();
+			stderr
+		})
+	});
+
+	// A document LibreOffice chokes on would otherwise hang the thumbnailer worker forever
+	let started_at = Instant::now();
+	let status = loop {
+		if let Some(status) = child.try_wait()? {
+			break status;
+		}
+
+		if started_at.elapsed() >= OFFICE_CONVERSION_TIMEOUT {
+			// The child may have exited in the meantime, which makes killing it fail
+			child.kill().ok();
+			child.wait()?;
+			return Err(PdfError::OfficeConversionTimeout(OFFICE_CONVERSION_TIMEOUT));
+		}
+
+		thread::sleep(OFFICE_CONVERSION_POLL_INTERVAL);
+	};
+
+	if !status.success() {
+		let stderr = stderr_reader
+			.and_then(|reader| reader.join().ok())
+			.unwrap_or_default();
+
+		return Err(PdfError::OfficeConversion(stderr));
+	}
+
+	let converted_path = converted_pdf_path(out_dir.path(), path)
+		.ok_or_else(|| PdfError::OfficeConversion("converted document not found".to_string()))?;
+
+	pdf_to_dynamic_image(&converted_path, target_size)
+}
+
+/// Checks if the provided extension is an office document that can be converted to PDF.
+pub fn is_office_extension(extension: &str) -> bool {
+	OFFICE_EXTENSIONS
+		.iter()
+		.any(|ext| extension.eq_ignore_ascii_case(ext))
+}
+
+fn converted_pdf_path(out_dir: &Path, original_path: &Path) -> Option<PathBuf> {
+	// Not `with_extension`, which would replace the last dot-separated part of a stem like `v1.2`
+	let converted_path = out_dir.join(format!(
+		"{}.pdf",
+		original_path.file_stem()?.to_string_lossy()
+	));
+
+	converted_path.exists().then_some(converted_path)
+}
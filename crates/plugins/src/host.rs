@@ -0,0 +1,138 @@
+//! The functions the host gives plugins, in the `sd` import module, each behind a capability
+
+use std::{
+	fs::File,
+	io::{Read, Seek, SeekFrom},
+};
+
+use tracing::info;
+use wasmtime::{Caller, Extern, Instance, Linker, Memory, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::{Capability, Manifest};
+
+const HOST_MODULE: &str = "sd";
+
+/// The largest read a plugin can make of its file at once
+const MAX_READ_BYTES: u32 = 16 * 1024 * 1024;
+
+pub(crate) struct HostState {
+	plugin_id: String,
+	/// The file a metadata extractor can read
+	file: Option<File>,
+	pub(crate) limits: StoreLimits,
+}
+
+impl HostState {
+	pub(crate) fn new(manifest: &Manifest, file: Option<File>, max_memory_bytes: usize) -> Self {
+		Self {
+			plugin_id: manifest.id.clone(),
+			file,
+			limits: StoreLimitsBuilder::new()
+				.memory_size(max_memory_bytes)
+				.instances(1)
+				.build(),
+		}
+	}
+}
+
+fn capability_of(name: &str) -> Option<Capability> {
+	match name {
+		"log" => Some(Capability::Log),
+		"file_read" => Some(Capability::ReadFile),
+		_ => None,
+	}
+}
+
+pub(crate) fn is_allowed(module: &str, name: &str, capabilities: &[Capability]) -> bool {
+	module == HOST_MODULE
+		&& capability_of(name).map_or(false, |capability| capabilities.contains(&capability))
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+	match caller.get_export("memory") {
+		Some(Extern::Memory(memory)) => Ok(memory),
+		_ => Err(wasmtime::Error::msg("the module doesn't export its memory")),
+	}
+}
+
+/// Defines the host functions of `capabilities`, and only those
+pub(crate) fn link(
+	linker: &mut Linker<HostState>,
+	capabilities: &[Capability],
+) -> wasmtime::Result<()> {
+	if capabilities.contains(&Capability::Log) {
+		// log(ptr, len) logs the UTF-8 message at ptr
+		linker.func_wrap(
+			HOST_MODULE,
+			"log",
+			|mut caller: Caller<'_, HostState>, ptr: u32, len: u32| -> wasmtime::Result<()> {
+				let memory = memory(&mut caller)?;
+				let mut message = vec![0; len.min(MAX_READ_BYTES) as usize];
+				memory.read(&caller, ptr as usize, &mut message)?;
+
+				info!(
+					"Plugin '{}': {}",
+					caller.data().plugin_id,
+					String::from_utf8_lossy(&message)
+				);
+
+				Ok(())
+			},
+		)?;
+	}
+
+	if capabilities.contains(&Capability::ReadFile) {
+		// file_read(offset, ptr, len) reads up to len bytes of the file at offset to ptr, returning
+		// how many it read, or -1 without a file
+		linker.func_wrap(
+			HOST_MODULE,
+			"file_read",
+			|mut caller: Caller<'_, HostState>,
+			 offset: u64,
+			 ptr: u32,
+			 len: u32|
+			 -> wasmtime::Result<i32> {
+				let memory = memory(&mut caller)?;
+				let Some(file) = caller.data_mut().file.as_mut() else {
+					return Ok(-1);
+				};
+
+				let mut buf = vec![0; len.min(MAX_READ_BYTES) as usize];
+				file.seek(SeekFrom::Start(offset))?;
+				let read = file.read(&mut buf)?;
+
+				memory.write(&mut caller, ptr as usize, &buf[..read])?;
+
+				Ok(read as i32)
+			},
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Writes `input` to memory from `sd_alloc`, calls `export` with it and reads back its output
+pub(crate) fn call_json(
+	store: &mut Store<HostState>,
+	instance: &Instance,
+	export: &str,
+	input: &[u8],
+) -> wasmtime::Result<Vec<u8>> {
+	let memory = instance
+		.get_memory(&mut *store, "memory")
+		.ok_or_else(|| wasmtime::Error::msg("the module doesn't export its memory"))?;
+	let alloc = instance.get_typed_func::<u32, u32>(&mut *store, "sd_alloc")?;
+	let func = instance.get_typed_func::<(u32, u32), u64>(&mut *store, export)?;
+
+	let input_ptr = alloc.call(&mut *store, input.len() as u32)?;
+	memory.write(&mut *store, input_ptr as usize, input)?;
+
+	let packed = func.call(&mut *store, (input_ptr, input.len() as u32))?;
+	let (output_ptr, output_len) = ((packed >> 32) as usize, packed as u32 as usize);
+
+	memory
+		.data(&*store)
+		.get(output_ptr..output_ptr + output_len)
+		.map(<[u8]>::to_vec)
+		.ok_or_else(|| wasmtime::Error::msg("the output is out of the module's memory"))
+}
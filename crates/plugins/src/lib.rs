@@ -0,0 +1,416 @@
+//! WebAssembly plugins, adding metadata extractors, jobs and search providers to Spacedrive without
+//! forking it. A plugin is a directory with a `plugin.json` manifest and a WebAssembly module.
+//!
+//! Plugins only reach the host through the functions of the capabilities their manifest declares,
+//! a module importing any other is refused when it's loaded. Each call runs in a new instance, with
+//! a bounded memory and a bounded amount of fuel, so a plugin keeps no state between calls and a
+//! plugin stuck in a loop is stopped.
+//!
+//! A module exports its memory, `sd_alloc(len: u32) -> u32` giving the host somewhere to write the
+//! input of a call, and the functions of what it provides. They take the pointer and length of
+//! their JSON input and return the pointer and length of their JSON output, packed in a `u64` with
+//! the pointer in the high bits:
+//!
+//! - `sd_extract_metadata`, for [`Provides::extensions`], from [`ExtractInput`] to any JSON
+//! - `sd_search`, for [`Provides::search`], from [`SearchInput`] to [`SearchResult`]s
+//! - `sd_job_init` and `sd_job_step`, for [`Provides::jobs`], from [`JobInitInput`] to
+//!   [`JobInitOutput`] then from each [`JobStepInput`] to a [`JobStepOutput`]
+
+use std::{
+	fs::{self, File},
+	path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use wasmtime::{Config, Engine, Linker, Module, Store};
+
+mod host;
+
+use host::HostState;
+
+pub const MANIFEST_FILE_NAME: &str = "plugin.json";
+
+/// The fuel a call can burn, roughly a WebAssembly instruction each
+const FUEL_PER_CALL: u64 = 10_000_000_000;
+/// The memory an instance can grow to
+const MAX_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+type PluginResult<T> = Result<T, PluginError>;
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+	#[error("io error on {}: {source}", .path.display())]
+	Io {
+		path: Box<Path>,
+		source: std::io::Error,
+	},
+	#[error("invalid manifest {}: {source}", .path.display())]
+	Manifest {
+		path: Box<Path>,
+		source: serde_json::Error,
+	},
+	#[error("plugin '{plugin}' imports '{import}' without declaring the capability it needs")]
+	CapabilityNotDeclared { plugin: String, import: String },
+	#[error("plugin '{0}' doesn't provide {1}")]
+	NotProvided(String, &'static str),
+	#[error("plugin '{plugin}' returned invalid JSON from {export}: {source}")]
+	Output {
+		plugin: String,
+		export: &'static str,
+		source: serde_json::Error,
+	},
+	#[error("error running plugin '{0}': {1}")]
+	Wasm(String, wasmtime::Error),
+}
+
+/// What a plugin can reach of the host
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capability {
+	/// Writing to the logs of the node
+	Log,
+	/// Reading the file metadata is extracted from, and only that one
+	ReadFile,
+}
+
+/// What a plugin adds to Spacedrive
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Provides {
+	/// The extensions of the files it extracts metadata from
+	#[serde(default)]
+	pub extensions: Vec<String>,
+	/// Whether it searches something
+	#[serde(default)]
+	pub search: bool,
+	/// The names of the jobs it runs
+	#[serde(default)]
+	pub jobs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Manifest {
+	/// Unique, like `com.example.exif`
+	pub id: String,
+	pub name: String,
+	pub version: String,
+	#[serde(default)]
+	pub description: Option<String>,
+	/// The module, relative to the directory of the manifest
+	pub module: PathBuf,
+	#[serde(default)]
+	pub capabilities: Vec<Capability>,
+	#[serde(default)]
+	pub provides: Provides,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExtractInput<'a> {
+	pub name: &'a str,
+	pub extension: &'a str,
+	pub size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchInput<'a> {
+	pub query: &'a str,
+	pub take: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResult {
+	pub title: String,
+	#[serde(default)]
+	pub subtitle: Option<String>,
+	/// Where the result leads, like a web page
+	#[serde(default)]
+	pub uri: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobInitInput<'a> {
+	pub job: &'a str,
+	pub args: &'a Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JobInitOutput {
+	pub steps: Vec<Value>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobStepInput<'a> {
+	pub job: &'a str,
+	pub step: &'a Value,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct JobStepOutput {
+	#[serde(default)]
+	pub errors: Vec<String>,
+}
+
+/// A loaded plugin, its module compiled once and instantiated for each call
+pub struct Plugin {
+	manifest: Manifest,
+	engine: Engine,
+	module: Module,
+}
+
+impl Plugin {
+	/// Loads the plugin in `directory`, refusing it if its module imports more than its
+	/// capabilities allow
+	pub fn load(directory: &Path) -> PluginResult<Self> {
+		let manifest_path = directory.join(MANIFEST_FILE_NAME);
+		let manifest_bytes = fs::read(&manifest_path).map_err(|source| PluginError::Io {
+			path: manifest_path.clone().into(),
+			source,
+		})?;
+		let manifest = serde_json::from_slice::<Manifest>(&manifest_bytes).map_err(|source| {
+			PluginError::Manifest {
+				path: manifest_path.into(),
+				source,
+			}
+		})?;
+
+		let module_path = directory.join(&manifest.module);
+		let module_bytes = fs::read(&module_path).map_err(|source| PluginError::Io {
+			path: module_path.into(),
+			source,
+		})?;
+
+		Self::from_module(manifest, &module_bytes)
+	}
+
+	fn from_module(manifest: Manifest, module_bytes: &[u8]) -> PluginResult<Self> {
+		let mut config = Config::new();
+		config.consume_fuel(true);
+
+		let engine = Engine::new(&config).map_err(|e| PluginError::Wasm(manifest.id.clone(), e))?;
+		let module = Module::new(&engine, module_bytes)
+			.map_err(|e| PluginError::Wasm(manifest.id.clone(), e))?;
+
+		for import in module.imports() {
+			if !host::is_allowed(import.module(), import.name(), &manifest.capabilities) {
+				return Err(PluginError::CapabilityNotDeclared {
+					plugin: manifest.id,
+					import: format!("{}::{}", import.module(), import.name()),
+				});
+			}
+		}
+
+		Ok(Self {
+			manifest,
+			engine,
+			module,
+		})
+	}
+
+	pub fn manifest(&self) -> &Manifest {
+		&self.manifest
+	}
+
+	pub fn id(&self) -> &str {
+		&self.manifest.id
+	}
+
+	/// Whether the plugin extracts metadata from files with this extension
+	pub fn extracts(&self, extension: &str) -> bool {
+		self.manifest
+			.provides
+			.extensions
+			.iter()
+			.any(|e| e.eq_ignore_ascii_case(extension))
+	}
+
+	/// Extracts the metadata of the file at `path`, `None` when the plugin found none
+	pub fn extract_metadata(
+		&self,
+		path: &Path,
+		input: &ExtractInput,
+	) -> PluginResult<Option<Value>> {
+		if !self.extracts(input.extension) {
+			return Err(PluginError::NotProvided(self.id().to_string(), "metadata"));
+		}
+
+		let file = File::open(path).map_err(|source| PluginError::Io {
+			path: path.into(),
+			source,
+		})?;
+
+		let metadata = self.call::<Value>("sd_extract_metadata", input, Some(file))?;
+
+		Ok((!metadata.is_null()).then_some(metadata))
+	}
+
+	pub fn search(&self, input: &SearchInput) -> PluginResult<Vec<SearchResult>> {
+		if !self.manifest.provides.search {
+			return Err(PluginError::NotProvided(self.id().to_string(), "search"));
+		}
+
+		self.call("sd_search", input, None)
+	}
+
+	fn check_job(&self, job: &str) -> PluginResult<()> {
+		if self.manifest.provides.jobs.iter().any(|name| name == job) {
+			Ok(())
+		} else {
+			Err(PluginError::NotProvided(self.id().to_string(), "this job"))
+		}
+	}
+
+	pub fn job_init(&self, input: &JobInitInput) -> PluginResult<JobInitOutput> {
+		self.check_job(input.job)?;
+		self.call("sd_job_init", input, None)
+	}
+
+	pub fn job_step(&self, input: &JobStepInput) -> PluginResult<JobStepOutput> {
+		self.check_job(input.job)?;
+		self.call("sd_job_step", input, None)
+	}
+
+	/// Calls `export` with `input` in a new instance, with `file` as the file it can read
+	fn call<T: DeserializeOwned>(
+		&self,
+		export: &'static str,
+		input: &impl Serialize,
+		file: Option<File>,
+	) -> PluginResult<T> {
+		let wasm_error = |e| PluginError::Wasm(self.id().to_string(), e);
+
+		let mut store = Store::new(
+			&self.engine,
+			HostState::new(&self.manifest, file, MAX_MEMORY_BYTES),
+		);
+		store.limiter(|state| &mut state.limits);
+		store.add_fuel(FUEL_PER_CALL).map_err(wasm_error)?;
+
+		let mut linker = Linker::new(&self.engine);
+		host::link(&mut linker, &self.manifest.capabilities).map_err(wasm_error)?;
+
+		let instance = linker
+			.instantiate(&mut store, &self.module)
+			.map_err(wasm_error)?;
+
+		let input = serde_json::to_vec(input).expect("inputs always serialize");
+		let output = host::call_json(&mut store, &instance, export, &input).map_err(wasm_error)?;
+
+		serde_json::from_slice(&output).map_err(|source| PluginError::Output {
+			plugin: self.id().to_string(),
+			export,
+			source,
+		})
+	}
+}
+
+/// Loads every plugin in the directories of `plugins_dir`, returning the ones that couldn't be
+/// loaded separately so a broken plugin doesn't keep the others from loading
+pub fn load_all(plugins_dir: &Path) -> (Vec<Plugin>, Vec<PluginError>) {
+	let mut plugins = vec![];
+	let mut errors = vec![];
+
+	let Ok(entries) = fs::read_dir(plugins_dir) else {
+		return (plugins, errors);
+	};
+
+	for entry in entries.flatten() {
+		let path = entry.path();
+		if !path.join(MANIFEST_FILE_NAME).is_file() {
+			continue;
+		}
+
+		match Plugin::load(&path) {
+			Ok(plugin) => plugins.push(plugin),
+			Err(e) => errors.push(e),
+		}
+	}
+
+	(plugins, errors)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	/// Logs the input of a search and finds nothing
+	const ECHO: &str = r#"
+		(module
+			(import "sd" "log" (func $log (param i32 i32)))
+			(memory (export "memory") 1)
+			(global $next (mut i32) (i32.const 1024))
+			(func (export "sd_alloc") (param $len i32) (result i32)
+				(local $ptr i32)
+				(local.set $ptr (global.get $next))
+				(global.set $next (i32.add (global.get $next) (local.get $len)))
+				(local.get $ptr))
+			(func (export "sd_search") (param $ptr i32) (param $len i32) (result i64)
+				(call $log (local.get $ptr) (local.get $len))
+				(i64.or
+					(i64.shl (i64.extend_i32_u (i32.const 16)) (i64.const 32))
+					(i64.const 2)))
+			(data (i32.const 16) "[]"))
+	"#;
+
+	fn manifest(capabilities: Vec<Capability>) -> Manifest {
+		Manifest {
+			id: "com.example.echo".to_string(),
+			name: "Echo".to_string(),
+			version: "1.0.0".to_string(),
+			description: None,
+			module: PathBuf::from("echo.wasm"),
+			capabilities,
+			provides: Provides {
+				search: true,
+				..Default::default()
+			},
+		}
+	}
+
+	#[test]
+	fn imports_need_their_capability() {
+		assert!(matches!(
+			Plugin::from_module(manifest(vec![]), ECHO.as_bytes()),
+			Err(PluginError::CapabilityNotDeclared { .. })
+		));
+	}
+
+	#[test]
+	fn calls_exchange_json() {
+		let plugin = Plugin::from_module(manifest(vec![Capability::Log]), ECHO.as_bytes()).unwrap();
+
+		let results = plugin
+			.search(&SearchInput {
+				query: "cat",
+				take: 10,
+			})
+			.unwrap();
+		assert!(results.is_empty());
+
+		assert!(matches!(
+			plugin.job_init(&JobInitInput {
+				job: "anything",
+				args: &json!(null),
+			}),
+			Err(PluginError::NotProvided(..))
+		));
+	}
+
+	#[test]
+	fn loads_plugins_from_their_directories() {
+		let dir = tempfile::tempdir().unwrap();
+		let plugin_dir = dir.path().join("echo");
+		fs::create_dir(&plugin_dir).unwrap();
+		fs::write(
+			plugin_dir.join(MANIFEST_FILE_NAME),
+			serde_json::to_vec(&manifest(vec![Capability::Log])).unwrap(),
+		)
+		.unwrap();
+		fs::write(plugin_dir.join("echo.wasm"), ECHO).unwrap();
+
+		let (plugins, errors) = load_all(dir.path());
+		assert!(errors.is_empty());
+		assert_eq!(plugins[0].id(), "com.example.echo");
+	}
+}
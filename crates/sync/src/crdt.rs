@@ -26,7 +26,15 @@ pub enum SharedOperationData {
 	#[serde(rename = "c")]
 	Create(Map<String, Value>),
 	#[serde(rename = "u")]
-	Update { field: String, value: Value },
+	Update {
+		field: String,
+		value: Value,
+		/// Timestamp of the last change to the field known by the node when it made this one,
+		/// changes made after it by other nodes are concurrent with this one
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		#[specta(type = Option<u32>)]
+		base: Option<NTP64>,
+	},
 	#[serde(rename = "d")]
 	Delete,
 }
@@ -163,7 +163,8 @@ export default (props: PropsWithChildren) => {
 							try {
 								await generateThumbsForLocation.mutateAsync({
 									id: locationId,
-									path: currentPath ?? '/'
+									path: currentPath ?? '/',
+									regenerate: false
 								});
 							} catch (error) {
 								showAlertDialog({
@@ -3,6 +3,7 @@ import {
 	Copy,
 	FileX,
 	Image,
+	Link,
 	Package,
 	Plus,
 	Scissors,
@@ -60,6 +61,7 @@ export default ({ data }: Props) => {
 	const fullRescan = useLibraryMutation('locations.fullRescan');
 	const removeFromRecents = useLibraryMutation('files.removeAccessTime');
 	const generateThumbnails = useLibraryMutation('jobs.generateThumbsForLocation');
+	const createShareLink = useLibraryMutation('shareLinks.create');
 
 	if (!data) return null;
 
@@ -198,6 +200,27 @@ export default ({ data }: Props) => {
 				disabled
 			/>
 
+			{objectData && (
+				<ContextMenu.Item
+					label="Copy share link"
+					icon={Link}
+					onClick={async () => {
+						try {
+							const token = await createShareLink.mutateAsync({
+								object_id: objectData.id,
+								date_expires: null
+							});
+							await navigator.clipboard.writeText(token);
+						} catch (error) {
+							showAlertDialog({
+								title: 'Error',
+								value: `Failed to create a share link, due to an error: ${error}`
+							});
+						}
+					}}
+				/>
+			)}
+
 			<ContextMenu.Separator />
 
 			{objectData && (
@@ -308,7 +331,8 @@ export default ({ data }: Props) => {
 								try {
 									await generateThumbnails.mutateAsync({
 										id: locationId,
-										path: currentPath ?? '/'
+										path: currentPath ?? '/',
+										regenerate: false
 									});
 								} catch (error) {
 									showAlertDialog({
@@ -270,6 +270,7 @@ export const RenameLocationTextBox = (props: Omit<Props, 'renameHandler'>) => {
 				name: newName,
 				generate_preview_media: null,
 				sync_preview_media: null,
+				generate_video_thumbnails: null,
 				hidden: null,
 				indexer_rules_ids: []
 			});
@@ -1,18 +1,20 @@
 import { getIcon, iconNames } from '@sd/assets/util';
 import clsx from 'clsx';
 import { ImgHTMLAttributes, memo, useEffect, useLayoutEffect, useRef, useState } from 'react';
-import { ExplorerItem, getItemLocation, useLibraryContext } from '@sd/client';
+import { ExplorerItem, getItemLocation, getItemObject, useLibraryContext } from '@sd/client';
 import { PDFViewer } from '~/components';
 import {
 	getExplorerStore,
 	useCallbackToWatchResize,
 	useExplorerItemData,
 	useExplorerStore,
-	useIsDark
+	useIsDark,
+	usePrioritizeThumbnail
 } from '~/hooks';
 import { usePlatform } from '~/util/Platform';
 import { pdfViewerEnabled } from '~/util/pdfViewer';
 import classes from './Thumb.module.scss';
+import VideoScrubber from './VideoScrubber';
 
 interface ThumbnailProps {
 	src: string;
@@ -125,6 +127,11 @@ function FileThumb({ size, cover, ...props }: ThumbProps) {
 	const [thumbType, setThumbType] = useState(ThumbType.Icon);
 	const { locationId: explorerLocationId } = useExplorerStore();
 
+	usePrioritizeThumbnail(
+		itemData.casId,
+		!props.loadOriginal && !itemData.isDir && !itemData.hasLocalThumbnail
+	);
+
 	// useLayoutEffect is required to ensure the thumbType is always updated before the onError listener can execute,
 	// thus avoiding improper thumb types changes
 	useLayoutEffect(() => {
@@ -204,7 +211,8 @@ function FileThumb({ size, cover, ...props }: ThumbProps) {
 		});
 	};
 
-	const { kind, extension } = itemData;
+	const { kind, extension, thumbnailKey } = itemData;
+	const objectId = getItemObject(props.data)?.id;
 	const childClassName = 'max-h-full max-w-full object-contain';
 	return (
 		<div
@@ -303,38 +311,46 @@ function FileThumb({ size, cover, ...props }: ThumbProps) {
 					// eslint-disable-next-line no-fallthrough
 					case ThumbType.Thumbnail:
 						return (
-							<Thumbnail
-								src={src}
-								cover={cover}
-								onLoad={onLoad}
-								onError={onError}
-								decoding={size ? 'async' : 'sync'}
-								className={clsx(
-									cover
-										? 'min-h-full min-w-full object-cover object-center'
-										: childClassName,
-									kind === 'Video' ? 'rounded' : 'rounded-sm',
-									ThumbType.Original || [
-										classes.checkers,
-										'shadow shadow-black/30'
-									],
-									size &&
-										(kind === 'Video'
-											? 'border-x-0 border-black'
-											: size > 60 && 'border-2 border-app-line'),
-									props.className
-								)}
-								crossOrigin={ThumbType.Original && 'anonymous'} // Here it is ok, because it is not a react attr
-								videoBarsSize={
-									(kind === 'Video' && size && Math.floor(size / 10)) || 0
-								}
-								videoExtension={
-									(kind === 'Video' &&
-										(cover || size == null || size > 80) &&
-										extension) ||
-									''
-								}
-							/>
+							<>
+								<Thumbnail
+									src={src}
+									cover={cover}
+									onLoad={onLoad}
+									onError={onError}
+									decoding={size ? 'async' : 'sync'}
+									className={clsx(
+										cover
+											? 'min-h-full min-w-full object-cover object-center'
+											: childClassName,
+										kind === 'Video' ? 'rounded' : 'rounded-sm',
+										ThumbType.Original || [
+											classes.checkers,
+											'shadow shadow-black/30'
+										],
+										size &&
+											(kind === 'Video'
+												? 'border-x-0 border-black'
+												: size > 60 && 'border-2 border-app-line'),
+										props.className
+									)}
+									crossOrigin={ThumbType.Original && 'anonymous'} // Here it is ok, because it is not a react attr
+									videoBarsSize={
+										(kind === 'Video' && size && Math.floor(size / 10)) || 0
+									}
+									videoExtension={
+										(kind === 'Video' &&
+											(cover || size == null || size > 80) &&
+											extension) ||
+										''
+									}
+								/>
+								{kind === 'Video' &&
+									thumbType === ThumbType.Thumbnail &&
+									objectId != null &&
+									thumbnailKey && (
+										<VideoScrubber objectId={objectId} thumbnailKey={thumbnailKey} />
+									)}
+							</>
 						);
 					default:
 						return (
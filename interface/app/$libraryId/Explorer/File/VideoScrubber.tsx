@@ -0,0 +1,57 @@
+import { useState } from 'react';
+import { useLibraryQuery } from '@sd/client';
+import { usePlatform } from '~/util/Platform';
+
+interface Props {
+	objectId: number;
+	thumbnailKey: string[];
+}
+
+// Shows frames across the video's timeline while hovering its thumbnail, taken from the sprite
+// sheet generated by the thumbnailer
+export default function VideoScrubber({ objectId, thumbnailKey }: Props) {
+	const platform = usePlatform();
+	const [hovering, setHovering] = useState(false);
+	const [position, setPosition] = useState(0);
+
+	// Only fetched once the thumbnail is hovered, most of them never are
+	const sprite = useLibraryQuery(['files.getVideoSprite', objectId], { enabled: hovering });
+
+	const layout = sprite.data;
+	const frame =
+		layout && Math.min(Math.floor(position * layout.frame_count), layout.frame_count - 1);
+
+	return (
+		<div
+			className="absolute inset-0"
+			onMouseEnter={() => setHovering(true)}
+			onMouseLeave={() => setHovering(false)}
+			onMouseMove={(e) => {
+				const rect = e.currentTarget.getBoundingClientRect();
+				setPosition(Math.max(0, (e.clientX - rect.left) / rect.width));
+			}}
+		>
+			{hovering && layout && frame != null && (
+				<>
+					<div
+						className="h-full w-full rounded bg-black bg-no-repeat"
+						style={{
+							backgroundImage: `url("${platform.getSpriteUrlByThumbKey(thumbnailKey)}")`,
+							backgroundSize: `${layout.columns * 100}% ${layout.rows * 100}%`,
+							backgroundPosition: `${
+								((frame % layout.columns) / Math.max(layout.columns - 1, 1)) * 100
+							}% ${
+								(Math.floor(frame / layout.columns) / Math.max(layout.rows - 1, 1)) *
+								100
+							}%`
+						}}
+					/>
+					<div
+						className="absolute bottom-0 left-0 h-0.5 bg-accent"
+						style={{ width: `${((frame + 1) / layout.frame_count) * 100}%` }}
+					/>
+				</>
+			)}
+		</div>
+	);
+}
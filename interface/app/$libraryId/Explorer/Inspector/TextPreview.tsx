@@ -0,0 +1,30 @@
+import { useLibraryQuery } from '@sd/client';
+import { MetaContainer, MetaTitle } from '.';
+
+interface Props {
+	objectId: number;
+}
+
+export default function TextPreview({ objectId }: Props) {
+	const textPreview = useLibraryQuery(['files.getTextPreview', objectId]);
+
+	const preview = textPreview.data;
+	if (!preview || preview.content.length === 0) return null;
+
+	return (
+		<MetaContainer>
+			<div className="flex items-center justify-between">
+				<MetaTitle>Preview</MetaTitle>
+				{preview.language && (
+					<span className="rounded bg-app-box px-1.5 py-0.5 text-tiny text-ink-dull">
+						{preview.language}
+					</span>
+				)}
+			</div>
+			<pre className="mt-1 max-h-64 overflow-auto whitespace-pre-wrap break-all rounded bg-app-box p-2 font-mono text-tiny text-ink-dull">
+				{preview.content}
+				{preview.truncated && '\n…'}
+			</pre>
+		</MetaContainer>
+	);
+}
@@ -0,0 +1,36 @@
+import { useLibraryQuery } from '@sd/client';
+import { MetaContainer, MetaTitle } from '.';
+
+interface Props {
+	objectId: number;
+}
+
+export default function Waveform({ objectId }: Props) {
+	const waveform = useLibraryQuery(['files.getWaveform', objectId]);
+
+	const peaks = waveform.data;
+	if (!peaks || peaks.length === 0) return null;
+
+	return (
+		<MetaContainer>
+			<MetaTitle>Waveform</MetaTitle>
+			<svg
+				className="mt-1 h-10 w-full text-accent"
+				viewBox={`0 0 ${peaks.length} 255`}
+				preserveAspectRatio="none"
+			>
+				{peaks.map((peak, i) => (
+					<rect
+						key={i}
+						x={i}
+						// Peaks are drawn centered, mirrored like most audio players do
+						y={(255 - peak) / 2}
+						width={0.7}
+						height={Math.max(peak, 2)}
+						fill="currentColor"
+					/>
+				))}
+			</svg>
+		</MetaContainer>
+	);
+}
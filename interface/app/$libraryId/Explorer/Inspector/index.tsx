@@ -3,12 +3,25 @@ import { Image, Image_Light } from '@sd/assets/icons';
 import byteSize from 'byte-size';
 import clsx from 'clsx';
 import dayjs from 'dayjs';
-import { Barcode, CircleWavyCheck, Clock, Cube, Hash, Link, Lock, Snowflake } from 'phosphor-react';
+import {
+	Barcode,
+	CircleWavyCheck,
+	Clock,
+	CloudArrowUp,
+	CloudCheck,
+	CloudSlash,
+	Cube,
+	Hash,
+	Link,
+	Lock,
+	Snowflake
+} from 'phosphor-react';
 import { HTMLAttributes, useEffect, useState } from 'react';
 import {
 	ExplorerItem,
 	Location,
 	ObjectKind,
+	RecordSyncStatus,
 	Tag,
 	bytesToNumber,
 	getItemFilePath,
@@ -22,6 +35,8 @@ import AssignTagMenuItems from '../AssignTagMenuItems';
 import FileThumb from '../File/Thumb';
 import FavoriteButton from './FavoriteButton';
 import Note from './Note';
+import TextPreview from './TextPreview';
+import Waveform from './Waveform';
 
 export const InfoPill = tw.span`inline border border-transparent px-1 text-[11px] font-medium shadow shadow-app-shade/5 bg-app-selected rounded-md text-ink-dull`;
 export const PlaceholderPill = tw.span`inline border px-1 text-[11px] shadow shadow-app-shade/10 rounded-md bg-transparent border-dashed border-app-active transition hover:text-ink-faint hover:border-ink-faint font-medium text-ink-faint/70`;
@@ -37,6 +52,34 @@ const InspectorIcon = ({ component: Icon, ...props }: any) => (
 	<Icon weight="bold" {...props} className={clsx('mr-2 shrink-0', props.className)} />
 );
 
+const SYNC_STATES = {
+	localOnly: { icon: CloudSlash, label: 'Local only' },
+	pending: { icon: CloudArrowUp, label: 'Pending sync' },
+	synced: { icon: CloudCheck, label: 'Synced' }
+};
+
+const SyncStatus = ({ status }: { status: RecordSyncStatus }) => {
+	const { icon, label } = SYNC_STATES[status.state];
+	const names = (nodes: RecordSyncStatus['synced_with']) => nodes.map((n) => n.name).join(', ');
+
+	const tooltip = [
+		status.synced_with.length > 0 && `Synced with ${names(status.synced_with)}`,
+		status.pending_with.length > 0 && `Pending for ${names(status.pending_with)}`
+	]
+		.filter(Boolean)
+		.join('; ');
+
+	return (
+		<Tooltip label={tooltip || 'Not synced with any paired node'}>
+			<MetaTextLine>
+				<InspectorIcon component={icon} />
+				<MetaKeyName className="mr-1.5">Sync</MetaKeyName>
+				<MetaValue>{label}</MetaValue>
+			</MetaTextLine>
+		</Tooltip>
+	);
+};
+
 interface Props extends HTMLAttributes<HTMLDivElement> {
 	context?: Location | Tag;
 	data?: ExplorerItem;
@@ -69,6 +112,11 @@ export const Inspector = ({ data, context, showThumbnail = true, ...props }: Pro
 		enabled: readyToFetch && objectData?.id !== undefined
 	});
 
+	const syncStatus = useLibraryQuery(
+		['sync.status', { model: 'object', ids: objectData ? [objectData.id] : [] }],
+		{ enabled: readyToFetch && objectData?.id !== undefined }
+	);
+
 	const item = data?.item;
 
 	// map array of numbers into string
@@ -196,6 +244,20 @@ export const Inspector = ({ data, context, showThumbnail = true, ...props }: Pro
 							</Tooltip>
 						</MetaContainer>
 
+						{readyToFetch && objectData?.kind === ObjectKind.Audio && (
+							<>
+								<Waveform objectId={objectData.id} />
+								<Divider />
+							</>
+						)}
+						{readyToFetch &&
+							(objectData?.kind === ObjectKind.Text ||
+								objectData?.kind === ObjectKind.Code) && (
+								<>
+									<TextPreview objectId={objectData.id} />
+									<Divider />
+								</>
+							)}
 						{!isDir && objectData && (
 							<>
 								<Note data={objectData} />
@@ -232,6 +294,9 @@ export const Inspector = ({ data, context, showThumbnail = true, ...props }: Pro
 											</MetaTextLine>
 										</Tooltip>
 									)}
+									{syncStatus.data?.[0] && (
+										<SyncStatus status={syncStatus.data[0]} />
+									)}
 								</MetaContainer>
 							</>
 						)}
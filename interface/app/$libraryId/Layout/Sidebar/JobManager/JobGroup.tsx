@@ -151,7 +151,7 @@ function JobGroup({ data: { jobs, ...data }, clearJob }: JobGroupProps) {
 									text:
 										(!showChildJobs &&
 											isJobsRunning &&
-											realtimeUpdate?.message) ||
+											realtimeUpdate?.message?.text) ||
 										undefined
 								}
 							]
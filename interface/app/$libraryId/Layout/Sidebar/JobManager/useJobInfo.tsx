@@ -29,8 +29,8 @@ export default function useJobInfo(
 				[
 					{
 						text:
-							isPaused ? job.message : isRunning && realtimeUpdate?.message
-								? realtimeUpdate.message
+							isPaused ? job.message?.text : isRunning && realtimeUpdate?.message
+								? realtimeUpdate.message.text
 								: `${comma(meta?.data?.total_paths)} ${plural(
 									meta?.data?.total_paths,
 									'path'
@@ -60,6 +60,43 @@ export default function useJobInfo(
 				]
 			]
 		},
+		thumbnail_integrity: {
+			name: `${isQueued ? 'Check' : isRunning ? 'Checking' : 'Checked'} thumbnails`,
+			icon: Image,
+			textItems: isRunning
+				? [[{ text: realtimeUpdate?.message?.text }]]
+				: [
+					[
+						{
+							text: `${comma(meta?.thumbnails_checked)} ${plural(
+								meta?.thumbnails_checked,
+								'thumbnail'
+							)} checked`
+						},
+						{
+							text:
+								(meta?.missing_regenerated || meta?.corrupted_regenerated) &&
+								`${comma(
+									(meta?.missing_regenerated || 0) +
+									(meta?.corrupted_regenerated || 0)
+								)} regenerated`
+						},
+						{
+							text:
+								meta?.regeneration_failed &&
+								`${comma(meta?.regeneration_failed)} failed`
+						},
+						{
+							text:
+								meta?.orphans_pruned &&
+								`${comma(meta?.orphans_pruned)} ${plural(
+									meta?.orphans_pruned,
+									'orphan'
+								)} removed`
+						}
+					]
+				]
+		},
 		file_identifier: {
 			name: `${isQueued ? 'Extract' : isRunning ? 'Extracting' : 'Extracted'} metadata`,
 			icon: Fingerprint,
@@ -87,7 +124,7 @@ export default function useJobInfo(
 								)} linked`
 							}
 						]
-					: [{ text: realtimeUpdate?.message }]
+					: [{ text: realtimeUpdate?.message?.text }]
 			]
 		},
 		file_copier: {
@@ -23,7 +23,7 @@ export default function LocationOptions({ location, path }: { location: Location
 	const scanLocation = () => _scanLocation.mutate(location.id);
 
 	const _regenThumbs = useLibraryMutation('jobs.generateThumbsForLocation');
-	const regenThumbs = () => _regenThumbs.mutate({ id: location.id, path });
+	const regenThumbs = () => _regenThumbs.mutate({ id: location.id, path, regenerate: true });
 
 	const archiveLocation = () => alert('Not implemented');
 
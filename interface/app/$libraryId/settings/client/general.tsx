@@ -27,7 +27,8 @@ export const Component = () => {
 
 	useDebouncedFormWatch(form, async (value) => {
 		await editNode.mutateAsync({
-			name: value.name || null
+			name: value.name || null,
+			thumbnail_size: null
 		});
 
 		node.refetch();
@@ -1,5 +1,19 @@
-import { MaybeUndefined, useBridgeMutation, useLibraryContext } from '@sd/client';
-import { Button, Input, Switch, Tooltip, dialogManager } from '@sd/ui';
+import {
+	MaybeUndefined,
+	useBridgeMutation,
+	useLibraryContext,
+	useLibraryMutation
+} from '@sd/client';
+import {
+	Button,
+	Input,
+	Select,
+	SelectOption,
+	Slider,
+	Switch,
+	Tooltip,
+	dialogManager
+} from '@sd/ui';
 import { useZodForm, z } from '@sd/ui/src/forms';
 import { useDebouncedFormWatch } from '~/hooks';
 import { Heading } from '../Layout';
@@ -9,7 +23,9 @@ import DeleteLibraryDialog from '../node/libraries/DeleteDialog';
 const schema = z.object({
 	id: z.string(),
 	name: z.string().min(1),
-	description: z.string().nullable()
+	description: z.string().nullable(),
+	thumbnail_format: z.enum(['webp', 'avif']),
+	thumbnail_quality: z.number().min(0).max(100)
 });
 
 // TODO: With some extra upstream Specta work this should be able to be removed
@@ -20,22 +36,36 @@ function toMaybeUndefined<T>(v: T | null | undefined): MaybeUndefined<T> {
 export const Component = () => {
 	const { library } = useLibraryContext();
 	const editLibrary = useBridgeMutation('library.edit');
+	const patchSettings = useLibraryMutation('library.patchSettings');
+	const checkThumbnails = useLibraryMutation('jobs.checkThumbnailIntegrity');
 
 	const form = useZodForm({
 		schema,
 		defaultValues: {
 			id: library!.uuid,
-			...library?.config
+			...library?.config,
+			thumbnail_format: library?.config.settings.thumbnail_format,
+			thumbnail_quality: library?.config.settings.thumbnail_quality
 		}
 	});
 
-	useDebouncedFormWatch(form, (value) =>
+	useDebouncedFormWatch(form, (value) => {
 		editLibrary.mutate({
 			id: library.uuid,
 			name: value.name ?? null,
-			description: toMaybeUndefined(value.description)
-		})
-	);
+			description: toMaybeUndefined(value.description),
+			sync_conflict_policy: null
+		});
+		patchSettings.mutate({
+			thumbnail_format: value.thumbnail_format ?? null,
+			thumbnail_quality: value.thumbnail_quality ?? null,
+			identifier_chunk_size: null,
+			max_concurrent_jobs: null,
+			quiet_hours: toMaybeUndefined(undefined)
+		});
+	});
+
+	const thumbnailQuality = form.watch('thumbnail_quality');
 
 	return (
 		<>
@@ -61,6 +91,61 @@ export const Component = () => {
 				</div>
 			</div>
 
+			<Setting
+				mini
+				title="Thumbnail Format"
+				description="AVIF thumbnails take less space than WebP ones, but take longer to generate. Existing thumbnails are kept until they are regenerated."
+			>
+				<div className="ml-3 flex items-center">
+					<Select
+						size="sm"
+						value={form.watch('thumbnail_format')}
+						onChange={(value) =>
+							form.setValue('thumbnail_format', value as 'webp' | 'avif')
+						}
+					>
+						<SelectOption value="webp">WebP</SelectOption>
+						<SelectOption value="avif">AVIF</SelectOption>
+					</Select>
+				</div>
+			</Setting>
+
+			<Setting
+				mini
+				title="Thumbnail Quality"
+				description="Higher quality thumbnails look sharper, but make the thumbnail cache bigger."
+			>
+				<div className="ml-3 flex w-40 items-center space-x-2">
+					<Slider
+						value={[thumbnailQuality]}
+						min={0}
+						max={100}
+						step={5}
+						onValueChange={([value]) =>
+							value !== undefined && form.setValue('thumbnail_quality', value)
+						}
+					/>
+					<span className="w-8 text-sm font-medium">{thumbnailQuality}</span>
+				</div>
+			</Setting>
+
+			<Setting
+				mini
+				title="Check Thumbnails"
+				description="Regenerates missing or corrupted thumbnails, and removes the ones no file uses anymore."
+			>
+				<div className="mt-2">
+					<Button
+						size="sm"
+						variant="gray"
+						disabled={checkThumbnails.isLoading}
+						onClick={() => checkThumbnails.mutate(null)}
+					>
+						Check
+					</Button>
+				</div>
+			</Setting>
+
 			<Setting
 				mini
 				title="Encrypt Library"
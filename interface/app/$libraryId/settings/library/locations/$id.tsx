@@ -21,7 +21,8 @@ const schema = z.object({
 	indexerRulesIds: z.array(z.number()),
 	locationType: z.string(),
 	syncPreviewMedia: z.boolean().nullable(),
-	generatePreviewMedia: z.boolean().nullable()
+	generatePreviewMedia: z.boolean().nullable(),
+	generateVideoThumbnails: z.boolean().nullable()
 });
 
 export const Component = () => {
@@ -69,7 +70,8 @@ const EditLocationForm = () => {
 			path: locationData.data?.path ?? '',
 			hidden: locationData.data?.hidden ?? false,
 			syncPreviewMedia: locationData.data?.sync_preview_media ?? false,
-			generatePreviewMedia: locationData.data?.generate_preview_media ?? false
+			generatePreviewMedia: locationData.data?.generate_preview_media ?? false,
+			generateVideoThumbnails: locationData.data?.generate_video_thumbnails ?? true
 		}
 	});
 
@@ -89,14 +91,22 @@ const EditLocationForm = () => {
 	const { isDirty } = form.formState;
 
 	const onSubmit = form.handleSubmit(
-		({ name, hidden, indexerRulesIds, syncPreviewMedia, generatePreviewMedia }) =>
+		({
+			name,
+			hidden,
+			indexerRulesIds,
+			syncPreviewMedia,
+			generatePreviewMedia,
+			generateVideoThumbnails
+		}) =>
 			updateLocation.mutateAsync({
 				id: locationId,
 				name,
 				hidden,
 				indexer_rules_ids: indexerRulesIds,
 				sync_preview_media: syncPreviewMedia,
-				generate_preview_media: generatePreviewMedia
+				generate_preview_media: generatePreviewMedia,
+				generate_video_thumbnails: generateVideoThumbnails
 			})
 	);
 
@@ -187,6 +197,15 @@ const EditLocationForm = () => {
 						</Label>
 						<Switch {...form.register('syncPreviewMedia')} size="sm" />
 					</ToggleSection>
+					<ToggleSection>
+						<Label className="grow">
+							Generate thumbnails for videos in this Location{' '}
+							<Tooltip label="Extracting frames from videos is CPU intensive, disabling it can speed up indexing large video collections.">
+								<Info className="inline" />
+							</Tooltip>
+						</Label>
+						<Switch {...form.register('generateVideoThumbnails')} size="sm" />
+					</ToggleSection>
 					<ToggleSection>
 						<Label className="grow">
 							Hide location and contents from view{' '}
@@ -1,5 +1,12 @@
-import { useDiscoveredPeers, useFeatureFlag, useLibraryMutation } from '@sd/client';
-import { Button } from '@sd/ui';
+import { useState } from 'react';
+import QRCode from 'react-qr-code';
+import {
+	useDiscoveredPeers,
+	useFeatureFlag,
+	useLibraryMutation,
+	useLibraryQuery
+} from '@sd/client';
+import { Button, Input } from '@sd/ui';
 import { Heading } from '../Layout';
 
 export const Component = () => {
@@ -14,25 +21,27 @@ export const Component = () => {
 
 			{/* TODO: Show paired nodes + unpair button */}
 
-			{isPairingEnabled && <IncorrectP2PPairingPane />}
+			{isPairingEnabled && <PairingPane />}
 		</>
 	);
 };
 
-// TODO: This entire component shows a UI which is pairing by node but that is just not how it works.
-function IncorrectP2PPairingPane() {
+function PairingPane() {
 	const onlineNodes = useDiscoveredPeers();
-	const p2pPair = useLibraryMutation('p2p.pair', {
-		onSuccess(data) {
-			console.log(data);
-		}
-	});
+	const [showPayload, setShowPayload] = useState(false);
+	const [payload, setPayload] = useState('');
 
-	console.log(onlineNodes);
+	const p2pPair = useLibraryMutation('p2p.pair');
+	const pairWithPayload = useLibraryMutation('p2p.pairWithPayload', {
+		onSuccess: () => setPayload('')
+	});
 
 	return (
 		<>
 			<h1>Pairing</h1>
+			<p className="text-sm text-gray-400">
+				Both nodes will show a code, check it's the same before confirming.
+			</p>
 			{[...onlineNodes.entries()].map(([id, node]) => (
 				<div key={id} className="flex space-x-2">
 					<p>{node.name}</p>
@@ -40,6 +49,57 @@ function IncorrectP2PPairingPane() {
 					<Button onClick={() => p2pPair.mutate(id)}>Pair</Button>
 				</div>
 			))}
+
+			<h1>Pair with a code</h1>
+			<p className="text-sm text-gray-400">
+				Scan or paste the pairing code shown by the other node, it's paired without
+				comparing codes.
+			</p>
+			<div className="flex space-x-2">
+				<Input
+					className="grow"
+					value={payload}
+					placeholder="spacedrive-pair:..."
+					onChange={(e) => setPayload(e.target.value)}
+				/>
+				<Button
+					variant="accent"
+					disabled={payload.trim() === '' || pairWithPayload.isLoading}
+					onClick={() => pairWithPayload.mutate(payload.trim())}
+				>
+					Pair
+				</Button>
+			</div>
+			{pairWithPayload.error && (
+				<p className="text-sm text-red-500">{pairWithPayload.error.message}</p>
+			)}
+
+			{showPayload ? (
+				<PairingPayload />
+			) : (
+				<Button variant="gray" onClick={() => setShowPayload(true)}>
+					Show pairing code
+				</Button>
+			)}
 		</>
 	);
 }
+
+// A new payload is created every time this is shown and it expires after 5 minutes
+function PairingPayload() {
+	const payload = useLibraryQuery(['p2p.pairingPayload'], {
+		cacheTime: 0,
+		refetchOnWindowFocus: false
+	});
+
+	if (!payload.data) return null;
+
+	return (
+		<div className="flex flex-col items-center space-y-2">
+			<div className="rounded bg-white p-2">
+				<QRCode value={payload.data} size={160} />
+			</div>
+			<code className="select-all break-all text-xs">{payload.data}</code>
+		</div>
+	);
+}
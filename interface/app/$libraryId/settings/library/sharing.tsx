@@ -1,9 +1,330 @@
+import byteSize from 'byte-size';
+import { useState } from 'react';
+import {
+	SharedCollection,
+	SharingAccess,
+	useBridgeMutation,
+	useDiscoveredPeers,
+	useLibraryMutation,
+	useLibraryQuery
+} from '@sd/client';
+import { Button, Input, Select, SelectOption, Switch } from '@sd/ui';
 import { Heading } from '../Layout';
+import Setting from '../Setting';
 
 export const Component = () => {
+	const collections = useLibraryQuery(['sharing.list']);
+
+	const owned = collections.data?.filter((collection) => collection.tag_id !== null) ?? [];
+	const received = collections.data?.filter((collection) => collection.tag_id === null) ?? [];
+
 	return (
 		<>
 			<Heading title="Sharing" description="Manage who has access to your libraries." />
+
+			<ShareTag />
+
+			{owned.length > 0 && (
+				<Setting
+					title="Shared by you"
+					description="Tags of this library shared with other people's libraries."
+				>
+					<div className="flex flex-col gap-2">
+						{owned.map((collection) => (
+							<Collection key={collection.id} collection={collection} />
+						))}
+					</div>
+				</Setting>
+			)}
+
+			{received.length > 0 && (
+				<Setting
+					title="Shared with you"
+					description="Collections other people shared with this library. They are updated when the library that shared them is reachable."
+				>
+					<div className="flex flex-col gap-2">
+						{received.map((collection) => (
+							<Collection key={collection.id} collection={collection} />
+						))}
+					</div>
+				</Setting>
+			)}
+
+			<ShareLinks />
+			<RedeemShareLink />
 		</>
 	);
 };
+
+function ShareLinks() {
+	const links = useLibraryQuery(['shareLinks.list', null]);
+	const revoke = useLibraryMutation('shareLinks.revoke');
+
+	if (!links.data?.length) return null;
+
+	return (
+		<Setting
+			title="Share links"
+			description="Links to objects of this library. Anyone with a link can get the object from this node until it expires or is revoked."
+		>
+			<div className="flex flex-col gap-2">
+				{links.data.map((link) => (
+					<div key={link.id} className="flex items-center justify-between">
+						<div className="flex flex-col">
+							<span className="text-sm font-medium">Object {link.object_id}</span>
+							<span className="text-xs text-ink-dull">
+								{link.date_revoked
+									? 'revoked'
+									: link.date_expires
+									? `expires ${new Date(link.date_expires).toLocaleString()}`
+									: 'never expires'}{' '}
+								· redeemed {link.redemptions} times
+							</span>
+						</div>
+						<div className="flex space-x-2">
+							<Button
+								size="sm"
+								variant="gray"
+								onClick={() => navigator.clipboard.writeText(link.token)}
+							>
+								Copy
+							</Button>
+							<Button
+								size="sm"
+								variant="gray"
+								disabled={!!link.date_revoked || revoke.isLoading}
+								onClick={() => revoke.mutate(link.id)}
+							>
+								Revoke
+							</Button>
+						</div>
+					</div>
+				))}
+			</div>
+		</Setting>
+	);
+}
+
+function RedeemShareLink() {
+	const redeem = useBridgeMutation('shareLinks.redeem');
+	const [token, setToken] = useState('');
+
+	return (
+		<Setting
+			title="Redeem a share link"
+			description="Get an object someone shared with you from their node."
+		>
+			<div className="flex flex-col gap-2">
+				<div className="flex space-x-2">
+					<Input
+						size="sm"
+						className="grow"
+						value={token}
+						onChange={(e) => setToken(e.target.value)}
+						placeholder="spacedrive-share:..."
+					/>
+					<Button
+						size="sm"
+						variant="accent"
+						disabled={!token || redeem.isLoading}
+						onClick={() => redeem.mutate({ token, path: null })}
+					>
+						Redeem
+					</Button>
+				</div>
+				{redeem.data && (
+					<p className="text-xs text-ink-dull">
+						Got{' '}
+						{redeem.data.extension
+							? `${redeem.data.name}.${redeem.data.extension}`
+							: redeem.data.name}
+						{redeem.data.local_path
+							? ` saved to ${redeem.data.local_path}`
+							: ", its file isn't on the node that shared it"}
+					</p>
+				)}
+				{redeem.error && <p className="text-xs text-red-500">{redeem.error.message}</p>}
+			</div>
+		</Setting>
+	);
+}
+
+function ShareTag() {
+	const tags = useLibraryQuery(['tags.list']);
+	const discoveredPeers = useDiscoveredPeers();
+	const share = useLibraryMutation('sharing.share');
+
+	const [tagId, setTagId] = useState<string>();
+	const [peerId, setPeerId] = useState<string>();
+	const [access, setAccess] = useState<SharingAccess>('readOnly');
+	const [includeFiles, setIncludeFiles] = useState(false);
+
+	return (
+		<Setting
+			title="Share a tag"
+			description="Share the objects with a tag with someone else's library. They choose the library to add it to, and can also get the files if you include them."
+		>
+			<div className="flex flex-col gap-2">
+				<div className="flex space-x-2">
+					<Select size="sm" value={tagId ?? ''} onChange={setTagId} placeholder="Tag">
+						{tags.data?.map((tag) => (
+							<SelectOption key={tag.id} value={tag.id.toString()}>
+								{tag.name}
+							</SelectOption>
+						))}
+					</Select>
+					<Select size="sm" value={peerId ?? ''} onChange={setPeerId} placeholder="Node">
+						{[...discoveredPeers.entries()].map(([peerId, metadata]) => (
+							<SelectOption key={peerId} value={peerId}>
+								{metadata.name}
+							</SelectOption>
+						))}
+					</Select>
+					<Select
+						size="sm"
+						value={access}
+						onChange={(value) => setAccess(value as SharingAccess)}
+					>
+						<SelectOption value="readOnly">Can view</SelectOption>
+						<SelectOption value="readWrite">Can edit</SelectOption>
+					</Select>
+				</div>
+				<div className="flex items-center justify-between">
+					<span className="text-sm">Include the files</span>
+					<Switch size="sm" checked={includeFiles} onCheckedChange={setIncludeFiles} />
+				</div>
+				<div>
+					<Button
+						size="sm"
+						variant="accent"
+						disabled={!tagId || !peerId || share.isLoading}
+						onClick={() =>
+							tagId &&
+							peerId &&
+							share.mutate({
+								tag_id: Number(tagId),
+								peer_id: peerId,
+								access,
+								include_files: includeFiles
+							})
+						}
+					>
+						{share.isLoading ? 'Waiting for them to accept...' : 'Share'}
+					</Button>
+					{share.error && (
+						<p className="mt-1 text-xs text-red-500">{share.error.message}</p>
+					)}
+				</div>
+			</div>
+		</Setting>
+	);
+}
+
+function Collection({ collection }: { collection: SharedCollection }) {
+	const [open, setOpen] = useState(false);
+	const refresh = useLibraryMutation('sharing.refresh');
+	const unshare = useLibraryMutation('sharing.unshare');
+	const owned = collection.tag_id !== null;
+
+	return (
+		<div className="flex flex-col">
+			<div className="flex items-center justify-between">
+				<div className="flex flex-col">
+					<span className="text-sm font-medium">
+						{collection.color && (
+							<span
+								className="mr-1.5 inline-block h-2 w-2 rounded-full"
+								style={{ backgroundColor: collection.color }}
+							/>
+						)}
+						{collection.name}
+					</span>
+					<span className="text-xs text-ink-dull">
+						{owned ? 'with' : 'from'} {collection.library_name} ·{' '}
+						{collection.access === 'readWrite' ? 'can edit' : 'can view'}
+						{collection.include_files && ' · with files'}
+						{!owned &&
+							` · ${collection.item_count} items · updated ${
+								collection.date_synced
+									? new Date(collection.date_synced).toLocaleString()
+									: 'never'
+							}`}
+					</span>
+				</div>
+				<div className="flex space-x-2">
+					{!owned && (
+						<>
+							<Button size="sm" variant="gray" onClick={() => setOpen(!open)}>
+								{open ? 'Hide' : 'Show'}
+							</Button>
+							<Button
+								size="sm"
+								variant="gray"
+								disabled={refresh.isLoading}
+								onClick={() => refresh.mutate(collection.id)}
+							>
+								Update
+							</Button>
+						</>
+					)}
+					<Button
+						size="sm"
+						variant="gray"
+						disabled={unshare.isLoading}
+						onClick={() => unshare.mutate(collection.id)}
+					>
+						{owned ? 'Stop sharing' : 'Leave'}
+					</Button>
+				</div>
+			</div>
+			{open && <Items collection={collection} />}
+		</div>
+	);
+}
+
+function Items({ collection }: { collection: SharedCollection }) {
+	const items = useLibraryQuery(['sharing.items', collection.id]);
+	const edit = useLibraryMutation('sharing.edit');
+
+	return (
+		<div className="mt-2 flex flex-col gap-1 pl-4">
+			{items.data?.map((item) => (
+				<div key={item.id} className="flex items-center justify-between">
+					<span className="text-xs">
+						{item.extension ? `${item.name}.${item.extension}` : item.name}
+						<span className="ml-2 text-ink-dull">
+							{byteSize(Number(item.size_in_bytes)).toString()}
+							{collection.include_files && !item.local_path && ' · not downloaded'}
+						</span>
+					</span>
+					{collection.access === 'readWrite' && (
+						<Button
+							size="sm"
+							variant="gray"
+							disabled={edit.isLoading}
+							onClick={() =>
+								edit.mutate({
+									collection_id: collection.id,
+									edit: {
+										type: 'RemoveItem',
+										object_pub_id: uuidFromBytes(item.object_pub_id)
+									}
+								})
+							}
+						>
+							Remove
+						</Button>
+					)}
+				</div>
+			))}
+		</div>
+	);
+}
+
+function uuidFromBytes(bytes: number[]) {
+	const hex = bytes.map((b) => b.toString(16).padStart(2, '0')).join('');
+	return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(
+		16,
+		20
+	)}-${hex.slice(20)}`;
+}
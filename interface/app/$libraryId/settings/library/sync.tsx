@@ -1,9 +1,290 @@
+import byteSize from 'byte-size';
+import { Fragment } from 'react';
+import {
+	ConflictPolicy,
+	NodePermissions,
+	SyncNode,
+	TrustLevel,
+	useBridgeMutation,
+	useLibraryContext,
+	useLibraryMutation,
+	useLibraryQuery
+} from '@sd/client';
+import { Button, Select, SelectOption, Switch } from '@sd/ui';
 import { Heading } from '../Layout';
+import Setting from '../Setting';
 
 export const Component = () => {
+	const nodes = useLibraryQuery(['sync.nodes']);
+
 	return (
 		<>
 			<Heading title="Sync" description="Manage how Spacedrive syncs." />
+
+			<ConflictPolicySetting nodes={nodes.data ?? []} />
+
+			<Conflicts />
+
+			<Health />
+
+			{nodes.data?.map((node) => (
+				<Fragment key={node.id}>
+					<NodeScope node={node} />
+					<NodePermissionsSetting node={node} />
+				</Fragment>
+			))}
 		</>
 	);
 };
+
+function ConflictPolicySetting({ nodes }: { nodes: SyncNode[] }) {
+	const { library } = useLibraryContext();
+	const editLibrary = useBridgeMutation('library.edit');
+
+	const policy = library.config.sync_conflict_policy;
+	const value = typeof policy === 'string' ? policy : `preferNode:${policy.preferNode}`;
+
+	const setPolicy = (value: string) => {
+		const sync_conflict_policy: ConflictPolicy =
+			value === 'lastWriterWins' || value === 'manual'
+				? value
+				: { preferNode: value.replace('preferNode:', '') };
+
+		editLibrary.mutate({
+			id: library.uuid,
+			name: null,
+			description: library.config.description,
+			sync_conflict_policy
+		});
+	};
+
+	return (
+		<Setting
+			mini
+			title="Conflicts"
+			description="How changes made to the same field on different nodes at the same time are settled. Every node of the library should use the same policy."
+		>
+			<div className="ml-3 flex items-center">
+				<Select size="sm" value={value} onChange={setPolicy}>
+					<SelectOption value="lastWriterWins">Keep the latest change</SelectOption>
+					<SelectOption value={`preferNode:${library.config.node_id}`}>
+						Prefer this node
+					</SelectOption>
+					{nodes.map((node) => (
+						<SelectOption key={node.id} value={`preferNode:${node.pub_id}`}>
+							Prefer {node.name}
+						</SelectOption>
+					))}
+					<SelectOption value="manual">Ask me</SelectOption>
+				</Select>
+			</div>
+		</Setting>
+	);
+}
+
+function Conflicts() {
+	const conflicts = useLibraryQuery(['sync.conflicts', { pending: true }]);
+	const resolveConflict = useLibraryMutation('sync.resolveConflict');
+
+	if (!conflicts.data?.length) return null;
+
+	return (
+		<Setting
+			title="Unresolved Conflicts"
+			description="These fields were changed on two nodes at the same time. Choose which value to keep, or keep both for lists."
+		>
+			<div className="flex flex-col gap-2">
+				{conflicts.data.map((conflict) => (
+					<div key={conflict.id} className="flex items-center justify-between">
+						<span className="text-sm">
+							{conflict.model} {conflict.field}
+						</span>
+						<div className="flex space-x-2">
+							<Button
+								size="sm"
+								variant="gray"
+								onClick={() =>
+									resolveConflict.mutate({ id: conflict.id, resolution: 'keepCurrent' })
+								}
+							>
+								{conflict.node_name}: {JSON.stringify(conflict.value)}
+							</Button>
+							<Button
+								size="sm"
+								variant="gray"
+								onClick={() =>
+									resolveConflict.mutate({ id: conflict.id, resolution: 'useOther' })
+								}
+							>
+								{conflict.other_node_name}: {JSON.stringify(conflict.other_value)}
+							</Button>
+							{Array.isArray(conflict.value) && Array.isArray(conflict.other_value) && (
+								<Button
+									size="sm"
+									variant="gray"
+									onClick={() =>
+										resolveConflict.mutate({
+											id: conflict.id,
+											resolution: {
+												merge: [
+													...new Set([
+														...conflict.value,
+														...conflict.other_value
+													])
+												]
+											}
+										})
+									}
+								>
+									Keep both
+								</Button>
+							)}
+						</div>
+					</div>
+				))}
+			</div>
+		</Setting>
+	);
+}
+
+function Health() {
+	const health = useLibraryQuery(['sync.health'], { refetchInterval: 5000 });
+
+	if (!health.data?.length) return null;
+
+	return (
+		<Setting
+			title="Health"
+			description="How syncing with each node went since Spacedrive started. Pending changes are sent once the node is reachable."
+		>
+			<div className="flex flex-col gap-2">
+				{health.data.map((node) => (
+					<div key={node.id} className="flex flex-col">
+						<span className="text-sm font-medium">
+							{node.name}
+							{node.behind && ' (behind)'}
+						</span>
+						<span className="text-xs text-ink-dull">
+							{node.pending_operations} pending changes · last synced{' '}
+							{node.last_exchange ? new Date(node.last_exchange).toLocaleString() : 'never'}
+							{node.round_trip_ms !== null && ` · ${node.round_trip_ms}ms round trip`}
+							{` · ${byteSize(Number(node.bytes_sent))} sent, ${byteSize(
+								Number(node.bytes_received)
+							)} received`}
+							{node.clock_skew_ms !== null &&
+								` · clock ${Math.abs(node.clock_skew_ms / 1000).toFixed(1)}s ${
+									node.clock_skew_ms >= 0 ? 'ahead' : 'behind'
+								}`}
+						</span>
+					</div>
+				))}
+			</div>
+		</Setting>
+	);
+}
+
+function NodeScope({ node }: { node: SyncNode }) {
+	const locations = useLibraryQuery(['locations.list']);
+	const tags = useLibraryQuery(['tags.list']);
+	const setScope = useLibraryMutation('sync.setScope');
+
+	const toggle = (list: number[] | null, id: number) => {
+		const next = list?.includes(id) ? list.filter((i) => i !== id) : [...(list ?? []), id];
+		return next.length > 0 ? next : null;
+	};
+
+	return (
+		<Setting
+			title={node.name}
+			description="Choose the locations and tags synced with this node. Nothing selected syncs everything. Objects are synced if they are in a synced location or have a synced tag."
+		>
+			<div className="flex flex-col gap-2">
+				{locations.data?.map((location) => (
+					<div key={location.id} className="flex items-center justify-between">
+						<span className="text-sm">{location.name}</span>
+						<Switch
+							size="sm"
+							checked={node.locations?.includes(location.id) ?? false}
+							onCheckedChange={() =>
+								setScope.mutate({
+									node_id: node.id,
+									locations: toggle(node.locations, location.id),
+									tags: node.tags
+								})
+							}
+						/>
+					</div>
+				))}
+				{tags.data?.map((tag) => (
+					<div key={tag.id} className="flex items-center justify-between">
+						<span className="text-sm">{tag.name}</span>
+						<Switch
+							size="sm"
+							checked={node.tags?.includes(tag.id) ?? false}
+							onCheckedChange={() =>
+								setScope.mutate({
+									node_id: node.id,
+									locations: node.locations,
+									tags: toggle(node.tags, tag.id)
+								})
+							}
+						/>
+					</div>
+				))}
+			</div>
+		</Setting>
+	);
+}
+
+const PERMISSIONS: { key: keyof NodePermissions; name: string }[] = [
+	{ key: 'spacedrop', name: 'Send files with Spacedrop' },
+	{ key: 'sync', name: 'Sync this library' },
+	{ key: 'read_files', name: 'Browse and read files' },
+	{ key: 'delete', name: 'Delete items' }
+];
+
+function NodePermissionsSetting({ node }: { node: SyncNode }) {
+	const setPermissions = useLibraryMutation('sync.setPermissions');
+	const setTrustLevel = useLibraryMutation('sync.setTrustLevel');
+
+	return (
+		<Setting
+			title={`${node.name} Permissions`}
+			description="What this node is allowed to do with this library. Blocked nodes stay paired but can't do anything."
+		>
+			<div className="flex flex-col gap-2">
+				<div className="flex items-center justify-between">
+					<span className="text-sm">Trust level</span>
+					<Select
+						size="sm"
+						value={node.trust_level ?? 'custom'}
+						onChange={(level) => {
+							if (level !== 'custom')
+								setTrustLevel.mutate({ node_id: node.id, level: level as TrustLevel });
+						}}
+					>
+						<SelectOption value="full">Full</SelectOption>
+						<SelectOption value="limited">Limited</SelectOption>
+						<SelectOption value="blocked">Blocked</SelectOption>
+						{node.trust_level === null && <SelectOption value="custom">Custom</SelectOption>}
+					</Select>
+				</div>
+				{PERMISSIONS.map(({ key, name }) => (
+					<div key={key} className="flex items-center justify-between">
+						<span className="text-sm">{name}</span>
+						<Switch
+							size="sm"
+							checked={node.permissions[key]}
+							onCheckedChange={(checked) =>
+								setPermissions.mutate({
+									node_id: node.id,
+									permissions: { ...node.permissions, [key]: checked }
+								})
+							}
+						/>
+					</div>
+				))}
+			</div>
+		</Setting>
+	);
+}
@@ -3,10 +3,12 @@ import { useNavigate } from 'react-router-dom';
 import { LibraryConfigWrapped, useBridgeMutation, usePlausibleEvent } from '@sd/client';
 import { Dialog, UseDialogProps, forms, useDialog } from '@sd/ui';
 
-const { Input, z, useZodForm } = forms;
+const { Input, PasswordInput, CheckBox, z, useZodForm } = forms;
 
 const schema = z.object({
-	name: z.string().min(1)
+	name: z.string().min(1),
+	password: z.string(),
+	rememberPassword: z.boolean()
 });
 
 export default (props: UseDialogProps) => {
@@ -32,12 +34,22 @@ export default (props: UseDialogProps) => {
 		onError: (err) => console.log(err)
 	});
 
-	const form = useZodForm({ schema: schema });
+	const form = useZodForm({
+		schema: schema,
+		defaultValues: { password: '', rememberPassword: true }
+	});
+	const password = form.watch('password');
 
 	return (
 		<Dialog
 			form={form}
-			onSubmit={form.handleSubmit((data) => createLibrary.mutateAsync({ name: data.name }))}
+			onSubmit={form.handleSubmit((data) =>
+				createLibrary.mutateAsync({
+					name: data.name,
+					password: data.password || null,
+					remember_password: data.rememberPassword
+				})
+			)}
 			dialog={useDialog(props)}
 			submitDisabled={!form.formState.isValid}
 			title="Create New Library"
@@ -51,6 +63,19 @@ export default (props: UseDialogProps) => {
 					placeholder={'e.g. "James\' Library"'}
 					size="md"
 				/>
+				<PasswordInput
+					{...form.register('password')}
+					label="Encryption password (optional)"
+					placeholder="Leave empty to not encrypt the library"
+					size="md"
+					showStrength
+				/>
+				{password && (
+					<CheckBox
+						{...form.register('rememberPassword')}
+						label="Unlock with the system keychain on startup"
+					/>
+				)}
 			</div>
 		</Dialog>
 	);
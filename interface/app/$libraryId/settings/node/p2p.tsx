@@ -1,8 +1,48 @@
-import { Input, Switch } from '@sd/ui';
+import { useState } from 'react';
+import { RateLimits, SyncSchedule, useBridgeMutation, useBridgeQuery } from '@sd/client';
+import { Button, Input, Switch } from '@sd/ui';
 import { Heading } from '../Layout';
 import Setting from '../Setting';
 
 export const Component = () => {
+	const node = useBridgeQuery(['nodeState']);
+	const setBandwidthLimits = useBridgeMutation('nodes.setBandwidthLimits', {
+		onSuccess: () => node.refetch()
+	});
+
+	const setPort = useBridgeMutation('nodes.setP2PPort', {
+		onSuccess: () => node.refetch()
+	});
+	const setRelay = useBridgeMutation('nodes.setRelay', {
+		onSuccess: () => node.refetch()
+	});
+
+	const setSyncSchedule = useBridgeMutation('nodes.setSyncSchedule', {
+		onSuccess: () => node.refetch()
+	});
+
+	const limits = node.data?.p2p_bandwidth_limits;
+	const schedule = node.data?.p2p_sync_schedule;
+
+	const updateSchedule = (update: Partial<SyncSchedule>) => {
+		if (schedule) setSyncSchedule.mutate({ ...schedule, ...update });
+	};
+
+	const setLimit = (key: keyof RateLimits, value: string) => {
+		if (!limits) return;
+
+		const limit = parseInt(value);
+
+		setBandwidthLimits.mutate({
+			...limits,
+			global: {
+				upload: limits.global?.upload ?? null,
+				download: limits.global?.download ?? null,
+				[key]: isNaN(limit) || limit <= 0 ? null : limit
+			}
+		});
+	};
+
 	return (
 		<>
 			<Heading
@@ -31,6 +71,179 @@ export const Component = () => {
 					</div>
 				</div>
 			</Setting>
+
+			<Setting
+				title="Relay"
+				description="Relay used to connect to your nodes that aren't on the same network. Connections through it are end-to-end encrypted, and you can host your own. Leave empty to only connect over LAN."
+			>
+				<div className="mt-1 flex flex-col">
+					<Input
+						className="grow"
+						placeholder="/ip4/1.2.3.4/udp/4001/quic-v1/p2p/12D3KooW..."
+						key={node.data?.p2p_relay ?? ''}
+						defaultValue={node.data?.p2p_relay ?? ''}
+						onBlur={(e) => setRelay.mutate(e.target.value.trim() || null)}
+					/>
+					{setRelay.error && (
+						<p className="mt-1 text-xs text-red-500">{setRelay.error.message}</p>
+					)}
+				</div>
+			</Setting>
+
+			<Setting
+				mini
+				title="Port"
+				description="The port other nodes connect to this node on. Set one so nodes that can't discover this node can add it by its address. Leave empty to pick a random one. Applied after restarting Spacedrive."
+			>
+				<Input
+					className="w-28"
+					type="number"
+					min={1}
+					max={65535}
+					key={`port-${node.data?.p2p_port}`}
+					defaultValue={node.data?.p2p_port ?? ''}
+					onBlur={(e) => {
+						const port = parseInt(e.target.value);
+						setPort.mutate(isNaN(port) || port <= 0 || port > 65535 ? null : port);
+					}}
+				/>
+			</Setting>
+
+			<ManualPeers />
+
+			<Setting
+				mini
+				title="Upload Limit"
+				description="The maximum speed in KiB/s this node sends files at to other nodes. Leave empty for no limit."
+			>
+				<Input
+					className="w-28"
+					type="number"
+					min={0}
+					key={`upload-${limits?.global?.upload}`}
+					defaultValue={limits?.global?.upload ?? ''}
+					onBlur={(e) => setLimit('upload', e.target.value)}
+				/>
+			</Setting>
+
+			<Setting
+				mini
+				title="Download Limit"
+				description="The maximum speed in KiB/s this node receives files at from other nodes. Leave empty for no limit."
+			>
+				<Input
+					className="w-28"
+					type="number"
+					min={0}
+					key={`download-${limits?.global?.download}`}
+					defaultValue={limits?.global?.download ?? ''}
+					onBlur={(e) => setLimit('download', e.target.value)}
+				/>
+			</Setting>
+
+			<Setting
+				mini
+				title="Sync Delay"
+				description="How many seconds changes are collected for before they're sent to your other nodes together."
+			>
+				<Input
+					className="w-28"
+					type="number"
+					min={0}
+					key={`sync-delay-${schedule?.batch_delay_ms}`}
+					defaultValue={schedule ? schedule.batch_delay_ms / 1000 : ''}
+					onBlur={(e) => {
+						const delay = parseFloat(e.target.value);
+						updateSchedule({
+							batch_delay_ms: isNaN(delay) || delay < 0 ? 0 : Math.round(delay * 1000)
+						});
+					}}
+				/>
+			</Setting>
+
+			<Setting
+				mini
+				title="Sync on Metered Connections"
+				description="Send changes every 10 minutes on metered connections instead of waiting for another connection. Your other nodes catch up either way."
+			>
+				<Switch
+					checked={schedule?.metered_delay_secs != null}
+					onCheckedChange={(checked) =>
+						updateSchedule({ metered_delay_secs: checked ? 600 : null })
+					}
+				/>
+			</Setting>
+
+			<Setting
+				mini
+				title="Sync Immediately When Idle"
+				description="Send every change right away while this device is idle and plugged in."
+			>
+				<Switch
+					checked={schedule?.eager_when_idle ?? true}
+					onCheckedChange={(eager_when_idle) => updateSchedule({ eager_when_idle })}
+				/>
+			</Setting>
 		</>
 	);
 };
+
+function ManualPeers() {
+	const [address, setAddress] = useState('');
+	const peers = useBridgeQuery(['p2p.manualPeers'], { refetchInterval: 5000 });
+	const addPeer = useBridgeMutation('p2p.addManualPeer', {
+		onSuccess: () => {
+			setAddress('');
+			peers.refetch();
+		}
+	});
+	const removePeer = useBridgeMutation('p2p.removeManualPeer', {
+		onSuccess: () => peers.refetch()
+	});
+
+	return (
+		<Setting
+			title="Manual Peers"
+			description="Nodes to connect to by their address, for when they can't be discovered on the local network like over a VPN or in Docker. Enter a hostname or IP address followed by a port."
+		>
+			<div className="mt-1 flex flex-col gap-2">
+				{peers.data?.map((peer) => (
+					<div key={peer.address} className="flex items-center justify-between">
+						<div className="flex flex-col">
+							<span className="text-sm">{peer.address}</span>
+							<span className="text-xs text-ink-faint">
+								{peer.connected ? 'Connected' : peer.error ?? 'Connecting...'}
+							</span>
+						</div>
+						<Button
+							size="sm"
+							variant="gray"
+							onClick={() => removePeer.mutate(peer.address)}
+						>
+							Remove
+						</Button>
+					</div>
+				))}
+				<div className="flex gap-2">
+					<Input
+						className="grow"
+						placeholder="192.168.1.2:7373"
+						value={address}
+						onChange={(e) => setAddress(e.target.value)}
+					/>
+					<Button
+						size="sm"
+						variant="accent"
+						disabled={!address.trim()}
+						onClick={() => addPeer.mutate(address)}
+					>
+						Add
+					</Button>
+				</div>
+				{addPeer.error && (
+					<p className="text-xs text-red-500">{addPeer.error.message}</p>
+				)}
+			</div>
+		</Setting>
+	);
+}
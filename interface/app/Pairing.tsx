@@ -0,0 +1,63 @@
+import { useBridgeMutation, useBridgeSubscription, useFeatureFlag } from '@sd/client';
+import { Dialog, UseDialogProps, dialogManager, forms, useDialog } from '@sd/ui';
+
+const { useZodForm, z } = forms;
+
+export function PairingUI() {
+	const isPairingEnabled = useFeatureFlag('p2pPairing');
+	if (!isPairingEnabled) {
+		return null;
+	}
+
+	return <PairingUIInner />;
+}
+
+function PairingUIInner() {
+	useBridgeSubscription(['p2p.events'], {
+		onData(data) {
+			if (data.type === 'PairingRequest') {
+				dialogManager.create((dp) => (
+					<PairingRequestDialog
+						pairingId={data.id}
+						name={data.name}
+						code={data.code}
+						{...dp}
+					/>
+				));
+			}
+		}
+	});
+
+	return null;
+}
+
+function PairingRequestDialog(
+	props: { pairingId: number; name: string; code: string } & UseDialogProps
+) {
+	// We aren't using this but it's required for the Dialog :(
+	const form = useZodForm({ schema: z.object({}) });
+
+	const pairingResponse = useBridgeMutation('p2p.pairingResponse');
+
+	return (
+		<Dialog
+			form={form}
+			dialog={useDialog(props)}
+			title="Pair Node"
+			loading={pairingResponse.isLoading}
+			ctaLabel="Codes match"
+			closeLabel="Cancel"
+			onSubmit={form.handleSubmit(() =>
+				pairingResponse.mutateAsync([props.pairingId, true])
+			)}
+			onCancelled={() => pairingResponse.mutate([props.pairingId, false])}
+		>
+			<div className="space-y-2 py-2">
+				<p>
+					Check that <b>{props.name}</b> shows the same code before pairing with it.
+				</p>
+				<p className="text-center font-mono text-3xl tracking-widest">{props.code}</p>
+			</div>
+		</Dialog>
+	);
+}
@@ -0,0 +1,93 @@
+import { useState } from 'react';
+import {
+	SharingAccess,
+	useBridgeMutation,
+	useBridgeQuery,
+	useBridgeSubscription
+} from '@sd/client';
+import {
+	Dialog,
+	Select,
+	SelectOption,
+	UseDialogProps,
+	dialogManager,
+	forms,
+	useDialog
+} from '@sd/ui';
+
+const { useZodForm, z } = forms;
+
+export function SharingUI() {
+	useBridgeSubscription(['p2p.events'], {
+		onData(data) {
+			if (data.type === 'ShareRequest') {
+				dialogManager.create((dp) => (
+					<ShareRequestDialog
+						shareId={data.id}
+						name={data.name}
+						libraryName={data.library_name}
+						access={data.access}
+						includeFiles={data.include_files}
+						{...dp}
+					/>
+				));
+			}
+		}
+	});
+
+	return null;
+}
+
+function ShareRequestDialog(
+	props: {
+		shareId: string;
+		name: string;
+		libraryName: string;
+		access: SharingAccess;
+		includeFiles: boolean;
+	} & UseDialogProps
+) {
+	// We aren't using this but it's required for the Dialog :(
+	const form = useZodForm({ schema: z.object({}) });
+
+	const libraries = useBridgeQuery(['library.list']);
+	const shareResponse = useBridgeMutation('sharing.respond');
+	const [libraryId, setLibraryId] = useState<string>();
+
+	// TODO: Automatically close this after 2 minutes cause the invitation would have expired
+
+	return (
+		<Dialog
+			form={form}
+			dialog={useDialog(props)}
+			title="Shared Collection"
+			loading={shareResponse.isLoading}
+			ctaLabel="Add"
+			closeLabel="Reject"
+			onSubmit={form.handleSubmit(() =>
+				shareResponse.mutateAsync([props.shareId, libraryId ?? null])
+			)}
+			onCancelled={() => shareResponse.mutate([props.shareId, null])}
+		>
+			<div className="space-y-2 py-2">
+				<p>
+					<b>{props.libraryName}</b> wants to share <b>{props.name}</b> with you
+					{props.access === 'readWrite' ? ', you can edit it' : ''}
+					{props.includeFiles ? ' and get its files' : ''}.
+				</p>
+				<Select
+					size="sm"
+					value={libraryId ?? ''}
+					onChange={setLibraryId}
+					placeholder="Add it to library"
+				>
+					{libraries.data?.map((library) => (
+						<SelectOption key={library.uuid} value={library.uuid}>
+							{library.config.name}
+						</SelectOption>
+					))}
+				</Select>
+			</div>
+		</Dialog>
+	);
+}
@@ -44,6 +44,7 @@ function SpacedropUIInner() {
 						dropId={data.id}
 						name={data.name}
 						peerId={data.peer_id}
+						paired={data.paired}
 						{...dp}
 					/>
 				));
@@ -97,7 +98,7 @@ function SpacedropDialog(props: UseDialogProps) {
 }
 
 function SpacedropRequestDialog(
-	props: { dropId: string; name: string; peerId: string } & UseDialogProps
+	props: { dropId: string; name: string; peerId: string; paired: boolean } & UseDialogProps
 ) {
 	const form = useZodForm({
 		// We aren't using this but it's required for the Dialog :(
@@ -126,6 +127,12 @@ function SpacedropRequestDialog(
 			<div className="space-y-2 py-2">
 				<p>File Name: {props.name}</p>
 				<p>Peer Id: {props.peerId}</p>
+				{!props.paired && (
+					<p className="text-sm text-red-500">
+						This node isn't paired with any of your libraries, only accept files from nodes you
+						trust.
+					</p>
+				)}
 				<Input
 					size="sm"
 					placeholder="/Users/oscar/Desktop/demo.txt"
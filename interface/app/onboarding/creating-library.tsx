@@ -52,7 +52,9 @@ export default function OnboardingCreatingLibrary() {
 		telemetryStore.shareTelemetry = obStore.shareTelemetry;
 
 		createLibrary.mutate({
-			name: obStore.newLibraryName
+			name: obStore.newLibraryName,
+			password: null,
+			remember_password: false
 		});
 
 		return;
@@ -15,6 +15,7 @@ export * from './useIsDark';
 export * from './useKeyDeleteFile';
 export * from './useKeyboardHandler';
 export * from './useOperatingSystem';
+export * from './usePrioritizeThumbnail';
 export * from './useScrolled';
 export * from './useSearchStore';
 export * from './useSpacedropState';
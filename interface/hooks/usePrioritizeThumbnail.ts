@@ -0,0 +1,31 @@
+import { useEffect } from 'react';
+import { useLibraryMutation } from '@sd/client';
+
+// Thumbs mounted within this window are requested together, so scrolling doesn't flood the core
+const BATCH_DELAY_MS = 100;
+
+const pendingCasIds = new Set<string>();
+let batchTimeout: ReturnType<typeof setTimeout> | undefined;
+
+/**
+ * Asks the core to generate the thumbnail of a visible item ahead of the thumbnailer jobs.
+ * Only the items on screen are mounted by the virtualized views, so those fill in first.
+ */
+export function usePrioritizeThumbnail(casId: string | null, enabled: boolean) {
+	const { mutate: prioritizeThumbnails } = useLibraryMutation('jobs.prioritizeThumbnails');
+
+	useEffect(() => {
+		if (!casId || !enabled) return;
+
+		pendingCasIds.add(casId);
+
+		clearTimeout(batchTimeout);
+		batchTimeout = setTimeout(() => {
+			prioritizeThumbnails([...pendingCasIds]);
+			pendingCasIds.clear();
+		}, BATCH_DELAY_MS);
+
+		// Items scrolled out of view before the batch was sent don't need priority anymore
+		return () => void pendingCasIds.delete(casId);
+	}, [casId, enabled, prioritizeThumbnails]);
+}
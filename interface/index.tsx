@@ -10,6 +10,8 @@ import { ErrorBoundary } from 'react-error-boundary';
 import { RouterProvider, RouterProviderProps } from 'react-router-dom';
 import { P2PContextProvider, useDebugState } from '@sd/client';
 import ErrorFallback from './ErrorFallback';
+import { PairingUI } from './app/Pairing';
+import { SharingUI } from './app/Sharing';
 import { SpacedropUI } from './app/Spacedrop';
 
 export { ErrorPage } from './ErrorFallback';
@@ -51,6 +53,8 @@ export const SpacedriveInterface = (props: { router: RouterProviderProps['router
 			<P2PContextProvider>
 				<Devtools />
 				<SpacedropUI />
+				<PairingUI />
+				<SharingUI />
 				<RouterProvider router={props.router} />
 			</P2PContextProvider>
 		</ErrorBoundary>
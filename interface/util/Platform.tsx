@@ -8,6 +8,8 @@ export type OperatingSystem = 'browser' | 'linux' | 'macOS' | 'windows' | 'unkno
 export type Platform = {
 	platform: 'web' | 'tauri'; // This represents the specific platform implementation
 	getThumbnailUrlByThumbKey: (thumbKey: string[]) => string;
+	// Sprite sheets of video frames, keyed like the thumbnails
+	getSpriteUrlByThumbKey: (thumbKey: string[]) => string;
 	getFileUrl: (
 		libraryId: string,
 		locationLocalId: number,
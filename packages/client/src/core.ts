@@ -3,52 +3,148 @@
 
 export type Procedures = {
     queries: 
+        { key: "analytics.get", input: never, result: AnalyticsReport } | 
+        { key: "apiTokens.list", input: never, result: ApiToken[] } | 
+        { key: "backups.findSnapshots", input: BackupTargetKind, result: BackupSnapshot[] } | 
+        { key: "backups.policies", input: LibraryArgs<null>, result: BackupPolicyStatus[] } | 
+        { key: "backups.snapshots", input: LibraryArgs<string>, result: BackupSnapshot[] } | 
+        { key: "backups.targets", input: LibraryArgs<null>, result: SanitisedBackupTarget[] } | 
+        { key: "backups.unprotectedObjects", input: LibraryArgs<number | null>, result: Object[] } | 
         { key: "buildInfo", input: never, result: BuildInfo } | 
         { key: "categories.list", input: LibraryArgs<null>, result: { [key in Category]: number } } | 
-        { key: "files.get", input: LibraryArgs<GetArgs>, result: { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null; file_paths: FilePath[]; media_data: MediaData | null } | null } | 
+        { key: "files.get", input: LibraryArgs<GetArgs>, result: { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null; date_deleted: string | null; file_paths: FilePath[]; media_data: MediaData | null; catalog_metadata: CatalogMetadata[] } | null } | 
+        { key: "files.getArchiveSources", input: LibraryArgs<number>, result: Object[] } | 
+        { key: "files.getDerivedObjects", input: LibraryArgs<number>, result: Object[] } | 
+        { key: "files.getTextPreview", input: LibraryArgs<number>, result: TextPreview | null } | 
+        { key: "files.getVideoSprite", input: LibraryArgs<number>, result: VideoSprite | null } | 
+        { key: "files.getWaveform", input: LibraryArgs<number>, result: number[] | null } | 
+        { key: "files.previewBatchRename", input: LibraryArgs<BatchRenameJobInit>, result: BatchRenamePreview[] } | 
+        { key: "files.trashed", input: LibraryArgs<null>, result: TrashedFile[] } | 
         { key: "invalidation.test-invalidate", input: never, result: number } | 
         { key: "jobs.isActive", input: LibraryArgs<null>, result: boolean } | 
+        { key: "jobs.messages", input: never, result: { [key: string]: string } } | 
         { key: "jobs.reports", input: LibraryArgs<null>, result: JobGroups } | 
+        { key: "keys.getDefault", input: LibraryArgs<null>, result: string | null } | 
+        { key: "keys.isKeyManagerUnlocking", input: LibraryArgs<null>, result: boolean | null } | 
+        { key: "keys.isSetup", input: LibraryArgs<null>, result: boolean } | 
+        { key: "keys.isUnlocked", input: LibraryArgs<null>, result: boolean } | 
+        { key: "keys.listMounted", input: LibraryArgs<null>, result: string[] } | 
+        { key: "library.activity", input: LibraryArgs<ActivityPageArgs>, result: ActivityPage } | 
+        { key: "library.inspectExport", input: string, result: LibraryExport } | 
         { key: "library.list", input: never, result: LibraryConfigWrapped[] } | 
+        { key: "library.locked", input: never, result: LockedLibrary[] } | 
+        { key: "library.overview", input: LibraryArgs<null>, result: LibraryOverview } | 
+        { key: "library.settings", input: LibraryArgs<null>, result: LibrarySettings } | 
         { key: "library.statistics", input: LibraryArgs<null>, result: Statistics } | 
         { key: "locations.get", input: LibraryArgs<number>, result: Location | null } | 
         { key: "locations.getWithRules", input: LibraryArgs<number>, result: LocationWithIndexerRules | null } | 
         { key: "locations.indexer_rules.get", input: LibraryArgs<number>, result: IndexerRule } | 
         { key: "locations.indexer_rules.list", input: LibraryArgs<null>, result: IndexerRule[] } | 
         { key: "locations.indexer_rules.listForLocation", input: LibraryArgs<number>, result: IndexerRule[] } | 
-        { key: "locations.list", input: LibraryArgs<null>, result: { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; node_id: number | null; node: Node | null }[] } | 
+        { key: "locations.list", input: LibraryArgs<null>, result: { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; generate_video_thumbnails: boolean | null; node_id: number | null; node: Node | null }[] } | 
+        { key: "locations.listRemote", input: LibraryArgs<ListRemoteArgs>, result: RemoteEntry[] } | 
+        { key: "logs.filter", input: never, result: string } | 
+        { key: "logs.recent", input: RecentLogsArgs, result: LogLine[] } | 
+        { key: "mounts.list", input: never, result: MountInfo[] } | 
         { key: "nodeState", input: never, result: NodeState } | 
         { key: "nodes.listLocations", input: LibraryArgs<string | null>, result: ExplorerItem[] } | 
+        { key: "p2p.manualPeers", input: never, result: ManualPeer[] } | 
+        { key: "p2p.pairingPayload", input: LibraryArgs<null>, result: string } | 
+        { key: "p2p.transferQueue", input: never, result: QueuedTransfer[] } | 
+        { key: "plugins.list", input: never, result: PluginInfo[] } | 
+        { key: "plugins.metadata", input: LibraryArgs<number>, result: ObjectPluginMetadata[] } | 
+        { key: "plugins.search", input: PluginSearchArgs, result: PluginSearchResult[] } | 
+        { key: "rules.list", input: LibraryArgs<null>, result: ActionRule[] } | 
         { key: "search.objects", input: LibraryArgs<ObjectSearchArgs>, result: SearchData<ExplorerItem> } | 
         { key: "search.paths", input: LibraryArgs<FilePathSearchArgs>, result: SearchData<ExplorerItem> } | 
+        { key: "shareLinks.list", input: LibraryArgs<number | null>, result: ShareLink[] } | 
+        { key: "sharing.items", input: LibraryArgs<number>, result: SharedCollectionItem[] } | 
+        { key: "sharing.list", input: LibraryArgs<null>, result: SharedCollection[] } | 
+        { key: "sync.conflicts", input: LibraryArgs<ListSyncConflictsArgs>, result: SyncConflict[] } | 
+        { key: "sync.health", input: LibraryArgs<null>, result: SyncPeerHealth[] } | 
         { key: "sync.messages", input: LibraryArgs<null>, result: CRDTOperation[] } | 
+        { key: "sync.nodes", input: LibraryArgs<null>, result: SyncNode[] } | 
+        { key: "sync.status", input: LibraryArgs<SyncStatusArgs>, result: RecordSyncStatus[] } | 
         { key: "tags.get", input: LibraryArgs<number>, result: Tag | null } | 
         { key: "tags.getForObject", input: LibraryArgs<number>, result: Tag[] } | 
         { key: "tags.list", input: LibraryArgs<null>, result: Tag[] } | 
-        { key: "volumes.list", input: never, result: Volume[] },
+        { key: "trash.list", input: LibraryArgs<null>, result: TrashItem[] } | 
+        { key: "volumes.list", input: never, result: Volume[] } | 
+        { key: "webdav.list", input: LibraryArgs<null>, result: WebdavShare[] } | 
+        { key: "webhooks.list", input: LibraryArgs<null>, result: Webhook[] },
     mutations: 
+        { key: "analytics.clear", input: never, result: null } | 
+        { key: "analytics.export", input: string, result: null } | 
+        { key: "analytics.record", input: AnalyticsEvent, result: null } | 
+        { key: "analytics.setEnabled", input: boolean, result: null } | 
+        { key: "apiTokens.create", input: CreateApiTokenArgs, result: CreatedApiToken } | 
+        { key: "apiTokens.revoke", input: string, result: null } | 
+        { key: "backups.addTarget", input: LibraryArgs<AddBackupTargetArgs>, result: string } | 
+        { key: "backups.create", input: LibraryArgs<BackupJobInit>, result: null } | 
+        { key: "backups.createPolicy", input: LibraryArgs<CreateBackupPolicyArgs>, result: number } | 
+        { key: "backups.deletePolicy", input: LibraryArgs<number>, result: null } | 
+        { key: "backups.editTarget", input: LibraryArgs<EditBackupTargetArgs>, result: null } | 
+        { key: "backups.removeTarget", input: LibraryArgs<string>, result: null } | 
+        { key: "backups.restore", input: RestoreBackupArgs, result: LibraryConfigWrapped } | 
+        { key: "backups.runPolicy", input: LibraryArgs<PolicyBackupJobInit>, result: null } | 
+        { key: "backups.setPassword", input: LibraryArgs<SetBackupPasswordArgs>, result: null } | 
+        { key: "files.archiveFiles", input: LibraryArgs<FileArchiverJobInit>, result: null } | 
+        { key: "files.batchRename", input: LibraryArgs<BatchRenameJobInit>, result: null } | 
+        { key: "files.convertImages", input: LibraryArgs<ImageConverterJobInit>, result: null } | 
         { key: "files.copyFiles", input: LibraryArgs<FileCopierJobInit>, result: null } | 
         { key: "files.cutFiles", input: LibraryArgs<FileCutterJobInit>, result: null } | 
+        { key: "files.decryptFiles", input: LibraryArgs<FileDecryptorJobInit>, result: null } | 
+        { key: "files.deduplicateFiles", input: LibraryArgs<FileDeduplicatorJobInit>, result: null } | 
         { key: "files.deleteFiles", input: LibraryArgs<FileDeleterJobInit>, result: null } | 
         { key: "files.duplicateFiles", input: LibraryArgs<FileCopierJobInit>, result: null } | 
+        { key: "files.encryptFiles", input: LibraryArgs<FileEncryptorJobInit>, result: null } | 
         { key: "files.eraseFiles", input: LibraryArgs<FileEraserJobInit>, result: null } | 
+        { key: "files.exportMetadata", input: LibraryArgs<MetadataExportJobInit>, result: null } | 
+        { key: "files.extractFiles", input: LibraryArgs<FileExtractorJobInit>, result: null } | 
+        { key: "files.ingest", input: LibraryArgs<InboxArgs>, result: InboxEntry[] } | 
+        { key: "files.joinFile", input: LibraryArgs<FileJoinerJobInit>, result: null } | 
+        { key: "files.mirror", input: LibraryArgs<MirrorJobInit>, result: null } | 
+        { key: "files.moveFiles", input: LibraryArgs<FileMoverJobInit>, result: null } | 
         { key: "files.removeAccessTime", input: LibraryArgs<number[]>, result: null } | 
         { key: "files.renameFile", input: LibraryArgs<RenameFileArgs>, result: null } | 
+        { key: "files.restoreTrashed", input: LibraryArgs<number[]>, result: null } | 
         { key: "files.setFavorite", input: LibraryArgs<SetFavoriteArgs>, result: null } | 
         { key: "files.setNote", input: LibraryArgs<SetNoteArgs>, result: null } | 
+        { key: "files.splitFile", input: LibraryArgs<FileSplitterJobInit>, result: null } | 
+        { key: "files.transcodeVideos", input: LibraryArgs<VideoTranscoderJobInit>, result: null } | 
         { key: "files.updateAccessTime", input: LibraryArgs<number>, result: null } | 
         { key: "invalidation.test-invalidate-mutation", input: LibraryArgs<null>, result: null } | 
         { key: "jobs.cancel", input: LibraryArgs<string>, result: null } | 
+        { key: "jobs.checkThumbnailIntegrity", input: LibraryArgs<null>, result: null } | 
         { key: "jobs.clear", input: LibraryArgs<string>, result: null } | 
         { key: "jobs.clearAll", input: LibraryArgs<null>, result: null } | 
         { key: "jobs.generateThumbsForLocation", input: LibraryArgs<GenerateThumbsForLocationArgs>, result: null } | 
         { key: "jobs.identifyUniqueFiles", input: LibraryArgs<IdentifyUniqueFilesArgs>, result: null } | 
         { key: "jobs.objectValidator", input: LibraryArgs<ObjectValidatorArgs>, result: null } | 
+        { key: "jobs.objectVerifier", input: LibraryArgs<ObjectVerifierJobInit>, result: null } | 
         { key: "jobs.pause", input: LibraryArgs<string>, result: null } | 
+        { key: "jobs.prioritizeThumbnails", input: LibraryArgs<string[]>, result: null } | 
         { key: "jobs.resume", input: LibraryArgs<string>, result: null } | 
+        { key: "keys.add", input: LibraryArgs<KeyAddArgs>, result: null } | 
+        { key: "keys.mount", input: LibraryArgs<string>, result: null } | 
+        { key: "keys.setDefault", input: LibraryArgs<string>, result: null } | 
+        { key: "keys.setup", input: LibraryArgs<OnboardingConfig>, result: null } | 
+        { key: "keys.unlockKeyManager", input: LibraryArgs<UnlockKeyManagerArgs>, result: null } | 
+        { key: "keys.unmount", input: LibraryArgs<string>, result: null } | 
+        { key: "library.checkIntegrity", input: LibraryArgs<null>, result: null } | 
+        { key: "library.cleanupOrphans", input: LibraryArgs<boolean>, result: null } | 
+        { key: "library.clone", input: LibraryArgs<string | null>, result: LibraryConfigWrapped } | 
         { key: "library.create", input: CreateLibraryArgs, result: LibraryConfigWrapped } | 
         { key: "library.delete", input: string, result: null } | 
         { key: "library.edit", input: EditLibraryArgs, result: null } | 
+        { key: "library.export", input: LibraryArgs<ExportLibraryArgs>, result: LibraryExport } | 
+        { key: "library.import", input: ImportLibraryArgs, result: LibraryConfigWrapped } | 
+        { key: "library.importCatalog", input: LibraryArgs<CatalogImportJobInit>, result: null } | 
+        { key: "library.maintain", input: LibraryArgs<null>, result: null } | 
+        { key: "library.merge", input: LibraryArgs<string>, result: null } | 
+        { key: "library.patchSettings", input: LibraryArgs<LibrarySettingsPatch>, result: LibrarySettings } | 
+        { key: "library.repairIntegrity", input: LibraryArgs<string>, result: null } | 
+        { key: "library.unlock", input: UnlockLibraryArgs, result: LibraryConfigWrapped } | 
         { key: "locations.addLibrary", input: LibraryArgs<LocationCreateArgs>, result: null } | 
         { key: "locations.create", input: LibraryArgs<LocationCreateArgs>, result: null } | 
         { key: "locations.delete", input: LibraryArgs<number>, result: null } | 
@@ -57,204 +153,1238 @@ export type Procedures = {
         { key: "locations.indexer_rules.delete", input: LibraryArgs<number>, result: null } | 
         { key: "locations.relink", input: LibraryArgs<string>, result: null } | 
         { key: "locations.update", input: LibraryArgs<LocationUpdateArgs>, result: null } | 
+        { key: "logs.setFilter", input: string, result: null } | 
+        { key: "mounts.mount", input: LibraryArgs<MountArgs>, result: MountInfo } | 
+        { key: "mounts.unmount", input: string, result: null } | 
         { key: "nodes.edit", input: ChangeNodeNameArgs, result: null } | 
+        { key: "nodes.setBandwidthLimits", input: BandwidthLimits, result: null } | 
+        { key: "nodes.setDatabaseSettings", input: DatabaseSettings, result: null } | 
+        { key: "nodes.setP2PPort", input: number | null, result: null } | 
+        { key: "nodes.setRelay", input: string | null, result: null } | 
+        { key: "nodes.setSyncSchedule", input: SyncSchedule, result: null } | 
+        { key: "nodes.setThumbnailCacheMaxSize", input: number | null, result: null } | 
+        { key: "operations.undo", input: LibraryArgs<null>, result: UndoReport } | 
         { key: "p2p.acceptSpacedrop", input: [string, string | null], result: null } | 
+        { key: "p2p.addManualPeer", input: string, result: null } | 
         { key: "p2p.pair", input: LibraryArgs<PeerId>, result: number } | 
+        { key: "p2p.pairWithPayload", input: LibraryArgs<string>, result: number } | 
+        { key: "p2p.pairingResponse", input: [number, boolean], result: null } | 
+        { key: "p2p.queueSpacedrop", input: QueueSpacedropArgs, result: string } | 
+        { key: "p2p.removeManualPeer", input: string, result: null } | 
+        { key: "p2p.removeQueuedTransfer", input: string, result: null } | 
+        { key: "p2p.retryQueuedTransfer", input: string, result: null } | 
+        { key: "p2p.scheduleQueuedTransfer", input: ScheduleQueuedTransferArgs, result: null } | 
+        { key: "p2p.setDeviceConditions", input: DeviceConditions, result: null } | 
         { key: "p2p.spacedrop", input: SpacedropArgs, result: string | null } | 
+        { key: "plugins.extractMetadata", input: LibraryArgs<PluginMetadataJobInit>, result: null } | 
+        { key: "plugins.runJob", input: LibraryArgs<PluginJobInit>, result: null } | 
+        { key: "rules.create", input: LibraryArgs<CreateRuleArgs>, result: number } | 
+        { key: "rules.delete", input: LibraryArgs<number>, result: null } | 
+        { key: "rules.setEnabled", input: LibraryArgs<SetRuleEnabledArgs>, result: null } | 
+        { key: "shareLinks.create", input: LibraryArgs<CreateShareLinkArgs>, result: string } | 
+        { key: "shareLinks.redeem", input: RedeemShareLinkArgs, result: SharedObject } | 
+        { key: "shareLinks.revoke", input: LibraryArgs<number>, result: null } | 
+        { key: "sharing.edit", input: LibraryArgs<EditArgs>, result: null } | 
+        { key: "sharing.refresh", input: LibraryArgs<number>, result: null } | 
+        { key: "sharing.respond", input: [string, string | null], result: null } | 
+        { key: "sharing.share", input: LibraryArgs<ShareArgs>, result: null } | 
+        { key: "sharing.unshare", input: LibraryArgs<number>, result: null } | 
+        { key: "sync.resolveConflict", input: LibraryArgs<ResolveSyncConflictArgs>, result: null } | 
+        { key: "sync.setPermissions", input: LibraryArgs<SetNodePermissionsArgs>, result: null } | 
+        { key: "sync.setScope", input: LibraryArgs<SetSyncScopeArgs>, result: null } | 
+        { key: "sync.setTrustLevel", input: LibraryArgs<SetNodeTrustLevelArgs>, result: null } | 
         { key: "tags.assign", input: LibraryArgs<TagAssignArgs>, result: null } | 
         { key: "tags.create", input: LibraryArgs<TagCreateArgs>, result: Tag } | 
         { key: "tags.delete", input: LibraryArgs<number>, result: null } | 
-        { key: "tags.update", input: LibraryArgs<TagUpdateArgs>, result: null },
+        { key: "tags.update", input: LibraryArgs<TagUpdateArgs>, result: null } | 
+        { key: "trash.purge", input: LibraryArgs<TrashItems>, result: null } | 
+        { key: "trash.restore", input: LibraryArgs<TrashItems>, result: null } | 
+        { key: "webdav.create", input: LibraryArgs<CreateWebdavShareArgs>, result: CreatedWebdavShare } | 
+        { key: "webdav.delete", input: LibraryArgs<number>, result: null } | 
+        { key: "webhooks.create", input: LibraryArgs<CreateWebhookArgs>, result: number } | 
+        { key: "webhooks.delete", input: LibraryArgs<number>, result: null } | 
+        { key: "webhooks.setEnabled", input: LibraryArgs<SetWebhookEnabledArgs>, result: null },
     subscriptions: 
+        { key: "events.listen", input: LibraryArgs<EventsArgs>, result: Event } | 
         { key: "invalidation.listen", input: never, result: InvalidateOperationEvent[] } | 
+        { key: "jobs.logs", input: LibraryArgs<string>, result: LogLine } | 
         { key: "jobs.newThumbnail", input: LibraryArgs<null>, result: string[] } | 
         { key: "jobs.progress", input: LibraryArgs<string>, result: JobProgressEvent } | 
         { key: "locations.online", input: never, result: number[][] } | 
         { key: "locations.quickRescan", input: LibraryArgs<LightScanArgs>, result: null } | 
+        { key: "notifications.listen", input: LibraryArgs<null>, result: Notification } | 
         { key: "p2p.events", input: never, result: P2PEvent } | 
         { key: "p2p.spacedropProgress", input: string, result: number } | 
         { key: "sync.newMessage", input: LibraryArgs<null>, result: CRDTOperation }
 };
 
+export type ActionRule = { id: number; name: string; enabled: boolean; trigger: RuleTrigger; location_id: number | null; pattern: string | null; job_name: string | null; actions: RuleAction[]; date_created: string; date_last_run: string | null }
+
+export type ActivityDay = { date: string; files_indexed: number }
+
+export type ActivityEntry = { id: number; kind: ActivityKind; details: any; 
+/**
+ * The node the event happened on, if it's still in the library
+ */
+node_name: string | null; date_created: string }
+
+export type ActivityKind = "LocationAdded" | "LocationRemoved" | "SettingsChanged" | "DevicePaired" | "FilesDeleted"
+
+/**
+ * A page of the log, newest first. `cursor` is where the next page starts, if there's one.
+ */
+export type ActivityPage = { items: ActivityEntry[]; cursor: number | null }
+
+export type ActivityPageArgs = { take?: number | null; cursor?: number | null }
+
+export type AddBackupTargetArgs = { name: string; kind: BackupTargetKind; 
+/**
+ * Which snapshots are kept on the target, defaults to 7 daily and 4 weekly ones
+ */
+retention: BackupRetention | null; 
+/**
+ * Whether the library is backed up to the target once a day
+ */
+automatic: boolean }
+
+/**
+ * These are all possible algorithms that can be used for encryption and decryption
+ */
+export type Algorithm = "XChaCha20Poly1305" | "Aes256Gcm"
+
+/**
+ * What the app records
+ */
+export type AnalyticsEvent = { type: "Feature"; name: string } | { type: "Timing"; name: string; ms: number }
+
+/**
+ * Everything counted since `since`, as it's exported
+ */
+export type AnalyticsReport = { since: string; 
+/**
+ * By the name of the job, like `indexer`
+ */
+jobs: { [key: string]: JobAnalytics }; 
+/**
+ * How many times each feature was used, by the names the app records them with
+ */
+features: { [key: string]: number }; timings: { [key: string]: Timing } }
+
+export type ApiToken = { id: string; name: string; scopes: TokenScope[]; date_created: string }
+
+export type ArchiveFormat = "Zip" | "TarGz" | "TarZst"
+
+export type BackupJobInit = { target_id: string }
+
+export type BackupPolicy = { id: number; pub_id: number[]; name: string; tag_id: number | null; location_id: number | null; kind: number | null; target_location_id: number; target_path: string; interval_hours: number; date_created: string; date_last_run: string | null }
+
+/**
+ * A backup policy, with how many of its objects are backed up and how many aren't yet
+ */
+export type BackupPolicyStatus = { policy: BackupPolicy; backed_up: number; unprotected: number }
+
+/**
+ * Which snapshots of a library are kept on a target. The newest snapshot of each of the last
+ * `daily` days and of each of the last `weekly` weeks with snapshots are kept, the rest are deleted.
+ */
+export type BackupRetention = { daily: number; weekly: number }
+
+/**
+ * A snapshot of a library on a backup target. The library and the date it was made are in its
+ * name, so snapshots can be listed without decrypting them.
+ */
+export type BackupSnapshot = { name: string; library_id: string; date_created: string }
+
+/**
+ * Where the snapshots of a library are uploaded to
+ */
+export type BackupTargetKind = { type: "local"; path: string } | { type: "s3"; endpoint: string; region: string; bucket: string; prefix: string | null; accessKeyId: string; secretAccessKey: string } | { type: "webDav"; url: string; username: string | null; password: string | null }
+
+/**
+ * The speed limits of the transfers with other nodes, like Spacedrop
+ */
+export type BandwidthLimits = { 
+/**
+ * Shared by all the transfers
+ */
+global?: RateLimits; 
+/**
+ * Shared by the transfers with a peer, by the id of the peer
+ */
+peers?: { [key: string]: RateLimits } }
+
+export type BatchRenameJobInit = { location_id: number; 
+/**
+ * The files to rename, `{counter}` counts them in this order
+ */
+file_path_ids: number[]; 
+/**
+ * The new names, see [`RenameTemplate`]
+ */
+template: string; 
+/**
+ * A regex for the capture groups of the template, files it doesn't match keep their name
+ */
+pattern: string | null; 
+/**
+ * The value of `{counter}` for the first file
+ */
+counter_start: number }
+
+export type BatchRenamePreview = { file_path_id: number; from: string; to: string; problem: BatchRenameProblem | null }
+
+/**
+ * Why a file of the batch won't be renamed
+ */
+export type BatchRenameProblem = "NoMatch" | "Directory" | "InvalidName" | "Conflict"
+
 export type BuildInfo = { version: string; commit: string }
 
 export type CRDTOperation = { node: string; timestamp: number; id: string; typ: CRDTOperationType }
 
-export type CRDTOperationType = SharedOperation | RelationOperation
+export type CRDTOperationType = SharedOperation | RelationOperation
+
+export type CatalogImportJobInit = { kind: CatalogKind; path: string }
+
+export type CatalogKind = "Lightroom" | "Digikam"
+
+export type CatalogMetadata = { object_id: number; catalog: number; rating: number | null; edits: string | null; date_imported: string }
+
+/**
+ * Meow
+ */
+export type Category = "Recents" | "Favorites" | "Photos" | "Videos" | "Movies" | "Music" | "Documents" | "Downloads" | "Encrypted" | "Projects" | "Applications" | "Archives" | "Databases" | "Games" | "Books" | "Contacts" | "Trash"
+
+export type ChangeNodeNameArgs = { name: string | null; thumbnail_size: ThumbnailSize | null }
+
+/**
+ * A change that the library a collection is shared with read-write can make to it
+ */
+export type CollectionEdit = { type: "Update"; name: string | null; color: string | null } | { type: "RemoveItem"; object_pub_id: string }
+
+/**
+ * What's done with an extracted file whose path is already taken
+ */
+export type CollisionPolicy = "Skip" | "Overwrite" | "Rename"
+
+/**
+ * How a node settles two paired nodes changing the same field of a record concurrently, meaning
+ * neither of them knew about the other's change when making theirs. Every node of a library should
+ * use the same policy, or they can end up keeping different values.
+ */
+export type ConflictPolicy = "lastWriterWins" | { preferNode: string } | "manual"
+
+/**
+ * How the user settles a pending conflict
+ */
+export type ConflictResolution = "keepCurrent" | "useOther" | { merge: any }
+
+export type ConversionTarget = { type: "SameDirectory" } | { type: "Directory"; location_id: number; relative_directory_path: string }
+
+export type CreateApiTokenArgs = { name: string; scopes: TokenScope[] }
+
+export type CreateBackupPolicyArgs = { name: string; 
+/**
+ * Backs up the objects with this tag
+ */
+tag_id: number | null; 
+/**
+ * Backs up the objects with files in this location
+ */
+location_id: number | null; 
+/**
+ * Backs up the objects of this kind, from `ObjectKind`
+ */
+kind: number | null; 
+/**
+ * The location the objects are backed up to, and the existing directory in it
+ */
+target_location_id: number; target_path: string; 
+/**
+ * How often new objects are backed up, in hours
+ */
+interval_hours: number }
+
+export type CreateLibraryArgs = { name: string; 
+/**
+ * Encrypts the database of the library on disk, with a key unlocked by this password
+ */
+password: Protected<string> | null; 
+/**
+ * Keeps the key of the database in the OS keychain, to unlock the library on startup
+ */
+remember_password: boolean }
+
+export type CreateRuleArgs = { name: string; trigger: RuleTrigger; 
+/**
+ * Only the files of this location
+ */
+location_id: number | null; 
+/**
+ * A glob the names of the files match, like `*.pdf`
+ */
+pattern: string | null; 
+/**
+ * Only the jobs of this name
+ */
+job_name: string | null; 
+/**
+ * Run in order, the first one failing stops the others
+ */
+actions: RuleAction[] }
+
+export type CreateShareLinkArgs = { object_id: number; 
+/**
+ * `null` for the link to be valid until it's revoked
+ */
+date_expires: string | null }
+
+export type CreateWebdavShareArgs = { name: string; username: string; 
+/**
+ * Either the location or the tag
+ */
+location_id: number | null; tag_id: number | null }
+
+export type CreateWebhookArgs = { url: string; events: WebhookEventKind[]; 
+/**
+ * Only the objects with a file in this location
+ */
+location_id: number | null; 
+/**
+ * Only the objects with this tag
+ */
+tag_id: number | null; 
+/**
+ * Signs the deliveries with HMAC-SHA256
+ */
+secret: string | null }
+
+export type CreatedApiToken = { id: string; 
+/**
+ * Only given back now, a token that lost it has to be made again
+ */
+token: string }
+
+export type CreatedWebdavShare = { id: number; 
+/**
+ * Only given back now, a share that lost it has to be made again
+ */
+password: string }
+
+/**
+ * How the databases of the libraries are opened, shared by all libraries on this node and applied
+ * when a library is loaded
+ */
+export type DatabaseSettings = { 
+/**
+ * How long a query waits for another connection to release the database before failing with
+ * "database is locked"
+ */
+busy_timeout_secs: number; 
+/**
+ * Size the write-ahead log grows to before it's moved into the database
+ */
+wal_size_mb: number; synchronous: SynchronousMode; 
+/**
+ * Memory used to keep pages of the database, by connection
+ */
+cache_size_mb: number }
+
+/**
+ * What the app knows about the device, the core can't tell it on every platform
+ */
+export type DeviceConditions = { metered: boolean; on_battery: boolean; idle: boolean }
+
+export type DiskType = "SSD" | "HDD" | "Removable"
+
+export type EditArgs = { collection_id: number; edit: CollectionEdit }
+
+export type EditBackupTargetArgs = { target_id: string; retention: BackupRetention | null; automatic: boolean | null }
+
+export type EditLibraryArgs = { id: string; name: string | null; description: MaybeUndefined<string>; sync_conflict_policy: ConflictPolicy | null }
+
+/**
+ * Where encrypted files, or decrypted ones, are written
+ */
+export type EncryptionTarget = { type: "InPlace" } | { type: "Directory"; location_id: number; relative_directory_path: string }
+
+export type Event = { topic: "JobProgress"; data: JobProgressEvent } | { topic: "ThumbnailReady"; data: { thumb_key: string[] } } | { topic: "LocationsOnline"; data: { location_pub_ids: number[][] } } | { topic: "SyncActivity"; data: { ingested: boolean; operation: CRDTOperation } }
+
+export type EventTopic = "JobProgress" | "ThumbnailReady" | "LocationsOnline" | "SyncActivity"
+
+export type EventsArgs = { 
+/**
+ * The topics to send, all of them when empty
+ */
+topics?: EventTopic[] }
+
+export type ExplorerItem = { type: "Path"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: FilePathWithObject } | { type: "Object"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: ObjectWithFilePaths } | { type: "Location"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: Location }
+
+export type ExportColumn = "ObjectId" | "ObjectPubId" | "Kind" | "Path" | "LocationId" | "Name" | "Extension" | "Size" | "CasId" | "IntegrityChecksum" | "DateCreated" | "DateModified" | "Tags" | "Favorite" | "Note" | "PixelWidth" | "PixelHeight" | "Latitude" | "Longitude" | "CaptureDeviceMake" | "CaptureDeviceModel" | "CaptureDeviceSoftware" | "DurationSeconds"
+
+export type ExportFormat = "Csv" | "JsonLines" | "Parquet"
+
+export type ExportLibraryArgs = { 
+/**
+ * The file to write the export to
+ */
+path: string; include_thumbnails: boolean }
+
+export type ExportedLocation = { pub_id: string; name: string | null; path: string | null }
+
+export type FileArchiverJobInit = { location_id: number; file_path_ids: number[]; 
+/**
+ * The name of the archive, without its extension
+ */
+name: string; format: ArchiveFormat; 
+/**
+ * From 0, only storing the files, to 9, the smallest and slowest archives
+ */
+compression_level: number }
+
+export type FileCopierJobInit = { source_location_id: number; target_location_id: number; sources_file_path_ids: number[]; target_location_relative_directory_path: string; target_file_name_suffix: string | null; 
+/**
+ * Checks that each copy has the content id of its source, removing the copies that don't
+ */
+verify?: boolean; 
+/**
+ * Gives the copies the timestamps, permissions and extended attributes of their sources,
+ * where the filesystem of the copies supports them
+ */
+preserve_metadata?: boolean }
+
+export type FileCutterJobInit = { source_location_id: number; target_location_id: number; sources_file_path_ids: number[]; target_location_relative_directory_path: string }
+
+export type FileDecryptorJobInit = { location_id: number; file_path_ids: number[]; target: EncryptionTarget }
+
+export type FileDeduplicatorJobInit = { 
+/**
+ * The locations whose duplicates are replaced, all the locations of this device when empty
+ */
+location_ids: number[]; method: LinkMethod }
+
+export type FileDeleterJobInit = { location_id: number; file_path_ids: number[]; 
+/**
+ * Deletes the files for good, instead of moving them to the trash of the operating system
+ */
+permanent?: boolean }
+
+export type FileEncryptorJobInit = { location_id: number; file_path_ids: number[]; 
+/**
+ * A mounted key of the key manager, the default key when not set
+ */
+key_uuid: string | null; target: EncryptionTarget }
+
+export type FileEraserJobInit = { location_id: number; file_path_ids: number[]; passes: string }
+
+export type FileExtractorJobInit = { location_id: number; 
+/**
+ * The archive to extract
+ */
+file_path_id: number; target_location_id: number; 
+/**
+ * An existing directory of the target location, the archive is extracted in it
+ */
+target_location_relative_directory_path: string; collision: CollisionPolicy }
+
+export type FileJoinerJobInit = { location_id: number; 
+/**
+ * The manifest of the split file, its parts are in the same directory
+ */
+file_path_id: number; target: ConversionTarget }
+
+export type FileMoverJobInit = { source_location_id: number; target_location_id: number; sources_file_path_ids: number[]; target_location_relative_directory_path: string; 
+/**
+ * Gives files moved to another device the timestamps, permissions and extended attributes
+ * they had, where the filesystem they're moved to supports them. Renamed files keep them all.
+ */
+preserve_metadata?: boolean }
+
+export type FileOperationKind = "Copy" | "Move" | "CreateDirectory" | "Trash" | "Delete"
+
+export type FilePath = { id: number; pub_id: number[]; is_dir: boolean | null; cas_id: string | null; integrity_checksum: string | null; location_id: number | null; materialized_path: string | null; name: string | null; extension: string | null; size_in_bytes: string | null; size_in_bytes_bytes: number[] | null; inode: number[] | null; device: number[] | null; object_id: number | null; key_id: number | null; date_created: string | null; date_modified: string | null; date_indexed: string | null; date_verified: string | null }
+
+export type FilePathFilterArgs = { locationId?: number | null; search?: string | null; extension?: string | null; createdAt?: OptionalRange<string>; path?: string | null; object?: ObjectFilterArgs | null }
+
+export type FilePathSearchArgs = { take?: number | null; order?: FilePathSearchOrdering | null; cursor?: number[] | null; filter?: FilePathFilterArgs }
+
+export type FilePathSearchOrdering = { name: SortOrder } | { sizeInBytes: SortOrder } | { dateCreated: SortOrder } | { dateModified: SortOrder } | { dateIndexed: SortOrder } | { object: ObjectSearchOrdering }
+
+export type FilePathWithObject = { id: number; pub_id: number[]; is_dir: boolean | null; cas_id: string | null; integrity_checksum: string | null; location_id: number | null; materialized_path: string | null; name: string | null; extension: string | null; size_in_bytes: string | null; size_in_bytes_bytes: number[] | null; inode: number[] | null; device: number[] | null; object_id: number | null; key_id: number | null; date_created: string | null; date_modified: string | null; date_indexed: string | null; date_verified: string | null; object: Object | null }
+
+export type FileSplitterJobInit = { location_id: number; file_path_id: number; 
+/**
+ * The size of each part in bytes, at least 1 MiB, the last part has what's left
+ */
+part_size: string; target: ConversionTarget }
+
+export type FilesOverview = { objects: number; files: number; total_bytes: string; locations: LocationStorage[]; 
+/**
+ * The files indexed each of the last days, oldest first
+ */
+activity: ActivityDay[]; 
+/**
+ * When these figures were computed, they can be a little behind the files
+ */
+date_computed: string }
+
+export type FromPattern = { pattern: string; replace_all: boolean }
+
+export type GenerateThumbsForLocationArgs = { id: number; path: string; regenerate: boolean }
+
+export type GetArgs = { id: number }
+
+/**
+ * This defines all available password hashing algorithms.
+ */
+export type HashingAlgorithm = { name: "Argon2id"; params: Params } | { name: "BalloonBlake3"; params: Params }
+
+export type IdentifyUniqueFilesArgs = { id: number; path: string }
+
+export type ImageConverterJobInit = { location_id: number; file_path_ids: number[]; format: ImageFormat; 
+/**
+ * Encoding quality in the range `1..=100`, 90 when not set
+ */
+quality: number | null; target: ConversionTarget }
+
+export type ImageFormat = "Jpeg" | "Webp"
+
+export type ImportLibraryArgs = { path: string; 
+/**
+ * The path on this node of the locations of the export, by their `pub_id`
+ */
+locations: { [key: string]: string } }
+
+export type InboxArgs = { items: InboxItem[]; 
+/**
+ * Tags given to all of the items
+ */
+tag_ids?: number[] }
+
+/**
+ * An item saved to the inbox
+ */
+export type InboxEntry = { object_id: number; path: string }
+
+export type InboxItem = { type: "File"; path: string } | { type: "Url"; url: string; name: string | null }
+
+export type IndexerRule = { id: number; pub_id: number[]; name: string | null; default: boolean | null; rules_per_kind: number[] | null; date_created: string | null; date_modified: string | null }
+
+/**
+ * `IndexerRuleCreateArgs` is the argument received from the client using rspc to create a new indexer rule.
+ * Note that `rules` field is a vector of tuples of `RuleKind` and `parameters`.
+ * 
+ * In case of  `RuleKind::AcceptFilesByGlob` or `RuleKind::RejectFilesByGlob`, it will be a
+ * vector of strings containing a glob patterns.
+ * 
+ * In case of `RuleKind::AcceptIfChildrenDirectoriesArePresent` or `RuleKind::RejectIfChildrenDirectoriesArePresent` the
+ * `parameters` field must be a vector of strings containing the names of the directories.
+ */
+export type IndexerRuleCreateArgs = { name: string; dry_run: boolean; rules: ([RuleKind, string[]])[] }
+
+export type InvalidateOperationEvent = { key: string; arg: any; result: any | null }
+
+export type JobAnalytics = { completed: number; completed_with_errors: number; failed: number; canceled: number; 
+/**
+ * How long the jobs took, finished or not
+ */
+duration: Timing }
+
+export type JobGroup = { id: string; action: string | null; status: JobStatus; created_at: string; jobs: JobReport[] }
+
+export type JobGroups = { groups: JobGroup[]; index: { [key: string]: number } }
+
+/**
+ * A message of a job, shown while it's running or when it failed
+ */
+export type JobMessage = { 
+/**
+ * Like `copy.copying`, see [`MESSAGES`]
+ */
+code: string; 
+/**
+ * The values shown in the message, by the names in its text
+ */
+params: { [key: string]: string }; 
+/**
+ * In English
+ */
+text: string }
+
+export type JobProgressEvent = { id: string; task_count: number; completed_task_count: number; message: JobMessage | null; estimated_completion: string }
+
+export type JobReport = { id: string; name: string; action: string | null; data: number[] | null; metadata: any | null; is_background: boolean; errors_text: string[]; 
+/**
+ * Why the job failed
+ */
+error: JobMessage | null; created_at: string | null; started_at: string | null; completed_at: string | null; parent_id: string | null; status: JobStatus; task_count: number; completed_task_count: number; message: JobMessage | null; estimated_completion: string }
+
+export type JobStatus = "Queued" | "Running" | "Completed" | "Canceled" | "Failed" | "Paused" | "CompletedWithErrors"
+
+/**
+ * The jobs of the last days, by how they ended
+ */
+export type JobsOverview = { running: number; queued: number; paused: number; completed: number; completed_with_errors: number; failed: number; canceled: number }
+
+export type KeyAddArgs = { algorithm: Algorithm; hashing_algorithm: HashingAlgorithm; key: Protected<string>; library_sync: boolean; automount: boolean }
+
+/**
+ * Can wrap a query argument to require it to contain a `library_id` and provide helpers for working with libraries.
+ */
+export type LibraryArgs<T> = { library_id: string; arg: T }
+
+export type LibraryConfigWrapped = { uuid: string; config: SanitisedLibraryConfig }
+
+/**
+ * What a library export holds, read without importing it
+ */
+export type LibraryExport = { library_id: string; name: string; description: string | null; date_exported: string; 
+/**
+ * The locations of the library, with their path on the node it was exported from
+ */
+locations: ExportedLocation[]; thumbnails: number }
+
+export type LibraryOverview = { files: FilesOverview; jobs: JobsOverview; sync: SyncOverview }
+
+/**
+ * How a library behaves on this node. The settings are versioned along with the library config,
+ * which migrates them.
+ */
+export type LibrarySettings = { 
+/**
+ * Format of the thumbnails generated for this library's files
+ */
+thumbnail_format: ThumbnailFormat; 
+/**
+ * Encoding quality of the thumbnails, from 0 to 100. Higher values trade cache size for fidelity.
+ */
+thumbnail_quality: number; 
+/**
+ * How many files the file identifier reads in a step. Bigger steps are faster, but the
+ * identifier reports its progress and can be paused less often.
+ */
+identifier_chunk_size: number; 
+/**
+ * How many jobs of the library run at once, the others wait in the queue. Jobs working hard on
+ * the same locations wait for each other, whatever this allows.
+ */
+max_concurrent_jobs: number; 
+/**
+ * Hours of the day during which the background jobs of the library don't start
+ */
+quiet_hours: QuietHours | null; 
+/**
+ * How much space the thumbnails, waveforms and text previews of the library's files can take,
+ * in MB. The ones of favorites and recently opened files are kept the longest.
+ */
+preview_media_max_size_mb: number | null; 
+/**
+ * The location the files and links shared to Spacedrive from other apps are saved to
+ */
+inbox_location_id: number | null }
+
+/**
+ * Changes to the settings of a library, the missing fields are left as they are
+ */
+export type LibrarySettingsPatch = { thumbnail_format: ThumbnailFormat | null; thumbnail_quality: number | null; identifier_chunk_size: number | null; max_concurrent_jobs: number | null; quiet_hours: MaybeUndefined<QuietHours>; preview_media_max_size_mb: MaybeUndefined<number>; inbox_location_id: MaybeUndefined<number> }
+
+export type LightScanArgs = { location_id: number; sub_path: string }
+
+export type LinkMethod = "Hardlink" | "Reflink"
+
+export type ListRemoteArgs = { location_id: number; 
+/**
+ * Relative to the location
+ */
+path: string }
+
+export type ListSyncConflictsArgs = { 
+/**
+ * Only lists the conflicts waiting to be resolved
+ */
+pending: boolean }
+
+export type Location = { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; generate_video_thumbnails: boolean | null; node_id: number | null }
+
+/**
+ * `LocationCreateArgs` is the argument received from the client using `rspc` to create a new location.
+ * It has the actual path and a vector of indexer rules ids, to create many-to-many relationships
+ * between the location and indexer rules.
+ */
+export type LocationCreateArgs = { path: string; dry_run: boolean; indexer_rules_ids: number[] }
+
+export type LocationStorage = { id: number; name: string | null; files: number; total_bytes: string }
+
+/**
+ * `LocationUpdateArgs` is the argument received from the client using `rspc` to update a location.
+ * It contains the id of the location to be updated, possible a name to change the current location's name
+ * and a vector of indexer rules ids to add or remove from the location.
+ * 
+ * It is important to note that only the indexer rule ids in this vector will be used from now on.
+ * Old rules that aren't in this vector will be purged.
+ */
+export type LocationUpdateArgs = { id: number; name: string | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; generate_video_thumbnails: boolean | null; hidden: boolean | null; indexer_rules_ids: number[] }
+
+export type LocationWithIndexerRules = { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; generate_video_thumbnails: boolean | null; node_id: number | null; indexer_rules: { indexer_rule: IndexerRule }[] }
+
+/**
+ * A library that can't be loaded until it's unlocked with its password
+ */
+export type LockedLibrary = { uuid: string; name: string }
+
+/**
+ * Ordered from the most verbose
+ */
+export type LogLevel = "Trace" | "Debug" | "Info" | "Warn" | "Error"
+
+export type LogLine = { timestamp: string; level: LogLevel; 
+/**
+ * The module it was logged from, like `sd_core::job::worker`
+ */
+target: string; message: string; fields: { [key: string]: string }; library_id: string | null; 
+/**
+ * The job it was logged by, which is in a library too
+ */
+job_id: string | null }
+
+/**
+ * A peer that was added by its address, for when it can't be discovered on the local network
+ */
+export type ManualPeer = { address: string; 
+/**
+ * Known once we connected to it once
+ */
+peer_id: PeerId | null; connected: boolean; 
+/**
+ * Why the last attempt to connect failed
+ */
+error: string | null }
+
+export type MaybeNot<T> = T | { not: T }
+
+export type MaybeUndefined<T> = null | null | T
+
+export type MediaData = { id: number; pixel_width: number | null; pixel_height: number | null; longitude: number | null; latitude: number | null; fps: number | null; capture_device_make: string | null; capture_device_model: string | null; capture_device_software: string | null; duration_seconds: number | null; codecs: string | null; streams: number | null }
+
+/**
+ * Which objects are exported, all of the filters have to match
+ */
+export type MetadataExportFilter = { 
+/**
+ * Only these objects, like the ones selected in the explorer
+ */
+object_ids?: number[]; location_id: number | null; 
+/**
+ * Only the objects with one of these tags
+ */
+tag_ids?: number[]; 
+/**
+ * Only the objects of these kinds, from `ObjectKind`
+ */
+kinds?: number[]; 
+/**
+ * Only the files with these extensions, without the dot
+ */
+extensions?: string[] }
+
+export type MetadataExportJobInit = { 
+/**
+ * The file to write the export to, it's replaced if it exists
+ */
+path: string; format: ExportFormat; 
+/**
+ * In the order they're exported
+ */
+columns: ExportColumn[]; filter?: MetadataExportFilter }
+
+export type MirrorDestination = { type: "Location"; location_id: number; sub_path: string } | { type: "Path"; path: string }
+
+export type MirrorJobInit = { location_id: number; 
+/**
+ * The directory of the location that's mirrored, all of the location when not set
+ */
+sub_path: string | null; destination: MirrorDestination; 
+/**
+ * Removes the files and directories of the destination that the source doesn't have
+ */
+delete_extraneous: boolean; 
+/**
+ * Only reports what would change, without changing anything
+ */
+dry_run: boolean }
+
+export type MountArgs = { 
+/**
+ * An empty directory of this node the library is mounted on
+ */
+path: string; views: MountView[] }
+
+export type MountInfo = { library_id: string; path: string; views: MountView[] }
+
+/**
+ * A directory at the root of a mount
+ */
+export type MountView = "Tags" | "Kinds" | "Albums" | "All"
+
+export type Node = { id: number; pub_id: number[]; name: string; platform: number; date_created: string; identity: number[] | null; node_peer_id: string | null; can_spacedrop: boolean; can_sync: boolean; can_read_files: boolean; can_delete: boolean }
+
+/**
+ * What a paired node is allowed to do with a library. They are only enforced by this node, the
+ * other node has its own permissions for us.
+ */
+export type NodePermissions = { 
+/**
+ * Send us files with Spacedrop, they're rejected without asking otherwise
+ */
+spacedrop: boolean; 
+/**
+ * Exchange the library's operations with us
+ */
+sync: boolean; 
+/**
+ * Browse the locations shared with it, read their files and get their thumbnails
+ */
+read_files: boolean; 
+/**
+ * Delete records of the library through sync
+ */
+delete: boolean }
+
+export type NodeState = ({ id: string; name: string; p2p_port: number | null; p2p_email: string | null; p2p_img_url: string | null; thumbnail_size: ThumbnailSize; thumbnail_cache_max_size_mb: number | null; p2p_bandwidth_limits: BandwidthLimits; p2p_relay: string | null; p2p_manual_peers: string[]; p2p_sync_schedule: SyncSchedule; analytics_enabled: boolean; database: DatabaseSettings }) & { data_path: string }
+
+export type Notification = { library_id: string; data: NotificationData; date_created: string }
+
+export type NotificationData = { type: "IntegrityMismatches"; location_id: number; 
+/**
+ * Changed without their size or modification date changing, likely damaged on disk
+ */
+corrupted: string[]; 
+/**
+ * Changed without Spacedrive noticing, their checksums were updated
+ */
+changed: string[] }
+
+export type Object = { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null; date_deleted: string | null }
+
+export type ObjectFilterArgs = { favorite?: boolean | null; hidden?: ObjectHiddenFilter; dateAccessed?: MaybeNot<string | null> | null; kind?: number[]; tags?: number[]; category?: Category | null }
+
+export type ObjectHiddenFilter = "exclude" | "include"
+
+export type ObjectPluginMetadata = { plugin_id: string; data: any }
+
+export type ObjectSearchArgs = { take?: number | null; order?: ObjectSearchOrdering | null; cursor?: number[] | null; filter?: ObjectFilterArgs }
+
+export type ObjectSearchOrdering = { dateAccessed: SortOrder }
+
+export type ObjectValidatorArgs = { id: number; path: string }
+
+export type ObjectVerifierJobInit = { location_id: number; 
+/**
+ * How many of the files verified the longest ago are verified, all of them when not set
+ */
+sample_size: number | null }
+
+export type ObjectWithFilePaths = { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null; date_deleted: string | null; file_paths: FilePath[] }
+
+export type OnboardingConfig = { password: Protected<string>; algorithm: Algorithm; hashing_algorithm: HashingAlgorithm }
+
+/**
+ * Represents the operating system which the remote peer is running.
+ * This is not used internally and predominantly is designed to be used for display purposes by the embedding application.
+ */
+export type OperatingSystem = "Windows" | "Linux" | "MacOS" | "Ios" | "Android" | { Other: string }
+
+export type OptionalRange<T> = { from: T | null; to: T | null }
+
+/**
+ * TODO: P2P event for the frontend
+ */
+export type P2PEvent = { type: "DiscoveredPeer"; peer_id: PeerId; metadata: PeerMetadata } | { type: "SpacedropRequest"; id: string; peer_id: PeerId; name: string; 
+/**
+ * If the peer is a node paired with one of our libraries, otherwise we know nothing about who sent it
+ */
+paired: boolean } | { type: "PairingRequest"; id: number; peer_id: PeerId; name: string; library_id: string; code: string } | { type: "PairingProgress"; id: number; status: PairingStatus } | { type: "ShareRequest"; id: string; peer_id: PeerId; library_name: string; name: string; access: SharingAccess; include_files: boolean } | { type: "TransferQueueChanged" }
+
+export type PairingStatus = { type: "Paired" } | { type: "Rejected" } | { type: "Failed"; error: string }
 
 /**
- * Meow
+ * These parameters define the password-hashing level.
+ * 
+ * The greater the parameter, the longer the password will take to hash.
  */
-export type Category = "Recents" | "Favorites" | "Photos" | "Videos" | "Movies" | "Music" | "Documents" | "Downloads" | "Encrypted" | "Projects" | "Applications" | "Archives" | "Databases" | "Games" | "Books" | "Contacts" | "Trash"
+export type Params = "Standard" | "Hardened" | "Paranoid"
 
-export type ChangeNodeNameArgs = { name: string | null }
+export type PeerId = string
 
-export type CreateLibraryArgs = { name: string }
+export type PeerMetadata = { name: string; operating_system: OperatingSystem | null; version: string | null; email: string | null; img_url: string | null }
 
-export type DiskType = "SSD" | "HDD" | "Removable"
+export type PluginInfo = { id: string; name: string; version: string; description: string | null; 
+/**
+ * What the plugin can reach of the node, like `ReadFile`
+ */
+capabilities: string[]; 
+/**
+ * The extensions of the files it extracts metadata from
+ */
+extensions: string[]; search: boolean; jobs: string[] }
 
-export type EditLibraryArgs = { id: string; name: string | null; description: MaybeUndefined<string> }
+export type PluginJobInit = { plugin_id: string; 
+/**
+ * The name of the job, one of the jobs of the plugin
+ */
+job: string; 
+/**
+ * The arguments of the job as JSON, passed to the plugin as they are
+ */
+args: string }
 
-export type ExplorerItem = { type: "Path"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: FilePathWithObject } | { type: "Object"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: ObjectWithFilePaths } | { type: "Location"; has_local_thumbnail: boolean; thumbnail_key: string[] | null; item: Location }
+export type PluginMetadataJobInit = { location_id: number; 
+/**
+ * Only the extractors of this plugin, all of them when not set
+ */
+plugin_id?: string | null }
 
-export type FileCopierJobInit = { source_location_id: number; target_location_id: number; sources_file_path_ids: number[]; target_location_relative_directory_path: string; target_file_name_suffix: string | null }
+export type PluginSearchArgs = { query: string; 
+/**
+ * The most results of each plugin, 20 when not set
+ */
+take: number | null }
 
-export type FileCutterJobInit = { source_location_id: number; target_location_id: number; sources_file_path_ids: number[]; target_location_relative_directory_path: string }
+export type PluginSearchResult = { plugin_id: string; title: string; subtitle: string | null; uri: string | null }
 
-export type FileDeleterJobInit = { location_id: number; file_path_ids: number[] }
+export type PolicyBackupJobInit = { policy_id: number }
 
-export type FileEraserJobInit = { location_id: number; file_path_ids: number[]; passes: string }
+export type Protected<T> = T
 
-export type FilePath = { id: number; pub_id: number[]; is_dir: boolean | null; cas_id: string | null; integrity_checksum: string | null; location_id: number | null; materialized_path: string | null; name: string | null; extension: string | null; size_in_bytes: string | null; size_in_bytes_bytes: number[] | null; inode: number[] | null; device: number[] | null; object_id: number | null; key_id: number | null; date_created: string | null; date_modified: string | null; date_indexed: string | null }
+export type QueueSpacedropArgs = { peer_id: PeerId; file_path: string[]; priority: TransferPriority; 
+/**
+ * `null` uses the default policy
+ */
+retry: RetryPolicy | null; 
+/**
+ * `null` sends it as soon as possible
+ */
+window: TransferWindow | null }
 
-export type FilePathFilterArgs = { locationId?: number | null; search?: string | null; extension?: string | null; createdAt?: OptionalRange<string>; path?: string | null; object?: ObjectFilterArgs | null }
+/**
+ * A Spacedrop waiting in the queue to be sent
+ */
+export type QueuedTransfer = { 
+/**
+ * Also the id of the Spacedrop, so an interrupted attempt is resumed by the next one
+ */
+id: string; peer_id: PeerId; paths: string[]; priority: TransferPriority; retry: RetryPolicy; 
+/**
+ * `null` sends the transfer at any time
+ */
+window: TransferWindow | null; status: QueuedTransferStatus; attempts: number; 
+/**
+ * Why the last attempt failed
+ */
+error: string | null; 
+/**
+ * When the transfer is retried after failing
+ */
+next_attempt: string | null; date_created: string }
 
-export type FilePathSearchArgs = { take?: number | null; order?: FilePathSearchOrdering | null; cursor?: number[] | null; filter?: FilePathFilterArgs }
+export type QueuedTransferStatus = "queued" | "sending" | "done" | "rejected" | "failed"
 
-export type FilePathSearchOrdering = { name: SortOrder } | { sizeInBytes: SortOrder } | { dateCreated: SortOrder } | { dateModified: SortOrder } | { dateIndexed: SortOrder } | { object: ObjectSearchOrdering }
+/**
+ * From `start` to `end`, in local hours. A range that ends before it starts goes past midnight.
+ */
+export type QuietHours = { start: number; end: number }
 
-export type FilePathWithObject = { id: number; pub_id: number[]; is_dir: boolean | null; cas_id: string | null; integrity_checksum: string | null; location_id: number | null; materialized_path: string | null; name: string | null; extension: string | null; size_in_bytes: string | null; size_in_bytes_bytes: number[] | null; inode: number[] | null; device: number[] | null; object_id: number | null; key_id: number | null; date_created: string | null; date_modified: string | null; date_indexed: string | null; object: Object | null }
+/**
+ * Upload and download limits in KiB/s, `None` is unlimited
+ */
+export type RateLimits = { upload: number | null; download: number | null }
 
-export type FromPattern = { pattern: string; replace_all: boolean }
+export type RecentLogsArgs = { 
+/**
+ * Only the lines logged while working on this library
+ */
+library_id?: string | null; min_level?: LogLevel | null; limit?: number | null }
 
-export type GenerateThumbsForLocationArgs = { id: number; path: string }
+export type RecordSyncStatus = { id: number; state: SyncState; 
+/**
+ * The paired nodes that have the latest changes of the record
+ */
+synced_with: SyncStatusNode[]; 
+/**
+ * The paired nodes that sync the record but haven't received its latest changes
+ */
+pending_with: SyncStatusNode[] }
 
-export type GetArgs = { id: number }
+export type RedeemShareLinkArgs = { token: string; 
+/**
+ * The directory to save the file of the object to, the shared directory if `null`
+ */
+path: string | null }
 
-export type IdentifyUniqueFilesArgs = { id: number; path: string }
+export type RelationOperation = { relation_item: string; relation_group: string; relation: string; data: RelationOperationData }
 
-export type IndexerRule = { id: number; pub_id: number[]; name: string | null; default: boolean | null; rules_per_kind: number[] | null; date_created: string | null; date_modified: string | null }
+export type RelationOperationData = "Create" | { Update: { field: string; value: any } } | "Delete"
 
 /**
- * `IndexerRuleCreateArgs` is the argument received from the client using rspc to create a new indexer rule.
- * Note that `rules` field is a vector of tuples of `RuleKind` and `parameters`.
- * 
- * In case of  `RuleKind::AcceptFilesByGlob` or `RuleKind::RejectFilesByGlob`, it will be a
- * vector of strings containing a glob patterns.
- * 
- * In case of `RuleKind::AcceptIfChildrenDirectoriesArePresent` or `RuleKind::RejectIfChildrenDirectoriesArePresent` the
- * `parameters` field must be a vector of strings containing the names of the directories.
+ * A file or directory in a location of another node
  */
-export type IndexerRuleCreateArgs = { name: string; dry_run: boolean; rules: ([RuleKind, string[]])[] }
+export type RemoteEntry = { name: string; is_dir: boolean; size: string; date_modified: string | null }
 
-export type InvalidateOperationEvent = { key: string; arg: any; result: any | null }
+export type RenameFileArgs = { location_id: number; kind: RenameKind }
 
-export type JobGroup = { id: string; action: string | null; status: JobStatus; created_at: string; jobs: JobReport[] }
+export type RenameKind = { One: RenameOne } | { Many: RenameMany }
 
-export type JobGroups = { groups: JobGroup[]; index: { [key: string]: number } }
+export type RenameMany = { from_pattern: FromPattern; to_pattern: string; from_file_path_ids: number[] }
 
-export type JobProgressEvent = { id: string; task_count: number; completed_task_count: number; message: string; estimated_completion: string }
+export type RenameOne = { from_file_path_id: number; to: string }
 
-export type JobReport = { id: string; name: string; action: string | null; data: number[] | null; metadata: any | null; is_background: boolean; errors_text: string[]; created_at: string | null; started_at: string | null; completed_at: string | null; parent_id: string | null; status: JobStatus; task_count: number; completed_task_count: number; message: string; estimated_completion: string }
+export type ResolutionCap = "P480" | "P720" | "P1080" | "P2160"
 
-export type JobStatus = "Queued" | "Running" | "Completed" | "Canceled" | "Failed" | "Paused" | "CompletedWithErrors"
+export type ResolveSyncConflictArgs = { id: number; resolution: ConflictResolution }
+
+export type RestoreBackupArgs = { target: BackupTargetKind; snapshot: string; password: string }
 
 /**
- * Can wrap a query argument to require it to contain a `library_id` and provide helpers for working with libraries.
+ * How a transfer that failed is retried, waiting twice as long after every failed attempt
  */
-export type LibraryArgs<T> = { library_id: string; arg: T }
+export type RetryPolicy = { 
+/**
+ * How many times the transfer is attempted before giving up
+ */
+max_attempts: number; 
+/**
+ * How long to wait after the first failed attempt, in seconds
+ */
+delay: number }
 
-export type LibraryConfigWrapped = { uuid: string; config: SanitisedLibraryConfig }
+export type RuleAction = { type: "Command"; program: string; args: string[] } | { type: "Http"; url: string } | { type: "Tag"; tag_id: number } | { type: "ExtractMetadata"; plugin_id: string | null } | { type: "PluginJob"; plugin_id: string; job: string }
 
-export type LightScanArgs = { location_id: number; sub_path: string }
+export type RuleKind = "AcceptFilesByGlob" | "RejectFilesByGlob" | "AcceptIfChildrenDirectoriesArePresent" | "RejectIfChildrenDirectoriesArePresent"
 
-export type Location = { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; node_id: number | null }
+export type RuleTrigger = "FileCreated" | "JobCompleted"
 
 /**
- * `LocationCreateArgs` is the argument received from the client using `rspc` to create a new location.
- * It has the actual path and a vector of indexer rules ids, to create many-to-many relationships
- * between the location and indexer rules.
+ * A backup target without its credentials
  */
-export type LocationCreateArgs = { path: string; dry_run: boolean; indexer_rules_ids: number[] }
-
+export type SanitisedBackupTarget = { id: string; name: string; 
 /**
- * `LocationUpdateArgs` is the argument received from the client using `rspc` to update a location.
- * It contains the id of the location to be updated, possible a name to change the current location's name
- * and a vector of indexer rules ids to add or remove from the location.
- * 
- * It is important to note that only the indexer rule ids in this vector will be used from now on.
- * Old rules that aren't in this vector will be purged.
+ * `local`, `s3` or `webDav`
+ */
+kind: string; 
+/**
+ * The path, bucket or URL the snapshots are stored in
  */
-export type LocationUpdateArgs = { id: number; name: string | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; indexer_rules_ids: number[] }
+location: string; retention: BackupRetention; automatic: boolean }
 
-export type LocationWithIndexerRules = { id: number; pub_id: number[]; name: string | null; path: string | null; total_capacity: number | null; available_capacity: number | null; is_archived: boolean | null; generate_preview_media: boolean | null; sync_preview_media: boolean | null; hidden: boolean | null; date_created: string | null; node_id: number | null; indexer_rules: { indexer_rule: IndexerRule }[] }
+export type SanitisedLibraryConfig = { name: string; description: string | null; node_id: string; settings: LibrarySettings; sync_conflict_policy: ConflictPolicy; has_backup_password: boolean; encrypted: boolean; cloned_from: string | null }
 
-export type MaybeNot<T> = T | { not: T }
+export type SanitisedNodeConfig = { id: string; name: string; p2p_port: number | null; p2p_email: string | null; p2p_img_url: string | null; thumbnail_size: ThumbnailSize; thumbnail_cache_max_size_mb: number | null; p2p_bandwidth_limits: BandwidthLimits; p2p_relay: string | null; p2p_manual_peers: string[]; p2p_sync_schedule: SyncSchedule }
 
-export type MaybeUndefined<T> = null | null | T
+export type ScheduleQueuedTransferArgs = { id: string; priority: TransferPriority; window: TransferWindow | null }
 
-export type MediaData = { id: number; pixel_width: number | null; pixel_height: number | null; longitude: number | null; latitude: number | null; fps: number | null; capture_device_make: string | null; capture_device_model: string | null; capture_device_software: string | null; duration_seconds: number | null; codecs: string | null; streams: number | null }
+export type SearchData<T> = { cursor: number[] | null; items: T[] }
 
-export type Node = { id: number; pub_id: number[]; name: string; platform: number; date_created: string; identity: number[] | null; node_peer_id: string | null }
+export type SetBackupPasswordArgs = { password: string }
 
-export type NodeState = ({ id: string; name: string; p2p_port: number | null; p2p_email: string | null; p2p_img_url: string | null }) & { data_path: string }
+export type SetFavoriteArgs = { id: number; favorite: boolean }
 
-export type Object = { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null }
+export type SetNodePermissionsArgs = { node_id: number; permissions: NodePermissions }
 
-export type ObjectFilterArgs = { favorite?: boolean | null; hidden?: ObjectHiddenFilter; dateAccessed?: MaybeNot<string | null> | null; kind?: number[]; tags?: number[]; category?: Category | null }
+export type SetNodeTrustLevelArgs = { node_id: number; level: TrustLevel }
 
-export type ObjectHiddenFilter = "exclude" | "include"
+export type SetNoteArgs = { id: number; note: string | null }
 
-export type ObjectSearchArgs = { take?: number | null; order?: ObjectSearchOrdering | null; cursor?: number[] | null; filter?: ObjectFilterArgs }
+export type SetRuleEnabledArgs = { id: number; enabled: boolean }
 
-export type ObjectSearchOrdering = { dateAccessed: SortOrder }
+export type SetSyncScopeArgs = { node_id: number; 
+/**
+ * The locations to sync with the node, `null` or an empty list syncs all of them
+ */
+locations: number[] | null; 
+/**
+ * The tags to sync with the node, `null` or an empty list syncs all of them
+ */
+tags: number[] | null }
 
-export type ObjectValidatorArgs = { id: number; path: string }
+export type SetWebhookEnabledArgs = { id: number; enabled: boolean }
 
-export type ObjectWithFilePaths = { id: number; pub_id: number[]; kind: number | null; key_id: number | null; hidden: boolean | null; favorite: boolean | null; important: boolean | null; note: string | null; date_created: string | null; date_accessed: string | null; file_paths: FilePath[] }
+export type ShareArgs = { tag_id: number; peer_id: PeerId; access: SharingAccess; include_files: boolean }
 
 /**
- * Represents the operating system which the remote peer is running.
- * This is not used internally and predominantly is designed to be used for display purposes by the embedding application.
+ * A link to an object of the library that other nodes can redeem
  */
-export type OperatingSystem = "Windows" | "Linux" | "MacOS" | "Ios" | "Android" | { Other: string }
-
-export type OptionalRange<T> = { from: T | null; to: T | null }
+export type ShareLink = { id: number; object_id: number; token: string; date_created: string; date_expires: string | null; date_revoked: string | null; redemptions: number; date_redeemed: string | null }
 
 /**
- * TODO: P2P event for the frontend
+ * A tag of this library shared with another user's library, or one of theirs shared with it
+ */
+export type SharedCollection = { id: number; name: string; color: string | null; 
+/**
+ * The tag that is shared, `null` for the collections shared with this library
  */
-export type P2PEvent = { type: "DiscoveredPeer"; peer_id: PeerId; metadata: PeerMetadata } | { type: "SpacedropRequest"; id: string; peer_id: PeerId; name: string }
+tag_id: number | null; 
+/**
+ * The library on the other side
+ */
+library_name: string; peer_id: string; access: SharingAccess; include_files: boolean; item_count: number; date_created: string; 
+/**
+ * When the items were last received from the owner, `null` for the collections we own
+ */
+date_synced: string | null }
 
-export type PeerId = string
+export type SharedCollectionItem = { id: number; collection_id: number; object_pub_id: number[]; name: string; extension: string | null; kind: number; size_in_bytes: string; date_modified: string | null; local_path: string | null }
 
-export type PeerMetadata = { name: string; operating_system: OperatingSystem | null; version: string | null; email: string | null; img_url: string | null }
+/**
+ * An object of another library that was got with a share link, as that library describes it
+ */
+export type SharedObject = { object_pub_id: string; name: string; extension: string | null; kind: number; size_in_bytes: string; note: string | null; favorite: boolean; tags: string[]; date_created: string | null; date_modified: string | null; 
+/**
+ * Where the file of the object was saved to, `null` if the file isn't on the node that
+ * shared it
+ */
+local_path: string | null }
 
-export type RelationOperation = { relation_item: string; relation_group: string; relation: string; data: RelationOperationData }
+export type SharedOperation = { record_id: any; model: string; data: SharedOperationData }
 
-export type RelationOperationData = "Create" | { Update: { field: string; value: any } } | "Delete"
+export type SharedOperationData = { c: { [key: string]: any } } | { u: { field: string; value: any; 
+/**
+ * Timestamp of the last change to the field known by the node when it made this one,
+ * changes made after it by other nodes are concurrent with this one
+ */
+base: number | null } } | "d"
 
-export type RenameFileArgs = { location_id: number; kind: RenameKind }
+/**
+ * What the library a collection is shared with can do with it
+ */
+export type SharingAccess = "readOnly" | "readWrite"
 
-export type RenameKind = { One: RenameOne } | { Many: RenameMany }
+export type SkippedOperation = { kind: FileOperationKind; path: string | null; reason: string }
 
-export type RenameMany = { from_pattern: FromPattern; to_pattern: string; from_file_path_ids: number[] }
+export type SortOrder = "Asc" | "Desc"
 
-export type RenameOne = { from_file_path_id: number; to: string }
+export type SpacedropArgs = { peer_id: PeerId; file_path: string[] }
 
-export type RuleKind = "AcceptFilesByGlob" | "RejectFilesByGlob" | "AcceptIfChildrenDirectoriesArePresent" | "RejectIfChildrenDirectoriesArePresent"
+export type Statistics = { id: number; date_captured: string; total_object_count: number; library_db_size: string; total_bytes_used: string; total_bytes_capacity: string; total_unique_bytes: string; total_bytes_free: string; preview_media_bytes: string }
 
-export type SanitisedLibraryConfig = { name: string; description: string | null; node_id: string }
+/**
+ * A field of a record that two nodes changed concurrently
+ */
+export type SyncConflict = { id: number; model: string; record_id: any; field: string; 
+/**
+ * The value currently in the record
+ */
+value: any; node_name: string; 
+/**
+ * The value that was concurrently set by the other node
+ */
+other_value: any; other_node_name: string; date_created: string; 
+/**
+ * The value the conflict was settled with, `null` if it's pending or the record was deleted
+ */
+resolved_value: any | null; 
+/**
+ * `null` while the conflict is pending
+ */
+date_resolved: string | null }
 
-export type SanitisedNodeConfig = { id: string; name: string; p2p_port: number | null; p2p_email: string | null; p2p_img_url: string | null }
+/**
+ * A paired node and the locations and tags that are synced with it
+ */
+export type SyncNode = { id: number; pub_id: string; name: string; 
+/**
+ * The locations synced with the node, `null` syncs all of them
+ */
+locations: number[] | null; 
+/**
+ * The tags synced with the node, `null` syncs all of them
+ */
+tags: number[] | null; permissions: NodePermissions; 
+/**
+ * `null` if the permissions don't match a trust level
+ */
+trust_level: TrustLevel | null }
 
-export type SearchData<T> = { cursor: number[] | null; items: T[] }
+export type SyncOverview = { paired_nodes: number; 
+/**
+ * Paired nodes that missed some operations
+ */
+behind_nodes: number; pending_conflicts: number; 
+/**
+ * The last exchange of operations with any paired node
+ */
+last_exchange: string | null }
 
-export type SetFavoriteArgs = { id: number; favorite: boolean }
+/**
+ * How the exchanges of operations with a paired node went, to debug why they drifted apart. The
+ * metrics of the exchanges are since this node started.
+ */
+export type SyncPeerHealth = { id: number; name: string; 
+/**
+ * `null` if the node was never seen on the network
+ */
+peer_id: string | null; 
+/**
+ * The operations within the node's sync scope it doesn't have yet
+ */
+pending_operations: number; 
+/**
+ * The node missed some operations, they're replayed once it's reachable again
+ */
+behind: boolean; last_exchange: string | null; round_trip_ms: number | null; bytes_sent: string; bytes_received: string; 
+/**
+ * How far ahead the node's clock is of ours, negative if it's behind
+ */
+clock_skew_ms: number | null }
 
-export type SetNoteArgs = { id: number; note: string | null }
+/**
+ * When the operations made on this device are sent to the paired nodes, configured per device
+ */
+export type SyncSchedule = { 
+/**
+ * How long to wait for more operations before sending them together, in milliseconds
+ */
+batch_delay_ms: number; 
+/**
+ * Operations are sent without waiting once this many are waiting
+ */
+max_batch_size: number; 
+/**
+ * How long to wait on a metered connection, in seconds. `None` doesn't send anything until
+ * the connection isn't metered anymore.
+ */
+metered_delay_secs: number | null; 
+/**
+ * A peer that takes longer than this to acknowledge operations has a poor connection, in
+ * milliseconds
+ */
+poor_latency_ms: number; 
+/**
+ * The longest we wait before sending operations to a peer with a poor connection again, in
+ * seconds
+ */
+max_backoff_secs: number; 
+/**
+ * Sends operations as soon as they're made while the device is idle and plugged in
+ */
+eager_when_idle: boolean }
 
-export type SharedOperation = { record_id: any; model: string; data: SharedOperationData }
+export type SyncState = "localOnly" | "pending" | "synced"
 
-export type SharedOperationData = { c: { [key: string]: any } } | { u: { field: string; value: any } } | "d"
+export type SyncStatusArgs = { model: SyncStatusModel; ids: number[] }
 
-export type SortOrder = "Asc" | "Desc"
+/**
+ * The kinds of records we can tell the sync status of
+ */
+export type SyncStatusModel = "object" | "tag"
 
-export type SpacedropArgs = { peer_id: PeerId; file_path: string[] }
+export type SyncStatusNode = { id: number; name: string }
 
-export type Statistics = { id: number; date_captured: string; total_object_count: number; library_db_size: string; total_bytes_used: string; total_bytes_capacity: string; total_unique_bytes: string; total_bytes_free: string; preview_media_bytes: string }
+/**
+ * How SQLite waits for the writes to reach the disk, a trade between speed and durability
+ */
+export type SynchronousMode = "Off" | "Normal" | "Full"
 
-export type Tag = { id: number; pub_id: number[]; name: string | null; color: string | null; redundancy_goal: number | null; date_created: string | null; date_modified: string | null }
+export type Tag = { id: number; pub_id: number[]; name: string | null; color: string | null; redundancy_goal: number | null; date_created: string | null; date_modified: string | null; date_deleted: string | null }
 
 export type TagAssignArgs = { object_ids: number[]; tag_id: number; unassign: boolean }
 
@@ -262,4 +1392,109 @@ export type TagCreateArgs = { name: string; color: string }
 
 export type TagUpdateArgs = { id: number; name: string | null; color: string | null }
 
+export type TextEncoding = "utf8" | "utf16Le" | "utf16Be" | "latin1"
+
+/**
+ * The start of a text file, so clients can show it without reading arbitrary paths themselves
+ */
+export type TextPreview = { language: string | null; encoding: TextEncoding; content: string; truncated: boolean }
+
+/**
+ * The image format of the generated thumbnails, chosen per library. AVIF thumbnails are smaller
+ * than WebP ones at the same quality, but are much slower to encode.
+ * 
+ * Thumbnails are shared by all libraries, so a thumbnail is only generated in another format when
+ * it's regenerated. Thumbnails of any format are served, so changing it doesn't hide existing ones.
+ */
+export type ThumbnailFormat = "webp" | "avif"
+
+/**
+ * The size of the generated thumbnails, configured per node as the thumbnail cache is shared
+ * between all libraries. Changing it only affects new thumbnails, existing ones must be
+ * regenerated with the thumbnailer job.
+ */
+export type ThumbnailSize = "small" | "medium" | "large"
+
+export type Timing = { count: number; total_ms: number; max_ms: number }
+
+export type TokenScope = "readOnly" | "search" | "jobs" | "fileContent"
+
+export type TranscodePreset = { codec: VideoCodec; 
+/**
+ * The videos keep their resolution when not set
+ */
+resolution_cap: ResolutionCap | null }
+
+/**
+ * Transfers to the same peer are sent one at a time, highest priority first
+ */
+export type TransferPriority = "low" | "normal" | "high"
+
+/**
+ * The time of the day transfers are started at, in minutes after midnight in local time. A window
+ * that ends before it starts spans midnight, like overnight from 22:00 to 6:00.
+ */
+export type TransferWindow = { start: number; end: number }
+
+export type TrashItem = { kind: TrashItemKind; id: number; 
+/**
+ * The name of a tag, objects have none
+ */
+name: string | null; date_deleted: string; 
+/**
+ * When the item is purged, if it's still in the trash
+ */
+date_purged: string }
+
+export type TrashItemKind = "Object" | "Tag"
+
+/**
+ * The items of the trash to restore or purge
+ */
+export type TrashItems = { objects?: number[]; tags?: number[] }
+
+export type TrashedFile = { id: number; location_id: number; original_path: string; trash_path: string; is_dir: boolean; date_trashed: string }
+
+/**
+ * Presets of [`NodePermissions`]
+ */
+export type TrustLevel = "full" | "limited" | "blocked"
+
+export type UndoReport = { 
+/**
+ * The job whose operations were undone, none if there was nothing left to undo
+ */
+batch_id: string | null; undone: number; skipped: SkippedOperation[] }
+
+export type UnlockKeyManagerArgs = { password: Protected<string>; secret_key: Protected<string> }
+
+export type UnlockLibraryArgs = { id: string; password: Protected<string>; remember: boolean }
+
+export type VideoCodec = "H264" | "H265" | "Av1"
+
+/**
+ * Layout of a video's sprite sheet, so clients know which part of the image to show for each
+ * position of the cursor. Frames are laid out left to right and top to bottom.
+ */
+export type VideoSprite = { columns: number; rows: number; frame_count: number }
+
+export type VideoTranscoderJobInit = { location_id: number; file_path_ids: number[]; preset: TranscodePreset; 
+/**
+ * Encoding on the GPU when it can, falling back to software encoders otherwise
+ */
+hardware_acceleration: boolean; target: ConversionTarget }
+
 export type Volume = { name: string; mount_point: string; total_capacity: string; available_capacity: string; is_removable: boolean; disk_type: DiskType | null; file_system: string | null; is_root_filesystem: boolean }
+
+/**
+ * A location or a tag served over WebDAV, at `/webdav/<library id>/<name>/` of the server
+ */
+export type WebdavShare = { id: number; name: string; location_id: number | null; tag_id: number | null; username: string; date_created: string }
+
+export type Webhook = { id: number; url: string; enabled: boolean; events: WebhookEventKind[]; location_id: number | null; tag_id: number | null; 
+/**
+ * The secret itself is never sent back
+ */
+has_secret: boolean; date_created: string; date_last_delivery: string | null; last_status: number | null }
+
+export type WebhookEventKind = "ObjectCreated" | "ObjectTagged" | "ObjectDeleted"